@@ -143,4 +143,13 @@ CREATE INDEX IF NOT EXISTS idx_files_project_id ON files(project_id);
 CREATE INDEX IF NOT EXISTS idx_project_collaborators_project_id ON project_collaborators(project_id);
 CREATE INDEX IF NOT EXISTS idx_project_collaborators_user_id ON project_collaborators(user_id);
 CREATE INDEX IF NOT EXISTS idx_compilation_jobs_project_id ON compilation_jobs(project_id);
-CREATE INDEX IF NOT EXISTS idx_compilation_jobs_user_id ON compilation_jobs(user_id);
\ No newline at end of file
+CREATE INDEX IF NOT EXISTS idx_compilation_jobs_user_id ON compilation_jobs(user_id);
+
+-- Trigger function used by later migrations (redefined in 005_create_functions)
+CREATE OR REPLACE FUNCTION update_updated_at_column()
+RETURNS TRIGGER AS $$
+BEGIN
+    NEW.updated_at = NOW();
+    RETURN NEW;
+END;
+$$ language 'plpgsql';
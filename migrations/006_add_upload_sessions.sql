@@ -0,0 +1,46 @@
+-- Resumable upload sessions for large binary files
+DO $$ BEGIN
+    CREATE TYPE uploadstatus AS ENUM ('pending', 'completed', 'expired');
+EXCEPTION
+    WHEN duplicate_object THEN null;
+END $$;
+
+CREATE TABLE IF NOT EXISTS upload_sessions (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    project_id UUID NOT NULL REFERENCES projects(id) ON DELETE CASCADE,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    name VARCHAR(255) NOT NULL,
+    path VARCHAR(500) NOT NULL,
+    content_type VARCHAR(100),
+    declared_size BIGINT NOT NULL,
+    chunk_size BIGINT NOT NULL,
+    total_chunks INTEGER NOT NULL,
+    content_hash VARCHAR(64) NOT NULL,       -- Declared SHA-256 of the assembled file
+    status uploadstatus NOT NULL DEFAULT 'pending',
+    file_id UUID REFERENCES files(id) ON DELETE SET NULL,
+    expires_at TIMESTAMP WITH TIME ZONE NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT NOW(),
+    updated_at TIMESTAMP WITH TIME ZONE DEFAULT NOW(),
+    CHECK (declared_size > 0),
+    CHECK (chunk_size > 0),
+    CHECK (total_chunks > 0)
+);
+
+-- Chunks that have been received and verified
+CREATE TABLE IF NOT EXISTS upload_chunks (
+    session_id UUID NOT NULL REFERENCES upload_sessions(id) ON DELETE CASCADE,
+    chunk_index INTEGER NOT NULL,
+    size BIGINT NOT NULL,
+    checksum VARCHAR(64) NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT NOW(),
+    PRIMARY KEY (session_id, chunk_index)
+);
+
+-- Indexes for performance
+CREATE INDEX IF NOT EXISTS idx_upload_sessions_project_id ON upload_sessions(project_id);
+CREATE INDEX IF NOT EXISTS idx_upload_sessions_user_id ON upload_sessions(user_id);
+CREATE INDEX IF NOT EXISTS idx_upload_sessions_pending_expiry ON upload_sessions(expires_at) WHERE status = 'pending';
+
+-- Trigger to update updated_at
+CREATE TRIGGER update_upload_sessions_updated_at BEFORE UPDATE ON upload_sessions
+    FOR EACH ROW EXECUTE FUNCTION update_updated_at_column();
@@ -0,0 +1,62 @@
+-- Content-addressed blob store shared by files and file versions
+CREATE TABLE IF NOT EXISTS blobs (
+    hash VARCHAR(64) PRIMARY KEY,            -- SHA-256 of the content, hex encoded
+    size BIGINT NOT NULL,
+    ref_count INTEGER NOT NULL DEFAULT 0,
+    storage_path VARCHAR(1000),              -- Relative to the file storage root; NULL when held inline
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT NOW(),
+    updated_at TIMESTAMP WITH TIME ZONE DEFAULT NOW(),
+    CHECK (ref_count >= 0),
+    CHECK (size >= 0)
+);
+
+-- File version history (referenced by the file models)
+CREATE TABLE IF NOT EXISTS file_versions (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    file_id UUID NOT NULL REFERENCES files(id) ON DELETE CASCADE,
+    version INTEGER NOT NULL,
+    content_hash VARCHAR(64) NOT NULL,
+    changes TEXT,
+    change_summary TEXT NOT NULL DEFAULT '',
+    author_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT NOW()
+);
+
+-- Indexes for performance
+CREATE INDEX IF NOT EXISTS idx_blobs_zero_refs ON blobs(hash) WHERE ref_count = 0;
+CREATE INDEX IF NOT EXISTS idx_files_content_hash ON files(content_hash);
+CREATE INDEX IF NOT EXISTS idx_file_versions_file_id ON file_versions(file_id);
+CREATE INDEX IF NOT EXISTS idx_file_versions_content_hash ON file_versions(content_hash);
+
+-- Trigger to update updated_at
+CREATE TRIGGER update_blobs_updated_at BEFORE UPDATE ON blobs
+    FOR EACH ROW EXECUTE FUNCTION update_updated_at_column();
+
+-- Backfill hashes for rows written before content hashing was consistent
+UPDATE files
+SET content_hash = encode(sha256(convert_to(content, 'UTF8')), 'hex')
+WHERE content_hash IS NULL OR content_hash = '';
+
+-- Backfill blobs from every existing reference. Externally stored files keep
+-- their bytes at the legacy per-file location until they are rewritten.
+INSERT INTO blobs (hash, size, ref_count, storage_path)
+SELECT
+    refs.hash,
+    MAX(refs.size),
+    COUNT(*),
+    MIN(refs.storage_path)
+FROM (
+    SELECT
+        content_hash AS hash,
+        CASE WHEN storage_strategy = 'external' THEN size ELSE octet_length(content) END AS size,
+        CASE WHEN storage_strategy = 'external' THEN id::text END AS storage_path
+    FROM files
+    UNION ALL
+    SELECT v.content_hash AS hash, COALESCE(f.size, 0) AS size, NULL AS storage_path
+    FROM file_versions v
+    LEFT JOIN files f ON f.id = v.file_id AND f.content_hash = v.content_hash
+) refs
+GROUP BY refs.hash
+ON CONFLICT (hash) DO UPDATE SET
+    ref_count = EXCLUDED.ref_count,
+    storage_path = COALESCE(blobs.storage_path, EXCLUDED.storage_path);
@@ -0,0 +1,2 @@
+-- TeX distribution, engine version and package files recorded per compile
+ALTER TABLE compilation_jobs ADD COLUMN IF NOT EXISTS environment JSONB;
@@ -0,0 +1,27 @@
+-- Pending collaborator invitations for people without an account yet
+CREATE TABLE IF NOT EXISTS project_invitations (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    project_id UUID NOT NULL REFERENCES projects(id) ON DELETE CASCADE,
+    email VARCHAR(255) NOT NULL,             -- Stored lowercased
+    role collaboratorrole NOT NULL DEFAULT 'viewer',
+    token VARCHAR(64) NOT NULL UNIQUE,
+    invited_by UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    expires_at TIMESTAMP WITH TIME ZONE NOT NULL,
+    accepted_by UUID REFERENCES users(id) ON DELETE SET NULL,
+    accepted_at TIMESTAMP WITH TIME ZONE,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT NOW(),
+    updated_at TIMESTAMP WITH TIME ZONE DEFAULT NOW()
+);
+
+-- Collaborators record who invited them
+ALTER TABLE project_collaborators ADD COLUMN IF NOT EXISTS invited_by UUID REFERENCES users(id) ON DELETE SET NULL;
+ALTER TABLE project_collaborators ADD COLUMN IF NOT EXISTS invited_at TIMESTAMP WITH TIME ZONE DEFAULT NOW();
+
+-- One open invitation per project and email; re-inviting refreshes it
+CREATE UNIQUE INDEX IF NOT EXISTS idx_project_invitations_pending
+    ON project_invitations(project_id, email) WHERE accepted_at IS NULL;
+CREATE INDEX IF NOT EXISTS idx_project_invitations_email ON project_invitations(email) WHERE accepted_at IS NULL;
+
+-- Trigger to update updated_at
+CREATE TRIGGER update_project_invitations_updated_at BEFORE UPDATE ON project_invitations
+    FOR EACH ROW EXECUTE FUNCTION update_updated_at_column();
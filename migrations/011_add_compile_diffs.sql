@@ -0,0 +1,19 @@
+-- Cached comparisons between the PDFs of two compilation jobs
+DO $$ BEGIN
+    CREATE TYPE compilediffstatus AS ENUM ('pending', 'ready', 'failed');
+EXCEPTION
+    WHEN duplicate_object THEN null;
+END $$;
+
+CREATE TABLE IF NOT EXISTS compile_diffs (
+    from_job_id UUID NOT NULL REFERENCES compilation_jobs(id) ON DELETE CASCADE,
+    to_job_id UUID NOT NULL REFERENCES compilation_jobs(id) ON DELETE CASCADE,
+    status compilediffstatus NOT NULL DEFAULT 'pending',
+    result JSONB,
+    error TEXT,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT NOW(),
+    updated_at TIMESTAMP WITH TIME ZONE DEFAULT NOW(),
+    PRIMARY KEY (from_job_id, to_job_id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_compile_diffs_to_job ON compile_diffs(to_job_id);
@@ -0,0 +1,2 @@
+-- Settings from an imported archive that have no Texler equivalent
+ALTER TABLE projects ADD COLUMN IF NOT EXISTS imported_settings JSONB;
@@ -0,0 +1,22 @@
+-- Words and terms accepted by the spell checker for a whole project
+CREATE TABLE IF NOT EXISTS project_dictionary (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    project_id UUID NOT NULL REFERENCES projects(id) ON DELETE CASCADE,
+    term VARCHAR(100) NOT NULL,        -- NFC normalized, as entered
+    term_key VARCHAR(100) NOT NULL,    -- Lowercased unless the entry is case sensitive
+    case_sensitive BOOLEAN NOT NULL DEFAULT false,
+    added_by UUID REFERENCES users(id) ON DELETE SET NULL,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT NOW(),
+    UNIQUE(project_id, term_key, case_sensitive)
+);
+
+-- Personal words that apply to every project of a user
+CREATE TABLE IF NOT EXISTS user_dictionary (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    term VARCHAR(100) NOT NULL,
+    term_key VARCHAR(100) NOT NULL,
+    case_sensitive BOOLEAN NOT NULL DEFAULT false,
+    created_at TIMESTAMP WITH TIME ZONE DEFAULT NOW(),
+    UNIQUE(user_id, term_key, case_sensitive)
+);
@@ -0,0 +1,15 @@
+-- File each session participant currently has open
+ALTER TABLE IF EXISTS session_participants
+    ADD COLUMN IF NOT EXISTS current_file_id UUID REFERENCES files(id) ON DELETE SET NULL;
+
+-- How often each participant focused a file during a session
+CREATE TABLE IF NOT EXISTS session_file_views (
+    session_id UUID NOT NULL,
+    file_id UUID NOT NULL REFERENCES files(id) ON DELETE CASCADE,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    focus_count INTEGER NOT NULL DEFAULT 1,
+    last_focused_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (session_id, file_id, user_id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_session_file_views_file ON session_file_views(session_id, file_id);
@@ -0,0 +1,2 @@
+-- Markdown file shown as the project's README
+ALTER TABLE projects ADD COLUMN IF NOT EXISTS readme_file_id UUID REFERENCES files(id) ON DELETE SET NULL;
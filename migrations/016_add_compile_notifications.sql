@@ -0,0 +1,24 @@
+-- How users hear about finished compilations
+DO $$ BEGIN
+    CREATE TYPE compilenotificationpreference AS ENUM ('none', 'in_app', 'in_app_and_email', 'failures_only');
+EXCEPTION
+    WHEN duplicate_object THEN null;
+END $$;
+
+ALTER TABLE user_preferences
+    ADD COLUMN IF NOT EXISTS compile_notifications compilenotificationpreference NOT NULL DEFAULT 'in_app';
+
+-- Compile outcome emails; rows without sent_at wait for the hourly digest
+CREATE TABLE IF NOT EXISTS compile_notification_emails (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    job_id UUID NOT NULL REFERENCES compilation_jobs(id) ON DELETE CASCADE,
+    project_name VARCHAR(255) NOT NULL,
+    succeeded BOOLEAN NOT NULL,
+    summary TEXT NOT NULL,
+    sent_at TIMESTAMP WITH TIME ZONE,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_compile_notification_emails_user ON compile_notification_emails(user_id, created_at DESC);
+CREATE INDEX IF NOT EXISTS idx_compile_notification_emails_pending ON compile_notification_emails(user_id) WHERE sent_at IS NULL;
@@ -0,0 +1,156 @@
+-- Bring the core tables in line with the models; 001_initial_schema predates
+-- most of their columns, so a database built from migrations alone could not
+-- create projects, files or compilation jobs
+
+-- Enum types bound by the models
+DO $$ BEGIN
+    CREATE TYPE userrole AS ENUM ('owner', 'maintainer', 'collaborator', 'viewer');
+EXCEPTION
+    WHEN duplicate_object THEN null;
+END $$;
+
+DO $$ BEGIN
+    CREATE TYPE contenttype AS ENUM ('latex', 'bibliography', 'image', 'other');
+EXCEPTION
+    WHEN duplicate_object THEN null;
+END $$;
+
+DO $$ BEGIN
+    CREATE TYPE storagestrategy AS ENUM ('toast', 'external');
+EXCEPTION
+    WHEN duplicate_object THEN null;
+END $$;
+
+DO $$ BEGIN
+    CREATE TYPE queuepriority AS ENUM ('low', 'normal', 'high', 'urgent');
+EXCEPTION
+    WHEN duplicate_object THEN null;
+END $$;
+
+-- Compilation statuses use the model's names
+DO $$ BEGIN
+    IF EXISTS (SELECT 1 FROM pg_enum WHERE enumtypid = 'compilationstatus'::regtype AND enumlabel = 'completed') THEN
+        ALTER TYPE compilationstatus RENAME VALUE 'completed' TO 'success';
+    END IF;
+    IF EXISTS (SELECT 1 FROM pg_enum WHERE enumtypid = 'compilationstatus'::regtype AND enumlabel = 'failed') THEN
+        ALTER TYPE compilationstatus RENAME VALUE 'failed' TO 'error';
+    END IF;
+END $$;
+
+ALTER TYPE compilationstatus ADD VALUE IF NOT EXISTS 'never' BEFORE 'pending';
+
+-- Projects: output_format is free text in the models
+ALTER TABLE projects ALTER COLUMN output_format DROP DEFAULT;
+ALTER TABLE projects ALTER COLUMN output_format TYPE VARCHAR(10) USING output_format::text;
+ALTER TABLE projects ALTER COLUMN output_format SET DEFAULT 'pdf';
+ALTER TABLE projects ADD COLUMN IF NOT EXISTS last_compilation_at TIMESTAMP WITH TIME ZONE;
+
+-- Collaborator roles; the legacy editor/admin names map onto collaborator/maintainer
+ALTER TABLE project_collaborators ALTER COLUMN role DROP DEFAULT;
+ALTER TABLE project_collaborators ALTER COLUMN role TYPE userrole USING (
+    CASE role::text WHEN 'editor' THEN 'collaborator' WHEN 'admin' THEN 'maintainer' ELSE role::text END
+)::userrole;
+ALTER TABLE project_collaborators ALTER COLUMN role SET DEFAULT 'viewer';
+
+ALTER TABLE project_invitations ALTER COLUMN role DROP DEFAULT;
+ALTER TABLE project_invitations ALTER COLUMN role TYPE userrole USING (
+    CASE role::text WHEN 'editor' THEN 'collaborator' WHEN 'admin' THEN 'maintainer' ELSE role::text END
+)::userrole;
+ALTER TABLE project_invitations ALTER COLUMN role SET DEFAULT 'viewer';
+
+-- Files
+ALTER TABLE files ALTER COLUMN content_type DROP DEFAULT;
+ALTER TABLE files ALTER COLUMN content_type TYPE contenttype USING (
+    CASE
+        WHEN content_type::text IN ('latex', 'bibliography', 'image', 'other') THEN content_type::text
+        WHEN content_type::text LIKE 'image/%' THEN 'image'
+        ELSE 'other'
+    END
+)::contenttype;
+ALTER TABLE files ALTER COLUMN content_type SET DEFAULT 'latex';
+
+ALTER TABLE files ALTER COLUMN storage_strategy DROP DEFAULT;
+ALTER TABLE files ALTER COLUMN storage_strategy TYPE storagestrategy USING (
+    CASE storage_strategy::text WHEN 'external' THEN 'external' ELSE 'toast' END
+)::storagestrategy;
+ALTER TABLE files ALTER COLUMN storage_strategy SET DEFAULT 'toast';
+
+ALTER TABLE files
+    ADD COLUMN IF NOT EXISTS checksum VARCHAR(64),
+    ADD COLUMN IF NOT EXISTS deleted_at TIMESTAMP WITH TIME ZONE,
+    ADD COLUMN IF NOT EXISTS created_by UUID REFERENCES users(id) ON DELETE SET NULL,
+    ADD COLUMN IF NOT EXISTS last_modified_by UUID REFERENCES users(id) ON DELETE SET NULL,
+    ADD COLUMN IF NOT EXISTS last_modified TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW();
+
+UPDATE files f SET created_by = p.owner_id
+FROM projects p
+WHERE f.project_id = p.id AND f.created_by IS NULL;
+
+-- Compilation jobs
+ALTER TABLE compilation_jobs
+    ADD COLUMN IF NOT EXISTS file_id UUID REFERENCES files(id) ON DELETE SET NULL,
+    ADD COLUMN IF NOT EXISTS command VARCHAR(100) NOT NULL DEFAULT '',
+    ADD COLUMN IF NOT EXISTS args TEXT[] NOT NULL DEFAULT '{}',
+    ADD COLUMN IF NOT EXISTS working_directory VARCHAR(1000) NOT NULL DEFAULT '',
+    ADD COLUMN IF NOT EXISTS output_files TEXT[] NOT NULL DEFAULT '{}',
+    ADD COLUMN IF NOT EXISTS duration_ms BIGINT,
+    ADD COLUMN IF NOT EXISTS exit_code INTEGER,
+    ADD COLUMN IF NOT EXISTS stdout TEXT,
+    ADD COLUMN IF NOT EXISTS stderr TEXT,
+    ADD COLUMN IF NOT EXISTS log_file_path VARCHAR(1000),
+    ADD COLUMN IF NOT EXISTS artifacts_created INTEGER NOT NULL DEFAULT 0,
+    ADD COLUMN IF NOT EXISTS output_size_bytes BIGINT NOT NULL DEFAULT 0,
+    ADD COLUMN IF NOT EXISTS updated_at TIMESTAMP WITH TIME ZONE DEFAULT NOW();
+
+-- input_files was JSONB; a transform expression cannot unnest it, so copy it over
+DO $$ BEGIN
+    IF EXISTS (
+        SELECT 1 FROM information_schema.columns
+        WHERE table_name = 'compilation_jobs' AND column_name = 'input_files' AND data_type = 'jsonb'
+    ) THEN
+        ALTER TABLE compilation_jobs RENAME COLUMN input_files TO input_files_json;
+        ALTER TABLE compilation_jobs ADD COLUMN input_files TEXT[] NOT NULL DEFAULT '{}';
+        UPDATE compilation_jobs
+        SET input_files = ARRAY(SELECT jsonb_array_elements_text(input_files_json))
+        WHERE jsonb_typeof(input_files_json) = 'array';
+        ALTER TABLE compilation_jobs DROP COLUMN input_files_json;
+    END IF;
+END $$;
+
+CREATE TABLE IF NOT EXISTS compilation_queue (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    job_id UUID NOT NULL UNIQUE REFERENCES compilation_jobs(id) ON DELETE CASCADE,
+    priority queuepriority NOT NULL DEFAULT 'normal',
+    queue_position INTEGER NOT NULL,
+    estimated_duration_seconds INTEGER,
+    worker_id VARCHAR(255),
+    queued_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    started_at TIMESTAMP WITH TIME ZONE,
+    retry_count INTEGER NOT NULL DEFAULT 0,
+    max_retries INTEGER NOT NULL DEFAULT 3
+);
+
+-- Project tags and activity log
+CREATE TABLE IF NOT EXISTS project_tags (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    project_id UUID NOT NULL REFERENCES projects(id) ON DELETE CASCADE,
+    name VARCHAR(100) NOT NULL,
+    color VARCHAR(20),
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    UNIQUE(project_id, name)
+);
+
+CREATE TABLE IF NOT EXISTS project_activity (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    project_id UUID NOT NULL REFERENCES projects(id) ON DELETE CASCADE,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    action VARCHAR(100) NOT NULL,
+    entity_type VARCHAR(50) NOT NULL,
+    entity_id UUID,
+    details TEXT,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_compilation_queue_priority ON compilation_queue(priority, queue_position);
+CREATE INDEX IF NOT EXISTS idx_project_tags_project_id ON project_tags(project_id);
+CREATE INDEX IF NOT EXISTS idx_project_activity_project_id ON project_activity(project_id, created_at DESC);
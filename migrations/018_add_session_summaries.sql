@@ -0,0 +1,131 @@
+-- Collaboration session tables used by the collaboration models; no earlier
+-- migration created them, so they are declared here if missing
+DO $$ BEGIN
+    CREATE TYPE sessiontype AS ENUM ('realtime', 'review', 'tutorial', 'meeting');
+EXCEPTION
+    WHEN duplicate_object THEN null;
+END $$;
+
+DO $$ BEGIN
+    CREATE TYPE participantrole AS ENUM ('host', 'presenter', 'editor', 'viewer');
+EXCEPTION
+    WHEN duplicate_object THEN null;
+END $$;
+
+DO $$ BEGIN
+    CREATE TYPE operationtype AS ENUM ('insert', 'delete', 'replace', 'format', 'cursor', 'selection');
+EXCEPTION
+    WHEN duplicate_object THEN null;
+END $$;
+
+DO $$ BEGIN
+    CREATE TYPE messagetype AS ENUM ('text', 'system', 'file', 'code');
+EXCEPTION
+    WHEN duplicate_object THEN null;
+END $$;
+
+CREATE TABLE IF NOT EXISTS collaboration_sessions (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    project_id UUID NOT NULL REFERENCES projects(id) ON DELETE CASCADE,
+    file_id UUID REFERENCES files(id) ON DELETE SET NULL,
+    created_by UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    session_type sessiontype NOT NULL DEFAULT 'realtime',
+    title VARCHAR(255),
+    description TEXT,
+    is_active BOOLEAN NOT NULL DEFAULT true,
+    max_participants INTEGER NOT NULL DEFAULT 10,
+    password_hash VARCHAR(255),
+    settings TEXT,
+    started_at TIMESTAMP WITH TIME ZONE,
+    ended_at TIMESTAMP WITH TIME ZONE,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
+
+CREATE TABLE IF NOT EXISTS session_participants (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    session_id UUID NOT NULL REFERENCES collaboration_sessions(id) ON DELETE CASCADE,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    role participantrole NOT NULL DEFAULT 'viewer',
+    joined_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    left_at TIMESTAMP WITH TIME ZONE,
+    cursor_position INTEGER,
+    selection TEXT,
+    is_online BOOLEAN NOT NULL DEFAULT true,
+    last_seen_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    permissions TEXT,
+    current_file_id UUID REFERENCES files(id) ON DELETE SET NULL
+);
+
+CREATE TABLE IF NOT EXISTS session_operations (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    session_id UUID NOT NULL REFERENCES collaboration_sessions(id) ON DELETE CASCADE,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    operation_type operationtype NOT NULL,
+    operation_data TEXT NOT NULL,
+    file_id UUID REFERENCES files(id) ON DELETE SET NULL,
+    position INTEGER,
+    length INTEGER,
+    content TEXT,
+    timestamp TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    applied BOOLEAN NOT NULL DEFAULT false,
+    applied_at TIMESTAMP WITH TIME ZONE,
+    rejected BOOLEAN NOT NULL DEFAULT false,
+    rejected_at TIMESTAMP WITH TIME ZONE,
+    rejection_reason TEXT
+);
+
+CREATE TABLE IF NOT EXISTS session_messages (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    session_id UUID NOT NULL REFERENCES collaboration_sessions(id) ON DELETE CASCADE,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    message_type messagetype NOT NULL DEFAULT 'text',
+    content TEXT NOT NULL,
+    reply_to UUID REFERENCES session_messages(id) ON DELETE SET NULL,
+    reactions TEXT,
+    edited BOOLEAN NOT NULL DEFAULT false,
+    edited_at TIMESTAMP WITH TIME ZONE,
+    deleted BOOLEAN NOT NULL DEFAULT false,
+    deleted_at TIMESTAMP WITH TIME ZONE,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
+
+CREATE TABLE IF NOT EXISTS session_recordings (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    session_id UUID NOT NULL REFERENCES collaboration_sessions(id) ON DELETE CASCADE,
+    started_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    ended_at TIMESTAMP WITH TIME ZONE,
+    duration_seconds INTEGER,
+    file_path VARCHAR(1000) NOT NULL,
+    file_size BIGINT NOT NULL DEFAULT 0,
+    format VARCHAR(20) NOT NULL,
+    quality VARCHAR(20) NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
+
+-- Set once an ended session's operations are summarized
+ALTER TABLE collaboration_sessions ADD COLUMN IF NOT EXISTS compacted_at TIMESTAMP WITH TIME ZONE;
+
+-- Applied operations of a compacted session, one row per file. file_id has no
+-- foreign key so the totals survive the file being deleted.
+CREATE TABLE IF NOT EXISTS session_summaries (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    session_id UUID NOT NULL REFERENCES collaboration_sessions(id) ON DELETE CASCADE,
+    file_id UUID,                             -- NULL for operations not tied to a file
+    content_hash VARCHAR(64),                 -- File content when the session was compacted
+    operation_count BIGINT NOT NULL,
+    characters_typed BIGINT NOT NULL,
+    contributions JSONB NOT NULL DEFAULT '[]', -- [{user_id, operation_count, characters_typed}]
+    first_operation_at TIMESTAMP WITH TIME ZONE NOT NULL,
+    last_operation_at TIMESTAMP WITH TIME ZONE NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_collaboration_sessions_project ON collaboration_sessions(project_id);
+CREATE INDEX IF NOT EXISTS idx_collaboration_sessions_uncompacted ON collaboration_sessions(ended_at) WHERE compacted_at IS NULL;
+CREATE INDEX IF NOT EXISTS idx_session_participants_session ON session_participants(session_id);
+CREATE INDEX IF NOT EXISTS idx_session_operations_session ON session_operations(session_id, timestamp);
+CREATE INDEX IF NOT EXISTS idx_session_messages_session ON session_messages(session_id, created_at);
+CREATE INDEX IF NOT EXISTS idx_session_recordings_active ON session_recordings(session_id) WHERE ended_at IS NULL;
+CREATE UNIQUE INDEX IF NOT EXISTS idx_session_summaries_file
+    ON session_summaries(session_id, COALESCE(file_id, '00000000-0000-0000-0000-000000000000'::uuid));
@@ -0,0 +1,21 @@
+-- Who changed which project settings or collaborator roles, and from what
+DO $$ BEGIN
+    CREATE TYPE settingschangekind AS ENUM ('settings', 'rollback', 'collaborator_role');
+EXCEPTION
+    WHEN duplicate_object THEN null;
+END $$;
+
+-- before/after hold only the changed fields; collaborator_id is set for role changes
+CREATE TABLE IF NOT EXISTS project_settings_history (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    project_id UUID NOT NULL REFERENCES projects(id) ON DELETE CASCADE,
+    kind settingschangekind NOT NULL,
+    changed_by UUID REFERENCES users(id) ON DELETE SET NULL,
+    collaborator_id UUID REFERENCES users(id) ON DELETE SET NULL,
+    rollback_of UUID REFERENCES project_settings_history(id) ON DELETE SET NULL,
+    before JSONB NOT NULL,
+    after JSONB NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_project_settings_history_project ON project_settings_history(project_id, created_at DESC);
@@ -0,0 +1,43 @@
+-- Opt-in activity digests, and the editor preferences the model has long
+-- expected on user_preferences
+DO $$ BEGIN
+    CREATE TYPE digestfrequency AS ENUM ('off', 'daily', 'weekly');
+EXCEPTION
+    WHEN duplicate_object THEN null;
+END $$;
+
+ALTER TABLE user_preferences
+    ADD COLUMN IF NOT EXISTS line_numbers BOOLEAN NOT NULL DEFAULT true,
+    ADD COLUMN IF NOT EXISTS word_wrap BOOLEAN NOT NULL DEFAULT true,
+    ADD COLUMN IF NOT EXISTS font_size INTEGER NOT NULL DEFAULT 14,
+    ADD COLUMN IF NOT EXISTS tab_size INTEGER NOT NULL DEFAULT 2,
+    ADD COLUMN IF NOT EXISTS digest_frequency digestfrequency NOT NULL DEFAULT 'off';
+
+-- auto_save is a flag in the model, not an interval
+DO $$ BEGIN
+    IF EXISTS (
+        SELECT 1 FROM information_schema.columns
+        WHERE table_name = 'user_preferences' AND column_name = 'auto_save' AND data_type = 'integer'
+    ) THEN
+        ALTER TABLE user_preferences ALTER COLUMN auto_save DROP DEFAULT;
+        ALTER TABLE user_preferences ALTER COLUMN auto_save TYPE BOOLEAN USING auto_save > 0;
+    END IF;
+END $$;
+ALTER TABLE user_preferences ALTER COLUMN auto_save SET DEFAULT true;
+
+-- The preferred engine is free text in the model
+ALTER TABLE user_preferences ALTER COLUMN latex_engine DROP DEFAULT;
+ALTER TABLE user_preferences ALTER COLUMN latex_engine TYPE VARCHAR(20) USING latex_engine::text;
+ALTER TABLE user_preferences ALTER COLUMN latex_engine SET DEFAULT 'pdflatex';
+
+-- One row per user and period; sent_at stays NULL when there was nothing to report
+CREATE TABLE IF NOT EXISTS digest_deliveries (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    frequency digestfrequency NOT NULL,
+    period_start TIMESTAMP WITH TIME ZONE NOT NULL,
+    period_end TIMESTAMP WITH TIME ZONE NOT NULL,
+    sent_at TIMESTAMP WITH TIME ZONE,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    UNIQUE (user_id, frequency, period_end)
+);
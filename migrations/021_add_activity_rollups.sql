@@ -0,0 +1,23 @@
+-- Daily per-action counters for project activity past the retention window
+CREATE TABLE IF NOT EXISTS project_activity_rollup (
+    project_id UUID NOT NULL REFERENCES projects(id) ON DELETE CASCADE,
+    day DATE NOT NULL,
+    action VARCHAR(100) NOT NULL,
+    count BIGINT NOT NULL DEFAULT 0,
+    PRIMARY KEY (project_id, day, action)
+);
+
+CREATE INDEX IF NOT EXISTS idx_project_activity_created_at ON project_activity(created_at);
+
+-- Announce new activity to the WebSocket server
+CREATE OR REPLACE FUNCTION notify_project_activity()
+RETURNS TRIGGER AS $$
+BEGIN
+    PERFORM pg_notify('project_activity', NEW.id::text);
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS notify_project_activity ON project_activity;
+CREATE TRIGGER notify_project_activity AFTER INSERT ON project_activity
+    FOR EACH ROW EXECUTE FUNCTION notify_project_activity();
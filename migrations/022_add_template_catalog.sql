@@ -0,0 +1,41 @@
+-- Compilation templates with ratings, forks and admin verification; the
+-- table itself was never created by earlier migrations
+CREATE TABLE IF NOT EXISTS compilation_templates (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    name VARCHAR(255) NOT NULL,
+    description TEXT,
+    engine latexengine NOT NULL DEFAULT 'pdflatex',
+    command_template TEXT NOT NULL,
+    default_args TEXT[] NOT NULL DEFAULT '{}',
+    required_files TEXT[] NOT NULL DEFAULT '{}',
+    output_patterns TEXT[] NOT NULL DEFAULT '{}',
+    is_public BOOLEAN NOT NULL DEFAULT false,
+    created_by UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    usage_count BIGINT NOT NULL DEFAULT 0,
+    success_rate DOUBLE PRECISION NOT NULL DEFAULT 1.0,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
+
+-- Aggregates of compilation_template_ratings, kept in step by the rating query
+ALTER TABLE compilation_templates
+    ADD COLUMN IF NOT EXISTS verified BOOLEAN NOT NULL DEFAULT false,
+    ADD COLUMN IF NOT EXISTS rating_count BIGINT NOT NULL DEFAULT 0,
+    ADD COLUMN IF NOT EXISTS rating_average DOUBLE PRECISION NOT NULL DEFAULT 0,
+    ADD COLUMN IF NOT EXISTS forked_from UUID REFERENCES compilation_templates(id) ON DELETE SET NULL;
+
+CREATE INDEX IF NOT EXISTS idx_compilation_templates_listing
+    ON compilation_templates(verified DESC, success_rate DESC, usage_count DESC) WHERE is_public = true;
+
+-- One fork of a template per user, so forking again returns the existing copy
+CREATE UNIQUE INDEX IF NOT EXISTS idx_compilation_templates_fork
+    ON compilation_templates(created_by, forked_from) WHERE forked_from IS NOT NULL;
+
+CREATE TABLE IF NOT EXISTS compilation_template_ratings (
+    template_id UUID NOT NULL REFERENCES compilation_templates(id) ON DELETE CASCADE,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    rating SMALLINT NOT NULL CHECK (rating BETWEEN 1 AND 5),
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (template_id, user_id)
+);
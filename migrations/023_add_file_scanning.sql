@@ -0,0 +1,29 @@
+-- Malware scanning of uploaded files and an audit log for security events
+DO $$ BEGIN
+    CREATE TYPE filescanstatus AS ENUM ('unscanned', 'clean', 'skipped', 'quarantined', 'released');
+EXCEPTION
+    WHEN duplicate_object THEN null;
+END $$;
+
+ALTER TABLE files
+    ADD COLUMN IF NOT EXISTS scan_status filescanstatus NOT NULL DEFAULT 'unscanned',
+    ADD COLUMN IF NOT EXISTS scan_signature TEXT,
+    ADD COLUMN IF NOT EXISTS scanned_at TIMESTAMP WITH TIME ZONE;
+
+-- Files written before scanning was enabled, for the re-scan sweep
+CREATE INDEX IF NOT EXISTS idx_files_unscanned ON files(created_at) WHERE scan_status = 'unscanned' AND is_deleted = false;
+CREATE INDEX IF NOT EXISTS idx_files_quarantined ON files(scanned_at) WHERE scan_status = 'quarantined';
+
+CREATE TABLE IF NOT EXISTS audit_log (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    actor_id UUID REFERENCES users(id) ON DELETE SET NULL,
+    action VARCHAR(100) NOT NULL,
+    entity_type VARCHAR(50) NOT NULL,
+    entity_id UUID,
+    project_id UUID REFERENCES projects(id) ON DELETE SET NULL,
+    details JSONB NOT NULL DEFAULT '{}',
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_audit_log_created_at ON audit_log(created_at DESC);
+CREATE INDEX IF NOT EXISTS idx_audit_log_action ON audit_log(action, created_at DESC);
@@ -0,0 +1,9 @@
+-- Join codes and failed join counters of collaboration sessions
+CREATE TABLE IF NOT EXISTS session_access (
+    session_id UUID PRIMARY KEY REFERENCES collaboration_sessions(id) ON DELETE CASCADE,
+    join_code_hash VARCHAR(64),
+    join_code_expires_at TIMESTAMP WITH TIME ZONE,
+    failed_attempts INTEGER NOT NULL DEFAULT 0,
+    locked_until TIMESTAMP WITH TIME ZONE,
+    updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
@@ -0,0 +1,18 @@
+-- Runs of the reindex that recomputes what is derived from file content
+CREATE TABLE IF NOT EXISTS file_reindexes (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    requested_by UUID REFERENCES users(id) ON DELETE SET NULL,
+    -- Files are walked in ID order; the last one done, to resume after
+    high_water UUID,
+    processed BIGINT NOT NULL DEFAULT 0,
+    changed BIGINT NOT NULL DEFAULT 0,
+    errors BIGINT NOT NULL DEFAULT 0,
+    last_error TEXT,
+    started_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    completed_at TIMESTAMP WITH TIME ZONE
+);
+
+-- One run at a time
+CREATE UNIQUE INDEX IF NOT EXISTS idx_file_reindexes_running ON file_reindexes((true)) WHERE completed_at IS NULL;
+CREATE INDEX IF NOT EXISTS idx_file_reindexes_started ON file_reindexes(started_at);
@@ -0,0 +1,10 @@
+-- Read-only freezes of projects, e.g. after a submission
+CREATE TABLE IF NOT EXISTS project_freezes (
+    project_id UUID PRIMARY KEY REFERENCES projects(id) ON DELETE CASCADE,
+    reason TEXT,
+    frozen_by UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    frozen_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    unfreeze_at TIMESTAMP WITH TIME ZONE
+);
+
+CREATE INDEX IF NOT EXISTS idx_project_freezes_unfreeze_at ON project_freezes(unfreeze_at) WHERE unfreeze_at IS NOT NULL;
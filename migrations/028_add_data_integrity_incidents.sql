@@ -0,0 +1,18 @@
+-- Stored content found not to match its recorded hash
+CREATE TABLE IF NOT EXISTS data_integrity_incidents (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    file_id UUID REFERENCES files(id) ON DELETE SET NULL,
+    project_id UUID REFERENCES projects(id) ON DELETE SET NULL,
+    expected_hash VARCHAR(64) NOT NULL,
+    actual_hash VARCHAR(64) NOT NULL,
+    detected_by VARCHAR(20) NOT NULL,     -- download, export, compile or scan
+    occurrences INTEGER NOT NULL DEFAULT 1,
+    first_detected_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    last_detected_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
+
+-- Repeated reads of the same corruption count towards one incident
+CREATE UNIQUE INDEX IF NOT EXISTS idx_data_integrity_incidents_hashes
+    ON data_integrity_incidents(expected_hash, actual_hash, detected_by);
+CREATE INDEX IF NOT EXISTS idx_data_integrity_incidents_last_detected_at
+    ON data_integrity_incidents(last_detected_at DESC);
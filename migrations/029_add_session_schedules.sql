@@ -0,0 +1,29 @@
+-- Scheduled collaboration sessions, stored in UTC
+ALTER TABLE collaboration_sessions ADD COLUMN IF NOT EXISTS scheduled_start TIMESTAMP WITH TIME ZONE;
+ALTER TABLE collaboration_sessions ADD COLUMN IF NOT EXISTS scheduled_end TIMESTAMP WITH TIME ZONE;
+ALTER TABLE collaboration_sessions ADD COLUMN IF NOT EXISTS reminder_sent_at TIMESTAMP WITH TIME ZONE;
+-- Set when a scheduled session passed its end without ever starting
+ALTER TABLE collaboration_sessions ADD COLUMN IF NOT EXISTS cancelled_at TIMESTAMP WITH TIME ZONE;
+
+CREATE INDEX IF NOT EXISTS idx_collaboration_sessions_scheduled_start
+    ON collaboration_sessions(scheduled_start) WHERE scheduled_start IS NOT NULL AND is_active = true;
+
+CREATE TABLE IF NOT EXISTS session_invitations (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    session_id UUID NOT NULL REFERENCES collaboration_sessions(id) ON DELETE CASCADE,
+    invited_by UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    invited_user UUID REFERENCES users(id) ON DELETE CASCADE,  -- Set on accepting an email invitation
+    email VARCHAR(255),                                         -- Stored lowercased
+    role participantrole NOT NULL DEFAULT 'viewer',
+    message TEXT,
+    token VARCHAR(64) NOT NULL UNIQUE,
+    expires_at TIMESTAMP WITH TIME ZONE NOT NULL,
+    accepted BOOLEAN NOT NULL DEFAULT false,
+    accepted_at TIMESTAMP WITH TIME ZONE,
+    declined BOOLEAN NOT NULL DEFAULT false,
+    declined_at TIMESTAMP WITH TIME ZONE,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_session_invitations_session_id ON session_invitations(session_id);
+CREATE INDEX IF NOT EXISTS idx_session_invitations_invited_user ON session_invitations(invited_user) WHERE declined = false;
@@ -0,0 +1,13 @@
+-- Anonymous review mode: identities are masked for everyone but the host until this time
+ALTER TABLE collaboration_sessions ADD COLUMN IF NOT EXISTS anonymous_until TIMESTAMP WITH TIME ZONE;
+
+-- Pseudonym of a user in a session, kept across reconnects
+CREATE TABLE IF NOT EXISTS session_pseudonyms (
+    session_id UUID NOT NULL REFERENCES collaboration_sessions(id) ON DELETE CASCADE,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    alias_id UUID NOT NULL UNIQUE,   -- Shown in place of user_id
+    number INTEGER NOT NULL,         -- "Reviewer <number>"
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (session_id, user_id),
+    UNIQUE (session_id, number)
+);
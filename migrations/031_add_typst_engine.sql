@@ -0,0 +1,5 @@
+-- Typst documents compile with the typst binary; the target's extension picks the toolchain
+ALTER TYPE latexengine ADD VALUE IF NOT EXISTS 'typst';
+
+-- .typ sources, kept apart from LaTeX so metadata extraction skips them
+ALTER TYPE contenttype ADD VALUE IF NOT EXISTS 'typst';
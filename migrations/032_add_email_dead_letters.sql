@@ -0,0 +1,15 @@
+-- Emails the sender gave up on, or could not deliver before shutting down
+CREATE TABLE IF NOT EXISTS email_dead_letters (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    recipient VARCHAR(255) NOT NULL,
+    template VARCHAR(100) NOT NULL,
+    subject TEXT NOT NULL,
+    text_body TEXT NOT NULL,
+    html_body TEXT NOT NULL,
+    attempts INTEGER NOT NULL DEFAULT 0,
+    error TEXT NOT NULL,
+    requeue BOOLEAN NOT NULL DEFAULT false,  -- Delivered again when the sender starts
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_email_dead_letters_requeue ON email_dead_letters(created_at) WHERE requeue;
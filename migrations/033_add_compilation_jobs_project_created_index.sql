@@ -0,0 +1,4 @@
+-- Project job history is listed newest first; the composite index replaces the plain project one
+CREATE INDEX IF NOT EXISTS idx_compilation_jobs_project_created
+    ON compilation_jobs(project_id, created_at DESC);
+DROP INDEX IF EXISTS idx_compilation_jobs_project_id;
@@ -0,0 +1,2 @@
+-- Hash of the uploaded avatar, whose sizes are stored under user-avatars/<user_id>/; NULL serves an identicon
+ALTER TABLE users ADD COLUMN IF NOT EXISTS avatar_hash VARCHAR(64);
@@ -0,0 +1,18 @@
+-- Version content: a full snapshot, a delta against the previous version in
+-- `changes`, or only the blob store (binary files and rows from before)
+DO $$ BEGIN
+    CREATE TYPE versionencoding AS ENUM ('snapshot', 'delta', 'blob');
+EXCEPTION
+    WHEN duplicate_object THEN null;
+END $$;
+
+ALTER TABLE file_versions ADD COLUMN IF NOT EXISTS encoding versionencoding NOT NULL DEFAULT 'blob';
+ALTER TABLE file_versions ADD COLUMN IF NOT EXISTS snapshot BYTEA;
+-- Deltas since the last snapshot and their total size, bounding reconstruction
+ALTER TABLE file_versions ADD COLUMN IF NOT EXISTS chain_length INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE file_versions ADD COLUMN IF NOT EXISTS chain_bytes BIGINT NOT NULL DEFAULT 0;
+-- Set once compaction re-encoded the version with the sparser archive checkpoints
+ALTER TABLE file_versions ADD COLUMN IF NOT EXISTS archived BOOLEAN NOT NULL DEFAULT false;
+
+CREATE INDEX IF NOT EXISTS idx_file_versions_file_version ON file_versions(file_id, version);
+CREATE INDEX IF NOT EXISTS idx_file_versions_unarchived ON file_versions(created_at) WHERE archived = false;
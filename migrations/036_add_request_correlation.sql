@@ -0,0 +1,5 @@
+-- Request that caused the work, for finding its logs and traces
+ALTER TABLE compilation_jobs ADD COLUMN IF NOT EXISTS request_id UUID;
+ALTER TABLE email_dead_letters ADD COLUMN IF NOT EXISTS request_id UUID;
+
+CREATE INDEX IF NOT EXISTS idx_compilation_jobs_request_id ON compilation_jobs(request_id) WHERE request_id IS NOT NULL;
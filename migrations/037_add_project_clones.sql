@@ -0,0 +1,5 @@
+-- Project a project was cloned from, kept while the source exists
+ALTER TABLE projects
+    ADD COLUMN IF NOT EXISTS cloned_from UUID REFERENCES projects(id) ON DELETE SET NULL;
+
+CREATE INDEX IF NOT EXISTS idx_projects_cloned_from ON projects(cloned_from) WHERE cloned_from IS NOT NULL;
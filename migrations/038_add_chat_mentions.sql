@@ -0,0 +1,13 @@
+-- How users hear about being mentioned in session chat
+DO $$ BEGIN
+    CREATE TYPE mentionnotificationpreference AS ENUM ('none', 'in_app', 'in_app_and_email');
+EXCEPTION
+    WHEN duplicate_object THEN null;
+END $$;
+
+ALTER TABLE user_preferences
+    ADD COLUMN IF NOT EXISTS mention_notifications mentionnotificationpreference NOT NULL DEFAULT 'in_app';
+
+-- Users a chat message mentions, resolved when it was sent or last edited
+ALTER TABLE session_messages
+    ADD COLUMN IF NOT EXISTS mentions UUID[] NOT NULL DEFAULT '{}';
@@ -0,0 +1,33 @@
+-- Set when the owner deleted the project; its rows are removed in the background
+ALTER TABLE projects ADD COLUMN IF NOT EXISTS purging_at TIMESTAMP WITH TIME ZONE;
+
+CREATE INDEX IF NOT EXISTS idx_projects_purging ON projects(purging_at) WHERE purging_at IS NOT NULL;
+
+-- Dependent rows are deleted in this order, the project row last
+DO $$ BEGIN
+    CREATE TYPE purgestage AS ENUM (
+        'compilation_jobs', 'session_operations', 'session_messages', 'sessions',
+        'activity', 'file_versions', 'files', 'project', 'done'
+    );
+EXCEPTION
+    WHEN duplicate_object THEN null;
+END $$;
+
+-- Progress of a project deletion; outlives the project so it can be reported
+CREATE TABLE IF NOT EXISTS project_purges (
+    project_id UUID PRIMARY KEY,
+    project_name VARCHAR(255) NOT NULL,
+    owner_id UUID REFERENCES users(id) ON DELETE SET NULL,
+    requested_by UUID REFERENCES users(id) ON DELETE SET NULL,
+    stage purgestage NOT NULL DEFAULT 'compilation_jobs',
+    -- Rows deleted so far per table
+    rows_deleted JSONB NOT NULL DEFAULT '{}',
+    bytes_reclaimed BIGINT NOT NULL DEFAULT 0,
+    last_error TEXT,
+    started_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    completed_at TIMESTAMP WITH TIME ZONE
+);
+
+CREATE INDEX IF NOT EXISTS idx_project_purges_pending ON project_purges(started_at) WHERE completed_at IS NULL;
+CREATE INDEX IF NOT EXISTS idx_project_purges_owner ON project_purges(owner_id, started_at);
@@ -0,0 +1,2 @@
+-- Per-project settings such as formatting options; missing keys mean defaults
+ALTER TABLE projects ADD COLUMN IF NOT EXISTS settings JSONB NOT NULL DEFAULT '{}';
@@ -0,0 +1,17 @@
+-- Private projects whose latest PDF may still be embedded with a token
+ALTER TABLE projects ADD COLUMN IF NOT EXISTS is_embeddable BOOLEAN NOT NULL DEFAULT false;
+
+-- Read-only tokens serving a project's latest PDF to other sites; only the hash is kept
+CREATE TABLE IF NOT EXISTS project_embed_tokens (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    project_id UUID NOT NULL REFERENCES projects(id) ON DELETE CASCADE,
+    token_hash TEXT NOT NULL UNIQUE,
+    created_by UUID REFERENCES users(id) ON DELETE SET NULL,
+    expires_at TIMESTAMPTZ,
+    revoked BOOLEAN NOT NULL DEFAULT false,
+    hit_count BIGINT NOT NULL DEFAULT 0,
+    last_used_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_project_embed_tokens_project ON project_embed_tokens(project_id, created_at);
@@ -0,0 +1,34 @@
+-- Activity exports too large to stream, written to file storage in the background
+DO $$ BEGIN
+    CREATE TYPE activityexportstatus AS ENUM ('pending', 'ready', 'failed');
+EXCEPTION
+    WHEN duplicate_object THEN null;
+END $$;
+
+DO $$ BEGIN
+    CREATE TYPE activityexportformat AS ENUM ('csv', 'json');
+EXCEPTION
+    WHEN duplicate_object THEN null;
+END $$;
+
+-- The export stays with its requester until it expires, even when its
+-- project or workspace is deleted meanwhile
+CREATE TABLE IF NOT EXISTS activity_exports (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    requested_by UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    project_id UUID REFERENCES projects(id) ON DELETE SET NULL,
+    workspace_id UUID REFERENCES workspaces(id) ON DELETE SET NULL,
+    format activityexportformat NOT NULL,
+    period_start TIMESTAMP WITH TIME ZONE,
+    period_end TIMESTAMP WITH TIME ZONE,
+    status activityexportstatus NOT NULL DEFAULT 'pending',
+    row_count BIGINT,
+    size_bytes BIGINT,
+    error TEXT,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    completed_at TIMESTAMP WITH TIME ZONE,
+    expires_at TIMESTAMP WITH TIME ZONE NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_activity_exports_requested_by ON activity_exports(requested_by, created_at);
+CREATE INDEX IF NOT EXISTS idx_activity_exports_expires_at ON activity_exports(expires_at);
@@ -0,0 +1,2 @@
+-- Preflight findings a compile was forced past with ignore_preflight
+ALTER TABLE compilation_jobs ADD COLUMN IF NOT EXISTS preflight JSONB;
@@ -0,0 +1,18 @@
+-- Secrets of a project, encrypted with the master key named by key_id.
+-- The project ID and name are bound into the ciphertext, so a value cannot
+-- be moved to another row.
+CREATE TABLE IF NOT EXISTS project_secrets (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    project_id UUID NOT NULL REFERENCES projects(id) ON DELETE CASCADE,
+    name VARCHAR(64) NOT NULL,
+    ciphertext BYTEA NOT NULL,
+    nonce BYTEA NOT NULL,
+    key_id VARCHAR(16) NOT NULL,
+    expose_to_compile BOOLEAN NOT NULL DEFAULT false,
+    created_by UUID REFERENCES users(id) ON DELETE SET NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    UNIQUE (project_id, name)
+);
+
+CREATE INDEX IF NOT EXISTS idx_project_secrets_key_id ON project_secrets(key_id);
@@ -0,0 +1,6 @@
+-- Word count following TeX conventions, next to the whitespace count in
+-- word_count. NULL until computed; the word_count_backfill task fills in
+-- files saved before the column existed.
+ALTER TABLE files ADD COLUMN IF NOT EXISTS word_count_tex INTEGER;
+
+CREATE INDEX IF NOT EXISTS idx_files_word_count_tex_pending ON files(id) WHERE word_count_tex IS NULL;
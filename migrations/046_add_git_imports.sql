@@ -0,0 +1,35 @@
+-- Repository and commit a project was imported from
+ALTER TABLE projects ADD COLUMN IF NOT EXISTS source_url TEXT;
+ALTER TABLE projects ADD COLUMN IF NOT EXISTS source_commit VARCHAR(64);
+
+DO $$ BEGIN
+    CREATE TYPE gitimportstatus AS ENUM ('pending', 'cloning', 'importing', 'ready', 'failed');
+EXCEPTION
+    WHEN duplicate_object THEN null;
+END $$;
+
+-- Imports from git repositories, run in the background. Access tokens for
+-- private repositories are used for the clone only and never stored.
+CREATE TABLE IF NOT EXISTS git_imports (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    requested_by UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    workspace_id UUID NOT NULL REFERENCES workspaces(id) ON DELETE CASCADE,
+    repository_url TEXT NOT NULL,
+    reference VARCHAR(255),
+    subdirectory TEXT,
+    name VARCHAR(255),
+    status gitimportstatus NOT NULL DEFAULT 'pending',
+    commit_hash VARCHAR(64),
+    files_total INTEGER,
+    files_imported INTEGER NOT NULL DEFAULT 0,
+    bytes_total BIGINT,
+    skipped JSONB NOT NULL DEFAULT '[]',
+    project_id UUID REFERENCES projects(id) ON DELETE SET NULL,
+    error TEXT,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    completed_at TIMESTAMP WITH TIME ZONE
+);
+
+CREATE INDEX IF NOT EXISTS idx_git_imports_requested_by ON git_imports(requested_by, created_at);
+CREATE INDEX IF NOT EXISTS idx_git_imports_running ON git_imports(updated_at) WHERE status IN ('pending', 'cloning', 'importing');
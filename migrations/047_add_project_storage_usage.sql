@@ -0,0 +1,14 @@
+-- Largest live files of a project, for the storage breakdown
+CREATE INDEX IF NOT EXISTS idx_files_project_size ON files(project_id, size DESC) WHERE is_deleted = false;
+
+-- Storage aggregates of projects, recomputed once stale
+CREATE TABLE IF NOT EXISTS project_storage_usage (
+    project_id UUID PRIMARY KEY REFERENCES projects(id) ON DELETE CASCADE,
+    live_bytes BIGINT NOT NULL,
+    pending_upload_bytes BIGINT NOT NULL,
+    by_content_type JSONB NOT NULL DEFAULT '[]',
+    history_raw_bytes BIGINT NOT NULL,
+    history_stored_bytes BIGINT NOT NULL,
+    artifact_bytes BIGINT NOT NULL,
+    computed_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
@@ -0,0 +1,5 @@
+-- Starter files of templates come from a project of the author; `variables`
+-- declares the `{{name}}` placeholders in them
+ALTER TABLE compilation_templates
+    ADD COLUMN IF NOT EXISTS source_project_id UUID REFERENCES projects(id) ON DELETE SET NULL,
+    ADD COLUMN IF NOT EXISTS variables JSONB NOT NULL DEFAULT '[]';
@@ -0,0 +1,13 @@
+-- Activity of session participants, apart from the socket heartbeat in
+-- `last_seen_at`; idle editors are downgraded and get `restore_role` back
+DO $$ BEGIN
+    CREATE TYPE participantactivity AS ENUM ('active', 'idle', 'away');
+EXCEPTION
+    WHEN duplicate_object THEN null;
+END $$;
+
+ALTER TABLE session_participants
+    ADD COLUMN IF NOT EXISTS last_activity_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    ADD COLUMN IF NOT EXISTS activity participantactivity NOT NULL DEFAULT 'active',
+    ADD COLUMN IF NOT EXISTS restore_role participantrole,
+    ADD COLUMN IF NOT EXISTS queued_at TIMESTAMP WITH TIME ZONE;
@@ -0,0 +1,18 @@
+-- A path names at most one live file of a project. Live duplicates left by
+-- racing creates keep the oldest file at the path; the others are moved
+-- aside under a suffix of their ID so nothing is lost.
+WITH duplicates AS (
+    SELECT id, ROW_NUMBER() OVER (PARTITION BY project_id, path ORDER BY created_at, id) AS rank
+    FROM files
+    WHERE is_deleted = false
+)
+UPDATE files f
+SET path = f.path || '.duplicate-' || LEFT(f.id::text, 8),
+    name = f.name || '.duplicate-' || LEFT(f.id::text, 8),
+    is_main = false,
+    updated_at = NOW()
+FROM duplicates d
+WHERE d.id = f.id AND d.rank > 1;
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_files_project_path_live
+    ON files(project_id, path) WHERE is_deleted = false;
@@ -0,0 +1,41 @@
+-- Views of public projects by non-members, counted per UTC day
+CREATE TABLE IF NOT EXISTS project_daily_views (
+    project_id UUID NOT NULL REFERENCES projects(id) ON DELETE CASCADE,
+    day DATE NOT NULL,
+    views BIGINT NOT NULL DEFAULT 0,
+    PRIMARY KEY (project_id, day)
+);
+
+CREATE INDEX IF NOT EXISTS idx_project_daily_views_day ON project_daily_views(day);
+
+-- Trending scores of public projects, replaced as a whole by each computation
+CREATE TABLE IF NOT EXISTS project_trending_scores (
+    project_id UUID PRIMARY KEY REFERENCES projects(id) ON DELETE CASCADE,
+    score DOUBLE PRECISION NOT NULL,
+    views BIGINT NOT NULL,
+    clones BIGINT NOT NULL,
+    compiles BIGINT NOT NULL,
+    computed_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_project_trending_scores_rank ON project_trending_scores(score DESC, project_id);
+
+-- Collections of public projects curated by administrators
+CREATE TABLE IF NOT EXISTS collections (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    name VARCHAR(255) NOT NULL,
+    description TEXT,
+    position INTEGER NOT NULL DEFAULT 0,
+    created_by UUID REFERENCES users(id) ON DELETE SET NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
+
+CREATE TABLE IF NOT EXISTS collection_projects (
+    collection_id UUID NOT NULL REFERENCES collections(id) ON DELETE CASCADE,
+    project_id UUID NOT NULL REFERENCES projects(id) ON DELETE CASCADE,
+    position INTEGER NOT NULL,
+    PRIMARY KEY (collection_id, project_id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_collection_projects_project ON collection_projects(project_id);
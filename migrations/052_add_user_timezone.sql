@@ -0,0 +1,3 @@
+-- IANA time zone whose calendar days digests and date filters follow
+ALTER TABLE user_preferences
+    ADD COLUMN IF NOT EXISTS timezone VARCHAR(64) NOT NULL DEFAULT 'UTC';
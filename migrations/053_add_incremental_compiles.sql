@@ -0,0 +1,6 @@
+-- How a compile job used the project's build directory
+ALTER TABLE compilation_jobs
+    ADD COLUMN IF NOT EXISTS clean BOOLEAN NOT NULL DEFAULT false,
+    ADD COLUMN IF NOT EXISTS incremental BOOLEAN NOT NULL DEFAULT false,
+    ADD COLUMN IF NOT EXISTS files_refreshed INTEGER,
+    ADD COLUMN IF NOT EXISTS retried_clean BOOLEAN NOT NULL DEFAULT false;
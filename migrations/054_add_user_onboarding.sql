@@ -0,0 +1,13 @@
+-- First-run milestones of each user; a milestone once reached stays reached
+CREATE TABLE IF NOT EXISTS user_onboarding (
+    user_id UUID PRIMARY KEY REFERENCES users(id) ON DELETE CASCADE,
+    first_project_created_at TIMESTAMPTZ,
+    first_file_edited_at TIMESTAMPTZ,
+    first_successful_compile_at TIMESTAMPTZ,
+    first_collaborator_added_at TIMESTAMPTZ,
+    -- The welcome project the workspace bootstrap created, while it exists
+    sample_project_id UUID REFERENCES projects(id) ON DELETE SET NULL,
+    dismissed_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
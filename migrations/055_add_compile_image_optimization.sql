@@ -0,0 +1,2 @@
+-- Images a compile job transcoded, the bytes it saved and the images it left as uploaded
+ALTER TABLE compilation_jobs ADD COLUMN IF NOT EXISTS image_optimization JSONB;
@@ -0,0 +1,13 @@
+-- Trigram indexes behind the command palette's quick search
+CREATE EXTENSION IF NOT EXISTS pg_trgm;
+
+CREATE INDEX IF NOT EXISTS idx_projects_name_trgm
+    ON projects USING gin (name gin_trgm_ops) WHERE purging_at IS NULL;
+CREATE INDEX IF NOT EXISTS idx_files_name_trgm
+    ON files USING gin (name gin_trgm_ops) WHERE is_deleted = false;
+CREATE INDEX IF NOT EXISTS idx_files_path_trgm
+    ON files USING gin (path gin_trgm_ops) WHERE is_deleted = false;
+CREATE INDEX IF NOT EXISTS idx_collaboration_sessions_title_trgm
+    ON collaboration_sessions USING gin (title gin_trgm_ops) WHERE is_active = true;
+CREATE INDEX IF NOT EXISTS idx_users_username_trgm
+    ON users USING gin (username gin_trgm_ops);
@@ -0,0 +1,30 @@
+-- Requested changes of a user's email address. The old address stays in use
+-- until the new one is confirmed; after that, the old address can undo the
+-- change for a while.
+CREATE TABLE IF NOT EXISTS email_changes (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    old_email VARCHAR(255) NOT NULL,
+    new_email VARCHAR(255) NOT NULL,
+    -- Sent to the new address
+    token VARCHAR(64) NOT NULL UNIQUE,
+    -- Sent to the old address once the change is confirmed
+    undo_token VARCHAR(64) UNIQUE,
+    revoke_sessions BOOLEAN NOT NULL DEFAULT false,
+    status VARCHAR(20) NOT NULL DEFAULT 'pending'
+        CHECK (status IN ('pending', 'confirmed', 'cancelled', 'rejected', 'reverted')),
+    expires_at TIMESTAMPTZ NOT NULL,
+    undo_expires_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    confirmed_at TIMESTAMPTZ,
+    cancelled_at TIMESTAMPTZ,
+    reverted_at TIMESTAMPTZ
+);
+
+-- A user has at most one change waiting for confirmation
+CREATE UNIQUE INDEX IF NOT EXISTS idx_email_changes_pending_user
+    ON email_changes(user_id) WHERE status = 'pending';
+
+-- Old addresses stay reserved while their change can be undone
+CREATE INDEX IF NOT EXISTS idx_email_changes_undoable_old_email
+    ON email_changes(old_email) WHERE status = 'confirmed';
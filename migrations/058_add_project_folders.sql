@@ -0,0 +1,30 @@
+-- Personal folders a user sorts their projects into, within one of their workspaces
+CREATE TABLE IF NOT EXISTS project_folders (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    workspace_id UUID NOT NULL REFERENCES workspaces(id) ON DELETE CASCADE,
+    owner_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    name VARCHAR(255) NOT NULL,
+    -- Manual order among the owner's folders of the workspace, ascending
+    position BIGINT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_project_folders_name
+    ON project_folders(workspace_id, owner_id, LOWER(name));
+CREATE INDEX IF NOT EXISTS idx_project_folders_owner ON project_folders(owner_id, workspace_id, position);
+
+-- Where a user has put a project: in one of their folders or unfiled
+-- (folder_id NULL), at a manual position. Projects the user never moved have
+-- no row and are listed unfiled, ahead of the arranged ones. One row per user
+-- and project keeps a project in at most one of that user's folders.
+CREATE TABLE IF NOT EXISTS project_placements (
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    project_id UUID NOT NULL REFERENCES projects(id) ON DELETE CASCADE,
+    folder_id UUID REFERENCES project_folders(id) ON DELETE SET NULL,
+    position BIGINT NOT NULL,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (user_id, project_id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_project_placements_folder ON project_placements(user_id, folder_id, position);
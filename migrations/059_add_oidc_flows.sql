@@ -0,0 +1,18 @@
+-- OIDC logins in progress. The state handed to the provider is stored only
+-- as its SHA-256; the PKCE verifier and the nonce never leave the server.
+-- A flow is consumed by its callback and cannot be used again.
+CREATE TABLE IF NOT EXISTS oidc_flows (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    state_hash VARCHAR(64) NOT NULL UNIQUE,
+    provider VARCHAR(100) NOT NULL,
+    code_verifier VARCHAR(128) NOT NULL,
+    -- Only for providers issuing ID tokens
+    nonce VARCHAR(64),
+    -- Path of the frontend to return to after signing in
+    redirect_to TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    expires_at TIMESTAMPTZ NOT NULL,
+    consumed_at TIMESTAMPTZ
+);
+
+CREATE INDEX IF NOT EXISTS idx_oidc_flows_expires_at ON oidc_flows(expires_at);
@@ -0,0 +1,15 @@
+-- Jobs queued together by one bulk compile of a project's targets
+CREATE TABLE IF NOT EXISTS compile_batches (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    project_id UUID NOT NULL REFERENCES projects(id) ON DELETE CASCADE,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    -- Set once, when the last member finished and the batch was reported
+    completed_at TIMESTAMPTZ
+);
+
+CREATE INDEX IF NOT EXISTS idx_compile_batches_project ON compile_batches(project_id, created_at);
+
+ALTER TABLE compilation_jobs ADD COLUMN IF NOT EXISTS batch_id UUID REFERENCES compile_batches(id) ON DELETE SET NULL;
+
+CREATE INDEX IF NOT EXISTS idx_compilation_jobs_batch ON compilation_jobs(batch_id) WHERE batch_id IS NOT NULL;
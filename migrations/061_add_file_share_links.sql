@@ -0,0 +1,32 @@
+-- Read-only links to a single file of a project; only the hash of the token is kept
+DO $$ BEGIN
+    CREATE TYPE filesharemode AS ENUM ('source', 'pdf');
+EXCEPTION
+    WHEN duplicate_object THEN null;
+END $$;
+
+CREATE TABLE IF NOT EXISTS file_share_links (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    file_id UUID NOT NULL REFERENCES files(id) ON DELETE CASCADE,
+    project_id UUID NOT NULL REFERENCES projects(id) ON DELETE CASCADE,
+    token_hash TEXT NOT NULL UNIQUE,
+    mode filesharemode NOT NULL,
+    -- Standalone compiles run as this user, so the link goes with them
+    created_by UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    expires_at TIMESTAMPTZ,
+    revoked BOOLEAN NOT NULL DEFAULT false,
+    hit_count BIGINT NOT NULL DEFAULT 0,
+    last_used_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_file_share_links_file ON file_share_links(file_id, created_at);
+
+-- Standalone compiles of shared files, by hash of the compiled document
+CREATE TABLE IF NOT EXISTS file_share_renders (
+    document_hash TEXT PRIMARY KEY,
+    job_id UUID NOT NULL REFERENCES compilation_jobs(id) ON DELETE CASCADE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_file_share_renders_job ON file_share_renders(job_id);
@@ -0,0 +1,18 @@
+-- Offers of a project's ownership to one of its collaborators; only the hash of the token is kept
+CREATE TABLE IF NOT EXISTS project_ownership_transfers (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    project_id UUID NOT NULL REFERENCES projects(id) ON DELETE CASCADE,
+    from_user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    to_user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    token_hash TEXT NOT NULL UNIQUE,
+    expires_at TIMESTAMPTZ NOT NULL,
+    accepted_at TIMESTAMPTZ,
+    cancelled_at TIMESTAMPTZ,
+    cancelled_by UUID REFERENCES users(id) ON DELETE SET NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_ownership_transfers_project ON project_ownership_transfers(project_id, created_at);
+
+-- The owner is `projects.owner_id` alone; collaborator rows claiming it are demoted
+UPDATE project_collaborators SET role = 'maintainer' WHERE role = 'owner';
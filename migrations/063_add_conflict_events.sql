@@ -0,0 +1,30 @@
+-- Sampled edit conflicts, for the admin conflict report and the contended files of a project
+DO $$ BEGIN
+    CREATE TYPE conflictkind AS ENUM ('rest', 'transform');
+EXCEPTION
+    WHEN duplicate_object THEN null;
+END $$;
+
+DO $$ BEGIN
+    CREATE TYPE conflictoutcome AS ENUM ('rejected', 'auto_merged', 'discarded');
+EXCEPTION
+    WHEN duplicate_object THEN null;
+END $$;
+
+CREATE TABLE IF NOT EXISTS conflict_events (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    kind conflictkind NOT NULL,
+    outcome conflictoutcome NOT NULL,
+    -- Route pattern of a 409, or the collaboration message a transform happened on
+    endpoint TEXT NOT NULL,
+    project_id UUID NOT NULL REFERENCES projects(id) ON DELETE CASCADE,
+    file_id UUID REFERENCES files(id) ON DELETE SET NULL,
+    -- Keyed hashes of the users involved, never their IDs
+    user_hashes TEXT[] NOT NULL DEFAULT '{}',
+    -- Conflicts the row stands for, the inverse of the sample rate it was kept at
+    weight DOUBLE PRECISION NOT NULL DEFAULT 1,
+    occurred_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_conflict_events_occurred ON conflict_events(occurred_at);
+CREATE INDEX IF NOT EXISTS idx_conflict_events_project ON conflict_events(project_id, occurred_at);
@@ -0,0 +1,17 @@
+-- Preview thumbnails of the first pages of a compile job's primary PDF
+ALTER TABLE compilation_jobs ADD COLUMN IF NOT EXISTS thumbnails JSONB;
+
+CREATE TABLE IF NOT EXISTS compilation_thumbnails (
+    job_id UUID NOT NULL REFERENCES compilation_jobs(id) ON DELETE CASCADE,
+    page INTEGER NOT NULL,
+    width INTEGER NOT NULL,
+    height INTEGER NOT NULL,
+    size_bytes INTEGER NOT NULL,
+    data BYTEA NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (job_id, page)
+);
+
+-- Successful jobs still waiting for their thumbnails
+CREATE INDEX IF NOT EXISTS idx_compilation_jobs_thumbnails_pending
+    ON compilation_jobs(completed_at) WHERE status = 'success' AND thumbnails IS NULL;
@@ -0,0 +1,23 @@
+-- Monthly usage of each workspace, rolled up from job records and file versions
+CREATE TABLE IF NOT EXISTS usage_periods (
+    workspace_id UUID NOT NULL REFERENCES workspaces(id) ON DELETE CASCADE,
+    period_start DATE NOT NULL,
+    active_users BIGINT NOT NULL,
+    storage_bytes BIGINT NOT NULL,
+    compile_ms BIGINT NOT NULL,
+    jobs_succeeded BIGINT NOT NULL,
+    jobs_failed BIGINT NOT NULL,
+    jobs_cancelled BIGINT NOT NULL,
+    jobs_unfinished BIGINT NOT NULL,
+    -- Bumped whenever a later rollup changes the figures; `previous` keeps
+    -- the figures the last restatement replaced
+    revision INTEGER NOT NULL DEFAULT 1,
+    previous JSONB,
+    restated_at TIMESTAMP WITH TIME ZONE,
+    computed_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (workspace_id, period_start)
+);
+
+CREATE INDEX IF NOT EXISTS idx_usage_periods_period ON usage_periods(period_start);
+CREATE INDEX IF NOT EXISTS idx_file_versions_created_at ON file_versions(created_at);
+CREATE INDEX IF NOT EXISTS idx_compilation_jobs_created_at ON compilation_jobs(created_at);
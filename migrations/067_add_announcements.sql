@@ -0,0 +1,31 @@
+-- Announcements administrators write for everyone on the instance, or for
+-- administrators only, shown between `starts_at` and `expires_at`
+DO $$ BEGIN
+    CREATE TYPE announcementaudience AS ENUM ('everyone', 'admins');
+EXCEPTION
+    WHEN duplicate_object THEN null;
+END $$;
+
+CREATE TABLE IF NOT EXISTS instance_announcements (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    title TEXT NOT NULL,
+    body TEXT NOT NULL,
+    audience announcementaudience NOT NULL DEFAULT 'everyone',
+    starts_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    expires_at TIMESTAMPTZ,
+    -- When the audience was notified, once the announcement started
+    notified_at TIMESTAMPTZ,
+    created_by UUID REFERENCES users(id) ON DELETE SET NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    CHECK (expires_at IS NULL OR expires_at > starts_at)
+);
+
+CREATE INDEX IF NOT EXISTS idx_instance_announcements_starts_at ON instance_announcements(starts_at);
+
+-- The release and the time up to which each user read the announcements
+CREATE TABLE IF NOT EXISTS announcement_acknowledgements (
+    user_id UUID PRIMARY KEY REFERENCES users(id) ON DELETE CASCADE,
+    version TEXT NOT NULL,
+    acknowledged_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
@@ -2,11 +2,14 @@
 
 use tracing::{info, warn};
 use crate::models::user::{User, CreateUser};
+use crate::models::validation::DisplayName;
+
+/// Username of the bootstrap administrator account
+pub const ADMIN_USERNAME: &str = "admin";
 
 /// Ensure that an admin user exists on startup
 /// Creates an admin user with username "admin" and password "password" if it doesn't exist
 pub async fn ensure_admin_user(db_pool: &sqlx::PgPool) -> Result<(), crate::error::AppError> {
-    const ADMIN_USERNAME: &str = "admin";
     const ADMIN_PASSWORD: &str = "password";
     const ADMIN_EMAIL: &str = "admin@texler.local";
     const ADMIN_DISPLAY_NAME: &str = "Administrator";
@@ -25,7 +28,7 @@ pub async fn ensure_admin_user(db_pool: &sqlx::PgPool) -> Result<(), crate::erro
                 username: ADMIN_USERNAME.to_string(),
                 email: ADMIN_EMAIL.to_string(),
                 password: ADMIN_PASSWORD.to_string(),
-                display_name: ADMIN_DISPLAY_NAME.to_string(),
+                display_name: DisplayName::new(ADMIN_DISPLAY_NAME)?,
                 avatar_url: None,
             };
 
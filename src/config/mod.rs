@@ -4,6 +4,8 @@ use serde::{Deserialize, Serialize};
 use std::env;
 use tracing::{info, warn};
 
+pub mod snapshot;
+
 /// Application configuration
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Config {
@@ -11,10 +13,16 @@ pub struct Config {
     pub database: DatabaseConfig,
     pub redis: RedisConfig,
     pub jwt: JwtConfig,
+    pub login_protection: LoginProtectionConfig,
     pub oidc: OidcConfig,
     pub websocket: WebSocketConfig,
     pub latex: LatexConfig,
     pub email: EmailConfig,
+    pub http_client: HttpClientConfig,
+    pub limits: LimitsConfig,
+    pub scanning: ScanConfig,
+    pub secrets: SecretsConfig,
+    pub git_import: GitImportConfig,
     pub features: FeaturesConfig,
     pub logging: LoggingConfig,
 }
@@ -29,10 +37,16 @@ impl Config {
             database: DatabaseConfig::load()?,
             redis: RedisConfig::load()?,
             jwt: JwtConfig::load()?,
+            login_protection: LoginProtectionConfig::load()?,
             oidc: OidcConfig::load()?,
             websocket: WebSocketConfig::load()?,
             latex: LatexConfig::load()?,
             email: EmailConfig::load()?,
+            http_client: HttpClientConfig::load()?,
+            limits: LimitsConfig::load()?,
+            scanning: ScanConfig::load()?,
+            secrets: SecretsConfig::load()?,
+            git_import: GitImportConfig::load()?,
             features: FeaturesConfig::load()?,
             logging: LoggingConfig::load()?,
         };
@@ -49,7 +63,14 @@ pub struct ServerConfig {
     pub port: u16,
     pub workers: usize,
     pub max_connections: usize,
+    /// Default time budget for a request, in seconds
     pub request_timeout: u64,
+    /// Budget for the synchronous LaTeX proxy compile
+    pub compile_timeout: u64,
+    /// Budget for file uploads and downloads that stream their bodies
+    pub transfer_timeout: u64,
+    /// Budget for project archive export and import
+    pub archive_timeout: u64,
     pub keep_alive: u64,
     pub tls: Option<TlsConfig>,
 }
@@ -70,6 +91,15 @@ impl ServerConfig {
             request_timeout: env::var("SERVER_REQUEST_TIMEOUT")
                 .unwrap_or_else(|_| "30".to_string())
                 .parse()?,
+            compile_timeout: env::var("SERVER_COMPILE_TIMEOUT")
+                .unwrap_or_else(|_| "300".to_string())
+                .parse()?,
+            transfer_timeout: env::var("SERVER_TRANSFER_TIMEOUT")
+                .unwrap_or_else(|_| "600".to_string())
+                .parse()?,
+            archive_timeout: env::var("SERVER_ARCHIVE_TIMEOUT")
+                .unwrap_or_else(|_| "600".to_string())
+                .parse()?,
             keep_alive: env::var("SERVER_KEEP_ALIVE")
                 .unwrap_or_else(|_| "75".to_string())
                 .parse()?,
@@ -108,6 +138,27 @@ pub struct DatabaseConfig {
     pub min_connections: u32,
     pub connect_timeout: u64,
     pub idle_timeout: u64,
+    /// Postgres `statement_timeout` in seconds, so queries of a timed out
+    /// request do not keep running on the server; 0 disables it
+    pub statement_timeout: u64,
+    /// Days project activity is kept row by row before it is rolled up
+    /// into daily counters
+    pub activity_retention_days: u64,
+    /// Queries taking longer than this many milliseconds are logged as slow
+    /// when metrics are enabled; 0 disables the log
+    pub slow_query_ms: u64,
+    /// Only report pending migrations at startup, then exit
+    pub migrate_dry_run: bool,
+    /// Refuse migrations that may rewrite more bytes of tables than this; 0 for no limit
+    pub migrate_max_rewrite_size: u64,
+    /// Seconds to wait for another instance to finish migrating
+    pub migrate_lock_wait: u64,
+    /// Consecutive lost or refused connections that open the circuit breaker
+    pub circuit_failure_threshold: u32,
+    /// Seconds the circuit stays open before the database is probed again
+    pub circuit_cooldown: u64,
+    /// Best-effort writes kept in memory while the database is unreachable
+    pub write_buffer_capacity: usize,
 }
 
 impl DatabaseConfig {
@@ -135,6 +186,35 @@ impl DatabaseConfig {
             idle_timeout: env::var("DATABASE_IDLE_TIMEOUT")
                 .unwrap_or_else(|_| "600".to_string())
                 .parse()?,
+            // Paired with the default request budget unless set explicitly
+            statement_timeout: env::var("DATABASE_STATEMENT_TIMEOUT")
+                .or_else(|_| env::var("SERVER_REQUEST_TIMEOUT"))
+                .unwrap_or_else(|_| "30".to_string())
+                .parse()?,
+            activity_retention_days: env::var("DATABASE_ACTIVITY_RETENTION_DAYS")
+                .unwrap_or_else(|_| "90".to_string())
+                .parse()?,
+            slow_query_ms: env::var("DATABASE_SLOW_QUERY_MS")
+                .unwrap_or_else(|_| "250".to_string())
+                .parse()?,
+            migrate_dry_run: env::var("MIGRATE_DRY_RUN")
+                .unwrap_or_else(|_| "false".to_string())
+                .parse()?,
+            migrate_max_rewrite_size: env::var("MIGRATE_MAX_REWRITE_SIZE")
+                .unwrap_or_else(|_| "0".to_string())
+                .parse()?,
+            migrate_lock_wait: env::var("MIGRATE_LOCK_WAIT")
+                .unwrap_or_else(|_| "300".to_string())
+                .parse()?,
+            circuit_failure_threshold: env::var("DATABASE_CIRCUIT_THRESHOLD")
+                .unwrap_or_else(|_| "5".to_string())
+                .parse()?,
+            circuit_cooldown: env::var("DATABASE_CIRCUIT_COOLDOWN")
+                .unwrap_or_else(|_| "5".to_string())
+                .parse()?,
+            write_buffer_capacity: env::var("DATABASE_WRITE_BUFFER")
+                .unwrap_or_else(|_| "10000".to_string())
+                .parse()?,
         })
     }
 
@@ -167,6 +247,8 @@ impl DatabaseConfig {
 /// Redis configuration
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RedisConfig {
+    /// Only true when REDIS_URL is set explicitly
+    pub enabled: bool,
     pub url: String,
     pub max_connections: u32,
     pub connection_timeout: u64,
@@ -175,6 +257,7 @@ pub struct RedisConfig {
 impl RedisConfig {
     fn load() -> Result<Self, Box<dyn std::error::Error>> {
         Ok(RedisConfig {
+            enabled: env::var("REDIS_URL").is_ok(),
             url: env::var("REDIS_URL")
                 .unwrap_or_else(|_| "redis://localhost:6379".to_string()),
             max_connections: env::var("REDIS_MAX_CONNECTIONS")
@@ -218,11 +301,50 @@ impl JwtConfig {
     }
 }
 
+/// Per-account login protection configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LoginProtectionConfig {
+    /// Consecutive failures after which the account is locked
+    pub lockout_threshold: u32,
+    /// Consecutive failures tolerated before retry delays kick in
+    pub delay_after_failures: u32,
+    pub base_delay_ms: u64,
+    pub max_delay_ms: u64,
+    /// Failed logins are padded to at least this duration
+    pub min_failure_response_ms: u64,
+}
+
+impl LoginProtectionConfig {
+    fn load() -> Result<Self, Box<dyn std::error::Error>> {
+        Ok(LoginProtectionConfig {
+            lockout_threshold: env::var("LOGIN_LOCKOUT_THRESHOLD")
+                .unwrap_or_else(|_| "10".to_string())
+                .parse()?,
+            delay_after_failures: env::var("LOGIN_DELAY_AFTER_FAILURES")
+                .unwrap_or_else(|_| "3".to_string())
+                .parse()?,
+            base_delay_ms: env::var("LOGIN_BASE_DELAY_MS")
+                .unwrap_or_else(|_| "1000".to_string())
+                .parse()?, // 1 second
+            max_delay_ms: env::var("LOGIN_MAX_DELAY_MS")
+                .unwrap_or_else(|_| "300000".to_string())
+                .parse()?, // 5 minutes
+            min_failure_response_ms: env::var("LOGIN_MIN_FAILURE_RESPONSE_MS")
+                .unwrap_or_else(|_| "500".to_string())
+                .parse()?,
+        })
+    }
+}
+
 /// OIDC configuration
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct OidcConfig {
     pub enabled: bool,
     pub providers: Vec<OidcProvider>,
+    /// Store the pictures of new accounts as uploaded avatars instead of linking them
+    pub mirror_avatars: bool,
+    /// Seconds a login may take between starting and the provider's callback
+    pub flow_ttl: u64,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -242,11 +364,19 @@ impl OidcConfig {
             .unwrap_or_else(|_| "false".to_string())
             .parse()
             .unwrap_or(false);
+        let mirror_avatars = env::var("OIDC_MIRROR_AVATARS")
+            .unwrap_or_else(|_| "false".to_string())
+            .parse()?;
+        let flow_ttl = env::var("OIDC_FLOW_TTL")
+            .unwrap_or_else(|_| "600".to_string())
+            .parse()?;
 
         if !enabled {
             return Ok(OidcConfig {
                 enabled: false,
                 providers: vec![],
+                mirror_avatars,
+                flow_ttl,
             });
         }
 
@@ -281,7 +411,7 @@ impl OidcConfig {
             i += 1;
         }
 
-        Ok(OidcConfig { enabled, providers })
+        Ok(OidcConfig { enabled, providers, mirror_avatars, flow_ttl })
     }
 }
 
@@ -292,6 +422,31 @@ pub struct WebSocketConfig {
     pub max_connections: usize,
     pub heartbeat_interval: u64,
     pub message_size_limit: usize,
+    /// Hours raw operations of ended sessions are kept after compaction
+    pub operation_retention_hours: u64,
+    /// Seconds a session join code stays valid
+    pub join_code_ttl: u64,
+    /// Failed session joins after which the session stops accepting secrets
+    pub join_max_failures: u32,
+    /// Seconds a session stays locked after too many failed joins
+    pub join_lockout: u64,
+    /// Operations, cursor and focus updates one connection may send per second
+    pub rate_operations_per_second: f64,
+    pub rate_operations_burst: u32,
+    /// Chat messages one connection may send per second
+    pub rate_chat_per_second: f64,
+    pub rate_chat_burst: u32,
+    /// Session joins one connection may attempt per minute
+    pub rate_joins_per_minute: f64,
+    pub rate_joins_burst: u32,
+    /// Rate limited messages within 10 seconds before the connection is closed
+    pub rate_max_violations: u32,
+    /// Authenticated connections one user may hold; beyond it their idlest is closed
+    pub max_connections_per_user: usize,
+    /// Minutes before a scheduled session starts that invitees are reminded; 0 turns reminders off
+    pub session_reminder_minutes: u64,
+    /// Whether session guests who cannot read the project count as active in it
+    pub presence_count_guests: bool,
 }
 
 impl WebSocketConfig {
@@ -309,6 +464,48 @@ impl WebSocketConfig {
             message_size_limit: env::var("WEBSOCKET_MESSAGE_SIZE_LIMIT")
                 .unwrap_or_else(|_| "65536".to_string())
                 .parse()?,
+            operation_retention_hours: env::var("WEBSOCKET_OPERATION_RETENTION_HOURS")
+                .unwrap_or_else(|_| "72".to_string())
+                .parse()?, // 3 days
+            join_code_ttl: env::var("WEBSOCKET_JOIN_CODE_TTL")
+                .unwrap_or_else(|_| "600".to_string())
+                .parse()?, // 10 minutes
+            join_max_failures: env::var("WEBSOCKET_JOIN_MAX_FAILURES")
+                .unwrap_or_else(|_| "10".to_string())
+                .parse()?,
+            join_lockout: env::var("WEBSOCKET_JOIN_LOCKOUT")
+                .unwrap_or_else(|_| "900".to_string())
+                .parse()?, // 15 minutes
+            rate_operations_per_second: env::var("WEBSOCKET_RATE_OPERATIONS")
+                .unwrap_or_else(|_| "200".to_string())
+                .parse()?,
+            rate_operations_burst: env::var("WEBSOCKET_RATE_OPERATIONS_BURST")
+                .unwrap_or_else(|_| "500".to_string())
+                .parse()?,
+            rate_chat_per_second: env::var("WEBSOCKET_RATE_CHAT")
+                .unwrap_or_else(|_| "2".to_string())
+                .parse()?,
+            rate_chat_burst: env::var("WEBSOCKET_RATE_CHAT_BURST")
+                .unwrap_or_else(|_| "5".to_string())
+                .parse()?,
+            rate_joins_per_minute: env::var("WEBSOCKET_RATE_JOINS_PER_MINUTE")
+                .unwrap_or_else(|_| "6".to_string())
+                .parse()?,
+            rate_joins_burst: env::var("WEBSOCKET_RATE_JOINS_BURST")
+                .unwrap_or_else(|_| "3".to_string())
+                .parse()?,
+            rate_max_violations: env::var("WEBSOCKET_RATE_MAX_VIOLATIONS")
+                .unwrap_or_else(|_| "50".to_string())
+                .parse()?,
+            max_connections_per_user: env::var("WEBSOCKET_MAX_CONNECTIONS_PER_USER")
+                .unwrap_or_else(|_| "8".to_string())
+                .parse()?,
+            session_reminder_minutes: env::var("WEBSOCKET_SESSION_REMINDER_MINUTES")
+                .unwrap_or_else(|_| "15".to_string())
+                .parse()?,
+            presence_count_guests: env::var("WEBSOCKET_PRESENCE_COUNT_GUESTS")
+                .unwrap_or_else(|_| "false".to_string())
+                .parse()?,
         })
     }
 
@@ -326,6 +523,18 @@ pub struct LatexConfig {
     pub temp_dir: String,
     pub engines: Vec<String>,
     pub default_engine: String,
+    /// latexindent binary used by the formatter; the built-in formatter is used when unset
+    pub latexindent_path: Option<String>,
+    /// Bytes of working directories kept between compiles for incremental
+    /// builds; 0 builds every job from scratch
+    pub build_cache_size: u64,
+    /// Pages of the primary PDF rendered to preview thumbnails after a
+    /// successful compile; 0 disables thumbnails
+    pub thumbnail_pages: u32,
+    /// PDFs larger than this many bytes are not thumbnailed
+    pub thumbnail_max_pdf_size: u64,
+    /// Seconds one job's thumbnails may take before the rest are skipped
+    pub thumbnail_budget: u64,
 }
 
 impl LatexConfig {
@@ -349,6 +558,19 @@ impl LatexConfig {
                 .collect(),
             default_engine: env::var("LATEX_DEFAULT_ENGINE")
                 .unwrap_or_else(|_| "pdflatex".to_string()),
+            latexindent_path: env::var("LATEXINDENT_PATH").ok().filter(|path| !path.is_empty()),
+            build_cache_size: env::var("LATEX_BUILD_CACHE_SIZE")
+                .unwrap_or_else(|_| "2147483648".to_string())
+                .parse()?, // 2 GB
+            thumbnail_pages: env::var("LATEX_THUMBNAIL_PAGES")
+                .unwrap_or_else(|_| "20".to_string())
+                .parse()?,
+            thumbnail_max_pdf_size: env::var("LATEX_THUMBNAIL_MAX_PDF_SIZE")
+                .unwrap_or_else(|_| "52428800".to_string())
+                .parse()?, // 50 MB
+            thumbnail_budget: env::var("LATEX_THUMBNAIL_BUDGET")
+                .unwrap_or_else(|_| "20".to_string())
+                .parse()?,
         })
     }
 }
@@ -358,10 +580,22 @@ impl LatexConfig {
 pub struct EmailConfig {
     pub smtp_host: String,
     pub smtp_port: u16,
+    /// "starttls", "tls" or "none"
+    pub smtp_tls: String,
     pub smtp_username: String,
     pub smtp_password: String,
     pub from_address: String,
     pub from_name: String,
+    /// Name of this instance in the email layout
+    pub instance_name: String,
+    /// Frontend address that links in emails point to
+    pub frontend_url: String,
+    /// Emails waiting for the sender before new ones go to the dead-letter log
+    pub queue_capacity: usize,
+    /// Delivery attempts of one email before it goes to the dead-letter log
+    pub max_attempts: u32,
+    /// Write rendered emails to this directory instead of sending them
+    pub dev_dir: Option<String>,
 }
 
 impl EmailConfig {
@@ -372,6 +606,8 @@ impl EmailConfig {
             smtp_port: env::var("SMTP_PORT")
                 .unwrap_or_else(|_| "587".to_string())
                 .parse()?,
+            smtp_tls: env::var("SMTP_TLS")
+                .unwrap_or_else(|_| "starttls".to_string()),
             smtp_username: env::var("SMTP_USERNAME")
                 .unwrap_or_else(|_| "".to_string()),
             smtp_password: env::var("SMTP_PASSWORD")
@@ -380,6 +616,188 @@ impl EmailConfig {
                 .unwrap_or_else(|_| "noreply@texler.dev".to_string()),
             from_name: env::var("EMAIL_FROM_NAME")
                 .unwrap_or_else(|_| "Texler".to_string()),
+            instance_name: env::var("INSTANCE_NAME")
+                .unwrap_or_else(|_| "Texler".to_string()),
+            frontend_url: env::var("FRONTEND_URL")
+                .unwrap_or_else(|_| "http://localhost:3000".to_string())
+                .trim_end_matches('/')
+                .to_string(),
+            queue_capacity: env::var("EMAIL_QUEUE_CAPACITY")
+                .unwrap_or_else(|_| "1000".to_string())
+                .parse()?,
+            max_attempts: env::var("EMAIL_MAX_ATTEMPTS")
+                .unwrap_or_else(|_| "5".to_string())
+                .parse()?,
+            dev_dir: env::var("EMAIL_DEV_DIR").ok().filter(|dir| !dir.is_empty()),
+        })
+    }
+
+    /// Whether emails leave the server, over SMTP or into the development directory
+    pub fn delivers(&self, features: &FeaturesConfig) -> bool {
+        self.dev_dir.is_some() || (features.email && !self.smtp_host.is_empty())
+    }
+}
+
+/// Outbound HTTP configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HttpClientConfig {
+    /// Seconds to establish a connection
+    pub connect_timeout: u64,
+    /// Seconds a response may stall between reads
+    pub read_timeout: u64,
+    /// Retries of an idempotent request after the first attempt
+    pub max_retries: u32,
+    /// Proxy for all outbound requests; HTTPS_PROXY and NO_PROXY are honored when unset
+    pub proxy: Option<String>,
+    /// Consecutive failures after which a destination is skipped
+    pub circuit_failure_threshold: u32,
+    /// Seconds a destination is skipped before it is tried again
+    pub circuit_cooldown: u64,
+}
+
+impl HttpClientConfig {
+    fn load() -> Result<Self, Box<dyn std::error::Error>> {
+        Ok(HttpClientConfig {
+            connect_timeout: env::var("HTTP_CLIENT_CONNECT_TIMEOUT")
+                .unwrap_or_else(|_| "5".to_string())
+                .parse()?,
+            read_timeout: env::var("HTTP_CLIENT_READ_TIMEOUT")
+                .unwrap_or_else(|_| "30".to_string())
+                .parse()?,
+            max_retries: env::var("HTTP_CLIENT_MAX_RETRIES")
+                .unwrap_or_else(|_| "2".to_string())
+                .parse()?,
+            proxy: env::var("HTTP_CLIENT_PROXY").ok().filter(|proxy| !proxy.is_empty()),
+            circuit_failure_threshold: env::var("HTTP_CLIENT_CIRCUIT_THRESHOLD")
+                .unwrap_or_else(|_| "5".to_string())
+                .parse()?,
+            circuit_cooldown: env::var("HTTP_CLIENT_CIRCUIT_COOLDOWN")
+                .unwrap_or_else(|_| "30".to_string())
+                .parse()?,
+        })
+    }
+}
+
+/// Per-project limits
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LimitsConfig {
+    /// Collaborators and pending invitations a project may have, 0 for no limit
+    pub max_collaborators_per_project: u64,
+}
+
+impl LimitsConfig {
+    fn load() -> Result<Self, Box<dyn std::error::Error>> {
+        Ok(LimitsConfig {
+            max_collaborators_per_project: env::var("LIMIT_MAX_COLLABORATORS_PER_PROJECT")
+                .unwrap_or_else(|_| "50".to_string())
+                .parse()?,
+        })
+    }
+
+    /// The collaborator limit, if there is one
+    pub fn max_collaborators(&self) -> Option<u64> {
+        (self.max_collaborators_per_project > 0).then_some(self.max_collaborators_per_project)
+    }
+}
+
+/// Malware scanning of uploads
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScanConfig {
+    /// "none" or "clamav"
+    pub backend: String,
+    /// clamd socket: `tcp://host:port`, `host:port` or `unix:/path`
+    pub clamd_address: String,
+    /// Files larger than this many bytes are accepted unscanned and flagged
+    pub max_size: u64,
+    /// Seconds a single scan may take
+    pub timeout: u64,
+}
+
+impl ScanConfig {
+    fn load() -> Result<Self, Box<dyn std::error::Error>> {
+        Ok(ScanConfig {
+            backend: env::var("SCAN_BACKEND").unwrap_or_else(|_| "none".to_string()),
+            clamd_address: env::var("SCAN_CLAMD_ADDRESS")
+                .unwrap_or_else(|_| "tcp://127.0.0.1:3310".to_string()),
+            max_size: env::var("SCAN_MAX_SIZE")
+                .unwrap_or_else(|_| "26214400".to_string())
+                .parse()?, // 25 MB, clamd's default StreamMaxLength
+            timeout: env::var("SCAN_TIMEOUT")
+                .unwrap_or_else(|_| "60".to_string())
+                .parse()?,
+        })
+    }
+}
+
+/// Encryption of project secrets
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SecretsConfig {
+    /// Base64 of the 32-byte key new secrets are encrypted with; project
+    /// secrets are disabled while unset
+    pub master_key: Option<String>,
+    /// Keys replaced by `master_key`, still accepted for decryption until the
+    /// rekey task has moved every secret to the current key
+    pub previous_master_keys: Vec<String>,
+}
+
+impl SecretsConfig {
+    fn load() -> Result<Self, Box<dyn std::error::Error>> {
+        Ok(SecretsConfig {
+            master_key: env::var("SECRETS_MASTER_KEY").ok().filter(|key| !key.is_empty()),
+            previous_master_keys: env::var("SECRETS_PREVIOUS_MASTER_KEYS")
+                .unwrap_or_default()
+                .split(',')
+                .map(|key| key.trim().to_string())
+                .filter(|key| !key.is_empty())
+                .collect(),
+        })
+    }
+}
+
+/// Project imports from git repositories
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GitImportConfig {
+    pub enabled: bool,
+    /// Hosts repositories may be cloned from; anything else is refused, so
+    /// imports cannot reach internal git servers
+    pub allowed_hosts: Vec<String>,
+    /// git executable to clone with
+    pub git_binary: String,
+    /// Most bytes an import may bring in; larger repositories are refused
+    pub max_total_size: u64,
+    /// Most files an import may bring in; larger repositories are refused
+    pub max_files: u64,
+    /// Larger files are left out of the import
+    pub max_file_size: u64,
+    /// Seconds the clone may take
+    pub timeout: u64,
+}
+
+impl GitImportConfig {
+    fn load() -> Result<Self, Box<dyn std::error::Error>> {
+        Ok(GitImportConfig {
+            enabled: env::var("GIT_IMPORT_ENABLED")
+                .unwrap_or_else(|_| "true".to_string())
+                .parse()?,
+            allowed_hosts: env::var("GIT_IMPORT_ALLOWED_HOSTS")
+                .unwrap_or_else(|_| "github.com,gitlab.com,bitbucket.org,codeberg.org".to_string())
+                .split(',')
+                .map(|host| host.trim().to_lowercase())
+                .filter(|host| !host.is_empty())
+                .collect(),
+            git_binary: env::var("GIT_IMPORT_GIT_BINARY").unwrap_or_else(|_| "git".to_string()),
+            max_total_size: env::var("GIT_IMPORT_MAX_TOTAL_SIZE")
+                .unwrap_or_else(|_| "104857600".to_string())
+                .parse()?, // 100MB
+            max_files: env::var("GIT_IMPORT_MAX_FILES")
+                .unwrap_or_else(|_| "2000".to_string())
+                .parse()?,
+            max_file_size: env::var("GIT_IMPORT_MAX_FILE_SIZE")
+                .unwrap_or_else(|_| "26214400".to_string())
+                .parse()?, // 25MB
+            timeout: env::var("GIT_IMPORT_TIMEOUT")
+                .unwrap_or_else(|_| "300".to_string())
+                .parse()?,
         })
     }
 }
@@ -392,6 +810,8 @@ pub struct FeaturesConfig {
     pub search: bool,
     pub email: bool,
     pub latex_compilation: bool,
+    /// Whether anyone may sign up with a password
+    pub registration: bool,
     pub file_storage: FileStorageConfig,
     pub rate_limiting: bool,
     pub metrics: bool,
@@ -403,6 +823,35 @@ pub struct FileStorageConfig {
     pub local_path: String,
     pub s3_bucket: Option<String>,
     pub s3_region: Option<String>,
+    pub max_upload_size: u64,
+    pub project_quota: u64,
+    pub upload_chunk_size: u64,
+    pub upload_max_chunk_size: u64,
+    pub upload_session_ttl: u64,
+    /// Largest project the full file tree is still built for
+    pub tree_full_max_files: u64,
+    /// Largest archive accepted for import, compressed and uncompressed
+    pub import_max_size: u64,
+    /// Files a reindex reads per batch
+    pub reindex_batch_size: u32,
+    /// Milliseconds a reindex waits between batches
+    pub reindex_pause_ms: u64,
+    /// Largest image or PDF accepted as a chat attachment
+    pub attachment_max_size: u64,
+    /// Largest file checked against its content hash when downloaded or
+    /// exported; 0 turns the check off. Compiles always check.
+    pub verify_max_size: u64,
+    /// Live files a project may have
+    pub max_project_files: u64,
+    /// Folders a file may be nested in
+    pub max_path_depth: u64,
+    /// Bytes of a file's path
+    pub max_path_length: u64,
+    /// What imports and clones do with files over a project limit
+    pub import_limit_mode: String, // "skip" or "strict"
+    /// Versions a blame looks back through; older lines are attributed to
+    /// the history before them
+    pub blame_max_versions: u32,
 }
 
 impl FeaturesConfig {
@@ -423,6 +872,9 @@ impl FeaturesConfig {
             latex_compilation: env::var("FEATURE_LATEX_COMPILATION")
                 .unwrap_or_else(|_| "true".to_string())
                 .parse()?,
+            registration: env::var("FEATURE_REGISTRATION")
+                .unwrap_or_else(|_| "true".to_string())
+                .parse()?,
             file_storage: FileStorageConfig {
                 type_: env::var("FILE_STORAGE_TYPE")
                     .unwrap_or_else(|_| "local".to_string()),
@@ -430,6 +882,56 @@ impl FeaturesConfig {
                     .unwrap_or_else(|_| "./uploads".to_string()),
                 s3_bucket: env::var("AWS_S3_BUCKET").ok(),
                 s3_region: env::var("AWS_REGION").ok(),
+                max_upload_size: env::var("FILE_STORAGE_MAX_UPLOAD_SIZE")
+                    .unwrap_or_else(|_| "1073741824".to_string())
+                    .parse()?, // 1 GB
+                project_quota: env::var("FILE_STORAGE_PROJECT_QUOTA")
+                    .unwrap_or_else(|_| "2147483648".to_string())
+                    .parse()?, // 2 GB
+                upload_chunk_size: env::var("FILE_UPLOAD_CHUNK_SIZE")
+                    .unwrap_or_else(|_| "8388608".to_string())
+                    .parse()?, // 8 MB
+                upload_max_chunk_size: env::var("FILE_UPLOAD_MAX_CHUNK_SIZE")
+                    .unwrap_or_else(|_| "33554432".to_string())
+                    .parse()?, // 32 MB
+                upload_session_ttl: env::var("FILE_UPLOAD_SESSION_TTL")
+                    .unwrap_or_else(|_| "86400".to_string())
+                    .parse()?, // 24 hours
+                tree_full_max_files: env::var("FILE_TREE_FULL_MAX_FILES")
+                    .unwrap_or_else(|_| "2000".to_string())
+                    .parse()?,
+                import_max_size: env::var("FILE_IMPORT_MAX_SIZE")
+                    .unwrap_or_else(|_| "104857600".to_string())
+                    .parse()?, // 100MB
+                reindex_batch_size: env::var("FILE_REINDEX_BATCH_SIZE")
+                    .unwrap_or_else(|_| "200".to_string())
+                    .parse()?,
+                reindex_pause_ms: env::var("FILE_REINDEX_PAUSE_MS")
+                    .unwrap_or_else(|_| "100".to_string())
+                    .parse()?,
+                attachment_max_size: env::var("FILE_ATTACHMENT_MAX_SIZE")
+                    .unwrap_or_else(|_| "10485760".to_string())
+                    .parse()?, // 10MB
+                verify_max_size: env::var("FILE_VERIFY_MAX_SIZE")
+                    .unwrap_or_else(|_| "52428800".to_string())
+                    .parse()?, // 50MB
+                max_project_files: env::var("FILE_MAX_PROJECT_FILES")
+                    .unwrap_or_else(|_| "5000".to_string())
+                    .parse()?,
+                max_path_depth: env::var("FILE_MAX_PATH_DEPTH")
+                    .unwrap_or_else(|_| "20".to_string())
+                    .parse()?,
+                max_path_length: env::var("FILE_MAX_PATH_LENGTH")
+                    .unwrap_or_else(|_| "1024".to_string())
+                    .parse()?,
+                import_limit_mode: match env::var("FILE_IMPORT_LIMIT_MODE").as_deref() {
+                    Ok("strict") => "strict".to_string(),
+                    Ok("skip") | Err(_) => "skip".to_string(),
+                    Ok(other) => return Err(format!("Unknown import limit mode: {}", other).into()),
+                },
+                blame_max_versions: env::var("FILE_BLAME_MAX_VERSIONS")
+                    .unwrap_or_else(|_| "200".to_string())
+                    .parse()?,
             },
             rate_limiting: env::var("FEATURE_RATE_LIMITING")
                 .unwrap_or_else(|_| "true".to_string())
@@ -447,6 +949,11 @@ pub struct LoggingConfig {
     pub level: String,
     pub format: String, // "json" or "compact"
     pub file: Option<String>,
+    /// Collector traces are exported to, see `telemetry`
+    pub otlp_endpoint: Option<String>,
+    /// Share of edit conflicts stored in `conflict_events`, from 0 to 1;
+    /// the conflict counters always count every one
+    pub conflict_sample_rate: f64,
 }
 
 impl LoggingConfig {
@@ -457,6 +964,11 @@ impl LoggingConfig {
             format: env::var("LOG_FORMAT")
                 .unwrap_or_else(|_| "json".to_string()),
             file: env::var("LOG_FILE").ok(),
+            otlp_endpoint: env::var("OTLP_ENDPOINT").ok().filter(|endpoint| !endpoint.is_empty()),
+            conflict_sample_rate: env::var("LOG_CONFLICT_SAMPLE_RATE")
+                .unwrap_or_else(|_| "1.0".to_string())
+                .parse::<f64>()?
+                .clamp(0.0, 1.0),
         })
     }
 }
\ No newline at end of file
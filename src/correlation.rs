@@ -0,0 +1,131 @@
+//! Correlation of work with the request that caused it
+//!
+//! The request ID middleware runs each request in a `request` span carrying
+//! its ID, and in a scope that [`current_request_id`] reads. Work that
+//! outlives the request keeps the ID: [`spawn`] starts a task in the
+//! spawning span and scope, compile jobs store it in
+//! `compilation_jobs.request_id` and their worker spans carry it, queued
+//! emails remember it for their delivery span, and outbound requests send it
+//! as `X-Request-Id`. Searching the logs for a request ID, or the exported
+//! traces for the `request_id` attribute, finds all of it.
+//!
+//! With the `otlp` feature compiled in and `FEATURE_METRICS` on, spans are
+//! exported to `OTLP_ENDPOINT`. Incoming `traceparent` headers become the
+//! parent of the request span and outbound requests carry the current one,
+//! so traces continue across services.
+
+use axum::http::{HeaderMap, HeaderValue, Method, Uri};
+use std::future::Future;
+use tokio::task::JoinHandle;
+use tracing::{Instrument, Span};
+
+use crate::error::RequestId;
+
+/// Header carrying the request ID in both directions
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+tokio::task_local! {
+    static REQUEST_ID: RequestId;
+}
+
+/// ID of the request the calling code runs for, if any
+pub fn current_request_id() -> Option<RequestId> {
+    REQUEST_ID.try_with(|id| *id).ok()
+}
+
+/// Run `future` on behalf of a request, or outside any when there is none
+pub async fn scope<F: Future>(request_id: Option<RequestId>, future: F) -> F::Output {
+    match request_id {
+        Some(request_id) => REQUEST_ID.scope(request_id, future).await,
+        None => future.await,
+    }
+}
+
+/// Spawn a task that stays in the current span and request
+pub fn spawn<F>(future: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    tokio::spawn(scope(current_request_id(), future.instrument(Span::current())))
+}
+
+/// Span of an HTTP request, continuing the caller's trace when exported
+pub fn request_span(request_id: RequestId, method: &Method, uri: &Uri, headers: &HeaderMap) -> Span {
+    // `user_id` is filled in once the caller is authenticated
+    let span = tracing::info_span!(
+        "request",
+        request_id = %request_id,
+        method = %method,
+        uri = %uri,
+        user_id = tracing::field::Empty,
+    );
+    #[cfg(feature = "otlp")]
+    {
+        use tracing_opentelemetry::OpenTelemetrySpanExt;
+        let parent = opentelemetry::global::get_text_map_propagator(|propagator| {
+            propagator.extract(&crate::telemetry::HeaderExtractor(headers))
+        });
+        span.set_parent(parent);
+    }
+    #[cfg(not(feature = "otlp"))]
+    let _ = headers;
+    span
+}
+
+/// Mark an outbound request with the current request and trace
+pub fn tag_outbound(headers: &mut HeaderMap) {
+    if let Some(request_id) = current_request_id() {
+        if !headers.contains_key(REQUEST_ID_HEADER) {
+            if let Ok(value) = HeaderValue::from_str(&request_id.to_string()) {
+                headers.insert(REQUEST_ID_HEADER, value);
+            }
+        }
+    }
+    #[cfg(feature = "otlp")]
+    {
+        use tracing_opentelemetry::OpenTelemetrySpanExt;
+        let context = Span::current().context();
+        opentelemetry::global::get_text_map_propagator(|propagator| {
+            propagator.inject_context(&context, &mut crate::telemetry::HeaderInjector(headers))
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{create_test_job, create_test_project, create_test_user, TestDb};
+
+    #[tokio::test]
+    async fn test_spawned_tasks_keep_the_request() {
+        assert!(current_request_id().is_none());
+
+        let request_id = RequestId::generate();
+        let spawned = scope(Some(request_id), async {
+            let mut headers = HeaderMap::new();
+            tag_outbound(&mut headers);
+            assert_eq!(headers[REQUEST_ID_HEADER], request_id.to_string().as_str());
+
+            spawn(async { current_request_id().map(|id| id.0) })
+        })
+        .await;
+        assert_eq!(spawned.await.unwrap(), Some(request_id.0));
+
+        let mut headers = HeaderMap::new();
+        tag_outbound(&mut headers);
+        assert!(headers.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_jobs_are_stamped_with_the_request() {
+        let Some(db) = TestDb::start().await else { return };
+        let owner = create_test_user(&db.pool).await;
+        let project = create_test_project(&db.pool, &owner, false).await;
+
+        let request_id = RequestId::generate();
+        let job = scope(Some(request_id), create_test_job(&db.pool, &project, &owner)).await;
+        assert_eq!(job.request_id, Some(request_id.0));
+        assert_eq!(create_test_job(&db.pool, &project, &owner).await.request_id, None);
+    }
+}
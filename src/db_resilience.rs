@@ -0,0 +1,552 @@
+//! Riding out database outages
+//!
+//! When Postgres restarts, for example in a maintenance window of a managed
+//! database, open connections drop and new ones are refused for a while.
+//! Errors are classified first: a refused, reset or timed out connection is
+//! retryable ([`is_retryable`]) and reaches clients as a 503
+//! `DATABASE_UNAVAILABLE`, while a violated constraint is not. Idempotent
+//! reads on hot paths go through [`DbResilience::read`], which retries them
+//! twice with jittered backoff. Writes no request depends on — the activity
+//! log, the project access log, onboarding milestones and notifications —
+//! go through [`DbResilience::write`]: they run in the background, and one
+//! failing for a retryable reason waits in a bounded in-memory buffer until
+//! [`DbFlushTask`] finds the database back. A full buffer drops its oldest
+//! writes, and buffered writes do not survive a restart.
+//!
+//! Consecutive retryable failures open a circuit breaker. While it is open,
+//! `/health/ready` fails at once instead of queueing behind connections that
+//! will time out, reads are not retried and new best-effort writes go
+//! straight to the buffer. After the cooldown the flush task probes the
+//! database; the first success closes the circuit. Retries, buffer depth,
+//! dropped writes and the breaker state are exported at `/admin/metrics`.
+
+use futures::future::BoxFuture;
+use prometheus::{IntCounter, IntCounterVec, IntGauge, Opts, Registry, TextEncoder};
+use sqlx::PgPool;
+use std::collections::VecDeque;
+use std::future::Future;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+use tokio::task::JoinHandle;
+
+use crate::config::DatabaseConfig;
+use crate::error::AppError;
+use crate::http_client::{backoff_delay, CircuitBreaker};
+use crate::server::AppState;
+
+/// Retries of an idempotent read
+const MAX_READ_RETRIES: u32 = 2;
+
+/// Delay before the first retry of a read, doubled for the second
+const READ_BACKOFF: Duration = Duration::from_millis(50);
+
+/// Time between two attempts to flush buffered writes
+const FLUSH_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Whether `error` comes from losing or not getting a connection, so the
+/// same statement may well succeed a moment later
+pub fn is_retryable(error: &sqlx::Error) -> bool {
+    use std::io::ErrorKind;
+
+    match error {
+        sqlx::Error::Io(e) => matches!(
+            e.kind(),
+            ErrorKind::ConnectionRefused
+                | ErrorKind::ConnectionReset
+                | ErrorKind::ConnectionAborted
+                | ErrorKind::NotConnected
+                | ErrorKind::BrokenPipe
+                | ErrorKind::UnexpectedEof
+                | ErrorKind::TimedOut
+        ),
+        sqlx::Error::PoolTimedOut | sqlx::Error::WorkerCrashed => true,
+        // Class 08 is connection exceptions; 57P01-03 a server shutting
+        // down, crashed or still starting
+        sqlx::Error::Database(e) => e
+            .code()
+            .is_some_and(|code| code.starts_with("08") || matches!(code.as_ref(), "57P01" | "57P02" | "57P03")),
+        _ => false,
+    }
+}
+
+/// Retries, circuit breaking and the write buffer of a `DbResilience`
+#[derive(Debug, Clone)]
+pub struct DbResilienceSettings {
+    pub max_retries: u32,
+    pub base_backoff: Duration,
+    pub circuit_failure_threshold: u32,
+    pub circuit_cooldown: Duration,
+    pub buffer_capacity: usize,
+}
+
+impl From<&DatabaseConfig> for DbResilienceSettings {
+    fn from(config: &DatabaseConfig) -> Self {
+        Self {
+            max_retries: MAX_READ_RETRIES,
+            base_backoff: READ_BACKOFF,
+            circuit_failure_threshold: config.circuit_failure_threshold.max(1),
+            circuit_cooldown: Duration::from_secs(config.circuit_cooldown),
+            buffer_capacity: config.write_buffer_capacity,
+        }
+    }
+}
+
+/// A best-effort write, run again from the buffer until it gets through
+struct PendingWrite {
+    /// Kind of write, for the dropped writes metric and the log
+    label: &'static str,
+    write: Box<dyn Fn(PgPool) -> BoxFuture<'static, Result<(), AppError>> + Send + Sync>,
+}
+
+/// Outage metrics
+struct DbMetrics {
+    registry: Registry,
+    retries: IntCounter,
+    buffered: IntGauge,
+    flushed: IntCounter,
+    dropped: IntCounterVec,
+    circuit_open: IntGauge,
+}
+
+impl DbMetrics {
+    fn new() -> Self {
+        let retries = IntCounter::new("texler_db_retries_total", "Reads retried after losing the database connection")
+            .expect("valid metric");
+        let buffered = IntGauge::new("texler_db_buffered_writes", "Best-effort writes waiting for the database")
+            .expect("valid metric");
+        let flushed = IntCounter::new("texler_db_flushed_writes_total", "Buffered writes that got through later")
+            .expect("valid metric");
+        let dropped = IntCounterVec::new(
+            Opts::new("texler_db_dropped_writes_total", "Best-effort writes given up, by kind"),
+            &["kind"],
+        )
+        .expect("valid metric");
+        let circuit_open = IntGauge::new("texler_db_circuit_open", "1 while the database circuit breaker is open")
+            .expect("valid metric");
+
+        let registry = Registry::new();
+        registry.register(Box::new(retries.clone())).expect("unique metric");
+        registry.register(Box::new(buffered.clone())).expect("unique metric");
+        registry.register(Box::new(flushed.clone())).expect("unique metric");
+        registry.register(Box::new(dropped.clone())).expect("unique metric");
+        registry.register(Box::new(circuit_open.clone())).expect("unique metric");
+
+        Self {
+            registry,
+            retries,
+            buffered,
+            flushed,
+            dropped,
+            circuit_open,
+        }
+    }
+}
+
+/// Database outage handling shared by all requests
+pub struct DbResilience {
+    db: PgPool,
+    settings: DbResilienceSettings,
+    breaker: Mutex<CircuitBreaker>,
+    buffer: Mutex<VecDeque<PendingWrite>>,
+    /// Held while the buffer is flushed, so writes keep their order
+    flushing: tokio::sync::Mutex<()>,
+    /// Best-effort writes started and not yet written or buffered
+    in_flight: AtomicUsize,
+    settled: Notify,
+    metrics: DbMetrics,
+}
+
+impl DbResilience {
+    pub fn new(settings: DbResilienceSettings, db: PgPool) -> Self {
+        Self {
+            db,
+            settings,
+            breaker: Mutex::new(CircuitBreaker::default()),
+            buffer: Mutex::new(VecDeque::new()),
+            flushing: tokio::sync::Mutex::new(()),
+            in_flight: AtomicUsize::new(0),
+            settled: Notify::new(),
+            metrics: DbMetrics::new(),
+        }
+    }
+
+    /// Run the idempotent read `read`, again after a retryable failure
+    ///
+    /// Gives up after `max_retries` retries, or at once while the circuit is
+    /// open, returning the last error.
+    pub async fn read<T, F, Fut>(&self, mut read: F) -> Result<T, AppError>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, AppError>>,
+    {
+        let mut attempt = 0;
+        loop {
+            match read().await {
+                Err(e) if e.is_retryable() => {
+                    let open = self.record(false);
+                    if open || attempt >= self.settings.max_retries {
+                        return Err(e);
+                    }
+                    tokio::time::sleep(backoff_delay(self.settings.base_backoff, attempt)).await;
+                    self.metrics.retries.inc();
+                    attempt += 1;
+                }
+                // Any answer, even an error, shows the database is there
+                result => {
+                    self.record(true);
+                    return result;
+                }
+            }
+        }
+    }
+
+    /// Run the best-effort write `write` in the background
+    ///
+    /// The caller never sees the outcome. A retryable failure, or an open
+    /// circuit, puts the write in the buffer; other failures are logged and
+    /// the write dropped. `write` may run more than once, so it must be
+    /// idempotent or tolerate a repeat after a connection lost mid-commit.
+    pub fn write<W, Fut>(self: &Arc<Self>, label: &'static str, write: W) -> JoinHandle<()>
+    where
+        W: Fn(PgPool) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), AppError>> + Send + 'static,
+    {
+        let pending = PendingWrite {
+            label,
+            write: Box::new(move |db| Box::pin(write(db))),
+        };
+        self.in_flight.fetch_add(1, Ordering::SeqCst);
+        let resilience = self.clone();
+        crate::correlation::spawn(async move {
+            if resilience.open_for().is_some() {
+                resilience.buffer(pending);
+            } else {
+                match (pending.write)(resilience.db.clone()).await {
+                    Ok(()) => {
+                        resilience.record(true);
+                    }
+                    Err(e) if e.is_retryable() => {
+                        resilience.record(false);
+                        tracing::debug!("Buffering {} write until the database is back: {}", label, e);
+                        resilience.buffer(pending);
+                    }
+                    Err(e) => {
+                        tracing::warn!("Dropping {} write: {}", label, e);
+                        resilience.metrics.dropped.with_label_values(&[label]).inc();
+                    }
+                }
+            }
+            if resilience.in_flight.fetch_sub(1, Ordering::SeqCst) == 1 {
+                resilience.settled.notify_waiters();
+            }
+        })
+    }
+
+    /// Write the buffered writes in order, stopping at the first retryable
+    /// failure; returns the number written
+    pub async fn flush(&self) -> usize {
+        let _flushing = self.flushing.lock().await;
+        let mut written = 0;
+        while let Some(pending) = self.pop() {
+            match (pending.write)(self.db.clone()).await {
+                Ok(()) => {
+                    self.record(true);
+                    self.metrics.flushed.inc();
+                    written += 1;
+                }
+                Err(e) if e.is_retryable() => {
+                    self.record(false);
+                    self.lock_buffer().push_front(pending);
+                    self.update_depth();
+                    break;
+                }
+                Err(e) => {
+                    self.record(true);
+                    tracing::warn!("Dropping buffered {} write: {}", pending.label, e);
+                    self.metrics.dropped.with_label_values(&[pending.label]).inc();
+                }
+            }
+        }
+        written
+    }
+
+    /// Probe the database when the circuit allows it and flush the buffer
+    /// once it answers
+    pub async fn recover(&self) -> Result<usize, AppError> {
+        let open = self.lock_breaker().is_open();
+        if !open && self.buffered() == 0 {
+            return Ok(0);
+        }
+        if !self.lock_breaker().allows(Instant::now()) {
+            return Ok(0);
+        }
+
+        let probe = sqlx::query_scalar::<_, i32>("SELECT 1").fetch_one(&self.db).await;
+        let reachable = !matches!(&probe, Err(e) if is_retryable(e));
+        self.record(reachable);
+        probe.map_err(AppError::Database)?;
+        Ok(self.flush().await)
+    }
+
+    /// Time until the next probe while the circuit is open
+    pub fn open_for(&self) -> Option<Duration> {
+        self.lock_breaker().open_for(Instant::now())
+    }
+
+    /// Record the outcome of a readiness check of the database
+    pub fn record_probe(&self, reachable: bool) {
+        self.record(reachable);
+    }
+
+    /// Writes waiting in the buffer
+    pub fn buffered(&self) -> usize {
+        self.lock_buffer().len()
+    }
+
+    /// Wait until every write started so far is written, buffered or dropped
+    #[cfg(test)]
+    pub async fn settled(&self) {
+        loop {
+            let settled = self.settled.notified();
+            if self.in_flight.load(Ordering::SeqCst) == 0 {
+                return;
+            }
+            settled.await;
+        }
+    }
+
+    /// Metrics in the Prometheus text format
+    pub fn metrics_text(&self) -> String {
+        let mut text = String::new();
+        if let Err(e) = TextEncoder::new().encode_utf8(&self.metrics.registry.gather(), &mut text) {
+            tracing::warn!("Failed to encode database metrics: {}", e);
+        }
+        text
+    }
+
+    /// Record a success or retryable failure; returns whether the circuit
+    /// is open afterwards
+    fn record(&self, success: bool) -> bool {
+        let mut breaker = self.lock_breaker();
+        let was_open = breaker.is_open();
+        breaker.record(
+            success,
+            Instant::now(),
+            self.settings.circuit_failure_threshold,
+            self.settings.circuit_cooldown,
+        );
+        let open = breaker.is_open();
+        if open != was_open {
+            if open {
+                tracing::error!("Database unreachable, opening the circuit");
+            } else {
+                tracing::info!("Database reachable again, closing the circuit");
+            }
+        }
+        self.metrics.circuit_open.set(open as i64);
+        open
+    }
+
+    fn buffer(&self, pending: PendingWrite) {
+        let mut buffer = self.lock_buffer();
+        if buffer.len() >= self.settings.buffer_capacity {
+            if let Some(oldest) = buffer.pop_front() {
+                tracing::warn!("Write buffer full, dropping a {} write", oldest.label);
+                self.metrics.dropped.with_label_values(&[oldest.label]).inc();
+            }
+        }
+        if self.settings.buffer_capacity > 0 {
+            buffer.push_back(pending);
+        } else {
+            self.metrics.dropped.with_label_values(&[pending.label]).inc();
+        }
+        self.metrics.buffered.set(buffer.len() as i64);
+    }
+
+    fn pop(&self) -> Option<PendingWrite> {
+        let mut buffer = self.lock_buffer();
+        let pending = buffer.pop_front();
+        self.metrics.buffered.set(buffer.len() as i64);
+        pending
+    }
+
+    fn update_depth(&self) {
+        self.metrics.buffered.set(self.buffered() as i64);
+    }
+
+    fn lock_breaker(&self) -> std::sync::MutexGuard<'_, CircuitBreaker> {
+        self.breaker.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn lock_buffer(&self) -> std::sync::MutexGuard<'_, VecDeque<PendingWrite>> {
+        self.buffer.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Probes the database while the circuit is open and flushes buffered writes
+pub struct DbFlushTask;
+
+impl crate::tasks::PeriodicTask for DbFlushTask {
+    fn name(&self) -> &'static str {
+        "db_write_flush"
+    }
+
+    fn interval(&self) -> Duration {
+        FLUSH_INTERVAL
+    }
+
+    fn run<'a>(&'a self, state: &'a AppState) -> BoxFuture<'a, Result<(), AppError>> {
+        Box::pin(async move {
+            let written = state.db_resilience.recover().await?;
+            if written > 0 {
+                tracing::info!("Wrote {} buffered writes", written);
+            }
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::project::ProjectActivity;
+    use crate::testing::{create_test_project, create_test_user, test_state, DbProxy, TestDb};
+    use axum::{extract::State, http::StatusCode, response::IntoResponse};
+    use std::sync::atomic::AtomicU32;
+
+    fn settings() -> DbResilienceSettings {
+        DbResilienceSettings {
+            max_retries: 2,
+            base_backoff: Duration::from_millis(5),
+            circuit_failure_threshold: 2,
+            circuit_cooldown: Duration::from_millis(300),
+            buffer_capacity: 10,
+        }
+    }
+
+    #[test]
+    fn test_classifies_errors() {
+        use std::io::{Error, ErrorKind};
+
+        for kind in [ErrorKind::ConnectionRefused, ErrorKind::ConnectionReset, ErrorKind::UnexpectedEof] {
+            assert!(is_retryable(&sqlx::Error::Io(Error::from(kind))), "{:?}", kind);
+        }
+        assert!(is_retryable(&sqlx::Error::PoolTimedOut));
+        assert!(!is_retryable(&sqlx::Error::RowNotFound));
+        assert!(!is_retryable(&sqlx::Error::PoolClosed));
+        assert!(!is_retryable(&sqlx::Error::Io(Error::from(ErrorKind::PermissionDenied))));
+
+        let lost = AppError::Database(sqlx::Error::PoolTimedOut);
+        assert!(lost.is_retryable());
+        assert_eq!(lost.status_code(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(lost.error_code(), "DATABASE_UNAVAILABLE");
+        assert_eq!(AppError::Database(sqlx::Error::RowNotFound).status_code(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_reads_retry_after_lost_connection() {
+        let Some(db) = TestDb::start().await else { return };
+        let (proxy, pool) = DbProxy::start(&db).await;
+        let resilience = DbResilience::new(settings(), pool.clone());
+
+        // The connection is cut while the first attempt waits for its answer
+        let attempts = AtomicU32::new(0);
+        let cut = {
+            let proxy = proxy.clone();
+            tokio::spawn(async move {
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                proxy.sever();
+            })
+        };
+        let answer = resilience
+            .read(|| {
+                attempts.fetch_add(1, Ordering::SeqCst);
+                let pool = pool.clone();
+                async move {
+                    sqlx::query_scalar::<_, i32>("SELECT 7 FROM pg_sleep(0.4)")
+                        .fetch_one(&pool)
+                        .await
+                        .map_err(AppError::Database)
+                }
+            })
+            .await
+            .unwrap();
+        cut.await.unwrap();
+        assert_eq!(answer, 7);
+        assert_eq!(attempts.load(Ordering::SeqCst), 2);
+        assert!(resilience.metrics_text().contains("texler_db_retries_total 1"));
+
+        // Other errors are returned at once
+        let attempts = AtomicU32::new(0);
+        let missing = resilience
+            .read(|| {
+                attempts.fetch_add(1, Ordering::SeqCst);
+                let pool = pool.clone();
+                async move {
+                    sqlx::query_scalar::<_, i32>("SELECT 1 WHERE false")
+                        .fetch_one(&pool)
+                        .await
+                        .map_err(AppError::Database)
+                }
+            })
+            .await;
+        assert!(matches!(missing, Err(AppError::Database(sqlx::Error::RowNotFound))));
+        assert_eq!(attempts.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_buffered_writes_drain_once_after_outage() {
+        let Some(db) = TestDb::start().await else { return };
+        let owner = create_test_user(&db.pool).await;
+        let project = create_test_project(&db.pool, &owner, false).await;
+        let (proxy, pool) = DbProxy::start(&db).await;
+        let resilience = Arc::new(DbResilience::new(settings(), pool));
+        let mut state = test_state(&db).await;
+        state.db_resilience = resilience.clone();
+        let log = |n: usize| {
+            ProjectActivity::log_later(&resilience, project.id, owner.id, "outage_test", "project", None, Some(n.to_string()))
+        };
+
+        proxy.refuse(true);
+        proxy.sever();
+        // Writes return without waiting; two failures open the circuit, the
+        // third write is buffered without trying
+        for n in 0..3 {
+            log(n).await.unwrap();
+        }
+        assert_eq!(resilience.buffered(), 3);
+        assert!(resilience.open_for().is_some());
+        let metrics = resilience.metrics_text();
+        assert!(metrics.contains("texler_db_buffered_writes 3"));
+        assert!(metrics.contains("texler_db_circuit_open 1"));
+
+        // Readiness fails without waiting for a connection
+        let started = Instant::now();
+        let response = crate::handlers::health::readiness(State(state.clone())).await.into_response();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert!(started.elapsed() < Duration::from_millis(100));
+
+        // Nothing is flushed before the cooldown, everything once after it
+        proxy.refuse(false);
+        assert_eq!(resilience.recover().await.unwrap(), 0);
+        tokio::time::sleep(Duration::from_millis(350)).await;
+        assert_eq!(resilience.recover().await.unwrap(), 3);
+        assert_eq!(resilience.recover().await.unwrap(), 0);
+        assert_eq!(resilience.buffered(), 0);
+        assert!(resilience.open_for().is_none());
+        let metrics = resilience.metrics_text();
+        assert!(metrics.contains("texler_db_circuit_open 0"));
+        assert!(metrics.contains("texler_db_flushed_writes_total 3"));
+
+        let logged = sqlx::query_scalar::<_, String>(
+            "SELECT details FROM project_activity WHERE project_id = $1 AND action = 'outage_test' ORDER BY details",
+        )
+        .bind(project.id)
+        .fetch_all(&db.pool)
+        .await
+        .unwrap();
+        assert_eq!(logged, ["0", "1", "2"]);
+    }
+}
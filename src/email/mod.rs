@@ -0,0 +1,13 @@
+//! Outgoing email
+//!
+//! Every email the server sends is a typed message rendered from handlebars
+//! templates (`templates`), in the recipient's language where a translation
+//! exists, and delivered by a background sender (`outbox`) so request
+//! handlers never wait on SMTP. With `EMAIL_DEV_DIR` set, emails are written
+//! to that directory instead of being sent.
+
+pub mod outbox;
+pub mod templates;
+
+pub use outbox::Mailer;
+pub use templates::{EmailTemplate, EmailTemplates, Locale, RenderedEmail};
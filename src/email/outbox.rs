@@ -0,0 +1,634 @@
+//! Queued email delivery
+//!
+//! Handlers never talk to SMTP themselves: `Mailer::send` renders the message
+//! and puts it on a bounded queue, and one sender task delivers the queue in
+//! order. A failed delivery is retried with a growing delay; an email that
+//! keeps failing, or that the server rejects outright, ends up in the
+//! `email_dead_letters` table instead of being dropped.
+//!
+//! On shutdown the sender stops between deliveries. Emails still waiting,
+//! including one that was between retries, are written to the dead-letter
+//! table marked `requeue`, and the next sender to start delivers them first,
+//! so an SMTP outage spanning a restart loses nothing. A full queue spills
+//! into the table the same way.
+
+use chrono::{DateTime, Utc};
+use futures::future::BoxFuture;
+use lettre::message::{Mailbox, MultiPart};
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use sqlx::FromRow;
+use std::collections::VecDeque;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::sync::{mpsc, watch};
+use tokio::task::JoinHandle;
+use tracing::{debug, error, info, warn, Instrument};
+use uuid::Uuid;
+
+use super::templates::{EmailTemplate, EmailTemplates, Locale, RenderedEmail};
+use crate::config::{Config, EmailConfig};
+use crate::correlation::current_request_id;
+use crate::error::AppError;
+
+/// Delay before the first retry, doubled for every further one
+const RETRY_BASE_DELAY: Duration = Duration::from_secs(2);
+
+/// Longest delay between two attempts
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(300);
+
+/// A rendered email waiting for delivery
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutgoingEmail {
+    pub to: String,
+    /// Template it was rendered from, for logs and dead letters
+    pub template: String,
+    pub content: RenderedEmail,
+    /// Request the email was sent for, logged with its delivery
+    pub request_id: Option<Uuid>,
+}
+
+/// Why a delivery failed
+#[derive(Debug, Clone)]
+pub struct DeliveryError {
+    pub message: String,
+    /// Retrying cannot help, e.g. the recipient was rejected
+    pub permanent: bool,
+}
+
+impl DeliveryError {
+    pub fn transient(message: impl Into<String>) -> Self {
+        Self { message: message.into(), permanent: false }
+    }
+
+    pub fn permanent(message: impl Into<String>) -> Self {
+        Self { message: message.into(), permanent: true }
+    }
+}
+
+/// Hands emails over for delivery
+pub trait EmailTransport: Send + Sync + 'static {
+    /// Name for logs
+    fn name(&self) -> &'static str;
+
+    fn send<'a>(&'a self, email: &'a OutgoingEmail) -> BoxFuture<'a, Result<(), DeliveryError>>;
+}
+
+/// Delivers over SMTP
+pub struct SmtpTransport {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+}
+
+impl SmtpTransport {
+    pub fn from_config(config: &EmailConfig) -> Result<Self, AppError> {
+        let relay_error = |e| AppError::Config(format!("Invalid SMTP host {}: {}", config.smtp_host, e));
+        let builder = match config.smtp_tls.as_str() {
+            "starttls" => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&config.smtp_host).map_err(relay_error)?,
+            "tls" => AsyncSmtpTransport::<Tokio1Executor>::relay(&config.smtp_host).map_err(relay_error)?,
+            "none" => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&config.smtp_host),
+            other => return Err(AppError::Config(format!("Unknown SMTP TLS mode: {}", other))),
+        };
+
+        let mut builder = builder.port(config.smtp_port);
+        if !config.smtp_username.is_empty() {
+            builder = builder.credentials(Credentials::new(
+                config.smtp_username.clone(),
+                config.smtp_password.clone(),
+            ));
+        }
+
+        let address = config
+            .from_address
+            .parse()
+            .map_err(|e| AppError::Config(format!("Invalid sender address {}: {}", config.from_address, e)))?;
+
+        Ok(Self {
+            transport: builder.build(),
+            from: Mailbox::new(Some(config.from_name.clone()), address),
+        })
+    }
+}
+
+impl EmailTransport for SmtpTransport {
+    fn name(&self) -> &'static str {
+        "smtp"
+    }
+
+    fn send<'a>(&'a self, email: &'a OutgoingEmail) -> BoxFuture<'a, Result<(), DeliveryError>> {
+        Box::pin(async move {
+            let to: Mailbox = email
+                .to
+                .parse()
+                .map_err(|e| DeliveryError::permanent(format!("Invalid recipient {}: {}", email.to, e)))?;
+
+            let message = Message::builder()
+                .from(self.from.clone())
+                .to(to)
+                .subject(&email.content.subject)
+                .multipart(MultiPart::alternative_plain_html(
+                    email.content.text.clone(),
+                    email.content.html.clone(),
+                ))
+                .map_err(|e| DeliveryError::permanent(format!("Failed to build the message: {}", e)))?;
+
+            self.transport.send(message).await.map(|_| ()).map_err(|e| DeliveryError {
+                message: e.to_string(),
+                permanent: e.is_permanent(),
+            })
+        })
+    }
+}
+
+/// Development transport writing each email to `<dir>/<time>-<template>-<id>.txt` and `.html`
+pub struct DirectoryTransport {
+    dir: PathBuf,
+}
+
+impl DirectoryTransport {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+}
+
+impl EmailTransport for DirectoryTransport {
+    fn name(&self) -> &'static str {
+        "directory"
+    }
+
+    fn send<'a>(&'a self, email: &'a OutgoingEmail) -> BoxFuture<'a, Result<(), DeliveryError>> {
+        Box::pin(async move {
+            let write_error = |e: std::io::Error| DeliveryError::transient(format!("Failed to write email: {}", e));
+            tokio::fs::create_dir_all(&self.dir).await.map_err(write_error)?;
+
+            let stem = format!(
+                "{}-{}-{}",
+                Utc::now().format("%Y%m%dT%H%M%S"),
+                email.template,
+                Uuid::new_v4().simple()
+            );
+            let text = format!("To: {}\nSubject: {}\n\n{}", email.to, email.content.subject, email.content.text);
+            tokio::fs::write(self.dir.join(format!("{}.txt", stem)), text).await.map_err(write_error)?;
+            tokio::fs::write(self.dir.join(format!("{}.html", stem)), &email.content.html)
+                .await
+                .map_err(write_error)?;
+
+            Ok(())
+        })
+    }
+}
+
+/// Email that was not delivered
+#[derive(Debug, Clone, FromRow)]
+pub struct DeadLetter {
+    pub id: Uuid,
+    pub recipient: String,
+    pub template: String,
+    pub subject: String,
+    pub text_body: String,
+    pub html_body: String,
+    pub attempts: i32,
+    pub error: String,
+    /// Delivered again when the next sender starts
+    pub requeue: bool,
+    pub request_id: Option<Uuid>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl DeadLetter {
+    pub async fn record(
+        db: &sqlx::PgPool,
+        email: &OutgoingEmail,
+        attempts: u32,
+        error: &str,
+        requeue: bool,
+    ) -> Result<(), AppError> {
+        sqlx::query(
+            r#"
+            INSERT INTO email_dead_letters
+                (recipient, template, subject, text_body, html_body, attempts, error, requeue, request_id)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+            "#
+        )
+        .bind(&email.to)
+        .bind(&email.template)
+        .bind(&email.content.subject)
+        .bind(&email.content.text)
+        .bind(&email.content.html)
+        .bind(attempts as i32)
+        .bind(error)
+        .bind(requeue)
+        .bind(email.request_id)
+        .execute(db)
+        .await
+        .map_err(AppError::Database)?;
+
+        Ok(())
+    }
+
+    /// Remove and return the emails waiting to be delivered again, oldest first
+    pub async fn take_requeued(db: &sqlx::PgPool) -> Result<Vec<Self>, AppError> {
+        let mut letters = sqlx::query_as::<_, DeadLetter>("DELETE FROM email_dead_letters WHERE requeue RETURNING *")
+            .fetch_all(db)
+            .await
+            .map_err(AppError::Database)?;
+
+        letters.sort_by_key(|letter| letter.created_at);
+        Ok(letters)
+    }
+
+    pub fn into_email(self) -> OutgoingEmail {
+        OutgoingEmail {
+            to: self.recipient,
+            template: self.template,
+            content: RenderedEmail {
+                subject: self.subject,
+                text: self.text_body,
+                html: self.html_body,
+            },
+            request_id: self.request_id,
+        }
+    }
+}
+
+/// Delay after the `attempt`th failed attempt
+pub fn retry_delay(attempt: u32) -> Duration {
+    RETRY_BASE_DELAY
+        .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+        .min(MAX_RETRY_DELAY)
+}
+
+/// Renders emails and queues them for the sender task
+pub struct Mailer {
+    templates: EmailTemplates,
+    enabled: bool,
+    db: sqlx::PgPool,
+    max_attempts: u32,
+    queue: mpsc::Sender<OutgoingEmail>,
+    receiver: Mutex<Option<mpsc::Receiver<OutgoingEmail>>>,
+    shutdown: watch::Sender<bool>,
+    handle: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl std::fmt::Debug for Mailer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Mailer").field("enabled", &self.enabled).finish()
+    }
+}
+
+impl Mailer {
+    pub fn new(config: &Config, db: sqlx::PgPool) -> Result<Self, AppError> {
+        let (queue, receiver) = mpsc::channel(config.email.queue_capacity.max(1));
+
+        Ok(Self {
+            templates: EmailTemplates::new(&config.email.instance_name)?,
+            enabled: config.email.delivers(&config.features),
+            db,
+            max_attempts: config.email.max_attempts.max(1),
+            queue,
+            receiver: Mutex::new(Some(receiver)),
+            shutdown: watch::channel(false).0,
+            handle: Mutex::new(None),
+        })
+    }
+
+    /// Whether emails are sent at all; when not, `send` does nothing
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn templates(&self) -> &EmailTemplates {
+        &self.templates
+    }
+
+    /// Render `message` in `locale` and queue it for `to`
+    pub async fn send<T: EmailTemplate>(&self, to: &str, locale: Locale, message: &T) -> Result<(), AppError> {
+        if !self.enabled {
+            return Ok(());
+        }
+
+        let content = self.templates.render(locale, message)?;
+        self.enqueue(OutgoingEmail {
+            to: to.to_string(),
+            template: T::NAME.to_string(),
+            content,
+            request_id: current_request_id().map(|id| id.0),
+        })
+        .await
+    }
+
+    /// Queue bodies rendered elsewhere, wrapped in the layout
+    pub async fn send_rendered(
+        &self,
+        to: &str,
+        locale: Locale,
+        template: &str,
+        content: RenderedEmail,
+    ) -> Result<(), AppError> {
+        if !self.enabled {
+            return Ok(());
+        }
+
+        let content = self.templates.layout(locale, content)?;
+        self.enqueue(OutgoingEmail {
+            to: to.to_string(),
+            template: template.to_string(),
+            content,
+            request_id: current_request_id().map(|id| id.0),
+        })
+        .await
+    }
+
+    async fn enqueue(&self, email: OutgoingEmail) -> Result<(), AppError> {
+        match self.queue.try_send(email) {
+            Ok(()) => Ok(()),
+            // Never block a request on a backed-up sender
+            Err(mpsc::error::TrySendError::Full(email) | mpsc::error::TrySendError::Closed(email)) => {
+                warn!("Email queue unavailable, deferring '{}' to {}", email.content.subject, email.to);
+                DeadLetter::record(&self.db, &email, 0, "Email queue was full", true).await
+            }
+        }
+    }
+
+    /// Start the sender task with the configured transport
+    pub fn start(&self, config: &EmailConfig) -> Result<(), AppError> {
+        let transport: Box<dyn EmailTransport> = match &config.dev_dir {
+            Some(dir) => Box::new(DirectoryTransport::new(dir)),
+            None => Box::new(SmtpTransport::from_config(config)?),
+        };
+        self.start_with(transport);
+        Ok(())
+    }
+
+    /// Start the sender task; does nothing when it was started before
+    pub(crate) fn start_with(&self, transport: Box<dyn EmailTransport>) {
+        let Some(queue) = self.receiver.lock().unwrap().take() else {
+            warn!("Email sender started twice");
+            return;
+        };
+
+        info!("Starting email sender ({})", transport.name());
+        let sender = SenderTask {
+            transport,
+            db: self.db.clone(),
+            max_attempts: self.max_attempts,
+            shutdown: self.shutdown.subscribe(),
+        };
+        *self.handle.lock().unwrap() = Some(tokio::spawn(sender.run(queue)));
+    }
+
+    /// Stop the sender after its current attempt, waiting up to `grace`
+    pub async fn shutdown(&self, grace: Duration) {
+        let _ = self.shutdown.send(true);
+
+        let Some(handle) = self.handle.lock().unwrap().take() else {
+            return;
+        };
+        let abort = handle.abort_handle();
+        if tokio::time::timeout(grace, handle).await.is_err() {
+            warn!("Email sender did not stop within {:?}, aborting it", grace);
+            abort.abort();
+        }
+    }
+}
+
+/// The task delivering the queue
+struct SenderTask {
+    transport: Box<dyn EmailTransport>,
+    db: sqlx::PgPool,
+    max_attempts: u32,
+    shutdown: watch::Receiver<bool>,
+}
+
+impl SenderTask {
+    async fn run(mut self, mut queue: mpsc::Receiver<OutgoingEmail>) {
+        // Emails deferred by the last shutdown or a full queue go first
+        let mut pending: VecDeque<OutgoingEmail> = match DeadLetter::take_requeued(&self.db).await {
+            Ok(letters) => letters.into_iter().map(DeadLetter::into_email).collect(),
+            Err(e) => {
+                error!("Failed to load deferred emails: {}", e);
+                VecDeque::new()
+            }
+        };
+
+        loop {
+            if self.stopping() {
+                break;
+            }
+
+            let email = match pending.pop_front() {
+                Some(email) => email,
+                None => tokio::select! {
+                    email = queue.recv() => match email {
+                        Some(email) => email,
+                        None => break,
+                    },
+                    _ = self.shutdown.changed() => break,
+                },
+            };
+
+            let span = tracing::info_span!(
+                "email_delivery",
+                template = %email.template,
+                request_id = email.request_id.map(tracing::field::display)
+            );
+            if !self.deliver(email).instrument(span).await {
+                break;
+            }
+        }
+
+        // Keep what is still waiting for the next start
+        queue.close();
+        while let Ok(email) = queue.try_recv() {
+            pending.push_back(email);
+        }
+        if !pending.is_empty() {
+            info!("Deferring {} undelivered emails until the next start", pending.len());
+        }
+        for email in pending {
+            self.dead_letter(&email, 0, "Sender stopped before delivery", true).await;
+        }
+
+        debug!("Email sender stopped");
+    }
+
+    /// Deliver one email, retrying; false when shutdown interrupted the retries
+    async fn deliver(&mut self, email: OutgoingEmail) -> bool {
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            let error = match self.transport.send(&email).await {
+                Ok(()) => {
+                    debug!("Sent email '{}' to {}", email.content.subject, email.to);
+                    return true;
+                }
+                Err(error) => error,
+            };
+
+            if error.permanent || attempt >= self.max_attempts {
+                warn!(
+                    "Giving up on email '{}' to {} after {} attempts: {}",
+                    email.content.subject, email.to, attempt, error.message
+                );
+                self.dead_letter(&email, attempt, &error.message, false).await;
+                return true;
+            }
+
+            debug!("Email to {} failed, retrying: {}", email.to, error.message);
+            let stopped = self.stopping() || tokio::select! {
+                _ = tokio::time::sleep(retry_delay(attempt)) => false,
+                _ = self.shutdown.changed() => true,
+            };
+            if stopped {
+                self.dead_letter(&email, attempt, &error.message, true).await;
+                return false;
+            }
+        }
+    }
+
+    fn stopping(&self) -> bool {
+        *self.shutdown.borrow()
+    }
+
+    async fn dead_letter(&self, email: &OutgoingEmail, attempts: u32, error: &str, requeue: bool) {
+        if let Err(e) = DeadLetter::record(&self.db, email, attempts, error, requeue).await {
+            error!("Lost email '{}' to {}: {}", email.content.subject, email.to, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{test_config, TestDb};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    fn email(to: &str) -> OutgoingEmail {
+        OutgoingEmail {
+            to: to.to_string(),
+            template: "notification".to_string(),
+            content: RenderedEmail {
+                subject: "Upload quarantined".to_string(),
+                text: "Removed.\n".to_string(),
+                html: "<p>Removed.</p>\n".to_string(),
+            },
+            request_id: None,
+        }
+    }
+
+    /// An SMTP server that is down
+    struct FailingTransport {
+        attempts: Arc<AtomicUsize>,
+    }
+
+    impl EmailTransport for FailingTransport {
+        fn name(&self) -> &'static str {
+            "failing"
+        }
+
+        fn send<'a>(&'a self, _email: &'a OutgoingEmail) -> BoxFuture<'a, Result<(), DeliveryError>> {
+            self.attempts.fetch_add(1, Ordering::SeqCst);
+            Box::pin(async { Err(DeliveryError::transient("Connection refused")) })
+        }
+    }
+
+    struct RecordingTransport {
+        sent: Arc<Mutex<Vec<OutgoingEmail>>>,
+    }
+
+    impl EmailTransport for RecordingTransport {
+        fn name(&self) -> &'static str {
+            "recording"
+        }
+
+        fn send<'a>(&'a self, email: &'a OutgoingEmail) -> BoxFuture<'a, Result<(), DeliveryError>> {
+            self.sent.lock().unwrap().push(email.clone());
+            Box::pin(async { Ok(()) })
+        }
+    }
+
+    #[test]
+    fn test_retry_delay_grows_to_cap() {
+        assert_eq!(retry_delay(1), Duration::from_secs(2));
+        assert_eq!(retry_delay(2), Duration::from_secs(4));
+        assert_eq!(retry_delay(5), Duration::from_secs(32));
+        assert_eq!(retry_delay(9), MAX_RETRY_DELAY);
+        assert_eq!(retry_delay(u32::MAX), MAX_RETRY_DELAY);
+    }
+
+    #[tokio::test]
+    async fn test_directory_transport_writes_both_bodies() {
+        let dir = tempfile::tempdir().unwrap();
+        DirectoryTransport::new(dir.path()).send(&email("ada@example.com")).await.unwrap();
+
+        let mut names: Vec<String> = std::fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        names.sort();
+        assert_eq!(names.len(), 2);
+        assert!(names[0].ends_with(".html") && names[1].ends_with(".txt"));
+        assert!(names[0].contains("-notification-"));
+
+        let text = std::fs::read_to_string(dir.path().join(&names[1])).unwrap();
+        assert_eq!(text, "To: ada@example.com\nSubject: Upload quarantined\n\nRemoved.\n");
+    }
+
+    #[tokio::test]
+    async fn test_queued_emails_survive_smtp_outage_across_shutdown() {
+        let Some(db) = TestDb::start().await else { return };
+        let mut config = test_config();
+        config.features.email = true;
+        config.email.smtp_host = "smtp.invalid".to_string();
+        config.email.dev_dir = None;
+
+        let recipients: Vec<String> = (0..3).map(|i| format!("{}-{}@example.com", Uuid::new_v4(), i)).collect();
+
+        let mailer = Mailer::new(&config, db.pool.clone()).unwrap();
+        let attempts = Arc::new(AtomicUsize::new(0));
+        mailer.start_with(Box::new(FailingTransport { attempts: attempts.clone() }));
+        for to in &recipients {
+            mailer.send_rendered(to, Locale::En, "notification", email(to).content).await.unwrap();
+        }
+
+        // The first email is now waiting for its retry, the others in the queue
+        while attempts.load(Ordering::SeqCst) == 0 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        mailer.shutdown(Duration::from_secs(10)).await;
+
+        let deferred = sqlx::query_scalar::<_, i64>(
+            "SELECT COUNT(*) FROM email_dead_letters WHERE requeue AND recipient = ANY($1)"
+        )
+        .bind(&recipients)
+        .fetch_one(&db.pool)
+        .await
+        .unwrap();
+        assert_eq!(deferred, 3);
+
+        // Once SMTP is back, the next sender delivers all of them
+        let mailer = Mailer::new(&config, db.pool.clone()).unwrap();
+        let sent = Arc::new(Mutex::new(Vec::new()));
+        mailer.start_with(Box::new(RecordingTransport { sent: sent.clone() }));
+        tokio::time::timeout(Duration::from_secs(10), async {
+            while sent.lock().unwrap().iter().filter(|email| recipients.contains(&email.to)).count() < 3 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("deferred emails should be delivered");
+        mailer.shutdown(Duration::from_secs(10)).await;
+
+        let delivered: Vec<String> = sent.lock().unwrap().iter().map(|email| email.to.clone()).collect();
+        assert_eq!(delivered.iter().filter(|to| recipients.contains(to)).count(), 3);
+
+        let left = sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM email_dead_letters WHERE recipient = ANY($1)")
+            .bind(&recipients)
+            .fetch_one(&db.pool)
+            .await
+            .unwrap();
+        assert_eq!(left, 0);
+    }
+}
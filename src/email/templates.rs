@@ -0,0 +1,453 @@
+//! Email templates
+//!
+//! Each message type is a struct implementing `EmailTemplate`; its fields are
+//! what the templates may use, and strict mode turns a misspelt field into a
+//! render error instead of a blank. A message has three templates per locale
+//! under `templates/<locale>/`: `<name>.subject.hbs`, `<name>.txt.hbs` and
+//! `<name>.html.hbs`. Text templates are rendered without escaping, HTML
+//! templates escape every `{{value}}`, so user content such as project names
+//! cannot inject markup. The rendered bodies are wrapped in the locale's
+//! `layout` templates, which add the instance name and the footer.
+//!
+//! A locale lacking a template falls back to the English one, so a message
+//! can be added in English first and translated later.
+
+use handlebars::Handlebars;
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::error::AppError;
+
+/// Language of an email
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum Locale {
+    #[default]
+    En,
+    De,
+}
+
+impl Locale {
+    pub const ALL: [Self; 2] = [Self::En, Self::De];
+
+    pub fn code(self) -> &'static str {
+        match self {
+            Self::En => "en",
+            Self::De => "de",
+        }
+    }
+
+    /// Locale of a language tag such as `de-AT`; unknown languages get English
+    pub fn from_language(language: &str) -> Self {
+        let primary = language.split(['-', '_']).next().unwrap_or_default();
+        Self::ALL
+            .into_iter()
+            .find(|locale| primary.eq_ignore_ascii_case(locale.code()))
+            .unwrap_or_default()
+    }
+
+    /// Locale of a user's `language` preference
+    pub async fn for_user(db: &sqlx::PgPool, user_id: Uuid) -> Result<Self, AppError> {
+        let language = sqlx::query_scalar::<_, String>("SELECT language FROM user_preferences WHERE user_id = $1")
+            .bind(user_id)
+            .fetch_optional(db)
+            .await
+            .map_err(AppError::Database)?;
+
+        Ok(language.map(|language| Self::from_language(&language)).unwrap_or_default())
+    }
+}
+
+/// A message type with templates under `templates/<locale>/<NAME>.*.hbs`
+pub trait EmailTemplate: Serialize {
+    const NAME: &'static str;
+}
+
+/// Subject and bodies of an email
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RenderedEmail {
+    pub subject: String,
+    pub text: String,
+    pub html: String,
+}
+
+macro_rules! template_files {
+    ($($locale:literal: [$($name:literal),* $(,)?]),* $(,)?) => {
+        &[$($(
+            (concat!($locale, "/", $name), include_str!(concat!("templates/", $locale, "/", $name, ".hbs"))),
+        )*)*]
+    };
+}
+
+/// Every template, named `<locale>/<file>` without the `.hbs`
+const TEMPLATE_FILES: &[(&str, &str)] = template_files! {
+    "en": [
+        "layout.txt", "layout.html",
+        "notification.subject", "notification.txt", "notification.html",
+        "password_reset.subject", "password_reset.txt", "password_reset.html",
+        "project_invitation.subject", "project_invitation.txt", "project_invitation.html",
+        "compile_outcome.subject", "compile_outcome.txt", "compile_outcome.html",
+        "compile_digest.subject", "compile_digest.txt", "compile_digest.html",
+        "email_change_confirmation.subject", "email_change_confirmation.txt", "email_change_confirmation.html",
+        "email_change_notice.subject", "email_change_notice.txt", "email_change_notice.html",
+    ],
+    "de": [
+        "layout.txt", "layout.html",
+        "password_reset.subject", "password_reset.txt", "password_reset.html",
+        "project_invitation.subject", "project_invitation.txt", "project_invitation.html",
+        "compile_outcome.subject", "compile_outcome.txt", "compile_outcome.html",
+        "compile_digest.subject", "compile_digest.txt", "compile_digest.html",
+        "email_change_confirmation.subject", "email_change_confirmation.txt", "email_change_confirmation.html",
+        "email_change_notice.subject", "email_change_notice.txt", "email_change_notice.html",
+    ],
+};
+
+/// Registry of the compiled templates
+pub struct EmailTemplates {
+    instance_name: String,
+    text: Handlebars<'static>,
+    html: Handlebars<'static>,
+}
+
+impl std::fmt::Debug for EmailTemplates {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("EmailTemplates").field("instance_name", &self.instance_name).finish()
+    }
+}
+
+impl EmailTemplates {
+    pub fn new(instance_name: &str) -> Result<Self, AppError> {
+        let mut text = Handlebars::new();
+        text.set_strict_mode(true);
+        text.register_escape_fn(handlebars::no_escape);
+
+        let mut html = Handlebars::new();
+        html.set_strict_mode(true);
+        html.register_escape_fn(escape_html);
+
+        for (name, source) in TEMPLATE_FILES {
+            // Subjects are plain text
+            let registry = if name.ends_with(".html") { &mut html } else { &mut text };
+            registry
+                .register_template_string(name, source)
+                .map_err(|e| AppError::Config(format!("Invalid email template {}: {}", name, e)))?;
+        }
+
+        Ok(Self {
+            instance_name: instance_name.to_string(),
+            text,
+            html,
+        })
+    }
+
+    /// Render `message` in `locale`, wrapped in the layout
+    pub fn render<T: EmailTemplate>(&self, locale: Locale, message: &T) -> Result<RenderedEmail, AppError> {
+        let mut data = serde_json::to_value(message)?;
+        if let Some(fields) = data.as_object_mut() {
+            fields.insert("instance_name".to_string(), self.instance_name.clone().into());
+        }
+
+        let content = RenderedEmail {
+            subject: self.render_one(&self.text, locale, &format!("{}.subject", T::NAME), &data)?.trim().to_string(),
+            text: self.render_one(&self.text, locale, &format!("{}.txt", T::NAME), &data)?,
+            html: self.render_one(&self.html, locale, &format!("{}.html", T::NAME), &data)?,
+        };
+        self.layout(locale, content)
+    }
+
+    /// Wrap bodies rendered elsewhere in the layout; `content.html` is taken as markup
+    pub fn layout(&self, locale: Locale, content: RenderedEmail) -> Result<RenderedEmail, AppError> {
+        let data = serde_json::json!({
+            "instance_name": self.instance_name,
+            "locale": locale.code(),
+            "subject": content.subject,
+            "body": content.text,
+        });
+        let text = self.render_one(&self.text, locale, "layout.txt", &data)?;
+
+        let data = serde_json::json!({
+            "instance_name": self.instance_name,
+            "locale": locale.code(),
+            "subject": content.subject,
+            "body": content.html,
+        });
+        let html = self.render_one(&self.html, locale, "layout.html", &data)?;
+
+        Ok(RenderedEmail {
+            subject: content.subject,
+            text,
+            html,
+        })
+    }
+
+    fn render_one(
+        &self,
+        registry: &Handlebars<'static>,
+        locale: Locale,
+        file: &str,
+        data: &serde_json::Value,
+    ) -> Result<String, AppError> {
+        let mut name = format!("{}/{}", locale.code(), file);
+        if !registry.has_template(&name) {
+            name = format!("{}/{}", Locale::En.code(), file);
+        }
+
+        registry
+            .render(&name, data)
+            .map_err(|e| AppError::Internal(format!("Failed to render email template {}: {}", name, e)))
+    }
+}
+
+fn escape_html(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#x27;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// An in-app notification mirrored by email
+#[derive(Debug, Clone, Serialize)]
+pub struct NotificationEmail {
+    pub title: String,
+    pub body: String,
+}
+
+impl EmailTemplate for NotificationEmail {
+    const NAME: &'static str = "notification";
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PasswordResetEmail {
+    pub username: String,
+    pub reset_url: String,
+    pub expires_in_minutes: i64,
+}
+
+impl EmailTemplate for PasswordResetEmail {
+    const NAME: &'static str = "password_reset";
+}
+
+/// Sent to a new address to confirm that it should become the account's
+#[derive(Debug, Clone, Serialize)]
+pub struct EmailChangeConfirmationEmail {
+    pub username: String,
+    pub confirm_url: String,
+    pub expires_in_hours: i64,
+}
+
+impl EmailTemplate for EmailChangeConfirmationEmail {
+    const NAME: &'static str = "email_change_confirmation";
+}
+
+/// Sent to the old address once a change is confirmed, with a link to undo it
+#[derive(Debug, Clone, Serialize)]
+pub struct EmailChangeNoticeEmail {
+    pub username: String,
+    pub new_email: String,
+    pub undo_url: String,
+    pub undo_expires_in_hours: i64,
+}
+
+impl EmailTemplate for EmailChangeNoticeEmail {
+    const NAME: &'static str = "email_change_notice";
+}
+
+/// Invitation to a project; `signup` when the invitee has no account yet
+#[derive(Debug, Clone, Serialize)]
+pub struct InvitationEmail {
+    pub inviter: String,
+    pub project_name: String,
+    pub role: String,
+    pub link: String,
+    pub signup: bool,
+}
+
+impl EmailTemplate for InvitationEmail {
+    const NAME: &'static str = "project_invitation";
+}
+
+/// Outcome of one compilation, with its first diagnostics
+#[derive(Debug, Clone, Serialize)]
+pub struct CompileOutcomeEmail {
+    pub project_name: String,
+    pub succeeded: bool,
+    pub diagnostics: Vec<String>,
+}
+
+impl EmailTemplate for CompileOutcomeEmail {
+    const NAME: &'static str = "compile_outcome";
+}
+
+/// Compilation outcomes held back for a digest
+#[derive(Debug, Clone, Serialize)]
+pub struct CompileDigestEmail {
+    pub total: usize,
+    pub failed: usize,
+    pub entries: Vec<CompileDigestEntry>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CompileDigestEntry {
+    /// `HH:MM` the compilation finished at
+    pub time: String,
+    pub project_name: String,
+    pub succeeded: bool,
+}
+
+impl EmailTemplate for CompileDigestEmail {
+    const NAME: &'static str = "compile_digest";
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    /// Compare against `golden/<name>.<locale>.eml`; `UPDATE_GOLDEN=1` rewrites the file
+    fn assert_golden<T: EmailTemplate>(templates: &EmailTemplates, message: &T) {
+        for locale in Locale::ALL {
+            let email = templates.render(locale, message).unwrap();
+            let rendered = format!("Subject: {}\n\n{}--- html ---\n{}", email.subject, email.text, email.html);
+
+            let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+                .join("src/email/golden")
+                .join(format!("{}.{}.eml", T::NAME, locale.code()));
+            if std::env::var_os("UPDATE_GOLDEN").is_some() {
+                std::fs::write(&path, &rendered).unwrap();
+                continue;
+            }
+
+            let expected = std::fs::read_to_string(&path).unwrap();
+            assert_eq!(rendered, expected, "{} differs from {}", T::NAME, path.display());
+        }
+    }
+
+    #[test]
+    fn test_templates_match_golden_files() {
+        let templates = EmailTemplates::new("Texler").unwrap();
+
+        assert_golden(
+            &templates,
+            &NotificationEmail {
+                title: "Upload quarantined".to_string(),
+                body: "figure.png was flagged by the malware scanner & removed.".to_string(),
+            },
+        );
+        assert_golden(
+            &templates,
+            &PasswordResetEmail {
+                username: "ada".to_string(),
+                reset_url: "https://texler.example/reset-password?token=3f9a2c".to_string(),
+                expires_in_minutes: 60,
+            },
+        );
+        assert_golden(
+            &templates,
+            &EmailChangeConfirmationEmail {
+                username: "ada".to_string(),
+                confirm_url: "https://texler.example/confirm-email-change?token=8d41e0".to_string(),
+                expires_in_hours: 24,
+            },
+        );
+        assert_golden(
+            &templates,
+            &EmailChangeNoticeEmail {
+                username: "ada".to_string(),
+                new_email: "ada@new.example".to_string(),
+                undo_url: "https://texler.example/undo-email-change?token=c27f9b".to_string(),
+                undo_expires_in_hours: 48,
+            },
+        );
+        assert_golden(
+            &templates,
+            &InvitationEmail {
+                inviter: "grace".to_string(),
+                project_name: "Thesis".to_string(),
+                role: "collaborator".to_string(),
+                link: "https://texler.example/signup?invitation=7b1e".to_string(),
+                signup: true,
+            },
+        );
+        assert_golden(
+            &templates,
+            &CompileOutcomeEmail {
+                project_name: "Thesis".to_string(),
+                succeeded: false,
+                diagnostics: vec![
+                    "! Undefined control sequence.".to_string(),
+                    "LaTeX Warning: Reference `fig:a' undefined.".to_string(),
+                ],
+            },
+        );
+        assert_golden(
+            &templates,
+            &CompileDigestEmail {
+                total: 2,
+                failed: 1,
+                entries: vec![
+                    CompileDigestEntry {
+                        time: "09:14".to_string(),
+                        project_name: "Thesis".to_string(),
+                        succeeded: false,
+                    },
+                    CompileDigestEntry {
+                        time: "09:31".to_string(),
+                        project_name: "Thesis".to_string(),
+                        succeeded: true,
+                    },
+                ],
+            },
+        );
+    }
+
+    #[test]
+    fn test_html_escapes_user_content() {
+        let templates = EmailTemplates::new("Texler <dev>").unwrap();
+        let email = templates
+            .render(
+                Locale::En,
+                &CompileOutcomeEmail {
+                    project_name: "<script>alert('x')</script>".to_string(),
+                    succeeded: true,
+                    diagnostics: Vec::new(),
+                },
+            )
+            .unwrap();
+
+        assert!(email.html.contains("&lt;script&gt;alert(&#x27;x&#x27;)&lt;/script&gt;"));
+        assert!(email.html.contains("Texler &lt;dev&gt;"));
+        assert!(!email.html.contains("<script>"));
+        assert!(email.text.contains("\"<script>alert('x')</script>\" compiled successfully."));
+    }
+
+    #[test]
+    fn test_locale_falls_back_to_english() {
+        assert_eq!(Locale::from_language("de"), Locale::De);
+        assert_eq!(Locale::from_language("de-AT"), Locale::De);
+        assert_eq!(Locale::from_language("DE_ch"), Locale::De);
+        assert_eq!(Locale::from_language("fr"), Locale::En);
+        assert_eq!(Locale::from_language(""), Locale::En);
+
+        // No German notification template: English content in the German layout
+        let templates = EmailTemplates::new("Texler").unwrap();
+        let email = templates
+            .render(
+                Locale::De,
+                &NotificationEmail {
+                    title: "Upload quarantined".to_string(),
+                    body: "Removed.".to_string(),
+                },
+            )
+            .unwrap();
+        assert_eq!(email.subject, "Upload quarantined");
+        assert!(email.text.starts_with("Removed.\n"));
+        assert!(email.text.contains("Gesendet von Texler."));
+    }
+}
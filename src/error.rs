@@ -6,10 +6,15 @@ use axum::{
     response::{IntoResponse, Response},
     Json,
 };
-use serde_json::json;
+use serde::Serialize;
 use thiserror::Error;
+use utoipa::ToSchema;
 use uuid::Uuid;
 
+use crate::models::archive_checksum::ArchiveVerification;
+use crate::models::oidc_flow::OidcFailure;
+use crate::models::project_limits::ExceededLimit;
+
 /// Custom error types for the application
 #[derive(Error, Debug)]
 pub enum AppError {
@@ -81,6 +86,13 @@ pub enum AppError {
     #[error("Rate limit exceeded")]
     RateLimit,
 
+    /// Request exceeded its time budget
+    #[error("Request timed out after {after_secs} seconds")]
+    Timeout {
+        request_id: Option<RequestId>,
+        after_secs: u64,
+    },
+
     /// Bad request errors
     #[error("Bad request: {0}")]
     BadRequest(String),
@@ -96,6 +108,52 @@ pub enum AppError {
     /// Background job errors
     #[error("Job error: {0}")]
     Job(String),
+
+    /// Another service failed or could not be reached
+    #[error("Upstream error: {0}")]
+    Upstream(String),
+
+    /// An uploaded file was found to contain malware, named by its signature
+    #[error("File rejected: malware detected ({0})")]
+    MalwareDetected(String),
+
+    /// The project is frozen read-only, with the reason given when freezing it
+    #[error("Project is frozen: {0}")]
+    ProjectFrozen(String),
+
+    /// Stored content no longer matches its recorded hash
+    #[error("Stored content is corrupted: {0}")]
+    ContentCorrupted(String),
+
+    /// A scheduled collaboration session was joined before its start
+    #[error("Session has not started yet; it starts at {}", .starts_at.to_rfc3339())]
+    SessionNotStarted { starts_at: chrono::DateTime<chrono::Utc> },
+
+    /// A collaboration session already has its maximum of users online
+    #[error("Session is full; it allows {max_participants} participants")]
+    SessionFull { max_participants: i32 },
+
+    /// A collaboration session was joined after it ended
+    #[error("Session has ended")]
+    SessionEnded,
+
+    /// A file went over a limit of its project; `rejected` lists every entry
+    /// of an import refused in strict mode
+    #[error("Project limit exceeded: {exceeded}")]
+    LimitExceeded {
+        exceeded: ExceededLimit,
+        rejected: Vec<ExceededLimit>,
+    },
+
+    /// An imported archive does not match its checksum manifest and imports
+    /// are verified strictly
+    #[error("Archive does not match its checksums: {0}")]
+    ArchiveUnverified(Box<ArchiveVerification>),
+
+    /// An OIDC callback was refused; expired and used logins must be started
+    /// again
+    #[error("{0}")]
+    OidcLogin(OidcFailure),
 }
 
 /// Request ID for tracking
@@ -124,9 +182,21 @@ impl AppError {
             AppError::NotFound { .. } => StatusCode::NOT_FOUND,
             AppError::Conflict(_) => StatusCode::CONFLICT,
             AppError::RateLimit => StatusCode::TOO_MANY_REQUESTS,
+            AppError::Timeout { .. } => StatusCode::GATEWAY_TIMEOUT,
+            AppError::Upstream(_) => StatusCode::BAD_GATEWAY,
+            AppError::MalwareDetected(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            AppError::ProjectFrozen(_) => StatusCode::LOCKED,
+            AppError::ContentCorrupted(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            AppError::SessionNotStarted { .. } => StatusCode::CONFLICT,
+            AppError::SessionFull { .. } => StatusCode::CONFLICT,
+            AppError::SessionEnded => StatusCode::GONE,
+            AppError::LimitExceeded { .. } => StatusCode::UNPROCESSABLE_ENTITY,
+            AppError::ArchiveUnverified(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            AppError::OidcLogin(failure) => failure.status_code(),
             AppError::BadRequest(_) => StatusCode::BAD_REQUEST,
             AppError::Jwt(_) => StatusCode::UNAUTHORIZED,
             AppError::Database(sqlx::Error::RowNotFound) => StatusCode::NOT_FOUND,
+            AppError::Database(e) if crate::db_resilience::is_retryable(e) => StatusCode::SERVICE_UNAVAILABLE,
             AppError::Database(_) => StatusCode::INTERNAL_SERVER_ERROR,
             _ => StatusCode::INTERNAL_SERVER_ERROR,
         }
@@ -141,9 +211,11 @@ impl AppError {
             AppError::NotFound { .. } => "NOT_FOUND",
             AppError::Conflict(_) => "CONFLICT",
             AppError::RateLimit => "RATE_LIMIT_EXCEEDED",
+            AppError::Timeout { .. } => "REQUEST_TIMEOUT",
             AppError::BadRequest(_) => "BAD_REQUEST",
             AppError::Jwt(_) => "INVALID_TOKEN",
             AppError::Bcrypt(_) => "BCRYPT_ERROR",
+            AppError::Database(e) if crate::db_resilience::is_retryable(e) => "DATABASE_UNAVAILABLE",
             AppError::Database(_) => "DATABASE_ERROR",
             AppError::Redis(_) => "REDIS_ERROR",
             AppError::Compilation(_) => "COMPILATION_ERROR",
@@ -155,33 +227,106 @@ impl AppError {
             AppError::Job(_) => "JOB_ERROR",
             AppError::Server(_) => "SERVER_ERROR",
             AppError::Storage(_) => "STORAGE_ERROR",
+            AppError::Upstream(_) => "UPSTREAM_ERROR",
+            AppError::MalwareDetected(_) => "MALWARE_DETECTED",
+            AppError::ProjectFrozen(_) => "PROJECT_FROZEN",
+            AppError::ContentCorrupted(_) => "CONTENT_CORRUPTED",
+            AppError::SessionNotStarted { .. } => "SESSION_NOT_STARTED",
+            AppError::SessionFull { .. } => "SESSION_FULL",
+            AppError::SessionEnded => "SESSION_ENDED",
+            AppError::LimitExceeded { .. } => "PROJECT_LIMIT_EXCEEDED",
+            AppError::ArchiveUnverified(_) => "ARCHIVE_CHECKSUM_MISMATCH",
+            AppError::OidcLogin(failure) => failure.error_code(),
         }
     }
 
+    /// Whether the database could not be reached, so the same request may
+    /// succeed a moment later
+    pub fn is_retryable(&self) -> bool {
+        matches!(self, AppError::Database(e) if crate::db_resilience::is_retryable(e))
+    }
+
     /// Check if this error is an operational error (expected errors)
     pub fn is_operational(&self) -> bool {
         !matches!(self, AppError::Internal(_))
     }
 }
 
+/// Body of every error response
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ErrorResponse {
+    /// Always `false`
+    pub success: bool,
+    pub error: ErrorBody,
+}
+
+/// Error details of an `ErrorResponse`
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ErrorBody {
+    /// Stable machine-readable code such as `NOT_FOUND`
+    pub code: String,
+    pub message: String,
+    /// RFC 3339 time the error was produced
+    pub timestamp: String,
+    /// Request the error was produced for, to quote when reporting it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+}
+
+/// Body of a response refusing a file over a limit of its project
+#[derive(Debug, Serialize, ToSchema)]
+pub struct LimitExceededResponse {
+    /// Always `false`
+    pub success: bool,
+    pub error: ErrorBody,
+    /// The limit that was exceeded, by the first file that went over it
+    pub limit: ExceededLimit,
+    /// Every entry of an import or clone over a limit, in strict mode
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub rejected: Vec<ExceededLimit>,
+}
+
+/// Body of a response refusing an archive that does not match its checksums
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ArchiveUnverifiedResponse {
+    /// Always `false`
+    pub success: bool,
+    pub error: ErrorBody,
+    pub verification: ArchiveVerification,
+}
+
 impl IntoResponse for AppError {
     fn into_response(self) -> Response {
         let status = self.status_code();
-        let error_code = self.error_code();
-        let message = self.to_string();
-
-        // Create error response body
-        let body = Json(json!({
-            "success": false,
-            "error": {
-                "code": error_code,
-                "message": message,
-                "timestamp": chrono::Utc::now().to_rfc3339(),
-            }
-        }));
 
-        // Add validation details if present
-        (status, body).into_response()
+        let request_id = match &self {
+            AppError::Timeout { request_id, .. } => *request_id,
+            _ => None,
+        }
+        .or_else(crate::correlation::current_request_id)
+        .map(|id| id.to_string());
+
+        let error = ErrorBody {
+            code: self.error_code().to_string(),
+            message: self.to_string(),
+            timestamp: chrono::Utc::now().to_rfc3339(),
+            request_id,
+        };
+
+        if let AppError::LimitExceeded { exceeded, rejected } = self {
+            let body = LimitExceededResponse { success: false, error, limit: exceeded, rejected };
+            return (status, Json(body)).into_response();
+        }
+        if let AppError::ArchiveUnverified(verification) = self {
+            let body = ArchiveUnverifiedResponse { success: false, error, verification: *verification };
+            return (status, Json(body)).into_response();
+        }
+        // Connections usually come back within seconds of a database restart
+        if self.is_retryable() {
+            let retry_after = [(axum::http::header::RETRY_AFTER, "1")];
+            return (status, retry_after, Json(ErrorResponse { success: false, error })).into_response();
+        }
+        (status, Json(ErrorResponse { success: false, error })).into_response()
     }
 }
 
@@ -230,7 +375,7 @@ mod tests {
 
     #[test]
     fn test_error_codes() {
-        let error = AppError::Auth("Invalid token");
+        let error = AppError::Auth("Invalid token".to_string());
         assert_eq!(error.error_code(), "AUTHENTICATION_ERROR");
         assert_eq!(error.status_code(), StatusCode::UNAUTHORIZED);
     }
@@ -245,12 +390,45 @@ mod tests {
         assert_eq!(error.status_code(), StatusCode::NOT_FOUND);
     }
 
+    #[test]
+    fn test_project_frozen_error() {
+        let error = AppError::ProjectFrozen("Submitted to ICML".to_string());
+        assert_eq!(error.error_code(), "PROJECT_FROZEN");
+        assert_eq!(error.status_code(), StatusCode::LOCKED);
+        assert_eq!(error.to_string(), "Project is frozen: Submitted to ICML");
+    }
+
+    #[test]
+    fn test_session_not_started_error() {
+        let starts_at = chrono::DateTime::parse_from_rfc3339("2026-11-02T13:30:00Z").unwrap().with_timezone(&chrono::Utc);
+        let error = AppError::SessionNotStarted { starts_at };
+        assert_eq!(error.error_code(), "SESSION_NOT_STARTED");
+        assert_eq!(error.status_code(), StatusCode::CONFLICT);
+        assert_eq!(error.to_string(), "Session has not started yet; it starts at 2026-11-02T13:30:00+00:00");
+    }
+
+    #[test]
+    fn test_limit_exceeded_error() {
+        let error = AppError::from(ExceededLimit {
+            path: "/a/b.tex".to_string(),
+            limit: crate::models::project_limits::ProjectLimit::Files,
+            max: 5000,
+            actual: 5001,
+        });
+        assert_eq!(error.error_code(), "PROJECT_LIMIT_EXCEEDED");
+        assert_eq!(error.status_code(), StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(
+            error.to_string(),
+            "Project limit exceeded: /a/b.tex would make 5001 files, more than the 5000 a project may have"
+        );
+    }
+
     #[test]
     fn test_operational_error() {
-        let error = AppError::Auth("test");
+        let error = AppError::Auth("test".to_string());
         assert!(error.is_operational());
 
-        let error = AppError::Internal("internal error");
+        let error = AppError::Internal("internal error".to_string());
         assert!(!error.is_operational());
     }
 }
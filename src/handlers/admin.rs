@@ -0,0 +1,1184 @@
+//! Administrative request handlers
+
+use crate::config::snapshot::ConfigSnapshot;
+use crate::error::{AppError, ErrorResponse};
+use crate::models::blob::{Blob, BlobConsistencyReport};
+use crate::models::compilation::CompilationTemplate;
+use crate::models::compile_sandbox::{SandboxReport, SelfTestReport};
+use crate::models::conflict_event::{self, AutoResolutionReport, ConflictReport};
+use crate::models::discovery::{Collection, CollectionRequest};
+use crate::models::announcement::{AnnouncementRequest, InstanceAnnouncement};
+use crate::models::file::File;
+use crate::models::file_reindex::{FileReindex, FILE_REINDEX_TASK};
+use crate::models::file_scan::QuarantinedFile;
+use crate::models::integrity::{IntegrityIncident, IntegritySummary, INTEGRITY_SCAN_TASK};
+use crate::models::login_protection::LoginFailure;
+use crate::models::project_purge::{ProjectPurge, PurgeProgress};
+use crate::models::session_summary::{self, CompactionReport, SessionSummary};
+use crate::models::usage_period::{self, UsageFormat, UsageParams, UsagePeriod};
+use crate::models::ws_connection_limit::WsConnectionStats;
+use crate::migrate::{self, AppliedMigration, PendingMigration};
+use crate::models::ApiResponse;
+use crate::openapi::MessageResponse;
+use crate::performance::PerformanceReport;
+use crate::seed::{self, SeedData, SeedReport};
+use crate::server::AppState;
+use crate::tasks::TaskStatus;
+use axum::{
+    extract::{Path, Query, State},
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::IntoResponse,
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+use uuid::Uuid;
+
+/// Blob store consistency response
+#[derive(Debug, Serialize, ToSchema)]
+pub struct BlobConsistencyResponse {
+    pub consistent: bool,
+    pub report: BlobConsistencyReport,
+}
+
+/// User unlock response
+#[derive(Debug, Serialize, ToSchema)]
+pub struct UnlockUserResponse {
+    pub user_id: Uuid,
+    /// False when the user had no failed logins on record
+    pub was_locked_or_throttled: bool,
+}
+
+/// Periodic tasks response
+#[derive(Debug, Serialize, ToSchema)]
+pub struct TasksResponse {
+    pub tasks: Vec<TaskStatus>,
+}
+
+/// Triggered task response
+#[derive(Debug, Serialize, ToSchema)]
+pub struct TaskTriggeredResponse {
+    pub task: TaskStatus,
+}
+
+/// Template verification request
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct TemplateVerificationRequest {
+    pub verified: bool,
+}
+
+/// Template verification response
+#[derive(Debug, Serialize, ToSchema)]
+pub struct TemplateVerificationResponse {
+    pub template: CompilationTemplate,
+}
+
+/// Quarantined files response
+#[derive(Debug, Serialize, ToSchema)]
+pub struct QuarantinedFilesResponse {
+    pub files: Vec<QuarantinedFile>,
+}
+
+/// Reindex response
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ReindexResponse {
+    /// The active reindex, or else the last one; `None` before the first
+    pub reindex: Option<FileReindex>,
+    /// Schedule and recent runs of the background task
+    pub task: Option<TaskStatus>,
+}
+
+/// Migration history response
+#[derive(Debug, Serialize, ToSchema)]
+pub struct MigrationsResponse {
+    /// Recorded migrations including failed attempts, oldest first
+    pub applied: Vec<AppliedMigration>,
+    /// Shipped with this build but not applied yet
+    pub pending: Vec<PendingMigration>,
+}
+
+/// Data integrity response
+#[derive(Debug, Serialize, ToSchema)]
+pub struct IntegrityResponse {
+    pub summary: IntegritySummary,
+    /// Schedule and recent runs of the background scan
+    pub scan: Option<TaskStatus>,
+}
+
+/// Compile environment response
+#[derive(Debug, Serialize, ToSchema)]
+pub struct CompileEnvironmentResponse {
+    pub environment: SandboxReport,
+    /// Latest sandbox self-test, from startup or an administrator
+    pub self_test: Option<SelfTestReport>,
+}
+
+/// Project purges response
+#[derive(Debug, Serialize, ToSchema)]
+pub struct PurgesResponse {
+    /// Unfinished purges oldest first, then the most recently completed
+    pub purges: Vec<PurgeProgress>,
+}
+
+/// Require the caller to be the instance administrator
+fn require_admin(auth_user: &crate::models::auth::AuthContext) -> Result<(), AppError> {
+    if !auth_user.is_admin() {
+        return Err(AppError::Authorization("Administrator access required".to_string()));
+    }
+
+    Ok(())
+}
+
+/// Report orphaned blobs, dangling references and missing blob content
+#[utoipa::path(
+    get,
+    path = "/blobs/consistency",
+    responses(
+        (status = 200, description = "Consistency report of the blob store", body = ApiResponse<BlobConsistencyResponse>),
+        (status = 403, description = "Administrator access required", body = ErrorResponse),
+    )
+)]
+pub async fn blob_consistency(
+    State(state): State<AppState>,
+    auth_user: axum::Extension<crate::models::auth::AuthContext>,
+) -> Result<impl IntoResponse, AppError> {
+    require_admin(&auth_user)?;
+
+    let report = Blob::check_consistency(&state.db_pool, &state.config.features.file_storage.local_path).await?;
+    let response = BlobConsistencyResponse {
+        consistent: report.is_consistent(),
+        report,
+    };
+
+    Ok(Json(serde_json::json!({
+        "success": true,
+        "data": response
+    })))
+}
+
+/// Report what the next session compaction pass would summarize and delete
+#[utoipa::path(
+    get,
+    path = "/sessions/compaction",
+    responses(
+        (status = 200, description = "Dry-run compaction report", body = ApiResponse<CompactionReport>),
+        (status = 403, description = "Administrator access required", body = ErrorResponse),
+    )
+)]
+pub async fn session_compaction_dry_run(
+    State(state): State<AppState>,
+    auth_user: axum::Extension<crate::models::auth::AuthContext>,
+) -> Result<impl IntoResponse, AppError> {
+    require_admin(&auth_user)?;
+
+    let report = SessionSummary::compact_dry_run(
+        &state.db_pool,
+        session_summary::operation_retention(&state.config),
+    )
+    .await?;
+
+    Ok(Json(serde_json::json!({
+        "success": true,
+        "data": report
+    })))
+}
+
+/// Lift a login lockout and reset the failed login counter
+#[utoipa::path(
+    post,
+    path = "/users/{id}/unlock",
+    params(("id" = Uuid, Path, description = "User ID")),
+    responses(
+        (status = 200, description = "Failed logins cleared", body = ApiResponse<UnlockUserResponse>),
+        (status = 403, description = "Administrator access required", body = ErrorResponse),
+    )
+)]
+pub async fn unlock_user(
+    State(state): State<AppState>,
+    Path(user_id): Path<Uuid>,
+    auth_user: axum::Extension<crate::models::auth::AuthContext>,
+) -> Result<impl IntoResponse, AppError> {
+    require_admin(&auth_user)?;
+
+    let cleared = LoginFailure::clear(&state.db_pool, user_id).await?;
+    tracing::info!("Administrator {} unlocked user {}", auth_user.user_id, user_id);
+
+    let response = UnlockUserResponse {
+        user_id,
+        was_locked_or_throttled: cleared,
+    };
+
+    Ok(Json(serde_json::json!({
+        "success": true,
+        "data": response
+    })))
+}
+
+/// Schedule and recent outcomes of the periodic background tasks
+#[utoipa::path(
+    get,
+    path = "/tasks",
+    responses(
+        (status = 200, description = "Every registered periodic task", body = ApiResponse<TasksResponse>),
+        (status = 403, description = "Administrator access required", body = ErrorResponse),
+    )
+)]
+pub async fn list_tasks(
+    State(state): State<AppState>,
+    auth_user: axum::Extension<crate::models::auth::AuthContext>,
+) -> Result<impl IntoResponse, AppError> {
+    require_admin(&auth_user)?;
+
+    let response = TasksResponse {
+        tasks: state.tasks.statuses(),
+    };
+
+    Ok(Json(serde_json::json!({
+        "success": true,
+        "data": response
+    })))
+}
+
+/// Run a periodic task now instead of waiting for its schedule
+#[utoipa::path(
+    post,
+    path = "/tasks/{name}/run",
+    params(("name" = String, Path, description = "Task name")),
+    responses(
+        (status = 202, description = "Run started", body = ApiResponse<TaskTriggeredResponse>),
+        (status = 403, description = "Administrator access required", body = ErrorResponse),
+        (status = 404, description = "No task with this name", body = ErrorResponse),
+        (status = 409, description = "Task is already running", body = ErrorResponse),
+    )
+)]
+pub async fn run_task(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    auth_user: axum::Extension<crate::models::auth::AuthContext>,
+) -> Result<impl IntoResponse, AppError> {
+    require_admin(&auth_user)?;
+
+    let status = state.tasks.trigger(&name, state.clone())?;
+    tracing::info!("Administrator {} triggered task {}", auth_user.user_id, name);
+
+    let response = TaskTriggeredResponse {
+        task: status,
+    };
+
+    Ok((
+        StatusCode::ACCEPTED,
+        Json(serde_json::json!({
+            "success": true,
+            "data": response
+        })),
+    ))
+}
+
+/// Mark a compilation template as officially maintained, which lists it first
+#[utoipa::path(
+    put,
+    path = "/templates/{id}/verified",
+    params(("id" = Uuid, Path, description = "Compilation template ID")),
+    request_body = TemplateVerificationRequest,
+    responses(
+        (status = 200, description = "Verification updated", body = ApiResponse<TemplateVerificationResponse>),
+        (status = 403, description = "Administrator access required", body = ErrorResponse),
+        (status = 404, description = "Template not found", body = ErrorResponse),
+    )
+)]
+pub async fn set_template_verified(
+    State(state): State<AppState>,
+    Path(template_id): Path<Uuid>,
+    auth_user: axum::Extension<crate::models::auth::AuthContext>,
+    Json(payload): Json<TemplateVerificationRequest>,
+) -> Result<impl IntoResponse, AppError> {
+    require_admin(&auth_user)?;
+
+    let template = CompilationTemplate::set_verified(&state.db_pool, template_id, payload.verified).await?;
+    tracing::info!(
+        "Administrator {} set template {} verified={}",
+        auth_user.user_id,
+        template_id,
+        payload.verified
+    );
+
+    let response = TemplateVerificationResponse {
+        template,
+    };
+
+    Ok(Json(serde_json::json!({
+        "success": true,
+        "data": response
+    })))
+}
+
+/// Files the re-scan task quarantined, awaiting review
+#[utoipa::path(
+    get,
+    path = "/files/quarantined",
+    responses(
+        (status = 200, description = "Quarantined files, oldest first", body = ApiResponse<QuarantinedFilesResponse>),
+        (status = 403, description = "Administrator access required", body = ErrorResponse),
+    )
+)]
+pub async fn list_quarantined_files(
+    State(state): State<AppState>,
+    auth_user: axum::Extension<crate::models::auth::AuthContext>,
+) -> Result<impl IntoResponse, AppError> {
+    require_admin(&auth_user)?;
+
+    let response = QuarantinedFilesResponse {
+        files: File::list_quarantined(&state.db_pool).await?,
+    };
+
+    Ok(Json(serde_json::json!({
+        "success": true,
+        "data": response
+    })))
+}
+
+/// Release a quarantined file after review, allowing downloads again
+#[utoipa::path(
+    post,
+    path = "/files/{id}/release",
+    params(("id" = Uuid, Path, description = "File ID")),
+    responses(
+        (status = 200, description = "Quarantine lifted", body = MessageResponse),
+        (status = 403, description = "Administrator access required", body = ErrorResponse),
+        (status = 404, description = "File is not quarantined", body = ErrorResponse),
+    )
+)]
+pub async fn release_quarantined_file(
+    State(state): State<AppState>,
+    Path(file_id): Path<Uuid>,
+    auth_user: axum::Extension<crate::models::auth::AuthContext>,
+) -> Result<impl IntoResponse, AppError> {
+    require_admin(&auth_user)?;
+
+    File::release_quarantine(&state.db_pool, file_id, auth_user.user_id).await?;
+    tracing::info!("Administrator {} released quarantined file {}", auth_user.user_id, file_id);
+
+    Ok(Json(serde_json::json!({
+        "success": true,
+        "message": "File released from quarantine"
+    })))
+}
+
+/// Recompute what is derived from every file's content in the background
+///
+/// Line and word counts and the LaTeX metadata are brought up to date with
+/// the current extractors, batch by batch, for all live files except
+/// images. Only rows whose derived data changed are rewritten, and a file
+/// saved meanwhile keeps what its save derived. A run that is interrupted
+/// resumes where it stopped.
+#[utoipa::path(
+    post,
+    path = "/reindex",
+    responses(
+        (status = 202, description = "Reindex started", body = ApiResponse<ReindexResponse>),
+        (status = 403, description = "Administrator access required", body = ErrorResponse),
+        (status = 409, description = "A reindex is already running", body = ErrorResponse),
+    )
+)]
+pub async fn start_reindex(
+    State(state): State<AppState>,
+    auth_user: axum::Extension<crate::models::auth::AuthContext>,
+) -> Result<impl IntoResponse, AppError> {
+    require_admin(&auth_user)?;
+
+    let reindex = FileReindex::start(&state.db_pool, Some(auth_user.user_id)).await?;
+    tracing::info!("Administrator {} started reindex {}", auth_user.user_id, reindex.id);
+
+    // A run of the task in progress picks the reindex up on its next round
+    let task = match state.tasks.trigger(FILE_REINDEX_TASK, state.clone()) {
+        Ok(status) => Some(status),
+        Err(AppError::Conflict(_)) => state.tasks.statuses().into_iter().find(|task| task.name == FILE_REINDEX_TASK),
+        Err(e) => return Err(e),
+    };
+
+    let response = ReindexResponse {
+        reindex: Some(reindex),
+        task,
+    };
+
+    Ok((
+        StatusCode::ACCEPTED,
+        Json(serde_json::json!({
+            "success": true,
+            "data": response
+        })),
+    ))
+}
+
+/// Progress of the active reindex, or the outcome of the last one
+#[utoipa::path(
+    get,
+    path = "/reindex/status",
+    responses(
+        (status = 200, description = "Reindex progress", body = ApiResponse<ReindexResponse>),
+        (status = 403, description = "Administrator access required", body = ErrorResponse),
+    )
+)]
+pub async fn get_reindex_status(
+    State(state): State<AppState>,
+    auth_user: axum::Extension<crate::models::auth::AuthContext>,
+) -> Result<impl IntoResponse, AppError> {
+    require_admin(&auth_user)?;
+
+    let response = ReindexResponse {
+        reindex: FileReindex::latest(&state.db_pool).await?,
+        task: state.tasks.statuses().into_iter().find(|task| task.name == FILE_REINDEX_TASK),
+    };
+
+    Ok(Json(serde_json::json!({
+        "success": true,
+        "data": response
+    })))
+}
+
+/// Outbound HTTP, WebSocket, OIDC, edit conflict and database outage metrics in the Prometheus text format
+#[utoipa::path(
+    get,
+    path = "/metrics",
+    responses(
+        (status = 200, description = "Request counts, retries and latencies per destination class, WebSocket connection counts, corrections of active user counts, refused OIDC callbacks by reason, edit conflicts by kind and outcome, and database retries, buffered writes and circuit state", body = String, content_type = "text/plain"),
+        (status = 403, description = "Administrator access required", body = ErrorResponse),
+    )
+)]
+pub async fn metrics(
+    State(state): State<AppState>,
+    auth_user: axum::Extension<crate::models::auth::AuthContext>,
+) -> Result<impl IntoResponse, AppError> {
+    require_admin(&auth_user)?;
+
+    Ok((
+        [(axum::http::header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        state.http_client.metrics_text()
+            + &state.ws_connection_limiter.metrics_text()
+            + &state.project_presence.metrics_text()
+            + &state.oidc_metrics.metrics_text()
+            + &state.conflicts.metrics_text()
+            + &state.db_resilience.metrics_text(),
+    ))
+}
+
+/// Authenticated WebSocket connections, with the users holding the most
+///
+/// Users are limited to `max_connections_per_user`; `evicted` counts the
+/// connections closed to make room for newer ones.
+#[utoipa::path(
+    get,
+    path = "/websocket/connections",
+    responses(
+        (status = 200, description = "Connection counts", body = ApiResponse<WsConnectionStats>),
+        (status = 403, description = "Administrator access required", body = ErrorResponse),
+    )
+)]
+pub async fn websocket_connections(
+    State(state): State<AppState>,
+    auth_user: axum::Extension<crate::models::auth::AuthContext>,
+) -> Result<impl IntoResponse, AppError> {
+    require_admin(&auth_user)?;
+
+    Ok(Json(serde_json::json!({
+        "success": true,
+        "data": state.ws_connection_limiter.stats()
+    })))
+}
+
+/// Applied and pending database migrations
+///
+/// Shows the checksum and duration of each applied migration, whether its
+/// SQL still matches this build, and the error of a failed attempt.
+#[utoipa::path(
+    get,
+    path = "/migrations",
+    responses(
+        (status = 200, description = "Migration history", body = ApiResponse<MigrationsResponse>),
+        (status = 403, description = "Administrator access required", body = ErrorResponse),
+    )
+)]
+pub async fn migrations_applied(
+    State(state): State<AppState>,
+    auth_user: axum::Extension<crate::models::auth::AuthContext>,
+) -> Result<impl IntoResponse, AppError> {
+    require_admin(&auth_user)?;
+
+    let response = MigrationsResponse {
+        applied: migrate::migration_history(&state.db_pool).await?,
+        pending: migrate::dry_run(&state.db_pool).await?.pending,
+    };
+
+    Ok(Json(serde_json::json!({
+        "success": true,
+        "data": response
+    })))
+}
+
+/// Stored content found not to match its hash
+///
+/// Incidents are recorded when a download, export or compile reads damaged
+/// content, and by the background scan.
+#[utoipa::path(
+    get,
+    path = "/integrity",
+    responses(
+        (status = 200, description = "Incident summary and scan status", body = ApiResponse<IntegrityResponse>),
+        (status = 403, description = "Administrator access required", body = ErrorResponse),
+    )
+)]
+pub async fn integrity_summary(
+    State(state): State<AppState>,
+    auth_user: axum::Extension<crate::models::auth::AuthContext>,
+) -> Result<impl IntoResponse, AppError> {
+    require_admin(&auth_user)?;
+
+    let response = IntegrityResponse {
+        summary: IntegrityIncident::summary(&state.db_pool).await?,
+        scan: state.tasks.statuses().into_iter().find(|task| task.name == INTEGRITY_SCAN_TASK),
+    };
+
+    Ok(Json(serde_json::json!({
+        "success": true,
+        "data": response
+    })))
+}
+
+/// Check every stored blob against its hash in the background
+#[utoipa::path(
+    post,
+    path = "/integrity/scan",
+    responses(
+        (status = 202, description = "Scan started", body = ApiResponse<TaskTriggeredResponse>),
+        (status = 403, description = "Administrator access required", body = ErrorResponse),
+        (status = 409, description = "A scan is already running", body = ErrorResponse),
+    )
+)]
+pub async fn start_integrity_scan(
+    State(state): State<AppState>,
+    auth_user: axum::Extension<crate::models::auth::AuthContext>,
+) -> Result<impl IntoResponse, AppError> {
+    require_admin(&auth_user)?;
+
+    let status = state.tasks.trigger(INTEGRITY_SCAN_TASK, state.clone())?;
+    tracing::info!("Administrator {} started an integrity scan", auth_user.user_id);
+
+    let response = TaskTriggeredResponse {
+        task: status,
+    };
+
+    Ok((
+        StatusCode::ACCEPTED,
+        Json(serde_json::json!({
+            "success": true,
+            "data": response
+        })),
+    ))
+}
+
+/// Configuration in effect on this instance
+///
+/// The configuration after environment parsing and defaults, with every
+/// value that is not known to be safe to show replaced by `***` and a flag
+/// telling whether it is set. Also lists the variable each value came from
+/// and the features that follow from the configuration and the build.
+#[utoipa::path(
+    get,
+    path = "/config",
+    responses(
+        (status = 200, description = "Effective configuration, secrets redacted", body = ApiResponse<ConfigSnapshot>),
+        (status = 403, description = "Administrator access required", body = ErrorResponse),
+    )
+)]
+pub async fn effective_config(
+    State(state): State<AppState>,
+    auth_user: axum::Extension<crate::models::auth::AuthContext>,
+) -> Result<impl IntoResponse, AppError> {
+    require_admin(&auth_user)?;
+
+    Ok(Json(serde_json::json!({
+        "success": true,
+        "data": ConfigSnapshot::of(&state.config)
+    })))
+}
+
+/// What a compile job can touch on this instance
+///
+/// Lists the engine binaries with their hashes, the TEXMF trees compiles see,
+/// the global shell-escape setting, the resource limits and the paths a job
+/// can write, for institutional compliance documentation.
+#[utoipa::path(
+    get,
+    path = "/compile-environment",
+    responses(
+        (status = 200, description = "Compile environment and the latest self-test", body = ApiResponse<CompileEnvironmentResponse>),
+        (status = 403, description = "Administrator access required", body = ErrorResponse),
+    )
+)]
+pub async fn compile_environment(
+    State(state): State<AppState>,
+    auth_user: axum::Extension<crate::models::auth::AuthContext>,
+) -> Result<impl IntoResponse, AppError> {
+    require_admin(&auth_user)?;
+
+    let response = CompileEnvironmentResponse {
+        environment: state.compile_sandbox.report().await,
+        self_test: state.compile_sandbox.last_self_test(),
+    };
+
+    Ok(Json(serde_json::json!({
+        "success": true,
+        "data": response
+    })))
+}
+
+/// Compile known-malicious fixtures in the sandbox
+///
+/// Every configured TeX engine tries to read `/etc/passwd`, write outside its
+/// directory, run a shell command and exhaust its memory. Failed probes show
+/// up in `/health/ready` until a later self-test passes.
+#[utoipa::path(
+    post,
+    path = "/compile-environment/selftest",
+    responses(
+        (status = 200, description = "Outcome of every probe", body = ApiResponse<SelfTestReport>),
+        (status = 403, description = "Administrator access required", body = ErrorResponse),
+    )
+)]
+pub async fn compile_sandbox_self_test(
+    State(state): State<AppState>,
+    auth_user: axum::Extension<crate::models::auth::AuthContext>,
+) -> Result<impl IntoResponse, AppError> {
+    require_admin(&auth_user)?;
+
+    tracing::info!("Administrator {} started a compile sandbox self-test", auth_user.user_id);
+    let report = state.compile_sandbox.self_test().await;
+
+    Ok(Json(serde_json::json!({
+        "success": true,
+        "data": report
+    })))
+}
+
+/// Progress of project deletions
+///
+/// Each purge moves through its stages in batches; `last_error` is set when
+/// a batch failed and will be retried on the next run of the purge task.
+#[utoipa::path(
+    get,
+    path = "/purges",
+    responses(
+        (status = 200, description = "Unfinished and recently completed purges", body = ApiResponse<PurgesResponse>),
+        (status = 403, description = "Administrator access required", body = ErrorResponse),
+    )
+)]
+pub async fn list_purges(
+    State(state): State<AppState>,
+    auth_user: axum::Extension<crate::models::auth::AuthContext>,
+) -> Result<impl IntoResponse, AppError> {
+    require_admin(&auth_user)?;
+
+    let response = PurgesResponse {
+        purges: ProjectPurge::list(&state.db_pool).await?.into_iter().map(PurgeProgress::from).collect(),
+    };
+
+    Ok(Json(serde_json::json!({
+        "success": true,
+        "data": response
+    })))
+}
+
+/// Curated collections response
+#[derive(Debug, Serialize, ToSchema)]
+pub struct CollectionsResponse {
+    pub collections: Vec<Collection>,
+}
+
+/// Curated collection response
+#[derive(Debug, Serialize, ToSchema)]
+pub struct CollectionResponse {
+    pub collection: Collection,
+}
+
+/// Collections curated for project discovery, in listing order
+#[utoipa::path(
+    get,
+    path = "/collections",
+    responses(
+        (status = 200, description = "Every collection with all its projects", body = ApiResponse<CollectionsResponse>),
+        (status = 403, description = "Administrator access required", body = ErrorResponse),
+    )
+)]
+pub async fn list_collections(
+    State(state): State<AppState>,
+    auth_user: axum::Extension<crate::models::auth::AuthContext>,
+) -> Result<impl IntoResponse, AppError> {
+    require_admin(&auth_user)?;
+
+    let response = CollectionsResponse {
+        collections: Collection::list(&state.db_pool).await?,
+    };
+
+    Ok(Json(serde_json::json!({
+        "success": true,
+        "data": response
+    })))
+}
+
+/// Curate a collection of public projects
+#[utoipa::path(
+    post,
+    path = "/collections",
+    request_body = CollectionRequest,
+    responses(
+        (status = 201, description = "Collection created", body = ApiResponse<CollectionResponse>),
+        (status = 400, description = "Invalid name, too many projects, or a project listed twice or not public", body = ErrorResponse),
+        (status = 403, description = "Administrator access required", body = ErrorResponse),
+    )
+)]
+pub async fn create_collection(
+    State(state): State<AppState>,
+    auth_user: axum::Extension<crate::models::auth::AuthContext>,
+    Json(payload): Json<CollectionRequest>,
+) -> Result<impl IntoResponse, AppError> {
+    require_admin(&auth_user)?;
+
+    let collection = Collection::create(&state.db_pool, &payload, auth_user.user_id).await?;
+    tracing::info!("Administrator {} created collection {}", auth_user.user_id, collection.id);
+
+    let response = CollectionResponse {
+        collection,
+    };
+
+    Ok((
+        StatusCode::CREATED,
+        Json(serde_json::json!({
+            "success": true,
+            "data": response
+        })),
+    ))
+}
+
+/// Replace a collection's details and projects
+#[utoipa::path(
+    put,
+    path = "/collections/{id}",
+    params(("id" = Uuid, Path, description = "Collection ID")),
+    request_body = CollectionRequest,
+    responses(
+        (status = 200, description = "Collection updated", body = ApiResponse<CollectionResponse>),
+        (status = 400, description = "Invalid name, too many projects, or a project listed twice or not public", body = ErrorResponse),
+        (status = 403, description = "Administrator access required", body = ErrorResponse),
+        (status = 404, description = "Collection not found", body = ErrorResponse),
+    )
+)]
+pub async fn update_collection(
+    State(state): State<AppState>,
+    Path(collection_id): Path<Uuid>,
+    auth_user: axum::Extension<crate::models::auth::AuthContext>,
+    Json(payload): Json<CollectionRequest>,
+) -> Result<impl IntoResponse, AppError> {
+    require_admin(&auth_user)?;
+
+    let collection = Collection::update(&state.db_pool, collection_id, &payload).await?;
+    tracing::info!("Administrator {} updated collection {}", auth_user.user_id, collection_id);
+
+    let response = CollectionResponse {
+        collection,
+    };
+
+    Ok(Json(serde_json::json!({
+        "success": true,
+        "data": response
+    })))
+}
+
+/// Delete a collection; its projects are not affected
+#[utoipa::path(
+    delete,
+    path = "/collections/{id}",
+    params(("id" = Uuid, Path, description = "Collection ID")),
+    responses(
+        (status = 200, description = "Collection deleted", body = MessageResponse),
+        (status = 403, description = "Administrator access required", body = ErrorResponse),
+        (status = 404, description = "Collection not found", body = ErrorResponse),
+    )
+)]
+pub async fn delete_collection(
+    State(state): State<AppState>,
+    Path(collection_id): Path<Uuid>,
+    auth_user: axum::Extension<crate::models::auth::AuthContext>,
+) -> Result<impl IntoResponse, AppError> {
+    require_admin(&auth_user)?;
+
+    Collection::delete(&state.db_pool, collection_id).await?;
+    tracing::info!("Administrator {} deleted collection {}", auth_user.user_id, collection_id);
+
+    Ok(Json(serde_json::json!({
+        "success": true,
+        "message": "Collection deleted"
+    })))
+}
+
+/// Instance announcements response
+#[derive(Debug, Serialize, ToSchema)]
+pub struct InstanceAnnouncementsResponse {
+    pub announcements: Vec<InstanceAnnouncement>,
+}
+
+/// Instance announcement response
+#[derive(Debug, Serialize, ToSchema)]
+pub struct InstanceAnnouncementResponse {
+    pub announcement: InstanceAnnouncement,
+}
+
+/// Every instance announcement, scheduled and expired ones included
+#[utoipa::path(
+    get,
+    path = "/announcements",
+    responses(
+        (status = 200, description = "Instance announcements, the latest to start first", body = ApiResponse<InstanceAnnouncementsResponse>),
+        (status = 403, description = "Administrator access required", body = ErrorResponse),
+    )
+)]
+pub async fn list_announcements(
+    State(state): State<AppState>,
+    auth_user: axum::Extension<crate::models::auth::AuthContext>,
+) -> Result<impl IntoResponse, AppError> {
+    require_admin(&auth_user)?;
+
+    let response = InstanceAnnouncementsResponse {
+        announcements: InstanceAnnouncement::list(&state.db_pool).await?,
+    };
+
+    Ok(Json(serde_json::json!({
+        "success": true,
+        "data": response
+    })))
+}
+
+/// Announce something to the instance's users
+///
+/// The audience is notified in the app once the announcement starts.
+#[utoipa::path(
+    post,
+    path = "/announcements",
+    request_body = AnnouncementRequest,
+    responses(
+        (status = 201, description = "Announcement created", body = ApiResponse<InstanceAnnouncementResponse>),
+        (status = 400, description = "Invalid title or body, or an expiry before the start", body = ErrorResponse),
+        (status = 403, description = "Administrator access required", body = ErrorResponse),
+    )
+)]
+pub async fn create_announcement(
+    State(state): State<AppState>,
+    auth_user: axum::Extension<crate::models::auth::AuthContext>,
+    Json(payload): Json<AnnouncementRequest>,
+) -> Result<impl IntoResponse, AppError> {
+    require_admin(&auth_user)?;
+
+    let announcement = InstanceAnnouncement::create(&state.db_pool, &payload, auth_user.user_id).await?;
+    tracing::info!("Administrator {} created announcement {}", auth_user.user_id, announcement.id);
+
+    let response = InstanceAnnouncementResponse {
+        announcement,
+    };
+
+    Ok((
+        StatusCode::CREATED,
+        Json(serde_json::json!({
+            "success": true,
+            "data": response
+        })),
+    ))
+}
+
+/// Replace an announcement's text and schedule
+#[utoipa::path(
+    put,
+    path = "/announcements/{id}",
+    params(("id" = Uuid, Path, description = "Announcement ID")),
+    request_body = AnnouncementRequest,
+    responses(
+        (status = 200, description = "Announcement updated", body = ApiResponse<InstanceAnnouncementResponse>),
+        (status = 400, description = "Invalid title or body, or an expiry before the start", body = ErrorResponse),
+        (status = 403, description = "Administrator access required", body = ErrorResponse),
+        (status = 404, description = "Announcement not found", body = ErrorResponse),
+    )
+)]
+pub async fn update_announcement(
+    State(state): State<AppState>,
+    Path(announcement_id): Path<Uuid>,
+    auth_user: axum::Extension<crate::models::auth::AuthContext>,
+    Json(payload): Json<AnnouncementRequest>,
+) -> Result<impl IntoResponse, AppError> {
+    require_admin(&auth_user)?;
+
+    let announcement = InstanceAnnouncement::update(&state.db_pool, announcement_id, &payload).await?;
+    tracing::info!("Administrator {} updated announcement {}", auth_user.user_id, announcement_id);
+
+    let response = InstanceAnnouncementResponse {
+        announcement,
+    };
+
+    Ok(Json(serde_json::json!({
+        "success": true,
+        "data": response
+    })))
+}
+
+/// Withdraw an announcement; notifications already sent stay
+#[utoipa::path(
+    delete,
+    path = "/announcements/{id}",
+    params(("id" = Uuid, Path, description = "Announcement ID")),
+    responses(
+        (status = 200, description = "Announcement deleted", body = MessageResponse),
+        (status = 403, description = "Administrator access required", body = ErrorResponse),
+        (status = 404, description = "Announcement not found", body = ErrorResponse),
+    )
+)]
+pub async fn delete_announcement(
+    State(state): State<AppState>,
+    Path(announcement_id): Path<Uuid>,
+    auth_user: axum::Extension<crate::models::auth::AuthContext>,
+) -> Result<impl IntoResponse, AppError> {
+    require_admin(&auth_user)?;
+
+    InstanceAnnouncement::delete(&state.db_pool, announcement_id).await?;
+    tracing::info!("Administrator {} deleted announcement {}", auth_user.user_id, announcement_id);
+
+    Ok(Json(serde_json::json!({
+        "success": true,
+        "message": "Announcement deleted"
+    })))
+}
+
+/// Seed parameters
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct SeedParams {
+    /// Load the seed although the instance already has users of its own
+    #[serde(default)]
+    pub force: bool,
+}
+
+/// Load seed data, creating what it describes and is missing
+///
+/// Takes the same YAML as `texler-backend seed --file`, except that files
+/// must be given inline.
+#[utoipa::path(
+    post,
+    path = "/seed",
+    params(SeedParams),
+    request_body(content = String, description = "Seed data", content_type = "application/yaml"),
+    responses(
+        (status = 200, description = "What the seed created and updated", body = ApiResponse<SeedReport>),
+        (status = 400, description = "Invalid seed data", body = ErrorResponse),
+        (status = 403, description = "Administrator access required", body = ErrorResponse),
+        (status = 404, description = "A user the seed names does not exist", body = ErrorResponse),
+        (status = 409, description = "The instance has users the seed does not describe", body = ErrorResponse),
+    )
+)]
+pub async fn seed(
+    State(state): State<AppState>,
+    Query(params): Query<SeedParams>,
+    auth_user: axum::Extension<crate::models::auth::AuthContext>,
+    body: String,
+) -> Result<impl IntoResponse, AppError> {
+    require_admin(&auth_user)?;
+
+    let seed_data = SeedData::parse(&body)?;
+    let report = seed::apply(&state, &seed_data, None, params.force).await?;
+    tracing::info!(
+        "Administrator {} loaded seed data: created {}; updated {}",
+        auth_user.user_id,
+        report.created,
+        report.updated
+    );
+
+    Ok(Json(serde_json::json!({
+        "success": true,
+        "data": report
+    })))
+}
+
+/// Performance report parameters
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct PerformanceParams {
+    /// Slow query sites to list, at most 100
+    pub limit: Option<usize>,
+}
+
+/// Request latencies, slow queries and database pool use of the last hour
+///
+/// Recorded in memory while `FEATURE_METRICS` is on; `enabled` is false and
+/// the lists are empty otherwise. Queries count as slow from
+/// `DATABASE_SLOW_QUERY_MS`.
+#[utoipa::path(
+    get,
+    path = "/performance",
+    params(PerformanceParams),
+    responses(
+        (status = 200, description = "Latency percentiles per route, the most frequent slow query sites and pool samples", body = ApiResponse<PerformanceReport>),
+        (status = 403, description = "Administrator access required", body = ErrorResponse),
+    )
+)]
+pub async fn performance(
+    State(state): State<AppState>,
+    Query(params): Query<PerformanceParams>,
+    auth_user: axum::Extension<crate::models::auth::AuthContext>,
+) -> Result<impl IntoResponse, AppError> {
+    require_admin(&auth_user)?;
+
+    let limit = params.limit.unwrap_or(10).min(100);
+
+    Ok(Json(serde_json::json!({
+        "success": true,
+        "data": state.performance.report(limit)
+    })))
+}
+
+/// Conflict report parameters
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ConflictReportParams {
+    /// Start of the period (default 7 days before `end`)
+    pub start: Option<chrono::DateTime<chrono::Utc>>,
+    /// End of the period (default now)
+    pub end: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+/// Contended project list parameters
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ConflictProjectsParams {
+    /// Projects to list, at most 100
+    pub limit: Option<i64>,
+}
+
+/// Edit conflicts of a period
+///
+/// Requests refused with 409 by route, and undos and redos that edits of
+/// other collaborators moved or left nothing of, with the projects that had
+/// the most. Counts are estimated from the events stored at
+/// `LOG_CONFLICT_SAMPLE_RATE`; conflicts of the last 30 seconds may not be
+/// stored yet.
+#[utoipa::path(
+    get,
+    path = "/conflicts",
+    params(ConflictReportParams, ConflictProjectsParams),
+    responses(
+        (status = 200, description = "Conflicts by kind, outcome and endpoint, and the most contended projects", body = ApiResponse<ConflictReport>),
+        (status = 400, description = "Period ends before it starts", body = ErrorResponse),
+        (status = 403, description = "Administrator access required", body = ErrorResponse),
+    )
+)]
+pub async fn conflicts(
+    State(state): State<AppState>,
+    Query(params): Query<ConflictReportParams>,
+    Query(projects): Query<ConflictProjectsParams>,
+    auth_user: axum::Extension<crate::models::auth::AuthContext>,
+) -> Result<impl IntoResponse, AppError> {
+    require_admin(&auth_user)?;
+
+    let (start, end) = conflict_event::report_period(params.start, params.end)?;
+    let limit = projects.limit.unwrap_or(10).clamp(1, 100);
+    let report = ConflictReport::between(&state.db_pool, start, end, state.conflicts.sample_rate(), limit).await?;
+
+    Ok(Json(serde_json::json!({
+        "success": true,
+        "data": report
+    })))
+}
+
+/// Share of the conflicts of a period resolved automatically
+///
+/// In total and per kind. Refused requests always need the user, so the
+/// rate of `transform` conflicts is the one that tells how well edits are
+/// merged.
+#[utoipa::path(
+    get,
+    path = "/conflicts/resolution",
+    params(ConflictReportParams),
+    responses(
+        (status = 200, description = "Conflicts by outcome and the auto-resolution rate", body = ApiResponse<AutoResolutionReport>),
+        (status = 400, description = "Period ends before it starts", body = ErrorResponse),
+        (status = 403, description = "Administrator access required", body = ErrorResponse),
+    )
+)]
+pub async fn conflict_resolution(
+    State(state): State<AppState>,
+    Query(params): Query<ConflictReportParams>,
+    auth_user: axum::Extension<crate::models::auth::AuthContext>,
+) -> Result<impl IntoResponse, AppError> {
+    require_admin(&auth_user)?;
+
+    let (start, end) = conflict_event::report_period(params.start, params.end)?;
+    let report = AutoResolutionReport::between(&state.db_pool, start, end).await?;
+
+    Ok(Json(serde_json::json!({
+        "success": true,
+        "data": report
+    })))
+}
+
+/// Usage export parameters
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct UsageExportParams {
+    /// `json` (default) or `csv`
+    pub format: Option<UsageFormat>,
+}
+
+/// Usage across workspaces
+#[derive(Debug, Serialize, ToSchema)]
+pub struct UsageReportResponse {
+    /// By workspace, then month; each workspace's current month is provisional
+    pub usage: Vec<UsagePeriod>,
+}
+
+/// Monthly usage of every workspace, for billing
+///
+/// The figures of `GET /workspaces/{workspace_id}/usage` for all
+/// workspaces. With `format=csv` the report is a CSV download with one row
+/// per workspace and month.
+#[utoipa::path(
+    get,
+    path = "/usage",
+    params(UsageParams, UsageExportParams),
+    responses(
+        (status = 200, description = "Usage by workspace and month", body = ApiResponse<UsageReportResponse>),
+        (status = 403, description = "Administrator access required", body = ErrorResponse),
+    )
+)]
+pub async fn usage(
+    State(state): State<AppState>,
+    Query(params): Query<UsageParams>,
+    Query(export): Query<UsageExportParams>,
+    auth_user: axum::Extension<crate::models::auth::AuthContext>,
+) -> Result<axum::response::Response, AppError> {
+    require_admin(&auth_user)?;
+
+    let now = chrono::Utc::now();
+    let usage = usage_period::report(&state.db_pool, None, params.months(), now).await?;
+    if export.format.unwrap_or_default() == UsageFormat::Csv {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::CONTENT_TYPE, HeaderValue::from_static("text/csv; charset=utf-8"));
+        let disposition = format!("attachment; filename=\"usage-{}.csv\"", usage_period::month_of(now));
+        headers.insert(
+            header::CONTENT_DISPOSITION,
+            HeaderValue::from_str(&disposition)
+                .map_err(|_| AppError::Internal("Invalid usage export file name".to_string()))?,
+        );
+        headers.insert(header::CACHE_CONTROL, HeaderValue::from_static("no-store"));
+        return Ok((StatusCode::OK, headers, usage_period::to_csv(&usage)).into_response());
+    }
+
+    Ok(Json(serde_json::json!({
+        "success": true,
+        "data": UsageReportResponse { usage }
+    }))
+    .into_response())
+}
@@ -1,70 +1,130 @@
 //! Authentication request handlers
 
-use crate::error::AppError;
+use crate::email::templates::PasswordResetEmail;
+use crate::email::Locale;
+use crate::error::{AppError, ErrorResponse};
 use crate::server::AppState;
-use crate::models::auth::PasswordUtils;
-use crate::models::user::{CreateUser, User, UserProfile, LoginRequest, LoginResponse, OidcLoginRequest, OidcCallbackRequest};
+use crate::models::ApiResponse;
+use crate::openapi::MessageResponse;
+use crate::models::auth::{PasswordUtils, TokenPair};
+use crate::models::email_change::EmailChange;
+use crate::models::invitation::ProjectInvitation;
+use crate::models::login_protection::{burn_password_check, login_fingerprint, KnownLogin, LoginFailure};
+use crate::models::notification::NotificationService;
+use crate::models::avatar::PictureMirror;
+use crate::models::oidc_flow::{validate_redirect, OidcFailure, OidcFlow, ProviderEndpoints};
+use crate::models::user::{
+    CreateUser, User, UserProfile, LoginRequest, LoginResponse, OidcLoginRequest, OidcLoginUrlResponse, OidcCallbackRequest,
+    OidcCallbackResponse,
+};
 use axum::{
     extract::{State, Json, Query},
-    http::StatusCode,
+    http::{HeaderMap, StatusCode},
     response::IntoResponse,
 };
 use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
 use uuid::Uuid;
-use std::collections::HashMap;
 
 /// User registration request
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 pub struct RegisterRequest {
     pub username: String,
     pub email: String,
     pub password: String,
-    pub display_name: String,
+    pub display_name: crate::models::validation::DisplayName,
 }
 
 /// Registration response
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, ToSchema)]
 pub struct RegisterResponse {
     pub user: UserProfile,
     pub message: String,
+    /// Project invitations that will be attached once the email is verified
+    pub pending_invitations: Vec<ProjectInvitation>,
+}
+
+/// Email verification response
+#[derive(Debug, Serialize, ToSchema)]
+pub struct VerifyEmailResponse {
+    /// Invitations to the verified address, now turned into memberships
+    pub accepted_invitations: Vec<ProjectInvitation>,
+}
+
+/// Configured OIDC provider
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct OidcProviderInfo {
+    pub name: String,
+    pub display_name: String,
+}
+
+/// OIDC providers response
+#[derive(Debug, Serialize, ToSchema)]
+pub struct OidcProvidersResponse {
+    pub enabled: bool,
+    pub providers: Vec<OidcProviderInfo>,
 }
 
 /// Password reset email request
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 pub struct PasswordResetEmailRequest {
     pub email: String,
 }
 
 /// Password reset confirm request
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 pub struct PasswordResetConfirmRequest {
     pub token: String,
     pub new_password: String,
 }
 
 /// Email verification request
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 pub struct VerifyEmailRequest {
     pub token: String,
 }
 
+/// Undo email change request
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct UndoEmailChangeRequest {
+    /// Token from the notice sent to the previous address
+    pub token: String,
+}
+
 /// Refresh token request
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 pub struct RefreshTokenRequest {
     pub refresh_token: String,
 }
 
 /// Logout request
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 pub struct LogoutRequest {
     pub refresh_token: String,
 }
 
 /// Register a new user
+#[utoipa::path(
+    post,
+    path = "/register",
+    request_body = RegisterRequest,
+    responses(
+        (status = 201, description = "User created; the token pair is returned next to `data` as `tokens`", body = ApiResponse<RegisterResponse>),
+        (status = 403, description = "Registration is closed", body = ErrorResponse),
+        (status = 409, description = "Email or username taken", body = ErrorResponse),
+    ),
+    security(())
+)]
 pub async fn register(
     State(state): State<AppState>,
     Json(payload): Json<RegisterRequest>,
 ) -> Result<impl IntoResponse, AppError> {
+    if !state.config.features.registration {
+        return Err(AppError::Authorization(
+            "Registration is closed on this instance".to_string(),
+        ));
+    }
+
     // Validate input
     if payload.username.len() < 3 {
         return Err(AppError::BadRequest(
@@ -88,8 +148,8 @@ pub async fn register(
         ));
     }
 
-    // Check if email already exists
-    if let Some(_) = User::find_by_email(&state.db_pool, &payload.email).await? {
+    // Check if email already exists, or is reserved while a change away from it can be undone
+    if EmailChange::is_taken(&state.db_pool, &payload.email, None).await? {
         return Err(AppError::Conflict(
             "Email already exists".to_string(),
         ));
@@ -119,9 +179,12 @@ pub async fn register(
 
     // TODO: Send verification email with verification.token
 
+    let pending_invitations = ProjectInvitation::pending_for_email(&state.db_pool, &user.email).await?;
+
     let response = RegisterResponse {
         user: user_profile,
         message: "User registered successfully. Please check your email for verification.".to_string(),
+        pending_invitations,
     };
 
     Ok((
@@ -135,23 +198,40 @@ pub async fn register(
 }
 
 /// Login user
+#[utoipa::path(
+    post,
+    path = "/login",
+    request_body = LoginRequest,
+    responses(
+        (status = 200, description = "Signed in", body = ApiResponse<LoginResponse>),
+        (status = 401, description = "Invalid credentials", body = ErrorResponse),
+    ),
+    security(())
+)]
 pub async fn login(
     State(state): State<AppState>,
+    headers: HeaderMap,
     Json(payload): Json<LoginRequest>,
 ) -> Result<impl IntoResponse, AppError> {
-    // Find user by email
-    let user = User::find_by_email(&state.db_pool, &payload.email)
-        .await?
-        .ok_or_else(|| AppError::Authentication("Invalid credentials".to_string()))?;
-
-    // Verify password
-    if !user.verify_password(&payload.password) {
-        return Err(AppError::Authentication("Invalid credentials".to_string()));
-    }
+    let started = std::time::Instant::now();
+
+    let user = match authenticate(&state, &payload).await {
+        Ok(user) => user,
+        Err(e) => {
+            // Unknown, locked, throttled and wrong-password attempts all look alike
+            let min_duration = std::time::Duration::from_millis(state.config.login_protection.min_failure_response_ms);
+            if let Some(remaining) = min_duration.checked_sub(started.elapsed()) {
+                tokio::time::sleep(remaining).await;
+            }
+            return Err(e);
+        }
+    };
 
     // Update last login
     user.update_last_login(&state.db_pool).await?;
 
+    notify_if_new_device(&state, &user, &headers).await;
+
     // Generate tokens
     let token_pair = state.jwt_service.generate_token_pair(&user, vec![])?;
 
@@ -168,13 +248,110 @@ pub async fn login(
     })))
 }
 
+/// Check a password login against the per-account protection rules
+async fn authenticate(state: &AppState, payload: &LoginRequest) -> Result<User, AppError> {
+    let config = &state.config.login_protection;
+    let invalid = || AppError::Authentication("Invalid credentials".to_string());
+
+    let Some(user) = User::find_by_email(&state.db_pool, &payload.email).await? else {
+        burn_password_check(&payload.password);
+        return Err(invalid());
+    };
+
+    if let Some(failure) = LoginFailure::find(&state.db_pool, user.id).await? {
+        if failure.is_locked() || failure.retry_after(config, chrono::Utc::now()).is_some() {
+            burn_password_check(&payload.password);
+            return Err(invalid());
+        }
+    }
+
+    if !user.verify_password(&payload.password) {
+        let failure = LoginFailure::record(&state.db_pool, user.id, config.lockout_threshold).await?;
+
+        if failure.is_locked() {
+            tracing::warn!("Account {} locked after {} failed logins", user.id, failure.failed_count);
+            NotificationService::notify_later(
+                &state.db_resilience,
+                &state.mailer,
+                &user,
+                "account_locked",
+                "Your account has been locked",
+                "Too many failed sign-in attempts were made on your account. Reset your password to unlock it.",
+                Some(serde_json::json!({ "failed_attempts": failure.failed_count })),
+            );
+        }
+
+        return Err(invalid());
+    }
+
+    LoginFailure::clear(&state.db_pool, user.id).await?;
+
+    Ok(user)
+}
+
+/// Record the device of a successful login and tell the user when it is new.
+///
+/// The login has already succeeded, so failing bookkeeping is only logged.
+async fn notify_if_new_device(state: &AppState, user: &User, headers: &HeaderMap) {
+    let ip_address = crate::middleware::client_ip_from_headers(headers).unwrap_or_else(|| "unknown".to_string());
+    let user_agent = headers
+        .get(axum::http::header::USER_AGENT)
+        .and_then(|value| value.to_str().ok());
+    let fingerprint = login_fingerprint(&ip_address, user_agent);
+
+    // The very first login is not suspicious
+    let new_device = async {
+        let has_history = KnownLogin::has_any(&state.db_pool, user.id).await?;
+        let is_new = KnownLogin::record(&state.db_pool, user.id, &fingerprint, &ip_address, user_agent).await?;
+        Ok::<_, AppError>(has_history && is_new)
+    };
+    let new_device = match new_device.await {
+        Ok(new_device) => new_device,
+        Err(e) => {
+            tracing::warn!("Failed to record the login device of user {}: {}", user.id, e);
+            return;
+        }
+    };
+
+    if new_device {
+        let at = chrono::Utc::now();
+        NotificationService::notify_later(
+            &state.db_resilience,
+            &state.mailer,
+            user,
+            "new_login",
+            "New sign-in to your account",
+            &format!(
+                "Your account was signed in to from a new device at {} (IP {}). If this wasn't you, reset your password.",
+                at.to_rfc3339(),
+                ip_address
+            ),
+            Some(serde_json::json!({
+                "at": at,
+                "ip_address": ip_address,
+                "user_agent": user_agent
+            })),
+        );
+    }
+}
+
 /// Refresh access token
+#[utoipa::path(
+    post,
+    path = "/refresh",
+    request_body = RefreshTokenRequest,
+    responses(
+        (status = 200, description = "New token pair", body = ApiResponse<TokenPair>),
+        (status = 401, description = "Invalid refresh token", body = ErrorResponse),
+    ),
+    security(())
+)]
 pub async fn refresh(
     State(state): State<AppState>,
     Json(payload): Json<RefreshTokenRequest>,
 ) -> Result<impl IntoResponse, AppError> {
-    // Verify refresh token
-    let claims = state.jwt_service.verify_token(&payload.refresh_token)?;
+    // Verify refresh token; logged out and revoked ones are rejected
+    let claims = state.jwt_service.verify_token_with_db(&payload.refresh_token, &state.db_pool).await?;
 
     // Find user
     let user = User::find_by_id(&state.db_pool, Uuid::parse_str(&claims.sub).unwrap())
@@ -195,6 +372,16 @@ pub async fn refresh(
 }
 
 /// Logout user
+#[utoipa::path(
+    post,
+    path = "/logout",
+    request_body = LogoutRequest,
+    responses(
+        (status = 200, description = "Refresh token revoked", body = MessageResponse),
+        (status = 401, description = "Invalid refresh token", body = ErrorResponse),
+    ),
+    security(())
+)]
 pub async fn logout(
     State(state): State<AppState>,
     Json(payload): Json<LogoutRequest>,
@@ -226,6 +413,15 @@ pub async fn logout(
 }
 
 /// Request password reset
+#[utoipa::path(
+    post,
+    path = "/forgot-password",
+    request_body = PasswordResetEmailRequest,
+    responses(
+        (status = 200, description = "Sent whether or not the account exists", body = MessageResponse),
+    ),
+    security(())
+)]
 pub async fn forgot_password(
     State(state): State<AppState>,
     Json(payload): Json<PasswordResetEmailRequest>,
@@ -236,9 +432,8 @@ pub async fn forgot_password(
     let reset_request = PasswordResetService::request_reset(&state.db_pool, payload.email.clone()).await?;
 
     if let Some(reset_req) = reset_request {
-        // TODO: Send password reset email with reset_req.token
         tracing::info!("Password reset requested for user: {}", reset_req.email);
-        tracing::debug!("Reset token: {}", reset_req.token);
+        send_password_reset(&state, &reset_req).await?;
     }
 
     // Always return success to prevent email enumeration
@@ -248,7 +443,41 @@ pub async fn forgot_password(
     })))
 }
 
+/// Email the link of a password reset request; a failure to send is only logged
+async fn send_password_reset(
+    state: &AppState,
+    reset_req: &crate::models::password_reset::PasswordResetRequest,
+) -> Result<(), AppError> {
+    if let Some(user) = User::find_by_id(&state.db_pool, reset_req.user_id).await? {
+        let message = PasswordResetEmail {
+            username: user.username,
+            reset_url: format!(
+                "{}/reset-password?token={}",
+                state.config.email.frontend_url, reset_req.token
+            ),
+            expires_in_minutes: (reset_req.expires_at - reset_req.created_at).num_minutes(),
+        };
+        let locale = Locale::for_user(&state.db_pool, user.id).await?;
+        // Failing here would tell whether the account exists
+        if let Err(e) = state.mailer.send(&reset_req.email, locale, &message).await {
+            tracing::warn!("Failed to send the password reset email to {}: {}", user.id, e);
+        }
+    }
+
+    Ok(())
+}
+
 /// Confirm password reset
+#[utoipa::path(
+    post,
+    path = "/reset-password",
+    request_body = PasswordResetConfirmRequest,
+    responses(
+        (status = 200, description = "Password changed", body = MessageResponse),
+        (status = 400, description = "Invalid or expired token", body = ErrorResponse),
+    ),
+    security(())
+)]
 pub async fn reset_password(
     State(state): State<AppState>,
     Json(payload): Json<PasswordResetConfirmRequest>,
@@ -265,6 +494,16 @@ pub async fn reset_password(
 }
 
 /// Verify email address
+#[utoipa::path(
+    post,
+    path = "/verify-email",
+    request_body = VerifyEmailRequest,
+    responses(
+        (status = 200, description = "Email verified; pending invitations were accepted", body = ApiResponse<VerifyEmailResponse>),
+        (status = 400, description = "Invalid or expired token", body = ErrorResponse),
+    ),
+    security(())
+)]
 pub async fn verify_email(
     State(state): State<AppState>,
     Json(payload): Json<VerifyEmailRequest>,
@@ -272,15 +511,63 @@ pub async fn verify_email(
     use crate::models::email_verification::EmailVerificationService;
 
     // Confirm email verification
-    EmailVerificationService::confirm_verification(&state.db_pool, &payload.token).await?;
+    let user = EmailVerificationService::confirm_verification(&state.db_pool, &payload.token).await?;
+
+    // The address is proven now, so invitations sent to it can be attached
+    let accepted_invitations = ProjectInvitation::accept_for_user(&state.db_pool, user.id, &user.email).await?;
 
     Ok(Json(serde_json::json!({
         "success": true,
-        "message": "Email verified successfully"
+        "message": "Email verified successfully",
+        "data": VerifyEmailResponse { accepted_invitations }
+    })))
+}
+
+/// Undo an email change
+///
+/// Called with the token sent to the previous address once a change was
+/// confirmed. Restores that address, signs the account out everywhere and
+/// locks it; a password reset link is sent to the restored address to unlock it.
+#[utoipa::path(
+    post,
+    path = "/undo-email-change",
+    request_body = UndoEmailChangeRequest,
+    responses(
+        (status = 200, description = "Change undone; the account is locked until its password is reset", body = MessageResponse),
+        (status = 400, description = "Invalid or expired token", body = ErrorResponse),
+        (status = 409, description = "The previous address was taken in the meantime", body = ErrorResponse),
+    ),
+    security(())
+)]
+pub async fn undo_email_change(
+    State(state): State<AppState>,
+    Json(payload): Json<UndoEmailChangeRequest>,
+) -> Result<impl IntoResponse, AppError> {
+    use crate::models::password_reset::PasswordResetService;
+
+    let (change, user) = EmailChange::undo(&state.db_pool, &payload.token).await?;
+    tracing::warn!("Email change {} of user {} was undone from the previous address", change.id, user.id);
+
+    if let Some(reset_req) = PasswordResetService::request_reset(&state.db_pool, user.email.clone()).await? {
+        send_password_reset(&state, &reset_req).await?;
+    }
+
+    Ok(Json(serde_json::json!({
+        "success": true,
+        "message": "The email change was undone. Reset your password to unlock your account."
     })))
 }
 
 /// Get OIDC providers
+#[utoipa::path(
+    get,
+    path = "/oidc/providers",
+    responses(
+        (status = 200, description = "Configured OIDC providers", body = ApiResponse<OidcProvidersResponse>),
+        (status = 404, description = "OIDC is disabled", body = ErrorResponse),
+    ),
+    security(())
+)]
 pub async fn get_oidc_providers(
     State(state): State<AppState>,
 ) -> Result<impl IntoResponse, AppError> {
@@ -291,27 +578,35 @@ pub async fn get_oidc_providers(
         });
     }
 
-    let providers: Vec<_> = state.config.oidc.providers.iter()
-        .map(|p| serde_json::json!({
-            "name": p.name,
-            "display_name": p.display_name,
-        }))
+    let providers = state.config.oidc.providers.iter()
+        .map(|p| OidcProviderInfo {
+            name: p.name.clone(),
+            display_name: p.display_name.clone(),
+        })
         .collect();
 
     Ok(Json(serde_json::json!({
         "success": true,
-        "data": {
-            "enabled": true,
-            "providers": providers
+        "data": OidcProvidersResponse {
+            enabled: true,
+            providers,
         }
     })))
 }
 
-/// Complete OIDC login flow using authware
-pub async fn oidc_login(
-    State(state): State<AppState>,
-    Json(payload): Json<OidcLoginRequest>,
-) -> Result<impl IntoResponse, AppError> {
+/// Provider callback query parameters
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct OidcCallbackParams {
+    /// Missing when the provider sent an `error` instead
+    pub code: Option<String>,
+    pub state: String,
+    /// Why the provider refused the login, e.g. `access_denied`
+    pub error: Option<String>,
+}
+
+/// A configured provider, by name
+fn oidc_provider<'a>(state: &'a AppState, name: &str) -> Result<&'a crate::config::OidcProvider, AppError> {
     if !state.config.oidc.enabled {
         return Err(AppError::NotFound {
             entity: "OIDC".to_string(),
@@ -319,94 +614,201 @@ pub async fn oidc_login(
         });
     }
 
-    let _oidc_client = state.oidc_clients.get(&payload.provider)
+    state.config.oidc.providers.iter()
+        .find(|provider| provider.name == name)
         .ok_or_else(|| AppError::NotFound {
-            entity: "OIDC Client".to_string(),
-            id: payload.provider.clone(),
-        })?;
+            entity: "OIDC provider".to_string(),
+            id: name.to_string(),
+        })
+}
+
+/// Start an OIDC login
+///
+/// Returns the provider's authorization URL. The state, PKCE verifier and
+/// nonce of the login stay on the server; the provider must redirect back
+/// within `OIDC_FLOW_TTL` seconds, and each login can complete only once.
+#[utoipa::path(
+    post,
+    path = "/oidc/login",
+    request_body = OidcLoginRequest,
+    responses(
+        (status = 200, description = "Authorization URL to send the user to", body = ApiResponse<OidcLoginUrlResponse>),
+        (status = 400, description = "Redirect target outside this site", body = ErrorResponse),
+        (status = 404, description = "OIDC is disabled or the provider is unknown", body = ErrorResponse),
+        (status = 502, description = "The provider's discovery document could not be fetched", body = ErrorResponse),
+    ),
+    security(())
+)]
+pub async fn oidc_login(
+    State(state): State<AppState>,
+    Json(payload): Json<OidcLoginRequest>,
+) -> Result<impl IntoResponse, AppError> {
+    let provider = oidc_provider(&state, &payload.provider)?;
+    let redirect_to = payload.redirect_to.as_deref().map(validate_redirect).transpose()?;
+
+    let endpoints = ProviderEndpoints::resolve(&state.http_client, provider).await?;
+    let flow = OidcFlow::start(
+        &state.db_pool,
+        &provider.name,
+        endpoints.issues_id_tokens(provider),
+        redirect_to,
+        std::time::Duration::from_secs(state.config.oidc.flow_ttl),
+    )
+    .await?;
 
-    // TODO: Implement proper OIDC flow with authware
-    // For now, return a placeholder implementation
     Ok(Json(serde_json::json!({
-        "success": false,
-        "message": "OIDC implementation pending authware crate integration"
+        "success": true,
+        "data": OidcLoginUrlResponse {
+            auth_url: endpoints.authorization_url(provider, &flow)?,
+            expires_at: flow.expires_at,
+        }
     })))
 }
 
-/// OIDC callback handler using authware
-pub async fn oidc_callback(
-    State(_state): State<AppState>,
-    _params: Query<HashMap<String, String>>,
+/// Sign in with the code of a started OIDC login, consuming it
+async fn complete_oidc_login(
+    state: &AppState,
+    headers: &HeaderMap,
+    flow_state: &str,
+    provider: Option<&str>,
+    code: Option<&str>,
+) -> Result<OidcCallbackResponse, AppError> {
+    if let Some(provider) = provider {
+        oidc_provider(state, provider)?;
+    }
+    let flow = OidcFlow::consume(&state.db_pool, flow_state, provider).await?;
+    let code = code.ok_or(OidcFailure::Denied)?;
+    let provider = oidc_provider(state, &flow.provider)?;
+
+    let endpoints = ProviderEndpoints::resolve(&state.http_client, provider)
+        .await
+        .map_err(|e| match e {
+            AppError::Upstream(detail) => {
+                tracing::warn!("OIDC login failed at the provider: {}", detail);
+                OidcFailure::ExchangeFailed.into()
+            }
+            e => e,
+        })?;
+    let user_info = endpoints.exchange(&state.http_client, provider, &flow, code).await?;
+    let mirror = PictureMirror::from_state(state);
+    let user = User::find_or_create_oidc(&state.db_pool, &user_info, &provider.name, mirror.as_ref()).await?;
+
+    user.update_last_login(&state.db_pool).await?;
+    notify_if_new_device(state, &user, headers).await;
+    let token_pair = state.jwt_service.generate_token_pair(&user, vec![])?;
+
+    Ok(OidcCallbackResponse {
+        login: LoginResponse {
+            user: UserProfile::from(user),
+            access_token: token_pair.access_token,
+            refresh_token: token_pair.refresh_token,
+            expires_in: token_pair.expires_in,
+        },
+        redirect_to: flow.redirect_to,
+    })
+}
+
+/// Complete a login, counting refused callbacks by reason
+async fn oidc_callback_response(
+    state: &AppState,
+    headers: &HeaderMap,
+    flow_state: &str,
+    provider: Option<&str>,
+    code: Option<&str>,
 ) -> Result<impl IntoResponse, AppError> {
-    // TODO: Implement proper OIDC callback handling with authware
+    let response = complete_oidc_login(state, headers, flow_state, provider, code)
+        .await
+        .inspect_err(|e| state.oidc_metrics.record(e))?;
+
     Ok(Json(serde_json::json!({
-        "success": false,
-        "message": "OIDC implementation pending authware crate integration"
+        "success": true,
+        "data": response
     })))
 }
 
-/// OIDC callback handler (POST version for mobile apps)
+/// Complete an OIDC login where the provider redirects to
+///
+/// A login's state works once and only until it expires; expired, used and
+/// unknown states are refused with `OIDC_LOGIN_EXPIRED`, `OIDC_LOGIN_USED`
+/// and `OIDC_LOGIN_INVALID`, and the login must be started again.
+#[utoipa::path(
+    get,
+    path = "/oidc/callback",
+    params(OidcCallbackParams),
+    responses(
+        (status = 200, description = "Signed in", body = ApiResponse<OidcCallbackResponse>),
+        (status = 400, description = "Expired, used or unknown login state", body = ErrorResponse),
+        (status = 401, description = "The provider refused the login, or its ID token does not match it", body = ErrorResponse),
+        (status = 502, description = "The code could not be exchanged with the provider", body = ErrorResponse),
+    ),
+    security(())
+)]
+pub async fn oidc_callback(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(params): Query<OidcCallbackParams>,
+) -> Result<impl IntoResponse, AppError> {
+    if let Some(error) = &params.error {
+        tracing::info!("OIDC provider refused a login: {}", error);
+    }
+    oidc_callback_response(&state, &headers, &params.state, None, params.code.as_deref()).await
+}
+
+/// Complete an OIDC login with a code the client received (for mobile apps)
+///
+/// Like the redirect callback, but the login must have been started with
+/// `provider`.
+#[utoipa::path(
+    post,
+    path = "/oidc/callback",
+    request_body = OidcCallbackRequest,
+    responses(
+        (status = 200, description = "Signed in", body = ApiResponse<OidcCallbackResponse>),
+        (status = 400, description = "Expired, used or unknown login state, or one of another provider", body = ErrorResponse),
+        (status = 401, description = "The ID token does not match the login", body = ErrorResponse),
+        (status = 404, description = "OIDC is disabled or the provider is unknown", body = ErrorResponse),
+        (status = 502, description = "The code could not be exchanged with the provider", body = ErrorResponse),
+    ),
+    security(())
+)]
 pub async fn oidc_callback_post(
-    State(_state): State<AppState>,
-    _payload: Json<OidcCallbackRequest>,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(payload): Json<OidcCallbackRequest>,
 ) -> Result<impl IntoResponse, AppError> {
-    // TODO: Implement proper OIDC callback handling with authware
-    Ok(Json(serde_json::json!({
-        "success": false,
-        "message": "OIDC implementation pending authware crate integration"
-    })))
+    oidc_callback_response(&state, &headers, &payload.state, Some(&payload.provider), Some(&payload.code)).await
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::config::Config;
-    use sqlx::PgPool;
-
-    // Note: These tests would require a test database setup
-    // For now, they serve as examples of how to structure the tests
+    use crate::testing::{create_test_user, test_state, TestDb};
+    use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
 
     #[tokio::test]
     async fn test_register_validation() {
-        let config = Config::load().unwrap();
-        let jwt_service = JwtService::new(
-            &config.jwt.secret,
-            config.jwt.issuer.clone(),
-            config.jwt.expiration as i64,
-            config.jwt.refresh_expiration as i64,
-        ).unwrap();
-
-        let state = AuthState {
-            db_pool: PgPool::connect("postgresql://test").await.unwrap(),
-            jwt_service,
-        };
+        let Some(db) = TestDb::start().await else { return };
+        let state = test_state(&db).await;
 
         // Test invalid username
         let request = RegisterRequest {
             username: "ab".to_string(), // Too short
             email: "test@example.com".to_string(),
             password: "ValidPass1!".to_string(),
-            display_name: "Test User".to_string(),
+            display_name: crate::models::validation::DisplayName::new("Test User").unwrap(),
         };
 
         let result = register(State(state.clone()), Json(request)).await;
-        assert!(result.is_err());
+        assert!(matches!(result, Err(AppError::BadRequest(_))));
     }
 
     #[tokio::test]
     async fn test_login_validation() {
-        let config = Config::load().unwrap();
-        let jwt_service = JwtService::new(
-            &config.jwt.secret,
-            config.jwt.issuer.clone(),
-            config.jwt.expiration as i64,
-            config.jwt.refresh_expiration as i64,
-        ).unwrap();
-
-        let state = AuthState {
-            db_pool: PgPool::connect("postgresql://test").await.unwrap(),
-            jwt_service,
-        };
+        let Some(db) = TestDb::start().await else { return };
+        let state = test_state(&db).await;
+        let user = create_test_user(&db.pool).await;
 
         // Test invalid email
         let request = LoginRequest {
@@ -414,7 +816,164 @@ mod tests {
             password: "password".to_string(),
         };
 
-        let result = login(State(state), Json(request)).await;
+        let result = login(State(state.clone()), HeaderMap::new(), Json(request)).await;
         assert!(result.is_err());
+
+        // Test wrong password for an existing account
+        let request = LoginRequest {
+            email: user.email.clone(),
+            password: "wrong-password".to_string(),
+        };
+
+        let result = login(State(state.clone()), HeaderMap::new(), Json(request)).await;
+        assert!(result.is_err());
+
+        let request = LoginRequest {
+            email: user.email,
+            password: "password123".to_string(),
+        };
+
+        let result = login(State(state), HeaderMap::new(), Json(request)).await;
+        assert!(result.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_login_survives_failed_device_bookkeeping() {
+        let Some(db) = TestDb::start().await else { return };
+        let state = test_state(&db).await;
+        let user = create_test_user(&db.pool).await;
+        let request = || LoginRequest {
+            email: user.email.clone(),
+            password: "password123".to_string(),
+        };
+
+        {
+            let _armed = crate::models::fail_point::arm("known_login_record");
+            assert!(login(State(state.clone()), HeaderMap::new(), Json(request())).await.is_ok());
+        }
+        let known = || async {
+            sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM known_logins WHERE user_id = $1")
+                .bind(user.id)
+                .fetch_one(&db.pool)
+                .await
+                .unwrap()
+        };
+        assert_eq!(known().await, 0);
+
+        // The device is recorded on the next login that can
+        assert!(login(State(state), HeaderMap::new(), Json(request())).await.is_ok());
+        assert_eq!(known().await, 1);
+    }
+
+    async fn response_json(response: axum::response::Response) -> serde_json::Value {
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        serde_json::from_slice(&bytes).unwrap()
+    }
+
+    /// A local OIDC provider that checks the PKCE verifier and signs the nonce
+    /// the test hands it into the ID token; returns its URL
+    async fn mock_provider(login: Arc<Mutex<Option<(String, String)>>>) -> String {
+        use axum::routing::{get, post};
+        use sha2::{Digest, Sha256};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let issuer = url.clone();
+        let discovery = serde_json::json!({
+            "issuer": url,
+            "authorization_endpoint": format!("{}/authorize", url),
+            "token_endpoint": format!("{}/token", url),
+            "userinfo_endpoint": format!("{}/userinfo", url),
+        });
+        let email = format!("oidc_{}@example.com", &Uuid::new_v4().simple().to_string()[..12]);
+
+        let app = axum::Router::new()
+            .route("/.well-known/openid-configuration", get(move || async move { Json(discovery) }))
+            .route(
+                "/token",
+                post(move |axum::Form(form): axum::Form<HashMap<String, String>>| async move {
+                    let (challenge, nonce) = login.lock().unwrap().clone().unwrap();
+                    let verifier = form.get("code_verifier").cloned().unwrap_or_default();
+                    if URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes())) != challenge {
+                        return Err(StatusCode::BAD_REQUEST);
+                    }
+                    let claims = serde_json::json!({
+                        "iss": issuer,
+                        "aud": "texler",
+                        "sub": "mock-1",
+                        "exp": chrono::Utc::now().timestamp() + 300,
+                        "nonce": nonce,
+                    });
+                    let key = jsonwebtoken::EncodingKey::from_secret(b"provider key");
+                    let id_token = jsonwebtoken::encode(&jsonwebtoken::Header::default(), &claims, &key).unwrap();
+                    Ok(Json(serde_json::json!({ "access_token": "access", "token_type": "Bearer", "id_token": id_token })))
+                }),
+            )
+            .route(
+                "/userinfo",
+                get(move || async move {
+                    Json(serde_json::json!({ "sub": "mock-1", "email": email, "email_verified": true, "name": "Mock User" }))
+                }),
+            );
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        url
+    }
+
+    #[tokio::test]
+    async fn test_oidc_callback_completes_a_login_once() {
+        let Some(db) = TestDb::start().await else { return };
+        let login = Arc::new(Mutex::new(None));
+        let issuer_url = mock_provider(login.clone()).await;
+
+        let mut config = crate::testing::test_config();
+        config.oidc.enabled = true;
+        config.oidc.providers = vec![crate::config::OidcProvider {
+            name: "mock".to_string(),
+            display_name: "Mock".to_string(),
+            client_id: "texler".to_string(),
+            client_secret: "secret".to_string(),
+            issuer_url,
+            redirect_uri: "http://localhost:8080/api/v1/auth/oidc/callback".to_string(),
+            scopes: vec!["openid".to_string(), "email".to_string()],
+        }];
+        let state = AppState::new(config, db.pool.clone()).await.unwrap();
+
+        let request = OidcLoginRequest { provider: "mock".to_string(), redirect_to: Some("/projects".to_string()) };
+        let response = oidc_login(State(state.clone()), Json(request)).await.unwrap().into_response();
+        let body = response_json(response).await;
+        let auth_url = url::Url::parse(body["data"]["auth_url"].as_str().unwrap()).unwrap();
+        let query: HashMap<String, String> = auth_url.query_pairs().into_owned().collect();
+        assert_eq!(query["code_challenge_method"], "S256");
+        assert!(!query.contains_key("code_verifier"));
+        *login.lock().unwrap() = Some((query["code_challenge"].clone(), query["nonce"].clone()));
+
+        // Two callbacks race for the same state: one signs in, the other is refused
+        let callback = |state: AppState, flow_state: String| {
+            tokio::spawn(async move {
+                let request = OidcCallbackRequest { code: "code".to_string(), state: flow_state, provider: "mock".to_string() };
+                oidc_callback_post(State(state), HeaderMap::new(), Json(request)).await.map(|r| r.into_response())
+            })
+        };
+        let racing = [callback(state.clone(), query["state"].clone()), callback(state.clone(), query["state"].clone())];
+        let mut outcomes = Vec::new();
+        for callback in racing {
+            outcomes.push(callback.await.unwrap());
+        }
+        let (signed_in, refused): (Vec<_>, Vec<_>) = outcomes.into_iter().partition(|outcome| outcome.is_ok());
+        assert_eq!((signed_in.len(), refused.len()), (1, 1));
+        assert!(matches!(refused[0], Err(AppError::OidcLogin(OidcFailure::ConsumedState))));
+
+        let body = response_json(signed_in.into_iter().next().unwrap().unwrap()).await;
+        assert_eq!(body["data"]["redirect_to"], "/projects");
+        assert_eq!(body["data"]["user"]["display_name"], "Mock User");
+        assert!(body["data"]["access_token"].is_string());
+
+        // A replay later is refused the same way and counted
+        let Err(error) = callback(state.clone(), query["state"].clone()).await.unwrap() else {
+            panic!("a replayed callback signed in");
+        };
+        assert_eq!(error.error_code(), "OIDC_LOGIN_USED");
+        assert_eq!(state.oidc_metrics.count(OidcFailure::ConsumedState), 2);
+        assert!(state.oidc_metrics.metrics_text().contains(r#"texler_oidc_callback_failures_total{reason="consumed_state"} 2"#));
     }
 }
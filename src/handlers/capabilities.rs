@@ -0,0 +1,342 @@
+//! Instance capabilities
+//!
+//! The frontend reads `GET /api/v1/capabilities` once at boot instead of
+//! assuming the defaults, so self-hosted instances with other limits, engines
+//! or sign-in options work without a rebuild. The document is assembled field
+//! by field from the configuration, never by serializing it, so secrets and
+//! hosts cannot leak into it when new settings are added. Its shape is part of
+//! the API: add fields, but do not rename or remove them.
+
+use crate::config::Config;
+use crate::handlers::auth::OidcProviderInfo;
+use crate::models::compile_environment::CompileEnvironment;
+use crate::models::project_limits::{LimitMode, ProjectLimits};
+use crate::models::{ApiResponse, LatexEngine};
+use crate::server::AppState;
+use axum::{extract::State, response::IntoResponse, Json};
+use serde::Serialize;
+use tokio::sync::OnceCell;
+use utoipa::ToSchema;
+
+/// Engines the compiler knows how to run
+const KNOWN_ENGINES: [LatexEngine; 4] =
+    [LatexEngine::Pdflatex, LatexEngine::Xelatex, LatexEngine::Lualatex, LatexEngine::Typst];
+
+/// Engine versions, probed on first use; they only change with the TeX or Typst installation
+static ENGINE_VERSIONS: OnceCell<Vec<(LatexEngine, Option<String>)>> = OnceCell::const_new();
+
+/// What this instance supports
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct Capabilities {
+    /// Server version
+    pub version: String,
+    pub features: FeatureCapabilities,
+    pub latex: LatexCapabilities,
+    pub limits: LimitCapabilities,
+    pub auth: AuthCapabilities,
+}
+
+/// Enabled features
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct FeatureCapabilities {
+    pub websocket: bool,
+    pub collaboration: bool,
+    pub search: bool,
+    /// Notification and invitation emails are delivered
+    pub email: bool,
+    /// Password reset links can be delivered by email
+    pub password_reset: bool,
+    pub latex_compilation: bool,
+}
+
+/// LaTeX compilation
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct LatexCapabilities {
+    pub default_engine: String,
+    pub engines: Vec<EngineCapability>,
+    /// Time budget of a compilation, in milliseconds
+    pub timeout_ms: u64,
+}
+
+/// An available LaTeX engine
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct EngineCapability {
+    pub name: String,
+    /// First line of `<engine> --version`, `null` when it could not be run
+    pub version: Option<String>,
+}
+
+/// Size and count limits, sizes in bytes
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct LimitCapabilities {
+    pub max_upload_size: u64,
+    pub upload_chunk_size: u64,
+    pub upload_max_chunk_size: u64,
+    pub project_quota: u64,
+    /// Live files a project may have
+    pub max_project_files: u64,
+    /// Folders a file may be nested in
+    pub max_path_depth: u64,
+    /// Bytes of a path, without its leading slash
+    pub max_path_length: u64,
+    pub import_max_size: u64,
+    /// What imports and clones do with files over a project limit
+    pub import_limit_mode: LimitMode,
+    /// Largest request body outside of chunked uploads
+    pub max_request_body_size: u64,
+    pub websocket_message_size: u64,
+    /// Collaborators and pending invitations per project, `null` for no limit
+    pub max_collaborators_per_project: Option<u64>,
+}
+
+/// Ways to sign in
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct AuthCapabilities {
+    /// Whether new accounts can sign up with a password
+    pub registration_open: bool,
+    pub oidc_enabled: bool,
+    pub oidc_providers: Vec<OidcProviderInfo>,
+}
+
+impl Capabilities {
+    /// Build the document from the configuration and probed engine versions
+    pub fn from_config(config: &Config, engine_versions: &[(LatexEngine, Option<String>)]) -> Self {
+        let features = &config.features;
+        let storage = &features.file_storage;
+        let project_limits = ProjectLimits::from_config(storage);
+        let email = config.email.delivers(features);
+
+        let engines = config
+            .latex
+            .engines
+            .iter()
+            .map(|name| EngineCapability {
+                name: name.clone(),
+                version: engine_versions
+                    .iter()
+                    .find(|(engine, _)| engine.command() == name)
+                    .and_then(|(_, version)| version.clone()),
+            })
+            .collect();
+
+        let oidc_providers: Vec<OidcProviderInfo> = if config.oidc.enabled {
+            config
+                .oidc
+                .providers
+                .iter()
+                .map(|provider| OidcProviderInfo {
+                    name: provider.name.clone(),
+                    display_name: provider.display_name.clone(),
+                })
+                .collect()
+        } else {
+            Vec::new()
+        };
+
+        Self {
+            version: crate::VERSION.to_string(),
+            features: FeatureCapabilities {
+                websocket: features.websocket,
+                collaboration: features.collaboration,
+                search: features.search,
+                email,
+                password_reset: email,
+                latex_compilation: features.latex_compilation,
+            },
+            latex: LatexCapabilities {
+                default_engine: config.latex.default_engine.clone(),
+                engines,
+                timeout_ms: config.latex.timeout,
+            },
+            limits: LimitCapabilities {
+                max_upload_size: storage.max_upload_size,
+                upload_chunk_size: storage.upload_chunk_size,
+                upload_max_chunk_size: storage.upload_max_chunk_size,
+                project_quota: storage.project_quota,
+                max_project_files: project_limits.max_files,
+                max_path_depth: project_limits.max_path_depth,
+                max_path_length: project_limits.max_path_length,
+                import_max_size: storage.import_max_size,
+                import_limit_mode: project_limits.import_mode,
+                max_request_body_size: crate::server::request_body_limit(config) as u64,
+                websocket_message_size: config.websocket.message_size_limit as u64,
+                max_collaborators_per_project: config.limits.max_collaborators(),
+            },
+            auth: AuthCapabilities {
+                registration_open: features.registration,
+                oidc_enabled: !oidc_providers.is_empty(),
+                oidc_providers,
+            },
+        }
+    }
+}
+
+/// Versions of the engines the compiler can run
+async fn engine_versions() -> &'static [(LatexEngine, Option<String>)] {
+    ENGINE_VERSIONS
+        .get_or_init(|| async {
+            let probes = KNOWN_ENGINES.map(CompileEnvironment::capture);
+            futures::future::join_all(probes)
+                .await
+                .into_iter()
+                .map(|environment| (environment.engine, environment.engine_version))
+                .collect()
+        })
+        .await
+}
+
+/// Get what this instance supports
+///
+/// Public, so the frontend can read it before anyone signs in.
+#[utoipa::path(
+    get,
+    path = "/api/v1/capabilities",
+    tag = "capabilities",
+    responses(
+        (status = 200, description = "Features, engines and limits of this instance", body = ApiResponse<Capabilities>),
+    ),
+    security(())
+)]
+pub async fn get_capabilities(State(state): State<AppState>) -> impl IntoResponse {
+    let capabilities = Capabilities::from_config(&state.config, engine_versions().await);
+
+    Json(serde_json::json!({
+        "success": true,
+        "data": capabilities
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::OidcProvider;
+    use crate::testing::test_config;
+
+    fn fixed_config() -> Config {
+        let mut config = test_config();
+        config.features.websocket = true;
+        config.features.collaboration = true;
+        config.features.search = false;
+        config.features.email = true;
+        config.features.latex_compilation = true;
+        config.features.registration = false;
+        config.features.file_storage.max_upload_size = 1024;
+        config.features.file_storage.upload_chunk_size = 256;
+        config.features.file_storage.upload_max_chunk_size = 512;
+        config.features.file_storage.project_quota = 4096;
+        config.features.file_storage.import_max_size = 2048;
+        config.features.file_storage.max_project_files = 500;
+        config.features.file_storage.max_path_depth = 8;
+        config.features.file_storage.max_path_length = 256;
+        config.features.file_storage.import_limit_mode = "strict".to_string();
+        config.latex.engines = vec!["pdflatex".to_string(), "xelatex".to_string(), "context".to_string()];
+        config.latex.default_engine = "pdflatex".to_string();
+        config.latex.timeout = 30000;
+        config.latex.output_size_limit = 100;
+        config.websocket.message_size_limit = 64;
+        config.limits.max_collaborators_per_project = 0;
+        config.email.smtp_host = "smtp.internal.example".to_string();
+        config.email.dev_dir = None;
+        config.email.smtp_password = "smtp-password-marker".to_string();
+        config.jwt.secret = "jwt-secret-marker".to_string();
+        config.oidc.enabled = true;
+        config.oidc.providers = vec![OidcProvider {
+            name: "github".to_string(),
+            display_name: "GitHub".to_string(),
+            client_id: "client-id-marker".to_string(),
+            client_secret: "client-secret-marker".to_string(),
+            issuer_url: "https://issuer.internal.example".to_string(),
+            redirect_uri: "https://texler.example/callback".to_string(),
+            scopes: vec!["read:user".to_string()],
+        }];
+        config
+    }
+
+    fn fixed_versions() -> Vec<(LatexEngine, Option<String>)> {
+        vec![
+            (LatexEngine::Pdflatex, Some("pdfTeX 3.141592653-2.6-1.40.25 (TeX Live 2023)".to_string())),
+            (LatexEngine::Xelatex, None),
+        ]
+    }
+
+    /// The frontend depends on this exact shape; extend it, do not change it
+    #[test]
+    fn test_document_snapshot() {
+        let document = serde_json::to_value(Capabilities::from_config(&fixed_config(), &fixed_versions())).unwrap();
+
+        assert_eq!(
+            document,
+            serde_json::json!({
+                "version": crate::VERSION,
+                "features": {
+                    "websocket": true,
+                    "collaboration": true,
+                    "search": false,
+                    "email": true,
+                    "password_reset": true,
+                    "latex_compilation": true
+                },
+                "latex": {
+                    "default_engine": "pdflatex",
+                    "engines": [
+                        { "name": "pdflatex", "version": "pdfTeX 3.141592653-2.6-1.40.25 (TeX Live 2023)" },
+                        { "name": "xelatex", "version": null },
+                        { "name": "context", "version": null }
+                    ],
+                    "timeout_ms": 30000
+                },
+                "limits": {
+                    "max_upload_size": 1024,
+                    "upload_chunk_size": 256,
+                    "upload_max_chunk_size": 512,
+                    "project_quota": 4096,
+                    "max_project_files": 500,
+                    "max_path_depth": 8,
+                    "max_path_length": 256,
+                    "import_max_size": 2048,
+                    "import_limit_mode": "strict",
+                    "max_request_body_size": 1000,
+                    "websocket_message_size": 64,
+                    "max_collaborators_per_project": null
+                },
+                "auth": {
+                    "registration_open": false,
+                    "oidc_enabled": true,
+                    "oidc_providers": [{ "name": "github", "display_name": "GitHub" }]
+                }
+            })
+        );
+    }
+
+    #[test]
+    fn test_document_leaks_no_secrets() {
+        let mut config = fixed_config();
+        config.limits.max_collaborators_per_project = 25;
+        let document = serde_json::to_string(&Capabilities::from_config(&config, &fixed_versions())).unwrap();
+
+        for secret in [
+            "smtp.internal.example",
+            "smtp-password-marker",
+            "jwt-secret-marker",
+            "client-id-marker",
+            "client-secret-marker",
+            "issuer.internal.example",
+        ] {
+            assert!(!document.contains(secret), "capabilities contain {:?}", secret);
+        }
+        assert!(document.contains("\"max_collaborators_per_project\":25"));
+    }
+
+    #[test]
+    fn test_email_needs_smtp_host() {
+        let mut config = fixed_config();
+        config.email.smtp_host.clear();
+        config.oidc.enabled = false;
+        let capabilities = Capabilities::from_config(&config, &[]);
+
+        assert!(!capabilities.features.email);
+        assert!(!capabilities.features.password_reset);
+        assert!(!capabilities.auth.oidc_enabled);
+        assert!(capabilities.auth.oidc_providers.is_empty());
+    }
+}
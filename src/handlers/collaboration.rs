@@ -1,44 +1,85 @@
 //! Collaboration request handlers
 
-use crate::error::AppError;
+use crate::error::{AppError, ErrorResponse};
 use crate::models::collaboration::{
     CollaborationSession, CreateCollaborationSession, UpdateCollaborationSession,
     SessionParticipant, SessionOperation, SessionMessage, SessionInvitation,
-    SessionType, ParticipantRole, OperationType, MessageType
+    SessionType, ParticipantRole, OperationType, MessageType, SETTINGS_UPDATED
 };
 use crate::models::auth::AuthContext;
+use crate::models::user::User;
+use crate::models::chat_attachment::{self, AttachmentView, ChatAttachment};
+use crate::models::file_scan;
+use crate::models::inline_render::{RenderedMath, MAX_RENDERS_PER_REQUEST};
+use crate::models::mention::MentionedUser;
+use crate::models::session_access::{self, JoinCode, JoinCredentials, SessionAccess};
+use crate::models::session_anonymity::SessionMask;
+use crate::models::session_schedule;
+use crate::models::session_summary::SessionSummary;
+use crate::models::{ApiResponse, PaginationParams};
+use crate::openapi::MessageResponse;
+use crate::models::validation::FileName;
+use crate::scanner::ScanVerdict;
 use axum::{
-    extract::{Path, Query, State},
-    http::StatusCode,
-    response::{IntoResponse, Response},
+    extract::{Multipart, Path, Query, State},
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::IntoResponse,
     Json,
 };
 use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
 use uuid::Uuid;
 
 /// Collaboration session response
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, ToSchema)]
 pub struct CollaborationSessionResponse {
     pub session: CollaborationSession,
     pub participants: Vec<SessionParticipant>,
 }
 
 /// Sessions list response
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, ToSchema)]
 pub struct SessionsListResponse {
     pub sessions: Vec<CollaborationSession>,
     pub pagination: crate::models::PaginationInfo,
 }
 
+/// Upcoming sessions window; defaults to the next 30 days
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct UpcomingSessionsParams {
+    /// Start of the window (default now)
+    pub from: Option<chrono::DateTime<chrono::Utc>>,
+    /// End of the window (default 30 days after `from`)
+    pub to: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+/// Upcoming sessions response
+#[derive(Debug, Serialize, ToSchema)]
+pub struct UpcomingSessionsResponse {
+    /// Soonest first
+    pub sessions: Vec<CollaborationSession>,
+}
+
 /// Session join request
-#[derive(Debug, Deserialize)]
+///
+/// Sessions protected by a password or join code accept either one.
+#[derive(Debug, Deserialize, ToSchema)]
 pub struct JoinSessionRequest {
     pub role: ParticipantRole,
     pub password: Option<String>,
+    pub join_code: Option<String>,
+}
+
+/// Session password change request
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct SessionPasswordRequest {
+    /// New password, `null` to remove it
+    pub password: Option<String>,
 }
 
 /// Session operation request
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 pub struct SessionOperationRequest {
     pub operation_type: OperationType,
     pub position: Option<i32>,
@@ -48,7 +89,7 @@ pub struct SessionOperationRequest {
 }
 
 /// Session message request
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 pub struct SessionMessageRequest {
     pub content: String,
     pub message_type: MessageType,
@@ -56,7 +97,7 @@ pub struct SessionMessageRequest {
 }
 
 /// Session invitation request
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 pub struct SessionInvitationRequest {
     pub email: Option<String>,
     pub user_id: Option<Uuid>,
@@ -64,8 +105,92 @@ pub struct SessionInvitationRequest {
     pub message: Option<String>,
 }
 
+/// Joined session response
+#[derive(Debug, Serialize, ToSchema)]
+pub struct JoinSessionResponse {
+    pub participant: SessionParticipant,
+    /// Active participants including the caller
+    pub participants: Vec<SessionParticipant>,
+}
+
+/// Session participants response
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ParticipantsResponse {
+    pub participants: Vec<SessionParticipant>,
+}
+
+/// Recorded operation response
+#[derive(Debug, Serialize, ToSchema)]
+pub struct OperationResponse {
+    pub operation: SessionOperation,
+}
+
+/// Session message with its rendered math
+#[derive(Debug, Serialize, ToSchema)]
+pub struct MessageView {
+    #[serde(flatten)]
+    pub message: SessionMessage,
+    /// Math fragments of `code` messages
+    pub rendered_math: Vec<RenderedMath>,
+    /// Attachment of `file` messages, unset once it has been deleted
+    pub attachment: Option<AttachmentView>,
+    /// Users the message mentions, in order of mention
+    pub mentions: Vec<MentionedUser>,
+    /// Set in place of the sender's identity while the session is anonymous
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pseudonym: Option<String>,
+}
+
+/// Session messages response
+#[derive(Debug, Serialize, ToSchema)]
+pub struct MessagesResponse {
+    pub messages: Vec<MessageView>,
+}
+
+/// Chat message edit request
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct EditMessageRequest {
+    pub content: String,
+}
+
+/// Sent message response
+#[derive(Debug, Serialize, ToSchema)]
+pub struct MessageSentResponse {
+    pub message: SessionMessage,
+}
+
+/// Multipart form of a chat attachment upload
+#[derive(Debug, ToSchema)]
+pub struct UploadAttachmentForm {
+    /// PNG, JPEG, GIF or WebP image, or PDF document
+    #[schema(value_type = String, format = Binary)]
+    pub file: Vec<u8>,
+}
+
+/// Anonymous review mode request
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct SessionAnonymityRequest {
+    /// Identities are revealed at this time, at most 30 days ahead
+    pub until: chrono::DateTime<chrono::Utc>,
+}
+
+/// Confirmation of revealing identities in a running session
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct RevealIdentitiesParams {
+    /// Required once the session has started
+    #[serde(default)]
+    pub confirm: bool,
+}
+
+/// Session invitation response
+#[derive(Debug, Serialize, ToSchema)]
+pub struct SessionInvitationResponse {
+    pub invitation: SessionInvitation,
+}
+
 /// Session statistics response
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, ToSchema)]
 pub struct SessionStatsResponse {
     pub stats: crate::models::collaboration::SessionStats,
 }
@@ -78,6 +203,15 @@ pub struct CollaborationState {
 }
 
 /// List collaboration sessions
+#[utoipa::path(
+    get,
+    path = "/sessions",
+    params(PaginationParams),
+    responses(
+        (status = 200, description = "Sessions the user created or takes part in", body = ApiResponse<SessionsListResponse>),
+        (status = 400, description = "Unknown sort column", body = ErrorResponse),
+    )
+)]
 pub async fn list_sessions(
     State(state): State<crate::server::AppState>,
     Query(params): Query<crate::models::PaginationParams>,
@@ -88,9 +222,12 @@ pub async fn list_sessions(
     // Get total count for pagination
     let total_count = sqlx::query_scalar::<_, i64>(
         r#"
-        SELECT COUNT(DISTINCT cs.id) FROM collaboration_sessions cs
-        LEFT JOIN session_participants sp ON cs.id = sp.session_id
-        WHERE cs.created_by = $1 OR sp.user_id = $1
+        SELECT COUNT(*) FROM collaboration_sessions cs
+        JOIN projects p ON p.id = cs.project_id
+        WHERE p.purging_at IS NULL AND (cs.created_by = $1 OR EXISTS (
+            SELECT 1 FROM session_participants sp
+            WHERE sp.session_id = cs.id AND sp.user_id = $1
+        ))
         "#
     )
     .bind(auth_user.user_id)
@@ -116,6 +253,18 @@ pub async fn list_sessions(
 }
 
 /// Create a new collaboration session
+///
+/// With `scheduled_start` the session waits for that time: only the host can
+/// join earlier. Times are stored in UTC.
+#[utoipa::path(
+    post,
+    path = "/sessions",
+    request_body = CreateCollaborationSession,
+    responses(
+        (status = 201, description = "Session created", body = ApiResponse<CollaborationSessionResponse>),
+        (status = 400, description = "Start in the past, end not after start, or invalid settings", body = ErrorResponse),
+    )
+)]
 pub async fn create_session(
     State(state): State<crate::server::AppState>,
     auth_user: axum::Extension<AuthContext>,
@@ -138,7 +287,101 @@ pub async fn create_session(
     ))
 }
 
+/// Scheduled sessions the user hosts or is invited to
+///
+/// Lists active sessions whose scheduled time overlaps the window, soonest first.
+#[utoipa::path(
+    get,
+    path = "/sessions/upcoming",
+    params(UpcomingSessionsParams),
+    responses(
+        (status = 200, description = "Upcoming sessions", body = ApiResponse<UpcomingSessionsResponse>),
+        (status = 400, description = "Window ends before it starts or is longer than a year", body = ErrorResponse),
+    )
+)]
+pub async fn upcoming_sessions(
+    State(state): State<crate::server::AppState>,
+    Query(params): Query<UpcomingSessionsParams>,
+    auth_user: axum::Extension<AuthContext>,
+) -> Result<impl IntoResponse, AppError> {
+    let from = params.from.unwrap_or_else(chrono::Utc::now);
+    let to = params
+        .to
+        .unwrap_or_else(|| from + chrono::Duration::days(session_schedule::DEFAULT_UPCOMING_WINDOW_DAYS));
+    if to <= from {
+        return Err(AppError::BadRequest("to must be after from".to_string()));
+    }
+    if to - from > chrono::Duration::days(session_schedule::MAX_UPCOMING_WINDOW_DAYS) {
+        return Err(AppError::BadRequest(format!(
+            "The window can span at most {} days",
+            session_schedule::MAX_UPCOMING_WINDOW_DAYS
+        )));
+    }
+
+    let sessions = CollaborationSession::upcoming(&state.db_pool, auth_user.user_id, from, to).await?;
+
+    Ok(Json(serde_json::json!({
+        "success": true,
+        "data": UpcomingSessionsResponse { sessions }
+    })))
+}
+
+/// Download a scheduled session as an iCalendar event
+#[utoipa::path(
+    get,
+    path = "/sessions/{id}/ics",
+    params(("id" = Uuid, Path, description = "Collaboration session ID")),
+    responses(
+        (status = 200, description = "iCalendar file with one event, times in UTC", content_type = "text/calendar", body = String,
+            headers(("Content-Disposition" = String, description = "Calendar file name"))),
+        (status = 400, description = "Session is not scheduled", body = ErrorResponse),
+        (status = 403, description = "Neither host, participant nor invitee of the session", body = ErrorResponse),
+        (status = 404, description = "Session not found", body = ErrorResponse),
+    )
+)]
+pub async fn get_session_ics(
+    State(state): State<crate::server::AppState>,
+    Path(session_id): Path<Uuid>,
+    auth_user: axum::Extension<AuthContext>,
+) -> Result<impl IntoResponse, AppError> {
+    let session = CollaborationSession::find_by_id(&state.db_pool, session_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound {
+            entity: "CollaborationSession".to_string(),
+            id: session_id.to_string(),
+        })?;
+
+    if !session.is_member(&state.db_pool, auth_user.user_id).await? {
+        return Err(AppError::Authorization(
+            "Access denied to this collaboration session".to_string(),
+        ));
+    }
+
+    let ics = session
+        .to_ics(chrono::Utc::now())
+        .ok_or_else(|| AppError::BadRequest("Session is not scheduled".to_string()))?;
+
+    let mut headers = HeaderMap::new();
+    headers.insert(header::CONTENT_TYPE, HeaderValue::from_static("text/calendar; charset=utf-8"));
+    let disposition = format!("attachment; filename=\"session-{}.ics\"", session.id);
+    let disposition_value = HeaderValue::from_str(&disposition)
+        .map_err(|_| AppError::Internal("Invalid file name for download".to_string()))?;
+    headers.insert(header::CONTENT_DISPOSITION, disposition_value);
+
+    Ok((headers, ics))
+}
+
 /// Get collaboration session details
+#[utoipa::path(
+    get,
+    path = "/sessions/{id}",
+    params(("id" = Uuid, Path, description = "Collaboration session ID")),
+    responses(
+        (status = 200, description = "Session with its active participants", body = ApiResponse<CollaborationSessionResponse>),
+        (status = 403, description = "Not a participant of the session", body = ErrorResponse),
+        (status = 404, description = "Session not found", body = ErrorResponse),
+    )
+)]
 pub async fn get_session(
     State(state): State<crate::server::AppState>,
     Path(session_id): Path<Uuid>,
@@ -162,6 +405,7 @@ pub async fn get_session(
         ));
     }
 
+    let participants = participants_for(&state, &session, auth_user.user_id, participants).await?;
     let response = CollaborationSessionResponse {
         session,
         participants,
@@ -174,6 +418,21 @@ pub async fn get_session(
 }
 
 /// Update collaboration session
+///
+/// `settings` is JSON; the server acts on its `idle_policy`, which downgrades
+/// idle editors to viewers, and `max_editors`, the editor roles held at once.
+#[utoipa::path(
+    put,
+    path = "/sessions/{id}",
+    params(("id" = Uuid, Path, description = "Collaboration session ID")),
+    request_body = UpdateCollaborationSession,
+    responses(
+        (status = 200, description = "Updated session", body = ApiResponse<CollaborationSessionResponse>),
+        (status = 400, description = "Invalid settings", body = ErrorResponse),
+        (status = 403, description = "Only the creator can update the session", body = ErrorResponse),
+        (status = 404, description = "Session not found", body = ErrorResponse),
+    )
+)]
 pub async fn update_session(
     State(state): State<crate::server::AppState>,
     Path(session_id): Path<Uuid>,
@@ -194,9 +453,17 @@ pub async fn update_session(
         ));
     }
 
-    // Update session (simplified - would need proper implementation)
+    // Only settings are updated so far
     // TODO: Implement session update logic in the model
-    let updated_session = session.clone(); // Placeholder
+    let updated_session = match payload.settings.as_deref() {
+        Some(settings) => {
+            let updated = session.update_settings(&state.db_pool, settings).await?;
+            // Connected participants get the new idle policy and edit slots
+            session_access::announce_status(&state.db_pool, session.id, SETTINGS_UPDATED).await?;
+            updated
+        }
+        None => session.clone(),
+    };
 
     let participants = SessionParticipant::get_active_participants(&state.db_pool, session_id).await?;
 
@@ -212,6 +479,16 @@ pub async fn update_session(
 }
 
 /// Delete collaboration session
+#[utoipa::path(
+    delete,
+    path = "/sessions/{id}",
+    params(("id" = Uuid, Path, description = "Collaboration session ID")),
+    responses(
+        (status = 200, description = "Session ended", body = MessageResponse),
+        (status = 403, description = "Only the creator can delete the session", body = ErrorResponse),
+        (status = 404, description = "Session not found", body = ErrorResponse),
+    )
+)]
 pub async fn delete_session(
     State(state): State<crate::server::AppState>,
     Path(session_id): Path<Uuid>,
@@ -234,6 +511,18 @@ pub async fn delete_session(
     // End session (soft delete)
     session.end(&state.db_pool).await?;
 
+    ChatAttachment::delete_for_session(
+        &state.db_pool,
+        &state.config.features.file_storage.local_path,
+        session.id,
+    )
+    .await?;
+
+    // Summarize its operations now; the compaction task retries on failure
+    if let Err(e) = SessionSummary::compact_session(&state.db_pool, session.id).await {
+        tracing::warn!("Failed to compact session {}: {}", session.id, e);
+    }
+
     Ok(Json(serde_json::json!({
         "success": true,
         "message": "Collaboration session deleted successfully"
@@ -241,12 +530,40 @@ pub async fn delete_session(
 }
 
 /// Join collaboration session
+#[utoipa::path(
+    post,
+    path = "/sessions/{id}/join",
+    params(("id" = Uuid, Path, description = "Collaboration session ID")),
+    request_body = JoinSessionRequest,
+    responses(
+        (status = 200, description = "Joined the session", body = ApiResponse<JoinSessionResponse>),
+        (status = 403, description = "Missing or wrong password or join code", body = ErrorResponse),
+        (status = 404, description = "Session not found or ended", body = ErrorResponse),
+        (status = 409, description = "Scheduled session has not started, and only the host can join early; or the session is full", body = ErrorResponse),
+        (status = 429, description = "Too many wrong secrets; the session is locked for a while", body = ErrorResponse),
+    )
+)]
 pub async fn join_session(
     State(state): State<crate::server::AppState>,
     Path(session_id): Path<Uuid>,
     auth_user: axum::Extension<AuthContext>,
     Json(payload): Json<JoinSessionRequest>,
 ) -> Result<impl IntoResponse, AppError> {
+    let session = CollaborationSession::find_by_id(&state.db_pool, session_id)
+        .await?
+        .filter(|session| session.is_active)
+        .ok_or_else(|| AppError::NotFound {
+            entity: "CollaborationSession".to_string(),
+            id: session_id.to_string(),
+        })?;
+
+    let credentials = JoinCredentials {
+        password: payload.password.as_deref(),
+        join_code: payload.join_code.as_deref(),
+    };
+    SessionAccess::authorize_join(&state.db_pool, &session, auth_user.user_id, credentials, &state.config.websocket)
+        .await?;
+
     let participant = SessionParticipant::join(
         &state.db_pool,
         session_id,
@@ -254,42 +571,243 @@ pub async fn join_session(
         payload.role,
     )
     .await?;
+    if session.started_at.is_none() {
+        session.start(&state.db_pool).await?;
+    }
 
     let updated_participants = SessionParticipant::get_active_participants(&state.db_pool, session_id).await?;
+    let updated_participants = participants_for(&state, &session, auth_user.user_id, updated_participants).await?;
+
+    let response = JoinSessionResponse {
+        participant,
+        participants: updated_participants,
+    };
 
     Ok(Json(serde_json::json!({
         "success": true,
-        "data": {
-            "participant": participant,
-            "participants": updated_participants
-        }
+        "data": response
+    })))
+}
+
+/// Refusal of join settings to others than the host
+const HOST_CHANGES_JOINING: &str = "Only the session host can change how others join";
+
+/// Refusal of anonymity changes to others than the host
+const HOST_CHANGES_ANONYMITY: &str = "Only the session host can change anonymity";
+
+/// The session if the caller is its host
+async fn find_hosted_session(
+    state: &crate::server::AppState,
+    session_id: Uuid,
+    user_id: Uuid,
+    denied: &str,
+) -> Result<CollaborationSession, AppError> {
+    let session = CollaborationSession::find_by_id(&state.db_pool, session_id)
+        .await?
+        .filter(|session| session.is_active)
+        .ok_or_else(|| AppError::NotFound {
+            entity: "CollaborationSession".to_string(),
+            id: session_id.to_string(),
+        })?;
+
+    if session.created_by != user_id {
+        return Err(AppError::Authorization(denied.to_string()));
+    }
+
+    Ok(session)
+}
+
+/// Set, change or remove the session password
+///
+/// Participants who already joined stay; later joins need the new password
+/// or a valid join code.
+#[utoipa::path(
+    put,
+    path = "/sessions/{id}/password",
+    params(("id" = Uuid, Path, description = "Collaboration session ID")),
+    request_body = SessionPasswordRequest,
+    responses(
+        (status = 200, description = "Password changed", body = MessageResponse),
+        (status = 400, description = "Empty password", body = ErrorResponse),
+        (status = 403, description = "Only the host can change the password", body = ErrorResponse),
+        (status = 404, description = "Session not found or ended", body = ErrorResponse),
+    )
+)]
+pub async fn set_session_password(
+    State(state): State<crate::server::AppState>,
+    Path(session_id): Path<Uuid>,
+    auth_user: axum::Extension<AuthContext>,
+    Json(payload): Json<SessionPasswordRequest>,
+) -> Result<impl IntoResponse, AppError> {
+    let session = find_hosted_session(&state, session_id, auth_user.user_id, HOST_CHANGES_JOINING).await?;
+
+    if payload.password.as_deref().is_some_and(|password| password.trim().is_empty()) {
+        return Err(AppError::Validation("Session password cannot be empty".to_string()));
+    }
+
+    SessionAccess::set_password(&state.db_pool, session.id, payload.password.as_deref()).await?;
+
+    let status = if payload.password.is_some() { "password_changed" } else { "password_removed" };
+    session_access::announce_status(&state.db_pool, session.id, status).await?;
+
+    Ok(Json(serde_json::json!({
+        "success": true,
+        "message": if payload.password.is_some() { "Session password set" } else { "Session password removed" }
+    })))
+}
+
+/// Generate a new join code for the session
+///
+/// Returns a 6-digit code that replaces any earlier one and expires after
+/// `WEBSOCKET_JOIN_CODE_TTL` seconds. It is only shown in this response.
+#[utoipa::path(
+    post,
+    path = "/sessions/{id}/join-code",
+    params(("id" = Uuid, Path, description = "Collaboration session ID")),
+    responses(
+        (status = 201, description = "New join code", body = ApiResponse<JoinCode>),
+        (status = 403, description = "Only the host can create join codes", body = ErrorResponse),
+        (status = 404, description = "Session not found or ended", body = ErrorResponse),
+    )
+)]
+pub async fn create_join_code(
+    State(state): State<crate::server::AppState>,
+    Path(session_id): Path<Uuid>,
+    auth_user: axum::Extension<AuthContext>,
+) -> Result<impl IntoResponse, AppError> {
+    let session = find_hosted_session(&state, session_id, auth_user.user_id, HOST_CHANGES_JOINING).await?;
+
+    let join_code = SessionAccess::rotate_join_code(&state.db_pool, session.id, state.config.websocket.join_code_ttl).await?;
+    session_access::announce_status(&state.db_pool, session.id, "join_code_rotated").await?;
+
+    Ok((
+        StatusCode::CREATED,
+        Json(serde_json::json!({
+            "success": true,
+            "data": join_code
+        })),
+    ))
+}
+
+/// Hide participant identities until a set time
+///
+/// Everyone but the host sees the other participants under a pseudonym such
+/// as "Reviewer 2" and an alias in place of their user id, in participant
+/// lists, chat messages and operations. Pseudonyms stay the same for the
+/// whole session. Enabling it again replaces the end time.
+#[utoipa::path(
+    put,
+    path = "/sessions/{id}/anonymity",
+    params(("id" = Uuid, Path, description = "Collaboration session ID")),
+    request_body = SessionAnonymityRequest,
+    responses(
+        (status = 200, description = "Session with its anonymity end", body = ApiResponse<CollaborationSession>),
+        (status = 400, description = "End time not in the future or too far ahead", body = ErrorResponse),
+        (status = 403, description = "Only the host can change anonymity", body = ErrorResponse),
+        (status = 404, description = "Session not found or ended", body = ErrorResponse),
+    )
+)]
+pub async fn enable_anonymity(
+    State(state): State<crate::server::AppState>,
+    Path(session_id): Path<Uuid>,
+    auth_user: axum::Extension<AuthContext>,
+    Json(payload): Json<SessionAnonymityRequest>,
+) -> Result<impl IntoResponse, AppError> {
+    let session = find_hosted_session(&state, session_id, auth_user.user_id, HOST_CHANGES_ANONYMITY).await?;
+    let session = session.enable_anonymity(&state.db_pool, payload.until, chrono::Utc::now()).await?;
+
+    Ok(Json(serde_json::json!({
+        "success": true,
+        "data": session
+    })))
+}
+
+/// Reveal participant identities before the set time
+///
+/// Once the session has started this reveals everyone to everyone at once,
+/// so it needs `confirm=true`. Participants are told with an
+/// `anonymity_disabled` session status.
+#[utoipa::path(
+    delete,
+    path = "/sessions/{id}/anonymity",
+    params(("id" = Uuid, Path, description = "Collaboration session ID"), RevealIdentitiesParams),
+    responses(
+        (status = 200, description = "Identities revealed", body = MessageResponse),
+        (status = 400, description = "Session is not anonymous", body = ErrorResponse),
+        (status = 403, description = "Only the host can change anonymity", body = ErrorResponse),
+        (status = 404, description = "Session not found or ended", body = ErrorResponse),
+        (status = 409, description = "Session is running and the reveal was not confirmed", body = ErrorResponse),
+    )
+)]
+pub async fn disable_anonymity(
+    State(state): State<crate::server::AppState>,
+    Path(session_id): Path<Uuid>,
+    Query(params): Query<RevealIdentitiesParams>,
+    auth_user: axum::Extension<AuthContext>,
+) -> Result<impl IntoResponse, AppError> {
+    let session = find_hosted_session(&state, session_id, auth_user.user_id, HOST_CHANGES_ANONYMITY).await?;
+    session.disable_anonymity(&state.db_pool, params.confirm, chrono::Utc::now()).await?;
+
+    Ok(Json(serde_json::json!({
+        "success": true,
+        "message": "Participant identities revealed"
     })))
 }
 
 /// Leave collaboration session
+#[utoipa::path(
+    post,
+    path = "/sessions/{id}/leave",
+    params(("id" = Uuid, Path, description = "Collaboration session ID")),
+    responses(
+        (status = 200, description = "Left the session; the participants still in it", body = ApiResponse<ParticipantsResponse>),
+        (status = 404, description = "Not a participant of the session", body = ErrorResponse),
+    )
+)]
 pub async fn leave_session(
     State(state): State<crate::server::AppState>,
     Path(session_id): Path<Uuid>,
     auth_user: axum::Extension<AuthContext>,
 ) -> Result<impl IntoResponse, AppError> {
+    let not_participant = || AppError::NotFound {
+        entity: "SessionParticipant".to_string(),
+        id: session_id.to_string(),
+    };
+    let session = CollaborationSession::find_by_id(&state.db_pool, session_id)
+        .await?
+        .ok_or_else(not_participant)?;
+
     // Find participant
     let participants = SessionParticipant::get_active_participants(&state.db_pool, session_id).await?;
     let participant = participants.iter()
         .find(|p| p.user_id == auth_user.user_id)
-        .ok_or_else(|| AppError::NotFound {
-            entity: "SessionParticipant".to_string(),
-            id: session_id.to_string(),
-        })?;
+        .ok_or_else(not_participant)?;
 
     participant.leave(&state.db_pool).await?;
 
+    let remaining = SessionParticipant::get_active_participants(&state.db_pool, session_id).await?;
+    let response = ParticipantsResponse {
+        participants: participants_for(&state, &session, auth_user.user_id, remaining).await?,
+    };
+
     Ok(Json(serde_json::json!({
         "success": true,
-        "message": "Left collaboration session successfully"
+        "message": "Left collaboration session successfully",
+        "data": response
     })))
 }
 
 /// Get session participants
+#[utoipa::path(
+    get,
+    path = "/sessions/{id}/participants",
+    params(("id" = Uuid, Path, description = "Collaboration session ID")),
+    responses(
+        (status = 200, description = "Active participants", body = ApiResponse<ParticipantsResponse>),
+        (status = 403, description = "Not a participant of the session", body = ErrorResponse),
+        (status = 404, description = "Session not found", body = ErrorResponse),
+    )
+)]
 pub async fn get_participants(
     State(state): State<crate::server::AppState>,
     Path(session_id): Path<Uuid>,
@@ -313,15 +831,43 @@ pub async fn get_participants(
         ));
     }
 
+    let participants = participants_for(&state, &session, auth_user.user_id, participants).await?;
+    let response = ParticipantsResponse {
+        participants,
+    };
+
     Ok(Json(serde_json::json!({
         "success": true,
-        "data": {
-            "participants": participants
-        }
+        "data": response
     })))
 }
 
+/// Participants as `viewer` may see them, masked while the session is anonymous
+async fn participants_for(
+    state: &crate::server::AppState,
+    session: &CollaborationSession,
+    viewer: Uuid,
+    participants: Vec<SessionParticipant>,
+) -> Result<Vec<SessionParticipant>, AppError> {
+    let Some(mut mask) = SessionMask::load(&state.db_pool, session, viewer, chrono::Utc::now()).await? else {
+        return Ok(participants);
+    };
+
+    mask.cover(&state.db_pool, participants.iter().map(|participant| participant.user_id)).await?;
+    Ok(participants.into_iter().map(|participant| mask.participant(participant)).collect())
+}
+
 /// Create operation in session
+#[utoipa::path(
+    post,
+    path = "/sessions/{id}/operations",
+    params(("id" = Uuid, Path, description = "Collaboration session ID")),
+    request_body = SessionOperationRequest,
+    responses(
+        (status = 200, description = "Operation recorded", body = ApiResponse<OperationResponse>),
+        (status = 403, description = "Not a participant of the session", body = ErrorResponse),
+    )
+)]
 pub async fn create_operation(
     State(state): State<crate::server::AppState>,
     Path(session_id): Path<Uuid>,
@@ -354,15 +900,27 @@ pub async fn create_operation(
     )
     .await?;
 
+    let response = OperationResponse {
+        operation,
+    };
+
     Ok(Json(serde_json::json!({
         "success": true,
-        "data": {
-            "operation": operation
-        }
+        "data": response
     })))
 }
 
 /// Get session messages
+#[utoipa::path(
+    get,
+    path = "/sessions/{id}/messages",
+    params(("id" = Uuid, Path, description = "Collaboration session ID"), PaginationParams),
+    responses(
+        (status = 200, description = "Messages, newest first", body = ApiResponse<MessagesResponse>),
+        (status = 403, description = "Not a participant of the session", body = ErrorResponse),
+        (status = 404, description = "Session not found", body = ErrorResponse),
+    )
+)]
 pub async fn get_messages(
     State(state): State<crate::server::AppState>,
     Path(session_id): Path<Uuid>,
@@ -402,15 +960,86 @@ pub async fn get_messages(
     .await
     .map_err(AppError::Database)?;
 
+    let attachment_ids: Vec<Uuid> = messages
+        .iter()
+        .filter(|message| message.message_type == MessageType::File)
+        .filter_map(|message| Uuid::parse_str(message.content.trim()).ok())
+        .collect();
+    let attachments: std::collections::HashMap<Uuid, ChatAttachment> =
+        ChatAttachment::find_many(&state.db_pool, session_id, &attachment_ids)
+            .await?
+            .into_iter()
+            .map(|attachment| (attachment.id, attachment))
+            .collect();
+
+    let mut mentioned_ids: Vec<Uuid> = messages.iter().flat_map(|message| message.mentions.iter().copied()).collect();
+    mentioned_ids.sort_unstable();
+    mentioned_ids.dedup();
+    let mentioned = MentionedUser::find_many(&state.db_pool, &mentioned_ids).await?;
+
+    let mut mask = SessionMask::load(&state.db_pool, &session, auth_user.user_id, chrono::Utc::now()).await?;
+    if let Some(mask) = mask.as_mut() {
+        let senders = messages.iter().map(|message| message.user_id);
+        mask.cover(&state.db_pool, senders.chain(mentioned_ids.iter().copied())).await?;
+    }
+
+    let mut renders_left = MAX_RENDERS_PER_REQUEST;
+    let mut views = Vec::with_capacity(messages.len());
+    for message in messages {
+        let rendered_math = if message.message_type == MessageType::Code {
+            state.inline_renderer.render_fragments(&message.content, &mut renders_left).await
+        } else {
+            Vec::new()
+        };
+        let attachment = if message.message_type == MessageType::File {
+            Uuid::parse_str(message.content.trim())
+                .ok()
+                .and_then(|id| attachments.get(&id).cloned())
+                .map(ChatAttachment::view)
+        } else {
+            None
+        };
+        let mentions: Vec<MentionedUser> =
+            message.mentions.iter().filter_map(|user_id| mentioned.get(user_id).cloned()).collect();
+
+        let view = match &mask {
+            Some(mask) => MessageView {
+                pseudonym: mask.pseudonym(message.user_id),
+                mentions: mentions.into_iter().map(|user| mask.mentioned_user(user)).collect(),
+                message: mask.message(message),
+                rendered_math,
+                attachment: attachment.map(|attachment| mask.attachment(attachment)),
+            },
+            None => MessageView { message, rendered_math, attachment, mentions, pseudonym: None },
+        };
+        views.push(view);
+    }
+
+    let response = MessagesResponse {
+        messages: views,
+    };
+
     Ok(Json(serde_json::json!({
         "success": true,
-        "data": {
-            "messages": messages
-        }
+        "data": response
     })))
 }
 
 /// Send message to session
+///
+/// The content of a `file` message is the id of an attachment the sender uploaded to the session.
+/// `@username` in a `text` message mentions a project member or session participant, who is notified.
+#[utoipa::path(
+    post,
+    path = "/sessions/{id}/messages",
+    params(("id" = Uuid, Path, description = "Collaboration session ID")),
+    request_body = SessionMessageRequest,
+    responses(
+        (status = 200, description = "Message sent", body = ApiResponse<MessageSentResponse>),
+        (status = 400, description = "File message without an attachment of the sender", body = ErrorResponse),
+        (status = 403, description = "Not a participant of the session", body = ErrorResponse),
+    )
+)]
 pub async fn send_message(
     State(state): State<crate::server::AppState>,
     Path(session_id): Path<Uuid>,
@@ -425,32 +1054,320 @@ pub async fn send_message(
         ));
     }
 
-    let message = sqlx::query_as::<_, SessionMessage>(
-        r#"
-        INSERT INTO session_messages (session_id, user_id, message_type, content, reply_to, created_at)
-        VALUES ($1, $2, $3, $4, $5, $6)
-        RETURNING *
-        "#
+    if payload.message_type == MessageType::File {
+        ChatAttachment::resolve_message(&state.db_pool, session_id, auth_user.user_id, &payload.content).await?;
+    }
+
+    let session = CollaborationSession::find_by_id(&state.db_pool, session_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound {
+            entity: "CollaborationSession".to_string(),
+            id: session_id.to_string(),
+        })?;
+
+    let message = SessionMessage::create(
+        &state.db_pool,
+        &session,
+        auth_user.user_id,
+        payload.message_type,
+        payload.content,
+        payload.reply_to,
     )
-    .bind(session_id)
-    .bind(auth_user.user_id)
-    .bind(payload.message_type as MessageType)
-    .bind(payload.content)
-    .bind(payload.reply_to)
-    .bind(chrono::Utc::now())
-    .fetch_one(&state.db_pool)
-    .await
-    .map_err(AppError::Database)?;
+    .await?;
+    state.mention_notifier.notify_or_warn(&state.db_pool, &session, &message, &[]).await;
+
+    let response = MessageSentResponse {
+        message,
+    };
 
     Ok(Json(serde_json::json!({
         "success": true,
-        "data": {
-            "message": message
-        }
+        "data": response
     })))
 }
 
+/// Edit a chat message
+///
+/// Only the author can edit, and only `text` and `code` messages. Mentions are
+/// resolved again; users the message did not mention before are notified.
+#[utoipa::path(
+    put,
+    path = "/sessions/{id}/messages/{message_id}",
+    params(
+        ("id" = Uuid, Path, description = "Collaboration session ID"),
+        ("message_id" = Uuid, Path, description = "Message ID"),
+    ),
+    request_body = EditMessageRequest,
+    responses(
+        (status = 200, description = "Message edited", body = ApiResponse<MessageSentResponse>),
+        (status = 400, description = "Not a text or code message", body = ErrorResponse),
+        (status = 403, description = "Not the author, or not a participant of the session", body = ErrorResponse),
+        (status = 404, description = "Session or message not found", body = ErrorResponse),
+    )
+)]
+pub async fn edit_message(
+    State(state): State<crate::server::AppState>,
+    Path((session_id, message_id)): Path<(Uuid, Uuid)>,
+    auth_user: axum::Extension<AuthContext>,
+    Json(payload): Json<EditMessageRequest>,
+) -> Result<impl IntoResponse, AppError> {
+    let session = CollaborationSession::find_by_id(&state.db_pool, session_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound {
+            entity: "CollaborationSession".to_string(),
+            id: session_id.to_string(),
+        })?;
+
+    let participants = SessionParticipant::get_active_participants(&state.db_pool, session_id).await?;
+    if !participants.iter().any(|p| p.user_id == auth_user.user_id) {
+        return Err(AppError::Authorization(
+            "You must be a session participant to edit messages".to_string(),
+        ));
+    }
+
+    let (message, previously) =
+        SessionMessage::edit(&state.db_pool, &session, message_id, auth_user.user_id, payload.content).await?;
+    state.mention_notifier.notify_or_warn(&state.db_pool, &session, &message, &previously).await;
+
+    let response = MessageSentResponse {
+        message,
+    };
+
+    Ok(Json(serde_json::json!({
+        "success": true,
+        "data": response
+    })))
+}
+
+/// Upload a chat attachment
+///
+/// Images and PDFs only, up to `FILE_ATTACHMENT_MAX_SIZE`. The upload is
+/// scanned for malware like project uploads; send its id in a `file` message
+/// to share it.
+#[utoipa::path(
+    post,
+    path = "/sessions/{id}/attachments",
+    params(("id" = Uuid, Path, description = "Collaboration session ID")),
+    request_body(content = UploadAttachmentForm, content_type = "multipart/form-data"),
+    responses(
+        (status = 201, description = "Attachment stored", body = ApiResponse<AttachmentView>),
+        (status = 400, description = "Missing file, not an image or PDF, or too large", body = ErrorResponse),
+        (status = 403, description = "Not a participant of the session", body = ErrorResponse),
+        (status = 404, description = "Session not found or ended", body = ErrorResponse),
+        (status = 422, description = "Malware was found in the file", body = ErrorResponse),
+        (status = 502, description = "The malware scanner could not be reached", body = ErrorResponse),
+    )
+)]
+pub async fn upload_attachment(
+    State(state): State<crate::server::AppState>,
+    Path(session_id): Path<Uuid>,
+    auth_user: axum::Extension<AuthContext>,
+    mut multipart: Multipart,
+) -> Result<impl IntoResponse, AppError> {
+    let session = CollaborationSession::find_by_id(&state.db_pool, session_id)
+        .await?
+        .filter(|session| session.is_active)
+        .ok_or_else(|| AppError::NotFound {
+            entity: "CollaborationSession".to_string(),
+            id: session_id.to_string(),
+        })?;
+
+    let participants = SessionParticipant::get_active_participants(&state.db_pool, session_id).await?;
+    if !participants.iter().any(|p| p.user_id == auth_user.user_id) {
+        return Err(AppError::Authorization(
+            "You must be a session participant to share files".to_string(),
+        ));
+    }
+
+    let storage = &state.config.features.file_storage;
+    if storage.type_ != "local" {
+        return Err(AppError::Storage("Unsupported storage type".to_string()));
+    }
+
+    while let Some(mut field) = multipart.next_field().await
+        .map_err(|e| AppError::Validation(format!("Failed to read multipart field: {}", e)))?
+    {
+        let file_name = field.file_name()
+            .ok_or_else(|| AppError::Validation("File name is required".to_string()))
+            .and_then(FileName::new)?;
+
+        let staged = crate::handlers::file::stage_field(&state, &mut field, storage.attachment_max_size).await?;
+
+        let mut head = [0u8; 16];
+        let head_len = {
+            use tokio::io::AsyncReadExt;
+            let mut staged_file = tokio::fs::File::open(&staged.path).await
+                .map_err(|e| AppError::Storage(format!("Failed to read upload: {}", e)))?;
+            staged_file.read(&mut head).await
+                .map_err(|e| AppError::Storage(format!("Failed to read upload: {}", e)))?
+        };
+        let media_type = chat_attachment::sniff_media_type(&head[..head_len]).ok_or_else(|| {
+            AppError::Validation("Only PNG, JPEG, GIF and WebP images and PDF documents can be attached".to_string())
+        })?;
+
+        let verdict = state.file_scanner.scan_path(&staged.path, staged.size).await?;
+        if let ScanVerdict::Infected { signature } = &verdict {
+            return Err(file_scan::reject_infected_upload(
+                &state,
+                session.project_id,
+                auth_user.user_id,
+                &format!("chat attachment {}", file_name),
+                staged.size as i64,
+                signature,
+            )
+            .await);
+        }
+
+        let attachment = ChatAttachment::store(
+            &state.db_pool,
+            &storage.local_path,
+            session_id,
+            auth_user.user_id,
+            file_name.as_str(),
+            media_type,
+            &staged.path,
+            staged.size as i64,
+            &staged.content_hash,
+        )
+        .await?;
+
+        return Ok((
+            StatusCode::CREATED,
+            Json(serde_json::json!({
+                "success": true,
+                "data": attachment.view()
+            })),
+        ));
+    }
+
+    Err(AppError::Validation("No file provided".to_string()))
+}
+
+/// Download a chat attachment
+#[utoipa::path(
+    get,
+    path = "/sessions/{id}/attachments/{attachment_id}",
+    params(
+        ("id" = Uuid, Path, description = "Collaboration session ID"),
+        ("attachment_id" = Uuid, Path, description = "Attachment ID"),
+    ),
+    responses(
+        (status = 200, description = "Attachment content, served with its detected media type", content_type = "application/octet-stream", body = Vec<u8>,
+            headers(("Content-Disposition" = String, description = "Attachment file name"))),
+        (status = 403, description = "Not a participant of the session", body = ErrorResponse),
+        (status = 404, description = "Session or attachment not found", body = ErrorResponse),
+    )
+)]
+pub async fn download_attachment(
+    State(state): State<crate::server::AppState>,
+    Path((session_id, attachment_id)): Path<(Uuid, Uuid)>,
+    auth_user: axum::Extension<AuthContext>,
+) -> Result<impl IntoResponse, AppError> {
+    let attachment = find_member_attachment(&state, session_id, attachment_id, auth_user.user_id).await?;
+    let content = attachment.read_bytes(&state.config.features.file_storage.local_path).await?;
+
+    let mut headers = attachment_headers(&attachment.media_type)?;
+    let disposition = format!("inline; filename=\"{}\"", attachment.file_name);
+    let disposition_value = HeaderValue::from_str(&disposition)
+        .map_err(|_| AppError::Internal("Invalid file name for download".to_string()))?;
+    headers.insert(header::CONTENT_DISPOSITION, disposition_value);
+
+    Ok((headers, content))
+}
+
+/// Thumbnail of an image attachment
+///
+/// A PNG of at most 320 pixels per side, rendered on first request. When it
+/// cannot be rendered the original image is served.
+#[utoipa::path(
+    get,
+    path = "/sessions/{id}/attachments/{attachment_id}/thumbnail",
+    params(
+        ("id" = Uuid, Path, description = "Collaboration session ID"),
+        ("attachment_id" = Uuid, Path, description = "Attachment ID"),
+    ),
+    responses(
+        (status = 200, description = "Thumbnail image", content_type = "image/png", body = Vec<u8>),
+        (status = 403, description = "Not a participant of the session", body = ErrorResponse),
+        (status = 404, description = "Session or image attachment not found", body = ErrorResponse),
+    )
+)]
+pub async fn get_attachment_thumbnail(
+    State(state): State<crate::server::AppState>,
+    Path((session_id, attachment_id)): Path<(Uuid, Uuid)>,
+    auth_user: axum::Extension<AuthContext>,
+) -> Result<impl IntoResponse, AppError> {
+    let attachment = find_member_attachment(&state, session_id, attachment_id, auth_user.user_id).await?;
+    if !attachment.is_image() {
+        return Err(AppError::NotFound {
+            entity: "Thumbnail".to_string(),
+            id: attachment_id.to_string(),
+        });
+    }
+
+    let (content, media_type) = attachment.thumbnail(&state.config.features.file_storage.local_path).await?;
+    Ok((attachment_headers(&media_type)?, content))
+}
+
+/// An attachment of a session the caller hosts or participates in
+async fn find_member_attachment(
+    state: &crate::server::AppState,
+    session_id: Uuid,
+    attachment_id: Uuid,
+    user_id: Uuid,
+) -> Result<ChatAttachment, AppError> {
+    let session = CollaborationSession::find_by_id(&state.db_pool, session_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound {
+            entity: "CollaborationSession".to_string(),
+            id: session_id.to_string(),
+        })?;
+
+    let participants = SessionParticipant::get_active_participants(&state.db_pool, session_id).await?;
+    let has_access = session.created_by == user_id ||
+        participants.iter().any(|p| p.user_id == user_id);
+
+    if !has_access {
+        return Err(AppError::Authorization(
+            "Access denied to this collaboration session".to_string(),
+        ));
+    }
+
+    ChatAttachment::find(&state.db_pool, session_id, attachment_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound {
+            entity: "ChatAttachment".to_string(),
+            id: attachment_id.to_string(),
+        })
+}
+
+/// Headers of attachment content; browsers must not second-guess the media type
+fn attachment_headers(media_type: &str) -> Result<HeaderMap, AppError> {
+    let mut headers = HeaderMap::new();
+    let content_type = HeaderValue::from_str(media_type)
+        .map_err(|_| AppError::Internal("Invalid attachment media type".to_string()))?;
+    headers.insert(header::CONTENT_TYPE, content_type);
+    headers.insert(header::X_CONTENT_TYPE_OPTIONS, HeaderValue::from_static("nosniff"));
+    headers.insert(header::CACHE_CONTROL, HeaderValue::from_static("private, max-age=3600"));
+    Ok(headers)
+}
+
 /// Invite participant to session
+///
+/// Invitations of a scheduled session stay valid until it is over. Invitees
+/// who accepted are reminded before it starts.
+#[utoipa::path(
+    post,
+    path = "/sessions/{id}/invite",
+    params(("id" = Uuid, Path, description = "Collaboration session ID")),
+    request_body = SessionInvitationRequest,
+    responses(
+        (status = 200, description = "Invitation created", body = ApiResponse<SessionInvitationResponse>),
+        (status = 400, description = "Neither a user nor an email was given, or the user does not exist", body = ErrorResponse),
+        (status = 403, description = "Only the creator can invite participants", body = ErrorResponse),
+        (status = 404, description = "Session not found", body = ErrorResponse),
+    )
+)]
 pub async fn invite_participant(
     State(state): State<crate::server::AppState>,
     Path(session_id): Path<Uuid>,
@@ -471,35 +1388,55 @@ pub async fn invite_participant(
         ));
     }
 
-    // Create invitation (simplified implementation)
-    let invitation = SessionInvitation {
-        id: Uuid::new_v4(),
-        session_id,
-        invited_by: auth_user.user_id,
-        invited_user: payload.user_id,
-        email: payload.email,
-        role: payload.role,
-        message: payload.message,
-        token: Uuid::new_v4().to_string(),
-        expires_at: chrono::Utc::now() + chrono::Duration::hours(24),
-        accepted: false,
-        accepted_at: None,
-        declined: false,
-        declined_at: None,
-        created_at: chrono::Utc::now(),
+    let email = payload.email.as_deref().map(str::trim).filter(|email| !email.is_empty());
+    let invited_user = match (payload.user_id, email) {
+        (Some(user_id), _) => {
+            let user = User::find_by_id(&state.db_pool, user_id)
+                .await?
+                .ok_or_else(|| AppError::BadRequest("Invited user does not exist".to_string()))?;
+            Some(user.id)
+        }
+        // Known addresses are linked right away so the session shows up as upcoming
+        (None, Some(email)) => User::find_by_email(&state.db_pool, &email.to_lowercase())
+            .await?
+            .map(|user| user.id),
+        (None, None) => {
+            return Err(AppError::BadRequest("Invite a user_id or an email".to_string()));
+        }
     };
 
-    // TODO: Save invitation to database and send notification
+    let invitation = SessionInvitation::create(
+        &state.db_pool,
+        &session,
+        auth_user.user_id,
+        invited_user,
+        email,
+        payload.role,
+        payload.message,
+    )
+    .await?;
+
+    let response = SessionInvitationResponse {
+        invitation,
+    };
 
     Ok(Json(serde_json::json!({
         "success": true,
-        "data": {
-            "invitation": invitation
-        }
+        "data": response
     })))
 }
 
 /// Get session statistics
+#[utoipa::path(
+    get,
+    path = "/sessions/{id}/stats",
+    params(("id" = Uuid, Path, description = "Collaboration session ID")),
+    responses(
+        (status = 200, description = "Session statistics", body = ApiResponse<SessionStatsResponse>),
+        (status = 403, description = "Not a participant of the session", body = ErrorResponse),
+        (status = 404, description = "Session not found", body = ErrorResponse),
+    )
+)]
 pub async fn get_session_stats(
     State(state): State<crate::server::AppState>,
     Path(session_id): Path<Uuid>,
@@ -536,41 +1473,92 @@ pub async fn get_session_stats(
 }
 
 /// Get invitation details
+#[utoipa::path(
+    get,
+    path = "/invitations/{token}",
+    params(("token" = String, Path, description = "Invitation token")),
+    responses(
+        (status = 200, description = "Invitation", body = ApiResponse<SessionInvitationResponse>),
+        (status = 404, description = "Invitation not found", body = ErrorResponse),
+    ),
+    security(())
+)]
 pub async fn get_invitation(
     State(state): State<crate::server::AppState>,
     Path(token): Path<String>,
-    _auth_user: axum::Extension<AuthContext>,
 ) -> Result<impl IntoResponse, AppError> {
-    // TODO: Implement invitation lookup from database
-    Err::<Response, AppError>(AppError::NotFound {
-        entity: "SessionInvitation".to_string(),
-        id: token,
-    })
+    let invitation = find_invitation(&state, token).await?;
+
+    Ok(Json(serde_json::json!({
+        "success": true,
+        "data": SessionInvitationResponse { invitation }
+    })))
 }
 
 /// Accept invitation
+///
+/// Signing in is required; the invitation must be addressed to the caller's
+/// account or email.
+#[utoipa::path(
+    post,
+    path = "/invitations/{token}",
+    params(("token" = String, Path, description = "Invitation token")),
+    responses(
+        (status = 200, description = "Invitation accepted", body = ApiResponse<SessionInvitationResponse>),
+        (status = 400, description = "Invitation has expired", body = ErrorResponse),
+        (status = 403, description = "Invitation is for someone else", body = ErrorResponse),
+        (status = 404, description = "Invitation not found", body = ErrorResponse),
+        (status = 409, description = "Invitation was declined", body = ErrorResponse),
+    )
+)]
 pub async fn accept_invitation(
     State(state): State<crate::server::AppState>,
     Path(token): Path<String>,
     auth_user: axum::Extension<AuthContext>,
 ) -> Result<impl IntoResponse, AppError> {
-    // TODO: Implement invitation acceptance logic
-    Err::<Response, AppError>(AppError::NotFound {
-        entity: "SessionInvitation".to_string(),
-        id: token,
-    })
+    let invitation = find_invitation(&state, token).await?;
+    let user = User::find_by_id(&state.db_pool, auth_user.user_id)
+        .await?
+        .ok_or_else(|| AppError::Authentication("User not found".to_string()))?;
+
+    let invitation = invitation.accept(&state.db_pool, &user).await?;
+
+    Ok(Json(serde_json::json!({
+        "success": true,
+        "data": SessionInvitationResponse { invitation }
+    })))
+}
+
+async fn find_invitation(state: &crate::server::AppState, token: String) -> Result<SessionInvitation, AppError> {
+    SessionInvitation::find_by_token(&state.db_pool, &token)
+        .await?
+        .ok_or(AppError::NotFound {
+            entity: "SessionInvitation".to_string(),
+            id: token,
+        })
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use sqlx::PgPool;
+    use crate::testing::{
+        auth_context, create_test_project, create_test_session, create_test_user, oneshot_as, test_config, test_state,
+        TestDb,
+    };
+    use tower::ServiceExt;
+    use axum::{
+        body::Body,
+        http::Request,
+        routing::{delete, get, post, put},
+        Router,
+    };
 
     #[tokio::test]
     async fn test_session_creation() {
+        let Some(db) = TestDb::start().await else { return };
         let state = crate::handlers::collaboration::CollaborationState {
-            db_pool: PgPool::connect("postgresql://test").await.unwrap(),
-            config: crate::config::Config::load().unwrap(),
+            db_pool: db.pool.clone(),
+            config: test_config(),
         };
 
         let request = CreateCollaborationSession {
@@ -581,6 +1569,8 @@ mod tests {
             max_participants: Some(5),
             password: None,
             settings: None,
+            scheduled_start: None,
+            scheduled_end: None,
         };
 
         // This test would require setting up proper auth context
@@ -595,12 +1585,428 @@ mod tests {
         let request = JoinSessionRequest {
             role: ParticipantRole::Editor,
             password: Some("password123".to_string()),
+            join_code: None,
         };
 
         assert_eq!(request.role, ParticipantRole::Editor);
         assert_eq!(request.password, Some("password123".to_string()));
     }
 
+    #[tokio::test]
+    async fn test_session_password_and_join_code() {
+        let Some(db) = TestDb::start().await else { return };
+        let state = test_state(&db).await;
+        let host = create_test_user(&db.pool).await;
+        let guest = create_test_user(&db.pool).await;
+        let student = create_test_user(&db.pool).await;
+        let project = create_test_project(&db.pool, &host, true).await;
+        let session = create_test_session(&db.pool, &project, &host).await;
+
+        let send = |user, method: &str, path: String, body: serde_json::Value| {
+            let router = Router::new()
+                .route("/sessions/:id/join", post(join_session))
+                .route("/sessions/:id/password", put(set_session_password))
+                .route("/sessions/:id/join-code", post(create_join_code));
+            let request = Request::builder()
+                .method(method)
+                .uri(path)
+                .header("content-type", "application/json")
+                .body(Body::from(body.to_string()))
+                .unwrap();
+            oneshot_as(router, state.clone(), user, request)
+        };
+        let password_path = format!("/sessions/{}/password", session.id);
+        let join_path = format!("/sessions/{}/join", session.id);
+
+        // Only the host decides how others join
+        let (status, _) = send(&guest, "PUT", password_path.clone(), serde_json::json!({ "password": "hunter2" })).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        let (status, body) = send(&host, "PUT", password_path.clone(), serde_json::json!({ "password": "hunter2" })).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+
+        let (status, body) = send(&guest, "POST", join_path.clone(), serde_json::json!({ "role": "editor" })).await;
+        assert_eq!(status, StatusCode::FORBIDDEN, "{}", body);
+
+        let (status, body) = send(
+            &guest,
+            "POST",
+            join_path.clone(),
+            serde_json::json!({ "role": "editor", "password": "hunter2" }),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+
+        // A join code works in place of the password
+        let join_code_path = format!("/sessions/{}/join-code", session.id);
+        let (status, body) = send(&host, "POST", join_code_path, serde_json::json!(null)).await;
+        assert_eq!(status, StatusCode::CREATED, "{}", body);
+        let code = body["data"]["join_code"].as_str().unwrap().to_string();
+        assert_eq!(code.len(), 6);
+
+        let (status, body) = send(
+            &student,
+            "POST",
+            join_path,
+            serde_json::json!({ "role": "viewer", "join_code": code }),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+
+        // Removing the password opens the session again
+        let (status, _) = send(&host, "PUT", password_path, serde_json::json!({ "password": null })).await;
+        assert_eq!(status, StatusCode::OK);
+        let stored = CollaborationSession::find_by_id(&db.pool, session.id).await.unwrap().unwrap();
+        assert!(stored.password_hash.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_join_and_leave_return_roster() {
+        let Some(db) = TestDb::start().await else { return };
+        let state = test_state(&db).await;
+        let host = create_test_user(&db.pool).await;
+        let guest = create_test_user(&db.pool).await;
+        let project = create_test_project(&db.pool, &host, true).await;
+        let session = create_test_session(&db.pool, &project, &host).await;
+
+        let send = |user, action: &str| {
+            let router = Router::new()
+                .route("/sessions/:id/join", post(join_session))
+                .route("/sessions/:id/leave", post(leave_session));
+            let request = Request::post(format!("/sessions/{}/{}", session.id, action))
+                .header("content-type", "application/json")
+                .body(Body::from(serde_json::json!({ "role": "editor" }).to_string()))
+                .unwrap();
+            oneshot_as(router, state.clone(), user, request)
+        };
+        let roster = |body: &serde_json::Value| -> Vec<String> {
+            body["data"]["participants"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|participant| participant["user_id"].as_str().unwrap().to_string())
+                .collect()
+        };
+
+        let (status, body) = send(&host, "join").await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        let (status, body) = send(&guest, "join").await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        assert_eq!(body["data"]["participant"]["user_id"], guest.id.to_string());
+        assert_eq!(roster(&body).len(), 2);
+
+        let (status, body) = send(&guest, "leave").await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        assert_eq!(roster(&body), vec![host.id.to_string()]);
+
+        let (status, _) = send(&guest, "leave").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_scheduled_session() {
+        let Some(db) = TestDb::start().await else { return };
+        let state = test_state(&db).await;
+        let host = create_test_user(&db.pool).await;
+        let guest = create_test_user(&db.pool).await;
+        let outsider = create_test_user(&db.pool).await;
+        let project = create_test_project(&db.pool, &host, true).await;
+        let session = create_test_session(&db.pool, &project, &host).await;
+        sqlx::query(
+            r#"
+            UPDATE collaboration_sessions
+            SET started_at = NULL, scheduled_start = NOW() + INTERVAL '1 hour', scheduled_end = NOW() + INTERVAL '2 hours'
+            WHERE id = $1
+            "#
+        )
+        .bind(session.id)
+        .execute(&db.pool)
+        .await
+        .unwrap();
+
+        let router = || {
+            Router::new()
+                .route("/sessions/upcoming", get(upcoming_sessions))
+                .route("/sessions/:id/join", post(join_session))
+                .route("/sessions/:id/invite", post(invite_participant))
+                .route("/sessions/:id/ics", get(get_session_ics))
+                .route("/invitations/:token", post(accept_invitation))
+        };
+        let json = |method: &str, path: String, body: serde_json::Value| {
+            Request::builder()
+                .method(method)
+                .uri(path)
+                .header("content-type", "application/json")
+                .body(Body::from(body.to_string()))
+                .unwrap()
+        };
+
+        let (status, body) = oneshot_as(
+            router(),
+            state.clone(),
+            &host,
+            json("POST", format!("/sessions/{}/invite", session.id), serde_json::json!({ "user_id": guest.id, "role": "editor" })),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        let token = body["data"]["invitation"]["token"].as_str().unwrap().to_string();
+
+        // The invitation is for the guest only
+        let (status, _) = oneshot_as(router(), state.clone(), &outsider, json("POST", format!("/invitations/{}", token), serde_json::json!(null))).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let (status, body) = oneshot_as(router(), state.clone(), &guest, json("POST", format!("/invitations/{}", token), serde_json::json!(null))).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        assert_eq!(body["data"]["invitation"]["accepted"], true);
+
+        let (status, body) = oneshot_as(router(), state.clone(), &guest, json("GET", "/sessions/upcoming".to_string(), serde_json::json!(null))).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        assert_eq!(body["data"]["sessions"][0]["id"], session.id.to_string());
+        let (_, body) = oneshot_as(router(), state.clone(), &outsider, json("GET", "/sessions/upcoming".to_string(), serde_json::json!(null))).await;
+        assert_eq!(body["data"]["sessions"], serde_json::json!([]));
+
+        // Only the host can join before the start
+        let join_path = format!("/sessions/{}/join", session.id);
+        let (status, body) = oneshot_as(router(), state.clone(), &guest, json("POST", join_path.clone(), serde_json::json!({ "role": "editor" }))).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert_eq!(body["error"]["code"], "SESSION_NOT_STARTED");
+        let (status, body) = oneshot_as(router(), state.clone(), &host, json("POST", join_path.clone(), serde_json::json!({ "role": "host" }))).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        let (status, body) = oneshot_as(router(), state.clone(), &guest, json("POST", join_path, serde_json::json!({ "role": "editor" }))).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+
+        let (status, _) = oneshot_as(router(), state.clone(), &outsider, json("GET", format!("/sessions/{}/ics", session.id), serde_json::json!(null))).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let response = router()
+            .layer(axum::Extension(auth_context(&guest)))
+            .with_state(state.clone())
+            .oneshot(json("GET", format!("/sessions/{}/ics", session.id), serde_json::json!(null)))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "text/calendar; charset=utf-8");
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let ics = String::from_utf8(bytes.to_vec()).unwrap();
+        assert!(ics.contains("BEGIN:VEVENT\r\n"));
+        assert!(ics.contains("\r\nSUMMARY:Test Session\r\n"));
+    }
+
+    #[tokio::test]
+    async fn test_chat_attachments() {
+        let Some(db) = TestDb::start().await else { return };
+        let storage = tempfile::tempdir().unwrap();
+        let mut state = test_state(&db).await;
+        let mut config = (*state.config).clone();
+        config.features.file_storage.type_ = "local".to_string();
+        config.features.file_storage.local_path = storage.path().to_string_lossy().into_owned();
+        state.config = std::sync::Arc::new(config);
+
+        let host = create_test_user(&db.pool).await;
+        let guest = create_test_user(&db.pool).await;
+        let outsider = create_test_user(&db.pool).await;
+        let project = create_test_project(&db.pool, &host, true).await;
+        let session = create_test_session(&db.pool, &project, &host).await;
+
+        let router = || {
+            Router::new()
+                .route("/sessions/:id", delete(delete_session))
+                .route("/sessions/:id/join", post(join_session))
+                .route("/sessions/:id/messages", get(get_messages).post(send_message))
+                .route("/sessions/:id/attachments", post(upload_attachment))
+        };
+        let json = |method: &str, path: String, body: serde_json::Value| {
+            Request::builder()
+                .method(method)
+                .uri(path)
+                .header("content-type", "application/json")
+                .body(Body::from(body.to_string()))
+                .unwrap()
+        };
+        let upload = |file_name: &str, content: &[u8]| {
+            let mut body = format!(
+                "--boundary\r\nContent-Disposition: form-data; name=\"file\"; filename=\"{}\"\r\n\r\n",
+                file_name
+            )
+            .into_bytes();
+            body.extend_from_slice(content);
+            body.extend_from_slice(b"\r\n--boundary--\r\n");
+            Request::post(format!("/sessions/{}/attachments", session.id))
+                .header("content-type", "multipart/form-data; boundary=boundary")
+                .body(Body::from(body))
+                .unwrap()
+        };
+        let messages_path = format!("/sessions/{}/messages", session.id);
+
+        let (status, body) = oneshot_as(
+            router(),
+            state.clone(),
+            &guest,
+            json("POST", format!("/sessions/{}/join", session.id), serde_json::json!({ "role": "editor" })),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+
+        // Only participants share files, and only images and PDFs
+        let png = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR";
+        let (status, _) = oneshot_as(router(), state.clone(), &outsider, upload("figure.png", png)).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let (status, _) = oneshot_as(router(), state.clone(), &guest, upload("figure.png", b"<svg/>")).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let (status, body) = oneshot_as(router(), state.clone(), &guest, upload("figure.png", png)).await;
+        assert_eq!(status, StatusCode::CREATED, "{}", body);
+        assert_eq!(body["data"]["media_type"], "image/png");
+        let attachment_id = body["data"]["id"].as_str().unwrap().to_string();
+        assert!(body["data"]["thumbnail_url"].as_str().unwrap().ends_with("/thumbnail"));
+
+        // A file message must name an attachment of its sender
+        let (status, _) = oneshot_as(
+            router(),
+            state.clone(),
+            &guest,
+            json("POST", messages_path.clone(), serde_json::json!({ "content": "figure.png", "message_type": "file" })),
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let (status, body) = oneshot_as(
+            router(),
+            state.clone(),
+            &guest,
+            json("POST", messages_path.clone(), serde_json::json!({ "content": attachment_id, "message_type": "file" })),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+
+        let (status, body) = oneshot_as(router(), state.clone(), &host, json("GET", messages_path.clone(), serde_json::json!(null))).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        let message = &body["data"]["messages"][0];
+        assert_eq!(message["attachment"]["id"], attachment_id.as_str());
+        assert_eq!(
+            message["attachment"]["download_url"],
+            format!("/api/v1/collaboration/sessions/{}/attachments/{}", session.id, attachment_id)
+        );
+
+        // Deleting the session deletes its attachments
+        let (status, _) = oneshot_as(
+            router(),
+            state.clone(),
+            &host,
+            json("DELETE", format!("/sessions/{}", session.id), serde_json::json!(null)),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        let attachment_id: Uuid = attachment_id.parse().unwrap();
+        assert!(ChatAttachment::find(&db.pool, session.id, attachment_id).await.unwrap().is_none());
+        assert!(!storage.path().join("attachments").join(session.id.to_string()).exists());
+    }
+
+    #[tokio::test]
+    async fn test_anonymous_review_masks_identities() {
+        let Some(db) = TestDb::start().await else { return };
+        let state = test_state(&db).await;
+        let host = create_test_user(&db.pool).await;
+        let author = create_test_user(&db.pool).await;
+        let reviewer = create_test_user(&db.pool).await;
+        let project = create_test_project(&db.pool, &host, true).await;
+        let session = create_test_session(&db.pool, &project, &host).await;
+
+        let send = |user, method: &str, path: String, body: serde_json::Value| {
+            let router = Router::new()
+                .route("/sessions/:id", get(get_session))
+                .route("/sessions/:id/join", post(join_session))
+                .route("/sessions/:id/anonymity", put(enable_anonymity).delete(disable_anonymity))
+                .route("/sessions/:id/participants", get(get_participants))
+                .route("/sessions/:id/messages", get(get_messages).post(send_message));
+            let request = Request::builder()
+                .method(method)
+                .uri(path)
+                .header("content-type", "application/json")
+                .body(Body::from(body.to_string()))
+                .unwrap();
+            oneshot_as(router, state.clone(), user, request)
+        };
+        let anonymity_path = format!("/sessions/{}/anonymity", session.id);
+        let join_path = format!("/sessions/{}/join", session.id);
+        let messages_path = format!("/sessions/{}/messages", session.id);
+        let until = serde_json::json!({ "until": chrono::Utc::now() + chrono::Duration::hours(2) });
+
+        let (status, _) = send(&author, "PUT", anonymity_path.clone(), until.clone()).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let (status, body) = send(&host, "PUT", anonymity_path.clone(), until).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+
+        for user in [&author, &reviewer] {
+            let (status, body) = send(user, "POST", join_path.clone(), serde_json::json!({ "role": "editor" })).await;
+            assert_eq!(status, StatusCode::OK, "{}", body);
+        }
+        let (status, body) = send(
+            &author,
+            "POST",
+            messages_path.clone(),
+            serde_json::json!({ "content": "Section 3 is mine", "message_type": "text" }),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        // Reactions name who reacted
+        sqlx::query("UPDATE session_messages SET reactions = $2 WHERE session_id = $1")
+            .bind(session.id)
+            .bind(serde_json::json!({ "+1": [author.id] }).to_string())
+            .execute(&db.pool)
+            .await
+            .unwrap();
+
+        let leaks = |body: &serde_json::Value| {
+            let text = body.to_string();
+            text.contains(&author.id.to_string()) || text.contains(&author.username) || text.contains(&author.email)
+        };
+        let reads = [
+            ("GET", format!("/sessions/{}", session.id)),
+            ("GET", format!("/sessions/{}/participants", session.id)),
+            ("GET", messages_path.clone()),
+        ];
+        for (method, path) in &reads {
+            let (status, body) = send(&reviewer, method, path.clone(), serde_json::json!(null)).await;
+            assert_eq!(status, StatusCode::OK, "{}", body);
+            assert!(!leaks(&body), "{} leaks the author: {}", path, body);
+        }
+        let (_, body) = send(&reviewer, "GET", messages_path.clone(), serde_json::json!(null)).await;
+        let message = &body["data"]["messages"][0];
+        assert_eq!(message["pseudonym"], "Reviewer 1");
+        assert!(message.get("reactions").map_or(true, serde_json::Value::is_null));
+        let alias = message["user_id"].clone();
+
+        // Pseudonyms are stable and the same in every payload
+        let (_, body) = send(&reviewer, "GET", format!("/sessions/{}/participants", session.id), serde_json::json!(null)).await;
+        let participants = body["data"]["participants"].as_array().unwrap();
+        let masked_author = participants.iter().find(|p| p["pseudonym"] == "Reviewer 1").unwrap();
+        assert_eq!(masked_author["user_id"], alias);
+        let own = participants.iter().find(|p| p["user_id"] == reviewer.id.to_string()).unwrap();
+        assert_eq!(own["pseudonym"], "Reviewer 2");
+
+        // The host and the database keep real identities
+        for (method, path) in &reads {
+            let (_, body) = send(&host, method, path.clone(), serde_json::json!(null)).await;
+            assert!(leaks(&body), "{} should show the host the author: {}", path, body);
+        }
+        let stored: Uuid = sqlx::query_scalar("SELECT user_id FROM session_messages WHERE session_id = $1")
+            .bind(session.id)
+            .fetch_one(&db.pool)
+            .await
+            .unwrap();
+        assert_eq!(stored, author.id);
+
+        // Revealing identities in a running session has to be confirmed
+        let (status, body) = send(&host, "DELETE", anonymity_path.clone(), serde_json::json!(null)).await;
+        assert_eq!(status, StatusCode::CONFLICT, "{}", body);
+        let (_, body) = send(&reviewer, "GET", messages_path.clone(), serde_json::json!(null)).await;
+        assert!(!leaks(&body));
+
+        let (status, body) = send(&host, "DELETE", format!("{}?confirm=true", anonymity_path), serde_json::json!(null)).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        let (_, body) = send(&reviewer, "GET", messages_path, serde_json::json!(null)).await;
+        assert_eq!(body["data"]["messages"][0]["user_id"], author.id.to_string());
+    }
+
     #[test]
     fn test_operation_request() {
         let request = SessionOperationRequest {
@@ -1,36 +1,49 @@
 //! Compilation request handlers
 
-use crate::error::AppError;
+use crate::error::{AppError, ErrorBody, ErrorResponse};
 use crate::models::compilation::{
-    CompilationJob, CreateCompilationJob, CompilationTemplate, CreateCompilationTemplate,
-    CompilationStats, QueuePriority
+    check_engine_enabled, ArtifactType, CompilationJob, CreateCompilationJob, CompilationTemplate, CreateCompilationTemplate,
+    CompilationStats, QueuePriority, PROJECT_WORKING_DIRECTORY_ROOT
 };
-use crate::models::LatexEngine;
+use crate::models::compile_batch::{CompileBatch, CompileBatchView};
+use crate::models::compile_cancel;
+use crate::models::compile_preflight::{self, CompilePreflight};
+use crate::models::compile_thumbnails::{CompileThumbnail, ThumbnailReport};
+use crate::models::job_history::{self, JobHistoryParams, PruneJobsParams};
+use crate::models::project::{Project, ProjectActivity};
+use crate::models::project_freeze::ProjectFreeze;
+use crate::models::template_catalog::{TemplateListing, TemplateSearchParams};
+use crate::models::timezone::{self, TimeZoneParams};
+use crate::models::typst;
+use crate::models::{ApiResponse, LatexEngine, PaginationParams};
+use crate::openapi::MessageResponse;
 use axum::{
     extract::{Path, Query, State},
-    http::StatusCode,
-    response::IntoResponse,
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
     Json,
 };
 use crate::server::AppState;
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
 use uuid::Uuid;
 
 /// Compilation job response
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, ToSchema)]
 pub struct CompilationJobResponse {
     pub job: CompilationJob,
 }
 
 /// Compilation jobs list response
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, ToSchema)]
 pub struct CompilationJobsListResponse {
     pub jobs: Vec<CompilationJob>,
     pub pagination: crate::models::PaginationInfo,
 }
 
 /// Compilation queue status response
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, ToSchema)]
 pub struct QueueStatusResponse {
     pub queue_length: i64,
     pub processing_jobs: i64,
@@ -39,30 +52,147 @@ pub struct QueueStatusResponse {
 }
 
 /// Compilation templates list response
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, ToSchema)]
 pub struct CompilationTemplatesListResponse {
-    pub templates: Vec<CompilationTemplate>,
+    pub templates: Vec<TemplateListing>,
     pub pagination: crate::models::PaginationInfo,
 }
 
+/// Compilation job logs response
+#[derive(Debug, Serialize, ToSchema)]
+pub struct JobLogsResponse {
+    pub stdout: Option<String>,
+    pub stderr: Option<String>,
+    pub exit_code: Option<i32>,
+    pub duration_ms: Option<i64>,
+    pub started_at: Option<DateTime<Utc>>,
+    pub completed_at: Option<DateTime<Utc>>,
+}
+
+/// Compilation job artifacts response
+#[derive(Debug, Serialize, ToSchema)]
+pub struct JobArtifactsResponse {
+    pub output_files: Vec<String>,
+    pub artifacts_created: i32,
+    pub output_size_bytes: i64,
+    /// One per output file, in the same order
+    pub download_urls: Vec<String>,
+}
+
+/// Page thumbnail of a job's PDF
+#[derive(Debug, Serialize, ToSchema)]
+pub struct JobThumbnail {
+    pub page: i32,
+    pub width: i32,
+    pub height: i32,
+    pub size_bytes: i32,
+    /// Always `thumbnail`
+    pub file_type: ArtifactType,
+    pub url: String,
+}
+
+/// Compilation job thumbnails response
+#[derive(Debug, Serialize, ToSchema)]
+pub struct JobThumbnailsResponse {
+    /// Unset until the job succeeded and its thumbnails were picked up
+    pub report: Option<ThumbnailReport>,
+    pub thumbnails: Vec<JobThumbnail>,
+}
+
+/// Compilation template response
+#[derive(Debug, Serialize, ToSchema)]
+pub struct CompilationTemplateResponse {
+    pub template: CompilationTemplate,
+}
+
+/// Template rating request
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct RateTemplateRequest {
+    /// 1 to 5
+    pub rating: i16,
+}
+
+/// Template rating response
+#[derive(Debug, Serialize, ToSchema)]
+pub struct TemplateRatingResponse {
+    /// With the updated rating count and average
+    pub template: CompilationTemplate,
+    pub rating: i16,
+}
+
+/// Template fork response
+#[derive(Debug, Serialize, ToSchema)]
+pub struct TemplateForkResponse {
+    pub template: CompilationTemplate,
+    /// False when the caller had already forked this template
+    pub created: bool,
+}
+
 /// Compilation job creation request
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 pub struct CreateJobRequest {
     pub project_id: Uuid,
+    /// File to compile, the project's main file when unset; a `.typ` file compiles with Typst
     pub file_id: Option<Uuid>,
+    /// Defaults to the project's engine; applies to LaTeX targets only
     pub engine: Option<LatexEngine>,
+    /// Engine arguments, ignored by Typst
     pub args: Option<Vec<String>>,
     pub priority: Option<QueuePriority>,
     pub template_id: Option<Uuid>,
+    /// Queue the job even though the preflight found problems
+    #[serde(default)]
+    pub ignore_preflight: bool,
+    /// Build from scratch instead of reusing the previous compile's files
+    #[serde(default)]
+    pub clean: bool,
+}
+
+/// Error body when the preflight refuses a compile
+#[derive(Debug, Serialize, ToSchema)]
+pub struct PreflightFailedResponse {
+    pub success: bool,
+    pub error: ErrorBody,
+    pub preflight: CompilePreflight,
+}
+
+/// 422 response listing the problems a compile was refused for
+pub(crate) fn preflight_refusal(preflight: CompilePreflight) -> Response {
+    (
+        StatusCode::UNPROCESSABLE_ENTITY,
+        Json(PreflightFailedResponse {
+            success: false,
+            error: ErrorBody {
+                code: "PREFLIGHT_FAILED".to_string(),
+                message: format!(
+                    "The compile would fail: {}. Fix the problems or pass ignore_preflight to compile anyway",
+                    preflight.problems.iter().map(|finding| finding.message.as_str()).collect::<Vec<_>>().join("; ")
+                ),
+                timestamp: chrono::Utc::now().to_rfc3339(),
+                request_id: crate::correlation::current_request_id().map(|id| id.to_string()),
+            },
+            preflight,
+        }),
+    )
+        .into_response()
 }
 
 /// Job cancellation request
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 pub struct CancelJobRequest {
     pub reason: Option<String>,
 }
 
 /// List compilation jobs for the user
+#[utoipa::path(
+    get,
+    path = "/jobs",
+    params(PaginationParams),
+    responses(
+        (status = 200, description = "Jobs the user started or can see through a project", body = ApiResponse<CompilationJobsListResponse>),
+        (status = 400, description = "Unknown sort column", body = ErrorResponse),
+    )
+)]
 pub async fn list_jobs(
     State(state): State<AppState>,
     Query(params): Query<crate::models::PaginationParams>,
@@ -75,9 +205,9 @@ pub async fn list_jobs(
         r#"
         SELECT COUNT(*) FROM compilation_jobs cj
         JOIN projects p ON cj.project_id = p.id
-        WHERE cj.user_id = $1 OR p.owner_id = $1 OR p.id IN (
+        WHERE p.purging_at IS NULL AND (cj.user_id = $1 OR p.owner_id = $1 OR p.id IN (
             SELECT project_id FROM project_collaborators WHERE user_id = $1
-        )
+        ))
         "#
     )
     .bind(auth_user.user_id)
@@ -103,17 +233,44 @@ pub async fn list_jobs(
 }
 
 /// Create a new compilation job
+///
+/// Refused with the preflight's findings when it finds problems the compile
+/// would fail on, unless `ignore_preflight` is set.
+#[utoipa::path(
+    post,
+    path = "/jobs",
+    request_body = CreateJobRequest,
+    responses(
+        (status = 201, description = "Job created", body = ApiResponse<CompilationJobResponse>),
+        (status = 400, description = "Engine not available, or template written for the other toolchain", body = ErrorResponse),
+        (status = 404, description = "Project, file or template not found", body = ErrorResponse),
+        (status = 422, description = "The preflight found problems", body = PreflightFailedResponse),
+    )
+)]
 pub async fn create_job(
     State(state): State<AppState>,
     auth_user: axum::Extension<crate::models::auth::AuthContext>,
     Json(payload): Json<CreateJobRequest>,
-) -> Result<impl IntoResponse, AppError> {
+) -> Result<Response, AppError> {
     // Check project access
-    if !crate::models::project::Project::has_access(&state.db_pool, payload.project_id, auth_user.user_id).await? {
-        return Err(AppError::NotFound {
+    let project = crate::models::project::Project::find_by_id(&state.db_pool, payload.project_id, auth_user.user_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound {
             entity: "Project".to_string(),
             id: payload.project_id.to_string(),
-        });
+        })?;
+
+    let target = CompilationJob::target_path(&state.db_pool, &project, payload.file_id, auth_user.user_id).await?;
+    let engine = payload.engine.unwrap_or(project.latex_engine).for_target(&target);
+    check_engine_enabled(&state.config.latex, engine)?;
+    if let Some(template_id) = payload.template_id {
+        let template = CompilationTemplate::find_visible(&state.db_pool, template_id, auth_user.user_id).await?;
+        typst::check_template_engine(template.engine, engine)?;
+    }
+
+    let preflight = compile_preflight::run(&state, &project, &target, engine).await?;
+    if preflight.is_blocking() && !payload.ignore_preflight {
+        return Ok(preflight_refusal(preflight));
     }
 
     let create_job = CreateCompilationJob {
@@ -122,21 +279,29 @@ pub async fn create_job(
         args: payload.args,
         priority: payload.priority,
         template_id: payload.template_id,
+        clean: payload.clean,
+        batch_id: None,
     };
 
-    let working_directory = format!("/tmp/texler/projects/{}", payload.project_id);
+    let working_directory = format!("{}/{}", PROJECT_WORKING_DIRECTORY_ROOT, payload.project_id);
     let input_files = vec![]; // TODO: Get project files
 
-    let job = CompilationJob::create(
-        &state.db_pool,
+    let mut tx = state.db_pool.begin().await.map_err(AppError::Database)?;
+    let mut job = CompilationJob::create(
+        &mut tx,
         payload.project_id,
         auth_user.user_id,
         create_job,
-        payload.engine.unwrap_or_default(),
+        engine,
+        &target,
         working_directory,
         input_files,
     )
     .await?;
+    if payload.ignore_preflight && !preflight.is_empty() {
+        job.record_preflight(&mut tx, &preflight).await?;
+    }
+    tx.commit().await.map_err(AppError::Database)?;
 
     let response = CompilationJobResponse {
         job,
@@ -148,10 +313,20 @@ pub async fn create_job(
             "success": true,
             "data": response
         })),
-    ))
+    )
+        .into_response())
 }
 
 /// Get compilation job details
+#[utoipa::path(
+    get,
+    path = "/jobs/{id}",
+    params(("id" = Uuid, Path, description = "Compilation job ID")),
+    responses(
+        (status = 200, description = "Compilation job", body = ApiResponse<CompilationJobResponse>),
+        (status = 404, description = "Job not found", body = ErrorResponse),
+    )
+)]
 pub async fn get_job(
     State(state): State<AppState>,
     Path(job_id): Path<Uuid>,
@@ -175,6 +350,17 @@ pub async fn get_job(
 }
 
 /// Cancel compilation job
+#[utoipa::path(
+    post,
+    path = "/jobs/{id}/cancel",
+    params(("id" = Uuid, Path, description = "Compilation job ID")),
+    request_body = CancelJobRequest,
+    responses(
+        (status = 200, description = "Job cancelled", body = MessageResponse),
+        (status = 400, description = "Job already finished", body = ErrorResponse),
+        (status = 404, description = "Job not found", body = ErrorResponse),
+    )
+)]
 pub async fn cancel_job(
     State(state): State<AppState>,
     Path(job_id): Path<Uuid>,
@@ -188,20 +374,26 @@ pub async fn cancel_job(
             id: job_id.to_string(),
         })?;
 
-    // Only allow cancellation if job is pending or running
-    match job.status {
-        crate::models::CompilationStatus::Pending | crate::models::CompilationStatus::Running => {
-            job.update_status(
-                &state.db_pool,
-                crate::models::CompilationStatus::Cancelled,
-                Some("Cancelled by user".to_string()),
-            )
-            .await?;
-        }
-        _ => {
-            return Err(AppError::BadRequest(
-                "Cannot cancel a completed job".to_string(),
-            ));
+    // Only pending and running jobs can be cancelled, even when one finishes
+    // right now
+    let Some(cancelled) = job.cancel(&state.db_pool, compile_cancel::CANCELLED_BY_USER).await? else {
+        return Err(AppError::BadRequest(
+            "Cannot cancel a completed job".to_string(),
+        ));
+    };
+
+    // A run on this server stops its engine and reports the end itself
+    if !state.running_compiles.cancel(job_id) {
+        compile_cancel::publish_progress(
+            &state,
+            &cancelled,
+            crate::models::CompilationStatus::Cancelled,
+            cancelled.error_message.clone(),
+        );
+    }
+    if let Some(batch_id) = cancelled.batch_id {
+        if let Err(e) = CompileBatch::member_finished(&state.db_pool, &state.compile_notifier, batch_id).await {
+            tracing::warn!("Failed to report batch {}: {}", batch_id, e);
         }
     }
 
@@ -211,7 +403,101 @@ pub async fn cancel_job(
     })))
 }
 
+/// Get a bulk compilation
+///
+/// The batch's jobs, how many are in each status, where the batch is as a
+/// whole, and the output files of all of them.
+#[utoipa::path(
+    get,
+    path = "/batches/{id}",
+    params(("id" = Uuid, Path, description = "Compilation batch ID")),
+    responses(
+        (status = 200, description = "The batch with its jobs and artifacts", body = ApiResponse<CompileBatchView>),
+        (status = 404, description = "Batch not found", body = ErrorResponse),
+    )
+)]
+pub async fn get_batch(
+    State(state): State<AppState>,
+    Path(batch_id): Path<Uuid>,
+    auth_user: axum::Extension<crate::models::auth::AuthContext>,
+) -> Result<impl IntoResponse, AppError> {
+    let batch = CompileBatch::find_by_id(&state.db_pool, batch_id, auth_user.user_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound {
+            entity: "CompileBatch".to_string(),
+            id: batch_id.to_string(),
+        })?;
+
+    Ok(Json(serde_json::json!({
+        "success": true,
+        "data": batch.view(&state.db_pool).await?
+    })))
+}
+
+/// Cancel a bulk compilation
+///
+/// Cancels every job of the batch that has not finished, like cancelling
+/// each; jobs that finished keep their result.
+#[utoipa::path(
+    post,
+    path = "/batches/{id}/cancel",
+    params(("id" = Uuid, Path, description = "Compilation batch ID")),
+    request_body = CancelJobRequest,
+    responses(
+        (status = 200, description = "The batch after cancelling its jobs", body = ApiResponse<CompileBatchView>),
+        (status = 400, description = "Every job of the batch already finished", body = ErrorResponse),
+        (status = 404, description = "Batch not found", body = ErrorResponse),
+    )
+)]
+pub async fn cancel_batch(
+    State(state): State<AppState>,
+    Path(batch_id): Path<Uuid>,
+    auth_user: axum::Extension<crate::models::auth::AuthContext>,
+    Json(_payload): Json<CancelJobRequest>,
+) -> Result<impl IntoResponse, AppError> {
+    let batch = CompileBatch::find_by_id(&state.db_pool, batch_id, auth_user.user_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound {
+            entity: "CompileBatch".to_string(),
+            id: batch_id.to_string(),
+        })?;
+
+    let cancelled = batch.cancel(&state.db_pool, compile_cancel::CANCELLED_BY_USER).await?;
+    if cancelled.is_empty() {
+        return Err(AppError::BadRequest(
+            "Cannot cancel a completed batch".to_string(),
+        ));
+    }
+    for job in &cancelled {
+        if !state.running_compiles.cancel(job.id) {
+            compile_cancel::publish_progress(
+                &state,
+                job,
+                crate::models::CompilationStatus::Cancelled,
+                job.error_message.clone(),
+            );
+        }
+    }
+    if let Err(e) = CompileBatch::member_finished(&state.db_pool, &state.compile_notifier, batch_id).await {
+        tracing::warn!("Failed to report batch {}: {}", batch_id, e);
+    }
+
+    Ok(Json(serde_json::json!({
+        "success": true,
+        "data": batch.view(&state.db_pool).await?
+    })))
+}
+
 /// Get compilation job logs
+#[utoipa::path(
+    get,
+    path = "/jobs/{id}/logs",
+    params(("id" = Uuid, Path, description = "Compilation job ID")),
+    responses(
+        (status = 200, description = "Output and timing of the job", body = ApiResponse<JobLogsResponse>),
+        (status = 404, description = "Job not found", body = ErrorResponse),
+    )
+)]
 pub async fn get_job_logs(
     State(state): State<AppState>,
     Path(job_id): Path<Uuid>,
@@ -224,14 +510,14 @@ pub async fn get_job_logs(
             id: job_id.to_string(),
         })?;
 
-    let logs = serde_json::json!({
-        "stdout": job.stdout,
-        "stderr": job.stderr,
-        "exit_code": job.exit_code,
-        "duration_ms": job.duration_ms,
-        "started_at": job.started_at,
-        "completed_at": job.completed_at
-    });
+    let logs = JobLogsResponse {
+        stdout: job.stdout,
+        stderr: job.stderr,
+        exit_code: job.exit_code,
+        duration_ms: job.duration_ms,
+        started_at: job.started_at,
+        completed_at: job.completed_at,
+    };
 
     Ok(Json(serde_json::json!({
         "success": true,
@@ -240,6 +526,15 @@ pub async fn get_job_logs(
 }
 
 /// Get compilation job artifacts
+#[utoipa::path(
+    get,
+    path = "/jobs/{id}/artifacts",
+    params(("id" = Uuid, Path, description = "Compilation job ID")),
+    responses(
+        (status = 200, description = "Files produced by the job", body = ApiResponse<JobArtifactsResponse>),
+        (status = 404, description = "Job not found", body = ErrorResponse),
+    )
+)]
 pub async fn get_job_artifacts(
     State(state): State<AppState>,
     Path(job_id): Path<Uuid>,
@@ -252,14 +547,14 @@ pub async fn get_job_artifacts(
             id: job_id.to_string(),
         })?;
 
-    let artifacts = serde_json::json!({
-        "output_files": job.output_files,
-        "artifacts_created": job.artifacts_created,
-        "output_size_bytes": job.output_size_bytes,
-        "download_urls": job.output_files.iter().map(|f| {
+    let artifacts = JobArtifactsResponse {
+        download_urls: job.output_files.iter().map(|f| {
             format!("/api/v1/compilation/jobs/{}/artifacts/{}", job_id, f)
-        }).collect::<Vec<String>>()
-    });
+        }).collect(),
+        output_files: job.output_files,
+        artifacts_created: job.artifacts_created,
+        output_size_bytes: job.output_size_bytes,
+    };
 
     Ok(Json(serde_json::json!({
         "success": true,
@@ -267,7 +562,115 @@ pub async fn get_job_artifacts(
     })))
 }
 
+/// List the page thumbnails of a compilation job
+///
+/// Thumbnails of the first pages of the job's PDF are rendered shortly after
+/// it succeeds; the report tells whether they are still pending, and why
+/// pages or the whole PDF were left out.
+#[utoipa::path(
+    get,
+    path = "/jobs/{id}/thumbnails",
+    params(("id" = Uuid, Path, description = "Compilation job ID")),
+    responses(
+        (status = 200, description = "Thumbnails of the job's PDF", body = ApiResponse<JobThumbnailsResponse>),
+        (status = 404, description = "Job not found", body = ErrorResponse),
+    )
+)]
+pub async fn list_job_thumbnails(
+    State(state): State<AppState>,
+    Path(job_id): Path<Uuid>,
+    auth_user: axum::Extension<crate::models::auth::AuthContext>,
+) -> Result<impl IntoResponse, AppError> {
+    let job = CompilationJob::find_by_id(&state.db_pool, job_id, auth_user.user_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound {
+            entity: "CompilationJob".to_string(),
+            id: job_id.to_string(),
+        })?;
+
+    let thumbnails = CompileThumbnail::list(&state.db_pool, job_id)
+        .await?
+        .into_iter()
+        .map(|thumbnail| JobThumbnail {
+            url: format!("/api/v1/compilation/jobs/{}/thumbnails/{}", job_id, thumbnail.page),
+            page: thumbnail.page,
+            width: thumbnail.width,
+            height: thumbnail.height,
+            size_bytes: thumbnail.size_bytes,
+            file_type: ArtifactType::Thumbnail,
+        })
+        .collect();
+    let response = JobThumbnailsResponse {
+        report: job.thumbnail_report(),
+        thumbnails,
+    };
+
+    Ok(Json(serde_json::json!({
+        "success": true,
+        "data": response
+    })))
+}
+
+/// Get the thumbnail of a page of a compilation job's PDF
+///
+/// A job's thumbnails never change, so they may be cached indefinitely.
+#[utoipa::path(
+    get,
+    path = "/jobs/{id}/thumbnails/{page}",
+    params(
+        ("id" = Uuid, Path, description = "Compilation job ID"),
+        ("page" = i32, Path, description = "Page number, from 1"),
+    ),
+    responses(
+        (status = 200, description = "Thumbnail image", content_type = "image/webp", body = Vec<u8>,
+            headers(("ETag" = String, description = "Job and page of the image"))),
+        (status = 304, description = "The image matches `If-None-Match`"),
+        (status = 404, description = "Job or thumbnail not found", body = ErrorResponse),
+    )
+)]
+pub async fn get_job_thumbnail(
+    State(state): State<AppState>,
+    Path((job_id, page)): Path<(Uuid, i32)>,
+    auth_user: axum::Extension<crate::models::auth::AuthContext>,
+    request_headers: HeaderMap,
+) -> Result<impl IntoResponse, AppError> {
+    CompilationJob::find_by_id(&state.db_pool, job_id, auth_user.user_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound {
+            entity: "CompilationJob".to_string(),
+            id: job_id.to_string(),
+        })?;
+
+    let etag = format!("\"{}-{}\"", job_id.simple(), page);
+    let mut headers = HeaderMap::new();
+    let etag_value = HeaderValue::from_str(&etag)
+        .map_err(|_| AppError::Internal("Invalid thumbnail ETag".to_string()))?;
+    headers.insert(header::ETAG, etag_value);
+    headers.insert(header::CACHE_CONTROL, HeaderValue::from_static("private, max-age=31536000, immutable"));
+
+    let image = CompileThumbnail::image(&state.db_pool, job_id, page)
+        .await?
+        .ok_or_else(|| AppError::NotFound {
+            entity: "Thumbnail".to_string(),
+            id: format!("{}/{}", job_id, page),
+        })?;
+    if request_headers.get(header::IF_NONE_MATCH).is_some_and(|value| value.as_bytes() == etag.as_bytes()) {
+        return Ok((StatusCode::NOT_MODIFIED, headers, Vec::new()));
+    }
+
+    headers.insert(header::CONTENT_TYPE, HeaderValue::from_static("image/webp"));
+    headers.insert(header::X_CONTENT_TYPE_OPTIONS, HeaderValue::from_static("nosniff"));
+    Ok((StatusCode::OK, headers, image))
+}
+
 /// Get compilation queue status
+#[utoipa::path(
+    get,
+    path = "/queue",
+    responses(
+        (status = 200, description = "Queue length and worker availability", body = ApiResponse<QueueStatusResponse>),
+    )
+)]
 pub async fn get_queue_status(
     State(state): State<AppState>,
     _auth_user: axum::Extension<crate::models::auth::AuthContext>,
@@ -309,32 +712,22 @@ pub async fn get_queue_status(
 }
 
 /// List compilation templates
+#[utoipa::path(
+    get,
+    path = "/templates",
+    params(TemplateSearchParams, PaginationParams),
+    responses(
+        (status = 200, description = "Public templates with their authors, verified first, then most successful", body = ApiResponse<CompilationTemplatesListResponse>),
+    )
+)]
 pub async fn list_templates(
     State(state): State<AppState>,
+    Query(search): Query<TemplateSearchParams>,
     Query(params): Query<crate::models::PaginationParams>,
-    _auth_user: axum::Extension<crate::models::auth::AuthContext>,
+    auth_user: axum::Extension<crate::models::auth::AuthContext>,
 ) -> Result<impl IntoResponse, AppError> {
-    let templates = sqlx::query_as::<_, CompilationTemplate>(
-        r#"
-        SELECT * FROM compilation_templates
-        WHERE is_public = true
-        ORDER BY success_rate DESC, usage_count DESC
-        LIMIT $1 OFFSET $2
-        "#
-    )
-    .bind(params.limit() as i64)
-    .bind(params.offset() as i64)
-    .fetch_all(&state.db_pool)
-    .await
-    .map_err(AppError::Database)?;
-
-    // Get total count for pagination
-    let total_count = sqlx::query_scalar::<_, i64>(
-        "SELECT COUNT(*) FROM compilation_templates WHERE is_public = true"
-    )
-    .fetch_one(&state.db_pool)
-    .await
-    .map_err(AppError::Database)?;
+    let (templates, total_count) =
+        CompilationTemplate::list_public(&state.db_pool, auth_user.user_id, &search, &params).await?;
 
     let pagination_info = crate::models::PaginatedResponse::new(
         templates.clone(),
@@ -354,6 +747,14 @@ pub async fn list_templates(
 }
 
 /// Create compilation template
+#[utoipa::path(
+    post,
+    path = "/templates",
+    request_body = CreateCompilationTemplate,
+    responses(
+        (status = 200, description = "Template created", body = ApiResponse<CompilationTemplateResponse>),
+    )
+)]
 pub async fn create_template(
     State(state): State<AppState>,
     auth_user: axum::Extension<crate::models::auth::AuthContext>,
@@ -361,54 +762,129 @@ pub async fn create_template(
 ) -> Result<impl IntoResponse, AppError> {
     let template = CompilationTemplate::create(&state.db_pool, auth_user.user_id, payload).await?;
 
+    let response = CompilationTemplateResponse {
+        template,
+    };
+
     Ok(Json(serde_json::json!({
         "success": true,
-        "data": {
-            "template": template
-        }
+        "data": response
     })))
 }
 
 /// Get compilation template details
+#[utoipa::path(
+    get,
+    path = "/templates/{id}",
+    params(("id" = Uuid, Path, description = "Compilation template ID")),
+    responses(
+        (status = 200, description = "Compilation template", body = ApiResponse<CompilationTemplateResponse>),
+        (status = 404, description = "Template not found or private to another user", body = ErrorResponse),
+    )
+)]
 pub async fn get_template(
     State(state): State<AppState>,
     Path(template_id): Path<Uuid>,
-    _auth_user: axum::Extension<crate::models::auth::AuthContext>,
+    auth_user: axum::Extension<crate::models::auth::AuthContext>,
+) -> Result<impl IntoResponse, AppError> {
+    let template = CompilationTemplate::find_visible(&state.db_pool, template_id, auth_user.user_id).await?;
+
+    let response = CompilationTemplateResponse {
+        template,
+    };
+
+    Ok(Json(serde_json::json!({
+        "success": true,
+        "data": response
+    })))
+}
+
+/// Rate a public template; rating again replaces the earlier rating
+#[utoipa::path(
+    post,
+    path = "/templates/{id}/rate",
+    params(("id" = Uuid, Path, description = "Compilation template ID")),
+    request_body = RateTemplateRequest,
+    responses(
+        (status = 200, description = "Rating recorded", body = ApiResponse<TemplateRatingResponse>),
+        (status = 400, description = "Rating is not between 1 and 5", body = ErrorResponse),
+        (status = 403, description = "Authors cannot rate their own templates", body = ErrorResponse),
+        (status = 404, description = "No public template with this ID", body = ErrorResponse),
+    )
+)]
+pub async fn rate_template(
+    State(state): State<AppState>,
+    Path(template_id): Path<Uuid>,
+    auth_user: axum::Extension<crate::models::auth::AuthContext>,
+    Json(payload): Json<RateTemplateRequest>,
 ) -> Result<impl IntoResponse, AppError> {
-    let template = sqlx::query_as::<_, CompilationTemplate>(
-        "SELECT * FROM compilation_templates WHERE id = $1"
+    let template = CompilationTemplate::rate(&state.db_pool, template_id, auth_user.user_id, payload.rating).await?;
+
+    let response = TemplateRatingResponse {
+        template,
+        rating: payload.rating,
+    };
+
+    Ok(Json(serde_json::json!({
+        "success": true,
+        "data": response
+    })))
+}
+
+/// Copy a template into the caller's ownership
+#[utoipa::path(
+    post,
+    path = "/templates/{id}/fork",
+    params(("id" = Uuid, Path, description = "Compilation template ID")),
+    responses(
+        (status = 201, description = "Private fork created", body = ApiResponse<TemplateForkResponse>),
+        (status = 200, description = "The caller's existing fork", body = ApiResponse<TemplateForkResponse>),
+        (status = 404, description = "Template not found or private to another user", body = ErrorResponse),
     )
-    .bind(template_id)
-    .fetch_optional(&state.db_pool)
-    .await
-    .map_err(AppError::Database)?;
+)]
+pub async fn fork_template(
+    State(state): State<AppState>,
+    Path(template_id): Path<Uuid>,
+    auth_user: axum::Extension<crate::models::auth::AuthContext>,
+) -> Result<impl IntoResponse, AppError> {
+    let (template, created) = CompilationTemplate::fork(&state.db_pool, template_id, auth_user.user_id).await?;
+
+    let status = if created { StatusCode::CREATED } else { StatusCode::OK };
+    let response = TemplateForkResponse {
+        template,
+        created,
+    };
 
-    if let Some(template) = template {
-        Ok(Json(serde_json::json!({
+    Ok((
+        status,
+        Json(serde_json::json!({
             "success": true,
-            "data": {
-                "template": template
-            }
-        })))
-    } else {
-        Err(AppError::NotFound {
-            entity: "CompilationTemplate".to_string(),
-            id: template_id.to_string(),
-        })
-    }
+            "data": response
+        })),
+    ))
 }
 
 /// Get compilation statistics
+///
+/// The period is made of whole calendar days in `tz`, up to now.
+#[utoipa::path(
+    get,
+    path = "/stats",
+    params(CompilationStatsParams, TimeZoneParams),
+    responses(
+        (status = 200, description = "Aggregate job statistics over the period", body = ApiResponse<CompilationStats>),
+        (status = 400, description = "Unknown time zone", body = ErrorResponse),
+    )
+)]
 pub async fn get_compilation_stats(
     State(state): State<AppState>,
     Query(params): Query<CompilationStatsParams>,
+    Query(zone): Query<TimeZoneParams>,
     _auth_user: axum::Extension<crate::models::auth::AuthContext>,
 ) -> Result<impl IntoResponse, AppError> {
-    use chrono::Duration;
-
-    let period_days = params.days.unwrap_or(7);
-    let period_start = chrono::Utc::now() - Duration::days(period_days);
+    let tz = zone.time_zone()?;
     let period_end = chrono::Utc::now();
+    let period_start = timezone::start_of_days_until(tz, period_end, params.days.unwrap_or(7));
 
     let stats = CompilationStats::get_stats(&state.db_pool, period_start, period_end).await?;
 
@@ -418,9 +894,97 @@ pub async fn get_compilation_stats(
     })))
 }
 
+/// Pruned job history response
+#[derive(Debug, Serialize, ToSchema)]
+pub struct PrunedJobsResponse {
+    /// Failed jobs deleted
+    pub deleted: u64,
+    pub before: DateTime<Utc>,
+}
+
+/// List a project's compilation jobs
+#[utoipa::path(
+    get,
+    path = "/{id}/compilation/jobs",
+    params(("id" = Uuid, Path, description = "Project ID"), JobHistoryParams, PaginationParams),
+    responses(
+        (status = 200, description = "Jobs of the project, newest first", body = ApiResponse<CompilationJobsListResponse>),
+        (status = 400, description = "Invalid filters", body = ErrorResponse),
+        (status = 404, description = "Project not found", body = ErrorResponse),
+    )
+)]
+pub async fn list_project_jobs(
+    State(state): State<AppState>,
+    Path(project_id): Path<Uuid>,
+    Query(filters): Query<JobHistoryParams>,
+    Query(params): Query<PaginationParams>,
+    auth_user: axum::Extension<crate::models::auth::AuthContext>,
+) -> Result<impl IntoResponse, AppError> {
+    if !Project::has_access(&state.db_pool, project_id, auth_user.user_id).await? {
+        return Err(AppError::NotFound {
+            entity: "Project".to_string(),
+            id: project_id.to_string(),
+        });
+    }
+    filters.validate()?;
+
+    let (jobs, total) = job_history::list(&state.db_pool, project_id, &filters, &params).await?;
+    let pagination = crate::models::PaginatedResponse::new(jobs.clone(), &params, total as u64).pagination;
+
+    Ok(Json(serde_json::json!({
+        "success": true,
+        "data": CompilationJobsListResponse { jobs, pagination }
+    })))
+}
+
+/// Delete a project's old failed jobs
+#[utoipa::path(
+    delete,
+    path = "/{id}/compilation/jobs/history",
+    params(("id" = Uuid, Path, description = "Project ID"), PruneJobsParams),
+    responses(
+        (status = 200, description = "Failed jobs before the cut-off deleted, except those still referred to", body = ApiResponse<PrunedJobsResponse>),
+        (status = 403, description = "Only the owner can prune the history", body = ErrorResponse),
+        (status = 423, description = "Project is frozen", body = ErrorResponse),
+    )
+)]
+pub async fn prune_project_jobs(
+    State(state): State<AppState>,
+    Path(project_id): Path<Uuid>,
+    Query(params): Query<PruneJobsParams>,
+    auth_user: axum::Extension<crate::models::auth::AuthContext>,
+) -> Result<impl IntoResponse, AppError> {
+    if !Project::is_owner(&state.db_pool, project_id, auth_user.user_id).await? {
+        return Err(AppError::Authorization(
+            "Only project owners can prune the compilation history".to_string(),
+        ));
+    }
+    ProjectFreeze::ensure_writable(&state.db_pool, project_id).await?;
+
+    let deleted = job_history::prune_failed(&state.db_pool, project_id, params.before).await?;
+    if deleted > 0 {
+        ProjectActivity::log_later(
+            &state.db_resilience,
+            project_id,
+            auth_user.user_id,
+            "compilation_history_pruned",
+            "project",
+            Some(project_id),
+            Some(serde_json::json!({ "deleted": deleted, "before": params.before }).to_string()),
+        );
+    }
+
+    Ok(Json(serde_json::json!({
+        "success": true,
+        "data": PrunedJobsResponse { deleted, before: params.before }
+    })))
+}
+
 /// Compilation statistics parameters
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct CompilationStatsParams {
+    /// Length of the period in calendar days, today included; defaults to 7
     pub days: Option<i64>,
 }
 
@@ -437,6 +1001,8 @@ mod tests {
             args: Some(vec!["-interaction=nonstopmode".to_string()]),
             priority: Some(QueuePriority::Normal),
             template_id: None,
+            ignore_preflight: false,
+            clean: false,
         };
 
         // This test would require setting up proper auth context and test project
@@ -458,4 +1024,143 @@ mod tests {
         assert_eq!(response.processing_jobs, 2);
         assert_eq!(response.workers_online, 3);
     }
+
+    #[tokio::test]
+    async fn test_project_jobs_follow_project_access() {
+        use crate::models::UserRole;
+        use crate::testing::{add_collaborator, create_test_job, create_test_project, create_test_user, oneshot_as, test_state, TestDb};
+        use axum::{body::Body, http::Request, routing::get, Router};
+
+        let Some(db) = TestDb::start().await else { return };
+        let state = test_state(&db).await;
+        let owner = create_test_user(&db.pool).await;
+        let viewer = create_test_user(&db.pool).await;
+        let outsider = create_test_user(&db.pool).await;
+        let project = create_test_project(&db.pool, &owner, false).await;
+        add_collaborator(&db.pool, &project, &viewer, UserRole::Viewer).await;
+        let job = create_test_job(&db.pool, &project, &owner).await;
+
+        let router = || {
+            Router::new()
+                .route("/projects/:id/compilation/jobs", get(list_project_jobs))
+                .route("/projects/:id/compilation/jobs/history", axum::routing::delete(prune_project_jobs))
+        };
+        let list = |uri: String| Request::get(uri).body(Body::empty()).unwrap();
+        let jobs_uri = format!("/projects/{}/compilation/jobs", project.id);
+
+        // Jobs someone else started are listed to every member
+        let (status, body) = oneshot_as(router(), state.clone(), &viewer, list(jobs_uri.clone())).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        assert_eq!(body["data"]["jobs"][0]["id"], job.id.to_string());
+        assert_eq!(body["data"]["pagination"]["total"], 1);
+
+        let (status, body) = oneshot_as(router(), state.clone(), &viewer, list(format!("{}?status=error", jobs_uri))).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        assert_eq!(body["data"]["pagination"]["total"], 0);
+
+        let (status, _) = oneshot_as(router(), state.clone(), &outsider, list(jobs_uri.clone())).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        let prune = || {
+            Request::delete(format!("/projects/{}/compilation/jobs/history?before=2100-01-01T00:00:00Z", project.id))
+                .body(Body::empty())
+                .unwrap()
+        };
+        let (status, _) = oneshot_as(router(), state.clone(), &viewer, prune()).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let (status, body) = oneshot_as(router(), state.clone(), &owner, prune()).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        assert_eq!(body["data"]["deleted"], 0);
+    }
+
+    #[tokio::test]
+    async fn test_cancel_stops_the_running_engine_once() {
+        use crate::models::compilation::CompilationQueue;
+        use crate::models::CompilationStatus;
+        use crate::testing::{create_test_job, create_test_project, create_test_user, oneshot_as, test_state, TestDb};
+        use crate::websocket::WsMessage;
+        use axum::{body::Body, http::Request, routing::post, Router};
+        use std::time::{Duration, Instant};
+
+        let Some(db) = TestDb::start().await else { return };
+        let state = test_state(&db).await;
+        let owner = create_test_user(&db.pool).await;
+        let project = create_test_project(&db.pool, &owner, false).await;
+        let job = create_test_job(&db.pool, &project, &owner).await;
+        let (_, dequeued) = CompilationQueue::dequeue(&db.pool).await.unwrap().unwrap();
+        assert_eq!(dequeued.id, job.id);
+        let mut progress = state.user_channels.subscribe(owner.id);
+
+        // A fake engine that prints, then hangs in a helper of its own
+        let directory = tempfile::tempdir().unwrap();
+        let pid_file = directory.path().join("engine.pid");
+        let mut command = tokio::process::Command::new("sh");
+        command.arg("-c").arg("echo $$ > \"$0\"; echo 'This is pdfTeX'; sleep 60; echo done").arg(&pid_file);
+        let run = {
+            let (state, job) = (state.clone(), job.clone());
+            tokio::spawn(async move { compile_cancel::execute(&state, &job, command, Some("worker-1".to_string())).await })
+        };
+        let started = Instant::now();
+        while state.running_compiles.pid(job.id).is_none() || !pid_file.exists() {
+            assert!(started.elapsed() < Duration::from_secs(5), "the engine did not start");
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        let pid = state.running_compiles.pid(job.id).unwrap();
+
+        let router = || Router::new().route("/compilation/jobs/:id/cancel", post(cancel_job));
+        let cancel = || {
+            Request::post(format!("/compilation/jobs/{}/cancel", job.id))
+                .header("content-type", "application/json")
+                .body(Body::from("{}"))
+                .unwrap()
+        };
+        let (first, second) = tokio::join!(
+            oneshot_as(router(), state.clone(), &owner, cancel()),
+            oneshot_as(router(), state.clone(), &owner, cancel()),
+        );
+        let mut statuses = [first.0, second.0];
+        statuses.sort();
+        assert_eq!(statuses, [StatusCode::OK, StatusCode::BAD_REQUEST]);
+
+        let outcome = tokio::time::timeout(Duration::from_secs(5), run).await.unwrap().unwrap().unwrap();
+        assert!(matches!(outcome, Some(compile_cancel::RunOutcome::Cancelled { .. })), "{:?}", outcome);
+        // Members of the engine's group are gone, or zombies left to init
+        let group_alive = || {
+            std::fs::read_dir("/proc").unwrap().flatten().any(|entry| {
+                let stat = std::fs::read_to_string(entry.path().join("stat")).unwrap_or_default();
+                let fields: Vec<&str> = stat.rsplit(')').next().unwrap_or_default().split_whitespace().collect();
+                fields.len() > 2 && fields[2] == pid.to_string() && fields[0] != "Z"
+            })
+        };
+        let cancelled = Instant::now();
+        while group_alive() {
+            assert!(cancelled.elapsed() < Duration::from_secs(2), "the engine survived the cancellation");
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert!(state.running_compiles.is_empty());
+
+        let found = CompilationJob::find_by_id(&db.pool, job.id, owner.id).await.unwrap().unwrap();
+        assert_eq!(found.status, CompilationStatus::Cancelled);
+        assert_eq!(found.stdout.as_deref(), Some("This is pdfTeX\n"));
+        let queued: bool = sqlx::query_scalar("SELECT EXISTS(SELECT 1 FROM compilation_queue WHERE job_id = $1)")
+            .bind(job.id)
+            .fetch_one(&db.pool)
+            .await
+            .unwrap();
+        assert!(!queued);
+
+        // A result arriving late does not overwrite the cancellation
+        found
+            .complete(&db.pool, &state.compile_notifier, &state.secret_keyring, 0, String::new(), String::new(), vec![], 1, 1)
+            .await
+            .unwrap();
+        let found = CompilationJob::find_by_id(&db.pool, job.id, owner.id).await.unwrap().unwrap();
+        assert_eq!(found.status, CompilationStatus::Cancelled);
+
+        let mut events = Vec::new();
+        while let Ok(WsMessage::CompileProgress { status, .. }) = progress.try_recv() {
+            events.push(status);
+        }
+        assert_eq!(events, vec![CompilationStatus::Running, CompilationStatus::Cancelled]);
+    }
 }
@@ -0,0 +1,376 @@
+//! Spell checker dictionary handlers
+
+use crate::error::{AppError, ErrorResponse};
+use crate::models::dictionary::{
+    parse_term_list, DictionaryEntry, DictionaryScope, EffectiveDictionary, EffectiveTerm, NewTerm,
+    TermOutcome,
+};
+use crate::models::project::{Project, ProjectActivity};
+use crate::models::{ApiResponse, UserRole};
+use axum::{
+    extract::{Path, Query, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use crate::server::AppState;
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+use uuid::Uuid;
+
+/// Add terms request
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct AddTermsRequest {
+    pub terms: Vec<NewTerm>,
+}
+
+/// Dictionary entries response
+#[derive(Debug, Serialize, ToSchema)]
+pub struct DictionaryEntriesResponse {
+    pub entries: Vec<DictionaryEntry>,
+    pub total: usize,
+}
+
+/// Per-term results of an add or import
+#[derive(Debug, Serialize, ToSchema)]
+pub struct AddTermsResponse {
+    pub results: Vec<TermOutcome>,
+    pub summary: AddTermsSummary,
+}
+
+/// Number of submitted terms per outcome
+#[derive(Debug, Serialize, ToSchema)]
+pub struct AddTermsSummary {
+    pub added: usize,
+    pub existing: usize,
+    pub invalid: usize,
+    pub limit_reached: usize,
+}
+
+/// Effective dictionary response
+#[derive(Debug, Serialize, ToSchema)]
+pub struct EffectiveDictionaryResponse {
+    pub terms: Vec<EffectiveTerm>,
+    pub total: usize,
+}
+
+/// Effective dictionary download parameters
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct EffectiveDictionaryParams {
+    /// `json` (default) or `text`
+    pub format: Option<String>,
+}
+
+/// List the project dictionary
+#[utoipa::path(
+    get,
+    path = "/{id}/dictionary",
+    params(("id" = Uuid, Path, description = "Project ID")),
+    responses(
+        (status = 200, description = "Project dictionary", body = ApiResponse<DictionaryEntriesResponse>),
+        (status = 404, description = "Project not found", body = ErrorResponse),
+    )
+)]
+pub async fn list_project_dictionary(
+    State(state): State<AppState>,
+    Path(project_id): Path<Uuid>,
+    auth_user: axum::Extension<crate::models::auth::AuthContext>,
+) -> Result<impl IntoResponse, AppError> {
+    require_role(&state, project_id, auth_user.user_id, UserRole::Viewer).await?;
+
+    let entries = DictionaryEntry::list(&state.db_pool, DictionaryScope::Project(project_id)).await?;
+
+    Ok(Json(serde_json::json!({
+        "success": true,
+        "data": DictionaryEntriesResponse {
+            total: entries.len(),
+            entries,
+        }
+    })))
+}
+
+/// Add terms to the project dictionary
+#[utoipa::path(
+    post,
+    path = "/{id}/dictionary",
+    params(("id" = Uuid, Path, description = "Project ID")),
+    request_body = AddTermsRequest,
+    responses(
+        (status = 200, description = "Outcome per term", body = ApiResponse<AddTermsResponse>),
+        (status = 403, description = "Requires the collaborator role", body = ErrorResponse),
+        (status = 404, description = "Project not found", body = ErrorResponse),
+    )
+)]
+pub async fn add_project_terms(
+    State(state): State<AppState>,
+    Path(project_id): Path<Uuid>,
+    auth_user: axum::Extension<crate::models::auth::AuthContext>,
+    Json(request): Json<AddTermsRequest>,
+) -> Result<impl IntoResponse, AppError> {
+    add_to_project(&state, project_id, auth_user.user_id, request.terms).await
+}
+
+/// Add terms to the project dictionary from a plain text list, one per line
+#[utoipa::path(
+    post,
+    path = "/{id}/dictionary/import",
+    params(("id" = Uuid, Path, description = "Project ID")),
+    request_body(
+        content = String,
+        content_type = "text/plain",
+        description = "One term per line; `#` starts a comment, a trailing ` !` keeps the case"
+    ),
+    responses(
+        (status = 200, description = "Outcome per term", body = ApiResponse<AddTermsResponse>),
+        (status = 403, description = "Requires the collaborator role", body = ErrorResponse),
+        (status = 404, description = "Project not found", body = ErrorResponse),
+    )
+)]
+pub async fn import_project_terms(
+    State(state): State<AppState>,
+    Path(project_id): Path<Uuid>,
+    auth_user: axum::Extension<crate::models::auth::AuthContext>,
+    body: String,
+) -> Result<impl IntoResponse, AppError> {
+    add_to_project(&state, project_id, auth_user.user_id, parse_term_list(&body)).await
+}
+
+/// Remove a project dictionary entry
+#[utoipa::path(
+    delete,
+    path = "/{id}/dictionary/{entry_id}",
+    params(
+        ("id" = Uuid, Path, description = "Project ID"),
+        ("entry_id" = Uuid, Path, description = "Dictionary entry ID"),
+    ),
+    responses(
+        (status = 204, description = "Entry removed"),
+        (status = 403, description = "Requires the maintainer role", body = ErrorResponse),
+        (status = 404, description = "Project or entry not found", body = ErrorResponse),
+    )
+)]
+pub async fn remove_project_term(
+    State(state): State<AppState>,
+    Path((project_id, entry_id)): Path<(Uuid, Uuid)>,
+    auth_user: axum::Extension<crate::models::auth::AuthContext>,
+) -> Result<impl IntoResponse, AppError> {
+    require_role(&state, project_id, auth_user.user_id, UserRole::Maintainer).await?;
+
+    let entry = DictionaryEntry::remove(&state.db_pool, DictionaryScope::Project(project_id), entry_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound {
+            entity: "Dictionary entry".to_string(),
+            id: entry_id.to_string(),
+        })?;
+
+    ProjectActivity::log_later(
+        &state.db_resilience,
+        project_id,
+        auth_user.user_id,
+        "dictionary_term_removed",
+        "dictionary",
+        Some(entry.id),
+        Some(entry.term),
+    );
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Download the project dictionary merged with the caller's personal one
+#[utoipa::path(
+    get,
+    path = "/{id}/dictionary/effective",
+    params(("id" = Uuid, Path, description = "Project ID"), EffectiveDictionaryParams),
+    responses(
+        (status = 200, description = "Project and personal terms merged", content(
+            (ApiResponse<EffectiveDictionaryResponse> = "application/json"),
+            (String = "text/plain"),
+        )),
+        (status = 404, description = "Project not found", body = ErrorResponse),
+    )
+)]
+pub async fn get_effective_dictionary(
+    State(state): State<AppState>,
+    Path(project_id): Path<Uuid>,
+    Query(params): Query<EffectiveDictionaryParams>,
+    auth_user: axum::Extension<crate::models::auth::AuthContext>,
+) -> Result<Response, AppError> {
+    require_role(&state, project_id, auth_user.user_id, UserRole::Viewer).await?;
+
+    let dictionary = EffectiveDictionary::load(&state.db_pool, project_id, auth_user.user_id).await?;
+
+    match params.format.as_deref().unwrap_or("json") {
+        "json" => Ok(Json(serde_json::json!({
+            "success": true,
+            "data": EffectiveDictionaryResponse {
+                terms: dictionary.terms().to_vec(),
+                total: dictionary.terms().len(),
+            }
+        }))
+        .into_response()),
+        "text" => Ok((
+            [
+                (header::CONTENT_TYPE, "text/plain; charset=utf-8".to_string()),
+                (
+                    header::CONTENT_DISPOSITION,
+                    format!("attachment; filename=\"dictionary-{}.txt\"", project_id),
+                ),
+            ],
+            dictionary.to_text(),
+        )
+            .into_response()),
+        other => Err(AppError::Validation(format!("Unknown dictionary format: {}", other))),
+    }
+}
+
+/// List the current user's personal dictionary
+#[utoipa::path(
+    get,
+    path = "/dictionary",
+    responses(
+        (status = 200, description = "Personal dictionary", body = ApiResponse<DictionaryEntriesResponse>),
+    )
+)]
+pub async fn list_personal_dictionary(
+    State(state): State<AppState>,
+    auth_user: axum::Extension<crate::models::auth::AuthContext>,
+) -> Result<impl IntoResponse, AppError> {
+    let entries = DictionaryEntry::list(&state.db_pool, DictionaryScope::User(auth_user.user_id)).await?;
+
+    Ok(Json(serde_json::json!({
+        "success": true,
+        "data": DictionaryEntriesResponse {
+            total: entries.len(),
+            entries,
+        }
+    })))
+}
+
+/// Add terms to the current user's personal dictionary
+#[utoipa::path(
+    post,
+    path = "/dictionary",
+    request_body = AddTermsRequest,
+    responses(
+        (status = 200, description = "Outcome per term", body = ApiResponse<AddTermsResponse>),
+    )
+)]
+pub async fn add_personal_terms(
+    State(state): State<AppState>,
+    auth_user: axum::Extension<crate::models::auth::AuthContext>,
+    Json(request): Json<AddTermsRequest>,
+) -> Result<impl IntoResponse, AppError> {
+    let outcomes = DictionaryEntry::add_terms(
+        &state.db_pool,
+        DictionaryScope::User(auth_user.user_id),
+        request.terms,
+        auth_user.user_id,
+    )
+    .await?;
+
+    Ok(outcomes_response(outcomes))
+}
+
+/// Remove an entry from the current user's personal dictionary
+#[utoipa::path(
+    delete,
+    path = "/dictionary/{entry_id}",
+    params(("entry_id" = Uuid, Path, description = "Dictionary entry ID")),
+    responses(
+        (status = 204, description = "Entry removed"),
+        (status = 404, description = "No such entry", body = ErrorResponse),
+    )
+)]
+pub async fn remove_personal_term(
+    State(state): State<AppState>,
+    Path(entry_id): Path<Uuid>,
+    auth_user: axum::Extension<crate::models::auth::AuthContext>,
+) -> Result<impl IntoResponse, AppError> {
+    DictionaryEntry::remove(&state.db_pool, DictionaryScope::User(auth_user.user_id), entry_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound {
+            entity: "Dictionary entry".to_string(),
+            id: entry_id.to_string(),
+        })?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Collaborators and above may add project terms
+async fn add_to_project(
+    state: &AppState,
+    project_id: Uuid,
+    user_id: Uuid,
+    terms: Vec<NewTerm>,
+) -> Result<impl IntoResponse, AppError> {
+    require_role(state, project_id, user_id, UserRole::Collaborator).await?;
+
+    let outcomes =
+        DictionaryEntry::add_terms(&state.db_pool, DictionaryScope::Project(project_id), terms, user_id)
+            .await?;
+
+    let added: Vec<&str> = outcomes
+        .iter()
+        .filter_map(|outcome| match outcome {
+            TermOutcome::Added { term, .. } => Some(term.as_str()),
+            _ => None,
+        })
+        .collect();
+
+    if !added.is_empty() {
+        ProjectActivity::log_later(
+            &state.db_resilience,
+            project_id,
+            user_id,
+            "dictionary_terms_added",
+            "dictionary",
+            None,
+            Some(added.join(", ")),
+        );
+    }
+
+    Ok(outcomes_response(outcomes))
+}
+
+fn outcomes_response(outcomes: Vec<TermOutcome>) -> Json<serde_json::Value> {
+    let count = |predicate: fn(&TermOutcome) -> bool| outcomes.iter().filter(|o| predicate(o)).count();
+    let summary = AddTermsSummary {
+        added: count(|o| matches!(o, TermOutcome::Added { .. })),
+        existing: count(|o| matches!(o, TermOutcome::Exists { .. })),
+        invalid: count(|o| matches!(o, TermOutcome::Invalid { .. })),
+        limit_reached: count(|o| matches!(o, TermOutcome::LimitReached { .. })),
+    };
+
+    Json(serde_json::json!({
+        "success": true,
+        "data": AddTermsResponse {
+            results: outcomes,
+            summary,
+        }
+    }))
+}
+
+/// Fail unless the user holds at least `required` on the project
+async fn require_role(
+    state: &AppState,
+    project_id: Uuid,
+    user_id: Uuid,
+    required: UserRole,
+) -> Result<UserRole, AppError> {
+    let role = Project::member_role(&state.db_pool, project_id, user_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound {
+            entity: "Project".to_string(),
+            id: project_id.to_string(),
+        })?;
+
+    if !role.at_least(required) {
+        return Err(AppError::Authorization(format!(
+            "Changing the project dictionary requires the {:?} role or higher",
+            required
+        )));
+    }
+
+    Ok(role)
+}
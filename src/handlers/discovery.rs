@@ -0,0 +1,157 @@
+//! Public project discovery handlers
+//!
+//! These are open to logged-out visitors and say so to caches: responses are
+//! public and may be reused for a short while.
+
+use crate::error::AppError;
+use crate::models::discovery::{DiscoveredProject, PublicCollection, TrendingProject};
+use crate::models::{ApiResponse, PaginatedResponse, PaginationInfo, PaginationParams};
+use crate::server::AppState;
+use axum::{
+    extract::{Query, State},
+    http::{header, HeaderValue},
+    response::IntoResponse,
+    Json,
+};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+/// Cache lifetime of trending projects, a fraction of how often they are recomputed
+const TRENDING_CACHE_CONTROL: &str = "public, max-age=120";
+
+/// Cache lifetime of recently updated projects
+const RECENT_CACHE_CONTROL: &str = "public, max-age=30";
+
+/// Cache lifetime of collections
+const COLLECTIONS_CACHE_CONTROL: &str = "public, max-age=300";
+
+/// Trending projects response
+#[derive(Debug, Serialize, ToSchema)]
+pub struct TrendingProjectsResponse {
+    pub projects: Vec<TrendingProject>,
+    pub pagination: PaginationInfo,
+}
+
+/// Discovered projects response
+#[derive(Debug, Serialize, ToSchema)]
+pub struct DiscoveredProjectsResponse {
+    pub projects: Vec<DiscoveredProject>,
+    pub pagination: PaginationInfo,
+}
+
+/// Public collections response
+#[derive(Debug, Serialize, ToSchema)]
+pub struct PublicCollectionsResponse {
+    pub collections: Vec<PublicCollection>,
+    pub pagination: PaginationInfo,
+}
+
+fn cacheable(cache_control: &'static str, data: impl Serialize) -> impl IntoResponse {
+    (
+        [(header::CACHE_CONTROL, HeaderValue::from_static(cache_control))],
+        Json(serde_json::json!({
+            "success": true,
+            "data": data
+        })),
+    )
+}
+
+/// Trending public projects
+///
+/// Ranked by recent views, clones and compiles, each counting less the older
+/// it is. Scores are recomputed every few minutes.
+#[utoipa::path(
+    get,
+    path = "/trending",
+    params(PaginationParams),
+    responses(
+        (status = 200, description = "Public projects, highest score first", body = ApiResponse<TrendingProjectsResponse>),
+    ),
+    security(())
+)]
+pub async fn trending_projects(
+    State(state): State<AppState>,
+    Query(params): Query<PaginationParams>,
+) -> Result<impl IntoResponse, AppError> {
+    let (projects, total) = TrendingProject::list(&state.db_pool, &params).await?;
+    let pagination = PaginatedResponse::new(projects.clone(), &params, total as u64).pagination;
+
+    Ok(cacheable(TRENDING_CACHE_CONTROL, TrendingProjectsResponse { projects, pagination }))
+}
+
+/// Recently updated public projects
+#[utoipa::path(
+    get,
+    path = "/recent",
+    params(PaginationParams),
+    responses(
+        (status = 200, description = "Public projects, latest change to settings or files first", body = ApiResponse<DiscoveredProjectsResponse>),
+    ),
+    security(())
+)]
+pub async fn recent_projects(
+    State(state): State<AppState>,
+    Query(params): Query<PaginationParams>,
+) -> Result<impl IntoResponse, AppError> {
+    let (projects, total) = DiscoveredProject::recent(&state.db_pool, &params).await?;
+    let pagination = PaginatedResponse::new(projects.clone(), &params, total as u64).pagination;
+
+    Ok(cacheable(RECENT_CACHE_CONTROL, DiscoveredProjectsResponse { projects, pagination }))
+}
+
+/// Collections of public projects curated by administrators
+///
+/// Projects of a collection that are no longer public are left out.
+#[utoipa::path(
+    get,
+    path = "/collections",
+    params(PaginationParams),
+    responses(
+        (status = 200, description = "Collections in their curated order, each with its projects", body = ApiResponse<PublicCollectionsResponse>),
+    ),
+    security(())
+)]
+pub async fn list_collections(
+    State(state): State<AppState>,
+    Query(params): Query<PaginationParams>,
+) -> Result<impl IntoResponse, AppError> {
+    let (collections, total) = PublicCollection::list(&state.db_pool, &params).await?;
+    let pagination = PaginatedResponse::new(collections.clone(), &params, total as u64).pagination;
+
+    Ok(cacheable(COLLECTIONS_CACHE_CONTROL, PublicCollectionsResponse { collections, pagination }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{create_test_project, create_test_user, test_state, TestDb};
+    use axum::body::Body;
+    use axum::http::{Request, StatusCode};
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn test_discovery_is_public_and_cacheable() {
+        let Some(db) = TestDb::start().await else { return };
+        let state = test_state(&db).await;
+        let owner = create_test_user(&db.pool).await;
+        let public = create_test_project(&db.pool, &owner, true).await;
+        let private = create_test_project(&db.pool, &owner, false).await;
+
+        let app: axum::Router = crate::server::create_router(&state).with_state(state.clone());
+        let response = app
+            .oneshot(Request::get("/api/v1/discover/recent?limit=100").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CACHE_CONTROL], RECENT_CACHE_CONTROL);
+
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        let projects = body["data"]["projects"].as_array().unwrap();
+        let listed = projects.iter().find(|p| p["id"] == public.id.to_string()).unwrap();
+        assert_eq!(listed["owner"]["username"], owner.username.as_str());
+        // Only the public profile of the owner is shown
+        assert!(listed["owner"].get("email").is_none() && listed.get("settings").is_none());
+        assert!(projects.iter().all(|p| p["id"] != private.id.to_string()));
+    }
+}
@@ -1,56 +1,168 @@
 //! File request handlers
 
-use crate::error::AppError;
-use crate::models::file::{File, CreateFile, UpdateFile, FileWithDetails, FileNode, FileSearchResult};
-use crate::models::{PaginationParams, ContentType, StorageStrategy};
+use crate::error::{AppError, ErrorBody, ErrorResponse, LimitExceededResponse};
+use crate::models::file::{File, CreateFile, UpdateFile, FileVersion, FileWithDetails, FileNode, FileSearchResult};
+use crate::models::detail_fields::{FieldsParams, FileFields};
+use crate::models::{ApiResponse, PaginationParams, ContentType, StorageStrategy};
+use crate::models::{autocomplete, file_tree};
+use crate::models::project::Project;
+use crate::models::project_freeze::ProjectFreeze;
+use crate::models::project_limits::ProjectLimits;
+use crate::models::upload::{CreateUploadSession, UploadSession};
+use crate::models::file_scan::{self, FileScanStatus};
+use crate::models::file_blame::{BlameParams, FileBlame};
+use crate::models::file_share::{CreateFileShareLink, CreatedFileShareLink, FileShareLink};
+use crate::models::formatter::{self, FormatProposal, LineRange};
+use crate::models::integrity::ReadPath;
+use crate::models::validation::FileName;
+use crate::models::UserRole;
+use crate::openapi::MessageResponse;
+use crate::scanner::ScanVerdict;
 use axum::{
-    extract::{Path, Query, State, Multipart},
+    body::Body,
+    extract::{multipart::Field, Path, Query, State, Multipart},
     http::{StatusCode, header, HeaderMap, HeaderValue},
-    response::IntoResponse,
+    response::{IntoResponse, Response},
     Json,
 };
 use crate::server::AppState;
 use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
 use uuid::Uuid;
-use std::path::Path as StdPath;
+use std::path::{Path as StdPath, PathBuf};
 
 /// File creation response
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, ToSchema)]
 pub struct FileResponse {
     pub file: FileWithDetails,
 }
 
+/// Saved file content response
+#[derive(Debug, Serialize, ToSchema)]
+pub struct FileContentUpdatedResponse {
+    /// The file with its versions, the new one first
+    pub file: FileWithDetails,
+    /// The version recorded for this save
+    pub version: FileVersion,
+}
+
+/// Deleted file response
+///
+/// A delta rather than the new listing: clients compare `parent` with the
+/// fingerprint of the directory they show and only reload it on a mismatch.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct FileDeletedResponse {
+    pub id: Uuid,
+    pub path: String,
+    pub permanent: bool,
+    /// The directory the file was in, after the deletion
+    pub parent: file_tree::DirectoryFingerprint,
+}
+
 /// Files list response
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, ToSchema)]
 pub struct FilesListResponse {
     pub files: Vec<FileWithDetails>,
     pub pagination: crate::models::PaginationInfo,
 }
 
 /// File content response
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, ToSchema)]
 pub struct FileContentResponse {
     pub file: FileWithDetails,
     pub content: String,
 }
 
+/// Share links of a file
+#[derive(Debug, Serialize, ToSchema)]
+pub struct FileShareLinksResponse {
+    pub links: Vec<FileShareLink>,
+}
+
 /// File upload response
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, ToSchema)]
 pub struct FileUploadResponse {
     pub file: FileWithDetails,
     pub url: Option<String>,
 }
 
 /// File tree response
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, ToSchema)]
 pub struct FileTreeResponse {
     pub tree: Vec<FileNode>,
     pub total_files: i64,
     pub total_size: i64,
 }
 
+/// Error body when a project is too large for the full tree
+#[derive(Debug, Serialize, ToSchema)]
+pub struct TreeTooLargeResponse {
+    pub success: bool,
+    pub error: ErrorBody,
+    pub total_files: i64,
+    pub max_files: u64,
+    /// How to load the tree instead
+    pub hint: String,
+}
+
+/// Largest upload kept inline as editable text
+const INLINE_UPLOAD_MAX_SIZE: u64 = 2 * 1024 * 1024;
+
+/// File content update request
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct UpdateFileContentRequest {
+    pub content: String,
+}
+
+/// Request to format a whole file
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct FormatFileRequest {
+    /// Save the formatted content as a new version instead of only proposing it
+    #[serde(default)]
+    pub apply: bool,
+}
+
+/// Request to format some lines of a file
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct FormatSelectionRequest {
+    /// First line to format, 1-based
+    pub start_line: usize,
+    /// Last line to format, inclusive
+    pub end_line: usize,
+    #[serde(default)]
+    pub apply: bool,
+}
+
+/// Formatting result
+#[derive(Debug, Serialize, ToSchema)]
+pub struct FormatFileResponse {
+    pub proposal: FormatProposal,
+    /// The saved file, when the proposal was applied
+    pub file: Option<FileWithDetails>,
+}
+
+/// Multipart form of a file upload
+#[derive(Debug, ToSchema)]
+pub struct UploadFileForm {
+    /// Stored at the project root under its own file name
+    #[schema(value_type = String, format = Binary)]
+    pub file: Vec<u8>,
+}
+
+/// Directory listing parameters
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct FileTreeParams {
+    pub path: Option<String>,
+    pub depth: Option<u32>,
+    /// Build the whole tree at once; only allowed for small projects
+    #[serde(default)]
+    pub full: bool,
+}
+
 /// File search parameters
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct FileSearchParams {
     pub query: Option<String>,
     pub content_type: Option<ContentType>,
@@ -59,31 +171,38 @@ pub struct FileSearchParams {
 }
 
 /// List files accessible to the user
+///
+/// `fields` selects the sections of each file: `file`, `modified_by` and `versions`.
+#[utoipa::path(
+    get,
+    path = "/",
+    params(FileSearchParams, PaginationParams, FieldsParams),
+    responses(
+        (status = 200, description = "Files of the project", body = ApiResponse<FilesListResponse>),
+        (status = 400, description = "Missing project ID, unknown field or unknown sort column", body = ErrorResponse),
+    )
+)]
 pub async fn list_files(
     State(state): State<AppState>,
     Query(params): Query<FileSearchParams>,
     Query(pagination_params): Query<PaginationParams>,
+    Query(fields): Query<FieldsParams>,
     auth_user: axum::Extension<crate::models::auth::AuthContext>,
 ) -> Result<impl IntoResponse, AppError> {
     let project_id = params.project_id.ok_or_else(|| AppError::Validation(
         "Project ID is required".to_string(),
     ))?;
+    let fields = FileFields::parse(fields.fields.as_deref())?;
 
     let files = File::list_for_project(&state.db_pool, project_id, auth_user.user_id, &pagination_params).await?;
-
-    // Get file details for each file
-    let mut files_with_details = Vec::new();
-    for file in files {
-        let file_details = File::get_with_details(&state.db_pool, file.id, auth_user.user_id).await?;
-        files_with_details.push(file_details);
-    }
+    let files_with_details = File::with_details(&state.db_pool, files, fields).await?;
 
     // Get total count for pagination
     let total_count = sqlx::query_scalar::<_, i64>(
         r#"
         SELECT COUNT(*) FROM files f
         JOIN projects p ON f.project_id = p.id
-        WHERE f.project_id = $1 AND f.is_deleted = false AND (
+        WHERE f.project_id = $1 AND f.is_deleted = false AND p.purging_at IS NULL AND (
             p.owner_id = $2 OR
             p.id IN (
                 SELECT project_id FROM project_collaborators
@@ -117,6 +236,18 @@ pub async fn list_files(
 }
 
 /// Create a new file
+#[utoipa::path(
+    post,
+    path = "/",
+    request_body = CreateFile,
+    responses(
+        (status = 201, description = "File created", body = ApiResponse<FileResponse>),
+        (status = 400, description = "Empty name or relative path", body = ErrorResponse),
+        (status = 409, description = "A file with this path already exists", body = ErrorResponse),
+        (status = 422, description = "The file goes over a limit of its project", body = LimitExceededResponse),
+        (status = 423, description = "Project is frozen", body = ErrorResponse),
+    )
+)]
 pub async fn create_file(
     State(state): State<AppState>,
     auth_user: axum::Extension<crate::models::auth::AuthContext>,
@@ -125,23 +256,16 @@ pub async fn create_file(
     // Extract project_id from the path (assuming it's provided as a query parameter or path)
     let project_id = auth_user.user_id; // TODO: This should come from the request
 
-    // Validate file path and name
-    if payload.name.is_empty() {
-        return Err(AppError::Validation("File name cannot be empty".to_string()));
-    }
-
+    // Validate file path; the name was checked when the request was read
     if !payload.path.starts_with('/') {
         return Err(AppError::Validation("File path must be absolute".to_string()));
     }
 
-    // Check if file already exists in project
-    if let Some(_) = File::find_by_path(&state.db_pool, project_id, &payload.path, auth_user.user_id).await? {
-        return Err(AppError::Conflict(
-            "File with this path already exists".to_string(),
-        ));
-    }
-
-    let file = File::create(&state.db_pool, project_id, payload, auth_user.user_id).await?;
+    // A path taken by a live file is a conflict, decided by the insert
+    let limits = ProjectLimits::from_config(&state.config.features.file_storage);
+    let file = File::create(&state.db_pool, &limits, project_id, payload, auth_user.user_id).await?;
+    state.autocomplete_cache.invalidate(file.project_id);
+    state.include_graph_cache.file_changed(&file);
     let file_with_details = File::get_with_details(&state.db_pool, file.id, auth_user.user_id).await?;
 
     let response = FileResponse {
@@ -158,12 +282,26 @@ pub async fn create_file(
 }
 
 /// Get file details
+///
+/// `fields` selects the sections to include: `file`, `modified_by` and `versions`.
+#[utoipa::path(
+    get,
+    path = "/{id}",
+    params(("id" = Uuid, Path, description = "File ID"), FieldsParams),
+    responses(
+        (status = 200, description = "File with its versions", body = ApiResponse<FileResponse>),
+        (status = 400, description = "Unknown field", body = ErrorResponse),
+        (status = 404, description = "File not found", body = ErrorResponse),
+    )
+)]
 pub async fn get_file(
     State(state): State<AppState>,
     Path(file_id): Path<Uuid>,
+    Query(fields): Query<FieldsParams>,
     auth_user: axum::Extension<crate::models::auth::AuthContext>,
 ) -> Result<impl IntoResponse, AppError> {
-    let file_with_details = File::get_with_details(&state.db_pool, file_id, auth_user.user_id).await?;
+    let fields = FileFields::parse(fields.fields.as_deref())?;
+    let file_with_details = File::get_with_fields(&state.db_pool, file_id, auth_user.user_id, fields).await?;
 
     let response = FileResponse {
         file: file_with_details,
@@ -176,6 +314,19 @@ pub async fn get_file(
 }
 
 /// Update file
+#[utoipa::path(
+    put,
+    path = "/{id}",
+    params(("id" = Uuid, Path, description = "File ID")),
+    request_body = UpdateFile,
+    responses(
+        (status = 200, description = "Updated file", body = ApiResponse<FileResponse>),
+        (status = 404, description = "File not found", body = ErrorResponse),
+        (status = 409, description = "A file with the new path already exists", body = ErrorResponse),
+        (status = 422, description = "The new path goes over the depth or length limit", body = LimitExceededResponse),
+        (status = 423, description = "Project is frozen", body = ErrorResponse),
+    )
+)]
 pub async fn update_file(
     State(state): State<AppState>,
     Path(file_id): Path<Uuid>,
@@ -190,19 +341,26 @@ pub async fn update_file(
             id: file_id.to_string(),
         })?;
 
+    let changes_index = payload.name.is_some()
+        || payload.path.is_some()
+        || payload.content_type.is_some()
+        || (payload.content.is_some() && autocomplete::indexes_content(current_file.content_type));
+
     // Update file fields
     let mut updated_file = current_file.clone();
 
-    if let Some(name) = payload.name {
-        updated_file.name = name;
-    }
-
-    if let Some(path) = payload.path {
-        updated_file.path = path;
+    if payload.name.is_some() || payload.path.is_some() {
+        let name = payload.name.map(String::from).unwrap_or_else(|| current_file.name.clone());
+        let path = payload.path.unwrap_or_else(|| current_file.path.clone());
+        if !path.starts_with('/') && path != current_file.path {
+            return Err(AppError::Validation("File path must be absolute".to_string()));
+        }
+        let limits = ProjectLimits::from_config(&state.config.features.file_storage);
+        updated_file = current_file.move_to(&state.db_pool, &limits, &name, &path, auth_user.user_id).await?;
     }
 
     if let Some(content) = payload.content {
-        updated_file = updated_file.update_content(&state.db_pool, content, auth_user.user_id).await?;
+        updated_file = updated_file.update_content(&state.db_pool, &state.config.features.file_storage.local_path, content, auth_user.user_id).await?;
     }
 
     if let Some(content_type) = payload.content_type {
@@ -213,6 +371,11 @@ pub async fn update_file(
         updated_file.is_main = is_main;
     }
 
+    if changes_index {
+        state.autocomplete_cache.invalidate(current_file.project_id);
+    }
+    state.include_graph_cache.file_changed(&updated_file);
+
     let file_with_details = File::get_with_details(&state.db_pool, updated_file.id, auth_user.user_id).await?;
 
     let response = FileResponse {
@@ -225,10 +388,30 @@ pub async fn update_file(
     })))
 }
 
+/// File deletion parameters
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct DeleteFileParams {
+    /// Remove the file and its history permanently instead of moving it to the trash
+    pub permanent: Option<bool>,
+}
+
 /// Delete file
+#[utoipa::path(
+    delete,
+    path = "/{id}",
+    params(("id" = Uuid, Path, description = "File ID"), DeleteFileParams),
+    responses(
+        (status = 200, description = "File deleted", body = ApiResponse<FileDeletedResponse>),
+        (status = 403, description = "Only the owner can delete permanently", body = ErrorResponse),
+        (status = 404, description = "File not found", body = ErrorResponse),
+        (status = 423, description = "Project is frozen", body = ErrorResponse),
+    )
+)]
 pub async fn delete_file(
     State(state): State<AppState>,
     Path(file_id): Path<Uuid>,
+    Query(params): Query<DeleteFileParams>,
     auth_user: axum::Extension<crate::models::auth::AuthContext>,
 ) -> Result<impl IntoResponse, AppError> {
     // Get file
@@ -239,16 +422,58 @@ pub async fn delete_file(
             id: file_id.to_string(),
         })?;
 
-    // Soft delete file
-    file.soft_delete(&state.db_pool, auth_user.user_id).await?;
+    let permanent = params.permanent.unwrap_or(false);
+    if permanent {
+        if !Project::is_owner(&state.db_pool, file.project_id, auth_user.user_id).await? {
+            return Err(AppError::Authorization(
+                "Only the project owner can permanently delete files".to_string(),
+            ));
+        }
+
+        file.purge(&state.db_pool, &state.config.features.file_storage.local_path, auth_user.user_id).await?;
+    } else {
+        // Soft delete file
+        file.soft_delete(&state.db_pool, auth_user.user_id).await?;
+    }
+    state.autocomplete_cache.invalidate(file.project_id);
+    state.include_graph_cache.file_removed(file.project_id, file.id);
+
+    let parent = file_tree::directory_fingerprint(
+        &state.db_pool,
+        file.project_id,
+        &file_tree::parent_directory(&file.path),
+    )
+    .await?;
 
     Ok(Json(serde_json::json!({
         "success": true,
-        "message": "File deleted successfully"
+        "message": "File deleted successfully",
+        "data": FileDeletedResponse {
+            id: file.id,
+            path: file.path,
+            permanent,
+            parent,
+        }
     })))
 }
 
 /// Get file content
+///
+/// Content is read like a download, from the file's row or the blob store.
+/// Images and other content that is not UTF-8 text are refused; fetch those
+/// from `/files/{id}/download`.
+#[utoipa::path(
+    get,
+    path = "/{id}/content",
+    params(("id" = Uuid, Path, description = "File ID")),
+    responses(
+        (status = 200, description = "File and its content", body = ApiResponse<FileContentResponse>),
+        (status = 400, description = "Image or other binary file", body = ErrorResponse),
+        (status = 403, description = "File is quarantined", body = ErrorResponse),
+        (status = 404, description = "File not found", body = ErrorResponse),
+        (status = 500, description = "Stored content does not match its hash", body = ErrorResponse),
+    )
+)]
 pub async fn get_file_content(
     State(state): State<AppState>,
     Path(file_id): Path<Uuid>,
@@ -260,10 +485,19 @@ pub async fn get_file_content(
             entity: "File".to_string(),
             id: file_id.to_string(),
         })?;
+    file.ensure_downloadable()?;
 
-    // For now, return empty content - in a real implementation, this would
-    // fetch the content from storage based on the storage strategy
-    let content = String::new(); // TODO: Implement content retrieval
+    let binary = || {
+        AppError::Validation(format!(
+            "{} is not a text file, download it from /files/{}/download instead",
+            file.name, file.id
+        ))
+    };
+    if file.content_type == ContentType::Image {
+        return Err(binary());
+    }
+    let bytes = file.read_verified(&state.db_pool, &state.config.features.file_storage, ReadPath::Download).await?;
+    let content = String::from_utf8(bytes).map_err(|_| binary())?;
 
     let file_with_details = File::get_with_details(&state.db_pool, file_id, auth_user.user_id).await?;
 
@@ -278,7 +512,64 @@ pub async fn get_file_content(
     })))
 }
 
+/// Get the blame of a file
+///
+/// Tells for each line of the current content the version that introduced
+/// it, with its author and time, following the file's history back through
+/// at most `FILE_BLAME_MAX_VERSIONS` versions. Lines older than that are
+/// marked `earlier`. `range` limits the blame to some lines, and
+/// `ignore_whitespace` lets changes in spacing alone keep a line's origin.
+#[utoipa::path(
+    get,
+    path = "/{id}/blame",
+    params(("id" = Uuid, Path, description = "File ID"), BlameParams),
+    responses(
+        (status = 200, description = "Origin of each line", body = ApiResponse<FileBlame>),
+        (status = 400, description = "Not a text file, or lines outside the file", body = ErrorResponse),
+        (status = 404, description = "File not found", body = ErrorResponse),
+    )
+)]
+pub async fn get_file_blame(
+    State(state): State<AppState>,
+    Path(file_id): Path<Uuid>,
+    Query(params): Query<BlameParams>,
+    auth_user: axum::Extension<crate::models::auth::AuthContext>,
+) -> Result<impl IntoResponse, AppError> {
+    let file = File::find_by_id(&state.db_pool, file_id, auth_user.user_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound {
+            entity: "File".to_string(),
+            id: file_id.to_string(),
+        })?;
+    if !matches!(file.content_type, ContentType::Latex | ContentType::Bibliography | ContentType::Typst)
+        || file.storage_strategy == StorageStrategy::External
+    {
+        return Err(AppError::Validation("Only text files have a blame".to_string()));
+    }
+
+    let blame = FileBlame::load(&state, &file, &params).await?;
+
+    Ok(Json(serde_json::json!({
+        "success": true,
+        "data": blame
+    })))
+}
+
 /// Update file content
+///
+/// Every save is recorded as a new version of the file.
+#[utoipa::path(
+    put,
+    path = "/{id}/content",
+    params(("id" = Uuid, Path, description = "File ID")),
+    request_body = UpdateFileContentRequest,
+    responses(
+        (status = 200, description = "Updated file and its new version", body = ApiResponse<FileContentUpdatedResponse>),
+        (status = 400, description = "Missing content", body = ErrorResponse),
+        (status = 404, description = "File not found", body = ErrorResponse),
+        (status = 423, description = "Project is frozen", body = ErrorResponse),
+    )
+)]
 pub async fn update_file_content(
     State(state): State<AppState>,
     Path(file_id): Path<Uuid>,
@@ -298,11 +589,26 @@ pub async fn update_file_content(
         })?;
 
     // Update file content
-    let updated_file = current_file.update_content(&state.db_pool, content.to_string(), auth_user.user_id).await?;
+    let updated_file = current_file.update_content(&state.db_pool, &state.config.features.file_storage.local_path, content.to_string(), auth_user.user_id).await?;
+    if autocomplete::indexes_content(updated_file.content_type) {
+        state.autocomplete_cache.invalidate(updated_file.project_id);
+    }
+    state.include_graph_cache.file_changed(&updated_file);
+    let version = FileVersion::create(
+        &state.db_pool,
+        updated_file.id,
+        updated_file.version,
+        content,
+        auth_user.user_id,
+        "Content updated",
+    )
+    .await?;
+    state.blame_cache.invalidate(updated_file.id);
     let file_with_details = File::get_with_details(&state.db_pool, updated_file.id, auth_user.user_id).await?;
 
-    let response = FileResponse {
+    let response = FileContentUpdatedResponse {
         file: file_with_details,
+        version,
     };
 
     Ok(Json(serde_json::json!({
@@ -311,7 +617,133 @@ pub async fn update_file_content(
     })))
 }
 
+/// Format a LaTeX file
+///
+/// Formats with latexindent when the server has it configured and with a
+/// conservative built-in formatter otherwise, using the project's formatting
+/// settings. Verbatim-like environments are never changed. The result is a
+/// proposal; with `apply` a changed file is also saved as a new version.
+#[utoipa::path(
+    post,
+    path = "/{id}/format",
+    params(("id" = Uuid, Path, description = "File ID")),
+    request_body = FormatFileRequest,
+    responses(
+        (status = 200, description = "Proposed formatting", body = ApiResponse<FormatFileResponse>),
+        (status = 400, description = "Not a LaTeX text file", body = ErrorResponse),
+        (status = 404, description = "File not found", body = ErrorResponse),
+        (status = 423, description = "Project is frozen", body = ErrorResponse),
+    )
+)]
+pub async fn format_file(
+    State(state): State<AppState>,
+    Path(file_id): Path<Uuid>,
+    auth_user: axum::Extension<crate::models::auth::AuthContext>,
+    Json(payload): Json<FormatFileRequest>,
+) -> Result<impl IntoResponse, AppError> {
+    let response = format_content(&state, file_id, &auth_user, None, payload.apply).await?;
+
+    Ok(Json(serde_json::json!({
+        "success": true,
+        "data": response
+    })))
+}
+
+/// Format lines of a LaTeX file
+///
+/// Like formatting the whole file, but only the given lines change.
+#[utoipa::path(
+    post,
+    path = "/{id}/format/selection",
+    params(("id" = Uuid, Path, description = "File ID")),
+    request_body = FormatSelectionRequest,
+    responses(
+        (status = 200, description = "Proposed formatting", body = ApiResponse<FormatFileResponse>),
+        (status = 400, description = "Not a LaTeX text file, or lines outside the file", body = ErrorResponse),
+        (status = 404, description = "File not found", body = ErrorResponse),
+        (status = 423, description = "Project is frozen", body = ErrorResponse),
+    )
+)]
+pub async fn format_selection(
+    State(state): State<AppState>,
+    Path(file_id): Path<Uuid>,
+    auth_user: axum::Extension<crate::models::auth::AuthContext>,
+    Json(payload): Json<FormatSelectionRequest>,
+) -> Result<impl IntoResponse, AppError> {
+    let range = (payload.start_line, payload.end_line);
+    let response = format_content(&state, file_id, &auth_user, Some(range), payload.apply).await?;
+
+    Ok(Json(serde_json::json!({
+        "success": true,
+        "data": response
+    })))
+}
+
+/// Propose formatting for a file, or lines `range` of it, and save it on `apply`
+async fn format_content(
+    state: &AppState,
+    file_id: Uuid,
+    auth_user: &crate::models::auth::AuthContext,
+    range: Option<(usize, usize)>,
+    apply: bool,
+) -> Result<FormatFileResponse, AppError> {
+    let file = File::find_by_id(&state.db_pool, file_id, auth_user.user_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound {
+            entity: "File".to_string(),
+            id: file_id.to_string(),
+        })?;
+    if file.content_type != ContentType::Latex {
+        return Err(AppError::Validation("Only LaTeX files can be formatted".to_string()));
+    }
+
+    let storage_root = &state.config.features.file_storage.local_path;
+    let content = String::from_utf8(file.read_bytes(storage_root).await?)
+        .map_err(|_| AppError::Validation("File is not valid UTF-8 text".to_string()))?;
+    let range = range
+        .map(|(start, end)| LineRange::new(&content, start, end))
+        .transpose()?;
+    let project = Project::find_by_id(&state.db_pool, file.project_id, auth_user.user_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound {
+            entity: "Project".to_string(),
+            id: file.project_id.to_string(),
+        })?;
+
+    let proposal = formatter::propose(
+        &content,
+        &project.settings.formatting,
+        range,
+        state.config.latex.latexindent_path.as_deref(),
+        &state.config.latex.temp_dir,
+    )
+    .await;
+
+    let file = if apply && proposal.changed {
+        let updated_file = file.update_content(&state.db_pool, storage_root, proposal.content.clone(), auth_user.user_id).await?;
+        state.autocomplete_cache.invalidate(updated_file.project_id);
+        state.include_graph_cache.file_changed(&updated_file);
+        Some(File::get_with_details(&state.db_pool, updated_file.id, auth_user.user_id).await?)
+    } else {
+        None
+    };
+
+    Ok(FormatFileResponse { proposal, file })
+}
+
 /// Download file
+#[utoipa::path(
+    get,
+    path = "/{id}/download",
+    params(("id" = Uuid, Path, description = "File ID")),
+    responses(
+        (status = 200, description = "File content", content_type = "application/octet-stream", body = Vec<u8>,
+            headers(("Content-Disposition" = String, description = "Attachment file name"))),
+        (status = 403, description = "File is quarantined", body = ErrorResponse),
+        (status = 404, description = "File not found", body = ErrorResponse),
+        (status = 500, description = "Stored content does not match its hash", body = ErrorResponse),
+    )
+)]
 pub async fn download_file(
     State(state): State<AppState>,
     Path(file_id): Path<Uuid>,
@@ -324,8 +756,8 @@ pub async fn download_file(
             id: file_id.to_string(),
         })?;
 
-    // Get file content
-    let content = String::new(); // TODO: Implement content retrieval from storage
+    file.ensure_downloadable()?;
+    let content = file.read_verified(&state.db_pool, &state.config.features.file_storage, ReadPath::Download).await?;
 
     let mut headers = HeaderMap::new();
     headers.insert(header::CONTENT_TYPE, HeaderValue::from_static("application/octet-stream"));
@@ -338,7 +770,135 @@ pub async fn download_file(
     Ok((headers, content))
 }
 
+/// The file `file_id`, when `user_id` may change its project
+async fn find_writable(state: &AppState, file_id: Uuid, user_id: Uuid) -> Result<File, AppError> {
+    let file = File::find_by_id(&state.db_pool, file_id, user_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound {
+            entity: "File".to_string(),
+            id: file_id.to_string(),
+        })?;
+
+    let role = Project::member_role(&state.db_pool, file.project_id, user_id).await?;
+    if !role.is_some_and(|role| role.at_least(UserRole::Collaborator)) {
+        return Err(AppError::Authorization(
+            "Sharing files requires write access to the project".to_string(),
+        ));
+    }
+
+    Ok(file)
+}
+
+/// Create a share link for a file
+///
+/// The link shows this file alone, read-only and without an account: its
+/// content, or for LaTeX files a standalone compile of it. The token is
+/// returned once.
+#[utoipa::path(
+    post,
+    path = "/{id}/share-links",
+    params(("id" = Uuid, Path, description = "File ID")),
+    request_body = CreateFileShareLink,
+    responses(
+        (status = 201, description = "Share link created", body = ApiResponse<CreatedFileShareLink>),
+        (status = 400, description = "Expiry in the past, or a PDF link for a file that is not LaTeX", body = ErrorResponse),
+        (status = 403, description = "No write access to the project, or the file is quarantined", body = ErrorResponse),
+        (status = 404, description = "File not found", body = ErrorResponse),
+    )
+)]
+pub async fn create_share_link(
+    State(state): State<AppState>,
+    Path(file_id): Path<Uuid>,
+    auth_user: axum::Extension<crate::models::auth::AuthContext>,
+    Json(payload): Json<CreateFileShareLink>,
+) -> Result<impl IntoResponse, AppError> {
+    let file = find_writable(&state, file_id, auth_user.user_id).await?;
+    let created = FileShareLink::create(&state.db_pool, &file, auth_user.user_id, payload).await?;
+
+    Ok((
+        StatusCode::CREATED,
+        Json(serde_json::json!({
+            "success": true,
+            "data": created
+        })),
+    ))
+}
+
+/// List the share links of a file
+#[utoipa::path(
+    get,
+    path = "/{id}/share-links",
+    params(("id" = Uuid, Path, description = "File ID")),
+    responses(
+        (status = 200, description = "Share links, newest first", body = ApiResponse<FileShareLinksResponse>),
+        (status = 403, description = "No write access to the project", body = ErrorResponse),
+        (status = 404, description = "File not found", body = ErrorResponse),
+    )
+)]
+pub async fn list_share_links(
+    State(state): State<AppState>,
+    Path(file_id): Path<Uuid>,
+    auth_user: axum::Extension<crate::models::auth::AuthContext>,
+) -> Result<impl IntoResponse, AppError> {
+    let file = find_writable(&state, file_id, auth_user.user_id).await?;
+    let links = FileShareLink::list(&state.db_pool, file.id).await?;
+
+    Ok(Json(serde_json::json!({
+        "success": true,
+        "data": FileShareLinksResponse { links }
+    })))
+}
+
+/// Revoke a share link of a file
+#[utoipa::path(
+    delete,
+    path = "/{id}/share-links/{link_id}",
+    params(
+        ("id" = Uuid, Path, description = "File ID"),
+        ("link_id" = Uuid, Path, description = "Share link ID"),
+    ),
+    responses(
+        (status = 200, description = "Share link revoked", body = MessageResponse),
+        (status = 403, description = "No write access to the project", body = ErrorResponse),
+        (status = 404, description = "File or share link not found", body = ErrorResponse),
+    )
+)]
+pub async fn revoke_share_link(
+    State(state): State<AppState>,
+    Path((file_id, link_id)): Path<(Uuid, Uuid)>,
+    auth_user: axum::Extension<crate::models::auth::AuthContext>,
+) -> Result<impl IntoResponse, AppError> {
+    let file = find_writable(&state, file_id, auth_user.user_id).await?;
+    if !FileShareLink::revoke(&state.db_pool, file.id, link_id).await? {
+        return Err(AppError::NotFound {
+            entity: "Share link".to_string(),
+            id: link_id.to_string(),
+        });
+    }
+
+    Ok(Json(serde_json::json!({
+        "success": true,
+        "message": "Share link revoked successfully"
+    })))
+}
+
 /// Upload file
+///
+/// The file is streamed to disk and scanned for malware before it is stored.
+#[utoipa::path(
+    post,
+    path = "/upload",
+    params(FileSearchParams),
+    request_body(content = UploadFileForm, content_type = "multipart/form-data"),
+    responses(
+        (status = 201, description = "File created from the upload", body = ApiResponse<FileUploadResponse>),
+        (status = 400, description = "Missing project ID or file, or the file is too large", body = ErrorResponse),
+        (status = 409, description = "A file with this path already exists", body = ErrorResponse),
+        (status = 422, description = "Malware was found in the file, or it goes over a limit of its project", body = ErrorResponse),
+        (status = 423, description = "Project is frozen", body = ErrorResponse),
+        (status = 502, description = "The malware scanner could not be reached", body = ErrorResponse),
+    )
+)]
 pub async fn upload_file(
     State(state): State<AppState>,
     Query(params): Query<FileSearchParams>,
@@ -349,57 +909,82 @@ pub async fn upload_file(
         "Project ID is required".to_string(),
     ))?;
 
-    while let Some(field) = multipart.next_field().await
+    let config = state.config.as_ref();
+    if config.features.file_storage.type_ != "local" {
+        return Err(AppError::Storage("Unsupported storage type".to_string()));
+    }
+    let limits = ProjectLimits::from_config(&config.features.file_storage);
+
+    while let Some(mut field) = multipart.next_field().await
         .map_err(|e| AppError::Validation(format!("Failed to read multipart field: {}", e)))?
     {
-        let name = field.name().unwrap_or("file");
         let file_name = field.file_name()
-            .ok_or_else(|| AppError::Validation("File name is required".to_string()))?
-            .to_string();
+            .ok_or_else(|| AppError::Validation("File name is required".to_string()))
+            .and_then(FileName::new)?;
+        let path = format!("/{}", file_name);
+        let content_type = content_type_for(file_name.as_str());
+        // Spares staging and scanning when the path is taken; the insert
+        // still decides between racing uploads
+        if File::find_by_path(&state.db_pool, project_id, &path, auth_user.user_id).await?.is_some() {
+            return Err(AppError::Conflict("File with this path already exists".to_string()));
+        }
 
-        let content = field.bytes()
-            .await
-            .map_err(|e| AppError::Validation(format!("Failed to read file content: {}", e)))?;
-
-        // Determine content type
-        let content_type = match StdPath::new(&file_name)
-            .extension()
-            .and_then(|ext| ext.to_str())
-        {
-            Some("tex") => ContentType::Latex,
-            Some("bib") => ContentType::Bibliography,
-            Some("png") | Some("jpg") | Some("jpeg") | Some("gif") | Some("svg") => ContentType::Image,
-            _ => ContentType::Other,
-        };
+        let staged = stage_field(&state, &mut field, config.features.file_storage.max_upload_size).await?;
+
+        let verdict = state.file_scanner.scan_path(&staged.path, staged.size).await?;
+        if let ScanVerdict::Infected { signature } = &verdict {
+            return Err(file_scan::reject_infected_upload(
+                &state,
+                project_id,
+                auth_user.user_id,
+                &path,
+                staged.size as i64,
+                signature,
+            )
+            .await);
+        }
 
-        // Create file record
-        let create_file = CreateFile {
-            name: file_name.clone(),
-            path: format!("/{}", file_name),
-            content: Some(String::from_utf8_lossy(&content).to_string()),
-            content_type: Some(content_type),
+        // Small valid UTF-8 text stays editable; everything else is kept byte for byte
+        let text = if content_type != ContentType::Image && staged.size <= INLINE_UPLOAD_MAX_SIZE {
+            let bytes = tokio::fs::read(&staged.path).await
+                .map_err(|e| AppError::Storage(format!("Failed to read upload: {}", e)))?;
+            String::from_utf8(bytes).ok()
+        } else {
+            None
         };
 
-        let file = File::create(&state.db_pool, project_id, create_file, auth_user.user_id).await?;
-        let file_with_details = File::get_with_details(&state.db_pool, file.id, auth_user.user_id).await?;
-
-        // TODO: Store file content based on storage strategy
-        let config = state.config.as_ref();
-        match config.features.file_storage.type_.as_str() {
-            "local" => {
-                // Store to local filesystem
-                let file_path = format!("{}/{}", config.features.file_storage.local_path, file.id);
-                tokio::fs::write(&file_path, &content).await
-                    .map_err(|e| AppError::Storage(format!("Failed to save file: {}", e)))?;
+        let file = match text {
+            Some(content) => {
+                let create_file = CreateFile {
+                    name: file_name,
+                    path,
+                    content: Some(content),
+                    content_type: Some(content_type),
+                };
+                File::create(&state.db_pool, &limits, project_id, create_file, auth_user.user_id).await?
             }
-            "s3" => {
-                // TODO: Implement S3 storage
-                return Err(AppError::Storage("S3 storage not implemented yet".to_string()));
+            None => {
+                File::create_from_staged(
+                    &state.db_pool,
+                    &config.features.file_storage.local_path,
+                    &limits,
+                    project_id,
+                    file_name.as_str(),
+                    &path,
+                    content_type,
+                    &staged.path,
+                    staged.size as i64,
+                    &staged.content_hash,
+                    auth_user.user_id,
+                )
+                .await?
             }
-            _ => {
-                return Err(AppError::Storage("Unsupported storage type".to_string()));
-            }
-        }
+        };
+
+        File::record_scan(&state.db_pool, file.id, FileScanStatus::accepted(&verdict), None).await?;
+        state.autocomplete_cache.invalidate(file.project_id);
+        state.include_graph_cache.file_changed(&file);
+        let file_with_details = File::get_with_details(&state.db_pool, file.id, auth_user.user_id).await?;
 
         let response = FileUploadResponse {
             file: file_with_details,
@@ -419,6 +1004,16 @@ pub async fn upload_file(
 }
 
 /// Get file tree for a project
+#[utoipa::path(
+    get,
+    path = "/tree",
+    params(FileSearchParams),
+    responses(
+        (status = 200, description = "Whole file tree of the project", body = ApiResponse<FileTreeResponse>),
+        (status = 404, description = "Project not found", body = ErrorResponse),
+        (status = 413, description = "Project too large for the full tree", body = TreeTooLargeResponse),
+    )
+)]
 pub async fn get_file_tree(
     State(state): State<AppState>,
     Query(params): Query<FileSearchParams>,
@@ -428,16 +1023,107 @@ pub async fn get_file_tree(
         "Project ID is required".to_string(),
     ))?;
 
-    // Get all files for the project
-    let pagination_params = PaginationParams {
-        page: Some(1),
-        limit: Some(10000), // Get all files
-        offset: None,
-        sort_by: None,
-        sort_order: None,
-    };
+    if !Project::has_access(&state.db_pool, project_id, auth_user.user_id).await? {
+        return Err(AppError::NotFound {
+            entity: "Project".to_string(),
+            id: project_id.to_string(),
+        });
+    }
 
-    let files = File::list_for_project(&state.db_pool, project_id, auth_user.user_id, &pagination_params).await?;
+    full_tree_response(&state, project_id).await
+}
+
+/// Get one directory of a project's file tree.
+///
+/// Returns the immediate children of `path` (expanded down to `depth`
+/// levels), with per-directory counts, sizes and fingerprints so clients can
+/// cache subtrees. `full=true` builds the whole tree for small projects.
+#[utoipa::path(
+    get,
+    path = "/{id}/tree",
+    params(("id" = Uuid, Path, description = "Project ID"), FileTreeParams, PaginationParams),
+    responses(
+        (status = 200, description = "Directory listing, or the whole tree with `full=true`", body = ApiResponse<file_tree::DirectoryListing>),
+        (status = 400, description = "Invalid path or depth", body = ErrorResponse),
+        (status = 404, description = "Project or directory not found", body = ErrorResponse),
+        (status = 413, description = "Project too large for the full tree", body = TreeTooLargeResponse),
+    )
+)]
+pub async fn get_project_tree(
+    State(state): State<AppState>,
+    Path(project_id): Path<Uuid>,
+    Query(params): Query<FileTreeParams>,
+    Query(pagination_params): Query<PaginationParams>,
+    auth_user: axum::Extension<crate::models::auth::AuthContext>,
+) -> Result<impl IntoResponse, AppError> {
+    if !Project::has_access(&state.db_pool, project_id, auth_user.user_id).await? {
+        return Err(AppError::NotFound {
+            entity: "Project".to_string(),
+            id: project_id.to_string(),
+        });
+    }
+
+    if params.full {
+        return full_tree_response(&state, project_id).await;
+    }
+
+    let path = file_tree::normalize_directory(params.path.as_deref()).ok_or_else(|| {
+        AppError::Validation("Path must be an absolute directory path".to_string())
+    })?;
+
+    let depth = params.depth.unwrap_or(1);
+    if depth == 0 || depth > file_tree::MAX_TREE_DEPTH {
+        return Err(AppError::Validation(format!(
+            "Depth must be between 1 and {}",
+            file_tree::MAX_TREE_DEPTH
+        )));
+    }
+
+    let listing = file_tree::list_directory(&state.db_pool, project_id, &path, depth, &pagination_params)
+        .await?
+        .ok_or_else(|| AppError::NotFound {
+            entity: "Directory".to_string(),
+            id: path.clone(),
+        })?;
+
+    Ok(Json(serde_json::json!({
+        "success": true,
+        "data": listing
+    }))
+    .into_response())
+}
+
+/// Build the whole file tree, refusing projects above the configured size
+async fn full_tree_response(
+    state: &AppState,
+    project_id: Uuid,
+) -> Result<Response, AppError> {
+    let max_files = state.config.features.file_storage.tree_full_max_files;
+    let total_files = file_tree::count_files(&state.db_pool, project_id).await?;
+
+    if total_files as u64 > max_files {
+        return Ok((
+            StatusCode::PAYLOAD_TOO_LARGE,
+            Json(TreeTooLargeResponse {
+                success: false,
+                error: ErrorBody {
+                    code: "TREE_TOO_LARGE".to_string(),
+                    message: format!(
+                        "Project has {} files; the full tree is only available up to {}",
+                        total_files, max_files
+                    ),
+                    timestamp: chrono::Utc::now().to_rfc3339(),
+                    request_id: None,
+                },
+                total_files,
+                max_files,
+                hint: format!("Load the tree one directory at a time with /api/v1/projects/{}/tree?path=/&depth=1", project_id),
+            }),
+        )
+            .into_response());
+    }
+
+    let files = File::list_all_for_project(&state.db_pool, project_id).await?;
 
     // Build file tree
     let tree = File::build_tree(&files).await;
@@ -454,10 +1140,20 @@ pub async fn get_file_tree(
     Ok(Json(serde_json::json!({
         "success": true,
         "data": response
-    })))
+    }))
+    .into_response())
 }
 
 /// Search files
+#[utoipa::path(
+    get,
+    path = "/search",
+    params(FileSearchParams, PaginationParams),
+    responses(
+        (status = 200, description = "Matching files", body = ApiResponse<FilesListResponse>),
+        (status = 400, description = "Missing project ID", body = ErrorResponse),
+    )
+)]
 pub async fn search_files(
     State(state): State<AppState>,
     Query(params): Query<FileSearchParams>,
@@ -472,7 +1168,7 @@ pub async fn search_files(
     let mut query = r#"
         SELECT f.* FROM files f
         JOIN projects p ON f.project_id = p.id
-        WHERE f.project_id = $1 AND f.is_deleted = false AND (
+        WHERE f.project_id = $1 AND f.is_deleted = false AND p.purging_at IS NULL AND (
             p.owner_id = $2 OR
             p.id IN (
                 SELECT project_id FROM project_collaborators
@@ -512,12 +1208,7 @@ pub async fn search_files(
         .await
         .map_err(AppError::Database)?;
 
-    // Get file details for each file
-    let mut files_with_details = Vec::new();
-    for file in files {
-        let file_details = File::get_with_details(&state.db_pool, file.id, auth_user.user_id).await?;
-        files_with_details.push(file_details);
-    }
+    let files_with_details = File::with_details(&state.db_pool, files, FileFields::ALL).await?;
 
     let response = FilesListResponse {
         files: files_with_details,
@@ -537,22 +1228,591 @@ pub async fn search_files(
     })))
 }
 
+/// Smallest chunk size a client may request, except for the final chunk
+const MIN_UPLOAD_CHUNK_SIZE: i64 = 256 * 1024;
+
+/// Header carrying the SHA-256 of an uploaded chunk
+const CHUNK_CHECKSUM_HEADER: &str = "x-chunk-checksum";
+
+/// Upload session response
+#[derive(Debug, Serialize, ToSchema)]
+pub struct UploadSessionResponse {
+    pub session: UploadSession,
+    pub received_chunks: Vec<i32>,
+    pub missing_chunks: Vec<i32>,
+}
+
+/// Stored chunk response
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ChunkReceivedResponse {
+    pub chunk_index: i32,
+    pub size: i64,
+    /// Chunks of the session received so far
+    pub received: usize,
+    pub total_chunks: i32,
+}
+
+/// Error body when an upload is completed before all chunks arrived
+#[derive(Debug, Serialize, ToSchema)]
+pub struct UploadIncompleteResponse {
+    pub success: bool,
+    pub error: ErrorBody,
+    pub missing_chunks: Vec<i32>,
+}
+
+/// Directory holding the chunks of an upload session
+fn upload_dir(state: &AppState, session_id: Uuid) -> PathBuf {
+    crate::models::upload::chunk_dir(&state.config.features.file_storage.local_path, session_id)
+}
+
+/// An upload streamed to disk, removed on drop unless it was moved into the blob store
+pub(crate) struct StagedUpload {
+    pub(crate) path: PathBuf,
+    pub(crate) size: u64,
+    pub(crate) content_hash: String,
+}
+
+impl Drop for StagedUpload {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// Write a multipart field to the staging directory chunk by chunk, hashing it
+/// and enforcing `max_size` on the way
+pub(crate) async fn stage_field(
+    state: &AppState,
+    field: &mut Field<'_>,
+    max_size: u64,
+) -> Result<StagedUpload, AppError> {
+    use sha2::{Digest, Sha256};
+    use tokio::io::AsyncWriteExt;
+
+    let storage = &state.config.features.file_storage;
+    let dir = PathBuf::from(&storage.local_path).join(".uploads");
+    tokio::fs::create_dir_all(&dir).await
+        .map_err(|e| AppError::Storage(format!("Failed to prepare upload directory: {}", e)))?;
+
+    let mut staged = StagedUpload {
+        path: dir.join(format!("direct-{}", Uuid::new_v4())),
+        size: 0,
+        content_hash: String::new(),
+    };
+    let mut output = tokio::fs::File::create(&staged.path).await
+        .map_err(|e| AppError::Storage(format!("Failed to stage upload: {}", e)))?;
+    let mut hasher = Sha256::new();
+
+    while let Some(chunk) = field.chunk().await
+        .map_err(|e| AppError::Validation(format!("Failed to read file content: {}", e)))?
+    {
+        staged.size += chunk.len() as u64;
+        if staged.size > max_size {
+            return Err(AppError::Validation(format!(
+                "File is larger than the upload limit of {} bytes",
+                max_size
+            )));
+        }
+
+        hasher.update(&chunk);
+        output.write_all(&chunk).await
+            .map_err(|e| AppError::Storage(format!("Failed to stage upload: {}", e)))?;
+    }
+
+    output.sync_all().await
+        .map_err(|e| AppError::Storage(format!("Failed to stage upload: {}", e)))?;
+    staged.content_hash = hex::encode(hasher.finalize());
+
+    Ok(staged)
+}
+
+/// Guess the content type from a file name
+pub(crate) fn content_type_for(file_name: &str) -> ContentType {
+    ContentType::from_file_name(file_name)
+}
+
+fn is_sha256_hex(value: &str) -> bool {
+    value.len() == 64 && value.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// Look up a session owned by the caller that still accepts data
+async fn open_upload_session(
+    state: &AppState,
+    session_id: Uuid,
+    user_id: Uuid,
+) -> Result<UploadSession, AppError> {
+    let session = UploadSession::find_for_user(&state.db_pool, session_id, user_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound {
+            entity: "Upload session".to_string(),
+            id: session_id.to_string(),
+        })?;
+
+    if !session.is_open() {
+        return Err(AppError::Conflict(
+            "Upload session is no longer accepting data".to_string(),
+        ));
+    }
+
+    Ok(session)
+}
+
+/// Start a resumable upload
+#[utoipa::path(
+    post,
+    path = "/uploads",
+    request_body = CreateUploadSession,
+    responses(
+        (status = 201, description = "Upload session started", body = ApiResponse<UploadSessionResponse>),
+        (status = 400, description = "Invalid size, hash, chunk size or quota exceeded", body = ErrorResponse),
+        (status = 409, description = "A file with this path already exists", body = ErrorResponse),
+        (status = 422, description = "The path goes over the depth or length limit", body = LimitExceededResponse),
+        (status = 423, description = "Project is frozen", body = ErrorResponse),
+    )
+)]
+pub async fn create_upload_session(
+    State(state): State<AppState>,
+    auth_user: axum::Extension<crate::models::auth::AuthContext>,
+    Json(payload): Json<CreateUploadSession>,
+) -> Result<impl IntoResponse, AppError> {
+    let storage = &state.config.features.file_storage;
+
+    if storage.type_ != "local" {
+        return Err(AppError::Storage("Resumable uploads require local storage".to_string()));
+    }
+
+    if !payload.path.starts_with('/') || payload.path.ends_with('/') {
+        return Err(AppError::Validation("File path must be absolute and name a file".to_string()));
+    }
+
+    if payload.size <= 0 {
+        return Err(AppError::Validation("Upload size must be positive".to_string()));
+    }
+
+    if payload.size as u64 > storage.max_upload_size {
+        return Err(AppError::Validation(format!(
+            "Upload exceeds the maximum size of {} bytes",
+            storage.max_upload_size
+        )));
+    }
+
+    if !is_sha256_hex(&payload.content_hash) {
+        return Err(AppError::Validation("Content hash must be a hex-encoded SHA-256 digest".to_string()));
+    }
+
+    let chunk_size = payload.chunk_size.unwrap_or(storage.upload_chunk_size as i64);
+    if chunk_size < MIN_UPLOAD_CHUNK_SIZE || chunk_size as u64 > storage.upload_max_chunk_size {
+        return Err(AppError::Validation(format!(
+            "Chunk size must be between {} and {} bytes",
+            MIN_UPLOAD_CHUNK_SIZE, storage.upload_max_chunk_size
+        )));
+    }
+
+    if !Project::has_access(&state.db_pool, payload.project_id, auth_user.user_id).await? {
+        return Err(AppError::NotFound {
+            entity: "Project".to_string(),
+            id: payload.project_id.to_string(),
+        });
+    }
+
+    // Fail before any chunk is sent rather than when the upload completes
+    ProjectFreeze::ensure_writable(&state.db_pool, payload.project_id).await?;
+    ProjectLimits::from_config(storage).check_path(&payload.path)?;
+
+    if File::find_by_path(&state.db_pool, payload.project_id, &payload.path, auth_user.user_id).await?.is_some() {
+        return Err(AppError::Conflict("File with this path already exists".to_string()));
+    }
+
+    // Reserve the declared size up front so a large upload cannot blow through the quota halfway
+    let usage = UploadSession::project_usage(&state.db_pool, payload.project_id).await?;
+    if (usage + payload.size) as u64 > storage.project_quota {
+        return Err(AppError::Validation(format!(
+            "Project storage quota of {} bytes would be exceeded",
+            storage.project_quota
+        )));
+    }
+
+    let name = StdPath::new(&payload.path)
+        .file_name()
+        .and_then(|name| name.to_str())
+        .ok_or_else(|| AppError::Validation("Invalid file path".to_string()))
+        .and_then(FileName::new)?;
+    let content_type = content_type_for(name.as_str());
+
+    let session = UploadSession::create(
+        &state.db_pool,
+        auth_user.user_id,
+        name,
+        content_type,
+        &payload,
+        chunk_size,
+        storage.upload_session_ttl as i64,
+    )
+    .await?;
+
+    tokio::fs::create_dir_all(upload_dir(&state, session.id)).await
+        .map_err(|e| AppError::Storage(format!("Failed to prepare upload directory: {}", e)))?;
+
+    let response = UploadSessionResponse {
+        missing_chunks: session.missing_chunks(&[]),
+        received_chunks: vec![],
+        session,
+    };
+
+    Ok((
+        StatusCode::CREATED,
+        Json(serde_json::json!({
+            "success": true,
+            "data": response
+        })),
+    ))
+}
+
+/// Get upload session progress, used by clients to resume
+#[utoipa::path(
+    get,
+    path = "/uploads/{id}",
+    params(("id" = Uuid, Path, description = "Upload session ID")),
+    responses(
+        (status = 200, description = "Session with received and missing chunks", body = ApiResponse<UploadSessionResponse>),
+        (status = 404, description = "Upload session not found", body = ErrorResponse),
+    )
+)]
+pub async fn get_upload_session(
+    State(state): State<AppState>,
+    Path(session_id): Path<Uuid>,
+    auth_user: axum::Extension<crate::models::auth::AuthContext>,
+) -> Result<impl IntoResponse, AppError> {
+    let session = UploadSession::find_for_user(&state.db_pool, session_id, auth_user.user_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound {
+            entity: "Upload session".to_string(),
+            id: session_id.to_string(),
+        })?;
+
+    let received_chunks = UploadSession::received_chunks(&state.db_pool, session.id).await?;
+
+    let response = UploadSessionResponse {
+        missing_chunks: session.missing_chunks(&received_chunks),
+        received_chunks,
+        session,
+    };
+
+    Ok(Json(serde_json::json!({
+        "success": true,
+        "data": response
+    })))
+}
+
+/// Upload a single chunk, streaming it to disk
+#[utoipa::path(
+    put,
+    path = "/uploads/{id}/chunks/{index}",
+    params(
+        ("id" = Uuid, Path, description = "Upload session ID"),
+        ("index" = i32, Path, description = "Zero-based chunk index"),
+        ("x-chunk-checksum" = String, Header, description = "Hex-encoded SHA-256 of the chunk"),
+    ),
+    request_body(content = Vec<u8>, content_type = "application/octet-stream"),
+    responses(
+        (status = 200, description = "Chunk stored", body = ApiResponse<ChunkReceivedResponse>),
+        (status = 400, description = "Wrong size or checksum", body = ErrorResponse),
+        (status = 409, description = "Session no longer accepts data", body = ErrorResponse),
+    )
+)]
+pub async fn upload_chunk(
+    State(state): State<AppState>,
+    Path((session_id, index)): Path<(Uuid, i32)>,
+    auth_user: axum::Extension<crate::models::auth::AuthContext>,
+    headers: HeaderMap,
+    body: Body,
+) -> Result<impl IntoResponse, AppError> {
+    use futures_util::StreamExt;
+    use sha2::{Digest, Sha256};
+    use tokio::io::AsyncWriteExt;
+
+    let session = open_upload_session(&state, session_id, auth_user.user_id).await?;
+
+    let expected_size = session.expected_chunk_size(index).ok_or_else(|| AppError::Validation(format!(
+        "Chunk index must be between 0 and {}",
+        session.total_chunks - 1
+    )))?;
+
+    let expected_checksum = headers
+        .get(CHUNK_CHECKSUM_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .filter(|value| is_sha256_hex(value))
+        .map(|value| value.to_lowercase())
+        .ok_or_else(|| AppError::Validation(format!(
+            "{} header with a hex-encoded SHA-256 digest is required",
+            CHUNK_CHECKSUM_HEADER
+        )))?;
+
+    let dir = upload_dir(&state, session.id);
+    let partial_path = dir.join(format!("{}.part.{}", index, Uuid::new_v4()));
+    let chunk_path = dir.join(format!("{}.part", index));
+
+    tokio::fs::create_dir_all(&dir).await
+        .map_err(|e| AppError::Storage(format!("Failed to prepare upload directory: {}", e)))?;
+
+    let mut output = tokio::fs::File::create(&partial_path).await
+        .map_err(|e| AppError::Storage(format!("Failed to create chunk file: {}", e)))?;
+
+    // Hash and write frame by frame so memory use stays bounded by the frame size
+    let mut hasher = Sha256::new();
+    let mut written: i64 = 0;
+    let mut stream = body.into_data_stream();
+
+    let result: Result<(), AppError> = async {
+        while let Some(frame) = stream.next().await {
+            let frame = frame.map_err(|e| AppError::BadRequest(format!("Failed to read chunk body: {}", e)))?;
+
+            written += frame.len() as i64;
+            if written > expected_size {
+                return Err(AppError::Validation(format!(
+                    "Chunk {} exceeds its expected size of {} bytes",
+                    index, expected_size
+                )));
+            }
+
+            hasher.update(&frame);
+            output.write_all(&frame).await
+                .map_err(|e| AppError::Storage(format!("Failed to write chunk: {}", e)))?;
+        }
+
+        output.flush().await
+            .map_err(|e| AppError::Storage(format!("Failed to write chunk: {}", e)))?;
+
+        if written != expected_size {
+            return Err(AppError::Validation(format!(
+                "Chunk {} is {} bytes, expected {}",
+                index, written, expected_size
+            )));
+        }
+
+        let checksum = hex::encode(hasher.finalize_reset());
+        if checksum != expected_checksum {
+            return Err(AppError::Validation(format!("Checksum mismatch for chunk {}", index)));
+        }
+
+        tokio::fs::rename(&partial_path, &chunk_path).await
+            .map_err(|e| AppError::Storage(format!("Failed to store chunk: {}", e)))
+    }
+    .await;
+
+    if let Err(e) = result {
+        let _ = tokio::fs::remove_file(&partial_path).await;
+        return Err(e);
+    }
+
+    UploadSession::record_chunk(&state.db_pool, session.id, index, written, &expected_checksum).await?;
+    let received_chunks = UploadSession::received_chunks(&state.db_pool, session.id).await?;
+
+    let response = ChunkReceivedResponse {
+        chunk_index: index,
+        size: written,
+        received: received_chunks.len(),
+        total_chunks: session.total_chunks,
+    };
+
+    Ok(Json(serde_json::json!({
+        "success": true,
+        "data": response
+    })))
+}
+
+/// Assemble the uploaded chunks, scan them for malware and create the file
+#[utoipa::path(
+    post,
+    path = "/uploads/{id}/complete",
+    params(("id" = Uuid, Path, description = "Upload session ID")),
+    responses(
+        (status = 201, description = "File created from the chunks", body = ApiResponse<FileUploadResponse>),
+        (status = 400, description = "Assembled content does not match the declared size or hash", body = ErrorResponse),
+        (status = 409, description = "Chunks are still missing", body = UploadIncompleteResponse),
+        (status = 422, description = "Malware was found in the file, or it goes over a limit of its project", body = ErrorResponse),
+        (status = 502, description = "The malware scanner could not be reached", body = ErrorResponse),
+    )
+)]
+pub async fn complete_upload(
+    State(state): State<AppState>,
+    Path(session_id): Path<Uuid>,
+    auth_user: axum::Extension<crate::models::auth::AuthContext>,
+) -> Result<impl IntoResponse, AppError> {
+    use sha2::{Digest, Sha256};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let session = open_upload_session(&state, session_id, auth_user.user_id).await?;
+
+    let received_chunks = UploadSession::received_chunks(&state.db_pool, session.id).await?;
+    let missing_chunks = session.missing_chunks(&received_chunks);
+
+    if !missing_chunks.is_empty() {
+        return Ok((
+            StatusCode::CONFLICT,
+            Json(UploadIncompleteResponse {
+                success: false,
+                error: ErrorBody {
+                    code: "UPLOAD_INCOMPLETE".to_string(),
+                    message: format!("{} chunk(s) still need to be uploaded", missing_chunks.len()),
+                    timestamp: chrono::Utc::now().to_rfc3339(),
+                    request_id: None,
+                },
+                missing_chunks,
+            }),
+        )
+            .into_response());
+    }
+
+    let dir = upload_dir(&state, session.id);
+    let assembled_path = dir.join("assembled");
+
+    let assembled: Result<(), AppError> = async {
+        let mut output = tokio::fs::File::create(&assembled_path).await
+            .map_err(|e| AppError::Storage(format!("Failed to assemble upload: {}", e)))?;
+        let mut hasher = Sha256::new();
+        let mut buffer = vec![0u8; 64 * 1024];
+        let mut total: i64 = 0;
+
+        for index in 0..session.total_chunks {
+            let mut chunk = tokio::fs::File::open(dir.join(format!("{}.part", index))).await
+                .map_err(|e| AppError::Storage(format!("Chunk {} is missing on disk: {}", index, e)))?;
+
+            loop {
+                let read = chunk.read(&mut buffer).await
+                    .map_err(|e| AppError::Storage(format!("Failed to read chunk {}: {}", index, e)))?;
+                if read == 0 {
+                    break;
+                }
+
+                hasher.update(&buffer[..read]);
+                output.write_all(&buffer[..read]).await
+                    .map_err(|e| AppError::Storage(format!("Failed to assemble upload: {}", e)))?;
+                total += read as i64;
+            }
+        }
+
+        output.sync_all().await
+            .map_err(|e| AppError::Storage(format!("Failed to assemble upload: {}", e)))?;
+
+        if total != session.declared_size {
+            return Err(AppError::Validation(format!(
+                "Assembled size {} does not match declared size {}",
+                total, session.declared_size
+            )));
+        }
+
+        if hex::encode(hasher.finalize()) != session.content_hash {
+            return Err(AppError::Validation("Assembled content does not match the declared hash".to_string()));
+        }
+
+        Ok(())
+    }
+    .await;
+
+    if let Err(e) = assembled {
+        let _ = tokio::fs::remove_file(&assembled_path).await;
+        return Err(e);
+    }
+
+    let verdict = match state.file_scanner.scan_path(&assembled_path, session.declared_size as u64).await {
+        Ok(verdict) => verdict,
+        Err(e) => {
+            let _ = tokio::fs::remove_file(&assembled_path).await;
+            return Err(e);
+        }
+    };
+    if let ScanVerdict::Infected { signature } = &verdict {
+        if let Err(e) = tokio::fs::remove_dir_all(&dir).await {
+            tracing::warn!("Failed to remove upload directory {}: {}", dir.display(), e);
+        }
+        return Err(file_scan::reject_infected_upload(
+            &state,
+            session.project_id,
+            auth_user.user_id,
+            &session.path,
+            session.declared_size,
+            signature,
+        )
+        .await);
+    }
+
+    let storage_root = PathBuf::from(&state.config.features.file_storage.local_path);
+    let limits = ProjectLimits::from_config(&state.config.features.file_storage);
+    let file = session
+        .complete(&state.db_pool, &limits, |acquired| {
+            let Some(storage_path) = acquired.blob.storage_path.as_ref().filter(|_| acquired.needs_content) else {
+                // Identical content is already stored, only the reference was added
+                return Ok(());
+            };
+
+            let target = storage_root.join(storage_path);
+            if let Some(parent) = target.parent() {
+                std::fs::create_dir_all(parent)
+                    .map_err(|e| AppError::Storage(format!("Failed to prepare blob directory: {}", e)))?;
+            }
+            std::fs::rename(&assembled_path, target)
+                .map_err(|e| AppError::Storage(format!("Failed to save file: {}", e)))
+        })
+        .await;
+
+    let file = match file {
+        Ok(file) => file,
+        Err(e) => {
+            let _ = tokio::fs::remove_file(&assembled_path).await;
+            return Err(e);
+        }
+    };
+
+    File::record_scan(&state.db_pool, file.id, FileScanStatus::accepted(&verdict), None).await?;
+    state.autocomplete_cache.invalidate(file.project_id);
+    state.include_graph_cache.file_changed(&file);
+
+    if let Err(e) = tokio::fs::remove_dir_all(&dir).await {
+        tracing::warn!("Failed to remove upload directory {}: {}", dir.display(), e);
+    }
+
+    let file_with_details = File::get_with_details(&state.db_pool, file.id, auth_user.user_id).await?;
+    let response = FileUploadResponse {
+        file: file_with_details,
+        url: Some(format!("/api/v1/files/{}/download", file.id)),
+    };
+
+    Ok((
+        StatusCode::CREATED,
+        Json(serde_json::json!({
+            "success": true,
+            "data": response
+        })),
+    )
+        .into_response())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::models::audit::AuditEvent;
+    use crate::models::notification::Notification;
+    use crate::models::user::User;
+    use crate::testing::{
+        add_collaborator, clamav_service, create_test_file, create_test_project, create_test_user, mock_clamd, oneshot_as,
+        single_connection_pool, test_config, test_state, QueryCounter, TestDb, EICAR,
+    };
+    use axum::{body::Body, http::Request, routing::{delete, get, post, put}, Router};
 
-    #[tokio::test]
-    async fn test_file_creation_validation() {
-        let create_file = CreateFile {
-            name: "".to_string(), // Empty name should fail validation
-            path: "/test.tex".to_string(),
-            content: Some("Hello World".to_string()),
-            content_type: Some(ContentType::Latex),
+    #[test]
+    fn test_file_creation_validation() {
+        let create_file = |name: &str| {
+            serde_json::from_value::<CreateFile>(serde_json::json!({
+                "name": name,
+                "path": "/test.tex",
+                "content": "Hello World",
+            }))
         };
 
-        // This test would require setting up proper auth context
-        // For now, we just verify the validation logic exists
-        assert!(create_file.name.is_empty());
+        // Empty and reserved names are rejected when the request is read
+        assert!(create_file("").is_err());
+        assert!(create_file("..").is_err());
+        assert_eq!(create_file(" test.tex ").unwrap().name.as_str(), "test.tex");
     }
 
     #[test]
@@ -560,5 +1820,404 @@ mod tests {
         assert_eq!(StdPath::new("document.tex").extension().and_then(|s| s.to_str()), Some("tex"));
         assert_eq!(StdPath::new("image.png").extension().and_then(|s| s.to_str()), Some("png"));
         assert_eq!(StdPath::new("references.bib").extension().and_then(|s| s.to_str()), Some("bib"));
+
+        assert_eq!(content_type_for("main.tex"), ContentType::Latex);
+        assert_eq!(content_type_for("slides.typ"), ContentType::Typst);
+        assert_eq!(content_type_for("figure.PNG"), ContentType::Other);
+        assert_eq!(content_type_for("dataset.csv"), ContentType::Other);
+    }
+
+    #[tokio::test]
+    async fn test_file_fields_select_sections() {
+        let Some(db) = TestDb::start().await else { return };
+        let pool = single_connection_pool(&db).await;
+        let state = AppState::new(test_config(), pool).await.unwrap();
+        let owner = create_test_user(&db.pool).await;
+        let project = create_test_project(&db.pool, &owner, false).await;
+        let file = create_test_file(&db.pool, &project, &owner).await;
+
+        let fetch = |uri: String| {
+            let router = Router::new()
+                .route("/files", get(list_files))
+                .route("/files/:id", get(get_file));
+            let request = Request::get(uri).body(Body::empty()).unwrap();
+            oneshot_as(router, state.clone(), &owner, request)
+        };
+        // Number of queries a request costs, after a warm-up run
+        let queries = |uri: String| async move {
+            fetch(uri.clone()).await;
+            let counter = QueryCounter::start();
+            let (status, body) = fetch(uri).await;
+            assert_eq!(status, StatusCode::OK, "{}", body);
+            counter.count()
+        };
+
+        let (status, body) = fetch(format!("/files/{}?fields=file", file.id)).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        let details = body["data"]["file"].as_object().unwrap();
+        assert_eq!(details["id"], file.id.to_string());
+        assert!(!details.contains_key("modified_by"));
+        assert!(!details.contains_key("versions"));
+
+        let (_, body) = fetch(format!("/files/{}?fields=versions", file.id)).await;
+        assert!(body["data"]["file"]["versions"].is_array());
+
+        let (status, _) = fetch(format!("/files/{}?fields=owner", file.id)).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let list = format!("/files?project_id={}", project.id);
+        let bare = queries(format!("{}&fields=file", list)).await;
+        let with_versions = queries(format!("{}&fields=file,versions", list)).await;
+        assert_eq!(with_versions - bare, 1);
+
+        // Sections are loaded for the whole page, not for each file
+        let one = queries(list.clone()).await;
+        for name in ["intro.tex", "refs.bib"] {
+            let create = CreateFile {
+                name: FileName::new(name).unwrap(),
+                path: name.to_string(),
+                content: Some("%".to_string()),
+                content_type: None,
+            };
+            File::create(&db.pool, &ProjectLimits::default(), project.id, create, owner.id).await.unwrap();
+        }
+        let three = queries(list).await;
+        assert_eq!(one, three);
+    }
+
+    #[test]
+    fn test_sha256_hex_validation() {
+        assert!(is_sha256_hex(&"a".repeat(64)));
+        assert!(!is_sha256_hex(&"a".repeat(63)));
+        assert!(!is_sha256_hex(&"g".repeat(64)));
+    }
+    fn multipart_upload(project_id: Uuid, file_name: &str, content: &[u8]) -> Request<Body> {
+        let mut body = format!(
+            "--boundary\r\nContent-Disposition: form-data; name=\"file\"; filename=\"{}\"\r\n\r\n",
+            file_name
+        )
+        .into_bytes();
+        body.extend_from_slice(content);
+        body.extend_from_slice(b"\r\n--boundary--\r\n");
+
+        Request::post(format!("/upload?project_id={}", project_id))
+            .header("content-type", "multipart/form-data; boundary=boundary")
+            .body(Body::from(body))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_infected_upload_is_rejected_and_reported() {
+        let Some(db) = TestDb::start().await else { return };
+        crate::admin_init::ensure_admin_user(&db.pool).await.unwrap();
+        let storage = tempfile::tempdir().unwrap();
+        let (address, streams) = mock_clamd().await;
+
+        let mut state = test_state(&db).await;
+        let mut config = (*state.config).clone();
+        config.features.file_storage.type_ = "local".to_string();
+        config.features.file_storage.local_path = storage.path().to_string_lossy().into_owned();
+        state.config = std::sync::Arc::new(config);
+        state.file_scanner = std::sync::Arc::new(clamav_service(&address, 1024 * 1024));
+
+        let owner = create_test_user(&db.pool).await;
+        let project = create_test_project(&db.pool, &owner, false).await;
+        let router = || Router::new().route("/upload", post(upload_file));
+
+        let file_name = format!("{}.txt", Uuid::new_v4().simple());
+        let (status, body) = oneshot_as(router(), state.clone(), &owner, multipart_upload(project.id, &file_name, EICAR)).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body["error"]["code"], "MALWARE_DETECTED");
+        assert_eq!(streams.load(std::sync::atomic::Ordering::SeqCst), 1);
+
+        let path = format!("/{}", file_name);
+        let stored: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM files WHERE project_id = $1")
+            .bind(project.id)
+            .fetch_one(&db.pool)
+            .await
+            .unwrap();
+        assert_eq!(stored, 0);
+
+        let events = AuditEvent::list_by_action(&db.pool, "upload_rejected_malware", 100).await.unwrap();
+        let event = events.iter().find(|event| event.project_id == Some(project.id)).unwrap();
+        assert_eq!(event.actor_id, Some(owner.id));
+        assert_eq!(event.details["signature"], "Eicar-Signature");
+
+        let admin = User::find_by_username(&db.pool, crate::admin_init::ADMIN_USERNAME).await.unwrap().unwrap();
+        let first_page = PaginationParams {
+            page: None,
+            limit: None,
+            offset: None,
+            sort_by: None,
+            sort_order: None,
+        };
+        let notifications = Notification::list_for_user(&db.pool, admin.id, false, &first_page).await.unwrap();
+        assert!(notifications
+            .iter()
+            .any(|n| n.kind == "malware_detected" && n.data.as_ref().is_some_and(|data| data["path"] == path.as_str())));
+
+        // Nothing is left behind in the staging directory
+        let mut staged = tokio::fs::read_dir(storage.path().join(".uploads")).await.unwrap();
+        assert!(staged.next_entry().await.unwrap().is_none());
+
+        // Clean uploads are stored and marked as scanned
+        let (status, body) = oneshot_as(router(), state.clone(), &owner, multipart_upload(project.id, "clean.txt", b"hello")).await;
+        assert_eq!(status, StatusCode::CREATED);
+        let file_id: Uuid = serde_json::from_value(body["data"]["file"]["id"].clone()).unwrap();
+        let file = File::find_by_id(&db.pool, file_id, owner.id).await.unwrap().unwrap();
+        assert_eq!(file.scan_status, FileScanStatus::Clean);
+    }
+
+    #[tokio::test]
+    async fn test_content_saves_and_deletes_return_what_changed() {
+        let Some(db) = TestDb::start().await else { return };
+        let state = test_state(&db).await;
+        let owner = create_test_user(&db.pool).await;
+        let project = create_test_project(&db.pool, &owner, false).await;
+        let chapter = |name: &str| {
+            File::create(
+                &db.pool,
+                &ProjectLimits::default(),
+                project.id,
+                CreateFile {
+                    name: FileName::new(name).unwrap(),
+                    path: format!("/chapters/{}", name),
+                    content: Some("\\section{Draft}\n".to_string()),
+                    content_type: None,
+                },
+                owner.id,
+            )
+        };
+        let intro = chapter("intro.tex").await.unwrap();
+        chapter("outro.tex").await.unwrap();
+
+        let send = |method: &str, uri: String, body: serde_json::Value| {
+            let router = Router::new()
+                .route("/files/:id", delete(delete_file))
+                .route("/files/:id/content", put(update_file_content));
+            let request = Request::builder()
+                .method(method)
+                .uri(uri)
+                .header("content-type", "application/json")
+                .body(Body::from(body.to_string()))
+                .unwrap();
+            oneshot_as(router, state.clone(), &owner, request)
+        };
+
+        // The new version comes back on its own and first in the file's history
+        let content = serde_json::json!({ "content": "\\section{Introduction}\n" });
+        let (status, body) = send("PUT", format!("/files/{}/content", intro.id), content).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        let version = &body["data"]["version"];
+        assert_eq!(version["version"], intro.version + 1);
+        assert_eq!(body["data"]["file"]["version"], version["version"]);
+        assert_eq!(body["data"]["file"]["versions"][0]["id"], version["id"]);
+
+        // Deleting reports the directory as a listing of it now would
+        let (status, body) = send("DELETE", format!("/files/{}", intro.id), serde_json::json!(null)).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        let parent = &body["data"]["parent"];
+        assert_eq!(body["data"]["permanent"], false);
+        assert_eq!(parent["path"], "/chapters/");
+        assert_eq!(parent["file_count"], 1);
+        let listing = file_tree::list_directory(&db.pool, project.id, "/chapters/", 1, &PaginationParams::default())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(parent["fingerprint"], listing.fingerprint);
+    }
+
+    #[tokio::test]
+    async fn test_content_is_read_from_its_storage() {
+        let Some(db) = TestDb::start().await else { return };
+        let state = test_state(&db).await;
+        let storage_root = state.config.features.file_storage.local_path.clone();
+        let owner = create_test_user(&db.pool).await;
+        let project = create_test_project(&db.pool, &owner, false).await;
+        let main = create_test_file(&db.pool, &project, &owner).await;
+        let stored = |name: &str, content_type, bytes: &'static [u8]| {
+            File::create_from_bytes(
+                &db.pool, &storage_root, &ProjectLimits::default(), project.id, name, name, content_type, bytes, owner.id,
+            )
+        };
+        let notes = stored("notes.txt", ContentType::Other, "Größe\n".as_bytes()).await.unwrap();
+        let data = stored("data.bin", ContentType::Other, &[0x00, 0xff, 0xfe]).await.unwrap();
+        let logo = stored("logo.png", ContentType::Image, b"\x89PNG\r\n").await.unwrap();
+        assert_eq!(notes.storage_strategy, StorageStrategy::External);
+        let fetch = |uri: String| {
+            let router = Router::new().route("/files/:id/content", get(get_file_content));
+            oneshot_as(router, state.clone(), &owner, Request::get(uri).body(Body::empty()).unwrap())
+        };
+
+        // Rows and the blob store alike
+        let (status, body) = fetch(format!("/files/{}/content", main.id)).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        assert_eq!(body["data"]["content"], main.content);
+        let (status, body) = fetch(format!("/files/{}/content", notes.id)).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        assert_eq!(body["data"]["content"], "Größe\n");
+
+        // Binary content points to the download
+        for file in [&data, &logo] {
+            let (status, body) = fetch(format!("/files/{}/content", file.id)).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{}", body);
+            assert!(body["error"]["message"].as_str().unwrap().contains("/download"), "{}", body);
+        }
+        let response = download_file(State(state.clone()), Path(data.id), axum::Extension(crate::testing::auth_context(&owner)))
+            .await
+            .unwrap()
+            .into_response();
+        assert_eq!(response.status(), StatusCode::OK);
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(bytes.as_ref(), [0x00, 0xff, 0xfe]);
+
+        let request = Request::delete(format!("/files/{}", main.id)).body(Body::empty()).unwrap();
+        let router = Router::new().route("/files/:id", delete(delete_file));
+        let (status, body) = oneshot_as(router, state.clone(), &owner, request).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        let (status, _) = fetch(format!("/files/{}/content", main.id)).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_blame_follows_saves_of_each_author() {
+        let Some(db) = TestDb::start().await else { return };
+        let state = test_state(&db).await;
+        let alice = create_test_user(&db.pool).await;
+        let bob = create_test_user(&db.pool).await;
+        let stranger = create_test_user(&db.pool).await;
+        let project = create_test_project(&db.pool, &alice, false).await;
+        add_collaborator(&db.pool, &project, &bob, UserRole::Editor).await;
+        let file = create_test_file(&db.pool, &project, &alice).await;
+
+        let send = |user: &User, request: Request<Body>| {
+            let router = Router::new()
+                .route("/files/:id/blame", get(get_file_blame))
+                .route("/files/:id/content", put(update_file_content));
+            oneshot_as(router, state.clone(), user, request)
+        };
+        let save = |user: &User, content: &str| {
+            let request = Request::put(format!("/files/{}/content", file.id))
+                .header("content-type", "application/json")
+                .body(Body::from(serde_json::json!({ "content": content }).to_string()))
+                .unwrap();
+            send(user, request)
+        };
+        let blame = |user: &User, query: &str| {
+            let request = Request::get(format!("/files/{}/blame{}", file.id, query)).body(Body::empty()).unwrap();
+            send(user, request)
+        };
+        let origins = |body: &serde_json::Value| {
+            body["data"]["lines"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|line| (line["version"].as_i64(), line["author"]["username"].as_str().map(str::to_string)))
+                .collect::<Vec<_>>()
+        };
+
+        for (user, content) in [
+            (&alice, "\\section{Intro}\nWe study blame.\n\\section{Results}\n"),
+            (&bob, "\\section{Intro}\nWe study line blame.\n\\section{Results}\nIt works.\n"),
+            (&alice, "\\section{Intro}\nWe study blame of lines.\n\\section{Results}\nIt works.\n"),
+        ] {
+            let (status, body) = save(user, content).await;
+            assert_eq!(status, StatusCode::OK, "{}", body);
+        }
+
+        // Alice rewrote the line bob had changed; bob's addition stays his
+        let (status, body) = blame(&bob, "").await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        let alice_name = Some(alice.username.clone());
+        let bob_name = Some(bob.username.clone());
+        assert_eq!(
+            origins(&body),
+            vec![(None, None), (Some(4), alice_name.clone()), (None, None), (Some(3), bob_name.clone())]
+        );
+        assert_eq!(body["data"]["lines"][0]["earlier"], true);
+        assert_eq!(body["data"]["horizon"], 2);
+
+        // A repeated blame is served from the cache
+        let first = {
+            let counter = QueryCounter::start();
+            blame(&alice, "?range=2-4").await;
+            counter.count()
+        };
+        let counter = QueryCounter::start();
+        let (status, body) = blame(&alice, "?range=2-4").await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        assert!(counter.count() < first, "{} queries, {} uncached", counter.count(), first);
+        assert_eq!(origins(&body), vec![(Some(4), alice_name), (None, None), (Some(3), bob_name.clone())]);
+
+        // A new version replaces the cached blame
+        let (status, _) = save(&bob, "\\section{Intro}\nWe study blame of lines.\n\\section{Results}\nIt works!\n").await;
+        assert_eq!(status, StatusCode::OK);
+        let (_, body) = blame(&alice, "?range=2-4").await;
+        assert_eq!(origins(&body)[2], (Some(5), bob_name));
+
+        let (status, _) = blame(&alice, "?range=3-9").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let (status, _) = blame(&stranger, "").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_format_proposes_and_applies() {
+        let Some(db) = TestDb::start().await else { return };
+        let state = test_state(&db).await;
+        let owner = create_test_user(&db.pool).await;
+        let project = create_test_project(&db.pool, &owner, false).await;
+        let storage = &state.config.features.file_storage.local_path;
+        let file = create_test_file(&db.pool, &project, &owner)
+            .await
+            .update_content(&db.pool, storage, "\\begin{center}\nA   \nB\n\\end{center}\n".to_string(), owner.id)
+            .await
+            .unwrap();
+        let update = serde_json::from_value(serde_json::json!({ "settings": { "formatting": { "indent_width": 4 } } }));
+        project.update(&db.pool, update.unwrap(), owner.id).await.unwrap();
+
+        let send = |path: String, body: serde_json::Value| {
+            let router = Router::new()
+                .route("/files/:id/format", post(format_file))
+                .route("/files/:id/format/selection", post(format_selection));
+            let request = Request::post(path)
+                .header("content-type", "application/json")
+                .body(Body::from(body.to_string()))
+                .unwrap();
+            oneshot_as(router, state.clone(), &owner, request)
+        };
+        let format_path = format!("/files/{}/format", file.id);
+        let selection_path = format!("/files/{}/format/selection", file.id);
+
+        // A proposal leaves the file alone
+        let (status, body) = send(format_path.clone(), serde_json::json!({})).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        let proposal = &body["data"]["proposal"];
+        assert_eq!(proposal["formatter"], "builtin");
+        assert_eq!(proposal["changed"], true);
+        assert_eq!(proposal["content"], "\\begin{center}\n    A\n    B\n\\end{center}\n");
+        assert_eq!(proposal["hunks"][0]["start_line"], 2);
+        assert!(body["data"]["file"].is_null());
+        let unchanged = File::find_by_id(&db.pool, file.id, owner.id).await.unwrap().unwrap();
+        assert_eq!(unchanged.version, file.version);
+
+        let (status, body) = send(selection_path.clone(), serde_json::json!({ "start_line": 3, "end_line": 3 })).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        assert_eq!(body["data"]["proposal"]["content"], "\\begin{center}\nA   \n    B\n\\end{center}\n");
+
+        let (status, _) = send(selection_path, serde_json::json!({ "start_line": 2, "end_line": 9 })).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        // Applying saves a new version, after which there is nothing left to do
+        let (status, body) = send(format_path.clone(), serde_json::json!({ "apply": true })).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        assert_eq!(body["data"]["file"]["id"], file.id.to_string());
+        let formatted = File::find_by_id(&db.pool, file.id, owner.id).await.unwrap().unwrap();
+        assert_eq!(formatted.version, file.version + 1);
+        assert_eq!(formatted.content, "\\begin{center}\n    A\n    B\n\\end{center}\n");
+
+        let (_, body) = send(format_path, serde_json::json!({ "apply": true })).await;
+        assert_eq!(body["data"]["proposal"]["changed"], false);
+        assert!(body["data"]["file"].is_null());
     }
 }
@@ -0,0 +1,499 @@
+//! Liveness and readiness probes
+//!
+//! `/health/live` only reports whether the process is up and accepting work,
+//! while `/health/ready` actually exercises the dependencies a request needs.
+//! Readiness results are cached briefly so that a burst of probe traffic
+//! cannot pile extra load onto a database that is already struggling.
+//! Failed compile sandbox probes degrade readiness as a security warning.
+//! While the database circuit breaker is open, readiness fails at once
+//! without running the checks.
+
+use crate::models::compile_sandbox::SelfTestReport;
+use crate::server::AppState;
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::IntoResponse,
+    Json,
+};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+use utoipa::ToSchema;
+
+/// How long a readiness report is reused before the checks run again
+const READINESS_CACHE_TTL: Duration = Duration::from_secs(2);
+
+/// Timeout for the database round trip
+const DATABASE_CHECK_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Timeout for the Redis round trip
+const REDIS_CHECK_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Outcome of a single dependency check
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum CheckStatus {
+    Ok,
+    Degraded,
+    Failed,
+}
+
+/// Result of a single dependency check
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct CheckResult {
+    pub name: &'static str,
+    pub status: CheckStatus,
+    /// Hard dependencies make the instance unready when they fail
+    pub hard: bool,
+    pub latency_ms: u64,
+    pub message: Option<String>,
+}
+
+impl CheckResult {
+    fn ok(name: &'static str, hard: bool, started: Instant) -> Self {
+        Self {
+            name,
+            status: CheckStatus::Ok,
+            hard,
+            latency_ms: started.elapsed().as_millis() as u64,
+            message: None,
+        }
+    }
+
+    fn failed(name: &'static str, hard: bool, started: Instant, message: String) -> Self {
+        Self {
+            name,
+            // Soft dependencies only ever degrade the instance
+            status: if hard { CheckStatus::Failed } else { CheckStatus::Degraded },
+            hard,
+            latency_ms: started.elapsed().as_millis() as u64,
+            message: Some(message),
+        }
+    }
+}
+
+/// Overall readiness state
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ReadinessStatus {
+    Ready,
+    Degraded,
+    Unavailable,
+}
+
+/// Aggregated readiness report
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct ReadinessReport {
+    pub status: ReadinessStatus,
+    pub checks: Vec<CheckResult>,
+    pub checked_at: DateTime<Utc>,
+    pub version: &'static str,
+}
+
+impl ReadinessReport {
+    fn from_checks(checks: Vec<CheckResult>) -> Self {
+        Self {
+            status: overall_status(&checks),
+            checks,
+            checked_at: Utc::now(),
+            version: crate::VERSION,
+        }
+    }
+
+    /// HTTP status for this report: 503 only when a hard dependency failed
+    pub fn http_status(&self) -> StatusCode {
+        match self.status {
+            ReadinessStatus::Ready | ReadinessStatus::Degraded => StatusCode::OK,
+            ReadinessStatus::Unavailable => StatusCode::SERVICE_UNAVAILABLE,
+        }
+    }
+}
+
+/// Liveness probe response
+#[derive(Debug, Serialize, ToSchema)]
+pub struct LivenessResponse {
+    /// `alive`, or `shutting_down` while draining
+    pub status: &'static str,
+    pub timestamp: DateTime<Utc>,
+    pub version: &'static str,
+}
+
+/// Health probe state shared across requests
+#[derive(Debug, Default)]
+pub struct HealthChecker {
+    shutting_down: AtomicBool,
+    cache: Mutex<Option<(Instant, ReadinessReport)>>,
+}
+
+impl HealthChecker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Mark the process as draining so probes start failing
+    pub fn begin_shutdown(&self) {
+        self.shutting_down.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_shutting_down(&self) -> bool {
+        self.shutting_down.load(Ordering::SeqCst)
+    }
+
+    /// Return the cached readiness report or run the checks again.
+    ///
+    /// The cache lock is held while the checks run, so concurrent probes wait
+    /// for the in-flight run instead of issuing their own queries.
+    pub async fn readiness(&self, state: &AppState) -> ReadinessReport {
+        let mut cache = self.cache.lock().await;
+
+        if let Some((checked, report)) = cache.as_ref() {
+            if checked.elapsed() < READINESS_CACHE_TTL {
+                return report.clone();
+            }
+        }
+
+        let report = ReadinessReport::from_checks(run_checks(state).await);
+        *cache = Some((Instant::now(), report.clone()));
+        report
+    }
+}
+
+/// Liveness probe: the process is up unless it is shutting down
+#[utoipa::path(
+    get,
+    path = "/health/live",
+    responses(
+        (status = 200, description = "Process is up", body = LivenessResponse),
+        (status = 503, description = "Process is shutting down", body = LivenessResponse),
+    ),
+    security(())
+)]
+pub async fn liveness(State(state): State<AppState>) -> impl IntoResponse {
+    let (status, label) = if state.health.is_shutting_down() {
+        (StatusCode::SERVICE_UNAVAILABLE, "shutting_down")
+    } else {
+        (StatusCode::OK, "alive")
+    };
+
+    (
+        status,
+        Json(LivenessResponse {
+            status: label,
+            timestamp: Utc::now(),
+            version: crate::VERSION,
+        }),
+    )
+}
+
+/// Readiness probe: checks every dependency required to serve traffic
+#[utoipa::path(
+    get,
+    path = "/health/ready",
+    responses(
+        (status = 200, description = "Ready, or degraded by a soft dependency", body = ReadinessReport),
+        (status = 503, description = "A hard dependency failed or the process is shutting down", body = ReadinessReport),
+    ),
+    security(())
+)]
+pub async fn readiness(State(state): State<AppState>) -> impl IntoResponse {
+    if state.health.is_shutting_down() {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(serde_json::json!({
+                "status": ReadinessStatus::Unavailable,
+                "checks": [],
+                "checked_at": Utc::now(),
+                "version": crate::VERSION
+            })),
+        );
+    }
+
+    // Probes during an outage would only queue behind dead connections
+    if let Some(open_for) = state.db_resilience.open_for() {
+        let message = format!(
+            "Database unreachable after repeated connection failures; probing again in {} ms",
+            open_for.as_millis()
+        );
+        let report = ReadinessReport::from_checks(vec![CheckResult::failed("database", true, Instant::now(), message)]);
+        return (report.http_status(), Json(serde_json::to_value(report).unwrap_or_default()));
+    }
+
+    let report = state.health.readiness(&state).await;
+    let status = report.http_status();
+
+    (status, Json(serde_json::to_value(report).unwrap_or_default()))
+}
+
+/// Run all dependency checks concurrently
+async fn run_checks(state: &AppState) -> Vec<CheckResult> {
+    let storage = &state.config.features.file_storage;
+    let storage_path = (storage.type_ == "local").then(|| storage.local_path.clone());
+
+    let (database, redis, temp_dir, storage_dir) = tokio::join!(
+        check_database(&state.db_pool),
+        check_redis(state),
+        check_writable("latex_temp_dir", &state.config.latex.temp_dir),
+        async {
+            match &storage_path {
+                Some(path) => Some(check_writable("file_storage", path).await),
+                None => None,
+            }
+        },
+    );
+
+    state.db_resilience.record_probe(database.status == CheckStatus::Ok);
+    let mut checks = vec![database];
+    checks.extend(redis);
+    checks.push(temp_dir);
+    checks.extend(storage_dir);
+    checks.extend(check_compile_sandbox(state.compile_sandbox.last_self_test()));
+    checks
+}
+
+/// `SELECT 1` against the pool with a short timeout
+async fn check_database(db: &sqlx::PgPool) -> CheckResult {
+    let started = Instant::now();
+    let query = sqlx::query_scalar::<_, i32>("SELECT 1").fetch_one(db);
+
+    match tokio::time::timeout(DATABASE_CHECK_TIMEOUT, query).await {
+        Ok(Ok(_)) => CheckResult::ok("database", true, started),
+        Ok(Err(e)) => CheckResult::failed("database", true, started, e.to_string()),
+        Err(_) => CheckResult::failed(
+            "database",
+            true,
+            started,
+            format!("Timed out after {} ms", DATABASE_CHECK_TIMEOUT.as_millis()),
+        ),
+    }
+}
+
+/// Redis PING, only when Redis is configured
+async fn check_redis(state: &AppState) -> Option<CheckResult> {
+    if !state.config.redis.enabled {
+        return None;
+    }
+
+    let started = Instant::now();
+    let ping = async {
+        let client = redis::Client::open(state.config.redis.url.as_str())?;
+        let mut connection = client.get_multiplexed_async_connection().await?;
+        redis::cmd("PING").query_async::<_, String>(&mut connection).await
+    };
+
+    let result = match tokio::time::timeout(REDIS_CHECK_TIMEOUT, ping).await {
+        Ok(Ok(_)) => CheckResult::ok("redis", false, started),
+        Ok(Err(e)) => CheckResult::failed("redis", false, started, e.to_string()),
+        Err(_) => CheckResult::failed(
+            "redis",
+            false,
+            started,
+            format!("Timed out after {} ms", REDIS_CHECK_TIMEOUT.as_millis()),
+        ),
+    };
+
+    Some(result)
+}
+
+/// Verify a directory exists and accepts writes by creating a probe file
+async fn check_writable(name: &'static str, dir: &str) -> CheckResult {
+    let started = Instant::now();
+    let probe = Path::new(dir).join(format!(".texler-health-{}", uuid::Uuid::new_v4()));
+
+    let result = async {
+        tokio::fs::create_dir_all(dir).await?;
+        tokio::fs::write(&probe, b"ok").await?;
+        tokio::fs::remove_file(&probe).await
+    }
+    .await;
+
+    match result {
+        Ok(()) => CheckResult::ok(name, true, started),
+        Err(e) => CheckResult::failed(name, true, started, format!("{} is not writable: {}", dir, e)),
+    }
+}
+
+/// The latest compile sandbox self-test must not have failed probes
+fn check_compile_sandbox(self_test: Option<SelfTestReport>) -> Option<CheckResult> {
+    let started = Instant::now();
+    let failures = self_test?.failures();
+
+    if failures.is_empty() {
+        Some(CheckResult::ok("compile_sandbox", false, started))
+    } else {
+        Some(CheckResult::failed(
+            "compile_sandbox",
+            false,
+            started,
+            format!("Degraded security: compile sandbox probes failed: {}", failures.join(", ")),
+        ))
+    }
+}
+
+/// Fold individual check results into the overall readiness state
+fn overall_status(checks: &[CheckResult]) -> ReadinessStatus {
+    if checks.iter().any(|c| c.hard && c.status == CheckStatus::Failed) {
+        ReadinessStatus::Unavailable
+    } else if checks.iter().any(|c| c.status != CheckStatus::Ok) {
+        ReadinessStatus::Degraded
+    } else {
+        ReadinessStatus::Ready
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{test_config, DbProxy, TestDb};
+    use axum::{body::Body, http::Request, routing::get, Router};
+    use std::sync::Arc;
+    use tower::ServiceExt;
+
+    /// State over the test database with writable scratch directories
+    async fn ready_state(db: &TestDb, scratch: &tempfile::TempDir) -> AppState {
+        let mut config = test_config();
+        config.redis.enabled = false;
+        config.latex.temp_dir = scratch.path().join("latex").to_string_lossy().into_owned();
+        config.features.file_storage.type_ = "local".to_string();
+        config.features.file_storage.local_path = scratch.path().join("files").to_string_lossy().into_owned();
+        AppState::new(config, db.pool.clone()).await.unwrap()
+    }
+
+    async fn probe(state: AppState) -> (StatusCode, serde_json::Value) {
+        let response = Router::new()
+            .route("/health/ready", get(readiness))
+            .with_state(state)
+            .oneshot(Request::get("/health/ready").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let status = response.status();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, serde_json::from_slice(&bytes).unwrap())
+    }
+
+    fn check_status<'a>(report: &'a serde_json::Value, name: &str) -> Option<&'a str> {
+        report["checks"].as_array()?.iter().find(|c| c["name"] == name)?["status"].as_str()
+    }
+
+    fn check(name: &'static str, hard: bool, status: CheckStatus) -> CheckResult {
+        CheckResult {
+            name,
+            status,
+            hard,
+            latency_ms: 0,
+            message: None,
+        }
+    }
+
+    #[test]
+    fn test_overall_status() {
+        let all_ok = vec![check("database", true, CheckStatus::Ok), check("redis", false, CheckStatus::Ok)];
+        assert_eq!(overall_status(&all_ok), ReadinessStatus::Ready);
+
+        let redis_down = vec![check("database", true, CheckStatus::Ok), check("redis", false, CheckStatus::Degraded)];
+        assert_eq!(overall_status(&redis_down), ReadinessStatus::Degraded);
+
+        let db_down = vec![check("database", true, CheckStatus::Failed), check("redis", false, CheckStatus::Ok)];
+        assert_eq!(overall_status(&db_down), ReadinessStatus::Unavailable);
+    }
+
+    #[test]
+    fn test_soft_failures_degrade() {
+        let result = CheckResult::failed("redis", false, Instant::now(), "down".to_string());
+        assert_eq!(result.status, CheckStatus::Degraded);
+
+        let result = CheckResult::failed("database", true, Instant::now(), "down".to_string());
+        assert_eq!(result.status, CheckStatus::Failed);
+    }
+
+    #[test]
+    fn test_failed_sandbox_probes_degrade() {
+        use crate::models::compile_sandbox::{Probe, ProbeResult, ProbeStatus};
+        use crate::models::LatexEngine;
+
+        assert!(check_compile_sandbox(None).is_none());
+
+        let report = |status| SelfTestReport {
+            results: vec![ProbeResult {
+                probe: Probe::ShellEscape,
+                engine: LatexEngine::Pdflatex,
+                status,
+                detail: String::new(),
+            }],
+            passed: 0,
+            failed: 0,
+            inconclusive: 0,
+            ran_at: Utc::now(),
+            duration_ms: 0,
+        };
+        let passed = check_compile_sandbox(Some(report(ProbeStatus::Passed))).unwrap();
+        assert_eq!(passed.status, CheckStatus::Ok);
+
+        let failed = check_compile_sandbox(Some(report(ProbeStatus::Failed))).unwrap();
+        assert_eq!(failed.status, CheckStatus::Degraded);
+        assert_eq!(
+            failed.message.as_deref(),
+            Some("Degraded security: compile sandbox probes failed: shell_escape (pdflatex)")
+        );
+        assert_eq!(overall_status(&[failed]), ReadinessStatus::Degraded);
+    }
+
+    #[tokio::test]
+    async fn test_ready_when_every_dependency_answers() {
+        let Some(db) = TestDb::start().await else { return };
+        let scratch = tempfile::tempdir().unwrap();
+
+        let (status, report) = probe(ready_state(&db, &scratch).await).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(report["status"], "ready");
+        let names: Vec<_> = report["checks"].as_array().unwrap().iter().map(|c| c["name"].as_str().unwrap()).collect();
+        assert_eq!(names, ["database", "latex_temp_dir", "file_storage"]);
+        assert!(report["checks"].as_array().unwrap().iter().all(|c| c["status"] == "ok"));
+    }
+
+    #[tokio::test]
+    async fn test_unwritable_directory_makes_instance_unready() {
+        let Some(db) = TestDb::start().await else { return };
+        let scratch = tempfile::tempdir().unwrap();
+        let mut state = ready_state(&db, &scratch).await;
+        // A directory cannot be created below a regular file
+        let blocker = scratch.path().join("blocker");
+        std::fs::write(&blocker, b"").unwrap();
+        let mut config = (*state.config).clone();
+        config.latex.temp_dir = blocker.join("latex").to_string_lossy().into_owned();
+        state.config = Arc::new(config);
+
+        let (status, report) = probe(state).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(report["status"], "unavailable");
+        assert_eq!(check_status(&report, "latex_temp_dir"), Some("failed"));
+        assert_eq!(check_status(&report, "database"), Some("ok"));
+    }
+
+    #[tokio::test]
+    async fn test_unreachable_database_makes_instance_unready() {
+        let Some(db) = TestDb::start().await else { return };
+        let scratch = tempfile::tempdir().unwrap();
+        let (proxy, pool) = DbProxy::start(&db).await;
+        let mut state = ready_state(&db, &scratch).await;
+        state.db_pool = pool;
+
+        proxy.refuse(true);
+        proxy.sever();
+        let (status, report) = probe(state.clone()).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(check_status(&report, "database"), Some("failed"));
+        assert_eq!(check_status(&report, "latex_temp_dir"), Some("ok"));
+
+        // The report is reused for a moment, then the checks run again
+        proxy.refuse(false);
+        let (status, _) = probe(state.clone()).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        tokio::time::sleep(READINESS_CACHE_TTL).await;
+        let (status, report) = probe(state).await;
+        assert_eq!((status, report["status"].as_str()), (StatusCode::OK, Some("ready")));
+    }
+}
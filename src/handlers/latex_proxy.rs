@@ -1,28 +1,34 @@
 //! LaTeX compilation proxy handler
 //!
 //! This module provides a simple proxy to the LaTeX compilation service
-//! for development and testing purposes.
+//! for development and testing purposes, and renders inline math snippets
+//! for chat.
 
-use crate::error::AppError;
+use crate::error::{AppError, ErrorResponse};
 use axum::{
-    extract::State,
-    http::StatusCode,
+    extract::{Path, State},
+    http::{header, HeaderMap, HeaderValue, StatusCode},
     response::IntoResponse,
     Json,
 };
+use crate::models::{ApiResponse, LatexEngine};
+use crate::models::compile_environment::{CompileEnvironment, TexDistribution};
+use crate::models::inline_render::{asset_url, INLINE_RENDER_LIMIT};
 use crate::server::AppState;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use utoipa::ToSchema;
 
 /// LaTeX compilation request (matching the frontend's expected format)
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 pub struct LatexCompileRequest {
+    /// File contents keyed by path
     pub files: HashMap<String, String>,
     pub main_file: String,
 }
 
 /// LaTeX compilation response (matching the existing Python service format)
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, ToSchema)]
 pub struct LatexCompileResponse {
     pub success: bool,
     pub output: String,
@@ -32,7 +38,44 @@ pub struct LatexCompileResponse {
     pub parsed_errors: Vec<serde_json::Value>,
 }
 
+/// LaTeX proxy health response
+#[derive(Debug, Serialize, ToSchema)]
+pub struct LatexHealthResponse {
+    pub status: String,
+    pub latex_service: String,
+    pub engine: LatexEngine,
+    pub engine_version: Option<String>,
+    pub distribution: Option<TexDistribution>,
+}
+
+/// Inline math render request
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct InlineRenderRequest {
+    /// Math snippet, with or without `$`, `$$`, `\(..\)` or `\[..\]` delimiters
+    pub snippet: String,
+}
+
+/// Inline math render response
+#[derive(Debug, Serialize, ToSchema)]
+pub struct InlineRenderResponse {
+    pub hash: String,
+    /// Where the SVG is served
+    pub url: String,
+    pub svg: String,
+}
+
 /// Proxy LaTeX compilation requests to the LaTeX service
+#[utoipa::path(
+    post,
+    path = "/compile",
+    request_body = LatexCompileRequest,
+    responses(
+        (status = 200, description = "Result from the LaTeX service, passed through unchanged", body = LatexCompileResponse),
+        (status = 500, description = "LaTeX service failed the compile", body = ErrorResponse),
+        (status = 502, description = "LaTeX service unreachable", body = ErrorResponse),
+    ),
+    security(())
+)]
 pub async fn compile_latex(
     State(state): State<AppState>,
     Json(payload): Json<LatexCompileRequest>,
@@ -41,18 +84,17 @@ pub async fn compile_latex(
     let latex_service_url = std::env::var("LATEX_SERVICE_URL")
         .unwrap_or_else(|_| "http://latex:8081".to_string());
 
-    let client = reqwest::Client::new();
-
     // Forward the request to the LaTeX service
-    let response = client
-        .post(format!("{}/compile", latex_service_url))
+    let request = state
+        .http_client
+        .request(reqwest::Method::POST, &format!("{}/compile", latex_service_url))
         .json(&serde_json::json!({
             "files": payload.files,
             "mainFile": payload.main_file
         }))
-        .send()
-        .await
-        .map_err(|e| AppError::Internal(format!("Failed to connect to LaTeX service: {}", e)))?;
+        .build()
+        .map_err(|e| AppError::Internal(format!("Failed to build LaTeX service request: {}", e)))?;
+    let response = state.http_client.send("latex", request).await?;
 
     if response.status().is_success() {
         let latex_response: serde_json::Value = response
@@ -72,20 +114,109 @@ pub async fn compile_latex(
 }
 
 /// Health check for LaTeX proxy
-pub async fn latex_health_check() -> Result<impl IntoResponse, AppError> {
+#[utoipa::path(
+    get,
+    path = "/health",
+    responses(
+        (status = 200, description = "LaTeX service reachable, with the default engine's environment", body = LatexHealthResponse),
+        (status = 500, description = "LaTeX service unhealthy", body = ErrorResponse),
+        (status = 502, description = "LaTeX service unreachable", body = ErrorResponse),
+    ),
+    security(())
+)]
+pub async fn latex_health_check(State(state): State<AppState>) -> Result<impl IntoResponse, AppError> {
     let latex_service_url = std::env::var("LATEX_SERVICE_URL")
         .unwrap_or_else(|_| "http://latex:8081".to_string());
 
-    let client = reqwest::Client::new();
+    let request = state
+        .http_client
+        .request(reqwest::Method::GET, &format!("{}/health", latex_service_url))
+        .build()
+        .map_err(|e| AppError::Internal(format!("Failed to build LaTeX service request: {}", e)))?;
 
-    match client.get(format!("{}/health", latex_service_url)).send().await {
+    match state.http_client.send("latex", request).await {
         Ok(response) if response.status().is_success() => {
-            Ok(Json(serde_json::json!({
-                "status": "ok",
-                "latex_service": "connected"
-            })))
+            let engine: LatexEngine = serde_json::from_value(
+                serde_json::Value::String(state.config.latex.default_engine.clone()),
+            )
+            .unwrap_or_default();
+            let environment = CompileEnvironment::capture(engine).await;
+
+            Ok(Json(LatexHealthResponse {
+                status: "ok".to_string(),
+                latex_service: "connected".to_string(),
+                engine: environment.engine,
+                engine_version: environment.engine_version,
+                distribution: environment.distribution,
+            }))
         }
         Ok(_) => Err(AppError::Internal("LaTeX service is unhealthy".to_string())),
-        Err(e) => Err(AppError::Internal(format!("Failed to connect to LaTeX service: {}", e))),
+        Err(e) => Err(e),
+    }
+}
+/// Render a math snippet to SVG
+///
+/// Identical snippets are rendered once and share a URL.
+#[utoipa::path(
+    post,
+    path = "/render-inline",
+    request_body = InlineRenderRequest,
+    responses(
+        (status = 200, description = "Rendered snippet", body = ApiResponse<InlineRenderResponse>),
+        (status = 400, description = "Snippet not allowed, does not compile or took too long", body = ErrorResponse),
+        (status = 429, description = "Too many renders", body = ErrorResponse),
+    )
+)]
+pub async fn render_inline(
+    State(state): State<AppState>,
+    auth_user: axum::Extension<crate::models::auth::AuthContext>,
+    Json(payload): Json<InlineRenderRequest>,
+) -> Result<impl IntoResponse, AppError> {
+    let key = format!("inline_render:{}", auth_user.user_id);
+    if !state.rate_limiter.is_allowed(&key, &INLINE_RENDER_LIMIT).await {
+        return Err(AppError::RateLimit);
     }
-}
\ No newline at end of file
+
+    let rendered = state.inline_renderer.render(&payload.snippet).await?;
+
+    Ok(Json(serde_json::json!({
+        "success": true,
+        "data": InlineRenderResponse {
+            url: asset_url(&rendered.hash),
+            svg: String::from_utf8_lossy(&rendered.svg).into_owned(),
+            hash: rendered.hash,
+        }
+    })))
+}
+
+/// Rendered math snippet
+#[utoipa::path(
+    get,
+    path = "/render-inline/{hash}",
+    params(
+        ("hash" = String, Path, description = "Snippet hash")
+    ),
+    responses(
+        (status = 200, description = "SVG image", content_type = "image/svg+xml", body = String),
+        (status = 404, description = "Snippet not rendered", body = ErrorResponse),
+    ),
+    security(())
+)]
+pub async fn get_inline_render(
+    State(state): State<AppState>,
+    Path(hash): Path<String>,
+) -> Result<impl IntoResponse, AppError> {
+    let svg = state.inline_renderer.cached(&hash).await.ok_or_else(|| AppError::NotFound {
+        entity: "Rendered snippet".to_string(),
+        id: hash.clone(),
+    })?;
+
+    // The hash covers the snippet and the template, so a render never changes
+    let mut headers = HeaderMap::new();
+    headers.insert(header::CONTENT_TYPE, HeaderValue::from_static("image/svg+xml"));
+    headers.insert(header::CACHE_CONTROL, HeaderValue::from_static("public, max-age=31536000, immutable"));
+    headers.insert(header::CONTENT_SECURITY_POLICY, HeaderValue::from_static("default-src 'none'; style-src 'unsafe-inline'"));
+    headers.insert(header::X_CONTENT_TYPE_OPTIONS, HeaderValue::from_static("nosniff"));
+
+    Ok((headers, svg.as_ref().clone()))
+}
@@ -1,10 +1,17 @@
 //! API request handlers
 
+pub mod admin;
 pub mod auth;
+pub mod capabilities;
 pub mod collaboration;
 pub mod compilation;
+pub mod dictionary;
+pub mod discovery;
 pub mod file;
+pub mod health;
 pub mod latex_proxy;
 pub mod project;
+pub mod search;
+pub mod shared;
 pub mod user;
 pub mod workspace;
@@ -0,0 +1,90 @@
+//! Search handlers
+
+use crate::error::{AppError, ErrorResponse};
+use crate::models::quick_search::{self, QuickSearchKind, QuickSearchParams, QuickSearchResult, QUICK_SEARCH_BUDGET};
+use crate::models::ApiResponse;
+use crate::server::AppState;
+use axum::{
+    extract::{Query, State},
+    response::IntoResponse,
+    Json,
+};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+/// Quick search response
+#[derive(Debug, Serialize, ToSchema)]
+pub struct QuickSearchResponse {
+    /// Best matches first
+    pub results: Vec<QuickSearchResult>,
+    /// Kinds of results left out because looking for them took too long
+    pub dropped: Vec<QuickSearchKind>,
+}
+
+/// Quick search for the command palette
+///
+/// Matches projects, files and members of the current project, open
+/// collaboration sessions and actions in one ranked list. Only what the user
+/// can open is returned; typos are tolerated.
+#[utoipa::path(
+    get,
+    path = "/quick",
+    params(QuickSearchParams),
+    responses(
+        (status = 200, description = "Ranked results", body = ApiResponse<QuickSearchResponse>),
+        (status = 400, description = "Empty or overlong query", body = ErrorResponse),
+    )
+)]
+pub async fn quick_search(
+    State(state): State<AppState>,
+    auth_user: axum::Extension<crate::models::auth::AuthContext>,
+    Query(params): Query<QuickSearchParams>,
+) -> Result<impl IntoResponse, AppError> {
+    let found = quick_search::search(&state.db_pool, auth_user.user_id, &params, QUICK_SEARCH_BUDGET).await?;
+
+    Ok(Json(serde_json::json!({
+        "success": true,
+        "data": QuickSearchResponse { results: found.results, dropped: found.dropped }
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{create_test_project, create_test_user, oneshot_as, test_state, TestDb};
+    use axum::body::Body;
+    use axum::http::{Request, StatusCode};
+    use axum::routing::get;
+    use axum::Router;
+
+    #[tokio::test]
+    async fn test_quick_search_validates_and_ranks() {
+        let Some(db) = TestDb::start().await else { return };
+        let state = test_state(&db).await;
+        let user = create_test_user(&db.pool).await;
+        let project = create_test_project(&db.pool, &user, false).await;
+        let router = || Router::new().route("/quick", get(quick_search));
+
+        let (status, body) = oneshot_as(
+            router(),
+            state.clone(),
+            &user,
+            Request::get("/quick?q=%20%20").body(Body::empty()).unwrap(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{}", body);
+
+        let (status, body) = oneshot_as(
+            router(),
+            state.clone(),
+            &user,
+            Request::get(format!("/quick?q=test&project_id={}", project.id)).body(Body::empty()).unwrap(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        let results = body["data"]["results"].as_array().unwrap();
+        assert_eq!(results[0]["kind"], "project");
+        assert_eq!(results[0]["id"], project.id.to_string());
+        assert_eq!(body["data"]["dropped"].as_array().unwrap().len(), 0);
+    }
+}
@@ -0,0 +1,248 @@
+//! Handlers of file share links
+//!
+//! Public: the token in the path is the only credential, and an unknown,
+//! revoked or expired one gives the same 404 as a link that never existed.
+//! Responses are private to the viewer and not stored by caches, since a
+//! link can stop working at any time.
+
+use crate::error::{AppError, ErrorResponse};
+use crate::models::compilation::check_engine_enabled;
+use crate::models::compile_diff;
+use crate::models::file::File;
+use crate::models::file_share::{self, FileShareLink, FileShareMode, SharedFileRender};
+use crate::models::integrity::ReadPath;
+use crate::models::{ApiResponse, CompilationStatus, ContentType};
+use crate::server::AppState;
+use axum::{
+    extract::{Path, State},
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+/// Responses of share links may only be kept by the viewer's browser
+const SHARED_CACHE_CONTROL: &str = "private, no-store";
+
+/// A file as seen through a share link
+#[derive(Debug, Serialize, ToSchema)]
+pub struct SharedFileView {
+    pub name: String,
+    pub path: String,
+    pub size: i64,
+    pub content_type: ContentType,
+    pub updated_at: DateTime<Utc>,
+    pub mode: FileShareMode,
+    pub expires_at: Option<DateTime<Utc>>,
+    /// Content of `source` links, unset for files that are not text
+    pub content: Option<String>,
+    /// Standalone compile of `pdf` links
+    pub render: Option<SharedFileRender>,
+}
+
+/// The working link `token` and its file, or the 404 of a missing link
+async fn open_link(state: &AppState, token: &str) -> Result<(FileShareLink, File), AppError> {
+    let (link, file) = FileShareLink::use_token(&state.db_pool, token)
+        .await?
+        .ok_or_else(|| AppError::NotFound {
+            entity: "Share link".to_string(),
+            id: token.to_string(),
+        })?;
+    file.ensure_downloadable()?;
+    Ok((link, file))
+}
+
+/// View a shared file
+///
+/// Source links show the content of the file. PDF links queue a standalone
+/// compile of the file alone on first view and report its progress; once it
+/// succeeded, `render.pdf_url` serves the PDF.
+#[utoipa::path(
+    get,
+    path = "/files/{token}",
+    params(("token" = String, Path, description = "Share link token")),
+    responses(
+        (status = 200, description = "The shared file", body = ApiResponse<SharedFileView>),
+        (status = 403, description = "File is quarantined", body = ErrorResponse),
+        (status = 404, description = "Share link not found", body = ErrorResponse),
+    ),
+    security(())
+)]
+pub async fn get_shared_file(
+    State(state): State<AppState>,
+    Path(token): Path<String>,
+) -> Result<impl IntoResponse, AppError> {
+    let (link, file) = open_link(&state, &token).await?;
+    let storage = &state.config.features.file_storage;
+
+    let (content, render) = match link.mode {
+        FileShareMode::Source => {
+            let bytes = file.read_verified(&state.db_pool, storage, ReadPath::Download).await?;
+            (String::from_utf8(bytes).ok(), None)
+        }
+        FileShareMode::Pdf => {
+            let bytes = file.read_verified(&state.db_pool, storage, ReadPath::Compile).await?;
+            let document = file_share::standalone_document(&String::from_utf8_lossy(&bytes));
+            let engine = file_share::render_engine(&state.db_pool, &file).await?;
+            check_engine_enabled(&state.config.latex, engine)?;
+            let job = file_share::request_render(&state.db_pool, &file, link.created_by, engine, &document).await?;
+            (None, Some(SharedFileRender::of(&job, &token)))
+        }
+    };
+
+    let view = SharedFileView {
+        name: file.name,
+        path: file.path,
+        size: file.size,
+        content_type: file.content_type,
+        updated_at: file.updated_at,
+        mode: link.mode,
+        expires_at: link.expires_at,
+        content,
+        render,
+    };
+
+    Ok((
+        [(header::CACHE_CONTROL, SHARED_CACHE_CONTROL)],
+        Json(serde_json::json!({
+            "success": true,
+            "data": view
+        })),
+    ))
+}
+
+/// Get the PDF of a shared file
+///
+/// The standalone compile of the file as it is now, streamed inline. Gives
+/// 404 until the compile queued by viewing the link has succeeded, and again
+/// after the file changed until it is viewed and compiled anew.
+#[utoipa::path(
+    get,
+    path = "/files/{token}/pdf",
+    params(("token" = String, Path, description = "Share link token")),
+    responses(
+        (status = 200, description = "PDF of the shared file", content_type = "application/pdf", body = Vec<u8>),
+        (status = 403, description = "File is quarantined", body = ErrorResponse),
+        (status = 404, description = "Share link not found, not a PDF link, or no PDF yet", body = ErrorResponse),
+    ),
+    security(())
+)]
+pub async fn get_shared_file_pdf(
+    State(state): State<AppState>,
+    Path(token): Path<String>,
+) -> Result<Response, AppError> {
+    let (link, file) = open_link(&state, &token).await?;
+    let no_pdf = || AppError::NotFound {
+        entity: "PDF".to_string(),
+        id: token.clone(),
+    };
+    if link.mode != FileShareMode::Pdf {
+        return Err(no_pdf());
+    }
+
+    let bytes = file
+        .read_verified(&state.db_pool, &state.config.features.file_storage, ReadPath::Compile)
+        .await?;
+    let document = file_share::standalone_document(&String::from_utf8_lossy(&bytes));
+    let engine = file_share::render_engine(&state.db_pool, &file).await?;
+    let hash = file_share::document_hash(&document, file.path.trim_start_matches('/'), engine);
+    let job = file_share::find_render(&state.db_pool, &hash)
+        .await?
+        .filter(|job| job.status == CompilationStatus::Success)
+        .ok_or_else(no_pdf)?;
+    let path = compile_diff::primary_pdf(&job).await.ok_or_else(no_pdf)?;
+    let metadata = tokio::fs::metadata(&path).await?;
+
+    let mut headers = HeaderMap::new();
+    headers.insert(header::CONTENT_TYPE, HeaderValue::from_static("application/pdf"));
+    headers.insert(header::CONTENT_LENGTH, HeaderValue::from(metadata.len()));
+    headers.insert(header::CACHE_CONTROL, HeaderValue::from_static(SHARED_CACHE_CONTROL));
+    headers.insert(header::X_CONTENT_TYPE_OPTIONS, HeaderValue::from_static("nosniff"));
+    let stem = file.name.rsplit_once('.').map_or(file.name.as_str(), |(stem, _)| stem);
+    let disposition = format!("inline; filename=\"{}.pdf\"", stem.replace('"', ""));
+    if let Ok(value) = HeaderValue::from_str(&disposition) {
+        headers.insert(header::CONTENT_DISPOSITION, value);
+    }
+
+    let pdf = tokio::fs::File::open(&path).await?;
+    Ok((StatusCode::OK, headers, crate::handlers::project::file_body(pdf)).into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::UserRole;
+    use crate::testing::{
+        add_collaborator, create_test_file, create_test_project, create_test_user, oneshot_as, test_state, TestDb,
+    };
+    use axum::{body::Body, http::Request, routing::{delete, get}, Router};
+
+    #[tokio::test]
+    async fn test_source_link_shows_the_file_until_revoked() {
+        let Some(db) = TestDb::start().await else { return };
+        let state = test_state(&db).await;
+        let owner = create_test_user(&db.pool).await;
+        let viewer = create_test_user(&db.pool).await;
+        let project = create_test_project(&db.pool, &owner, false).await;
+        add_collaborator(&db.pool, &project, &viewer, UserRole::Viewer).await;
+        let file = create_test_file(&db.pool, &project, &owner).await;
+
+        let router = || {
+            Router::new()
+                .route(
+                    "/files/:id/share-links",
+                    get(crate::handlers::file::list_share_links).post(crate::handlers::file::create_share_link),
+                )
+                .route("/files/:id/share-links/:link_id", delete(crate::handlers::file::revoke_share_link))
+                .route("/shared/files/:token", get(get_shared_file))
+                .route("/shared/files/:token/pdf", get(get_shared_file_pdf))
+        };
+        let create = |body: serde_json::Value| {
+            Request::post(format!("/files/{}/share-links", file.id))
+                .header("content-type", "application/json")
+                .body(Body::from(body.to_string()))
+                .unwrap()
+        };
+        let view = |uri: String| Request::get(uri).body(Body::empty()).unwrap();
+
+        let (status, _) = oneshot_as(router(), state.clone(), &viewer, create(serde_json::json!({}))).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let (status, body) = oneshot_as(router(), state.clone(), &owner, create(serde_json::json!({}))).await;
+        assert_eq!(status, StatusCode::CREATED, "{}", body);
+        assert_eq!(body["data"]["mode"], "source");
+        let token = body["data"]["token"].as_str().unwrap().to_string();
+        let link_id = body["data"]["id"].as_str().unwrap().to_string();
+        let share_url = body["data"]["share_url"].as_str().unwrap().trim_start_matches("/api/v1").to_string();
+
+        let (status, body) = oneshot_as(router(), state.clone(), &viewer, view(share_url.clone())).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        assert_eq!(body["data"]["path"], "main.tex");
+        assert!(body["data"]["content"].as_str().unwrap().contains("\\documentclass{article}"));
+        assert!(body["data"]["render"].is_null());
+        // A source link has no PDF
+        let (status, _) = oneshot_as(router(), state.clone(), &viewer, view(format!("{}/pdf", share_url))).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        let (_, body) = oneshot_as(
+            router(),
+            state.clone(),
+            &owner,
+            view(format!("/files/{}/share-links", file.id)),
+        )
+        .await;
+        assert_eq!(body["data"]["links"][0]["hit_count"], 2);
+        assert!(body["data"]["links"][0].get("token").is_none());
+
+        let revoke = Request::delete(format!("/files/{}/share-links/{}", file.id, link_id))
+            .body(Body::empty())
+            .unwrap();
+        let (status, _) = oneshot_as(router(), state.clone(), &owner, revoke).await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, _) = oneshot_as(router(), state.clone(), &viewer, view(share_url)).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let (status, _) = oneshot_as(router(), state.clone(), &viewer, view("/shared/files/unknown".to_string())).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+}
@@ -1,46 +1,119 @@
 //! User request handlers
 
-use crate::error::AppError;
+use crate::error::{AppError, ErrorResponse};
 use crate::models::user::{User, UpdateUser, UserProfile, UserPreferences};
+use crate::models::compile_notification::CompileNotificationPreference;
+use crate::models::activity_digest::{ActivityDigest, DigestFrequency, RenderedDigest};
+use crate::models::mention::MentionNotificationPreference;
+use crate::models::settings_transfer::{SettingsExport, SettingsImportReport};
 use crate::models::UserRole;
+use crate::models::{ApiResponse, PaginationParams};
+use crate::openapi::MessageResponse;
+use crate::models::notification::Notification;
+use crate::models::onboarding::Onboarding;
+use crate::models::announcement::{Acknowledgement, Announcements};
+use crate::models::email_change::{EmailChange, PendingEmailChange, EMAIL_CHANGE_EXPIRATION_HOURS, EMAIL_CHANGE_UNDO_HOURS};
+use crate::models::auth::TokenPair;
+use crate::email::templates::{EmailChangeConfirmationEmail, EmailChangeNoticeEmail};
+use crate::email::Locale;
+use crate::models::avatar;
+use crate::models::activity_export::{ActivityExport, ActivityExportStatus, ActivityExportView};
 use axum::{
-    extract::{Path, State},
-    http::StatusCode,
+    extract::{Multipart, Path, Query, State},
+    http::{header, HeaderMap, HeaderValue, StatusCode},
     response::IntoResponse,
     Json,
 };
 use crate::server::AppState;
 use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
 use uuid::Uuid;
 
 /// User profile response
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, ToSchema)]
 pub struct UserProfileResponse {
     pub user: UserProfile,
 }
 
+/// Current user response
+#[derive(Debug, Serialize, ToSchema)]
+pub struct CurrentUserResponse {
+    pub user: UserProfile,
+    /// Email change waiting for the new address to confirm it
+    pub pending_email_change: Option<PendingEmailChange>,
+}
+
+/// Pending email change response
+#[derive(Debug, Serialize, ToSchema)]
+pub struct PendingEmailChangeResponse {
+    pub pending_email_change: PendingEmailChange,
+}
+
+/// Confirmed email change response
+#[derive(Debug, Serialize, ToSchema)]
+pub struct EmailChangeConfirmedResponse {
+    pub user: UserProfile,
+    /// Fresh tokens when the change signed out every session, this one included
+    pub tokens: Option<TokenPair>,
+}
+
 /// User preferences response
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, ToSchema)]
 pub struct UserPreferencesResponse {
     pub preferences: UserPreferences,
 }
 
+/// Onboarding state response
+#[derive(Debug, Serialize, ToSchema)]
+pub struct OnboardingResponse {
+    pub onboarding: Onboarding,
+}
+
+/// Announcements acknowledgement response
+#[derive(Debug, Serialize, ToSchema)]
+pub struct AcknowledgementResponse {
+    pub acknowledgement: Acknowledgement,
+}
+
 /// User search response
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, ToSchema)]
 pub struct UserSearchResponse {
     pub users: Vec<UserProfile>,
     pub total: u64,
 }
 
+/// Notification list response
+#[derive(Debug, Serialize, ToSchema)]
+pub struct NotificationListResponse {
+    pub notifications: Vec<Notification>,
+    pub unread: i64,
+}
+
 /// User update request
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 pub struct UserUpdateRequest {
-    pub display_name: Option<String>,
+    pub display_name: Option<crate::models::validation::DisplayName>,
     pub avatar_url: Option<String>,
 }
 
+/// Email change request
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct EmailChangeRequest {
+    pub new_email: String,
+    /// Sign out every session once the change is confirmed
+    #[serde(default)]
+    pub revoke_sessions: bool,
+}
+
+/// Email change confirmation request
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct ConfirmEmailChangeRequest {
+    /// Token sent to the new address
+    pub token: String,
+}
+
 /// User preferences update request
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 pub struct UserPreferencesUpdateRequest {
     pub theme: Option<String>,
     pub language: Option<String>,
@@ -50,17 +123,71 @@ pub struct UserPreferencesUpdateRequest {
     pub word_wrap: Option<bool>,
     pub font_size: Option<i32>,
     pub tab_size: Option<i32>,
+    pub compile_notifications: Option<CompileNotificationPreference>,
+    pub digest_frequency: Option<DigestFrequency>,
+    pub mention_notifications: Option<MentionNotificationPreference>,
+    /// IANA time zone name, such as `Europe/Berlin`
+    pub timezone: Option<String>,
+}
+
+/// Activity digest preview response
+#[derive(Debug, Serialize, ToSchema)]
+pub struct DigestPreviewResponse {
+    pub digest: ActivityDigest,
+    pub rendered: RenderedDigest,
 }
 
 /// User search parameters
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 pub struct UserSearchParams {
     pub query: String,
     pub limit: Option<u32>,
     pub offset: Option<u32>,
 }
 
+/// Notification list parameters
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct NotificationListParams {
+    #[serde(default)]
+    pub unread_only: bool,
+}
+
+/// Settings import parameters
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct SettingsImportParams {
+    /// Report what would change without storing anything
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+/// Multipart form of an avatar upload
+#[derive(Debug, ToSchema)]
+pub struct UploadAvatarForm {
+    /// PNG, JPEG or WebP image of at most 5 MB
+    #[schema(value_type = String, format = Binary)]
+    pub file: Vec<u8>,
+}
+
+/// Avatar parameters
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct AvatarParams {
+    /// Side in pixels, rounded up to a stored size (64 or 256); defaults to 64
+    pub size: Option<u32>,
+    /// Version from `avatar_url`; a current version makes the response cacheable for good
+    pub v: Option<String>,
+}
+
 /// Get current user profile
+#[utoipa::path(
+    get,
+    path = "/",
+    responses(
+        (status = 200, description = "Current user", body = ApiResponse<CurrentUserResponse>),
+    )
+)]
 pub async fn get_current_user(
     State(state): State<AppState>,
     auth_user: axum::Extension<crate::models::auth::AuthContext>,
@@ -72,10 +199,11 @@ pub async fn get_current_user(
             id: auth_user.user_id.to_string(),
         })?;
 
-    let user_profile = UserProfile::from(user);
+    let pending_email_change = EmailChange::pending_for_user(&state.db_pool, user.id).await?;
 
-    let response = UserProfileResponse {
-        user: user_profile,
+    let response = CurrentUserResponse {
+        user: UserProfile::from(user),
+        pending_email_change,
     };
 
     Ok(Json(serde_json::json!({
@@ -85,6 +213,14 @@ pub async fn get_current_user(
 }
 
 /// Update current user profile
+#[utoipa::path(
+    post,
+    path = "/",
+    request_body = UserUpdateRequest,
+    responses(
+        (status = 200, description = "Updated user", body = ApiResponse<UserProfileResponse>),
+    )
+)]
 pub async fn update_user(
     State(state): State<AppState>,
     auth_user: axum::Extension<crate::models::auth::AuthContext>,
@@ -117,6 +253,13 @@ pub async fn update_user(
 }
 
 /// Get user preferences
+#[utoipa::path(
+    get,
+    path = "/preferences",
+    responses(
+        (status = 200, description = "Editor preferences", body = ApiResponse<UserPreferencesResponse>),
+    )
+)]
 pub async fn get_preferences(
     State(state): State<AppState>,
     auth_user: axum::Extension<crate::models::auth::AuthContext>,
@@ -141,6 +284,15 @@ pub async fn get_preferences(
 }
 
 /// Update user preferences
+#[utoipa::path(
+    post,
+    path = "/preferences",
+    request_body = UserPreferencesUpdateRequest,
+    responses(
+        (status = 200, description = "Updated preferences", body = ApiResponse<UserPreferencesResponse>),
+        (status = 400, description = "Unknown time zone", body = ErrorResponse),
+    )
+)]
 pub async fn update_preferences(
     State(state): State<AppState>,
     auth_user: axum::Extension<crate::models::auth::AuthContext>,
@@ -189,6 +341,22 @@ pub async fn update_preferences(
         preferences.tab_size = tab_size;
     }
 
+    if let Some(compile_notifications) = payload.compile_notifications {
+        preferences.compile_notifications = compile_notifications;
+    }
+
+    if let Some(digest_frequency) = payload.digest_frequency {
+        preferences.digest_frequency = digest_frequency;
+    }
+
+    if let Some(mention_notifications) = payload.mention_notifications {
+        preferences.mention_notifications = mention_notifications;
+    }
+
+    if let Some(timezone) = payload.timezone {
+        preferences.timezone = crate::models::timezone::parse(&timezone)?.name().to_string();
+    }
+
     let updated_preferences = user.update_preferences(&state.db_pool, &preferences).await?;
 
     let response = UserPreferencesResponse {
@@ -201,7 +369,116 @@ pub async fn update_preferences(
     })))
 }
 
+/// Preview the activity digest for the current period without sending it
+///
+/// Uses the user's digest frequency, or a weekly digest when digests are off,
+/// and the days of the user's time zone.
+#[utoipa::path(
+    post,
+    path = "/digest/preview",
+    responses(
+        (status = 200, description = "Rendered activity digest", body = ApiResponse<DigestPreviewResponse>),
+    )
+)]
+pub async fn preview_digest(
+    State(state): State<AppState>,
+    auth_user: axum::Extension<crate::models::auth::AuthContext>,
+) -> Result<impl IntoResponse, AppError> {
+    let frequency = match DigestFrequency::for_user(&state.db_pool, auth_user.user_id).await? {
+        DigestFrequency::Off => DigestFrequency::Weekly,
+        frequency => frequency,
+    };
+
+    let tz = crate::models::timezone::for_user(&state.db_pool, auth_user.user_id).await?;
+    let period = frequency.current_period(chrono::Utc::now(), tz);
+    let digest = ActivityDigest::build(&state.db_pool, auth_user.user_id, frequency, period).await?;
+    let rendered = digest.render(tz);
+
+    Ok(Json(serde_json::json!({
+        "success": true,
+        "data": DigestPreviewResponse { digest, rendered }
+    })))
+}
+
+/// Export the current user's settings
+///
+/// Returns a versioned JSON file with editor and notification preferences and
+/// the personal dictionary, for importing on another instance. It holds
+/// nothing that identifies the account.
+#[utoipa::path(
+    get,
+    path = "/settings/export",
+    responses(
+        (status = 200, description = "Settings file", body = SettingsExport),
+    )
+)]
+pub async fn export_settings(
+    State(state): State<AppState>,
+    auth_user: axum::Extension<crate::models::auth::AuthContext>,
+) -> Result<impl IntoResponse, AppError> {
+    let user = User::find_by_id(&state.db_pool, auth_user.user_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound {
+            entity: "User".to_string(),
+            id: auth_user.user_id.to_string(),
+        })?;
+
+    let export = SettingsExport::build(&state.db_pool, &user).await?;
+
+    Ok((
+        [(
+            header::CONTENT_DISPOSITION,
+            "attachment; filename=\"texler-settings.json\"",
+        )],
+        Json(export),
+    ))
+}
+
+/// Import a settings file from `export_settings`
+///
+/// Each category applies fully or not at all; unknown and account fields are
+/// skipped with a warning. With `dry_run` nothing is stored and the changes
+/// list what an import would do.
+#[utoipa::path(
+    post,
+    path = "/settings/import",
+    params(SettingsImportParams),
+    request_body = SettingsExport,
+    responses(
+        (status = 200, description = "What changed, what was rejected and what was skipped", body = ApiResponse<SettingsImportReport>),
+        (status = 400, description = "Not a settings file", body = ErrorResponse),
+    )
+)]
+pub async fn import_settings(
+    State(state): State<AppState>,
+    Query(params): Query<SettingsImportParams>,
+    auth_user: axum::Extension<crate::models::auth::AuthContext>,
+    Json(payload): Json<serde_json::Value>,
+) -> Result<impl IntoResponse, AppError> {
+    let user = User::find_by_id(&state.db_pool, auth_user.user_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound {
+            entity: "User".to_string(),
+            id: auth_user.user_id.to_string(),
+        })?;
+
+    let report = SettingsExport::import(&state.db_pool, &user, &payload, params.dry_run).await?;
+
+    Ok(Json(serde_json::json!({
+        "success": true,
+        "data": report
+    })))
+}
+
 /// Search users
+#[utoipa::path(
+    get,
+    path = "/search",
+    request_body = UserSearchParams,
+    responses(
+        (status = 200, description = "Matching active users", body = ApiResponse<UserSearchResponse>),
+    )
+)]
 pub async fn search_users(
     State(state): State<AppState>,
     auth_user: axum::Extension<crate::models::auth::AuthContext>,
@@ -272,6 +549,508 @@ pub async fn search_users(
     })))
 }
 
+/// List the current user's notifications
+#[utoipa::path(
+    get,
+    path = "/notifications",
+    params(NotificationListParams, PaginationParams),
+    responses(
+        (status = 200, description = "Notifications, newest first", body = ApiResponse<NotificationListResponse>),
+    )
+)]
+pub async fn list_notifications(
+    State(state): State<AppState>,
+    Query(params): Query<NotificationListParams>,
+    Query(pagination_params): Query<PaginationParams>,
+    auth_user: axum::Extension<crate::models::auth::AuthContext>,
+) -> Result<impl IntoResponse, AppError> {
+    let notifications = Notification::list_for_user(
+        &state.db_pool,
+        auth_user.user_id,
+        params.unread_only,
+        &pagination_params,
+    )
+    .await?;
+    let unread = Notification::unread_count(&state.db_pool, auth_user.user_id).await?;
+
+    Ok(Json(serde_json::json!({
+        "success": true,
+        "data": NotificationListResponse { notifications, unread }
+    })))
+}
+
+/// Mark one of the current user's notifications as read
+#[utoipa::path(
+    post,
+    path = "/notifications/{id}/read",
+    params(("id" = Uuid, Path, description = "Notification ID")),
+    responses(
+        (status = 200, description = "Marked as read", body = MessageResponse),
+        (status = 404, description = "No such notification", body = ErrorResponse),
+    )
+)]
+pub async fn mark_notification_read(
+    State(state): State<AppState>,
+    Path(notification_id): Path<Uuid>,
+    auth_user: axum::Extension<crate::models::auth::AuthContext>,
+) -> Result<impl IntoResponse, AppError> {
+    if !Notification::mark_read(&state.db_pool, notification_id, auth_user.user_id).await? {
+        return Err(AppError::NotFound {
+            entity: "Notification".to_string(),
+            id: notification_id.to_string(),
+        });
+    }
+
+    Ok(Json(serde_json::json!({
+        "success": true,
+        "message": "Notification marked as read"
+    })))
+}
+
+/// Get a background activity export
+///
+/// Only the user who requested the export can see it.
+#[utoipa::path(
+    get,
+    path = "/activity-exports/{id}",
+    params(("id" = Uuid, Path, description = "Export ID")),
+    responses(
+        (status = 200, description = "The export and, once ready, its download URL", body = ApiResponse<ActivityExportView>),
+        (status = 404, description = "No such export, or it has expired", body = ErrorResponse),
+    )
+)]
+pub async fn get_activity_export(
+    State(state): State<AppState>,
+    Path(export_id): Path<Uuid>,
+    auth_user: axum::Extension<crate::models::auth::AuthContext>,
+) -> Result<impl IntoResponse, AppError> {
+    let export = find_activity_export(&state, export_id, auth_user.user_id).await?;
+
+    Ok(Json(serde_json::json!({
+        "success": true,
+        "data": export.view()
+    })))
+}
+
+/// Download a background activity export
+#[utoipa::path(
+    get,
+    path = "/activity-exports/{id}/download",
+    params(("id" = Uuid, Path, description = "Export ID")),
+    responses(
+        (status = 200, description = "The export as CSV or NDJSON", content_type = "text/csv", body = String,
+            headers(("Content-Disposition" = String, description = "Attachment file name"))),
+        (status = 404, description = "No such export, or it has expired", body = ErrorResponse),
+        (status = 409, description = "The export is not ready or failed", body = ErrorResponse),
+    )
+)]
+pub async fn download_activity_export(
+    State(state): State<AppState>,
+    Path(export_id): Path<Uuid>,
+    auth_user: axum::Extension<crate::models::auth::AuthContext>,
+) -> Result<impl IntoResponse, AppError> {
+    let export = find_activity_export(&state, export_id, auth_user.user_id).await?;
+    match export.status {
+        ActivityExportStatus::Ready => {}
+        ActivityExportStatus::Pending => {
+            return Err(AppError::Conflict("The export is not ready yet".to_string()))
+        }
+        ActivityExportStatus::Failed => {
+            return Err(AppError::Conflict(format!(
+                "The export failed: {}",
+                export.error.as_deref().unwrap_or("unknown error")
+            )))
+        }
+    }
+
+    let path = export.storage_path(&state.config.features.file_storage.local_path);
+    let file = match tokio::fs::File::open(&path).await {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            tracing::warn!("Activity export {} is missing from storage", export.id);
+            return Err(AppError::NotFound {
+                entity: "Activity export".to_string(),
+                id: export_id.to_string(),
+            });
+        }
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut headers = HeaderMap::new();
+    headers.insert(header::CONTENT_TYPE, HeaderValue::from_static(export.format.content_type()));
+    let disposition = format!("attachment; filename=\"{}\"", export.file_name());
+    headers.insert(
+        header::CONTENT_DISPOSITION,
+        HeaderValue::from_str(&disposition)
+            .map_err(|_| AppError::Internal("Invalid export file name".to_string()))?,
+    );
+    if let Some(size) = export.size_bytes {
+        headers.insert(header::CONTENT_LENGTH, HeaderValue::from(size));
+    }
+    headers.insert(header::CACHE_CONTROL, HeaderValue::from_static("no-store"));
+    headers.insert(header::X_CONTENT_TYPE_OPTIONS, HeaderValue::from_static("nosniff"));
+    Ok((headers, crate::handlers::project::file_body(file)))
+}
+
+async fn find_activity_export(state: &AppState, export_id: Uuid, user_id: Uuid) -> Result<ActivityExport, AppError> {
+    ActivityExport::find_for_user(&state.db_pool, export_id, user_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound {
+            entity: "Activity export".to_string(),
+            id: export_id.to_string(),
+        })
+}
+
+/// Upload an avatar
+///
+/// The image is checked by its content, scaled to squares of 64 and 256
+/// pixels and stored without its metadata; `avatar_url` points at the result.
+/// The previous avatar is deleted.
+#[utoipa::path(
+    post,
+    path = "/me/avatar",
+    request_body(content = UploadAvatarForm, content_type = "multipart/form-data"),
+    responses(
+        (status = 200, description = "Avatar stored", body = ApiResponse<UserProfileResponse>),
+        (status = 400, description = "Missing file, not a PNG, JPEG or WebP image, or larger than 5 MB", body = ErrorResponse),
+    )
+)]
+pub async fn upload_avatar(
+    State(state): State<AppState>,
+    auth_user: axum::Extension<crate::models::auth::AuthContext>,
+    mut multipart: Multipart,
+) -> Result<impl IntoResponse, AppError> {
+    let storage = &state.config.features.file_storage;
+    if storage.type_ != "local" {
+        return Err(AppError::Storage("Unsupported storage type".to_string()));
+    }
+
+    while let Some(mut field) = multipart.next_field().await
+        .map_err(|e| AppError::Validation(format!("Failed to read multipart field: {}", e)))?
+    {
+        if field.name() != Some("file") {
+            continue;
+        }
+
+        let mut bytes = Vec::new();
+        while let Some(chunk) = field.chunk().await
+            .map_err(|e| AppError::Validation(format!("Failed to read file content: {}", e)))?
+        {
+            if bytes.len() + chunk.len() > avatar::AVATAR_MAX_SIZE {
+                return Err(AppError::Validation(format!(
+                    "Avatars can be at most {} bytes",
+                    avatar::AVATAR_MAX_SIZE
+                )));
+            }
+            bytes.extend_from_slice(&chunk);
+        }
+
+        let processed = avatar::process_upload(bytes).await?;
+        let user = avatar::store(&state.db_pool, &storage.local_path, auth_user.user_id, &processed).await?;
+
+        return Ok(Json(serde_json::json!({
+            "success": true,
+            "data": UserProfileResponse { user: UserProfile::from(user) }
+        })));
+    }
+
+    Err(AppError::Validation("No file provided".to_string()))
+}
+
+/// Delete the uploaded avatar
+///
+/// The user is shown a generated identicon again.
+#[utoipa::path(
+    delete,
+    path = "/me/avatar",
+    responses(
+        (status = 200, description = "Avatar deleted", body = ApiResponse<UserProfileResponse>),
+    )
+)]
+pub async fn delete_avatar(
+    State(state): State<AppState>,
+    auth_user: axum::Extension<crate::models::auth::AuthContext>,
+) -> Result<impl IntoResponse, AppError> {
+    let root = &state.config.features.file_storage.local_path;
+    let user = avatar::remove(&state.db_pool, root, auth_user.user_id).await?;
+
+    Ok(Json(serde_json::json!({
+        "success": true,
+        "data": UserProfileResponse { user: UserProfile::from(user) }
+    })))
+}
+
+/// Get the first-run onboarding state
+///
+/// Milestones are recorded as they are reached and never unset.
+#[utoipa::path(
+    get,
+    path = "/me/onboarding",
+    responses(
+        (status = 200, description = "Onboarding milestones", body = ApiResponse<OnboardingResponse>),
+    )
+)]
+pub async fn get_onboarding(
+    State(state): State<AppState>,
+    auth_user: axum::Extension<crate::models::auth::AuthContext>,
+) -> Result<impl IntoResponse, AppError> {
+    let onboarding = Onboarding::for_user(&state.db_pool, auth_user.user_id).await?;
+
+    Ok(Json(serde_json::json!({
+        "success": true,
+        "data": OnboardingResponse { onboarding }
+    })))
+}
+
+/// Dismiss the first-run guide
+#[utoipa::path(
+    post,
+    path = "/me/onboarding/dismiss",
+    responses(
+        (status = 200, description = "Onboarding dismissed", body = ApiResponse<OnboardingResponse>),
+    )
+)]
+pub async fn dismiss_onboarding(
+    State(state): State<AppState>,
+    auth_user: axum::Extension<crate::models::auth::AuthContext>,
+) -> Result<impl IntoResponse, AppError> {
+    let onboarding = Onboarding::dismiss(&state.db_pool, auth_user.user_id).await?;
+
+    Ok(Json(serde_json::json!({
+        "success": true,
+        "data": OnboardingResponse { onboarding }
+    })))
+}
+
+/// Get the announcements not acknowledged yet
+///
+/// Notes of the releases since the last acknowledgement, up to the one the
+/// server runs, and instance announcements that started since, newest first.
+#[utoipa::path(
+    get,
+    path = "/me/announcements",
+    responses(
+        (status = 200, description = "Announcements due", body = ApiResponse<Announcements>),
+    )
+)]
+pub async fn get_announcements(
+    State(state): State<AppState>,
+    auth_user: axum::Extension<crate::models::auth::AuthContext>,
+) -> Result<impl IntoResponse, AppError> {
+    let announcements = Announcements::for_user(&state.db_pool, auth_user.user_id, auth_user.is_admin()).await?;
+
+    Ok(Json(serde_json::json!({
+        "success": true,
+        "data": announcements
+    })))
+}
+
+/// Acknowledge the announcements up to the running release and now
+#[utoipa::path(
+    post,
+    path = "/me/announcements/ack",
+    responses(
+        (status = 200, description = "Announcements acknowledged", body = ApiResponse<AcknowledgementResponse>),
+    )
+)]
+pub async fn acknowledge_announcements(
+    State(state): State<AppState>,
+    auth_user: axum::Extension<crate::models::auth::AuthContext>,
+) -> Result<impl IntoResponse, AppError> {
+    let acknowledgement = Announcements::acknowledge(&state.db_pool, auth_user.user_id).await?;
+
+    Ok(Json(serde_json::json!({
+        "success": true,
+        "data": AcknowledgementResponse { acknowledgement }
+    })))
+}
+
+/// Request an email change
+///
+/// Sends a confirmation link to the new address. The account keeps its
+/// current address until the link is used; a pending change is replaced.
+#[utoipa::path(
+    post,
+    path = "/me/email",
+    request_body = EmailChangeRequest,
+    responses(
+        (status = 200, description = "Confirmation sent to the new address", body = ApiResponse<PendingEmailChangeResponse>),
+        (status = 400, description = "Invalid address, or the current one", body = ErrorResponse),
+        (status = 409, description = "Address already in use", body = ErrorResponse),
+    )
+)]
+pub async fn request_email_change(
+    State(state): State<AppState>,
+    auth_user: axum::Extension<crate::models::auth::AuthContext>,
+    Json(payload): Json<EmailChangeRequest>,
+) -> Result<impl IntoResponse, AppError> {
+    let user = User::find_by_id(&state.db_pool, auth_user.user_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound {
+            entity: "User".to_string(),
+            id: auth_user.user_id.to_string(),
+        })?;
+
+    let change = EmailChange::request(&state.db_pool, &user, &payload.new_email, payload.revoke_sessions).await?;
+    let message = EmailChangeConfirmationEmail {
+        username: user.username,
+        confirm_url: format!("{}/confirm-email-change?token={}", state.config.email.frontend_url, change.token),
+        expires_in_hours: EMAIL_CHANGE_EXPIRATION_HOURS,
+    };
+    let locale = Locale::for_user(&state.db_pool, user.id).await?;
+    state.mailer.send(&change.new_email, locale, &message).await?;
+
+    Ok(Json(serde_json::json!({
+        "success": true,
+        "data": PendingEmailChangeResponse {
+            pending_email_change: PendingEmailChange {
+                new_email: change.new_email,
+                requested_at: change.created_at,
+                expires_at: change.expires_at,
+            }
+        }
+    })))
+}
+
+/// Cancel the pending email change
+#[utoipa::path(
+    delete,
+    path = "/me/email",
+    responses(
+        (status = 200, description = "Change cancelled", body = MessageResponse),
+        (status = 404, description = "No pending change", body = ErrorResponse),
+    )
+)]
+pub async fn cancel_email_change(
+    State(state): State<AppState>,
+    auth_user: axum::Extension<crate::models::auth::AuthContext>,
+) -> Result<impl IntoResponse, AppError> {
+    EmailChange::cancel(&state.db_pool, auth_user.user_id).await?;
+
+    Ok(Json(serde_json::json!({
+        "success": true,
+        "message": "Email change cancelled"
+    })))
+}
+
+/// Confirm an email change
+///
+/// Switches the account to the new address, unless it was taken since the
+/// change was requested. The previous address is told about the change and
+/// can undo it for 48 hours.
+#[utoipa::path(
+    post,
+    path = "/me/email/confirm",
+    request_body = ConfirmEmailChangeRequest,
+    responses(
+        (status = 200, description = "Email changed", body = ApiResponse<EmailChangeConfirmedResponse>),
+        (status = 400, description = "Invalid or expired token", body = ErrorResponse),
+        (status = 409, description = "Address taken since the change was requested", body = ErrorResponse),
+    )
+)]
+pub async fn confirm_email_change(
+    State(state): State<AppState>,
+    auth_user: axum::Extension<crate::models::auth::AuthContext>,
+    Json(payload): Json<ConfirmEmailChangeRequest>,
+) -> Result<impl IntoResponse, AppError> {
+    let (change, user) = EmailChange::confirm(&state.db_pool, auth_user.user_id, &payload.token).await?;
+
+    if let Some(undo_token) = &change.undo_token {
+        let message = EmailChangeNoticeEmail {
+            username: user.username.clone(),
+            new_email: change.new_email.clone(),
+            undo_url: format!("{}/undo-email-change?token={}", state.config.email.frontend_url, undo_token),
+            undo_expires_in_hours: EMAIL_CHANGE_UNDO_HOURS,
+        };
+        let locale = Locale::for_user(&state.db_pool, user.id).await?;
+        // The change is made; a lost notice must not make it look failed
+        if let Err(e) = state.mailer.send(&change.old_email, locale, &message).await {
+            tracing::warn!("Failed to send the email change notice of user {}: {}", user.id, e);
+        }
+    }
+
+    let tokens = if change.revoke_sessions {
+        Some(state.jwt_service.generate_token_pair(&user, vec![])?)
+    } else {
+        None
+    };
+
+    Ok(Json(serde_json::json!({
+        "success": true,
+        "data": EmailChangeConfirmedResponse { user: UserProfile::from(user), tokens }
+    })))
+}
+
+/// Get a user's avatar
+///
+/// The uploaded avatar as PNG, or an identicon for users without one.
+/// Public, so it can be used in image tags.
+#[utoipa::path(
+    get,
+    path = "/{id}/avatar",
+    params(("id" = Uuid, Path, description = "User ID"), AvatarParams),
+    responses(
+        (status = 200, description = "Avatar image", content_type = "image/png", body = Vec<u8>,
+            headers(("ETag" = String, description = "Hash of the image"))),
+        (status = 304, description = "The image matches `If-None-Match`"),
+        (status = 404, description = "User not found", body = ErrorResponse),
+    ),
+    security(())
+)]
+pub async fn get_avatar(
+    State(state): State<AppState>,
+    Path(user_id): Path<Uuid>,
+    Query(params): Query<AvatarParams>,
+    request_headers: HeaderMap,
+) -> Result<impl IntoResponse, AppError> {
+    let hash = avatar::current_hash(&state.db_pool, user_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound {
+            entity: "User".to_string(),
+            id: user_id.to_string(),
+        })?;
+    let size = avatar::stored_size(params.size);
+
+    let stored = match &hash {
+        Some(hash) => {
+            let root = &state.config.features.file_storage.local_path;
+            let stored = avatar::read(root, user_id, hash, size).await?;
+            if stored.is_none() {
+                tracing::warn!("Avatar {} of user {} is missing from storage", hash, user_id);
+            }
+            stored
+        }
+        None => None,
+    };
+    // Only a URL naming the current avatar shows the same image forever
+    let pinned = stored.is_some()
+        && matches!((&hash, &params.v), (Some(hash), Some(v)) if v == avatar::version(hash));
+    let content = match stored {
+        Some(content) => content,
+        None => avatar::identicon(user_id, size)?,
+    };
+
+    let etag = {
+        use sha2::{Digest, Sha256};
+        format!("\"{}\"", &hex::encode(Sha256::digest(&content))[..32])
+    };
+    let mut headers = HeaderMap::new();
+    let etag_value = HeaderValue::from_str(&etag)
+        .map_err(|_| AppError::Internal("Invalid avatar ETag".to_string()))?;
+    headers.insert(header::ETAG, etag_value);
+    headers.insert(
+        header::CACHE_CONTROL,
+        HeaderValue::from_static(if pinned { "public, max-age=31536000, immutable" } else { "public, no-cache" }),
+    );
+    if request_headers.get(header::IF_NONE_MATCH).is_some_and(|value| value.as_bytes() == etag.as_bytes()) {
+        return Ok((StatusCode::NOT_MODIFIED, headers, Vec::new()));
+    }
+
+    headers.insert(header::CONTENT_TYPE, HeaderValue::from_static("image/png"));
+    headers.insert(header::X_CONTENT_TYPE_OPTIONS, HeaderValue::from_static("nosniff"));
+    Ok((StatusCode::OK, headers, content))
+}
+
 /// Get user by ID (public profile)
 pub async fn get_user_by_id(
     State(state): State<AppState>,
@@ -333,6 +1112,14 @@ pub async fn get_user_stats(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::testing::{create_test_user, oneshot_as, test_state, TestDb};
+    use axum::{
+        body::Body,
+        http::Request,
+        routing::{get, post},
+        Router,
+    };
+    use tower::ServiceExt;
 
     #[tokio::test]
     async fn test_user_search_validation() {
@@ -350,7 +1137,7 @@ mod tests {
     #[test]
     fn test_user_update_request() {
         let request = UserUpdateRequest {
-            display_name: Some("New Display Name".to_string()),
+            display_name: Some(crate::models::validation::DisplayName::new("New Display Name").unwrap()),
             avatar_url: Some("https://example.com/avatar.png".to_string()),
         };
 
@@ -365,6 +1152,14 @@ mod tests {
             font_size: Some(16),
             auto_save: Some(true),
             tab_size: Some(4),
+            language: None,
+            latex_engine: None,
+            line_numbers: None,
+            word_wrap: None,
+            compile_notifications: None,
+            digest_frequency: None,
+            mention_notifications: None,
+            timezone: None,
         };
 
         assert_eq!(request.theme, Some("dark".to_string()));
@@ -372,4 +1167,242 @@ mod tests {
         assert_eq!(request.auto_save, Some(true));
         assert_eq!(request.tab_size, Some(4));
     }
+
+    #[tokio::test]
+    async fn test_avatar_upload_serving_and_removal() {
+        let Some(db) = TestDb::start().await else { return };
+        let storage = tempfile::tempdir().unwrap();
+        let mut state = test_state(&db).await;
+        let mut config = (*state.config).clone();
+        config.features.file_storage.type_ = "local".to_string();
+        config.features.file_storage.local_path = storage.path().to_string_lossy().into_owned();
+        state.config = std::sync::Arc::new(config);
+        let user = create_test_user(&db.pool).await;
+
+        let router = || {
+            Router::new()
+                .route("/me/avatar", post(upload_avatar).delete(delete_avatar))
+                .route("/:id/avatar", get(get_avatar))
+        };
+        let upload = |content: &[u8]| {
+            let mut body = b"--boundary\r\nContent-Disposition: form-data; name=\"file\"; filename=\"me.png\"\r\n\r\n".to_vec();
+            body.extend_from_slice(content);
+            body.extend_from_slice(b"\r\n--boundary--\r\n");
+            Request::post("/me/avatar")
+                .header("content-type", "multipart/form-data; boundary=boundary")
+                .body(Body::from(body))
+                .unwrap()
+        };
+        let fetch = |uri: String, if_none_match: Option<String>| {
+            let mut request = Request::get(uri);
+            if let Some(etag) = if_none_match {
+                request = request.header(header::IF_NONE_MATCH, etag);
+            }
+            router().with_state(state.clone()).oneshot(request.body(Body::empty()).unwrap())
+        };
+        let png = |color: [u8; 4]| {
+            let mut png = Vec::new();
+            image::DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(300, 200, image::Rgba(color)))
+                .write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png)
+                .unwrap();
+            png
+        };
+        let avatar_dir = storage.path().join("user-avatars").join(user.id.to_string());
+
+        let (status, _) = oneshot_as(router(), state.clone(), &user, upload(b"<svg/>")).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let first = png([0, 128, 0, 255]);
+        let (status, body) = oneshot_as(router(), state.clone(), &user, upload(&first)).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        let first_hash = {
+            use sha2::{Digest, Sha256};
+            hex::encode(Sha256::digest(&first))
+        };
+        let avatar_url = avatar::avatar_url(user.id, Some(&first_hash));
+        assert_eq!(body["data"]["user"]["avatar_url"], avatar_url.as_str());
+
+        // The versioned URL is cacheable for good and revalidates by ETag
+        let response = fetch(avatar_url.trim_start_matches("/api/v1/users").to_string(), None).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "image/png");
+        assert_eq!(response.headers()[header::CACHE_CONTROL], "public, max-age=31536000, immutable");
+        let etag = response.headers()[header::ETAG].to_str().unwrap().to_string();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let served = image::load_from_memory_with_format(&bytes, image::ImageFormat::Png).unwrap();
+        assert_eq!((served.width(), served.height()), (64, 64));
+
+        let response = fetch(format!("/{}/avatar?size=200", user.id), None).await.unwrap();
+        assert_eq!(response.headers()[header::CACHE_CONTROL], "public, no-cache");
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let served = image::load_from_memory_with_format(&bytes, image::ImageFormat::Png).unwrap();
+        assert_eq!((served.width(), served.height()), (256, 256));
+
+        let response = fetch(format!("/{}/avatar", user.id), Some(etag)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+
+        // A new avatar replaces the files of the old one
+        let (status, body) = oneshot_as(router(), state.clone(), &user, upload(&png([0, 0, 128, 255]))).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        let files: Vec<String> = std::fs::read_dir(&avatar_dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        assert_eq!(files.len(), 2);
+        assert!(files.iter().all(|name| !name.starts_with(&first_hash)), "{:?}", files);
+
+        // Without an avatar the identicon is served
+        let (status, body) = oneshot_as(
+            router(),
+            state.clone(),
+            &user,
+            Request::delete("/me/avatar").body(Body::empty()).unwrap(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        assert_eq!(body["data"]["user"]["avatar_url"], avatar::avatar_url(user.id, None).as_str());
+        assert!(!avatar_dir.exists());
+        let response = fetch(format!("/{}/avatar", user.id), None).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(bytes.to_vec(), avatar::identicon(user.id, 64).unwrap());
+
+        let response = fetch(format!("/{}/avatar", Uuid::new_v4()), None).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_onboarding_state_and_dismissal() {
+        let Some(db) = TestDb::start().await else { return };
+        let state = test_state(&db).await;
+        let user = create_test_user(&db.pool).await;
+        let router = || {
+            Router::new()
+                .route("/me/onboarding", get(get_onboarding))
+                .route("/me/onboarding/dismiss", post(dismiss_onboarding))
+        };
+
+        let (status, body) = oneshot_as(
+            router(),
+            state.clone(),
+            &user,
+            Request::get("/me/onboarding").body(Body::empty()).unwrap(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        assert!(body["data"]["onboarding"]["first_project_created_at"].is_null());
+        assert_eq!(body["data"]["onboarding"]["has_sample_project"], false);
+        assert_eq!(body["data"]["onboarding"]["completed"], false);
+
+        let (status, body) = oneshot_as(
+            router(),
+            state.clone(),
+            &user,
+            Request::post("/me/onboarding/dismiss").body(Body::empty()).unwrap(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        assert!(body["data"]["onboarding"]["dismissed_at"].is_string());
+    }
+
+    #[tokio::test]
+    async fn test_announcements_until_acknowledged() {
+        let Some(db) = TestDb::start().await else { return };
+        let state = test_state(&db).await;
+        let user = create_test_user(&db.pool).await;
+        let router = || {
+            Router::new()
+                .route("/me/announcements", get(get_announcements))
+                .route("/me/announcements/ack", post(acknowledge_announcements))
+        };
+        let fetch = |user: &User| {
+            oneshot_as(
+                router(),
+                state.clone(),
+                user,
+                Request::get("/me/announcements").body(Body::empty()).unwrap(),
+            )
+        };
+
+        let (status, body) = fetch(&user).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        assert_eq!(body["data"]["current_version"], crate::VERSION);
+        assert!(body["data"]["acknowledged_version"].is_null());
+        let announcements = body["data"]["announcements"].as_array().unwrap();
+        assert!(announcements.iter().all(|a| a["source"] == "release" && a["audience"] == "everyone"));
+
+        let (status, body) = oneshot_as(
+            router(),
+            state.clone(),
+            &user,
+            Request::post("/me/announcements/ack").body(Body::empty()).unwrap(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        assert_eq!(body["data"]["acknowledgement"]["version"], crate::VERSION);
+
+        let (status, body) = fetch(&user).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        assert_eq!(body["data"]["announcements"].as_array().unwrap().len(), 0);
+        assert_eq!(body["data"]["acknowledged_version"], crate::VERSION);
+    }
+
+    #[tokio::test]
+    async fn test_email_change_flow() {
+        let Some(db) = TestDb::start().await else { return };
+        let state = test_state(&db).await;
+        let user = create_test_user(&db.pool).await;
+        let new_email = format!("changed_{}@example.com", &Uuid::new_v4().simple().to_string()[..12]);
+        let router = || {
+            Router::new()
+                .route("/", get(get_current_user))
+                .route("/me/email", post(request_email_change).delete(cancel_email_change))
+                .route("/me/email/confirm", post(confirm_email_change))
+        };
+        let json = |value: serde_json::Value| Body::from(value.to_string());
+
+        let (status, body) = oneshot_as(
+            router(),
+            state.clone(),
+            &user,
+            Request::post("/me/email")
+                .header("content-type", "application/json")
+                .body(json(serde_json::json!({ "new_email": new_email, "revoke_sessions": true })))
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+
+        let (status, body) = oneshot_as(router(), state.clone(), &user, Request::get("/").body(Body::empty()).unwrap()).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        assert_eq!(body["data"]["user"]["email"], user.email.as_str());
+        assert_eq!(body["data"]["pending_email_change"]["new_email"], new_email.as_str());
+
+        let token: String = sqlx::query_scalar("SELECT token FROM email_changes WHERE user_id = $1 AND status = 'pending'")
+            .bind(user.id)
+            .fetch_one(&db.pool)
+            .await
+            .unwrap();
+        let (status, body) = oneshot_as(
+            router(),
+            state.clone(),
+            &user,
+            Request::post("/me/email/confirm")
+                .header("content-type", "application/json")
+                .body(json(serde_json::json!({ "token": token })))
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        assert_eq!(body["data"]["user"]["email"], new_email.as_str());
+        // Every session was signed out, so this one gets new tokens
+        let access_token = body["data"]["tokens"]["access_token"].as_str().unwrap();
+        state.jwt_service.verify_token_with_db(access_token, &db.pool).await.unwrap();
+
+        let (status, body) = oneshot_as(router(), state.clone(), &user, Request::get("/").body(Body::empty()).unwrap()).await;
+        assert_eq!(status, StatusCode::OK, "{}", body);
+        assert!(body["data"]["pending_email_change"].is_null());
+        let (status, _) = oneshot_as(router(), state, &user, Request::delete("/me/email").body(Body::empty()).unwrap()).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
 }
@@ -3,18 +3,25 @@
 use std::collections::HashMap;
 
 use axum::{
-    extract::{Path, State},
+    extract::{Path, Query, State},
     response::IntoResponse,
     Json,
     Extension,
 };
 use serde::Serialize;
+use utoipa::ToSchema;
 use uuid::Uuid;
 
-use crate::error::AppError;
+use crate::error::{AppError, ErrorResponse, LimitExceededResponse};
+use crate::models::activity_export::{ActivityExportParams, ActivityExportView, ExportScope};
 use crate::models::auth::AuthContext;
 use crate::models::file::{CreateFile, File};
 use crate::models::project::{CreateProject, Project};
+use crate::models::project_folder::{MoveProjectFolder, NewProjectFolder, ProjectFolder, RenameProjectFolder};
+use crate::models::project_limits::ProjectLimits;
+use crate::models::storage_usage::{StorageParams, WorkspaceStorage};
+use crate::models::usage_period::{self, UsageParams, UsagePeriod};
+use crate::models::validation::{FileName, ProjectName};
 use crate::models::workspace::{
     FileUpsert,
     MainFileUpdate,
@@ -25,35 +32,64 @@ use crate::models::workspace::{
     Workspace,
     WorkspaceSummary,
 };
-use crate::models::ContentType;
+use crate::models::{autocomplete, ApiResponse, ContentType};
 use crate::server::AppState;
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, ToSchema)]
 pub struct WorkspaceListResponse {
     pub workspaces: Vec<WorkspaceSummary>,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, ToSchema)]
 pub struct WorkspaceResponse {
     pub workspace: WorkspaceSummary,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, ToSchema)]
+pub struct WorkspaceStorageResponse {
+    pub storage: WorkspaceStorage,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct WorkspaceUsageResponse {
+    /// Closed months as recorded, then the current month, provisional
+    pub usage: Vec<UsagePeriod>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ProjectFoldersResponse {
+    pub folders: Vec<ProjectFolder>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ProjectFolderResponse {
+    pub folder: ProjectFolder,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ProjectFolderDeletedResponse {
+    /// Projects of the folder, now unfiled
+    pub unfiled_projects: u64,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+#[schema(as = WorkspaceProjectResponse)]
 pub struct ProjectResponse {
     pub project: ProjectPayload,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, ToSchema)]
+#[schema(as = WorkspaceFileResponse)]
 pub struct FileResponse {
     pub file: FileResponsePayload,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, ToSchema)]
 pub struct FileResponsePayload {
     pub path: String,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, ToSchema)]
 pub struct ProjectPayload {
     pub id: Uuid,
     pub workspace_id: Uuid,
@@ -66,7 +102,7 @@ pub struct ProjectPayload {
     pub file_count: usize,
 }
 
-#[derive(Debug, Serialize, Clone)]
+#[derive(Debug, Serialize, Clone, ToSchema)]
 pub struct ProjectFilePayload {
     pub path: String,
     pub content: String,
@@ -75,6 +111,13 @@ pub struct ProjectFilePayload {
 }
 
 /// List workspaces for the authenticated user (auto-seeding the default workspace)
+#[utoipa::path(
+    get,
+    path = "/",
+    responses(
+        (status = 200, description = "Workspaces with their projects; a default one is created on first use", body = WorkspaceListResponse),
+    )
+)]
 pub async fn list_workspaces(
     State(state): State<AppState>,
     Extension(auth_user): Extension<AuthContext>,
@@ -91,6 +134,14 @@ pub async fn list_workspaces(
 }
 
 /// Create a new workspace
+#[utoipa::path(
+    post,
+    path = "/",
+    request_body = NewWorkspace,
+    responses(
+        (status = 200, description = "Workspace created", body = WorkspaceResponse),
+    )
+)]
 pub async fn create_workspace(
     State(state): State<AppState>,
     Extension(auth_user): Extension<AuthContext>,
@@ -106,6 +157,15 @@ pub async fn create_workspace(
 }
 
 /// Get workspace with nested projects
+#[utoipa::path(
+    get,
+    path = "/{workspace_id}",
+    params(("workspace_id" = Uuid, Path, description = "Workspace ID")),
+    responses(
+        (status = 200, description = "Workspace with its projects", body = WorkspaceResponse),
+        (status = 404, description = "Workspace not found", body = ErrorResponse),
+    )
+)]
 pub async fn get_workspace(
     State(state): State<AppState>,
     Path(workspace_id): Path<Uuid>,
@@ -115,7 +175,226 @@ pub async fn get_workspace(
     Ok(Json(WorkspaceResponse { workspace }))
 }
 
+/// Export the activity of every project in a workspace
+///
+/// Like the project activity export, across the workspace's current
+/// projects; each row names its project. Workspace owners only.
+#[utoipa::path(
+    get,
+    path = "/{workspace_id}/activity/export",
+    params(("workspace_id" = Uuid, Path, description = "Workspace ID"), ActivityExportParams),
+    responses(
+        (status = 200, description = "The activity as CSV, or as NDJSON with `format=json`", content_type = "text/csv", body = String,
+            headers(("Content-Disposition" = String, description = "Attachment file name"))),
+        (status = 202, description = "Too large to stream; generated in the background", body = ApiResponse<ActivityExportView>,
+            headers(("Location" = String, description = "URL to poll"))),
+        (status = 400, description = "Period ends before it starts", body = ErrorResponse),
+        (status = 404, description = "Workspace not found", body = ErrorResponse),
+        (status = 429, description = "Too many exports", body = ErrorResponse),
+    )
+)]
+pub async fn export_workspace_activity(
+    State(state): State<AppState>,
+    Path(workspace_id): Path<Uuid>,
+    Query(params): Query<ActivityExportParams>,
+    Extension(auth_user): Extension<AuthContext>,
+) -> Result<axum::response::Response, AppError> {
+    Workspace::find_by_id(&state.db_pool, workspace_id, auth_user.user_id).await?;
+
+    crate::handlers::project::activity_export_response(
+        &state,
+        auth_user.user_id,
+        ExportScope::Workspace(workspace_id),
+        params,
+    )
+    .await
+}
+
+/// Roll up the storage of a workspace's projects
+///
+/// Totals and per-project figures as in the project storage breakdown,
+/// largest projects first. Workspace owners only; `refresh=true` recomputes
+/// every project's aggregates.
+#[utoipa::path(
+    get,
+    path = "/{workspace_id}/storage",
+    params(("workspace_id" = Uuid, Path, description = "Workspace ID"), StorageParams),
+    responses(
+        (status = 200, description = "Storage of the workspace and each of its projects", body = WorkspaceStorageResponse),
+        (status = 404, description = "Workspace not found", body = ErrorResponse),
+    )
+)]
+pub async fn get_workspace_storage(
+    State(state): State<AppState>,
+    Path(workspace_id): Path<Uuid>,
+    Query(params): Query<StorageParams>,
+    Extension(auth_user): Extension<AuthContext>,
+) -> Result<impl IntoResponse, AppError> {
+    Workspace::find_by_id(&state.db_pool, workspace_id, auth_user.user_id).await?;
+
+    let storage = WorkspaceStorage::build(&state.db_pool, workspace_id, params.refresh).await?;
+    Ok(Json(WorkspaceStorageResponse { storage }))
+}
+
+/// Monthly usage of a workspace
+///
+/// Active users, storage, compile time and jobs by outcome for each of the
+/// last `months` closed months, then the current month so far. Closed
+/// months that late data changed are marked restated, with the figures
+/// they replaced. Workspace owners only.
+#[utoipa::path(
+    get,
+    path = "/{workspace_id}/usage",
+    params(("workspace_id" = Uuid, Path, description = "Workspace ID"), UsageParams),
+    responses(
+        (status = 200, description = "Usage of the workspace by month, oldest first", body = WorkspaceUsageResponse),
+        (status = 404, description = "Workspace not found", body = ErrorResponse),
+    )
+)]
+pub async fn get_workspace_usage(
+    State(state): State<AppState>,
+    Path(workspace_id): Path<Uuid>,
+    Query(params): Query<UsageParams>,
+    Extension(auth_user): Extension<AuthContext>,
+) -> Result<impl IntoResponse, AppError> {
+    Workspace::find_by_id(&state.db_pool, workspace_id, auth_user.user_id).await?;
+
+    let usage = usage_period::report(&state.db_pool, Some(workspace_id), params.months(), chrono::Utc::now()).await?;
+    Ok(Json(WorkspaceUsageResponse { usage }))
+}
+
+/// List the user's project folders in a workspace
+#[utoipa::path(
+    get,
+    path = "/{workspace_id}/folders",
+    params(("workspace_id" = Uuid, Path, description = "Workspace ID")),
+    responses(
+        (status = 200, description = "Folders in their manual order", body = ProjectFoldersResponse),
+        (status = 404, description = "Workspace not found", body = ErrorResponse),
+    )
+)]
+pub async fn list_folders(
+    State(state): State<AppState>,
+    Path(workspace_id): Path<Uuid>,
+    Extension(auth_user): Extension<AuthContext>,
+) -> Result<impl IntoResponse, AppError> {
+    let folders = ProjectFolder::list(&state.db_pool, workspace_id, auth_user.user_id).await?;
+    Ok(Json(ProjectFoldersResponse { folders }))
+}
+
+/// Create a project folder after the user's other folders
+///
+/// Folders are personal; projects are put in them with
+/// `PUT /projects/{id}/placement`.
+#[utoipa::path(
+    post,
+    path = "/{workspace_id}/folders",
+    params(("workspace_id" = Uuid, Path, description = "Workspace ID")),
+    request_body = NewProjectFolder,
+    responses(
+        (status = 200, description = "Folder created", body = ProjectFolderResponse),
+        (status = 400, description = "Invalid name", body = ErrorResponse),
+        (status = 404, description = "Workspace not found", body = ErrorResponse),
+        (status = 409, description = "A folder of the workspace has that name", body = ErrorResponse),
+    )
+)]
+pub async fn create_folder(
+    State(state): State<AppState>,
+    Path(workspace_id): Path<Uuid>,
+    Extension(auth_user): Extension<AuthContext>,
+    Json(payload): Json<NewProjectFolder>,
+) -> Result<impl IntoResponse, AppError> {
+    let folder = ProjectFolder::create(&state.db_pool, workspace_id, auth_user.user_id, &payload.name).await?;
+    Ok(Json(ProjectFolderResponse { folder }))
+}
+
+/// Rename a project folder
+#[utoipa::path(
+    put,
+    path = "/{workspace_id}/folders/{folder_id}",
+    params(
+        ("workspace_id" = Uuid, Path, description = "Workspace ID"),
+        ("folder_id" = Uuid, Path, description = "Folder ID"),
+    ),
+    request_body = RenameProjectFolder,
+    responses(
+        (status = 200, description = "Folder renamed", body = ProjectFolderResponse),
+        (status = 400, description = "Invalid name", body = ErrorResponse),
+        (status = 404, description = "Folder not found", body = ErrorResponse),
+        (status = 409, description = "Another folder of the workspace has that name", body = ErrorResponse),
+    )
+)]
+pub async fn rename_folder(
+    State(state): State<AppState>,
+    Path((workspace_id, folder_id)): Path<(Uuid, Uuid)>,
+    Extension(auth_user): Extension<AuthContext>,
+    Json(payload): Json<RenameProjectFolder>,
+) -> Result<impl IntoResponse, AppError> {
+    let folder = ProjectFolder::rename(&state.db_pool, folder_id, workspace_id, auth_user.user_id, &payload.name).await?;
+    Ok(Json(ProjectFolderResponse { folder }))
+}
+
+/// Move a project folder right after another, or first
+#[utoipa::path(
+    post,
+    path = "/{workspace_id}/folders/{folder_id}/move",
+    params(
+        ("workspace_id" = Uuid, Path, description = "Workspace ID"),
+        ("folder_id" = Uuid, Path, description = "Folder ID"),
+    ),
+    request_body = MoveProjectFolder,
+    responses(
+        (status = 200, description = "Folder with its new position", body = ProjectFolderResponse),
+        (status = 400, description = "Folder put after itself", body = ErrorResponse),
+        (status = 404, description = "Folder not found", body = ErrorResponse),
+        (status = 409, description = "`after` is no longer a folder of the workspace", body = ErrorResponse),
+    )
+)]
+pub async fn move_folder(
+    State(state): State<AppState>,
+    Path((workspace_id, folder_id)): Path<(Uuid, Uuid)>,
+    Extension(auth_user): Extension<AuthContext>,
+    Json(payload): Json<MoveProjectFolder>,
+) -> Result<impl IntoResponse, AppError> {
+    let folder = ProjectFolder::move_after(&state.db_pool, folder_id, workspace_id, auth_user.user_id, payload.after).await?;
+    Ok(Json(ProjectFolderResponse { folder }))
+}
+
+/// Delete a project folder
+///
+/// Its projects are not deleted but unfiled, after the other unfiled ones.
+#[utoipa::path(
+    delete,
+    path = "/{workspace_id}/folders/{folder_id}",
+    params(
+        ("workspace_id" = Uuid, Path, description = "Workspace ID"),
+        ("folder_id" = Uuid, Path, description = "Folder ID"),
+    ),
+    responses(
+        (status = 200, description = "Folder deleted", body = ProjectFolderDeletedResponse),
+        (status = 404, description = "Folder not found", body = ErrorResponse),
+    )
+)]
+pub async fn delete_folder(
+    State(state): State<AppState>,
+    Path((workspace_id, folder_id)): Path<(Uuid, Uuid)>,
+    Extension(auth_user): Extension<AuthContext>,
+) -> Result<impl IntoResponse, AppError> {
+    let unfiled_projects = ProjectFolder::delete(&state.db_pool, folder_id, workspace_id, auth_user.user_id).await?;
+    Ok(Json(ProjectFolderDeletedResponse { unfiled_projects }))
+}
+
 /// Create a project inside a workspace (with a starter main.tex)
+#[utoipa::path(
+    post,
+    path = "/{workspace_id}/projects",
+    params(("workspace_id" = Uuid, Path, description = "Workspace ID")),
+    request_body = NewWorkspaceProject,
+    responses(
+        (status = 200, description = "Project created with a starter main.tex", body = ProjectResponse),
+        (status = 404, description = "Workspace not found", body = ErrorResponse),
+    )
+)]
 pub async fn create_project(
     State(state): State<AppState>,
     Path(workspace_id): Path<Uuid>,
@@ -125,9 +404,10 @@ pub async fn create_project(
     Workspace::find_by_id(&state.db_pool, workspace_id, auth_user.user_id).await?;
 
     let create_project = CreateProject {
-        name: payload
-            .name
-            .unwrap_or_else(|| format!("Project {}", chrono::Utc::now().timestamp())),
+        name: match payload.name {
+            Some(name) => name,
+            None => ProjectName::new(&format!("Project {}", chrono::Utc::now().timestamp()))?,
+        },
         description: payload.description,
         is_public: Some(false),
         main_file_path: Some("main.tex".to_string()),
@@ -144,9 +424,10 @@ pub async fn create_project(
     // Seed project with a blank main file if none exists yet
     File::create(
         &state.db_pool,
+        &ProjectLimits::from_config(&state.config.features.file_storage),
         project.id,
         CreateFile {
-            name: "main.tex".to_string(),
+            name: FileName::new("main.tex")?,
             path: "main.tex".to_string(),
             content: Some("% Start writing LaTeX here".to_string()),
             content_type: Some(ContentType::Latex),
@@ -160,6 +441,18 @@ pub async fn create_project(
 }
 
 /// Fetch project details and raw files
+#[utoipa::path(
+    get,
+    path = "/{workspace_id}/projects/{project_id}",
+    params(
+        ("workspace_id" = Uuid, Path, description = "Workspace ID"),
+        ("project_id" = Uuid, Path, description = "Project ID"),
+    ),
+    responses(
+        (status = 200, description = "Project with its files keyed by path", body = ProjectResponse),
+        (status = 404, description = "Workspace or project not found", body = ErrorResponse),
+    )
+)]
 pub async fn get_project(
     State(state): State<AppState>,
     Path((workspace_id, project_id)): Path<(Uuid, Uuid)>,
@@ -170,6 +463,20 @@ pub async fn get_project(
 }
 
 /// Add a file to a project
+#[utoipa::path(
+    post,
+    path = "/{workspace_id}/projects/{project_id}/files",
+    params(
+        ("workspace_id" = Uuid, Path, description = "Workspace ID"),
+        ("project_id" = Uuid, Path, description = "Project ID"),
+    ),
+    request_body = FileUpsert,
+    responses(
+        (status = 200, description = "File added", body = FileResponse),
+        (status = 404, description = "Workspace or project not found", body = ErrorResponse),
+        (status = 422, description = "The file goes over a limit of its project", body = LimitExceededResponse),
+    )
+)]
 pub async fn add_file(
     State(state): State<AppState>,
     Path((workspace_id, project_id)): Path<(Uuid, Uuid)>,
@@ -178,11 +485,12 @@ pub async fn add_file(
 ) -> Result<impl IntoResponse, AppError> {
     Workspace::get_project_details(&state.db_pool, workspace_id, project_id, auth_user.user_id).await?;
 
-    File::create(
+    let file = File::create(
         &state.db_pool,
+        &ProjectLimits::from_config(&state.config.features.file_storage),
         project_id,
         CreateFile {
-            name: payload.path.split('/').last().unwrap_or(&payload.path).to_string(),
+            name: FileName::new(payload.path.split('/').last().unwrap_or(&payload.path))?,
             path: payload.path.clone(),
             content: Some(payload.content.clone()),
             content_type: Some(ContentType::Latex),
@@ -190,11 +498,26 @@ pub async fn add_file(
         auth_user.user_id,
     )
     .await?;
+    state.autocomplete_cache.invalidate(project_id);
+    state.include_graph_cache.file_changed(&file);
 
     Ok(Json(FileResponse { file: FileResponsePayload { path: payload.path } }))
 }
 
 /// Update file contents
+#[utoipa::path(
+    put,
+    path = "/{workspace_id}/projects/{project_id}/files",
+    params(
+        ("workspace_id" = Uuid, Path, description = "Workspace ID"),
+        ("project_id" = Uuid, Path, description = "Project ID"),
+    ),
+    request_body = FileUpsert,
+    responses(
+        (status = 200, description = "File content replaced", body = FileResponse),
+        (status = 404, description = "Workspace, project or file not found", body = ErrorResponse),
+    )
+)]
 pub async fn update_file(
     State(state): State<AppState>,
     Path((workspace_id, project_id)): Path<(Uuid, Uuid)>,
@@ -210,12 +533,29 @@ pub async fn update_file(
             id: payload.path.clone(),
         })?;
 
-    file.update_content(&state.db_pool, payload.content, auth_user.user_id).await?;
+    let file = file.update_content(&state.db_pool, &state.config.features.file_storage.local_path, payload.content, auth_user.user_id).await?;
+    if autocomplete::indexes_content(file.content_type) {
+        state.autocomplete_cache.invalidate(project_id);
+    }
+    state.include_graph_cache.file_changed(&file);
 
     Ok(Json(FileResponse { file: FileResponsePayload { path: payload.path } }))
 }
 
 /// Change the main compilation file for a project
+#[utoipa::path(
+    post,
+    path = "/{workspace_id}/projects/{project_id}/main-file",
+    params(
+        ("workspace_id" = Uuid, Path, description = "Workspace ID"),
+        ("project_id" = Uuid, Path, description = "Project ID"),
+    ),
+    request_body = MainFileUpdate,
+    responses(
+        (status = 200, description = "Project with the new main file", body = ProjectResponse),
+        (status = 404, description = "Workspace or project not found", body = ErrorResponse),
+    )
+)]
 pub async fn set_main_file(
     State(state): State<AppState>,
     Path((workspace_id, project_id)): Path<(Uuid, Uuid)>,
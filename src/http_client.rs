@@ -0,0 +1,515 @@
+//! Outbound HTTP
+//!
+//! Requests to other services (the LaTeX service, webhooks, identity
+//! providers) go through one shared `HttpClient` instead of ad hoc
+//! `reqwest::Client`s, so they share timeouts, the user agent and proxy
+//! settings. Idempotent requests — safe methods, and anything carrying an
+//! `Idempotency-Key` — are retried with jittered exponential backoff, paid
+//! for from a retry budget that grows with ordinary traffic so an outage
+//! cannot multiply the load. Each destination host has its own circuit
+//! breaker: after repeated failures it is skipped for a cooldown, so one dead
+//! endpoint fails fast instead of eating the budget every other destination
+//! relies on. Request counts, outcomes and latencies are recorded per
+//! destination class in a Prometheus registry, exposed at `/admin/metrics`.
+
+use prometheus::{HistogramOpts, HistogramVec, IntCounterVec, Opts, Registry, TextEncoder};
+use rand::Rng;
+use reqwest::{Request, Response};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::config::HttpClientConfig;
+use crate::error::AppError;
+
+/// Header that marks a non-idempotent request as safe to retry
+pub const IDEMPOTENCY_KEY: &str = "idempotency-key";
+
+/// Delay before the first retry, doubled for every further one
+const BASE_BACKOFF: Duration = Duration::from_millis(200);
+
+/// Longest delay between two attempts
+const MAX_BACKOFF: Duration = Duration::from_secs(5);
+
+/// Retries earned per request sent
+const BUDGET_RATIO: f64 = 0.2;
+
+/// Retries that can be saved up
+const BUDGET_CAPACITY: f64 = 10.0;
+
+/// Timeouts, retries and circuit breaking of an `HttpClient`
+#[derive(Debug, Clone)]
+pub struct HttpClientSettings {
+    pub connect_timeout: Duration,
+    pub read_timeout: Duration,
+    pub max_retries: u32,
+    pub proxy: Option<String>,
+    pub circuit_failure_threshold: u32,
+    pub circuit_cooldown: Duration,
+    pub base_backoff: Duration,
+}
+
+impl From<&HttpClientConfig> for HttpClientSettings {
+    fn from(config: &HttpClientConfig) -> Self {
+        Self {
+            connect_timeout: Duration::from_secs(config.connect_timeout),
+            read_timeout: Duration::from_secs(config.read_timeout),
+            max_retries: config.max_retries,
+            proxy: config.proxy.clone(),
+            circuit_failure_threshold: config.circuit_failure_threshold.max(1),
+            circuit_cooldown: Duration::from_secs(config.circuit_cooldown),
+            base_backoff: BASE_BACKOFF,
+        }
+    }
+}
+
+/// Delay before retry number `attempt` (0-based): a random duration up to
+/// the exponential backoff, so clients that failed together spread out
+pub fn backoff_delay(base: Duration, attempt: u32) -> Duration {
+    let ceiling = base.saturating_mul(2u32.saturating_pow(attempt)).min(MAX_BACKOFF);
+    ceiling.mul_f64(rand::thread_rng().gen_range(0.0..=1.0))
+}
+
+/// Shared allowance of retries
+#[derive(Debug)]
+struct RetryBudget {
+    balance: f64,
+}
+
+impl RetryBudget {
+    fn deposit(&mut self) {
+        self.balance = (self.balance + BUDGET_RATIO).min(BUDGET_CAPACITY);
+    }
+
+    fn withdraw(&mut self) -> bool {
+        if self.balance < 1.0 {
+            return false;
+        }
+        self.balance -= 1.0;
+        true
+    }
+}
+
+/// Failure tracking of one destination
+#[derive(Debug, Default)]
+pub(crate) struct CircuitBreaker {
+    consecutive_failures: u32,
+    open_until: Option<Instant>,
+    /// A request is testing the destination after the cooldown
+    probing: bool,
+}
+
+impl CircuitBreaker {
+    pub(crate) fn allows(&mut self, now: Instant) -> bool {
+        match self.open_until {
+            None => true,
+            Some(until) if now < until => false,
+            // Cooldown over: let one request through to test the destination
+            Some(_) if self.probing => false,
+            Some(_) => {
+                self.probing = true;
+                true
+            }
+        }
+    }
+
+    /// Whether failures opened the circuit and no probe has succeeded since
+    pub(crate) fn is_open(&self) -> bool {
+        self.open_until.is_some()
+    }
+
+    /// Time left until the next probe, while the circuit is open
+    pub(crate) fn open_for(&self, now: Instant) -> Option<Duration> {
+        self.open_until.filter(|until| now < *until).map(|until| until - now)
+    }
+
+    pub(crate) fn record(&mut self, success: bool, now: Instant, threshold: u32, cooldown: Duration) {
+        if success {
+            *self = Self::default();
+            return;
+        }
+
+        self.consecutive_failures += 1;
+        if self.probing || self.consecutive_failures >= threshold {
+            self.open_until = Some(now + cooldown);
+            self.probing = false;
+        }
+    }
+}
+
+/// Outbound request metrics, labeled by destination class
+struct HttpMetrics {
+    registry: Registry,
+    requests: IntCounterVec,
+    retries: IntCounterVec,
+    duration: HistogramVec,
+}
+
+impl HttpMetrics {
+    fn new() -> Self {
+        let requests = IntCounterVec::new(
+            Opts::new("texler_http_client_requests_total", "Outbound HTTP attempts by outcome"),
+            &["class", "outcome"],
+        )
+        .expect("valid metric");
+        let retries = IntCounterVec::new(
+            Opts::new("texler_http_client_retries_total", "Outbound HTTP retries"),
+            &["class"],
+        )
+        .expect("valid metric");
+        let duration = HistogramVec::new(
+            HistogramOpts::new("texler_http_client_request_duration_seconds", "Outbound HTTP attempt latency"),
+            &["class"],
+        )
+        .expect("valid metric");
+
+        let registry = Registry::new();
+        registry.register(Box::new(requests.clone())).expect("unique metric");
+        registry.register(Box::new(retries.clone())).expect("unique metric");
+        registry.register(Box::new(duration.clone())).expect("unique metric");
+
+        Self {
+            registry,
+            requests,
+            retries,
+            duration,
+        }
+    }
+}
+
+/// What became of one attempt
+enum Attempt {
+    Response(Response),
+    Failed(reqwest::Error),
+}
+
+impl Attempt {
+    /// Whether the destination looks unhealthy
+    fn is_failure(&self) -> bool {
+        match self {
+            Self::Response(response) => response.status().is_server_error(),
+            Self::Failed(_) => true,
+        }
+    }
+
+    /// Label for the `outcome` metric: the status code, or the kind of error
+    fn outcome(&self) -> String {
+        match self {
+            Self::Response(response) => response.status().as_u16().to_string(),
+            Self::Failed(e) if e.is_timeout() => "timeout".to_string(),
+            Self::Failed(e) if e.is_connect() => "connect_error".to_string(),
+            Self::Failed(_) => "error".to_string(),
+        }
+    }
+}
+
+/// Shared client for requests to other services
+pub struct HttpClient {
+    client: reqwest::Client,
+    settings: HttpClientSettings,
+    budget: Mutex<RetryBudget>,
+    breakers: Mutex<HashMap<String, CircuitBreaker>>,
+    metrics: HttpMetrics,
+}
+
+impl HttpClient {
+    pub fn new(settings: HttpClientSettings) -> Result<Self, AppError> {
+        let mut builder = reqwest::Client::builder()
+            .user_agent(format!("texler/{}", crate::VERSION))
+            .connect_timeout(settings.connect_timeout)
+            .read_timeout(settings.read_timeout);
+        if let Some(proxy) = &settings.proxy {
+            let proxy = reqwest::Proxy::all(proxy)
+                .map_err(|e| AppError::Config(format!("Invalid HTTP client proxy '{}': {}", proxy, e)))?;
+            builder = builder.proxy(proxy);
+        }
+        let client = builder
+            .build()
+            .map_err(|e| AppError::Config(format!("Failed to build HTTP client: {}", e)))?;
+
+        Ok(Self {
+            client,
+            settings,
+            budget: Mutex::new(RetryBudget { balance: BUDGET_CAPACITY }),
+            breakers: Mutex::new(HashMap::new()),
+            metrics: HttpMetrics::new(),
+        })
+    }
+
+    /// Start building a request; send it with [`HttpClient::send`]
+    pub fn request(&self, method: reqwest::Method, url: &str) -> reqwest::RequestBuilder {
+        self.client.request(method, url)
+    }
+
+    /// Send `request`, retrying it when that is safe
+    ///
+    /// `class` groups destinations in the metrics, e.g. `"webhook"`. Server
+    /// errors are returned as responses once retries are exhausted; requests
+    /// that never got a response, or were refused by an open circuit, fail
+    /// with `AppError::Upstream`.
+    pub async fn send(&self, class: &'static str, request: Request) -> Result<Response, AppError> {
+        let destination = destination(&request);
+        let retryable = request.method().is_idempotent() || request.headers().contains_key(IDEMPOTENCY_KEY);
+        self.budget.lock().unwrap_or_else(|e| e.into_inner()).deposit();
+
+        let mut request = request;
+        crate::correlation::tag_outbound(request.headers_mut());
+        let mut attempt = 0;
+        loop {
+            if !self.breaker_allows(&destination) {
+                self.metrics.requests.with_label_values(&[class, "circuit_open"]).inc();
+                return Err(AppError::Upstream(format!("{} is unavailable after repeated failures", destination)));
+            }
+
+            // Keep a copy while another attempt may follow; streaming bodies cannot be copied
+            let retry_copy = if retryable && attempt < self.settings.max_retries {
+                request.try_clone()
+            } else {
+                None
+            };
+
+            let started = Instant::now();
+            let outcome = match self.client.execute(request).await {
+                Ok(response) => Attempt::Response(response),
+                Err(e) => Attempt::Failed(e),
+            };
+            self.metrics.duration.with_label_values(&[class]).observe(started.elapsed().as_secs_f64());
+            self.metrics.requests.with_label_values(&[class, &outcome.outcome()]).inc();
+
+            let failed = outcome.is_failure();
+            self.record(&destination, !failed);
+
+            if let Some(next) = retry_copy.filter(|_| failed && self.withdraw_retry()) {
+                tokio::time::sleep(backoff_delay(self.settings.base_backoff, attempt)).await;
+                self.metrics.retries.with_label_values(&[class]).inc();
+                request = next;
+                attempt += 1;
+                continue;
+            }
+
+            return match outcome {
+                Attempt::Response(response) => Ok(response),
+                Attempt::Failed(e) => Err(AppError::Upstream(format!("Request to {} failed: {}", destination, e))),
+            };
+        }
+    }
+
+    /// Metrics in the Prometheus text format
+    pub fn metrics_text(&self) -> String {
+        let mut text = String::new();
+        if let Err(e) = TextEncoder::new().encode_utf8(&self.metrics.registry.gather(), &mut text) {
+            tracing::warn!("Failed to encode HTTP client metrics: {}", e);
+        }
+        text
+    }
+
+    fn breaker_allows(&self, destination: &str) -> bool {
+        let mut breakers = self.breakers.lock().unwrap_or_else(|e| e.into_inner());
+        breakers.entry(destination.to_string()).or_default().allows(Instant::now())
+    }
+
+    fn record(&self, destination: &str, success: bool) {
+        let mut breakers = self.breakers.lock().unwrap_or_else(|e| e.into_inner());
+        let breaker = breakers.entry(destination.to_string()).or_default();
+        let was_open = breaker.open_until.is_some();
+        breaker.record(
+            success,
+            Instant::now(),
+            self.settings.circuit_failure_threshold,
+            self.settings.circuit_cooldown,
+        );
+        if !was_open && breaker.open_until.is_some() {
+            tracing::warn!("Outbound requests to {} are failing, skipping it for a while", destination);
+        }
+    }
+
+    fn withdraw_retry(&self) -> bool {
+        self.budget.lock().unwrap_or_else(|e| e.into_inner()).withdraw()
+    }
+}
+
+/// Host and port a request goes to
+fn destination(request: &Request) -> String {
+    let url = request.url();
+    format!(
+        "{}:{}",
+        url.host_str().unwrap_or_default(),
+        url.port_or_known_default().unwrap_or_default()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{http::StatusCode, routing::{any, get}, Router};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    fn settings() -> HttpClientSettings {
+        HttpClientSettings {
+            connect_timeout: Duration::from_secs(1),
+            read_timeout: Duration::from_millis(200),
+            max_retries: 2,
+            proxy: None,
+            circuit_failure_threshold: 3,
+            circuit_cooldown: Duration::from_secs(60),
+            base_backoff: Duration::from_millis(5),
+        }
+    }
+
+    /// A local server whose `/` answers with the status `respond` picks
+    /// for the n-th hit (starting at 0); returns its URL and hit counter
+    async fn mock_server(respond: fn(usize) -> StatusCode) -> (String, Arc<AtomicUsize>) {
+        let hits = Arc::new(AtomicUsize::new(0));
+        let counter = hits.clone();
+        let app = Router::new()
+            .route(
+                "/",
+                any(move || {
+                    let hit = counter.fetch_add(1, Ordering::SeqCst);
+                    async move { respond(hit) }
+                }),
+            )
+            .route(
+                "/slow",
+                get(|| async {
+                    tokio::time::sleep(Duration::from_secs(2)).await;
+                    "late"
+                }),
+            );
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        (url, hits)
+    }
+
+    async fn get_url(client: &HttpClient, url: &str) -> Result<Response, AppError> {
+        let request = client.request(reqwest::Method::GET, url).build().unwrap();
+        client.send("test", request).await
+    }
+
+    #[tokio::test]
+    async fn test_server_errors_are_retried() {
+        let (url, hits) = mock_server(|hit| if hit < 2 { StatusCode::SERVICE_UNAVAILABLE } else { StatusCode::OK }).await;
+        let client = HttpClient::new(settings()).unwrap();
+
+        let response = get_url(&client, &url).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(hits.load(Ordering::SeqCst), 3);
+
+        let metrics = client.metrics_text();
+        assert!(metrics.contains(r#"texler_http_client_requests_total{class="test",outcome="503"} 2"#), "{}", metrics);
+        assert!(metrics.contains(r#"texler_http_client_retries_total{class="test"} 2"#), "{}", metrics);
+    }
+
+    #[tokio::test]
+    async fn test_non_idempotent_requests_are_not_retried() {
+        let (url, hits) = mock_server(|_| StatusCode::SERVICE_UNAVAILABLE).await;
+        let client = HttpClient::new(HttpClientSettings {
+            circuit_failure_threshold: 10,
+            ..settings()
+        })
+        .unwrap();
+
+        let request = client.request(reqwest::Method::POST, &url).body("{}").build().unwrap();
+        let response = client.send("test", request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(hits.load(Ordering::SeqCst), 1);
+
+        // Unless the receiver can recognize the repeat
+        let request = client
+            .request(reqwest::Method::POST, &url)
+            .header(IDEMPOTENCY_KEY, "delivery-1")
+            .body("{}")
+            .build()
+            .unwrap();
+        client.send("test", request).await.unwrap();
+        assert_eq!(hits.load(Ordering::SeqCst), 4);
+    }
+
+    #[tokio::test]
+    async fn test_timeouts_fail_as_upstream_errors() {
+        let (url, _) = mock_server(|_| StatusCode::OK).await;
+        let client = HttpClient::new(HttpClientSettings {
+            max_retries: 0,
+            ..settings()
+        })
+        .unwrap();
+
+        let started = Instant::now();
+        let result = get_url(&client, &format!("{}/slow", url)).await;
+        assert!(matches!(result, Err(AppError::Upstream(_))));
+        assert!(started.elapsed() < Duration::from_secs(2));
+        assert!(client.metrics_text().contains(r#"outcome="timeout""#));
+    }
+
+    #[tokio::test]
+    async fn test_open_circuit_fails_fast_and_spares_other_destinations() {
+        let (dead, dead_hits) = mock_server(|_| StatusCode::BAD_GATEWAY).await;
+        let (healthy, healthy_hits) = mock_server(|_| StatusCode::OK).await;
+        let client = HttpClient::new(settings()).unwrap();
+
+        // Three failed attempts (one request and its two retries) open the circuit
+        let response = get_url(&client, &dead).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+        assert_eq!(dead_hits.load(Ordering::SeqCst), 3);
+
+        let result = get_url(&client, &dead).await;
+        assert!(matches!(result, Err(AppError::Upstream(_))));
+        assert_eq!(dead_hits.load(Ordering::SeqCst), 3);
+        assert!(client.metrics_text().contains(r#"outcome="circuit_open""#));
+
+        let response = get_url(&client, &healthy).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(healthy_hits.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_breaker_half_opens_after_cooldown() {
+        let cooldown = Duration::from_secs(30);
+        let start = Instant::now();
+        let mut breaker = CircuitBreaker::default();
+        breaker.record(false, start, 2, cooldown);
+        assert!(breaker.allows(start));
+        breaker.record(false, start, 2, cooldown);
+        assert!(!breaker.allows(start));
+
+        // One probe after the cooldown; its failure reopens the circuit
+        let later = start + cooldown;
+        assert!(breaker.allows(later));
+        assert!(!breaker.allows(later));
+        breaker.record(false, later, 2, cooldown);
+        assert!(!breaker.allows(later + Duration::from_secs(1)));
+
+        // A successful probe closes it
+        let much_later = later + cooldown;
+        assert!(breaker.allows(much_later));
+        breaker.record(true, much_later, 2, cooldown);
+        assert!(breaker.allows(much_later));
+        assert!(breaker.allows(much_later));
+    }
+
+    #[test]
+    fn test_retry_budget_is_bounded() {
+        let mut budget = RetryBudget { balance: 1.0 };
+        assert!(budget.withdraw());
+        assert!(!budget.withdraw());
+        for _ in 0..6 {
+            budget.deposit();
+        }
+        assert!(budget.withdraw());
+
+        for _ in 0..1000 {
+            budget.deposit();
+        }
+        assert_eq!(budget.balance, BUDGET_CAPACITY);
+    }
+
+    #[test]
+    fn test_backoff_is_capped() {
+        for attempt in 0..20 {
+            assert!(backoff_delay(BASE_BACKOFF, attempt) <= MAX_BACKOFF);
+        }
+    }
+}
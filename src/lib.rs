@@ -25,12 +25,24 @@
 
 pub mod admin_init;
 pub mod config;
+pub mod correlation;
+pub mod db_resilience;
+pub mod email;
 pub mod error;
 pub mod handlers;
+pub mod http_client;
 pub mod middleware;
 pub mod migrate;
 pub mod models;
+pub mod openapi;
+pub mod performance;
+pub mod scanner;
+pub mod seed;
 pub mod server;
+pub mod tasks;
+pub mod telemetry;
+#[cfg(test)]
+pub mod testing;
 pub mod websocket;
 
 // Re-export commonly used types
@@ -1,20 +1,28 @@
 use sqlx::postgres::PgPoolOptions;
-use texler_backend::{config, server};
+use texler_backend::{config, models::file_reindex::ReindexCommand, seed, server, telemetry};
 use tracing::{error, info};
-use tracing_subscriber::EnvFilter;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    tracing_subscriber::fmt()
-        .with_env_filter(EnvFilter::from_default_env())
-        .init();
+    // Trace export is configured, so logging starts after the configuration
+    let config = config::Config::load()?;
+    telemetry::init(&config)?;
 
     info!("Starting Texler backend server...");
+    let snapshot = config::snapshot::ConfigSnapshot::of(&config);
+    info!(config = %serde_json::to_string(&snapshot)?, "Effective configuration");
 
-    let config = config::Config::load()?;
-
+    let statement_timeout = format!("SET statement_timeout = {}", config.database.statement_timeout * 1000);
     let db_pool = PgPoolOptions::new()
         .max_connections(config.database.max_connections)
+        // Stop queries of timed out requests on the server as well
+        .after_connect(move |conn, _meta| {
+            let statement_timeout = statement_timeout.clone();
+            Box::pin(async move {
+                sqlx::Executor::execute(conn, statement_timeout.as_str()).await?;
+                Ok(())
+            })
+        })
         .connect(&config.database.connection_string())
         .await
         .map_err(|e| {
@@ -23,13 +31,20 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         })?;
 
     // Run database migrations
-    texler_backend::migrate::run_migrations(&db_pool)
+    let mut migrate_options = texler_backend::migrate::MigrateOptions::from_config(&config.database);
+    migrate_options.dry_run |= std::env::args().any(|arg| arg == "--dry-run");
+    texler_backend::migrate::run_migrations_with(&db_pool, &migrate_options)
         .await
         .map_err(|e| {
             error!("Failed to run database migrations: {}", e);
             e
         })?;
 
+    if migrate_options.dry_run {
+        info!("Dry run finished, no migrations were applied");
+        return Ok(());
+    }
+
     // Ensure admin user exists
     texler_backend::admin_init::ensure_admin_user(&db_pool)
         .await
@@ -38,10 +53,36 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             e
         })?;
 
-    server::start_server(config, db_pool)
+    // `seed --file <seed.yaml>` loads seed data instead of serving
+    if let Some(seed) = seed::SeedCommand::from_args(std::env::args().skip(1))? {
+        let state = server::AppState::new(config, db_pool).await?;
+        let report = seed.run(&state).await.map_err(|e| {
+            error!("Failed to load seed data from {}: {}", seed.file.display(), e);
+            e
+        })?;
+        info!("Seed data loaded: created {}; updated {}", report.created, report.updated);
+        telemetry::shutdown();
+        return Ok(());
+    }
+
+    // `reindex` recomputes what is derived from file content instead of serving
+    if let Some(reindex) = ReindexCommand::from_args(std::env::args().skip(1))? {
+        let state = server::AppState::new(config, db_pool).await?;
+        let done = reindex.run(&state).await.map_err(|e| {
+            error!("Failed to reindex files: {}", e);
+            e
+        })?;
+        info!("Reindexed {} files: {} changed, {} failed", done.processed, done.changed, done.errors);
+        telemetry::shutdown();
+        return Ok(());
+    }
+
+    let result = server::start_server(config, db_pool)
         .await
         .map_err(|e| {
             error!("Server error: {}", e);
             Box::<dyn std::error::Error>::from(e)
-        })
+        });
+    telemetry::shutdown();
+    result
 }
@@ -0,0 +1,94 @@
+//! Conflict telemetry of HTTP requests
+//!
+//! Every response with status 409 Conflict is recorded as a rejected
+//! conflict of its route, see `models::conflict_event`. The project and file
+//! are taken from the path, from the segments after `projects` and `files`.
+//! Runs inside authentication, so the caller is known.
+
+use axum::{
+    extract::{MatchedPath, Request, State},
+    http::StatusCode,
+    middleware::Next,
+    response::Response,
+};
+use chrono::Utc;
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::models::auth::AuthContext;
+use crate::models::conflict_event::{Conflict, ConflictKind, ConflictOutcome, ConflictRecorder};
+
+/// Project and file IDs in a request path
+pub fn path_ids(path: &str) -> (Option<Uuid>, Option<Uuid>) {
+    let segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+    let id_after = |name: &str| {
+        segments
+            .windows(2)
+            .find(|pair| pair[0] == name)
+            .and_then(|pair| Uuid::parse_str(pair[1]).ok())
+    };
+    (id_after("projects"), id_after("files"))
+}
+
+/// Record 409 responses as conflicts
+pub async fn conflict_middleware(
+    State(conflicts): State<Arc<ConflictRecorder>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let method = request.method().clone();
+    let path = request.uri().path().to_string();
+    let route = request.extensions().get::<MatchedPath>().map(|route| route.as_str().to_string());
+    let user_id = request.extensions().get::<AuthContext>().map(|auth| auth.user_id);
+
+    let response = next.run(request).await;
+
+    if response.status() == StatusCode::CONFLICT {
+        let (project_id, file_id) = path_ids(&path);
+        conflicts.record(Conflict {
+            kind: ConflictKind::Rest,
+            outcome: ConflictOutcome::Rejected,
+            endpoint: format!("{} {}", method, route.as_deref().unwrap_or(&path)),
+            project_id,
+            file_id,
+            session_id: None,
+            users: user_id.into_iter().collect(),
+            occurred_at: Utc::now(),
+        });
+    }
+
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::test_config;
+    use axum::{body::Body, routing::put, Router};
+    use tower::ServiceExt;
+
+    #[test]
+    fn test_path_ids() {
+        let (project, file) = (Uuid::new_v4(), Uuid::new_v4());
+        assert_eq!(path_ids(&format!("/api/v1/projects/{}/files/{}/move", project, file)), (Some(project), Some(file)));
+        assert_eq!(path_ids(&format!("/api/v1/files/{}", file)), (None, Some(file)));
+        assert_eq!(path_ids("/api/v1/projects/import"), (None, None));
+    }
+
+    #[tokio::test]
+    async fn test_conflicts_are_recorded_by_route() {
+        let conflicts = Arc::new(ConflictRecorder::new(&test_config()));
+        let router = Router::new()
+            .route("/files/:id", put(|| async { StatusCode::CONFLICT }))
+            .route("/projects/:id", put(|| async { StatusCode::OK }))
+            .layer(axum::middleware::from_fn_with_state(conflicts.clone(), conflict_middleware));
+
+        for uri in [format!("/files/{}", Uuid::new_v4()), format!("/projects/{}", Uuid::new_v4())] {
+            let request = axum::http::Request::put(uri).body(Body::empty()).unwrap();
+            router.clone().oneshot(request).await.unwrap();
+        }
+
+        assert_eq!(conflicts.count(ConflictKind::Rest, ConflictOutcome::Rejected), 1);
+        assert_eq!(conflicts.pending(), 1);
+    }
+}
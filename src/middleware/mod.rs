@@ -1,8 +1,12 @@
 //! Middleware for the Texler backend
 
+pub mod conflicts;
 pub mod rate_limit;
+pub mod timeout;
 
 pub use rate_limit::{
     RateLimiter, RateLimitConfig, AuthRateLimits,
-    rate_limit_middleware, auth_rate_limit_middleware, cleanup_task,
-};
\ No newline at end of file
+    rate_limit_middleware, auth_rate_limit_middleware, client_ip_from_headers, RateLimitCleanupTask,
+};
+pub use timeout::{RequestTimeouts, timeout_middleware};
+pub use conflicts::conflict_middleware;
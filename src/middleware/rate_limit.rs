@@ -2,7 +2,7 @@
 
 use axum::{
     extract::{Request, State},
-    http::StatusCode,
+    http::{HeaderMap, StatusCode},
     middleware::Next,
     response::Response,
 };
@@ -127,18 +127,8 @@ impl RateLimiter {
 
     /// Get client IP address from request
     fn get_client_ip(req: &Request) -> String {
-        // Try to get real IP from headers first
-        if let Some(forwarded_for) = req.headers().get("x-forwarded-for") {
-            if let Ok(forwarded_str) = forwarded_for.to_str() {
-                // Take the first IP in the forwarded list
-                return forwarded_str.split(',').next().unwrap_or("unknown").trim().to_string();
-            }
-        }
-
-        if let Some(real_ip) = req.headers().get("x-real-ip") {
-            if let Ok(real_ip_str) = real_ip.to_str() {
-                return real_ip_str.to_string();
-            }
+        if let Some(ip) = client_ip_from_headers(req.headers()) {
+            return ip;
         }
 
         if let Some(remote_addr) = req.extensions().get::<std::net::SocketAddr>() {
@@ -149,6 +139,24 @@ impl RateLimiter {
     }
 }
 
+/// Client IP address as reported by the reverse proxy headers
+pub fn client_ip_from_headers(headers: &HeaderMap) -> Option<String> {
+    if let Some(forwarded_for) = headers.get("x-forwarded-for") {
+        if let Ok(forwarded_str) = forwarded_for.to_str() {
+            // Take the first IP in the forwarded list
+            return forwarded_str.split(',').next().map(|ip| ip.trim().to_string());
+        }
+    }
+
+    if let Some(real_ip) = headers.get("x-real-ip") {
+        if let Ok(real_ip_str) = real_ip.to_str() {
+            return Some(real_ip_str.to_string());
+        }
+    }
+
+    None
+}
+
 impl Default for RateLimiter {
     fn default() -> Self {
         Self::new()
@@ -207,13 +215,26 @@ pub async fn auth_rate_limit_middleware(
     Ok(next.run(request).await)
 }
 
-/// Task to periodically clean up expired rate limit entries
-pub async fn cleanup_task(rate_limiter: Arc<RateLimiter>) {
-    let mut interval = tokio::time::interval(Duration::from_secs(300)); // Every 5 minutes
+/// Periodically drop expired rate limit entries
+pub struct RateLimitCleanupTask;
+
+impl crate::tasks::PeriodicTask for RateLimitCleanupTask {
+    fn name(&self) -> &'static str {
+        "rate_limit_cleanup"
+    }
 
-    loop {
-        interval.tick().await;
-        rate_limiter.cleanup().await;
-        tracing::debug!("Cleaned up expired rate limit entries");
+    fn interval(&self) -> Duration {
+        Duration::from_secs(300) // Every 5 minutes
     }
-}
\ No newline at end of file
+
+    fn run<'a>(
+        &'a self,
+        state: &'a crate::server::AppState,
+    ) -> futures::future::BoxFuture<'a, Result<(), crate::error::AppError>> {
+        Box::pin(async move {
+            state.rate_limiter.cleanup().await;
+            tracing::debug!("Cleaned up expired rate limit entries");
+            Ok(())
+        })
+    }
+}
@@ -0,0 +1,179 @@
+//! Request timeout middleware
+//!
+//! Every request gets `ServerConfig::request_timeout` to produce its response
+//! head; a few routes that legitimately take longer get their own budgets.
+//! The budget covers the handler only, not the response body, so a download
+//! that has started streaming is never cut off. Timing out drops the handler
+//! future, which cancels its pending work; queries already running in
+//! Postgres are bounded separately by `DatabaseConfig::statement_timeout`.
+
+use axum::{
+    extract::{Request, State},
+    http::Method,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use std::time::Duration;
+use tracing::warn;
+
+use crate::config::ServerConfig;
+use crate::error::{AppError, RequestId};
+
+/// Time budgets per route class
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RequestTimeouts {
+    pub default: Duration,
+    pub compile: Duration,
+    pub transfer: Duration,
+    pub archive: Duration,
+}
+
+impl RequestTimeouts {
+    pub fn from_config(config: &ServerConfig) -> Self {
+        Self {
+            default: Duration::from_secs(config.request_timeout),
+            compile: Duration::from_secs(config.compile_timeout),
+            transfer: Duration::from_secs(config.transfer_timeout),
+            archive: Duration::from_secs(config.archive_timeout),
+        }
+    }
+
+    /// Budget for a request
+    pub fn budget_for(&self, method: &Method, path: &str) -> Duration {
+        let segments: Vec<&str> = path
+            .trim_start_matches("/api/v1")
+            .split('/')
+            .filter(|s| !s.is_empty())
+            .collect();
+
+        match (method, segments.as_slice()) {
+            (&Method::POST, ["latex", "compile"]) => self.compile,
+            (&Method::POST, ["files", "upload"])
+            | (&Method::PUT, ["files", "uploads", _, "chunks", _])
+            | (&Method::POST, ["files", "uploads", _, "complete"])
+            | (&Method::GET, ["files", _, "download"]) => self.transfer,
+            (&Method::GET, ["projects", _, "export"])
+            | (&Method::POST, ["projects", "import"]) => self.archive,
+            _ => self.default,
+        }
+    }
+}
+
+/// Answer with a 504 once a request exceeds its budget
+pub async fn timeout_middleware(
+    State(timeouts): State<RequestTimeouts>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let budget = timeouts.budget_for(request.method(), request.uri().path());
+    let request_id = request.extensions().get::<RequestId>().copied();
+    let method = request.method().clone();
+    let uri = request.uri().clone();
+
+    match tokio::time::timeout(budget, next.run(request)).await {
+        Ok(response) => response,
+        Err(_) => {
+            warn!(
+                request_id = %request_id.map(|id| id.to_string()).unwrap_or_else(|| "unknown".to_string()),
+                method = %method,
+                uri = %uri,
+                budget_secs = budget.as_secs(),
+                "Request timed out"
+            );
+
+            AppError::Timeout {
+                request_id,
+                after_secs: budget.as_secs(),
+            }
+            .into_response()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, http::StatusCode, middleware, routing::get, Router};
+    use tower::ServiceExt;
+
+    fn timeouts(default: Duration) -> RequestTimeouts {
+        RequestTimeouts {
+            default,
+            compile: Duration::from_secs(300),
+            transfer: Duration::from_secs(600),
+            archive: Duration::from_secs(600),
+        }
+    }
+
+    fn app(timeouts: RequestTimeouts) -> Router {
+        Router::new()
+            .route(
+                "/slow",
+                get(|| async {
+                    tokio::time::sleep(Duration::from_millis(500)).await;
+                    "done"
+                }),
+            )
+            .route(
+                "/api/v1/files/:id/download",
+                get(|| async {
+                    // Each chunk arrives well after the short budget has passed
+                    let chunks = futures::stream::unfold(0u8, |sent| async move {
+                        if sent == 4 {
+                            return None;
+                        }
+                        tokio::time::sleep(Duration::from_millis(30)).await;
+                        Some((Ok::<_, std::io::Error>(vec![sent; 64 * 1024]), sent + 1))
+                    });
+                    Body::from_stream(chunks)
+                }),
+            )
+            .layer(middleware::from_fn_with_state(timeouts, timeout_middleware))
+    }
+
+    #[test]
+    fn test_budget_for_routes() {
+        let timeouts = timeouts(Duration::from_secs(30));
+
+        assert_eq!(timeouts.budget_for(&Method::GET, "/api/v1/projects"), timeouts.default);
+        assert_eq!(timeouts.budget_for(&Method::POST, "/api/v1/latex/compile"), timeouts.compile);
+        assert_eq!(timeouts.budget_for(&Method::GET, "/api/v1/files/abc/download"), timeouts.transfer);
+        assert_eq!(timeouts.budget_for(&Method::PUT, "/api/v1/files/uploads/abc/chunks/3"), timeouts.transfer);
+        assert_eq!(timeouts.budget_for(&Method::GET, "/api/v1/projects/abc/export"), timeouts.archive);
+        assert_eq!(timeouts.budget_for(&Method::POST, "/api/v1/projects/import"), timeouts.archive);
+        // Only the streaming method of a route gets the longer budget
+        assert_eq!(timeouts.budget_for(&Method::DELETE, "/api/v1/files/abc/download"), timeouts.default);
+    }
+
+    #[tokio::test]
+    async fn test_slow_handler_times_out() {
+        let request_id = RequestId::generate();
+        let mut request = axum::http::Request::get("/slow").body(Body::empty()).unwrap();
+        request.extensions_mut().insert(request_id);
+
+        let response = app(timeouts(Duration::from_millis(50))).oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::GATEWAY_TIMEOUT);
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["error"]["code"], "REQUEST_TIMEOUT");
+        assert_eq!(body["error"]["request_id"], request_id.to_string());
+    }
+
+    #[tokio::test]
+    async fn test_streaming_download_outlives_default_budget() {
+        let request = axum::http::Request::get("/api/v1/files/abc/download")
+            .body(Body::empty())
+            .unwrap();
+
+        // The default budget elapses while the body streams, which must not matter
+        let mut timeouts = timeouts(Duration::from_millis(50));
+        timeouts.transfer = Duration::from_millis(50);
+
+        let response = app(timeouts).oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(body.len(), 4 * 64 * 1024);
+    }
+}
@@ -1,75 +1,500 @@
 //! Database migration management
+//!
+//! Migrations are applied at startup, each in its own transaction, while
+//! holding an advisory lock so replicas starting together apply them once.
+//! Pre-flight checks run first: other open connections are reported, and the
+//! run is refused when another instance keeps the lock past
+//! `MIGRATE_LOCK_WAIT` or when the tables the pending migrations alter or
+//! index add up to more than `MIGRATE_MAX_REWRITE_SIZE`. Postgres cannot tell
+//! how much disk is free, so that limit is the operator's headroom estimate.
+//!
+//! Every attempt is recorded in `schema_migrations` with the checksum of its
+//! SQL and how long it took. A failed migration is rolled back, recorded with
+//! its error and stops the server; it is retried on the next start. With
+//! `MIGRATE_DRY_RUN` or `--dry-run` the pending migrations and pre-flight
+//! results are only reported.
 
-use sqlx::PgPool;
-use tracing::{error, info};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use sqlx::pool::PoolConnection;
+use sqlx::{Connection, FromRow, PgConnection, PgPool, Postgres};
+use std::collections::HashSet;
+use std::time::{Duration, Instant};
+use tracing::{error, info, warn};
+use utoipa::ToSchema;
+
+use crate::config::DatabaseConfig;
+use crate::error::AppError;
+
+/// Advisory lock held while migrating ("texler" in ASCII)
+pub const MIGRATION_LOCK_KEY: i64 = 0x7465_786c_6572;
+
+/// How often a waiting instance checks whether the migration lock is free
+const LOCK_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How migrations are run
+#[derive(Debug, Clone)]
+pub struct MigrateOptions {
+    /// Report pending migrations and pre-flight results without applying anything
+    pub dry_run: bool,
+    /// Largest total size in bytes of the tables pending migrations may rewrite; 0 for no limit
+    pub max_rewrite_size: u64,
+    /// How long to wait for another instance to finish migrating
+    pub lock_wait: Duration,
+}
+
+impl Default for MigrateOptions {
+    fn default() -> Self {
+        Self {
+            dry_run: false,
+            max_rewrite_size: 0,
+            lock_wait: Duration::from_secs(300),
+        }
+    }
+}
+
+impl MigrateOptions {
+    pub fn from_config(config: &DatabaseConfig) -> Self {
+        Self {
+            dry_run: config.migrate_dry_run,
+            max_rewrite_size: config.migrate_max_rewrite_size,
+            lock_wait: Duration::from_secs(config.migrate_lock_wait),
+        }
+    }
+}
+
+/// A migration that has not been applied yet
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct PendingMigration {
+    pub version: String,
+    /// Size of its SQL in bytes
+    pub sql_size: usize,
+    pub checksum: String,
+    /// Existing tables it alters or indexes, which may be rewritten
+    pub tables: Vec<String>,
+}
+
+/// What applying the pending migrations would involve
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct PreflightReport {
+    pub pending: Vec<PendingMigration>,
+    /// Client connections to the database besides the migrating one
+    pub other_connections: i64,
+    /// Total size in bytes of the tables the pending migrations may rewrite
+    pub rewrite_estimate: i64,
+    /// Another instance holds the migration lock
+    pub locked_by_other: bool,
+}
+
+/// A row of the migration history
+#[derive(Debug, Clone, Serialize, FromRow, ToSchema)]
+pub struct AppliedMigration {
+    pub version: String,
+    /// Unset for migrations applied before checksums were recorded
+    pub checksum: Option<String>,
+    /// Whether the recorded checksum matches the SQL shipped with this build
+    #[sqlx(skip)]
+    pub checksum_matches: Option<bool>,
+    pub applied_at: Option<DateTime<Utc>>,
+    pub execution_ms: Option<i64>,
+    /// False for a migration whose last attempt failed and was rolled back
+    pub success: bool,
+    pub error: Option<String>,
+}
 
 /// Run all database migrations
-pub async fn run_migrations(db_pool: &PgPool) -> Result<(), crate::error::AppError> {
+pub async fn run_migrations(db_pool: &PgPool) -> Result<(), AppError> {
+    run_migrations_with(db_pool, &MigrateOptions::default()).await.map(|_| ())
+}
+
+/// Run the pre-flight checks and apply the pending migrations, unless this is a dry run
+pub async fn run_migrations_with(db_pool: &PgPool, options: &MigrateOptions) -> Result<PreflightReport, AppError> {
+    let mut conn = db_pool.acquire().await.map_err(AppError::Database)?;
+
+    if options.dry_run {
+        let report = preflight(&mut conn, &get_migrations()).await?;
+        log_report(&report);
+        return Ok(report);
+    }
+
+    lock(&mut conn, options.lock_wait).await?;
+    let result = apply(&mut conn, &get_migrations(), options).await;
+    unlock(conn).await;
+    result
+}
+
+/// Pending migrations and pre-flight results without taking the lock
+pub async fn dry_run(db_pool: &PgPool) -> Result<PreflightReport, AppError> {
+    let mut conn = db_pool.acquire().await.map_err(AppError::Database)?;
+    preflight(&mut conn, &get_migrations()).await
+}
+
+/// Recorded migrations, oldest first, checked against the SQL of this build
+pub async fn migration_history(db_pool: &PgPool) -> Result<Vec<AppliedMigration>, AppError> {
+    let mut history = sqlx::query_as::<_, AppliedMigration>(
+        r#"
+        SELECT version, checksum, applied_at, execution_ms, success, error
+        FROM schema_migrations
+        ORDER BY version
+        "#
+    )
+    .fetch_all(db_pool)
+    .await
+    .map_err(AppError::Database)?;
+
+    let migrations = get_migrations();
+    for applied in &mut history {
+        let shipped = migrations.iter().find(|m| m.version == applied.version);
+        applied.checksum_matches = match (shipped, &applied.checksum) {
+            (Some(migration), Some(checksum)) => Some(&migration.checksum() == checksum),
+            _ => None,
+        };
+    }
+
+    Ok(history)
+}
+
+async fn apply(
+    conn: &mut PgConnection,
+    migrations: &[Migration],
+    options: &MigrateOptions,
+) -> Result<PreflightReport, AppError> {
     info!("Running database migrations...");
 
     // Create migration tracking table if it doesn't exist
-    sqlx::query(
+    sqlx::raw_sql(
         r#"
         CREATE TABLE IF NOT EXISTS schema_migrations (
             version VARCHAR(255) PRIMARY KEY,
             applied_at TIMESTAMP WITH TIME ZONE DEFAULT NOW()
         );
+        ALTER TABLE schema_migrations
+            ADD COLUMN IF NOT EXISTS checksum VARCHAR(64),
+            ADD COLUMN IF NOT EXISTS execution_ms BIGINT,
+            ADD COLUMN IF NOT EXISTS success BOOLEAN NOT NULL DEFAULT true,
+            ADD COLUMN IF NOT EXISTS error TEXT;
         "#
     )
-    .execute(db_pool)
+    .execute(&mut *conn)
     .await
-    .map_err(crate::error::AppError::Database)?;
+    .map_err(AppError::Database)?;
 
-    let migrations = get_migrations();
+    let report = preflight(conn, migrations).await?;
+    log_report(&report);
 
-    for migration in migrations {
-        // Check if migration was already applied
-        let applied = sqlx::query_scalar::<_, bool>(
-            "SELECT EXISTS(SELECT 1 FROM schema_migrations WHERE version = $1)"
-        )
-        .bind(migration.version)
-        .fetch_one(db_pool)
-        .await
-        .map_err(crate::error::AppError::Database)?;
+    if options.max_rewrite_size > 0 && report.rewrite_estimate as u64 > options.max_rewrite_size {
+        return Err(AppError::Server(format!(
+            "Pending migrations may rewrite {} bytes of tables, more than MIGRATE_MAX_REWRITE_SIZE ({} bytes)",
+            report.rewrite_estimate, options.max_rewrite_size
+        )));
+    }
 
-        if applied {
-            info!("Migration {} already applied", migration.version);
-            continue;
-        }
+    warn_on_changed_checksums(conn, migrations).await?;
 
+    for pending in &report.pending {
+        let Some(migration) = migrations.iter().find(|m| m.version == pending.version) else {
+            continue;
+        };
         info!("Applying migration: {}", migration.version);
 
-        // Execute migration using simple SQL execution (not prepared statements)
-        // This allows for DO blocks and other complex SQL constructs
-        sqlx::raw_sql(migration.sql)
-            .execute(db_pool)
-            .await
-            .map_err(|e| {
+        let started = Instant::now();
+        match apply_one(conn, migration, &pending.checksum).await {
+            Ok(()) => info!(
+                "Migration {} applied successfully in {} ms",
+                migration.version,
+                started.elapsed().as_millis()
+            ),
+            Err(e) => {
                 error!("Failed to apply migration {}: {}", migration.version, e);
-                crate::error::AppError::Database(e)
-            })?;
+                record(conn, migration.version, &pending.checksum, started, Some(&e.to_string())).await?;
+                return Err(AppError::Server(format!(
+                    "Migration {} failed and was rolled back: {}",
+                    migration.version, e
+                )));
+            }
+        }
+    }
+
+    info!("All migrations completed successfully");
+    Ok(report)
+}
+
+/// Apply one migration and record it in the same transaction
+async fn apply_one(conn: &mut PgConnection, migration: &Migration, checksum: &str) -> Result<(), AppError> {
+    let started = Instant::now();
+    let mut tx = conn.begin().await.map_err(AppError::Database)?;
+
+    // Execute migration using simple SQL execution (not prepared statements)
+    // This allows for DO blocks and other complex SQL constructs
+    sqlx::raw_sql(migration.sql)
+        .execute(&mut *tx)
+        .await
+        .map_err(AppError::Database)?;
+    record(&mut *tx, migration.version, checksum, started, None).await?;
+
+    tx.commit().await.map_err(AppError::Database)
+}
+
+/// Store the outcome of a migration attempt
+async fn record(
+    conn: &mut PgConnection,
+    version: &str,
+    checksum: &str,
+    started: Instant,
+    error: Option<&str>,
+) -> Result<(), AppError> {
+    sqlx::query(
+        r#"
+        INSERT INTO schema_migrations (version, checksum, execution_ms, success, error, applied_at)
+        VALUES ($1, $2, $3, $4, $5, NOW())
+        ON CONFLICT (version) DO UPDATE SET
+            checksum = EXCLUDED.checksum,
+            execution_ms = EXCLUDED.execution_ms,
+            success = EXCLUDED.success,
+            error = EXCLUDED.error,
+            applied_at = EXCLUDED.applied_at
+        "#
+    )
+    .bind(version)
+    .bind(checksum)
+    .bind(started.elapsed().as_millis() as i64)
+    .bind(error.is_none())
+    .bind(error)
+    .execute(conn)
+    .await
+    .map_err(AppError::Database)?;
+
+    Ok(())
+}
+
+async fn preflight(conn: &mut PgConnection, migrations: &[Migration]) -> Result<PreflightReport, AppError> {
+    let tracked = sqlx::query_scalar::<_, bool>("SELECT to_regclass('schema_migrations') IS NOT NULL")
+        .fetch_one(&mut *conn)
+        .await
+        .map_err(AppError::Database)?;
 
-        // Mark migration as applied
-        sqlx::query(
-            "INSERT INTO schema_migrations (version) VALUES ($1)"
+    // Read through jsonb so a dry run works before the history columns exist
+    let applied: HashSet<String> = if tracked {
+        sqlx::query_scalar::<_, String>(
+            "SELECT version FROM schema_migrations s WHERE (to_jsonb(s) ->> 'success') IS DISTINCT FROM 'false'"
         )
-        .bind(migration.version)
-        .execute(db_pool)
+        .fetch_all(&mut *conn)
         .await
-        .map_err(crate::error::AppError::Database)?;
+        .map_err(AppError::Database)?
+        .into_iter()
+        .collect()
+    } else {
+        HashSet::new()
+    };
 
-        info!("Migration {} applied successfully", migration.version);
+    let mut pending = Vec::new();
+    let mut rewritten = Vec::new();
+    for migration in migrations.iter().filter(|m| !applied.contains(m.version)) {
+        let mut tables = Vec::new();
+        for table in altered_tables(migration.sql) {
+            let exists = sqlx::query_scalar::<_, bool>("SELECT to_regclass($1) IS NOT NULL")
+                .bind(&table)
+                .fetch_one(&mut *conn)
+                .await
+                .map_err(AppError::Database)?;
+            if exists {
+                tables.push(table);
+            }
+        }
+        rewritten.extend(tables.iter().cloned());
+
+        pending.push(PendingMigration {
+            version: migration.version.to_string(),
+            sql_size: migration.sql.len(),
+            checksum: migration.checksum(),
+            tables,
+        });
+    }
+
+    rewritten.sort();
+    rewritten.dedup();
+    let rewrite_estimate = sqlx::query_scalar::<_, i64>(
+        "SELECT COALESCE(SUM(pg_total_relation_size(to_regclass(t))), 0)::BIGINT FROM UNNEST($1::TEXT[]) AS t"
+    )
+    .bind(&rewritten)
+    .fetch_one(&mut *conn)
+    .await
+    .map_err(AppError::Database)?;
+
+    let other_connections = sqlx::query_scalar::<_, i64>(
+        r#"
+        SELECT COUNT(*) FROM pg_stat_activity
+        WHERE datname = current_database() AND pid <> pg_backend_pid() AND backend_type = 'client backend'
+        "#
+    )
+    .fetch_one(&mut *conn)
+    .await
+    .map_err(AppError::Database)?;
+
+    // A bigint advisory key is split into classid (high half) and objid (low half)
+    let locked_by_other = sqlx::query_scalar::<_, bool>(
+        r#"
+        SELECT EXISTS(
+            SELECT 1 FROM pg_locks
+            WHERE locktype = 'advisory' AND granted AND pid <> pg_backend_pid()
+              AND classid::BIGINT = $1 AND objid::BIGINT = $2 AND objsubid = 1
+        )
+        "#
+    )
+    .bind(MIGRATION_LOCK_KEY >> 32)
+    .bind(MIGRATION_LOCK_KEY & 0xFFFF_FFFF)
+    .fetch_one(&mut *conn)
+    .await
+    .map_err(AppError::Database)?;
+
+    Ok(PreflightReport {
+        pending,
+        other_connections,
+        rewrite_estimate,
+        locked_by_other,
+    })
+}
+
+fn log_report(report: &PreflightReport) {
+    if report.pending.is_empty() {
+        info!("No pending migrations");
+    }
+    for pending in &report.pending {
+        info!(
+            "Pending migration {} ({} bytes of SQL, alters {})",
+            pending.version,
+            pending.sql_size,
+            if pending.tables.is_empty() { "no existing tables".to_string() } else { pending.tables.join(", ") }
+        );
+    }
+    if report.rewrite_estimate > 0 {
+        warn!("Pending migrations may rewrite up to {} bytes of tables", report.rewrite_estimate);
+    }
+    if report.other_connections > 0 && !report.pending.is_empty() {
+        warn!(
+            "{} other connections to the database are open; migrations may wait for their locks",
+            report.other_connections
+        );
+    }
+    if report.locked_by_other {
+        warn!("Another instance is applying migrations right now");
+    }
+}
+
+/// Warn about applied migrations whose SQL changed since
+async fn warn_on_changed_checksums(conn: &mut PgConnection, migrations: &[Migration]) -> Result<(), AppError> {
+    let recorded = sqlx::query_as::<_, (String, String)>(
+        "SELECT version, checksum FROM schema_migrations WHERE success AND checksum IS NOT NULL"
+    )
+    .fetch_all(&mut *conn)
+    .await
+    .map_err(AppError::Database)?;
+
+    for (version, checksum) in recorded {
+        if let Some(migration) = migrations.iter().find(|m| m.version == version) {
+            if migration.checksum() != checksum {
+                warn!("Migration {} was changed after it was applied", version);
+            }
+        }
     }
 
-    info!("All migrations completed successfully");
     Ok(())
 }
 
+/// Take the migration lock, waiting up to `wait` for another instance to release it
+async fn lock(conn: &mut PgConnection, wait: Duration) -> Result<(), AppError> {
+    let started = Instant::now();
+    let mut waiting = false;
+
+    loop {
+        let locked = sqlx::query_scalar::<_, bool>("SELECT pg_try_advisory_lock($1)")
+            .bind(MIGRATION_LOCK_KEY)
+            .fetch_one(&mut *conn)
+            .await
+            .map_err(AppError::Database)?;
+        if locked {
+            return Ok(());
+        }
+
+        if started.elapsed() >= wait {
+            return Err(AppError::Conflict(format!(
+                "Another instance has been migrating the database for more than {} s",
+                wait.as_secs()
+            )));
+        }
+        if !waiting {
+            info!("Another instance is migrating the database, waiting for it to finish");
+            waiting = true;
+        }
+        tokio::time::sleep(LOCK_POLL_INTERVAL.min(wait.saturating_sub(started.elapsed()))).await;
+    }
+}
+
+/// Release the migration lock; a connection that cannot release it is closed instead
+async fn unlock(mut conn: PoolConnection<Postgres>) {
+    let released = sqlx::query_scalar::<_, bool>("SELECT pg_advisory_unlock($1)")
+        .bind(MIGRATION_LOCK_KEY)
+        .fetch_one(&mut *conn)
+        .await;
+
+    if !matches!(released, Ok(true)) {
+        warn!("Failed to release the migration lock, closing its connection");
+        let _ = conn.close().await;
+    }
+}
+
+/// Tables named by the `ALTER TABLE` and `CREATE INDEX ... ON` statements of `sql`
+fn altered_tables(sql: &str) -> Vec<String> {
+    let uncommented: String = sql
+        .lines()
+        .map(|line| line.split("--").next().unwrap_or_default())
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    let mut tables = Vec::new();
+    for statement in uncommented.split(';') {
+        let words: Vec<String> = statement
+            .split(|c: char| c.is_whitespace() || c == '(')
+            .filter(|word| !word.is_empty())
+            .map(|word| word.to_ascii_lowercase())
+            .collect();
+
+        let name_at = match words.iter().map(String::as_str).collect::<Vec<_>>().as_slice() {
+            ["alter", "table", ..] => Some(2),
+            ["create", "index", ..] | ["create", "unique", "index", ..] => {
+                words.iter().position(|word| word == "on").map(|i| i + 1)
+            }
+            _ => None,
+        };
+
+        let table = name_at.and_then(|mut i| {
+            while matches!(words.get(i).map(String::as_str), Some("if" | "exists" | "only")) {
+                i += 1;
+            }
+            words.get(i).map(|word| word.trim_matches('"').to_string())
+        });
+        if let Some(table) = table {
+            if !tables.contains(&table) {
+                tables.push(table);
+            }
+        }
+    }
+
+    tables
+}
+
 struct Migration {
     version: &'static str,
     sql: &'static str,
 }
 
+impl Migration {
+    fn checksum(&self) -> String {
+        hex::encode(Sha256::digest(self.sql.as_bytes()))
+    }
+}
+
 fn get_migrations() -> Vec<Migration> {
     vec![
         Migration {
@@ -92,5 +517,333 @@ fn get_migrations() -> Vec<Migration> {
             version: "005_create_functions",
             sql: include_str!("../migrations/005_create_functions.sql"),
         },
+        Migration {
+            version: "006_add_upload_sessions",
+            sql: include_str!("../migrations/006_add_upload_sessions.sql"),
+        },
+        Migration {
+            version: "007_add_blobs",
+            sql: include_str!("../migrations/007_add_blobs.sql"),
+        },
+        Migration {
+            version: "008_add_compile_environment",
+            sql: include_str!("../migrations/008_add_compile_environment.sql"),
+        },
+        Migration {
+            version: "009_add_project_invitations",
+            sql: include_str!("../migrations/009_add_project_invitations.sql"),
+        },
+        Migration {
+            version: "010_add_login_protection",
+            sql: include_str!("../migrations/010_add_login_protection.sql"),
+        },
+        Migration {
+            version: "011_add_compile_diffs",
+            sql: include_str!("../migrations/011_add_compile_diffs.sql"),
+        },
+        Migration {
+            version: "012_add_project_imported_settings",
+            sql: include_str!("../migrations/012_add_project_imported_settings.sql"),
+        },
+        Migration {
+            version: "013_add_dictionaries",
+            sql: include_str!("../migrations/013_add_dictionaries.sql"),
+        },
+        Migration {
+            version: "014_add_participant_focus",
+            sql: include_str!("../migrations/014_add_participant_focus.sql"),
+        },
+        Migration {
+            version: "015_add_project_readme",
+            sql: include_str!("../migrations/015_add_project_readme.sql"),
+        },
+        Migration {
+            version: "016_add_compile_notifications",
+            sql: include_str!("../migrations/016_add_compile_notifications.sql"),
+        },
+        Migration {
+            version: "017_align_core_tables",
+            sql: include_str!("../migrations/017_align_core_tables.sql"),
+        },
+        Migration {
+            version: "018_add_session_summaries",
+            sql: include_str!("../migrations/018_add_session_summaries.sql"),
+        },
+        Migration {
+            version: "019_add_project_settings_history",
+            sql: include_str!("../migrations/019_add_project_settings_history.sql"),
+        },
+        Migration {
+            version: "020_add_activity_digests",
+            sql: include_str!("../migrations/020_add_activity_digests.sql"),
+        },
+        Migration {
+            version: "021_add_activity_rollups",
+            sql: include_str!("../migrations/021_add_activity_rollups.sql"),
+        },
+        Migration {
+            version: "022_add_template_catalog",
+            sql: include_str!("../migrations/022_add_template_catalog.sql"),
+        },
+        Migration {
+            version: "023_add_file_scanning",
+            sql: include_str!("../migrations/023_add_file_scanning.sql"),
+        },
+        Migration {
+            version: "024_add_session_join_codes",
+            sql: include_str!("../migrations/024_add_session_join_codes.sql"),
+        },
+        Migration {
+            version: "025_add_file_reindexes",
+            sql: include_str!("../migrations/025_add_file_reindexes.sql"),
+        },
+        Migration {
+            version: "026_add_chat_attachments",
+            sql: include_str!("../migrations/026_add_chat_attachments.sql"),
+        },
+        Migration {
+            version: "027_add_project_freezes",
+            sql: include_str!("../migrations/027_add_project_freezes.sql"),
+        },
+        Migration {
+            version: "028_add_data_integrity_incidents",
+            sql: include_str!("../migrations/028_add_data_integrity_incidents.sql"),
+        },
+        Migration {
+            version: "029_add_session_schedules",
+            sql: include_str!("../migrations/029_add_session_schedules.sql"),
+        },
+        Migration {
+            version: "030_add_session_anonymity",
+            sql: include_str!("../migrations/030_add_session_anonymity.sql"),
+        },
+        Migration {
+            version: "031_add_typst_engine",
+            sql: include_str!("../migrations/031_add_typst_engine.sql"),
+        },
+        Migration {
+            version: "032_add_email_dead_letters",
+            sql: include_str!("../migrations/032_add_email_dead_letters.sql"),
+        },
+        Migration {
+            version: "033_add_compilation_jobs_project_created_index",
+            sql: include_str!("../migrations/033_add_compilation_jobs_project_created_index.sql"),
+        },
+        Migration {
+            version: "034_add_user_avatars",
+            sql: include_str!("../migrations/034_add_user_avatars.sql"),
+        },
+        Migration {
+            version: "035_add_file_version_deltas",
+            sql: include_str!("../migrations/035_add_file_version_deltas.sql"),
+        },
+        Migration {
+            version: "036_add_request_correlation",
+            sql: include_str!("../migrations/036_add_request_correlation.sql"),
+        },
+        Migration {
+            version: "037_add_project_clones",
+            sql: include_str!("../migrations/037_add_project_clones.sql"),
+        },
+        Migration {
+            version: "038_add_chat_mentions",
+            sql: include_str!("../migrations/038_add_chat_mentions.sql"),
+        },
+        Migration {
+            version: "039_add_project_purges",
+            sql: include_str!("../migrations/039_add_project_purges.sql"),
+        },
+        Migration {
+            version: "040_add_project_settings",
+            sql: include_str!("../migrations/040_add_project_settings.sql"),
+        },
+        Migration {
+            version: "041_add_embed_tokens",
+            sql: include_str!("../migrations/041_add_embed_tokens.sql"),
+        },
+        Migration {
+            version: "042_add_activity_exports",
+            sql: include_str!("../migrations/042_add_activity_exports.sql"),
+        },
+        Migration {
+            version: "043_add_compile_preflight",
+            sql: include_str!("../migrations/043_add_compile_preflight.sql"),
+        },
+        Migration {
+            version: "044_add_project_secrets",
+            sql: include_str!("../migrations/044_add_project_secrets.sql"),
+        },
+        Migration {
+            version: "045_add_tex_word_count",
+            sql: include_str!("../migrations/045_add_tex_word_count.sql"),
+        },
+        Migration {
+            version: "046_add_git_imports",
+            sql: include_str!("../migrations/046_add_git_imports.sql"),
+        },
+        Migration {
+            version: "047_add_project_storage_usage",
+            sql: include_str!("../migrations/047_add_project_storage_usage.sql"),
+        },
+        Migration {
+            version: "048_add_template_variables",
+            sql: include_str!("../migrations/048_add_template_variables.sql"),
+        },
+        Migration {
+            version: "049_add_participant_activity",
+            sql: include_str!("../migrations/049_add_participant_activity.sql"),
+        },
+        Migration {
+            version: "050_add_unique_live_file_paths",
+            sql: include_str!("../migrations/050_add_unique_live_file_paths.sql"),
+        },
+        Migration {
+            version: "051_add_project_discovery",
+            sql: include_str!("../migrations/051_add_project_discovery.sql"),
+        },
+        Migration {
+            version: "052_add_user_timezone",
+            sql: include_str!("../migrations/052_add_user_timezone.sql"),
+        },
+        Migration {
+            version: "053_add_incremental_compiles",
+            sql: include_str!("../migrations/053_add_incremental_compiles.sql"),
+        },
+        Migration {
+            version: "054_add_user_onboarding",
+            sql: include_str!("../migrations/054_add_user_onboarding.sql"),
+        },
+        Migration {
+            version: "055_add_compile_image_optimization",
+            sql: include_str!("../migrations/055_add_compile_image_optimization.sql"),
+        },
+        Migration {
+            version: "056_add_quick_search_indexes",
+            sql: include_str!("../migrations/056_add_quick_search_indexes.sql"),
+        },
+        Migration {
+            version: "057_add_email_changes",
+            sql: include_str!("../migrations/057_add_email_changes.sql"),
+        },
+        Migration {
+            version: "058_add_project_folders",
+            sql: include_str!("../migrations/058_add_project_folders.sql"),
+        },
+        Migration {
+            version: "059_add_oidc_flows",
+            sql: include_str!("../migrations/059_add_oidc_flows.sql"),
+        },
+        Migration {
+            version: "060_add_compile_batches",
+            sql: include_str!("../migrations/060_add_compile_batches.sql"),
+        },
+        Migration {
+            version: "061_add_file_share_links",
+            sql: include_str!("../migrations/061_add_file_share_links.sql"),
+        },
+        Migration {
+            version: "062_add_ownership_transfers",
+            sql: include_str!("../migrations/062_add_ownership_transfers.sql"),
+        },
+        Migration {
+            version: "063_add_conflict_events",
+            sql: include_str!("../migrations/063_add_conflict_events.sql"),
+        },
+        Migration {
+            version: "064_add_compile_thumbnails",
+            sql: include_str!("../migrations/064_add_compile_thumbnails.sql"),
+        },
+        Migration {
+            version: "065_add_usage_periods",
+            sql: include_str!("../migrations/065_add_usage_periods.sql"),
+        },
+        Migration {
+            version: "066_add_project_switcher",
+            sql: include_str!("../migrations/066_add_project_switcher.sql"),
+        },
+        Migration {
+            version: "067_add_announcements",
+            sql: include_str!("../migrations/067_add_announcements.sql"),
+        },
     ]
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestDb;
+
+    #[test]
+    fn test_altered_tables() {
+        let sql = r#"
+            -- ALTER TABLE commented_out ADD COLUMN x INT;
+            CREATE TABLE IF NOT EXISTS notes (
+                id UUID PRIMARY KEY,
+                project_id UUID REFERENCES projects(id) ON DELETE CASCADE
+            );
+            ALTER TABLE IF EXISTS ONLY files ADD COLUMN pinned BOOLEAN;
+            alter table "users" add column bio text;
+            CREATE UNIQUE INDEX IF NOT EXISTS idx_files_pinned ON files(pinned);
+            CREATE INDEX idx_sessions ON ONLY collaboration_sessions (ended_at);
+        "#;
+
+        assert_eq!(altered_tables(sql), vec!["files", "users", "collaboration_sessions"]);
+        assert!(altered_tables("CREATE TABLE t (id INT);").is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_failed_migration_is_recorded() {
+        let Some(db) = TestDb::start().await else { return };
+
+        // Everything shipped is applied with a matching checksum
+        let report = dry_run(&db.pool).await.unwrap();
+        assert!(report.pending.is_empty());
+        let history = migration_history(&db.pool).await.unwrap();
+        let latest = history.iter().find(|m| m.version == "026_add_chat_attachments").unwrap();
+        assert!(latest.success);
+        assert_ne!(latest.checksum_matches, Some(false));
+
+        let broken = [Migration {
+            version: "999_test_broken_migration",
+            sql: "CREATE TABLE test_half_applied (id INT); SELECT * FROM no_such_table;",
+        }];
+        let mut conn = db.pool.acquire().await.unwrap();
+        let result = apply(&mut conn, &broken, &MigrateOptions::default()).await;
+        assert!(matches!(result, Err(AppError::Server(_))));
+
+        // Rolled back as a whole, with the failure on record
+        let half_applied = sqlx::query_scalar::<_, bool>("SELECT to_regclass('test_half_applied') IS NOT NULL")
+            .fetch_one(&db.pool)
+            .await
+            .unwrap();
+        assert!(!half_applied);
+
+        let history = migration_history(&db.pool).await.unwrap();
+        let failed = history.iter().find(|m| m.version == "999_test_broken_migration").unwrap();
+        assert!(!failed.success);
+        assert!(failed.error.as_deref().unwrap().contains("no_such_table"));
+
+        sqlx::query("DELETE FROM schema_migrations WHERE version = '999_test_broken_migration'")
+            .execute(&db.pool)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_migration_lock_is_exclusive() {
+        let Some(db) = TestDb::start().await else { return };
+
+        let mut other = db.pool.acquire().await.unwrap();
+        lock(&mut other, Duration::ZERO).await.unwrap();
+
+        let options = MigrateOptions {
+            lock_wait: Duration::ZERO,
+            ..MigrateOptions::default()
+        };
+        let result = run_migrations_with(&db.pool, &options).await;
+        assert!(matches!(result, Err(AppError::Conflict(_))));
+        assert!(dry_run(&db.pool).await.unwrap().locked_by_other);
+
+        unlock(other).await;
+        run_migrations_with(&db.pool, &MigrateOptions::default()).await.unwrap();
+    }
+}
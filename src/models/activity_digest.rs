@@ -0,0 +1,577 @@
+//! Project activity digests
+//!
+//! Users who opt in through their `DigestFrequency` preference get a daily or
+//! weekly email summarising what happened in the projects they own or
+//! collaborate on: files changed with their word-count deltas, compilations
+//! and how many failed, and collaborators who joined. The digest is built from
+//! the project activity log, compilation statistics and settings history, one
+//! project at a time so a project that cannot be read only drops out of that
+//! user's digest. Periods are calendar days or weeks in the user's time zone,
+//! and a digest goes out from the morning after its period ended there.
+//! Every period is claimed in `digest_deliveries` before the email goes out,
+//! so overlapping runs never send it twice.
+
+use chrono::{DateTime, Datelike, Duration as ChronoDuration, NaiveDate, Timelike, Utc};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::time::Duration;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use super::compilation::CompilationStats;
+use super::file::FileChangeDetails;
+use super::project::{Project, ProjectActivity};
+use super::settings_history::{ProjectSettingsChange, SettingsChangeKind};
+use super::timezone;
+use super::user::User;
+use crate::email::{Locale, Mailer, RenderedEmail};
+use crate::error::AppError;
+
+/// Activity log actions that count as a file change
+const FILE_ACTIONS: [&str; 3] = ["file_created", "file_updated", "file_deleted"];
+
+/// Local hour from which the digest of the period that ended at midnight is sent
+const DIGEST_HOUR: u32 = 8;
+
+/// How often a user gets an activity digest
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, sqlx::Type, ToSchema)]
+pub enum DigestFrequency {
+    #[serde(rename = "off")]
+    #[sqlx(rename = "off")]
+    #[default]
+    Off,
+    #[serde(rename = "daily")]
+    #[sqlx(rename = "daily")]
+    Daily,
+    #[serde(rename = "weekly")]
+    #[sqlx(rename = "weekly")]
+    Weekly,
+}
+
+/// Time span a digest covers, end exclusive
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+pub struct DigestPeriod {
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+}
+
+impl DigestFrequency {
+    /// Preference of a user; users without stored preferences get no digest
+    pub async fn for_user(db: &sqlx::PgPool, user_id: Uuid) -> Result<Self, AppError> {
+        let frequency = sqlx::query_scalar::<_, DigestFrequency>(
+            "SELECT digest_frequency FROM user_preferences WHERE user_id = $1"
+        )
+        .bind(user_id)
+        .fetch_optional(db)
+        .await
+        .map_err(AppError::Database)?;
+
+        Ok(frequency.unwrap_or_default())
+    }
+
+    /// First day of the period `day` falls in: the day itself, or the Monday
+    /// of its week for weekly digests
+    fn first_day(self, day: NaiveDate) -> NaiveDate {
+        match self {
+            Self::Weekly => day - ChronoDuration::days(day.weekday().num_days_from_monday() as i64),
+            Self::Off | Self::Daily => day,
+        }
+    }
+
+    /// First day of the period before the one starting on `first_day`
+    fn previous_first_day(self, first_day: NaiveDate) -> NaiveDate {
+        match self {
+            Self::Weekly => first_day - ChronoDuration::weeks(1),
+            Self::Off | Self::Daily => first_day - ChronoDuration::days(1),
+        }
+    }
+
+    /// The period in progress at `now` in `tz`, up to `now`
+    pub fn current_period(self, now: DateTime<Utc>, tz: Tz) -> DigestPeriod {
+        let first_day = self.first_day(timezone::local_date(tz, now));
+        DigestPeriod { start: timezone::start_of_day(tz, first_day), end: now }
+    }
+
+    /// The period whose digest is due at `now`: the most recent one that
+    /// ended before the last local morning in `tz`
+    pub fn due_period(self, now: DateTime<Utc>, tz: Tz) -> DigestPeriod {
+        let local = now.with_timezone(&tz);
+        let mut day = local.date_naive();
+        if local.hour() < DIGEST_HOUR {
+            day -= ChronoDuration::days(1);
+        }
+
+        let end = self.first_day(day);
+        DigestPeriod {
+            start: timezone::start_of_day(tz, self.previous_first_day(end)),
+            end: timezone::start_of_day(tz, end),
+        }
+    }
+}
+
+/// Net change to one file over the period
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+pub struct FileChange {
+    pub path: String,
+    pub word_count_delta: i32,
+}
+
+/// What happened in one project
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct ProjectDigest {
+    pub project_id: Uuid,
+    pub project_name: String,
+    pub files: Vec<FileChange>,
+    pub compiles: i64,
+    pub failed_compiles: i64,
+    /// Usernames of collaborators who joined
+    pub new_collaborators: Vec<String>,
+}
+
+impl ProjectDigest {
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty() && self.compiles == 0 && self.new_collaborators.is_empty()
+    }
+
+    async fn build(db: &sqlx::PgPool, project: &Project, period: DigestPeriod) -> Result<Self, AppError> {
+        let activities = ProjectActivity::list_between(db, project.id, period.start, period.end).await?;
+        let files = file_changes(&activities);
+
+        let stats = CompilationStats::get_project_stats(db, project.id, period.start, period.end).await?;
+
+        let mut new_collaborators = Vec::new();
+        for change in ProjectSettingsChange::list_between(db, project.id, period.start, period.end).await? {
+            let joined = change.kind == SettingsChangeKind::CollaboratorRole
+                && change.before["role"].is_null()
+                && !change.after["role"].is_null();
+            let Some(collaborator_id) = change.collaborator_id.filter(|_| joined) else {
+                continue;
+            };
+            if let Some(user) = User::find_by_id(db, collaborator_id).await? {
+                if !new_collaborators.contains(&user.username) {
+                    new_collaborators.push(user.username);
+                }
+            }
+        }
+
+        Ok(Self {
+            project_id: project.id,
+            project_name: project.name.clone(),
+            files,
+            compiles: stats.total_jobs,
+            failed_compiles: stats.failed_jobs,
+            new_collaborators,
+        })
+    }
+}
+
+/// Net word-count change per file, under the file's latest path
+///
+/// Entries logged before file activity carried details are left out.
+pub fn file_changes(activities: &[ProjectActivity]) -> Vec<FileChange> {
+    let mut changes: BTreeMap<Uuid, FileChange> = BTreeMap::new();
+    for activity in activities {
+        if !FILE_ACTIONS.contains(&activity.action.as_str()) {
+            continue;
+        }
+        let (Some(file_id), Some(details)) = (activity.entity_id, activity.details.as_deref()) else {
+            continue;
+        };
+        let Ok(details) = serde_json::from_str::<FileChangeDetails>(details) else {
+            continue;
+        };
+
+        let change = changes.entry(file_id).or_insert_with(|| FileChange {
+            path: String::new(),
+            word_count_delta: 0,
+        });
+        change.path = details.path;
+        change.word_count_delta += details.word_count_delta;
+    }
+
+    let mut changes: Vec<FileChange> = changes.into_values().collect();
+    changes.sort_by(|a, b| a.path.cmp(&b.path));
+    changes
+}
+
+/// Activity across all of a user's projects
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct ActivityDigest {
+    pub frequency: DigestFrequency,
+    pub period: DigestPeriod,
+    /// Projects with activity, by name
+    pub projects: Vec<ProjectDigest>,
+    /// Projects whose activity could not be read
+    pub skipped_projects: usize,
+}
+
+/// Subject and bodies of a digest email
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct RenderedDigest {
+    pub subject: String,
+    pub text: String,
+    pub html: String,
+}
+
+impl ActivityDigest {
+    /// Gather the activity of every project the user owns or collaborates on
+    pub async fn build(
+        db: &sqlx::PgPool,
+        user_id: Uuid,
+        frequency: DigestFrequency,
+        period: DigestPeriod,
+    ) -> Result<Self, AppError> {
+        let mut digest = Self {
+            frequency,
+            period,
+            projects: Vec::new(),
+            skipped_projects: 0,
+        };
+
+        for project in Project::list_for_member(db, user_id).await? {
+            match ProjectDigest::build(db, &project, period).await {
+                Ok(project_digest) if !project_digest.is_empty() => digest.projects.push(project_digest),
+                Ok(_) => {}
+                Err(e) => {
+                    tracing::warn!("Leaving project {} out of the digest for {}: {}", project.id, user_id, e);
+                    digest.skipped_projects += 1;
+                }
+            }
+        }
+
+        Ok(digest)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.projects.is_empty()
+    }
+
+    /// Render the digest, with the period in the reader's time zone
+    pub fn render(&self, tz: Tz) -> RenderedDigest {
+        let label = match self.frequency {
+            DigestFrequency::Weekly => "Weekly",
+            DigestFrequency::Off | DigestFrequency::Daily => "Daily",
+        };
+        let range = format!(
+            "{} to {}",
+            self.period.start.with_timezone(&tz).format("%Y-%m-%d %H:%M"),
+            self.period.end.with_timezone(&tz).format("%Y-%m-%d %H:%M %Z")
+        );
+        let subject = format!(
+            "{} activity digest: {} project{}",
+            label,
+            self.projects.len(),
+            if self.projects.len() == 1 { "" } else { "s" }
+        );
+
+        let mut text = format!("Activity in your projects from {}.\n", range);
+        let mut html = format!("<h1>{} activity digest</h1>\n<p>Activity in your projects from {}.</p>\n", label, range);
+
+        if self.is_empty() {
+            text.push_str("\nNothing happened in this period.\n");
+            html.push_str("<p>Nothing happened in this period.</p>\n");
+        }
+
+        for project in &self.projects {
+            let compiles = format!("{} compilations, {} failed", project.compiles, project.failed_compiles);
+
+            let _ = write!(text, "\n{}\n  {}\n", project.project_name, compiles);
+            let _ = write!(html, "<h2>{}</h2>\n<p>{}</p>\n", escape_html(&project.project_name), compiles);
+
+            if !project.files.is_empty() {
+                text.push_str("  Files changed:\n");
+                html.push_str("<p>Files changed:</p>\n<ul>\n");
+                for file in &project.files {
+                    let _ = writeln!(text, "    {} ({:+} words)", file.path, file.word_count_delta);
+                    let _ = writeln!(
+                        html,
+                        "<li><code>{}</code> ({:+} words)</li>",
+                        escape_html(&file.path),
+                        file.word_count_delta
+                    );
+                }
+                html.push_str("</ul>\n");
+            }
+
+            if !project.new_collaborators.is_empty() {
+                let names = project.new_collaborators.join(", ");
+                let _ = writeln!(text, "  New collaborators: {}", names);
+                let _ = writeln!(html, "<p>New collaborators: {}</p>", escape_html(&names));
+            }
+        }
+
+        if self.skipped_projects > 0 {
+            let note = format!(
+                "{} project{} could not be summarised this time.",
+                self.skipped_projects,
+                if self.skipped_projects == 1 { "" } else { "s" }
+            );
+            let _ = write!(text, "\n{}\n", note);
+            let _ = writeln!(html, "<p>{}</p>", note);
+        }
+
+        RenderedDigest { subject, text, html }
+    }
+
+    /// Claim the period for the user, returning false when it was already claimed
+    async fn claim(&self, db: &sqlx::PgPool, user_id: Uuid) -> Result<bool, AppError> {
+        let claimed = sqlx::query_scalar::<_, Uuid>(
+            r#"
+            INSERT INTO digest_deliveries (user_id, frequency, period_start, period_end, sent_at)
+            VALUES ($1, $2, $3, $4, CASE WHEN $5 THEN NOW() END)
+            ON CONFLICT (user_id, frequency, period_end) DO NOTHING
+            RETURNING id
+            "#
+        )
+        .bind(user_id)
+        .bind(self.frequency)
+        .bind(self.period.start)
+        .bind(self.period.end)
+        .bind(!self.is_empty())
+        .fetch_optional(db)
+        .await
+        .map_err(AppError::Database)?;
+
+        Ok(claimed.is_some())
+    }
+
+    /// Send the user's digest for the period due at `now` in `tz` unless it
+    /// went out already; empty digests are recorded but not sent
+    pub async fn deliver(
+        db: &sqlx::PgPool,
+        mailer: &Mailer,
+        user: &User,
+        frequency: DigestFrequency,
+        tz: Tz,
+        now: DateTime<Utc>,
+    ) -> Result<bool, AppError> {
+        let digest = Self::build(db, user.id, frequency, frequency.due_period(now, tz)).await?;
+        if !digest.claim(db, user.id).await? || digest.is_empty() {
+            return Ok(false);
+        }
+
+        let rendered = digest.render(tz);
+        let content = RenderedEmail {
+            subject: rendered.subject,
+            text: rendered.text,
+            html: rendered.html,
+        };
+        let locale = Locale::for_user(db, user.id).await?;
+        mailer.send_rendered(&user.email, locale, "activity_digest", content).await?;
+        Ok(true)
+    }
+
+    /// Send the digests that are due at `now`, returning how many went out
+    ///
+    /// A user whose digest fails is logged and retried on the next run.
+    pub async fn send_due(db: &sqlx::PgPool, mailer: &Mailer, now: DateTime<Utc>) -> Result<usize, AppError> {
+        // Periods follow each user's time zone, so only the last period each
+        // user got is read here and what is due is worked out per user
+        let candidates = sqlx::query_as::<_, (Uuid, DigestFrequency, String, Option<DateTime<Utc>>)>(
+            r#"
+            SELECT up.user_id, up.digest_frequency, up.timezone,
+                   (SELECT MAX(d.period_end) FROM digest_deliveries d
+                    WHERE d.user_id = up.user_id AND d.frequency = up.digest_frequency) AS last_period_end
+            FROM user_preferences up
+            JOIN users u ON u.id = up.user_id
+            WHERE up.digest_frequency <> 'off' AND u.is_active = true
+            "#
+        )
+        .fetch_all(db)
+        .await
+        .map_err(AppError::Database)?;
+
+        let mut sent = 0;
+        for (user_id, frequency, timezone, last_period_end) in candidates {
+            let tz = timezone.parse::<Tz>().unwrap_or(Tz::UTC);
+            // A period ending no later than the last one sent is covered,
+            // also after the user moved to a zone further east
+            if last_period_end.is_some_and(|end| end >= frequency.due_period(now, tz).end) {
+                continue;
+            }
+            let Some(user) = User::find_by_id(db, user_id).await? else {
+                continue;
+            };
+            match Self::deliver(db, mailer, &user, frequency, tz, now).await {
+                Ok(true) => sent += 1,
+                Ok(false) => {}
+                Err(e) => tracing::warn!("Failed to send the activity digest of {}: {}", user_id, e),
+            }
+        }
+
+        Ok(sent)
+    }
+}
+
+fn escape_html(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Sends activity digests once their period has ended
+pub struct ActivityDigestTask;
+
+impl crate::tasks::PeriodicTask for ActivityDigestTask {
+    fn name(&self) -> &'static str {
+        "activity_digest"
+    }
+
+    fn interval(&self) -> Duration {
+        Duration::from_secs(3600)
+    }
+
+    fn run<'a>(
+        &'a self,
+        state: &'a crate::server::AppState,
+    ) -> futures::future::BoxFuture<'a, Result<(), AppError>> {
+        Box::pin(async move {
+            // Periods are only claimed once they can actually be delivered
+            if !state.mailer.is_enabled() {
+                return Ok(());
+            }
+
+            let sent = ActivityDigest::send_due(&state.db_pool, &state.mailer, Utc::now()).await?;
+            if sent > 0 {
+                tracing::info!("Sent {} activity digest emails", sent);
+            }
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::UserRole;
+    use crate::testing::{add_collaborator, create_test_file, create_test_project, create_test_user, test_config, TestDb};
+
+    fn at(s: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc)
+    }
+
+    #[test]
+    fn test_periods() {
+        // A Thursday afternoon
+        let now = at("2026-10-15T14:30:00Z");
+
+        let daily = DigestFrequency::Daily.due_period(now, Tz::UTC);
+        assert_eq!(daily.start, at("2026-10-14T00:00:00Z"));
+        assert_eq!(daily.end, at("2026-10-15T00:00:00Z"));
+
+        let weekly = DigestFrequency::Weekly.due_period(now, Tz::UTC);
+        assert_eq!(weekly.start, at("2026-10-05T00:00:00Z"));
+        assert_eq!(weekly.end, at("2026-10-12T00:00:00Z"));
+
+        let current = DigestFrequency::Weekly.current_period(now, Tz::UTC);
+        assert_eq!(current.start, at("2026-10-12T00:00:00Z"));
+        assert_eq!(current.end, now);
+
+        // The week that ended at midnight is due on Monday morning, not before
+        let early = DigestFrequency::Weekly.due_period(at("2026-10-12T07:59:00Z"), Tz::UTC);
+        assert_eq!(early.end, at("2026-10-05T00:00:00Z"));
+        let morning = DigestFrequency::Weekly.due_period(at("2026-10-12T08:00:00Z"), Tz::UTC);
+        assert_eq!(morning.end, at("2026-10-12T00:00:00Z"));
+    }
+
+    #[test]
+    fn test_periods_across_daylight_saving_changes() {
+        let berlin = chrono_tz::Europe::Berlin;
+
+        // Monday morning after the clocks went back: the Sunday lasted 25 hours
+        let daily = DigestFrequency::Daily.due_period(at("2030-10-28T07:00:00Z"), berlin);
+        assert_eq!(daily.start, at("2030-10-26T22:00:00Z"));
+        assert_eq!(daily.end, at("2030-10-27T23:00:00Z"));
+        assert_eq!((daily.end - daily.start).num_hours(), 25);
+
+        // Half past seven in Berlin is still too early, Saturday is due
+        let early = DigestFrequency::Daily.due_period(at("2030-10-28T06:30:00Z"), berlin);
+        assert_eq!(early.end, at("2030-10-26T22:00:00Z"));
+
+        // The Sunday the clocks went forward lasted 23 hours, its week 167
+        let daily = DigestFrequency::Daily.due_period(at("2030-04-01T06:00:00Z"), berlin);
+        assert_eq!(daily.start, at("2030-03-30T23:00:00Z"));
+        assert_eq!(daily.end, at("2030-03-31T22:00:00Z"));
+        assert_eq!((daily.end - daily.start).num_hours(), 23);
+        let weekly = DigestFrequency::Weekly.due_period(at("2030-04-01T06:00:00Z"), berlin);
+        assert_eq!(weekly.start, at("2030-03-24T23:00:00Z"));
+        assert_eq!(weekly.end, daily.end);
+        assert_eq!((weekly.end - weekly.start).num_hours(), 167);
+
+        // The week in progress started at midnight Berlin time
+        let current = DigestFrequency::Weekly.current_period(at("2030-10-30T12:00:00Z"), berlin);
+        assert_eq!(current.start, at("2030-10-27T23:00:00Z"));
+    }
+
+    #[test]
+    fn test_render_escapes_html() {
+        let digest = ActivityDigest {
+            frequency: DigestFrequency::Weekly,
+            period: DigestFrequency::Weekly.due_period(at("2026-10-15T14:30:00Z"), Tz::UTC),
+            projects: vec![ProjectDigest {
+                project_id: Uuid::new_v4(),
+                project_name: "Thesis <draft>".to_string(),
+                files: vec![FileChange { path: "chapters/intro.tex".to_string(), word_count_delta: -12 }],
+                compiles: 4,
+                failed_compiles: 1,
+                new_collaborators: vec!["ada".to_string()],
+            }],
+            skipped_projects: 1,
+        };
+
+        let rendered = digest.render(Tz::UTC);
+        assert_eq!(rendered.subject, "Weekly activity digest: 1 project");
+        assert!(rendered.text.contains("Thesis <draft>"));
+        assert!(rendered.text.contains("chapters/intro.tex (-12 words)"));
+        assert!(rendered.text.contains("4 compilations, 1 failed"));
+        assert!(rendered.text.contains("New collaborators: ada"));
+        assert!(rendered.text.contains("1 project could not be summarised"));
+        assert!(rendered.html.contains("Thesis &lt;draft&gt;"));
+        assert!(!rendered.html.contains("<draft>"));
+    }
+
+    #[tokio::test]
+    async fn test_digest_is_sent_once_per_period() {
+        let Some(db) = TestDb::start().await else { return };
+        let owner = create_test_user(&db.pool).await;
+        let collaborator = create_test_user(&db.pool).await;
+        let project = create_test_project(&db.pool, &owner, false).await;
+        let file = create_test_file(&db.pool, &project, &owner).await;
+        file.update_content(&db.pool, "", format!("{} more words here", file.content), owner.id)
+            .await
+            .unwrap();
+        add_collaborator(&db.pool, &project, &collaborator, UserRole::Viewer).await;
+
+        let now = Utc::now();
+        let period = DigestPeriod { start: now - ChronoDuration::hours(1), end: now + ChronoDuration::hours(1) };
+        let digest = ActivityDigest::build(&db.pool, owner.id, DigestFrequency::Daily, period).await.unwrap();
+        assert_eq!(digest.projects.len(), 1);
+        let summary = &digest.projects[0];
+        assert_eq!(summary.files, vec![FileChange { path: file.path.clone(), word_count_delta: file.word_count + 3 }]);
+        assert_eq!(summary.compiles, 0);
+        assert_eq!(summary.new_collaborators, vec![collaborator.username.clone()]);
+
+        // Today's activity is reported in tomorrow morning's digest
+        let mailer = Mailer::new(&test_config(), db.pool.clone()).unwrap();
+        let tomorrow = timezone::start_of_day(Tz::UTC, now.date_naive() + ChronoDuration::days(1)) + ChronoDuration::hours(9);
+        let daily = DigestFrequency::Daily;
+        assert!(ActivityDigest::deliver(&db.pool, &mailer, &owner, daily, Tz::UTC, tomorrow).await.unwrap());
+        assert!(!ActivityDigest::deliver(&db.pool, &mailer, &owner, daily, Tz::UTC, tomorrow).await.unwrap());
+
+        // Nothing to report: the period is recorded without an email
+        let later = tomorrow + ChronoDuration::days(2);
+        assert!(!ActivityDigest::deliver(&db.pool, &mailer, &owner, daily, Tz::UTC, later).await.unwrap());
+        let unsent = sqlx::query_scalar::<_, i64>(
+            "SELECT COUNT(*) FROM digest_deliveries WHERE user_id = $1 AND sent_at IS NULL"
+        )
+        .bind(owner.id)
+        .fetch_one(&db.pool)
+        .await
+        .unwrap();
+        assert_eq!(unsent, 1);
+    }
+}
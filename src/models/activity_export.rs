@@ -0,0 +1,892 @@
+//! Activity exports for audits
+//!
+//! Exports the activity log of a project, or of every project in a
+//! workspace, for a period as CSV or NDJSON: one row per activity with the
+//! actor's username, oldest first. Days past the retention window only exist
+//! as daily counters (see `activity_rollup`); they come first, as rows with
+//! `record` set to `aggregated`, covering the whole UTC day from
+//! `occurred_at` to `period_end`, without actor and with the number of
+//! activities in `count`. Like [`ActivityCount::between`], a rolled-up day
+//! is exported when its date falls in the period.
+//!
+//! Rows are read in keyset pages of `EXPORT_PAGE_ROWS` and written one by
+//! one, so an export never holds the log in memory nor a connection for the
+//! length of a slow download. Exports of more than `SYNC_EXPORT_MAX_ROWS`
+//! rows are written to file storage in the background instead, and the
+//! requester is notified once the download is ready. Every export is
+//! recorded in the audit log.
+//!
+//! [`ActivityCount::between`]: super::activity_rollup::ActivityCount::between
+
+use chrono::{DateTime, NaiveDate, SecondsFormat, Utc};
+use futures::{Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use std::borrow::Cow;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use utoipa::{IntoParams, ToSchema};
+use uuid::Uuid;
+
+use super::audit::AuditEvent;
+use super::notification::NotificationService;
+use super::user::User;
+use crate::error::AppError;
+use crate::middleware::RateLimitConfig;
+
+/// Larger exports are generated in the background
+pub const SYNC_EXPORT_MAX_ROWS: i64 = 50_000;
+
+/// Rows read per query
+pub const EXPORT_PAGE_ROWS: i64 = 1_000;
+
+/// Days a background export can be downloaded
+pub const EXPORT_RETENTION_DAYS: i64 = 7;
+
+/// A pending export older than this belongs to a server that died meanwhile
+const STALE_PENDING_MINUTES: i64 = 60;
+
+/// How often expired exports are removed
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Exports per user, project and workspace exports together
+pub const ACTIVITY_EXPORT_LIMIT: RateLimitConfig = RateLimitConfig {
+    requests_per_window: 10,
+    window_duration: Duration::from_secs(3600),
+    burst_size: 2,
+};
+
+/// Columns of a CSV export, in order
+const CSV_COLUMNS: [&str; 13] = [
+    "record", "activity_id", "project_id", "project_name", "occurred_at", "period_end", "user_id",
+    "username", "action", "entity_type", "entity_id", "count", "details",
+];
+
+/// Encoding of an export; `json` is newline-delimited JSON, one object per row
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, sqlx::Type, ToSchema)]
+#[sqlx(type_name = "activityexportformat")]
+pub enum ExportFormat {
+    #[default]
+    #[serde(rename = "csv")]
+    #[sqlx(rename = "csv")]
+    Csv,
+    #[serde(rename = "json")]
+    #[sqlx(rename = "json")]
+    Json,
+}
+
+impl ExportFormat {
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Self::Csv => "text/csv; charset=utf-8",
+            Self::Json => "application/x-ndjson",
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Csv => "csv",
+            Self::Json => "ndjson",
+        }
+    }
+
+    /// Text before the first row
+    fn preamble(self) -> Option<String> {
+        match self {
+            Self::Csv => Some(format!("{}\r\n", CSV_COLUMNS.join(","))),
+            Self::Json => None,
+        }
+    }
+
+    /// A row as one line
+    pub fn encode(self, row: &ExportRow) -> String {
+        match self {
+            Self::Csv => {
+                let time = |time: DateTime<Utc>| time.to_rfc3339_opts(SecondsFormat::AutoSi, true);
+                let id = |id: Option<Uuid>| id.map(|id| id.to_string()).unwrap_or_default();
+                let fields = [
+                    row.record.as_str().to_string(),
+                    id(row.activity_id),
+                    row.project_id.to_string(),
+                    csv_field(&row.project_name).into_owned(),
+                    time(row.occurred_at),
+                    row.period_end.map(time).unwrap_or_default(),
+                    id(row.user_id),
+                    csv_field(row.username.as_deref().unwrap_or_default()).into_owned(),
+                    csv_field(&row.action).into_owned(),
+                    csv_field(row.entity_type.as_deref().unwrap_or_default()).into_owned(),
+                    id(row.entity_id),
+                    row.count.to_string(),
+                    csv_field(row.details.as_deref().unwrap_or_default()).into_owned(),
+                ];
+                format!("{}\r\n", fields.join(","))
+            }
+            Self::Json => {
+                let mut line = serde_json::to_string(row).expect("export rows serialize");
+                line.push('\n');
+                line
+            }
+        }
+    }
+}
+
+/// A CSV field, quoted when needed; text a spreadsheet would evaluate as a
+/// formula is prefixed with `'`
+pub(crate) fn csv_field(value: &str) -> Cow<'_, str> {
+    let value: Cow<'_, str> = if value.starts_with(['=', '+', '-', '@', '\t', '\r']) {
+        Cow::Owned(format!("'{}", value))
+    } else {
+        Cow::Borrowed(value)
+    };
+    if value.contains([',', '"', '\n', '\r']) {
+        Cow::Owned(format!("\"{}\"", value.replace('"', "\"\"")))
+    } else {
+        value
+    }
+}
+
+/// Activity export parameters
+#[derive(Debug, Clone, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ActivityExportParams {
+    /// Start of the period; unset for everything before `to`
+    pub from: Option<DateTime<Utc>>,
+    /// End of the period, exclusive; unset for everything after `from`
+    pub to: Option<DateTime<Utc>>,
+    /// `csv` (default) or `json` for NDJSON
+    pub format: Option<ExportFormat>,
+}
+
+impl ActivityExportParams {
+    pub fn validate(&self) -> Result<(), AppError> {
+        match (self.from, self.to) {
+            (Some(from), Some(to)) if to < from => {
+                Err(AppError::Validation("Period ends before it starts".to_string()))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Whose activity is exported
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportScope {
+    Project(Uuid),
+    Workspace(Uuid),
+}
+
+impl ExportScope {
+    pub fn entity_type(self) -> &'static str {
+        match self {
+            Self::Project(_) => "project",
+            Self::Workspace(_) => "workspace",
+        }
+    }
+
+    pub fn id(self) -> Uuid {
+        match self {
+            Self::Project(id) | Self::Workspace(id) => id,
+        }
+    }
+
+    /// File name offered for an export of this scope
+    pub fn file_name(self, format: ExportFormat) -> String {
+        format!(
+            "activity-{}-{}-{}.{}",
+            self.entity_type(),
+            self.id(),
+            Utc::now().format("%Y%m%d-%H%M%S"),
+            format.extension()
+        )
+    }
+}
+
+/// Kind of an exported row
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExportRecord {
+    /// A single activity
+    Activity,
+    /// Activities of a whole day past the retention window, counted
+    Aggregated,
+}
+
+impl ExportRecord {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Activity => "activity",
+            Self::Aggregated => "aggregated",
+        }
+    }
+}
+
+impl TryFrom<String> for ExportRecord {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        match value.as_str() {
+            "activity" => Ok(Self::Activity),
+            "aggregated" => Ok(Self::Aggregated),
+            _ => Err(format!("unknown export record {}", value)),
+        }
+    }
+}
+
+/// One exported row
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct ExportRow {
+    #[sqlx(try_from = "String")]
+    pub record: ExportRecord,
+    pub activity_id: Option<Uuid>,
+    pub project_id: Uuid,
+    pub project_name: String,
+    /// Time of the activity, or start of the aggregated day
+    pub occurred_at: DateTime<Utc>,
+    /// End of the aggregated day
+    pub period_end: Option<DateTime<Utc>>,
+    pub user_id: Option<Uuid>,
+    pub username: Option<String>,
+    pub action: String,
+    pub entity_type: Option<String>,
+    pub entity_id: Option<Uuid>,
+    /// Activities the row stands for, 1 for single activities
+    pub count: i64,
+    pub details: Option<String>,
+}
+
+/// Where reading an export continues
+enum Cursor {
+    Rollups(Option<(NaiveDate, Uuid, String)>),
+    Activities(Option<(DateTime<Utc>, Uuid)>),
+    Done,
+}
+
+/// The rows of an export, resolved to the projects they cover
+#[derive(Debug, Clone)]
+pub struct ActivityExportQuery {
+    project_ids: Vec<Uuid>,
+    from: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>,
+}
+
+impl ActivityExportQuery {
+    /// Rows of `scope` in a period; a workspace covers its current projects
+    pub async fn new(
+        db: &sqlx::PgPool,
+        scope: ExportScope,
+        from: Option<DateTime<Utc>>,
+        to: Option<DateTime<Utc>>,
+    ) -> Result<Self, AppError> {
+        let project_ids = match scope {
+            ExportScope::Project(project_id) => vec![project_id],
+            ExportScope::Workspace(workspace_id) => sqlx::query_scalar::<_, Uuid>(
+                "SELECT id FROM projects WHERE workspace_id = $1 AND purging_at IS NULL"
+            )
+            .bind(workspace_id)
+            .fetch_all(db)
+            .await
+            .map_err(AppError::Database)?,
+        };
+
+        Ok(Self { project_ids, from, to })
+    }
+
+    /// Number of rows the export will have
+    pub async fn count_rows(&self, db: &sqlx::PgPool) -> Result<i64, AppError> {
+        sqlx::query_scalar::<_, i64>(
+            r#"
+            SELECT
+                (SELECT COUNT(*) FROM project_activity_rollup
+                 WHERE project_id = ANY($1)
+                   AND ($2::TIMESTAMPTZ IS NULL OR day >= ($2 AT TIME ZONE 'UTC')::DATE)
+                   AND ($3::TIMESTAMPTZ IS NULL OR day < ($3 AT TIME ZONE 'UTC')::DATE))
+              + (SELECT COUNT(*) FROM project_activity
+                 WHERE project_id = ANY($1)
+                   AND ($2::TIMESTAMPTZ IS NULL OR created_at >= $2)
+                   AND ($3::TIMESTAMPTZ IS NULL OR created_at < $3))
+            "#
+        )
+        .bind(&self.project_ids)
+        .bind(self.from)
+        .bind(self.to)
+        .fetch_one(db)
+        .await
+        .map_err(AppError::Database)
+    }
+
+    /// The rows, aggregated days first, read a page at a time
+    pub fn rows(self, db: sqlx::PgPool) -> impl Stream<Item = Result<ExportRow, AppError>> + Send + 'static {
+        futures::stream::unfold((self, db, Cursor::Rollups(None)), |(query, db, cursor)| async move {
+            if matches!(cursor, Cursor::Done) {
+                return None;
+            }
+            match query.page(&db, &cursor).await {
+                Ok((rows, next)) => Some((Ok(rows), (query, db, next))),
+                Err(e) => Some((Err(e), (query, db, Cursor::Done))),
+            }
+        })
+        .flat_map(|page| {
+            futures::stream::iter(match page {
+                Ok(rows) => rows.into_iter().map(Ok).collect::<Vec<_>>(),
+                Err(e) => vec![Err(e)],
+            })
+        })
+    }
+
+    async fn page(&self, db: &sqlx::PgPool, cursor: &Cursor) -> Result<(Vec<ExportRow>, Cursor), AppError> {
+        match cursor {
+            Cursor::Rollups(after) => {
+                let (day, project_id, action) = match after {
+                    Some((day, project_id, action)) => (Some(*day), Some(*project_id), Some(action.as_str())),
+                    None => (None, None, None),
+                };
+                let rows = sqlx::query_as::<_, ExportRow>(
+                    r#"
+                    SELECT 'aggregated'::TEXT AS record, NULL::UUID AS activity_id, r.project_id,
+                        p.name AS project_name, r.day::TIMESTAMP AT TIME ZONE 'UTC' AS occurred_at,
+                        (r.day + 1)::TIMESTAMP AT TIME ZONE 'UTC' AS period_end,
+                        NULL::UUID AS user_id, NULL::TEXT AS username, r.action::TEXT AS action,
+                        NULL::TEXT AS entity_type, NULL::UUID AS entity_id, r.count, NULL::TEXT AS details
+                    FROM project_activity_rollup r
+                    JOIN projects p ON p.id = r.project_id
+                    WHERE r.project_id = ANY($1)
+                      AND ($2::TIMESTAMPTZ IS NULL OR r.day >= ($2 AT TIME ZONE 'UTC')::DATE)
+                      AND ($3::TIMESTAMPTZ IS NULL OR r.day < ($3 AT TIME ZONE 'UTC')::DATE)
+                      AND ($4::DATE IS NULL OR (r.day, r.project_id, r.action) > ($4, $5, $6))
+                    ORDER BY r.day, r.project_id, r.action
+                    LIMIT $7
+                    "#
+                )
+                .bind(&self.project_ids)
+                .bind(self.from)
+                .bind(self.to)
+                .bind(day)
+                .bind(project_id)
+                .bind(action)
+                .bind(EXPORT_PAGE_ROWS)
+                .fetch_all(db)
+                .await
+                .map_err(AppError::Database)?;
+
+                let next = match rows.last() {
+                    Some(last) if rows.len() as i64 == EXPORT_PAGE_ROWS => Cursor::Rollups(Some((
+                        last.occurred_at.date_naive(),
+                        last.project_id,
+                        last.action.clone(),
+                    ))),
+                    _ => Cursor::Activities(None),
+                };
+                Ok((rows, next))
+            }
+            Cursor::Activities(after) => {
+                let (created_at, id) = match after {
+                    Some((created_at, id)) => (Some(*created_at), Some(*id)),
+                    None => (None, None),
+                };
+                let rows = sqlx::query_as::<_, ExportRow>(
+                    r#"
+                    SELECT 'activity'::TEXT AS record, a.id AS activity_id, a.project_id,
+                        p.name AS project_name, a.created_at AS occurred_at,
+                        NULL::TIMESTAMPTZ AS period_end, a.user_id, u.username::TEXT AS username,
+                        a.action::TEXT AS action, a.entity_type::TEXT AS entity_type, a.entity_id,
+                        1::BIGINT AS count, a.details
+                    FROM project_activity a
+                    JOIN projects p ON p.id = a.project_id
+                    LEFT JOIN users u ON u.id = a.user_id
+                    WHERE a.project_id = ANY($1)
+                      AND ($2::TIMESTAMPTZ IS NULL OR a.created_at >= $2)
+                      AND ($3::TIMESTAMPTZ IS NULL OR a.created_at < $3)
+                      AND ($4::TIMESTAMPTZ IS NULL OR (a.created_at, a.id) > ($4, $5))
+                    ORDER BY a.created_at, a.id
+                    LIMIT $6
+                    "#
+                )
+                .bind(&self.project_ids)
+                .bind(self.from)
+                .bind(self.to)
+                .bind(created_at)
+                .bind(id)
+                .bind(EXPORT_PAGE_ROWS)
+                .fetch_all(db)
+                .await
+                .map_err(AppError::Database)?;
+
+                let next = match rows.last() {
+                    Some(last) if rows.len() as i64 == EXPORT_PAGE_ROWS => {
+                        Cursor::Activities(Some((last.occurred_at, last.activity_id.unwrap_or_default())))
+                    }
+                    _ => Cursor::Done,
+                };
+                Ok((rows, next))
+            }
+            Cursor::Done => Ok((Vec::new(), Cursor::Done)),
+        }
+    }
+}
+
+/// The rows as text in `format`, header included
+pub fn encode(
+    format: ExportFormat,
+    rows: impl Stream<Item = Result<ExportRow, AppError>> + Send + 'static,
+) -> impl Stream<Item = Result<String, AppError>> + Send + 'static {
+    futures::stream::iter(format.preamble().map(Ok))
+        .chain(rows.map(move |row| row.map(|row| format.encode(&row))))
+}
+
+/// Record who exported whose activity
+pub async fn record_export(
+    db: &sqlx::PgPool,
+    user_id: Uuid,
+    scope: ExportScope,
+    params: &ActivityExportParams,
+    rows: i64,
+    background_export: Option<Uuid>,
+) -> Result<(), AppError> {
+    let project_id = match scope {
+        ExportScope::Project(project_id) => Some(project_id),
+        ExportScope::Workspace(_) => None,
+    };
+    AuditEvent::record(
+        db,
+        Some(user_id),
+        "activity_exported",
+        scope.entity_type(),
+        Some(scope.id()),
+        project_id,
+        serde_json::json!({
+            "from": params.from,
+            "to": params.to,
+            "format": params.format.unwrap_or_default(),
+            "rows": rows,
+            "background_export_id": background_export,
+        }),
+    )
+    .await?;
+    Ok(())
+}
+
+/// Background export status
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type, ToSchema)]
+#[sqlx(type_name = "activityexportstatus")]
+pub enum ActivityExportStatus {
+    #[serde(rename = "pending")]
+    #[sqlx(rename = "pending")]
+    Pending,
+    #[serde(rename = "ready")]
+    #[sqlx(rename = "ready")]
+    Ready,
+    #[serde(rename = "failed")]
+    #[sqlx(rename = "failed")]
+    Failed,
+}
+
+/// An export generated in the background
+#[derive(Debug, Clone, Serialize, FromRow, ToSchema)]
+pub struct ActivityExport {
+    pub id: Uuid,
+    pub requested_by: Uuid,
+    /// Exported project; unset for workspace exports and once the project is deleted
+    pub project_id: Option<Uuid>,
+    /// Exported workspace; unset for project exports and once the workspace is deleted
+    pub workspace_id: Option<Uuid>,
+    pub format: ExportFormat,
+    pub period_start: Option<DateTime<Utc>>,
+    pub period_end: Option<DateTime<Utc>>,
+    pub status: ActivityExportStatus,
+    pub row_count: Option<i64>,
+    pub size_bytes: Option<i64>,
+    pub error: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub completed_at: Option<DateTime<Utc>>,
+    /// The export and its file are removed after this
+    pub expires_at: DateTime<Utc>,
+}
+
+/// A background export with the URLs it is served at
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct ActivityExportView {
+    #[serde(flatten)]
+    pub export: ActivityExport,
+    pub status_url: String,
+    /// Set once the export is ready
+    pub download_url: Option<String>,
+}
+
+fn exports_dir(root: &str) -> PathBuf {
+    PathBuf::from(root).join("activity-exports")
+}
+
+impl ActivityExport {
+    pub fn status_url(&self) -> String {
+        format!("/api/v1/users/activity-exports/{}", self.id)
+    }
+
+    pub fn storage_path(&self, root: &str) -> PathBuf {
+        exports_dir(root).join(format!("{}.{}", self.id, self.format.extension()))
+    }
+
+    fn scope(&self) -> Option<ExportScope> {
+        self.project_id
+            .map(ExportScope::Project)
+            .or(self.workspace_id.map(ExportScope::Workspace))
+    }
+
+    /// File name offered for the download
+    pub fn file_name(&self) -> String {
+        match self.scope() {
+            Some(scope) => scope.file_name(self.format),
+            None => format!("activity-{}.{}", self.id, self.format.extension()),
+        }
+    }
+
+    pub fn view(self) -> ActivityExportView {
+        ActivityExportView {
+            status_url: self.status_url(),
+            download_url: (self.status == ActivityExportStatus::Ready)
+                .then(|| format!("{}/download", self.status_url())),
+            export: self,
+        }
+    }
+
+    /// Record a pending export of `scope`
+    pub async fn create(
+        db: &sqlx::PgPool,
+        requested_by: Uuid,
+        scope: ExportScope,
+        params: &ActivityExportParams,
+    ) -> Result<Self, AppError> {
+        let (project_id, workspace_id) = match scope {
+            ExportScope::Project(project_id) => (Some(project_id), None),
+            ExportScope::Workspace(workspace_id) => (None, Some(workspace_id)),
+        };
+        sqlx::query_as::<_, ActivityExport>(
+            r#"
+            INSERT INTO activity_exports
+                (requested_by, project_id, workspace_id, format, period_start, period_end, expires_at)
+            VALUES ($1, $2, $3, $4, $5, $6, NOW() + $7 * INTERVAL '1 day')
+            RETURNING *
+            "#
+        )
+        .bind(requested_by)
+        .bind(project_id)
+        .bind(workspace_id)
+        .bind(params.format.unwrap_or_default())
+        .bind(params.from)
+        .bind(params.to)
+        .bind(EXPORT_RETENTION_DAYS as f64)
+        .fetch_one(db)
+        .await
+        .map_err(AppError::Database)
+    }
+
+    /// An unexpired export requested by `user_id`
+    pub async fn find_for_user(db: &sqlx::PgPool, export_id: Uuid, user_id: Uuid) -> Result<Option<Self>, AppError> {
+        sqlx::query_as::<_, ActivityExport>(
+            "SELECT * FROM activity_exports WHERE id = $1 AND requested_by = $2 AND expires_at > NOW()"
+        )
+        .bind(export_id)
+        .bind(user_id)
+        .fetch_optional(db)
+        .await
+        .map_err(AppError::Database)
+    }
+
+    /// Write the export's file and mark it ready
+    pub async fn generate(&self, db: &sqlx::PgPool, root: &str) -> Result<Self, AppError> {
+        let scope = self.scope().ok_or_else(|| {
+            AppError::Validation("The exported project or workspace no longer exists".to_string())
+        })?;
+        let query = ActivityExportQuery::new(db, scope, self.period_start, self.period_end).await?;
+
+        let path = self.storage_path(root);
+        let partial = path.with_extension("partial");
+        tokio::fs::create_dir_all(exports_dir(root)).await?;
+        let written = write_rows(&partial, self.format, query.rows(db.clone())).await;
+        let (rows, size) = match written {
+            Ok(written) => written,
+            Err(e) => {
+                let _ = tokio::fs::remove_file(&partial).await;
+                return Err(e);
+            }
+        };
+        tokio::fs::rename(&partial, &path).await?;
+
+        sqlx::query_as::<_, ActivityExport>(
+            r#"
+            UPDATE activity_exports
+            SET status = 'ready', row_count = $2, size_bytes = $3, completed_at = NOW()
+            WHERE id = $1
+            RETURNING *
+            "#
+        )
+        .bind(self.id)
+        .bind(rows)
+        .bind(size)
+        .fetch_one(db)
+        .await
+        .map_err(AppError::Database)
+    }
+
+    async fn mark_failed(&self, db: &sqlx::PgPool, error: &str) -> Result<Self, AppError> {
+        sqlx::query_as::<_, ActivityExport>(
+            r#"
+            UPDATE activity_exports
+            SET status = 'failed', error = $2, completed_at = NOW()
+            WHERE id = $1
+            RETURNING *
+            "#
+        )
+        .bind(self.id)
+        .bind(error)
+        .fetch_one(db)
+        .await
+        .map_err(AppError::Database)
+    }
+
+    /// Generate the export and notify the requester of the outcome
+    pub async fn run(self, state: crate::server::AppState) {
+        let db = &state.db_pool;
+        let export = match self.generate(db, &state.config.features.file_storage.local_path).await {
+            Ok(export) => export,
+            Err(e) => {
+                tracing::warn!("Activity export {} failed: {}", self.id, e);
+                match self.mark_failed(db, &e.to_string()).await {
+                    Ok(export) => export,
+                    Err(e) => {
+                        tracing::error!("Failed to record the failure of activity export {}: {}", self.id, e);
+                        return;
+                    }
+                }
+            }
+        };
+
+        let (title, body) = match export.status {
+            ActivityExportStatus::Ready => (
+                "Activity export ready",
+                format!("Your export of {} rows can be downloaded until {}.", export.row_count.unwrap_or(0), export.expires_at.to_rfc3339()),
+            ),
+            _ => ("Activity export failed", "Your activity export could not be generated.".to_string()),
+        };
+        let notified = async {
+            let user = User::find_by_id(db, export.requested_by).await?.ok_or_else(|| AppError::NotFound {
+                entity: "User".to_string(),
+                id: export.requested_by.to_string(),
+            })?;
+            let view = export.clone().view();
+            NotificationService::notify(
+                db,
+                &state.mailer,
+                &user,
+                "activity_export",
+                title,
+                &body,
+                Some(serde_json::json!({
+                    "export_id": export.id,
+                    "status": export.status,
+                    "status_url": view.status_url,
+                    "download_url": view.download_url,
+                })),
+            )
+            .await
+        }
+        .await;
+        if let Err(e) = notified {
+            tracing::warn!("Failed to notify {} about activity export {}: {}", export.requested_by, export.id, e);
+        }
+    }
+
+    /// Remove expired exports with their files, and fail exports left
+    /// pending by a server that stopped; returns the exports removed
+    pub async fn clean_up(db: &sqlx::PgPool, root: &str) -> Result<u64, AppError> {
+        sqlx::query(
+            r#"
+            UPDATE activity_exports
+            SET status = 'failed', error = 'Interrupted by a server restart', completed_at = NOW()
+            WHERE status = 'pending' AND created_at < NOW() - $1 * INTERVAL '1 minute'
+            "#
+        )
+        .bind(STALE_PENDING_MINUTES as f64)
+        .execute(db)
+        .await
+        .map_err(AppError::Database)?;
+
+        let expired = sqlx::query_as::<_, ActivityExport>(
+            "DELETE FROM activity_exports WHERE expires_at <= NOW() RETURNING *"
+        )
+        .fetch_all(db)
+        .await
+        .map_err(AppError::Database)?;
+
+        for export in &expired {
+            let path = export.storage_path(root);
+            for path in [path.with_extension("partial"), path] {
+                match tokio::fs::remove_file(&path).await {
+                    Ok(()) => {}
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                    Err(e) => tracing::warn!("Failed to remove activity export {}: {}", path.display(), e),
+                }
+            }
+        }
+
+        Ok(expired.len() as u64)
+    }
+}
+
+/// Write the rows to `path`; returns the rows and bytes written
+async fn write_rows(
+    path: &Path,
+    format: ExportFormat,
+    rows: impl Stream<Item = Result<ExportRow, AppError>> + Send + 'static,
+) -> Result<(i64, i64), AppError> {
+    let mut file = tokio::io::BufWriter::new(tokio::fs::File::create(path).await?);
+    let (mut count, mut size) = (0i64, 0i64);
+    if let Some(preamble) = format.preamble() {
+        file.write_all(preamble.as_bytes()).await?;
+        size += preamble.len() as i64;
+    }
+
+    let mut rows = std::pin::pin!(rows);
+    while let Some(row) = rows.next().await {
+        let line = format.encode(&row?);
+        file.write_all(line.as_bytes()).await?;
+        count += 1;
+        size += line.len() as i64;
+    }
+    file.flush().await?;
+    file.into_inner().sync_all().await?;
+    Ok((count, size))
+}
+
+/// Removes expired activity exports
+pub struct ActivityExportCleanupTask;
+
+impl crate::tasks::PeriodicTask for ActivityExportCleanupTask {
+    fn name(&self) -> &'static str {
+        "activity_export_cleanup"
+    }
+
+    fn interval(&self) -> Duration {
+        CLEANUP_INTERVAL
+    }
+
+    fn run<'a>(
+        &'a self,
+        state: &'a crate::server::AppState,
+    ) -> futures::future::BoxFuture<'a, Result<(), AppError>> {
+        Box::pin(async move {
+            let removed = ActivityExport::clean_up(&state.db_pool, &state.config.features.file_storage.local_path).await?;
+            if removed > 0 {
+                tracing::info!("Removed {} expired activity exports", removed);
+            }
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{create_test_project, create_test_user, TestDb};
+    use futures::TryStreamExt;
+
+    fn row(details: Option<&str>) -> ExportRow {
+        ExportRow {
+            record: ExportRecord::Activity,
+            activity_id: Some(Uuid::nil()),
+            project_id: Uuid::nil(),
+            project_name: "Thesis, draft \"2\"".to_string(),
+            occurred_at: DateTime::parse_from_rfc3339("2024-03-10T12:00:00Z").unwrap().into(),
+            period_end: None,
+            user_id: Some(Uuid::nil()),
+            username: Some("ada".to_string()),
+            action: "file_updated".to_string(),
+            entity_type: Some("file".to_string()),
+            entity_id: None,
+            count: 1,
+            details: details.map(String::from),
+        }
+    }
+
+    #[test]
+    fn test_csv_fields_are_escaped() {
+        assert_eq!(csv_field("plain"), "plain");
+        assert_eq!(csv_field("a,b"), "\"a,b\"");
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(csv_field("two\nlines"), "\"two\nlines\"");
+        assert_eq!(csv_field("=HYPERLINK(\"x\")"), "\"'=HYPERLINK(\"\"x\"\")\"");
+        assert_eq!(csv_field("@sum"), "'@sum");
+
+        let line = ExportFormat::Csv.encode(&row(Some(r#"{"path":"main.tex"}"#)));
+        let nil = Uuid::nil();
+        assert_eq!(
+            line,
+            format!(
+                r#"activity,{nil},{nil},"Thesis, draft ""2""",2024-03-10T12:00:00Z,,{nil},ada,file_updated,file,,1,"{{""path"":""main.tex""}}""#
+            ) + "\r\n"
+        );
+        assert_eq!(ExportFormat::Csv.preamble().unwrap().trim_end().split(',').count(), CSV_COLUMNS.len());
+    }
+
+    #[test]
+    fn test_json_rows_are_single_lines() {
+        let line = ExportFormat::Json.encode(&row(Some("multi\nline")));
+        assert_eq!(line.matches('\n').count(), 1);
+        let value: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(value["record"], "activity");
+        assert_eq!(value["details"], "multi\nline");
+        assert_eq!(value["count"], 1);
+    }
+
+    #[tokio::test]
+    async fn test_export_marks_rolled_up_days() {
+        let Some(db) = TestDb::start().await else { return };
+        let owner = create_test_user(&db.pool).await;
+        let project = create_test_project(&db.pool, &owner, false).await;
+
+        // 2500 activities over the last 50 days, the older half rolled up
+        let now = Utc::now();
+        sqlx::query(
+            r#"
+            INSERT INTO project_activity (project_id, user_id, action, entity_type, created_at)
+            SELECT $1, $2, CASE WHEN i % 2 = 0 THEN 'file_updated' ELSE 'compile' END, 'file',
+                $3 - (i % 50) * INTERVAL '1 day'
+            FROM generate_series(0, 2499) AS i
+            "#
+        )
+        .bind(project.id)
+        .bind(owner.id)
+        .bind(now)
+        .execute(&db.pool)
+        .await
+        .unwrap();
+        let cutoff = crate::models::activity_rollup::rollup_cutoff(now, chrono::Duration::days(25));
+        crate::models::activity_rollup::roll_up(&db.pool, cutoff).await.unwrap();
+
+        let query = ActivityExportQuery::new(&db.pool, ExportScope::Project(project.id), None, None).await.unwrap();
+        let rows: Vec<ExportRow> = query.clone().rows(db.pool.clone()).try_collect().await.unwrap();
+        assert_eq!(rows.len() as i64, query.count_rows(&db.pool).await.unwrap());
+        assert_eq!(rows.iter().map(|row| row.count).sum::<i64>(), 2500);
+
+        // Aggregated days come first, each spanning one day without an actor
+        let split = rows.iter().position(|row| row.record == ExportRecord::Activity).unwrap();
+        assert!(split > 0);
+        assert!(rows[split..].iter().all(|row| row.record == ExportRecord::Activity));
+        for row in &rows[..split] {
+            assert_eq!(row.record, ExportRecord::Aggregated);
+            assert_eq!(row.period_end, Some(row.occurred_at + chrono::Duration::days(1)));
+            assert!(row.username.is_none() && row.occurred_at < cutoff);
+        }
+        assert!(rows[split..].iter().all(|row| row.username.as_deref() == Some(owner.username.as_str())));
+        // Pages continue exactly where the previous one stopped
+        assert!(rows[split..].windows(2).all(|pair| {
+            (pair[0].occurred_at, pair[0].activity_id) < (pair[1].occurred_at, pair[1].activity_id)
+        }));
+
+        let recent = ActivityExportQuery::new(&db.pool, ExportScope::Project(project.id), Some(cutoff), None)
+            .await
+            .unwrap();
+        let rows: Vec<ExportRow> = recent.rows(db.pool.clone()).try_collect().await.unwrap();
+        assert!(rows.iter().all(|row| row.record == ExportRecord::Activity && row.occurred_at >= cutoff));
+    }
+}
@@ -0,0 +1,286 @@
+//! Project activity retention
+//!
+//! Activity rows are kept for `DatabaseConfig::activity_retention_days`, then
+//! rolled up into daily per-action counters in `project_activity_rollup` and
+//! deleted, so the log stops growing while long-term charts keep working.
+//! Rolling up moves rows and bumps counters in one statement, so every
+//! activity is counted exactly once; [`ActivityCount::between`] reads both
+//! sides of the retention boundary and is what aggregate views should use.
+
+use chrono::{DateTime, Duration as ChronoDuration, NaiveDate, TimeZone, Utc};
+use chrono_tz::Tz;
+use serde::Serialize;
+use sqlx::FromRow;
+use std::collections::BTreeMap;
+use std::time::Duration;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::error::AppError;
+
+/// How often old activity is rolled up
+pub const ROLLUP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Shortest retention, so a weekly digest still sees every file change
+pub const MIN_RETENTION_DAYS: u64 = 7;
+
+/// Activities of one action on one day
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, FromRow, ToSchema)]
+pub struct ActivityCount {
+    /// Day in the requested time zone; rolled-up days are UTC days
+    pub day: NaiveDate,
+    pub action: String,
+    pub count: i64,
+}
+
+impl ActivityCount {
+    /// Daily counts per action in a period, from detailed rows and rollups
+    ///
+    /// Detailed rows are counted on their day in `tz`. Rollups only keep UTC
+    /// days, which count whole when their date falls in the period.
+    pub async fn between(
+        db: &sqlx::PgPool,
+        project_id: Uuid,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        tz: Tz,
+    ) -> Result<Vec<Self>, AppError> {
+        sqlx::query_as::<_, ActivityCount>(
+            r#"
+            SELECT day, action, SUM(count)::BIGINT AS count FROM (
+                SELECT day, action, count FROM project_activity_rollup
+                WHERE project_id = $1
+                  AND day >= ($2 AT TIME ZONE 'UTC')::DATE
+                  AND day < ($3 AT TIME ZONE 'UTC')::DATE
+                UNION ALL
+                SELECT (created_at AT TIME ZONE $4)::DATE AS day, action, COUNT(*) AS count
+                FROM project_activity
+                WHERE project_id = $1 AND created_at >= $2 AND created_at < $3
+                GROUP BY 1, 2
+            ) counts
+            GROUP BY day, action
+            ORDER BY day, action
+            "#
+        )
+        .bind(project_id)
+        .bind(start)
+        .bind(end)
+        .bind(tz.name())
+        .fetch_all(db)
+        .await
+        .map_err(AppError::Database)
+    }
+
+    /// Totals per action
+    pub fn totals(counts: &[ActivityCount]) -> BTreeMap<String, i64> {
+        let mut totals = BTreeMap::new();
+        for count in counts {
+            *totals.entry(count.action.clone()).or_insert(0) += count.count;
+        }
+        totals
+    }
+}
+
+/// Outcome of a rollup
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct ActivityRollupReport {
+    pub activities_rolled_up: i64,
+    pub counters_updated: i64,
+    /// Activity before this was rolled up
+    pub cutoff: DateTime<Utc>,
+}
+
+/// How long activity is kept row by row
+pub fn activity_retention(config: &crate::config::Config) -> ChronoDuration {
+    ChronoDuration::days(config.database.activity_retention_days.max(MIN_RETENTION_DAYS) as i64)
+}
+
+/// Start of the UTC day `retention` before `now`, so only whole days are rolled up
+pub fn rollup_cutoff(now: DateTime<Utc>, retention: ChronoDuration) -> DateTime<Utc> {
+    let day = (now - retention).date_naive();
+    Utc.from_utc_datetime(&day.and_hms_opt(0, 0, 0).unwrap())
+}
+
+/// Move activity older than `cutoff` into the daily counters
+pub async fn roll_up(db: &sqlx::PgPool, cutoff: DateTime<Utc>) -> Result<ActivityRollupReport, AppError> {
+    let (activities_rolled_up, counters_updated) = sqlx::query_as::<_, (i64, i64)>(
+        r#"
+        WITH moved AS (
+            DELETE FROM project_activity
+            WHERE created_at < $1
+            RETURNING project_id, action, created_at
+        ), counted AS (
+            INSERT INTO project_activity_rollup (project_id, day, action, count)
+            SELECT project_id, (created_at AT TIME ZONE 'UTC')::DATE, action, COUNT(*)
+            FROM moved
+            GROUP BY 1, 2, 3
+            ON CONFLICT (project_id, day, action)
+            DO UPDATE SET count = project_activity_rollup.count + EXCLUDED.count
+            RETURNING 1
+        )
+        SELECT (SELECT COUNT(*) FROM moved), (SELECT COUNT(*) FROM counted)
+        "#
+    )
+    .bind(cutoff)
+    .fetch_one(db)
+    .await
+    .map_err(AppError::Database)?;
+
+    Ok(ActivityRollupReport {
+        activities_rolled_up,
+        counters_updated,
+        cutoff,
+    })
+}
+
+/// Rolls up project activity past the retention window
+pub struct ActivityRollupTask;
+
+impl crate::tasks::PeriodicTask for ActivityRollupTask {
+    fn name(&self) -> &'static str {
+        "activity_rollup"
+    }
+
+    fn interval(&self) -> Duration {
+        ROLLUP_INTERVAL
+    }
+
+    fn run<'a>(
+        &'a self,
+        state: &'a crate::server::AppState,
+    ) -> futures::future::BoxFuture<'a, Result<(), AppError>> {
+        Box::pin(async move {
+            let cutoff = rollup_cutoff(Utc::now(), activity_retention(&state.config));
+            let report = roll_up(&state.db_pool, cutoff).await?;
+            if report.activities_rolled_up > 0 {
+                tracing::info!(
+                    "Rolled up {} project activities into {} daily counters",
+                    report.activities_rolled_up,
+                    report.counters_updated
+                );
+            }
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{create_test_project, create_test_user, TestDb};
+
+    const ACTIONS: [&str; 3] = ["file_updated", "file_created", "compile"];
+
+    #[test]
+    fn test_cutoff_is_start_of_day() {
+        let now = Utc.with_ymd_and_hms(2024, 3, 20, 15, 30, 0).unwrap();
+        assert_eq!(
+            rollup_cutoff(now, ChronoDuration::days(10)),
+            Utc.with_ymd_and_hms(2024, 3, 10, 0, 0, 0).unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_rollup_preserves_counts() {
+        let Some(db) = TestDb::start().await else { return };
+        let owner = create_test_user(&db.pool).await;
+        let project = create_test_project(&db.pool, &owner, false).await;
+
+        // 10k activities spread over the 100 days before `now`
+        let now = Utc::now();
+        sqlx::query(
+            r#"
+            INSERT INTO project_activity (project_id, user_id, action, entity_type, created_at)
+            SELECT $1, $2, ($3::TEXT[])[1 + i % 3], 'file', $4 - (i % 100) * INTERVAL '1 day' - (i % 7) * INTERVAL '1 hour'
+            FROM generate_series(0, 9999) AS i
+            "#
+        )
+        .bind(project.id)
+        .bind(owner.id)
+        .bind(ACTIONS.map(String::from).to_vec())
+        .bind(now)
+        .execute(&db.pool)
+        .await
+        .unwrap();
+
+        let start = now - ChronoDuration::days(120);
+        let end = now + ChronoDuration::hours(1);
+        let before = ActivityCount::between(&db.pool, project.id, start, end, Tz::UTC).await.unwrap();
+        let totals = ActivityCount::totals(&before);
+        assert_eq!(ACTIONS.iter().map(|action| totals[*action]).sum::<i64>(), 10_000);
+
+        let cutoff = rollup_cutoff(now, ChronoDuration::days(30));
+        let report = roll_up(&db.pool, cutoff).await.unwrap();
+        assert!(report.activities_rolled_up > 0);
+
+        let remaining = sqlx::query_scalar::<_, i64>(
+            "SELECT COUNT(*) FROM project_activity WHERE project_id = $1 AND created_at < $2"
+        )
+        .bind(project.id)
+        .bind(cutoff)
+        .fetch_one(&db.pool)
+        .await
+        .unwrap();
+        assert_eq!(remaining, 0);
+
+        let after = ActivityCount::between(&db.pool, project.id, start, end, Tz::UTC).await.unwrap();
+        assert_eq!(after, before);
+        assert_eq!(ActivityCount::totals(&after), totals);
+
+        // Rolling up again finds nothing new and changes nothing
+        let again = roll_up(&db.pool, cutoff).await.unwrap();
+        assert_eq!(again.activities_rolled_up, 0);
+        assert_eq!(ActivityCount::between(&db.pool, project.id, start, end, Tz::UTC).await.unwrap(), before);
+    }
+
+    /// Activity per day of 2030 in `tz`
+    async fn daily_totals(db: &sqlx::PgPool, project_id: Uuid, tz: Tz) -> Vec<(String, i64)> {
+        let start = crate::models::timezone::start_of_day(tz, "2030-01-01".parse().unwrap());
+        let end = crate::models::timezone::start_of_day(tz, "2031-01-01".parse().unwrap());
+        ActivityCount::between(db, project_id, start, end, tz)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|count| (count.day.to_string(), count.count))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_days_follow_the_time_zone_across_daylight_saving_changes() {
+        let Some(db) = TestDb::start().await else { return };
+        let owner = create_test_user(&db.pool).await;
+        let project = create_test_project(&db.pool, &owner, false).await;
+
+        // Half an hour either side of local midnight around the 23-hour
+        // Sunday in March and the 25-hour one in October
+        let times = [
+            "2030-03-30T22:30:00Z", "2030-03-30T23:30:00Z", "2030-03-31T21:30:00Z", "2030-03-31T22:30:00Z",
+            "2030-10-26T21:30:00Z", "2030-10-26T22:30:00Z", "2030-10-27T22:30:00Z", "2030-10-27T23:30:00Z",
+        ];
+        for time in times {
+            sqlx::query(
+                "INSERT INTO project_activity (project_id, user_id, action, entity_type, created_at) VALUES ($1, $2, 'file_updated', 'file', $3)"
+            )
+            .bind(project.id)
+            .bind(owner.id)
+            .bind(DateTime::parse_from_rfc3339(time).unwrap().with_timezone(&Utc))
+            .execute(&db.pool)
+            .await
+            .unwrap();
+        }
+
+        let expected = |days: &[(&str, i64)]| days.iter().map(|(day, count)| (day.to_string(), *count)).collect::<Vec<_>>();
+
+        assert_eq!(
+            daily_totals(&db.pool, project.id, chrono_tz::Europe::Berlin).await,
+            expected(&[
+                ("2030-03-30", 1), ("2030-03-31", 2), ("2030-04-01", 1),
+                ("2030-10-26", 1), ("2030-10-27", 2), ("2030-10-28", 1),
+            ])
+        );
+        assert_eq!(
+            daily_totals(&db.pool, project.id, Tz::UTC).await,
+            expected(&[("2030-03-30", 2), ("2030-03-31", 2), ("2030-10-26", 2), ("2030-10-27", 2)])
+        );
+    }
+}
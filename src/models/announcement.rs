@@ -0,0 +1,728 @@
+//! Announcements
+//!
+//! Users learn what changed in the releases they upgraded to, and what the
+//! instance's administrators have to tell them, without leaving the app.
+//! Release notes are compiled in: [`RELEASE_NOTES`] has an entry per change,
+//! keyed by the version that shipped it. Administrators write instance
+//! announcements at runtime, shown from their start to their optional
+//! expiry; once one starts, its audience also gets an in-app notification.
+//!
+//! Users acknowledge announcements up to the running release and the current
+//! time. From then on they see the notes of releases after the acknowledged
+//! one up to the running one, so a jump over several releases shows each of
+//! them once, and the instance announcements that started since. A user who
+//! never acknowledged sees the notes of the running release only, not the
+//! whole history. Versions compare by semantic versioning precedence, where
+//! a prerelease comes before its release and build metadata is ignored.
+
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use std::cmp::Ordering;
+use std::time::Duration;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use super::readme::render_markdown;
+use crate::error::AppError;
+use crate::server::AppState;
+
+/// Announcements returned at most, newest first
+pub const MAX_ANNOUNCEMENTS: usize = 20;
+
+/// Longest announcement title, in characters
+const MAX_TITLE_CHARS: usize = 200;
+
+/// Longest announcement body, in bytes of Markdown
+const MAX_BODY_BYTES: usize = 20_000;
+
+/// How often started announcements are delivered as notifications
+const DELIVERY_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Who an announcement is for: everyone, or the instance administrator only
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, sqlx::Type, ToSchema)]
+#[sqlx(type_name = "announcementaudience")]
+pub enum AnnouncementAudience {
+    #[serde(rename = "everyone")]
+    #[sqlx(rename = "everyone")]
+    #[default]
+    Everyone,
+    #[serde(rename = "admins")]
+    #[sqlx(rename = "admins")]
+    Admins,
+}
+
+impl AnnouncementAudience {
+    fn includes(self, admin: bool) -> bool {
+        self == AnnouncementAudience::Everyone || admin
+    }
+}
+
+/// A change described in the release that shipped it
+#[derive(Debug, Clone, Copy)]
+pub struct ReleaseNote {
+    /// Semantic version of the release
+    pub version: &'static str,
+    /// Day of the release, `YYYY-MM-DD`
+    pub date: &'static str,
+    pub title: &'static str,
+    /// Markdown
+    pub body: &'static str,
+    pub audience: AnnouncementAudience,
+}
+
+/// Notes of every release, newest first
+pub const RELEASE_NOTES: &[ReleaseNote] = &[
+    ReleaseNote {
+        version: "0.1.0",
+        date: "2026-10-17",
+        title: "What's new",
+        body: "Notes like this one tell you what changed after the server was upgraded. \
+               Mark them as read and they only come back with the next release.",
+        audience: AnnouncementAudience::Everyone,
+    },
+    ReleaseNote {
+        version: "0.1.0",
+        date: "2026-10-17",
+        title: "Line-level blame",
+        body: "Files can show, for each line, the version that introduced it and who wrote it. \
+               A rewritten line belongs to whoever rewrote it, and changes in spacing alone \
+               can be ignored.",
+        audience: AnnouncementAudience::Everyone,
+    },
+    ReleaseNote {
+        version: "0.1.0",
+        date: "2026-10-17",
+        title: "Riding out database restarts",
+        body: "Reads are retried when the database connection drops, and writes that can wait, \
+               like activity entries and notifications, are kept in memory until the database \
+               is back. See `DATABASE_CIRCUIT_THRESHOLD`, `DATABASE_CIRCUIT_COOLDOWN` and \
+               `DATABASE_WRITE_BUFFER`.",
+        audience: AnnouncementAudience::Admins,
+    },
+];
+
+/// A prerelease identifier; numeric ones come before alphanumeric ones
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+enum Identifier {
+    Numeric(u64),
+    Alphanumeric(String),
+}
+
+/// A semantic version, ordered by precedence
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReleaseVersion {
+    major: u64,
+    minor: u64,
+    patch: u64,
+    prerelease: Vec<Identifier>,
+}
+
+impl ReleaseVersion {
+    /// Parse `major.minor.patch`, with an optional `-prerelease` and `+build`
+    pub fn parse(version: &str) -> Option<Self> {
+        let version = version.trim();
+        let version = version.split_once('+').map_or(version, |(version, _build)| version);
+        let (core, prerelease) = match version.split_once('-') {
+            Some((core, prerelease)) => (core, Some(prerelease)),
+            None => (version, None),
+        };
+
+        let mut numbers = core.split('.').map(|number| number.parse::<u64>().ok());
+        let (major, minor, patch) = (numbers.next()??, numbers.next()??, numbers.next()??);
+        if numbers.next().is_some() {
+            return None;
+        }
+
+        let prerelease = match prerelease {
+            None => Vec::new(),
+            Some(prerelease) => prerelease
+                .split('.')
+                .map(|identifier| match identifier.parse::<u64>() {
+                    _ if identifier.is_empty() => None,
+                    Ok(number) => Some(Identifier::Numeric(number)),
+                    Err(_) => Some(Identifier::Alphanumeric(identifier.to_string())),
+                })
+                .collect::<Option<_>>()?,
+        };
+        Some(Self { major, minor, patch, prerelease })
+    }
+}
+
+impl Ord for ReleaseVersion {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.major, self.minor, self.patch)
+            .cmp(&(other.major, other.minor, other.patch))
+            .then_with(|| match (self.prerelease.is_empty(), other.prerelease.is_empty()) {
+                (true, true) => Ordering::Equal,
+                (true, false) => Ordering::Greater,
+                (false, true) => Ordering::Less,
+                (false, false) => self.prerelease.cmp(&other.prerelease),
+            })
+    }
+}
+
+impl PartialOrd for ReleaseVersion {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Notes a user is due, newest release first: those after `acknowledged` up
+/// to `current`, or only those of `current` when nothing was acknowledged
+pub fn due_release_notes<'a>(
+    notes: &'a [ReleaseNote],
+    acknowledged: Option<&ReleaseVersion>,
+    current: &ReleaseVersion,
+    admin: bool,
+) -> Vec<(&'a ReleaseNote, ReleaseVersion)> {
+    let mut due: Vec<_> = notes
+        .iter()
+        .filter(|note| note.audience.includes(admin))
+        .filter_map(|note| ReleaseVersion::parse(note.version).map(|version| (note, version)))
+        .filter(|(_, version)| match acknowledged {
+            Some(acknowledged) => version > acknowledged && version <= current,
+            None => version == current,
+        })
+        .collect();
+    due.sort_by(|(_, a), (_, b)| b.cmp(a));
+    due
+}
+
+/// Where an announcement comes from: a note of a release, compiled into the
+/// server, or an instance announcement written by an administrator
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum AnnouncementSource {
+    Release,
+    Instance,
+}
+
+/// An announcement as shown to a user
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct Announcement {
+    pub source: AnnouncementSource,
+    /// ID of an instance announcement
+    pub id: Option<Uuid>,
+    /// Release a release note belongs to
+    pub version: Option<String>,
+    pub title: String,
+    /// Markdown source
+    pub body: String,
+    /// Sanitized HTML of the body
+    pub html: String,
+    pub audience: AnnouncementAudience,
+    /// Day of the release, or when the instance announcement started
+    pub published_at: DateTime<Utc>,
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+/// Announcements a user has not acknowledged yet
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct Announcements {
+    /// Newest first, at most `MAX_ANNOUNCEMENTS`
+    pub announcements: Vec<Announcement>,
+    /// Whether more are due than listed
+    pub has_more: bool,
+    /// Release the server runs
+    pub current_version: String,
+    /// Release the user last acknowledged, if ever
+    pub acknowledged_version: Option<String>,
+}
+
+/// How far a user read the announcements
+#[derive(Debug, Clone, Serialize, FromRow, ToSchema)]
+pub struct Acknowledgement {
+    pub version: String,
+    pub acknowledged_at: DateTime<Utc>,
+}
+
+/// An announcement written by an administrator
+#[derive(Debug, Clone, Serialize, FromRow, ToSchema)]
+pub struct InstanceAnnouncement {
+    pub id: Uuid,
+    pub title: String,
+    /// Markdown
+    pub body: String,
+    pub audience: AnnouncementAudience,
+    pub starts_at: DateTime<Utc>,
+    pub expires_at: Option<DateTime<Utc>>,
+    /// When the audience was notified, once the announcement started
+    pub notified_at: Option<DateTime<Utc>>,
+    pub created_by: Option<Uuid>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// Request to create or replace an instance announcement
+#[derive(Debug, Clone, Deserialize, ToSchema)]
+pub struct AnnouncementRequest {
+    pub title: String,
+    /// Markdown
+    pub body: String,
+    #[serde(default)]
+    pub audience: AnnouncementAudience,
+    /// When to start showing it; now by default
+    pub starts_at: Option<DateTime<Utc>>,
+    /// When to stop showing it; never by default
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+impl AnnouncementRequest {
+    /// Check the title, body and schedule, and settle the start
+    fn validate(&self) -> Result<DateTime<Utc>, AppError> {
+        let title = self.title.trim();
+        if title.is_empty() || title.chars().count() > MAX_TITLE_CHARS {
+            return Err(AppError::Validation(format!(
+                "Announcement title must be 1 to {} characters",
+                MAX_TITLE_CHARS
+            )));
+        }
+        if self.body.trim().is_empty() || self.body.len() > MAX_BODY_BYTES {
+            return Err(AppError::Validation(format!(
+                "Announcement body must be 1 to {} bytes",
+                MAX_BODY_BYTES
+            )));
+        }
+        let starts_at = self.starts_at.unwrap_or_else(Utc::now);
+        if self.expires_at.is_some_and(|expires_at| expires_at <= starts_at) {
+            return Err(AppError::Validation("Announcement must expire after it starts".to_string()));
+        }
+        Ok(starts_at)
+    }
+}
+
+fn render(body: &str) -> String {
+    render_markdown(body).link_images(|_| None)
+}
+
+impl Announcement {
+    fn from_release_note(note: &ReleaseNote) -> Self {
+        let published_at = NaiveDate::parse_from_str(note.date, "%Y-%m-%d")
+            .ok()
+            .and_then(|date| date.and_hms_opt(0, 0, 0))
+            .map(|midnight| midnight.and_utc())
+            .unwrap_or_default();
+        Self {
+            source: AnnouncementSource::Release,
+            id: None,
+            version: Some(note.version.to_string()),
+            title: note.title.to_string(),
+            body: note.body.to_string(),
+            html: render(note.body),
+            audience: note.audience,
+            published_at,
+            expires_at: None,
+        }
+    }
+
+    fn from_instance(announcement: InstanceAnnouncement) -> Self {
+        Self {
+            source: AnnouncementSource::Instance,
+            id: Some(announcement.id),
+            version: None,
+            html: render(&announcement.body),
+            title: announcement.title,
+            body: announcement.body,
+            audience: announcement.audience,
+            published_at: announcement.starts_at,
+            expires_at: announcement.expires_at,
+        }
+    }
+}
+
+impl Announcements {
+    /// Announcements due to `user_id` on this release
+    pub async fn for_user(db: &sqlx::PgPool, user_id: Uuid, admin: bool) -> Result<Self, AppError> {
+        Self::load(db, user_id, admin, RELEASE_NOTES, crate::VERSION).await
+    }
+
+    async fn load(
+        db: &sqlx::PgPool,
+        user_id: Uuid,
+        admin: bool,
+        notes: &[ReleaseNote],
+        current_version: &str,
+    ) -> Result<Self, AppError> {
+        let acknowledgement = sqlx::query_as::<_, Acknowledgement>(
+            "SELECT version, acknowledged_at FROM announcement_acknowledgements WHERE user_id = $1",
+        )
+        .bind(user_id)
+        .fetch_optional(db)
+        .await
+        .map_err(AppError::Database)?;
+
+        let instance = sqlx::query_as::<_, InstanceAnnouncement>(
+            r#"
+            SELECT * FROM instance_announcements
+            WHERE starts_at <= NOW() AND (expires_at IS NULL OR expires_at > NOW())
+              AND ($1::timestamptz IS NULL OR starts_at > $1)
+              AND (audience = 'everyone' OR $2)
+            ORDER BY starts_at DESC, id
+            LIMIT $3
+            "#,
+        )
+        .bind(acknowledgement.as_ref().map(|acknowledgement| acknowledgement.acknowledged_at))
+        .bind(admin)
+        .bind(MAX_ANNOUNCEMENTS as i64 + 1)
+        .fetch_all(db)
+        .await
+        .map_err(AppError::Database)?;
+
+        let current = ReleaseVersion::parse(current_version)
+            .ok_or_else(|| AppError::Internal(format!("Invalid release version {}", current_version)))?;
+        let acknowledged = acknowledgement
+            .as_ref()
+            .map(|acknowledgement| ReleaseVersion::parse(&acknowledgement.version));
+        let notes = match acknowledged {
+            // An unreadable acknowledgement is treated as none
+            Some(Some(acknowledged)) => due_release_notes(notes, Some(&acknowledged), &current, admin),
+            _ => due_release_notes(notes, None, &current, admin),
+        };
+
+        let mut announcements: Vec<Announcement> = instance
+            .into_iter()
+            .map(Announcement::from_instance)
+            .chain(notes.into_iter().map(|(note, _)| Announcement::from_release_note(note)))
+            .collect();
+        // Stable, so notes of one release keep their order
+        announcements.sort_by(|a, b| b.published_at.cmp(&a.published_at));
+        let has_more = announcements.len() > MAX_ANNOUNCEMENTS;
+        announcements.truncate(MAX_ANNOUNCEMENTS);
+
+        Ok(Self {
+            announcements,
+            has_more,
+            current_version: current_version.to_string(),
+            acknowledged_version: acknowledgement.map(|acknowledgement| acknowledgement.version),
+        })
+    }
+
+    /// Mark everything up to this release and now as read for `user_id`
+    pub async fn acknowledge(db: &sqlx::PgPool, user_id: Uuid) -> Result<Acknowledgement, AppError> {
+        Self::acknowledge_version(db, user_id, crate::VERSION).await
+    }
+
+    async fn acknowledge_version(
+        db: &sqlx::PgPool,
+        user_id: Uuid,
+        current_version: &str,
+    ) -> Result<Acknowledgement, AppError> {
+        let previous: Option<String> =
+            sqlx::query_scalar("SELECT version FROM announcement_acknowledgements WHERE user_id = $1")
+                .bind(user_id)
+                .fetch_optional(db)
+                .await
+                .map_err(AppError::Database)?;
+        // Running an older release does not bring back what a newer one showed
+        let version = match previous {
+            Some(previous)
+                if ReleaseVersion::parse(&previous)
+                    .zip(ReleaseVersion::parse(current_version))
+                    .is_some_and(|(previous, current)| previous > current) =>
+            {
+                previous
+            }
+            _ => current_version.to_string(),
+        };
+
+        sqlx::query_as::<_, Acknowledgement>(
+            r#"
+            INSERT INTO announcement_acknowledgements (user_id, version)
+            VALUES ($1, $2)
+            ON CONFLICT (user_id) DO UPDATE SET version = EXCLUDED.version, acknowledged_at = NOW()
+            RETURNING version, acknowledged_at
+            "#,
+        )
+        .bind(user_id)
+        .bind(version)
+        .fetch_one(db)
+        .await
+        .map_err(AppError::Database)
+    }
+}
+
+impl InstanceAnnouncement {
+    /// Every instance announcement, the latest to start first
+    pub async fn list(db: &sqlx::PgPool) -> Result<Vec<Self>, AppError> {
+        sqlx::query_as::<_, InstanceAnnouncement>(
+            "SELECT * FROM instance_announcements ORDER BY starts_at DESC, id",
+        )
+        .fetch_all(db)
+        .await
+        .map_err(AppError::Database)
+    }
+
+    pub async fn create(db: &sqlx::PgPool, request: &AnnouncementRequest, created_by: Uuid) -> Result<Self, AppError> {
+        let starts_at = request.validate()?;
+        sqlx::query_as::<_, InstanceAnnouncement>(
+            r#"
+            INSERT INTO instance_announcements (title, body, audience, starts_at, expires_at, created_by)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            RETURNING *
+            "#,
+        )
+        .bind(request.title.trim())
+        .bind(&request.body)
+        .bind(request.audience)
+        .bind(starts_at)
+        .bind(request.expires_at)
+        .bind(created_by)
+        .fetch_one(db)
+        .await
+        .map_err(AppError::Database)
+    }
+
+    /// Replace an announcement; an audience already notified is not notified again
+    pub async fn update(
+        db: &sqlx::PgPool,
+        announcement_id: Uuid,
+        request: &AnnouncementRequest,
+    ) -> Result<Self, AppError> {
+        let starts_at = request.validate()?;
+        sqlx::query_as::<_, InstanceAnnouncement>(
+            r#"
+            UPDATE instance_announcements
+            SET title = $2, body = $3, audience = $4, starts_at = $5, expires_at = $6, updated_at = NOW()
+            WHERE id = $1
+            RETURNING *
+            "#,
+        )
+        .bind(announcement_id)
+        .bind(request.title.trim())
+        .bind(&request.body)
+        .bind(request.audience)
+        .bind(starts_at)
+        .bind(request.expires_at)
+        .fetch_optional(db)
+        .await
+        .map_err(AppError::Database)?
+        .ok_or_else(|| AppError::NotFound {
+            entity: "Announcement".to_string(),
+            id: announcement_id.to_string(),
+        })
+    }
+
+    pub async fn delete(db: &sqlx::PgPool, announcement_id: Uuid) -> Result<(), AppError> {
+        let deleted = sqlx::query("DELETE FROM instance_announcements WHERE id = $1")
+            .bind(announcement_id)
+            .execute(db)
+            .await
+            .map_err(AppError::Database)?;
+        if deleted.rows_affected() == 0 {
+            return Err(AppError::NotFound {
+                entity: "Announcement".to_string(),
+                id: announcement_id.to_string(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Notify the audience of every announcement that started and was not
+    /// delivered yet; returns the notifications created
+    pub async fn deliver_started(db: &sqlx::PgPool) -> Result<u64, AppError> {
+        let mut tx = db.begin().await.map_err(AppError::Database)?;
+        let started = sqlx::query_as::<_, InstanceAnnouncement>(
+            r#"
+            UPDATE instance_announcements SET notified_at = NOW()
+            WHERE notified_at IS NULL AND starts_at <= NOW() AND (expires_at IS NULL OR expires_at > NOW())
+            RETURNING *
+            "#,
+        )
+        .fetch_all(&mut *tx)
+        .await
+        .map_err(AppError::Database)?;
+
+        let mut delivered = 0;
+        for announcement in started {
+            let created = sqlx::query(
+                r#"
+                INSERT INTO notifications (user_id, kind, title, body, data)
+                SELECT id, 'announcement', $1, $2, $3
+                FROM users
+                WHERE is_active = true AND ($4 = 'everyone' OR username = $5)
+                "#,
+            )
+            .bind(&announcement.title)
+            .bind(&announcement.body)
+            .bind(serde_json::json!({ "announcement_id": announcement.id }))
+            .bind(announcement.audience)
+            .bind(crate::admin_init::ADMIN_USERNAME)
+            .execute(&mut *tx)
+            .await
+            .map_err(AppError::Database)?;
+            delivered += created.rows_affected();
+        }
+
+        tx.commit().await.map_err(AppError::Database)?;
+        Ok(delivered)
+    }
+}
+
+/// Notifies the audiences of announcements as they start
+pub struct AnnouncementDeliveryTask;
+
+impl crate::tasks::PeriodicTask for AnnouncementDeliveryTask {
+    fn name(&self) -> &'static str {
+        "announcement_delivery"
+    }
+
+    fn interval(&self) -> Duration {
+        DELIVERY_INTERVAL
+    }
+
+    fn run<'a>(&'a self, state: &'a AppState) -> futures::future::BoxFuture<'a, Result<(), AppError>> {
+        Box::pin(async move {
+            let delivered = InstanceAnnouncement::deliver_started(&state.db_pool).await?;
+            if delivered > 0 {
+                tracing::info!("Delivered {} announcement notifications", delivered);
+            }
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{create_test_user, TestDb};
+
+    fn version(version: &str) -> ReleaseVersion {
+        ReleaseVersion::parse(version).unwrap()
+    }
+
+    fn note(version: &'static str, title: &'static str, audience: AnnouncementAudience) -> ReleaseNote {
+        ReleaseNote { version, date: "2026-01-01", title, body: "Body", audience }
+    }
+
+    #[test]
+    fn test_versions_follow_semver_precedence() {
+        let ordered = [
+            "0.9.12",
+            "1.0.0-alpha",
+            "1.0.0-alpha.1",
+            "1.0.0-alpha.beta",
+            "1.0.0-beta",
+            "1.0.0-beta.2",
+            "1.0.0-beta.11",
+            "1.0.0-rc.1",
+            "1.0.0",
+            "1.0.1",
+            "1.2.0",
+            "1.10.0",
+        ];
+        for pair in ordered.windows(2) {
+            assert!(version(pair[0]) < version(pair[1]), "{} < {}", pair[0], pair[1]);
+        }
+        assert_eq!(version("1.0.0+build.5"), version("1.0.0"));
+        for invalid in ["1.0", "1.0.0.0", "v1.0.0", "1.0.0-", "1.0.0-alpha..1", ""] {
+            assert!(ReleaseVersion::parse(invalid).is_none(), "{}", invalid);
+        }
+        assert!(RELEASE_NOTES.iter().all(|note| ReleaseVersion::parse(note.version).is_some()));
+        assert!(RELEASE_NOTES.iter().all(|note| NaiveDate::parse_from_str(note.date, "%Y-%m-%d").is_ok()));
+    }
+
+    #[test]
+    fn test_due_notes_span_the_releases_since_acknowledged() {
+        let notes = [
+            note("1.0.0", "one", AnnouncementAudience::Everyone),
+            note("1.1.0-beta.1", "beta", AnnouncementAudience::Everyone),
+            note("1.1.0", "one-one", AnnouncementAudience::Everyone),
+            note("1.2.0", "one-two", AnnouncementAudience::Everyone),
+            note("1.2.0", "one-two-admin", AnnouncementAudience::Admins),
+            note("1.3.0", "one-three", AnnouncementAudience::Everyone),
+        ];
+        let titles = |acknowledged: Option<&str>, current: &str, admin: bool| {
+            let acknowledged = acknowledged.map(version);
+            due_release_notes(&notes, acknowledged.as_ref(), &version(current), admin)
+                .into_iter()
+                .map(|(note, _)| note.title)
+                .collect::<Vec<_>>()
+        };
+
+        // Jumping over releases shows each once, newest first, and nothing newer
+        assert_eq!(titles(Some("1.0.0"), "1.2.0", false), ["one-two", "one-one", "beta"]);
+        assert_eq!(titles(Some("1.0.0"), "1.2.0", true), ["one-two", "one-two-admin", "one-one", "beta"]);
+        assert!(titles(Some("1.2.0"), "1.2.0", false).is_empty());
+
+        // A prerelease comes before its release
+        assert_eq!(titles(Some("1.1.0-beta.1"), "1.1.0", false), ["one-one"]);
+        assert_eq!(titles(Some("1.0.0"), "1.1.0-beta.2", false), ["beta"]);
+        assert!(titles(Some("1.1.0-beta.1"), "1.1.0-rc.1", false).is_empty());
+
+        // Without an acknowledgement only the running release is announced
+        assert_eq!(titles(None, "1.3.0", false), ["one-three"]);
+        assert!(titles(None, "1.2.1", false).is_empty());
+
+        // Nor does a downgrade show anything
+        assert!(titles(Some("1.3.0"), "1.2.0", false).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_instance_announcements_are_scheduled_and_acknowledged() {
+        let Some(db) = TestDb::start().await else { return };
+        let user = create_test_user(&db.pool).await;
+        let author = create_test_user(&db.pool).await;
+        let notes = [
+            note("0.9.0", "old", AnnouncementAudience::Everyone),
+            note("1.0.0", "current", AnnouncementAudience::Everyone),
+        ];
+        let request = |title: &str, audience, starts_in: i64, expires_in: Option<i64>| AnnouncementRequest {
+            title: title.to_string(),
+            body: format!("**{}** <script>alert(1)</script>", title),
+            audience,
+            starts_at: Some(Utc::now() + chrono::Duration::minutes(starts_in)),
+            expires_at: expires_in.map(|minutes| Utc::now() + chrono::Duration::minutes(minutes)),
+        };
+        let titles = |announcements: &Announcements| {
+            announcements.announcements.iter().map(|a| a.title.clone()).collect::<Vec<_>>()
+        };
+
+        let live = request("Maintenance", AnnouncementAudience::Everyone, -5, Some(60));
+        InstanceAnnouncement::create(&db.pool, &live, author.id).await.unwrap();
+        let admins = request("Disk space", AnnouncementAudience::Admins, -5, None);
+        InstanceAnnouncement::create(&db.pool, &admins, author.id).await.unwrap();
+        let later = request("Later", AnnouncementAudience::Everyone, 60, None);
+        let later = InstanceAnnouncement::create(&db.pool, &later, author.id).await.unwrap();
+        let expired = request("Expired", AnnouncementAudience::Everyone, -60, Some(-30));
+        InstanceAnnouncement::create(&db.pool, &expired, author.id).await.unwrap();
+        let invalid = request("Backwards", AnnouncementAudience::Everyone, 10, Some(5));
+        assert!(matches!(
+            InstanceAnnouncement::create(&db.pool, &invalid, author.id).await,
+            Err(AppError::Validation(_))
+        ));
+
+        let due = Announcements::load(&db.pool, user.id, false, &notes, "1.0.0").await.unwrap();
+        assert_eq!(titles(&due), ["Maintenance", "current"]);
+        assert!(due.announcements[0].html.contains("<strong>Maintenance</strong>"));
+        assert!(!due.announcements[0].html.contains("<script"));
+        assert!(due.acknowledged_version.is_none());
+        let as_admin = Announcements::load(&db.pool, user.id, true, &notes, "1.0.0").await.unwrap();
+        assert_eq!(titles(&as_admin), ["Maintenance", "Disk space", "current"]);
+
+        // Started announcements reach their audience once
+        let delivered = InstanceAnnouncement::deliver_started(&db.pool).await.unwrap();
+        assert!(delivered >= 1);
+        assert_eq!(InstanceAnnouncement::deliver_started(&db.pool).await.unwrap(), 0);
+        let notified: Vec<String> =
+            sqlx::query_scalar("SELECT title FROM notifications WHERE user_id = $1 AND kind = 'announcement'")
+                .bind(user.id)
+                .fetch_all(&db.pool)
+                .await
+                .unwrap();
+        assert_eq!(notified, ["Maintenance"]);
+
+        Announcements::acknowledge_version(&db.pool, user.id, "1.0.0").await.unwrap();
+        let read = Announcements::load(&db.pool, user.id, false, &notes, "1.0.0").await.unwrap();
+        assert!(read.announcements.is_empty());
+        assert_eq!(read.acknowledged_version.as_deref(), Some("1.0.0"));
+
+        // The scheduled one shows once it starts, and a downgrade keeps the acknowledgement
+        let moved = request("Later", AnnouncementAudience::Everyone, 0, None);
+        InstanceAnnouncement::update(&db.pool, later.id, &moved).await.unwrap();
+        let due = Announcements::load(&db.pool, user.id, false, &notes, "1.0.0").await.unwrap();
+        assert_eq!(titles(&due), ["Later"]);
+        let kept = Announcements::acknowledge_version(&db.pool, user.id, "0.9.0").await.unwrap();
+        assert_eq!(kept.version, "1.0.0");
+    }
+}
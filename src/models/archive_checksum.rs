@@ -0,0 +1,299 @@
+//! Checksums of exported project archives
+//!
+//! Native exports carry a `texler-checksums.json` manifest listing every
+//! project file with its size and SHA-256, plus a hash of that list. Imports
+//! check the archive against it, and `GET /projects/:id/export/checksum`
+//! computes the list hash of the live project, so an archive can later be
+//! confirmed to still match it. The settings in `texler.json` change with
+//! every export and are not covered.
+//!
+//! The list hash is the SHA-256 of one `<sha256> <size> <path>\n` line per
+//! file, in byte order of the paths. `version` names this layout, so it can
+//! change without old archives failing to verify.
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use super::file::File;
+use super::integrity::ReadPath;
+use super::project_archive::ArchiveEntry;
+use crate::config::FileStorageConfig;
+use crate::error::AppError;
+
+/// Checksum manifest inside native exports
+pub const CHECKSUMS_NAME: &str = "texler-checksums.json";
+
+/// Manifest layout written by this version
+pub const CHECKSUM_VERSION: u32 = 1;
+
+/// What an import does with an archive that does not match its checksums
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ChecksumMode {
+    /// Refuse the archive
+    #[default]
+    Strict,
+    /// Import the entries that match and list the rest
+    Permissive,
+}
+
+impl ChecksumMode {
+    pub fn parse(value: &str) -> Result<Self, AppError> {
+        match value.trim() {
+            "strict" => Ok(Self::Strict),
+            "permissive" => Ok(Self::Permissive),
+            other => Err(AppError::Validation(format!(
+                "Unknown verification mode '{}', expected strict or permissive",
+                other
+            ))),
+        }
+    }
+}
+
+/// A file listed in a checksum manifest
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct ChecksumEntry {
+    pub path: String,
+    pub size: u64,
+    pub sha256: String,
+}
+
+impl ChecksumEntry {
+    /// Size and hash of an archive entry in memory
+    pub fn of(entry: &ArchiveEntry) -> Self {
+        Self {
+            path: entry.path.clone(),
+            size: entry.bytes.len() as u64,
+            sha256: hex::encode(Sha256::digest(&entry.bytes)),
+        }
+    }
+}
+
+/// Sizes and hashes of every file of an archive
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct ChecksumManifest {
+    pub version: u32,
+    /// SHA-256 of the file list
+    pub manifest_hash: String,
+    /// Ordered by path
+    pub files: Vec<ChecksumEntry>,
+}
+
+/// An archive entry that does not match its listed size or hash
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+pub struct ChecksumMismatch {
+    pub path: String,
+    pub expected_size: u64,
+    pub actual_size: u64,
+    pub expected_sha256: String,
+    pub actual_sha256: String,
+}
+
+/// How an imported archive compares to its checksum manifest
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+pub struct ArchiveVerification {
+    pub version: u32,
+    /// Whether the manifest hash matches the listed files
+    pub manifest_intact: bool,
+    /// Entries matching their listed size and hash
+    pub verified: Vec<String>,
+    pub mismatched: Vec<ChecksumMismatch>,
+    /// Listed files the archive does not contain
+    pub missing: Vec<String>,
+    /// Entries the manifest does not list
+    pub extra: Vec<String>,
+}
+
+impl ArchiveVerification {
+    /// Whether the archive is exactly what the manifest describes
+    pub fn is_clean(&self) -> bool {
+        self.manifest_intact && self.mismatched.is_empty() && self.missing.is_empty() && self.extra.is_empty()
+    }
+}
+
+impl std::fmt::Display for ArchiveVerification {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if !self.manifest_intact {
+            write!(f, "the manifest hash does not match its file list; ")?;
+        }
+        write!(
+            f,
+            "{} mismatched, {} missing and {} unlisted files",
+            self.mismatched.len(),
+            self.missing.len(),
+            self.extra.len()
+        )
+    }
+}
+
+impl ChecksumManifest {
+    /// Manifest of the given files, in any order
+    pub fn new(mut files: Vec<ChecksumEntry>) -> Self {
+        files.sort_by(|a, b| a.path.cmp(&b.path));
+        Self {
+            version: CHECKSUM_VERSION,
+            manifest_hash: list_hash(&files),
+            files,
+        }
+    }
+
+    /// Manifest of archive entries already in memory
+    pub fn of_entries(entries: &[ArchiveEntry]) -> Self {
+        Self::new(
+            entries.iter().map(ChecksumEntry::of).collect(),
+        )
+    }
+
+    /// Manifest of the files a native export of the project would contain
+    ///
+    /// Recorded hashes are reused where an export would not check them, and
+    /// content stored outside the database is hashed without reading it whole.
+    pub async fn of_project(
+        db: &sqlx::PgPool,
+        storage: &FileStorageConfig,
+        project_id: Uuid,
+    ) -> Result<Self, AppError> {
+        let mut files = Vec::new();
+        for file in File::list_all_for_project(db, project_id).await? {
+            if file.is_quarantined() {
+                continue;
+            }
+            files.push(ChecksumEntry {
+                path: file.path.trim_start_matches('/').to_string(),
+                size: file.size.max(0) as u64,
+                sha256: file.verified_hash(db, storage, ReadPath::Export).await?,
+            });
+        }
+        Ok(Self::new(files))
+    }
+
+    /// Read the manifest of an imported archive
+    pub fn parse(bytes: &[u8]) -> Result<Self, AppError> {
+        let manifest: Self = serde_json::from_slice(bytes)
+            .map_err(|e| AppError::Validation(format!("{} is not a valid checksum manifest: {}", CHECKSUMS_NAME, e)))?;
+        if manifest.version != CHECKSUM_VERSION {
+            return Err(AppError::Validation(format!(
+                "{} has version {}, only version {} can be verified",
+                CHECKSUMS_NAME, manifest.version, CHECKSUM_VERSION
+            )));
+        }
+        Ok(manifest)
+    }
+
+    /// Compare archive entries to the listed files
+    pub fn verify(&self, entries: &[ArchiveEntry]) -> ArchiveVerification {
+        let listed: HashMap<&str, &ChecksumEntry> =
+            self.files.iter().map(|file| (file.path.as_str(), file)).collect();
+        let present: HashSet<&str> = entries.iter().map(|entry| entry.path.as_str()).collect();
+
+        let mut verification = ArchiveVerification {
+            version: self.version,
+            manifest_intact: list_hash(&self.files).eq_ignore_ascii_case(&self.manifest_hash),
+            verified: Vec::new(),
+            mismatched: Vec::new(),
+            missing: Vec::new(),
+            extra: Vec::new(),
+        };
+
+        for entry in entries {
+            let Some(expected) = listed.get(entry.path.as_str()) else {
+                verification.extra.push(entry.path.clone());
+                continue;
+            };
+            let actual_size = entry.bytes.len() as u64;
+            let actual_sha256 = hex::encode(Sha256::digest(&entry.bytes));
+            if actual_size == expected.size && actual_sha256.eq_ignore_ascii_case(&expected.sha256) {
+                verification.verified.push(entry.path.clone());
+            } else {
+                verification.mismatched.push(ChecksumMismatch {
+                    path: entry.path.clone(),
+                    expected_size: expected.size,
+                    actual_size,
+                    expected_sha256: expected.sha256.clone(),
+                    actual_sha256,
+                });
+            }
+        }
+
+        verification.missing = self
+            .files
+            .iter()
+            .filter(|file| !present.contains(file.path.as_str()))
+            .map(|file| file.path.clone())
+            .collect();
+        verification
+    }
+}
+
+/// Hash of a file list ordered by path
+fn list_hash(files: &[ChecksumEntry]) -> String {
+    let mut hasher = Sha256::new();
+    for file in files {
+        hasher.update(format!("{} {} {}\n", file.sha256.to_ascii_lowercase(), file.size, file.path).as_bytes());
+    }
+    hex::encode(hasher.finalize())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(path: &str, content: &[u8]) -> ArchiveEntry {
+        ArchiveEntry { path: path.to_string(), bytes: content.to_vec() }
+    }
+
+    #[test]
+    fn test_manifest_hash_ignores_order() {
+        let main = entry("main.tex", b"\\documentclass{article}");
+        let refs = entry("refs.bib", b"@book{}");
+
+        let manifest = ChecksumManifest::of_entries(&[refs.clone(), main.clone()]);
+        assert_eq!(manifest, ChecksumManifest::of_entries(&[main, refs]));
+        assert_eq!(manifest.files[0].path, "main.tex");
+        assert_eq!(manifest.files[1].sha256, hex::encode(Sha256::digest(b"@book{}")));
+
+        let json = serde_json::to_vec(&manifest).unwrap();
+        assert_eq!(ChecksumManifest::parse(&json).unwrap(), manifest);
+
+        let mut future = manifest.clone();
+        future.version = CHECKSUM_VERSION + 1;
+        assert!(ChecksumManifest::parse(&serde_json::to_vec(&future).unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_verify_reports_every_difference() {
+        let manifest = ChecksumManifest::of_entries(&[
+            entry("main.tex", b"\\documentclass{article}"),
+            entry("chapter.tex", b"\\chapter{One}"),
+            entry("refs.bib", b"@book{}"),
+        ]);
+
+        let verification = manifest.verify(&[
+            entry("main.tex", b"\\documentclass{article}"),
+            entry("chapter.tex", b"\\chapter{Two}"),
+            entry("notes.txt", b"added later"),
+        ]);
+        assert!(verification.manifest_intact);
+        assert_eq!(verification.verified, vec!["main.tex".to_string()]);
+        assert_eq!(verification.mismatched.len(), 1);
+        assert_eq!(verification.mismatched[0].path, "chapter.tex");
+        assert_eq!(verification.missing, vec!["refs.bib".to_string()]);
+        assert_eq!(verification.extra, vec!["notes.txt".to_string()]);
+        assert!(!verification.is_clean());
+
+        // Editing a listed hash without recomputing the manifest hash shows
+        let mut tampered = manifest.clone();
+        tampered.files[0].sha256 = hex::encode(Sha256::digest(b"\\chapter{Two}"));
+        let verification = tampered.verify(&[
+            entry("main.tex", b"\\documentclass{article}"),
+            entry("chapter.tex", b"\\chapter{Two}"),
+            entry("refs.bib", b"@book{}"),
+        ]);
+        assert!(!verification.manifest_intact);
+        assert!(verification.mismatched.is_empty());
+        assert!(!verification.is_clean());
+    }
+}
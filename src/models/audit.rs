@@ -0,0 +1,68 @@
+//! Audit log of security-relevant events
+//!
+//! Unlike project activity, which collaborators see and which is rolled up
+//! after a while, audit events are for administrators and kept as written.
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::FromRow;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::error::AppError;
+
+/// A recorded event
+#[derive(Debug, Clone, Serialize, FromRow, ToSchema)]
+pub struct AuditEvent {
+    pub id: Uuid,
+    /// User who caused the event, if any
+    pub actor_id: Option<Uuid>,
+    pub action: String,
+    pub entity_type: String,
+    pub entity_id: Option<Uuid>,
+    pub project_id: Option<Uuid>,
+    pub details: serde_json::Value,
+    pub created_at: DateTime<Utc>,
+}
+
+impl AuditEvent {
+    /// Append an event
+    pub async fn record(
+        db: impl sqlx::PgExecutor<'_>,
+        actor_id: Option<Uuid>,
+        action: &str,
+        entity_type: &str,
+        entity_id: Option<Uuid>,
+        project_id: Option<Uuid>,
+        details: serde_json::Value,
+    ) -> Result<Self, AppError> {
+        sqlx::query_as::<_, AuditEvent>(
+            r#"
+            INSERT INTO audit_log (actor_id, action, entity_type, entity_id, project_id, details)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            RETURNING *
+            "#
+        )
+        .bind(actor_id)
+        .bind(action)
+        .bind(entity_type)
+        .bind(entity_id)
+        .bind(project_id)
+        .bind(details)
+        .fetch_one(db)
+        .await
+        .map_err(AppError::Database)
+    }
+
+    /// Newest events with the given action
+    pub async fn list_by_action(db: &sqlx::PgPool, action: &str, limit: i64) -> Result<Vec<Self>, AppError> {
+        sqlx::query_as::<_, AuditEvent>(
+            "SELECT * FROM audit_log WHERE action = $1 ORDER BY created_at DESC LIMIT $2"
+        )
+        .bind(action)
+        .bind(limit)
+        .fetch_all(db)
+        .await
+        .map_err(AppError::Database)
+    }
+}
@@ -3,6 +3,7 @@
 use chrono::{DateTime, Utc, Duration};
 use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
 use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
 use uuid::Uuid;
 
 use crate::models::UserRole;
@@ -45,7 +46,7 @@ impl Claims {
 }
 
 /// Authentication token pair
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, ToSchema)]
 pub struct TokenPair {
     pub access_token: String,
     pub refresh_token: String,
@@ -123,6 +124,14 @@ impl JwtService {
         Ok(token_data.claims)
     }
 
+    /// Verify and decode token, keeping the JWT error so callers can tell
+    /// an expired token from an invalid one
+    pub fn decode_token(&self, token: &str) -> Result<Claims, AppError> {
+        let token_data = decode::<Claims>(token, &self.decoding_key, &self.validation)?;
+
+        Ok(token_data.claims)
+    }
+
     /// Verify and decode token with blacklist check
     pub async fn verify_token_with_db(&self, token: &str, db: &sqlx::PgPool) -> Result<Claims, AppError> {
         let claims = self.verify_token(token)?;
@@ -134,7 +143,7 @@ impl JwtService {
         let user_id = Uuid::parse_str(&claims.sub)
             .map_err(|_| AppError::Authentication("Invalid user ID in token".to_string()))?;
 
-        if TokenBlacklistService::should_reject_token(db, &claims.jti, user_id).await? {
+        if TokenBlacklistService::should_reject_token(db, &claims.jti, user_id, claims.iat).await? {
             return Err(AppError::Authentication("Token has been revoked".to_string()));
         }
 
@@ -216,6 +225,35 @@ impl PasswordUtils {
             .collect()
     }
 
+    /// Generate project invitation token
+    pub fn generate_invitation_token() -> String {
+        use rand::distributions::Alphanumeric;
+        use rand::{thread_rng, Rng};
+
+        thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(64)
+            .map(char::from)
+            .collect()
+    }
+
+    /// Generate project embed token
+    pub fn generate_embed_token() -> String {
+        use rand::distributions::Alphanumeric;
+        use rand::{thread_rng, Rng};
+
+        thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(64)
+            .map(char::from)
+            .collect()
+    }
+
+    /// Generate file share token
+    pub fn generate_share_token() -> String {
+        Self::generate_embed_token()
+    }
+
     /// Validate password strength
     pub fn validate_password_strength(password: &str) -> Result<(), AppError> {
         if password.len() < 8 {
@@ -298,6 +336,11 @@ impl AuthContext {
         self.has_role(UserRole::Owner) || self.has_role(UserRole::Maintainer)
     }
 
+    /// Check if user is the instance administrator
+    pub fn is_admin(&self) -> bool {
+        self.username == crate::admin_init::ADMIN_USERNAME
+    }
+
     /// Check if user can write
     pub fn can_write(&self) -> bool {
         self.has_role(UserRole::Owner) || self.has_role(UserRole::Maintainer) || self.has_role(UserRole::Collaborator)
@@ -0,0 +1,607 @@
+//! Editor autocomplete indexes
+//!
+//! Completions for `\cite{`, `\ref{` and file-taking commands are served from
+//! a per-project index of citation keys (from `.bib` files), labels (from all
+//! LaTeX files) and file paths. Indexes are built once from the project files
+//! and kept in memory until a file of the project changes, so typing does not
+//! query or parse anything.
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::{Arc, Mutex, OnceLock};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use super::ContentType;
+
+/// Completions returned when the request gives no limit
+pub const DEFAULT_AUTOCOMPLETE_LIMIT: usize = 50;
+
+/// Most completions returned for one request
+pub const MAX_AUTOCOMPLETE_LIMIT: usize = 200;
+
+/// Project indexes kept in memory
+pub const AUTOCOMPLETE_CACHE_CAPACITY: usize = 512;
+
+/// What is being completed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum AutocompleteKind {
+    /// Citation keys from the project's `.bib` files
+    Citation,
+    /// Labels defined in the project's LaTeX files
+    Label,
+    /// Project file paths
+    Path,
+}
+
+/// One completion
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct AutocompleteEntry {
+    pub key: String,
+    /// Title of the bibliography entry, or section containing the label
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+/// A project file to index; content is only needed for LaTeX and bibliography files
+#[derive(Debug, Clone)]
+pub struct IndexSource {
+    pub path: String,
+    pub content_type: ContentType,
+    pub content: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+struct IndexedKey {
+    key: String,
+    key_lower: String,
+    detail: Option<String>,
+    content_type: ContentType,
+    /// How often the project's LaTeX files refer to the key
+    uses: usize,
+}
+
+impl IndexedKey {
+    fn new(key: String, detail: Option<String>, content_type: ContentType) -> Self {
+        Self {
+            key_lower: key.to_lowercase(),
+            key,
+            detail,
+            content_type,
+            uses: 0,
+        }
+    }
+}
+
+/// Completions of one project
+#[derive(Debug, Default)]
+pub struct AutocompleteIndex {
+    citations: Vec<IndexedKey>,
+    labels: Vec<IndexedKey>,
+    paths: Vec<IndexedKey>,
+}
+
+/// Whether changes to a file's content can change the project's index
+pub fn indexes_content(content_type: ContentType) -> bool {
+    matches!(content_type, ContentType::Latex | ContentType::Bibliography)
+}
+
+impl AutocompleteIndex {
+    pub fn build(sources: &[IndexSource]) -> Self {
+        static CITE: OnceLock<Regex> = OnceLock::new();
+        static REF: OnceLock<Regex> = OnceLock::new();
+        static FILE: OnceLock<Regex> = OnceLock::new();
+        // `\cite`, `\citep`, `\parencite`, ... with optional notes
+        let cite = CITE.get_or_init(|| {
+            Regex::new(r"\\[a-zA-Z]*cite[a-zA-Z]*\*?(?:\[[^\]]*\]){0,2}\{([^}]+)\}").unwrap()
+        });
+        // `\ref`, `\eqref`, `\cref`, ...
+        let reference = REF.get_or_init(|| Regex::new(r"\\[a-zA-Z]*ref\*?\{([^}]+)\}").unwrap());
+        // Commands that take a project file
+        let file = FILE.get_or_init(|| {
+            Regex::new(
+                r"\\(?:includegraphics|input|include|subfile|bibliography|addbibresource)\*?(?:\[[^\]]*\])?\{([^}]+)\}",
+            )
+            .unwrap()
+        });
+
+        let mut citations = Vec::new();
+        let mut labels = Vec::new();
+        let mut seen_citations = HashSet::new();
+        let mut seen_labels = HashSet::new();
+        let mut cite_uses: HashMap<&str, usize> = HashMap::new();
+        let mut ref_uses: HashMap<&str, usize> = HashMap::new();
+        let mut file_uses: HashMap<&str, usize> = HashMap::new();
+
+        for source in sources {
+            let Some(content) = source.content.as_deref() else {
+                continue;
+            };
+
+            match source.content_type {
+                ContentType::Bibliography => {
+                    for (key, title) in parse_bib_entries(content) {
+                        if seen_citations.insert(key.clone()) {
+                            citations.push(IndexedKey::new(key, title, source.content_type));
+                        }
+                    }
+                }
+                ContentType::Latex => {
+                    for (key, section) in parse_labels(content) {
+                        if seen_labels.insert(key.clone()) {
+                            labels.push(IndexedKey::new(key, section, source.content_type));
+                        }
+                    }
+                    count_keys(cite, content, &mut cite_uses);
+                    count_keys(reference, content, &mut ref_uses);
+                    count_keys(file, content, &mut file_uses);
+                }
+                _ => {}
+            }
+        }
+
+        for citation in &mut citations {
+            citation.uses = cite_uses.get(citation.key.as_str()).copied().unwrap_or(0);
+        }
+        for label in &mut labels {
+            label.uses = ref_uses.get(label.key.as_str()).copied().unwrap_or(0);
+        }
+
+        let paths = sources
+            .iter()
+            .map(|source| {
+                let path = source.path.trim_start_matches('/');
+                // Graphics and inputs are usually referenced without extension
+                let stem = path.rsplit_once('.').map_or(path, |(stem, _)| stem);
+                let mut entry = IndexedKey::new(path.to_string(), None, source.content_type);
+                entry.uses = file_uses.get(path).copied().unwrap_or(0)
+                    + if stem != path { file_uses.get(stem).copied().unwrap_or(0) } else { 0 };
+                entry
+            })
+            .collect();
+
+        Self { citations, labels, paths }
+    }
+
+    /// Number of indexed keys of every kind
+    pub fn len(&self) -> usize {
+        self.citations.len() + self.labels.len() + self.paths.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Keys matching `prefix`, best matches first
+    ///
+    /// Exact matches rank above case-sensitive prefix matches, then
+    /// case-insensitive ones, then matches at the start of a key segment (such
+    /// as `plot` in `fig:plot`). Ties go to the more frequently used key.
+    /// `content_type` restricts path completions, e.g. to images for
+    /// `\includegraphics`.
+    pub fn complete(
+        &self,
+        kind: AutocompleteKind,
+        prefix: &str,
+        content_type: Option<ContentType>,
+        limit: usize,
+    ) -> Vec<AutocompleteEntry> {
+        let keys = match kind {
+            AutocompleteKind::Citation => &self.citations,
+            AutocompleteKind::Label => &self.labels,
+            AutocompleteKind::Path => &self.paths,
+        };
+        let prefix_lower = prefix.to_lowercase();
+
+        let mut matches: Vec<(u8, &IndexedKey)> = keys
+            .iter()
+            .filter(|key| content_type.is_none_or(|content_type| key.content_type == content_type))
+            .filter_map(|key| match_rank(key, prefix, &prefix_lower).map(|rank| (rank, key)))
+            .collect();
+
+        let by_quality = |a: &(u8, &IndexedKey), b: &(u8, &IndexedKey)| {
+            a.0.cmp(&b.0)
+                .then_with(|| b.1.uses.cmp(&a.1.uses))
+                .then_with(|| a.1.key.cmp(&b.1.key))
+        };
+        if matches.len() > limit {
+            matches.select_nth_unstable_by(limit, by_quality);
+            matches.truncate(limit);
+        }
+        matches.sort_unstable_by(by_quality);
+
+        matches
+            .into_iter()
+            .map(|(_, key)| AutocompleteEntry {
+                key: key.key.clone(),
+                detail: key.detail.clone(),
+            })
+            .collect()
+    }
+}
+
+/// Rank of a key for a prefix, lower is better; `None` when it does not match
+fn match_rank(key: &IndexedKey, prefix: &str, prefix_lower: &str) -> Option<u8> {
+    if key.key == prefix {
+        Some(0)
+    } else if key.key.starts_with(prefix) {
+        Some(1)
+    } else if key.key_lower.starts_with(prefix_lower) {
+        Some(2)
+    } else if key
+        .key_lower
+        .match_indices([':', '/', '_', '-', '.'])
+        .any(|(at, _)| key.key_lower[at + 1..].starts_with(prefix_lower))
+    {
+        Some(3)
+    } else {
+        None
+    }
+}
+
+/// Count the comma-separated keys in the first group of every match
+fn count_keys<'a>(regex: &Regex, content: &'a str, counts: &mut HashMap<&'a str, usize>) {
+    for cap in regex.captures_iter(content) {
+        let Some(keys) = cap.get(1) else { continue };
+        for key in keys.as_str().split(',').map(str::trim).filter(|key| !key.is_empty()) {
+            *counts.entry(key).or_default() += 1;
+        }
+    }
+}
+
+/// Labels with the title of the section they appear in
+fn parse_labels(content: &str) -> Vec<(String, Option<String>)> {
+    static SECTION: OnceLock<Regex> = OnceLock::new();
+    static LABEL: OnceLock<Regex> = OnceLock::new();
+    let section_regex = SECTION.get_or_init(|| {
+        Regex::new(
+            r"\\(?:part|chapter|section|subsection|subsubsection|paragraph|subparagraph)\*?(?:\[[^\]]*\])?\{([^}]+)\}",
+        )
+        .unwrap()
+    });
+    let label_regex = LABEL.get_or_init(|| Regex::new(r"\\label\{([^}]+)\}").unwrap());
+
+    let mut labels = Vec::new();
+    let mut section = None;
+
+    for line in content.lines() {
+        let line = strip_comment(line);
+        if let Some(cap) = section_regex.captures(line) {
+            section = Some(collapse_whitespace(&cap[1]));
+        }
+        for cap in label_regex.captures_iter(line) {
+            labels.push((cap[1].trim().to_string(), section.clone()));
+        }
+    }
+
+    labels
+}
+
+/// A line without its `%` comment
+fn strip_comment(line: &str) -> &str {
+    let mut escaped = false;
+    for (at, c) in line.char_indices() {
+        match c {
+            '%' if !escaped => return &line[..at],
+            '\\' => escaped = !escaped,
+            _ => escaped = false,
+        }
+    }
+    line
+}
+
+/// Keys and titles of the entries in a BibTeX/BibLaTeX file
+fn parse_bib_entries(content: &str) -> Vec<(String, Option<String>)> {
+    static ENTRY: OnceLock<Regex> = OnceLock::new();
+    static TITLE: OnceLock<Regex> = OnceLock::new();
+    let entry_regex = ENTRY.get_or_init(|| Regex::new(r"@([a-zA-Z]+)\s*([{(])").unwrap());
+    let title_regex = TITLE.get_or_init(|| Regex::new(r"(?i)\btitle\s*=\s*").unwrap());
+
+    let mut entries = Vec::new();
+    let mut rest = content;
+
+    while let Some(cap) = entry_regex.captures(rest) {
+        let whole = cap.get(0).unwrap();
+        let entry_type = cap[1].to_lowercase();
+        let close = if &cap[2] == "(" { ')' } else { '}' };
+        let body_start = whole.end();
+        let body_len = balanced_len(&rest[body_start..], &cap[2], close);
+        let body = &rest[body_start..body_start + body_len];
+        rest = &rest[(body_start + body_len).min(rest.len())..];
+
+        if matches!(entry_type.as_str(), "string" | "comment" | "preamble") {
+            continue;
+        }
+
+        let Some((key, fields)) = body.split_once(',') else {
+            continue;
+        };
+        let key = key.trim();
+        if key.is_empty() || key.contains(char::is_whitespace) {
+            continue;
+        }
+
+        let title = title_regex
+            .find(fields)
+            .and_then(|m| field_value(&fields[m.end()..]))
+            .filter(|title| !title.is_empty());
+        entries.push((key.to_string(), title));
+    }
+
+    entries
+}
+
+/// Length of the text up to the `close` that ends an already opened `open`
+fn balanced_len(text: &str, open: &str, close: char) -> usize {
+    let open = open.chars().next().unwrap_or('{');
+    let mut depth = 1usize;
+    for (at, c) in text.char_indices() {
+        if c == open {
+            depth += 1;
+        } else if c == close {
+            depth -= 1;
+            if depth == 0 {
+                return at;
+            }
+        }
+    }
+    text.len()
+}
+
+/// Value of a `{...}` or `"..."` BibTeX field, without the inner braces
+fn field_value(text: &str) -> Option<String> {
+    let value = if let Some(braced) = text.strip_prefix('{') {
+        &braced[..balanced_len(braced, "{", '}')]
+    } else if let Some(quoted) = text.strip_prefix('"') {
+        &quoted[..quoted.find('"')?]
+    } else {
+        return None;
+    };
+
+    Some(collapse_whitespace(&value.replace(['{', '}'], "")))
+}
+
+fn collapse_whitespace(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Autocomplete indexes by project, dropped whenever a project file changes
+#[derive(Default)]
+pub struct AutocompleteCache {
+    state: Mutex<CacheState>,
+}
+
+#[derive(Default)]
+struct CacheState {
+    indexes: HashMap<Uuid, Arc<AutocompleteIndex>>,
+    order: VecDeque<Uuid>,
+    /// Builds in progress; a build is discarded if the project changes meanwhile
+    loading: HashMap<Uuid, u64>,
+    next_ticket: u64,
+}
+
+impl AutocompleteCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, project_id: Uuid) -> Option<Arc<AutocompleteIndex>> {
+        let state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        state.indexes.get(&project_id).cloned()
+    }
+
+    /// Start building the index of a project, before reading its files
+    pub fn begin_build(&self, project_id: Uuid) -> u64 {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        state.next_ticket += 1;
+        let ticket = state.next_ticket;
+        state.loading.insert(project_id, ticket);
+        ticket
+    }
+
+    /// Store a built index unless the project changed since `begin_build`,
+    /// evicting the oldest index when full
+    pub fn insert(&self, project_id: Uuid, ticket: u64, index: Arc<AutocompleteIndex>) {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        if state.loading.get(&project_id) != Some(&ticket) {
+            return;
+        }
+        state.loading.remove(&project_id);
+
+        if state.indexes.insert(project_id, index).is_none() {
+            state.order.push_back(project_id);
+        }
+        while state.order.len() > AUTOCOMPLETE_CACHE_CAPACITY {
+            if let Some(oldest) = state.order.pop_front() {
+                state.indexes.remove(&oldest);
+            }
+        }
+    }
+
+    /// Drop the project's index after one of its files changed
+    pub fn invalidate(&self, project_id: Uuid) {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        state.loading.remove(&project_id);
+        if state.indexes.remove(&project_id).is_some() {
+            state.order.retain(|id| *id != project_id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, Instant};
+
+    fn source(path: &str, content_type: ContentType, content: &str) -> IndexSource {
+        IndexSource {
+            path: path.to_string(),
+            content_type,
+            content: Some(content.to_string()),
+        }
+    }
+
+    fn keys(entries: &[AutocompleteEntry]) -> Vec<&str> {
+        entries.iter().map(|entry| entry.key.as_str()).collect()
+    }
+
+    #[test]
+    fn test_bib_entries_with_titles() {
+        let entries = parse_bib_entries(
+            r#"@string{jacm = "Journal of the ACM"}
+            % a comment
+            @Article{knuth1984,
+              author = {Donald Knuth},
+              booktitle = {Not this},
+              title = {Literate {P}rogramming},
+            }
+            @book(lamport94, title = "LaTeX: A Document
+                Preparation System")
+            @misc{untitled, note = {none}}"#,
+        );
+
+        assert_eq!(
+            entries,
+            vec![
+                ("knuth1984".to_string(), Some("Literate Programming".to_string())),
+                ("lamport94".to_string(), Some("LaTeX: A Document Preparation System".to_string())),
+                ("untitled".to_string(), None),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_labels_carry_their_section() {
+        let labels = parse_labels(
+            "\\label{top}\n\\section{Intro}\\label{sec:intro}\n% \\label{old}\n\
+             \\subsection*{Related   work}\n\\begin{figure}\\label{fig:plot}\\end{figure}\n",
+        );
+
+        assert_eq!(
+            labels,
+            vec![
+                ("top".to_string(), None),
+                ("sec:intro".to_string(), Some("Intro".to_string())),
+                ("fig:plot".to_string(), Some("Related work".to_string())),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_ranking_by_match_quality_then_use() {
+        let index = AutocompleteIndex::build(&[
+            source(
+                "/refs.bib",
+                ContentType::Bibliography,
+                "@article{smith2020, title={A}} @article{Smith2019, title={B}}
+                 @article{smith, title={C}} @article{jones_smith, title={D}}
+                 @article{smithers, title={E}} @article{brown, title={F}}",
+            ),
+            source(
+                "/main.tex",
+                ContentType::Latex,
+                "\\cite{smithers} \\citep[p.~1]{smithers, brown} \\parencite{smith2020}\n\\ref{x}",
+            ),
+        ]);
+
+        let entries = index.complete(AutocompleteKind::Citation, "smith", None, 10);
+        assert_eq!(
+            keys(&entries),
+            vec!["smith", "smithers", "smith2020", "Smith2019", "jones_smith"]
+        );
+        assert_eq!(entries[0].detail.as_deref(), Some("C"));
+
+        let limited = index.complete(AutocompleteKind::Citation, "smith", None, 2);
+        assert_eq!(keys(&limited), vec!["smith", "smithers"]);
+
+        let all = index.complete(AutocompleteKind::Citation, "", None, 2);
+        assert_eq!(keys(&all), vec!["smithers", "brown"]);
+    }
+
+    #[test]
+    fn test_paths_filtered_by_content_type() {
+        let index = AutocompleteIndex::build(&[
+            source("/main.tex", ContentType::Latex, "\\includegraphics[width=5cm]{figures/plot}"),
+            IndexSource { path: "/figures/photo.jpg".to_string(), content_type: ContentType::Image, content: None },
+            IndexSource { path: "/figures/plot.png".to_string(), content_type: ContentType::Image, content: None },
+            source("/figures/notes.tex", ContentType::Latex, ""),
+        ]);
+
+        let images = index.complete(AutocompleteKind::Path, "fig", Some(ContentType::Image), 10);
+        assert_eq!(keys(&images), vec!["figures/plot.png", "figures/photo.jpg"]);
+
+        let all = index.complete(AutocompleteKind::Path, "fig", None, 10);
+        assert_eq!(all.len(), 3);
+    }
+
+    #[test]
+    fn test_cache_discards_builds_overtaken_by_changes() {
+        let cache = AutocompleteCache::new();
+        let project = Uuid::new_v4();
+
+        let ticket = cache.begin_build(project);
+        cache.invalidate(project);
+        cache.insert(project, ticket, Arc::new(AutocompleteIndex::default()));
+        assert!(cache.get(project).is_none());
+
+        let ticket = cache.begin_build(project);
+        cache.insert(project, ticket, Arc::new(AutocompleteIndex::default()));
+        assert!(cache.get(project).is_some());
+
+        cache.invalidate(project);
+        assert!(cache.get(project).is_none());
+    }
+
+    #[test]
+    fn test_warm_cache_completes_quickly() {
+        let mut bib = String::new();
+        let mut tex = String::new();
+        let mut sources = Vec::new();
+        for i in 0..3000 {
+            bib.push_str(&format!("@article{{author{}, title = {{Paper number {}}}}}\n", i, i));
+            tex.push_str(&format!("\\section{{Part {}}}\\label{{sec:part{}}}\n\\cite{{author{}}}\n", i, i, i % 97));
+        }
+        for i in 0..1000 {
+            sources.push(IndexSource {
+                path: format!("/figures/plot{}.png", i),
+                content_type: ContentType::Image,
+                content: None,
+            });
+        }
+        sources.push(source("/refs.bib", ContentType::Bibliography, &bib));
+        sources.push(source("/main.tex", ContentType::Latex, &tex));
+
+        let cache = AutocompleteCache::new();
+        let project = Uuid::new_v4();
+        let ticket = cache.begin_build(project);
+        cache.insert(project, ticket, Arc::new(AutocompleteIndex::build(&sources)));
+
+        let queries = [
+            (AutocompleteKind::Citation, "author1", None),
+            (AutocompleteKind::Citation, "", None),
+            (AutocompleteKind::Label, "part2", None),
+            (AutocompleteKind::Path, "figures/plot9", Some(ContentType::Image)),
+        ];
+        for (kind, prefix, content_type) in queries {
+            let mut timings = Vec::new();
+            for _ in 0..20 {
+                let started = Instant::now();
+                let index = cache.get(project).expect("index is cached");
+                let entries = index.complete(kind, prefix, content_type, DEFAULT_AUTOCOMPLETE_LIMIT);
+                timings.push(started.elapsed());
+                assert!(!entries.is_empty());
+            }
+            timings.sort();
+            let median = timings[timings.len() / 2];
+            assert!(
+                median < Duration::from_millis(10),
+                "{:?} {:?} took {:?}",
+                kind,
+                prefix,
+                median
+            );
+        }
+    }
+}
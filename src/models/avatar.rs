@@ -0,0 +1,406 @@
+//! User avatars
+//!
+//! Avatars are uploaded rather than linked, so instances without an outside
+//! image host still have them. An upload is a PNG, JPEG or WebP image of at
+//! most `AVATAR_MAX_SIZE` bytes, recognized by its first bytes. It is never
+//! stored as sent: it is decoded, turned upright by its EXIF orientation,
+//! cropped to a centered square and encoded again as a PNG in each of
+//! `AVATAR_SIZES`, which leaves EXIF and any other metadata behind. The
+//! sizes live under `user-avatars/<user_id>/` in the file storage root,
+//! named by the hash of the upload; the previous avatar's files go once the
+//! new ones are in place.
+//!
+//! `avatar_url` then points at `/api/v1/users/<id>/avatar`, which serves the
+//! stored image, or an identicon drawn from the user id for users without
+//! one. The URL carries a prefix of the avatar's hash, so it always shows the
+//! same image and may be cached for good.
+//!
+//! Pictures of new OIDC accounts can be mirrored through the same pipeline
+//! (`OIDC_MIRROR_AVATARS`) instead of hotlinking the provider.
+
+use image::imageops::FilterType;
+use image::{DynamicImage, ImageDecoder, ImageFormat, ImageReader, Limits, Rgba, RgbaImage};
+use sha2::{Digest, Sha256};
+use std::io::Cursor;
+use std::path::PathBuf;
+use uuid::Uuid;
+
+use super::chat_attachment;
+use super::user::User;
+use crate::error::AppError;
+use crate::http_client::HttpClient;
+
+/// Largest image accepted as an avatar, in bytes
+pub const AVATAR_MAX_SIZE: usize = 5 * 1024 * 1024;
+
+/// Sides of the stored squares in pixels, smallest first
+pub const AVATAR_SIZES: [u32; 2] = [64, 256];
+
+/// Longest side of an image that is decoded at all
+const MAX_DIMENSION: u32 = 8192;
+
+/// Hex digits of the hash that version an avatar URL
+const VERSION_LEN: usize = 16;
+
+/// Cells per side of an identicon
+const IDENTICON_GRID: u32 = 5;
+
+/// An upload scaled to each of `AVATAR_SIZES`
+#[derive(Debug, Clone)]
+pub struct ProcessedAvatar {
+    /// SHA-256 of the upload
+    pub hash: String,
+    /// PNG of each size
+    pub images: Vec<(u32, Vec<u8>)>,
+}
+
+/// Stored size served for a requested one: the smallest that is at least as large
+pub fn stored_size(requested: Option<u32>) -> u32 {
+    let largest = AVATAR_SIZES[AVATAR_SIZES.len() - 1];
+    match requested {
+        Some(requested) => AVATAR_SIZES.into_iter().find(|&size| size >= requested).unwrap_or(largest),
+        None => AVATAR_SIZES[0],
+    }
+}
+
+/// Version of an avatar URL for the avatar with `hash`
+pub fn version(hash: &str) -> &str {
+    &hash[..VERSION_LEN.min(hash.len())]
+}
+
+/// URL the avatar of `user_id` is served at; `hash` pins an uploaded avatar
+pub fn avatar_url(user_id: Uuid, hash: Option<&str>) -> String {
+    let url = format!("/api/v1/users/{}/avatar?size={}", user_id, AVATAR_SIZES[0]);
+    match hash {
+        Some(hash) => format!("{}&v={}", url, version(hash)),
+        None => url,
+    }
+}
+
+fn avatar_dir(root: &str, user_id: Uuid) -> PathBuf {
+    PathBuf::from(root).join("user-avatars").join(user_id.to_string())
+}
+
+fn image_path(root: &str, user_id: Uuid, hash: &str, size: u32) -> PathBuf {
+    avatar_dir(root, user_id).join(format!("{}-{}.png", hash, size))
+}
+
+/// Check, decode and scale an uploaded image
+pub fn process(bytes: &[u8]) -> Result<ProcessedAvatar, AppError> {
+    if bytes.len() > AVATAR_MAX_SIZE {
+        return Err(AppError::Validation(format!(
+            "Avatars can be at most {} bytes",
+            AVATAR_MAX_SIZE
+        )));
+    }
+
+    let format = match chat_attachment::sniff_media_type(bytes) {
+        Some("image/png") => ImageFormat::Png,
+        Some("image/jpeg") => ImageFormat::Jpeg,
+        Some("image/webp") => ImageFormat::WebP,
+        _ => return Err(AppError::Validation("Avatars must be PNG, JPEG or WebP images".to_string())),
+    };
+    let image = decode(bytes, format).map_err(|e| AppError::Validation(format!("Invalid image: {}", e)))?;
+
+    let side = image.width().min(image.height());
+    if side == 0 {
+        return Err(AppError::Validation("Image is empty".to_string()));
+    }
+    let square = image.crop_imm((image.width() - side) / 2, (image.height() - side) / 2, side, side);
+
+    let images = AVATAR_SIZES
+        .into_iter()
+        .map(|size| Ok((size, encode_png(&square.resize_exact(size, size, FilterType::Lanczos3))?)))
+        .collect::<Result<_, AppError>>()?;
+
+    Ok(ProcessedAvatar {
+        hash: hex::encode(Sha256::digest(bytes)),
+        images,
+    })
+}
+
+/// [`process`] off the async runtime
+pub async fn process_upload(bytes: Vec<u8>) -> Result<ProcessedAvatar, AppError> {
+    tokio::task::spawn_blocking(move || process(&bytes))
+        .await
+        .map_err(|e| AppError::Internal(format!("Avatar processing failed: {}", e)))?
+}
+
+fn decode(bytes: &[u8], format: ImageFormat) -> image::ImageResult<DynamicImage> {
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(MAX_DIMENSION);
+    limits.max_image_height = Some(MAX_DIMENSION);
+
+    let mut reader = ImageReader::with_format(Cursor::new(bytes), format);
+    reader.limits(limits);
+    let mut decoder = reader.into_decoder()?;
+    let orientation = decoder.orientation()?;
+
+    let mut image = DynamicImage::from_decoder(decoder)?;
+    image.apply_orientation(orientation);
+    Ok(image)
+}
+
+fn encode_png(image: &DynamicImage) -> Result<Vec<u8>, AppError> {
+    let mut png = Vec::new();
+    image
+        .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+        .map_err(|e| AppError::Internal(format!("Failed to encode avatar: {}", e)))?;
+    Ok(png)
+}
+
+/// Identicon of `user_id`: a mirrored pattern of cells in a color taken from the id
+pub fn identicon(user_id: Uuid, size: u32) -> Result<Vec<u8>, AppError> {
+    let digest = Sha256::digest(user_id.as_bytes());
+    // Keep the color away from the light background
+    let color = Rgba([digest[0] / 2 + 32, digest[1] / 2 + 32, digest[2] / 2 + 32, 255]);
+    let background = Rgba([240, 240, 240, 255]);
+
+    let filled = |column: u32, row: u32| {
+        let column = column.min(IDENTICON_GRID - 1 - column);
+        digest[3 + (row * IDENTICON_GRID + column) as usize] & 1 == 1
+    };
+    let image = RgbaImage::from_fn(size, size, |x, y| {
+        if filled(x * IDENTICON_GRID / size, y * IDENTICON_GRID / size) {
+            color
+        } else {
+            background
+        }
+    });
+
+    encode_png(&DynamicImage::ImageRgba8(image))
+}
+
+/// Hash of the uploaded avatar of an active user; `None` for an unknown user
+pub async fn current_hash(db: &sqlx::PgPool, user_id: Uuid) -> Result<Option<Option<String>>, AppError> {
+    sqlx::query_scalar::<_, Option<String>>("SELECT avatar_hash FROM users WHERE id = $1 AND is_active = true")
+        .bind(user_id)
+        .fetch_optional(db)
+        .await
+        .map_err(AppError::Database)
+}
+
+/// The stored PNG of an avatar in `size`, `None` when its file is gone
+pub async fn read(root: &str, user_id: Uuid, hash: &str, size: u32) -> Result<Option<Vec<u8>>, AppError> {
+    match tokio::fs::read(image_path(root, user_id, hash, size)).await {
+        Ok(bytes) => Ok(Some(bytes)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(AppError::Storage(format!("Failed to read avatar: {}", e))),
+    }
+}
+
+/// Make `avatar` the avatar of `user_id`, removing the previous one
+pub async fn store(db: &sqlx::PgPool, root: &str, user_id: Uuid, avatar: &ProcessedAvatar) -> Result<User, AppError> {
+    let dir = avatar_dir(root, user_id);
+    tokio::fs::create_dir_all(&dir).await
+        .map_err(|e| AppError::Storage(format!("Failed to prepare avatar directory: {}", e)))?;
+
+    for (size, png) in &avatar.images {
+        let path = image_path(root, user_id, &avatar.hash, *size);
+        let partial = path.with_extension("partial");
+        tokio::fs::write(&partial, png).await
+            .map_err(|e| AppError::Storage(format!("Failed to store avatar: {}", e)))?;
+        tokio::fs::rename(&partial, &path).await
+            .map_err(|e| AppError::Storage(format!("Failed to store avatar: {}", e)))?;
+    }
+
+    let user = sqlx::query_as::<_, User>(
+        r#"
+        UPDATE users
+        SET avatar_hash = $2, avatar_url = $3, updated_at = NOW()
+        WHERE id = $1
+        RETURNING *
+        "#
+    )
+    .bind(user_id)
+    .bind(&avatar.hash)
+    .bind(avatar_url(user_id, Some(&avatar.hash)))
+    .fetch_one(db)
+    .await
+    .map_err(AppError::Database)?;
+
+    // The new avatar is in place; a leftover file only costs space
+    if let Err(e) = remove_stale(&dir, &avatar.hash).await {
+        tracing::warn!("Failed to remove the previous avatar of user {}: {}", user_id, e);
+    }
+
+    Ok(user)
+}
+
+/// Delete the files in `dir` that do not belong to the avatar with `hash`
+async fn remove_stale(dir: &std::path::Path, hash: &str) -> std::io::Result<()> {
+    let prefix = format!("{}-", hash);
+    let mut entries = tokio::fs::read_dir(dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        if !entry.file_name().to_string_lossy().starts_with(&prefix) {
+            tokio::fs::remove_file(entry.path()).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Drop the uploaded avatar of `user_id`, who is shown an identicon again
+pub async fn remove(db: &sqlx::PgPool, root: &str, user_id: Uuid) -> Result<User, AppError> {
+    let user = sqlx::query_as::<_, User>(
+        r#"
+        UPDATE users
+        SET avatar_hash = NULL, avatar_url = $2, updated_at = NOW()
+        WHERE id = $1
+        RETURNING *
+        "#
+    )
+    .bind(user_id)
+    .bind(avatar_url(user_id, None))
+    .fetch_one(db)
+    .await
+    .map_err(AppError::Database)?;
+
+    match tokio::fs::remove_dir_all(avatar_dir(root, user_id)).await {
+        Ok(()) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => return Err(AppError::Storage(format!("Failed to remove avatar: {}", e))),
+    }
+
+    Ok(user)
+}
+
+/// Where pictures of new OIDC accounts are mirrored to
+pub struct PictureMirror<'a> {
+    pub http: &'a HttpClient,
+    pub storage_root: &'a str,
+}
+
+impl<'a> PictureMirror<'a> {
+    /// The mirror of this instance, `None` unless `OIDC_MIRROR_AVATARS` is on
+    pub fn from_state(state: &'a crate::server::AppState) -> Option<Self> {
+        let storage = &state.config.features.file_storage;
+        (state.config.oidc.mirror_avatars && storage.type_ == "local").then(|| Self {
+            http: &state.http_client,
+            storage_root: &storage.local_path,
+        })
+    }
+
+    /// Fetch the picture at `url` and make it the avatar of `user_id`
+    pub async fn mirror(&self, db: &sqlx::PgPool, user_id: Uuid, url: &str) -> Result<User, AppError> {
+        let parsed = url::Url::parse(url).map_err(|e| AppError::Validation(format!("Invalid picture URL: {}", e)))?;
+        if !matches!(parsed.scheme(), "http" | "https") {
+            return Err(AppError::Validation(format!("Unsupported picture URL scheme '{}'", parsed.scheme())));
+        }
+
+        let request = self.http
+            .request(reqwest::Method::GET, url)
+            .build()
+            .map_err(|e| AppError::Upstream(format!("Invalid picture request: {}", e)))?;
+        let mut response = self.http.send("avatar", request).await?;
+        if !response.status().is_success() {
+            return Err(AppError::Upstream(format!("Picture request returned {}", response.status())));
+        }
+
+        let mut bytes = Vec::new();
+        while let Some(chunk) = response.chunk().await
+            .map_err(|e| AppError::Upstream(format!("Failed to read picture: {}", e)))?
+        {
+            if bytes.len() + chunk.len() > AVATAR_MAX_SIZE {
+                return Err(AppError::Validation(format!(
+                    "Picture is larger than {} bytes",
+                    AVATAR_MAX_SIZE
+                )));
+            }
+            bytes.extend_from_slice(&chunk);
+        }
+
+        let avatar = process_upload(bytes).await?;
+        store(db, self.storage_root, user_id, &avatar).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A JPEG of a red left half and a blue right half, carrying `orientation` in EXIF
+    fn jpeg_with_orientation(orientation: u8) -> Vec<u8> {
+        // The halves meet on a block boundary, so neither bleeds into the other
+        let image = RgbaImage::from_fn(64, 32, |x, _| {
+            if x < 32 { Rgba([255, 0, 0, 255]) } else { Rgba([0, 0, 255, 255]) }
+        });
+        let mut jpeg = Vec::new();
+        DynamicImage::ImageRgba8(image)
+            .to_rgb8()
+            .write_to(&mut Cursor::new(&mut jpeg), ImageFormat::Jpeg)
+            .unwrap();
+
+        // Big-endian TIFF with a single orientation entry
+        let mut exif = b"Exif\0\0MM\0\x2a\0\0\0\x08\0\x01".to_vec();
+        exif.extend_from_slice(&[0x01, 0x12, 0x00, 0x03, 0, 0, 0, 1, 0, orientation, 0, 0]);
+        exif.extend_from_slice(&[0, 0, 0, 0]);
+        let mut segment = vec![0xFF, 0xE1];
+        segment.extend_from_slice(&((exif.len() + 2) as u16).to_be_bytes());
+        segment.extend_from_slice(&exif);
+
+        // Right after the start of image marker
+        let rest = jpeg.split_off(2);
+        jpeg.extend(segment);
+        jpeg.extend(rest);
+        jpeg
+    }
+
+    fn decode_png(png: &[u8]) -> RgbaImage {
+        image::load_from_memory_with_format(png, ImageFormat::Png).unwrap().to_rgba8()
+    }
+
+    #[test]
+    fn test_process_strips_metadata_and_applies_orientation() {
+        let upload = jpeg_with_orientation(6);
+        let avatar = process(&upload).unwrap();
+
+        assert_eq!(avatar.hash, hex::encode(Sha256::digest(&upload)));
+        assert_eq!(avatar.images.iter().map(|(size, _)| *size).collect::<Vec<_>>(), vec![64, 256]);
+        for (size, png) in &avatar.images {
+            assert_eq!(decode_png(png).dimensions(), (*size, *size));
+            assert!(!png.windows(4).any(|window| window == b"Exif" || window == b"eXIf"));
+        }
+
+        // Turned a quarter clockwise, the red left half ends up on top
+        let small = decode_png(&avatar.images[0].1);
+        let top = small.get_pixel(32, 4);
+        let bottom = small.get_pixel(32, 60);
+        assert!(top[0] > 200 && top[2] < 60, "{:?}", top);
+        assert!(bottom[2] > 200 && bottom[0] < 60, "{:?}", bottom);
+    }
+
+    #[test]
+    fn test_process_rejects_other_content() {
+        assert!(matches!(process(b"GIF89a\x01\0\x01\0"), Err(AppError::Validation(_))));
+        assert!(matches!(process(b"<svg xmlns=\"http://www.w3.org/2000/svg\"/>"), Err(AppError::Validation(_))));
+        // Right magic bytes, broken image
+        assert!(matches!(process(b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR"), Err(AppError::Validation(_))));
+        assert!(matches!(process(&vec![0; AVATAR_MAX_SIZE + 1]), Err(AppError::Validation(_))));
+    }
+
+    #[test]
+    fn test_sizes_urls_and_identicons() {
+        assert_eq!(stored_size(None), 64);
+        assert_eq!(stored_size(Some(32)), 64);
+        assert_eq!(stored_size(Some(100)), 256);
+        assert_eq!(stored_size(Some(4096)), 256);
+
+        let user_id = Uuid::new_v4();
+        let hash = "ab".repeat(32);
+        assert_eq!(
+            avatar_url(user_id, Some(&hash)),
+            format!("/api/v1/users/{}/avatar?size=64&v={}", user_id, &hash[..16])
+        );
+        assert_eq!(avatar_url(user_id, None), format!("/api/v1/users/{}/avatar?size=64", user_id));
+
+        let identicon_png = identicon(user_id, 64).unwrap();
+        assert_eq!(identicon_png, identicon(user_id, 64).unwrap());
+        assert_ne!(identicon_png, identicon(Uuid::new_v4(), 64).unwrap());
+        let decoded = decode_png(&identicon_png);
+        assert_eq!(decoded.dimensions(), (64, 64));
+        // Mirrored around the middle column
+        for y in 0..64 {
+            assert_eq!(decoded.get_pixel(3, y), decoded.get_pixel(60, y));
+        }
+    }
+}
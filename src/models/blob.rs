@@ -0,0 +1,352 @@
+//! Content-addressed blob store models
+//!
+//! Files and file versions reference their bytes by SHA-256 hash. Each
+//! distinct hash has one `blobs` row carrying a reference count; the row and
+//! any bytes on disk are removed when the last reference goes away.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use utoipa::ToSchema;
+
+/// Blob model
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, ToSchema)]
+pub struct Blob {
+    pub hash: String,
+    pub size: i64,
+    pub ref_count: i32,
+    /// Path relative to the file storage root; `None` when the bytes are kept
+    /// inline on the referencing rows
+    pub storage_path: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// Result of acquiring a blob reference
+#[derive(Debug, Clone)]
+pub struct AcquiredBlob {
+    pub blob: Blob,
+    /// True when no stored copy of the bytes existed yet, so the caller must
+    /// write them to `blob.storage_path`
+    pub needs_content: bool,
+}
+
+/// A file or version row pointing at a hash with no blob
+#[derive(Debug, Clone, Serialize, FromRow, ToSchema)]
+pub struct DanglingReference {
+    pub source: String,
+    pub id: uuid::Uuid,
+    pub hash: String,
+}
+
+/// A blob whose stored count disagrees with the rows that reference it
+#[derive(Debug, Clone, Serialize, FromRow, ToSchema)]
+pub struct RefCountMismatch {
+    pub hash: String,
+    pub ref_count: i32,
+    pub actual_refs: i64,
+}
+
+/// Blob store consistency report
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct BlobConsistencyReport {
+    pub total_blobs: i64,
+    pub total_bytes: i64,
+    /// Blobs no file or version references
+    pub orphaned_blobs: Vec<Blob>,
+    /// File or version rows pointing at a hash with no blob
+    pub dangling_references: Vec<DanglingReference>,
+    pub ref_count_mismatches: Vec<RefCountMismatch>,
+    /// Blobs whose bytes are missing from storage
+    pub missing_content: Vec<String>,
+    pub checked_at: DateTime<Utc>,
+}
+
+impl BlobConsistencyReport {
+    pub fn is_consistent(&self) -> bool {
+        self.orphaned_blobs.is_empty()
+            && self.dangling_references.is_empty()
+            && self.ref_count_mismatches.is_empty()
+            && self.missing_content.is_empty()
+    }
+}
+
+/// Rows that count as references to a blob
+const BLOB_REFERENCES: &str = r#"
+    SELECT 'file' AS source, id, content_hash AS hash FROM files WHERE content_hash IS NOT NULL
+    UNION ALL
+    SELECT 'file_version' AS source, id, content_hash AS hash FROM file_versions WHERE content_hash IS NOT NULL
+"#;
+
+impl Blob {
+    /// Add a reference to a blob, creating it if this is the first one.
+    ///
+    /// The upsert takes a row lock, so a concurrent `release` of the same
+    /// hash either completes first (and the blob is recreated) or sees the
+    /// incremented count and keeps the row. Passing a `storage_path` for a
+    /// blob that so far only existed inline records where its bytes will live.
+    pub async fn acquire(
+        conn: &mut sqlx::PgConnection,
+        hash: &str,
+        size: i64,
+        storage_path: Option<&str>,
+    ) -> Result<AcquiredBlob, crate::error::AppError> {
+        let previous = sqlx::query_scalar::<_, Option<String>>(
+            "SELECT storage_path FROM blobs WHERE hash = $1 FOR UPDATE"
+        )
+        .bind(hash)
+        .fetch_optional(&mut *conn)
+        .await
+        .map_err(crate::error::AppError::Database)?;
+
+        let blob = sqlx::query_as::<_, Blob>(
+            r#"
+            INSERT INTO blobs (hash, size, ref_count, storage_path)
+            VALUES ($1, $2, 1, $3)
+            ON CONFLICT (hash) DO UPDATE SET
+                ref_count = blobs.ref_count + 1,
+                storage_path = COALESCE(blobs.storage_path, EXCLUDED.storage_path),
+                updated_at = NOW()
+            RETURNING *
+            "#
+        )
+        .bind(hash)
+        .bind(size)
+        .bind(storage_path)
+        .fetch_one(&mut *conn)
+        .await
+        .map_err(crate::error::AppError::Database)?;
+
+        let needs_content = storage_path.is_some() && !matches!(previous, Some(Some(_)));
+
+        Ok(AcquiredBlob { blob, needs_content })
+    }
+
+    /// Drop a reference to a blob.
+    ///
+    /// Returns the blob when this was the last reference and the row was
+    /// deleted; the caller removes any stored bytes before committing so a
+    /// concurrent `acquire` cannot recreate the blob while the file is still
+    /// being unlinked.
+    pub async fn release(
+        conn: &mut sqlx::PgConnection,
+        hash: &str,
+    ) -> Result<Option<Blob>, crate::error::AppError> {
+        let remaining = sqlx::query_scalar::<_, i32>(
+            r#"
+            UPDATE blobs SET ref_count = ref_count - 1, updated_at = NOW()
+            WHERE hash = $1 AND ref_count > 0
+            RETURNING ref_count
+            "#
+        )
+        .bind(hash)
+        .fetch_optional(&mut *conn)
+        .await
+        .map_err(crate::error::AppError::Database)?;
+
+        if remaining != Some(0) {
+            return Ok(None);
+        }
+
+        let removed = sqlx::query_as::<_, Blob>(
+            "DELETE FROM blobs WHERE hash = $1 AND ref_count = 0 RETURNING *"
+        )
+        .bind(hash)
+        .fetch_optional(&mut *conn)
+        .await
+        .map_err(crate::error::AppError::Database)?;
+
+        Ok(removed)
+    }
+
+    /// Find blob by hash
+    pub async fn find_by_hash(
+        db: &sqlx::PgPool,
+        hash: &str,
+    ) -> Result<Option<Self>, crate::error::AppError> {
+        let blob = sqlx::query_as::<_, Blob>("SELECT * FROM blobs WHERE hash = $1")
+            .bind(hash)
+            .fetch_optional(db)
+            .await
+            .map_err(crate::error::AppError::Database)?;
+
+        Ok(blob)
+    }
+
+    /// Check blob bookkeeping against the referencing rows and storage
+    pub async fn check_consistency(
+        db: &sqlx::PgPool,
+        storage_root: &str,
+    ) -> Result<BlobConsistencyReport, crate::error::AppError> {
+        let (total_blobs, total_bytes) = sqlx::query_as::<_, (i64, i64)>(
+            "SELECT COUNT(*), COALESCE(SUM(size), 0)::BIGINT FROM blobs"
+        )
+        .fetch_one(db)
+        .await
+        .map_err(crate::error::AppError::Database)?;
+
+        let orphaned_blobs = sqlx::query_as::<_, Blob>(&format!(
+            r#"
+            SELECT b.* FROM blobs b
+            WHERE NOT EXISTS (SELECT 1 FROM ({}) r WHERE r.hash = b.hash)
+            ORDER BY b.created_at
+            "#,
+            BLOB_REFERENCES
+        ))
+        .fetch_all(db)
+        .await
+        .map_err(crate::error::AppError::Database)?;
+
+        let dangling_references = sqlx::query_as::<_, DanglingReference>(&format!(
+            r#"
+            SELECT r.source, r.id, r.hash FROM ({}) r
+            WHERE NOT EXISTS (SELECT 1 FROM blobs b WHERE b.hash = r.hash)
+            ORDER BY r.source, r.id
+            "#,
+            BLOB_REFERENCES
+        ))
+        .fetch_all(db)
+        .await
+        .map_err(crate::error::AppError::Database)?;
+
+        let ref_count_mismatches = sqlx::query_as::<_, RefCountMismatch>(&format!(
+            r#"
+            SELECT b.hash, b.ref_count, COUNT(r.hash) AS actual_refs
+            FROM blobs b
+            LEFT JOIN ({}) r ON r.hash = b.hash
+            GROUP BY b.hash, b.ref_count
+            HAVING COUNT(r.hash) > 0 AND COUNT(r.hash) <> b.ref_count
+            ORDER BY b.hash
+            "#,
+            BLOB_REFERENCES
+        ))
+        .fetch_all(db)
+        .await
+        .map_err(crate::error::AppError::Database)?;
+
+        let stored = sqlx::query_as::<_, (String, String)>(
+            "SELECT hash, storage_path FROM blobs WHERE storage_path IS NOT NULL"
+        )
+        .fetch_all(db)
+        .await
+        .map_err(crate::error::AppError::Database)?;
+
+        let mut missing_content = Vec::new();
+        for (hash, storage_path) in stored {
+            let path = std::path::Path::new(storage_root).join(&storage_path);
+            if !tokio::fs::try_exists(&path).await.unwrap_or(false) {
+                missing_content.push(hash);
+            }
+        }
+
+        Ok(BlobConsistencyReport {
+            total_blobs,
+            total_bytes,
+            orphaned_blobs,
+            dangling_references,
+            ref_count_mismatches,
+            missing_content,
+            checked_at: Utc::now(),
+        })
+    }
+}
+
+/// Storage path for a blob, relative to the file storage root
+pub fn blob_storage_path(hash: &str) -> String {
+    let prefix = hash.get(..2).unwrap_or("00");
+    format!("blobs/{}/{}", prefix, hash)
+}
+
+/// Remove the stored bytes of a released blob, if it had any
+pub async fn remove_blob_content(storage_root: &str, blob: &Blob) -> Result<(), crate::error::AppError> {
+    let Some(storage_path) = &blob.storage_path else {
+        return Ok(());
+    };
+
+    match tokio::fs::remove_file(std::path::Path::new(storage_root).join(storage_path)).await {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(crate::error::AppError::Storage(format!(
+            "Failed to remove blob {}: {}",
+            blob.hash, e
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{create_test_file, create_test_project, create_test_user, TestDb};
+
+    fn unique_hash() -> String {
+        format!("{}{}", uuid::Uuid::new_v4().simple(), uuid::Uuid::new_v4().simple())
+    }
+
+    #[test]
+    fn test_blob_storage_path() {
+        let hash = "ab".repeat(32);
+        assert_eq!(blob_storage_path(&hash), format!("blobs/ab/{}", hash));
+    }
+
+    #[tokio::test]
+    async fn test_consistency_check_reports_each_kind_of_drift() {
+        let Some(db) = TestDb::start().await else { return };
+        let storage = tempfile::tempdir().unwrap();
+        let root = storage.path().to_str().unwrap();
+        let owner = create_test_user(&db.pool).await;
+        let project = create_test_project(&db.pool, &owner, false).await;
+        let file = create_test_file(&db.pool, &project, &owner).await;
+
+        // A file pointing at a blob with the wrong count
+        let counted = unique_hash();
+        let mut conn = db.pool.acquire().await.unwrap();
+        Blob::acquire(&mut conn, &counted, 4, None).await.unwrap();
+        Blob::acquire(&mut conn, &counted, 4, None).await.unwrap();
+        drop(conn);
+        sqlx::query("UPDATE files SET content_hash = $1 WHERE id = $2")
+            .bind(&counted)
+            .bind(file.id)
+            .execute(&db.pool)
+            .await
+            .unwrap();
+
+        // A blob nothing references, whose bytes were never written
+        let orphaned = unique_hash();
+        let mut conn = db.pool.acquire().await.unwrap();
+        Blob::acquire(&mut conn, &orphaned, 4, Some(&blob_storage_path(&orphaned))).await.unwrap();
+        drop(conn);
+
+        let report = Blob::check_consistency(&db.pool, root).await.unwrap();
+        assert!(!report.is_consistent());
+        let mismatch = report.ref_count_mismatches.iter().find(|m| m.hash == counted).unwrap();
+        assert_eq!((mismatch.ref_count, mismatch.actual_refs), (2, 1));
+        assert!(report.orphaned_blobs.iter().any(|blob| blob.hash == orphaned));
+        assert!(report.missing_content.contains(&orphaned));
+
+        // Once the count is right and the orphan's bytes exist, only the orphan remains
+        sqlx::query("UPDATE blobs SET ref_count = 1 WHERE hash = $1")
+            .bind(&counted)
+            .execute(&db.pool)
+            .await
+            .unwrap();
+        let path = storage.path().join(blob_storage_path(&orphaned));
+        tokio::fs::create_dir_all(path.parent().unwrap()).await.unwrap();
+        tokio::fs::write(&path, b"data").await.unwrap();
+
+        let report = Blob::check_consistency(&db.pool, root).await.unwrap();
+        assert!(!report.ref_count_mismatches.iter().any(|m| m.hash == counted));
+        assert!(!report.missing_content.contains(&orphaned));
+        assert!(report.orphaned_blobs.iter().any(|blob| blob.hash == orphaned));
+
+        // A file whose blob row is gone is a dangling reference
+        sqlx::query("DELETE FROM blobs WHERE hash = ANY($1)")
+            .bind(vec![counted.clone(), orphaned.clone()])
+            .execute(&db.pool)
+            .await
+            .unwrap();
+        let report = Blob::check_consistency(&db.pool, root).await.unwrap();
+        let dangling = report.dangling_references.iter().find(|r| r.hash == counted).unwrap();
+        assert_eq!((dangling.source.as_str(), dangling.id), ("file", file.id));
+        assert!(!report.orphaned_blobs.iter().any(|blob| blob.hash == orphaned));
+    }
+}
@@ -0,0 +1,400 @@
+//! Files shared in session chat
+//!
+//! Attachments belong to a collaboration session rather than the project file
+//! tree. A participant uploads an image or PDF, gets its id back and sends a
+//! `file` message whose content is that id. The bytes live under
+//! `attachments/<session_id>/` in the file storage root, after the same
+//! malware scan as project uploads, and only the host and participants of the
+//! session can fetch them. Images get a thumbnail, rendered with ImageMagick
+//! on first request and kept next to the original. All attachments of a
+//! session go when its host deletes it or once it has been over for the
+//! operation retention window.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::time::Duration;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::error::AppError;
+
+/// Longest side of a thumbnail in pixels
+pub const THUMBNAIL_SIZE: u32 = 320;
+
+/// Time ImageMagick gets for one thumbnail
+const THUMBNAIL_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Time between two passes of the cleanup task
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// Media type of an accepted attachment, recognized by its first bytes
+///
+/// The file name is not trusted, and SVG is left out because it can carry scripts.
+pub fn sniff_media_type(head: &[u8]) -> Option<&'static str> {
+    if head.starts_with(b"\x89PNG\r\n\x1a\n") {
+        Some("image/png")
+    } else if head.starts_with(&[0xFF, 0xD8, 0xFF]) {
+        Some("image/jpeg")
+    } else if head.starts_with(b"GIF87a") || head.starts_with(b"GIF89a") {
+        Some("image/gif")
+    } else if head.len() >= 12 && head.starts_with(b"RIFF") && &head[8..12] == b"WEBP" {
+        Some("image/webp")
+    } else if head.starts_with(b"%PDF-") {
+        Some("application/pdf")
+    } else {
+        None
+    }
+}
+
+/// Directory of a session's attachments under the storage root
+pub(crate) fn session_dir(root: &str, session_id: Uuid) -> PathBuf {
+    PathBuf::from(root).join("attachments").join(session_id.to_string())
+}
+
+/// A file shared in a session's chat
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, ToSchema)]
+pub struct ChatAttachment {
+    pub id: Uuid,
+    pub session_id: Uuid,
+    pub uploaded_by: Uuid,
+    pub file_name: String,
+    /// Detected from the content, not the file name
+    pub media_type: String,
+    pub size: i64,
+    pub content_hash: String,
+    pub created_at: DateTime<Utc>,
+}
+
+/// An attachment with the URLs it is served at
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct AttachmentView {
+    #[serde(flatten)]
+    pub attachment: ChatAttachment,
+    pub download_url: String,
+    /// Set for images
+    pub thumbnail_url: Option<String>,
+}
+
+impl ChatAttachment {
+    pub fn is_image(&self) -> bool {
+        self.media_type.starts_with("image/")
+    }
+
+    pub fn storage_path(&self, root: &str) -> PathBuf {
+        session_dir(root, self.session_id).join(self.id.to_string())
+    }
+
+    pub fn thumbnail_path(&self, root: &str) -> PathBuf {
+        session_dir(root, self.session_id).join(format!("{}.thumb.png", self.id))
+    }
+
+    /// The attachment with its download and thumbnail URLs
+    pub fn view(self) -> AttachmentView {
+        let download_url = format!(
+            "/api/v1/collaboration/sessions/{}/attachments/{}",
+            self.session_id, self.id
+        );
+        AttachmentView {
+            thumbnail_url: self.is_image().then(|| format!("{}/thumbnail", download_url)),
+            download_url,
+            attachment: self,
+        }
+    }
+
+    /// Move a scanned upload into the session's attachments and record it
+    #[allow(clippy::too_many_arguments)]
+    pub async fn store(
+        db: &sqlx::PgPool,
+        root: &str,
+        session_id: Uuid,
+        uploaded_by: Uuid,
+        file_name: &str,
+        media_type: &str,
+        staged_path: &Path,
+        size: i64,
+        content_hash: &str,
+    ) -> Result<Self, AppError> {
+        let id = Uuid::new_v4();
+        let dir = session_dir(root, session_id);
+        tokio::fs::create_dir_all(&dir).await
+            .map_err(|e| AppError::Storage(format!("Failed to prepare attachment directory: {}", e)))?;
+
+        let path = dir.join(id.to_string());
+        tokio::fs::rename(staged_path, &path).await
+            .map_err(|e| AppError::Storage(format!("Failed to store attachment: {}", e)))?;
+
+        let attachment = sqlx::query_as::<_, ChatAttachment>(
+            r#"
+            INSERT INTO session_attachments (id, session_id, uploaded_by, file_name, media_type, size, content_hash)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)
+            RETURNING *
+            "#
+        )
+        .bind(id)
+        .bind(session_id)
+        .bind(uploaded_by)
+        .bind(file_name)
+        .bind(media_type)
+        .bind(size)
+        .bind(content_hash)
+        .fetch_one(db)
+        .await
+        .map_err(AppError::Database);
+
+        if attachment.is_err() {
+            let _ = tokio::fs::remove_file(&path).await;
+        }
+        attachment
+    }
+
+    pub async fn find(db: &sqlx::PgPool, session_id: Uuid, id: Uuid) -> Result<Option<Self>, AppError> {
+        sqlx::query_as::<_, ChatAttachment>(
+            "SELECT * FROM session_attachments WHERE id = $1 AND session_id = $2"
+        )
+        .bind(id)
+        .bind(session_id)
+        .fetch_optional(db)
+        .await
+        .map_err(AppError::Database)
+    }
+
+    /// Attachments of a session among `ids`
+    pub async fn find_many(db: &sqlx::PgPool, session_id: Uuid, ids: &[Uuid]) -> Result<Vec<Self>, AppError> {
+        if ids.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        sqlx::query_as::<_, ChatAttachment>(
+            "SELECT * FROM session_attachments WHERE session_id = $1 AND id = ANY($2)"
+        )
+        .bind(session_id)
+        .bind(ids)
+        .fetch_all(db)
+        .await
+        .map_err(AppError::Database)
+    }
+
+    /// The attachment a `file` message by `user_id` refers to
+    ///
+    /// The message content must be the id of an attachment the sender uploaded
+    /// to the same session.
+    pub async fn resolve_message(
+        db: &sqlx::PgPool,
+        session_id: Uuid,
+        user_id: Uuid,
+        content: &str,
+    ) -> Result<Self, AppError> {
+        let not_found = || AppError::Validation(
+            "File messages must contain the id of an attachment you uploaded to this session".to_string(),
+        );
+
+        let id = Uuid::parse_str(content.trim()).map_err(|_| not_found())?;
+        Self::find(db, session_id, id)
+            .await?
+            .filter(|attachment| attachment.uploaded_by == user_id)
+            .ok_or_else(not_found)
+    }
+
+    pub async fn read_bytes(&self, root: &str) -> Result<Vec<u8>, AppError> {
+        tokio::fs::read(self.storage_path(root)).await
+            .map_err(|e| AppError::Storage(format!("Failed to read attachment: {}", e)))
+    }
+
+    /// Thumbnail of an image attachment and its media type
+    ///
+    /// Rendered on first request and cached next to the original. When
+    /// ImageMagick is missing or fails, the original image is returned instead.
+    pub async fn thumbnail(&self, root: &str) -> Result<(Vec<u8>, String), AppError> {
+        let path = self.thumbnail_path(root);
+        if let Ok(bytes) = tokio::fs::read(&path).await {
+            return Ok((bytes, "image/png".to_string()));
+        }
+
+        match render_thumbnail(&self.storage_path(root), &self.media_type, &path).await {
+            Ok(bytes) => Ok((bytes, "image/png".to_string())),
+            Err(e) => {
+                tracing::warn!("Failed to render thumbnail of attachment {}: {}", self.id, e);
+                Ok((self.read_bytes(root).await?, self.media_type.clone()))
+            }
+        }
+    }
+
+    /// Delete all attachments of a session, rows and files
+    pub async fn delete_for_session(db: &sqlx::PgPool, root: &str, session_id: Uuid) -> Result<u64, AppError> {
+        let deleted = sqlx::query("DELETE FROM session_attachments WHERE session_id = $1")
+            .bind(session_id)
+            .execute(db)
+            .await
+            .map_err(AppError::Database)?
+            .rows_affected();
+
+        match tokio::fs::remove_dir_all(session_dir(root, session_id)).await {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(AppError::Storage(format!("Failed to remove attachments: {}", e))),
+        }
+
+        Ok(deleted)
+    }
+
+    /// Delete the attachments of sessions that ended before `now - retention`
+    pub async fn purge_expired(
+        db: &sqlx::PgPool,
+        root: &str,
+        retention: chrono::Duration,
+    ) -> Result<u64, AppError> {
+        let sessions = sqlx::query_scalar::<_, Uuid>(
+            r#"
+            SELECT DISTINCT a.session_id
+            FROM session_attachments a
+            JOIN collaboration_sessions s ON s.id = a.session_id
+            WHERE s.is_active = false AND s.ended_at < $1
+            "#
+        )
+        .bind(Utc::now() - retention)
+        .fetch_all(db)
+        .await
+        .map_err(AppError::Database)?;
+
+        let mut deleted = 0;
+        for session_id in sessions {
+            deleted += Self::delete_for_session(db, root, session_id).await?;
+        }
+
+        Ok(deleted)
+    }
+}
+
+/// Scale an image down to `THUMBNAIL_SIZE` as PNG at `output`
+async fn render_thumbnail(input: &Path, media_type: &str, output: &Path) -> Result<Vec<u8>, AppError> {
+    // Name the coder explicitly so ImageMagick does not guess from the content;
+    // `[0]` takes the first frame of animations
+    let coder = media_type.trim_start_matches("image/");
+    let partial = output.with_extension("partial.png");
+
+    let convert = tokio::process::Command::new("convert")
+        .arg(format!("{}:{}[0]", coder, input.display()))
+        .args(["-auto-orient", "-strip", "-thumbnail"])
+        .arg(format!("{0}x{0}>", THUMBNAIL_SIZE))
+        .arg(format!("png:{}", partial.display()))
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .kill_on_drop(true)
+        .status();
+
+    let status = tokio::time::timeout(THUMBNAIL_TIMEOUT, convert)
+        .await
+        .map_err(|_| AppError::Internal("Thumbnail rendering timed out".to_string()))?
+        .map_err(|e| AppError::Internal(format!("Failed to run convert: {}", e)))?;
+
+    if !status.success() {
+        let _ = tokio::fs::remove_file(&partial).await;
+        return Err(AppError::Internal("convert could not render the thumbnail".to_string()));
+    }
+
+    tokio::fs::rename(&partial, output).await?;
+    Ok(tokio::fs::read(output).await?)
+}
+
+/// Removes the attachments of sessions past the retention window
+pub struct AttachmentCleanupTask;
+
+impl crate::tasks::PeriodicTask for AttachmentCleanupTask {
+    fn name(&self) -> &'static str {
+        "chat_attachment_cleanup"
+    }
+
+    fn interval(&self) -> Duration {
+        CLEANUP_INTERVAL
+    }
+
+    fn run<'a>(
+        &'a self,
+        state: &'a crate::server::AppState,
+    ) -> futures::future::BoxFuture<'a, Result<(), AppError>> {
+        Box::pin(async move {
+            let deleted = ChatAttachment::purge_expired(
+                &state.db_pool,
+                &state.config.features.file_storage.local_path,
+                super::session_summary::operation_retention(&state.config),
+            )
+            .await?;
+            if deleted > 0 {
+                tracing::info!("Deleted {} chat attachments of expired sessions", deleted);
+            }
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{create_test_project, create_test_session, create_test_user, TestDb};
+
+    #[test]
+    fn test_media_type_is_sniffed_from_content() {
+        assert_eq!(sniff_media_type(b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR"), Some("image/png"));
+        assert_eq!(sniff_media_type(&[0xFF, 0xD8, 0xFF, 0xE0]), Some("image/jpeg"));
+        assert_eq!(sniff_media_type(b"GIF89a\x01\0"), Some("image/gif"));
+        assert_eq!(sniff_media_type(b"RIFF\x24\0\0\0WEBPVP8 "), Some("image/webp"));
+        assert_eq!(sniff_media_type(b"%PDF-1.7\n"), Some("application/pdf"));
+
+        assert_eq!(sniff_media_type(b"<svg xmlns=\"http://www.w3.org/2000/svg\">"), None);
+        assert_eq!(sniff_media_type(b"RIFF\x24\0\0\0WAVE"), None);
+        assert_eq!(sniff_media_type(b""), None);
+    }
+
+    #[tokio::test]
+    async fn test_attachments_expire_with_their_session() {
+        let Some(db) = TestDb::start().await else { return };
+        let storage = tempfile::tempdir().unwrap();
+        let root = storage.path().to_str().unwrap();
+
+        let owner = create_test_user(&db.pool).await;
+        let project = create_test_project(&db.pool, &owner, false).await;
+        let session = create_test_session(&db.pool, &project, &owner).await;
+
+        let staged = storage.path().join("staged");
+        tokio::fs::write(&staged, b"%PDF-1.7\n").await.unwrap();
+        let attachment = ChatAttachment::store(
+            &db.pool, root, session.id, owner.id, "notes.pdf", "application/pdf", &staged, 9, "hash",
+        )
+        .await
+        .unwrap();
+        assert_eq!(attachment.read_bytes(root).await.unwrap(), b"%PDF-1.7\n");
+
+        let view = attachment.clone().view();
+        assert!(view.download_url.ends_with(&format!("/attachments/{}", attachment.id)));
+        assert_eq!(view.thumbnail_url, None);
+
+        // Only the uploader can send it, by id
+        let resolved = ChatAttachment::resolve_message(&db.pool, session.id, owner.id, &attachment.id.to_string())
+            .await
+            .unwrap();
+        assert_eq!(resolved.id, attachment.id);
+        let stranger = create_test_user(&db.pool).await;
+        assert!(ChatAttachment::resolve_message(&db.pool, session.id, stranger.id, &attachment.id.to_string())
+            .await
+            .is_err());
+        assert!(ChatAttachment::resolve_message(&db.pool, session.id, owner.id, "not an id").await.is_err());
+
+        // Active sessions keep their attachments
+        let retention = chrono::Duration::hours(1);
+        ChatAttachment::purge_expired(&db.pool, root, retention).await.unwrap();
+        assert!(ChatAttachment::find(&db.pool, session.id, attachment.id).await.unwrap().is_some());
+
+        sqlx::query("UPDATE collaboration_sessions SET is_active = false, ended_at = NOW() - INTERVAL '2 hours' WHERE id = $1")
+            .bind(session.id)
+            .execute(&db.pool)
+            .await
+            .unwrap();
+        ChatAttachment::purge_expired(&db.pool, root, retention).await.unwrap();
+        assert!(ChatAttachment::find(&db.pool, session.id, attachment.id).await.unwrap().is_none());
+        assert!(!attachment.storage_path(root).exists());
+    }
+}
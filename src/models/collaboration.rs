@@ -3,12 +3,13 @@
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
+use utoipa::ToSchema;
 use uuid::Uuid;
 
-use super::{Entity, UserRole};
+use super::{Entity, SortColumn, SortOrder, Sortable, UserRole};
 
 /// Collaboration session
-#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, ToSchema)]
 pub struct CollaborationSession {
     pub id: Uuid,
     pub project_id: Uuid,
@@ -25,6 +26,14 @@ pub struct CollaborationSession {
     pub ended_at: Option<DateTime<Utc>>,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
+    /// Planned start; others than the host cannot join earlier
+    pub scheduled_start: Option<DateTime<Utc>>,
+    pub scheduled_end: Option<DateTime<Utc>>,
+    pub reminder_sent_at: Option<DateTime<Utc>>,
+    /// Set when the session passed its scheduled end without starting
+    pub cancelled_at: Option<DateTime<Utc>>,
+    /// Identities are hidden from everyone but the host until then
+    pub anonymous_until: Option<DateTime<Utc>>,
 }
 
 impl Entity for CollaborationSession {
@@ -42,7 +51,7 @@ impl Entity for CollaborationSession {
 }
 
 /// Session type
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type, ToSchema)]
 pub enum SessionType {
     #[serde(rename = "realtime")]
     #[sqlx(rename = "realtime")]
@@ -65,7 +74,7 @@ impl Default for SessionType {
 }
 
 /// Session participant
-#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, ToSchema)]
 pub struct SessionParticipant {
     pub id: Uuid,
     pub session_id: Uuid,
@@ -78,6 +87,20 @@ pub struct SessionParticipant {
     pub is_online: bool,
     pub last_seen_at: DateTime<Utc>,
     pub permissions: Option<String>, // JSON field
+    /// File the participant has open, as last persisted
+    pub current_file_id: Option<Uuid>,
+    /// Last operation, cursor move or chat message, as last persisted; the
+    /// socket heartbeat is `last_seen_at`
+    pub last_activity_at: DateTime<Utc>,
+    pub activity: ParticipantActivity,
+    /// Role given back on activity, after the idle policy downgraded the participant
+    pub restore_role: Option<ParticipantRole>,
+    /// Waiting for an edit slot since
+    pub queued_at: Option<DateTime<Utc>>,
+    /// Set in place of the identity while the session is anonymous
+    #[sqlx(skip)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pseudonym: Option<String>,
 }
 
 impl Entity for SessionParticipant {
@@ -95,7 +118,7 @@ impl Entity for SessionParticipant {
 }
 
 /// Participant role in session
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type, ToSchema)]
 pub enum ParticipantRole {
     #[serde(rename = "host")]
     #[sqlx(rename = "host")]
@@ -117,8 +140,23 @@ impl Default for ParticipantRole {
     }
 }
 
+/// Whether a participant is at work: idle past the idle policy's
+/// `downgrade_after_minutes`, away after `away_after_minutes` more
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type, ToSchema)]
+pub enum ParticipantActivity {
+    #[serde(rename = "active")]
+    #[sqlx(rename = "active")]
+    Active,
+    #[serde(rename = "idle")]
+    #[sqlx(rename = "idle")]
+    Idle,
+    #[serde(rename = "away")]
+    #[sqlx(rename = "away")]
+    Away,
+}
+
 /// Session operation/changes
-#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, ToSchema)]
 pub struct SessionOperation {
     pub id: Uuid,
     pub session_id: Uuid,
@@ -152,7 +190,7 @@ impl Entity for SessionOperation {
 }
 
 /// Operation type for collaborative editing
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type, ToSchema)]
 pub enum OperationType {
     #[serde(rename = "insert")]
     #[sqlx(rename = "insert")]
@@ -174,8 +212,15 @@ pub enum OperationType {
     Selection,
 }
 
+impl OperationType {
+    /// Whether the operation changes content, as opposed to moving a cursor or selection
+    pub fn is_edit(self) -> bool {
+        !matches!(self, Self::Cursor | Self::Selection)
+    }
+}
+
 /// Session chat message
-#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, ToSchema)]
 pub struct SessionMessage {
     pub id: Uuid,
     pub session_id: Uuid,
@@ -184,6 +229,8 @@ pub struct SessionMessage {
     pub content: String,
     pub reply_to: Option<Uuid>,
     pub reactions: Option<String>, // JSON field
+    /// Users mentioned as `@username`, resolved when the message was sent or last edited
+    pub mentions: Vec<Uuid>,
     pub edited: bool,
     pub edited_at: Option<DateTime<Utc>>,
     pub deleted: bool,
@@ -206,7 +253,7 @@ impl Entity for SessionMessage {
 }
 
 /// Message type in session chat
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type, ToSchema)]
 pub enum MessageType {
     #[serde(rename = "text")]
     #[sqlx(rename = "text")]
@@ -229,7 +276,7 @@ impl Default for MessageType {
 }
 
 /// Session invitation
-#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, ToSchema)]
 pub struct SessionInvitation {
     pub id: Uuid,
     pub session_id: Uuid,
@@ -291,7 +338,7 @@ impl Entity for SessionRecording {
 }
 
 /// Creation request for collaboration session
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, ToSchema)]
 pub struct CreateCollaborationSession {
     pub title: Option<String>,
     pub description: Option<String>,
@@ -300,10 +347,14 @@ pub struct CreateCollaborationSession {
     pub max_participants: Option<i32>,
     pub password: Option<String>,
     pub settings: Option<String>,
+    /// Schedule the session instead of starting it right away
+    pub scheduled_start: Option<DateTime<Utc>>,
+    /// Requires `scheduled_start`; an unstarted session is cancelled after it
+    pub scheduled_end: Option<DateTime<Utc>>,
 }
 
 /// Update request for collaboration session
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, ToSchema)]
 pub struct UpdateCollaborationSession {
     pub title: Option<String>,
     pub description: Option<String>,
@@ -313,8 +364,63 @@ pub struct UpdateCollaborationSession {
     pub settings: Option<String>,
 }
 
+/// Session status announced when the host changed the settings
+pub const SETTINGS_UPDATED: &str = "settings_updated";
+
+/// Longest idle time a policy may set
+pub const MAX_IDLE_MINUTES: u32 = 24 * 60;
+
+/// Settings of a session the server acts on, read from its `settings` JSON
+///
+/// Other keys of the JSON are left to clients.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SessionSettings {
+    /// Downgrade idle editors; off when missing
+    #[serde(default)]
+    pub idle_policy: Option<IdlePolicy>,
+    /// Users holding the editor role at once; unlimited when missing
+    #[serde(default)]
+    pub max_editors: Option<u32>,
+}
+
+/// When idle participants lose their edit slot
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IdlePolicy {
+    /// Minutes without activity after which an editor becomes a viewer
+    pub downgrade_after_minutes: u32,
+    /// Further minutes after which an idle participant is marked away
+    pub away_after_minutes: u32,
+}
+
+impl SessionSettings {
+    /// Parse and check settings given by a host
+    pub fn validate(settings: &str) -> Result<Self, crate::error::AppError> {
+        let parsed: Self = serde_json::from_str(settings)
+            .map_err(|e| crate::error::AppError::Validation(format!("Invalid session settings: {}", e)))?;
+
+        if let Some(policy) = parsed.idle_policy {
+            for (name, minutes) in [
+                ("downgrade_after_minutes", policy.downgrade_after_minutes),
+                ("away_after_minutes", policy.away_after_minutes),
+            ] {
+                if minutes == 0 || minutes > MAX_IDLE_MINUTES {
+                    return Err(crate::error::AppError::Validation(format!(
+                        "{} must be between 1 and {}",
+                        name, MAX_IDLE_MINUTES
+                    )));
+                }
+            }
+        }
+        if parsed.max_editors == Some(0) {
+            return Err(crate::error::AppError::Validation("max_editors must be at least 1".to_string()));
+        }
+
+        Ok(parsed)
+    }
+}
+
 /// Session statistics
-#[derive(Debug, Clone, Serialize, FromRow)]
+#[derive(Debug, Clone, Serialize, FromRow, ToSchema)]
 pub struct SessionStats {
     pub session_id: Uuid,
     pub total_participants: i64,
@@ -324,9 +430,25 @@ pub struct SessionStats {
     pub duration_minutes: i64,
     pub peak_participants: i64,
     pub files_edited: i64,
+    /// Files any participant focused during the session
+    pub files_viewed: i64,
+    /// Files that were both focused and edited
+    pub files_viewed_and_edited: i64,
     pub total_characters_typed: i64,
 }
 
+/// Orders of `CollaborationSession::list_for_user`, recently updated first
+/// by default; unscheduled sessions come last when sorting by schedule
+pub const SESSION_SORT: Sortable = Sortable {
+    columns: &[
+        SortColumn { name: "updated_at", expression: "cs.updated_at", default_order: SortOrder::Desc },
+        SortColumn { name: "created_at", expression: "cs.created_at", default_order: SortOrder::Desc },
+        SortColumn { name: "scheduled_start", expression: "cs.scheduled_start", default_order: SortOrder::Asc },
+        SortColumn { name: "title", expression: "cs.title", default_order: SortOrder::Asc },
+    ],
+    tiebreaker: "cs.id",
+};
+
 impl CollaborationSession {
     /// Create a new collaboration session
     pub async fn create(
@@ -334,6 +456,15 @@ impl CollaborationSession {
         created_by: Uuid,
         create_session: CreateCollaborationSession,
     ) -> Result<Self, crate::error::AppError> {
+        super::session_schedule::validate_schedule(
+            create_session.scheduled_start,
+            create_session.scheduled_end,
+            Utc::now(),
+        )?;
+        if let Some(settings) = &create_session.settings {
+            SessionSettings::validate(settings)?;
+        }
+
         let password_hash = if let Some(password) = &create_session.password {
             Some(bcrypt::hash(password, bcrypt::DEFAULT_COST)?)
         } else {
@@ -344,8 +475,9 @@ impl CollaborationSession {
             r#"
             INSERT INTO collaboration_sessions (
                 project_id, file_id, created_by, session_type, title, description,
-                is_active, max_participants, password_hash, settings
-            ) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+                is_active, max_participants, password_hash, settings,
+                scheduled_start, scheduled_end
+            ) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12)
             RETURNING *
             "#
         )
@@ -359,6 +491,8 @@ impl CollaborationSession {
         .bind(create_session.max_participants.unwrap_or(10))
         .bind(password_hash)
         .bind(create_session.settings)
+        .bind(create_session.scheduled_start)
+        .bind(create_session.scheduled_end)
         .fetch_one(db)
         .await
         .map_err(crate::error::AppError::Database)?;
@@ -366,13 +500,17 @@ impl CollaborationSession {
         Ok(session)
     }
 
-    /// Find session by ID
+    /// Find session by ID; sessions of deleted projects are not found
     pub async fn find_by_id(
         db: &sqlx::PgPool,
         session_id: Uuid,
     ) -> Result<Option<Self>, crate::error::AppError> {
         let session = sqlx::query_as::<_, CollaborationSession>(
-            "SELECT * FROM collaboration_sessions WHERE id = $1"
+            r#"
+            SELECT cs.* FROM collaboration_sessions cs
+            JOIN projects p ON p.id = cs.project_id
+            WHERE cs.id = $1 AND p.purging_at IS NULL
+            "#
         )
         .bind(session_id)
         .fetch_optional(db)
@@ -382,35 +520,26 @@ impl CollaborationSession {
         Ok(session)
     }
 
-    /// Get session with access control
-    pub async fn find_with_access(
+    /// Whether a file belongs to the session's project and is not deleted
+    pub async fn contains_file(
         db: &sqlx::PgPool,
         session_id: Uuid,
-        user_id: Uuid,
-        password: Option<&str>,
-    ) -> Result<Option<Self>, crate::error::AppError> {
-        let session = Self::find_by_id(db, session_id).await?;
-
-        if let Some(session) = session {
-            // Check if session is active
-            if !session.is_active {
-                return Ok(None);
-            }
-
-            // Check password protection
-            if let (Some(session_password), Some(provided_password)) = (&session.password_hash, password) {
-                if !bcrypt::verify(provided_password, session_password).unwrap_or(false) {
-                    return Ok(None);
-                }
-            } else if session.password_hash.is_some() && password.is_none() {
-                return Ok(None);
-            }
-
-            // TODO: Add additional access control logic
-            Ok(Some(session))
-        } else {
-            Ok(None)
-        }
+        file_id: Uuid,
+    ) -> Result<bool, crate::error::AppError> {
+        sqlx::query_scalar::<_, bool>(
+            r#"
+            SELECT EXISTS (
+                SELECT 1 FROM files f
+                JOIN collaboration_sessions s ON s.project_id = f.project_id
+                WHERE s.id = $1 AND f.id = $2 AND f.is_deleted = false
+            )
+            "#
+        )
+        .bind(session_id)
+        .bind(file_id)
+        .fetch_one(db)
+        .await
+        .map_err(crate::error::AppError::Database)
     }
 
     /// List sessions for a user
@@ -419,15 +548,20 @@ impl CollaborationSession {
         user_id: Uuid,
         params: &super::PaginationParams,
     ) -> Result<Vec<Self>, crate::error::AppError> {
-        let sessions = sqlx::query_as::<_, CollaborationSession>(
+        let order_by = params.order_by(&SESSION_SORT)?;
+        let sessions = sqlx::query_as::<_, CollaborationSession>(&format!(
             r#"
-            SELECT DISTINCT cs.* FROM collaboration_sessions cs
-            LEFT JOIN session_participants sp ON cs.id = sp.session_id
-            WHERE cs.created_by = $1 OR sp.user_id = $1
-            ORDER BY cs.updated_at DESC
+            SELECT cs.* FROM collaboration_sessions cs
+            JOIN projects p ON p.id = cs.project_id
+            WHERE p.purging_at IS NULL AND (cs.created_by = $1 OR EXISTS (
+                SELECT 1 FROM session_participants sp
+                WHERE sp.session_id = cs.id AND sp.user_id = $1
+            ))
+            ORDER BY {}
             LIMIT $2 OFFSET $3
-            "#
-        )
+            "#,
+            order_by
+        ))
         .bind(user_id)
         .bind(params.limit() as i64)
         .bind(params.offset() as i64)
@@ -438,10 +572,39 @@ impl CollaborationSession {
         Ok(sessions)
     }
 
-    /// Start session
+    /// Settings the server acts on; settings it cannot read are the defaults
+    pub fn session_settings(&self) -> SessionSettings {
+        let Some(settings) = self.settings.as_deref() else {
+            return SessionSettings::default();
+        };
+        SessionSettings::validate(settings).unwrap_or_else(|e| {
+            tracing::warn!("Ignoring settings of session {}: {}", self.id, e);
+            SessionSettings::default()
+        })
+    }
+
+    /// Replace the session's settings JSON, once checked
+    pub async fn update_settings(
+        &self,
+        db: &sqlx::PgPool,
+        settings: &str,
+    ) -> Result<Self, crate::error::AppError> {
+        SessionSettings::validate(settings)?;
+
+        sqlx::query_as::<_, CollaborationSession>(
+            "UPDATE collaboration_sessions SET settings = $1, updated_at = NOW() WHERE id = $2 RETURNING *"
+        )
+        .bind(settings)
+        .bind(self.id)
+        .fetch_one(db)
+        .await
+        .map_err(crate::error::AppError::Database)
+    }
+
+    /// Start session; a session that already started keeps its start time
     pub async fn start(&self, db: &sqlx::PgPool) -> Result<(), crate::error::AppError> {
         sqlx::query(
-            "UPDATE collaboration_sessions SET started_at = NOW() WHERE id = $1"
+            "UPDATE collaboration_sessions SET started_at = COALESCE(started_at, NOW()) WHERE id = $1"
         )
         .bind(self.id)
         .execute(db)
@@ -466,13 +629,39 @@ impl CollaborationSession {
 }
 
 impl SessionParticipant {
-    /// Add participant to session
+    /// Add participant to session, unless `max_participants` other users are online
     pub async fn join(
         db: &sqlx::PgPool,
         session_id: Uuid,
         user_id: Uuid,
         role: ParticipantRole,
     ) -> Result<Self, crate::error::AppError> {
+        // The session row is locked so concurrent joins cannot both take the last place
+        let mut tx = db.begin().await.map_err(crate::error::AppError::Database)?;
+        let max_participants: Option<i32> =
+            sqlx::query_scalar("SELECT max_participants FROM collaboration_sessions WHERE id = $1 FOR UPDATE")
+                .bind(session_id)
+                .fetch_optional(&mut *tx)
+                .await
+                .map_err(crate::error::AppError::Database)?;
+        let max_participants = max_participants.ok_or_else(|| crate::error::AppError::NotFound {
+            entity: "CollaborationSession".to_string(),
+            id: session_id.to_string(),
+        })?;
+
+        // A user already online, say in another tab, does not take another place
+        let others_online: i64 = sqlx::query_scalar(
+            "SELECT COUNT(DISTINCT user_id) FROM session_participants WHERE session_id = $1 AND is_online = true AND user_id <> $2"
+        )
+        .bind(session_id)
+        .bind(user_id)
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(crate::error::AppError::Database)?;
+        if others_online >= i64::from(max_participants) {
+            return Err(crate::error::AppError::SessionFull { max_participants });
+        }
+
         let participant = sqlx::query_as::<_, SessionParticipant>(
             r#"
             INSERT INTO session_participants (session_id, user_id, role, is_online, last_seen_at)
@@ -485,9 +674,10 @@ impl SessionParticipant {
         .bind(role as ParticipantRole)
         .bind(true)
         .bind(Utc::now())
-        .fetch_one(db)
+        .fetch_one(&mut *tx)
         .await
         .map_err(crate::error::AppError::Database)?;
+        tx.commit().await.map_err(crate::error::AppError::Database)?;
 
         Ok(participant)
     }
@@ -498,7 +688,7 @@ impl SessionParticipant {
         db: &sqlx::PgPool,
     ) -> Result<(), crate::error::AppError> {
         sqlx::query(
-            "UPDATE session_participants SET is_online = false, left_at = NOW() WHERE id = $1"
+            "UPDATE session_participants SET is_online = false, left_at = NOW(), current_file_id = NULL WHERE id = $1"
         )
         .bind(self.id)
         .execute(db)
@@ -546,6 +736,49 @@ impl SessionParticipant {
         Ok(())
     }
 
+    /// Record the file the participant has open and count the view
+    pub async fn update_focus(
+        db: &sqlx::PgPool,
+        participant_id: Uuid,
+        file_id: Uuid,
+    ) -> Result<(), crate::error::AppError> {
+        let mut tx = db.begin().await.map_err(crate::error::AppError::Database)?;
+
+        let participant = sqlx::query_as::<_, (Uuid, Uuid)>(
+            r#"
+            UPDATE session_participants SET current_file_id = $1, last_seen_at = NOW()
+            WHERE id = $2
+            RETURNING session_id, user_id
+            "#
+        )
+        .bind(file_id)
+        .bind(participant_id)
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(crate::error::AppError::Database)?;
+
+        if let Some((session_id, user_id)) = participant {
+            sqlx::query(
+                r#"
+                INSERT INTO session_file_views (session_id, file_id, user_id)
+                VALUES ($1, $2, $3)
+                ON CONFLICT (session_id, file_id, user_id) DO UPDATE
+                SET focus_count = session_file_views.focus_count + 1, last_focused_at = NOW()
+                "#
+            )
+            .bind(session_id)
+            .bind(file_id)
+            .bind(user_id)
+            .execute(&mut *tx)
+            .await
+            .map_err(crate::error::AppError::Database)?;
+        }
+
+        tx.commit().await.map_err(crate::error::AppError::Database)?;
+
+        Ok(())
+    }
+
     /// Get active participants for session
     pub async fn get_active_participants(
         db: &sqlx::PgPool,
@@ -561,6 +794,58 @@ impl SessionParticipant {
 
         Ok(participants)
     }
+
+    /// Persist a change the idle policy made; `None` once the participant is gone
+    pub async fn record_activity(
+        db: &sqlx::PgPool,
+        change: &super::session_activity::ActivityChange,
+    ) -> Result<Option<Self>, crate::error::AppError> {
+        sqlx::query_as::<_, SessionParticipant>(
+            r#"
+            UPDATE session_participants
+            SET role = $2, restore_role = $3, activity = $4, queued_at = $5,
+                last_activity_at = GREATEST(last_activity_at, $6)
+            WHERE id = $1
+            RETURNING *
+            "#
+        )
+        .bind(change.participant_id)
+        .bind(change.role)
+        .bind(change.restore_role)
+        .bind(change.activity)
+        .bind(change.queued_at)
+        .bind(change.last_activity_at)
+        .fetch_optional(db)
+        .await
+        .map_err(crate::error::AppError::Database)
+    }
+
+    /// Write out activity timestamps kept in memory, in one statement
+    pub async fn flush_activity(
+        db: &sqlx::PgPool,
+        activity: &[(Uuid, DateTime<Utc>)],
+    ) -> Result<(), crate::error::AppError> {
+        if activity.is_empty() {
+            return Ok(());
+        }
+        let (ids, timestamps): (Vec<Uuid>, Vec<DateTime<Utc>>) = activity.iter().copied().unzip();
+
+        sqlx::query(
+            r#"
+            UPDATE session_participants sp
+            SET last_activity_at = GREATEST(sp.last_activity_at, a.last_activity_at)
+            FROM UNNEST($1::uuid[], $2::timestamptz[]) AS a(id, last_activity_at)
+            WHERE sp.id = a.id
+            "#
+        )
+        .bind(ids)
+        .bind(timestamps)
+        .execute(db)
+        .await
+        .map_err(crate::error::AppError::Database)?;
+
+        Ok(())
+    }
 }
 
 impl SessionOperation {
@@ -575,6 +860,10 @@ impl SessionOperation {
         position: Option<i32>,
         content: Option<String>,
     ) -> Result<Self, crate::error::AppError> {
+        if operation_type.is_edit() {
+            super::project_freeze::ProjectFreeze::ensure_session_writable(db, session_id).await?;
+        }
+
         let operation = sqlx::query_as::<_, SessionOperation>(
             r#"
             INSERT INTO session_operations (
@@ -631,6 +920,103 @@ impl SessionOperation {
     }
 }
 
+impl SessionInvitation {
+    /// Store an invitation; it stays valid for a day, or until a scheduled
+    /// session is over when that is later
+    pub async fn create(
+        db: &sqlx::PgPool,
+        session: &CollaborationSession,
+        invited_by: Uuid,
+        invited_user: Option<Uuid>,
+        email: Option<&str>,
+        role: ParticipantRole,
+        message: Option<String>,
+    ) -> Result<Self, crate::error::AppError> {
+        use crate::models::auth::PasswordUtils;
+
+        let mut expires_at = Utc::now() + chrono::Duration::hours(24);
+        if let Some(until) = session.scheduled_end.or(session.scheduled_start) {
+            expires_at = expires_at.max(until);
+        }
+
+        let invitation = sqlx::query_as::<_, SessionInvitation>(
+            r#"
+            INSERT INTO session_invitations (session_id, invited_by, invited_user, email, role, message, token, expires_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+            RETURNING *
+            "#
+        )
+        .bind(session.id)
+        .bind(invited_by)
+        .bind(invited_user)
+        .bind(email.map(|email| email.trim().to_lowercase()))
+        .bind(role as ParticipantRole)
+        .bind(message)
+        .bind(PasswordUtils::generate_invitation_token())
+        .bind(expires_at)
+        .fetch_one(db)
+        .await
+        .map_err(crate::error::AppError::Database)?;
+
+        Ok(invitation)
+    }
+
+    /// Find invitation by token
+    pub async fn find_by_token(
+        db: &sqlx::PgPool,
+        token: &str,
+    ) -> Result<Option<Self>, crate::error::AppError> {
+        sqlx::query_as::<_, SessionInvitation>("SELECT * FROM session_invitations WHERE token = $1")
+            .bind(token)
+            .fetch_optional(db)
+            .await
+            .map_err(crate::error::AppError::Database)
+    }
+
+    /// Accept the invitation as `user`, who must be the invited user or own the invited email
+    pub async fn accept(
+        &self,
+        db: &sqlx::PgPool,
+        user: &super::user::User,
+    ) -> Result<Self, crate::error::AppError> {
+        use crate::error::AppError;
+
+        let addressed_to_user = match (self.invited_user, &self.email) {
+            (Some(invited_user), _) => invited_user == user.id,
+            (None, Some(email)) => email.eq_ignore_ascii_case(&user.email),
+            (None, None) => false,
+        };
+        if !addressed_to_user {
+            return Err(AppError::Authorization("This invitation is for someone else".to_string()));
+        }
+        if self.declined {
+            return Err(AppError::Conflict("Invitation was declined".to_string()));
+        }
+        if self.accepted {
+            return Ok(self.clone());
+        }
+        if self.expires_at <= Utc::now() {
+            return Err(AppError::Validation("Invitation has expired".to_string()));
+        }
+
+        let invitation = sqlx::query_as::<_, SessionInvitation>(
+            r#"
+            UPDATE session_invitations
+            SET accepted = true, accepted_at = NOW(), invited_user = $2
+            WHERE id = $1
+            RETURNING *
+            "#
+        )
+        .bind(self.id)
+        .bind(user.id)
+        .fetch_one(db)
+        .await
+        .map_err(AppError::Database)?;
+
+        Ok(invitation)
+    }
+}
+
 impl SessionStats {
     /// Get session statistics
     pub async fn get(
@@ -653,13 +1039,39 @@ impl SessionStats {
                 ) pc ON sp.session_id = pc.session_id
                 WHERE sp.session_id = $1
             ),
+            -- Compacted sessions are counted from their summaries, since the
+            -- raw operations may already be pruned
+            edits AS (
+                SELECT
+                    file_id,
+                    COUNT(*) as operation_count,
+                    COALESCE(SUM(LENGTH(content)), 0) as characters_typed
+                FROM session_operations
+                WHERE session_id = $1 AND applied = true AND NOT EXISTS (
+                    SELECT 1 FROM collaboration_sessions
+                    WHERE id = $1 AND compacted_at IS NOT NULL
+                )
+                GROUP BY file_id
+                UNION ALL
+                SELECT file_id, operation_count, characters_typed
+                FROM session_summaries
+                WHERE session_id = $1
+            ),
             operation_stats AS (
                 SELECT
-                    COUNT(*) as total_operations,
-                    COALESCE(SUM(LENGTH(content)), 0) as total_characters_typed,
+                    COALESCE(SUM(operation_count), 0)::bigint as total_operations,
+                    COALESCE(SUM(characters_typed), 0)::bigint as total_characters_typed,
                     COUNT(DISTINCT file_id) as files_edited
-                FROM session_operations
-                WHERE session_id = $1 AND applied = true
+                FROM edits
+            ),
+            focus_stats AS (
+                SELECT
+                    COUNT(DISTINCT v.file_id) as files_viewed,
+                    COUNT(DISTINCT v.file_id) FILTER (
+                        WHERE v.file_id IN (SELECT file_id FROM edits)
+                    ) as files_viewed_and_edited
+                FROM session_file_views v
+                WHERE v.session_id = $1
             ),
             message_stats AS (
                 SELECT COUNT(*) as total_messages
@@ -681,9 +1093,12 @@ impl SessionStats {
                 COALESCE(si.duration_minutes, 0)::bigint as duration_minutes,
                 COALESCE(ps.peak_participants, 0) as peak_participants,
                 COALESCE(os.files_edited, 0) as files_edited,
+                COALESCE(fs.files_viewed, 0) as files_viewed,
+                COALESCE(fs.files_viewed_and_edited, 0) as files_viewed_and_edited,
                 COALESCE(os.total_characters_typed, 0) as total_characters_typed
             FROM participant_stats ps
             CROSS JOIN operation_stats os
+            CROSS JOIN focus_stats fs
             CROSS JOIN message_stats ms
             CROSS JOIN session_info si
             "#
@@ -700,6 +1115,7 @@ impl SessionStats {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::testing::{assert_pages_are_stable, create_test_project, create_test_session, create_test_user, TestDb};
 
     #[test]
     fn test_session_type_default() {
@@ -715,4 +1131,26 @@ mod tests {
     fn test_message_type_default() {
         assert_eq!(MessageType::default(), MessageType::Text);
     }
-}
\ No newline at end of file
+
+    #[tokio::test]
+    async fn test_list_pages_are_stable_under_every_sort() {
+        let Some(db) = TestDb::start().await else { return };
+        let owner = create_test_user(&db.pool).await;
+        let project = create_test_project(&db.pool, &owner, false).await;
+        for _ in 0..5 {
+            create_test_session(&db.pool, &project, &owner).await;
+        }
+        sqlx::query("UPDATE collaboration_sessions SET created_at = NOW(), updated_at = NOW() WHERE project_id = $1")
+            .bind(project.id)
+            .execute(&db.pool)
+            .await
+            .unwrap();
+
+        let pool = &db.pool;
+        assert_pages_are_stable(&SESSION_SORT, move |params| async move {
+            let sessions = CollaborationSession::list_for_user(pool, owner.id, &params).await.unwrap();
+            sessions.into_iter().map(|session| session.id).collect()
+        })
+        .await;
+    }
+}
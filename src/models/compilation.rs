@@ -3,12 +3,22 @@
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
+use utoipa::ToSchema;
 use uuid::Uuid;
 
-use super::{CompilationStatus, Entity, LatexEngine};
+use super::{CompilationStatus, Entity, LatexEngine, SortColumn, SortOrder, Sortable};
+use super::compile_environment::{collect_recorded_packages, CompileEnvironment};
+use super::compile_preflight::CompilePreflight;
+use super::compile_images::ImageOptimizationReport;
+use super::compile_thumbnails::ThumbnailReport;
+use super::project_template::{validate_declarations, TemplateVariable};
+use crate::performance::QueryParam;
+
+/// Directory the working directories of compile jobs are created in, one per project
+pub const PROJECT_WORKING_DIRECTORY_ROOT: &str = "/tmp/texler/projects";
 
 /// Compilation job
-#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, ToSchema)]
 pub struct CompilationJob {
     pub id: Uuid,
     pub project_id: Uuid,
@@ -31,6 +41,31 @@ pub struct CompilationJob {
     pub log_file_path: Option<String>,
     pub artifacts_created: i32,
     pub output_size_bytes: i64,
+    /// TeX environment recorded while the job ran, see `CompileEnvironment`
+    #[schema(value_type = Option<CompileEnvironment>)]
+    pub environment: Option<serde_json::Value>,
+    /// Request that created the job; its logs and traces carry the same ID
+    pub request_id: Option<Uuid>,
+    /// Preflight findings the job was started despite, with `ignore_preflight`
+    #[schema(value_type = Option<CompilePreflight>)]
+    pub preflight: Option<serde_json::Value>,
+    /// A build from scratch was requested
+    pub clean: bool,
+    /// The result came from a build on the sources and auxiliary files left
+    /// by the project's previous compile
+    pub incremental: bool,
+    /// Sources written to the build directory for the run reported, once it ran
+    pub files_refreshed: Option<i32>,
+    /// The incremental build failed and the job was built again from scratch
+    pub retried_clean: bool,
+    /// Images transcoded for the compile, when the project optimizes them
+    #[schema(value_type = Option<ImageOptimizationReport>)]
+    pub image_optimization: Option<serde_json::Value>,
+    /// Bulk compile the job was queued by, see `CompileBatch`
+    pub batch_id: Option<Uuid>,
+    /// Page thumbnails rendered from the job's PDF, once it succeeded
+    #[schema(value_type = Option<ThumbnailReport>)]
+    pub thumbnails: Option<serde_json::Value>,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
 }
@@ -79,7 +114,7 @@ impl Entity for CompilationQueue {
 }
 
 /// Queue priority
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type, ToSchema)]
 pub enum QueuePriority {
     #[serde(rename = "low")]
     #[sqlx(rename = "low")]
@@ -157,7 +192,7 @@ impl Default for WorkerStatus {
 }
 
 /// Compilation template
-#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, ToSchema)]
 pub struct CompilationTemplate {
     pub id: Uuid,
     pub name: String,
@@ -171,6 +206,18 @@ pub struct CompilationTemplate {
     pub created_by: Uuid,
     pub usage_count: i64,
     pub success_rate: f64, // 0.0 to 1.0
+    /// Officially maintained; only administrators set this
+    pub verified: bool,
+    pub rating_count: i64,
+    /// 0 until rated, otherwise 1.0 to 5.0
+    pub rating_average: f64,
+    /// Template this one was forked from, if it still exists
+    pub forked_from: Option<Uuid>,
+    /// Project whose files new projects from this template start with
+    pub source_project_id: Option<Uuid>,
+    /// Placeholders in the starter files, for the form to create a project
+    #[schema(value_type = Vec<TemplateVariable>)]
+    pub variables: sqlx::types::Json<Vec<TemplateVariable>>,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
 }
@@ -220,7 +267,7 @@ impl Entity for CompilationArtifact {
 }
 
 /// Artifact type
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type, ToSchema)]
 pub enum ArtifactType {
     #[serde(rename = "pdf")]
     #[sqlx(rename = "pdf")]
@@ -240,6 +287,10 @@ pub enum ArtifactType {
     #[serde(rename = "bbl")]
     #[sqlx(rename = "bbl")]
     Bbl,
+    /// WebP preview of a page of the primary PDF
+    #[serde(rename = "thumbnail")]
+    #[sqlx(rename = "thumbnail")]
+    Thumbnail,
     #[serde(rename = "other")]
     #[sqlx(rename = "other")]
     Other,
@@ -257,7 +308,7 @@ struct CompilationStatsRow {
 }
 
 /// Compilation statistics
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, ToSchema)]
 pub struct CompilationStats {
     pub period_start: DateTime<Utc>,
     pub period_end: DateTime<Utc>,
@@ -274,7 +325,7 @@ pub struct CompilationStats {
 }
 
 /// Engine-specific statistics
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, ToSchema)]
 pub struct EngineStats {
     pub engine: LatexEngine,
     pub job_count: i64,
@@ -283,14 +334,14 @@ pub struct EngineStats {
 }
 
 /// Status-specific statistics
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, ToSchema)]
 pub struct StatusStats {
     pub status: CompilationStatus,
     pub count: i64,
 }
 
 /// Error message statistics
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, ToSchema)]
 pub struct ErrorStats {
     pub error_message: String,
     pub count: i64,
@@ -305,10 +356,14 @@ pub struct CreateCompilationJob {
     pub args: Option<Vec<String>>,
     pub priority: Option<QueuePriority>,
     pub template_id: Option<Uuid>,
+    #[serde(default)]
+    pub clean: bool,
+    #[serde(default)]
+    pub batch_id: Option<Uuid>,
 }
 
 /// Request for creating a compilation template
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, ToSchema)]
 pub struct CreateCompilationTemplate {
     pub name: String,
     pub description: Option<String>,
@@ -318,38 +373,67 @@ pub struct CreateCompilationTemplate {
     pub required_files: Option<Vec<String>>,
     pub output_patterns: Option<Vec<String>>,
     pub is_public: Option<bool>,
+    /// Project of the caller whose files new projects start with
+    pub source_project_id: Option<Uuid>,
+    /// Placeholders in the starter files
+    pub variables: Option<Vec<TemplateVariable>>,
 }
 
+/// Orders of `CompilationJob::list_for_user`, newest first by default;
+/// unfinished jobs come last when sorting by completion or duration
+pub const JOB_SORT: Sortable = Sortable {
+    columns: &[
+        SortColumn { name: "created_at", expression: "cj.created_at", default_order: SortOrder::Desc },
+        SortColumn { name: "updated_at", expression: "cj.updated_at", default_order: SortOrder::Desc },
+        SortColumn { name: "completed_at", expression: "cj.completed_at", default_order: SortOrder::Desc },
+        SortColumn { name: "duration_ms", expression: "cj.duration_ms", default_order: SortOrder::Desc },
+    ],
+    tiebreaker: "cj.id",
+};
+
 impl CompilationJob {
-    /// Create a new compilation job
-    pub async fn create(
-        db: &sqlx::PgPool,
+    /// Create a new compilation job compiling `target`.
+    ///
+    /// The target's extension decides the toolchain, see `typst`; Typst jobs
+    /// ignore the requested arguments. The job and its queue entry are
+    /// inserted together, the job stamped with the current request's ID.
+    pub async fn create<'a>(
+        db: impl sqlx::Acquire<'a, Database = sqlx::Postgres>,
         project_id: Uuid,
         user_id: Uuid,
         create_job: CreateCompilationJob,
         engine: LatexEngine,
+        target: &str,
         working_directory: String,
         input_files: Vec<String>,
     ) -> Result<Self, crate::error::AppError> {
-        let command = match engine {
-            LatexEngine::Pdflatex => "pdflatex".to_string(),
-            LatexEngine::Xelatex => "xelatex".to_string(),
-            LatexEngine::Lualatex => "lualatex".to_string(),
-        };
+        let engine = engine.for_target(target);
+        let command = engine.command().to_string();
 
-        let args = create_job.args.unwrap_or_else(|| vec![
-            "-interaction=nonstopmode".to_string(),
-            "-file-line-error".to_string(),
-            "-synctex=1".to_string(),
-            "-output-directory=output".to_string(),
-        ]);
+        let args = if engine.is_typst() {
+            super::typst::compile_args(target)
+        } else {
+            let mut args = create_job.args.unwrap_or_else(|| vec![
+                "-interaction=nonstopmode".to_string(),
+                "-file-line-error".to_string(),
+                "-synctex=1".to_string(),
+                "-output-directory=output".to_string(),
+            ]);
+
+            // The .fls recorder output is how the package versions get recorded
+            if !args.iter().any(|arg| arg == "-recorder") {
+                args.push("-recorder".to_string());
+            }
+            args
+        };
 
+        let mut tx = db.begin().await.map_err(crate::error::AppError::Database)?;
         let job = sqlx::query_as::<_, CompilationJob>(
             r#"
             INSERT INTO compilation_jobs (
                 project_id, user_id, file_id, engine, command, args,
-                working_directory, input_files, status, created_at, updated_at
-            ) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)
+                working_directory, input_files, status, created_at, updated_at, request_id, clean, batch_id
+            ) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14)
             RETURNING *
             "#
         )
@@ -364,50 +448,127 @@ impl CompilationJob {
         .bind(CompilationStatus::Pending as CompilationStatus)
         .bind(Utc::now())
         .bind(Utc::now())
-        .fetch_one(db)
+        .bind(crate::correlation::current_request_id().map(|id| id.0))
+        .bind(create_job.clean)
+        .bind(create_job.batch_id)
+        .fetch_one(&mut *tx)
         .await
         .map_err(crate::error::AppError::Database)?;
+        super::fail_point::check("compilation_job_create")?;
 
         // Add to compilation queue
-        CompilationQueue::enqueue(db, job.id, create_job.priority.unwrap_or_default()).await?;
+        CompilationQueue::enqueue(&mut *tx, job.id, create_job.priority.unwrap_or_default()).await?;
 
+        tx.commit().await.map_err(crate::error::AppError::Database)?;
         Ok(job)
     }
 
+    /// Keep the preflight findings a job was forced past
+    pub async fn record_preflight<'a>(
+        &mut self,
+        db: impl sqlx::Acquire<'a, Database = sqlx::Postgres>,
+        preflight: &CompilePreflight,
+    ) -> Result<(), crate::error::AppError> {
+        let preflight = serde_json::to_value(preflight)?;
+        let mut conn = db.acquire().await.map_err(crate::error::AppError::Database)?;
+        sqlx::query("UPDATE compilation_jobs SET preflight = $1 WHERE id = $2")
+            .bind(&preflight)
+            .bind(self.id)
+            .execute(&mut *conn)
+            .await
+            .map_err(crate::error::AppError::Database)?;
+
+        self.preflight = Some(preflight);
+        Ok(())
+    }
+
+    /// Preflight findings the job was forced past, if any
+    pub fn ignored_preflight(&self) -> Option<CompilePreflight> {
+        self.preflight.clone().and_then(|preflight| serde_json::from_value(preflight).ok())
+    }
+
+    /// Path of the file a job compiles: `file_id` when given, else the project's main file
+    pub async fn target_path(
+        db: &sqlx::PgPool,
+        project: &super::project::Project,
+        file_id: Option<Uuid>,
+        user_id: Uuid,
+    ) -> Result<String, crate::error::AppError> {
+        let Some(file_id) = file_id else {
+            return Ok(project.main_file_path.clone());
+        };
+
+        super::file::File::find_by_id(db, file_id, user_id)
+            .await?
+            .filter(|file| file.project_id == project.id)
+            .map(|file| file.path)
+            .ok_or_else(|| crate::error::AppError::NotFound {
+                entity: "File".to_string(),
+                id: file_id.to_string(),
+            })
+    }
+
     /// Find compilation job by ID
     pub async fn find_by_id(
         db: &sqlx::PgPool,
         job_id: Uuid,
         user_id: Uuid,
     ) -> Result<Option<Self>, crate::error::AppError> {
-        let job = sqlx::query_as::<_, CompilationJob>(
-            r#"
-            SELECT cj.* FROM compilation_jobs cj
-            JOIN projects p ON cj.project_id = p.id
-            WHERE cj.id = $1 AND (
-                cj.user_id = $2 OR
-                p.owner_id = $2 OR
-                p.id IN (
-                    SELECT project_id FROM project_collaborators
-                    WHERE user_id = $2
-                ) OR
-                p.is_public = true
+        let job = crate::performance::timed(
+            "job.find_by_id",
+            &[QueryParam::of(&job_id), QueryParam::of(&user_id)],
+            sqlx::query_as::<_, CompilationJob>(
+                r#"
+                SELECT cj.* FROM compilation_jobs cj
+                JOIN projects p ON cj.project_id = p.id
+                WHERE cj.id = $1 AND p.purging_at IS NULL AND (
+                    cj.user_id = $2 OR
+                    p.owner_id = $2 OR
+                    p.id IN (
+                        SELECT project_id FROM project_collaborators
+                        WHERE user_id = $2
+                    ) OR
+                    p.is_public = true
+                )
+                "#
             )
-            "#
+            .bind(job_id)
+            .bind(user_id)
+            .fetch_optional(db),
         )
-        .bind(job_id)
-        .bind(user_id)
-        .fetch_optional(db)
         .await
         .map_err(crate::error::AppError::Database)?;
 
         Ok(job)
     }
 
+    /// The project's most recently completed successful job, standalone
+    /// compiles of shared files aside
+    ///
+    /// Access is not checked; callers decide who may see the project.
+    pub async fn latest_success(
+        db: &sqlx::PgPool,
+        project_id: Uuid,
+    ) -> Result<Option<Self>, crate::error::AppError> {
+        sqlx::query_as::<_, CompilationJob>(
+            r#"
+            SELECT * FROM compilation_jobs
+            WHERE project_id = $1 AND status = 'success'
+                AND NOT EXISTS (SELECT 1 FROM file_share_renders r WHERE r.job_id = compilation_jobs.id)
+            ORDER BY completed_at DESC NULLS LAST, created_at DESC
+            LIMIT 1
+            "#
+        )
+        .bind(project_id)
+        .fetch_optional(db)
+        .await
+        .map_err(crate::error::AppError::Database)
+    }
+
     /// Update job status
-    pub async fn update_status(
+    pub async fn update_status<'a>(
         &self,
-        db: &sqlx::PgPool,
+        db: impl sqlx::Acquire<'a, Database = sqlx::Postgres>,
         status: CompilationStatus,
         error_message: Option<String>,
     ) -> Result<(), crate::error::AppError> {
@@ -424,6 +585,7 @@ impl CompilationJob {
             _ => (None, None),
         };
 
+        let mut tx = db.begin().await.map_err(crate::error::AppError::Database)?;
         sqlx::query(
             r#"
             UPDATE compilation_jobs
@@ -437,7 +599,7 @@ impl CompilationJob {
         .bind(duration_ms)
         .bind(Utc::now())
         .bind(self.id)
-        .execute(db)
+        .execute(&mut *tx)
         .await
         .map_err(crate::error::AppError::Database)?;
 
@@ -449,51 +611,79 @@ impl CompilationJob {
             .bind(status as CompilationStatus)
             .bind(Utc::now())
             .bind(self.project_id)
-            .execute(db)
+            .execute(&mut *tx)
             .await
             .map_err(crate::error::AppError::Database)?;
         }
 
+        tx.commit().await.map_err(crate::error::AppError::Database)?;
         Ok(())
     }
 
     /// Start the compilation job
+    ///
+    /// Returns false, changing nothing, when the job is no longer pending,
+    /// e.g. because it was cancelled after it was dequeued.
+    #[tracing::instrument(
+        name = "compile_job",
+        skip_all,
+        fields(job_id = %self.id, project_id = %self.project_id, request_id = self.request_id.map(tracing::field::display))
+    )]
     pub async fn start(
         &self,
         db: &sqlx::PgPool,
         worker_id: Option<String>,
-    ) -> Result<(), crate::error::AppError> {
-        sqlx::query(
-            "UPDATE compilation_jobs SET status = $1, started_at = $2, updated_at = $3 WHERE id = $4"
+    ) -> Result<bool, crate::error::AppError> {
+        let environment = CompileEnvironment::capture(self.engine).await;
+
+        let mut tx = db.begin().await.map_err(crate::error::AppError::Database)?;
+        let started = sqlx::query(
+            r#"
+            UPDATE compilation_jobs SET status = $1, started_at = $2, environment = $3, updated_at = $4
+            WHERE id = $5 AND status = 'pending'
+            "#
         )
         .bind(CompilationStatus::Running as CompilationStatus)
         .bind(Utc::now())
+        .bind(serde_json::to_value(&environment).unwrap_or_default())
         .bind(Utc::now())
         .bind(self.id)
-        .execute(db)
+        .execute(&mut *tx)
         .await
         .map_err(crate::error::AppError::Database)?;
+        if started.rows_affected() == 0 {
+            return Ok(false);
+        }
 
         // Update queue
-        if let Some(queue_id) = self.get_queue_id(db).await? {
-            sqlx::query(
-                "UPDATE compilation_queue SET started_at = $1, worker_id = $2 WHERE id = $3"
-            )
-            .bind(Utc::now())
-            .bind(worker_id)
-            .bind(queue_id)
-            .execute(db)
-            .await
-            .map_err(crate::error::AppError::Database)?;
-        }
+        sqlx::query(
+            "UPDATE compilation_queue SET started_at = $1, worker_id = $2 WHERE job_id = $3"
+        )
+        .bind(Utc::now())
+        .bind(worker_id)
+        .bind(self.id)
+        .execute(&mut *tx)
+        .await
+        .map_err(crate::error::AppError::Database)?;
 
-        Ok(())
+        tx.commit().await.map_err(crate::error::AppError::Database)?;
+        Ok(true)
     }
 
-    /// Complete the compilation job
+    /// Complete the compilation job and notify its creator
+    ///
+    /// A job cancelled meanwhile stays cancelled; only its queue entry is
+    /// removed.
+    #[tracing::instrument(
+        name = "compile_job",
+        skip_all,
+        fields(job_id = %self.id, project_id = %self.project_id, request_id = self.request_id.map(tracing::field::display))
+    )]
     pub async fn complete(
         &self,
         db: &sqlx::PgPool,
+        notifier: &super::compile_notification::CompileNotifier,
+        keyring: &super::project_secret::SecretKeyring,
         exit_code: i32,
         stdout: String,
         stderr: String,
@@ -514,13 +704,28 @@ impl CompilationJob {
             None
         };
 
-        sqlx::query(
+        // Recorder files only feed the environment record, never the artifacts
+        let packages = collect_recorded_packages(&self.working_directory).await;
+        let output_files: Vec<String> = output_files
+            .into_iter()
+            .filter(|file| !file.ends_with(".fls"))
+            .collect();
+
+        // Engines echo what they read, secrets included; none is ever stored
+        let secrets = super::project_secret::CompileSecrets::load(db, keyring, self.project_id).await?;
+        let stdout = secrets.redact(&stdout);
+        let stderr = secrets.redact(&stderr);
+
+        // The job, its queue entry and the project status change together
+        let mut tx = db.begin().await.map_err(crate::error::AppError::Database)?;
+        let completed = sqlx::query(
             r#"
             UPDATE compilation_jobs
             SET status = $1, completed_at = $2, duration_ms = $3, exit_code = $4,
                 stdout = $5, stderr = $6, output_files = $7, artifacts_created = $8,
-                output_size_bytes = $9, updated_at = $10
-            WHERE id = $11
+                output_size_bytes = $9, updated_at = $10,
+                environment = jsonb_set(COALESCE(environment, '{}'::jsonb), '{packages}', $12)
+            WHERE id = $11 AND status IN ('pending', 'running')
             "#
         )
         .bind(status as CompilationStatus)
@@ -534,19 +739,27 @@ impl CompilationJob {
         .bind(output_size_bytes)
         .bind(Utc::now())
         .bind(self.id)
-        .execute(db)
+        .bind(serde_json::to_value(&packages).unwrap_or_default())
+        .execute(&mut *tx)
         .await
         .map_err(crate::error::AppError::Database)?;
+        super::fail_point::check("compilation_job_complete")?;
 
         // Remove from queue
         sqlx::query(
             "DELETE FROM compilation_queue WHERE job_id = $1"
         )
         .bind(self.id)
-        .execute(db)
+        .execute(&mut *tx)
         .await
         .map_err(crate::error::AppError::Database)?;
 
+        if completed.rows_affected() == 0 {
+            tx.commit().await.map_err(crate::error::AppError::Database)?;
+            tracing::info!("Job {} finished after it was cancelled; keeping it cancelled", self.id);
+            return Ok(());
+        }
+
         // Update project compilation status
         sqlx::query(
             "UPDATE projects SET compilation_status = $1, last_compilation_at = $2 WHERE id = $3"
@@ -554,24 +767,95 @@ impl CompilationJob {
         .bind(status as CompilationStatus)
         .bind(Utc::now())
         .bind(self.project_id)
-        .execute(db)
+        .execute(&mut *tx)
         .await
         .map_err(crate::error::AppError::Database)?;
+        tx.commit().await.map_err(crate::error::AppError::Database)?;
+
+        if status == CompilationStatus::Success {
+            super::onboarding::record(db, self.user_id, super::onboarding::Milestone::SuccessfulCompile);
+        }
+
+        // A failed notification must not fail the job
+        if let Some(job) = Self::find_by_id(db, self.id, self.user_id).await? {
+            if let Err(e) = notifier.job_finished(db, &job).await {
+                tracing::warn!("Failed to notify user {} about job {}: {}", self.user_id, self.id, e);
+            }
+        }
+        if let Some(batch_id) = self.batch_id {
+            if let Err(e) = super::compile_batch::CompileBatch::member_finished(db, notifier, batch_id).await {
+                tracing::warn!("Failed to report batch {} of job {}: {}", batch_id, self.id, e);
+            }
+        }
 
         Ok(())
     }
 
-    /// Get queue ID for this job
-    async fn get_queue_id(&self, db: &sqlx::PgPool) -> Result<Option<Uuid>, crate::error::AppError> {
-        let queue_id = sqlx::query_scalar::<_, Uuid>(
-            "SELECT id FROM compilation_queue WHERE job_id = $1"
+    /// Cancel the job unless it finished already; returns the cancelled job
+    ///
+    /// Compare-and-set on the status, so a job is cancelled at most once and a
+    /// result written just before is never overwritten. A job still waiting
+    /// leaves the queue; the run of a started one releases its entry when it
+    /// stops.
+    pub async fn cancel(
+        &self,
+        db: &sqlx::PgPool,
+        error_message: &str,
+    ) -> Result<Option<Self>, crate::error::AppError> {
+        let mut tx = db.begin().await.map_err(crate::error::AppError::Database)?;
+        let job = sqlx::query_as::<_, CompilationJob>(
+            r#"
+            UPDATE compilation_jobs
+            SET status = 'cancelled', error_message = $2, completed_at = NOW(), updated_at = NOW(),
+                duration_ms = (EXTRACT(EPOCH FROM NOW() - started_at) * 1000)::BIGINT
+            WHERE id = $1 AND status IN ('pending', 'running')
+            RETURNING *
+            "#
         )
         .bind(self.id)
-        .fetch_optional(db)
+        .bind(error_message)
+        .fetch_optional(&mut *tx)
         .await
         .map_err(crate::error::AppError::Database)?;
 
-        Ok(queue_id.and_then(|id| Some(id)))
+        if job.is_some() {
+            sqlx::query("DELETE FROM compilation_queue WHERE job_id = $1 AND started_at IS NULL")
+                .bind(self.id)
+                .execute(&mut *tx)
+                .await
+                .map_err(crate::error::AppError::Database)?;
+        }
+        tx.commit().await.map_err(crate::error::AppError::Database)?;
+        Ok(job)
+    }
+
+    /// Keep what a cancelled run printed and release its queue entry
+    pub async fn record_cancelled_output(
+        &self,
+        db: &sqlx::PgPool,
+        keyring: &super::project_secret::SecretKeyring,
+        stdout: &str,
+        stderr: &str,
+    ) -> Result<(), crate::error::AppError> {
+        let secrets = super::project_secret::CompileSecrets::load(db, keyring, self.project_id).await?;
+
+        let mut tx = db.begin().await.map_err(crate::error::AppError::Database)?;
+        sqlx::query(
+            "UPDATE compilation_jobs SET stdout = $2, stderr = $3, updated_at = NOW() WHERE id = $1 AND status = 'cancelled'"
+        )
+        .bind(self.id)
+        .bind(secrets.redact(stdout))
+        .bind(secrets.redact(stderr))
+        .execute(&mut *tx)
+        .await
+        .map_err(crate::error::AppError::Database)?;
+        sqlx::query("DELETE FROM compilation_queue WHERE job_id = $1")
+            .bind(self.id)
+            .execute(&mut *tx)
+            .await
+            .map_err(crate::error::AppError::Database)?;
+        tx.commit().await.map_err(crate::error::AppError::Database)?;
+        Ok(())
     }
 
     /// List jobs for a user
@@ -580,17 +864,19 @@ impl CompilationJob {
         user_id: Uuid,
         params: &super::PaginationParams,
     ) -> Result<Vec<Self>, crate::error::AppError> {
-        let jobs = sqlx::query_as::<_, CompilationJob>(
+        let order_by = params.order_by(&JOB_SORT)?;
+        let jobs = sqlx::query_as::<_, CompilationJob>(&format!(
             r#"
             SELECT cj.* FROM compilation_jobs cj
             JOIN projects p ON cj.project_id = p.id
-            WHERE cj.user_id = $1 OR p.owner_id = $1 OR p.id IN (
+            WHERE p.purging_at IS NULL AND (cj.user_id = $1 OR p.owner_id = $1 OR p.id IN (
                 SELECT project_id FROM project_collaborators WHERE user_id = $1
-            )
-            ORDER BY cj.created_at DESC
+            ))
+            ORDER BY {}
             LIMIT $2 OFFSET $3
-            "#
-        )
+            "#,
+            order_by
+        ))
         .bind(user_id)
         .bind(params.limit() as i64)
         .bind(params.offset() as i64)
@@ -602,32 +888,35 @@ impl CompilationJob {
     }
 }
 
+/// Refuse engines this instance does not offer (`LATEX_ENGINES`)
+pub fn check_engine_enabled(config: &crate::config::LatexConfig, engine: LatexEngine) -> Result<(), crate::error::AppError> {
+    if !config.engines.iter().any(|name| name == engine.command()) {
+        return Err(crate::error::AppError::Validation(format!(
+            "The {} engine is not available on this instance",
+            engine.command()
+        )));
+    }
+    Ok(())
+}
+
 impl CompilationQueue {
     /// Add job to compilation queue
     pub async fn enqueue(
-        db: &sqlx::PgPool,
+        db: impl sqlx::PgExecutor<'_>,
         job_id: Uuid,
         priority: QueuePriority,
     ) -> Result<Self, crate::error::AppError> {
-        // Get the next queue position for this priority
-        let queue_position = sqlx::query_scalar::<_, i64>(
-            "SELECT COALESCE(MAX(queue_position), 0) + 1 FROM compilation_queue WHERE priority = $1"
-        )
-        .bind(priority as QueuePriority)
-        .fetch_one(db)
-        .await
-        .map_err(crate::error::AppError::Database)?;
-
+        // Placed after the last job of the same priority
         let queue_item = sqlx::query_as::<_, CompilationQueue>(
             r#"
             INSERT INTO compilation_queue (job_id, priority, queue_position, queued_at, retry_count, max_retries)
-            VALUES ($1, $2, $3, $4, $5, $6)
+            SELECT $1, $2, COALESCE(MAX(queue_position), 0) + 1, $3, $4, $5
+            FROM compilation_queue WHERE priority = $2
             RETURNING *
             "#
         )
         .bind(job_id)
         .bind(priority as QueuePriority)
-        .bind(queue_position)
         .bind(Utc::now())
         .bind(0)
         .bind(3)
@@ -639,16 +928,29 @@ impl CompilationQueue {
     }
 
     /// Get next job from queue
+    ///
+    /// Within a priority, members of a batch take turns with other work: a
+    /// member counts as queued behind the members of its batch ahead of it,
+    /// running ones included, so only a batch's first waiting member competes
+    /// by queue position.
     pub async fn dequeue(db: &sqlx::PgPool) -> Result<Option<(Self, CompilationJob)>, crate::error::AppError> {
         let queue_item = sqlx::query_as::<_, CompilationQueue>(
             r#"
             UPDATE compilation_queue
             SET started_at = NOW()
             WHERE id = (
-                SELECT id FROM compilation_queue
-                WHERE started_at IS NULL
-                ORDER BY priority DESC, queue_position ASC
-                FOR UPDATE SKIP LOCKED
+                SELECT q.id FROM compilation_queue q
+                JOIN (
+                    SELECT q.id, ROW_NUMBER() OVER (
+                        PARTITION BY COALESCE(cj.batch_id, cj.id)
+                        ORDER BY q.started_at IS NULL, q.queue_position
+                    ) AS turn
+                    FROM compilation_queue q
+                    JOIN compilation_jobs cj ON cj.id = q.job_id
+                ) turns ON turns.id = q.id
+                WHERE q.started_at IS NULL
+                ORDER BY q.priority DESC, turns.turn ASC, q.queue_position ASC
+                FOR UPDATE OF q SKIP LOCKED
                 LIMIT 1
             )
             RETURNING *
@@ -693,13 +995,34 @@ impl CompilationTemplate {
         created_by: Uuid,
         create_template: CreateCompilationTemplate,
     ) -> Result<Self, crate::error::AppError> {
+        let variables = create_template.variables.unwrap_or_default();
+        validate_declarations(&variables)?;
+        if let Some(project_id) = create_template.source_project_id {
+            // Publishing a project's files is up to its owner
+            let owned = sqlx::query_scalar::<_, bool>(
+                "SELECT EXISTS (SELECT 1 FROM projects WHERE id = $1 AND owner_id = $2 AND purging_at IS NULL)"
+            )
+            .bind(project_id)
+            .bind(created_by)
+            .fetch_one(db)
+            .await
+            .map_err(crate::error::AppError::Database)?;
+            if !owned {
+                return Err(crate::error::AppError::NotFound {
+                    entity: "Project".to_string(),
+                    id: project_id.to_string(),
+                });
+            }
+        }
+
         let template = sqlx::query_as::<_, CompilationTemplate>(
             r#"
             INSERT INTO compilation_templates (
                 name, description, engine, command_template, default_args,
                 required_files, output_patterns, is_public, created_by,
-                usage_count, success_rate, created_at, updated_at
-            ) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13)
+                usage_count, success_rate, created_at, updated_at,
+                source_project_id, variables
+            ) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15)
             RETURNING *
             "#
         )
@@ -716,6 +1039,8 @@ impl CompilationTemplate {
         .bind(1.0)
         .bind(Utc::now())
         .bind(Utc::now())
+        .bind(create_template.source_project_id)
+        .bind(sqlx::types::Json(variables))
         .fetch_one(db)
         .await
         .map_err(crate::error::AppError::Database)?;
@@ -757,6 +1082,25 @@ impl CompilationStats {
         db: &sqlx::PgPool,
         period_start: DateTime<Utc>,
         period_end: DateTime<Utc>,
+    ) -> Result<Self, crate::error::AppError> {
+        Self::query(db, None, period_start, period_end).await
+    }
+
+    /// Get compilation statistics of one project for a period
+    pub async fn get_project_stats(
+        db: &sqlx::PgPool,
+        project_id: Uuid,
+        period_start: DateTime<Utc>,
+        period_end: DateTime<Utc>,
+    ) -> Result<Self, crate::error::AppError> {
+        Self::query(db, Some(project_id), period_start, period_end).await
+    }
+
+    async fn query(
+        db: &sqlx::PgPool,
+        project_id: Option<Uuid>,
+        period_start: DateTime<Utc>,
+        period_end: DateTime<Utc>,
     ) -> Result<Self, crate::error::AppError> {
         let basic_stats = sqlx::query_as::<_, CompilationStatsRow>(
             r#"
@@ -765,14 +1109,16 @@ impl CompilationStats {
                 COUNT(*) FILTER (WHERE status = 'success') as successful_jobs,
                 COUNT(*) FILTER (WHERE status = 'error') as failed_jobs,
                 COUNT(*) FILTER (WHERE status = 'cancelled') as cancelled_jobs,
-                COALESCE(AVG(duration_ms), 0) as avg_duration,
-                COALESCE(SUM(output_size_bytes), 0) as total_output_size
+                COALESCE(AVG(duration_ms), 0)::FLOAT8 as avg_duration,
+                COALESCE(SUM(output_size_bytes), 0)::BIGINT as total_output_size
             FROM compilation_jobs
             WHERE created_at BETWEEN $1 AND $2
+              AND ($3::UUID IS NULL OR project_id = $3)
             "#
         )
         .bind(period_start)
         .bind(period_end)
+        .bind(project_id)
         .fetch_one(db)
         .await
         .map_err(crate::error::AppError::Database)?;
@@ -808,6 +1154,12 @@ impl CompilationStats {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::models::UserRole;
+    use crate::models::fail_point;
+    use crate::testing::{
+        add_collaborator, assert_pages_are_stable, create_test_job, create_test_project, create_test_user,
+        test_state, TestDb,
+    };
 
     #[test]
     fn test_queue_priority_default() {
@@ -821,8 +1173,116 @@ mod tests {
 
     #[test]
     fn test_artifact_type_values() {
-        assert_eq!(ArtifactType::Pdf as &str, "pdf");
-        assert_eq!(ArtifactType::Log as &str, "log");
-        assert_eq!(ArtifactType::Aux as &str, "aux");
+        assert_eq!(serde_json::to_value(ArtifactType::Pdf).unwrap(), "pdf");
+        assert_eq!(serde_json::to_value(ArtifactType::Log).unwrap(), "log");
+        assert_eq!(serde_json::to_value(ArtifactType::Aux).unwrap(), "aux");
+        assert_eq!(serde_json::to_value(ArtifactType::Thumbnail).unwrap(), "thumbnail");
+    }
+
+    #[tokio::test]
+    async fn test_job_access_by_relationship() {
+        let Some(db) = TestDb::start().await else { return };
+        let owner = create_test_user(&db.pool).await;
+        let collaborator = create_test_user(&db.pool).await;
+        let stranger = create_test_user(&db.pool).await;
+
+        let private = create_test_project(&db.pool, &owner, false).await;
+        let public = create_test_project(&db.pool, &owner, true).await;
+        add_collaborator(&db.pool, &private, &collaborator, UserRole::Viewer).await;
+
+        let private_job = create_test_job(&db.pool, &private, &owner).await;
+        let public_job = create_test_job(&db.pool, &public, &owner).await;
+
+        for user in [&owner, &collaborator] {
+            let found = CompilationJob::find_by_id(&db.pool, private_job.id, user.id).await.unwrap();
+            assert_eq!(found.map(|job| job.id), Some(private_job.id));
+        }
+        assert!(CompilationJob::find_by_id(&db.pool, private_job.id, stranger.id).await.unwrap().is_none());
+        assert!(CompilationJob::find_by_id(&db.pool, public_job.id, stranger.id).await.unwrap().is_some());
+    }
+
+    async fn queued(db: &sqlx::PgPool, job_id: Uuid) -> bool {
+        sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM compilation_queue WHERE job_id = $1)")
+            .bind(job_id)
+            .fetch_one(db)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_failed_create_leaves_no_unqueued_job() {
+        let Some(db) = TestDb::start().await else { return };
+        let owner = create_test_user(&db.pool).await;
+        let project = create_test_project(&db.pool, &owner, false).await;
+
+        {
+            let _armed = fail_point::arm("compilation_job_create");
+            let created = CompilationJob::create(
+                &db.pool,
+                project.id,
+                owner.id,
+                CreateCompilationJob { file_id: None, engine: None, args: None, priority: None, template_id: None, clean: false, batch_id: None },
+                project.latex_engine,
+                &project.main_file_path,
+                "/tmp".to_string(),
+                vec![],
+            )
+            .await;
+            assert!(created.is_err());
+        }
+        let jobs: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM compilation_jobs WHERE project_id = $1")
+            .bind(project.id)
+            .fetch_one(&db.pool)
+            .await
+            .unwrap();
+        assert_eq!(jobs, 0);
+
+        let job = create_test_job(&db.pool, &project, &owner).await;
+        assert!(queued(&db.pool, job.id).await);
+    }
+
+    #[tokio::test]
+    async fn test_failed_complete_keeps_job_queued() {
+        let Some(db) = TestDb::start().await else { return };
+        let state = test_state(&db).await;
+        let owner = create_test_user(&db.pool).await;
+        let project = create_test_project(&db.pool, &owner, false).await;
+        let job = create_test_job(&db.pool, &project, &owner).await;
+
+        let complete = || job.complete(&db.pool, &state.compile_notifier, &state.secret_keyring, 0, String::new(), String::new(), vec![], 1, 1024);
+        {
+            let _armed = fail_point::arm("compilation_job_complete");
+            assert!(complete().await.is_err());
+        }
+        let found = CompilationJob::find_by_id(&db.pool, job.id, owner.id).await.unwrap().unwrap();
+        assert_eq!(found.status, CompilationStatus::Pending);
+        assert!(queued(&db.pool, job.id).await);
+
+        complete().await.unwrap();
+        let found = CompilationJob::find_by_id(&db.pool, job.id, owner.id).await.unwrap().unwrap();
+        assert_eq!(found.status, CompilationStatus::Success);
+        assert!(!queued(&db.pool, job.id).await);
+    }
+
+    #[tokio::test]
+    async fn test_list_pages_are_stable_under_every_sort() {
+        let Some(db) = TestDb::start().await else { return };
+        let owner = create_test_user(&db.pool).await;
+        let project = create_test_project(&db.pool, &owner, false).await;
+        for _ in 0..5 {
+            create_test_job(&db.pool, &project, &owner).await;
+        }
+        sqlx::query("UPDATE compilation_jobs SET created_at = NOW(), updated_at = NOW() WHERE project_id = $1")
+            .bind(project.id)
+            .execute(&db.pool)
+            .await
+            .unwrap();
+
+        let pool = &db.pool;
+        assert_pages_are_stable(&JOB_SORT, move |params| async move {
+            let jobs = CompilationJob::list_for_user(pool, owner.id, &params).await.unwrap();
+            jobs.into_iter().map(|job| job.id).collect()
+        })
+        .await;
     }
 }
\ No newline at end of file
@@ -0,0 +1,438 @@
+//! Bulk compiles of a project's targets
+//!
+//! `POST /projects/{id}/compile-all` queues one job per compile target of the
+//! project, or per target listed in the request, linked by a batch. Targets
+//! are kept in the project's settings; a project without any compiles its
+//! main file.
+//!
+//! The queue takes turns between batches: a member waits behind the first
+//! waiting job of every other batch, and every standalone job, while another
+//! member of its batch is queued ahead of it or running. A project with many
+//! targets so holds up other users by one job at most.
+//!
+//! When the last member finishes, whether it completed or was cancelled, the
+//! batch is marked completed and its creator told with every member's
+//! outcome. Members check this after their own result is committed, so of
+//! two finishing together the later one always sees the batch done, and the
+//! compare-and-set on `completed_at` reports it once.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use std::collections::HashSet;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use super::compilation::CompilationJob;
+use super::compile_notification::CompileNotifier;
+use super::{CompilationStatus, LatexEngine};
+use crate::error::AppError;
+
+/// Targets a project or a bulk compile may list
+pub const MAX_COMPILE_TARGETS: usize = 20;
+
+/// A file `compile-all` compiles, with what to compile it with
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct CompileTarget {
+    /// Path of the file in the project; a `.typ` file compiles with Typst
+    pub path: String,
+    /// Defaults to the project's engine; applies to LaTeX targets only
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub engine: Option<LatexEngine>,
+    /// Engine arguments, ignored by Typst
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub args: Option<Vec<String>>,
+}
+
+/// Refuse target lists that are too long, name no file or name one twice
+pub fn validate_targets(targets: &[CompileTarget]) -> Result<(), AppError> {
+    if targets.len() > MAX_COMPILE_TARGETS {
+        return Err(AppError::Validation(format!(
+            "At most {} compile targets can be listed",
+            MAX_COMPILE_TARGETS
+        )));
+    }
+
+    let mut seen = HashSet::new();
+    for target in targets {
+        if target.path.trim().is_empty() {
+            return Err(AppError::Validation("Compile targets need a file path".to_string()));
+        }
+        if !seen.insert(target.path.as_str()) {
+            return Err(AppError::Validation(format!("{} is listed as a compile target twice", target.path)));
+        }
+    }
+
+    Ok(())
+}
+
+/// Jobs queued together by one bulk compile
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, ToSchema)]
+pub struct CompileBatch {
+    pub id: Uuid,
+    pub project_id: Uuid,
+    /// Who queued the batch; its members are theirs
+    pub user_id: Uuid,
+    pub created_at: DateTime<Utc>,
+    /// When the last member finished
+    pub completed_at: Option<DateTime<Utc>>,
+}
+
+/// Where a batch is as a whole
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum BatchStatus {
+    /// No member started yet
+    Pending,
+    /// Some members started or finished, others not yet
+    Running,
+    /// Every member finished, some without success
+    Partial,
+    /// Every member compiled successfully
+    Complete,
+    /// Every member finished, none successfully
+    Failed,
+}
+
+/// Members of a batch by status
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, ToSchema)]
+pub struct BatchCounts {
+    pub pending: i64,
+    pub running: i64,
+    pub succeeded: i64,
+    pub failed: i64,
+    pub cancelled: i64,
+}
+
+impl BatchCounts {
+    pub fn of(jobs: &[CompilationJob]) -> Self {
+        let mut counts = Self::default();
+        for job in jobs {
+            match job.status {
+                CompilationStatus::Success => counts.succeeded += 1,
+                CompilationStatus::Error => counts.failed += 1,
+                CompilationStatus::Cancelled => counts.cancelled += 1,
+                CompilationStatus::Running => counts.running += 1,
+                CompilationStatus::Pending | CompilationStatus::Never => counts.pending += 1,
+            }
+        }
+        counts
+    }
+
+    pub fn finished(&self) -> i64 {
+        self.succeeded + self.failed + self.cancelled
+    }
+
+    pub fn status(&self) -> BatchStatus {
+        if self.pending + self.running > 0 {
+            if self.running == 0 && self.finished() == 0 {
+                BatchStatus::Pending
+            } else {
+                BatchStatus::Running
+            }
+        } else if self.succeeded == self.finished() {
+            BatchStatus::Complete
+        } else if self.succeeded == 0 {
+            BatchStatus::Failed
+        } else {
+            BatchStatus::Partial
+        }
+    }
+}
+
+/// A file produced by a member of a batch
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+pub struct BatchArtifact {
+    pub job_id: Uuid,
+    pub file_id: Option<Uuid>,
+    pub output_file: String,
+    pub download_url: String,
+}
+
+/// How a member of a finished batch ended
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+pub struct BatchMemberOutcome {
+    pub job_id: Uuid,
+    /// File the member compiled
+    pub file_id: Option<Uuid>,
+    pub status: CompilationStatus,
+    pub error_message: Option<String>,
+}
+
+impl From<&CompilationJob> for BatchMemberOutcome {
+    fn from(job: &CompilationJob) -> Self {
+        Self {
+            job_id: job.id,
+            file_id: job.file_id,
+            status: job.status,
+            error_message: job.error_message.clone(),
+        }
+    }
+}
+
+/// A batch with its members, their status and what they produced
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct CompileBatchView {
+    pub batch: CompileBatch,
+    pub status: BatchStatus,
+    pub counts: BatchCounts,
+    /// In the order they were queued
+    pub jobs: Vec<CompilationJob>,
+    /// Output files of every member, member by member
+    pub artifacts: Vec<BatchArtifact>,
+}
+
+impl CompileBatchView {
+    pub fn new(batch: CompileBatch, jobs: Vec<CompilationJob>) -> Self {
+        let counts = BatchCounts::of(&jobs);
+        let artifacts = jobs
+            .iter()
+            .flat_map(|job| {
+                job.output_files.iter().map(|output_file| BatchArtifact {
+                    job_id: job.id,
+                    file_id: job.file_id,
+                    output_file: output_file.clone(),
+                    download_url: format!("/api/v1/compilation/jobs/{}/artifacts/{}", job.id, output_file),
+                })
+            })
+            .collect();
+
+        Self { batch, status: counts.status(), counts, jobs, artifacts }
+    }
+}
+
+impl CompileBatch {
+    /// Create an empty batch; its members are created with its ID
+    pub async fn create(
+        db: impl sqlx::PgExecutor<'_>,
+        project_id: Uuid,
+        user_id: Uuid,
+    ) -> Result<Self, AppError> {
+        sqlx::query_as::<_, CompileBatch>(
+            "INSERT INTO compile_batches (project_id, user_id) VALUES ($1, $2) RETURNING *"
+        )
+        .bind(project_id)
+        .bind(user_id)
+        .fetch_one(db)
+        .await
+        .map_err(AppError::Database)
+    }
+
+    /// Find a batch whose project the user can see
+    pub async fn find_by_id(
+        db: &sqlx::PgPool,
+        batch_id: Uuid,
+        user_id: Uuid,
+    ) -> Result<Option<Self>, AppError> {
+        sqlx::query_as::<_, CompileBatch>(
+            r#"
+            SELECT b.* FROM compile_batches b
+            JOIN projects p ON b.project_id = p.id
+            WHERE b.id = $1 AND p.purging_at IS NULL AND (
+                b.user_id = $2 OR
+                p.owner_id = $2 OR
+                p.id IN (
+                    SELECT project_id FROM project_collaborators
+                    WHERE user_id = $2
+                ) OR
+                p.is_public = true
+            )
+            "#
+        )
+        .bind(batch_id)
+        .bind(user_id)
+        .fetch_optional(db)
+        .await
+        .map_err(AppError::Database)
+    }
+
+    /// Members in the order they were queued
+    pub async fn members(&self, db: &sqlx::PgPool) -> Result<Vec<CompilationJob>, AppError> {
+        sqlx::query_as::<_, CompilationJob>(
+            "SELECT * FROM compilation_jobs WHERE batch_id = $1 ORDER BY created_at, id"
+        )
+        .bind(self.id)
+        .fetch_all(db)
+        .await
+        .map_err(AppError::Database)
+    }
+
+    pub async fn view(self, db: &sqlx::PgPool) -> Result<CompileBatchView, AppError> {
+        let jobs = self.members(db).await?;
+        Ok(CompileBatchView::new(self, jobs))
+    }
+
+    /// Cancel the members that have not finished; returns those cancelled
+    pub async fn cancel(&self, db: &sqlx::PgPool, error_message: &str) -> Result<Vec<CompilationJob>, AppError> {
+        let mut cancelled = Vec::new();
+        for job in self.members(db).await? {
+            if matches!(job.status, CompilationStatus::Pending | CompilationStatus::Running) {
+                cancelled.extend(job.cancel(db, error_message).await?);
+            }
+        }
+        Ok(cancelled)
+    }
+
+    /// Mark the batch completed once no member is left to finish
+    ///
+    /// Returns the batch only to the one caller that completed it.
+    pub async fn complete_if_finished(db: &sqlx::PgPool, batch_id: Uuid) -> Result<Option<Self>, AppError> {
+        sqlx::query_as::<_, CompileBatch>(
+            r#"
+            UPDATE compile_batches SET completed_at = NOW()
+            WHERE id = $1 AND completed_at IS NULL AND NOT EXISTS (
+                SELECT 1 FROM compilation_jobs
+                WHERE batch_id = $1 AND status IN ('pending', 'running')
+            )
+            RETURNING *
+            "#
+        )
+        .bind(batch_id)
+        .fetch_optional(db)
+        .await
+        .map_err(AppError::Database)
+    }
+
+    /// Complete the batch if a member finishing finished it, and tell its
+    /// creator; call after the member's result is committed
+    pub async fn member_finished(db: &sqlx::PgPool, notifier: &CompileNotifier, batch_id: Uuid) -> Result<(), AppError> {
+        if let Some(batch) = Self::complete_if_finished(db, batch_id).await? {
+            let members = batch.members(db).await?;
+            notifier.batch_finished(db, &batch, &members).await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::compilation::{CompilationQueue, CreateCompilationJob};
+    use crate::models::notification::Notification;
+    use crate::testing::{create_test_job, create_test_project, create_test_user, test_state, TestDb};
+
+    fn target(path: &str) -> CompileTarget {
+        CompileTarget { path: path.to_string(), engine: None, args: None }
+    }
+
+    #[test]
+    fn test_target_lists_are_validated() {
+        assert!(validate_targets(&[target("paper.tex"), target("slides.tex")]).is_ok());
+        assert!(validate_targets(&[]).is_ok());
+        assert!(matches!(validate_targets(&[target(" ")]), Err(AppError::Validation(_))));
+        assert!(matches!(validate_targets(&[target("a.tex"), target("a.tex")]), Err(AppError::Validation(_))));
+
+        let many: Vec<CompileTarget> = (0..=MAX_COMPILE_TARGETS).map(|i| target(&format!("{}.tex", i))).collect();
+        assert!(matches!(validate_targets(&many), Err(AppError::Validation(_))));
+    }
+
+    #[test]
+    fn test_batch_status_from_member_counts() {
+        let counts = |pending, running, succeeded, failed, cancelled| BatchCounts { pending, running, succeeded, failed, cancelled };
+
+        assert_eq!(counts(3, 0, 0, 0, 0).status(), BatchStatus::Pending);
+        assert_eq!(counts(2, 1, 0, 0, 0).status(), BatchStatus::Running);
+        assert_eq!(counts(2, 0, 1, 0, 0).status(), BatchStatus::Running);
+        assert_eq!(counts(0, 0, 3, 0, 0).status(), BatchStatus::Complete);
+        assert_eq!(counts(0, 0, 2, 1, 0).status(), BatchStatus::Partial);
+        assert_eq!(counts(0, 0, 1, 0, 2).status(), BatchStatus::Partial);
+        assert_eq!(counts(0, 0, 0, 2, 1).status(), BatchStatus::Failed);
+    }
+
+    async fn create_member(db: &sqlx::PgPool, batch: &CompileBatch, project: &crate::models::project::Project) -> CompilationJob {
+        CompilationJob::create(
+            db,
+            project.id,
+            batch.user_id,
+            CreateCompilationJob {
+                file_id: None,
+                engine: None,
+                args: None,
+                priority: None,
+                template_id: None,
+                clean: false,
+                batch_id: Some(batch.id),
+            },
+            project.latex_engine,
+            &project.main_file_path,
+            "/tmp".to_string(),
+            vec![],
+        )
+        .await
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_queue_takes_turns_between_batches() {
+        let Some(db) = TestDb::start().await else { return };
+        let owner = create_test_user(&db.pool).await;
+        let other = create_test_user(&db.pool).await;
+        let project = create_test_project(&db.pool, &owner, false).await;
+        let other_project = create_test_project(&db.pool, &other, false).await;
+
+        let batch = CompileBatch::create(&db.pool, project.id, owner.id).await.unwrap();
+        let mut members = Vec::new();
+        for _ in 0..3 {
+            members.push(create_member(&db.pool, &batch, &project).await);
+        }
+        let standalone = create_test_job(&db.pool, &other_project, &other).await;
+
+        let next = || async { CompilationQueue::dequeue(&db.pool).await.unwrap().unwrap().1.id };
+        assert_eq!(next().await, members[0].id);
+        // Queued after the whole batch, but served while its first member runs
+        assert_eq!(next().await, standalone.id);
+        assert_eq!(next().await, members[1].id);
+        assert_eq!(next().await, members[2].id);
+        assert!(CompilationQueue::dequeue(&db.pool).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_last_member_reports_the_batch_once() {
+        let Some(db) = TestDb::start().await else { return };
+        let state = test_state(&db).await;
+        let owner = create_test_user(&db.pool).await;
+        let project = create_test_project(&db.pool, &owner, false).await;
+        let batch = CompileBatch::create(&db.pool, project.id, owner.id).await.unwrap();
+        let first = create_member(&db.pool, &batch, &project).await;
+        let second = create_member(&db.pool, &batch, &project).await;
+
+        let complete = |job: CompilationJob, exit_code| {
+            let state = state.clone();
+            async move {
+                job.complete(&state.db_pool, &state.compile_notifier, &state.secret_keyring, exit_code, String::new(), String::new(), vec!["main.pdf".to_string()], 1, 1)
+                    .await
+                    .unwrap()
+            }
+        };
+        complete(first, 0).await;
+        assert!(CompileBatch::find_by_id(&db.pool, batch.id, owner.id).await.unwrap().unwrap().completed_at.is_none());
+
+        // Finishing together, or again, still reports it once
+        let (_, reported) = tokio::join!(
+            complete(second.clone(), 1),
+            CompileBatch::member_finished(&db.pool, &state.compile_notifier, batch.id),
+        );
+        reported.unwrap();
+        CompileBatch::member_finished(&db.pool, &state.compile_notifier, batch.id).await.unwrap();
+
+        let view = CompileBatch::find_by_id(&db.pool, batch.id, owner.id).await.unwrap().unwrap().view(&db.pool).await.unwrap();
+        assert!(view.batch.completed_at.is_some());
+        assert_eq!(view.status, BatchStatus::Partial);
+        assert_eq!((view.counts.succeeded, view.counts.failed), (1, 1));
+
+        let notifications = sqlx::query_as::<_, Notification>(
+            "SELECT * FROM notifications WHERE user_id = $1 AND kind = 'compile_batch_finished'"
+        )
+        .bind(owner.id)
+        .fetch_all(&db.pool)
+        .await
+        .unwrap();
+        assert_eq!(notifications.len(), 1);
+        let data = notifications[0].data.clone().unwrap();
+        assert_eq!(data["batch_id"], batch.id.to_string());
+        assert_eq!(data["status"], "partial");
+        assert_eq!(data["members"].as_array().unwrap().len(), 2);
+        assert_eq!(data["members"][1]["job_id"], second.id.to_string());
+        assert_eq!(data["members"][1]["status"], "error");
+    }
+}
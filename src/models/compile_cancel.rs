@@ -0,0 +1,308 @@
+//! Cancellation of running compiles
+//!
+//! A worker runs a job's engine through [`execute`], which registers the run
+//! in [`RunningCompiles`] with the process ID and a watch channel. Cancelling
+//! the job first flips its status with a compare-and-set in SQL, so a job is
+//! cancelled once and a result written just before stays; then the run, if it
+//! is on this server, is signalled. The engine is started in its own process
+//! group and the whole group is killed, so helpers it spawned die with it.
+//! What the run printed until then is kept on the job, its queue entry is
+//! released and its creator gets a final `CompileProgress` over the
+//! WebSocket.
+//!
+//! Like the rate limits the registry lives in memory: a job running on
+//! another server is marked cancelled but runs on until that worker finishes
+//! it, and its result is discarded.
+
+use std::collections::HashMap;
+use std::process::Stdio;
+use std::sync::{Arc, Mutex};
+use tokio::io::AsyncReadExt;
+use tokio::process::Command;
+use tokio::sync::watch;
+use uuid::Uuid;
+
+use super::compilation::CompilationJob;
+use super::CompilationStatus;
+use crate::error::AppError;
+use crate::server::AppState;
+use crate::websocket::WsMessage;
+
+/// Error message of jobs cancelled by a user
+pub const CANCELLED_BY_USER: &str = "Cancelled by user";
+
+#[derive(Debug)]
+struct RunningCompile {
+    /// Process group of the engine, once it started
+    pid: Option<u32>,
+    cancel: watch::Sender<bool>,
+}
+
+/// Compile runs of this server, by job
+#[derive(Debug, Default)]
+pub struct RunningCompiles {
+    jobs: Mutex<HashMap<Uuid, RunningCompile>>,
+}
+
+/// Registration of a run; dropping it unregisters the run
+#[derive(Debug)]
+pub struct CancelHandle {
+    registry: Arc<RunningCompiles>,
+    job_id: Uuid,
+    cancelled: watch::Receiver<bool>,
+}
+
+/// How a run ended
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RunOutcome {
+    Exited { exit_code: i32, stdout: String, stderr: String },
+    /// Killed on cancellation, with what it printed until then
+    Cancelled { stdout: String, stderr: String },
+}
+
+impl RunningCompiles {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register the run of a job, before it starts
+    pub fn register(self: &Arc<Self>, job_id: Uuid) -> CancelHandle {
+        let (cancel, cancelled) = watch::channel(false);
+        let mut jobs = self.jobs.lock().unwrap_or_else(|e| e.into_inner());
+        jobs.insert(job_id, RunningCompile { pid: None, cancel });
+        CancelHandle { registry: self.clone(), job_id, cancelled }
+    }
+
+    /// Signal the run of a job; returns false when it does not run here
+    pub fn cancel(&self, job_id: Uuid) -> bool {
+        let jobs = self.jobs.lock().unwrap_or_else(|e| e.into_inner());
+        match jobs.get(&job_id) {
+            Some(run) => {
+                run.cancel.send_replace(true);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Process group of a job's engine
+    pub fn pid(&self, job_id: Uuid) -> Option<u32> {
+        let jobs = self.jobs.lock().unwrap_or_else(|e| e.into_inner());
+        jobs.get(&job_id).and_then(|run| run.pid)
+    }
+
+    pub fn len(&self) -> usize {
+        self.jobs.lock().unwrap_or_else(|e| e.into_inner()).len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl CancelHandle {
+    fn set_pid(&self, pid: u32) {
+        let mut jobs = self.registry.jobs.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(run) = jobs.get_mut(&self.job_id) {
+            run.pid = Some(pid);
+        }
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        *self.cancelled.borrow()
+    }
+
+    /// Resolves once the run is cancelled
+    pub async fn cancelled(&mut self) {
+        // The sender lives in the registry as long as this handle
+        let _ = self.cancelled.wait_for(|cancelled| *cancelled).await;
+    }
+}
+
+impl Drop for CancelHandle {
+    fn drop(&mut self) {
+        let mut jobs = self.registry.jobs.lock().unwrap_or_else(|e| e.into_inner());
+        jobs.remove(&self.job_id);
+    }
+}
+
+/// Kill a process group
+fn kill_group(pid: u32) {
+    // SAFETY: killpg only sends a signal; a group that is gone yields ESRCH
+    let killed = unsafe { libc::killpg(pid as libc::pid_t, libc::SIGKILL) };
+    if killed != 0 {
+        let error = std::io::Error::last_os_error();
+        if error.raw_os_error() != Some(libc::ESRCH) {
+            tracing::warn!("Failed to kill process group {}: {}", pid, error);
+        }
+    }
+}
+
+/// Run `command` in its own process group until it exits or `handle` is
+/// cancelled, which kills the group
+pub async fn run_cancellable(command: &mut Command, handle: &mut CancelHandle) -> Result<RunOutcome, AppError> {
+    command
+        .process_group(0)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+    let mut child = command.spawn()?;
+    let pid = child.id().ok_or_else(|| AppError::Internal("The compile exited before it started".to_string()))?;
+    handle.set_pid(pid);
+
+    // Read as the engine writes, so the output up to a cancellation is kept
+    let mut stdout = child.stdout.take().expect("stdout is piped");
+    let mut stderr = child.stderr.take().expect("stderr is piped");
+    let stdout = tokio::spawn(async move {
+        let mut buffer = Vec::new();
+        let _ = stdout.read_to_end(&mut buffer).await;
+        buffer
+    });
+    let stderr = tokio::spawn(async move {
+        let mut buffer = Vec::new();
+        let _ = stderr.read_to_end(&mut buffer).await;
+        buffer
+    });
+
+    let exited = tokio::select! {
+        status = child.wait() => Some(status?),
+        _ = handle.cancelled() => None,
+    };
+    // Helpers that outlived the engine would hold its pipes open, so the
+    // group goes whichever way the run ended
+    kill_group(pid);
+    if exited.is_none() {
+        child.wait().await?;
+    }
+
+    let text = |buffer: Result<Vec<u8>, tokio::task::JoinError>| String::from_utf8_lossy(&buffer.unwrap_or_default()).into_owned();
+    let (stdout, stderr) = (text(stdout.await), text(stderr.await));
+    Ok(match exited {
+        Some(status) => RunOutcome::Exited { exit_code: status.code().unwrap_or(-1), stdout, stderr },
+        None => RunOutcome::Cancelled { stdout, stderr },
+    })
+}
+
+/// Tell the creator of a job about its new status, and those watching its batch
+pub fn publish_progress(state: &AppState, job: &CompilationJob, status: CompilationStatus, message: Option<String>) {
+    let progress = WsMessage::CompileProgress {
+        job_id: job.id,
+        project_id: job.project_id,
+        batch_id: job.batch_id,
+        status,
+        message,
+    };
+    match job.batch_id {
+        Some(batch_id) => state.user_channels.send_to_batch(batch_id, job.user_id, progress),
+        None => {
+            state.user_channels.send(job.user_id, progress);
+        }
+    }
+}
+
+/// Start `job` and register its run
+///
+/// Returns `None` when the job was cancelled before it started.
+pub async fn begin(
+    state: &AppState,
+    job: &CompilationJob,
+    worker_id: Option<String>,
+) -> Result<Option<CancelHandle>, AppError> {
+    let handle = state.running_compiles.register(job.id);
+    if !job.start(&state.db_pool, worker_id).await? {
+        // Dequeued entries are left for their run to release
+        job.record_cancelled_output(&state.db_pool, &state.secret_keyring, "", "").await?;
+        return Ok(None);
+    }
+    publish_progress(state, job, CompilationStatus::Running, None);
+    Ok(Some(handle))
+}
+
+/// Run the engine of a started job with `command`
+///
+/// A cancelled run keeps its partial output on the job and releases its queue
+/// entry; an exited one is for the worker to complete.
+pub async fn run(
+    state: &AppState,
+    job: &CompilationJob,
+    command: &mut Command,
+    handle: &mut CancelHandle,
+) -> Result<RunOutcome, AppError> {
+    let outcome = run_cancellable(command, handle).await?;
+    if let RunOutcome::Cancelled { stdout, stderr } = &outcome {
+        job.record_cancelled_output(&state.db_pool, &state.secret_keyring, stdout, stderr).await?;
+        publish_progress(state, job, CompilationStatus::Cancelled, Some(CANCELLED_BY_USER.to_string()));
+    }
+    Ok(outcome)
+}
+
+/// Start `job` and run its engine with `command`, see [`begin`] and [`run`]
+pub async fn execute(
+    state: &AppState,
+    job: &CompilationJob,
+    mut command: Command,
+    worker_id: Option<String>,
+) -> Result<Option<RunOutcome>, AppError> {
+    let Some(mut handle) = begin(state, job, worker_id).await? else {
+        return Ok(None);
+    };
+    let outcome = run(state, job, &mut command, &mut handle).await?;
+    Ok(Some(outcome))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_cancellation_kills_the_process_group() {
+        let registry = Arc::new(RunningCompiles::new());
+        let directory = tempfile::tempdir().unwrap();
+        let pid_file = directory.path().join("helper.pid");
+
+        let mut handle = registry.register(Uuid::nil());
+        let mut command = Command::new("sh");
+        command.arg("-c").arg("sleep 60 & echo $! > \"$0\"; echo started; wait").arg(&pid_file);
+        let run = tokio::spawn(async move { run_cancellable(&mut command, &mut handle).await });
+
+        let started = std::time::Instant::now();
+        while !pid_file.exists() || std::fs::read_to_string(&pid_file).unwrap().trim().is_empty() {
+            assert!(started.elapsed() < std::time::Duration::from_secs(5), "the command did not start");
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+        let helper: i32 = std::fs::read_to_string(&pid_file).unwrap().trim().parse().unwrap();
+        assert!(registry.pid(Uuid::nil()).is_some());
+
+        assert!(registry.cancel(Uuid::nil()));
+        let outcome = tokio::time::timeout(std::time::Duration::from_secs(5), run).await.unwrap().unwrap().unwrap();
+        assert_eq!(outcome, RunOutcome::Cancelled { stdout: "started\n".to_string(), stderr: String::new() });
+        assert!(registry.is_empty());
+        assert!(!registry.cancel(Uuid::nil()));
+
+        // The helper the shell spawned is gone too, or a zombie left to init
+        let alive = || {
+            std::fs::read_to_string(format!("/proc/{}/stat", helper))
+                .map(|stat| stat.split_whitespace().nth(2) != Some("Z"))
+                .unwrap_or(false)
+        };
+        let killed = std::time::Instant::now();
+        while alive() {
+            assert!(killed.elapsed() < std::time::Duration::from_secs(2), "the helper survived the cancellation");
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_finished_runs_report_their_exit() {
+        let registry = Arc::new(RunningCompiles::new());
+        let mut handle = registry.register(Uuid::nil());
+        let mut command = Command::new("sh");
+        command.arg("-c").arg("echo out; echo err >&2; exit 3");
+
+        let outcome = run_cancellable(&mut command, &mut handle).await.unwrap();
+        assert_eq!(
+            outcome,
+            RunOutcome::Exited { exit_code: 3, stdout: "out\n".to_string(), stderr: "err\n".to_string() }
+        );
+    }
+}
@@ -0,0 +1,418 @@
+//! Output comparison between two compilation jobs
+//!
+//! Extracts the text of every page of both jobs' primary PDFs, aligns pages
+//! by number and runs a word-level diff on each page. Page hashes are taken
+//! over whitespace-normalized text, so a page only counts as changed when its
+//! words do. Extraction is slow on long documents, so results are computed
+//! in the background once and cached per (from, to) job pair.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::FromRow;
+use std::path::{Path, PathBuf};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use super::compilation::CompilationJob;
+
+/// Largest word diff table computed per page; bigger pages are reported as
+/// fully replaced instead
+pub const MAX_WORD_DIFF_CELLS: usize = 4_000_000;
+
+/// A pending diff older than this is assumed to belong to a computation that
+/// died with its server and may be claimed again
+pub const STALE_PENDING_MINUTES: i64 = 15;
+
+/// Compile diff computation status
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type, ToSchema)]
+pub enum CompileDiffStatus {
+    #[serde(rename = "pending")]
+    #[sqlx(rename = "pending")]
+    Pending,
+    #[serde(rename = "ready")]
+    #[sqlx(rename = "ready")]
+    Ready,
+    #[serde(rename = "failed")]
+    #[sqlx(rename = "failed")]
+    Failed,
+}
+
+/// Cached comparison of two jobs' PDFs
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct CompileDiff {
+    pub from_job_id: Uuid,
+    pub to_job_id: Uuid,
+    pub status: CompileDiffStatus,
+    /// Serialized `PdfDiff` once ready
+    pub result: Option<serde_json::Value>,
+    pub error: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// Comparison of two PDFs
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct PdfDiff {
+    pub from_page_count: u32,
+    pub to_page_count: u32,
+    pub page_count_delta: i64,
+    /// Pages whose text hash differs, including added and removed pages
+    pub changed_pages: Vec<u32>,
+    pub pages: Vec<PageDiff>,
+}
+
+/// Differences on a single page
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct PageDiff {
+    pub page: u32,
+    pub change: PageChange,
+    pub segments: Vec<DiffSegment>,
+}
+
+/// How a page changed between the two PDFs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum PageChange {
+    Added,
+    Removed,
+    Modified,
+}
+
+/// Run of consecutive words with the same diff operation
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct DiffSegment {
+    pub op: DiffOp,
+    pub text: String,
+}
+
+/// Word diff operation
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum DiffOp {
+    Equal,
+    Insert,
+    Delete,
+}
+
+impl CompileDiff {
+    /// Find the cached diff for a job pair
+    pub async fn find(
+        db: &sqlx::PgPool,
+        from_job_id: Uuid,
+        to_job_id: Uuid,
+    ) -> Result<Option<Self>, crate::error::AppError> {
+        let diff = sqlx::query_as::<_, CompileDiff>(
+            "SELECT * FROM compile_diffs WHERE from_job_id = $1 AND to_job_id = $2"
+        )
+        .bind(from_job_id)
+        .bind(to_job_id)
+        .fetch_optional(db)
+        .await
+        .map_err(crate::error::AppError::Database)?;
+
+        Ok(diff)
+    }
+
+    /// Reserve the computation of a job pair.
+    ///
+    /// Returns the pending row when the caller should compute the diff, or
+    /// `None` when another request already does (or did).
+    pub async fn claim(
+        db: &sqlx::PgPool,
+        from_job_id: Uuid,
+        to_job_id: Uuid,
+    ) -> Result<Option<Self>, crate::error::AppError> {
+        let diff = sqlx::query_as::<_, CompileDiff>(
+            r#"
+            INSERT INTO compile_diffs (from_job_id, to_job_id, status)
+            VALUES ($1, $2, 'pending')
+            ON CONFLICT (from_job_id, to_job_id) DO UPDATE SET
+                status = 'pending',
+                error = NULL,
+                updated_at = NOW()
+            WHERE compile_diffs.status = 'pending'
+                AND compile_diffs.updated_at < NOW() - make_interval(mins => $3)
+            RETURNING *
+            "#
+        )
+        .bind(from_job_id)
+        .bind(to_job_id)
+        .bind(STALE_PENDING_MINUTES as i32)
+        .fetch_optional(db)
+        .await
+        .map_err(crate::error::AppError::Database)?;
+
+        Ok(diff)
+    }
+
+    /// Store the finished comparison
+    pub async fn mark_ready(
+        db: &sqlx::PgPool,
+        from_job_id: Uuid,
+        to_job_id: Uuid,
+        result: &PdfDiff,
+    ) -> Result<(), crate::error::AppError> {
+        sqlx::query(
+            r#"
+            UPDATE compile_diffs
+            SET status = 'ready', result = $3, error = NULL, updated_at = NOW()
+            WHERE from_job_id = $1 AND to_job_id = $2
+            "#
+        )
+        .bind(from_job_id)
+        .bind(to_job_id)
+        .bind(serde_json::to_value(result)?)
+        .execute(db)
+        .await
+        .map_err(crate::error::AppError::Database)?;
+
+        Ok(())
+    }
+
+    /// Record why the comparison could not be computed
+    pub async fn mark_failed(
+        db: &sqlx::PgPool,
+        from_job_id: Uuid,
+        to_job_id: Uuid,
+        error: &str,
+    ) -> Result<(), crate::error::AppError> {
+        sqlx::query(
+            r#"
+            UPDATE compile_diffs
+            SET status = 'failed', error = $3, updated_at = NOW()
+            WHERE from_job_id = $1 AND to_job_id = $2
+            "#
+        )
+        .bind(from_job_id)
+        .bind(to_job_id)
+        .bind(error)
+        .execute(db)
+        .await
+        .map_err(crate::error::AppError::Database)?;
+
+        Ok(())
+    }
+}
+
+/// Locate the primary PDF a job produced, or `None` when it was never
+/// produced or has since been cleaned up
+pub async fn primary_pdf(job: &CompilationJob) -> Option<PathBuf> {
+    let file = job
+        .output_files
+        .iter()
+        .find(|file| file.to_lowercase().ends_with(".pdf"))?;
+
+    let path = Path::new(&job.working_directory).join(file);
+    match tokio::fs::metadata(&path).await {
+        Ok(metadata) if metadata.is_file() => Some(path),
+        _ => None,
+    }
+}
+
+/// Extract and compare the text of two PDFs
+pub async fn compare_pdfs(from: PathBuf, to: PathBuf) -> Result<PdfDiff, crate::error::AppError> {
+    tokio::task::spawn_blocking(move || {
+        let from_pages = extract_page_texts(&from)?;
+        let to_pages = extract_page_texts(&to)?;
+        Ok(diff_pages(&from_pages, &to_pages))
+    })
+    .await
+    .map_err(|e| crate::error::AppError::Internal(format!("Compile diff task failed: {}", e)))?
+}
+
+/// Text of every page, in page order. Pages whose text cannot be extracted
+/// (unsupported font encodings, image-only pages) are treated as empty.
+fn extract_page_texts(path: &Path) -> Result<Vec<String>, crate::error::AppError> {
+    let document = lopdf::Document::load(path).map_err(|e| {
+        crate::error::AppError::Compilation(format!("Cannot read PDF {}: {}", path.display(), e))
+    })?;
+
+    let pages = document
+        .get_pages()
+        .into_keys()
+        .map(|page| document.extract_text(&[page]).unwrap_or_default())
+        .collect();
+
+    Ok(pages)
+}
+
+/// Compare pages by number
+pub fn diff_pages(from: &[String], to: &[String]) -> PdfDiff {
+    let mut changed_pages = Vec::new();
+    let mut pages = Vec::new();
+
+    for index in 0..from.len().max(to.len()) {
+        let page = index as u32 + 1;
+        let from_words = from.get(index).map(|text| words(text));
+        let to_words = to.get(index).map(|text| words(text));
+
+        let diff = match (from_words, to_words) {
+            (Some(from_words), Some(to_words)) => {
+                if page_hash(&from_words) == page_hash(&to_words) {
+                    continue;
+                }
+                PageDiff {
+                    page,
+                    change: PageChange::Modified,
+                    segments: word_diff(&from_words, &to_words),
+                }
+            }
+            (None, Some(to_words)) => PageDiff {
+                page,
+                change: PageChange::Added,
+                segments: segments_of(DiffOp::Insert, &to_words),
+            },
+            (Some(from_words), None) => PageDiff {
+                page,
+                change: PageChange::Removed,
+                segments: segments_of(DiffOp::Delete, &from_words),
+            },
+            (None, None) => continue,
+        };
+
+        changed_pages.push(page);
+        pages.push(diff);
+    }
+
+    PdfDiff {
+        from_page_count: from.len() as u32,
+        to_page_count: to.len() as u32,
+        page_count_delta: to.len() as i64 - from.len() as i64,
+        changed_pages,
+        pages,
+    }
+}
+
+fn words(text: &str) -> Vec<&str> {
+    text.split_whitespace().collect()
+}
+
+/// Hash of a page's words, insensitive to line breaks and spacing
+fn page_hash(words: &[&str]) -> String {
+    hex::encode(Sha256::digest(words.join(" ").as_bytes()))
+}
+
+fn segments_of(op: DiffOp, words: &[&str]) -> Vec<DiffSegment> {
+    if words.is_empty() {
+        return Vec::new();
+    }
+    vec![DiffSegment { op, text: words.join(" ") }]
+}
+
+/// Word-level diff based on the longest common subsequence
+pub fn word_diff(from: &[&str], to: &[&str]) -> Vec<DiffSegment> {
+    // Only the differing middle needs the quadratic table
+    let prefix = from.iter().zip(to).take_while(|(a, b)| a == b).count();
+    let suffix = from[prefix..]
+        .iter()
+        .rev()
+        .zip(to[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let from_mid = &from[prefix..from.len() - suffix];
+    let to_mid = &to[prefix..to.len() - suffix];
+
+    let mut ops: Vec<(DiffOp, &str)> = from[..prefix].iter().map(|w| (DiffOp::Equal, *w)).collect();
+
+    let cells = (from_mid.len() + 1).saturating_mul(to_mid.len() + 1);
+    if cells > MAX_WORD_DIFF_CELLS {
+        ops.extend(from_mid.iter().map(|w| (DiffOp::Delete, *w)));
+        ops.extend(to_mid.iter().map(|w| (DiffOp::Insert, *w)));
+    } else {
+        ops.extend(lcs_ops(from_mid, to_mid));
+    }
+
+    ops.extend(from[from.len() - suffix..].iter().map(|w| (DiffOp::Equal, *w)));
+
+    let mut segments: Vec<DiffSegment> = Vec::new();
+    for (op, word) in ops {
+        match segments.last_mut() {
+            Some(last) if last.op == op => {
+                last.text.push(' ');
+                last.text.push_str(word);
+            }
+            _ => segments.push(DiffSegment { op, text: word.to_string() }),
+        }
+    }
+    segments
+}
+
+/// Edit script turning `from` into `to` along a longest common subsequence
+pub(crate) fn lcs_ops<'a>(from: &[&'a str], to: &[&'a str]) -> Vec<(DiffOp, &'a str)> {
+    let width = to.len() + 1;
+    // lengths[i * width + j] = LCS length of from[i..] and to[j..]
+    let mut lengths = vec![0u32; (from.len() + 1) * width];
+    for i in (0..from.len()).rev() {
+        for j in (0..to.len()).rev() {
+            lengths[i * width + j] = if from[i] == to[j] {
+                lengths[(i + 1) * width + j + 1] + 1
+            } else {
+                lengths[(i + 1) * width + j].max(lengths[i * width + j + 1])
+            };
+        }
+    }
+
+    let mut ops = Vec::with_capacity(from.len() + to.len());
+    let (mut i, mut j) = (0, 0);
+    while i < from.len() && j < to.len() {
+        if from[i] == to[j] {
+            ops.push((DiffOp::Equal, from[i]));
+            i += 1;
+            j += 1;
+        } else if lengths[(i + 1) * width + j] >= lengths[i * width + j + 1] {
+            ops.push((DiffOp::Delete, from[i]));
+            i += 1;
+        } else {
+            ops.push((DiffOp::Insert, to[j]));
+            j += 1;
+        }
+    }
+    ops.extend(from[i..].iter().map(|w| (DiffOp::Delete, *w)));
+    ops.extend(to[j..].iter().map(|w| (DiffOp::Insert, *w)));
+    ops
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn segment(op: DiffOp, text: &str) -> DiffSegment {
+        DiffSegment { op, text: text.to_string() }
+    }
+
+    #[test]
+    fn test_word_diff() {
+        let from = words("the quick brown fox jumps");
+        let to = words("the slow brown fox leaps high");
+
+        assert_eq!(
+            word_diff(&from, &to),
+            vec![
+                segment(DiffOp::Equal, "the"),
+                segment(DiffOp::Delete, "quick"),
+                segment(DiffOp::Insert, "slow"),
+                segment(DiffOp::Equal, "brown fox"),
+                segment(DiffOp::Delete, "jumps"),
+                segment(DiffOp::Insert, "leaps high"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_diff_pages_ignores_reflowed_text() {
+        let from = vec!["Title\n\nIntro text".to_string(), "Old results".to_string()];
+        let to = vec![
+            "Title  Intro\ntext".to_string(),
+            "New results".to_string(),
+            "Appendix".to_string(),
+        ];
+
+        let diff = diff_pages(&from, &to);
+        assert_eq!(diff.page_count_delta, 1);
+        assert_eq!(diff.changed_pages, vec![2, 3]);
+        assert_eq!(diff.pages[0].change, PageChange::Modified);
+        assert_eq!(diff.pages[1].change, PageChange::Added);
+        assert_eq!(diff.pages[1].segments, vec![segment(DiffOp::Insert, "Appendix")]);
+    }
+}
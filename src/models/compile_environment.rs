@@ -0,0 +1,320 @@
+//! TeX environment recorded for each compilation
+//!
+//! Captures the engine version and TeX distribution when a job starts and the
+//! package files the engine actually read (from the `-recorder` .fls file)
+//! when it finishes, so two compiles of the same project can be compared.
+
+use chrono::{DateTime, Utc};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::time::Duration;
+use utoipa::ToSchema;
+
+use super::LatexEngine;
+
+/// How long to wait for `<engine> --version`
+const VERSION_PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// File extensions recorded as packages
+const PACKAGE_EXTENSIONS: &[&str] = &["sty", "cls"];
+
+/// TeX distribution the engine belongs to
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct TexDistribution {
+    pub name: String,
+    pub release: String,
+}
+
+/// A package or class file read during compilation
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct PackageFile {
+    pub name: String,
+    pub path: String,
+}
+
+/// Environment recorded on a compilation job
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct CompileEnvironment {
+    pub engine: LatexEngine,
+    pub engine_version: Option<String>,
+    pub distribution: Option<TexDistribution>,
+    #[serde(default)]
+    pub packages: Vec<PackageFile>,
+    pub captured_at: DateTime<Utc>,
+}
+
+/// A value that differs between two jobs
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct Change<T> {
+    pub from: T,
+    pub to: T,
+}
+
+/// A package whose resolved file changed between two jobs
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct PackageChange {
+    pub name: String,
+    pub from_path: String,
+    pub to_path: String,
+}
+
+/// Differences between the environments of two jobs
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct CompileEnvironmentDiff {
+    pub engine: Option<Change<LatexEngine>>,
+    pub engine_version: Option<Change<Option<String>>>,
+    pub distribution: Option<Change<Option<TexDistribution>>>,
+    pub packages_added: Vec<PackageFile>,
+    pub packages_removed: Vec<PackageFile>,
+    pub packages_changed: Vec<PackageChange>,
+}
+
+impl CompileEnvironmentDiff {
+    pub fn is_empty(&self) -> bool {
+        self.engine.is_none()
+            && self.engine_version.is_none()
+            && self.distribution.is_none()
+            && self.packages_added.is_empty()
+            && self.packages_removed.is_empty()
+            && self.packages_changed.is_empty()
+    }
+}
+
+impl CompileEnvironment {
+    /// Probe the engine binary for its version and distribution
+    pub async fn capture(engine: LatexEngine) -> Self {
+        let output = tokio::time::timeout(
+            VERSION_PROBE_TIMEOUT,
+            tokio::process::Command::new(engine.command())
+                .arg("--version")
+                .kill_on_drop(true)
+                .output(),
+        )
+        .await;
+
+        let (engine_version, distribution) = match output {
+            Ok(Ok(output)) if output.status.success() => {
+                parse_engine_version(&String::from_utf8_lossy(&output.stdout))
+            }
+            Ok(Ok(output)) => {
+                tracing::warn!("{} --version exited with {}", engine.command(), output.status);
+                (None, None)
+            }
+            Ok(Err(e)) => {
+                tracing::warn!("Failed to run {} --version: {}", engine.command(), e);
+                (None, None)
+            }
+            Err(_) => {
+                tracing::warn!("{} --version timed out", engine.command());
+                (None, None)
+            }
+        };
+
+        Self {
+            engine,
+            engine_version,
+            distribution,
+            packages: Vec::new(),
+            captured_at: Utc::now(),
+        }
+    }
+
+    /// Compare this environment against a later one
+    pub fn diff(&self, to: &CompileEnvironment) -> CompileEnvironmentDiff {
+        let from_packages: BTreeMap<&str, &PackageFile> =
+            self.packages.iter().map(|p| (p.name.as_str(), p)).collect();
+        let to_packages: BTreeMap<&str, &PackageFile> =
+            to.packages.iter().map(|p| (p.name.as_str(), p)).collect();
+
+        let packages_added = to_packages
+            .iter()
+            .filter(|(name, _)| !from_packages.contains_key(*name))
+            .map(|(_, package)| (*package).clone())
+            .collect();
+
+        let packages_removed = from_packages
+            .iter()
+            .filter(|(name, _)| !to_packages.contains_key(*name))
+            .map(|(_, package)| (*package).clone())
+            .collect();
+
+        let packages_changed = from_packages
+            .iter()
+            .filter_map(|(name, from)| {
+                let to = to_packages.get(name)?;
+                (from.path != to.path).then(|| PackageChange {
+                    name: name.to_string(),
+                    from_path: from.path.clone(),
+                    to_path: to.path.clone(),
+                })
+            })
+            .collect();
+
+        CompileEnvironmentDiff {
+            engine: changed(&self.engine, &to.engine),
+            engine_version: changed(&self.engine_version, &to.engine_version),
+            distribution: changed(&self.distribution, &to.distribution),
+            packages_added,
+            packages_removed,
+            packages_changed,
+        }
+    }
+}
+
+fn changed<T: Clone + PartialEq>(from: &T, to: &T) -> Option<Change<T>> {
+    (from != to).then(|| Change {
+        from: from.clone(),
+        to: to.clone(),
+    })
+}
+
+/// Extract the version line and distribution from `<engine> --version` output,
+/// e.g. `pdfTeX 3.141592653-2.6-1.40.25 (TeX Live 2023/Debian)`
+pub fn parse_engine_version(output: &str) -> (Option<String>, Option<TexDistribution>) {
+    let Some(version_line) = output.lines().map(str::trim).find(|line| !line.is_empty()) else {
+        return (None, None);
+    };
+
+    let distribution_regex = Regex::new(r"(TeX Live|MiKTeX)\s+([0-9][0-9.]*)").unwrap();
+    let distribution = distribution_regex.captures(version_line).map(|captures| TexDistribution {
+        name: captures[1].to_string(),
+        release: captures[2].trim_end_matches('.').to_string(),
+    });
+
+    (Some(version_line.to_string()), distribution)
+}
+
+/// Parse the package and class files from a `-recorder` .fls file
+pub fn parse_fls(contents: &str) -> Vec<PackageFile> {
+    let mut packages: BTreeMap<String, PackageFile> = BTreeMap::new();
+
+    for line in contents.lines() {
+        let Some(path) = line.strip_prefix("INPUT ") else {
+            continue;
+        };
+        let path = path.trim();
+
+        let file = Path::new(path);
+        let is_package = file
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map_or(false, |ext| PACKAGE_EXTENSIONS.contains(&ext));
+        if !is_package {
+            continue;
+        }
+
+        if let Some(name) = file.file_name().and_then(|name| name.to_str()) {
+            // Keep the first resolution, which is the one TeX actually loaded
+            packages.entry(name.to_string()).or_insert_with(|| PackageFile {
+                name: name.to_string(),
+                path: path.to_string(),
+            });
+        }
+    }
+
+    packages.into_values().collect()
+}
+
+/// Parse and delete the .fls files left in a job's working directory.
+///
+/// The recorder output is only used for the environment record and is never
+/// kept as an artifact.
+pub async fn collect_recorded_packages(working_directory: &str) -> Vec<PackageFile> {
+    let root = Path::new(working_directory);
+    let mut packages: BTreeMap<String, PackageFile> = BTreeMap::new();
+
+    for dir in [root.to_path_buf(), root.join("output")] {
+        let Ok(mut entries) = tokio::fs::read_dir(&dir).await else {
+            continue;
+        };
+
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            let path = entry.path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some("fls") {
+                continue;
+            }
+
+            match tokio::fs::read_to_string(&path).await {
+                Ok(contents) => {
+                    for package in parse_fls(&contents) {
+                        packages.entry(package.name.clone()).or_insert(package);
+                    }
+                }
+                Err(e) => tracing::warn!("Failed to read recorder file {}: {}", path.display(), e),
+            }
+
+            if let Err(e) = tokio::fs::remove_file(&path).await {
+                tracing::warn!("Failed to remove recorder file {}: {}", path.display(), e);
+            }
+        }
+    }
+
+    packages.into_values().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_engine_version() {
+        let (version, distribution) = parse_engine_version(
+            "pdfTeX 3.141592653-2.6-1.40.25 (TeX Live 2023/Debian)\nkpathsea version 6.3.5\n",
+        );
+        assert_eq!(version.as_deref(), Some("pdfTeX 3.141592653-2.6-1.40.25 (TeX Live 2023/Debian)"));
+        assert_eq!(
+            distribution,
+            Some(TexDistribution { name: "TeX Live".to_string(), release: "2023".to_string() })
+        );
+
+        let (_, distribution) = parse_engine_version("MiKTeX-pdfTeX 4.10 (MiKTeX 22.1)");
+        assert_eq!(distribution.map(|d| d.release), Some("22.1".to_string()));
+
+        assert_eq!(parse_engine_version(""), (None, None));
+    }
+
+    #[test]
+    fn test_parse_fls() {
+        let fls = "PWD /tmp/job\n\
+            INPUT /usr/share/texlive/texmf-dist/tex/latex/base/article.cls\n\
+            INPUT /usr/share/texlive/texmf-dist/tex/latex/amsmath/amsmath.sty\n\
+            INPUT /usr/share/texlive/texmf-dist/tex/latex/amsmath/amsmath.sty\n\
+            INPUT ./main.tex\n\
+            OUTPUT main.pdf\n";
+
+        let packages = parse_fls(fls);
+        assert_eq!(packages.len(), 2);
+        assert_eq!(packages[0].name, "amsmath.sty");
+        assert_eq!(packages[1].name, "article.cls");
+    }
+
+    #[test]
+    fn test_environment_diff() {
+        let package = |name: &str, path: &str| PackageFile { name: name.to_string(), path: path.to_string() };
+        let from = CompileEnvironment {
+            engine: LatexEngine::Pdflatex,
+            engine_version: Some("pdfTeX 1.40.24 (TeX Live 2022)".to_string()),
+            distribution: Some(TexDistribution { name: "TeX Live".to_string(), release: "2022".to_string() }),
+            packages: vec![package("amsmath.sty", "/texlive/2022/amsmath.sty"), package("old.sty", "/old.sty")],
+            captured_at: Utc::now(),
+        };
+        let to = CompileEnvironment {
+            engine_version: Some("pdfTeX 1.40.25 (TeX Live 2023)".to_string()),
+            distribution: Some(TexDistribution { name: "TeX Live".to_string(), release: "2023".to_string() }),
+            packages: vec![package("amsmath.sty", "/texlive/2023/amsmath.sty"), package("new.sty", "/new.sty")],
+            ..from.clone()
+        };
+
+        let diff = from.diff(&to);
+        assert!(diff.engine.is_none());
+        assert!(diff.distribution.is_some());
+        assert_eq!(diff.packages_added, vec![package("new.sty", "/new.sty")]);
+        assert_eq!(diff.packages_removed, vec![package("old.sty", "/old.sty")]);
+        assert_eq!(diff.packages_changed.len(), 1);
+        assert_eq!(diff.packages_changed[0].name, "amsmath.sty");
+
+        assert!(from.diff(&from).is_empty());
+    }
+}
@@ -0,0 +1,463 @@
+//! Image optimization for compiles
+//!
+//! Photos straight off a camera make compiles slow and PDFs enormous. With a
+//! project's `images.optimize_images_on_compile` setting on, PNG and JPEG
+//! files larger than `OPTIMIZE_ABOVE_BYTES`, or than `max_dimension` on a
+//! side, are transcoded as they are written to the build directory: scaled
+//! down to fit `max_dimension` and encoded as JPEG at `jpeg_quality`, or as
+//! PNG when they have transparency. The engines tell image formats apart by
+//! their content, so a PNG that became a JPEG keeps its name and the
+//! `\includegraphics` lines keep working. The files in storage are never
+//! touched, and a result that is not smaller is not used.
+//!
+//! Nothing else is touched: PDF figures, SVG and EPS are vector graphics that
+//! recompression would break. Transcoding is deterministic, the same image
+//! and settings always giving the same bytes, and the settings are part of
+//! the build directory's fingerprint. It is bounded by `IMAGE_TIME_BUDGET`
+//! per job; an image that cannot be decoded, or comes after the budget is
+//! spent, is compiled as uploaded with a warning.
+
+use image::codecs::jpeg::JpegEncoder;
+use image::imageops::FilterType;
+use image::{DynamicImage, ImageFormat, ImageReader, Limits};
+use serde::{Deserialize, Serialize};
+use std::io::Cursor;
+use std::ops::RangeInclusive;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use super::chat_attachment;
+use super::compilation::CompilationJob;
+use super::project::ProjectSettings;
+use crate::error::AppError;
+
+/// Images at most this large and within `max_dimension` are compiled as uploaded
+pub const OPTIMIZE_ABOVE_BYTES: usize = 1024 * 1024;
+
+/// Accepted `max_dimension` values, in pixels
+pub const MAX_DIMENSIONS: RangeInclusive<u32> = 256..=8192;
+
+/// Accepted `jpeg_quality` values
+pub const JPEG_QUALITIES: RangeInclusive<u8> = 40..=95;
+
+/// Time a job may spend transcoding images
+pub const IMAGE_TIME_BUDGET: Duration = Duration::from_secs(30);
+
+/// Longest side of an image that is decoded at all
+const MAX_DECODED_DIMENSION: u32 = 20_000;
+
+/// Memory decoding one image may take
+const MAX_DECODE_ALLOC: u64 = 512 * 1024 * 1024;
+
+/// Extensions of the files that may be transcoded
+const OPTIMIZED_EXTENSIONS: &[&str] = &["png", "jpg", "jpeg"];
+
+/// How a project's images are compiled
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(default)]
+pub struct ImageSettings {
+    /// Transcode oversized PNG and JPEG images for compiles; the uploaded
+    /// files stay as they are
+    pub optimize_images_on_compile: bool,
+    /// Longest side optimized images are scaled down to, 256 to 8192 pixels
+    pub max_dimension: u32,
+    /// Quality of images encoded as JPEG, 40 to 95
+    pub jpeg_quality: u8,
+}
+
+impl Default for ImageSettings {
+    fn default() -> Self {
+        Self {
+            optimize_images_on_compile: false,
+            max_dimension: 2400,
+            jpeg_quality: 85,
+        }
+    }
+}
+
+impl ImageSettings {
+    pub fn validate(&self) -> Result<(), AppError> {
+        if !MAX_DIMENSIONS.contains(&self.max_dimension) {
+            return Err(AppError::Validation(format!(
+                "Maximum image dimension must be between {} and {} pixels",
+                MAX_DIMENSIONS.start(),
+                MAX_DIMENSIONS.end()
+            )));
+        }
+
+        if !JPEG_QUALITIES.contains(&self.jpeg_quality) {
+            return Err(AppError::Validation(format!(
+                "JPEG quality must be between {} and {}",
+                JPEG_QUALITIES.start(),
+                JPEG_QUALITIES.end()
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Image settings of a project
+    pub async fn for_project(db: &sqlx::PgPool, project_id: Uuid) -> Result<Self, AppError> {
+        let settings = sqlx::query_scalar::<_, sqlx::types::Json<ProjectSettings>>(
+            "SELECT settings FROM projects WHERE id = $1"
+        )
+        .bind(project_id)
+        .fetch_optional(db)
+        .await
+        .map_err(AppError::Database)?;
+
+        Ok(settings.map(|settings| settings.0.images).unwrap_or_default())
+    }
+}
+
+/// An image compiled in a smaller form than uploaded
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct OptimizedImage {
+    pub path: String,
+    pub original_bytes: i64,
+    pub optimized_bytes: i64,
+    /// Dimensions as compiled, in pixels
+    pub width: u32,
+    pub height: u32,
+}
+
+/// What image optimization did for a job
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct ImageOptimizationReport {
+    pub images: Vec<OptimizedImage>,
+    pub bytes_saved: i64,
+    /// Images compiled as uploaded because they could not be optimized
+    pub warnings: Vec<String>,
+}
+
+impl ImageOptimizationReport {
+    pub fn add(&mut self, image: OptimizedImage) {
+        self.bytes_saved += image.original_bytes - image.optimized_bytes;
+        self.images.push(image);
+    }
+
+    /// One line for the compile progress, when there is anything to tell
+    pub fn summary(&self) -> Option<String> {
+        if self.images.is_empty() && self.warnings.is_empty() {
+            return None;
+        }
+        let mut summary = format!("Optimized {} images, saving {} bytes", self.images.len(), self.bytes_saved);
+        if !self.warnings.is_empty() {
+            summary.push_str(&format!("; {} left as uploaded", self.warnings.len()));
+        }
+        Some(summary)
+    }
+}
+
+/// What became of an image written for a compile
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ImageOutcome {
+    /// Not an image that is optimized, or small enough already
+    Unchanged,
+    Optimized(OptimizedImage),
+    /// Compiled as uploaded, with a warning
+    Skipped,
+}
+
+/// An image transcoded by [`optimize`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Transcoded {
+    pub bytes: Vec<u8>,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Whether the file at `path` is one that may be transcoded
+pub fn is_optimizable(path: &str) -> bool {
+    std::path::Path::new(path)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| OPTIMIZED_EXTENSIONS.contains(&ext.to_ascii_lowercase().as_str()))
+}
+
+/// Transcode a PNG or JPEG image, `None` when it is small enough already or
+/// transcoding would not make it smaller
+///
+/// EXIF orientation is left alone, as the engines ignore it too.
+pub fn optimize(bytes: &[u8], settings: &ImageSettings) -> Result<Option<Transcoded>, String> {
+    let format = match chat_attachment::sniff_media_type(bytes) {
+        Some("image/png") => ImageFormat::Png,
+        Some("image/jpeg") => ImageFormat::Jpeg,
+        _ => return Err("not a PNG or JPEG image".to_string()),
+    };
+    let reader = || {
+        let mut limits = Limits::default();
+        limits.max_image_width = Some(MAX_DECODED_DIMENSION);
+        limits.max_image_height = Some(MAX_DECODED_DIMENSION);
+        limits.max_alloc = Some(MAX_DECODE_ALLOC);
+        let mut reader = ImageReader::with_format(Cursor::new(bytes), format);
+        reader.limits(limits);
+        reader
+    };
+
+    let (width, height) = reader().into_dimensions().map_err(|e| e.to_string())?;
+    let oversized = width.max(height) > settings.max_dimension;
+    if !oversized && bytes.len() <= OPTIMIZE_ABOVE_BYTES {
+        return Ok(None);
+    }
+
+    let mut image = reader().decode().map_err(|e| e.to_string())?;
+    if oversized {
+        image = image.resize(settings.max_dimension, settings.max_dimension, FilterType::Lanczos3);
+    }
+
+    let mut transcoded = Vec::new();
+    let encoded = if has_transparency(&image) {
+        image.write_to(&mut Cursor::new(&mut transcoded), ImageFormat::Png)
+    } else {
+        JpegEncoder::new_with_quality(&mut transcoded, settings.jpeg_quality).encode_image(&image.to_rgb8())
+    };
+    encoded.map_err(|e| e.to_string())?;
+
+    if transcoded.len() >= bytes.len() {
+        return Ok(None);
+    }
+    Ok(Some(Transcoded { bytes: transcoded, width: image.width(), height: image.height() }))
+}
+
+/// Whether any pixel is not fully opaque; an alpha channel alone does not count
+fn has_transparency(image: &DynamicImage) -> bool {
+    image.color().has_alpha() && image.to_rgba8().pixels().any(|pixel| pixel.0[3] < u8::MAX)
+}
+
+/// Transcodes the images of one job within [`IMAGE_TIME_BUDGET`]
+#[derive(Debug)]
+pub struct ImageOptimizer {
+    settings: ImageSettings,
+    spent: Duration,
+    pub report: ImageOptimizationReport,
+}
+
+impl ImageOptimizer {
+    /// `None` when the project does not optimize images
+    pub fn new(settings: &ImageSettings) -> Option<Self> {
+        settings.optimize_images_on_compile.then(|| Self {
+            settings: settings.clone(),
+            spent: Duration::ZERO,
+            report: ImageOptimizationReport::default(),
+        })
+    }
+
+    /// The bytes to write for the file at `path`, off the async runtime
+    pub async fn materialize(&mut self, path: &str, bytes: Vec<u8>) -> (Vec<u8>, ImageOutcome) {
+        if !is_optimizable(path) {
+            return (bytes, ImageOutcome::Unchanged);
+        }
+        if self.spent >= IMAGE_TIME_BUDGET {
+            self.report.warnings.push(format!("{}: left as uploaded, the time for optimizing images ran out", path));
+            return (bytes, ImageOutcome::Skipped);
+        }
+
+        let started = Instant::now();
+        let settings = self.settings.clone();
+        let shared = Arc::new(bytes);
+        let image = shared.clone();
+        let result = tokio::task::spawn_blocking(move || optimize(&image, &settings))
+            .await
+            .unwrap_or_else(|e| Err(format!("decoding failed: {}", e)));
+        let bytes = Arc::try_unwrap(shared).unwrap_or_else(|shared| shared.as_ref().clone());
+        self.spent += started.elapsed();
+
+        match result {
+            Ok(Some(transcoded)) => {
+                let image = OptimizedImage {
+                    path: path.to_string(),
+                    original_bytes: bytes.len() as i64,
+                    optimized_bytes: transcoded.bytes.len() as i64,
+                    width: transcoded.width,
+                    height: transcoded.height,
+                };
+                self.report.add(image.clone());
+                (transcoded.bytes, ImageOutcome::Optimized(image))
+            }
+            Ok(None) => (bytes, ImageOutcome::Unchanged),
+            Err(e) => {
+                tracing::warn!("Compiling image {} as uploaded: {}", path, e);
+                self.report.warnings.push(format!("{}: left as uploaded, {}", path, e));
+                (bytes, ImageOutcome::Skipped)
+            }
+        }
+    }
+}
+
+impl CompilationJob {
+    /// Images transcoded for the job, when its project optimizes them
+    pub fn image_report(&self) -> Option<ImageOptimizationReport> {
+        self.image_optimization.clone().and_then(|report| serde_json::from_value(report).ok())
+    }
+
+    /// Record what image optimization did for the job
+    pub async fn record_image_optimization(
+        &self,
+        db: &sqlx::PgPool,
+        report: &ImageOptimizationReport,
+    ) -> Result<(), AppError> {
+        sqlx::query("UPDATE compilation_jobs SET image_optimization = $1, updated_at = NOW() WHERE id = $2")
+            .bind(serde_json::to_value(report)?)
+            .bind(self.id)
+            .execute(db)
+            .await
+            .map_err(AppError::Database)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::file::File;
+    use crate::models::incremental_build;
+    use crate::models::project_limits::ProjectLimits;
+    use crate::models::ContentType;
+    use crate::testing::{create_test_file, create_test_job, create_test_project, create_test_user, test_state, TestDb};
+    use image::{Rgb, RgbImage, Rgba, RgbaImage};
+    use tokio::process::Command;
+
+    fn settings() -> ImageSettings {
+        ImageSettings { optimize_images_on_compile: true, max_dimension: 600, jpeg_quality: 80 }
+    }
+
+    /// A photo-like image: noisy enough that PNG compresses it badly
+    fn photo(width: u32, height: u32) -> DynamicImage {
+        DynamicImage::ImageRgb8(RgbImage::from_fn(width, height, |x, y| {
+            let noise = (x.wrapping_mul(7919) ^ y.wrapping_mul(104_729)).wrapping_mul(2_654_435_761) >> 24;
+            Rgb([(x % 256) as u8, (y % 256) as u8, noise as u8])
+        }))
+    }
+
+    fn encode(image: &DynamicImage, format: ImageFormat) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        image.write_to(&mut Cursor::new(&mut bytes), format).unwrap();
+        bytes
+    }
+
+    fn dimensions(bytes: &[u8]) -> (u32, u32) {
+        let image = image::load_from_memory(bytes).unwrap();
+        (image.width(), image.height())
+    }
+
+    #[test]
+    fn test_oversized_images_are_capped_deterministically() {
+        let png = encode(&photo(1200, 800), ImageFormat::Png);
+        let transcoded = optimize(&png, &settings()).unwrap().expect("the photo should be optimized");
+        assert_eq!((transcoded.width, transcoded.height), (600, 400));
+        assert_eq!(dimensions(&transcoded.bytes), (600, 400));
+        // Without transparency it became a JPEG
+        assert_eq!(chat_attachment::sniff_media_type(&transcoded.bytes), Some("image/jpeg"));
+        assert!(transcoded.bytes.len() < png.len());
+        assert_eq!(optimize(&png, &settings()).unwrap(), Some(transcoded));
+
+        // Transparency survives
+        let overlay = DynamicImage::ImageRgba8(RgbaImage::from_fn(900, 1200, |x, y| {
+            Rgba([(x % 256) as u8, (y % 256) as u8, ((x ^ y) % 256) as u8, (x % 200) as u8])
+        }));
+        let transcoded = optimize(&encode(&overlay, ImageFormat::Png), &settings()).unwrap().unwrap();
+        assert_eq!(chat_attachment::sniff_media_type(&transcoded.bytes), Some("image/png"));
+        assert_eq!(dimensions(&transcoded.bytes), (450, 600));
+        assert!(image::load_from_memory(&transcoded.bytes).unwrap().color().has_alpha());
+
+        // Small images are compiled as uploaded
+        assert_eq!(optimize(&encode(&photo(300, 200), ImageFormat::Jpeg), &settings()).unwrap(), None);
+    }
+
+    #[test]
+    fn test_only_png_and_jpeg_are_touched() {
+        assert!(is_optimizable("figures/photo.JPG"));
+        assert!(is_optimizable("scan.jpeg") && is_optimizable("plot.png"));
+        for path in ["figures/plot.pdf", "diagram.svg", "old.eps", "main.tex", "png"] {
+            assert!(!is_optimizable(path), "{} would be transcoded", path);
+        }
+
+        let mut corrupt = vec![0xFF, 0xD8, 0xFF, 0xE0];
+        corrupt.resize(4096, 0x42);
+        assert!(optimize(&corrupt, &settings()).is_err());
+        assert!(optimize(b"%PDF-1.5\n", &settings()).is_err());
+
+        assert!(ImageSettings { max_dimension: 100, ..settings() }.validate().is_err());
+        assert!(ImageSettings { jpeg_quality: 100, ..settings() }.validate().is_err());
+        assert!(settings().validate().is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_corrupt_images_warn_without_failing_the_job() {
+        let Some(db) = TestDb::start().await else { return };
+        let storage = tempfile::tempdir().unwrap();
+        let mut state = test_state(&db).await;
+        let mut config = (*state.config).clone();
+        config.features.file_storage.local_path = storage.path().to_string_lossy().into_owned();
+        state.config = std::sync::Arc::new(config);
+        let root = &state.config.features.file_storage.local_path;
+
+        let user = create_test_user(&db.pool).await;
+        let project = create_test_project(&db.pool, &user, false).await;
+        let settings = serde_json::json!({ "images": settings() });
+        sqlx::query("UPDATE projects SET settings = $1 WHERE id = $2")
+            .bind(settings)
+            .bind(project.id)
+            .execute(&db.pool)
+            .await
+            .unwrap();
+        create_test_file(&db.pool, &project, &user).await;
+        let png = encode(&photo(1200, 800), ImageFormat::Png);
+        let pdf = b"%PDF-1.5\n% a figure\n".repeat(100_000);
+        let mut corrupt = vec![0xFF, 0xD8, 0xFF, 0xE0];
+        corrupt.resize(2 * OPTIMIZE_ABOVE_BYTES, 0x42);
+        for (path, bytes) in [("figures/photo.png", &png), ("figures/plot.pdf", &pdf), ("broken.jpg", &corrupt)] {
+            let name = path.rsplit('/').next().unwrap();
+            let limits = ProjectLimits::default();
+            File::create_from_bytes(&db.pool, root, &limits, project.id, name, path, ContentType::Image, bytes, user.id)
+                .await
+                .unwrap();
+        }
+        let files = File::list_all_for_project(&db.pool, project.id).await.unwrap();
+        let work = tempfile::tempdir().unwrap();
+
+        let build = || async {
+            let mut job = create_test_job(&db.pool, &project, &user).await;
+            job.working_directory = work.path().to_string_lossy().into_owned();
+            job.args = vec!["-output-directory=output".to_string(), "main.tex".to_string()];
+            let engine = |root: &std::path::Path| {
+                let mut command = Command::new("sh");
+                command.arg("-c").arg("mkdir -p output && cp main.tex output/main.pdf").current_dir(root);
+                command
+            };
+            let run = incremental_build::execute(&state, &job, &files, engine, None).await.unwrap().unwrap();
+            (job, run)
+        };
+
+        let (job, run) = build().await;
+        assert!(matches!(run.outcome, crate::models::compile_cancel::RunOutcome::Exited { exit_code: 0, .. }));
+        let report = run.images.unwrap();
+        assert_eq!(report.images.len(), 1);
+        assert_eq!(report.images[0].path, "figures/photo.png");
+        assert_eq!((report.images[0].width, report.images[0].height), (600, 400));
+        assert_eq!(report.bytes_saved, report.images[0].original_bytes - report.images[0].optimized_bytes);
+        assert_eq!(report.warnings.len(), 1);
+        assert!(report.warnings[0].starts_with("broken.jpg: left as uploaded"), "{:?}", report.warnings);
+
+        // The working directory has the optimized copy, storage the original
+        let compiled = std::fs::read(work.path().join("figures/photo.png")).unwrap();
+        assert_eq!(dimensions(&compiled), (600, 400));
+        let stored = files.iter().find(|file| file.path == "figures/photo.png").unwrap();
+        assert_eq!(stored.read_bytes(root).await.unwrap(), png);
+        assert_eq!(std::fs::read(work.path().join("figures/plot.pdf")).unwrap(), pdf);
+        assert_eq!(std::fs::read(work.path().join("broken.jpg")).unwrap(), corrupt);
+
+        let recorded = CompilationJob::find_by_id(&db.pool, job.id, user.id).await.unwrap().unwrap();
+        assert_eq!(recorded.image_report(), Some(report.clone()));
+
+        // An incremental build keeps the optimized copy and tries the corrupt image again
+        let (_, run) = build().await;
+        assert!(run.incremental);
+        assert_eq!(run.files_refreshed, 1);
+        assert_eq!(run.images, Some(report));
+    }
+}
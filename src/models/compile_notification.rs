@@ -0,0 +1,540 @@
+//! Compile completion notifications
+//!
+//! When a job finishes its creator is told according to their
+//! `CompileNotificationPreference`: an in-app notification, pushed over the
+//! WebSocket when they are connected, and optionally an email with the first
+//! few diagnostics. Someone iterating on a document would drown in mail, so
+//! once `EMAILS_BEFORE_DIGEST` outcome emails went out within the digest
+//! window, further outcomes are held back and `CompileDigestTask` sends them
+//! as a single digest. A bulk compile is reported once more, in-app, when the
+//! last of its jobs finished, with the outcome of each.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use std::sync::Arc;
+use std::time::Duration;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use super::compilation::CompilationJob;
+use super::compile_batch::{BatchCounts, BatchMemberOutcome, BatchStatus, CompileBatch};
+use super::notification::Notification;
+use super::user::User;
+use super::CompilationStatus;
+use crate::email::templates::{CompileDigestEmail, CompileDigestEntry, CompileOutcomeEmail};
+use crate::email::{Locale, Mailer};
+use crate::error::AppError;
+use crate::websocket::{UserChannels, WsMessage};
+
+/// Outcome emails sent individually per window before the rest go into a digest
+pub const EMAILS_BEFORE_DIGEST: i64 = 3;
+
+/// Window over which outcome emails are counted, and the longest a held-back
+/// outcome waits for its digest
+pub const DIGEST_WINDOW_MINUTES: i64 = 60;
+
+/// Diagnostics quoted in an outcome email
+pub const MAX_EMAIL_DIAGNOSTICS: usize = 5;
+
+/// How a user hears about their finished compilations
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, sqlx::Type, ToSchema)]
+pub enum CompileNotificationPreference {
+    #[serde(rename = "none")]
+    #[sqlx(rename = "none")]
+    None,
+    #[serde(rename = "in_app")]
+    #[sqlx(rename = "in_app")]
+    #[default]
+    InApp,
+    #[serde(rename = "in_app_and_email")]
+    #[sqlx(rename = "in_app_and_email")]
+    InAppAndEmail,
+    /// In-app and email, but only when the compilation failed
+    #[serde(rename = "failures_only")]
+    #[sqlx(rename = "failures_only")]
+    FailuresOnly,
+}
+
+/// Channels a finished job is reported on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Delivery {
+    pub in_app: bool,
+    pub email: bool,
+}
+
+impl CompileNotificationPreference {
+    pub fn delivery(self, succeeded: bool) -> Delivery {
+        match self {
+            Self::None => Delivery { in_app: false, email: false },
+            Self::InApp => Delivery { in_app: true, email: false },
+            Self::InAppAndEmail => Delivery { in_app: true, email: true },
+            Self::FailuresOnly => Delivery { in_app: !succeeded, email: !succeeded },
+        }
+    }
+
+    /// Preference of a user; users without stored preferences get the default
+    pub async fn for_user(db: &sqlx::PgPool, user_id: Uuid) -> Result<Self, AppError> {
+        let preference = sqlx::query_scalar::<_, CompileNotificationPreference>(
+            "SELECT compile_notifications FROM user_preferences WHERE user_id = $1"
+        )
+        .bind(user_id)
+        .fetch_optional(db)
+        .await
+        .map_err(AppError::Database)?;
+
+        Ok(preference.unwrap_or_default())
+    }
+}
+
+/// What happens to an outcome email
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EmailPlan {
+    Send,
+    /// Hold it back for the digest
+    Digest,
+}
+
+/// Decide on an outcome email given how many the user got within the window
+pub fn plan_email(emails_in_window: i64) -> EmailPlan {
+    if emails_in_window < EMAILS_BEFORE_DIGEST {
+        EmailPlan::Send
+    } else {
+        EmailPlan::Digest
+    }
+}
+
+/// First errors, then warnings, from a LaTeX log
+pub fn compile_diagnostics(log: &str) -> Vec<String> {
+    let errors = log.lines().filter(|line| line.starts_with("! "));
+    let warnings = log.lines().filter(|line| line.contains("Warning:"));
+
+    errors
+        .chain(warnings)
+        .map(|line| line.trim().to_string())
+        .take(MAX_EMAIL_DIAGNOSTICS)
+        .collect()
+}
+
+/// Diagnostics of a finished job; Typst reports on stderr, LaTeX in its log on stdout
+///
+/// A failed job that was forced past preflight problems leads with them, since
+/// they are the likely cause.
+pub fn job_diagnostics(job: &CompilationJob) -> Vec<String> {
+    let mut diagnostics: Vec<String> = match job.ignored_preflight() {
+        Some(preflight) if job.status != CompilationStatus::Success => preflight
+            .problems
+            .iter()
+            .map(|problem| format!("Compiled despite preflight problem: {}", problem.message))
+            .collect(),
+        _ => Vec::new(),
+    };
+    if job.engine.is_typst() {
+        diagnostics.extend(super::typst::diagnostics(job.stderr.as_deref().unwrap_or_default()));
+    } else {
+        diagnostics.extend(compile_diagnostics(job.stdout.as_deref().unwrap_or_default()));
+    }
+    if let Some(report) = job.image_report() {
+        diagnostics.extend(report.warnings);
+    }
+    diagnostics.truncate(MAX_EMAIL_DIAGNOSTICS);
+    diagnostics
+}
+
+/// One-line outcome of a job, used as the notification body
+pub fn outcome_summary(project_name: &str, succeeded: bool) -> String {
+    if succeeded {
+        format!("\"{}\" compiled successfully.", project_name)
+    } else {
+        format!("\"{}\" failed to compile.", project_name)
+    }
+}
+
+/// Title of the in-app notification of a job
+pub fn outcome_title(project_name: &str, succeeded: bool) -> String {
+    if succeeded {
+        format!("Compilation succeeded: {}", project_name)
+    } else {
+        format!("Compilation failed: {}", project_name)
+    }
+}
+
+/// A single outcome email
+pub fn outcome_email(project_name: &str, succeeded: bool, diagnostics: &[String]) -> CompileOutcomeEmail {
+    CompileOutcomeEmail {
+        project_name: project_name.to_string(),
+        succeeded,
+        diagnostics: diagnostics.to_vec(),
+    }
+}
+
+/// Outcome email held back for a digest
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct CompileEmail {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub job_id: Uuid,
+    pub project_name: String,
+    pub succeeded: bool,
+    pub summary: String,
+    pub sent_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// A digest covering several outcomes
+pub fn digest_email(emails: &[CompileEmail]) -> CompileDigestEmail {
+    CompileDigestEmail {
+        total: emails.len(),
+        failed: emails.iter().filter(|email| !email.succeeded).count(),
+        entries: emails
+            .iter()
+            .map(|email| CompileDigestEntry {
+                time: email.created_at.format("%H:%M").to_string(),
+                project_name: email.project_name.clone(),
+                succeeded: email.succeeded,
+            })
+            .collect(),
+    }
+}
+
+impl CompileEmail {
+    /// Outcome emails of a user created within the digest window
+    pub async fn count_in_window(db: &sqlx::PgPool, user_id: Uuid) -> Result<i64, AppError> {
+        sqlx::query_scalar::<_, i64>(
+            r#"
+            SELECT COUNT(*) FROM compile_notification_emails
+            WHERE user_id = $1 AND created_at > NOW() - make_interval(mins => $2)
+            "#
+        )
+        .bind(user_id)
+        .bind(DIGEST_WINDOW_MINUTES as i32)
+        .fetch_one(db)
+        .await
+        .map_err(AppError::Database)
+    }
+
+    /// Record an outcome email, as sent or waiting for the digest
+    pub async fn record(
+        db: &sqlx::PgPool,
+        job: &CompilationJob,
+        project_name: &str,
+        succeeded: bool,
+        summary: &str,
+        sent: bool,
+    ) -> Result<(), AppError> {
+        sqlx::query(
+            r#"
+            INSERT INTO compile_notification_emails (user_id, job_id, project_name, succeeded, summary, sent_at)
+            VALUES ($1, $2, $3, $4, $5, CASE WHEN $6 THEN NOW() END)
+            "#
+        )
+        .bind(job.user_id)
+        .bind(job.id)
+        .bind(project_name)
+        .bind(succeeded)
+        .bind(summary)
+        .bind(sent)
+        .execute(db)
+        .await
+        .map_err(AppError::Database)?;
+
+        Ok(())
+    }
+
+    /// Held-back emails of users whose oldest one has waited a full window
+    pub async fn due_for_digest(db: &sqlx::PgPool) -> Result<Vec<Self>, AppError> {
+        sqlx::query_as::<_, CompileEmail>(
+            r#"
+            SELECT * FROM compile_notification_emails
+            WHERE sent_at IS NULL AND user_id IN (
+                SELECT user_id FROM compile_notification_emails
+                WHERE sent_at IS NULL
+                GROUP BY user_id
+                HAVING MIN(created_at) <= NOW() - make_interval(mins => $1)
+            )
+            ORDER BY user_id, created_at
+            "#
+        )
+        .bind(DIGEST_WINDOW_MINUTES as i32)
+        .fetch_all(db)
+        .await
+        .map_err(AppError::Database)
+    }
+
+    pub async fn mark_sent(db: &sqlx::PgPool, ids: &[Uuid]) -> Result<(), AppError> {
+        sqlx::query("UPDATE compile_notification_emails SET sent_at = NOW() WHERE id = ANY($1)")
+            .bind(ids)
+            .execute(db)
+            .await
+            .map_err(AppError::Database)?;
+
+        Ok(())
+    }
+
+    /// Send one digest per user with held-back emails that are due
+    pub async fn send_due_digests(db: &sqlx::PgPool, mailer: &Mailer) -> Result<usize, AppError> {
+        let due = Self::due_for_digest(db).await?;
+
+        let mut sent = 0;
+        for emails in due.chunk_by(|a, b| a.user_id == b.user_id) {
+            // Inactive users get nothing, but their backlog is cleared all the same
+            if let Some(user) = User::find_by_id(db, emails[0].user_id).await? {
+                let locale = Locale::for_user(db, user.id).await?;
+                match mailer.send(&user.email, locale, &digest_email(emails)).await {
+                    Ok(()) => sent += 1,
+                    Err(e) => tracing::warn!("Failed to send the compile digest of {}: {}", user.id, e),
+                }
+            }
+
+            let ids: Vec<Uuid> = emails.iter().map(|email| email.id).collect();
+            Self::mark_sent(db, &ids).await?;
+        }
+
+        Ok(sent)
+    }
+}
+
+/// Reports finished compilations to their creators
+#[derive(Debug)]
+pub struct CompileNotifier {
+    mailer: Arc<Mailer>,
+    user_channels: Arc<UserChannels>,
+}
+
+impl CompileNotifier {
+    pub fn new(mailer: Arc<Mailer>, user_channels: Arc<UserChannels>) -> Self {
+        Self { mailer, user_channels }
+    }
+
+    /// Notify the job's creator of a finished job as their preference asks
+    pub async fn job_finished(&self, db: &sqlx::PgPool, job: &CompilationJob) -> Result<(), AppError> {
+        let succeeded = match job.status {
+            CompilationStatus::Success => true,
+            CompilationStatus::Error => false,
+            _ => return Ok(()),
+        };
+
+        let preference = CompileNotificationPreference::for_user(db, job.user_id).await?;
+        let delivery = preference.delivery(succeeded);
+        if !delivery.in_app && !delivery.email {
+            return Ok(());
+        }
+
+        let Some(user) = User::find_by_id(db, job.user_id).await? else {
+            return Ok(());
+        };
+
+        let project_name = project_name(db, job.project_id).await?;
+        let summary = outcome_summary(&project_name, succeeded);
+
+        if delivery.in_app {
+            let notification = Notification::create(
+                db,
+                user.id,
+                "compile_finished",
+                &outcome_title(&project_name, succeeded),
+                &summary,
+                Some(serde_json::json!({
+                    "job_id": job.id,
+                    "project_id": job.project_id,
+                    "status": job.status,
+                })),
+            )
+            .await?;
+
+            self.user_channels.send(user.id, WsMessage::Notification { notification });
+        }
+
+        if delivery.email && self.mailer.is_enabled() {
+            let plan = plan_email(CompileEmail::count_in_window(db, user.id).await?);
+            if plan == EmailPlan::Send {
+                let mut diagnostics = job_diagnostics(job);
+                if diagnostics.is_empty() {
+                    diagnostics.extend(job.error_message.clone());
+                }
+                let locale = Locale::for_user(db, user.id).await?;
+                self.mailer
+                    .send(&user.email, locale, &outcome_email(&project_name, succeeded, &diagnostics))
+                    .await?;
+            }
+            CompileEmail::record(db, job, &project_name, succeeded, &summary, plan == EmailPlan::Send).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Notify the creator of a batch whose last member finished, in-app, with
+    /// the outcome of every member
+    ///
+    /// The batch counts as succeeded when every member did; a batch whose
+    /// members were all cancelled is not reported.
+    pub async fn batch_finished(
+        &self,
+        db: &sqlx::PgPool,
+        batch: &CompileBatch,
+        members: &[CompilationJob],
+    ) -> Result<(), AppError> {
+        let counts = BatchCounts::of(members);
+        if counts.cancelled == counts.finished() {
+            return Ok(());
+        }
+
+        let status = counts.status();
+        let preference = CompileNotificationPreference::for_user(db, batch.user_id).await?;
+        if !preference.delivery(status == BatchStatus::Complete).in_app {
+            return Ok(());
+        }
+
+        let project_name = project_name(db, batch.project_id).await?;
+        let members: Vec<BatchMemberOutcome> = members.iter().map(BatchMemberOutcome::from).collect();
+        let notification = Notification::create(
+            db,
+            batch.user_id,
+            "compile_batch_finished",
+            &format!("Bulk compilation finished: {}", project_name),
+            &batch_summary(&project_name, &counts),
+            Some(serde_json::json!({
+                "batch_id": batch.id,
+                "project_id": batch.project_id,
+                "status": status,
+                "counts": counts,
+                "members": members,
+            })),
+        )
+        .await?;
+
+        self.user_channels.send(batch.user_id, WsMessage::Notification { notification });
+        Ok(())
+    }
+}
+
+/// Name of a job's project for its notifications
+async fn project_name(db: &sqlx::PgPool, project_id: Uuid) -> Result<String, AppError> {
+    let name = sqlx::query_scalar::<_, String>("SELECT name FROM projects WHERE id = $1")
+        .bind(project_id)
+        .fetch_optional(db)
+        .await
+        .map_err(AppError::Database)?;
+
+    Ok(name.unwrap_or_else(|| "Untitled project".to_string()))
+}
+
+/// One-line outcome of a batch, used as the notification body
+pub fn batch_summary(project_name: &str, counts: &BatchCounts) -> String {
+    format!(
+        "{} of {} targets of \"{}\" compiled successfully.",
+        counts.succeeded,
+        counts.finished(),
+        project_name
+    )
+}
+
+/// Sends compile outcome digests once they are due
+pub struct CompileDigestTask;
+
+impl crate::tasks::PeriodicTask for CompileDigestTask {
+    fn name(&self) -> &'static str {
+        "compile_digest"
+    }
+
+    fn interval(&self) -> Duration {
+        Duration::from_secs(300)
+    }
+
+    fn run<'a>(
+        &'a self,
+        state: &'a crate::server::AppState,
+    ) -> futures::future::BoxFuture<'a, Result<(), AppError>> {
+        Box::pin(async move {
+            let sent = CompileEmail::send_due_digests(&state.db_pool, &state.mailer).await?;
+            if sent > 0 {
+                tracing::info!("Sent {} compile digest emails", sent);
+            }
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::email::EmailTemplates;
+
+    #[test]
+    fn test_delivery_per_preference() {
+        use CompileNotificationPreference::*;
+
+        let none = Delivery { in_app: false, email: false };
+        let in_app = Delivery { in_app: true, email: false };
+        let both = Delivery { in_app: true, email: true };
+
+        assert_eq!(None.delivery(true), none);
+        assert_eq!(None.delivery(false), none);
+        assert_eq!(InApp.delivery(true), in_app);
+        assert_eq!(InApp.delivery(false), in_app);
+        assert_eq!(InAppAndEmail.delivery(true), both);
+        assert_eq!(InAppAndEmail.delivery(false), both);
+        assert_eq!(FailuresOnly.delivery(true), none);
+        assert_eq!(FailuresOnly.delivery(false), both);
+        assert_eq!(CompileNotificationPreference::default(), InApp);
+    }
+
+    #[test]
+    fn test_burst_of_compiles_collapses_into_digest() {
+        let plans: Vec<EmailPlan> = (0..20).map(plan_email).collect();
+
+        let sent = plans.iter().filter(|plan| **plan == EmailPlan::Send).count();
+        assert_eq!(sent as i64, EMAILS_BEFORE_DIGEST);
+        assert!(plans[EMAILS_BEFORE_DIGEST as usize..].iter().all(|plan| *plan == EmailPlan::Digest));
+
+        let now = Utc::now();
+        let held_back: Vec<CompileEmail> = (0..20 - sent)
+            .map(|i| CompileEmail {
+                id: Uuid::new_v4(),
+                user_id: Uuid::nil(),
+                job_id: Uuid::new_v4(),
+                project_name: "Thesis".to_string(),
+                succeeded: i % 4 != 0,
+                summary: outcome_summary("Thesis", i % 4 != 0),
+                sent_at: Option::None,
+                created_at: now,
+            })
+            .collect();
+
+        let digest = digest_email(&held_back);
+        assert_eq!((digest.total, digest.failed), (17, 5));
+
+        let email = EmailTemplates::new("Texler").unwrap().render(Locale::En, &digest).unwrap();
+        assert_eq!(email.subject, "17 compilations finished (5 failed)");
+        assert_eq!(email.text.lines().filter(|line| line.contains("Thesis")).count(), 17);
+    }
+
+    #[test]
+    fn test_outcome_email_quotes_first_diagnostics() {
+        let log = "This is pdfTeX\n\
+                   LaTeX Warning: Reference `fig:a' undefined.\n\
+                   ! Undefined control sequence.\n\
+                   l.12 \\foo\n\
+                   ! Missing $ inserted.\n";
+
+        let diagnostics = compile_diagnostics(log);
+        assert_eq!(
+            diagnostics,
+            vec![
+                "! Undefined control sequence.",
+                "! Missing $ inserted.",
+                "LaTeX Warning: Reference `fig:a' undefined.",
+            ]
+        );
+
+        let email = EmailTemplates::new("Texler")
+            .unwrap()
+            .render(Locale::En, &outcome_email("Thesis", false, &diagnostics))
+            .unwrap();
+        assert_eq!(email.subject, "Compilation failed: Thesis");
+        assert!(email.text.contains("  ! Undefined control sequence.\n"));
+
+        let many = "! error\n".repeat(20);
+        assert_eq!(compile_diagnostics(&many).len(), MAX_EMAIL_DIAGNOSTICS);
+    }
+}
@@ -0,0 +1,268 @@
+//! Checks run before a compile is queued
+//!
+//! Many failed compiles are predictable from what is already known about a
+//! project: a missing main file, includes of deleted files, graphics or
+//! bibliography databases that do not exist. The preflight reads these from
+//! the cached include graph and the LaTeX metadata recorded when files are
+//! saved, never from file contents, so it costs about as much as reading the
+//! graph. Files saved before graphics and bibliographies were recorded name
+//! none until they are saved again.
+//!
+//! Only files the main file reaches are checked. Problems refuse the compile
+//! unless the caller passes `ignore_preflight`; the findings of a compile
+//! forced past them are kept on its job, so a failure can point back to them.
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use super::include_graph::{self, IncludeGraph, IncludeStatus, ResourceKind};
+use super::project::Project;
+use super::LatexEngine;
+use crate::error::AppError;
+use crate::server::AppState;
+
+/// What a finding is about
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum PreflightCode {
+    MainFileMissing,
+    IncludeMissing,
+    IncludeAmbiguous,
+    IncludeCycle,
+    GraphicsMissing,
+    BibliographyMissing,
+}
+
+/// A problem or warning, with the file and line it was found at when known
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct PreflightFinding {
+    pub code: PreflightCode,
+    pub message: String,
+    pub file_id: Option<Uuid>,
+    pub path: Option<String>,
+    pub line_number: Option<i32>,
+}
+
+/// Findings of a preflight
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct CompilePreflight {
+    /// Findings the compile would fail on; they refuse it unless ignored
+    pub problems: Vec<PreflightFinding>,
+    pub warnings: Vec<PreflightFinding>,
+}
+
+impl CompilePreflight {
+    /// Check the compile of `target` against its include graph
+    pub fn check(graph: &IncludeGraph, target: &str, engine: LatexEngine, bibliography_path: Option<&str>) -> Self {
+        let mut preflight = Self::default();
+        if graph.main_file_id.is_none() {
+            preflight.problems.push(PreflightFinding {
+                code: PreflightCode::MainFileMissing,
+                message: format!("The file to compile, {}, does not exist", target),
+                file_id: None,
+                path: Some(target.to_string()),
+                line_number: None,
+            });
+            return preflight;
+        }
+        // The graph follows LaTeX only
+        if engine.is_typst() {
+            return preflight;
+        }
+
+        let nodes: HashMap<Uuid, (&str, bool)> = graph
+            .nodes
+            .iter()
+            .map(|node| (node.file_id, (node.path.as_str(), node.reachable)))
+            .collect();
+        let reachable = |file_id: &Uuid| nodes.get(file_id).is_some_and(|&(_, reachable)| reachable);
+        let at = |file_id: Uuid| nodes.get(&file_id).map_or("?", |&(path, _)| path);
+        let finding = |code, message, from: Uuid, line_number| PreflightFinding {
+            code,
+            message,
+            file_id: Some(from),
+            path: nodes.get(&from).map(|&(path, _)| path.to_string()),
+            line_number,
+        };
+
+        for edge in graph.edges.iter().filter(|edge| reachable(&edge.from)) {
+            match edge.status {
+                IncludeStatus::Resolved => {}
+                IncludeStatus::Missing => preflight.problems.push(finding(
+                    PreflightCode::IncludeMissing,
+                    format!("{} includes {}, which does not exist", at(edge.from), edge.target),
+                    edge.from,
+                    edge.line_number,
+                )),
+                IncludeStatus::Ambiguous => preflight.warnings.push(finding(
+                    PreflightCode::IncludeAmbiguous,
+                    format!(
+                        "{} includes {}, which matches {}",
+                        at(edge.from),
+                        edge.target,
+                        edge.candidates.iter().map(|&candidate| at(candidate)).collect::<Vec<_>>().join(" and ")
+                    ),
+                    edge.from,
+                    edge.line_number,
+                )),
+            }
+        }
+
+        // TeX never finishes an include cycle
+        for cycle in graph.cycles.iter().filter(|cycle| reachable(&cycle[0])) {
+            let mut chain: Vec<&str> = cycle.iter().map(|&file_id| at(file_id)).collect();
+            chain.push(at(cycle[0]));
+            preflight.problems.push(finding(
+                PreflightCode::IncludeCycle,
+                format!("Include cycle: {}", chain.join(" -> ")),
+                cycle[0],
+                None,
+            ));
+        }
+
+        for resource in graph.resources.iter().filter(|resource| !resource.found && reachable(&resource.from)) {
+            let (code, kind) = match resource.kind {
+                ResourceKind::Graphics => (PreflightCode::GraphicsMissing, "graphics"),
+                ResourceKind::Bibliography => (PreflightCode::BibliographyMissing, "bibliography"),
+            };
+            preflight.problems.push(finding(
+                code,
+                format!("{} uses {} {}, which does not exist", at(resource.from), kind, resource.target),
+                resource.from,
+                Some(resource.line_number),
+            ));
+        }
+
+        if let Some(path) = bibliography_path.filter(|path| !graph.has_file(path)) {
+            preflight.problems.push(PreflightFinding {
+                code: PreflightCode::BibliographyMissing,
+                message: format!("The project's bibliography, {}, does not exist", path),
+                file_id: None,
+                path: Some(path.to_string()),
+                line_number: None,
+            });
+        }
+
+        preflight
+    }
+
+    /// Whether the compile should be refused
+    pub fn is_blocking(&self) -> bool {
+        !self.problems.is_empty()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.problems.is_empty() && self.warnings.is_empty()
+    }
+
+    /// Messages of all findings, problems first
+    pub fn messages(&self) -> Vec<String> {
+        self.problems
+            .iter()
+            .chain(&self.warnings)
+            .map(|finding| finding.message.clone())
+            .collect()
+    }
+}
+
+/// Preflight of compiling `target` of a project with `engine`
+pub async fn run(
+    state: &AppState,
+    project: &Project,
+    target: &str,
+    engine: LatexEngine,
+) -> Result<CompilePreflight, AppError> {
+    let graph = include_graph::load(state, project.id, target).await?;
+    Ok(CompilePreflight::check(&graph, target, engine, project.bibliography_path.as_deref()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::file::ResourceInfo;
+    use crate::models::include_graph::{IncludeGraphCache, IncludeSource};
+    use crate::models::ContentType;
+    use std::time::{Duration, Instant};
+
+    fn latex(path: &str, includes: &[&str]) -> IncludeSource {
+        IncludeSource {
+            file_id: Uuid::new_v4(),
+            path: path.to_string(),
+            content_type: ContentType::Latex,
+            includes: includes.iter().map(|include| include.to_string()).collect(),
+            include_lines: (1..=includes.len() as i32).collect(),
+            graphics: Vec::new(),
+            bibliographies: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_problems_of_reachable_files_block() {
+        let main = IncludeSource {
+            graphics: vec![ResourceInfo { target: "plot".to_string(), line_number: 7 }],
+            ..latex("/main.tex", &["other/intro.tex", "gone.tex"])
+        };
+        let intro = latex("/other/intro.tex", &["shared.tex", "../main.tex"]);
+        let shared = [latex("/shared.tex", &[]), latex("/other/shared.tex", &[])];
+        let orphan = latex("/orphan.tex", &["nowhere.tex"]);
+        let graph = IncludeGraph::build([&main, &intro, &shared[0], &shared[1], &orphan], "/main.tex");
+
+        let preflight = CompilePreflight::check(&graph, "/main.tex", LatexEngine::Pdflatex, Some("refs.bib"));
+        let codes: Vec<PreflightCode> = preflight.problems.iter().map(|finding| finding.code).collect();
+        assert_eq!(
+            codes,
+            vec![
+                PreflightCode::IncludeMissing,
+                PreflightCode::IncludeCycle,
+                PreflightCode::GraphicsMissing,
+                PreflightCode::BibliographyMissing,
+            ]
+        );
+        let missing = &preflight.problems[0];
+        assert_eq!((missing.path.as_deref(), missing.line_number), (Some("/main.tex"), Some(2)));
+        assert_eq!(preflight.problems[2].line_number, Some(7));
+        // The orphan's missing include never reaches the compile
+        assert!(preflight.messages().iter().all(|message| !message.contains("nowhere")));
+        assert_eq!(preflight.warnings[0].code, PreflightCode::IncludeAmbiguous);
+
+        let preflight = CompilePreflight::check(&graph, "/thesis.tex", LatexEngine::Pdflatex, None);
+        assert_eq!(preflight.problems.len(), 1);
+        assert_eq!(preflight.problems[0].code, PreflightCode::MainFileMissing);
+
+        let clean = IncludeGraph::build([&latex("/main.tex", &[])], "/main.tex");
+        assert!(CompilePreflight::check(&clean, "/main.tex", LatexEngine::Pdflatex, None).is_empty());
+    }
+
+    #[test]
+    fn test_thousand_file_preflight_is_fast() {
+        let mut sources = vec![latex("/main.tex", &[])];
+        for chapter in 0..100 {
+            sources[0].includes.push(format!("chapters/{}/chapter.tex", chapter));
+            let sections: Vec<String> = (0..9).map(|section| format!("section{}.tex", section)).collect();
+            let sections: Vec<&str> = sections.iter().map(String::as_str).collect();
+            sources.push(latex(&format!("/chapters/{}/chapter.tex", chapter), &sections));
+            for section in 0..9 {
+                sources.push(IncludeSource {
+                    graphics: vec![ResourceInfo { target: format!("figures/{}-{}", chapter, section), line_number: 1 }],
+                    ..latex(&format!("/chapters/{}/section{}.tex", chapter, section), &[])
+                });
+            }
+        }
+
+        let cache = IncludeGraphCache::new();
+        let project_id = Uuid::new_v4();
+        let ticket = cache.begin_build(project_id);
+        cache.insert(project_id, ticket, sources, "/main.tex");
+
+        let started = Instant::now();
+        let graph = cache.get(project_id, "/main.tex").expect("sources are cached");
+        let preflight = CompilePreflight::check(&graph, "/main.tex", LatexEngine::Pdflatex, None);
+        let elapsed = started.elapsed();
+
+        assert_eq!(preflight.problems.len(), 900);
+        // Generous for debug builds; the budget is tens of milliseconds
+        assert!(elapsed < Duration::from_millis(50), "preflight took {:?}", elapsed);
+    }
+}
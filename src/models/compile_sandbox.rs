@@ -0,0 +1,683 @@
+//! Confinement of TeX runs and its compliance report
+//!
+//! Institutions deploying Texler have to document what a compile can touch.
+//! [`confine`] is what every TeX run of this instance gets: paranoid file
+//! access, so names outside the job's directory can neither be read nor
+//! written, shell escape off and no standard input. [`CompileSandbox`] adds
+//! the `LatexConfig` memory limit as an address space limit.
+//!
+//! [`CompileSandbox::report`] describes the environment a job sees: the engine
+//! binaries with their hashes, the TEXMF trees, the global shell-escape
+//! setting, the limits and the paths a job can write. [`CompileSandbox::self_test`]
+//! compiles known-malicious fixtures in the sandbox with every configured TeX
+//! engine and records whether each attack was stopped. Probes run without any
+//! command-line switches, so the sandbox alone has to hold. Both run shortly
+//! after startup and hourly from then on; failed probes degrade readiness
+//! until a self-test passes.
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::process::Command;
+use utoipa::ToSchema;
+
+use super::compilation::PROJECT_WORKING_DIRECTORY_ROOT;
+use super::compile_environment::CompileEnvironment;
+use super::LatexEngine;
+use crate::config::LatexConfig;
+
+/// Variables every TeX run gets, besides `TEXMFOUTPUT`
+pub const SANDBOX_ENV: &[(&str, &str)] = &[("openin_any", "p"), ("openout_any", "p"), ("shell_escape", "f")];
+
+/// TEXMF variables whose trees are reported
+const TEXMF_VARIABLES: &[&str] = &[
+    "TEXMFHOME",
+    "TEXMFVAR",
+    "TEXMFCONFIG",
+    "TEXMFLOCAL",
+    "TEXMFSYSVAR",
+    "TEXMFSYSCONFIG",
+    "TEXMFDIST",
+];
+
+/// How long to wait for `kpsewhich`
+const KPSEWHICH_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How often the environment is reported and the self-test repeated
+const SCAN_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// Printed by a fixture right before its attack
+const PROBE_REACHED: &str = "TEXLER-PROBE-REACHED";
+
+/// Confine a TeX process to `work_dir`: it may only read and write below it
+/// and in the TEXMF trees, cannot run commands, gets no input and is killed
+/// with its handle
+pub fn confine<'a>(command: &'a mut Command, work_dir: &Path) -> &'a mut Command {
+    for (name, value) in SANDBOX_ENV {
+        command.env(name, value);
+    }
+    command
+        .env("TEXMFOUTPUT", work_dir)
+        .current_dir(work_dir)
+        .stdin(Stdio::null())
+        .kill_on_drop(true)
+}
+
+/// Limits of a compile, from `LatexConfig`
+#[derive(Debug, Clone, Copy, Serialize, ToSchema)]
+pub struct ResourceLimits {
+    pub timeout_ms: u64,
+    /// Address space of the engine process
+    pub memory_limit_mb: u64,
+    /// Largest output accepted, in bytes
+    pub output_size_limit: u64,
+}
+
+/// An engine binary as resolved on `PATH`
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct EngineBinary {
+    pub engine: LatexEngine,
+    /// Unset when the engine is not installed on this instance
+    pub path: Option<String>,
+    /// Target of the symlinks, e.g. `pdftex` for `pdflatex`
+    pub resolved_path: Option<String>,
+    /// SHA-256 of the resolved binary
+    pub sha256: Option<String>,
+    pub version: Option<String>,
+}
+
+/// A TEXMF tree visible to compiles
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct TexmfTree {
+    pub variable: String,
+    pub path: String,
+    pub exists: bool,
+    /// Compiles could change what later compiles load
+    pub writable: bool,
+}
+
+/// Environment a compile job runs in
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct SandboxReport {
+    pub engines: Vec<EngineBinary>,
+    pub texmf_trees: Vec<TexmfTree>,
+    /// `shell_escape` in texmf.cnf, unset when kpsewhich is not available
+    pub shell_escape_setting: Option<String>,
+    /// Whether texmf.cnf turns shell escape off for every TeX run of the host
+    pub shell_escape_disabled: bool,
+    /// Variables the sandbox sets for every run, overriding texmf.cnf
+    pub sandbox_env: Vec<String>,
+    pub limits: ResourceLimits,
+    /// Paths a job can write; `{project_id}` stands for the job's project
+    pub writable_paths: Vec<String>,
+    pub generated_at: DateTime<Utc>,
+}
+
+/// An attack the self-test makes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Probe {
+    /// Read `/etc/passwd`; `\input` goes through the same check as `\openin`
+    ReadSystemFile,
+    /// Write a file by absolute path outside the job's directory
+    WriteAbsolutePath,
+    /// Write a file in the parent of the job's directory
+    WriteParentDirectory,
+    /// Run a command through `\write18`
+    ShellEscape,
+    /// Allocate memory until something stops the engine
+    ExhaustMemory,
+}
+
+impl Probe {
+    pub const ALL: [Probe; 5] = [
+        Probe::ReadSystemFile,
+        Probe::WriteAbsolutePath,
+        Probe::WriteParentDirectory,
+        Probe::ShellEscape,
+        Probe::ExhaustMemory,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::ReadSystemFile => "read_system_file",
+            Self::WriteAbsolutePath => "write_absolute_path",
+            Self::WriteParentDirectory => "write_parent_directory",
+            Self::ShellEscape => "shell_escape",
+            Self::ExhaustMemory => "exhaust_memory",
+        }
+    }
+
+    /// File the attack creates when it succeeds, in the directory above the job's
+    fn target(&self) -> Option<&'static str> {
+        match self {
+            Self::WriteAbsolutePath => Some("absolute-target.txt"),
+            Self::WriteParentDirectory => Some("parent-target.txt"),
+            Self::ShellEscape => Some("shell-target.txt"),
+            Self::ReadSystemFile | Self::ExhaustMemory => None,
+        }
+    }
+
+    /// The fixture of a job in `root/<probe>`
+    fn fixture(&self, root: &Path) -> String {
+        let target = |probe: &Probe| root.join(probe.target().unwrap_or_default()).display().to_string();
+        let attack = match self {
+            Self::ReadSystemFile => "\\newread\\probe\n\
+                \\openin\\probe=/etc/passwd\n\
+                \\ifeof\\probe\\typeout{TEXLER-PROBE-BLOCKED}\\else\\typeout{TEXLER-PROBE-ESCAPED}\\fi\n"
+                .to_string(),
+            Self::WriteAbsolutePath => format!(
+                "\\newwrite\\probe\n\\immediate\\openout\\probe={}\n\\immediate\\write\\probe{{escaped}}\n\\immediate\\closeout\\probe\n",
+                target(self)
+            ),
+            Self::WriteParentDirectory => format!(
+                "\\newwrite\\probe\n\\immediate\\openout\\probe=../{}\n\\immediate\\write\\probe{{escaped}}\n\\immediate\\closeout\\probe\n",
+                self.target().unwrap_or_default()
+            ),
+            Self::ShellEscape => format!("\\immediate\\write18{{echo escaped > {}}}\n", target(self)),
+            Self::ExhaustMemory => {
+                "\\def\\probe{xxxxxxxxxxxxxxxx}\n\\loop\\edef\\probe{\\probe\\probe}\\iftrue\\repeat\n".to_string()
+            }
+        };
+
+        format!(
+            "\\documentclass{{article}}\n\\typeout{{{}}}\n{}\\begin{{document}}\nprobe\n\\end{{document}}\n",
+            PROBE_REACHED, attack
+        )
+    }
+
+    /// Whether the sandbox stopped the attack, and why
+    fn judge(&self, ending: &Ending, target_exists: bool) -> (ProbeStatus, String) {
+        let stdout = match ending {
+            Ending::Exited { stdout, .. } => stdout.as_str(),
+            Ending::TimedOut => "",
+        };
+        if !stdout.contains(PROBE_REACHED) && !matches!(ending, Ending::TimedOut) {
+            return (ProbeStatus::Inconclusive, "The fixture stopped before its attack".to_string());
+        }
+
+        match self {
+            Self::ReadSystemFile if stdout.contains("TEXLER-PROBE-ESCAPED") => {
+                (ProbeStatus::Failed, "/etc/passwd was opened for reading".to_string())
+            }
+            Self::ReadSystemFile if stdout.contains("TEXLER-PROBE-BLOCKED") => {
+                (ProbeStatus::Passed, "/etc/passwd could not be opened".to_string())
+            }
+            Self::ReadSystemFile => (ProbeStatus::Inconclusive, "The fixture did not reach its check".to_string()),
+            Self::WriteAbsolutePath | Self::WriteParentDirectory | Self::ShellEscape if target_exists => {
+                (ProbeStatus::Failed, "The file outside the job's directory was created".to_string())
+            }
+            Self::WriteAbsolutePath | Self::WriteParentDirectory | Self::ShellEscape => match ending {
+                Ending::TimedOut => (ProbeStatus::Inconclusive, "The compile timed out".to_string()),
+                Ending::Exited { .. } => (ProbeStatus::Passed, "No file was created outside the job's directory".to_string()),
+            },
+            Self::ExhaustMemory => match ending {
+                Ending::TimedOut => (
+                    ProbeStatus::Failed,
+                    "The engine ran until the timeout; no memory limit stopped it".to_string(),
+                ),
+                Ending::Exited { success: true, .. } => {
+                    (ProbeStatus::Failed, "The engine finished despite its allocations".to_string())
+                }
+                Ending::Exited { stdout, .. } => {
+                    let error = stdout.lines().find(|line| line.starts_with("! ")).unwrap_or("killed");
+                    (ProbeStatus::Passed, format!("The engine was stopped: {}", error.trim_start_matches("! ")))
+                }
+            },
+        }
+    }
+}
+
+/// How the compile of a fixture ended
+#[derive(Debug)]
+enum Ending {
+    Exited { success: bool, stdout: String },
+    TimedOut,
+}
+
+/// Outcome of a probe
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ProbeStatus {
+    /// The sandbox stopped the attack
+    Passed,
+    /// The attack succeeded
+    Failed,
+    /// The probe could not tell, e.g. because the engine is not installed
+    Inconclusive,
+}
+
+/// A probe run with one engine
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct ProbeResult {
+    pub probe: Probe,
+    pub engine: LatexEngine,
+    pub status: ProbeStatus,
+    pub detail: String,
+}
+
+/// Outcome of a sandbox self-test
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct SelfTestReport {
+    pub results: Vec<ProbeResult>,
+    pub passed: usize,
+    pub failed: usize,
+    pub inconclusive: usize,
+    pub ran_at: DateTime<Utc>,
+    pub duration_ms: u64,
+}
+
+impl SelfTestReport {
+    fn new(results: Vec<ProbeResult>, ran_at: DateTime<Utc>, started: Instant) -> Self {
+        let count = |status| results.iter().filter(|result| result.status == status).count();
+        Self {
+            passed: count(ProbeStatus::Passed),
+            failed: count(ProbeStatus::Failed),
+            inconclusive: count(ProbeStatus::Inconclusive),
+            results,
+            ran_at,
+            duration_ms: started.elapsed().as_millis() as u64,
+        }
+    }
+
+    /// Failed probes as `probe (engine)`
+    pub fn failures(&self) -> Vec<String> {
+        self.results
+            .iter()
+            .filter(|result| result.status == ProbeStatus::Failed)
+            .map(|result| format!("{} ({})", result.probe.name(), result.engine.command()))
+            .collect()
+    }
+}
+
+/// The sandbox of this instance's TeX runs and its latest self-test
+#[derive(Debug)]
+pub struct CompileSandbox {
+    temp_dir: PathBuf,
+    engines: Vec<LatexEngine>,
+    limits: ResourceLimits,
+    last_self_test: Mutex<Option<SelfTestReport>>,
+    /// Held while a self-test runs, so concurrent requests wait for it
+    self_test_running: tokio::sync::Mutex<()>,
+}
+
+impl CompileSandbox {
+    pub fn new(config: &LatexConfig) -> Self {
+        let engines = config
+            .engines
+            .iter()
+            .filter_map(|name| match serde_json::from_value(serde_json::Value::String(name.clone())) {
+                Ok(engine) => Some(engine),
+                Err(_) => {
+                    tracing::warn!("Ignoring unknown engine {} in LATEX_ENGINES", name);
+                    None
+                }
+            })
+            .collect();
+
+        Self {
+            temp_dir: PathBuf::from(&config.temp_dir),
+            engines,
+            limits: ResourceLimits {
+                timeout_ms: config.timeout,
+                memory_limit_mb: config.memory_limit,
+                output_size_limit: config.output_size_limit,
+            },
+            last_self_test: Mutex::new(None),
+            self_test_running: tokio::sync::Mutex::new(()),
+        }
+    }
+
+    /// A confined run of `program` in `work_dir`, limited to the configured memory
+    pub fn command(&self, program: &str, work_dir: &Path) -> Command {
+        let mut command = Command::new("sh");
+        command
+            .arg("-c")
+            .arg("ulimit -v \"$0\" && exec \"$@\"")
+            .arg((self.limits.memory_limit_mb * 1024).to_string())
+            .arg(program);
+        confine(&mut command, work_dir);
+        command
+    }
+
+    /// The latest self-test, from startup or an administrator
+    pub fn last_self_test(&self) -> Option<SelfTestReport> {
+        self.last_self_test.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    /// Describe the environment compile jobs get
+    pub async fn report(&self) -> SandboxReport {
+        let mut engines = Vec::new();
+        for engine in &self.engines {
+            engines.push(engine_binary(*engine).await);
+        }
+
+        let scratch = self.temp_dir.join(format!("sandbox-report-{}", uuid::Uuid::new_v4()));
+        let mut texmf_trees = Vec::new();
+        if tokio::fs::create_dir_all(&scratch).await.is_ok() {
+            for variable in TEXMF_VARIABLES {
+                let Some(value) = self.kpsewhich_var(variable, Some(&scratch)).await else { continue };
+                for path in value.split(':').map(|path| path.trim_start_matches("!!")).filter(|path| !path.is_empty()) {
+                    texmf_trees.push(texmf_tree(variable, path).await);
+                }
+            }
+            if let Err(e) = tokio::fs::remove_dir_all(&scratch).await {
+                tracing::warn!("Failed to remove {}: {}", scratch.display(), e);
+            }
+        }
+
+        // Outside the sandbox, as every other TeX run on the host sees it
+        let shell_escape_setting = self.kpsewhich_var("shell_escape", None).await;
+        let shell_escape_disabled =
+            matches!(shell_escape_setting.as_deref(), Some("f" | "false" | "0"));
+
+        let mut writable_paths = vec![
+            format!("{}/{{project_id}}", PROJECT_WORKING_DIRECTORY_ROOT),
+            format!("{}/{{project_id}}/output", PROJECT_WORKING_DIRECTORY_ROOT),
+        ];
+        writable_paths.extend(texmf_trees.iter().filter(|tree| tree.writable).map(|tree| tree.path.clone()));
+
+        SandboxReport {
+            engines,
+            texmf_trees,
+            shell_escape_setting,
+            shell_escape_disabled,
+            sandbox_env: SANDBOX_ENV
+                .iter()
+                .map(|(name, value)| format!("{}={}", name, value))
+                .chain(std::iter::once("TEXMFOUTPUT=<job directory>".to_string()))
+                .collect(),
+            limits: self.limits,
+            writable_paths,
+            generated_at: Utc::now(),
+        }
+    }
+
+    /// Value of a kpathsea variable, in the sandbox when `work_dir` is given
+    async fn kpsewhich_var(&self, variable: &str, work_dir: Option<&Path>) -> Option<String> {
+        let mut command = match work_dir {
+            Some(work_dir) => self.command("kpsewhich", work_dir),
+            None => {
+                let mut command = Command::new("kpsewhich");
+                command.stdin(Stdio::null()).kill_on_drop(true);
+                command
+            }
+        };
+        command.arg(format!("-var-value={}", variable));
+
+        match tokio::time::timeout(KPSEWHICH_TIMEOUT, command.output()).await {
+            Ok(Ok(output)) if output.status.success() => {
+                let value = String::from_utf8_lossy(&output.stdout).trim().to_string();
+                (!value.is_empty()).then_some(value)
+            }
+            // kpsewhich exits with 1 for variables that are not set
+            Ok(Ok(_)) => None,
+            Ok(Err(e)) => {
+                tracing::debug!("Failed to run kpsewhich: {}", e);
+                None
+            }
+            Err(_) => {
+                tracing::warn!("kpsewhich -var-value={} timed out", variable);
+                None
+            }
+        }
+    }
+
+    /// Run every probe with every configured TeX engine and keep the outcome
+    pub async fn self_test(&self) -> SelfTestReport {
+        let _running = self.self_test_running.lock().await;
+        let ran_at = Utc::now();
+        let started = Instant::now();
+
+        let root = self.temp_dir.join(format!("sandbox-selftest-{}", uuid::Uuid::new_v4()));
+        let mut results = Vec::new();
+        for engine in self.engines.iter().copied().filter(|engine| *engine != LatexEngine::Typst) {
+            let installed = find_on_path(engine.command()).is_some();
+            for probe in Probe::ALL {
+                let (status, detail) = if installed {
+                    self.run_probe(engine, probe, &root.join(engine.command())).await
+                } else {
+                    (ProbeStatus::Inconclusive, format!("{} is not installed on this instance", engine.command()))
+                };
+                results.push(ProbeResult { probe, engine, status, detail });
+            }
+        }
+        if let Err(e) = tokio::fs::remove_dir_all(&root).await {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                tracing::warn!("Failed to remove {}: {}", root.display(), e);
+            }
+        }
+
+        let report = SelfTestReport::new(results, ran_at, started);
+        let failures = report.failures();
+        if failures.is_empty() {
+            tracing::info!(
+                "Compile sandbox self-test: {} probes passed, {} inconclusive",
+                report.passed,
+                report.inconclusive
+            );
+        } else {
+            tracing::warn!("Compile sandbox self-test failed: {}", failures.join(", "));
+        }
+
+        *self.last_self_test.lock().unwrap_or_else(|e| e.into_inner()) = Some(report.clone());
+        report
+    }
+
+    /// Compile the fixture of `probe` in `root/<probe>`
+    async fn run_probe(&self, engine: LatexEngine, probe: Probe, root: &Path) -> (ProbeStatus, String) {
+        let job = root.join(probe.name());
+        let prepared = async {
+            tokio::fs::create_dir_all(&job).await?;
+            tokio::fs::write(job.join("probe.tex"), probe.fixture(root)).await
+        };
+        if let Err(e) = prepared.await {
+            return (ProbeStatus::Inconclusive, format!("Failed to prepare the fixture: {}", e));
+        }
+
+        let mut command = self.command(engine.command(), &job);
+        command.args(["-interaction=nonstopmode", "probe.tex"]).stderr(Stdio::null());
+        let timeout = Duration::from_millis(self.limits.timeout_ms);
+        let ending = match tokio::time::timeout(timeout, command.output()).await {
+            Ok(Ok(output)) => Ending::Exited {
+                success: output.status.success(),
+                stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+            },
+            Ok(Err(e)) => return (ProbeStatus::Inconclusive, format!("Failed to run {}: {}", engine.command(), e)),
+            Err(_) => Ending::TimedOut,
+        };
+
+        let target_exists = match probe.target() {
+            Some(target) => tokio::fs::try_exists(root.join(target)).await.unwrap_or(false),
+            None => false,
+        };
+        probe.judge(&ending, target_exists)
+    }
+}
+
+/// Reports the environment and runs the self-test
+pub struct SandboxScanTask;
+
+impl crate::tasks::PeriodicTask for SandboxScanTask {
+    fn name(&self) -> &'static str {
+        "compile_sandbox_scan"
+    }
+
+    fn interval(&self) -> Duration {
+        SCAN_INTERVAL
+    }
+
+    fn run<'a>(
+        &'a self,
+        state: &'a crate::server::AppState,
+    ) -> futures::future::BoxFuture<'a, Result<(), crate::error::AppError>> {
+        Box::pin(async move {
+            scan(&state.compile_sandbox).await;
+            Ok(())
+        })
+    }
+}
+
+/// Log what compile jobs can touch and check the sandbox holds
+async fn scan(sandbox: &CompileSandbox) {
+    let report = sandbox.report().await;
+
+    for engine in &report.engines {
+        match (&engine.path, &engine.sha256) {
+            (Some(path), Some(sha256)) => {
+                tracing::info!("Compile engine {} at {} (sha256 {})", engine.engine.command(), path, sha256)
+            }
+            _ => tracing::warn!("Compile engine {} is not installed on this instance", engine.engine.command()),
+        }
+    }
+    if report.shell_escape_setting.is_some() && !report.shell_escape_disabled {
+        tracing::warn!(
+            "texmf.cnf does not disable shell escape (shell_escape = {}); only the compile sandbox turns it off",
+            report.shell_escape_setting.as_deref().unwrap_or_default()
+        );
+    }
+    for tree in report.texmf_trees.iter().filter(|tree| tree.writable) {
+        tracing::warn!("TEXMF tree {} ({}) is writable by compile jobs", tree.path, tree.variable);
+    }
+
+    let tex_installed = report
+        .engines
+        .iter()
+        .any(|engine| engine.engine != LatexEngine::Typst && engine.path.is_some());
+    if tex_installed {
+        sandbox.self_test().await;
+    } else {
+        tracing::info!("No TeX engine is installed on this instance, skipping the compile sandbox self-test");
+    }
+}
+
+/// Resolve, hash and version an engine binary
+async fn engine_binary(engine: LatexEngine) -> EngineBinary {
+    let Some(path) = find_on_path(engine.command()) else {
+        return EngineBinary { engine, path: None, resolved_path: None, sha256: None, version: None };
+    };
+
+    let resolved = tokio::fs::canonicalize(&path).await.unwrap_or_else(|_| path.clone());
+    let sha256 = match tokio::fs::read(&resolved).await {
+        Ok(contents) => Some(format!("{:x}", Sha256::digest(&contents))),
+        Err(e) => {
+            tracing::warn!("Failed to read {}: {}", resolved.display(), e);
+            None
+        }
+    };
+
+    EngineBinary {
+        engine,
+        path: Some(path.display().to_string()),
+        resolved_path: Some(resolved.display().to_string()),
+        sha256,
+        version: CompileEnvironment::capture(engine).await.engine_version,
+    }
+}
+
+/// Whether a tree exists and accepts a new file
+async fn texmf_tree(variable: &str, path: &str) -> TexmfTree {
+    let exists = tokio::fs::metadata(path).await.is_ok_and(|metadata| metadata.is_dir());
+    let writable = exists && {
+        let probe = Path::new(path).join(format!(".texler-sandbox-{}", uuid::Uuid::new_v4()));
+        match tokio::fs::OpenOptions::new().write(true).create_new(true).open(&probe).await {
+            Ok(_) => {
+                let _ = tokio::fs::remove_file(&probe).await;
+                true
+            }
+            Err(_) => false,
+        }
+    };
+
+    TexmfTree { variable: variable.to_string(), path: path.to_string(), exists, writable }
+}
+
+fn find_on_path(program: &str) -> Option<PathBuf> {
+    let paths = std::env::var_os("PATH")?;
+    std::env::split_paths(&paths).map(|dir| dir.join(program)).find(|path| path.is_file())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn exited(success: bool, stdout: &str) -> Ending {
+        Ending::Exited { success, stdout: stdout.to_string() }
+    }
+
+    #[test]
+    fn test_fixtures_attack_outside_the_job() {
+        let root = Path::new("/tmp/texler/sandbox-selftest/pdflatex");
+        for probe in Probe::ALL {
+            assert!(probe.fixture(root).contains(PROBE_REACHED), "{}", probe.name());
+        }
+        assert!(Probe::WriteAbsolutePath
+            .fixture(root)
+            .contains("\\openout\\probe=/tmp/texler/sandbox-selftest/pdflatex/absolute-target.txt"));
+        assert!(Probe::WriteParentDirectory.fixture(root).contains("\\openout\\probe=../parent-target.txt"));
+        assert!(Probe::ShellEscape
+            .fixture(root)
+            .contains("\\write18{echo escaped > /tmp/texler/sandbox-selftest/pdflatex/shell-target.txt}"));
+    }
+
+    #[test]
+    fn test_probe_judgement() {
+        let reached = format!("{}\n", PROBE_REACHED);
+
+        let (status, _) = Probe::ReadSystemFile.judge(&exited(true, &format!("{}TEXLER-PROBE-BLOCKED", reached)), false);
+        assert_eq!(status, ProbeStatus::Passed);
+        let (status, _) = Probe::ReadSystemFile.judge(&exited(true, &format!("{}TEXLER-PROBE-ESCAPED", reached)), false);
+        assert_eq!(status, ProbeStatus::Failed);
+
+        // A refused write ends the compile with an error; only the file counts
+        assert_eq!(Probe::WriteAbsolutePath.judge(&exited(false, &reached), false).0, ProbeStatus::Passed);
+        assert_eq!(Probe::ShellEscape.judge(&exited(true, &reached), true).0, ProbeStatus::Failed);
+        assert_eq!(Probe::ShellEscape.judge(&exited(false, "! LaTeX Error"), false).0, ProbeStatus::Inconclusive);
+
+        let (status, detail) = Probe::ExhaustMemory.judge(
+            &exited(false, &format!("{}! TeX capacity exceeded, sorry [main memory size=5000000].", reached)),
+            false,
+        );
+        assert_eq!(status, ProbeStatus::Passed);
+        assert!(detail.ends_with("TeX capacity exceeded, sorry [main memory size=5000000]."));
+        assert_eq!(Probe::ExhaustMemory.judge(&Ending::TimedOut, false).0, ProbeStatus::Failed);
+    }
+
+    #[test]
+    fn test_report_counts_failures() {
+        let result = |probe, status| ProbeResult { probe, engine: LatexEngine::Xelatex, status, detail: String::new() };
+        let report = SelfTestReport::new(
+            vec![
+                result(Probe::ReadSystemFile, ProbeStatus::Passed),
+                result(Probe::ShellEscape, ProbeStatus::Failed),
+                result(Probe::ExhaustMemory, ProbeStatus::Inconclusive),
+            ],
+            Utc::now(),
+            Instant::now(),
+        );
+        assert_eq!((report.passed, report.failed, report.inconclusive), (1, 1, 1));
+        assert_eq!(report.failures(), vec!["shell_escape (xelatex)".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn test_sandbox_stops_every_probe() {
+        if find_on_path("pdflatex").is_none() {
+            eprintln!("skipping: pdflatex is not installed");
+            return;
+        }
+        let mut config = crate::testing::test_config().latex;
+        config.temp_dir = std::env::temp_dir().join(format!("texler-sandbox-{}", uuid::Uuid::new_v4())).display().to_string();
+        config.engines = vec!["pdflatex".to_string()];
+        let sandbox = CompileSandbox::new(&config);
+
+        let report = sandbox.self_test().await;
+        assert_eq!(report.failures(), Vec::<String>::new());
+        assert_eq!(report.results.len(), Probe::ALL.len());
+        assert!(sandbox.last_self_test().is_some());
+        let _ = tokio::fs::remove_dir_all(&config.temp_dir).await;
+    }
+}
@@ -0,0 +1,623 @@
+//! Page thumbnails of compiled PDFs
+//!
+//! Previews used to load the whole PDF to draw their page strip. After a
+//! successful compile, [`ThumbnailTask`] renders the first
+//! `LATEX_THUMBNAIL_PAGES` pages of the job's primary PDF with `pdftoppm`
+//! and stores them as lossless WebP, `THUMBNAIL_WIDTH` pixels wide, as
+//! thumbnail artifacts of the job: they are deleted with it. Rendering a job
+//! gets `LATEX_THUMBNAIL_BUDGET` of wall-clock time, pages after the budget
+//! runs out are left without a thumbnail, and PDFs larger than
+//! `LATEX_THUMBNAIL_MAX_PDF_SIZE` are not rendered at all.
+//!
+//! Rendering runs after the job completed and only records a
+//! [`ThumbnailReport`] on it, so it can never change a compile's result.
+//! Jobs share their project's working directory; a job whose PDF a later
+//! compile already replaced is skipped.
+
+use chrono::{DateTime, Utc};
+use image::codecs::webp::WebPEncoder;
+use image::{ExtendedColorType, ImageFormat};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use std::path::Path;
+use std::process::Stdio;
+use std::time::{Duration, Instant};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use super::compilation::CompilationJob;
+use super::compile_diff;
+use crate::config::LatexConfig;
+use crate::error::AppError;
+
+/// Width of a thumbnail in pixels; the height follows the page
+pub const THUMBNAIL_WIDTH: u32 = 200;
+
+/// How often completed jobs are checked for thumbnails to render
+pub const THUMBNAIL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Jobs completed longer ago than this are not thumbnailed
+pub const THUMBNAIL_WINDOW_HOURS: i64 = 1;
+
+/// A pending render older than this is assumed to belong to a server that
+/// died and may be claimed again
+pub const STALE_PENDING_MINUTES: i64 = 15;
+
+/// Jobs rendered per run of the task
+const THUMBNAIL_BATCH: i64 = 4;
+
+/// Bounds of thumbnail rendering, from `LatexConfig`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ThumbnailLimits {
+    /// Pages rendered, from the first; 0 disables thumbnails
+    pub pages: u32,
+    /// Largest PDF rendered, in bytes
+    pub max_pdf_size: u64,
+    /// Wall-clock time rendering one job may take
+    pub budget: Duration,
+}
+
+impl ThumbnailLimits {
+    pub fn from_config(config: &LatexConfig) -> Self {
+        Self {
+            pages: config.thumbnail_pages,
+            max_pdf_size: config.thumbnail_max_pdf_size,
+            budget: Duration::from_secs(config.thumbnail_budget),
+        }
+    }
+}
+
+/// Where a job's thumbnails stand
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ThumbnailStatus {
+    Pending,
+    Ready,
+    Skipped,
+    Failed,
+}
+
+/// Why a job's PDF was not rendered
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ThumbnailSkip {
+    /// Larger than `LATEX_THUMBNAIL_MAX_PDF_SIZE`
+    PdfTooLarge,
+    /// The job produced no PDF, or it is gone
+    NoPdf,
+    /// A later compile of the project replaced the PDF first
+    Superseded,
+}
+
+/// Thumbnails rendered for a job
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct ThumbnailReport {
+    pub status: ThumbnailStatus,
+    /// Pages with a thumbnail, from the first
+    pub pages: u32,
+    /// Pages of the PDF, once it was read
+    pub page_count: Option<u32>,
+    /// Pages past the page limit or the time budget have no thumbnail
+    pub truncated: bool,
+    pub skipped: Option<ThumbnailSkip>,
+    /// Why rendering failed or stopped early
+    pub error: Option<String>,
+    pub duration_ms: i64,
+}
+
+impl ThumbnailReport {
+    fn pending() -> Self {
+        Self {
+            status: ThumbnailStatus::Pending,
+            pages: 0,
+            page_count: None,
+            truncated: false,
+            skipped: None,
+            error: None,
+            duration_ms: 0,
+        }
+    }
+
+    fn skipped(reason: ThumbnailSkip) -> Self {
+        Self {
+            status: ThumbnailStatus::Skipped,
+            skipped: Some(reason),
+            ..Self::pending()
+        }
+    }
+
+    fn failed(error: String, started: Instant) -> Self {
+        Self {
+            status: ThumbnailStatus::Failed,
+            error: Some(error),
+            duration_ms: started.elapsed().as_millis() as i64,
+            ..Self::pending()
+        }
+    }
+}
+
+/// A page rendered as WebP
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RenderedPage {
+    pub page: u32,
+    pub width: u32,
+    pub height: u32,
+    pub webp: Vec<u8>,
+}
+
+/// A stored thumbnail, without its image
+#[derive(Debug, Clone, Serialize, FromRow, ToSchema)]
+pub struct CompileThumbnail {
+    pub page: i32,
+    pub width: i32,
+    pub height: i32,
+    pub size_bytes: i32,
+    pub created_at: DateTime<Utc>,
+}
+
+impl CompileThumbnail {
+    /// Thumbnails of a job, in page order
+    pub async fn list(db: &sqlx::PgPool, job_id: Uuid) -> Result<Vec<Self>, AppError> {
+        sqlx::query_as::<_, CompileThumbnail>(
+            r#"
+            SELECT page, width, height, size_bytes, created_at
+            FROM compilation_thumbnails
+            WHERE job_id = $1
+            ORDER BY page
+            "#
+        )
+        .bind(job_id)
+        .fetch_all(db)
+        .await
+        .map_err(AppError::Database)
+    }
+
+    /// The WebP image of one page
+    pub async fn image(db: &sqlx::PgPool, job_id: Uuid, page: i32) -> Result<Option<Vec<u8>>, AppError> {
+        sqlx::query_scalar::<_, Vec<u8>>("SELECT data FROM compilation_thumbnails WHERE job_id = $1 AND page = $2")
+            .bind(job_id)
+            .bind(page)
+            .fetch_optional(db)
+            .await
+            .map_err(AppError::Database)
+    }
+}
+
+impl CompilationJob {
+    /// Thumbnails rendered for the job, once it was picked up
+    pub fn thumbnail_report(&self) -> Option<ThumbnailReport> {
+        self.thumbnails.clone().and_then(|report| serde_json::from_value(report).ok())
+    }
+}
+
+/// Pages of the PDF at `path`
+pub fn page_count(path: &Path) -> Result<u32, AppError> {
+    let document = lopdf::Document::load(path)
+        .map_err(|e| AppError::Compilation(format!("Cannot read PDF {}: {}", path.display(), e)))?;
+    Ok(document.get_pages().len() as u32)
+}
+
+/// Render the first pages of `pdf` within `limits`, using `work_dir` for
+/// intermediate files
+///
+/// A PDF that cannot be read fails; a page that cannot be rendered, or the
+/// budget running out, ends rendering with the pages done so far.
+pub async fn render(pdf: &Path, limits: &ThumbnailLimits, work_dir: &Path) -> (Vec<RenderedPage>, ThumbnailReport) {
+    let started = Instant::now();
+    match tokio::fs::metadata(pdf).await {
+        Ok(metadata) if metadata.len() > limits.max_pdf_size => {
+            return (Vec::new(), ThumbnailReport::skipped(ThumbnailSkip::PdfTooLarge))
+        }
+        Ok(metadata) if metadata.is_file() => {}
+        _ => return (Vec::new(), ThumbnailReport::skipped(ThumbnailSkip::NoPdf)),
+    }
+
+    let path = pdf.to_path_buf();
+    let page_count = match tokio::task::spawn_blocking(move || page_count(&path)).await {
+        Ok(Ok(count)) => count,
+        Ok(Err(e)) => return (Vec::new(), ThumbnailReport::failed(e.to_string(), started)),
+        Err(e) => return (Vec::new(), ThumbnailReport::failed(format!("Reading the PDF failed: {}", e), started)),
+    };
+
+    let mut pages = Vec::new();
+    let mut error = None;
+    for page in 1..=page_count.min(limits.pages) {
+        let Some(remaining) = limits.budget.checked_sub(started.elapsed()).filter(|left| !left.is_zero()) else {
+            break;
+        };
+        match render_page(pdf, page, work_dir, remaining).await {
+            Ok(rendered) => pages.push(rendered),
+            // A page cut short by the budget is not an error of the PDF
+            Err(_) if started.elapsed() >= limits.budget => break,
+            Err(e) => {
+                error = Some(e.to_string());
+                break;
+            }
+        }
+    }
+
+    let rendered = pages.len() as u32;
+    let report = ThumbnailReport {
+        status: if rendered == 0 && error.is_some() { ThumbnailStatus::Failed } else { ThumbnailStatus::Ready },
+        pages: rendered,
+        page_count: Some(page_count),
+        truncated: rendered < page_count,
+        skipped: None,
+        error,
+        duration_ms: started.elapsed().as_millis() as i64,
+    };
+    (pages, report)
+}
+
+/// Render one page of `pdf` as WebP, `THUMBNAIL_WIDTH` pixels wide, in at
+/// most `timeout`
+pub async fn render_page(pdf: &Path, page: u32, work_dir: &Path, timeout: Duration) -> Result<RenderedPage, AppError> {
+    let prefix = work_dir.join(format!("page-{}", page));
+    let pdftoppm = tokio::process::Command::new("pdftoppm")
+        .args(["-png", "-singlefile", "-scale-to-x"])
+        .arg(THUMBNAIL_WIDTH.to_string())
+        .args(["-scale-to-y", "-1", "-f"])
+        .arg(page.to_string())
+        .arg("-l")
+        .arg(page.to_string())
+        .arg(pdf)
+        .arg(&prefix)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .output();
+
+    let output = tokio::time::timeout(timeout, pdftoppm)
+        .await
+        .map_err(|_| AppError::Internal(format!("Rendering page {} timed out", page)))?
+        .map_err(|e| AppError::Internal(format!("Failed to run pdftoppm: {}", e)))?;
+
+    let png_path = prefix.with_extension("png");
+    if !output.status.success() {
+        let _ = tokio::fs::remove_file(&png_path).await;
+        return Err(AppError::Compilation(format!(
+            "pdftoppm could not render page {}: {}",
+            page,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+
+    let png = tokio::fs::read(&png_path).await?;
+    tokio::fs::remove_file(&png_path).await?;
+    tokio::task::spawn_blocking(move || encode_webp(page, &png))
+        .await
+        .map_err(|e| AppError::Internal(format!("Thumbnail encoding task failed: {}", e)))?
+}
+
+/// Re-encode a page rendered by pdftoppm as lossless WebP
+fn encode_webp(page: u32, png: &[u8]) -> Result<RenderedPage, AppError> {
+    let image = image::load_from_memory_with_format(png, ImageFormat::Png)
+        .map_err(|e| AppError::Internal(format!("Cannot read rendered page {}: {}", page, e)))?
+        .to_rgb8();
+
+    let mut webp = Vec::new();
+    WebPEncoder::new_lossless(&mut webp)
+        .encode(image.as_raw(), image.width(), image.height(), ExtendedColorType::Rgb8)
+        .map_err(|e| AppError::Internal(format!("Cannot encode page {} as WebP: {}", page, e)))?;
+
+    Ok(RenderedPage {
+        page,
+        width: image.width(),
+        height: image.height(),
+        webp,
+    })
+}
+
+/// Mark up to `limit` recently succeeded jobs without thumbnails as pending
+/// and return them; jobs another server is rendering are left to it
+pub async fn claim(db: &sqlx::PgPool, limit: i64) -> Result<Vec<CompilationJob>, AppError> {
+    sqlx::query_as::<_, CompilationJob>(
+        r#"
+        UPDATE compilation_jobs SET thumbnails = $1, updated_at = NOW()
+        WHERE id IN (
+            SELECT id FROM compilation_jobs
+            WHERE status = 'success'
+                AND completed_at > NOW() - make_interval(hours => $2)
+                AND (thumbnails IS NULL OR (
+                    thumbnails->>'status' = 'pending'
+                    AND updated_at < NOW() - make_interval(mins => $3)
+                ))
+            ORDER BY completed_at
+            LIMIT $4
+            FOR UPDATE SKIP LOCKED
+        )
+        RETURNING *
+        "#
+    )
+    .bind(serde_json::to_value(ThumbnailReport::pending())?)
+    .bind(THUMBNAIL_WINDOW_HOURS as i32)
+    .bind(STALE_PENDING_MINUTES as i32)
+    .bind(limit)
+    .fetch_all(db)
+    .await
+    .map_err(AppError::Database)
+}
+
+/// Whether a later job ran in the job's working directory, replacing its PDF
+async fn superseded(db: &sqlx::PgPool, job: &CompilationJob) -> Result<bool, AppError> {
+    sqlx::query_scalar::<_, bool>(
+        r#"
+        SELECT EXISTS (
+            SELECT 1 FROM compilation_jobs
+            WHERE project_id = $1 AND working_directory = $2 AND id <> $3 AND started_at > $4
+        )
+        "#
+    )
+    .bind(job.project_id)
+    .bind(&job.working_directory)
+    .bind(job.id)
+    .bind(job.started_at.unwrap_or(job.created_at))
+    .fetch_one(db)
+    .await
+    .map_err(AppError::Database)
+}
+
+/// Replace the job's thumbnails with `pages` and record `report` on it
+pub async fn store(
+    db: &sqlx::PgPool,
+    job_id: Uuid,
+    pages: &[RenderedPage],
+    report: &ThumbnailReport,
+) -> Result<(), AppError> {
+    let mut tx = db.begin().await.map_err(AppError::Database)?;
+
+    sqlx::query("DELETE FROM compilation_thumbnails WHERE job_id = $1")
+        .bind(job_id)
+        .execute(&mut *tx)
+        .await
+        .map_err(AppError::Database)?;
+
+    for page in pages {
+        sqlx::query(
+            r#"
+            INSERT INTO compilation_thumbnails (job_id, page, width, height, size_bytes, data)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            "#
+        )
+        .bind(job_id)
+        .bind(page.page as i32)
+        .bind(page.width as i32)
+        .bind(page.height as i32)
+        .bind(page.webp.len() as i32)
+        .bind(&page.webp)
+        .execute(&mut *tx)
+        .await
+        .map_err(AppError::Database)?;
+    }
+
+    sqlx::query("UPDATE compilation_jobs SET thumbnails = $1, updated_at = NOW() WHERE id = $2")
+        .bind(serde_json::to_value(report)?)
+        .bind(job_id)
+        .execute(&mut *tx)
+        .await
+        .map_err(AppError::Database)?;
+
+    tx.commit().await.map_err(AppError::Database)?;
+    Ok(())
+}
+
+/// Render and store the thumbnails of a succeeded job
+pub async fn generate(
+    db: &sqlx::PgPool,
+    job: &CompilationJob,
+    limits: &ThumbnailLimits,
+    temp_dir: &str,
+) -> Result<ThumbnailReport, AppError> {
+    let (pages, report) = if superseded(db, job).await? {
+        (Vec::new(), ThumbnailReport::skipped(ThumbnailSkip::Superseded))
+    } else {
+        match compile_diff::primary_pdf(job).await {
+            Some(pdf) => {
+                tokio::fs::create_dir_all(temp_dir).await?;
+                let work = tempfile::Builder::new().prefix("thumbnails-").tempdir_in(temp_dir)?;
+                render(&pdf, limits, work.path()).await
+            }
+            None => (Vec::new(), ThumbnailReport::skipped(ThumbnailSkip::NoPdf)),
+        }
+    };
+
+    store(db, job.id, &pages, &report).await?;
+    Ok(report)
+}
+
+/// Renders the thumbnails of jobs that just succeeded
+pub struct ThumbnailTask;
+
+impl crate::tasks::PeriodicTask for ThumbnailTask {
+    fn name(&self) -> &'static str {
+        "compile_thumbnails"
+    }
+
+    fn interval(&self) -> Duration {
+        THUMBNAIL_INTERVAL
+    }
+
+    fn run<'a>(
+        &'a self,
+        state: &'a crate::server::AppState,
+    ) -> futures::future::BoxFuture<'a, Result<(), AppError>> {
+        Box::pin(async move {
+            let limits = ThumbnailLimits::from_config(&state.config.latex);
+            if limits.pages == 0 {
+                return Ok(());
+            }
+
+            for job in claim(&state.db_pool, THUMBNAIL_BATCH).await? {
+                let started = Instant::now();
+                match generate(&state.db_pool, &job, &limits, &state.config.latex.temp_dir).await {
+                    Ok(report) => {
+                        tracing::debug!("Rendered {} thumbnails of job {} in {} ms", report.pages, job.id, report.duration_ms)
+                    }
+                    Err(e) => {
+                        tracing::warn!("Thumbnails of job {} failed: {}", job.id, e);
+                        store(&state.db_pool, job.id, &[], &ThumbnailReport::failed(e.to_string(), started)).await?;
+                    }
+                }
+            }
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::CompilationStatus;
+    use crate::testing::{create_test_job, create_test_project, create_test_user, TestDb};
+    use lopdf::{dictionary, Document, Object, Stream};
+
+    fn pdftoppm_installed() -> bool {
+        std::process::Command::new("pdftoppm").arg("-v").output().is_ok()
+    }
+
+    /// A PDF of `count` pages of `width` by `height` points, each with a line
+    fn fixture_pdf(count: u32, width: i64, height: i64) -> Vec<u8> {
+        let mut document = Document::with_version("1.5");
+        let pages_id = document.new_object_id();
+        let content_id = document.add_object(Stream::new(dictionary! {}, b"10 10 m 100 100 l S".to_vec()));
+        let kids: Vec<Object> = (0..count)
+            .map(|_| {
+                document
+                    .add_object(dictionary! {
+                        "Type" => "Page",
+                        "Parent" => pages_id,
+                        "Contents" => content_id,
+                    })
+                    .into()
+            })
+            .collect();
+        document.objects.insert(
+            pages_id,
+            Object::Dictionary(dictionary! {
+                "Type" => "Pages",
+                "Kids" => kids,
+                "Count" => count as i64,
+                "MediaBox" => vec![0.into(), 0.into(), width.into(), height.into()],
+            }),
+        );
+        let catalog_id = document.add_object(dictionary! {
+            "Type" => "Catalog",
+            "Pages" => pages_id,
+        });
+        document.trailer.set("Root", catalog_id);
+
+        let mut bytes = Vec::new();
+        document.save_to(&mut bytes).unwrap();
+        bytes
+    }
+
+    fn limits(pages: u32) -> ThumbnailLimits {
+        ThumbnailLimits {
+            pages,
+            max_pdf_size: 1024 * 1024,
+            budget: Duration::from_secs(20),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_renders_first_pages_as_webp() {
+        if !pdftoppm_installed() {
+            return;
+        }
+        let work = tempfile::tempdir().unwrap();
+        let pdf = work.path().join("main.pdf");
+        std::fs::write(&pdf, fixture_pdf(3, 300, 600)).unwrap();
+        assert_eq!(page_count(&pdf).unwrap(), 3);
+
+        let (pages, report) = render(&pdf, &limits(2), work.path()).await;
+        assert_eq!(report.status, ThumbnailStatus::Ready);
+        assert_eq!((report.pages, report.page_count, report.truncated), (2, Some(3), true));
+        assert_eq!(pages.iter().map(|page| page.page).collect::<Vec<_>>(), vec![1, 2]);
+        for page in &pages {
+            assert_eq!((page.width, page.height), (THUMBNAIL_WIDTH, 2 * THUMBNAIL_WIDTH));
+            let decoded = image::load_from_memory_with_format(&page.webp, ImageFormat::WebP).unwrap();
+            assert_eq!((decoded.width(), decoded.height()), (page.width, page.height));
+        }
+        assert!(!work.path().join("page-1.png").exists());
+    }
+
+    #[tokio::test]
+    async fn test_corrupt_and_oversized_pdfs_are_not_rendered() {
+        let work = tempfile::tempdir().unwrap();
+        let corrupt = work.path().join("corrupt.pdf");
+        std::fs::write(&corrupt, b"%PDF-1.5\nnot really a PDF").unwrap();
+        let (pages, report) = render(&corrupt, &limits(20), work.path()).await;
+        assert!(pages.is_empty());
+        assert_eq!(report.status, ThumbnailStatus::Failed);
+        assert!(report.error.unwrap().contains("Cannot read PDF"));
+
+        let large = work.path().join("large.pdf");
+        std::fs::write(&large, fixture_pdf(1, 300, 600)).unwrap();
+        let tiny = ThumbnailLimits { max_pdf_size: 16, ..limits(20) };
+        let (pages, report) = render(&large, &tiny, work.path()).await;
+        assert!(pages.is_empty());
+        assert_eq!((report.status, report.skipped), (ThumbnailStatus::Skipped, Some(ThumbnailSkip::PdfTooLarge)));
+
+        let (_, report) = render(&work.path().join("missing.pdf"), &limits(20), work.path()).await;
+        assert_eq!(report.skipped, Some(ThumbnailSkip::NoPdf));
+    }
+
+    #[tokio::test]
+    async fn test_claimed_jobs_get_thumbnails_unless_superseded() {
+        let Some(db) = TestDb::start().await else { return };
+        let user = create_test_user(&db.pool).await;
+        let project = create_test_project(&db.pool, &user, false).await;
+        let work = tempfile::tempdir().unwrap();
+        std::fs::write(work.path().join("main.pdf"), fixture_pdf(2, 300, 600)).unwrap();
+
+        let succeed = |job: CompilationJob, ago: i64| {
+            let pool = db.pool.clone();
+            let directory = work.path().to_string_lossy().into_owned();
+            async move {
+                sqlx::query(
+                    r#"
+                    UPDATE compilation_jobs
+                    SET status = $2, working_directory = $3, output_files = ARRAY['main.pdf'],
+                        started_at = NOW() - make_interval(secs => $4), completed_at = NOW() - make_interval(secs => $4)
+                    WHERE id = $1
+                    "#
+                )
+                .bind(job.id)
+                .bind(CompilationStatus::Success)
+                .bind(directory)
+                .bind(ago as f64)
+                .execute(&pool)
+                .await
+                .unwrap();
+            }
+        };
+        let earlier = create_test_job(&db.pool, &project, &user).await;
+        succeed(earlier.clone(), 60).await;
+        let latest = create_test_job(&db.pool, &project, &user).await;
+        succeed(latest.clone(), 10).await;
+
+        let claimed = claim(&db.pool, 10).await.unwrap();
+        assert_eq!(claimed.iter().map(|job| job.id).collect::<Vec<_>>(), vec![earlier.id, latest.id]);
+        assert!(claim(&db.pool, 10).await.unwrap().is_empty());
+        assert_eq!(claimed[0].thumbnail_report().unwrap().status, ThumbnailStatus::Pending);
+
+        let temp = tempfile::tempdir().unwrap();
+        let temp_dir = temp.path().to_str().unwrap();
+        let report = generate(&db.pool, &claimed[0], &limits(20), temp_dir).await.unwrap();
+        assert_eq!(report.skipped, Some(ThumbnailSkip::Superseded));
+        assert!(CompileThumbnail::list(&db.pool, earlier.id).await.unwrap().is_empty());
+
+        if !pdftoppm_installed() {
+            return;
+        }
+        let report = generate(&db.pool, &claimed[1], &limits(20), temp_dir).await.unwrap();
+        assert_eq!((report.status, report.pages), (ThumbnailStatus::Ready, 2));
+        let thumbnails = CompileThumbnail::list(&db.pool, latest.id).await.unwrap();
+        assert_eq!(thumbnails.iter().map(|thumbnail| thumbnail.page).collect::<Vec<_>>(), vec![1, 2]);
+        let image = CompileThumbnail::image(&db.pool, latest.id, 2).await.unwrap().unwrap();
+        assert_eq!(image.len() as i32, thumbnails[1].size_bytes);
+        let recorded = CompilationJob::find_by_id(&db.pool, latest.id, user.id).await.unwrap().unwrap();
+        assert_eq!(recorded.thumbnail_report(), Some(report));
+    }
+}
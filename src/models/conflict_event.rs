@@ -0,0 +1,599 @@
+//! Edit conflict telemetry
+//!
+//! Two kinds of conflict are recorded: requests refused with 409 Conflict,
+//! by route, and undo or redo entries of collaboration sessions that edits
+//! of other participants had to transform before they could be reverted.
+//! A transformed entry that still had text to revert was merged
+//! automatically; one whose text others removed entirely is discarded.
+//!
+//! Every conflict counts in `texler_edit_conflicts_total`. A sample of them,
+//! `LOG_CONFLICT_SAMPLE_RATE`, is buffered in [`ConflictRecorder`] and
+//! [`ConflictFlushTask`] writes the buffer to `conflict_events` in one
+//! insert, so neither requests nor edits wait on the database. Each row
+//! weighs the inverse of the rate it was sampled at, and reports add up
+//! weights to estimate all conflicts. The users involved are stored as
+//! hashes keyed with the JWT secret: rows of the same user can be told
+//! apart from others', but not traced back to them. Conflicts buffered when
+//! the server stops are lost.
+
+use chrono::{DateTime, Duration as ChronoDuration, Utc};
+use prometheus::{IntCounter, IntCounterVec, Opts, Registry, TextEncoder};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::FromRow;
+use std::sync::Mutex;
+use std::time::Duration;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::config::Config;
+use crate::error::AppError;
+use crate::server::AppState;
+
+/// How often buffered conflicts are written
+pub const CONFLICT_FLUSH_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Conflicts kept until the next flush; more are only counted
+pub const MAX_PENDING_CONFLICTS: usize = 10_000;
+
+/// Days conflict events are kept
+pub const CONFLICT_RETENTION_DAYS: i64 = 180;
+
+/// Days reports cover unless told otherwise
+pub const DEFAULT_REPORT_DAYS: i64 = 7;
+
+/// How a conflict came about
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, sqlx::Type, ToSchema)]
+#[sqlx(type_name = "conflictkind")]
+pub enum ConflictKind {
+    /// A request refused with 409 Conflict
+    #[serde(rename = "rest")]
+    #[sqlx(rename = "rest")]
+    Rest,
+    /// An undo or redo moved or cut short by edits of others
+    #[serde(rename = "transform")]
+    #[sqlx(rename = "transform")]
+    Transform,
+}
+
+impl ConflictKind {
+    pub const ALL: [ConflictKind; 2] = [ConflictKind::Rest, ConflictKind::Transform];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ConflictKind::Rest => "rest",
+            ConflictKind::Transform => "transform",
+        }
+    }
+}
+
+/// How a conflict was resolved
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, sqlx::Type, ToSchema)]
+#[sqlx(type_name = "conflictoutcome")]
+pub enum ConflictOutcome {
+    /// Refused, the user has to resolve it
+    #[serde(rename = "rejected")]
+    #[sqlx(rename = "rejected")]
+    Rejected,
+    /// Resolved without the user's help
+    #[serde(rename = "auto_merged")]
+    #[sqlx(rename = "auto_merged")]
+    AutoMerged,
+    /// The change was lost to edits of others
+    #[serde(rename = "discarded")]
+    #[sqlx(rename = "discarded")]
+    Discarded,
+}
+
+impl ConflictOutcome {
+    pub const ALL: [ConflictOutcome; 3] = [
+        ConflictOutcome::Rejected,
+        ConflictOutcome::AutoMerged,
+        ConflictOutcome::Discarded,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ConflictOutcome::Rejected => "rejected",
+            ConflictOutcome::AutoMerged => "auto_merged",
+            ConflictOutcome::Discarded => "discarded",
+        }
+    }
+}
+
+/// A conflict as it happened
+#[derive(Debug, Clone, PartialEq)]
+pub struct Conflict {
+    pub kind: ConflictKind,
+    pub outcome: ConflictOutcome,
+    /// Route pattern of a 409, or the collaboration message of a transform
+    pub endpoint: String,
+    pub project_id: Option<Uuid>,
+    pub file_id: Option<Uuid>,
+    /// Session of a transform; its project stands in when the file has none
+    pub session_id: Option<Uuid>,
+    pub users: Vec<Uuid>,
+    pub occurred_at: DateTime<Utc>,
+}
+
+/// A sampled conflict waiting to be written
+#[derive(Debug, Clone, Serialize)]
+struct PendingConflict {
+    kind: ConflictKind,
+    outcome: ConflictOutcome,
+    endpoint: String,
+    project_id: Option<Uuid>,
+    file_id: Option<Uuid>,
+    session_id: Option<Uuid>,
+    user_hashes: Vec<String>,
+    weight: f64,
+    occurred_at: DateTime<Utc>,
+}
+
+/// Counts every conflict and buffers a sample of them for `conflict_events`
+#[derive(Debug)]
+pub struct ConflictRecorder {
+    sample_rate: f64,
+    /// Key of the user hashes, derived from the JWT secret
+    user_key: [u8; 32],
+    pending: Mutex<Vec<PendingConflict>>,
+    registry: Registry,
+    conflicts: IntCounterVec,
+    dropped: IntCounter,
+}
+
+impl ConflictRecorder {
+    pub fn new(config: &Config) -> Self {
+        let conflicts = IntCounterVec::new(
+            Opts::new("texler_edit_conflicts_total", "Edit conflicts by kind and outcome"),
+            &["kind", "outcome"],
+        )
+        .expect("valid metric");
+        for kind in ConflictKind::ALL {
+            for outcome in ConflictOutcome::ALL {
+                conflicts.with_label_values(&[kind.as_str(), outcome.as_str()]);
+            }
+        }
+        let dropped = IntCounter::new(
+            "texler_edit_conflicts_dropped_total",
+            "Sampled edit conflicts not stored because the buffer was full",
+        )
+        .expect("valid metric");
+
+        let registry = Registry::new();
+        registry.register(Box::new(conflicts.clone())).expect("unique metric");
+        registry.register(Box::new(dropped.clone())).expect("unique metric");
+
+        let mut hasher = Sha256::new();
+        hasher.update(b"texler-conflict-users:");
+        hasher.update(config.jwt.secret.as_bytes());
+
+        Self {
+            sample_rate: config.logging.conflict_sample_rate,
+            user_key: hasher.finalize().into(),
+            pending: Mutex::new(Vec::new()),
+            registry,
+            conflicts,
+            dropped,
+        }
+    }
+
+    pub fn sample_rate(&self) -> f64 {
+        self.sample_rate
+    }
+
+    /// Count a conflict and keep it for the next flush if it is sampled
+    pub fn record(&self, conflict: Conflict) {
+        self.conflicts
+            .with_label_values(&[conflict.kind.as_str(), conflict.outcome.as_str()])
+            .inc();
+        if self.sample_rate <= 0.0 || rand::random::<f64>() >= self.sample_rate {
+            return;
+        }
+
+        let mut user_hashes: Vec<String> = conflict.users.iter().map(|user| self.hash_user(*user)).collect();
+        user_hashes.sort();
+        user_hashes.dedup();
+        let pending = PendingConflict {
+            kind: conflict.kind,
+            outcome: conflict.outcome,
+            endpoint: conflict.endpoint,
+            project_id: conflict.project_id,
+            file_id: conflict.file_id,
+            session_id: conflict.session_id,
+            user_hashes,
+            weight: 1.0 / self.sample_rate,
+            occurred_at: conflict.occurred_at,
+        };
+
+        let mut buffer = self.pending.lock().unwrap_or_else(|e| e.into_inner());
+        if buffer.len() < MAX_PENDING_CONFLICTS {
+            buffer.push(pending);
+        } else {
+            self.dropped.inc();
+        }
+    }
+
+    fn hash_user(&self, user_id: Uuid) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(self.user_key);
+        hasher.update(user_id.as_bytes());
+        hex::encode(&hasher.finalize()[..16])
+    }
+
+    /// Conflicts counted so far of a kind and outcome
+    pub fn count(&self, kind: ConflictKind, outcome: ConflictOutcome) -> u64 {
+        self.conflicts.with_label_values(&[kind.as_str(), outcome.as_str()]).get()
+    }
+
+    /// Conflicts waiting for the next flush
+    pub fn pending(&self) -> usize {
+        self.pending.lock().unwrap_or_else(|e| e.into_inner()).len()
+    }
+
+    /// Write the buffered conflicts; returns how many were buffered
+    ///
+    /// Conflicts of projects deleted since are skipped, and those of a
+    /// deleted file keep their project without the file.
+    pub async fn flush(&self, db: &sqlx::PgPool) -> Result<usize, AppError> {
+        let pending = std::mem::take(&mut *self.pending.lock().unwrap_or_else(|e| e.into_inner()));
+        if pending.is_empty() {
+            return Ok(0);
+        }
+
+        sqlx::query(
+            r#"
+            INSERT INTO conflict_events (kind, outcome, endpoint, project_id, file_id, user_hashes, weight, occurred_at)
+            SELECT e.kind::conflictkind, e.outcome::conflictoutcome, e.endpoint, p.id, f.id,
+                   e.user_hashes, e.weight, e.occurred_at
+            FROM jsonb_to_recordset($1) AS e(
+                kind TEXT, outcome TEXT, endpoint TEXT, project_id UUID, file_id UUID,
+                session_id UUID, user_hashes TEXT[], weight DOUBLE PRECISION, occurred_at TIMESTAMPTZ
+            )
+            LEFT JOIN files f ON f.id = e.file_id
+            LEFT JOIN collaboration_sessions s ON s.id = e.session_id
+            JOIN projects p ON p.id = COALESCE(e.project_id, f.project_id, s.project_id)
+            "#
+        )
+        .bind(sqlx::types::Json(&pending))
+        .execute(db)
+        .await
+        .map_err(AppError::Database)?;
+
+        Ok(pending.len())
+    }
+
+    /// Conflict counters in the Prometheus text format
+    pub fn metrics_text(&self) -> String {
+        let mut text = String::new();
+        if let Err(e) = TextEncoder::new().encode_utf8(&self.registry.gather(), &mut text) {
+            tracing::warn!("Failed to encode conflict metrics: {}", e);
+        }
+        text
+    }
+}
+
+/// Period of a report, by default the `DEFAULT_REPORT_DAYS` until `end` or now
+pub fn report_period(
+    start: Option<DateTime<Utc>>,
+    end: Option<DateTime<Utc>>,
+) -> Result<(DateTime<Utc>, DateTime<Utc>), AppError> {
+    let end = end.unwrap_or_else(Utc::now);
+    let start = start.unwrap_or(end - ChronoDuration::days(DEFAULT_REPORT_DAYS));
+    if end < start {
+        return Err(AppError::Validation("Period ends before it starts".to_string()));
+    }
+    Ok((start, end))
+}
+
+/// Estimated conflicts of a kind and outcome on one endpoint
+#[derive(Debug, Clone, Serialize, FromRow, ToSchema)]
+pub struct ConflictCount {
+    pub kind: ConflictKind,
+    pub outcome: ConflictOutcome,
+    pub endpoint: String,
+    /// Stored events
+    pub events: i64,
+    /// Conflicts the events stand for
+    pub conflicts: i64,
+}
+
+/// A project by its estimated conflicts
+#[derive(Debug, Clone, Serialize, FromRow, ToSchema)]
+pub struct ContendedProject {
+    pub project_id: Uuid,
+    pub project_name: String,
+    pub conflicts: i64,
+    /// Distinct users involved in its stored events
+    pub users: i64,
+}
+
+/// Conflicts of a period, for administrators
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct ConflictReport {
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    /// Rate conflicts are stored at now; older events keep the weight of theirs
+    pub sample_rate: f64,
+    /// Most conflicts first
+    pub counts: Vec<ConflictCount>,
+    /// Most contended projects first
+    pub projects: Vec<ContendedProject>,
+}
+
+impl ConflictReport {
+    pub async fn between(
+        db: &sqlx::PgPool,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        sample_rate: f64,
+        limit: i64,
+    ) -> Result<Self, AppError> {
+        let counts = sqlx::query_as::<_, ConflictCount>(
+            r#"
+            SELECT kind, outcome, endpoint, COUNT(*) AS events, ROUND(SUM(weight))::BIGINT AS conflicts
+            FROM conflict_events
+            WHERE occurred_at >= $1 AND occurred_at < $2
+            GROUP BY kind, outcome, endpoint
+            ORDER BY conflicts DESC, endpoint
+            "#
+        )
+        .bind(start)
+        .bind(end)
+        .fetch_all(db)
+        .await
+        .map_err(AppError::Database)?;
+
+        let projects = sqlx::query_as::<_, ContendedProject>(
+            r#"
+            WITH events AS (
+                SELECT project_id, user_hashes, weight
+                FROM conflict_events
+                WHERE occurred_at >= $1 AND occurred_at < $2
+            ),
+            totals AS (
+                SELECT project_id, ROUND(SUM(weight))::BIGINT AS conflicts
+                FROM events
+                GROUP BY project_id
+                ORDER BY conflicts DESC
+                LIMIT $3
+            )
+            SELECT t.project_id, p.name AS project_name, t.conflicts,
+                   (SELECT COUNT(DISTINCT h) FROM events e, UNNEST(e.user_hashes) AS h
+                    WHERE e.project_id = t.project_id) AS users
+            FROM totals t
+            JOIN projects p ON p.id = t.project_id
+            ORDER BY t.conflicts DESC, p.name
+            "#
+        )
+        .bind(start)
+        .bind(end)
+        .bind(limit)
+        .fetch_all(db)
+        .await
+        .map_err(AppError::Database)?;
+
+        Ok(Self { start, end, sample_rate, counts, projects })
+    }
+}
+
+/// Estimated conflicts by outcome
+#[derive(Debug, Clone, Serialize, FromRow, ToSchema)]
+pub struct ResolutionCounts {
+    /// Unset for the total of every kind
+    pub kind: Option<ConflictKind>,
+    pub conflicts: i64,
+    pub auto_merged: i64,
+    pub discarded: i64,
+    pub rejected: i64,
+    /// Share of the conflicts merged automatically; unset without conflicts
+    pub auto_resolution_rate: Option<f64>,
+}
+
+/// How many conflicts of a period were resolved automatically
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct AutoResolutionReport {
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    pub total: ResolutionCounts,
+    pub by_kind: Vec<ResolutionCounts>,
+}
+
+impl AutoResolutionReport {
+    pub async fn between(db: &sqlx::PgPool, start: DateTime<Utc>, end: DateTime<Utc>) -> Result<Self, AppError> {
+        let rows = sqlx::query_as::<_, ResolutionCounts>(
+            r#"
+            SELECT kind,
+                   ROUND(COALESCE(SUM(weight), 0))::BIGINT AS conflicts,
+                   ROUND(COALESCE(SUM(weight) FILTER (WHERE outcome = 'auto_merged'), 0))::BIGINT AS auto_merged,
+                   ROUND(COALESCE(SUM(weight) FILTER (WHERE outcome = 'discarded'), 0))::BIGINT AS discarded,
+                   ROUND(COALESCE(SUM(weight) FILTER (WHERE outcome = 'rejected'), 0))::BIGINT AS rejected,
+                   COALESCE(SUM(weight) FILTER (WHERE outcome = 'auto_merged'), 0) / NULLIF(SUM(weight), 0)
+                       AS auto_resolution_rate
+            FROM conflict_events
+            WHERE occurred_at >= $1 AND occurred_at < $2
+            GROUP BY ROLLUP (kind)
+            ORDER BY kind NULLS FIRST
+            "#
+        )
+        .bind(start)
+        .bind(end)
+        .fetch_all(db)
+        .await
+        .map_err(AppError::Database)?;
+
+        // Without events the rollup still has its total row
+        let mut rows = rows.into_iter();
+        let total = rows.next().unwrap_or(ResolutionCounts {
+            kind: None,
+            conflicts: 0,
+            auto_merged: 0,
+            discarded: 0,
+            rejected: 0,
+            auto_resolution_rate: None,
+        });
+
+        Ok(Self { start, end, total, by_kind: rows.collect() })
+    }
+}
+
+/// A file of a project by its estimated conflicts; says nothing of who
+/// was involved
+#[derive(Debug, Clone, Serialize, FromRow, ToSchema)]
+pub struct ContendedFile {
+    pub file_id: Uuid,
+    pub path: String,
+    pub conflicts: i64,
+    pub auto_merged: i64,
+    pub last_conflict_at: DateTime<Utc>,
+}
+
+impl ContendedFile {
+    /// Most contended files of a project first
+    pub async fn for_project(
+        db: &sqlx::PgPool,
+        project_id: Uuid,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        limit: i64,
+    ) -> Result<Vec<Self>, AppError> {
+        sqlx::query_as::<_, ContendedFile>(
+            r#"
+            SELECT f.id AS file_id, f.path,
+                   ROUND(SUM(e.weight))::BIGINT AS conflicts,
+                   ROUND(COALESCE(SUM(e.weight) FILTER (WHERE e.outcome = 'auto_merged'), 0))::BIGINT AS auto_merged,
+                   MAX(e.occurred_at) AS last_conflict_at
+            FROM conflict_events e
+            JOIN files f ON f.id = e.file_id AND NOT f.is_deleted
+            WHERE e.project_id = $1 AND e.occurred_at >= $2 AND e.occurred_at < $3
+            GROUP BY f.id, f.path
+            ORDER BY conflicts DESC, last_conflict_at DESC
+            LIMIT $4
+            "#
+        )
+        .bind(project_id)
+        .bind(start)
+        .bind(end)
+        .bind(limit)
+        .fetch_all(db)
+        .await
+        .map_err(AppError::Database)
+    }
+}
+
+/// Delete conflict events past the retention period
+pub async fn prune(db: &sqlx::PgPool, now: DateTime<Utc>) -> Result<u64, AppError> {
+    let result = sqlx::query("DELETE FROM conflict_events WHERE occurred_at < $1")
+        .bind(now - ChronoDuration::days(CONFLICT_RETENTION_DAYS))
+        .execute(db)
+        .await
+        .map_err(AppError::Database)?;
+
+    Ok(result.rows_affected())
+}
+
+/// Writes buffered conflicts and drops those past the retention period
+pub struct ConflictFlushTask;
+
+impl crate::tasks::PeriodicTask for ConflictFlushTask {
+    fn name(&self) -> &'static str {
+        "conflict_event_flush"
+    }
+
+    fn interval(&self) -> Duration {
+        CONFLICT_FLUSH_INTERVAL
+    }
+
+    fn run<'a>(&'a self, state: &'a AppState) -> futures::future::BoxFuture<'a, Result<(), AppError>> {
+        Box::pin(async move {
+            state.conflicts.flush(&state.db_pool).await?;
+            prune(&state.db_pool, Utc::now()).await?;
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{create_test_file, create_test_project, create_test_user, test_config, TestDb};
+
+    fn recorder(sample_rate: f64) -> ConflictRecorder {
+        let mut config = test_config();
+        config.logging.conflict_sample_rate = sample_rate;
+        ConflictRecorder::new(&config)
+    }
+
+    fn conflict(outcome: ConflictOutcome, project_id: Option<Uuid>, file_id: Option<Uuid>, users: Vec<Uuid>) -> Conflict {
+        Conflict {
+            kind: if outcome == ConflictOutcome::Rejected { ConflictKind::Rest } else { ConflictKind::Transform },
+            outcome,
+            endpoint: "PUT /api/v1/files/:id".to_string(),
+            project_id,
+            file_id,
+            session_id: None,
+            users,
+            occurred_at: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn test_unsampled_conflicts_are_only_counted() {
+        let recorder = recorder(0.0);
+        recorder.record(conflict(ConflictOutcome::Rejected, None, None, vec![Uuid::new_v4()]));
+        recorder.record(conflict(ConflictOutcome::Rejected, None, None, vec![]));
+
+        assert_eq!(recorder.count(ConflictKind::Rest, ConflictOutcome::Rejected), 2);
+        assert_eq!(recorder.pending(), 0);
+        assert!(recorder.metrics_text().contains("texler_edit_conflicts_total{kind=\"rest\",outcome=\"rejected\"} 2"));
+    }
+
+    #[test]
+    fn test_users_are_hashed_with_the_secret() {
+        let user = Uuid::new_v4();
+        let recorder = recorder(1.0);
+        recorder.record(conflict(ConflictOutcome::AutoMerged, None, None, vec![user, user]));
+
+        let pending = recorder.pending.lock().unwrap();
+        assert_eq!(pending[0].user_hashes, vec![recorder.hash_user(user)]);
+        assert_eq!(pending[0].weight, 1.0);
+
+        let mut other = test_config();
+        other.jwt.secret = "another-secret-that-is-long-enough-to-use".to_string();
+        assert_ne!(ConflictRecorder::new(&other).hash_user(user), recorder.hash_user(user));
+    }
+
+    #[tokio::test]
+    async fn test_flushed_conflicts_are_reported() {
+        let Some(db) = TestDb::start().await else { return };
+        let (alice, bob) = (create_test_user(&db.pool).await, create_test_user(&db.pool).await);
+        let project = create_test_project(&db.pool, &alice, false).await;
+        let file = create_test_file(&db.pool, &project, &alice).await;
+        let recorder = recorder(1.0);
+
+        recorder.record(conflict(ConflictOutcome::Rejected, Some(project.id), Some(file.id), vec![alice.id]));
+        recorder.record(conflict(ConflictOutcome::AutoMerged, None, Some(file.id), vec![alice.id, bob.id]));
+        recorder.record(conflict(ConflictOutcome::AutoMerged, None, Some(file.id), vec![bob.id, alice.id]));
+        recorder.record(conflict(ConflictOutcome::Discarded, None, Some(file.id), vec![bob.id, alice.id]));
+        // Neither a project nor a file to place it in
+        recorder.record(conflict(ConflictOutcome::Rejected, None, None, vec![alice.id]));
+        assert_eq!(recorder.flush(&db.pool).await.unwrap(), 5);
+        assert_eq!(recorder.pending(), 0);
+
+        let (start, end) = (Utc::now() - ChronoDuration::hours(1), Utc::now() + ChronoDuration::hours(1));
+        let resolution = AutoResolutionReport::between(&db.pool, start, end).await.unwrap();
+        assert_eq!(resolution.total.conflicts, 4);
+        assert_eq!(resolution.total.auto_merged, 2);
+        assert_eq!(resolution.total.auto_resolution_rate, Some(0.5));
+        let transforms = resolution.by_kind.iter().find(|counts| counts.kind == Some(ConflictKind::Transform)).unwrap();
+        assert_eq!((transforms.conflicts, transforms.discarded), (3, 1));
+
+        let report = ConflictReport::between(&db.pool, start, end, 1.0, 10).await.unwrap();
+        assert_eq!(report.projects.len(), 1);
+        assert_eq!((report.projects[0].conflicts, report.projects[0].users), (4, 2));
+
+        let files = ContendedFile::for_project(&db.pool, project.id, start, end, 10).await.unwrap();
+        assert_eq!(files.len(), 1);
+        assert_eq!((files[0].conflicts, files[0].auto_merged), (4, 2));
+    }
+}
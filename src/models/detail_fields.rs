@@ -0,0 +1,139 @@
+//! Partial detail responses
+//!
+//! Project and file responses carry sections that each cost a query: owner,
+//! collaborators, statistics and freeze of a project, author and versions of
+//! a file. Clients that need only some of them name them in `?fields=`, a
+//! comma-separated list; sections left out are neither queried nor sent.
+//! Without the parameter every section is included.
+//!
+//! Lists load each section with one query for the whole page (see
+//! `Project::with_details` and `File::with_details`), so a list costs the
+//! same number of queries for one item as for a hundred, and leaving a
+//! section out saves that one query.
+
+use serde::Deserialize;
+use utoipa::IntoParams;
+
+use crate::error::AppError;
+
+/// Section selection of a detail response
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct FieldsParams {
+    /// Comma-separated sections to include, e.g. `project,owner`; all when unset
+    pub fields: Option<String>,
+}
+
+/// Sections of `ProjectWithDetails`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProjectFields {
+    pub owner: bool,
+    pub collaborators: bool,
+    /// `file_count`, `word_count` and `tag_count`
+    pub stats: bool,
+    /// `frozen` and `freeze`
+    pub freeze: bool,
+}
+
+impl ProjectFields {
+    /// Names accepted in `fields`; `project` is the project itself, always included
+    pub const NAMES: &'static [&'static str] = &["project", "owner", "collaborators", "stats", "freeze"];
+
+    pub const ALL: Self = Self {
+        owner: true,
+        collaborators: true,
+        stats: true,
+        freeze: true,
+    };
+
+    pub fn parse(fields: Option<&str>) -> Result<Self, AppError> {
+        let Some(names) = parse_names(fields, Self::NAMES)? else {
+            return Ok(Self::ALL);
+        };
+
+        Ok(Self {
+            owner: names.contains(&"owner"),
+            collaborators: names.contains(&"collaborators"),
+            stats: names.contains(&"stats"),
+            freeze: names.contains(&"freeze"),
+        })
+    }
+}
+
+/// Sections of `FileWithDetails`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileFields {
+    pub modified_by: bool,
+    pub versions: bool,
+}
+
+impl FileFields {
+    /// Names accepted in `fields`; `file` is the file itself, always included
+    pub const NAMES: &'static [&'static str] = &["file", "modified_by", "versions"];
+
+    pub const ALL: Self = Self {
+        modified_by: true,
+        versions: true,
+    };
+
+    pub fn parse(fields: Option<&str>) -> Result<Self, AppError> {
+        let Some(names) = parse_names(fields, Self::NAMES)? else {
+            return Ok(Self::ALL);
+        };
+
+        Ok(Self {
+            modified_by: names.contains(&"modified_by"),
+            versions: names.contains(&"versions"),
+        })
+    }
+}
+
+/// The names of a `fields` value, `None` when it is absent
+fn parse_names<'a>(fields: Option<&'a str>, allowed: &[&str]) -> Result<Option<Vec<&'a str>>, AppError> {
+    let Some(fields) = fields else {
+        return Ok(None);
+    };
+
+    let names: Vec<&str> = fields.split(',').map(str::trim).filter(|name| !name.is_empty()).collect();
+    if names.is_empty() {
+        return Err(AppError::Validation("fields must name at least one section".to_string()));
+    }
+    if let Some(unknown) = names.iter().find(|name| !allowed.contains(name)) {
+        return Err(AppError::Validation(format!(
+            "Unknown field '{}'; expected one of {}",
+            unknown,
+            allowed.join(", ")
+        )));
+    }
+
+    Ok(Some(names))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_fields() {
+        assert_eq!(ProjectFields::parse(None).unwrap(), ProjectFields::ALL);
+        assert_eq!(
+            ProjectFields::parse(Some("project, owner")).unwrap(),
+            ProjectFields {
+                owner: true,
+                collaborators: false,
+                stats: false,
+                freeze: false,
+            }
+        );
+        assert_eq!(
+            FileFields::parse(Some("file")).unwrap(),
+            FileFields {
+                modified_by: false,
+                versions: false,
+            }
+        );
+
+        assert!(matches!(ProjectFields::parse(Some("owner,versions")), Err(AppError::Validation(_))));
+        assert!(matches!(FileFields::parse(Some(" , ")), Err(AppError::Validation(_))));
+    }
+}
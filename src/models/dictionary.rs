@@ -0,0 +1,444 @@
+//! Spell checker dictionaries
+//!
+//! A project dictionary holds words and multi-word terms every member accepts;
+//! a personal dictionary follows one user across projects. Terms are stored
+//! NFC normalized with whitespace collapsed, and compared case-insensitively
+//! unless the entry asks for an exact case match.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use std::collections::HashSet;
+use unicode_normalization::UnicodeNormalization;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::error::AppError;
+
+/// Entries a single project dictionary may hold
+pub const MAX_PROJECT_DICTIONARY_ENTRIES: i64 = 5000;
+
+/// Entries a personal dictionary may hold
+pub const MAX_USER_DICTIONARY_ENTRIES: i64 = 2000;
+
+/// Longest accepted term, in characters
+pub const MAX_TERM_CHARS: usize = 100;
+
+/// Terms accepted in one add or import request
+pub const MAX_TERMS_PER_REQUEST: usize = 1000;
+
+/// Dictionary an entry belongs to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DictionaryScope {
+    Project(Uuid),
+    User(Uuid),
+}
+
+impl DictionaryScope {
+    fn table(self) -> &'static str {
+        match self {
+            Self::Project(_) => "project_dictionary",
+            Self::User(_) => "user_dictionary",
+        }
+    }
+
+    fn owner_column(self) -> &'static str {
+        match self {
+            Self::Project(_) => "project_id",
+            Self::User(_) => "user_id",
+        }
+    }
+
+    fn owner_id(self) -> Uuid {
+        match self {
+            Self::Project(id) | Self::User(id) => id,
+        }
+    }
+
+    /// Table and key of the row locked while checking the size cap
+    fn owner_table(self) -> &'static str {
+        match self {
+            Self::Project(_) => "projects",
+            Self::User(_) => "users",
+        }
+    }
+
+    fn max_entries(self) -> i64 {
+        match self {
+            Self::Project(_) => MAX_PROJECT_DICTIONARY_ENTRIES,
+            Self::User(_) => MAX_USER_DICTIONARY_ENTRIES,
+        }
+    }
+
+    /// Columns shared by both tables; personal entries have no `added_by`
+    fn columns(self) -> &'static str {
+        match self {
+            Self::Project(_) => "id, term, case_sensitive, added_by, created_at",
+            Self::User(_) => "id, term, case_sensitive, user_id AS added_by, created_at",
+        }
+    }
+}
+
+/// Dictionary entry
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, ToSchema)]
+pub struct DictionaryEntry {
+    pub id: Uuid,
+    pub term: String,
+    pub case_sensitive: bool,
+    pub added_by: Option<Uuid>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Term to add
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct NewTerm {
+    pub term: String,
+    #[serde(default)]
+    pub case_sensitive: bool,
+}
+
+/// What happened to one submitted term
+#[derive(Debug, Clone, Serialize, ToSchema)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum TermOutcome {
+    Added { term: String, entry: DictionaryEntry },
+    /// The term was already in the dictionary; adding it again is a no-op
+    Exists { term: String },
+    Invalid { term: String, message: String },
+    LimitReached { term: String },
+}
+
+/// Normalize a term for storage: NFC, trimmed, inner whitespace collapsed
+pub fn normalize_term(raw: &str) -> Result<String, String> {
+    let normalized: String = raw.nfc().collect();
+    let term = normalized.split_whitespace().collect::<Vec<_>>().join(" ");
+
+    if term.is_empty() {
+        return Err("Term is empty".to_string());
+    }
+    if term.chars().count() > MAX_TERM_CHARS {
+        return Err(format!("Term is longer than {} characters", MAX_TERM_CHARS));
+    }
+    if term.chars().any(char::is_control) {
+        return Err("Term contains control characters".to_string());
+    }
+
+    Ok(term)
+}
+
+/// Lookup key of a normalized term
+pub fn term_key(term: &str, case_sensitive: bool) -> String {
+    if case_sensitive {
+        term.to_string()
+    } else {
+        term.to_lowercase()
+    }
+}
+
+/// Terms from a plain text list: one per line, `#` starts a comment line.
+/// A line ending in ` !` keeps its case.
+pub fn parse_term_list(text: &str) -> Vec<NewTerm> {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| match line.strip_suffix(" !") {
+            Some(term) => NewTerm {
+                term: term.to_string(),
+                case_sensitive: true,
+            },
+            None => NewTerm {
+                term: line.to_string(),
+                case_sensitive: false,
+            },
+        })
+        .collect()
+}
+
+impl DictionaryEntry {
+    /// Entries of a dictionary, alphabetically
+    pub async fn list(db: &sqlx::PgPool, scope: DictionaryScope) -> Result<Vec<Self>, AppError> {
+        let entries = sqlx::query_as::<_, DictionaryEntry>(&format!(
+            "SELECT {} FROM {} WHERE {} = $1 ORDER BY term_key, case_sensitive",
+            scope.columns(),
+            scope.table(),
+            scope.owner_column()
+        ))
+        .bind(scope.owner_id())
+        .fetch_all(db)
+        .await
+        .map_err(AppError::Database)?;
+
+        Ok(entries)
+    }
+
+    /// Add terms, skipping ones already present and stopping at the size cap
+    pub async fn add_terms(
+        db: &sqlx::PgPool,
+        scope: DictionaryScope,
+        terms: Vec<NewTerm>,
+        added_by: Uuid,
+    ) -> Result<Vec<TermOutcome>, AppError> {
+        if terms.len() > MAX_TERMS_PER_REQUEST {
+            return Err(AppError::Validation(format!(
+                "At most {} terms can be added at once",
+                MAX_TERMS_PER_REQUEST
+            )));
+        }
+
+        let mut tx = db.begin().await.map_err(AppError::Database)?;
+
+        // Serialize writers of the same dictionary so the cap holds
+        sqlx::query(&format!("SELECT id FROM {} WHERE id = $1 FOR UPDATE", scope.owner_table()))
+            .bind(scope.owner_id())
+            .execute(&mut *tx)
+            .await
+            .map_err(AppError::Database)?;
+
+        let mut count = sqlx::query_scalar::<_, i64>(&format!(
+            "SELECT COUNT(*) FROM {} WHERE {} = $1",
+            scope.table(),
+            scope.owner_column()
+        ))
+        .bind(scope.owner_id())
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(AppError::Database)?;
+
+        let insert = match scope {
+            DictionaryScope::Project(_) => format!(
+                r#"
+                INSERT INTO project_dictionary (project_id, term, term_key, case_sensitive, added_by)
+                VALUES ($1, $2, $3, $4, $5)
+                ON CONFLICT (project_id, term_key, case_sensitive) DO NOTHING
+                RETURNING {}
+                "#,
+                scope.columns()
+            ),
+            DictionaryScope::User(_) => format!(
+                r#"
+                INSERT INTO user_dictionary (user_id, term, term_key, case_sensitive)
+                VALUES ($1, $2, $3, $4)
+                ON CONFLICT (user_id, term_key, case_sensitive) DO NOTHING
+                RETURNING {}
+                "#,
+                scope.columns()
+            ),
+        };
+
+        let mut outcomes = Vec::with_capacity(terms.len());
+        for new_term in terms {
+            let term = match normalize_term(&new_term.term) {
+                Ok(term) => term,
+                Err(message) => {
+                    outcomes.push(TermOutcome::Invalid {
+                        term: new_term.term,
+                        message,
+                    });
+                    continue;
+                }
+            };
+
+            if count >= scope.max_entries() {
+                outcomes.push(TermOutcome::LimitReached { term });
+                continue;
+            }
+
+            let mut query = sqlx::query_as::<_, DictionaryEntry>(&insert)
+                .bind(scope.owner_id())
+                .bind(&term)
+                .bind(term_key(&term, new_term.case_sensitive))
+                .bind(new_term.case_sensitive);
+            if let DictionaryScope::Project(_) = scope {
+                query = query.bind(added_by);
+            }
+
+            let inserted = query
+                .fetch_optional(&mut *tx)
+                .await
+                .map_err(AppError::Database)?;
+
+            outcomes.push(match inserted {
+                Some(entry) => {
+                    count += 1;
+                    TermOutcome::Added { term, entry }
+                }
+                None => TermOutcome::Exists { term },
+            });
+        }
+
+        tx.commit().await.map_err(AppError::Database)?;
+
+        Ok(outcomes)
+    }
+
+    /// Remove an entry, returning it when it existed
+    pub async fn remove(
+        db: &sqlx::PgPool,
+        scope: DictionaryScope,
+        entry_id: Uuid,
+    ) -> Result<Option<Self>, AppError> {
+        let entry = sqlx::query_as::<_, DictionaryEntry>(&format!(
+            "DELETE FROM {} WHERE id = $1 AND {} = $2 RETURNING {}",
+            scope.table(),
+            scope.owner_column(),
+            scope.columns()
+        ))
+        .bind(entry_id)
+        .bind(scope.owner_id())
+        .fetch_optional(db)
+        .await
+        .map_err(AppError::Database)?;
+
+        Ok(entry)
+    }
+}
+
+/// Where an effective dictionary term comes from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum TermSource {
+    Project,
+    Personal,
+}
+
+/// Term of an effective dictionary
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct EffectiveTerm {
+    pub term: String,
+    pub case_sensitive: bool,
+    pub source: TermSource,
+}
+
+/// Project dictionary merged with a user's personal one, as the spell
+/// checker sees it
+#[derive(Debug, Clone, Default)]
+pub struct EffectiveDictionary {
+    terms: Vec<EffectiveTerm>,
+    exact: HashSet<String>,
+    folded: HashSet<String>,
+}
+
+impl EffectiveDictionary {
+    /// Load the dictionary a user works with on a project
+    pub async fn load(db: &sqlx::PgPool, project_id: Uuid, user_id: Uuid) -> Result<Self, AppError> {
+        let project = DictionaryEntry::list(db, DictionaryScope::Project(project_id)).await?;
+        let personal = DictionaryEntry::list(db, DictionaryScope::User(user_id)).await?;
+
+        Ok(Self::from_entries(
+            project
+                .into_iter()
+                .map(|entry| (entry, TermSource::Project))
+                .chain(personal.into_iter().map(|entry| (entry, TermSource::Personal))),
+        ))
+    }
+
+    /// Build from entries; the first entry of a term wins
+    pub fn from_entries(entries: impl IntoIterator<Item = (DictionaryEntry, TermSource)>) -> Self {
+        let mut dictionary = Self::default();
+
+        for (entry, source) in entries {
+            let inserted = if entry.case_sensitive {
+                dictionary.exact.insert(entry.term.clone())
+            } else {
+                dictionary.folded.insert(entry.term.to_lowercase())
+            };
+
+            if inserted {
+                dictionary.terms.push(EffectiveTerm {
+                    term: entry.term,
+                    case_sensitive: entry.case_sensitive,
+                    source,
+                });
+            }
+        }
+
+        dictionary.terms.sort_by(|a, b| a.term.to_lowercase().cmp(&b.term.to_lowercase()));
+        dictionary
+    }
+
+    /// Whether a word or phrase flagged by the spell checker is accepted
+    pub fn accepts(&self, text: &str) -> bool {
+        let Ok(term) = normalize_term(text) else {
+            return false;
+        };
+
+        self.exact.contains(&term) || self.folded.contains(&term.to_lowercase())
+    }
+
+    pub fn terms(&self) -> &[EffectiveTerm] {
+        &self.terms
+    }
+
+    /// Plain text list in the format `parse_term_list` reads
+    pub fn to_text(&self) -> String {
+        self.terms
+            .iter()
+            .map(|term| {
+                if term.case_sensitive {
+                    format!("{} !\n", term.term)
+                } else {
+                    format!("{}\n", term.term)
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(term: &str, case_sensitive: bool) -> DictionaryEntry {
+        DictionaryEntry {
+            id: Uuid::new_v4(),
+            term: term.to_string(),
+            case_sensitive,
+            added_by: None,
+            created_at: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn test_normalize_term() {
+        // Decomposed "é" is stored composed
+        assert_eq!(normalize_term("  Poincare\u{301}  ").unwrap(), "Poincaré");
+        assert_eq!(normalize_term("Navier \t Stokes").unwrap(), "Navier Stokes");
+        assert!(normalize_term("   ").is_err());
+        assert!(normalize_term(&"a".repeat(MAX_TERM_CHARS + 1)).is_err());
+        assert!(normalize_term("bad\u{7}term").is_err());
+    }
+
+    #[test]
+    fn test_parse_term_list() {
+        let terms = parse_term_list("# physics\nhomotopy\n\n  LaTeX !\nNavier Stokes\n");
+
+        assert_eq!(terms.len(), 3);
+        assert_eq!(terms[0].term, "homotopy");
+        assert!(!terms[0].case_sensitive);
+        assert_eq!(terms[1].term, "LaTeX");
+        assert!(terms[1].case_sensitive);
+        assert_eq!(terms[2].term, "Navier Stokes");
+    }
+
+    #[test]
+    fn test_effective_dictionary_matching() {
+        let dictionary = EffectiveDictionary::from_entries(vec![
+            (entry("homotopy", false), TermSource::Project),
+            (entry("LaTeX", true), TermSource::Project),
+            (entry("Navier Stokes", false), TermSource::Personal),
+            (entry("Homotopy", false), TermSource::Personal),
+        ]);
+
+        assert!(dictionary.accepts("Homotopy"));
+        assert!(dictionary.accepts("LaTeX"));
+        assert!(!dictionary.accepts("Latex"));
+        assert!(dictionary.accepts("navier  stokes"));
+        assert!(!dictionary.accepts("stokes"));
+
+        // The personal duplicate of a project term is dropped
+        assert_eq!(dictionary.terms().len(), 3);
+        assert_eq!(dictionary.terms()[0].term, "homotopy");
+        assert_eq!(dictionary.terms()[0].source, TermSource::Project);
+        assert_eq!(dictionary.to_text(), "homotopy\nLaTeX !\nNavier Stokes\n");
+    }
+}
@@ -0,0 +1,697 @@
+//! Discovery of public projects
+//!
+//! Public projects can be browsed without an account in three ways: trending,
+//! recently updated and in collections curated by administrators. Listings
+//! carry only what anyone may see of a public project plus its owner's public
+//! profile, and projects of deactivated owners are left out.
+//!
+//! Trending ranks projects by recent views, clones and compiles. Each event
+//! weighs less by half every [`TRENDING_HALF_LIFE_HOURS`] and events older
+//! than [`TRENDING_WINDOW_DAYS`] no longer count. [`TrendingTask`] writes the
+//! scores to `project_trending_scores`, so listing them is a plain read; a
+//! project made private since is skipped until the next computation drops it.
+//!
+//! A view is a non-member opening a public project. Views are buffered in
+//! [`ProjectViews`], each viewer counted once per project until the next
+//! flush, and [`ViewFlushTask`] adds them to daily counters. Views buffered
+//! when the server stops are lost.
+
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+use std::time::Duration;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use super::{LatexEngine, PaginationParams};
+use crate::error::AppError;
+use crate::server::AppState;
+
+/// How often buffered views are written
+pub const VIEW_FLUSH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How often trending scores are recomputed
+pub const TRENDING_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+/// Age at which an event counts half
+pub const TRENDING_HALF_LIFE_HOURS: f64 = 48.0;
+
+/// Age past which events no longer count
+pub const TRENDING_WINDOW_DAYS: i64 = 14;
+
+/// Weight of a view
+pub const VIEW_WEIGHT: f64 = 1.0;
+
+/// Weight of a clone
+pub const CLONE_WEIGHT: f64 = 10.0;
+
+/// Weight of a compile
+pub const COMPILE_WEIGHT: f64 = 2.0;
+
+/// Most projects in a collection
+pub const MAX_COLLECTION_PROJECTS: usize = 50;
+
+/// Public profile of a project's owner
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ProjectOwner {
+    pub id: Uuid,
+    pub username: String,
+    pub display_name: String,
+    pub avatar_url: Option<String>,
+}
+
+/// A public project as listed for discovery
+#[derive(Debug, Clone, Serialize, FromRow, ToSchema)]
+pub struct DiscoveredProject {
+    pub id: Uuid,
+    pub name: String,
+    pub description: Option<String>,
+    pub latex_engine: LatexEngine,
+    pub created_at: DateTime<Utc>,
+    /// Latest change to the project's settings or files
+    pub last_updated_at: DateTime<Utc>,
+    #[sqlx(json)]
+    pub owner: ProjectOwner,
+}
+
+/// A trending project with its score
+#[derive(Debug, Clone, Serialize, FromRow, ToSchema)]
+pub struct TrendingProject {
+    #[serde(flatten)]
+    #[sqlx(flatten)]
+    pub project: DiscoveredProject,
+    pub score: f64,
+    /// Views, clones and compiles within the trending window, unweighted
+    pub views: i64,
+    pub clones: i64,
+    pub compiles: i64,
+    pub computed_at: DateTime<Utc>,
+}
+
+/// Columns of a [`DiscoveredProject`], over [`LISTED_PROJECTS`]
+const PROJECT_COLUMNS: &str = r#"
+    p.id, p.name, p.description, p.latex_engine, p.created_at,
+    GREATEST(p.updated_at, f.last_modified, p.created_at) AS last_updated_at,
+    json_build_object(
+        'id', u.id,
+        'username', u.username,
+        'display_name', COALESCE(u.display_name, u.username),
+        'avatar_url', u.avatar_url
+    ) AS owner
+"#;
+
+/// Public projects that may be listed, with their owners and latest file change
+const LISTED_PROJECTS: &str = r#"
+    projects p
+    JOIN users u ON u.id = p.owner_id AND u.is_active IS NOT FALSE
+    LEFT JOIN LATERAL (
+        SELECT MAX(last_modified) AS last_modified FROM files
+        WHERE project_id = p.id AND is_deleted = false
+    ) f ON true
+"#;
+
+/// Filter of [`LISTED_PROJECTS`]
+const LISTED: &str = "p.is_public = true AND p.purging_at IS NULL";
+
+impl DiscoveredProject {
+    /// Public projects, most recently updated first
+    pub async fn recent(db: &sqlx::PgPool, params: &PaginationParams) -> Result<(Vec<Self>, i64), AppError> {
+        let projects = sqlx::query_as::<_, DiscoveredProject>(&format!(
+            r#"
+            SELECT {PROJECT_COLUMNS} FROM {LISTED_PROJECTS}
+            WHERE {LISTED}
+            ORDER BY last_updated_at DESC, p.id
+            LIMIT $1 OFFSET $2
+            "#
+        ))
+        .bind(params.limit() as i64)
+        .bind(params.offset() as i64)
+        .fetch_all(db)
+        .await
+        .map_err(AppError::Database)?;
+
+        let total = sqlx::query_scalar::<_, i64>(&format!(
+            "SELECT COUNT(*) FROM projects p JOIN users u ON u.id = p.owner_id AND u.is_active IS NOT FALSE WHERE {LISTED}"
+        ))
+        .fetch_one(db)
+        .await
+        .map_err(AppError::Database)?;
+
+        Ok((projects, total))
+    }
+}
+
+impl TrendingProject {
+    /// Projects by their latest trending score, highest first
+    pub async fn list(db: &sqlx::PgPool, params: &PaginationParams) -> Result<(Vec<Self>, i64), AppError> {
+        let projects = sqlx::query_as::<_, TrendingProject>(&format!(
+            r#"
+            SELECT {PROJECT_COLUMNS}, t.score, t.views, t.clones, t.compiles, t.computed_at
+            FROM project_trending_scores t
+            JOIN {LISTED_PROJECTS} ON p.id = t.project_id
+            WHERE {LISTED}
+            ORDER BY t.score DESC, t.project_id
+            LIMIT $1 OFFSET $2
+            "#
+        ))
+        .bind(params.limit() as i64)
+        .bind(params.offset() as i64)
+        .fetch_all(db)
+        .await
+        .map_err(AppError::Database)?;
+
+        let total = sqlx::query_scalar::<_, i64>(&format!(
+            r#"
+            SELECT COUNT(*) FROM project_trending_scores t
+            JOIN projects p ON p.id = t.project_id
+            JOIN users u ON u.id = p.owner_id AND u.is_active IS NOT FALSE
+            WHERE {LISTED}
+            "#
+        ))
+        .fetch_one(db)
+        .await
+        .map_err(AppError::Database)?;
+
+        Ok((projects, total))
+    }
+}
+
+/// Recompute the trending scores of all public projects as of `now`,
+/// returning how many projects have one
+pub async fn compute_trending(db: &sqlx::PgPool, now: DateTime<Utc>) -> Result<u64, AppError> {
+    let since = now - chrono::Duration::days(TRENDING_WINDOW_DAYS);
+    let mut tx = db.begin().await.map_err(AppError::Database)?;
+    sqlx::query("DELETE FROM project_trending_scores")
+        .execute(&mut *tx)
+        .await
+        .map_err(AppError::Database)?;
+
+    // Views of a day count as of its middle, or now for today's
+    let scored = sqlx::query(
+        r#"
+        INSERT INTO project_trending_scores (project_id, score, views, clones, compiles, computed_at)
+        SELECT
+            p.id,
+            SUM(e.weight * e.count * POWER(0.5, EXTRACT(EPOCH FROM ($1 - e.at)) / 3600.0 / $3)),
+            COALESCE(SUM(e.count) FILTER (WHERE e.kind = 'view'), 0)::BIGINT,
+            COALESCE(SUM(e.count) FILTER (WHERE e.kind = 'clone'), 0)::BIGINT,
+            COALESCE(SUM(e.count) FILTER (WHERE e.kind = 'compile'), 0)::BIGINT,
+            $1
+        FROM (
+            SELECT project_id, 'view' AS kind, views AS count, $4::FLOAT8 AS weight,
+                   LEAST((day::TIMESTAMP + INTERVAL '12 hours') AT TIME ZONE 'UTC', $1) AS at
+            FROM project_daily_views
+            WHERE day >= ($2 AT TIME ZONE 'UTC')::DATE
+            UNION ALL
+            SELECT cloned_from, 'clone', 1, $5, created_at
+            FROM projects
+            WHERE cloned_from IS NOT NULL AND created_at >= $2
+            UNION ALL
+            SELECT project_id, 'compile', 1, $6, created_at
+            FROM compilation_jobs
+            WHERE created_at >= $2
+        ) e
+        JOIN projects p ON p.id = e.project_id
+        WHERE p.is_public = true AND p.purging_at IS NULL
+        GROUP BY p.id
+        "#
+    )
+    .bind(now)
+    .bind(since)
+    .bind(TRENDING_HALF_LIFE_HOURS)
+    .bind(VIEW_WEIGHT)
+    .bind(CLONE_WEIGHT)
+    .bind(COMPILE_WEIGHT)
+    .execute(&mut *tx)
+    .await
+    .map_err(AppError::Database)?
+    .rows_affected();
+
+    tx.commit().await.map_err(AppError::Database)?;
+    Ok(scored)
+}
+
+/// Views of public projects not written yet
+#[derive(Debug, Default)]
+pub struct ProjectViews {
+    viewers: Mutex<HashSet<(Uuid, Uuid)>>,
+}
+
+impl ProjectViews {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Count a view of `project_id`; again by the same viewer only after the
+    /// next flush
+    pub fn record(&self, project_id: Uuid, viewer_id: Uuid) {
+        self.viewers.lock().unwrap_or_else(|e| e.into_inner()).insert((project_id, viewer_id));
+    }
+
+    /// Views per project since the last call
+    pub fn take(&self) -> HashMap<Uuid, i64> {
+        let viewers = std::mem::take(&mut *self.viewers.lock().unwrap_or_else(|e| e.into_inner()));
+        let mut views = HashMap::new();
+        for (project_id, _) in viewers {
+            *views.entry(project_id).or_insert(0) += 1;
+        }
+        views
+    }
+
+    /// Add the buffered views to the counters of `day`
+    pub async fn flush(&self, db: &sqlx::PgPool, day: NaiveDate) -> Result<usize, AppError> {
+        let (project_ids, views): (Vec<Uuid>, Vec<i64>) = self.take().into_iter().unzip();
+        if project_ids.is_empty() {
+            return Ok(0);
+        }
+
+        // Projects deleted since their view are skipped
+        sqlx::query(
+            r#"
+            INSERT INTO project_daily_views (project_id, day, views)
+            SELECT v.project_id, $3, v.views
+            FROM UNNEST($1::UUID[], $2::BIGINT[]) AS v(project_id, views)
+            JOIN projects p ON p.id = v.project_id
+            ON CONFLICT (project_id, day) DO UPDATE SET views = project_daily_views.views + EXCLUDED.views
+            "#
+        )
+        .bind(&project_ids)
+        .bind(&views)
+        .bind(day)
+        .execute(db)
+        .await
+        .map_err(AppError::Database)?;
+
+        Ok(project_ids.len())
+    }
+}
+
+/// A collection as administrators manage it
+#[derive(Debug, Clone, Serialize, FromRow, ToSchema)]
+pub struct Collection {
+    pub id: Uuid,
+    pub name: String,
+    pub description: Option<String>,
+    /// Collections are listed by position, lowest first
+    pub position: i32,
+    pub created_by: Option<Uuid>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    /// Projects in order, including those no longer public
+    pub project_ids: Vec<Uuid>,
+}
+
+/// Request to create or replace a collection
+#[derive(Debug, Clone, Deserialize, ToSchema)]
+pub struct CollectionRequest {
+    pub name: String,
+    pub description: Option<String>,
+    #[serde(default)]
+    pub position: i32,
+    /// Public projects, in the order they are shown
+    pub project_ids: Vec<Uuid>,
+}
+
+/// A collection as listed for discovery, with the projects still public
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct PublicCollection {
+    pub id: Uuid,
+    pub name: String,
+    pub description: Option<String>,
+    pub projects: Vec<DiscoveredProject>,
+}
+
+#[derive(Debug, FromRow)]
+struct CollectionProject {
+    collection_id: Uuid,
+    #[sqlx(flatten)]
+    project: DiscoveredProject,
+}
+
+const COLLECTION_COLUMNS: &str = r#"
+    c.*,
+    ARRAY(SELECT project_id FROM collection_projects WHERE collection_id = c.id ORDER BY position) AS project_ids
+"#;
+
+impl CollectionRequest {
+    /// Check the name and that the projects are distinct, public and few enough
+    async fn validate(&self, db: &mut sqlx::PgConnection) -> Result<(), AppError> {
+        let name = self.name.trim();
+        if name.is_empty() || name.chars().count() > 255 {
+            return Err(AppError::Validation("Collection name must be 1 to 255 characters".to_string()));
+        }
+        if self.project_ids.len() > MAX_COLLECTION_PROJECTS {
+            return Err(AppError::Validation(format!(
+                "A collection holds at most {} projects",
+                MAX_COLLECTION_PROJECTS
+            )));
+        }
+        let distinct: HashSet<_> = self.project_ids.iter().collect();
+        if distinct.len() != self.project_ids.len() {
+            return Err(AppError::Validation("A project is listed twice".to_string()));
+        }
+
+        let public: Vec<Uuid> = sqlx::query_scalar(
+            "SELECT id FROM projects WHERE id = ANY($1) AND is_public = true AND purging_at IS NULL"
+        )
+        .bind(&self.project_ids)
+        .fetch_all(&mut *db)
+        .await
+        .map_err(AppError::Database)?;
+        if let Some(missing) = self.project_ids.iter().find(|id| !public.contains(id)) {
+            return Err(AppError::Validation(format!("Project {} is not public", missing)));
+        }
+
+        Ok(())
+    }
+}
+
+impl Collection {
+    /// All collections in listing order
+    pub async fn list(db: &sqlx::PgPool) -> Result<Vec<Self>, AppError> {
+        sqlx::query_as::<_, Collection>(&format!(
+            "SELECT {COLLECTION_COLUMNS} FROM collections c ORDER BY c.position, c.created_at, c.id"
+        ))
+        .fetch_all(db)
+        .await
+        .map_err(AppError::Database)
+    }
+
+    async fn find(db: &mut sqlx::PgConnection, collection_id: Uuid) -> Result<Self, AppError> {
+        sqlx::query_as::<_, Collection>(&format!("SELECT {COLLECTION_COLUMNS} FROM collections c WHERE c.id = $1"))
+            .bind(collection_id)
+            .fetch_optional(db)
+            .await
+            .map_err(AppError::Database)?
+            .ok_or_else(|| AppError::NotFound {
+                entity: "Collection".to_string(),
+                id: collection_id.to_string(),
+            })
+    }
+
+    async fn set_projects(db: &mut sqlx::PgConnection, collection_id: Uuid, project_ids: &[Uuid]) -> Result<(), AppError> {
+        sqlx::query("DELETE FROM collection_projects WHERE collection_id = $1")
+            .bind(collection_id)
+            .execute(&mut *db)
+            .await
+            .map_err(AppError::Database)?;
+        sqlx::query(
+            r#"
+            INSERT INTO collection_projects (collection_id, project_id, position)
+            SELECT $1, project_id, position::INTEGER
+            FROM UNNEST($2::UUID[]) WITH ORDINALITY AS p(project_id, position)
+            "#
+        )
+        .bind(collection_id)
+        .bind(project_ids)
+        .execute(&mut *db)
+        .await
+        .map_err(AppError::Database)?;
+
+        Ok(())
+    }
+
+    pub async fn create(db: &sqlx::PgPool, request: &CollectionRequest, created_by: Uuid) -> Result<Self, AppError> {
+        let mut tx = db.begin().await.map_err(AppError::Database)?;
+        request.validate(&mut tx).await?;
+
+        let collection_id: Uuid = sqlx::query_scalar(
+            "INSERT INTO collections (name, description, position, created_by) VALUES ($1, $2, $3, $4) RETURNING id"
+        )
+        .bind(request.name.trim())
+        .bind(&request.description)
+        .bind(request.position)
+        .bind(created_by)
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(AppError::Database)?;
+        Self::set_projects(&mut tx, collection_id, &request.project_ids).await?;
+
+        let collection = Self::find(&mut tx, collection_id).await?;
+        tx.commit().await.map_err(AppError::Database)?;
+        Ok(collection)
+    }
+
+    /// Replace a collection's details and projects
+    pub async fn update(db: &sqlx::PgPool, collection_id: Uuid, request: &CollectionRequest) -> Result<Self, AppError> {
+        let mut tx = db.begin().await.map_err(AppError::Database)?;
+        request.validate(&mut tx).await?;
+
+        let updated = sqlx::query(
+            "UPDATE collections SET name = $2, description = $3, position = $4, updated_at = NOW() WHERE id = $1"
+        )
+        .bind(collection_id)
+        .bind(request.name.trim())
+        .bind(&request.description)
+        .bind(request.position)
+        .execute(&mut *tx)
+        .await
+        .map_err(AppError::Database)?;
+        if updated.rows_affected() == 0 {
+            return Err(AppError::NotFound {
+                entity: "Collection".to_string(),
+                id: collection_id.to_string(),
+            });
+        }
+        Self::set_projects(&mut tx, collection_id, &request.project_ids).await?;
+
+        let collection = Self::find(&mut tx, collection_id).await?;
+        tx.commit().await.map_err(AppError::Database)?;
+        Ok(collection)
+    }
+
+    pub async fn delete(db: &sqlx::PgPool, collection_id: Uuid) -> Result<(), AppError> {
+        let deleted = sqlx::query("DELETE FROM collections WHERE id = $1")
+            .bind(collection_id)
+            .execute(db)
+            .await
+            .map_err(AppError::Database)?;
+        if deleted.rows_affected() == 0 {
+            return Err(AppError::NotFound {
+                entity: "Collection".to_string(),
+                id: collection_id.to_string(),
+            });
+        }
+
+        Ok(())
+    }
+}
+
+impl PublicCollection {
+    /// A page of collections in listing order, each with its public projects
+    pub async fn list(db: &sqlx::PgPool, params: &PaginationParams) -> Result<(Vec<Self>, i64), AppError> {
+        let collections = sqlx::query_as::<_, (Uuid, String, Option<String>)>(
+            "SELECT id, name, description FROM collections ORDER BY position, created_at, id LIMIT $1 OFFSET $2"
+        )
+        .bind(params.limit() as i64)
+        .bind(params.offset() as i64)
+        .fetch_all(db)
+        .await
+        .map_err(AppError::Database)?;
+
+        let total = sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM collections")
+            .fetch_one(db)
+            .await
+            .map_err(AppError::Database)?;
+
+        let collection_ids: Vec<Uuid> = collections.iter().map(|(id, _, _)| *id).collect();
+        let projects = sqlx::query_as::<_, CollectionProject>(&format!(
+            r#"
+            SELECT cp.collection_id, {PROJECT_COLUMNS}
+            FROM collection_projects cp
+            JOIN {LISTED_PROJECTS} ON p.id = cp.project_id
+            WHERE cp.collection_id = ANY($1) AND {LISTED}
+            ORDER BY cp.collection_id, cp.position
+            "#
+        ))
+        .bind(&collection_ids)
+        .fetch_all(db)
+        .await
+        .map_err(AppError::Database)?;
+
+        let mut by_collection: HashMap<Uuid, Vec<DiscoveredProject>> = HashMap::new();
+        for entry in projects {
+            by_collection.entry(entry.collection_id).or_default().push(entry.project);
+        }
+        let collections = collections
+            .into_iter()
+            .map(|(id, name, description)| PublicCollection {
+                id,
+                name,
+                description,
+                projects: by_collection.remove(&id).unwrap_or_default(),
+            })
+            .collect();
+
+        Ok((collections, total))
+    }
+}
+
+/// Writes buffered project views
+pub struct ViewFlushTask;
+
+impl crate::tasks::PeriodicTask for ViewFlushTask {
+    fn name(&self) -> &'static str {
+        "project_view_flush"
+    }
+
+    fn interval(&self) -> Duration {
+        VIEW_FLUSH_INTERVAL
+    }
+
+    fn run<'a>(&'a self, state: &'a AppState) -> futures::future::BoxFuture<'a, Result<(), AppError>> {
+        Box::pin(async move {
+            state.project_views.flush(&state.db_pool, Utc::now().date_naive()).await?;
+            Ok(())
+        })
+    }
+}
+
+/// Recomputes trending scores
+pub struct TrendingTask;
+
+impl crate::tasks::PeriodicTask for TrendingTask {
+    fn name(&self) -> &'static str {
+        "trending_projects"
+    }
+
+    fn interval(&self) -> Duration {
+        TRENDING_INTERVAL
+    }
+
+    fn run<'a>(&'a self, state: &'a AppState) -> futures::future::BoxFuture<'a, Result<(), AppError>> {
+        Box::pin(async move {
+            let scored = compute_trending(&state.db_pool, Utc::now()).await?;
+            tracing::debug!("Computed trending scores of {} projects", scored);
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{create_test_job, create_test_project, create_test_user, TestDb};
+
+    /// Trending scores are computed for every project at once
+    static TRENDING: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+    #[test]
+    fn test_views_count_each_viewer_once_per_flush() {
+        let views = ProjectViews::new();
+        let (project, other) = (Uuid::new_v4(), Uuid::new_v4());
+        let (alice, bob) = (Uuid::new_v4(), Uuid::new_v4());
+        views.record(project, alice);
+        views.record(project, alice);
+        views.record(project, bob);
+        views.record(other, alice);
+
+        assert_eq!(views.take(), HashMap::from([(project, 2), (other, 1)]));
+        assert!(views.take().is_empty());
+        views.record(project, alice);
+        assert_eq!(views.take(), HashMap::from([(project, 1)]));
+    }
+
+    #[tokio::test]
+    async fn test_trending_ranks_recent_interest_of_public_projects() {
+        let Some(db) = TestDb::start().await else { return };
+        let _turn = TRENDING.lock().await;
+        let owner = create_test_user(&db.pool).await;
+        let viewer = create_test_user(&db.pool).await;
+        let viewed = create_test_project(&db.pool, &owner, true).await;
+        let compiled = create_test_project(&db.pool, &owner, true).await;
+        let private = create_test_project(&db.pool, &owner, false).await;
+
+        let now = Utc::now();
+        let views = ProjectViews::new();
+        views.record(viewed.id, viewer.id);
+        views.record(viewed.id, owner.id);
+        views.record(private.id, viewer.id);
+        views.flush(&db.pool, now.date_naive()).await.unwrap();
+        // Views long ago have faded
+        sqlx::query("INSERT INTO project_daily_views (project_id, day, views) VALUES ($1, $2, 1000)")
+            .bind(compiled.id)
+            .bind((now - chrono::Duration::days(TRENDING_WINDOW_DAYS + 1)).date_naive())
+            .execute(&db.pool)
+            .await
+            .unwrap();
+        create_test_job(&db.pool, &compiled, &owner).await;
+        create_test_job(&db.pool, &compiled, &owner).await;
+        create_test_job(&db.pool, &private, &owner).await;
+
+        let ids = vec![viewed.id, compiled.id, private.id];
+        let scores = |ids: Vec<Uuid>| {
+            let pool = db.pool.clone();
+            async move {
+                sqlx::query_as::<_, (Uuid, f64, i64, i64)>(
+                    "SELECT project_id, score, views, compiles FROM project_trending_scores WHERE project_id = ANY($1) ORDER BY score DESC"
+                )
+                .bind(ids)
+                .fetch_all(&pool)
+                .await
+                .unwrap()
+            }
+        };
+        compute_trending(&db.pool, now).await.unwrap();
+        let ranked = scores(ids.clone()).await;
+        assert_eq!(
+            ranked.iter().map(|&(id, _, views, compiles)| (id, views, compiles)).collect::<Vec<_>>(),
+            vec![(compiled.id, 0, 2), (viewed.id, 2, 0)]
+        );
+
+        let params = PaginationParams { limit: Some(100), ..PaginationParams::default() };
+        let (trending, _) = TrendingProject::list(&db.pool, &params).await.unwrap();
+        assert!(trending.windows(2).all(|pair| pair[0].score >= pair[1].score));
+        assert!(trending.iter().all(|t| t.project.id != private.id));
+
+        // Scores decay: the same events a half-life later count half
+        let later = now + chrono::Duration::hours(TRENDING_HALF_LIFE_HOURS as i64);
+        compute_trending(&db.pool, later).await.unwrap();
+        let decayed = scores(vec![compiled.id]).await;
+        assert!((decayed[0].1 - ranked[0].1 / 2.0).abs() < 1e-6);
+    }
+
+    #[tokio::test]
+    async fn test_collections_list_only_public_projects_in_order() {
+        let Some(db) = TestDb::start().await else { return };
+        let admin = create_test_user(&db.pool).await;
+        let owner = create_test_user(&db.pool).await;
+        let first = create_test_project(&db.pool, &owner, true).await;
+        let second = create_test_project(&db.pool, &owner, true).await;
+        let private = create_test_project(&db.pool, &owner, false).await;
+
+        let request = |project_ids: Vec<Uuid>| CollectionRequest {
+            name: "Theses".to_string(),
+            description: None,
+            position: -1_000_000,
+            project_ids,
+        };
+        assert!(matches!(
+            Collection::create(&db.pool, &request(vec![first.id, private.id]), admin.id).await,
+            Err(AppError::Validation(_))
+        ));
+        assert!(matches!(
+            Collection::create(&db.pool, &request(vec![first.id, first.id]), admin.id).await,
+            Err(AppError::Validation(_))
+        ));
+        let collection = Collection::create(&db.pool, &request(vec![second.id, first.id]), admin.id).await.unwrap();
+        assert_eq!(collection.project_ids, vec![second.id, first.id]);
+
+        // A project made private is hidden, but stays in the collection
+        sqlx::query("UPDATE projects SET is_public = false WHERE id = $1")
+            .bind(second.id)
+            .execute(&db.pool)
+            .await
+            .unwrap();
+        let (collections, _) = PublicCollection::list(&db.pool, &PaginationParams::default()).await.unwrap();
+        let listed = collections.iter().find(|c| c.id == collection.id).unwrap();
+        assert_eq!(listed.projects.iter().map(|p| p.id).collect::<Vec<_>>(), vec![first.id]);
+
+        let updated = Collection::update(&db.pool, collection.id, &request(vec![first.id])).await.unwrap();
+        assert_eq!(updated.project_ids, vec![first.id]);
+        Collection::delete(&db.pool, collection.id).await.unwrap();
+        assert!(matches!(Collection::delete(&db.pool, collection.id).await, Err(AppError::NotFound { .. })));
+    }
+}
@@ -0,0 +1,575 @@
+//! Changing a user's email address
+//!
+//! A new address is not trusted until it is proven: requesting a change only
+//! sends a token to the new address, and the account keeps signing in and
+//! receiving mail at the old one until that token is confirmed. Confirming
+//! checks again that nobody took the address in the meantime and switches it
+//! in one transaction. The old address is then told about the change with an
+//! undo link; undoing restores it, signs the account out everywhere and locks
+//! it until the password is reset, so someone who briefly had access to the
+//! account or the new inbox cannot keep it. While the change can be undone
+//! the old address stays reserved. Every step is written to the audit log.
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::FromRow;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use super::audit::AuditEvent;
+use super::auth::PasswordUtils;
+use super::login_protection::LoginFailure;
+use super::password_reset::PasswordResetRequest;
+use super::token_blacklist::BlacklistedToken;
+use super::user::User;
+use crate::error::AppError;
+
+/// Hours the new address has to confirm a change
+pub const EMAIL_CHANGE_EXPIRATION_HOURS: i64 = 24;
+
+/// Hours the old address can undo a confirmed change
+pub const EMAIL_CHANGE_UNDO_HOURS: i64 = 48;
+
+/// A requested change of a user's email address
+#[derive(Debug, Clone, FromRow)]
+pub struct EmailChange {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub old_email: String,
+    pub new_email: String,
+    pub token: String,
+    pub undo_token: Option<String>,
+    /// Sign out every session when the change is confirmed
+    pub revoke_sessions: bool,
+    /// `pending`, `confirmed`, `cancelled`, `rejected` or `reverted`
+    pub status: String,
+    pub expires_at: DateTime<Utc>,
+    pub undo_expires_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+    pub confirmed_at: Option<DateTime<Utc>>,
+    pub cancelled_at: Option<DateTime<Utc>>,
+    pub reverted_at: Option<DateTime<Utc>>,
+}
+
+/// An email change waiting for confirmation
+#[derive(Debug, Clone, PartialEq, Serialize, FromRow, ToSchema)]
+pub struct PendingEmailChange {
+    pub new_email: String,
+    pub requested_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
+
+fn taken() -> AppError {
+    AppError::Conflict("That email address is already in use".to_string())
+}
+
+fn is_unique_violation(e: &sqlx::Error) -> bool {
+    e.as_database_error().is_some_and(|e| e.is_unique_violation())
+}
+
+impl EmailChange {
+    /// Whether `email` belongs to an account other than `user_id`, or is the
+    /// old address of a change that can still be undone
+    pub async fn is_taken(
+        db: impl sqlx::PgExecutor<'_>,
+        email: &str,
+        user_id: Option<Uuid>,
+    ) -> Result<bool, AppError> {
+        sqlx::query_scalar::<_, bool>(
+            r#"
+            SELECT EXISTS (SELECT 1 FROM users WHERE email = $1 AND ($2::uuid IS NULL OR id <> $2))
+                OR EXISTS (
+                    SELECT 1 FROM email_changes
+                    WHERE old_email = $1 AND status = 'confirmed' AND undo_expires_at > NOW()
+                      AND ($2::uuid IS NULL OR user_id <> $2)
+                )
+            "#
+        )
+        .bind(email)
+        .bind(user_id)
+        .fetch_one(db)
+        .await
+        .map_err(AppError::Database)
+    }
+
+    /// Start changing `user`'s address to `new_email`, replacing any pending change
+    pub async fn request(
+        db: &sqlx::PgPool,
+        user: &User,
+        new_email: &str,
+        revoke_sessions: bool,
+    ) -> Result<Self, AppError> {
+        let new_email = new_email.trim();
+        if !new_email.contains('@') {
+            return Err(AppError::Validation("Invalid email address".to_string()));
+        }
+        if new_email == user.email {
+            return Err(AppError::Validation("That is already your email address".to_string()));
+        }
+
+        let mut tx = db.begin().await.map_err(AppError::Database)?;
+        if Self::is_taken(&mut *tx, new_email, Some(user.id)).await? {
+            return Err(taken());
+        }
+
+        let superseded = sqlx::query_scalar::<_, Uuid>(
+            r#"
+            UPDATE email_changes SET status = 'cancelled', cancelled_at = NOW()
+            WHERE user_id = $1 AND status = 'pending'
+            RETURNING id
+            "#
+        )
+        .bind(user.id)
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(AppError::Database)?;
+
+        let change = sqlx::query_as::<_, EmailChange>(
+            r#"
+            INSERT INTO email_changes (user_id, old_email, new_email, token, revoke_sessions, expires_at)
+            VALUES ($1, $2, $3, $4, $5, NOW() + INTERVAL '1 hour' * $6)
+            RETURNING *
+            "#
+        )
+        .bind(user.id)
+        .bind(&user.email)
+        .bind(new_email)
+        .bind(PasswordUtils::generate_verification_token())
+        .bind(revoke_sessions)
+        .bind(EMAIL_CHANGE_EXPIRATION_HOURS)
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(AppError::Database)?;
+
+        AuditEvent::record(
+            &mut *tx,
+            Some(user.id),
+            "email_change_requested",
+            "email_change",
+            Some(change.id),
+            None,
+            serde_json::json!({
+                "old_email": change.old_email,
+                "new_email": change.new_email,
+                "revoke_sessions": revoke_sessions,
+                "superseded": superseded,
+            }),
+        )
+        .await?;
+        tx.commit().await.map_err(AppError::Database)?;
+
+        Ok(change)
+    }
+
+    /// The user's change waiting for confirmation, unless it expired
+    pub async fn pending_for_user(db: &sqlx::PgPool, user_id: Uuid) -> Result<Option<PendingEmailChange>, AppError> {
+        sqlx::query_as::<_, PendingEmailChange>(
+            r#"
+            SELECT new_email, created_at AS requested_at, expires_at
+            FROM email_changes
+            WHERE user_id = $1 AND status = 'pending' AND expires_at > NOW()
+            "#
+        )
+        .bind(user_id)
+        .fetch_optional(db)
+        .await
+        .map_err(AppError::Database)
+    }
+
+    /// Withdraw the user's pending change
+    pub async fn cancel(db: &sqlx::PgPool, user_id: Uuid) -> Result<(), AppError> {
+        let mut tx = db.begin().await.map_err(AppError::Database)?;
+        let change = sqlx::query_as::<_, EmailChange>(
+            r#"
+            UPDATE email_changes SET status = 'cancelled', cancelled_at = NOW()
+            WHERE user_id = $1 AND status = 'pending' AND expires_at > NOW()
+            RETURNING *
+            "#
+        )
+        .bind(user_id)
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(AppError::Database)?
+        .ok_or_else(|| AppError::NotFound {
+            entity: "Pending email change".to_string(),
+            id: user_id.to_string(),
+        })?;
+
+        AuditEvent::record(
+            &mut *tx,
+            Some(user_id),
+            "email_change_cancelled",
+            "email_change",
+            Some(change.id),
+            None,
+            serde_json::json!({ "new_email": change.new_email }),
+        )
+        .await?;
+        tx.commit().await.map_err(AppError::Database)?;
+
+        Ok(())
+    }
+
+    /// Switch the user to the new address of the change `token` was sent for
+    ///
+    /// Returns the confirmed change, with its undo token, and the updated user.
+    pub async fn confirm(db: &sqlx::PgPool, user_id: Uuid, token: &str) -> Result<(Self, User), AppError> {
+        let mut tx = db.begin().await.map_err(AppError::Database)?;
+        let change = sqlx::query_as::<_, EmailChange>(
+            r#"
+            SELECT * FROM email_changes
+            WHERE token = $1 AND user_id = $2 AND status = 'pending' AND expires_at > NOW()
+            FOR UPDATE
+            "#
+        )
+        .bind(token)
+        .bind(user_id)
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(AppError::Database)?
+        .ok_or_else(|| AppError::BadRequest("Invalid or expired email change token".to_string()))?;
+
+        // Someone may have registered the address since the change was requested
+        if Self::is_taken(&mut *tx, &change.new_email, Some(user_id)).await? {
+            tx.rollback().await.map_err(AppError::Database)?;
+            return Err(Self::reject(db, &change).await);
+        }
+
+        let switched = sqlx::query_as::<_, User>(
+            r#"
+            UPDATE users SET email = $2, email_verified = true, updated_at = NOW()
+            WHERE id = $1 AND email = $3
+            RETURNING *
+            "#
+        )
+        .bind(user_id)
+        .bind(&change.new_email)
+        .bind(&change.old_email)
+        .fetch_optional(&mut *tx)
+        .await;
+        let user = match switched {
+            Ok(Some(user)) => user,
+            // The address was changed some other way since
+            Ok(None) => return Err(AppError::BadRequest("Invalid or expired email change token".to_string())),
+            Err(e) if is_unique_violation(&e) => {
+                tx.rollback().await.map_err(AppError::Database)?;
+                return Err(Self::reject(db, &change).await);
+            }
+            Err(e) => return Err(AppError::Database(e)),
+        };
+
+        let change = sqlx::query_as::<_, EmailChange>(
+            r#"
+            UPDATE email_changes
+            SET status = 'confirmed', confirmed_at = NOW(), undo_token = $2,
+                undo_expires_at = NOW() + INTERVAL '1 hour' * $3
+            WHERE id = $1
+            RETURNING *
+            "#
+        )
+        .bind(change.id)
+        .bind(PasswordUtils::generate_verification_token())
+        .bind(EMAIL_CHANGE_UNDO_HOURS)
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(AppError::Database)?;
+
+        if change.revoke_sessions {
+            BlacklistedToken::blacklist_all_for_user(&mut *tx, user_id, "email_changed".to_string()).await?;
+        }
+        AuditEvent::record(
+            &mut *tx,
+            Some(user_id),
+            "email_change_confirmed",
+            "email_change",
+            Some(change.id),
+            None,
+            serde_json::json!({
+                "old_email": change.old_email,
+                "new_email": change.new_email,
+                "sessions_revoked": change.revoke_sessions,
+            }),
+        )
+        .await?;
+        tx.commit().await.map_err(AppError::Database)?;
+
+        // Reset links sent to the old address must not work for the account anymore
+        PasswordResetRequest::invalidate_for_email(db, &change.old_email).await?;
+
+        Ok((change, user))
+    }
+
+    /// Give up on a change whose new address was taken, returning the error to report
+    async fn reject(db: &sqlx::PgPool, change: &EmailChange) -> AppError {
+        let rejected = async {
+            let mut tx = db.begin().await.map_err(AppError::Database)?;
+            sqlx::query("UPDATE email_changes SET status = 'rejected' WHERE id = $1 AND status = 'pending'")
+                .bind(change.id)
+                .execute(&mut *tx)
+                .await
+                .map_err(AppError::Database)?;
+            AuditEvent::record(
+                &mut *tx,
+                Some(change.user_id),
+                "email_change_rejected",
+                "email_change",
+                Some(change.id),
+                None,
+                serde_json::json!({ "new_email": change.new_email, "reason": "email_taken" }),
+            )
+            .await?;
+            tx.commit().await.map_err(AppError::Database)
+        };
+
+        match rejected.await {
+            Ok(()) => taken(),
+            Err(e) => e,
+        }
+    }
+
+    /// Undo the confirmed change `undo_token` was sent for: restore the old
+    /// address, sign out every session and lock the account until its
+    /// password is reset
+    ///
+    /// Returns the change and the restored user.
+    pub async fn undo(db: &sqlx::PgPool, undo_token: &str) -> Result<(Self, User), AppError> {
+        let invalid = || AppError::BadRequest("Invalid or expired undo token".to_string());
+
+        let mut tx = db.begin().await.map_err(AppError::Database)?;
+        let change = sqlx::query_as::<_, EmailChange>(
+            r#"
+            UPDATE email_changes SET status = 'reverted', reverted_at = NOW()
+            WHERE undo_token = $1 AND status = 'confirmed' AND undo_expires_at > NOW()
+            RETURNING *
+            "#
+        )
+        .bind(undo_token)
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(AppError::Database)?
+        .ok_or_else(invalid)?;
+
+        let user = sqlx::query_as::<_, User>(
+            r#"
+            UPDATE users SET email = $2, email_verified = true, updated_at = NOW()
+            WHERE id = $1
+            RETURNING *
+            "#
+        )
+        .bind(change.user_id)
+        .bind(&change.old_email)
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(|e| if is_unique_violation(&e) { taken() } else { AppError::Database(e) })?
+        .ok_or_else(invalid)?;
+
+        sqlx::query(
+            r#"
+            UPDATE email_changes SET status = 'cancelled', cancelled_at = NOW()
+            WHERE user_id = $1 AND status = 'pending'
+            "#
+        )
+        .bind(change.user_id)
+        .execute(&mut *tx)
+        .await
+        .map_err(AppError::Database)?;
+        BlacklistedToken::blacklist_all_for_user(&mut *tx, change.user_id, "email_change_undone".to_string()).await?;
+        LoginFailure::lock(&mut *tx, change.user_id).await?;
+        AuditEvent::record(
+            &mut *tx,
+            None,
+            "email_change_reverted",
+            "email_change",
+            Some(change.id),
+            None,
+            serde_json::json!({
+                "user_id": change.user_id,
+                "restored_email": change.old_email,
+                "undone_email": change.new_email,
+                "account_locked": true,
+            }),
+        )
+        .await?;
+        tx.commit().await.map_err(AppError::Database)?;
+
+        Ok((change, user))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::user::CreateUser;
+    use crate::models::validation::DisplayName;
+    use crate::testing::{create_test_user, TestDb};
+
+    fn fresh_email() -> String {
+        format!("new_{}@example.com", &Uuid::new_v4().simple().to_string()[..12])
+    }
+
+    async fn register(db: &sqlx::PgPool, email: &str) -> Result<User, AppError> {
+        User::create(
+            db,
+            CreateUser {
+                username: format!("user_{}", &Uuid::new_v4().simple().to_string()[..12]),
+                email: email.to_string(),
+                password: "password123".to_string(),
+                display_name: DisplayName::new("Test User").unwrap(),
+                avatar_url: None,
+            },
+        )
+        .await
+    }
+
+    async fn audit_actions(db: &sqlx::PgPool, change_id: Uuid) -> Vec<String> {
+        sqlx::query_scalar("SELECT action FROM audit_log WHERE entity_id = $1 ORDER BY created_at, action")
+            .bind(change_id)
+            .fetch_all(db)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_old_address_stays_active_until_confirmed() {
+        let Some(db) = TestDb::start().await else { return };
+        let user = create_test_user(&db.pool).await;
+        let new_email = fresh_email();
+
+        let change = EmailChange::request(&db.pool, &user, &new_email, false).await.unwrap();
+        assert_eq!(User::find_by_id(&db.pool, user.id).await.unwrap().unwrap().email, user.email);
+        let pending = EmailChange::pending_for_user(&db.pool, user.id).await.unwrap().unwrap();
+        assert_eq!(pending.new_email, new_email);
+
+        // Only the account that asked can confirm
+        let other = create_test_user(&db.pool).await;
+        assert!(matches!(
+            EmailChange::confirm(&db.pool, other.id, &change.token).await,
+            Err(AppError::BadRequest(_))
+        ));
+
+        let (confirmed, switched) = EmailChange::confirm(&db.pool, user.id, &change.token).await.unwrap();
+        assert_eq!(switched.email, new_email);
+        assert!(switched.email_verified);
+        assert!(confirmed.undo_token.is_some());
+        assert!(EmailChange::pending_for_user(&db.pool, user.id).await.unwrap().is_none());
+        assert!(matches!(
+            EmailChange::confirm(&db.pool, user.id, &change.token).await,
+            Err(AppError::BadRequest(_))
+        ));
+
+        // The old address is reserved while the change can be undone
+        assert!(EmailChange::is_taken(&db.pool, &user.email, Some(other.id)).await.unwrap());
+        assert_eq!(audit_actions(&db.pool, change.id).await, vec!["email_change_confirmed", "email_change_requested"]);
+    }
+
+    #[tokio::test]
+    async fn test_address_taken_before_confirmation_is_rejected() {
+        let Some(db) = TestDb::start().await else { return };
+        let user = create_test_user(&db.pool).await;
+        let new_email = fresh_email();
+        let change = EmailChange::request(&db.pool, &user, &new_email, false).await.unwrap();
+
+        // Someone registers the address between the request and its confirmation
+        register(&db.pool, &new_email).await.unwrap();
+        assert!(matches!(
+            EmailChange::confirm(&db.pool, user.id, &change.token).await,
+            Err(AppError::Conflict(_))
+        ));
+
+        let unchanged = User::find_by_id(&db.pool, user.id).await.unwrap().unwrap();
+        assert_eq!(unchanged.email, user.email);
+        assert!(EmailChange::pending_for_user(&db.pool, user.id).await.unwrap().is_none());
+        assert!(audit_actions(&db.pool, change.id).await.contains(&"email_change_rejected".to_string()));
+
+        // Nor can it be requested again
+        assert!(matches!(
+            EmailChange::request(&db.pool, &user, &new_email, false).await,
+            Err(AppError::Conflict(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_address_taken_while_confirming_is_rejected() {
+        let Some(db) = TestDb::start().await else { return };
+        let user = create_test_user(&db.pool).await;
+        let new_email = fresh_email();
+        let change = EmailChange::request(&db.pool, &user, &new_email, false).await.unwrap();
+
+        // The registration commits after the confirmation checked the address
+        // but before it switches, so the switch runs into the unique constraint
+        let mut registration = db.pool.begin().await.unwrap();
+        let hash = PasswordUtils::hash_password("password123").unwrap();
+        sqlx::query("INSERT INTO users (username, email, password_hash) VALUES ($1, $2, $3)")
+            .bind(format!("racer_{}", &Uuid::new_v4().simple().to_string()[..12]))
+            .bind(&new_email)
+            .bind(hash)
+            .execute(&mut *registration)
+            .await
+            .unwrap();
+
+        let confirm = tokio::spawn({
+            let pool = db.pool.clone();
+            let token = change.token.clone();
+            async move { EmailChange::confirm(&pool, user.id, &token).await }
+        });
+        tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+        registration.commit().await.unwrap();
+
+        assert!(matches!(confirm.await.unwrap(), Err(AppError::Conflict(_))));
+        let unchanged = User::find_by_id(&db.pool, user.id).await.unwrap().unwrap();
+        assert_eq!(unchanged.email, user.email);
+        assert!(audit_actions(&db.pool, change.id).await.contains(&"email_change_rejected".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_undo_restores_and_locks() {
+        let Some(db) = TestDb::start().await else { return };
+        let user = create_test_user(&db.pool).await;
+        let change = EmailChange::request(&db.pool, &user, &fresh_email(), true).await.unwrap();
+        let (confirmed, _) = EmailChange::confirm(&db.pool, user.id, &change.token).await.unwrap();
+        let issued_before = Utc::now().timestamp() - 5;
+        assert!(BlacklistedToken::revoked_since(&db.pool, user.id, issued_before).await.unwrap());
+
+        let undo_token = confirmed.undo_token.unwrap();
+        let (_, restored) = EmailChange::undo(&db.pool, &undo_token).await.unwrap();
+        assert_eq!(restored.email, user.email);
+        assert!(LoginFailure::find(&db.pool, user.id).await.unwrap().unwrap().is_locked());
+        assert!(matches!(EmailChange::undo(&db.pool, &undo_token).await, Err(AppError::BadRequest(_))));
+
+        // The undone address is free again, the restored one is not reserved
+        assert!(!EmailChange::is_taken(&db.pool, &change.new_email, None).await.unwrap());
+        assert_eq!(
+            audit_actions(&db.pool, change.id).await,
+            vec!["email_change_confirmed", "email_change_requested", "email_change_reverted"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_pending_changes_expire_and_can_be_cancelled() {
+        let Some(db) = TestDb::start().await else { return };
+        let user = create_test_user(&db.pool).await;
+
+        let first = EmailChange::request(&db.pool, &user, &fresh_email(), false).await.unwrap();
+        let second = EmailChange::request(&db.pool, &user, &fresh_email(), false).await.unwrap();
+        assert!(matches!(
+            EmailChange::confirm(&db.pool, user.id, &first.token).await,
+            Err(AppError::BadRequest(_))
+        ));
+        EmailChange::cancel(&db.pool, user.id).await.unwrap();
+        assert!(EmailChange::pending_for_user(&db.pool, user.id).await.unwrap().is_none());
+        assert!(matches!(EmailChange::cancel(&db.pool, user.id).await, Err(AppError::NotFound { .. })));
+        assert!(audit_actions(&db.pool, second.id).await.contains(&"email_change_cancelled".to_string()));
+
+        let third = EmailChange::request(&db.pool, &user, &fresh_email(), false).await.unwrap();
+        sqlx::query("UPDATE email_changes SET expires_at = NOW() - INTERVAL '1 minute' WHERE id = $1")
+            .bind(third.id)
+            .execute(&db.pool)
+            .await
+            .unwrap();
+        assert!(EmailChange::pending_for_user(&db.pool, user.id).await.unwrap().is_none());
+        assert!(matches!(
+            EmailChange::confirm(&db.pool, user.id, &third.token).await,
+            Err(AppError::BadRequest(_))
+        ));
+    }
+}
@@ -167,11 +167,11 @@ impl EmailVerificationService {
         ).await
     }
 
-    /// Confirm an email verification
+    /// Confirm an email verification, returning the now verified user
     pub async fn confirm_verification(
         db: &sqlx::PgPool,
         token: &str,
-    ) -> Result<(), crate::error::AppError> {
+    ) -> Result<crate::models::user::User, crate::error::AppError> {
         use crate::models::user::User;
 
         // Find valid verification request
@@ -198,7 +198,7 @@ impl EmailVerificationService {
         // Mark verification request as verified
         verification_request.mark_as_verified(db).await?;
 
-        Ok(())
+        Ok(user)
     }
 
     /// Resend verification email
@@ -0,0 +1,234 @@
+//! Read-only tokens for embedding a project's PDF in other sites
+//!
+//! `GET /projects/{id}/latest.pdf?token=` serves the primary PDF of the
+//! project's newest successful compilation to anyone holding one of its
+//! embed tokens, so a course page can show it in an iframe under a URL that
+//! never changes. Tokens are shown once and only their SHA-256 is stored.
+//!
+//! Tokens can only be created while the project is public or flagged
+//! embeddable, and stop working once it is neither, once they expire or are
+//! revoked. All of these look exactly like a project that does not exist.
+//! Every request with a working token is counted on the token.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::FromRow;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use super::auth::PasswordUtils;
+use super::Entity;
+use crate::error::AppError;
+
+/// Embed token of a project; the token itself is never stored
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, ToSchema)]
+pub struct EmbedToken {
+    pub id: Uuid,
+    pub project_id: Uuid,
+    pub created_by: Option<Uuid>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub revoked: bool,
+    /// Requests served with this token
+    pub hit_count: i64,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl Entity for EmbedToken {
+    fn id(&self) -> Uuid {
+        self.id
+    }
+
+    fn created_at(&self) -> DateTime<Utc> {
+        self.created_at
+    }
+
+    fn updated_at(&self) -> DateTime<Utc> {
+        self.last_used_at.unwrap_or(self.created_at)
+    }
+}
+
+/// Embed token creation request
+#[derive(Debug, Clone, Default, Deserialize, ToSchema)]
+pub struct CreateEmbedToken {
+    /// The token stops working at this time; unset for a token that only
+    /// ends when revoked
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+/// A new embed token, the only time it is shown
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct CreatedEmbedToken {
+    #[serde(flatten)]
+    pub embed_token: EmbedToken,
+    pub token: String,
+    /// URL of the latest PDF with the token, ready for an iframe
+    pub embed_url: String,
+}
+
+fn token_hash(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+impl EmbedToken {
+    /// Create a token for `project_id`, which must be public or embeddable
+    pub async fn create(
+        db: &sqlx::PgPool,
+        project_id: Uuid,
+        created_by: Uuid,
+        request: CreateEmbedToken,
+    ) -> Result<CreatedEmbedToken, AppError> {
+        if request.expires_at.is_some_and(|expires_at| expires_at <= Utc::now()) {
+            return Err(AppError::Validation("Expiry must be in the future".to_string()));
+        }
+
+        let token = PasswordUtils::generate_embed_token();
+        let embed_token = sqlx::query_as::<_, EmbedToken>(
+            r#"
+            INSERT INTO project_embed_tokens (project_id, token_hash, created_by, expires_at)
+            SELECT id, $2, $3, $4 FROM projects
+            WHERE id = $1 AND (is_public OR is_embeddable) AND purging_at IS NULL
+            RETURNING *
+            "#
+        )
+        .bind(project_id)
+        .bind(token_hash(&token))
+        .bind(created_by)
+        .bind(request.expires_at)
+        .fetch_optional(db)
+        .await
+        .map_err(AppError::Database)?
+        .ok_or_else(|| {
+            AppError::Validation("Only public projects or projects flagged embeddable can be embedded".to_string())
+        })?;
+
+        Ok(CreatedEmbedToken {
+            embed_url: format!("/api/v1/projects/{}/latest.pdf?token={}", project_id, token),
+            embed_token,
+            token,
+        })
+    }
+
+    /// Tokens of a project, newest first
+    pub async fn list(db: &sqlx::PgPool, project_id: Uuid) -> Result<Vec<Self>, AppError> {
+        sqlx::query_as::<_, EmbedToken>(
+            "SELECT * FROM project_embed_tokens WHERE project_id = $1 ORDER BY created_at DESC"
+        )
+        .bind(project_id)
+        .fetch_all(db)
+        .await
+        .map_err(AppError::Database)
+    }
+
+    /// Revoke a token of a project; false when there is no such token
+    pub async fn revoke(db: &sqlx::PgPool, project_id: Uuid, token_id: Uuid) -> Result<bool, AppError> {
+        let result = sqlx::query(
+            "UPDATE project_embed_tokens SET revoked = true WHERE id = $1 AND project_id = $2"
+        )
+        .bind(token_id)
+        .bind(project_id)
+        .execute(db)
+        .await
+        .map_err(AppError::Database)?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// The working token `token` of `project_id`, counting the use
+    ///
+    /// `None` when the token is unknown, revoked or expired, or the project
+    /// can no longer be embedded.
+    pub async fn use_token(db: &sqlx::PgPool, project_id: Uuid, token: &str) -> Result<Option<Self>, AppError> {
+        sqlx::query_as::<_, EmbedToken>(
+            r#"
+            UPDATE project_embed_tokens t
+            SET hit_count = t.hit_count + 1, last_used_at = NOW()
+            FROM projects p
+            WHERE t.token_hash = $2 AND t.project_id = $1 AND p.id = t.project_id
+                AND NOT t.revoked AND (t.expires_at IS NULL OR t.expires_at > NOW())
+                AND (p.is_public OR p.is_embeddable) AND p.purging_at IS NULL
+            RETURNING t.*
+            "#
+        )
+        .bind(project_id)
+        .bind(token_hash(token))
+        .fetch_optional(db)
+        .await
+        .map_err(AppError::Database)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::project::UpdateProject;
+    use crate::testing::{create_test_project, create_test_user, TestDb};
+
+    #[tokio::test]
+    async fn test_tokens_follow_project_visibility() {
+        let Some(db) = TestDb::start().await else { return };
+        let owner = create_test_user(&db.pool).await;
+        let project = create_test_project(&db.pool, &owner, false).await;
+
+        let create = |expires_at| EmbedToken::create(&db.pool, project.id, owner.id, CreateEmbedToken { expires_at });
+        assert!(matches!(create(None).await, Err(AppError::Validation(_))));
+
+        let update = |is_embeddable| -> UpdateProject {
+            serde_json::from_value(serde_json::json!({ "is_embeddable": is_embeddable })).unwrap()
+        };
+        let project = project.update(&db.pool, update(true), owner.id).await.unwrap();
+        let created = create(None).await.unwrap();
+        assert!(created.embed_url.ends_with(&format!("/latest.pdf?token={}", created.token)));
+
+        let used = EmbedToken::use_token(&db.pool, project.id, &created.token).await.unwrap().unwrap();
+        assert_eq!(used.hit_count, 1);
+        assert!(EmbedToken::use_token(&db.pool, project.id, "not-a-token").await.unwrap().is_none());
+
+        // The same token stops working while the project cannot be embedded
+        let project = project.update(&db.pool, update(false), owner.id).await.unwrap();
+        assert!(EmbedToken::use_token(&db.pool, project.id, &created.token).await.unwrap().is_none());
+        project.update(&db.pool, update(true), owner.id).await.unwrap();
+        let used = EmbedToken::use_token(&db.pool, project.id, &created.token).await.unwrap().unwrap();
+        assert_eq!(used.hit_count, 2);
+
+        let other = create_test_project(&db.pool, &owner, true).await;
+        assert!(EmbedToken::use_token(&db.pool, other.id, &created.token).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_revoked_and_expired_tokens_stop_working() {
+        let Some(db) = TestDb::start().await else { return };
+        let owner = create_test_user(&db.pool).await;
+        let project = create_test_project(&db.pool, &owner, true).await;
+
+        let past = Utc::now() - chrono::Duration::minutes(1);
+        let refused = EmbedToken::create(&db.pool, project.id, owner.id, CreateEmbedToken { expires_at: Some(past) }).await;
+        assert!(matches!(refused, Err(AppError::Validation(_))));
+
+        let expiring = EmbedToken::create(
+            &db.pool,
+            project.id,
+            owner.id,
+            CreateEmbedToken { expires_at: Some(Utc::now() + chrono::Duration::hours(1)) },
+        )
+        .await
+        .unwrap();
+        let revoked = EmbedToken::create(&db.pool, project.id, owner.id, CreateEmbedToken::default()).await.unwrap();
+
+        sqlx::query("UPDATE project_embed_tokens SET expires_at = NOW() - INTERVAL '1 second' WHERE id = $1")
+            .bind(expiring.embed_token.id)
+            .execute(&db.pool)
+            .await
+            .unwrap();
+        assert!(EmbedToken::use_token(&db.pool, project.id, &expiring.token).await.unwrap().is_none());
+
+        assert!(EmbedToken::revoke(&db.pool, project.id, revoked.embed_token.id).await.unwrap());
+        assert!(EmbedToken::use_token(&db.pool, project.id, &revoked.token).await.unwrap().is_none());
+        assert!(!EmbedToken::revoke(&db.pool, Uuid::new_v4(), revoked.embed_token.id).await.unwrap());
+
+        let tokens = EmbedToken::list(&db.pool, project.id).await.unwrap();
+        assert_eq!(tokens.len(), 2);
+        assert!(tokens.iter().all(|token| token.hit_count == 0));
+    }
+}
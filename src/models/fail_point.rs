@@ -0,0 +1,43 @@
+//! Failure injection for transaction tests
+//!
+//! Mutations that run several statements call [`check`] between them. A test
+//! can arm a fail point for its thread, making `check` fail there the way a
+//! crash or a lost connection would, and then verify that none of the earlier
+//! statements persisted. Tests run on a current-thread runtime, so the armed
+//! point is seen by every query of the request. Outside tests `check` does
+//! nothing.
+
+use crate::error::AppError;
+
+#[cfg(test)]
+thread_local! {
+    static ARMED: std::cell::Cell<Option<&'static str>> = const { std::cell::Cell::new(None) };
+}
+
+/// Fail when the fail point `name` is armed
+#[cfg_attr(not(test), allow(unused_variables))]
+pub fn check(name: &'static str) -> Result<(), AppError> {
+    #[cfg(test)]
+    if ARMED.with(|armed| armed.get()) == Some(name) {
+        return Err(AppError::Internal(format!("Injected failure at {}", name)));
+    }
+    Ok(())
+}
+
+/// Arm the fail point `name` on this thread until the guard is dropped
+#[cfg(test)]
+pub fn arm(name: &'static str) -> Armed {
+    ARMED.with(|armed| armed.set(Some(name)));
+    Armed
+}
+
+/// Disarms the fail point when dropped
+#[cfg(test)]
+pub struct Armed;
+
+#[cfg(test)]
+impl Drop for Armed {
+    fn drop(&mut self) {
+        ARMED.with(|armed| armed.set(None));
+    }
+}
@@ -4,14 +4,23 @@ use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
 use std::path::PathBuf;
+use utoipa::ToSchema;
 use uuid::Uuid;
 
-use super::{ContentType, Entity, StorageStrategy};
+use super::{ContentType, Entity, SortColumn, SortOrder, Sortable, StorageStrategy};
 use super::user::UserProfile;
 use super::project::ProjectActivity;
+use super::project_freeze::ProjectFreeze;
+use super::project_limits::ProjectLimits;
+use super::blob::{blob_storage_path, remove_blob_content, AcquiredBlob, Blob};
+use super::file_scan::FileScanStatus;
+use super::word_count::stored_word_count;
+use super::detail_fields::FileFields;
+use std::collections::HashMap;
+use crate::performance::QueryParam;
 
 /// File model
-#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, ToSchema)]
 pub struct File {
     pub id: Uuid,
     pub project_id: Uuid,
@@ -23,7 +32,12 @@ pub struct File {
     pub content_hash: Option<String>,
     pub size: i64,
     pub line_count: i32,
+    /// Words split on whitespace, markup included
     pub word_count: i32,
+    /// Words following TeX conventions, see `models::word_count`; `None`
+    /// until the backfill reaches files saved before it was counted
+    #[sqlx(default)]
+    pub word_count_tex: Option<i32>,
     pub latex_metadata: Option<serde_json::Value>,
     pub version: i32,
     pub checksum: Option<String>,
@@ -35,6 +49,9 @@ pub struct File {
     pub last_modified: DateTime<Utc>,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
+    /// Malware scan result; quarantined files cannot be downloaded
+    #[sqlx(default)]
+    pub scan_status: FileScanStatus,
 }
 
 impl Entity for File {
@@ -58,14 +75,24 @@ pub struct FileMetadata {
     pub references: Vec<String>,
     pub labels: Vec<String>,
     pub includes: Vec<String>,
+    /// Line of each of `includes`; empty for files saved before lines were recorded
+    #[serde(default)]
+    pub include_lines: Vec<i32>,
     pub sections: Vec<SectionInfo>,
     pub figures: Vec<FigureInfo>,
     pub tables: Vec<TableInfo>,
     pub equations: Vec<EquationInfo>,
+    /// `\includegraphics` targets as written
+    #[serde(default)]
+    pub graphics: Vec<ResourceInfo>,
+    /// Databases named by `\bibliography` and `\addbibresource`, with `.bib`
+    /// added when missing
+    #[serde(default)]
+    pub bibliographies: Vec<ResourceInfo>,
 }
 
 /// Section information
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
 pub struct SectionInfo {
     pub title: String,
     pub level: i32,
@@ -74,7 +101,7 @@ pub struct SectionInfo {
 }
 
 /// Figure information
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
 pub struct FigureInfo {
     pub caption: String,
     pub label: Option<String>,
@@ -82,33 +109,47 @@ pub struct FigureInfo {
 }
 
 /// Table information
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
 pub struct TableInfo {
     pub caption: String,
     pub label: Option<String>,
     pub line_number: i32,
 }
 
+/// A file a LaTeX file refers to besides its includes
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ResourceInfo {
+    pub target: String,
+    pub line_number: i32,
+}
+
 /// Equation information
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
 pub struct EquationInfo {
     pub label: Option<String>,
     pub line_number: i32,
 }
 
+/// Details stored with file activity entries
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FileChangeDetails {
+    pub path: String,
+    pub word_count_delta: i32,
+}
+
 /// File creation request
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, ToSchema)]
 pub struct CreateFile {
-    pub name: String,
+    pub name: crate::models::validation::FileName,
     pub path: String,
     pub content: Option<String>,
     pub content_type: Option<ContentType>,
 }
 
 /// File update request
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, ToSchema)]
 pub struct UpdateFile {
-    pub name: Option<String>,
+    pub name: Option<crate::models::validation::FileName>,
     pub path: Option<String>,
     pub content: Option<String>,
     pub content_type: Option<ContentType>,
@@ -116,25 +157,36 @@ pub struct UpdateFile {
 }
 
 /// File version for version history
-#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, ToSchema)]
 pub struct FileVersion {
     pub id: Uuid,
     pub file_id: Uuid,
     pub version: i32,
     pub content_hash: String,
-    pub changes: Option<String>, // JSON diff
+    /// Delta against the previous version, see `file_history`
+    #[serde(skip)]
+    pub changes: Option<String>,
     pub change_summary: String,
     pub author_id: Uuid,
     pub created_at: DateTime<Utc>,
 }
 
+/// Columns of `FileVersion`, leaving out the stored content
+pub(crate) const FILE_VERSION_COLUMNS: &str =
+    "id, file_id, version, content_hash, changes, change_summary, author_id, created_at";
+
 /// File with additional data
-#[derive(Debug, Clone, Serialize)]
+///
+/// Sections left out with `?fields=` are missing from the response.
+#[derive(Debug, Clone, Serialize, ToSchema)]
 pub struct FileWithDetails {
     #[serde(flatten)]
     pub file: File,
-    pub modified_by: Option<UserProfile>,
-    pub versions: Vec<FileVersion>,
+    /// `null` when nobody modified the file yet
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub modified_by: Option<Option<UserProfile>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub versions: Option<Vec<FileVersion>>,
     pub url: Option<String>,
 }
 
@@ -158,7 +210,7 @@ pub struct SearchHighlight {
 }
 
 /// File tree structure
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, ToSchema)]
 pub struct FileNode {
     pub id: Uuid,
     pub name: String,
@@ -166,14 +218,48 @@ pub struct FileNode {
     pub is_directory: bool,
     pub size: i64,
     pub modified_at: DateTime<Utc>,
+    #[schema(no_recursion)]
     pub children: Vec<FileNode>,
     pub level: i32,
 }
 
+/// Orders of `File::list_for_project`, by path by default
+pub const FILE_SORT: Sortable = Sortable {
+    columns: &[
+        SortColumn { name: "path", expression: "f.path", default_order: SortOrder::Asc },
+        SortColumn { name: "name", expression: "f.name", default_order: SortOrder::Asc },
+        SortColumn { name: "size", expression: "f.size", default_order: SortOrder::Desc },
+        SortColumn { name: "created_at", expression: "f.created_at", default_order: SortOrder::Desc },
+        SortColumn { name: "updated_at", expression: "f.updated_at", default_order: SortOrder::Desc },
+    ],
+    tiebreaker: "f.id",
+};
+
+/// Unique index keeping the paths of a project's live files apart
+const LIVE_PATH_INDEX: &str = "idx_files_project_path_live";
+
+fn path_taken() -> crate::error::AppError {
+    crate::error::AppError::Conflict("File with this path already exists".to_string())
+}
+
+/// A move onto the path of a live file is a conflict, not a database error
+fn path_conflict(error: sqlx::Error) -> crate::error::AppError {
+    match &error {
+        sqlx::Error::Database(e) if e.constraint() == Some(LIVE_PATH_INDEX) => path_taken(),
+        _ => crate::error::AppError::Database(error),
+    }
+}
+
 impl File {
-    /// Create a new file
-    pub async fn create(
-        db: &sqlx::PgPool,
+    /// Create a new file, logging its creation
+    ///
+    /// Runs in a transaction of its own, nested in `db` when that is one.
+    /// The path must not be taken by a live file of the project; of creates
+    /// racing for a path one wins and the others get a conflict. A file over
+    /// one of the project's `limits` is refused.
+    pub async fn create<'a>(
+        db: impl sqlx::Acquire<'a, Database = sqlx::Postgres>,
+        limits: &ProjectLimits,
         project_id: Uuid,
         create_file: CreateFile,
         created_by: Uuid,
@@ -189,23 +275,26 @@ impl File {
         let content_type = content_type.unwrap_or_default();
         let content_hash = Some(calculate_content_hash(&content));
         let size = content.len() as i64;
-        let line_count = content.lines().count() as i32;
-        let word_count = content.split_whitespace().count() as i32;
-        let latex_metadata = extract_latex_metadata(&content, content_type)
-            .and_then(|metadata| serde_json::to_value(metadata).ok());
+        let DerivedContent { line_count, word_count, word_count_tex, latex_metadata } =
+            DerivedContent::of(&content, content_type);
+
+        let mut tx = db.begin().await.map_err(crate::error::AppError::Database)?;
+        ProjectFreeze::ensure_writable(&mut *tx, project_id).await?;
+        limits.ensure_room(&mut tx, project_id, &path, size).await?;
 
         let file = sqlx::query_as::<_, File>(
             r#"
             INSERT INTO files (
                 project_id, name, path, content_type, content, storage_strategy,
-                content_hash, size, line_count, word_count, latex_metadata,
+                content_hash, size, line_count, word_count, word_count_tex, latex_metadata,
                 version, checksum, is_main, is_deleted, created_by, last_modified,
                 created_at, updated_at
             ) VALUES (
                 $1, $2, $3, $4, $5, $6,
-                $7, $8, $9, $10, $11,
+                $7, $8, $9, $10, $15, $11,
                 1, $12, $13, false, $14, NOW(), NOW(), NOW()
             )
+            ON CONFLICT (project_id, path) WHERE is_deleted = false DO NOTHING
             RETURNING *
             "#
         )
@@ -223,48 +312,266 @@ impl File {
         .bind(content_hash.as_ref().unwrap())
         .bind(path == "main.tex")
         .bind(created_by)
-        .fetch_one(db)
+        .bind(word_count_tex)
+        .fetch_optional(&mut *tx)
         .await
-        .map_err(crate::error::AppError::Database)?;
+        .map_err(crate::error::AppError::Database)?
+        .ok_or_else(path_taken)?;
+        super::fail_point::check("file_create")?;
+
+        Blob::acquire(&mut tx, content_hash.as_ref().unwrap(), size, None).await?;
 
         // Log file creation
         ProjectActivity::log(
-            db,
+            &mut *tx,
             project_id,
             created_by,
             "file_created",
             "file",
             Some(file.id),
-            None,
+            Some(file.change_details(file.word_count)),
+        )
+        .await?;
+
+        tx.commit().await.map_err(crate::error::AppError::Database)?;
+        Ok(file)
+    }
+
+    /// Create a file row whose content lives in the blob store.
+    ///
+    /// Returns the acquired blob so the caller can store the bytes when this
+    /// is the first copy of the content. Like [`File::create`] it fails with
+    /// a conflict when the path is taken and checks the project's `limits`.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn create_external(
+        conn: &mut sqlx::PgConnection,
+        limits: &ProjectLimits,
+        project_id: Uuid,
+        name: &str,
+        path: &str,
+        content_type: ContentType,
+        size: i64,
+        content_hash: &str,
+        created_by: Uuid,
+    ) -> Result<(Self, AcquiredBlob), crate::error::AppError> {
+        ProjectFreeze::ensure_writable(&mut *conn, project_id).await?;
+        limits.ensure_room(&mut *conn, project_id, path, size).await?;
+
+        let file = sqlx::query_as::<_, File>(
+            r#"
+            INSERT INTO files (
+                project_id, name, path, content_type, content, storage_strategy,
+                content_hash, size, line_count, word_count, word_count_tex, version, checksum,
+                is_main, is_deleted, created_by, last_modified, created_at, updated_at
+            ) VALUES (
+                $1, $2, $3, $4, '', $5,
+                $6, $7, 0, 0, 0, 1, $6,
+                false, false, $8, NOW(), NOW(), NOW()
+            )
+            ON CONFLICT (project_id, path) WHERE is_deleted = false DO NOTHING
+            RETURNING *
+            "#
+        )
+        .bind(project_id)
+        .bind(name)
+        .bind(path)
+        .bind(content_type)
+        .bind(StorageStrategy::External)
+        .bind(content_hash)
+        .bind(size)
+        .bind(created_by)
+        .fetch_optional(&mut *conn)
+        .await
+        .map_err(crate::error::AppError::Database)?
+        .ok_or_else(path_taken)?;
+
+        let blob = Blob::acquire(conn, content_hash, size, Some(&blob_storage_path(content_hash))).await?;
+
+        Ok((file, blob))
+    }
+
+    /// Create a file whose bytes go to the blob store unchanged, for binary
+    /// content that must not pass through a `String`
+    #[allow(clippy::too_many_arguments)]
+    pub async fn create_from_bytes(
+        db: &sqlx::PgPool,
+        storage_root: &str,
+        limits: &ProjectLimits,
+        project_id: Uuid,
+        name: &str,
+        path: &str,
+        content_type: ContentType,
+        bytes: &[u8],
+        created_by: Uuid,
+    ) -> Result<Self, crate::error::AppError> {
+        use sha2::{Digest, Sha256};
+
+        let content_hash = hex::encode(Sha256::digest(bytes));
+        let mut tx = db.begin().await.map_err(crate::error::AppError::Database)?;
+
+        let (file, acquired) = Self::create_external(
+            &mut tx,
+            limits,
+            project_id,
+            name,
+            path,
+            content_type,
+            bytes.len() as i64,
+            &content_hash,
+            created_by,
         )
         .await?;
 
+        if let Some(storage_path) = acquired.blob.storage_path.as_ref().filter(|_| acquired.needs_content) {
+            let target = std::path::Path::new(storage_root).join(storage_path);
+            if let Some(parent) = target.parent() {
+                tokio::fs::create_dir_all(parent).await
+                    .map_err(|e| crate::error::AppError::Storage(format!("Failed to prepare blob directory: {}", e)))?;
+            }
+            tokio::fs::write(&target, bytes).await
+                .map_err(|e| crate::error::AppError::Storage(format!("Failed to save file: {}", e)))?;
+        }
+
+        tx.commit().await.map_err(crate::error::AppError::Database)?;
+
         Ok(file)
     }
 
+    /// Create an imported file at `path`, relative to the project root
+    ///
+    /// Valid UTF-8 text stays editable; images and everything else are kept
+    /// byte for byte.
+    pub async fn import(
+        db: &sqlx::PgPool,
+        storage_root: &str,
+        limits: &ProjectLimits,
+        project_id: Uuid,
+        path: &str,
+        bytes: Vec<u8>,
+        created_by: Uuid,
+    ) -> Result<Self, crate::error::AppError> {
+        let file_name = crate::models::validation::FileName::new(path.rsplit('/').next().unwrap_or(path))?;
+        let path = format!("/{}", path);
+        let content_type = ContentType::from_file_name(file_name.as_str());
+
+        let bytes = match content_type {
+            ContentType::Image => bytes,
+            _ => match String::from_utf8(bytes) {
+                Ok(content) => {
+                    let create_file = CreateFile {
+                        name: file_name,
+                        path,
+                        content: Some(content),
+                        content_type: Some(content_type),
+                    };
+                    return Self::create(db, limits, project_id, create_file, created_by).await;
+                }
+                Err(e) => e.into_bytes(),
+            },
+        };
+
+        Self::create_from_bytes(
+            db,
+            storage_root,
+            limits,
+            project_id,
+            file_name.as_str(),
+            &path,
+            content_type,
+            &bytes,
+            created_by,
+        )
+        .await
+    }
+
+    /// Create a file from content staged on disk, moving it into the blob store
+    /// when this is the first copy of the content
+    #[allow(clippy::too_many_arguments)]
+    pub async fn create_from_staged(
+        db: &sqlx::PgPool,
+        storage_root: &str,
+        limits: &ProjectLimits,
+        project_id: Uuid,
+        name: &str,
+        path: &str,
+        content_type: ContentType,
+        staged: &std::path::Path,
+        size: i64,
+        content_hash: &str,
+        created_by: Uuid,
+    ) -> Result<Self, crate::error::AppError> {
+        let mut tx = db.begin().await.map_err(crate::error::AppError::Database)?;
+
+        let (file, acquired) = Self::create_external(
+            &mut tx,
+            limits,
+            project_id,
+            name,
+            path,
+            content_type,
+            size,
+            content_hash,
+            created_by,
+        )
+        .await?;
+
+        if let Some(storage_path) = acquired.blob.storage_path.as_ref().filter(|_| acquired.needs_content) {
+            let target = std::path::Path::new(storage_root).join(storage_path);
+            if let Some(parent) = target.parent() {
+                tokio::fs::create_dir_all(parent).await
+                    .map_err(|e| crate::error::AppError::Storage(format!("Failed to prepare blob directory: {}", e)))?;
+            }
+            tokio::fs::rename(staged, &target).await
+                .map_err(|e| crate::error::AppError::Storage(format!("Failed to save file: {}", e)))?;
+        }
+
+        tx.commit().await.map_err(crate::error::AppError::Database)?;
+
+        Ok(file)
+    }
+
+    /// Read the file's content as stored, from the row or the blob store
+    pub async fn read_bytes(&self, storage_root: &str) -> Result<Vec<u8>, crate::error::AppError> {
+        if self.storage_strategy != StorageStrategy::External {
+            return Ok(self.content.clone().into_bytes());
+        }
+
+        let hash = self.content_hash.as_deref().ok_or_else(|| {
+            crate::error::AppError::Storage(format!("File {} has no content hash", self.id))
+        })?;
+
+        tokio::fs::read(std::path::Path::new(storage_root).join(blob_storage_path(hash)))
+            .await
+            .map_err(|e| crate::error::AppError::Storage(format!("Failed to read file {}: {}", self.path, e)))
+    }
+
     /// Find file by ID with access control
     pub async fn find_by_id(
         db: &sqlx::PgPool,
         file_id: Uuid,
         user_id: Uuid,
     ) -> Result<Option<Self>, crate::error::AppError> {
-        let file = sqlx::query_as::<_, File>(
-            r#"
-            SELECT f.* FROM files f
-            JOIN projects p ON f.project_id = p.id
-            WHERE f.id = $1 AND f.is_deleted = false AND (
-                p.owner_id = $2 OR
-                p.id IN (
-                    SELECT project_id FROM project_collaborators
-                    WHERE user_id = $2
-                ) OR
-                p.is_public = true
+        let file = crate::performance::timed(
+            "file.find_by_id",
+            &[QueryParam::of(&file_id), QueryParam::of(&user_id)],
+            sqlx::query_as::<_, File>(
+                r#"
+                SELECT f.* FROM files f
+                JOIN projects p ON f.project_id = p.id
+                WHERE f.id = $1 AND f.is_deleted = false AND p.purging_at IS NULL AND (
+                    p.owner_id = $2 OR
+                    p.id IN (
+                        SELECT project_id FROM project_collaborators
+                        WHERE user_id = $2
+                    ) OR
+                    p.is_public = true
+                )
+                "#
             )
-            "#
+            .bind(file_id)
+            .bind(user_id)
+            .fetch_optional(db),
         )
-        .bind(file_id)
-        .bind(user_id)
-        .fetch_optional(db)
         .await
         .map_err(crate::error::AppError::Database)?;
 
@@ -278,24 +585,28 @@ impl File {
         path: &str,
         user_id: Uuid,
     ) -> Result<Option<Self>, crate::error::AppError> {
-        let file = sqlx::query_as::<_, File>(
-            r#"
-            SELECT f.* FROM files f
-            JOIN projects p ON f.project_id = p.id
-            WHERE f.project_id = $1 AND f.path = $2 AND f.is_deleted = false AND (
-                p.owner_id = $3 OR
-                p.id IN (
-                    SELECT project_id FROM project_collaborators
-                    WHERE user_id = $3
-                ) OR
-                p.is_public = true
+        let file = crate::performance::timed(
+            "file.find_by_path",
+            &[QueryParam::of(&project_id), QueryParam::of(&path), QueryParam::of(&user_id)],
+            sqlx::query_as::<_, File>(
+                r#"
+                SELECT f.* FROM files f
+                JOIN projects p ON f.project_id = p.id
+                WHERE f.project_id = $1 AND f.path = $2 AND f.is_deleted = false AND p.purging_at IS NULL AND (
+                    p.owner_id = $3 OR
+                    p.id IN (
+                        SELECT project_id FROM project_collaborators
+                        WHERE user_id = $3
+                    ) OR
+                    p.is_public = true
+                )
+                "#
             )
-            "#
+            .bind(project_id)
+            .bind(path)
+            .bind(user_id)
+            .fetch_optional(db),
         )
-        .bind(project_id)
-        .bind(path)
-        .bind(user_id)
-        .fetch_optional(db)
         .await
         .map_err(crate::error::AppError::Database)?;
 
@@ -309,11 +620,12 @@ impl File {
         user_id: Uuid,
         params: &super::PaginationParams,
     ) -> Result<Vec<Self>, crate::error::AppError> {
-        let files = sqlx::query_as::<_, File>(
+        let order_by = params.order_by(&FILE_SORT)?;
+        let files = sqlx::query_as::<_, File>(&format!(
             r#"
             SELECT f.* FROM files f
             JOIN projects p ON f.project_id = p.id
-            WHERE f.project_id = $1 AND f.is_deleted = false AND (
+            WHERE f.project_id = $1 AND f.is_deleted = false AND p.purging_at IS NULL AND (
                 p.owner_id = $2 OR
                 p.id IN (
                     SELECT project_id FROM project_collaborators
@@ -321,10 +633,11 @@ impl File {
                 ) OR
                 p.is_public = true
             )
-            ORDER BY f.path
+            ORDER BY {}
             LIMIT $3 OFFSET $4
-            "#
-        )
+            "#,
+            order_by
+        ))
         .bind(project_id)
         .bind(user_id)
         .bind(params.limit() as i64)
@@ -336,19 +649,57 @@ impl File {
         Ok(files)
     }
 
+    /// List every live file of a project, for building the full tree
+    pub async fn list_all_for_project(
+        db: &sqlx::PgPool,
+        project_id: Uuid,
+    ) -> Result<Vec<Self>, crate::error::AppError> {
+        let files = sqlx::query_as::<_, File>(
+            "SELECT * FROM files WHERE project_id = $1 AND is_deleted = false ORDER BY path"
+        )
+        .bind(project_id)
+        .fetch_all(db)
+        .await
+        .map_err(crate::error::AppError::Database)?;
+
+        Ok(files)
+    }
+
     /// Update file content
     pub async fn update_content(
         &self,
         db: &sqlx::PgPool,
+        storage_root: &str,
         content: String,
         modified_by: Uuid,
     ) -> Result<Self, crate::error::AppError> {
+        if self.storage_strategy == StorageStrategy::External {
+            return Err(crate::error::AppError::Validation(
+                "Binary files cannot be edited as text".to_string(),
+            ));
+        }
+        ProjectFreeze::ensure_writable(db, self.project_id).await?;
+
         let content_hash = Some(calculate_content_hash(&content));
         let size = content.len() as i64;
-        let line_count = content.lines().count() as i32;
-        let word_count = content.split_whitespace().count() as i32;
-        let latex_metadata = extract_latex_metadata(&content, self.content_type)
-            .and_then(|metadata| serde_json::to_value(metadata).ok());
+        let DerivedContent { line_count, word_count, word_count_tex, latex_metadata } =
+            DerivedContent::of(&content, self.content_type);
+
+        let mut tx = db.begin().await.map_err(crate::error::AppError::Database)?;
+
+        // Lock the row and release the hash it holds now, not the one this copy
+        // was loaded with, so concurrent writers never release the same reference
+        let previous_hash = sqlx::query_scalar::<_, Option<String>>(
+            "SELECT content_hash FROM files WHERE id = $1 FOR UPDATE"
+        )
+        .bind(self.id)
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(crate::error::AppError::Database)?
+        .ok_or_else(|| crate::error::AppError::NotFound {
+            entity: "File".to_string(),
+            id: self.id.to_string(),
+        })?;
 
         let file = sqlx::query_as::<_, File>(
             r#"
@@ -358,6 +709,7 @@ impl File {
                 size = $3,
                 line_count = $4,
                 word_count = $5,
+                word_count_tex = $9,
                 latex_metadata = $6,
                 version = version + 1,
                 checksum = $2,
@@ -376,19 +728,107 @@ impl File {
         .bind(&latex_metadata)
         .bind(modified_by)
         .bind(self.id)
-        .fetch_one(db)
+        .bind(word_count_tex)
+        .fetch_one(&mut *tx)
         .await
         .map_err(crate::error::AppError::Database)?;
 
+        // Take the new reference before dropping the old one so unchanged content never hits zero
+        Blob::acquire(&mut tx, content_hash.as_ref().unwrap(), size, None).await?;
+        if let Some(previous_hash) = &previous_hash {
+            if let Some(released) = Blob::release(&mut tx, previous_hash).await? {
+                remove_blob_content(storage_root, &released).await?;
+            }
+        }
+
+        tx.commit().await.map_err(crate::error::AppError::Database)?;
+
+        ProjectActivity::log(
+            db,
+            self.project_id,
+            modified_by,
+            "file_updated",
+            "file",
+            Some(self.id),
+            Some(file.change_details(file.word_count - self.word_count)),
+        )
+        .await?;
+        super::onboarding::record(db, modified_by, super::onboarding::Milestone::FileEdited);
+
         Ok(file)
     }
 
+    /// Activity details of a change to this file
+    fn change_details(&self, word_count_delta: i32) -> String {
+        serde_json::to_string(&FileChangeDetails {
+            path: self.path.clone(),
+            word_count_delta,
+        })
+        .unwrap_or_default()
+    }
+
+    /// Permanently delete a file and its versions, releasing their blobs.
+    ///
+    /// Soft-deleted files keep their references so they can be restored;
+    /// only a purge gives the storage back.
+    pub async fn purge(
+        &self,
+        db: &sqlx::PgPool,
+        storage_root: &str,
+        user_id: Uuid,
+    ) -> Result<(), crate::error::AppError> {
+        ProjectFreeze::ensure_writable(db, self.project_id).await?;
+
+        let mut tx = db.begin().await.map_err(crate::error::AppError::Database)?;
+
+        let mut hashes = sqlx::query_scalar::<_, String>(
+            "DELETE FROM file_versions WHERE file_id = $1 AND content_hash IS NOT NULL RETURNING content_hash"
+        )
+        .bind(self.id)
+        .fetch_all(&mut *tx)
+        .await
+        .map_err(crate::error::AppError::Database)?;
+
+        let file_hash = sqlx::query_scalar::<_, Option<String>>(
+            "DELETE FROM files WHERE id = $1 RETURNING content_hash"
+        )
+        .bind(self.id)
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(crate::error::AppError::Database)?;
+
+        hashes.extend(file_hash.flatten());
+
+        for hash in &hashes {
+            if let Some(released) = Blob::release(&mut tx, hash).await? {
+                remove_blob_content(storage_root, &released).await?;
+            }
+        }
+
+        tx.commit().await.map_err(crate::error::AppError::Database)?;
+
+        ProjectActivity::log(
+            db,
+            self.project_id,
+            user_id,
+            "file_purged",
+            "file",
+            Some(self.id),
+            None,
+        )
+        .await?;
+
+        Ok(())
+    }
+
     /// Soft delete file
     pub async fn soft_delete(
         &self,
         db: &sqlx::PgPool,
         user_id: Uuid,
     ) -> Result<(), crate::error::AppError> {
+        ProjectFreeze::ensure_writable(db, self.project_id).await?;
+
         sqlx::query(
             "UPDATE files SET is_deleted = true, deleted_at = NOW() WHERE id = $1"
         )
@@ -405,26 +845,92 @@ impl File {
             "file_deleted",
             "file",
             Some(self.id),
-            None,
+            Some(self.change_details(-self.word_count)),
         )
         .await?;
 
         Ok(())
     }
 
-    /// Restore soft-deleted file
+    /// Restore soft-deleted file, at `path` when given
+    ///
+    /// A file created at the path since the deletion makes this a conflict;
+    /// the file can then be restored under another path. Like a new file it
+    /// must fit within the project's `limits`.
     pub async fn restore(
         &self,
         db: &sqlx::PgPool,
+        limits: &ProjectLimits,
+        path: Option<&str>,
+    ) -> Result<Self, crate::error::AppError> {
+        let mut tx = db.begin().await.map_err(crate::error::AppError::Database)?;
+        ProjectFreeze::ensure_writable(&mut *tx, self.project_id).await?;
+
+        let (name, path): (String, &str) = match path {
+            Some(path) => {
+                let name = crate::models::validation::FileName::new(path.rsplit('/').next().unwrap_or(path))?;
+                (name.into(), path)
+            }
+            None => (self.name.clone(), self.path.as_str()),
+        };
+        limits.ensure_room(&mut tx, self.project_id, path, self.size).await?;
+
+        let file = sqlx::query_as::<_, File>(
+            r#"
+            UPDATE files SET is_deleted = false, deleted_at = NULL, name = $2, path = $3
+            WHERE id = $1
+            RETURNING *
+            "#
+        )
+        .bind(self.id)
+        .bind(name)
+        .bind(path)
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(path_conflict)?;
+
+        tx.commit().await.map_err(crate::error::AppError::Database)?;
+        Ok(file)
+    }
+
+    /// Rename or move a file, logging the move
+    ///
+    /// Fails with a conflict when a live file of the project has the path,
+    /// and when the path goes over the depth or length `limits`.
+    pub async fn move_to(
+        &self,
+        db: &sqlx::PgPool,
+        limits: &ProjectLimits,
+        name: &str,
+        path: &str,
+        user_id: Uuid,
     ) -> Result<Self, crate::error::AppError> {
+        ProjectFreeze::ensure_writable(db, self.project_id).await?;
+        limits.check_path(path)?;
+
+        let mut tx = db.begin().await.map_err(crate::error::AppError::Database)?;
         let file = sqlx::query_as::<_, File>(
-            "UPDATE files SET is_deleted = false, deleted_at = NULL WHERE id = $1 RETURNING *"
+            "UPDATE files SET name = $2, path = $3, updated_at = NOW() WHERE id = $1 AND is_deleted = false RETURNING *"
         )
         .bind(self.id)
-        .fetch_one(db)
+        .bind(name)
+        .bind(path)
+        .fetch_one(&mut *tx)
         .await
-        .map_err(crate::error::AppError::Database)?;
+        .map_err(path_conflict)?;
 
+        ProjectActivity::log(
+            &mut *tx,
+            self.project_id,
+            user_id,
+            "file_moved",
+            "file",
+            Some(self.id),
+            Some(file.change_details(0)),
+        )
+        .await?;
+
+        tx.commit().await.map_err(crate::error::AppError::Database)?;
         Ok(file)
     }
 
@@ -433,6 +939,16 @@ impl File {
         db: &sqlx::PgPool,
         file_id: Uuid,
         user_id: Uuid,
+    ) -> Result<FileWithDetails, crate::error::AppError> {
+        Self::get_with_fields(db, file_id, user_id, FileFields::ALL).await
+    }
+
+    /// Get file with the selected details
+    pub async fn get_with_fields(
+        db: &sqlx::PgPool,
+        file_id: Uuid,
+        user_id: Uuid,
+        fields: FileFields,
     ) -> Result<FileWithDetails, crate::error::AppError> {
         // Get basic file info with access control
         let file = Self::find_by_id(db, file_id, user_id).await?
@@ -441,41 +957,64 @@ impl File {
                 id: file_id.to_string(),
             })?;
 
-        // Get modified by user info
-        let modified_by = if let Some(user_id) = file.last_modified_by {
-            Some(
-                sqlx::query_as::<_, UserProfile>(
-                    r#"
-                    SELECT id, username, email, display_name, avatar_url,
-                           is_active, email_verified, last_login_at, created_at
-                    FROM users
-                    WHERE id = $1
-                    "#
-                )
-                .bind(user_id)
-                .fetch_one(db)
-                .await
-                .map_err(crate::error::AppError::Database)?,
-            )
+        let mut details = Self::with_details(db, vec![file], fields).await?;
+        Ok(details.remove(0))
+    }
+
+    /// Load the selected details of several files, one query per section
+    pub async fn with_details(
+        db: &sqlx::PgPool,
+        files: Vec<File>,
+        fields: FileFields,
+    ) -> Result<Vec<FileWithDetails>, crate::error::AppError> {
+        if files.is_empty() {
+            return Ok(Vec::new());
+        }
+        let authors = if fields.modified_by {
+            let author_ids: Vec<Uuid> = files.iter().filter_map(|file| file.last_modified_by).collect();
+            if author_ids.is_empty() {
+                Some(HashMap::new())
+            } else {
+                Some(UserProfile::find_many(db, &author_ids).await?)
+            }
         } else {
             None
         };
 
-        // Get file versions
-        let versions = sqlx::query_as::<_, FileVersion>(
-            "SELECT * FROM file_versions WHERE file_id = $1 ORDER BY created_at DESC"
-        )
-        .bind(file_id)
-        .fetch_all(db)
-        .await
-        .map_err(crate::error::AppError::Database)?;
+        let mut versions = if fields.versions {
+            let file_ids: Vec<Uuid> = files.iter().map(|file| file.id).collect();
+            let rows = sqlx::query_as::<_, FileVersion>(&format!(
+                "SELECT {FILE_VERSION_COLUMNS} FROM file_versions WHERE file_id = ANY($1) ORDER BY created_at DESC"
+            ))
+            .bind(&file_ids)
+            .fetch_all(db)
+            .await
+            .map_err(crate::error::AppError::Database)?;
+
+            let mut by_file: HashMap<Uuid, Vec<FileVersion>> = HashMap::new();
+            for version in rows {
+                by_file.entry(version.file_id).or_default().push(version);
+            }
+            Some(by_file)
+        } else {
+            None
+        };
 
-        Ok(FileWithDetails {
-            file,
-            modified_by,
-            versions,
-            url: None, // TODO: Implement URL generation for stored files
-        })
+        let details = files
+            .into_iter()
+            .map(|file| FileWithDetails {
+                modified_by: authors
+                    .as_ref()
+                    .map(|authors| file.last_modified_by.and_then(|id| authors.get(&id).cloned())),
+                versions: versions
+                    .as_mut()
+                    .map(|by_file| by_file.remove(&file.id).unwrap_or_default()),
+                url: None, // TODO: Implement URL generation for stored files
+                file,
+            })
+            .collect();
+
+        Ok(details)
     }
 
     /// Build file tree structure (simplified version that avoids borrow checker issues)
@@ -533,47 +1072,15 @@ impl File {
 }
 
 impl FileVersion {
-    /// Create new version
-    pub async fn create(
-        db: &sqlx::PgPool,
-        file_id: Uuid,
-        version: i32,
-        content: &str,
-        author_id: Uuid,
-        message: &str,
-    ) -> Result<Self, crate::error::AppError> {
-        let content_hash = calculate_content_hash(content);
-        let changes: Option<String> = None; // TODO: Calculate diff from previous version
-
-        let file_version = sqlx::query_as::<_, FileVersion>(
-            r#"
-            INSERT INTO file_versions (file_id, version, content_hash, changes, change_summary, author_id)
-            VALUES ($1, $2, $3, $4, $5, $6)
-            RETURNING *
-            "#
-        )
-        .bind(file_id)
-        .bind(version)
-        .bind(content_hash)
-        .bind(changes)
-        .bind(message)
-        .bind(author_id)
-        .fetch_one(db)
-        .await
-        .map_err(crate::error::AppError::Database)?;
-
-        Ok(file_version)
-    }
-
     /// Get version history for file
     pub async fn get_history(
         db: &sqlx::PgPool,
         file_id: Uuid,
         limit: u32,
     ) -> Result<Vec<Self>, crate::error::AppError> {
-        let versions = sqlx::query_as::<_, FileVersion>(
-            "SELECT * FROM file_versions WHERE file_id = $1 ORDER BY created_at DESC LIMIT $2"
-        )
+        let versions = sqlx::query_as::<_, FileVersion>(&format!(
+            "SELECT {FILE_VERSION_COLUMNS} FROM file_versions WHERE file_id = $1 ORDER BY created_at DESC LIMIT $2"
+        ))
         .bind(file_id)
         .bind(limit as i64)
         .fetch_all(db)
@@ -592,8 +1099,41 @@ fn calculate_content_hash(content: &str) -> String {
     format!("{:x}", hasher.finalize())
 }
 
+/// What is stored alongside the content of a text file, derived from it
+#[derive(Debug, Clone, PartialEq)]
+pub struct DerivedContent {
+    pub line_count: i32,
+    pub word_count: i32,
+    pub word_count_tex: i32,
+    pub latex_metadata: Option<serde_json::Value>,
+}
+
+impl DerivedContent {
+    /// Derive with the current extractors
+    pub fn of(content: &str, content_type: ContentType) -> Self {
+        Self {
+            line_count: content.lines().count() as i32,
+            word_count: content.split_whitespace().count() as i32,
+            word_count_tex: stored_word_count(content, content_type),
+            latex_metadata: extract_latex_metadata(content, content_type)
+                .and_then(|metadata| serde_json::to_value(metadata).ok()),
+        }
+    }
+
+    /// What is stored for a file now
+    pub fn stored(file: &File) -> Self {
+        Self {
+            line_count: file.line_count,
+            word_count: file.word_count,
+            // Never counted, so never equal to a count
+            word_count_tex: file.word_count_tex.unwrap_or(-1),
+            latex_metadata: file.latex_metadata.clone(),
+        }
+    }
+}
+
 /// Extract LaTeX metadata from content
-fn extract_latex_metadata(content: &str, content_type: ContentType) -> Option<FileMetadata> {
+pub(crate) fn extract_latex_metadata(content: &str, content_type: ContentType) -> Option<FileMetadata> {
     if content_type != ContentType::Latex {
         return None;
     }
@@ -603,12 +1143,21 @@ fn extract_latex_metadata(content: &str, content_type: ContentType) -> Option<Fi
         references: Vec::new(),
         labels: Vec::new(),
         includes: Vec::new(),
+        include_lines: Vec::new(),
         sections: Vec::new(),
         figures: Vec::new(),
         tables: Vec::new(),
         equations: Vec::new(),
+        graphics: Vec::new(),
+        bibliographies: Vec::new(),
     };
 
+    // Offsets where each line starts, to number the lines of matches
+    let line_starts: Vec<usize> = std::iter::once(0)
+        .chain(content.match_indices('\n').map(|(offset, _)| offset + 1))
+        .collect();
+    let line_of = |offset: usize| line_starts.partition_point(|&start| start <= offset) as i32;
+
     // Extract citations
     let citation_regex = regex::Regex::new(r"\\cite\{([^}]+)\}").unwrap();
     for cap in citation_regex.captures_iter(content) {
@@ -641,6 +1190,29 @@ fn extract_latex_metadata(content: &str, content_type: ContentType) -> Option<Fi
             include.to_string()
         };
         metadata.includes.push(path);
+        metadata.include_lines.push(line_of(cap.get(0).map_or(0, |m| m.start())));
+    }
+
+    // Extract graphics and bibliography databases
+    let graphics_regex = regex::Regex::new(r"\\includegraphics\*?(?:\[[^\]]*\])?\{([^}]+)\}").unwrap();
+    for cap in graphics_regex.captures_iter(content) {
+        metadata.graphics.push(ResourceInfo {
+            target: cap[1].trim().to_string(),
+            line_number: line_of(cap.get(0).map_or(0, |m| m.start())),
+        });
+    }
+    let bibliography_regex =
+        regex::Regex::new(r"\\(bibliography|addbibresource)(?:\[[^\]]*\])?\{([^}]+)\}").unwrap();
+    for cap in bibliography_regex.captures_iter(content) {
+        let line_number = line_of(cap.get(0).map_or(0, |m| m.start()));
+        for target in cap[2].split(',').map(str::trim).filter(|target| !target.is_empty()) {
+            let target = if target.ends_with(".bib") {
+                target.to_string()
+            } else {
+                format!("{}.bib", target)
+            };
+            metadata.bibliographies.push(ResourceInfo { target, line_number });
+        }
     }
 
     // Extract sections
@@ -668,13 +1240,60 @@ fn extract_latex_metadata(content: &str, content_type: ContentType) -> Option<Fi
         line_number += 1;
     }
 
+    extract_environments(content, &mut metadata);
+
     Some(metadata)
 }
 
+/// Floats and numbered equations, with the caption and label inside them
+fn extract_environments(content: &str, metadata: &mut FileMetadata) {
+    let begin_regex = regex::Regex::new(r"\\begin\{(figure|table|equation|align|gather|multline)(\*?)\}").unwrap();
+    let caption_regex = regex::Regex::new(r"\\caption\{([^}]+)\}").unwrap();
+    let label_regex = regex::Regex::new(r"\\label\{([^}]+)\}").unwrap();
+
+    // Environment name, starred, line it begins on, caption and label so far
+    let mut open: Option<(String, bool, i32, Option<String>, Option<String>)> = None;
+    for (index, line) in content.lines().enumerate() {
+        let line_number = index as i32 + 1;
+        if open.is_none() {
+            if let Some(cap) = begin_regex.captures(line) {
+                open = Some((cap[1].to_string(), !cap[2].is_empty(), line_number, None, None));
+            }
+        }
+
+        let Some((environment, starred, begin_line, caption, label)) = open.as_mut() else { continue };
+        if caption.is_none() {
+            *caption = caption_regex.captures(line).map(|cap| cap[1].to_string());
+        }
+        if label.is_none() {
+            *label = label_regex.captures(line).map(|cap| cap[1].to_string());
+        }
+        let end = format!("\\end{{{}{}}}", environment, if *starred { "*" } else { "" });
+        if !line.contains(&end) {
+            continue;
+        }
+
+        let (caption, label, line_number) = (caption.take().unwrap_or_default(), label.take(), *begin_line);
+        match environment.as_str() {
+            "figure" => metadata.figures.push(FigureInfo { caption, label, line_number }),
+            "table" => metadata.tables.push(TableInfo { caption, label, line_number }),
+            // Unnumbered math has nothing to refer to
+            _ if !*starred => metadata.equations.push(EquationInfo { label, line_number }),
+            _ => {}
+        }
+        open = None;
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::error::AppError;
+    use crate::models::validation::FileName;
+    use crate::models::UserRole;
+    use crate::models::PaginationParams;
+    use crate::testing::{
+        add_collaborator, assert_pages_are_stable, create_test_file, create_test_project, create_test_user, TestDb,
+    };
 
     #[test]
     fn test_content_hash() {
@@ -685,7 +1304,7 @@ mod tests {
 
         let different_content = "Different content";
         let hash3 = calculate_content_hash(different_content);
-        assert_neq!(hash1, hash3);
+        assert_ne!(hash1, hash3);
     }
 
     #[test]
@@ -717,5 +1336,221 @@ E = mc^2
         assert_eq!(metadata.sections.len(), 1);
         assert_eq!(metadata.sections[0].title, "Introduction");
         assert_eq!(metadata.sections[0].level, 1);
+        assert_eq!(
+            metadata.figures,
+            vec![FigureInfo { caption: "Test Figure".to_string(), label: Some("fig:test".to_string()), line_number: 9 }]
+        );
+        assert!(metadata.tables.is_empty());
+        assert_eq!(metadata.equations, vec![EquationInfo { label: Some("eq:einstein".to_string()), line_number: 14 }]);
+    }
+
+    #[test]
+    fn test_includes_and_resources_record_lines() {
+        let content = "\\input{intro}\n\\includegraphics[width=5cm]{figures/plot}\n\n\\bibliography{refs, more.bib}\n\\bibliographystyle{plain}\n";
+        let metadata = extract_latex_metadata(content, ContentType::Latex).unwrap();
+
+        assert_eq!(metadata.includes, vec!["intro.tex"]);
+        assert_eq!(metadata.include_lines, vec![1]);
+        assert_eq!(metadata.graphics, vec![ResourceInfo { target: "figures/plot".to_string(), line_number: 2 }]);
+        assert_eq!(
+            metadata.bibliographies,
+            vec![
+                ResourceInfo { target: "refs.bib".to_string(), line_number: 4 },
+                ResourceInfo { target: "more.bib".to_string(), line_number: 4 },
+            ]
+        );
+
+        // Metadata stored before lines and resources were recorded still reads
+        let stored = serde_json::json!({
+            "citations": [], "references": [], "labels": [], "includes": ["intro.tex"],
+            "sections": [], "figures": [], "tables": [], "equations": [],
+        });
+        let stored: FileMetadata = serde_json::from_value(stored).unwrap();
+        assert!(stored.include_lines.is_empty() && stored.graphics.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_file_access_by_relationship() {
+        let Some(db) = TestDb::start().await else { return };
+        let owner = create_test_user(&db.pool).await;
+        let collaborator = create_test_user(&db.pool).await;
+        let stranger = create_test_user(&db.pool).await;
+
+        let private = create_test_project(&db.pool, &owner, false).await;
+        let public = create_test_project(&db.pool, &owner, true).await;
+        add_collaborator(&db.pool, &private, &collaborator, UserRole::Collaborator).await;
+
+        let private_file = create_test_file(&db.pool, &private, &owner).await;
+        let public_file = create_test_file(&db.pool, &public, &owner).await;
+
+        for user in [&owner, &collaborator] {
+            let found = File::find_by_id(&db.pool, private_file.id, user.id).await.unwrap();
+            assert_eq!(found.map(|f| f.id), Some(private_file.id));
+        }
+        assert!(File::find_by_id(&db.pool, private_file.id, stranger.id).await.unwrap().is_none());
+        assert!(File::find_by_id(&db.pool, public_file.id, stranger.id).await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_failed_create_leaves_no_file() {
+        let Some(db) = TestDb::start().await else { return };
+        let owner = create_test_user(&db.pool).await;
+        let project = create_test_project(&db.pool, &owner, false).await;
+
+        {
+            let _armed = crate::models::fail_point::arm("file_create");
+            let create = CreateFile {
+                name: FileName::new("main.tex").unwrap(),
+                path: "main.tex".to_string(),
+                content: Some("\\documentclass{article}".to_string()),
+                content_type: None,
+            };
+            assert!(File::create(&db.pool, &ProjectLimits::default(), project.id, create, owner.id).await.is_err());
+        }
+        let files: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM files WHERE project_id = $1")
+            .bind(project.id)
+            .fetch_one(&db.pool)
+            .await
+            .unwrap();
+        assert_eq!(files, 0);
+
+        // The path is free again and the file's activity is logged once
+        let file = create_test_file(&db.pool, &project, &owner).await;
+        let logged: i64 = sqlx::query_scalar(
+            "SELECT COUNT(*) FROM project_activity WHERE project_id = $1 AND action = 'file_created'"
+        )
+        .bind(project.id)
+        .fetch_one(&db.pool)
+        .await
+        .unwrap();
+        assert_eq!((file.path.as_str(), logged), ("main.tex", 1));
+    }
+
+    #[tokio::test]
+    async fn test_list_pages_are_stable_under_every_sort() {
+        let Some(db) = TestDb::start().await else { return };
+        let owner = create_test_user(&db.pool).await;
+        let project = create_test_project(&db.pool, &owner, false).await;
+        for i in [3, 1, 4, 0, 2] {
+            let name = format!("chapter{}.tex", i);
+            File::create(
+                &db.pool,
+                &ProjectLimits::default(),
+                project.id,
+                CreateFile {
+                    name: FileName::new(&name).unwrap(),
+                    path: format!("chapters/{}", name),
+                    content: Some("\\chapter{Draft}\n".to_string()),
+                    content_type: None,
+                },
+                owner.id,
+            )
+            .await
+            .unwrap();
+        }
+        sqlx::query("UPDATE files SET created_at = NOW(), updated_at = NOW() WHERE project_id = $1")
+            .bind(project.id)
+            .execute(&db.pool)
+            .await
+            .unwrap();
+
+        let pool = &db.pool;
+        assert_pages_are_stable(&FILE_SORT, move |params| async move {
+            let files = File::list_for_project(pool, project.id, owner.id, &params).await.unwrap();
+            files.into_iter().map(|f| f.id).collect()
+        })
+        .await;
+
+        // Without a sort the list stays ordered by path
+        let files = File::list_for_project(&db.pool, project.id, owner.id, &PaginationParams::default()).await.unwrap();
+        let paths: Vec<_> = files.iter().map(|f| f.path.as_str()).collect();
+        assert_eq!(paths, (0..5).map(|i| format!("chapters/chapter{}.tex", i)).collect::<Vec<_>>());
+    }
+
+    #[tokio::test]
+    async fn test_racing_creates_of_a_path_conflict() {
+        let Some(db) = TestDb::start().await else { return };
+        let owner = create_test_user(&db.pool).await;
+        let project = create_test_project(&db.pool, &owner, false).await;
+        let limits = ProjectLimits::default();
+
+        let create = |content: String| CreateFile {
+            name: FileName::new("race.tex").unwrap(),
+            path: "/race.tex".to_string(),
+            content: Some(content),
+            content_type: None,
+        };
+        let creates: Vec<_> = (0..20)
+            .map(|i| {
+                let (pool, file) = (db.pool.clone(), create(format!("attempt {}", i)));
+                tokio::spawn(async move { File::create(&pool, &limits, project.id, file, owner.id).await })
+            })
+            .collect();
+        let mut created = Vec::new();
+        for create in creates {
+            match create.await.unwrap() {
+                Ok(file) => created.push(file),
+                Err(error) => {
+                    assert_eq!((error.status_code(), error.error_code()), (axum::http::StatusCode::CONFLICT, "CONFLICT"));
+                }
+            }
+        }
+        assert_eq!(created.len(), 1);
+
+        // A deleted file frees its path, and takes it back only under another
+        let first = created.remove(0);
+        first.soft_delete(&db.pool, owner.id).await.unwrap();
+        let second = File::create(&db.pool, &limits, project.id, create("again".to_string()), owner.id).await.unwrap();
+        assert!(matches!(first.restore(&db.pool, &limits, None).await, Err(crate::error::AppError::Conflict(_))));
+        let restored = first.restore(&db.pool, &limits, Some("/race-old.tex")).await.unwrap();
+        assert_eq!((restored.name.as_str(), restored.is_deleted), ("race-old.tex", false));
+
+        assert!(matches!(
+            restored.move_to(&db.pool, &limits, "race.tex", "/race.tex", owner.id).await,
+            Err(crate::error::AppError::Conflict(_))
+        ));
+        let moved = second.move_to(&db.pool, &limits, "final.tex", "/final.tex", owner.id).await.unwrap();
+        assert_eq!(moved.path, "/final.tex");
+        restored.move_to(&db.pool, &limits, "race.tex", "/race.tex", owner.id).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_updates_release_each_reference_once() {
+        let Some(db) = TestDb::start().await else { return };
+        let storage = tempfile::tempdir().unwrap();
+        let root = storage.path().to_str().unwrap();
+        let owner = create_test_user(&db.pool).await;
+        let project = create_test_project(&db.pool, &owner, false).await;
+        let file = create_test_file(&db.pool, &project, &owner).await;
+        // Content unique to this file, so no other row shares its blobs
+        let edit = |label: &str| format!("{} edit of {}\n", label, file.id);
+        let file = file.update_content(&db.pool, root, edit("initial"), owner.id).await.unwrap();
+        let original = file.content_hash.clone().unwrap();
+
+        // Both writers start from the same, soon stale, copy of the file
+        let (first, second) = tokio::join!(
+            file.update_content(&db.pool, root, edit("first"), owner.id),
+            file.update_content(&db.pool, root, edit("second"), owner.id),
+        );
+        let (first, second) = (first.unwrap(), second.unwrap());
+        assert!(Blob::find_by_hash(&db.pool, &original).await.unwrap().is_none());
+
+        // Only the surviving content keeps a blob
+        let current = sqlx::query_scalar::<_, String>("SELECT content_hash FROM files WHERE id = $1")
+            .bind(file.id)
+            .fetch_one(&db.pool)
+            .await
+            .unwrap();
+        let overwritten = [first.content_hash.unwrap(), second.content_hash.unwrap()]
+            .into_iter()
+            .find(|hash| *hash != current)
+            .unwrap();
+        assert_eq!(Blob::find_by_hash(&db.pool, &current).await.unwrap().unwrap().ref_count, 1);
+        assert!(Blob::find_by_hash(&db.pool, &overwritten).await.unwrap().is_none());
+
+        let report = Blob::check_consistency(&db.pool, root).await.unwrap();
+        let involved = [&original, &current, &overwritten];
+        assert!(!report.orphaned_blobs.iter().any(|blob| involved.contains(&&blob.hash)));
+        assert!(!report.ref_count_mismatches.iter().any(|mismatch| involved.contains(&&mismatch.hash)));
     }
 }
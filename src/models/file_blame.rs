@@ -0,0 +1,469 @@
+//! Line-level blame of text files
+//!
+//! A blame tells, for each line of a file's current content, which version
+//! introduced it. It walks the history backwards from the current content:
+//! each version is diffed against the one before it along a longest common
+//! subsequence, lines the diff keeps are followed into the older version and
+//! the others are attributed to the newer one. Moves are not detected, so a
+//! moved line counts as written where it now is, and a rewritten line
+//! belongs to whoever rewrote it. Lines may be compared ignoring whitespace,
+//! so reindenting a paragraph does not take it over.
+//!
+//! Only the newest `blame_max_versions` versions are looked at. Lines still
+//! unattributed past them are marked `earlier`, as are lines of a first
+//! recorded version that is not the file's first. Content saved without a
+//! version, like an applied formatting, belongs to the file's last save.
+//! Versions are reconstructed a run at a time, so blaming a few lines
+//! usually stops well before the horizon. Blames are cached by file and
+//! content hash, and dropped when the file gets a new version.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::{Arc, Mutex};
+use utoipa::{IntoParams, ToSchema};
+use uuid::Uuid;
+
+use super::compile_diff::{lcs_ops, DiffOp, MAX_WORD_DIFF_CELLS};
+use super::file::{File, FileVersion};
+use crate::error::AppError;
+use crate::server::AppState;
+
+/// Blames kept in memory
+const BLAME_CACHE_CAPACITY: usize = 256;
+
+/// Versions reconstructed at a time while walking back
+const BLAME_SEGMENT: usize = 32;
+
+/// Blame parameters
+#[derive(Debug, Clone, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct BlameParams {
+    /// Lines to blame as `first-last`, 1-based and inclusive; all by default
+    pub range: Option<String>,
+    /// Compare lines ignoring whitespace
+    #[serde(default)]
+    pub ignore_whitespace: bool,
+}
+
+/// Public profile of a line's author
+#[derive(Debug, Clone, Serialize, FromRow, ToSchema)]
+pub struct BlameAuthor {
+    pub id: Uuid,
+    pub username: String,
+    pub display_name: String,
+    pub avatar_url: Option<String>,
+}
+
+/// Where a line of the current content comes from
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct BlameLine {
+    /// Line number, 1-based
+    pub line: usize,
+    /// Version that introduced the line; `None` when `earlier`
+    pub version: Option<i32>,
+    /// Author of that version, unless their account is gone
+    pub author: Option<BlameAuthor>,
+    pub created_at: Option<DateTime<Utc>>,
+    /// Whether the line predates the versions looked at
+    pub earlier: bool,
+}
+
+/// Blame of a file's current content
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct FileBlame {
+    pub file_id: Uuid,
+    /// Hash of the blamed content
+    pub content_hash: String,
+    /// Lines of the whole content
+    pub line_count: usize,
+    /// Oldest version looked at, if the file has any
+    pub horizon: Option<i32>,
+    /// The blamed lines, in order
+    pub lines: Vec<BlameLine>,
+}
+
+/// Version a line was introduced in
+#[derive(Debug, Clone, Copy, FromRow)]
+struct Origin {
+    version: i32,
+    author_id: Uuid,
+    created_at: DateTime<Utc>,
+}
+
+/// Lines `first` to `last` of a `first-last` range, checked against the line count
+pub fn parse_range(range: &str, line_count: usize) -> Result<(usize, usize), AppError> {
+    let invalid = || AppError::Validation(format!("Invalid line range {}: the file has {} lines", range, line_count));
+    let (first, last) = range.split_once('-').ok_or_else(invalid)?;
+    let first: usize = first.trim().parse().map_err(|_| invalid())?;
+    let last: usize = last.trim().parse().map_err(|_| invalid())?;
+    if first == 0 || first > last || last > line_count {
+        return Err(invalid());
+    }
+    Ok((first, last))
+}
+
+/// Lines of `text` as compared by the diff
+fn line_keys(text: &str, ignore_whitespace: bool) -> Vec<String> {
+    text.lines()
+        .map(|line| if ignore_whitespace { line.split_whitespace().collect() } else { line.to_string() })
+        .collect()
+}
+
+/// For each line of `newer`, the line of `older` it is kept from, if any
+fn kept_lines(older: &[String], newer: &[String]) -> Vec<Option<usize>> {
+    // Only the differing middle needs the quadratic table
+    let prefix = older.iter().zip(newer).take_while(|(a, b)| a == b).count();
+    let suffix = older[prefix..]
+        .iter()
+        .rev()
+        .zip(newer[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    let mut kept: Vec<Option<usize>> = (0..prefix).map(Some).collect();
+    kept.resize(newer.len() - suffix, None);
+    kept.extend((older.len() - suffix..older.len()).map(Some));
+
+    let older_mid: Vec<&str> = older[prefix..older.len() - suffix].iter().map(String::as_str).collect();
+    let newer_mid: Vec<&str> = newer[prefix..newer.len() - suffix].iter().map(String::as_str).collect();
+    // A middle too large to diff counts as rewritten
+    if (older_mid.len() + 1).saturating_mul(newer_mid.len() + 1) <= MAX_WORD_DIFF_CELLS {
+        let (mut old, mut new) = (prefix, prefix);
+        for (op, _) in lcs_ops(&older_mid, &newer_mid) {
+            match op {
+                DiffOp::Equal => {
+                    kept[new] = Some(old);
+                    old += 1;
+                    new += 1;
+                }
+                DiffOp::Delete => old += 1,
+                DiffOp::Insert => new += 1,
+            }
+        }
+    }
+    kept
+}
+
+/// Attribute the pending lines `newer` does not keep from `older` to
+/// `origin`, and follow the others to their place in `older`
+fn follow(
+    pending: &mut Vec<(usize, usize)>,
+    origins: &mut [Option<Origin>],
+    older: &[String],
+    newer: &[String],
+    origin: Origin,
+) {
+    let kept = kept_lines(older, newer);
+    pending.retain_mut(|(index, position)| match kept[*position] {
+        Some(old) => {
+            *position = old;
+            true
+        }
+        None => {
+            origins[*index] = Some(origin);
+            false
+        }
+    });
+}
+
+impl FileBlame {
+    /// Blame of lines `range` of `content`, the current content of `file`,
+    /// looking back through at most `max_versions` versions
+    pub async fn build(
+        db: &sqlx::PgPool,
+        storage_root: &str,
+        file: &File,
+        content: &str,
+        range: Option<(usize, usize)>,
+        ignore_whitespace: bool,
+        max_versions: u32,
+    ) -> Result<Self, AppError> {
+        let line_count = content.lines().count();
+        let (first, last) = range.unwrap_or((1, line_count));
+
+        let versions = sqlx::query_as::<_, Origin>(
+            "SELECT version, author_id, created_at FROM file_versions WHERE file_id = $1 ORDER BY version DESC LIMIT $2",
+        )
+        .bind(file.id)
+        .bind(max_versions as i64 + 1)
+        .fetch_all(db)
+        .await
+        .map_err(AppError::Database)?;
+        // The version past the horizon is only there to tell what the oldest
+        // version looked at introduced
+        let beyond_horizon = versions.len() > max_versions as usize;
+        let looked_at = versions.len().min(max_versions as usize);
+        let horizon = looked_at.checked_sub(1).map(|oldest| versions[oldest].version);
+
+        // Lines still to attribute: their index in `origins` and their
+        // position in the text being looked at
+        let mut origins: Vec<Option<Origin>> = vec![None; last + 1 - first];
+        let mut pending: Vec<(usize, usize)> = (0..origins.len()).map(|index| (index, first - 1 + index)).collect();
+
+        let mut newer = line_keys(content, ignore_whitespace);
+        let mut newer_origin = Origin {
+            version: file.version,
+            author_id: file.last_modified_by.unwrap_or(file.created_by),
+            created_at: file.last_modified,
+        };
+        for segment in versions.chunks(BLAME_SEGMENT) {
+            if pending.is_empty() {
+                break;
+            }
+            let oldest = segment[segment.len() - 1].version;
+            let texts = FileVersion::materialize_texts(db, storage_root, file.id, oldest, segment[0].version).await?;
+            for (origin, (_, text)) in segment.iter().zip(texts.iter().rev()) {
+                let older = line_keys(text, ignore_whitespace);
+                follow(&mut pending, &mut origins, &older, &newer, newer_origin);
+                newer = older;
+                newer_origin = *origin;
+                if pending.is_empty() {
+                    break;
+                }
+            }
+        }
+
+        // What is left was there in the oldest version read, which only
+        // introduced it when it started the file
+        let root = match versions.last() {
+            None => Some(newer_origin),
+            Some(oldest) if oldest.version == 1 && !beyond_horizon => Some(newer_origin),
+            Some(_) => None,
+        };
+        for (index, _) in pending {
+            origins[index] = root;
+        }
+
+        let author_ids: Vec<Uuid> = origins
+            .iter()
+            .flatten()
+            .map(|origin| origin.author_id)
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect();
+        let authors: HashMap<Uuid, BlameAuthor> = sqlx::query_as::<_, BlameAuthor>(
+            "SELECT id, username, display_name, avatar_url FROM users WHERE id = ANY($1)",
+        )
+        .bind(&author_ids)
+        .fetch_all(db)
+        .await
+        .map_err(AppError::Database)?
+        .into_iter()
+        .map(|author| (author.id, author))
+        .collect();
+
+        let lines = origins
+            .into_iter()
+            .enumerate()
+            .map(|(index, origin)| BlameLine {
+                line: first + index,
+                version: origin.map(|origin| origin.version),
+                author: origin.and_then(|origin| authors.get(&origin.author_id).cloned()),
+                created_at: origin.map(|origin| origin.created_at),
+                earlier: origin.is_none(),
+            })
+            .collect();
+
+        Ok(Self {
+            file_id: file.id,
+            content_hash: file.content_hash.clone().unwrap_or_default(),
+            line_count,
+            horizon,
+            lines,
+        })
+    }
+
+    /// Blame of `file` as `params` ask, from the cache when its content has one
+    pub async fn load(state: &AppState, file: &File, params: &BlameParams) -> Result<Arc<Self>, AppError> {
+        let storage = &state.config.features.file_storage;
+        let content = String::from_utf8(file.read_bytes(&storage.local_path).await?)
+            .map_err(|_| AppError::Validation("File is not valid UTF-8 text".to_string()))?;
+        let range = params
+            .range
+            .as_deref()
+            .map(|range| parse_range(range, content.lines().count()))
+            .transpose()?;
+
+        let key = file.content_hash.clone().map(|content_hash| BlameKey {
+            file_id: file.id,
+            content_hash,
+            range,
+            ignore_whitespace: params.ignore_whitespace,
+        });
+        if let Some(cached) = key.as_ref().and_then(|key| state.blame_cache.get(key)) {
+            return Ok(cached);
+        }
+
+        let blame = Arc::new(
+            Self::build(
+                &state.db_pool,
+                &storage.local_path,
+                file,
+                &content,
+                range,
+                params.ignore_whitespace,
+                storage.blame_max_versions,
+            )
+            .await?,
+        );
+        if let Some(key) = key {
+            state.blame_cache.insert(key, blame.clone());
+        }
+        Ok(blame)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct BlameKey {
+    file_id: Uuid,
+    content_hash: String,
+    range: Option<(usize, usize)>,
+    ignore_whitespace: bool,
+}
+
+/// Blames by file, content and parameters
+#[derive(Default)]
+pub struct BlameCache {
+    entries: Mutex<(HashMap<BlameKey, Arc<FileBlame>>, VecDeque<BlameKey>)>,
+}
+
+impl BlameCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn get(&self, key: &BlameKey) -> Option<Arc<FileBlame>> {
+        let entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        entries.0.get(key).cloned()
+    }
+
+    /// Store a blame, evicting the oldest one when full
+    fn insert(&self, key: BlameKey, blame: Arc<FileBlame>) {
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        let (map, order) = &mut *entries;
+
+        if map.insert(key.clone(), blame).is_none() {
+            order.push_back(key);
+        }
+        while order.len() > BLAME_CACHE_CAPACITY {
+            if let Some(oldest) = order.pop_front() {
+                map.remove(&oldest);
+            }
+        }
+    }
+
+    /// Drop the blames of a file, once it has a new version
+    pub fn invalidate(&self, file_id: Uuid) {
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        let (map, order) = &mut *entries;
+        map.retain(|key, _| key.file_id != file_id);
+        order.retain(|key| key.file_id != file_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::UserRole;
+    use crate::testing::{add_collaborator, create_test_file, create_test_project, create_test_user, test_state, TestDb};
+
+    fn keys(lines: &[&str]) -> Vec<String> {
+        lines.iter().map(|line| line.to_string()).collect()
+    }
+
+    #[test]
+    fn test_kept_lines_follow_the_common_subsequence() {
+        let older = keys(&["a", "b", "c", "d", "e"]);
+        let newer = keys(&["a", "x", "c", "e", "b"]);
+        assert_eq!(kept_lines(&older, &newer), vec![Some(0), None, Some(2), Some(4), None]);
+
+        assert_eq!(kept_lines(&keys(&["a"]), &keys(&["a", "a"])), vec![Some(0), None]);
+        assert_eq!(kept_lines(&[], &keys(&["a"])), vec![None]);
+        assert_eq!(line_keys("  \\item one \n\t\\item  two", true), keys(&["\\itemone", "\\itemtwo"]));
+
+        assert_eq!(parse_range("120-180", 200).unwrap(), (120, 180));
+        for invalid in ["0-3", "5-4", "1-201", "12", "a-b"] {
+            assert!(parse_range(invalid, 200).is_err(), "{}", invalid);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_blame_attributes_lines_through_edits() {
+        let Some(db) = TestDb::start().await else { return };
+        let state = test_state(&db).await;
+        let storage_root = state.config.features.file_storage.local_path.clone();
+        let alice = create_test_user(&db.pool).await;
+        let bob = create_test_user(&db.pool).await;
+        let carol = create_test_user(&db.pool).await;
+        let project = create_test_project(&db.pool, &alice, false).await;
+        add_collaborator(&db.pool, &project, &bob, UserRole::Editor).await;
+        add_collaborator(&db.pool, &project, &carol, UserRole::Editor).await;
+        let mut file = create_test_file(&db.pool, &project, &alice).await;
+
+        let script = [
+            (&alice, "Intro\nMethods\nResults\n"),
+            (&bob, "Intro\nMethods, revised\nResults\n"),
+            (&alice, "Intro\nMethods, revised\nResults\nOutlook\n"),
+            (&carol, "Intro\nMethods, rewritten\nResults\nOutlook\n"),
+            (&bob, "  Intro\nMethods, rewritten\nResults\nOutlook\nThanks\n"),
+            (&alice, "  Intro\nMethods, rewritten\nOutlook\nThanks\n"),
+        ];
+        for (author, content) in script {
+            file = file.update_content(&db.pool, &storage_root, content.to_string(), author.id).await.unwrap();
+            FileVersion::create(&db.pool, file.id, file.version, content, author.id, "save").await.unwrap();
+        }
+        let content = file.content.clone();
+        let blame = |range, ignore_whitespace, max_versions| {
+            FileBlame::build(&db.pool, &storage_root, &file, &content, range, ignore_whitespace, max_versions)
+        };
+        let attribution = |blame: &FileBlame| {
+            blame
+                .lines
+                .iter()
+                .map(|line| (line.version, line.author.as_ref().map(|author| author.id)))
+                .collect::<Vec<_>>()
+        };
+
+        // The created file is version 1, the script versions 2 to 7
+        let full = blame(None, false, 200).await.unwrap();
+        assert_eq!(full.line_count, 4);
+        assert_eq!(full.horizon, Some(2));
+        assert_eq!(
+            attribution(&full),
+            vec![(Some(6), Some(bob.id)), (Some(5), Some(carol.id)), (Some(4), Some(alice.id)), (Some(6), Some(bob.id))]
+        );
+        assert_eq!(full.lines[1].author.as_ref().unwrap().username, carol.username);
+
+        // Bob only reindented the first line, which is then as old as the
+        // first recorded version; that one did not start the file
+        let whitespace = blame(None, true, 200).await.unwrap();
+        assert_eq!(whitespace.lines[0].version, None);
+        assert!(whitespace.lines[0].earlier);
+        assert_eq!(attribution(&whitespace)[1..], attribution(&full)[1..]);
+
+        let window = blame(Some((2, 3)), false, 200).await.unwrap();
+        assert_eq!(window.lines.iter().map(|line| line.line).collect::<Vec<_>>(), vec![2, 3]);
+        assert_eq!(attribution(&window), attribution(&full)[1..3]);
+
+        // Past the horizon only the newer lines keep their version
+        let shallow = blame(None, false, 2).await.unwrap();
+        assert_eq!(shallow.horizon, Some(6));
+        assert_eq!(attribution(&shallow)[0], (Some(6), Some(bob.id)));
+        assert!(shallow.lines[1..3].iter().all(|line| line.earlier && line.author.is_none()));
+        assert_eq!(attribution(&shallow)[3], (Some(6), Some(bob.id)));
+
+        // Content saved without a version belongs to the last save
+        let formatted = file
+            .update_content(&db.pool, &storage_root, format!("{}Appendix\n", content), carol.id)
+            .await
+            .unwrap();
+        let blame = FileBlame::build(&db.pool, &storage_root, &formatted, &formatted.content, None, false, 200)
+            .await
+            .unwrap();
+        assert_eq!(blame.lines[4].version, Some(formatted.version));
+        assert_eq!(blame.lines[4].author.as_ref().unwrap().id, carol.id);
+        assert_eq!(blame.lines[3].version, Some(6));
+    }
+}
@@ -0,0 +1,932 @@
+//! Stored content of file versions
+//!
+//! A version of a text file is kept either as a full snapshot or as a delta
+//! against the version before it, serialized into the row's `changes`. Deltas
+//! are line based: runs of lines copied from the previous version and the
+//! text inserted between them. A snapshot is written instead whenever the
+//! chain of deltas since the last snapshot would grow past the checkpoint
+//! policy's length or size, so reconstructing a version applies a bounded
+//! number of deltas. Versions of binary files always keep their full content
+//! in the blob store, as do versions recorded before deltas existed.
+//!
+//! [`FileVersion::materialize`] walks from the nearest snapshot to the
+//! requested version and checks the result against the recorded hash. The
+//! `version_compaction` task re-encodes versions older than
+//! `ARCHIVE_VERSION_AGE` with sparser checkpoints, trading slower reads of
+//! old history for less storage.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::{FromRow, Postgres};
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::time::Duration;
+use uuid::Uuid;
+
+use super::blob::{blob_storage_path, Blob};
+use super::file::{FileVersion, FILE_VERSION_COLUMNS};
+use super::integrity::{IntegrityIncident, ReadPath};
+use crate::error::AppError;
+use crate::server::AppState;
+
+/// Name of the background compaction
+pub const VERSION_COMPACTION_TASK: &str = "version_compaction";
+
+/// How often old versions are compacted
+const VERSION_COMPACTION_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Versions older than this are re-encoded with the archive checkpoints
+pub const ARCHIVE_VERSION_AGE: chrono::Duration = chrono::Duration::days(30);
+
+/// Files compacted per batch
+const COMPACTION_BATCH_SIZE: i64 = 50;
+
+/// Versions of a file loaded at a time while compacting it
+const COMPACTION_PAGE_SIZE: i64 = 64;
+
+/// Shortest run of copied lines, in bytes, worth a copy instead of an insert
+const MIN_COPY_BYTES: usize = 16;
+
+/// Earlier occurrences of a line considered when looking for a run to copy
+const MAX_COPY_CANDIDATES: usize = 32;
+
+/// When a version is stored as a snapshot instead of a delta
+#[derive(Debug, Clone, Copy)]
+pub struct CheckpointPolicy {
+    /// A snapshot at least every this many versions
+    pub snapshot_interval: i32,
+    /// Largest total size of the deltas since the last snapshot
+    pub max_chain_bytes: i64,
+}
+
+/// Checkpoints of recent versions, which are read the most
+pub const LIVE_CHECKPOINTS: CheckpointPolicy = CheckpointPolicy {
+    snapshot_interval: 16,
+    max_chain_bytes: 256 * 1024,
+};
+
+/// Checkpoints of versions older than `ARCHIVE_VERSION_AGE`
+pub const ARCHIVE_CHECKPOINTS: CheckpointPolicy = CheckpointPolicy {
+    snapshot_interval: 64,
+    max_chain_bytes: 1024 * 1024,
+};
+
+/// How the content of a version is stored
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+pub enum VersionEncoding {
+    /// The full content in the row
+    #[sqlx(rename = "snapshot")]
+    #[serde(rename = "snapshot")]
+    Snapshot,
+    /// A delta against the previous version in `changes`
+    #[sqlx(rename = "delta")]
+    #[serde(rename = "delta")]
+    Delta,
+    /// Only the blob store, for binary files and versions from before deltas
+    #[sqlx(rename = "blob")]
+    #[serde(rename = "blob")]
+    Blob,
+}
+
+/// One step of a delta
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum DeltaOp {
+    /// Lines `start..start + count` of the previous version
+    Copy { start: usize, count: usize },
+    Insert(String),
+}
+
+#[derive(Debug, FromRow)]
+struct StoredVersion {
+    id: Uuid,
+    version: i32,
+    content_hash: String,
+    changes: Option<String>,
+    encoding: VersionEncoding,
+    snapshot: Option<Vec<u8>>,
+    chain_length: i32,
+    chain_bytes: i64,
+    archived: bool,
+    created_at: DateTime<Utc>,
+}
+
+const STORED_VERSION_COLUMNS: &str =
+    "id, version, content_hash, changes, encoding, snapshot, chain_length, chain_bytes, archived, created_at";
+
+/// Content of a version as it is to be written
+#[derive(Debug, PartialEq)]
+struct EncodedVersion {
+    encoding: VersionEncoding,
+    changes: Option<String>,
+    snapshot: Option<Vec<u8>>,
+    chain_length: i32,
+    chain_bytes: i64,
+}
+
+impl EncodedVersion {
+    fn snapshot(content: &str) -> Self {
+        Self {
+            encoding: VersionEncoding::Snapshot,
+            changes: None,
+            snapshot: Some(content.as_bytes().to_vec()),
+            chain_length: 0,
+            chain_bytes: 0,
+        }
+    }
+
+    fn blob() -> Self {
+        Self { encoding: VersionEncoding::Blob, changes: None, snapshot: None, chain_length: 0, chain_bytes: 0 }
+    }
+}
+
+/// Encode `content` following a version with the given content and chain,
+/// or as a snapshot when there is no text to base a delta on
+fn encode(previous: Option<(&str, i32, i64)>, content: &str, policy: CheckpointPolicy) -> EncodedVersion {
+    let Some((base, chain_length, chain_bytes)) = previous else {
+        return EncodedVersion::snapshot(content);
+    };
+    if chain_length + 1 >= policy.snapshot_interval {
+        return EncodedVersion::snapshot(content);
+    }
+
+    let delta = serde_json::to_string(&encode_delta(base, content)).expect("delta serializes");
+    let delta_bytes = delta.len() as i64;
+    if delta.len() >= content.len() || chain_bytes + delta_bytes > policy.max_chain_bytes {
+        return EncodedVersion::snapshot(content);
+    }
+
+    EncodedVersion {
+        encoding: VersionEncoding::Delta,
+        changes: Some(delta),
+        snapshot: None,
+        chain_length: chain_length + 1,
+        chain_bytes: chain_bytes + delta_bytes,
+    }
+}
+
+/// Line-based delta turning `base` into `target`
+///
+/// Each line of the target is copied from the longest run of equal lines in
+/// the base, preferring to continue where the previous copy ended, so edits
+/// in place, insertions, deletions and moved blocks all stay small.
+fn encode_delta(base: &str, target: &str) -> Vec<DeltaOp> {
+    let base: Vec<&str> = base.split_inclusive('\n').collect();
+    let target: Vec<&str> = target.split_inclusive('\n').collect();
+
+    let mut starts: HashMap<&str, Vec<usize>> = HashMap::new();
+    for (index, &line) in base.iter().enumerate() {
+        starts.entry(line).or_default().push(index);
+    }
+
+    let run_length = |start: usize, from: usize| {
+        base[start..].iter().zip(&target[from..]).take_while(|(a, b)| a == b).count()
+    };
+
+    let mut ops = Vec::new();
+    let mut next = 0;
+    let mut index = 0;
+    while index < target.len() {
+        let best = starts.get(target[index]).and_then(|positions| {
+            let after = positions.partition_point(|&start| start < next);
+            positions[after..]
+                .iter()
+                .take(MAX_COPY_CANDIDATES)
+                .chain(positions[..after].iter().rev().take(MAX_COPY_CANDIDATES))
+                .map(|&start| (start, run_length(start, index)))
+                .max_by_key(|&(start, count)| (count, start == next))
+        });
+
+        match best {
+            Some((start, count))
+                if base[start..start + count].iter().map(|line| line.len()).sum::<usize>() >= MIN_COPY_BYTES =>
+            {
+                ops.push(DeltaOp::Copy { start, count });
+                index += count;
+                next = start + count;
+            }
+            _ => {
+                match ops.last_mut() {
+                    Some(DeltaOp::Insert(text)) => text.push_str(target[index]),
+                    _ => ops.push(DeltaOp::Insert(target[index].to_string())),
+                }
+                index += 1;
+            }
+        }
+    }
+
+    ops
+}
+
+/// Apply a delta to the previous version's content
+fn apply_delta(base: &str, ops: &[DeltaOp]) -> Option<String> {
+    let base: Vec<&str> = base.split_inclusive('\n').collect();
+    let mut content = String::new();
+    for op in ops {
+        match op {
+            DeltaOp::Copy { start, count } => {
+                let lines = base.get(*start..start.checked_add(*count)?)?;
+                content.extend(lines.iter().copied());
+            }
+            DeltaOp::Insert(text) => content.push_str(text),
+        }
+    }
+    Some(content)
+}
+
+/// Content of a version given the content of the one before it, when the row holds it
+fn decode(previous: Option<&str>, row: &StoredVersion) -> Result<Option<String>, AppError> {
+    let corrupted = || AppError::ContentCorrupted(format!("version {} cannot be reconstructed", row.version));
+    match row.encoding {
+        VersionEncoding::Blob => Ok(None),
+        VersionEncoding::Snapshot => {
+            let bytes = row.snapshot.clone().ok_or_else(corrupted)?;
+            String::from_utf8(bytes).map(Some).map_err(|_| corrupted())
+        }
+        VersionEncoding::Delta => {
+            let ops: Vec<DeltaOp> =
+                serde_json::from_str(row.changes.as_deref().ok_or_else(corrupted)?).map_err(|_| corrupted())?;
+            apply_delta(previous.ok_or_else(corrupted)?, &ops).map(Some).ok_or_else(corrupted)
+        }
+    }
+}
+
+fn content_hash(bytes: &[u8]) -> String {
+    hex::encode(Sha256::digest(bytes))
+}
+
+/// The rows needed to reconstruct versions `from` to `to`: the nearest
+/// version at or before `from` that is not a delta, and every version after
+/// that up to `to`
+async fn load_chain(
+    conn: &mut sqlx::PgConnection,
+    file_id: Uuid,
+    from: i32,
+    to: i32,
+) -> Result<Vec<StoredVersion>, AppError> {
+    let chain = sqlx::query_as::<_, StoredVersion>(&format!(
+        r#"
+        SELECT {STORED_VERSION_COLUMNS} FROM file_versions
+        WHERE file_id = $1 AND version <= $3
+          AND version >= COALESCE((
+            SELECT MAX(version) FROM file_versions
+            WHERE file_id = $1 AND version <= $2 AND encoding <> 'delta'
+          ), 0)
+        ORDER BY version
+        "#
+    ))
+    .bind(file_id)
+    .bind(from)
+    .bind(to)
+    .fetch_all(&mut *conn)
+    .await
+    .map_err(AppError::Database)?;
+
+    if chain.last().map(|row| row.version) != Some(to) {
+        return Err(AppError::NotFound {
+            entity: "File version".to_string(),
+            id: format!("{} of file {}", to, file_id),
+        });
+    }
+    Ok(chain)
+}
+
+/// Reconstruct the text of the last version of a chain starting at a snapshot
+fn replay(chain: &[StoredVersion]) -> Result<Option<String>, AppError> {
+    let mut content = None;
+    for row in chain {
+        content = decode(content.as_deref(), row)?;
+    }
+    Ok(content)
+}
+
+/// Content of a version kept only in the blob store, or as the file's current content
+async fn read_blob_version(
+    db: &sqlx::PgPool,
+    storage_root: &str,
+    file_id: Uuid,
+    row: &StoredVersion,
+) -> Result<Vec<u8>, AppError> {
+    let storage_path = sqlx::query_scalar::<_, Option<String>>("SELECT storage_path FROM blobs WHERE hash = $1")
+        .bind(&row.content_hash)
+        .fetch_optional(db)
+        .await
+        .map_err(AppError::Database)?
+        .flatten();
+    if let Some(storage_path) = storage_path {
+        return tokio::fs::read(Path::new(storage_root).join(storage_path))
+            .await
+            .map_err(|e| AppError::Storage(format!("Failed to read version {} of file {}: {}", row.version, file_id, e)));
+    }
+
+    // Versions of text files recorded before deltas only kept their hash
+    let current = sqlx::query_scalar::<_, String>("SELECT content FROM files WHERE id = $1 AND content_hash = $2")
+        .bind(file_id)
+        .bind(&row.content_hash)
+        .fetch_optional(db)
+        .await
+        .map_err(AppError::Database)?;
+    current.map(String::into_bytes).ok_or_else(|| AppError::NotFound {
+        entity: "File version content".to_string(),
+        id: format!("{} of file {}", row.version, file_id),
+    })
+}
+
+/// Fail with `ContentCorrupted`, and record the incident, when a reconstructed version does not match its hash
+async fn verify_version(db: &sqlx::PgPool, file_id: Uuid, row: &StoredVersion, bytes: &[u8]) -> Result<(), AppError> {
+    let actual = content_hash(bytes);
+    if actual.eq_ignore_ascii_case(&row.content_hash) {
+        return Ok(());
+    }
+
+    tracing::error!(
+        "Version {} of file {} does not match its hash: expected {}, found {}",
+        row.version,
+        file_id,
+        row.content_hash,
+        actual
+    );
+    if let Err(e) = IntegrityIncident::record(db, Some(file_id), None, &row.content_hash, &actual, ReadPath::History).await {
+        tracing::error!("Failed to record integrity incident of file {}: {}", file_id, e);
+    }
+
+    Err(AppError::ContentCorrupted(format!(
+        "version {} of file {} does not match its recorded hash",
+        row.version, file_id
+    )))
+}
+
+impl FileVersion {
+    /// Record a version of a text file, as a delta against the previous
+    /// version unless the checkpoint policy calls for a snapshot
+    pub async fn create<'a>(
+        db: impl sqlx::Acquire<'a, Database = Postgres>,
+        file_id: Uuid,
+        version: i32,
+        content: &str,
+        author_id: Uuid,
+        message: &str,
+    ) -> Result<Self, AppError> {
+        let content_hash = content_hash(content.as_bytes());
+        let mut tx = db.begin().await.map_err(AppError::Database)?;
+
+        // Versions of a file are encoded one at a time against the newest one
+        sqlx::query("SELECT 1 FROM files WHERE id = $1 FOR UPDATE")
+            .bind(file_id)
+            .execute(&mut *tx)
+            .await
+            .map_err(AppError::Database)?;
+        let previous = sqlx::query_as::<_, StoredVersion>(&format!(
+            "SELECT {STORED_VERSION_COLUMNS} FROM file_versions WHERE file_id = $1 ORDER BY version DESC LIMIT 1"
+        ))
+        .bind(file_id)
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(AppError::Database)?;
+
+        let encoded = match previous {
+            Some(previous) if previous.version >= version => {
+                return Err(AppError::Conflict(format!(
+                    "File {} already has version {}, newer than {}",
+                    file_id, previous.version, version
+                )));
+            }
+            Some(previous) if previous.encoding != VersionEncoding::Blob => {
+                let chain = load_chain(&mut tx, file_id, previous.version, previous.version).await?;
+                match replay(&chain)? {
+                    // A damaged chain is not extended; the snapshot starts a sound one
+                    Some(base) if content_hash(base.as_bytes()) == previous.content_hash => encode(
+                        Some((base.as_str(), previous.chain_length, previous.chain_bytes)),
+                        content,
+                        LIVE_CHECKPOINTS,
+                    ),
+                    _ => EncodedVersion::snapshot(content),
+                }
+            }
+            _ => EncodedVersion::snapshot(content),
+        };
+
+        let file_version = insert(&mut tx, file_id, version, &content_hash, &encoded, author_id, message).await?;
+        Blob::acquire(&mut tx, &content_hash, content.len() as i64, None).await?;
+        tx.commit().await.map_err(AppError::Database)?;
+
+        Ok(file_version)
+    }
+
+    /// Record a version of a binary file, always as its full content in the blob store
+    pub async fn create_binary<'a>(
+        db: impl sqlx::Acquire<'a, Database = Postgres>,
+        storage_root: &str,
+        file_id: Uuid,
+        version: i32,
+        bytes: &[u8],
+        author_id: Uuid,
+        message: &str,
+    ) -> Result<Self, AppError> {
+        let content_hash = content_hash(bytes);
+        let mut tx = db.begin().await.map_err(AppError::Database)?;
+
+        let file_version =
+            insert(&mut tx, file_id, version, &content_hash, &EncodedVersion::blob(), author_id, message).await?;
+        let storage_path = blob_storage_path(&content_hash);
+        let acquired = Blob::acquire(&mut tx, &content_hash, bytes.len() as i64, Some(&storage_path)).await?;
+
+        if let Some(storage_path) = acquired.blob.storage_path.as_ref().filter(|_| acquired.needs_content) {
+            let target = Path::new(storage_root).join(storage_path);
+            if let Some(parent) = target.parent() {
+                tokio::fs::create_dir_all(parent)
+                    .await
+                    .map_err(|e| AppError::Storage(format!("Failed to prepare blob directory: {}", e)))?;
+            }
+            tokio::fs::write(&target, bytes)
+                .await
+                .map_err(|e| AppError::Storage(format!("Failed to save file version: {}", e)))?;
+        }
+
+        tx.commit().await.map_err(AppError::Database)?;
+
+        Ok(file_version)
+    }
+
+    /// Content of a version, reconstructed from the nearest snapshot and
+    /// checked against the version's hash
+    pub async fn materialize(
+        db: &sqlx::PgPool,
+        storage_root: &str,
+        file_id: Uuid,
+        version: i32,
+    ) -> Result<Vec<u8>, AppError> {
+        let mut conn = db.acquire().await.map_err(AppError::Database)?;
+        let chain = load_chain(&mut conn, file_id, version, version).await?;
+        drop(conn);
+
+        let row = chain.last().expect("chain ends at the version");
+        let bytes = match replay(&chain)? {
+            Some(content) => content.into_bytes(),
+            None => read_blob_version(db, storage_root, file_id, &chain[0]).await?,
+        };
+        verify_version(db, file_id, row, &bytes).await?;
+
+        Ok(bytes)
+    }
+
+    /// Text of the versions from `from` to `to`, oldest first, each checked
+    /// against its hash
+    ///
+    /// The chain is replayed once for the whole range, so reading a run of
+    /// versions costs about as much as reading the newest of them. Fails
+    /// with `Validation` when a version is not UTF-8 text.
+    pub async fn materialize_texts(
+        db: &sqlx::PgPool,
+        storage_root: &str,
+        file_id: Uuid,
+        from: i32,
+        to: i32,
+    ) -> Result<Vec<(i32, String)>, AppError> {
+        let mut conn = db.acquire().await.map_err(AppError::Database)?;
+        let chain = load_chain(&mut conn, file_id, from, to).await?;
+        drop(conn);
+
+        let mut texts = Vec::new();
+        let mut previous: Option<String> = None;
+        for row in &chain {
+            let content = match decode(previous.as_deref(), row)? {
+                Some(content) => content,
+                None => String::from_utf8(read_blob_version(db, storage_root, file_id, row).await?).map_err(|_| {
+                    AppError::Validation(format!("Version {} of file {} is not UTF-8 text", row.version, file_id))
+                })?,
+            };
+            if row.version >= from {
+                verify_version(db, file_id, row, content.as_bytes()).await?;
+                texts.push((row.version, content.clone()));
+            }
+            previous = Some(content);
+        }
+        Ok(texts)
+    }
+}
+
+async fn insert(
+    conn: &mut sqlx::PgConnection,
+    file_id: Uuid,
+    version: i32,
+    content_hash: &str,
+    encoded: &EncodedVersion,
+    author_id: Uuid,
+    message: &str,
+) -> Result<FileVersion, AppError> {
+    sqlx::query_as::<_, FileVersion>(&format!(
+        r#"
+        INSERT INTO file_versions
+            (file_id, version, content_hash, changes, change_summary, author_id,
+             encoding, snapshot, chain_length, chain_bytes)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+        RETURNING {FILE_VERSION_COLUMNS}
+        "#
+    ))
+    .bind(file_id)
+    .bind(version)
+    .bind(content_hash)
+    .bind(&encoded.changes)
+    .bind(message)
+    .bind(author_id)
+    .bind(encoded.encoding)
+    .bind(&encoded.snapshot)
+    .bind(encoded.chain_length)
+    .bind(encoded.chain_bytes)
+    .fetch_one(conn)
+    .await
+    .map_err(AppError::Database)
+}
+
+/// Versions re-encoded and archived by a compaction
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CompactionReport {
+    pub files: u64,
+    pub versions_archived: u64,
+    pub versions_rewritten: u64,
+}
+
+/// Re-encode the versions of one file, archiving those created before `cutoff`
+///
+/// Archived versions are left as they are. Older ones are re-encoded with the
+/// archive checkpoints and recent ones with the live checkpoints, continuing
+/// the chains the older ones now end in.
+async fn compact_file(
+    db: &sqlx::PgPool,
+    file_id: Uuid,
+    cutoff: DateTime<Utc>,
+    report: &mut CompactionReport,
+) -> Result<(), AppError> {
+    let mut tx = db.begin().await.map_err(AppError::Database)?;
+    sqlx::query("SELECT 1 FROM files WHERE id = $1 FOR UPDATE")
+        .bind(file_id)
+        .execute(&mut *tx)
+        .await
+        .map_err(AppError::Database)?;
+
+    // Content of the previous version as stored and its chain as re-encoded
+    let mut previous: Option<(String, i32, i64)> = None;
+    let mut after = i32::MIN;
+    loop {
+        let page = sqlx::query_as::<_, StoredVersion>(&format!(
+            r#"
+            SELECT {STORED_VERSION_COLUMNS} FROM file_versions
+            WHERE file_id = $1 AND version > $2
+            ORDER BY version
+            LIMIT $3
+            "#
+        ))
+        .bind(file_id)
+        .bind(after)
+        .bind(COMPACTION_PAGE_SIZE)
+        .fetch_all(&mut *tx)
+        .await
+        .map_err(AppError::Database)?;
+        let Some(last) = page.last() else { break };
+        after = last.version;
+
+        for row in page {
+            let content = decode(previous.as_ref().map(|(content, ..)| content.as_str()), &row)?;
+            let Some(content) = content else {
+                if !row.archived && row.created_at < cutoff {
+                    sqlx::query("UPDATE file_versions SET archived = true WHERE id = $1")
+                        .bind(row.id)
+                        .execute(&mut *tx)
+                        .await
+                        .map_err(AppError::Database)?;
+                    report.versions_archived += 1;
+                }
+                previous = None;
+                continue;
+            };
+            if content_hash(content.as_bytes()) != row.content_hash {
+                return Err(AppError::ContentCorrupted(format!(
+                    "version {} of file {} does not match its recorded hash",
+                    row.version, file_id
+                )));
+            }
+
+            let chain = previous.as_ref().map(|(base, length, bytes)| (base.as_str(), *length, *bytes));
+            let (encoded, archive) = if row.archived {
+                let kept = EncodedVersion {
+                    encoding: row.encoding,
+                    changes: row.changes.clone(),
+                    snapshot: row.snapshot.clone(),
+                    chain_length: row.chain_length,
+                    chain_bytes: row.chain_bytes,
+                };
+                (kept, false)
+            } else if row.created_at < cutoff {
+                (encode(chain, &content, ARCHIVE_CHECKPOINTS), true)
+            } else {
+                (encode(chain, &content, LIVE_CHECKPOINTS), false)
+            };
+
+            let unchanged = encoded.encoding == row.encoding
+                && encoded.chain_length == row.chain_length
+                && encoded.chain_bytes == row.chain_bytes
+                && (encoded.encoding != VersionEncoding::Delta || encoded.changes == row.changes);
+            if !unchanged || archive {
+                sqlx::query(
+                    r#"
+                    UPDATE file_versions
+                    SET encoding = $2, changes = $3, snapshot = $4, chain_length = $5, chain_bytes = $6,
+                        archived = archived OR $7
+                    WHERE id = $1
+                    "#
+                )
+                .bind(row.id)
+                .bind(encoded.encoding)
+                .bind(&encoded.changes)
+                .bind(&encoded.snapshot)
+                .bind(encoded.chain_length)
+                .bind(encoded.chain_bytes)
+                .bind(archive)
+                .execute(&mut *tx)
+                .await
+                .map_err(AppError::Database)?;
+                if !unchanged {
+                    report.versions_rewritten += 1;
+                }
+                if archive {
+                    report.versions_archived += 1;
+                }
+            }
+
+            previous = Some((content, encoded.chain_length, encoded.chain_bytes));
+        }
+    }
+
+    tx.commit().await.map_err(AppError::Database)?;
+    report.files += 1;
+    Ok(())
+}
+
+/// Compact the history of every file with unarchived versions created before `cutoff`
+pub async fn compact(db: &sqlx::PgPool, cutoff: DateTime<Utc>) -> Result<CompactionReport, AppError> {
+    let mut report = CompactionReport::default();
+    // Files that failed stay unarchived; skip them for the rest of the run
+    let mut failed: HashSet<Uuid> = HashSet::new();
+
+    loop {
+        let skipped: Vec<Uuid> = failed.iter().copied().collect();
+        let batch = sqlx::query_scalar::<_, Uuid>(
+            r#"
+            SELECT DISTINCT file_id FROM file_versions
+            WHERE archived = false AND created_at < $1 AND NOT (file_id = ANY($2))
+            LIMIT $3
+            "#
+        )
+        .bind(cutoff)
+        .bind(&skipped)
+        .bind(COMPACTION_BATCH_SIZE)
+        .fetch_all(db)
+        .await
+        .map_err(AppError::Database)?;
+        if batch.is_empty() {
+            break;
+        }
+
+        for file_id in batch {
+            if let Err(e) = compact_file(db, file_id, cutoff, &mut report).await {
+                tracing::warn!("Failed to compact the versions of file {}: {}", file_id, e);
+                failed.insert(file_id);
+            }
+        }
+    }
+
+    Ok(report)
+}
+
+/// Re-encodes old file versions with sparser snapshots
+pub struct VersionCompactionTask;
+
+impl crate::tasks::PeriodicTask for VersionCompactionTask {
+    fn name(&self) -> &'static str {
+        VERSION_COMPACTION_TASK
+    }
+
+    fn interval(&self) -> Duration {
+        VERSION_COMPACTION_INTERVAL
+    }
+
+    fn run<'a>(&'a self, state: &'a AppState) -> futures::future::BoxFuture<'a, Result<(), AppError>> {
+        Box::pin(async move {
+            let report = compact(&state.db_pool, Utc::now() - ARCHIVE_VERSION_AGE).await?;
+            tracing::info!(
+                "Version compaction archived {} versions of {} files, rewriting {}",
+                report.versions_archived,
+                report.files,
+                report.versions_rewritten
+            );
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::ContentType;
+    use crate::models::file::File;
+    use crate::models::project_limits::ProjectLimits;
+    use crate::testing::{create_test_file, create_test_project, create_test_user, TestDb};
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    /// A random edit of a LaTeX-like text: lines replaced, inserted, removed or moved
+    fn edit(rng: &mut StdRng, content: &str) -> String {
+        let mut lines: Vec<String> = content.split_inclusive('\n').map(str::to_string).collect();
+        for _ in 0..rng.gen_range(1..4) {
+            let at = rng.gen_range(0..=lines.len());
+            let line = format!("\\item change {} {}\n", rng.gen::<u32>(), "x".repeat(rng.gen_range(0..40)));
+            match rng.gen_range(0..4) {
+                0 if at < lines.len() => lines[at] = line,
+                1 if at < lines.len() => {
+                    lines.remove(at);
+                }
+                2 if at + 3 < lines.len() => {
+                    let moved: Vec<String> = lines.drain(at..at + 3).collect();
+                    let tail = lines.split_off(rng.gen_range(0..=lines.len()));
+                    lines.extend(moved);
+                    lines.extend(tail);
+                }
+                _ => lines.insert(at, line),
+            }
+        }
+        // Now and then without a final newline
+        let mut content = lines.concat();
+        if rng.gen_bool(0.1) {
+            content.push_str("no newline");
+        }
+        content
+    }
+
+    #[test]
+    fn test_delta_round_trips_random_edits() {
+        let mut rng = StdRng::seed_from_u64(157);
+        let mut content: String = (0..200).map(|i| format!("Line {} of the chapter\n", i)).collect();
+        for _ in 0..300 {
+            let next = edit(&mut rng, &content);
+            let ops = encode_delta(&content, &next);
+            assert_eq!(apply_delta(&content, &ops).as_deref(), Some(next.as_str()));
+            content = next;
+        }
+
+        assert_eq!(apply_delta("", &encode_delta("", "new\n")).as_deref(), Some("new\n"));
+        assert_eq!(apply_delta("old\n", &encode_delta("old\n", "")).as_deref(), Some(""));
+        assert_eq!(apply_delta("a\n", &[DeltaOp::Copy { start: 0, count: 2 }]), None);
+    }
+
+    #[test]
+    fn test_checkpoints_bound_the_chain() {
+        let base: String = (0..100).map(|i| format!("Line {} of the chapter\n", i)).collect();
+        let edited = base.replace("Line 50 ", "Line fifty ");
+
+        let delta = encode(Some((base.as_str(), 0, 0)), &edited, LIVE_CHECKPOINTS);
+        assert_eq!(delta.encoding, VersionEncoding::Delta);
+        assert_eq!(delta.chain_length, 1);
+        assert!(delta.chain_bytes > 0 && (delta.chain_bytes as usize) < edited.len() / 10);
+
+        let at_interval = encode(Some((base.as_str(), LIVE_CHECKPOINTS.snapshot_interval - 1, 0)), &edited, LIVE_CHECKPOINTS);
+        assert_eq!(at_interval, EncodedVersion::snapshot(&edited));
+        let too_large = encode(Some((base.as_str(), 1, LIVE_CHECKPOINTS.max_chain_bytes)), &edited, LIVE_CHECKPOINTS);
+        assert_eq!(too_large, EncodedVersion::snapshot(&edited));
+        // A rewrite is cheaper as a snapshot than as a delta
+        let rewritten = encode(Some((base.as_str(), 0, 0)), "Something else entirely\n", LIVE_CHECKPOINTS);
+        assert_eq!(rewritten.encoding, VersionEncoding::Snapshot);
+    }
+
+    #[tokio::test]
+    async fn test_materialize_returns_every_saved_version() {
+        let Some(db) = TestDb::start().await else { return };
+        let storage_dir = tempfile::tempdir().unwrap();
+        let storage_root = storage_dir.path().to_str().unwrap();
+        let owner = create_test_user(&db.pool).await;
+        let project = create_test_project(&db.pool, &owner, false).await;
+        let file = create_test_file(&db.pool, &project, &owner).await;
+
+        let mut rng = StdRng::seed_from_u64(57);
+        let mut content: String = (0..300).map(|i| format!("Paragraph {} with some text\n", i)).collect();
+        let mut saved = Vec::new();
+        for version in 1..=60 {
+            content = edit(&mut rng, &content);
+            FileVersion::create(&db.pool, file.id, version, &content, owner.id, "save").await.unwrap();
+            saved.push(content.clone());
+        }
+
+        let encodings = sqlx::query_as::<_, (VersionEncoding, i32)>(
+            "SELECT encoding, chain_length FROM file_versions WHERE file_id = $1 ORDER BY version"
+        )
+        .bind(file.id)
+        .fetch_all(&db.pool)
+        .await
+        .unwrap();
+        assert_eq!(encodings[0].0, VersionEncoding::Snapshot);
+        assert!(encodings.iter().filter(|(encoding, _)| *encoding == VersionEncoding::Delta).count() > 40);
+        assert!(encodings.iter().all(|(_, length)| *length < LIVE_CHECKPOINTS.snapshot_interval));
+
+        for (index, expected) in saved.iter().enumerate() {
+            let bytes = FileVersion::materialize(&db.pool, storage_root, file.id, index as i32 + 1).await.unwrap();
+            assert_eq!(bytes, expected.as_bytes(), "version {}", index + 1);
+        }
+
+        assert!(matches!(
+            FileVersion::materialize(&db.pool, storage_root, file.id, 61).await,
+            Err(AppError::NotFound { .. })
+        ));
+        assert!(matches!(
+            FileVersion::create(&db.pool, file.id, 60, "again", owner.id, "save").await,
+            Err(AppError::Conflict(_))
+        ));
+
+        // Compacting everything as old keeps every version's content
+        let report = compact(&db.pool, Utc::now() + chrono::Duration::hours(1)).await.unwrap();
+        assert_eq!((report.files, report.versions_archived), (1, 60));
+        assert!(report.versions_rewritten > 0);
+        let snapshots = sqlx::query_scalar::<_, i64>(
+            "SELECT COUNT(*) FROM file_versions WHERE file_id = $1 AND encoding = 'snapshot'"
+        )
+        .bind(file.id)
+        .fetch_one(&db.pool)
+        .await
+        .unwrap();
+        assert!(snapshots < 60 / LIVE_CHECKPOINTS.snapshot_interval as i64 + 1);
+        for (index, expected) in saved.iter().enumerate() {
+            let bytes = FileVersion::materialize(&db.pool, storage_root, file.id, index as i32 + 1).await.unwrap();
+            assert_eq!(bytes, expected.as_bytes(), "version {} after compaction", index + 1);
+        }
+        assert_eq!(compact(&db.pool, Utc::now()).await.unwrap(), CompactionReport::default());
+
+        // Damage in the chain is reported, not served
+        let damaged = sqlx::query_scalar::<_, i32>(
+            r#"
+            UPDATE file_versions SET changes = '[{"insert":"tampered\n"}]'
+            WHERE id = (
+                SELECT id FROM file_versions WHERE file_id = $1 AND encoding = 'delta' ORDER BY version DESC LIMIT 1
+            )
+            RETURNING version
+            "#
+        )
+        .bind(file.id)
+        .fetch_one(&db.pool)
+        .await
+        .unwrap();
+        assert!(matches!(
+            FileVersion::materialize(&db.pool, storage_root, file.id, damaged).await,
+            Err(AppError::ContentCorrupted(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_binary_versions_are_full_snapshots() {
+        let Some(db) = TestDb::start().await else { return };
+        let storage_dir = tempfile::tempdir().unwrap();
+        let storage_root = storage_dir.path().to_str().unwrap();
+        let owner = create_test_user(&db.pool).await;
+        let project = create_test_project(&db.pool, &owner, false).await;
+        let mut rng = StdRng::seed_from_u64(7);
+        let mut bytes: Vec<u8> = (0..4096).map(|_| rng.gen()).collect();
+        let file = File::create_from_bytes(
+            &db.pool,
+            storage_root,
+            &ProjectLimits::default(),
+            project.id,
+            "figure.png",
+            "figure.png",
+            ContentType::Image,
+            &bytes,
+            owner.id,
+        )
+        .await
+        .unwrap();
+
+        let mut saved = Vec::new();
+        for version in 1..=10 {
+            let at = rng.gen_range(0..bytes.len());
+            bytes[at] = bytes[at].wrapping_add(1);
+            bytes.extend((0..rng.gen_range(0..64)).map(|_| rng.gen::<u8>()));
+            FileVersion::create_binary(&db.pool, storage_root, file.id, version, &bytes, owner.id, "upload")
+                .await
+                .unwrap();
+            saved.push(bytes.clone());
+        }
+
+        let encodings = sqlx::query_scalar::<_, VersionEncoding>(
+            "SELECT encoding FROM file_versions WHERE file_id = $1"
+        )
+        .bind(file.id)
+        .fetch_all(&db.pool)
+        .await
+        .unwrap();
+        assert!(encodings.iter().all(|encoding| *encoding == VersionEncoding::Blob));
+
+        compact(&db.pool, Utc::now() + chrono::Duration::hours(1)).await.unwrap();
+        for (index, expected) in saved.iter().enumerate() {
+            let read = FileVersion::materialize(&db.pool, storage_root, file.id, index as i32 + 1).await.unwrap();
+            assert_eq!(&read, expected, "version {}", index + 1);
+        }
+    }
+}
@@ -0,0 +1,469 @@
+//! Reindexing what is derived from file content
+//!
+//! Line and word counts and the LaTeX metadata (labels, citations, includes,
+//! sections, ...) are derived from a text file's content when it is saved.
+//! After an extractor changes, a reindex brings the rows of existing files up
+//! to date: `FileReindexTask` walks all live files except images in ID
+//! order, in batches of `FILE_REINDEX_BATCH_SIZE` with
+//! `FILE_REINDEX_PAUSE_MS` between them, and rewrites only the rows whose
+//! derived data differs. Content kept in the blob store, as from chunked
+//! uploads, is read from there; content that is not UTF-8 has nothing
+//! derived and is left alone. The last file of each batch is saved as the
+//! run's high-water mark together with the running totals, so a restart
+//! resumes after it.
+//!
+//! A row is only rewritten when its `updated_at` is still the one read, so a
+//! save while the batch runs wins, and it derives its data itself. Caches
+//! built from the metadata are dropped for the projects of rewritten files.
+//! Only one run is active at a time; the task, the admin endpoint and the
+//! `reindex` command all work on it.
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::{Connection, FromRow};
+use std::time::{Duration, Instant};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use super::file::{DerivedContent, File};
+use super::integrity::ReadPath;
+use super::StorageStrategy;
+use crate::config::FileStorageConfig;
+use crate::error::AppError;
+use crate::server::AppState;
+
+/// Name of the reindex task, for starting it right after a run is requested
+pub const FILE_REINDEX_TASK: &str = "file_reindex";
+
+/// Time between two runs of the reindex task
+const REINDEX_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Time one run of the reindex task works before leaving the rest to the next
+const RUN_BUDGET: Duration = Duration::from_secs(30);
+
+/// Batch size and pause of a reindex
+#[derive(Debug, Clone, Copy)]
+pub struct ReindexSettings {
+    pub batch_size: i64,
+    pub pause: Duration,
+}
+
+impl ReindexSettings {
+    pub fn from_config(storage: &FileStorageConfig) -> Self {
+        Self {
+            batch_size: storage.reindex_batch_size.max(1) as i64,
+            pause: Duration::from_millis(storage.reindex_pause_ms),
+        }
+    }
+}
+
+/// Progress of a reindex
+#[derive(Debug, Clone, Serialize, FromRow, ToSchema)]
+pub struct FileReindex {
+    pub id: Uuid,
+    /// `None` when started from the command line
+    pub requested_by: Option<Uuid>,
+    /// Last file done; files are walked in ID order
+    pub high_water: Option<Uuid>,
+    /// Files looked at so far
+    pub processed: i64,
+    /// Files whose derived data was rewritten
+    pub changed: i64,
+    /// Files that could not be read or rewritten
+    pub errors: i64,
+    pub last_error: Option<String>,
+    pub started_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    pub completed_at: Option<DateTime<Utc>>,
+}
+
+/// What one batch did
+#[derive(Debug)]
+pub struct ReindexBatch {
+    pub reindex: FileReindex,
+    /// The rewritten files
+    pub changed: Vec<File>,
+}
+
+impl FileReindex {
+    /// Start a run over all files; refused while one is active
+    pub async fn start(db: &sqlx::PgPool, requested_by: Option<Uuid>) -> Result<Self, AppError> {
+        sqlx::query_as::<_, FileReindex>(
+            r#"
+            INSERT INTO file_reindexes (requested_by)
+            VALUES ($1)
+            ON CONFLICT ((true)) WHERE completed_at IS NULL DO NOTHING
+            RETURNING *
+            "#,
+        )
+        .bind(requested_by)
+        .fetch_optional(db)
+        .await
+        .map_err(AppError::Database)?
+        .ok_or_else(|| AppError::Conflict("A reindex is already running".to_string()))
+    }
+
+    /// The active run, or else start one
+    pub async fn resume_or_start(db: &sqlx::PgPool, requested_by: Option<Uuid>) -> Result<Self, AppError> {
+        match Self::active(db).await? {
+            Some(reindex) => Ok(reindex),
+            None => Self::start(db, requested_by).await,
+        }
+    }
+
+    /// The run not completed yet, if any
+    pub async fn active(db: &sqlx::PgPool) -> Result<Option<Self>, AppError> {
+        sqlx::query_as::<_, FileReindex>("SELECT * FROM file_reindexes WHERE completed_at IS NULL")
+            .fetch_optional(db)
+            .await
+            .map_err(AppError::Database)
+    }
+
+    /// The active run, or else the last one to start
+    pub async fn latest(db: &sqlx::PgPool) -> Result<Option<Self>, AppError> {
+        sqlx::query_as::<_, FileReindex>(
+            "SELECT * FROM file_reindexes ORDER BY completed_at IS NULL DESC, started_at DESC LIMIT 1",
+        )
+        .fetch_optional(db)
+        .await
+        .map_err(AppError::Database)
+    }
+
+    /// Work on the active run for up to `budget`; returns it as left
+    pub async fn run_pending(
+        state: &AppState,
+        settings: ReindexSettings,
+        budget: Duration,
+    ) -> Result<Option<Self>, AppError> {
+        let storage = &state.config.features.file_storage;
+        let deadline = Instant::now() + budget;
+        let mut reindex = None;
+        while Instant::now() < deadline {
+            let Some(batch) = Self::step(&state.db_pool, storage, settings.batch_size).await? else { break };
+            for file in &batch.changed {
+                state.autocomplete_cache.invalidate(file.project_id);
+                state.include_graph_cache.file_changed(file);
+            }
+            let done = batch.reindex.completed_at.is_some();
+            reindex = Some(batch.reindex);
+            if done {
+                break;
+            }
+            tokio::time::sleep(settings.pause).await;
+        }
+        Ok(reindex)
+    }
+
+    /// Reindex the next batch of the active run and save the progress
+    ///
+    /// Returns `None` when no run is active or another worker is on it.
+    pub async fn step(
+        db: &sqlx::PgPool,
+        storage: &FileStorageConfig,
+        batch_size: i64,
+    ) -> Result<Option<ReindexBatch>, AppError> {
+        let mut tx = db.begin().await.map_err(AppError::Database)?;
+
+        let Some(reindex) = sqlx::query_as::<_, FileReindex>(
+            "SELECT * FROM file_reindexes WHERE completed_at IS NULL FOR UPDATE SKIP LOCKED",
+        )
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(AppError::Database)?
+        else {
+            return Ok(None);
+        };
+
+        // Images have nothing derived
+        let files = sqlx::query_as::<_, File>(
+            r#"
+            SELECT * FROM files
+            WHERE is_deleted = false AND content_type <> 'image'
+              AND ($1::uuid IS NULL OR id > $1)
+            ORDER BY id
+            LIMIT $2
+            "#,
+        )
+        .bind(reindex.high_water)
+        .bind(batch_size)
+        .fetch_all(&mut *tx)
+        .await
+        .map_err(AppError::Database)?;
+
+        let mut changed = Vec::new();
+        let mut errors = 0i64;
+        let mut last_error = None;
+        for file in &files {
+            // A file that fails is counted and skipped, the batch goes on
+            let rewritten = match Self::derive(db, file, storage).await {
+                Ok(Some(derived)) if derived != DerivedContent::stored(file) => {
+                    let mut savepoint = tx.begin().await.map_err(AppError::Database)?;
+                    match Self::rewrite(&mut *savepoint, file, &derived).await {
+                        Ok(updated) => {
+                            savepoint.commit().await.map_err(AppError::Database)?;
+                            Ok(updated)
+                        }
+                        Err(e) => Err(AppError::Database(e)),
+                    }
+                }
+                Ok(_) => Ok(None),
+                Err(e) => Err(e),
+            };
+            match rewritten {
+                // `None` when a save came first
+                Ok(updated) => changed.extend(updated),
+                Err(e) => {
+                    tracing::warn!("Failed to reindex file {}: {}", file.id, e);
+                    errors += 1;
+                    last_error = Some(format!("File {}: {}", file.id, e));
+                }
+            }
+        }
+
+        let reindex = sqlx::query_as::<_, FileReindex>(
+            r#"
+            UPDATE file_reindexes SET
+                high_water = COALESCE($2, high_water),
+                processed = processed + $3,
+                changed = changed + $4,
+                errors = errors + $5,
+                last_error = COALESCE($6, last_error),
+                updated_at = NOW(),
+                completed_at = CASE WHEN $7 THEN NOW() END
+            WHERE id = $1
+            RETURNING *
+            "#,
+        )
+        .bind(reindex.id)
+        .bind(files.last().map(|file| file.id))
+        .bind(files.len() as i64)
+        .bind(changed.len() as i64)
+        .bind(errors)
+        .bind(last_error)
+        .bind((files.len() as i64) < batch_size)
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(AppError::Database)?;
+
+        tx.commit().await.map_err(AppError::Database)?;
+        Ok(Some(ReindexBatch { reindex, changed }))
+    }
+
+    /// Derive from a file's content wherever it is kept; `None` for binary content
+    async fn derive(
+        db: &sqlx::PgPool,
+        file: &File,
+        storage: &FileStorageConfig,
+    ) -> Result<Option<DerivedContent>, AppError> {
+        if file.storage_strategy != StorageStrategy::External {
+            return Ok(Some(DerivedContent::of(&file.content, file.content_type)));
+        }
+
+        let bytes = file.read_verified(db, storage, ReadPath::Reindex).await?;
+        Ok(String::from_utf8(bytes)
+            .ok()
+            .map(|content| DerivedContent::of(&content, file.content_type)))
+    }
+
+    /// Store a file's derived data unless it was saved after it was read
+    async fn rewrite(
+        conn: &mut sqlx::PgConnection,
+        file: &File,
+        derived: &DerivedContent,
+    ) -> Result<Option<File>, sqlx::Error> {
+        // `updated_at` stays: the content did not change
+        sqlx::query_as::<_, File>(
+            r#"
+            UPDATE files SET line_count = $3, word_count = $4, word_count_tex = $5, latex_metadata = $6
+            WHERE id = $1 AND updated_at = $2 AND is_deleted = false
+            RETURNING *
+            "#,
+        )
+        .bind(file.id)
+        .bind(file.updated_at)
+        .bind(derived.line_count)
+        .bind(derived.word_count)
+        .bind(derived.word_count_tex)
+        .bind(&derived.latex_metadata)
+        .fetch_optional(conn)
+        .await
+    }
+}
+
+/// Works through an active reindex in the background
+pub struct FileReindexTask;
+
+impl crate::tasks::PeriodicTask for FileReindexTask {
+    fn name(&self) -> &'static str {
+        FILE_REINDEX_TASK
+    }
+
+    fn interval(&self) -> Duration {
+        REINDEX_INTERVAL
+    }
+
+    fn run<'a>(&'a self, state: &'a AppState) -> futures::future::BoxFuture<'a, Result<(), AppError>> {
+        Box::pin(async move {
+            let settings = ReindexSettings::from_config(&state.config.features.file_storage);
+            if let Some(reindex) = FileReindex::run_pending(state, settings, RUN_BUDGET).await? {
+                if reindex.completed_at.is_some() {
+                    tracing::info!(
+                        "Reindexed {} files: {} changed, {} failed",
+                        reindex.processed,
+                        reindex.changed,
+                        reindex.errors
+                    );
+                }
+            }
+            Ok(())
+        })
+    }
+}
+
+/// `reindex` on the command line: run the reindex to the end in the foreground
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReindexCommand;
+
+impl ReindexCommand {
+    /// The reindex command in the arguments after the program name, if any
+    pub fn from_args(args: impl IntoIterator<Item = String>) -> Result<Option<Self>, AppError> {
+        let mut args = args.into_iter();
+        if args.next().as_deref() != Some("reindex") {
+            return Ok(None);
+        }
+        if let Some(arg) = args.next() {
+            return Err(AppError::BadRequest(format!("Unknown reindex argument '{}'", arg)));
+        }
+        Ok(Some(Self))
+    }
+
+    /// Resume the active run, or start one, and work on it until it completes
+    pub async fn run(&self, state: &AppState) -> Result<FileReindex, AppError> {
+        let settings = ReindexSettings::from_config(&state.config.features.file_storage);
+        let mut reindex = FileReindex::resume_or_start(&state.db_pool, None).await?;
+        while reindex.completed_at.is_none() {
+            match FileReindex::run_pending(state, settings, RUN_BUDGET).await? {
+                Some(progress) => {
+                    tracing::info!(
+                        "Reindexed {} files so far: {} changed, {} failed",
+                        progress.processed,
+                        progress.changed,
+                        progress.errors
+                    );
+                    reindex = progress;
+                }
+                // The server is on a batch; wait for it to let go
+                None => {
+                    tokio::time::sleep(settings.pause.max(Duration::from_millis(100))).await;
+                    match FileReindex::active(&state.db_pool).await? {
+                        Some(active) => reindex = active,
+                        None => break,
+                    }
+                }
+            }
+        }
+        Ok(FileReindex::latest(&state.db_pool).await?.unwrap_or(reindex))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::ContentType;
+    use crate::testing::{create_test_file, create_test_project, create_test_user, test_config, TestDb};
+
+    #[test]
+    fn test_reindex_command_arguments() {
+        let args = |args: &[&str]| ReindexCommand::from_args(args.iter().map(|arg| arg.to_string()));
+        assert_eq!(args(&["reindex"]).unwrap(), Some(ReindexCommand));
+        assert_eq!(args(&["serve"]).unwrap(), None);
+        assert_eq!(args(&[]).unwrap(), None);
+        assert!(args(&["reindex", "--all"]).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_reindex_rewrites_stale_rows_and_resumes() {
+        let Some(db) = TestDb::start().await else { return };
+        let storage = tempfile::tempdir().unwrap();
+        let mut config = test_config();
+        config.features.file_storage.local_path = storage.path().to_string_lossy().into_owned();
+        let state = AppState::new(config, db.pool.clone()).await.unwrap();
+        let root = state.config.features.file_storage.local_path.clone();
+
+        let owner = create_test_user(&db.pool).await;
+        let mut files = Vec::new();
+        for _ in 0..3 {
+            let project = create_test_project(&db.pool, &owner, false).await;
+            files.push(create_test_file(&db.pool, &project, &owner).await);
+        }
+        let fresh = DerivedContent::of(&files[0].content, files[0].content_type);
+        // Uploaded in chunks, so kept in the blob store without derived data
+        let uploaded = File::create_from_bytes(
+            &db.pool,
+            &root,
+            files[0].project_id,
+            "chapter.tex",
+            "chapter.tex",
+            ContentType::Latex,
+            files[0].content.as_bytes(),
+            owner.id,
+        )
+        .await
+        .unwrap();
+        assert_ne!(DerivedContent::stored(&uploaded), fresh);
+
+        // As if an older extractor had derived them
+        let ids: Vec<Uuid> = files.iter().map(|file| file.id).collect();
+        sqlx::query("UPDATE files SET line_count = 0, latex_metadata = NULL WHERE id = ANY($1)")
+            .bind(&ids)
+            .execute(&db.pool)
+            .await
+            .unwrap();
+        let load = |file_id: Uuid| {
+            sqlx::query_as::<_, File>("SELECT * FROM files WHERE id = $1")
+                .bind(file_id)
+                .fetch_one(&db.pool)
+        };
+
+        // A save after the reindex read the file wins
+        let read = load(files[2].id).await.unwrap();
+        sqlx::query("UPDATE files SET updated_at = NOW() + INTERVAL '1 second' WHERE id = $1")
+            .bind(files[2].id)
+            .execute(&db.pool)
+            .await
+            .unwrap();
+        let mut conn = db.pool.acquire().await.unwrap();
+        assert!(FileReindex::rewrite(&mut *conn, &read, &fresh).await.unwrap().is_none());
+        assert_eq!(load(files[2].id).await.unwrap().line_count, 0);
+
+        let before = load(files[0].id).await.unwrap();
+        let reindex = FileReindex::start(&db.pool, Some(owner.id)).await.unwrap();
+        assert!(matches!(FileReindex::start(&db.pool, None).await, Err(AppError::Conflict(_))));
+
+        // One file per batch; the high-water mark carries over between them
+        let storage_config = &state.config.features.file_storage;
+        let first = FileReindex::step(&db.pool, storage_config, 1).await.unwrap().unwrap().reindex;
+        let second = FileReindex::step(&db.pool, storage_config, 1).await.unwrap().unwrap().reindex;
+        assert_eq!((first.processed, second.processed), (1, 2));
+        assert!(second.high_water > first.high_water);
+
+        let settings = ReindexSettings { batch_size: 1, pause: Duration::ZERO };
+        let done = FileReindex::run_pending(&state, settings, Duration::from_secs(30)).await.unwrap().unwrap();
+        assert_eq!(done.id, reindex.id);
+        assert!(done.completed_at.is_some());
+        assert!(done.processed >= 4 && done.changed >= 4);
+        assert!(FileReindex::step(&db.pool, storage_config, 1).await.unwrap().is_none());
+
+        for file_id in ids.into_iter().chain([uploaded.id]) {
+            assert_eq!(DerivedContent::stored(&load(file_id).await.unwrap()), fresh);
+        }
+        // Derived data is not an edit
+        assert_eq!(load(files[0].id).await.unwrap().updated_at, before.updated_at);
+
+        // Nothing is left to rewrite the second time
+        FileReindex::start(&db.pool, None).await.unwrap();
+        let again = FileReindex::run_pending(&state, settings, Duration::from_secs(30)).await.unwrap().unwrap();
+        assert!(again.completed_at.is_some());
+        assert_eq!(FileReindex::latest(&db.pool).await.unwrap().unwrap().id, again.id);
+    }
+}
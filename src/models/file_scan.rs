@@ -0,0 +1,431 @@
+//! Malware scan results of stored files
+//!
+//! Uploads are scanned before their file row exists, so an infected upload
+//! never becomes a file; it is rejected, audited and reported to the
+//! administrator. Files stored before scanning was enabled are `unscanned`
+//! until the `file_rescan` task reaches them; a hit there quarantines the
+//! file, which blocks downloads until an administrator releases it.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use std::path::Path;
+use std::time::Duration;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use super::audit::AuditEvent;
+use super::blob::blob_storage_path;
+use super::file::File;
+use super::notification::NotificationService;
+use super::user::User;
+use super::StorageStrategy;
+use crate::error::AppError;
+use crate::scanner::ScanVerdict;
+use crate::server::AppState;
+
+/// Files scanned per batch of the re-scan task
+const RESCAN_BATCH_SIZE: i64 = 100;
+
+/// How often the re-scan task runs on its own; admins usually trigger it
+pub const RESCAN_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Scan state of a file
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, sqlx::Type, ToSchema)]
+pub enum FileScanStatus {
+    /// Stored while scanning was disabled
+    #[serde(rename = "unscanned")]
+    #[sqlx(rename = "unscanned")]
+    #[default]
+    Unscanned,
+    #[serde(rename = "clean")]
+    #[sqlx(rename = "clean")]
+    Clean,
+    /// Too large to scan, accepted with this warning
+    #[serde(rename = "skipped")]
+    #[sqlx(rename = "skipped")]
+    Skipped,
+    /// Found infected after it was stored; cannot be downloaded
+    #[serde(rename = "quarantined")]
+    #[sqlx(rename = "quarantined")]
+    Quarantined,
+    /// Quarantined, then released by an administrator
+    #[serde(rename = "released")]
+    #[sqlx(rename = "released")]
+    Released,
+}
+
+impl FileScanStatus {
+    /// Status for a file the scanner did not object to
+    pub fn accepted(verdict: &ScanVerdict) -> Self {
+        match verdict {
+            ScanVerdict::Clean => Self::Clean,
+            ScanVerdict::Skipped => Self::Skipped,
+            ScanVerdict::NotScanned => Self::Unscanned,
+            ScanVerdict::Infected { .. } => Self::Quarantined,
+        }
+    }
+}
+
+/// A quarantined file awaiting review
+#[derive(Debug, Clone, Serialize, FromRow, ToSchema)]
+pub struct QuarantinedFile {
+    pub id: Uuid,
+    pub project_id: Uuid,
+    pub path: String,
+    pub size: i64,
+    pub scan_signature: Option<String>,
+    pub scanned_at: Option<DateTime<Utc>>,
+    pub created_by: Uuid,
+}
+
+/// Outcome of a re-scan run
+#[derive(Debug, Clone, Default, Serialize, ToSchema)]
+pub struct RescanReport {
+    pub scanned: u64,
+    pub skipped: u64,
+    pub quarantined: u64,
+    /// Files whose content could not be read; they are retried next run
+    pub failed: u64,
+}
+
+impl File {
+    /// Whether the file may be served to users
+    pub fn is_quarantined(&self) -> bool {
+        self.scan_status == FileScanStatus::Quarantined
+    }
+
+    /// Fail for quarantined files
+    pub fn ensure_downloadable(&self) -> Result<(), AppError> {
+        if self.is_quarantined() {
+            return Err(AppError::Authorization(
+                "File is quarantined until an administrator reviews it".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Store the scan result of a file
+    pub async fn record_scan(
+        db: &sqlx::PgPool,
+        file_id: Uuid,
+        status: FileScanStatus,
+        signature: Option<&str>,
+    ) -> Result<(), AppError> {
+        sqlx::query("UPDATE files SET scan_status = $2, scan_signature = $3, scanned_at = NOW() WHERE id = $1")
+            .bind(file_id)
+            .bind(status)
+            .bind(signature)
+            .execute(db)
+            .await
+            .map_err(AppError::Database)?;
+
+        Ok(())
+    }
+
+    /// Quarantined files, oldest hit first
+    pub async fn list_quarantined(db: &sqlx::PgPool) -> Result<Vec<QuarantinedFile>, AppError> {
+        sqlx::query_as::<_, QuarantinedFile>(
+            r#"
+            SELECT id, project_id, path, size, scan_signature, scanned_at, created_by
+            FROM files
+            WHERE scan_status = 'quarantined' AND is_deleted = false
+            ORDER BY scanned_at
+            "#
+        )
+        .fetch_all(db)
+        .await
+        .map_err(AppError::Database)
+    }
+
+    /// Lift the quarantine of a file after review
+    pub async fn release_quarantine(db: &sqlx::PgPool, file_id: Uuid, admin_id: Uuid) -> Result<(), AppError> {
+        let mut tx = db.begin().await.map_err(AppError::Database)?;
+
+        let released = sqlx::query_as::<_, (Uuid, Option<String>)>(
+            r#"
+            UPDATE files SET scan_status = 'released'
+            WHERE id = $1 AND scan_status = 'quarantined'
+            RETURNING project_id, scan_signature
+            "#
+        )
+        .bind(file_id)
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(AppError::Database)?;
+
+        let Some((project_id, signature)) = released else {
+            return Err(AppError::NotFound {
+                entity: "Quarantined file".to_string(),
+                id: file_id.to_string(),
+            });
+        };
+
+        AuditEvent::record(
+            &mut *tx,
+            Some(admin_id),
+            "file_quarantine_released",
+            "file",
+            Some(file_id),
+            Some(project_id),
+            serde_json::json!({ "signature": signature }),
+        )
+        .await?;
+
+        tx.commit().await.map_err(AppError::Database)?;
+
+        Ok(())
+    }
+}
+
+/// Audit and report an upload that was found infected, and build the error it is rejected with
+pub async fn reject_infected_upload(
+    state: &AppState,
+    project_id: Uuid,
+    user_id: Uuid,
+    path: &str,
+    size: i64,
+    signature: &str,
+) -> AppError {
+    tracing::warn!("Rejected upload of {} to project {}: {} found", path, project_id, signature);
+
+    let details = serde_json::json!({ "path": path, "size": size, "signature": signature });
+    let reported = async {
+        AuditEvent::record(
+            &state.db_pool,
+            Some(user_id),
+            "upload_rejected_malware",
+            "file",
+            None,
+            Some(project_id),
+            details.clone(),
+        )
+        .await?;
+
+        notify_admin(
+            state,
+            "Infected upload rejected",
+            &format!("An upload of {} was rejected because {} was found in it.", path, signature),
+            details,
+        )
+        .await
+    }
+    .await;
+
+    if let Err(e) = reported {
+        tracing::error!("Failed to report rejected upload of {}: {}", path, e);
+    }
+
+    AppError::MalwareDetected(signature.to_string())
+}
+
+/// Quarantine a stored file the scanner flagged, audit it and report it
+async fn quarantine(state: &AppState, file: &File, signature: &str) -> Result<(), AppError> {
+    tracing::warn!("Quarantined file {} of project {}: {} found", file.id, file.project_id, signature);
+
+    File::record_scan(&state.db_pool, file.id, FileScanStatus::Quarantined, Some(signature)).await?;
+
+    let details = serde_json::json!({ "path": file.path, "size": file.size, "signature": signature });
+    AuditEvent::record(
+        &state.db_pool,
+        None,
+        "file_quarantined",
+        "file",
+        Some(file.id),
+        Some(file.project_id),
+        details.clone(),
+    )
+    .await?;
+
+    notify_admin(
+        state,
+        "File quarantined",
+        &format!("{} was quarantined because {} was found in it. Review it under /admin/files/quarantined.", file.path, signature),
+        details,
+    )
+    .await
+}
+
+/// Notify the instance administrator, when the account exists
+async fn notify_admin(state: &AppState, title: &str, body: &str, data: serde_json::Value) -> Result<(), AppError> {
+    let Some(admin) = User::find_by_username(&state.db_pool, crate::admin_init::ADMIN_USERNAME).await? else {
+        return Ok(());
+    };
+
+    NotificationService::notify(
+        &state.db_pool,
+        &state.mailer,
+        &admin,
+        "malware_detected",
+        title,
+        body,
+        Some(data),
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// Scan every file stored before scanning was enabled
+pub async fn rescan_unscanned(state: &AppState) -> Result<RescanReport, AppError> {
+    let mut report = RescanReport::default();
+    if !state.file_scanner.enabled() {
+        return Ok(report);
+    }
+
+    let storage_root = Path::new(&state.config.features.file_storage.local_path);
+    // Keyset cursor, so files that fail to scan are not fetched again
+    let mut after: Option<(DateTime<Utc>, Uuid)> = None;
+    loop {
+        let batch = sqlx::query_as::<_, File>(
+            r#"
+            SELECT * FROM files
+            WHERE scan_status = 'unscanned' AND is_deleted = false
+              AND ($2::timestamptz IS NULL OR (created_at, id) > ($2, $3))
+            ORDER BY created_at, id
+            LIMIT $1
+            "#
+        )
+        .bind(RESCAN_BATCH_SIZE)
+        .bind(after.map(|(created_at, _)| created_at))
+        .bind(after.map(|(_, id)| id))
+        .fetch_all(&state.db_pool)
+        .await
+        .map_err(AppError::Database)?;
+
+        let Some(last) = batch.last() else {
+            return Ok(report);
+        };
+        after = Some((last.created_at, last.id));
+
+        for file in batch {
+            let scanned = match (&file.storage_strategy, &file.content_hash) {
+                (StorageStrategy::External, Some(hash)) => {
+                    let path = storage_root.join(blob_storage_path(hash));
+                    state.file_scanner.scan_path(&path, file.size.max(0) as u64).await
+                }
+                _ => state.file_scanner.scan_bytes(file.content.as_bytes()).await,
+            };
+
+            // A missing blob should not stop the sweep; an unreachable scanner should
+            let verdict = match scanned {
+                Ok(verdict) => verdict,
+                Err(AppError::Storage(e)) => {
+                    tracing::warn!("Could not re-scan file {}: {}", file.id, e);
+                    report.failed += 1;
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+
+            report.scanned += 1;
+            match verdict {
+                ScanVerdict::Infected { signature } => {
+                    quarantine(state, &file, &signature).await?;
+                    report.quarantined += 1;
+                }
+                verdict => {
+                    if verdict == ScanVerdict::Skipped {
+                        report.skipped += 1;
+                    }
+                    File::record_scan(&state.db_pool, file.id, FileScanStatus::accepted(&verdict), None).await?;
+                }
+            }
+        }
+    }
+}
+
+/// Sweeps files stored before scanning was enabled
+pub struct FileRescanTask;
+
+impl crate::tasks::PeriodicTask for FileRescanTask {
+    fn name(&self) -> &'static str {
+        "file_rescan"
+    }
+
+    fn interval(&self) -> Duration {
+        RESCAN_INTERVAL
+    }
+
+    fn run<'a>(&'a self, state: &'a AppState) -> futures::future::BoxFuture<'a, Result<(), AppError>> {
+        Box::pin(async move {
+            let report = rescan_unscanned(state).await?;
+            if report.scanned > 0 {
+                tracing::info!(
+                    "Re-scanned {} files: {} quarantined, {} too large to scan",
+                    report.scanned,
+                    report.quarantined,
+                    report.skipped
+                );
+            }
+            if report.failed > 0 {
+                tracing::warn!("{} files could not be read for re-scanning", report.failed);
+            }
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::file::CreateFile;
+    use crate::models::project_limits::ProjectLimits;
+    use crate::models::validation::FileName;
+    use crate::testing::{clamav_service, create_test_project, create_test_user, mock_clamd, test_state, TestDb, EICAR};
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_rescan_quarantines_infected_files_until_released() {
+        let Some(db) = TestDb::start().await else { return };
+        let (address, _) = mock_clamd().await;
+        let mut state = test_state(&db).await;
+        state.file_scanner = Arc::new(clamav_service(&address, 1024 * 1024));
+
+        let owner = create_test_user(&db.pool).await;
+        let project = create_test_project(&db.pool, &owner, false).await;
+        let infected = File::create(
+            &db.pool,
+            &ProjectLimits::default(),
+            project.id,
+            CreateFile {
+                name: FileName::new("notes.txt").unwrap(),
+                path: "/notes.txt".to_string(),
+                content: Some(String::from_utf8(EICAR.to_vec()).unwrap()),
+                content_type: None,
+            },
+            owner.id,
+        )
+        .await
+        .unwrap();
+        assert_eq!(infected.scan_status, FileScanStatus::Unscanned);
+
+        let report = rescan_unscanned(&state).await.unwrap();
+        assert!(report.quarantined >= 1);
+
+        let file = File::find_by_id(&db.pool, infected.id, owner.id).await.unwrap().unwrap();
+        assert_eq!(file.scan_status, FileScanStatus::Quarantined);
+        assert!(matches!(file.ensure_downloadable(), Err(AppError::Authorization(_))));
+
+        let quarantined = File::list_quarantined(&db.pool).await.unwrap();
+        let entry = quarantined.iter().find(|q| q.id == file.id).unwrap();
+        assert_eq!(entry.scan_signature.as_deref(), Some("Eicar-Signature"));
+
+        let events = AuditEvent::list_by_action(&db.pool, "file_quarantined", 100).await.unwrap();
+        assert!(events.iter().any(|event| event.entity_id == Some(file.id)));
+
+        // Quarantined files are not picked up again
+        rescan_unscanned(&state).await.unwrap();
+        let file = File::find_by_id(&db.pool, file.id, owner.id).await.unwrap().unwrap();
+        assert_eq!(file.scan_status, FileScanStatus::Quarantined);
+
+        File::release_quarantine(&db.pool, file.id, owner.id).await.unwrap();
+        let file = File::find_by_id(&db.pool, file.id, owner.id).await.unwrap().unwrap();
+        assert_eq!(file.scan_status, FileScanStatus::Released);
+        assert!(file.ensure_downloadable().is_ok());
+        assert!(matches!(
+            File::release_quarantine(&db.pool, file.id, owner.id).await,
+            Err(AppError::NotFound { .. })
+        ));
+    }
+}
@@ -0,0 +1,454 @@
+//! Read-only links to a single file
+//!
+//! `GET /shared/files/{token}` shows one file of a project to anyone holding
+//! one of its share links, without an account and without access to any
+//! other file of the project. A `source` link shows the file's content, a
+//! `pdf` link a standalone compile of the file alone. Tokens are shown once
+//! and only their SHA-256 is stored; links stop working once revoked or
+//! expired, and when the file is deleted. All of these look exactly like a
+//! link that does not exist. Every request with a working token is counted
+//! on the link.
+//!
+//! A standalone compile typesets [`standalone_document`]: the file as it is
+//! when it has a `\documentclass`, otherwise wrapped in a minimal article.
+//! Includes of other files are replaced by visible placeholders, and the job
+//! runs in a directory of its own under `SHARED_RENDER_ROOT` holding nothing
+//! but that document, so a shared file cannot pull in the rest of its
+//! project. Jobs are queued at low priority and reused for as long as the
+//! document is the same, by its hash.
+
+use chrono::{DateTime, Utc};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::FromRow;
+use std::path::Path;
+use std::sync::OnceLock;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use super::auth::PasswordUtils;
+use super::compilation::{CompilationJob, CreateCompilationJob, QueuePriority};
+use super::file::File;
+use super::{CompilationStatus, ContentType, Entity, LatexEngine};
+use crate::error::AppError;
+
+/// Directory standalone compiles run in, one subdirectory per document
+pub const SHARED_RENDER_ROOT: &str = "/tmp/texler/shared-files";
+
+/// Bumped whenever `standalone_document` changes, so old compiles are not reused
+const RENDER_VERSION: &str = "1";
+
+/// Commands reading the file named by their argument, which becomes a placeholder
+const INCLUDE_COMMANDS: &[&str] = &[
+    "input", "include", "subfile", "includegraphics", "includepdf", "includestandalone",
+    "lstinputlisting", "verbatiminput", "bibliography", "addbibresource",
+];
+
+/// Commands taking a directory, then the file in it
+const IMPORT_COMMANDS: &[&str] = &["import", "subimport", "inputfrom", "subinputfrom", "includefrom", "subincludefrom"];
+
+/// What a share link shows
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, sqlx::Type, ToSchema)]
+#[sqlx(type_name = "filesharemode")]
+pub enum FileShareMode {
+    /// The content of the file
+    #[default]
+    #[serde(rename = "source")]
+    #[sqlx(rename = "source")]
+    Source,
+    /// A standalone compile of the file
+    #[serde(rename = "pdf")]
+    #[sqlx(rename = "pdf")]
+    Pdf,
+}
+
+/// Share link of a file; the token itself is never stored
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, ToSchema)]
+pub struct FileShareLink {
+    pub id: Uuid,
+    pub file_id: Uuid,
+    pub project_id: Uuid,
+    pub mode: FileShareMode,
+    pub created_by: Uuid,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub revoked: bool,
+    /// Requests served with this link
+    pub hit_count: i64,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl Entity for FileShareLink {
+    fn id(&self) -> Uuid {
+        self.id
+    }
+
+    fn created_at(&self) -> DateTime<Utc> {
+        self.created_at
+    }
+
+    fn updated_at(&self) -> DateTime<Utc> {
+        self.last_used_at.unwrap_or(self.created_at)
+    }
+}
+
+/// Share link creation request
+#[derive(Debug, Clone, Default, Deserialize, ToSchema)]
+pub struct CreateFileShareLink {
+    #[serde(default)]
+    pub mode: FileShareMode,
+    /// The link stops working at this time; unset for a link that only ends
+    /// when revoked
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+/// A new share link, the only time its token is shown
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct CreatedFileShareLink {
+    #[serde(flatten)]
+    pub link: FileShareLink,
+    pub token: String,
+    /// URL of the shared view
+    pub share_url: String,
+}
+
+fn token_hash(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+/// URL of the shared view of `token`
+pub fn share_url(token: &str) -> String {
+    format!("/api/v1/shared/files/{}", token)
+}
+
+/// URL of the standalone compile of `token`
+pub fn pdf_url(token: &str) -> String {
+    format!("{}/pdf", share_url(token))
+}
+
+impl FileShareLink {
+    /// Create a link to `file`; PDF links only for LaTeX files
+    pub async fn create(
+        db: &sqlx::PgPool,
+        file: &File,
+        created_by: Uuid,
+        request: CreateFileShareLink,
+    ) -> Result<CreatedFileShareLink, AppError> {
+        if request.expires_at.is_some_and(|expires_at| expires_at <= Utc::now()) {
+            return Err(AppError::Validation("Expiry must be in the future".to_string()));
+        }
+        if request.mode == FileShareMode::Pdf && file.content_type != ContentType::Latex {
+            return Err(AppError::Validation("Only LaTeX files can be shared as a PDF".to_string()));
+        }
+        file.ensure_downloadable()?;
+
+        let token = PasswordUtils::generate_share_token();
+        let link = sqlx::query_as::<_, FileShareLink>(
+            r#"
+            INSERT INTO file_share_links (file_id, project_id, token_hash, mode, created_by, expires_at)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            RETURNING *
+            "#
+        )
+        .bind(file.id)
+        .bind(file.project_id)
+        .bind(token_hash(&token))
+        .bind(request.mode)
+        .bind(created_by)
+        .bind(request.expires_at)
+        .fetch_one(db)
+        .await
+        .map_err(AppError::Database)?;
+
+        Ok(CreatedFileShareLink {
+            share_url: share_url(&token),
+            link,
+            token,
+        })
+    }
+
+    /// Links of a file, newest first
+    pub async fn list(db: &sqlx::PgPool, file_id: Uuid) -> Result<Vec<Self>, AppError> {
+        sqlx::query_as::<_, FileShareLink>(
+            "SELECT * FROM file_share_links WHERE file_id = $1 ORDER BY created_at DESC"
+        )
+        .bind(file_id)
+        .fetch_all(db)
+        .await
+        .map_err(AppError::Database)
+    }
+
+    /// Revoke a link of a file; false when there is no such link
+    pub async fn revoke(db: &sqlx::PgPool, file_id: Uuid, link_id: Uuid) -> Result<bool, AppError> {
+        let result = sqlx::query(
+            "UPDATE file_share_links SET revoked = true WHERE id = $1 AND file_id = $2"
+        )
+        .bind(link_id)
+        .bind(file_id)
+        .execute(db)
+        .await
+        .map_err(AppError::Database)?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// The working link `token` and its file, counting the use
+    ///
+    /// `None` when the token is unknown, revoked or expired, or the file is
+    /// deleted or its project being purged.
+    pub async fn use_token(db: &sqlx::PgPool, token: &str) -> Result<Option<(Self, File)>, AppError> {
+        let Some(link) = sqlx::query_as::<_, FileShareLink>(
+            r#"
+            UPDATE file_share_links l
+            SET hit_count = l.hit_count + 1, last_used_at = NOW()
+            FROM files f
+            JOIN projects p ON p.id = f.project_id
+            WHERE l.token_hash = $1 AND f.id = l.file_id
+                AND NOT l.revoked AND (l.expires_at IS NULL OR l.expires_at > NOW())
+                AND NOT f.is_deleted AND p.purging_at IS NULL
+            RETURNING l.*
+            "#
+        )
+        .bind(token_hash(token))
+        .fetch_optional(db)
+        .await
+        .map_err(AppError::Database)?
+        else {
+            return Ok(None);
+        };
+
+        let file = sqlx::query_as::<_, File>("SELECT * FROM files WHERE id = $1 AND is_deleted = false")
+            .bind(link.file_id)
+            .fetch_optional(db)
+            .await
+            .map_err(AppError::Database)?;
+        Ok(file.map(|file| (link, file)))
+    }
+}
+
+/// Whether `content` is a full document rather than a fragment
+fn has_document_class(content: &str) -> bool {
+    content.lines().any(|line| {
+        let code = line.split('%').next().unwrap_or_default();
+        code.contains("\\documentclass")
+    })
+}
+
+/// `content` as a document that compiles on its own
+///
+/// Fragments are wrapped in a minimal article. Every include of another
+/// file, with or without braces, becomes a framed placeholder naming it.
+pub fn standalone_document(content: &str) -> String {
+    static INCLUDE: OnceLock<Regex> = OnceLock::new();
+    let include = INCLUDE.get_or_init(|| {
+        let pattern = [
+            format!(r"\\(?:{})\*?\{{[^{{}}]*\}}\{{(?P<imported>[^{{}}]*)\}}", IMPORT_COMMANDS.join("|")),
+            format!(r"\\(?:{})\*?(?:\[[^\]]*\])?\{{(?P<named>[^{{}}]*)\}}", INCLUDE_COMMANDS.join("|")),
+            r"\\input\s+(?P<bare>[^\s{}\\%]+)".to_string(),
+        ]
+        .join("|");
+        Regex::new(&pattern).expect("valid include pattern")
+    });
+
+    let replaced = include.replace_all(content, |captures: &regex::Captures| {
+        let name = ["imported", "named", "bare"]
+            .iter()
+            .find_map(|group| captures.name(group))
+            .map_or("", |name| name.as_str());
+        format!("\\fbox{{\\texttt{{\\detokenize{{{}}}}} (not shared)}}", name.trim())
+    });
+
+    if has_document_class(content) {
+        replaced.into_owned()
+    } else {
+        format!(
+            "\\documentclass{{article}}\n\\begin{{document}}\n{}\n\\end{{document}}\n",
+            replaced.trim_end()
+        )
+    }
+}
+
+/// Key of the standalone compile of `document` at `path` with `engine`
+pub fn document_hash(document: &str, path: &str, engine: LatexEngine) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(format!("{}\n{}\n{}\n", RENDER_VERSION, engine.command(), path).as_bytes());
+    hasher.update(document.as_bytes());
+    hex::encode(hasher.finalize())
+}
+
+/// Standalone compile of a shared file
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct SharedFileRender {
+    pub status: CompilationStatus,
+    pub error_message: Option<String>,
+    /// Set once the PDF is ready
+    pub pdf_url: Option<String>,
+}
+
+impl SharedFileRender {
+    /// State of `job`, linking the PDF of share link `token` once it succeeded
+    pub fn of(job: &CompilationJob, token: &str) -> Self {
+        Self {
+            status: job.status,
+            error_message: job.error_message.clone(),
+            pdf_url: (job.status == CompilationStatus::Success).then(|| pdf_url(token)),
+        }
+    }
+}
+
+/// Engine standalone compiles of `file` use: its project's, or the one its
+/// extension needs
+pub async fn render_engine(db: &sqlx::PgPool, file: &File) -> Result<LatexEngine, AppError> {
+    let engine = sqlx::query_scalar::<_, LatexEngine>("SELECT latex_engine FROM projects WHERE id = $1")
+        .bind(file.project_id)
+        .fetch_one(db)
+        .await
+        .map_err(AppError::Database)?;
+    Ok(engine.for_target(&file.path))
+}
+
+/// The compile job of the document with hash `document_hash`, if any
+pub async fn find_render(db: &sqlx::PgPool, document_hash: &str) -> Result<Option<CompilationJob>, AppError> {
+    sqlx::query_as::<_, CompilationJob>(
+        r#"
+        SELECT cj.* FROM file_share_renders r
+        JOIN compilation_jobs cj ON cj.id = r.job_id
+        WHERE r.document_hash = $1
+        "#
+    )
+    .bind(document_hash)
+    .fetch_optional(db)
+    .await
+    .map_err(AppError::Database)
+}
+
+/// The standalone compile of `document`, queued when there is none that
+/// succeeded or may still succeed
+///
+/// The job compiles `file` of its project for `user_id`, so it shows up
+/// among the project's jobs, but in a directory of its own.
+pub async fn request_render(
+    db: &sqlx::PgPool,
+    file: &File,
+    user_id: Uuid,
+    engine: LatexEngine,
+    document: &str,
+) -> Result<CompilationJob, AppError> {
+    let target = file.path.trim_start_matches('/');
+    let hash = document_hash(document, target, engine);
+    if let Some(job) = find_render(db, &hash).await? {
+        if !matches!(job.status, CompilationStatus::Error | CompilationStatus::Cancelled) {
+            return Ok(job);
+        }
+    }
+
+    let directory = Path::new(SHARED_RENDER_ROOT).join(&hash);
+    let source = directory.join(target);
+    if let Some(parent) = source.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    tokio::fs::write(&source, document).await?;
+
+    let mut tx = db.begin().await.map_err(AppError::Database)?;
+    let create_job = CreateCompilationJob {
+        file_id: Some(file.id),
+        engine: Some(engine),
+        args: None,
+        priority: Some(QueuePriority::Low),
+        template_id: None,
+        clean: true,
+        batch_id: None,
+    };
+    let job = CompilationJob::create(
+        &mut tx,
+        file.project_id,
+        user_id,
+        create_job,
+        engine,
+        target,
+        directory.to_string_lossy().into_owned(),
+        vec![target.to_string()],
+    )
+    .await?;
+
+    // Another viewer may have queued the same document meanwhile; then
+    // this job is dropped and theirs is used
+    let claimed = sqlx::query_scalar::<_, Uuid>(
+        r#"
+        INSERT INTO file_share_renders (document_hash, job_id) VALUES ($1, $2)
+        ON CONFLICT (document_hash) DO UPDATE SET job_id = EXCLUDED.job_id, created_at = NOW()
+        WHERE (SELECT status::text FROM compilation_jobs WHERE id = file_share_renders.job_id)
+            IN ('error', 'cancelled')
+        RETURNING job_id
+        "#
+    )
+    .bind(&hash)
+    .bind(job.id)
+    .fetch_optional(&mut *tx)
+    .await
+    .map_err(AppError::Database)?;
+
+    if claimed.is_none() {
+        tx.rollback().await.map_err(AppError::Database)?;
+        return find_render(db, &hash).await?.ok_or_else(|| {
+            AppError::Internal(format!("Standalone compile of {} disappeared", hash))
+        });
+    }
+    tx.commit().await.map_err(AppError::Database)?;
+    Ok(job)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{create_test_file, create_test_project, create_test_user, TestDb};
+
+    #[test]
+    fn test_standalone_document_hides_other_files() {
+        let fragment = "\\section{Results}\n\\input{tables/summary}\nSee \\include{appendix} and\n\\input figures/plot.tex\n\\includegraphics[width=\\linewidth]{img/a_b.png}\n";
+        let document = standalone_document(fragment);
+        assert!(document.starts_with("\\documentclass{article}\n\\begin{document}\n\\section{Results}"));
+        assert!(document.ends_with("\\end{document}\n"));
+        assert!(document.contains("\\fbox{\\texttt{\\detokenize{tables/summary}} (not shared)}"));
+        assert!(document.contains("\\fbox{\\texttt{\\detokenize{appendix}} (not shared)}"));
+        assert!(document.contains("\\fbox{\\texttt{\\detokenize{figures/plot.tex}} (not shared)}"));
+        assert!(document.contains("\\fbox{\\texttt{\\detokenize{img/a_b.png}} (not shared)}"));
+        assert!(!document.contains("\\input{") && !document.contains("\\include{"));
+
+        // Full documents keep their preamble; look-alike commands stay
+        let full = "% \\documentclass{book} in a comment does not count\n\\documentclass{report}\n\\usepackage[utf8]{inputenc}\n\\includeonly{one}\n\\begin{document}\n\\import{parts/}{one}\n\\end{document}\n";
+        let document = standalone_document(full);
+        assert!(document.starts_with("% \\documentclass{book}"));
+        assert!(document.contains("\\usepackage[utf8]{inputenc}\n\\includeonly{one}"));
+        assert!(document.contains("\\fbox{\\texttt{\\detokenize{one}} (not shared)}"));
+        assert!(!has_document_class("% \\documentclass{article}\nText"));
+    }
+
+    #[tokio::test]
+    async fn test_links_follow_the_file() {
+        let Some(db) = TestDb::start().await else { return };
+        let owner = create_test_user(&db.pool).await;
+        let project = create_test_project(&db.pool, &owner, false).await;
+        let file = create_test_file(&db.pool, &project, &owner).await;
+
+        let past = Utc::now() - chrono::Duration::minutes(1);
+        let expired = CreateFileShareLink { mode: FileShareMode::Source, expires_at: Some(past) };
+        assert!(matches!(FileShareLink::create(&db.pool, &file, owner.id, expired).await, Err(AppError::Validation(_))));
+
+        let created = FileShareLink::create(&db.pool, &file, owner.id, CreateFileShareLink::default()).await.unwrap();
+        assert_eq!(created.share_url, format!("/api/v1/shared/files/{}", created.token));
+        let (link, shared) = FileShareLink::use_token(&db.pool, &created.token).await.unwrap().unwrap();
+        assert_eq!((link.hit_count, shared.id), (1, file.id));
+        assert!(FileShareLink::use_token(&db.pool, "not-a-token").await.unwrap().is_none());
+
+        assert!(FileShareLink::revoke(&db.pool, file.id, link.id).await.unwrap());
+        assert!(FileShareLink::use_token(&db.pool, &created.token).await.unwrap().is_none());
+        assert!(!FileShareLink::revoke(&db.pool, Uuid::new_v4(), link.id).await.unwrap());
+
+        let other = FileShareLink::create(&db.pool, &file, owner.id, CreateFileShareLink::default()).await.unwrap();
+        file.soft_delete(&db.pool, owner.id).await.unwrap();
+        assert!(FileShareLink::use_token(&db.pool, &other.token).await.unwrap().is_none());
+        assert_eq!(FileShareLink::list(&db.pool, file.id).await.unwrap().len(), 2);
+    }
+}
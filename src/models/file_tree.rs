@@ -0,0 +1,412 @@
+//! Lazily loaded project file trees
+//!
+//! Directories only exist implicitly as prefixes of file paths, so a listing
+//! aggregates the files under a prefix in SQL and returns one level (or a
+//! few, up to `MAX_TREE_DEPTH`) instead of materializing the whole project.
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::FromRow;
+use std::collections::HashMap;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use super::ContentType;
+
+/// Deepest expansion a single listing may request
+pub const MAX_TREE_DEPTH: u32 = 5;
+
+/// A file or directory in a directory listing
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct TreeEntry {
+    pub name: String,
+    pub path: String,
+    pub is_directory: bool,
+    /// File ID; `None` for directories
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<Uuid>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content_type: Option<ContentType>,
+    /// File size, or the total size of all files under a directory
+    pub size: i64,
+    pub modified_at: DateTime<Utc>,
+    /// Immediate children of a directory
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub child_count: Option<i64>,
+    /// All files under a directory, at any depth
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file_count: Option<i64>,
+    /// Changes whenever anything under the directory changes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fingerprint: Option<String>,
+    /// Children of an expanded directory; `None` when it must be fetched lazily
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(no_recursion)]
+    pub children: Option<Vec<TreeEntry>>,
+}
+
+/// One page of a directory listing
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct DirectoryListing {
+    pub path: String,
+    pub depth: u32,
+    pub fingerprint: String,
+    pub file_count: i64,
+    pub total_size: i64,
+    pub entries: Vec<TreeEntry>,
+    pub pagination: super::PaginationInfo,
+}
+
+/// Aggregate over every file under a prefix
+#[derive(Debug, Clone, FromRow)]
+struct PrefixSummary {
+    file_count: i64,
+    total_size: i64,
+    last_modified: Option<DateTime<Utc>>,
+}
+
+/// A directory under the listed prefix, relative to it
+#[derive(Debug, Clone, FromRow)]
+struct DirectoryRow {
+    rel_path: String,
+    child_count: i64,
+    file_count: i64,
+    total_size: i64,
+    last_modified: DateTime<Utc>,
+}
+
+/// A file under the listed prefix, relative to it
+#[derive(Debug, Clone, FromRow)]
+struct FileRow {
+    id: Uuid,
+    rel_path: String,
+    content_type: ContentType,
+    size: i64,
+    last_modified: DateTime<Utc>,
+}
+
+/// Matches the live files of a project under `$2` (a LIKE pattern)
+const FILES_UNDER_PREFIX: &str = r#"
+    FROM files
+    WHERE project_id = $1 AND is_deleted = false AND path LIKE $2 ESCAPE '\'
+"#;
+
+/// List a directory of a project, expanding subdirectories down to `depth`.
+///
+/// `path` must already be normalized with `normalize_directory`. Pagination
+/// applies to the immediate children of `path` only. Returns `None` when no
+/// live file exists under a non-root directory.
+pub async fn list_directory(
+    db: &sqlx::PgPool,
+    project_id: Uuid,
+    path: &str,
+    depth: u32,
+    params: &super::PaginationParams,
+) -> Result<Option<DirectoryListing>, crate::error::AppError> {
+    let pattern = format!("{}%", escape_like(path));
+    // substr() is 1-based, so this strips the prefix from each path
+    let rel_start = path.chars().count() as i32 + 1;
+    let depth = depth.clamp(1, MAX_TREE_DEPTH);
+
+    let summary = summarize(db, project_id, &pattern).await?;
+    if summary.file_count == 0 && path != "/" {
+        return Ok(None);
+    }
+
+    let directories = sqlx::query_as::<_, DirectoryRow>(&format!(
+        r#"
+        WITH scoped AS (
+            SELECT size, updated_at, string_to_array(substr(path, $3), '/') AS segments
+            {}
+        )
+        SELECT array_to_string(segments[1:level], '/') AS rel_path,
+               COUNT(DISTINCT segments[level + 1]) AS child_count,
+               COUNT(*) AS file_count,
+               COALESCE(SUM(size), 0)::BIGINT AS total_size,
+               MAX(updated_at) AS last_modified
+        FROM scoped
+        CROSS JOIN LATERAL generate_series(1, LEAST(array_length(segments, 1) - 1, $4)) AS level
+        GROUP BY 1
+        "#,
+        FILES_UNDER_PREFIX
+    ))
+    .bind(project_id)
+    .bind(&pattern)
+    .bind(rel_start)
+    .bind(depth as i32)
+    .fetch_all(db)
+    .await
+    .map_err(crate::error::AppError::Database)?;
+
+    let files = sqlx::query_as::<_, FileRow>(&format!(
+        r#"
+        SELECT id, substr(path, $3) AS rel_path, content_type, size, last_modified
+        {}
+        AND array_length(string_to_array(substr(path, $3), '/'), 1) <= $4
+        "#,
+        FILES_UNDER_PREFIX
+    ))
+    .bind(project_id)
+    .bind(&pattern)
+    .bind(rel_start)
+    .bind(depth as i32)
+    .fetch_all(db)
+    .await
+    .map_err(crate::error::AppError::Database)?;
+
+    let mut entries = build_entries(path, depth, directories, files);
+
+    let total = entries.len() as u64;
+    let start = (params.offset() as usize).min(entries.len());
+    let end = (start + params.limit() as usize).min(entries.len());
+    let entries: Vec<TreeEntry> = entries.drain(start..end).collect();
+    let page = super::PaginatedResponse::new(entries, params, total);
+
+    Ok(Some(DirectoryListing {
+        path: path.to_string(),
+        depth,
+        fingerprint: fingerprint(summary.last_modified, summary.file_count),
+        file_count: summary.file_count,
+        total_size: summary.total_size,
+        entries: page.data,
+        pagination: page.pagination,
+    }))
+}
+
+/// Change marker of a directory, as a listing of it would report it
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct DirectoryFingerprint {
+    pub path: String,
+    pub fingerprint: String,
+    /// Zero once the last file under the directory is gone
+    pub file_count: i64,
+    pub total_size: i64,
+}
+
+/// Fingerprint of the directory `path`, normalized with `normalize_directory`
+pub async fn directory_fingerprint(
+    db: &sqlx::PgPool,
+    project_id: Uuid,
+    path: &str,
+) -> Result<DirectoryFingerprint, crate::error::AppError> {
+    let summary = summarize(db, project_id, &format!("{}%", escape_like(path))).await?;
+
+    Ok(DirectoryFingerprint {
+        path: path.to_string(),
+        fingerprint: fingerprint(summary.last_modified, summary.file_count),
+        file_count: summary.file_count,
+        total_size: summary.total_size,
+    })
+}
+
+async fn summarize(db: &sqlx::PgPool, project_id: Uuid, pattern: &str) -> Result<PrefixSummary, crate::error::AppError> {
+    sqlx::query_as::<_, PrefixSummary>(&format!(
+        r#"
+        SELECT COUNT(*) AS file_count,
+               COALESCE(SUM(size), 0)::BIGINT AS total_size,
+               MAX(updated_at) AS last_modified
+        {}
+        "#,
+        FILES_UNDER_PREFIX
+    ))
+    .bind(project_id)
+    .bind(pattern)
+    .fetch_one(db)
+    .await
+    .map_err(crate::error::AppError::Database)
+}
+
+/// Count the live files of a project
+pub async fn count_files(db: &sqlx::PgPool, project_id: Uuid) -> Result<i64, crate::error::AppError> {
+    let count = sqlx::query_scalar::<_, i64>(
+        "SELECT COUNT(*) FROM files WHERE project_id = $1 AND is_deleted = false"
+    )
+    .bind(project_id)
+    .fetch_one(db)
+    .await
+    .map_err(crate::error::AppError::Database)?;
+
+    Ok(count)
+}
+
+/// Turn a requested directory into the prefix its files share: `/` for the
+/// root, otherwise `/a/b/`. Rejects relative paths and `.`/`..` segments.
+pub fn normalize_directory(path: Option<&str>) -> Option<String> {
+    let path = path.unwrap_or("/").trim();
+    if !path.starts_with('/') {
+        return None;
+    }
+
+    let segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+    if segments.iter().any(|s| *s == "." || *s == "..") {
+        return None;
+    }
+
+    if segments.is_empty() {
+        Some("/".to_string())
+    } else {
+        Some(format!("/{}/", segments.join("/")))
+    }
+}
+
+/// The directory holding the file at `path`, in the form `normalize_directory` returns
+pub fn parent_directory(path: &str) -> String {
+    match path.rfind('/') {
+        Some(end) if end > 0 => path[..=end].to_string(),
+        _ => "/".to_string(),
+    }
+}
+
+/// Cheap change marker for everything under a prefix.
+///
+/// Includes the file count so deleting a file changes it even when the
+/// newest remaining file is untouched.
+pub fn fingerprint(last_modified: Option<DateTime<Utc>>, file_count: i64) -> String {
+    let modified = last_modified.map_or(0, |t| t.timestamp_micros());
+    format!("{:x}-{:x}", modified, file_count)
+}
+
+/// Escape `value` for use inside a LIKE pattern
+pub(crate) fn escape_like(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+/// Assemble the flat directory and file rows into nested entries
+fn build_entries(
+    prefix: &str,
+    depth: u32,
+    directories: Vec<DirectoryRow>,
+    files: Vec<FileRow>,
+) -> Vec<TreeEntry> {
+    let mut by_parent: HashMap<String, Vec<(String, TreeEntry)>> = HashMap::new();
+
+    for dir in directories {
+        let (parent, name) = split_relative(&dir.rel_path);
+        let level = dir.rel_path.split('/').count() as u32;
+        let entry = TreeEntry {
+            name: name.to_string(),
+            path: format!("{}{}", prefix, dir.rel_path),
+            is_directory: true,
+            id: None,
+            content_type: None,
+            size: dir.total_size,
+            modified_at: dir.last_modified,
+            child_count: Some(dir.child_count),
+            file_count: Some(dir.file_count),
+            fingerprint: Some(fingerprint(Some(dir.last_modified), dir.file_count)),
+            // Marked expandable here; filled in below when within depth
+            children: (level < depth).then(Vec::new),
+        };
+        by_parent.entry(parent.to_string()).or_default().push((dir.rel_path, entry));
+    }
+
+    for file in files {
+        let (parent, name) = split_relative(&file.rel_path);
+        let entry = TreeEntry {
+            name: name.to_string(),
+            path: format!("{}{}", prefix, file.rel_path),
+            is_directory: false,
+            id: Some(file.id),
+            content_type: Some(file.content_type),
+            size: file.size,
+            modified_at: file.last_modified,
+            child_count: None,
+            file_count: None,
+            fingerprint: None,
+            children: None,
+        };
+        by_parent.entry(parent.to_string()).or_default().push((file.rel_path, entry));
+    }
+
+    take_children("", &mut by_parent)
+}
+
+fn take_children(parent: &str, by_parent: &mut HashMap<String, Vec<(String, TreeEntry)>>) -> Vec<TreeEntry> {
+    let Some(children) = by_parent.remove(parent) else {
+        return Vec::new();
+    };
+
+    let mut entries: Vec<TreeEntry> = children
+        .into_iter()
+        .map(|(rel_path, mut entry)| {
+            if entry.children.is_some() {
+                entry.children = Some(take_children(&rel_path, by_parent));
+            }
+            entry
+        })
+        .collect();
+
+    // Directories first, then by name
+    entries.sort_by(|a, b| b.is_directory.cmp(&a.is_directory).then_with(|| a.name.cmp(&b.name)));
+    entries
+}
+
+fn split_relative(rel_path: &str) -> (&str, &str) {
+    rel_path.rsplit_once('/').unwrap_or(("", rel_path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_directory() {
+        assert_eq!(normalize_directory(None), Some("/".to_string()));
+        assert_eq!(normalize_directory(Some("/figures")), Some("/figures/".to_string()));
+        assert_eq!(normalize_directory(Some("/figures//plots/")), Some("/figures/plots/".to_string()));
+        assert_eq!(normalize_directory(Some("figures")), None);
+        assert_eq!(normalize_directory(Some("/figures/../secret")), None);
+    }
+
+    #[test]
+    fn test_parent_directory() {
+        assert_eq!(parent_directory("/main.tex"), "/");
+        assert_eq!(parent_directory("/chapters/intro/part.tex"), "/chapters/intro/");
+        assert_eq!(parent_directory("main.tex"), "/");
+    }
+
+    #[test]
+    fn test_build_entries_nests_within_depth() {
+        let now = Utc::now();
+        let dir = |rel_path: &str, child_count: i64| DirectoryRow {
+            rel_path: rel_path.to_string(),
+            child_count,
+            file_count: child_count,
+            total_size: 10,
+            last_modified: now,
+        };
+        let file = |rel_path: &str| FileRow {
+            id: Uuid::new_v4(),
+            rel_path: rel_path.to_string(),
+            content_type: ContentType::Latex,
+            size: 5,
+            last_modified: now,
+        };
+
+        let entries = build_entries(
+            "/",
+            2,
+            vec![dir("figures", 1), dir("figures/plots", 2), dir("sections", 1)],
+            vec![file("main.tex"), file("figures/logo.png"), file("sections/intro.tex")],
+        );
+
+        let names: Vec<&str> = entries.iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(names, vec!["figures", "sections", "main.tex"]);
+
+        let figures = entries[0].children.as_ref().unwrap();
+        assert_eq!(figures[0].path, "/figures/plots");
+        // Depth 2 reaches plots but does not expand it
+        assert!(figures[0].children.is_none());
+        assert_eq!(figures[1].path, "/figures/logo.png");
+    }
+
+    #[test]
+    fn test_fingerprint_tracks_count() {
+        let now = Utc::now();
+        assert_ne!(fingerprint(Some(now), 3), fingerprint(Some(now), 2));
+        assert_eq!(fingerprint(None, 0), "0-0");
+    }
+}
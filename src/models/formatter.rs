@@ -0,0 +1,1058 @@
+//! Formatting LaTeX sources
+//!
+//! `POST /files/{id}/format` proposes a formatted version of a file, or of a
+//! range of its lines, as hunks against the current content; nothing is saved
+//! unless the caller asks for it. With `LATEXINDENT_PATH` set the proposal
+//! comes from latexindent, run with the project's [`FormatSettings`].
+//! Otherwise, or when latexindent fails, the built-in formatter is used. It
+//! is deliberately conservative and only
+//!
+//! - indents environment bodies, `\item`s and their continuation lines,
+//! - trims trailing whitespace,
+//! - puts one blank line before sectioning commands and drops the spaces
+//!   between the command and its arguments,
+//! - and, when enabled, wraps long lines of running text.
+//!
+//! Lines that start inside an open brace group keep their indentation, and
+//! line endings are kept as they are.
+//!
+//! Verbatim-like environments are never touched by either formatter: their
+//! lines come back byte for byte, and a latexindent result that changed them
+//! is discarded. Formatting the formatter's own output changes nothing.
+
+use serde::{Deserialize, Serialize};
+use std::ops::RangeInclusive;
+use std::path::Path;
+use std::process::Stdio;
+use std::time::Duration;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use super::compile_diff::{lcs_ops, DiffOp, MAX_WORD_DIFF_CELLS};
+use crate::error::AppError;
+
+/// Environments whose content is never formatted, starred variants included
+pub const VERBATIM_ENVIRONMENTS: [&str; 9] = [
+    "verbatim",
+    "Verbatim",
+    "BVerbatim",
+    "LVerbatim",
+    "lstlisting",
+    "minted",
+    "comment",
+    "alltt",
+    "filecontents",
+];
+
+/// Environments whose `\item`s start a new indentation level
+const LIST_ENVIRONMENTS: [&str; 3] = ["itemize", "enumerate", "description"];
+
+/// Environments whose body stays at the enclosing indentation
+const UNINDENTED_ENVIRONMENTS: [&str; 1] = ["document"];
+
+const SECTION_COMMANDS: [&str; 7] = [
+    "part",
+    "chapter",
+    "section",
+    "subsection",
+    "subsubsection",
+    "paragraph",
+    "subparagraph",
+];
+
+pub const MAX_INDENT_WIDTH: u8 = 8;
+
+pub const LINE_WIDTHS: RangeInclusive<u16> = 40..=200;
+
+pub const MAX_SKIP_ENVIRONMENTS: usize = 50;
+
+/// How long latexindent may run before the built-in formatter takes over
+const LATEXINDENT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How a project's files are formatted
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(default)]
+pub struct FormatSettings {
+    /// Spaces per indentation level, at most 8
+    pub indent_width: u8,
+    /// Re-wrap running text longer than `line_width`
+    pub wrap_lines: bool,
+    /// Column to wrap at, 40 to 200
+    pub line_width: u16,
+    /// Environments left as they are, next to the verbatim-like ones
+    ///
+    /// A name without `*` also covers its starred variant.
+    pub skip_environments: Vec<String>,
+}
+
+impl Default for FormatSettings {
+    fn default() -> Self {
+        Self {
+            indent_width: 2,
+            wrap_lines: false,
+            line_width: 80,
+            skip_environments: Vec::new(),
+        }
+    }
+}
+
+impl FormatSettings {
+    pub fn validate(&self) -> Result<(), AppError> {
+        if self.indent_width > MAX_INDENT_WIDTH {
+            return Err(AppError::Validation(format!(
+                "Indent width must be at most {}",
+                MAX_INDENT_WIDTH
+            )));
+        }
+
+        if !LINE_WIDTHS.contains(&self.line_width) {
+            return Err(AppError::Validation(format!(
+                "Line width must be between {} and {}",
+                LINE_WIDTHS.start(),
+                LINE_WIDTHS.end()
+            )));
+        }
+
+        if self.skip_environments.len() > MAX_SKIP_ENVIRONMENTS {
+            return Err(AppError::Validation(format!(
+                "At most {} environments can be skipped",
+                MAX_SKIP_ENVIRONMENTS
+            )));
+        }
+
+        if let Some(name) = self.skip_environments.iter().find(|name| {
+            let base = name.strip_suffix('*').unwrap_or(name);
+            base.is_empty() || !base.chars().all(|c| c.is_ascii_alphabetic())
+        }) {
+            return Err(AppError::Validation(format!(
+                "Invalid environment name '{}': expected letters, optionally followed by '*'",
+                name
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Whether the content of environment `name` is left as it is
+    fn skips(&self, name: &str) -> bool {
+        let base = name.trim_end_matches('*');
+        VERBATIM_ENVIRONMENTS.contains(&base)
+            || self.skip_environments.iter().any(|skipped| skipped == name || skipped == base)
+    }
+}
+
+/// Formatter a proposal came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Formatter {
+    Builtin,
+    Latexindent,
+}
+
+/// Consecutive lines a proposal replaces
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct FormatHunk {
+    /// First replaced line of the current content, 1-based; for a pure
+    /// insertion, the line the new lines go before
+    pub start_line: usize,
+    pub removed: Vec<String>,
+    pub added: Vec<String>,
+}
+
+/// Proposed formatting of a file
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct FormatProposal {
+    pub formatter: Formatter,
+    /// Whether formatting would change the file
+    pub changed: bool,
+    pub hunks: Vec<FormatHunk>,
+    /// The whole file after formatting
+    pub content: String,
+}
+
+impl FormatProposal {
+    fn new(formatter: Formatter, before: &str, after: String) -> Self {
+        let old: Vec<&str> = split_lines(before).0.iter().map(|line| line.text).collect();
+        let new: Vec<&str> = split_lines(&after).0.iter().map(|line| line.text).collect();
+        Self {
+            formatter,
+            changed: before != after,
+            hunks: line_hunks(&old, &new),
+            content: after,
+        }
+    }
+}
+
+/// Lines of a file to format, 1-based and inclusive
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LineRange {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl LineRange {
+    /// Lines `start` to `end` of `content`
+    pub fn new(content: &str, start: usize, end: usize) -> Result<Self, AppError> {
+        let count = split_lines(content).0.len();
+        if start == 0 || start > end || end > count {
+            return Err(AppError::Validation(format!(
+                "Invalid line range {}-{}: the file has {} lines",
+                start, end, count
+            )));
+        }
+        Ok(Self { start, end })
+    }
+
+    fn contains(&self, line: usize) -> bool {
+        (self.start..=self.end).contains(&line)
+    }
+}
+
+/// Format `content`, or the lines in `range`, and describe the changes
+///
+/// Uses latexindent at `latexindent` when given, working in a scratch
+/// directory under `temp_root`, and the built-in formatter otherwise.
+pub async fn propose(
+    content: &str,
+    settings: &FormatSettings,
+    range: Option<LineRange>,
+    latexindent: Option<&str>,
+    temp_root: &str,
+) -> FormatProposal {
+    if let Some(binary) = latexindent {
+        match run_latexindent(binary, content, settings, range, temp_root).await {
+            Ok(formatted) if protected_lines(&formatted, settings) == protected_lines(content, settings) => {
+                return FormatProposal::new(Formatter::Latexindent, content, formatted);
+            }
+            Ok(_) => tracing::warn!("latexindent changed a verbatim environment, using the built-in formatter"),
+            Err(e) => tracing::warn!("latexindent failed, using the built-in formatter: {}", e),
+        }
+    }
+
+    FormatProposal::new(Formatter::Builtin, content, format_builtin(content, settings, range))
+}
+
+/// Format `content`, or the lines in `range`, with the built-in formatter
+pub fn format_builtin(content: &str, settings: &FormatSettings, range: Option<LineRange>) -> String {
+    let (lines, final_newline) = split_lines(content);
+    let mut builtin = Builtin::new(settings);
+    for line in &lines {
+        builtin.push(line.text);
+    }
+
+    let mut formatted = Vec::with_capacity(lines.len());
+    for (index, (line, output)) in lines.iter().zip(builtin.outputs).enumerate() {
+        if range.is_some_and(|range| !range.contains(index + 1)) {
+            formatted.push((line.text.to_string(), line.cr));
+        } else {
+            formatted.extend(output.into_iter().map(|text| (text, line.cr)));
+        }
+    }
+    join_lines(&formatted, final_newline)
+}
+
+/// Lines inside verbatim-like and skipped environments, `\end` included
+fn protected_lines(content: &str, settings: &FormatSettings) -> Vec<String> {
+    let mut builtin = Builtin::new(settings);
+    for line in &split_lines(content).0 {
+        builtin.push(line.text);
+    }
+    builtin.protected
+}
+
+/// A line without its terminator
+struct Line<'a> {
+    text: &'a str,
+    /// Terminated by `\r\n` rather than `\n`
+    cr: bool,
+}
+
+/// Split `content` into lines, and whether it ends with a newline
+fn split_lines(content: &str) -> (Vec<Line<'_>>, bool) {
+    let final_newline = content.ends_with('\n');
+    let body = content.strip_suffix('\n').unwrap_or(content);
+    let lines = body
+        .split('\n')
+        .map(|text| match text.strip_suffix('\r') {
+            Some(text) => Line { text, cr: true },
+            None => Line { text, cr: false },
+        })
+        .collect();
+    (lines, final_newline)
+}
+
+fn join_lines(lines: &[(String, bool)], final_newline: bool) -> String {
+    let mut content = String::new();
+    for (index, (text, cr)) in lines.iter().enumerate() {
+        content.push_str(text);
+        if index + 1 < lines.len() || final_newline {
+            content.push_str(if *cr { "\r\n" } else { "\n" });
+        }
+    }
+    content
+}
+
+/// Line-level diff of `old` and `new` as hunks
+fn line_hunks(old: &[&str], new: &[&str]) -> Vec<FormatHunk> {
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let old_mid = &old[prefix..old.len() - suffix];
+    let new_mid = &new[prefix..new.len() - suffix];
+
+    let cells = (old_mid.len() + 1).saturating_mul(new_mid.len() + 1);
+    let ops = if cells > MAX_WORD_DIFF_CELLS {
+        let mut ops: Vec<(DiffOp, &str)> = old_mid.iter().map(|line| (DiffOp::Delete, *line)).collect();
+        ops.extend(new_mid.iter().map(|line| (DiffOp::Insert, *line)));
+        ops
+    } else {
+        lcs_ops(old_mid, new_mid)
+    };
+
+    let mut hunks = Vec::new();
+    let mut current: Option<FormatHunk> = None;
+    let mut line = prefix + 1;
+    for (op, text) in ops {
+        match op {
+            DiffOp::Equal => {
+                hunks.extend(current.take());
+                line += 1;
+            }
+            DiffOp::Delete => {
+                current
+                    .get_or_insert_with(|| FormatHunk { start_line: line, removed: Vec::new(), added: Vec::new() })
+                    .removed
+                    .push(text.to_string());
+                line += 1;
+            }
+            DiffOp::Insert => {
+                current
+                    .get_or_insert_with(|| FormatHunk { start_line: line, removed: Vec::new(), added: Vec::new() })
+                    .added
+                    .push(text.to_string());
+            }
+        }
+    }
+    hunks.extend(current);
+    hunks
+}
+
+/// Open group the indentation of a line depends on
+enum Frame {
+    Environment { name: String, indents: bool },
+    Item,
+}
+
+/// Structural command found on a line
+enum Event {
+    Begin(String),
+    End(String),
+    Item,
+}
+
+/// The built-in formatter, fed one line at a time
+struct Builtin<'s> {
+    settings: &'s FormatSettings,
+    stack: Vec<Frame>,
+    /// Skipped environment the next line is in
+    raw: Option<String>,
+    /// Unclosed `{` before the next line
+    depth: usize,
+    /// Formatted lines for each input line; blank lines before a section
+    /// heading may end up with none
+    outputs: Vec<Vec<String>>,
+    protected: Vec<String>,
+}
+
+impl<'s> Builtin<'s> {
+    fn new(settings: &'s FormatSettings) -> Self {
+        Self {
+            settings,
+            stack: Vec::new(),
+            raw: None,
+            depth: 0,
+            outputs: Vec::new(),
+            protected: Vec::new(),
+        }
+    }
+
+    fn level(&self) -> usize {
+        self.stack
+            .iter()
+            .filter(|frame| match frame {
+                Frame::Environment { indents, .. } => *indents,
+                Frame::Item => true,
+            })
+            .count()
+    }
+
+    fn in_list(&self) -> bool {
+        matches!(
+            self.stack.last(),
+            Some(Frame::Environment { name, .. }) if LIST_ENVIRONMENTS.contains(&name.as_str())
+        )
+    }
+
+    fn push(&mut self, line: &str) {
+        let output = self.format_line(line);
+        self.outputs.push(output);
+    }
+
+    fn format_line(&mut self, line: &str) -> Vec<String> {
+        if let Some(name) = &self.raw {
+            if line.contains(&format!("\\end{{{}}}", name)) {
+                self.raw = None;
+            }
+            self.protected.push(line.to_string());
+            return vec![line.to_string()];
+        }
+
+        let text = trim_trailing(line.trim_start_matches([' ', '\t']));
+        if text.is_empty() {
+            return vec![String::new()];
+        }
+
+        let code = code_of(text);
+        let start_level = self.level();
+        let start_depth = self.depth;
+        let mut line_level = None;
+        let mut leading = true;
+        let mut has_events = false;
+        let mut raw_from = None;
+        let mut last_end = 0;
+        for (start, end, event) in scan(&code) {
+            has_events = true;
+            if !code[last_end..start].trim().is_empty() {
+                leading = false;
+            }
+            last_end = end;
+            match event {
+                Event::Begin(name) => {
+                    leading = false;
+                    if self.settings.skips(&name) {
+                        if !text[end..].contains(&format!("\\end{{{}}}", name)) {
+                            self.raw = Some(name);
+                        }
+                        raw_from = Some(start);
+                        break;
+                    }
+                    let indents = !UNINDENTED_ENVIRONMENTS.contains(&name.as_str());
+                    self.stack.push(Frame::Environment { name, indents });
+                }
+                Event::End(name) => {
+                    let open = self.stack.iter().rposition(|frame| {
+                        matches!(frame, Frame::Environment { name: open, .. } if *open == name)
+                    });
+                    if let Some(open) = open {
+                        self.stack.truncate(open);
+                    }
+                    if leading {
+                        line_level = Some(self.level());
+                    }
+                }
+                Event::Item => {
+                    if matches!(self.stack.last(), Some(Frame::Item)) || self.in_list() {
+                        if matches!(self.stack.last(), Some(Frame::Item)) {
+                            self.stack.pop();
+                        }
+                        if leading {
+                            line_level = Some(self.level());
+                        }
+                        self.stack.push(Frame::Item);
+                    }
+                    leading = false;
+                }
+            }
+        }
+        self.depth = brace_depth(start_depth, &code[..raw_from.unwrap_or(code.len())]);
+
+        let indent = if start_depth > 0 {
+            line[..line.len() - line.trim_start_matches([' ', '\t']).len()].to_string()
+        } else {
+            " ".repeat(line_level.unwrap_or(start_level) * usize::from(self.settings.indent_width))
+        };
+
+        // The rest of a line opening a verbatim environment is left alone
+        if raw_from.is_some() {
+            return vec![format!("{}{}", indent, line.trim_start_matches([' ', '\t']))];
+        }
+
+        if start_depth == 0 {
+            if let Some(heading) = normalize_section(text) {
+                let mut output = Vec::new();
+                if self.separate_section() {
+                    output.push(String::new());
+                }
+                output.push(format!("{}{}", indent, heading));
+                return output;
+            }
+        }
+
+        let width = usize::from(self.settings.line_width);
+        let wrappable = self.settings.wrap_lines
+            && start_depth == 0
+            && !has_events
+            && code == text
+            && !text.contains("\\\\")
+            && !text.contains('&');
+        if wrappable && indent.len() + text.chars().count() > width {
+            return wrap(&indent, text, width);
+        }
+
+        vec![format!("{}{}", indent, text)]
+    }
+
+    /// Leave exactly one blank line before a section heading, unless it
+    /// follows a comment or starts the file; true when the heading has to
+    /// bring its own
+    fn separate_section(&mut self) -> bool {
+        let mut blanks = Vec::new();
+        let mut previous = None;
+        for (index, output) in self.outputs.iter().enumerate().rev() {
+            match output.as_slice() {
+                [] => continue,
+                [line] if line.is_empty() => blanks.push(index),
+                _ => {
+                    previous = output.last();
+                    break;
+                }
+            }
+        }
+
+        if previous.is_none_or(|line| line.trim_start().starts_with('%')) {
+            return false;
+        }
+        // Keep the first blank line and drop the others
+        if let Some((_, extra)) = blanks.split_last() {
+            for &index in extra {
+                self.outputs[index].clear();
+            }
+            return false;
+        }
+        true
+    }
+}
+
+/// Trim trailing spaces and tabs, keeping the one a control space needs
+fn trim_trailing(text: &str) -> &str {
+    let trimmed = text.trim_end_matches([' ', '\t']);
+    let backslashes = trimmed.len() - trimmed.trim_end_matches('\\').len();
+    if backslashes % 2 == 1 && trimmed.len() < text.len() {
+        &text[..trimmed.len() + 1]
+    } else {
+        trimmed
+    }
+}
+
+/// `text` without its comment, with inline verbatim arguments blanked out
+///
+/// Blanked bytes become spaces, so positions in the result match `text`.
+fn code_of(text: &str) -> String {
+    let mut code = String::with_capacity(text.len());
+    let mut i = 0;
+    while i < text.len() {
+        let rest = &text[i..];
+        if rest.starts_with('%') {
+            break;
+        }
+        if let Some(after) = rest.strip_prefix('\\') {
+            let name_len = after.find(|c: char| !c.is_ascii_alphabetic()).unwrap_or(after.len());
+            if name_len == 0 {
+                // A control symbol such as \% or \\ hides the next character
+                let len = 1 + after.chars().next().map_or(0, char::len_utf8);
+                code.push_str(&rest[..len]);
+                i += len;
+                continue;
+            }
+            code.push_str(&rest[..=name_len]);
+            i += 1 + name_len;
+            if matches!(&after[..name_len], "verb" | "lstinline") {
+                i += blank_inline_verbatim(&text[i..], &mut code);
+            }
+            continue;
+        }
+        let c = rest.chars().next().unwrap_or_default();
+        code.push(c);
+        i += c.len_utf8();
+    }
+    code
+}
+
+/// Copy the argument of `\verb` or `\lstinline` at the start of `rest` into
+/// `code` with its content blanked; returns the bytes consumed
+fn blank_inline_verbatim(rest: &str, code: &mut String) -> usize {
+    let mut i = 0;
+    if rest.starts_with('*') {
+        code.push('*');
+        i += 1;
+    }
+    if rest[i..].starts_with('[') {
+        if let Some(close) = rest[i..].find(']') {
+            code.push_str(&rest[i..=i + close]);
+            i += close + 1;
+        }
+    }
+    let Some(open) = rest[i..].chars().next() else {
+        return i;
+    };
+    let close = if open == '{' { '}' } else { open };
+    code.push(open);
+    i += open.len_utf8();
+
+    let content_len = rest[i..].find(close).unwrap_or(rest.len() - i);
+    code.extend(std::iter::repeat_n(' ', content_len));
+    i += content_len;
+    if i < rest.len() {
+        code.push(close);
+        i += close.len_utf8();
+    }
+    i
+}
+
+/// `\begin`, `\end` and `\item` commands in `code` with their byte spans
+fn scan(code: &str) -> Vec<(usize, usize, Event)> {
+    let mut events = Vec::new();
+    let mut i = 0;
+    while let Some(offset) = code[i..].find('\\') {
+        let start = i + offset;
+        let after = &code[start + 1..];
+        let name_len = after.find(|c: char| !c.is_ascii_alphabetic()).unwrap_or(after.len());
+        if name_len == 0 {
+            i = start + 1 + after.chars().next().map_or(0, char::len_utf8);
+            continue;
+        }
+        let mut end = start + 1 + name_len;
+        match &after[..name_len] {
+            "item" => events.push((start, end, Event::Item)),
+            name @ ("begin" | "end") => {
+                let argument = code[end..].trim_start_matches([' ', '\t']);
+                if let Some(argument) = argument.strip_prefix('{') {
+                    if let Some(close) = argument.find('}') {
+                        let environment = argument[..close].trim().to_string();
+                        end = code.len() - argument.len() + close + 1;
+                        events.push((
+                            start,
+                            end,
+                            if name == "begin" { Event::Begin(environment) } else { Event::End(environment) },
+                        ));
+                    }
+                }
+            }
+            _ => {}
+        }
+        i = end;
+    }
+    events
+}
+
+/// Brace depth after `code`, starting from `depth`
+fn brace_depth(mut depth: usize, code: &str) -> usize {
+    let mut escaped = false;
+    for c in code.chars() {
+        if escaped {
+            escaped = false;
+            continue;
+        }
+        match c {
+            '\\' => escaped = true,
+            '{' => depth += 1,
+            '}' => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+    }
+    depth
+}
+
+/// A sectioning command without spaces before its arguments, or `None` if
+/// `text` does not start with one
+fn normalize_section(text: &str) -> Option<String> {
+    let after = text.strip_prefix('\\')?;
+    let name_len = after.find(|c: char| !c.is_ascii_alphabetic()).unwrap_or(after.len());
+    let name = &after[..name_len];
+    if !SECTION_COMMANDS.contains(&name) {
+        return None;
+    }
+
+    let mut rest = after[name_len..].trim_start_matches([' ', '\t']);
+    let star = rest.starts_with('*');
+    if star {
+        rest = rest[1..].trim_start_matches([' ', '\t']);
+    }
+    if !rest.starts_with(['[', '{']) {
+        return None;
+    }
+    Some(format!("\\{}{}{}", name, if star { "*" } else { "" }, rest))
+}
+
+/// Greedily wrap the words of `text` at `width` columns
+///
+/// Never breaks after a control space, where the line end would change
+/// meaning.
+fn wrap(indent: &str, text: &str, width: usize) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut current = indent.to_string();
+    let mut empty = true;
+    let mut breakable = true;
+    for word in text.split_whitespace() {
+        if !empty && breakable && current.chars().count() + 1 + word.chars().count() > width {
+            lines.push(std::mem::replace(&mut current, indent.to_string()));
+            empty = true;
+        }
+        if !empty {
+            current.push(' ');
+        }
+        current.push_str(word);
+        empty = false;
+        breakable = (word.len() - word.trim_end_matches('\\').len()) % 2 == 0;
+    }
+    lines.push(current);
+    lines
+}
+
+/// latexindent configuration matching `settings`
+fn latexindent_settings(settings: &FormatSettings) -> String {
+    let mut yaml = format!(
+        "defaultIndent: \"{}\"\nnoAdditionalIndent:\n  document: 1\nverbatimEnvironments:\n",
+        " ".repeat(usize::from(settings.indent_width))
+    );
+    let skipped = VERBATIM_ENVIRONMENTS
+        .iter()
+        .flat_map(|name| [name.to_string(), format!("{}*", name)])
+        .chain(settings.skip_environments.iter().cloned());
+    for name in skipped {
+        yaml.push_str(&format!("  \"{}\": 1\n", name));
+    }
+    if settings.wrap_lines {
+        yaml.push_str(&format!("modifyLineBreaks:\n  textWrapOptions:\n    columns: {}\n", settings.line_width));
+    }
+    yaml
+}
+
+/// Format `content` with the latexindent binary at `binary`
+async fn run_latexindent(
+    binary: &str,
+    content: &str,
+    settings: &FormatSettings,
+    range: Option<LineRange>,
+    temp_root: &str,
+) -> Result<String, AppError> {
+    let dir = Path::new(temp_root).join(format!("format-{}", Uuid::new_v4()));
+    tokio::fs::create_dir_all(&dir).await?;
+    let result = latexindent_in(&dir, binary, content, settings, range).await;
+    if let Err(e) = tokio::fs::remove_dir_all(&dir).await {
+        tracing::warn!("Failed to remove {}: {}", dir.display(), e);
+    }
+    result
+}
+
+async fn latexindent_in(
+    dir: &Path,
+    binary: &str,
+    content: &str,
+    settings: &FormatSettings,
+    range: Option<LineRange>,
+) -> Result<String, AppError> {
+    tokio::fs::write(dir.join("input.tex"), content).await?;
+    tokio::fs::write(dir.join("settings.yaml"), latexindent_settings(settings)).await?;
+
+    let mut command = tokio::process::Command::new(binary);
+    command.args(["-s", "-g=indent.log", "-l=settings.yaml"]);
+    if settings.wrap_lines {
+        command.arg("-m");
+    }
+    if let Some(range) = range {
+        command.arg(format!("--lines={}-{}", range.start, range.end));
+    }
+    command
+        .arg("input.tex")
+        .current_dir(dir)
+        .stdin(Stdio::null())
+        .kill_on_drop(true);
+
+    let output = tokio::time::timeout(LATEXINDENT_TIMEOUT, command.output())
+        .await
+        .map_err(|_| AppError::Internal("latexindent timed out".to_string()))?
+        .map_err(|e| AppError::Internal(format!("Failed to run latexindent: {}", e)))?;
+    if !output.status.success() {
+        return Err(AppError::Internal(format!(
+            "latexindent exited with {}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+
+    String::from_utf8(output.stdout)
+        .map_err(|_| AppError::Internal("latexindent produced invalid UTF-8".to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A paper with the usual inconsistencies
+    const ARTICLE: &str = r#"\documentclass{article}
+\usepackage{listings}
+\begin{document}
+\section {Introduction}
+Some text with \verb|\begin{itemize}| and a 50\% share.
+    \begin{itemize}
+\item First
+continued here
+  \item Second
+\begin{enumerate}
+\item Nested
+\end{enumerate}
+\end{itemize}
+
+
+\subsection* {Details}
+\begin{verbatim}
+   keep   this
+\begin{itemize}
+	tabs stay
+\end{verbatim}
+A control space\
+% A comment before a heading
+\section{Method}
+\begin{figure}
+\caption{A caption spanning
+       two lines}
+\end{figure}
+\end{document}
+"#;
+
+    /// Listings and custom environments, with Windows line endings
+    const LISTINGS: &str = "\\begin{document}\r\n\\begin{lstlisting}[language=C]\r\n  int main() {   \r\n\r\n}\r\n\\end{lstlisting}\r\n\\begin{minted}{python}\r\ndef f():\r\n        return 1   \r\n\\end{minted}\r\n  \\begin{center}\r\n\\begin{tikzpicture}\r\n  \\draw (0,0) -- (1,1);\r\n    \\end{tikzpicture}\r\n\\end{center}\r\n\\begin{comment}\r\n\\section  {Draft}\r\n\\end{comment}\r\n\\end{document}";
+
+    /// Long running text, inline verbatim and an unclosed environment
+    const PROSE: &str = r#"\chapter{One}
+This paragraph is deliberately written as one very long line so that the wrapping option has something to do, with \emph{emphasis} and $x^2$ math.
+Short line.
+\lstinline{  spaced %  } stays, and so does \verb*|a  b|.
+\begin{quote}
+Quoted text that is long enough to be wrapped when the option is enabled by the project settings.
+\begin{Verbatim*}
+                                  An overlong verbatim line that must never be wrapped or reindented by the formatter.
+\end{Verbatim*}
+"#;
+
+    const FIXTURES: [&str; 3] = [ARTICLE, LISTINGS, PROSE];
+
+    fn variants() -> Vec<FormatSettings> {
+        vec![
+            FormatSettings::default(),
+            FormatSettings { indent_width: 4, ..FormatSettings::default() },
+            FormatSettings { indent_width: 0, ..FormatSettings::default() },
+            FormatSettings { wrap_lines: true, line_width: 40, ..FormatSettings::default() },
+            FormatSettings { skip_environments: vec!["tikzpicture".to_string()], ..FormatSettings::default() },
+        ]
+    }
+
+    /// Bodies of verbatim-like environments in `content`, `\end` included,
+    /// found without the formatter's own parsing
+    fn verbatim_blocks(content: &str) -> Vec<&str> {
+        let mut blocks = Vec::new();
+        for name in VERBATIM_ENVIRONMENTS.iter().flat_map(|name| [name.to_string(), format!("{}*", name)]) {
+            let (begin, end) = (format!("\\begin{{{}}}", name), format!("\\end{{{}}}", name));
+            let mut rest = content;
+            while let Some(start) = rest.find(&begin) {
+                let body = &rest[start + begin.len()..];
+                let body = &body[body.find('\n').map_or(body.len(), |newline| newline + 1)..];
+                let Some(close) = body.find(&end) else { break };
+                blocks.push(&body[..close + end.len()]);
+                rest = &body[close + end.len()..];
+            }
+        }
+        blocks
+    }
+
+    #[test]
+    fn test_formatting_is_idempotent() {
+        for settings in variants() {
+            for fixture in FIXTURES {
+                let once = format_builtin(fixture, &settings, None);
+                let twice = format_builtin(&once, &settings, None);
+                assert_eq!(once, twice, "settings {:?}", settings);
+            }
+        }
+    }
+
+    #[test]
+    fn test_verbatim_environments_are_untouched() {
+        for settings in variants() {
+            for fixture in FIXTURES {
+                let blocks = verbatim_blocks(fixture);
+                assert!(!blocks.is_empty());
+
+                let formatted = format_builtin(fixture, &settings, None);
+                for block in blocks {
+                    assert!(formatted.contains(block), "{:?} changed with {:?}", block, settings);
+                }
+                assert_eq!(protected_lines(&formatted, &settings), protected_lines(fixture, &settings));
+            }
+        }
+    }
+
+    #[test]
+    fn test_skipped_environments_are_untouched() {
+        let settings = FormatSettings { skip_environments: vec!["tikzpicture".to_string()], ..FormatSettings::default() };
+        let formatted = format_builtin(LISTINGS, &settings, None);
+        assert!(formatted.contains("  \\begin{tikzpicture}\r\n  \\draw (0,0) -- (1,1);\r\n    \\end{tikzpicture}\r\n"));
+
+        let formatted = format_builtin(LISTINGS, &FormatSettings::default(), None);
+        assert!(formatted.contains("  \\begin{tikzpicture}\r\n    \\draw (0,0) -- (1,1);\r\n  \\end{tikzpicture}\r\n"));
+    }
+
+    #[test]
+    fn test_article_layout() {
+        let formatted = format_builtin(ARTICLE, &FormatSettings::default(), None);
+        assert_eq!(
+            formatted,
+            r#"\documentclass{article}
+\usepackage{listings}
+\begin{document}
+
+\section{Introduction}
+Some text with \verb|\begin{itemize}| and a 50\% share.
+\begin{itemize}
+  \item First
+    continued here
+  \item Second
+    \begin{enumerate}
+      \item Nested
+    \end{enumerate}
+\end{itemize}
+
+\subsection*{Details}
+\begin{verbatim}
+   keep   this
+\begin{itemize}
+	tabs stay
+\end{verbatim}
+A control space\
+% A comment before a heading
+\section{Method}
+\begin{figure}
+  \caption{A caption spanning
+       two lines}
+\end{figure}
+\end{document}
+"#
+        );
+    }
+
+    #[test]
+    fn test_line_endings_are_kept() {
+        let formatted = format_builtin(LISTINGS, &FormatSettings::default(), None);
+        assert!(!formatted.ends_with('\n'));
+        assert!(!formatted.replace("\r\n", "").contains('\n'));
+        assert!(formatted.contains("\\begin{lstlisting}[language=C]\r\n  int main() {   \r\n\r\n}\r\n"));
+    }
+
+    #[test]
+    fn test_wraps_running_text_only() {
+        let settings = FormatSettings { wrap_lines: true, line_width: 40, ..FormatSettings::default() };
+        let formatted = format_builtin(PROSE, &settings, None);
+        let lines: Vec<&str> = formatted.lines().collect();
+
+        assert_eq!(lines[1], "This paragraph is deliberately written");
+        assert!(lines.contains(&"  Quoted text that is long enough to be"));
+        assert!(lines.contains(&"\\lstinline{  spaced %  } stays, and so does \\verb*|a  b|."));
+        for line in &lines {
+            let unwrappable = line.contains("overlong verbatim") || line.starts_with("\\lstinline");
+            assert!(unwrappable || line.chars().count() <= 40, "{:?}", line);
+        }
+        assert_eq!(formatted.replace('\n', " ").split_whitespace().collect::<Vec<_>>(), PROSE.split_whitespace().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_control_space_is_never_broken() {
+        let settings = FormatSettings { wrap_lines: true, line_width: 40, ..FormatSettings::default() };
+        let text = "A sentence ending in an abbreviation like etc.\\ and continuing afterwards.\n";
+        let formatted = format_builtin(text, &settings, None);
+        assert!(formatted.contains("etc.\\ and"));
+    }
+
+    #[test]
+    fn test_range_formats_only_selected_lines() {
+        let content = "\\begin{itemize}\n\\item One   \n\\item Two   \n\\end{itemize}\n";
+        let range = LineRange::new(content, 2, 2).unwrap();
+        let formatted = format_builtin(content, &FormatSettings::default(), Some(range));
+        assert_eq!(formatted, "\\begin{itemize}\n  \\item One\n\\item Two   \n\\end{itemize}\n");
+
+        assert!(LineRange::new(content, 0, 1).is_err());
+        assert!(LineRange::new(content, 3, 2).is_err());
+        assert!(LineRange::new(content, 1, 5).is_err());
+        assert!(LineRange::new(content, 1, 4).is_ok());
+    }
+
+    #[test]
+    fn test_hunks_describe_changes() {
+        let content = "\\begin{center}\nA\nB\n\\end{center}\n\\section {S}\n";
+        let proposal = FormatProposal::new(
+            Formatter::Builtin,
+            content,
+            format_builtin(content, &FormatSettings::default(), None),
+        );
+
+        assert!(proposal.changed);
+        assert_eq!(
+            proposal.hunks,
+            vec![
+                FormatHunk {
+                    start_line: 2,
+                    removed: vec!["A".to_string(), "B".to_string()],
+                    added: vec!["  A".to_string(), "  B".to_string()],
+                },
+                FormatHunk {
+                    start_line: 5,
+                    removed: vec!["\\section {S}".to_string()],
+                    added: vec![String::new(), "\\section{S}".to_string()],
+                },
+            ]
+        );
+
+        let again = FormatProposal::new(Formatter::Builtin, &proposal.content, proposal.content.clone());
+        assert!(!again.changed);
+        assert!(again.hunks.is_empty());
+    }
+
+    #[test]
+    fn test_settings_validation() {
+        assert!(FormatSettings::default().validate().is_ok());
+        assert!(FormatSettings { indent_width: 9, ..FormatSettings::default() }.validate().is_err());
+        assert!(FormatSettings { line_width: 20, ..FormatSettings::default() }.validate().is_err());
+        assert!(FormatSettings { skip_environments: vec!["align*".to_string()], ..FormatSettings::default() }
+            .validate()
+            .is_ok());
+        for name in ["", "*", "my env", "a\"b", "x**"] {
+            let settings = FormatSettings { skip_environments: vec![name.to_string()], ..FormatSettings::default() };
+            assert!(settings.validate().is_err(), "{:?}", name);
+        }
+
+        // Settings saved before a field existed still load
+        let settings: FormatSettings = serde_json::from_value(serde_json::json!({ "indent_width": 4 })).unwrap();
+        assert_eq!(settings, FormatSettings { indent_width: 4, ..FormatSettings::default() });
+    }
+
+    #[tokio::test]
+    async fn test_missing_latexindent_falls_back() {
+        let temp = tempfile::tempdir().unwrap();
+        let proposal = propose(
+            "\\begin{center}\nA\n\\end{center}\n",
+            &FormatSettings::default(),
+            None,
+            Some("/nonexistent/latexindent"),
+            temp.path().to_str().unwrap(),
+        )
+        .await;
+
+        assert_eq!(proposal.formatter, Formatter::Builtin);
+        assert_eq!(proposal.content, "\\begin{center}\n  A\n\\end{center}\n");
+        assert_eq!(std::fs::read_dir(temp.path()).unwrap().count(), 0);
+    }
+}
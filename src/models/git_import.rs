@@ -0,0 +1,967 @@
+//! Project imports from git repositories
+//!
+//! An import records a `git_imports` row and clones in the background; the
+//! requester polls the row for progress and is notified once it is done.
+//! Only https URLs of the hosts in `git_import.allowed_hosts` are cloned, and
+//! git runs without system or user configuration, hooks, credential helpers,
+//! redirects or protocols other than https, so an import cannot be pointed at
+//! internal servers. A token for a private repository reaches git through
+//! its environment for the one clone and is never stored.
+//!
+//! The clone is shallow and partial: commits and trees come down first, so
+//! the file count and total size are checked against the limits before any
+//! content is fetched, and only the files that pass are checked out.
+//! Symbolic links, submodules, files over `max_file_size`, paths matched by
+//! a `.texlerignore` and, unless imports are strict, files over a limit of
+//! the project are left out and listed on the import. Ignore patterns
+//! are globs relative to the imported directory, one per line, as the
+//! `exclude` patterns of a clone; lines starting with `#` are comments.
+//!
+//! The files go through the same path as archive imports, and the project
+//! records the URL and commit it came from. A failed import removes the
+//! project it had started.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use std::path::Path;
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use super::file::File;
+use super::notification::NotificationService;
+use super::project::{CreateProject, Project};
+use super::project_archive::{detect_main_file, ArchiveEntry};
+use super::project_clone::PathFilter;
+use super::project_limits::{ProjectLimit, ProjectLimits};
+use super::user::User;
+use super::validation::ProjectName;
+use crate::config::GitImportConfig;
+use crate::error::AppError;
+use crate::server::AppState;
+
+/// Patterns of paths left out of an import, in the imported directory
+pub const TEXLERIGNORE_NAME: &str = ".texlerignore";
+
+/// Imports not updated for this long were interrupted and are failed
+const STALE_IMPORT_MINUTES: i64 = 30;
+
+/// Finished imports are removed after this many days
+const IMPORT_RETENTION_DAYS: i64 = 30;
+
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(15 * 60);
+
+/// Files imported between two progress updates
+const PROGRESS_INTERVAL: usize = 20;
+
+/// Git import request
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct GitImportRequest {
+    /// https URL of the repository
+    pub repository_url: String,
+    pub workspace_id: Uuid,
+    /// Branch or tag to import; the repository's default branch when unset
+    pub reference: Option<String>,
+    /// Directory of the repository to import; the whole repository when unset
+    pub subdirectory: Option<String>,
+    /// Defaults to the repository's name
+    pub name: Option<String>,
+    /// Access token for a private repository; used for this clone only and never stored
+    pub token: Option<String>,
+}
+
+/// Stage of a git import
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type, ToSchema)]
+#[sqlx(type_name = "gitimportstatus")]
+pub enum GitImportStatus {
+    #[serde(rename = "pending")]
+    #[sqlx(rename = "pending")]
+    Pending,
+    #[serde(rename = "cloning")]
+    #[sqlx(rename = "cloning")]
+    Cloning,
+    #[serde(rename = "importing")]
+    #[sqlx(rename = "importing")]
+    Importing,
+    #[serde(rename = "ready")]
+    #[sqlx(rename = "ready")]
+    Ready,
+    #[serde(rename = "failed")]
+    #[sqlx(rename = "failed")]
+    Failed,
+}
+
+/// Why a file of the repository was not imported: a symbolic link, a
+/// submodule, larger than the instance allows, matched by the
+/// `.texlerignore`, or over a limit of the project
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SkipReason {
+    Symlink,
+    Submodule,
+    TooLarge,
+    Ignored,
+    OverLimit,
+}
+
+/// A file of the repository that was not imported
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct SkippedFile {
+    /// Path relative to the imported directory
+    pub path: String,
+    pub reason: SkipReason,
+    /// The limit the file went over, for `over_limit`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub limit: Option<ProjectLimit>,
+}
+
+/// An import from a git repository
+#[derive(Debug, Clone, Serialize, FromRow, ToSchema)]
+pub struct GitImport {
+    pub id: Uuid,
+    pub requested_by: Uuid,
+    pub workspace_id: Uuid,
+    pub repository_url: String,
+    pub reference: Option<String>,
+    pub subdirectory: Option<String>,
+    pub name: Option<String>,
+    pub status: GitImportStatus,
+    /// Commit imported, known once cloned
+    pub commit_hash: Option<String>,
+    /// Files to import, known once cloned
+    pub files_total: Option<i32>,
+    pub files_imported: i32,
+    pub bytes_total: Option<i64>,
+    #[schema(value_type = Vec<SkippedFile>)]
+    pub skipped: sqlx::types::Json<Vec<SkippedFile>>,
+    /// The project, once files are being imported into it
+    pub project_id: Option<Uuid>,
+    pub error: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    pub completed_at: Option<DateTime<Utc>>,
+}
+
+/// A git import with the URL its progress is served at
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct GitImportView {
+    #[serde(flatten)]
+    pub import: GitImport,
+    pub status_url: String,
+}
+
+/// A file in the repository's tree
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TreeEntry {
+    pub mode: String,
+    /// Path from the repository root
+    pub path: String,
+    /// Bytes, for files
+    pub size: Option<u64>,
+}
+
+/// Files picked from a tree for import
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Selection {
+    /// Paths from the repository root, with their paths in the project
+    pub files: Vec<(String, String)>,
+    pub skipped: Vec<SkippedFile>,
+    pub total_size: u64,
+}
+
+/// Check a repository URL against the allowed hosts; returns it normalized
+pub fn validate_repository_url(repository_url: &str, allowed_hosts: &[String]) -> Result<String, AppError> {
+    let url = url::Url::parse(repository_url.trim())
+        .map_err(|_| AppError::Validation("The repository URL is not a valid URL".to_string()))?;
+    if url.scheme() != "https" {
+        return Err(AppError::Validation("Only https repository URLs can be imported".to_string()));
+    }
+    if !url.username().is_empty() || url.password().is_some() {
+        return Err(AppError::Validation(
+            "Pass access tokens in token, not in the repository URL".to_string(),
+        ));
+    }
+    if url.port().is_some() || url.query().is_some() || url.fragment().is_some() {
+        return Err(AppError::Validation(
+            "Repository URLs cannot have a port, query or fragment".to_string(),
+        ));
+    }
+    let Some(url::Host::Domain(host)) = url.host() else {
+        return Err(AppError::Validation("Repository URLs need a host name".to_string()));
+    };
+    if !allowed_hosts.iter().any(|allowed| allowed.eq_ignore_ascii_case(host)) {
+        return Err(AppError::Validation(format!(
+            "Imports from {} are not allowed on this instance",
+            host
+        )));
+    }
+    if url.path().trim_matches('/').is_empty() {
+        return Err(AppError::Validation("The repository URL names no repository".to_string()));
+    }
+    Ok(url.to_string())
+}
+
+/// Check a branch or tag name; anything git could read as an option or a
+/// revision expression is refused
+pub fn validate_reference(reference: &str) -> Result<(), AppError> {
+    let valid = !reference.is_empty()
+        && reference.len() <= 255
+        && !reference.starts_with(['-', '/', '.'])
+        && !reference.ends_with(['/', '.'])
+        && !reference.ends_with(".lock")
+        && !reference.contains("..")
+        && !reference.contains("//")
+        && reference.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | '/'));
+    if valid {
+        Ok(())
+    } else {
+        Err(AppError::Validation(format!("Invalid branch or tag name: {}", reference)))
+    }
+}
+
+/// Normalize a subdirectory to `a/b`; `None` for the repository root
+pub fn normalize_subdirectory(subdirectory: &str) -> Result<Option<String>, AppError> {
+    let segments: Vec<&str> = subdirectory
+        .split('/')
+        .filter(|segment| !segment.is_empty() && *segment != ".")
+        .collect();
+    if segments.iter().any(|segment| *segment == ".." || *segment == ".git") {
+        return Err(AppError::Validation(format!("Invalid subdirectory: {}", subdirectory)));
+    }
+    Ok((!segments.is_empty()).then(|| segments.join("/")))
+}
+
+/// Name of a repository from its URL, without `.git`
+pub fn repository_name(repository_url: &str) -> Option<String> {
+    let url = url::Url::parse(repository_url).ok()?;
+    let last = url.path_segments()?.filter(|segment| !segment.is_empty()).last()?;
+    let name = last.strip_suffix(".git").unwrap_or(last);
+    (!name.is_empty()).then(|| name.to_string())
+}
+
+/// Parse the output of `git ls-tree -r -l -z`
+pub fn parse_tree(output: &[u8]) -> Result<Vec<TreeEntry>, AppError> {
+    output
+        .split(|&byte| byte == 0)
+        .filter(|record| !record.is_empty())
+        .map(|record| {
+            let record = std::str::from_utf8(record)
+                .map_err(|_| AppError::Validation("The repository has a path that is not UTF-8".to_string()))?;
+            let (meta, path) = record
+                .split_once('\t')
+                .ok_or_else(|| AppError::Upstream(format!("Unexpected git ls-tree output: {}", record)))?;
+            let fields: Vec<&str> = meta.split_whitespace().collect();
+            let [mode, _kind, _object, size] = fields[..] else {
+                return Err(AppError::Upstream(format!("Unexpected git ls-tree output: {}", record)));
+            };
+            Ok(TreeEntry { mode: mode.to_string(), path: path.to_string(), size: size.parse().ok() })
+        })
+        .collect()
+}
+
+/// Patterns of a `.texlerignore`
+pub fn ignore_filter(texlerignore: &str) -> Result<PathFilter, AppError> {
+    let patterns: Vec<String> = texlerignore
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_string)
+        .collect();
+    PathFilter::new(None, Some(&patterns), false).map_err(|e| match e {
+        AppError::Validation(message) => AppError::Validation(format!("Invalid {}: {}", TEXLERIGNORE_NAME, message)),
+        e => e,
+    })
+}
+
+/// Pick the files of `subdirectory` to import
+///
+/// Files left out are listed with the reason, those over the project's
+/// `limits` too unless imports are strict; a selection over the import's
+/// file count or total size limit refuses the import.
+pub fn select_files(
+    tree: Vec<TreeEntry>,
+    subdirectory: Option<&str>,
+    ignore: Option<&PathFilter>,
+    config: &GitImportConfig,
+    limits: &ProjectLimits,
+) -> Result<Selection, AppError> {
+    let mut selection = Selection::default();
+    let mut candidates = Vec::new();
+    for entry in tree {
+        let relative = match subdirectory {
+            Some(subdirectory) => match entry.path.strip_prefix(subdirectory).and_then(|rest| rest.strip_prefix('/')) {
+                Some(relative) => relative.to_string(),
+                None => continue,
+            },
+            None => entry.path.clone(),
+        };
+        if relative == TEXLERIGNORE_NAME || relative.split('/').any(|segment| segment == ".git") {
+            continue;
+        }
+
+        let reason = match entry.mode.as_str() {
+            "120000" => Some(SkipReason::Symlink),
+            "160000" => Some(SkipReason::Submodule),
+            "100644" | "100755" => None,
+            _ => continue,
+        };
+        let reason = reason
+            .or_else(|| ignore.filter(|ignore| !ignore.keeps(&ignore.matches(&relative))).map(|_| SkipReason::Ignored))
+            .or_else(|| (entry.size.unwrap_or(0) > config.max_file_size).then_some(SkipReason::TooLarge));
+        if let Some(reason) = reason {
+            selection.skipped.push(SkippedFile { path: relative, reason, limit: None });
+            continue;
+        }
+
+        candidates.push((entry.path, relative, entry.size.unwrap_or(0)));
+    }
+
+    let screened = limits.screen(candidates, |(_, relative, size)| (relative.as_str(), *size))?;
+    for (path, relative, size) in screened.kept {
+        selection.total_size += size;
+        selection.files.push((path, relative));
+    }
+    selection.skipped.extend(screened.rejected.into_iter().map(|rejected| SkippedFile {
+        path: rejected.path,
+        reason: SkipReason::OverLimit,
+        limit: Some(rejected.limit),
+    }));
+
+    if selection.files.is_empty() {
+        return Err(AppError::Validation("The repository has no files to import there".to_string()));
+    }
+    if selection.files.len() as u64 > config.max_files {
+        return Err(AppError::Validation(format!(
+            "The repository has {} files, more than the {} an import may bring in",
+            selection.files.len(),
+            config.max_files
+        )));
+    }
+    if selection.total_size > config.max_total_size {
+        return Err(AppError::Validation(format!(
+            "The repository has {} bytes of files, more than the {} an import may bring in",
+            selection.total_size, config.max_total_size
+        )));
+    }
+    Ok(selection)
+}
+
+/// git confined to one clone of one repository
+struct Git<'a> {
+    config: &'a GitImportConfig,
+    /// Where the repository is cloned to; also git's home
+    directory: &'a Path,
+    token: Option<&'a str>,
+}
+
+impl Git<'_> {
+    fn repository(&self) -> std::path::PathBuf {
+        self.directory.join("repository")
+    }
+
+    fn command(&self) -> tokio::process::Command {
+        let mut command = tokio::process::Command::new(&self.config.git_binary);
+        command
+            .env_clear()
+            .env("PATH", std::env::var_os("PATH").unwrap_or_default())
+            .env("HOME", self.directory)
+            .env("GIT_CONFIG_NOSYSTEM", "1")
+            .env("GIT_CONFIG_GLOBAL", "/dev/null")
+            .env("GIT_TERMINAL_PROMPT", "0")
+            .env("GIT_ALLOW_PROTOCOL", "https")
+            .env("GIT_LITERAL_PATHSPECS", "1")
+            .args([
+                "-c", "http.followRedirects=false",
+                "-c", "credential.helper=",
+                "-c", "core.hooksPath=/dev/null",
+                "-c", "core.symlinks=false",
+                "-c", "core.fsmonitor=false",
+                "-c", "submodule.recurse=false",
+            ])
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true);
+        if let Some(token) = self.token {
+            // Kept out of the arguments, which other processes can read
+            use base64::{engine::general_purpose::STANDARD, Engine};
+            let credentials = STANDARD.encode(format!("x-access-token:{}", token));
+            command
+                .env("GIT_CONFIG_COUNT", "1")
+                .env("GIT_CONFIG_KEY_0", "http.extraHeader")
+                .env("GIT_CONFIG_VALUE_0", format!("Authorization: Basic {}", credentials));
+        }
+        command
+    }
+
+    /// Run git in the repository, or to clone it; returns its output
+    async fn run(&self, args: &[&str], stdin: Option<&[u8]>) -> Result<Vec<u8>, AppError> {
+        let mut command = self.command();
+        if args.first() != Some(&"clone") {
+            command.arg("-C").arg(self.repository());
+        }
+        command.args(args);
+        if stdin.is_some() {
+            command.stdin(Stdio::piped());
+        }
+
+        let mut child = command
+            .spawn()
+            .map_err(|e| AppError::Internal(format!("Failed to run {}: {}", self.config.git_binary, e)))?;
+        if let (Some(input), Some(mut pipe)) = (stdin, child.stdin.take()) {
+            pipe.write_all(input).await?;
+        }
+        let output = child.wait_with_output().await?;
+        if !output.status.success() {
+            let mut message = String::from_utf8_lossy(&output.stderr).trim().to_string();
+            if let Some(token) = self.token.filter(|token| !token.is_empty()) {
+                message = message.replace(token, super::project_secret::REDACTED);
+            }
+            return Err(AppError::Upstream(format!("git {} failed: {}", args[0], message)));
+        }
+        Ok(output.stdout)
+    }
+}
+
+impl GitImport {
+    pub fn status_url(&self) -> String {
+        format!("/api/v1/projects/import/git/{}", self.id)
+    }
+
+    pub fn view(self) -> GitImportView {
+        GitImportView { status_url: self.status_url(), import: self }
+    }
+
+    /// Validate a request and record it as a pending import
+    ///
+    /// A user runs one import at a time.
+    pub async fn create(
+        db: &sqlx::PgPool,
+        config: &GitImportConfig,
+        requested_by: Uuid,
+        request: &GitImportRequest,
+    ) -> Result<Self, AppError> {
+        if !config.enabled {
+            return Err(AppError::BadRequest("Git imports are disabled on this instance".to_string()));
+        }
+        let repository_url = validate_repository_url(&request.repository_url, &config.allowed_hosts)?;
+        let reference = request.reference.as_deref().map(str::trim).filter(|reference| !reference.is_empty());
+        if let Some(reference) = reference {
+            validate_reference(reference)?;
+        }
+        let subdirectory = request.subdirectory.as_deref().map(normalize_subdirectory).transpose()?.flatten();
+        let name = request.name.as_deref().map(str::trim).filter(|name| !name.is_empty());
+        if let Some(name) = name {
+            ProjectName::new(name)?;
+        }
+
+        let mut tx = db.begin().await.map_err(AppError::Database)?;
+        super::workspace::Workspace::find_by_id(&mut *tx, request.workspace_id, requested_by).await?;
+        // Serializes the requester's imports
+        sqlx::query("SELECT id FROM users WHERE id = $1 FOR UPDATE")
+            .bind(requested_by)
+            .execute(&mut *tx)
+            .await
+            .map_err(AppError::Database)?;
+        let running: bool = sqlx::query_scalar(
+            "SELECT EXISTS(SELECT 1 FROM git_imports WHERE requested_by = $1 AND status IN ('pending', 'cloning', 'importing'))"
+        )
+        .bind(requested_by)
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(AppError::Database)?;
+        if running {
+            return Err(AppError::Conflict("Another import of yours is still running".to_string()));
+        }
+
+        let import = sqlx::query_as::<_, GitImport>(
+            r#"
+            INSERT INTO git_imports (requested_by, workspace_id, repository_url, reference, subdirectory, name)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            RETURNING *
+            "#
+        )
+        .bind(requested_by)
+        .bind(request.workspace_id)
+        .bind(&repository_url)
+        .bind(reference)
+        .bind(&subdirectory)
+        .bind(name)
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(AppError::Database)?;
+        tx.commit().await.map_err(AppError::Database)?;
+        Ok(import)
+    }
+
+    pub async fn find_for_user(db: &sqlx::PgPool, import_id: Uuid, user_id: Uuid) -> Result<Option<Self>, AppError> {
+        sqlx::query_as::<_, GitImport>("SELECT * FROM git_imports WHERE id = $1 AND requested_by = $2")
+            .bind(import_id)
+            .bind(user_id)
+            .fetch_optional(db)
+            .await
+            .map_err(AppError::Database)
+    }
+
+    async fn update(&self, db: &sqlx::PgPool, query: &str) -> Result<Self, AppError> {
+        sqlx::query_as::<_, GitImport>(query)
+            .bind(self.id)
+            .fetch_one(db)
+            .await
+            .map_err(AppError::Database)
+    }
+
+    /// Clone the repository and create the project from it
+    async fn import(&self, state: &AppState, token: Option<&str>) -> Result<Self, AppError> {
+        let db = &state.db_pool;
+        let config = &state.config.git_import;
+        let limits = ProjectLimits::from_config(&state.config.features.file_storage);
+        let mut import = self
+            .update(db, "UPDATE git_imports SET status = 'cloning', updated_at = NOW() WHERE id = $1 RETURNING *")
+            .await?;
+
+        let directory = tempfile::Builder::new().prefix("texler-git-import-").tempdir()?;
+        let git = Git { config, directory: directory.path(), token };
+        let repository = git.repository();
+        let repository_path = repository.to_string_lossy().into_owned();
+
+        let cloned = async {
+            let branch = import.reference.as_ref().map(|reference| format!("--branch={}", reference));
+            let mut clone = vec![
+                "clone", "--quiet", "--depth=1", "--single-branch", "--no-tags", "--filter=blob:none", "--no-checkout",
+            ];
+            clone.extend(branch.as_deref());
+            clone.extend(["--", import.repository_url.as_str(), repository_path.as_str()]);
+            git.run(&clone, None).await?;
+
+            let commit = String::from_utf8_lossy(&git.run(&["rev-parse", "HEAD"], None).await?).trim().to_string();
+            let tree = parse_tree(&git.run(&["ls-tree", "-r", "-l", "-z", "--full-tree", "HEAD"], None).await?)?;
+
+            let ignore_path = match &import.subdirectory {
+                Some(subdirectory) => format!("{}/{}", subdirectory, TEXLERIGNORE_NAME),
+                None => TEXLERIGNORE_NAME.to_string(),
+            };
+            let ignore = if tree.iter().any(|entry| entry.path == ignore_path && entry.mode.starts_with("100")) {
+                let object = format!("HEAD:{}", ignore_path);
+                let texlerignore = git.run(&["cat-file", "blob", &object], None).await?;
+                Some(ignore_filter(&String::from_utf8_lossy(&texlerignore))?)
+            } else {
+                None
+            };
+            let selection = select_files(tree, import.subdirectory.as_deref(), ignore.as_ref(), config, &limits)?;
+
+            // Fetches the content of the selected files only
+            let mut pathspecs = Vec::new();
+            for (repository_path, _) in &selection.files {
+                pathspecs.extend_from_slice(repository_path.as_bytes());
+                pathspecs.push(0);
+            }
+            git.run(&["checkout", "--quiet", "HEAD", "--pathspec-from-file=-", "--pathspec-file-nul"], Some(&pathspecs))
+                .await?;
+            Ok::<_, AppError>((commit, selection))
+        };
+        let (commit, selection) = tokio::time::timeout(Duration::from_secs(config.timeout), cloned)
+            .await
+            .map_err(|_| AppError::Upstream(format!("Cloning took longer than {} seconds", config.timeout)))??;
+
+        import = sqlx::query_as::<_, GitImport>(
+            r#"
+            UPDATE git_imports
+            SET status = 'importing', commit_hash = $2, files_total = $3, bytes_total = $4, skipped = $5,
+                updated_at = NOW()
+            WHERE id = $1
+            RETURNING *
+            "#
+        )
+        .bind(import.id)
+        .bind(&commit)
+        .bind(selection.files.len() as i32)
+        .bind(selection.total_size as i64)
+        .bind(sqlx::types::Json(&selection.skipped))
+        .fetch_one(db)
+        .await
+        .map_err(AppError::Database)?;
+
+        let mut entries = Vec::with_capacity(selection.files.len());
+        for (repository_path, path) in selection.files {
+            let on_disk = repository.join(&repository_path);
+            // Checked out without following links, but never read through one
+            if !tokio::fs::symlink_metadata(&on_disk).await?.is_file() {
+                continue;
+            }
+            let bytes = tokio::fs::read(&on_disk).await?;
+            if bytes.len() as u64 > config.max_file_size {
+                return Err(AppError::Validation(format!("{} is larger than its tree entry said", path)));
+            }
+            entries.push(ArchiveEntry { path, bytes });
+        }
+        drop(directory);
+
+        let name = import
+            .name
+            .clone()
+            .or_else(|| repository_name(&import.repository_url))
+            .unwrap_or_else(|| "Imported project".to_string());
+        let main_file = detect_main_file(&entries).map(|path| format!("/{}", path));
+        let project = Project::create(
+            db,
+            import.requested_by,
+            CreateProject {
+                name: ProjectName::new(&name)?,
+                description: None,
+                is_public: None,
+                main_file_path: main_file.clone(),
+                latex_engine: None,
+                output_format: None,
+                custom_args: None,
+                bibliography_path: None,
+                tags: None,
+                workspace_id: Some(import.workspace_id),
+            },
+        )
+        .await?;
+        import = sqlx::query_as::<_, GitImport>(
+            "UPDATE git_imports SET project_id = $2, updated_at = NOW() WHERE id = $1 RETURNING *"
+        )
+        .bind(import.id)
+        .bind(project.id)
+        .fetch_one(db)
+        .await
+        .map_err(AppError::Database)?;
+
+        let storage_root = &state.config.features.file_storage.local_path;
+        let total = entries.len();
+        for (index, entry) in entries.into_iter().enumerate() {
+            File::import(db, storage_root, &limits, project.id, &entry.path, entry.bytes, import.requested_by).await?;
+            let imported = index + 1;
+            if imported % PROGRESS_INTERVAL == 0 || imported == total {
+                sqlx::query("UPDATE git_imports SET files_imported = $2, updated_at = NOW() WHERE id = $1")
+                    .bind(import.id)
+                    .bind(imported as i32)
+                    .execute(db)
+                    .await
+                    .map_err(AppError::Database)?;
+            }
+        }
+
+        if let Some(main_file) = &main_file {
+            Project::set_main_file(db, project.id, import.requested_by, main_file).await?;
+        }
+        Project::set_source(db, project.id, &import.repository_url, &commit).await?;
+
+        self.update(
+            db,
+            "UPDATE git_imports SET status = 'ready', updated_at = NOW(), completed_at = NOW() WHERE id = $1 RETURNING *",
+        )
+        .await
+    }
+
+    /// Mark the import failed and remove the project it started
+    async fn fail(&self, db: &sqlx::PgPool, error: &str) -> Result<Self, AppError> {
+        // Read first, as a failed run may have started a project this copy does not know of
+        let started: Option<Uuid> = sqlx::query_scalar("SELECT project_id FROM git_imports WHERE id = $1")
+            .bind(self.id)
+            .fetch_optional(db)
+            .await
+            .map_err(AppError::Database)?
+            .flatten();
+
+        let import = sqlx::query_as::<_, GitImport>(
+            r#"
+            UPDATE git_imports
+            SET status = 'failed', error = $2, project_id = NULL, updated_at = NOW(), completed_at = NOW()
+            WHERE id = $1
+            RETURNING *
+            "#
+        )
+        .bind(self.id)
+        .bind(error)
+        .fetch_one(db)
+        .await
+        .map_err(AppError::Database)?;
+
+        if let Some(project_id) = started {
+            remove_started_project(db, project_id, self.requested_by).await;
+        }
+        Ok(import)
+    }
+
+    /// Run the import and notify the requester of the outcome
+    pub async fn run(self, state: AppState, token: Option<String>) {
+        let db = &state.db_pool;
+        let import = match self.import(&state, token.as_deref()).await {
+            Ok(import) => import,
+            Err(e) => {
+                tracing::warn!("Git import {} of {} failed: {}", self.id, self.repository_url, e);
+                match self.fail(db, &e.to_string()).await {
+                    Ok(import) => import,
+                    Err(e) => {
+                        tracing::error!("Failed to record the failure of git import {}: {}", self.id, e);
+                        return;
+                    }
+                }
+            }
+        };
+
+        let (title, body) = match import.status {
+            GitImportStatus::Ready => (
+                "Repository imported",
+                format!(
+                    "{} files of {} were imported into a new project.",
+                    import.files_imported, import.repository_url
+                ),
+            ),
+            _ => (
+                "Repository import failed",
+                format!(
+                    "{} could not be imported: {}",
+                    import.repository_url,
+                    import.error.as_deref().unwrap_or("unknown error")
+                ),
+            ),
+        };
+        let notified = async {
+            let user = User::find_by_id(db, import.requested_by).await?.ok_or_else(|| AppError::NotFound {
+                entity: "User".to_string(),
+                id: import.requested_by.to_string(),
+            })?;
+            NotificationService::notify(
+                db,
+                &state.mailer,
+                &user,
+                "git_import",
+                title,
+                &body,
+                Some(serde_json::json!({
+                    "import_id": import.id,
+                    "status": import.status,
+                    "status_url": import.status_url(),
+                    "project_id": import.project_id,
+                })),
+            )
+            .await
+        }
+        .await;
+        if let Err(e) = notified {
+            tracing::warn!("Failed to notify {} about git import {}: {}", import.requested_by, import.id, e);
+        }
+    }
+
+    /// Fail imports left running by a server that stopped, and remove
+    /// finished imports past their retention; returns the imports removed
+    pub async fn clean_up(db: &sqlx::PgPool) -> Result<u64, AppError> {
+        let stale = sqlx::query_as::<_, GitImport>(
+            r#"
+            SELECT * FROM git_imports
+            WHERE status IN ('pending', 'cloning', 'importing')
+              AND updated_at < NOW() - $1 * INTERVAL '1 minute'
+            "#
+        )
+        .bind(STALE_IMPORT_MINUTES as f64)
+        .fetch_all(db)
+        .await
+        .map_err(AppError::Database)?;
+        for import in stale {
+            import.fail(db, "Interrupted by a server restart").await?;
+        }
+
+        let removed = sqlx::query(
+            r#"
+            DELETE FROM git_imports
+            WHERE status IN ('ready', 'failed') AND completed_at < NOW() - $1 * INTERVAL '1 day'
+            "#
+        )
+        .bind(IMPORT_RETENTION_DAYS as f64)
+        .execute(db)
+        .await
+        .map_err(AppError::Database)?;
+        Ok(removed.rows_affected())
+    }
+}
+
+async fn remove_started_project(db: &sqlx::PgPool, project_id: Uuid, owner_id: Uuid) {
+    let removed = async {
+        if let Some(project) = Project::find_by_id(db, project_id, owner_id).await? {
+            project.delete(db, owner_id).await?;
+        }
+        Ok::<_, AppError>(())
+    }
+    .await;
+    if let Err(e) = removed {
+        tracing::warn!("Failed to remove partially imported project {}: {}", project_id, e);
+    }
+}
+
+/// Fails interrupted git imports and removes old ones
+pub struct GitImportCleanupTask;
+
+impl crate::tasks::PeriodicTask for GitImportCleanupTask {
+    fn name(&self) -> &'static str {
+        "git_import_cleanup"
+    }
+
+    fn interval(&self) -> Duration {
+        CLEANUP_INTERVAL
+    }
+
+    fn run<'a>(&'a self, state: &'a AppState) -> futures::future::BoxFuture<'a, Result<(), AppError>> {
+        Box::pin(async move {
+            let removed = GitImport::clean_up(&state.db_pool).await?;
+            if removed > 0 {
+                tracing::info!("Removed {} finished git imports", removed);
+            }
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::project_limits::LimitMode;
+
+    fn config() -> GitImportConfig {
+        GitImportConfig {
+            enabled: true,
+            allowed_hosts: vec!["github.com".to_string()],
+            git_binary: "git".to_string(),
+            max_total_size: 1000,
+            max_files: 4,
+            max_file_size: 400,
+            timeout: 60,
+        }
+    }
+
+    fn file(path: &str, size: u64) -> TreeEntry {
+        TreeEntry { mode: "100644".to_string(), path: path.to_string(), size: Some(size) }
+    }
+
+    #[test]
+    fn test_only_allowed_https_urls_are_cloned() {
+        let hosts = config().allowed_hosts;
+        assert_eq!(
+            validate_repository_url(" https://GitHub.com/owner/thesis.git ", &hosts).unwrap(),
+            "https://github.com/owner/thesis.git"
+        );
+        for refused in [
+            "http://github.com/owner/thesis",
+            "ssh://git@github.com/owner/thesis",
+            "file:///srv/git/thesis",
+            "https://token@github.com/owner/thesis",
+            "https://github.com:8443/owner/thesis",
+            "https://github.com/owner/thesis?ref=x",
+            "https://git.internal/owner/thesis",
+            "https://10.0.0.5/owner/thesis",
+            "https://github.com.evil.example/owner/thesis",
+            "https://github.com/",
+            "--upload-pack=touch /tmp/x",
+        ] {
+            assert!(validate_repository_url(refused, &hosts).is_err(), "{}", refused);
+        }
+        assert_eq!(repository_name("https://github.com/owner/thesis.git").as_deref(), Some("thesis"));
+    }
+
+    #[test]
+    fn test_references_and_subdirectories_cannot_escape() {
+        for valid in ["main", "v1.2", "release/2024-spring", "feature_x"] {
+            assert!(validate_reference(valid).is_ok(), "{}", valid);
+        }
+        for invalid in ["-u", "--upload-pack=x", "main..dev", "a//b", "HEAD@{1}", "x.lock", "/main", "a b", ""] {
+            assert!(validate_reference(invalid).is_err(), "{}", invalid);
+        }
+        assert_eq!(normalize_subdirectory("/paper/./tex/").unwrap().as_deref(), Some("paper/tex"));
+        assert_eq!(normalize_subdirectory("/").unwrap(), None);
+        assert!(normalize_subdirectory("paper/../../etc").is_err());
+        assert!(normalize_subdirectory(".git/hooks").is_err());
+    }
+
+    #[test]
+    fn test_tree_output_is_parsed() {
+        let output = b"100644 blob 3b18e512dba79e4c8300dd08aeb37f8e728b8dad      12\tpaper/main.tex\0\
+            120000 blob 5f1ab2c3d4e5f60718293a4b5c6d7e8f90a1b2c3      11\tpaper/link.tex\0\
+            160000 commit 0123456789abcdef0123456789abcdef01234567       -\tvendor/style\0";
+        let tree = parse_tree(output).unwrap();
+        assert_eq!(tree.len(), 3);
+        assert_eq!(tree[0], file("paper/main.tex", 12));
+        assert_eq!((tree[2].mode.as_str(), tree[2].size), ("160000", None));
+    }
+
+    #[test]
+    fn test_selection_skips_and_limits() {
+        let ignore = ignore_filter("# build output\nbuild/\n*.log\n").unwrap();
+        let tree = vec![
+            file("README.md", 10),
+            file("paper/main.tex", 100),
+            file("paper/figures/plot.png", 300),
+            file("paper/.texlerignore", 20),
+            file("paper/build/main.pdf", 50),
+            file("paper/main.log", 50),
+            file("paper/data.csv", 401),
+            TreeEntry { mode: "120000".to_string(), path: "paper/shared.tex".to_string(), size: Some(9) },
+            TreeEntry { mode: "160000".to_string(), path: "paper/style".to_string(), size: None },
+        ];
+        let limits = ProjectLimits::default();
+        let selection = select_files(tree.clone(), Some("paper"), Some(&ignore), &config(), &limits).unwrap();
+        assert_eq!(
+            selection.files,
+            vec![
+                ("paper/main.tex".to_string(), "main.tex".to_string()),
+                ("paper/figures/plot.png".to_string(), "figures/plot.png".to_string()),
+            ]
+        );
+        assert_eq!(selection.total_size, 400);
+        let skipped: Vec<(&str, SkipReason)> =
+            selection.skipped.iter().map(|skipped| (skipped.path.as_str(), skipped.reason)).collect();
+        assert_eq!(
+            skipped,
+            vec![
+                ("build/main.pdf", SkipReason::Ignored),
+                ("main.log", SkipReason::Ignored),
+                ("data.csv", SkipReason::TooLarge),
+                ("shared.tex", SkipReason::Symlink),
+                ("style", SkipReason::Submodule),
+            ]
+        );
+
+        // The whole repository is over the file count, and then over the total size
+        assert!(select_files(tree.clone(), None, None, &config(), &limits).is_err());
+        let config = GitImportConfig { max_files: 10, max_total_size: 500, ..config() };
+        let error = select_files(tree, None, None, &config, &limits).unwrap_err();
+        assert!(error.to_string().contains("bytes"), "{}", error);
+        assert!(select_files(vec![file("a.tex", 1)], Some("paper"), None, &config, &limits).is_err());
+    }
+
+    #[test]
+    fn test_selection_over_project_limits_skips_or_refuses() {
+        let tree = vec![
+            file("main.tex", 100),
+            file("a/b/c/deep.tex", 10),
+            file("one.tex", 10),
+            file("two.tex", 10),
+        ];
+        let mut limits = ProjectLimits { max_files: 2, max_path_depth: 2, ..ProjectLimits::default() };
+
+        let selection = select_files(tree.clone(), None, None, &config(), &limits).unwrap();
+        let paths: Vec<&str> = selection.files.iter().map(|(_, path)| path.as_str()).collect();
+        assert_eq!(paths, vec!["main.tex", "one.tex"]);
+        assert_eq!(selection.total_size, 110);
+        assert_eq!(
+            selection.skipped,
+            vec![
+                SkippedFile {
+                    path: "a/b/c/deep.tex".to_string(),
+                    reason: SkipReason::OverLimit,
+                    limit: Some(ProjectLimit::PathDepth),
+                },
+                SkippedFile { path: "two.tex".to_string(), reason: SkipReason::OverLimit, limit: Some(ProjectLimit::Files) },
+            ]
+        );
+
+        limits.import_mode = LimitMode::Strict;
+        let error = select_files(tree, None, None, &config(), &limits).unwrap_err();
+        assert!(matches!(error, AppError::LimitExceeded { ref rejected, .. } if rejected.len() == 2), "{}", error);
+    }
+}
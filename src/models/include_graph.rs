@@ -0,0 +1,787 @@
+//! Project include graph
+//!
+//! `\input` and `\include` targets recorded in each file's LaTeX metadata are
+//! resolved against the project files the way LaTeX finds them in our
+//! layout: relative to the including file's directory, then to the project
+//! root, with `.tex` optional. The graph reports includes that resolve to no
+//! file or to more than one, include cycles, and LaTeX files the main file
+//! never reaches, so compiles can warn before they fail on them. Graphics
+//! and bibliography databases named by each file are resolved the same way,
+//! also trying the main file's directory, and kept for the compile preflight.
+//!
+//! Graphs are cached per project. File changes update the cached sources in
+//! place and the graph is re-resolved from them on the next read, without
+//! going back to the database.
+
+use serde::Serialize;
+use sqlx::types::Json;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::{Arc, Mutex};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use super::file::{File, ResourceInfo};
+use super::ContentType;
+use crate::error::AppError;
+use crate::server::AppState;
+
+/// Project graphs kept in memory
+pub const INCLUDE_GRAPH_CACHE_CAPACITY: usize = 512;
+
+/// Extensions `\includegraphics` tries, in pdfLaTeX's order, for targets written without one
+const GRAPHICS_EXTENSIONS: &[&str] = &["", ".pdf", ".png", ".jpg", ".jpeg", ".eps"];
+
+/// A project file with the include targets of its LaTeX metadata
+#[derive(Debug, Clone)]
+pub struct IncludeSource {
+    pub file_id: Uuid,
+    pub path: String,
+    pub content_type: ContentType,
+    pub includes: Vec<String>,
+    /// Line of each include, when the metadata records it
+    pub include_lines: Vec<i32>,
+    pub graphics: Vec<ResourceInfo>,
+    pub bibliographies: Vec<ResourceInfo>,
+}
+
+impl IncludeSource {
+    pub fn from_file(file: &File) -> Self {
+        fn field<T: serde::de::DeserializeOwned + Default>(file: &File, name: &str) -> T {
+            file.latex_metadata
+                .as_ref()
+                .and_then(|metadata| metadata.get(name))
+                .and_then(|value| serde_json::from_value(value.clone()).ok())
+                .unwrap_or_default()
+        }
+
+        Self {
+            file_id: file.id,
+            path: file.path.clone(),
+            content_type: file.content_type,
+            includes: field(file, "includes"),
+            include_lines: field(file, "include_lines"),
+            graphics: field(file, "graphics"),
+            bibliographies: field(file, "bibliographies"),
+        }
+    }
+}
+
+/// How an include target resolved
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum IncludeStatus {
+    /// Exactly one project file matches
+    Resolved,
+    /// No project file matches
+    Missing,
+    /// Several project files match; see `candidates`
+    Ambiguous,
+}
+
+/// A file of the graph
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct IncludeNode {
+    pub file_id: Uuid,
+    pub path: String,
+    /// Reached from the main file; always false when the main file is missing
+    pub reachable: bool,
+}
+
+/// An include of one file by another
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct IncludeEdge {
+    /// Including file
+    pub from: Uuid,
+    /// Target as written, with `.tex` added when it had no extension
+    pub target: String,
+    pub status: IncludeStatus,
+    /// Included file, when resolved
+    pub file_id: Option<Uuid>,
+    /// Matching files, when ambiguous
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub candidates: Vec<Uuid>,
+    /// Line of the include, unknown for files not saved since lines were recorded
+    pub line_number: Option<i32>,
+}
+
+/// Kind of file named by a LaTeX file besides its includes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResourceKind {
+    Graphics,
+    Bibliography,
+}
+
+/// A graphics file or bibliography database named by a file of the graph
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResourceEdge {
+    pub from: Uuid,
+    pub kind: ResourceKind,
+    /// Target as written, with `.bib` added to bibliographies
+    pub target: String,
+    pub line_number: i32,
+    /// Some project file matches
+    pub found: bool,
+}
+
+/// Resolved includes of a project
+#[derive(Debug, Clone, Default, PartialEq, Serialize, ToSchema)]
+pub struct IncludeGraph {
+    /// The project's main file, `null` when no file has its path
+    pub main_file_id: Option<Uuid>,
+    /// LaTeX files and included files, by path
+    pub nodes: Vec<IncludeNode>,
+    pub edges: Vec<IncludeEdge>,
+    /// Include cycles, each listed in include order starting from its first file by path
+    pub cycles: Vec<Vec<Uuid>>,
+    /// LaTeX files the main file does not reach, by path
+    pub unreachable: Vec<Uuid>,
+    /// Graphics and bibliographies of the LaTeX files
+    #[serde(skip)]
+    pub resources: Vec<ResourceEdge>,
+    /// Paths of all project files, normalized
+    #[serde(skip)]
+    paths: HashSet<String>,
+}
+
+impl IncludeGraph {
+    pub fn build<'a>(sources: impl IntoIterator<Item = &'a IncludeSource>, main_file_path: &str) -> Self {
+        let mut sources: Vec<&IncludeSource> = sources.into_iter().collect();
+        sources.sort_by(|a, b| a.path.cmp(&b.path));
+
+        let by_path: HashMap<String, usize> = sources
+            .iter()
+            .enumerate()
+            .filter_map(|(index, source)| normalize_path(&source.path).map(|path| (path, index)))
+            .collect();
+
+        // Outgoing edges by source index: definite ones for cycles, any match for reachability
+        let mut resolved: Vec<Vec<usize>> = vec![Vec::new(); sources.len()];
+        let mut possible: Vec<Vec<usize>> = vec![Vec::new(); sources.len()];
+        let mut included = vec![false; sources.len()];
+        let mut edges = Vec::new();
+
+        for (from, source) in sources.iter().enumerate() {
+            for (at, target) in source.includes.iter().enumerate() {
+                let matches = resolve(&by_path, &source.path, target);
+                for &index in &matches {
+                    included[index] = true;
+                    possible[from].push(index);
+                }
+
+                let (status, file_id, candidates) = match matches.as_slice() {
+                    [] => (IncludeStatus::Missing, None, Vec::new()),
+                    [index] => {
+                        resolved[from].push(*index);
+                        (IncludeStatus::Resolved, Some(sources[*index].file_id), Vec::new())
+                    }
+                    _ => (
+                        IncludeStatus::Ambiguous,
+                        None,
+                        matches.iter().map(|&index| sources[index].file_id).collect(),
+                    ),
+                };
+                edges.push(IncludeEdge {
+                    from: source.file_id,
+                    target: target.clone(),
+                    status,
+                    file_id,
+                    candidates,
+                    line_number: source.include_lines.get(at).copied(),
+                });
+            }
+        }
+
+        let main = normalize_path(main_file_path).and_then(|path| by_path.get(&path).copied());
+
+        // LaTeX looks for these from the directory it runs in, the main file's
+        let main_path = main.map(|index| sources[index].path.as_str()).unwrap_or(main_file_path);
+        let mut resources = Vec::new();
+        for source in &sources {
+            let named = source
+                .graphics
+                .iter()
+                .map(|resource| (ResourceKind::Graphics, resource))
+                .chain(source.bibliographies.iter().map(|resource| (ResourceKind::Bibliography, resource)));
+            for (kind, resource) in named {
+                let extensions: &[&str] = match kind {
+                    ResourceKind::Graphics => GRAPHICS_EXTENSIONS,
+                    ResourceKind::Bibliography => &[""],
+                };
+                let found = [source.path.as_str(), main_path].iter().any(|including_path| {
+                    extensions.iter().any(|extension| {
+                        !resolve(&by_path, including_path, &format!("{}{}", resource.target, extension)).is_empty()
+                    })
+                });
+                resources.push(ResourceEdge {
+                    from: source.file_id,
+                    kind,
+                    target: resource.target.clone(),
+                    line_number: resource.line_number,
+                    found,
+                });
+            }
+        }
+        let mut reachable = vec![false; sources.len()];
+        if let Some(main) = main {
+            let mut queue = VecDeque::from([main]);
+            reachable[main] = true;
+            while let Some(index) = queue.pop_front() {
+                for &next in &possible[index] {
+                    if !reachable[next] {
+                        reachable[next] = true;
+                        queue.push_back(next);
+                    }
+                }
+            }
+        }
+
+        let mut nodes = Vec::new();
+        let mut unreachable = Vec::new();
+        for (index, source) in sources.iter().enumerate() {
+            let is_latex = source.content_type == ContentType::Latex;
+            if !is_latex && !included[index] {
+                continue;
+            }
+            if is_latex && main.is_some() && !reachable[index] {
+                unreachable.push(source.file_id);
+            }
+            nodes.push(IncludeNode {
+                file_id: source.file_id,
+                path: source.path.clone(),
+                reachable: reachable[index],
+            });
+        }
+
+        let cycles = find_cycles(&resolved)
+            .into_iter()
+            .map(|cycle| cycle.into_iter().map(|index| sources[index].file_id).collect())
+            .collect();
+
+        Self {
+            main_file_id: main.map(|index| sources[index].file_id),
+            nodes,
+            edges,
+            cycles,
+            unreachable,
+            resources,
+            paths: by_path.into_keys().collect(),
+        }
+    }
+
+    /// Whether a project file has `path`
+    pub fn has_file(&self, path: &str) -> bool {
+        normalize_path(path).is_some_and(|path| self.paths.contains(&path))
+    }
+
+    /// Problems worth reporting before a compile
+    pub fn warnings(&self) -> Vec<String> {
+        let path_of = |file_id: &Uuid| {
+            self.nodes
+                .iter()
+                .find(|node| node.file_id == *file_id)
+                .map_or("?", |node| node.path.as_str())
+        };
+
+        let mut warnings = Vec::new();
+        if self.main_file_id.is_none() {
+            warnings.push("The main file does not exist".to_string());
+        }
+        for edge in &self.edges {
+            match edge.status {
+                IncludeStatus::Resolved => {}
+                IncludeStatus::Missing => warnings.push(format!(
+                    "{} includes {}, which does not exist",
+                    path_of(&edge.from),
+                    edge.target
+                )),
+                IncludeStatus::Ambiguous => warnings.push(format!(
+                    "{} includes {}, which matches {}",
+                    path_of(&edge.from),
+                    edge.target,
+                    edge.candidates.iter().map(path_of).collect::<Vec<_>>().join(" and ")
+                )),
+            }
+        }
+        for cycle in &self.cycles {
+            let mut chain: Vec<&str> = cycle.iter().map(path_of).collect();
+            chain.push(path_of(&cycle[0]));
+            warnings.push(format!("Include cycle: {}", chain.join(" -> ")));
+        }
+        warnings
+    }
+}
+
+/// `path` as an absolute project path without `.` and `..` segments;
+/// `None` when it leaves the project root or names no file
+fn normalize_path(path: &str) -> Option<String> {
+    let mut normalized = String::with_capacity(path.len() + 1);
+    for segment in path.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => {
+                let parent = normalized.rfind('/')?;
+                normalized.truncate(parent);
+            }
+            segment => {
+                normalized.push('/');
+                normalized.push_str(segment);
+            }
+        }
+    }
+
+    if normalized.is_empty() {
+        return None;
+    }
+    Some(normalized)
+}
+
+/// Files an include target can refer to, first match of each search location
+fn resolve(by_path: &HashMap<String, usize>, including_path: &str, target: &str) -> Vec<usize> {
+    let target = target.trim();
+    let mut bases = Vec::with_capacity(2);
+    if !target.starts_with('/') {
+        if let Some((directory, _)) = including_path.trim_start_matches('/').rsplit_once('/') {
+            bases.push(format!("{}/{}", directory, target));
+        }
+    }
+    bases.push(target.to_string());
+
+    let mut matches = Vec::new();
+    for base in bases {
+        // Metadata adds `.tex` to every target without it, so `fig.tikz` arrives as `fig.tikz.tex`
+        let found = normalize_path(&base)
+            .and_then(|path| {
+                by_path.get(&path).or_else(|| {
+                    path.strip_suffix(".tex")
+                        .and_then(|stem| by_path.get(stem))
+                })
+            })
+            .copied();
+
+        if let Some(index) = found {
+            if !matches.contains(&index) {
+                matches.push(index);
+            }
+        }
+    }
+    matches
+}
+
+/// Cycles found along back edges of a depth-first walk, in include order
+fn find_cycles(edges: &[Vec<usize>]) -> Vec<Vec<usize>> {
+    let mut cycles = Vec::new();
+    let mut seen: HashSet<Vec<usize>> = HashSet::new();
+    let mut visited = vec![false; edges.len()];
+    let mut on_stack = vec![false; edges.len()];
+
+    for start in 0..edges.len() {
+        if visited[start] {
+            continue;
+        }
+
+        // Explicit stack of (node, next edge), so deep include chains cannot overflow
+        let mut stack = vec![(start, 0usize)];
+        let mut path = vec![start];
+        visited[start] = true;
+        on_stack[start] = true;
+
+        while let Some((node, next_edge)) = stack.last_mut() {
+            let node = *node;
+            let Some(&next) = edges[node].get(*next_edge) else {
+                stack.pop();
+                path.pop();
+                on_stack[node] = false;
+                continue;
+            };
+            *next_edge += 1;
+
+            if on_stack[next] {
+                let from = path.iter().position(|&index| index == next).unwrap_or(0);
+                let mut cycle = path[from..].to_vec();
+                // Sources are sorted by path, so the lowest index is the first file by path
+                let first = cycle.iter().enumerate().min_by_key(|(_, &index)| index).map_or(0, |(at, _)| at);
+                cycle.rotate_left(first);
+                if seen.insert(cycle.clone()) {
+                    cycles.push(cycle);
+                }
+            } else if !visited[next] {
+                visited[next] = true;
+                on_stack[next] = true;
+                stack.push((next, 0));
+                path.push(next);
+            }
+        }
+    }
+
+    cycles
+}
+
+#[derive(sqlx::FromRow)]
+struct SourceRow {
+    id: Uuid,
+    path: String,
+    content_type: ContentType,
+    includes: Json<Vec<String>>,
+    include_lines: Json<Vec<i32>>,
+    graphics: Json<Vec<ResourceInfo>>,
+    bibliographies: Json<Vec<ResourceInfo>>,
+}
+
+/// Include sources of every live file of a project, without reading contents
+pub async fn load_sources(db: &sqlx::PgPool, project_id: Uuid) -> Result<Vec<IncludeSource>, AppError> {
+    let rows = sqlx::query_as::<_, SourceRow>(
+        r#"
+        SELECT id, path, content_type,
+               COALESCE(latex_metadata->'includes', '[]'::jsonb) AS includes,
+               COALESCE(latex_metadata->'include_lines', '[]'::jsonb) AS include_lines,
+               COALESCE(latex_metadata->'graphics', '[]'::jsonb) AS graphics,
+               COALESCE(latex_metadata->'bibliographies', '[]'::jsonb) AS bibliographies
+        FROM files
+        WHERE project_id = $1 AND is_deleted = false
+        "#
+    )
+    .bind(project_id)
+    .fetch_all(db)
+    .await
+    .map_err(AppError::Database)?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| IncludeSource {
+            file_id: row.id,
+            path: row.path,
+            content_type: row.content_type,
+            includes: row.includes.0,
+            include_lines: row.include_lines.0,
+            graphics: row.graphics.0,
+            bibliographies: row.bibliographies.0,
+        })
+        .collect())
+}
+
+/// The project's include graph, loaded from its files on a cache miss
+pub async fn load(state: &AppState, project_id: Uuid, main_file_path: &str) -> Result<Arc<IncludeGraph>, AppError> {
+    if let Some(graph) = state.include_graph_cache.get(project_id, main_file_path) {
+        return Ok(graph);
+    }
+
+    let ticket = state.include_graph_cache.begin_build(project_id);
+    let sources = load_sources(&state.db_pool, project_id).await?;
+    Ok(state.include_graph_cache.insert(project_id, ticket, sources, main_file_path))
+}
+
+struct ProjectIncludes {
+    sources: HashMap<Uuid, IncludeSource>,
+    /// Resolved graph and the main file it was resolved for; `None` after a change
+    graph: Option<(String, Arc<IncludeGraph>)>,
+}
+
+impl ProjectIncludes {
+    fn graph(&mut self, main_file_path: &str) -> Arc<IncludeGraph> {
+        match &self.graph {
+            Some((main, graph)) if main == main_file_path => graph.clone(),
+            _ => {
+                let graph = Arc::new(IncludeGraph::build(self.sources.values(), main_file_path));
+                self.graph = Some((main_file_path.to_string(), graph.clone()));
+                graph
+            }
+        }
+    }
+}
+
+/// Include sources and graphs by project, updated as files change
+#[derive(Default)]
+pub struct IncludeGraphCache {
+    state: Mutex<CacheState>,
+}
+
+#[derive(Default)]
+struct CacheState {
+    projects: HashMap<Uuid, ProjectIncludes>,
+    order: VecDeque<Uuid>,
+    /// Loads in progress; a load is discarded if the project changes meanwhile
+    loading: HashMap<Uuid, u64>,
+    next_ticket: u64,
+}
+
+impl IncludeGraphCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The cached graph, re-resolved if files or the main file changed since
+    pub fn get(&self, project_id: Uuid, main_file_path: &str) -> Option<Arc<IncludeGraph>> {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        state
+            .projects
+            .get_mut(&project_id)
+            .map(|project| project.graph(main_file_path))
+    }
+
+    /// Start loading the sources of a project, before reading its files
+    pub fn begin_build(&self, project_id: Uuid) -> u64 {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        state.next_ticket += 1;
+        let ticket = state.next_ticket;
+        state.loading.insert(project_id, ticket);
+        ticket
+    }
+
+    /// Resolve loaded sources, keeping them unless the project changed since
+    /// `begin_build`, and evicting the oldest project when full
+    pub fn insert(
+        &self,
+        project_id: Uuid,
+        ticket: u64,
+        sources: Vec<IncludeSource>,
+        main_file_path: &str,
+    ) -> Arc<IncludeGraph> {
+        let mut project = ProjectIncludes {
+            sources: sources.into_iter().map(|source| (source.file_id, source)).collect(),
+            graph: None,
+        };
+        let graph = project.graph(main_file_path);
+
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        if state.loading.get(&project_id) != Some(&ticket) {
+            return graph;
+        }
+        state.loading.remove(&project_id);
+
+        if state.projects.insert(project_id, project).is_none() {
+            state.order.push_back(project_id);
+        }
+        while state.order.len() > INCLUDE_GRAPH_CACHE_CAPACITY {
+            if let Some(oldest) = state.order.pop_front() {
+                state.projects.remove(&oldest);
+            }
+        }
+        graph
+    }
+
+    /// Record a created or changed file
+    pub fn file_changed(&self, file: &File) {
+        self.source_changed(file.project_id, IncludeSource::from_file(file));
+    }
+
+    pub fn source_changed(&self, project_id: Uuid, source: IncludeSource) {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        state.loading.remove(&project_id);
+        if let Some(project) = state.projects.get_mut(&project_id) {
+            project.sources.insert(source.file_id, source);
+            project.graph = None;
+        }
+    }
+
+    /// Record a deleted file
+    pub fn file_removed(&self, project_id: Uuid, file_id: Uuid) {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        state.loading.remove(&project_id);
+        if let Some(project) = state.projects.get_mut(&project_id) {
+            project.sources.remove(&file_id);
+            project.graph = None;
+        }
+    }
+
+    /// Drop the project after changes that are not tracked file by file
+    pub fn invalidate(&self, project_id: Uuid) {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        state.loading.remove(&project_id);
+        if state.projects.remove(&project_id).is_some() {
+            state.order.retain(|id| *id != project_id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, Instant};
+
+    fn latex(path: &str, includes: &[&str]) -> IncludeSource {
+        IncludeSource {
+            file_id: Uuid::new_v4(),
+            path: path.to_string(),
+            content_type: ContentType::Latex,
+            includes: includes.iter().map(|include| include.to_string()).collect(),
+            include_lines: Vec::new(),
+            graphics: Vec::new(),
+            bibliographies: Vec::new(),
+        }
+    }
+
+    fn edge<'a>(graph: &'a IncludeGraph, from: &IncludeSource, target: &str) -> &'a IncludeEdge {
+        graph
+            .edges
+            .iter()
+            .find(|edge| edge.from == from.file_id && edge.target == target)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_resolution_relative_to_directory_and_root() {
+        let main = latex("/main.tex", &["chapters/intro.tex", "missing.tex", "figure.tikz.tex"]);
+        let intro = latex("/chapters/intro.tex", &["details.tex", "shared.tex", "../main.tex"]);
+        let details = latex("/chapters/details.tex", &[]);
+        let shared_root = latex("/shared.tex", &[]);
+        let shared_local = latex("/chapters/shared.tex", &[]);
+        let figure = IncludeSource {
+            content_type: ContentType::Other,
+            ..latex("/figure.tikz", &[])
+        };
+        let sources = [&main, &intro, &details, &shared_root, &shared_local, &figure];
+
+        let graph = IncludeGraph::build(sources, "main.tex");
+        assert_eq!(graph.main_file_id, Some(main.file_id));
+
+        let intro_edge = edge(&graph, &main, "chapters/intro.tex");
+        assert_eq!(intro_edge.status, IncludeStatus::Resolved);
+        assert_eq!(intro_edge.file_id, Some(intro.file_id));
+
+        // Found next to the including file, though LaTeX itself only looks in the root
+        assert_eq!(edge(&graph, &intro, "details.tex").file_id, Some(details.file_id));
+        assert_eq!(edge(&graph, &main, "missing.tex").status, IncludeStatus::Missing);
+        assert_eq!(edge(&graph, &main, "figure.tikz.tex").file_id, Some(figure.file_id));
+
+        let shared = edge(&graph, &intro, "shared.tex");
+        assert_eq!(shared.status, IncludeStatus::Ambiguous);
+        assert_eq!(shared.candidates, vec![shared_local.file_id, shared_root.file_id]);
+
+        // The included non-LaTeX file is a node, and both candidates count as reached
+        assert!(graph.nodes.iter().any(|node| node.file_id == figure.file_id && node.reachable));
+        assert!(graph.unreachable.is_empty());
+        assert_eq!(graph.cycles, vec![vec![intro.file_id, main.file_id]]);
+    }
+
+    #[test]
+    fn test_cycles_and_unreachable_files() {
+        let main = latex("/main.tex", &["a.tex"]);
+        let a = latex("/a.tex", &["b.tex"]);
+        let b = latex("/b.tex", &["a.tex"]);
+        let orphan = latex("/orphan.tex", &["orphan.tex"]);
+        let graph = IncludeGraph::build([&main, &a, &b, &orphan], "/main.tex");
+
+        assert_eq!(graph.cycles, vec![vec![a.file_id, b.file_id], vec![orphan.file_id]]);
+        assert_eq!(graph.unreachable, vec![orphan.file_id]);
+        assert_eq!(
+            graph.warnings(),
+            vec![
+                "Include cycle: /a.tex -> /b.tex -> /a.tex".to_string(),
+                "Include cycle: /orphan.tex -> /orphan.tex".to_string(),
+            ]
+        );
+
+        // Without a main file nothing is reachable, and nothing is reported unreachable
+        let graph = IncludeGraph::build([&a, &b], "/main.tex");
+        assert_eq!(graph.main_file_id, None);
+        assert!(graph.unreachable.is_empty());
+        assert_eq!(graph.warnings()[0], "The main file does not exist");
+    }
+
+    #[test]
+    fn test_resources_resolve_with_default_extensions() {
+        let resource = |target: &str, line_number| ResourceInfo { target: target.to_string(), line_number };
+        let main = IncludeSource {
+            graphics: vec![resource("figures/plot", 3), resource("logo.png", 4)],
+            bibliographies: vec![resource("refs.bib", 9)],
+            ..latex("/thesis/main.tex", &["chapters/intro.tex"])
+        };
+        // LaTeX runs in the main file's directory, so included files name graphics from there
+        let intro = IncludeSource {
+            graphics: vec![resource("figures/plot.pdf", 1), resource("chapters/missing", 2)],
+            ..latex("/thesis/chapters/intro.tex", &[])
+        };
+        let plot = IncludeSource { content_type: ContentType::Other, ..latex("/thesis/figures/plot.pdf", &[]) };
+        let graph = IncludeGraph::build([&main, &intro, &plot], "/thesis/main.tex");
+
+        let missing: Vec<(&str, i32)> = graph
+            .resources
+            .iter()
+            .filter(|resource| !resource.found)
+            .map(|resource| (resource.target.as_str(), resource.line_number))
+            .collect();
+        assert_eq!(missing, vec![("chapters/missing", 2), ("logo.png", 4), ("refs.bib", 9)]);
+        assert!(graph.has_file("thesis/figures/plot.pdf"));
+        assert!(!graph.has_file("/thesis/refs.bib"));
+    }
+
+    #[test]
+    fn test_targets_leaving_the_project_are_missing() {
+        let main = latex("/main.tex", &["../outside.tex", "/etc/passwd.tex"]);
+        let graph = IncludeGraph::build([&main], "/main.tex");
+
+        assert!(graph.edges.iter().all(|edge| edge.status == IncludeStatus::Missing));
+    }
+
+    #[test]
+    fn test_cache_applies_file_changes_without_reloading() {
+        let cache = IncludeGraphCache::new();
+        let project_id = Uuid::new_v4();
+        let main = latex("/main.tex", &["intro.tex"]);
+
+        let ticket = cache.begin_build(project_id);
+        let graph = cache.insert(project_id, ticket, vec![main.clone()], "/main.tex");
+        assert_eq!(graph.edges[0].status, IncludeStatus::Missing);
+
+        let intro = latex("/intro.tex", &[]);
+        cache.source_changed(project_id, intro.clone());
+        let graph = cache.get(project_id, "/main.tex").unwrap();
+        assert_eq!(graph.edges[0].file_id, Some(intro.file_id));
+
+        cache.file_removed(project_id, intro.file_id);
+        assert_eq!(cache.get(project_id, "/main.tex").unwrap().edges[0].status, IncludeStatus::Missing);
+
+        // A different main file re-resolves from the cached sources
+        assert_eq!(cache.get(project_id, "/other.tex").unwrap().main_file_id, None);
+
+        // A load that raced with a change is returned but not kept
+        cache.invalidate(project_id);
+        let ticket = cache.begin_build(project_id);
+        cache.source_changed(project_id, intro);
+        cache.insert(project_id, ticket, vec![main], "/main.tex");
+        assert!(cache.get(project_id, "/main.tex").is_none());
+    }
+
+    #[test]
+    fn test_thousand_file_project_resolves_quickly() {
+        let mut sources = vec![latex("/main.tex", &[])];
+        for chapter in 0..100 {
+            let sections: Vec<String> = (0..9).map(|section| format!("section{}.tex", section)).collect();
+            let sections: Vec<&str> = sections.iter().map(String::as_str).collect();
+            sources[0].includes.push(format!("chapters/{}/chapter.tex", chapter));
+            sources.push(latex(&format!("/chapters/{}/chapter.tex", chapter), &sections));
+            for section in 0..9 {
+                sources.push(latex(&format!("/chapters/{}/section{}.tex", chapter, section), &["../../macros.tex"]));
+            }
+        }
+        sources.push(latex("/macros.tex", &[]));
+
+        let cache = IncludeGraphCache::new();
+        let project_id = Uuid::new_v4();
+        let ticket = cache.begin_build(project_id);
+        let graph = cache.insert(project_id, ticket, sources, "/main.tex");
+        assert_eq!(graph.nodes.len(), 1002);
+        assert!(graph.unreachable.is_empty());
+        assert!(graph.edges.iter().all(|edge| edge.status == IncludeStatus::Resolved));
+
+        let median = |mut timings: Vec<Duration>| {
+            timings.sort();
+            timings[timings.len() / 2]
+        };
+        let time = |change: bool| {
+            if change {
+                cache.source_changed(project_id, latex("/macros.tex", &[]));
+            }
+            let started = Instant::now();
+            cache.get(project_id, "/main.tex").expect("sources are cached");
+            started.elapsed()
+        };
+
+        let cached = median((0..20).map(|_| time(false)).collect());
+        assert!(cached < Duration::from_millis(1), "reading took {:?}", cached);
+
+        // The first read after a change re-resolves the whole project; generous for debug builds
+        let changed = median((0..20).map(|_| time(true)).collect());
+        assert!(changed < Duration::from_millis(50), "re-resolving took {:?}", changed);
+    }
+}
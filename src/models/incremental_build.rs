@@ -0,0 +1,744 @@
+//! Incremental compiles
+//!
+//! A project's working directory is kept from one compile to the next. Before
+//! a run, [`BuildDirectory::prepare`] writes only the sources whose content
+//! hash differs from the copy already on disk, removes those deleted or moved
+//! since, and puts back the auxiliary files (`.aux`, `.toc`, `.bbl` and the
+//! like) of the last successful run, so cross references usually resolve in a
+//! single pass. The directory is built from scratch instead when the job asks
+//! for `clean`, when the command, its arguments or the project's image
+//! settings (see `compile_images`) changed, when there is no record of a
+//! previous run, or when incremental builds are off (`LATEX_BUILD_CACHE_SIZE=0`).
+//!
+//! Stale auxiliary files must never fail a compile that would have worked:
+//! [`execute`] retries a failed incremental run once from scratch and reports
+//! that run instead. How a job was built is recorded on it.
+//!
+//! Kept directories count against `LATEX_BUILD_CACHE_SIZE`; [`BuildCacheTask`]
+//! removes the least recently built ones beyond it, except those of projects
+//! compiling right now.
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashSet};
+use std::path::{Component, Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use tokio::process::Command;
+use uuid::Uuid;
+
+use super::compilation::{CompilationJob, PROJECT_WORKING_DIRECTORY_ROOT};
+use super::compile_cancel::{self, RunOutcome};
+use super::compile_images::{ImageOptimizationReport, ImageOptimizer, ImageOutcome, ImageSettings, OptimizedImage};
+use super::file::File;
+use super::integrity::ReadPath;
+use super::CompilationStatus;
+use crate::config::FileStorageConfig;
+use crate::error::AppError;
+use crate::server::AppState;
+
+/// How often kept build directories are checked against the budget
+pub const BUILD_CACHE_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+/// Directory of the build state, next to the sources; no source may live in it
+const STATE_DIR: &str = ".texler";
+
+/// Record of the last run, in [`STATE_DIR`]
+const MANIFEST_FILE: &str = "build.json";
+
+/// Auxiliary files of the last successful run, in [`STATE_DIR`]
+const SAVED_AUX_DIR: &str = "aux";
+
+/// Extensions of the files a run leaves for the next one: cross references,
+/// tables of contents, bibliographies, indexes and beamer navigation
+const AUX_EXTENSIONS: &[&str] = &["aux", "toc", "lof", "lot", "bbl", "bcf", "out", "nav", "snm", "ind", "gls"];
+
+/// What the last run left in a build directory
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct BuildManifest {
+    /// Command and arguments the directory was built with
+    fingerprint: String,
+    /// Content hash of every source written, by project path; none for
+    /// those to write again next time
+    sources: BTreeMap<String, Option<String>>,
+    /// Sources written as transcoded images, by project path
+    #[serde(default)]
+    optimized: BTreeMap<String, OptimizedImage>,
+}
+
+/// How a build directory was brought up to date
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Prepared {
+    /// The sources and auxiliary files of the previous run were reused
+    pub incremental: bool,
+    /// Sources written because they were missing or had changed
+    pub files_refreshed: i32,
+    /// Images compiled transcoded, when the project optimizes them
+    pub images: Option<ImageOptimizationReport>,
+}
+
+/// How an incremental compile went
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IncrementalRun {
+    pub outcome: RunOutcome,
+    pub incremental: bool,
+    pub files_refreshed: i32,
+    pub retried_clean: bool,
+    pub images: Option<ImageOptimizationReport>,
+}
+
+/// A project's working directory, as one job builds in it
+#[derive(Debug, Clone)]
+pub struct BuildDirectory {
+    root: PathBuf,
+    /// Where the engine writes its output and auxiliary files
+    output: PathBuf,
+}
+
+impl BuildDirectory {
+    pub fn for_job(job: &CompilationJob) -> Self {
+        let root = PathBuf::from(&job.working_directory);
+        let output = output_directory(&job.args).map_or_else(|| root.clone(), |dir| root.join(dir));
+        Self { root, output }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    fn state_dir(&self) -> PathBuf {
+        self.root.join(STATE_DIR)
+    }
+
+    async fn manifest(&self) -> Option<BuildManifest> {
+        let bytes = tokio::fs::read(self.state_dir().join(MANIFEST_FILE)).await.ok()?;
+        serde_json::from_slice(&bytes).ok()
+    }
+
+    async fn write_manifest(&self, manifest: &BuildManifest) -> Result<(), AppError> {
+        tokio::fs::create_dir_all(self.state_dir()).await?;
+        tokio::fs::write(self.state_dir().join(MANIFEST_FILE), serde_json::to_vec(manifest)?).await?;
+        Ok(())
+    }
+
+    /// Where the source at project path `path` is written
+    fn source_path(&self, path: &str) -> Result<PathBuf, AppError> {
+        let relative = Path::new(path.trim_start_matches('/'));
+        let plain = relative.components().all(|component| matches!(component, Component::Normal(_)));
+        if !plain || relative.as_os_str().is_empty() || relative.starts_with(STATE_DIR) {
+            return Err(AppError::Validation(format!(
+                "{} cannot be compiled: {} is reserved for the build state",
+                path, STATE_DIR
+            )));
+        }
+        Ok(self.root.join(relative))
+    }
+
+    /// Remove everything, for a build from scratch
+    async fn reset(&self) -> Result<(), AppError> {
+        remove_dir_if_exists(&self.root).await?;
+        tokio::fs::create_dir_all(&self.root).await?;
+        Ok(())
+    }
+
+    /// Bring the directory up to date with `files` for `job`, from scratch
+    /// when `clean` is set or the previous run cannot be reused
+    pub async fn prepare(
+        &self,
+        db: &sqlx::PgPool,
+        storage: &FileStorageConfig,
+        job: &CompilationJob,
+        files: &[File],
+        images: &ImageSettings,
+        clean: bool,
+    ) -> Result<Prepared, AppError> {
+        let fingerprint = fingerprint(job, images);
+        let previous = if clean {
+            None
+        } else {
+            self.manifest().await.filter(|manifest| manifest.fingerprint == fingerprint)
+        };
+        let incremental = previous.is_some();
+        let previous = match previous {
+            Some(manifest) => manifest,
+            None => {
+                self.reset().await?;
+                BuildManifest::default()
+            }
+        };
+
+        // Sources deleted or moved since the last run
+        let current: HashSet<&str> = files.iter().map(|file| file.path.as_str()).collect();
+        for path in previous.sources.keys().filter(|path| !current.contains(path.as_str())) {
+            remove_file_if_exists(&self.source_path(path)?).await?;
+        }
+
+        let mut optimizer = ImageOptimizer::new(images);
+        let mut manifest = BuildManifest { fingerprint, ..BuildManifest::default() };
+        let mut files_refreshed = 0;
+        for file in files {
+            let target = self.source_path(&file.path)?;
+            let mut content_hash = file.content_hash.clone();
+            let on_disk = content_hash.is_some()
+                && previous.sources.get(&file.path) == Some(&content_hash)
+                && tokio::fs::try_exists(&target).await.unwrap_or(false);
+            if on_disk {
+                if let (Some(optimizer), Some(image)) = (&mut optimizer, previous.optimized.get(&file.path)) {
+                    optimizer.report.add(image.clone());
+                    manifest.optimized.insert(file.path.clone(), image.clone());
+                }
+            } else {
+                let mut bytes = file.read_verified(db, storage, ReadPath::Compile).await?;
+                if let Some(optimizer) = &mut optimizer {
+                    let (materialized, outcome) = optimizer.materialize(&file.path, bytes).await;
+                    bytes = materialized;
+                    match outcome {
+                        ImageOutcome::Optimized(image) => {
+                            manifest.optimized.insert(file.path.clone(), image);
+                        }
+                        // Given another try next time
+                        ImageOutcome::Skipped => content_hash = None,
+                        ImageOutcome::Unchanged => {}
+                    }
+                }
+                if let Some(parent) = target.parent() {
+                    tokio::fs::create_dir_all(parent).await?;
+                }
+                tokio::fs::write(&target, bytes).await?;
+                files_refreshed += 1;
+            }
+            manifest.sources.insert(file.path.clone(), content_hash);
+        }
+
+        self.restore_aux(&manifest).await?;
+        self.write_manifest(&manifest).await?;
+        Ok(Prepared {
+            incremental,
+            files_refreshed,
+            images: optimizer.map(|optimizer| optimizer.report),
+        })
+    }
+
+    /// Sources of `manifest`, where they are on disk
+    fn source_paths(&self, manifest: &BuildManifest) -> HashSet<PathBuf> {
+        manifest.sources.keys().filter_map(|path| self.source_path(path).ok()).collect()
+    }
+
+    /// Replace the auxiliary files in the output directory with those of the
+    /// last successful run, or none when there was none
+    async fn restore_aux(&self, manifest: &BuildManifest) -> Result<(), AppError> {
+        let sources = self.source_paths(manifest);
+        for path in aux_files(&self.output).await? {
+            let path = self.output.join(path);
+            if !sources.contains(&path) {
+                remove_file_if_exists(&path).await?;
+            }
+        }
+
+        let saved = self.state_dir().join(SAVED_AUX_DIR);
+        for path in aux_files(&saved).await? {
+            let target = self.output.join(&path);
+            if let Some(parent) = target.parent() {
+                tokio::fs::create_dir_all(parent).await?;
+            }
+            tokio::fs::copy(saved.join(&path), target).await?;
+        }
+        Ok(())
+    }
+
+    /// Keep the auxiliary files of a successful run for the next one
+    pub async fn save_aux(&self) -> Result<(), AppError> {
+        let sources = self.source_paths(&self.manifest().await.unwrap_or_default());
+        let saved = self.state_dir().join(SAVED_AUX_DIR);
+        remove_dir_if_exists(&saved).await?;
+
+        for path in aux_files(&self.output).await? {
+            let source = self.output.join(&path);
+            if sources.contains(&source) {
+                continue;
+            }
+            let target = saved.join(&path);
+            if let Some(parent) = target.parent() {
+                tokio::fs::create_dir_all(parent).await?;
+            }
+            tokio::fs::copy(source, target).await?;
+        }
+        Ok(())
+    }
+}
+
+/// What a build directory's contents depend on besides the sources
+fn fingerprint(job: &CompilationJob, images: &ImageSettings) -> String {
+    let mut hasher = Sha256::new();
+    for part in std::iter::once(&job.command).chain(&job.args) {
+        hasher.update(part.as_bytes());
+        hasher.update([0]);
+    }
+    // Transcoded images depend on the settings they were transcoded with
+    if images.optimize_images_on_compile {
+        hasher.update(format!("images:{}x{}", images.max_dimension, images.jpeg_quality).as_bytes());
+    }
+    hex::encode(hasher.finalize())
+}
+
+/// The `-output-directory` of `args`, when it stays inside the working directory
+fn output_directory(args: &[String]) -> Option<PathBuf> {
+    let dir = args.iter().find_map(|arg| {
+        arg.strip_prefix("-output-directory=")
+            .or_else(|| arg.strip_prefix("--output-directory="))
+    })?;
+    let dir = Path::new(dir);
+    let plain = dir.components().all(|component| matches!(component, Component::Normal(_)));
+    (plain && !dir.as_os_str().is_empty()).then(|| dir.to_path_buf())
+}
+
+/// Auxiliary files below `dir`, relative to it, outside the build state
+async fn aux_files(dir: &Path) -> Result<Vec<PathBuf>, AppError> {
+    let mut found = Vec::new();
+    let mut pending = vec![PathBuf::new()];
+    while let Some(relative) = pending.pop() {
+        let mut entries = match tokio::fs::read_dir(dir.join(&relative)).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e.into()),
+        };
+        while let Some(entry) = entries.next_entry().await? {
+            let path = relative.join(entry.file_name());
+            if entry.file_type().await?.is_dir() {
+                if entry.file_name() != STATE_DIR {
+                    pending.push(path);
+                }
+            } else if path
+                .extension()
+                .and_then(|ext| ext.to_str())
+                .is_some_and(|ext| AUX_EXTENSIONS.contains(&ext))
+            {
+                found.push(path);
+            }
+        }
+    }
+    Ok(found)
+}
+
+async fn remove_file_if_exists(path: &Path) -> Result<(), AppError> {
+    match tokio::fs::remove_file(path).await {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+async fn remove_dir_if_exists(path: &Path) -> Result<(), AppError> {
+    match tokio::fs::remove_dir_all(path).await {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+/// Size of the files below `dir`
+async fn directory_size(dir: &Path) -> Result<u64, AppError> {
+    let mut bytes = 0;
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        let mut entries = match tokio::fs::read_dir(&dir).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e.into()),
+        };
+        while let Some(entry) = entries.next_entry().await? {
+            let metadata = entry.metadata().await?;
+            if metadata.is_dir() {
+                pending.push(entry.path());
+            } else {
+                bytes += metadata.len();
+            }
+        }
+    }
+    Ok(bytes)
+}
+
+impl CompilationJob {
+    /// Record how the job was built
+    pub async fn record_build(
+        &self,
+        db: &sqlx::PgPool,
+        incremental: bool,
+        files_refreshed: i32,
+        retried_clean: bool,
+    ) -> Result<(), AppError> {
+        sqlx::query(
+            r#"
+            UPDATE compilation_jobs
+            SET incremental = $1, files_refreshed = $2, retried_clean = $3, updated_at = NOW()
+            WHERE id = $4
+            "#
+        )
+        .bind(incremental)
+        .bind(files_refreshed)
+        .bind(retried_clean)
+        .bind(self.id)
+        .execute(db)
+        .await
+        .map_err(AppError::Database)?;
+
+        Ok(())
+    }
+}
+
+/// Start `job` and build `files` in its project's build directory, running
+/// the command `command` makes for that directory
+///
+/// Returns `None` when the job was cancelled before it started. A failed
+/// incremental run is retried once from scratch and only the retry is
+/// reported; cancelled runs are not retried. How the job was built is
+/// recorded on it; the result is for the worker to complete.
+pub async fn execute(
+    state: &AppState,
+    job: &CompilationJob,
+    files: &[File],
+    command: impl Fn(&Path) -> Command,
+    worker_id: Option<String>,
+) -> Result<Option<IncrementalRun>, AppError> {
+    let Some(mut handle) = compile_cancel::begin(state, job, worker_id).await? else {
+        return Ok(None);
+    };
+
+    let directory = BuildDirectory::for_job(job);
+    let storage = &state.config.features.file_storage;
+    let images = ImageSettings::for_project(&state.db_pool, job.project_id).await?;
+    let clean = job.clean || state.config.latex.build_cache_size == 0;
+    let mut prepared = directory.prepare(&state.db_pool, storage, job, files, &images, clean).await?;
+    publish_images(state, job, &prepared);
+    let mut outcome = compile_cancel::run(state, job, &mut command(directory.root()), &mut handle).await?;
+
+    let failed = matches!(outcome, RunOutcome::Exited { exit_code, .. } if exit_code != 0);
+    let retried_clean = prepared.incremental && failed;
+    if retried_clean {
+        tracing::info!("Incremental build of job {} failed, building it again from scratch", job.id);
+        prepared = directory.prepare(&state.db_pool, storage, job, files, &images, true).await?;
+        publish_images(state, job, &prepared);
+        outcome = compile_cancel::run(state, job, &mut command(directory.root()), &mut handle).await?;
+    }
+
+    if matches!(outcome, RunOutcome::Exited { exit_code: 0, .. }) {
+        directory.save_aux().await?;
+    }
+    job.record_build(&state.db_pool, prepared.incremental, prepared.files_refreshed, retried_clean).await?;
+    if let Some(report) = &prepared.images {
+        job.record_image_optimization(&state.db_pool, report).await?;
+    }
+
+    Ok(Some(IncrementalRun {
+        outcome,
+        incremental: prepared.incremental,
+        files_refreshed: prepared.files_refreshed,
+        retried_clean,
+        images: prepared.images,
+    }))
+}
+
+/// Tell the job's creator what image optimization did, if anything
+fn publish_images(state: &AppState, job: &CompilationJob, prepared: &Prepared) {
+    if let Some(summary) = prepared.images.as_ref().and_then(|report| report.summary()) {
+        compile_cancel::publish_progress(state, job, CompilationStatus::Running, Some(summary));
+    }
+}
+
+/// Outcome of holding kept build directories to the budget
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Eviction {
+    pub removed: usize,
+    pub freed_bytes: u64,
+    pub kept_bytes: u64,
+}
+
+/// Remove the least recently built project directories below `root` until
+/// the rest fit in `budget` bytes; those of `busy` projects stay
+pub async fn evict(root: &Path, budget: u64, busy: &HashSet<Uuid>) -> Result<Eviction, AppError> {
+    let mut entries = match tokio::fs::read_dir(root).await {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Eviction::default()),
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut directories = Vec::new();
+    while let Some(entry) = entries.next_entry().await? {
+        let Some(project_id) = entry.file_name().to_str().and_then(|name| Uuid::parse_str(name).ok()) else {
+            continue;
+        };
+        if !entry.file_type().await?.is_dir() {
+            continue;
+        }
+        let path = entry.path();
+        // Every run rewrites the manifest
+        let metadata = match tokio::fs::metadata(path.join(STATE_DIR).join(MANIFEST_FILE)).await {
+            Ok(metadata) => metadata,
+            Err(_) => entry.metadata().await?,
+        };
+        let last_built = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+        let size = directory_size(&path).await?;
+        directories.push((last_built, project_id, path, size));
+    }
+    directories.sort_by_key(|(last_built, ..)| *last_built);
+
+    let mut eviction = Eviction {
+        kept_bytes: directories.iter().map(|(.., size)| size).sum(),
+        ..Eviction::default()
+    };
+    for (_, project_id, path, size) in directories {
+        if eviction.kept_bytes <= budget {
+            break;
+        }
+        if busy.contains(&project_id) {
+            continue;
+        }
+        remove_dir_if_exists(&path).await?;
+        eviction.removed += 1;
+        eviction.freed_bytes += size;
+        eviction.kept_bytes -= size;
+    }
+    Ok(eviction)
+}
+
+/// Holds kept build directories to `LATEX_BUILD_CACHE_SIZE`
+pub struct BuildCacheTask;
+
+impl crate::tasks::PeriodicTask for BuildCacheTask {
+    fn name(&self) -> &'static str {
+        "compile_build_cache"
+    }
+
+    fn interval(&self) -> Duration {
+        BUILD_CACHE_INTERVAL
+    }
+
+    fn run<'a>(&'a self, state: &'a AppState) -> futures::future::BoxFuture<'a, Result<(), AppError>> {
+        Box::pin(async move {
+            let busy: HashSet<Uuid> = sqlx::query_scalar::<_, Uuid>(
+                "SELECT DISTINCT project_id FROM compilation_jobs WHERE status = 'running'"
+            )
+            .fetch_all(&state.db_pool)
+            .await
+            .map_err(AppError::Database)?
+            .into_iter()
+            .collect();
+
+            let root = Path::new(PROJECT_WORKING_DIRECTORY_ROOT);
+            let eviction = evict(root, state.config.latex.build_cache_size, &busy).await?;
+            if eviction.removed > 0 {
+                tracing::info!(
+                    "Removed {} build directories ({} bytes) over the build cache budget, {} bytes kept",
+                    eviction.removed,
+                    eviction.freed_bytes,
+                    eviction.kept_bytes
+                );
+            }
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::file::CreateFile;
+    use crate::models::project::Project;
+    use crate::models::project_limits::ProjectLimits;
+    use crate::models::user::User;
+    use crate::models::validation::FileName;
+    use crate::testing::{create_test_file, create_test_job, create_test_project, create_test_user, test_state, TestDb};
+
+    /// Stands in for the engine: fails on stale auxiliary files, needs a
+    /// second pass when there are none, and leaves them for the next run
+    const FAKE_ENGINE: &str = r#"
+        mkdir -p output
+        if grep -q stale output/main.aux 2>/dev/null; then echo "stale main.aux" >&2; exit 1; fi
+        cat $(find . -name '*.tex' -not -path './.texler/*') > /dev/null
+        [ -f output/main.aux ] || sleep 0.3
+        echo '\relax' > output/main.aux
+        cat main.tex > output/main.pdf
+    "#;
+
+    fn fake_engine(root: &Path) -> Command {
+        let mut command = Command::new("sh");
+        command.arg("-c").arg(FAKE_ENGINE).current_dir(root);
+        command
+    }
+
+    async fn build(
+        state: &AppState,
+        project: &Project,
+        user: &User,
+        root: &Path,
+        clean: bool,
+    ) -> IncrementalRun {
+        let mut job = create_test_job(&state.db_pool, project, user).await;
+        job.working_directory = root.to_string_lossy().into_owned();
+        job.args = vec!["-output-directory=output".to_string(), "main.tex".to_string()];
+        job.clean = clean;
+        let files = crate::models::file::File::list_all_for_project(&state.db_pool, project.id).await.unwrap();
+        execute(state, &job, &files, fake_engine, None).await.unwrap().expect("the job should start")
+    }
+
+    fn succeeded(run: &IncrementalRun) -> bool {
+        matches!(run.outcome, RunOutcome::Exited { exit_code: 0, .. })
+    }
+
+    #[test]
+    fn test_sources_and_output_stay_inside_the_directory() {
+        let args = |arg: &str| vec!["-synctex=1".to_string(), arg.to_string()];
+        assert_eq!(output_directory(&args("-output-directory=output")), Some(PathBuf::from("output")));
+        assert_eq!(output_directory(&args("--output-directory=build/pdf")), Some(PathBuf::from("build/pdf")));
+        assert_eq!(output_directory(&args("-output-directory=../elsewhere")), None);
+        assert_eq!(output_directory(&args("-output-directory=/tmp")), None);
+        assert_eq!(output_directory(&args("main.tex")), None);
+
+        let directory = BuildDirectory { root: PathBuf::from("/work/p"), output: PathBuf::from("/work/p/output") };
+        assert_eq!(directory.source_path("/chapters/one.tex").unwrap(), PathBuf::from("/work/p/chapters/one.tex"));
+        for path in ["../escape.tex", "chapters/../../escape.tex", ".texler/build.json", ""] {
+            assert!(matches!(directory.source_path(path), Err(AppError::Validation(_))), "{:?} was accepted", path);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_eviction_removes_the_least_recently_built_first() {
+        let root = tempfile::tempdir().unwrap();
+        let built = |minutes_ago: u64| SystemTime::now() - Duration::from_secs(minutes_ago * 60);
+        let mut projects = Vec::new();
+        for minutes_ago in [30, 20, 10] {
+            let project_id = Uuid::new_v4();
+            let state = root.path().join(project_id.to_string()).join(STATE_DIR);
+            std::fs::create_dir_all(&state).unwrap();
+            std::fs::write(state.join(MANIFEST_FILE), vec![b' '; 1000]).unwrap();
+            std::fs::File::options()
+                .write(true)
+                .open(state.join(MANIFEST_FILE))
+                .unwrap()
+                .set_modified(built(minutes_ago))
+                .unwrap();
+            projects.push(project_id);
+        }
+        std::fs::create_dir(root.path().join("not-a-project")).unwrap();
+
+        // The oldest is compiling, so the next oldest goes
+        let busy = HashSet::from([projects[0]]);
+        let eviction = evict(root.path(), 2500, &busy).await.unwrap();
+        assert_eq!(eviction, Eviction { removed: 1, freed_bytes: 1000, kept_bytes: 2000 });
+        assert!(root.path().join(projects[0].to_string()).exists());
+        assert!(!root.path().join(projects[1].to_string()).exists());
+        assert!(root.path().join(projects[2].to_string()).exists());
+        assert!(root.path().join("not-a-project").exists());
+
+        let eviction = evict(root.path(), 0, &HashSet::new()).await.unwrap();
+        assert_eq!(eviction, Eviction { removed: 2, freed_bytes: 2000, kept_bytes: 0 });
+        assert_eq!(evict(&root.path().join("missing"), 0, &HashSet::new()).await.unwrap(), Eviction::default());
+    }
+
+    #[tokio::test]
+    async fn test_builds_reuse_the_previous_run() {
+        let Some(db) = TestDb::start().await else { return };
+        let state = test_state(&db).await;
+        let user = create_test_user(&db.pool).await;
+        let project = create_test_project(&db.pool, &user, false).await;
+        let main = create_test_file(&db.pool, &project, &user).await;
+        let chapter = crate::models::file::File::create(
+            &db.pool,
+            &ProjectLimits::default(),
+            project.id,
+            CreateFile {
+                name: FileName::new("one.tex").unwrap(),
+                path: "chapters/one.tex".to_string(),
+                content: Some("One\n".to_string()),
+                content_type: None,
+            },
+            user.id,
+        )
+        .await
+        .unwrap();
+        let work = tempfile::tempdir().unwrap();
+        let root = work.path().join(project.id.to_string());
+
+        let first = build(&state, &project, &user, &root, false).await;
+        assert!(succeeded(&first));
+        assert_eq!((first.incremental, first.files_refreshed, first.retried_clean), (false, 2, false));
+        assert!(root.join("chapters/one.tex").exists());
+
+        // Nothing changed
+        let noop = build(&state, &project, &user, &root, false).await;
+        assert!(succeeded(&noop));
+        assert_eq!((noop.incremental, noop.files_refreshed, noop.retried_clean), (true, 0, false));
+
+        // One source edited, the other deleted
+        let storage = &state.config.features.file_storage.local_path;
+        main.update_content(&db.pool, storage, "\\documentclass{article}\nEdited\n".to_string(), user.id)
+            .await
+            .unwrap();
+        chapter.soft_delete(&db.pool, user.id).await.unwrap();
+        let edited = build(&state, &project, &user, &root, false).await;
+        assert!(succeeded(&edited));
+        assert_eq!((edited.incremental, edited.files_refreshed), (true, 1));
+        assert!(std::fs::read_to_string(root.join("output/main.pdf")).unwrap().contains("Edited"));
+        assert!(!root.join("chapters/one.tex").exists());
+
+        // Auxiliary files the engine chokes on are retried away
+        std::fs::write(root.join(STATE_DIR).join(SAVED_AUX_DIR).join("main.aux"), "stale").unwrap();
+        let retried = build(&state, &project, &user, &root, false).await;
+        assert!(succeeded(&retried));
+        assert_eq!((retried.incremental, retried.retried_clean), (false, true));
+
+        let clean = build(&state, &project, &user, &root, true).await;
+        assert!(succeeded(&clean));
+        assert_eq!((clean.incremental, clean.files_refreshed, clean.retried_clean), (false, 1, false));
+
+        let (incremental, files_refreshed, retried_clean): (bool, Option<i32>, bool) = sqlx::query_as(
+            "SELECT incremental, files_refreshed, retried_clean FROM compilation_jobs
+             WHERE project_id = $1 ORDER BY created_at DESC LIMIT 1 OFFSET 1"
+        )
+        .bind(project.id)
+        .fetch_one(&db.pool)
+        .await
+        .unwrap();
+        assert_eq!((incremental, files_refreshed, retried_clean), (false, Some(1), true));
+    }
+
+    #[tokio::test]
+    async fn test_noop_incremental_rebuild_rewrites_no_sources() {
+        let Some(db) = TestDb::start().await else { return };
+        let state = test_state(&db).await;
+        let user = create_test_user(&db.pool).await;
+        let project = create_test_project(&db.pool, &user, false).await;
+        create_test_file(&db.pool, &project, &user).await;
+        for chapter in 0..120 {
+            let content = format!("\\section{{Chapter {}}}\n{}\n", chapter, "Lorem ipsum dolor sit amet. ".repeat(200));
+            crate::models::file::File::create(
+                &db.pool,
+                &ProjectLimits::default(),
+                project.id,
+                CreateFile {
+                    name: FileName::new(&format!("chapter{}.tex", chapter)).unwrap(),
+                    path: format!("chapters/chapter{}.tex", chapter),
+                    content: Some(content),
+                    content_type: None,
+                },
+                user.id,
+            )
+            .await
+            .unwrap();
+        }
+        let work = tempfile::tempdir().unwrap();
+        let root = work.path().join(project.id.to_string());
+
+        let sources: Vec<_> = std::iter::once(root.join("main.tex"))
+            .chain((0..120).map(|chapter| root.join(format!("chapters/chapter{}.tex", chapter))))
+            .collect();
+        let modified = || sources.iter().map(|path| std::fs::metadata(path).unwrap().modified().unwrap()).collect::<Vec<_>>();
+
+        let clean = build(&state, &project, &user, &root, true).await;
+        let written = modified();
+        let noop = build(&state, &project, &user, &root, false).await;
+
+        assert!(succeeded(&clean) && succeeded(&noop));
+        assert_eq!((clean.incremental, clean.files_refreshed), (false, 121));
+        assert_eq!((noop.incremental, noop.files_refreshed), (true, 0));
+        // Every source is reused from the previous run as it is
+        assert_eq!(modified(), written);
+    }
+}
@@ -0,0 +1,462 @@
+//! Inline math rendering for chat
+//!
+//! Short math snippets are typeset on their own in a fixed document that loads
+//! nothing beyond `ALLOWED_PACKAGES`, run through `latex` and `dvisvgm`, and
+//! served as SVG by the hash of the snippet. Input comes straight from chat,
+//! so it is checked before TeX sees it: no primitives that read or write
+//! files, define macros or change category codes, only math environments,
+//! and the whole render gets `RENDER_TIMEOUT` of wall-clock time. TeX itself
+//! runs with shell escape off and paranoid file access as a second line.
+//!
+//! Rendered SVGs are kept in memory and under the LaTeX temp directory, so a
+//! snippet is only ever typeset once.
+
+use regex::Regex;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, VecDeque};
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+use utoipa::ToSchema;
+
+use super::compile_sandbox::confine;
+use crate::error::AppError;
+use crate::middleware::rate_limit::RateLimitConfig;
+
+/// Largest snippet accepted, in bytes
+pub const MAX_SNIPPET_BYTES: usize = 2048;
+
+/// Uncached fragments typeset while listing chat messages
+pub const MAX_RENDERS_PER_REQUEST: usize = 16;
+
+/// Wall-clock budget for typesetting and converting one snippet
+pub const RENDER_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// The only packages a snippet is typeset with
+pub const ALLOWED_PACKAGES: &[&str] = &["amsmath", "amssymb"];
+
+/// Rendered snippets kept in memory
+pub const INLINE_RENDER_CACHE_CAPACITY: usize = 1024;
+
+/// Math fragments rendered per chat message
+pub const MAX_FRAGMENTS_PER_MESSAGE: usize = 8;
+
+/// Renders per user through the render endpoint
+pub const INLINE_RENDER_LIMIT: RateLimitConfig = RateLimitConfig {
+    requests_per_window: 30,
+    window_duration: Duration::from_secs(60),
+    burst_size: 5,
+};
+
+/// Control words a snippet may not use
+const FORBIDDEN_COMMANDS: &[&str] = &[
+    "afterassignment", "aftergroup", "batchmode", "catcode", "closein", "closeout", "csname",
+    "def", "DeclareRobustCommand", "directlua", "documentclass", "dump", "edef", "endcsname",
+    "endinput", "errmessage", "errorstopmode", "everycr", "everydisplay", "everyhbox", "everyjob",
+    "everymath", "everypar", "everyvbox", "expandafter", "filedump", "filemoddate", "filesize",
+    "futurelet", "gdef", "include", "includeonly", "input", "jobname", "latelua", "lccode", "let",
+    "loop", "makeatletter", "makeatother", "mdfivesum", "message", "newcommand", "newenvironment",
+    "newread", "newwrite", "nonstopmode", "openin", "openout", "output", "pdffiledump",
+    "pdffilemoddate", "pdffilesize", "pdfmdfivesum", "pdfprimitive", "pdfshellescape", "primitive",
+    "providecommand", "read", "readline", "renewcommand", "renewenvironment", "repeat",
+    "RequirePackage", "scantokens", "scrollmode", "shipout", "special", "typein", "typeout",
+    "uccode", "usepackage", "write", "xdef",
+];
+
+/// Environments a snippet may open
+const ALLOWED_ENVIRONMENTS: &[&str] = &[
+    "array", "Bmatrix", "bmatrix", "cases", "aligned", "gathered", "matrix", "pmatrix", "smallmatrix",
+    "split", "subarray", "Vmatrix", "vmatrix",
+];
+
+/// Bumped whenever the document template changes, so old renders are not reused
+const TEMPLATE_VERSION: &str = "1";
+
+fn document(snippet: &str) -> String {
+    let packages: String = ALLOWED_PACKAGES
+        .iter()
+        .map(|package| format!("\\usepackage{{{}}}\n", package))
+        .collect();
+
+    format!(
+        "\\documentclass{{article}}\n{}\\pagestyle{{empty}}\n\\begin{{document}}\n$\\displaystyle {}$\n\\end{{document}}\n",
+        packages, snippet
+    )
+}
+
+/// Strip the math delimiters around a snippet and check it is safe to typeset
+pub fn normalize_snippet(snippet: &str) -> Result<String, AppError> {
+    if snippet.len() > MAX_SNIPPET_BYTES {
+        return Err(AppError::Validation(format!(
+            "Snippet is longer than {} bytes",
+            MAX_SNIPPET_BYTES
+        )));
+    }
+
+    let mut snippet = snippet.trim();
+    for (open, close) in [("$$", "$$"), ("$", "$"), ("\\[", "\\]"), ("\\(", "\\)")] {
+        if let Some(inner) = snippet.strip_prefix(open).and_then(|rest| rest.strip_suffix(close)) {
+            snippet = inner.trim();
+            break;
+        }
+    }
+
+    if snippet.is_empty() {
+        return Err(AppError::Validation("Snippet is empty".to_string()));
+    }
+    check_snippet(snippet)?;
+    Ok(snippet.to_string())
+}
+
+fn check_snippet(snippet: &str) -> Result<(), AppError> {
+    static CONTROL_WORD: OnceLock<Regex> = OnceLock::new();
+    let control_word = CONTROL_WORD.get_or_init(|| Regex::new(r"\\([A-Za-z@]+)(\s*\{([^}]*)\})?").unwrap());
+
+    let reject = |reason: String| Err(AppError::Validation(reason));
+
+    // `^^` spells characters by code, `$` would leave math mode and `#` only means something in definitions
+    for pattern in ["^^", "$", "#"] {
+        if snippet.contains(pattern) {
+            return reject(format!("Snippets may not contain '{}'", pattern));
+        }
+    }
+    if snippet.chars().any(|c| c.is_control() && !c.is_whitespace()) {
+        return reject("Snippets may not contain control characters".to_string());
+    }
+
+    let mut depth: i32 = 0;
+    for c in snippet.chars() {
+        match c {
+            '{' => depth += 1,
+            '}' => depth -= 1,
+            _ => {}
+        }
+        if depth < 0 {
+            return reject("Unbalanced braces".to_string());
+        }
+    }
+    if depth != 0 {
+        return reject("Unbalanced braces".to_string());
+    }
+
+    for captures in control_word.captures_iter(snippet) {
+        let command = &captures[1];
+        if command.contains('@') || FORBIDDEN_COMMANDS.contains(&command) {
+            return reject(format!("\\{} is not allowed in snippets", command));
+        }
+        if command == "begin" || command == "end" {
+            let environment = captures.get(3).map(|m| m.as_str().trim());
+            match environment {
+                Some(environment) if ALLOWED_ENVIRONMENTS.contains(&environment) => {}
+                Some(environment) => return reject(format!("Environment '{}' is not allowed in snippets", environment)),
+                None => return reject(format!("\\{} is not allowed in snippets", command)),
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Cache key of a normalized snippet
+pub fn snippet_hash(snippet: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(TEMPLATE_VERSION.as_bytes());
+    hasher.update([0]);
+    hasher.update(snippet.as_bytes());
+    format!("{:x}", hasher.finalize())
+}
+
+fn is_hash(value: &str) -> bool {
+    value.len() == 64 && value.bytes().all(|b| b.is_ascii_hexdigit() && !b.is_ascii_uppercase())
+}
+
+/// Where a rendered snippet is served
+pub fn asset_url(hash: &str) -> String {
+    format!("/api/v1/latex/render-inline/{}", hash)
+}
+
+/// Math fragments in a chat message: `$...$`, `$$...$$`, `\(...\)` and `\[...\]`
+pub fn math_fragments(content: &str) -> Vec<String> {
+    static FRAGMENT: OnceLock<Regex> = OnceLock::new();
+    let fragment = FRAGMENT.get_or_init(|| {
+        Regex::new(r"(?s)\$\$(.+?)\$\$|\$([^$]+?)\$|\\\((.+?)\\\)|\\\[(.+?)\\\]").unwrap()
+    });
+
+    fragment
+        .captures_iter(content)
+        .filter_map(|captures| (1..=4).find_map(|group| captures.get(group)))
+        .map(|m| m.as_str().trim().to_string())
+        .filter(|fragment| !fragment.is_empty())
+        .take(MAX_FRAGMENTS_PER_MESSAGE)
+        .collect()
+}
+
+/// Typeset a normalized snippet in `dir` and return the SVG
+async fn typeset(dir: &Path, snippet: &str) -> Result<Vec<u8>, AppError> {
+    tokio::fs::write(dir.join("snippet.tex"), document(snippet)).await?;
+
+    let mut latex = tokio::process::Command::new("latex");
+    let latex = confine(&mut latex, dir)
+        .args(["-no-shell-escape", "-interaction=nonstopmode", "-halt-on-error", "snippet.tex"])
+        .output()
+        .await
+        .map_err(|e| AppError::Internal(format!("Failed to run latex: {}", e)))?;
+
+    if !latex.status.success() {
+        let log = String::from_utf8_lossy(&latex.stdout);
+        let error = log
+            .lines()
+            .find(|line| line.starts_with("! "))
+            .map(|line| line.trim_start_matches("! ").to_string())
+            .unwrap_or_else(|| "LaTeX error".to_string());
+        return Err(AppError::Validation(format!("Snippet does not compile: {}", error)));
+    }
+
+    let dvisvgm = tokio::process::Command::new("dvisvgm")
+        .args(["--no-fonts", "--bbox=min", "--stdout", "snippet.dvi"])
+        .current_dir(dir)
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .kill_on_drop(true)
+        .output()
+        .await
+        .map_err(|e| AppError::Internal(format!("Failed to run dvisvgm: {}", e)))?;
+
+    if !dvisvgm.status.success() || dvisvgm.stdout.is_empty() {
+        return Err(AppError::Internal("dvisvgm could not convert the snippet".to_string()));
+    }
+
+    Ok(dvisvgm.stdout)
+}
+
+/// A rendered snippet
+#[derive(Debug, Clone)]
+pub struct RenderedSnippet {
+    pub hash: String,
+    pub svg: Arc<Vec<u8>>,
+}
+
+/// A math fragment of a chat message and where its render is served
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct RenderedMath {
+    pub source: String,
+    /// Unset when the fragment failed or has not been rendered yet
+    pub url: Option<String>,
+    pub error: Option<String>,
+}
+
+/// Rendered SVGs by hash, with insertion order for eviction
+type RenderCache = (HashMap<String, Arc<Vec<u8>>>, VecDeque<String>);
+
+/// Renders snippets and keeps the results
+#[derive(Debug)]
+pub struct InlineRenderer {
+    root: PathBuf,
+    entries: Mutex<RenderCache>,
+}
+
+impl InlineRenderer {
+    /// Renderer keeping its files under `temp_dir`
+    pub fn new(temp_dir: &str) -> Self {
+        Self {
+            root: Path::new(temp_dir).join("inline-render"),
+            entries: Mutex::new((HashMap::new(), VecDeque::new())),
+        }
+    }
+
+    fn remember(&self, hash: &str, svg: Arc<Vec<u8>>) {
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        let (map, order) = &mut *entries;
+        if map.insert(hash.to_string(), svg).is_none() {
+            order.push_back(hash.to_string());
+        }
+        while order.len() > INLINE_RENDER_CACHE_CAPACITY {
+            if let Some(oldest) = order.pop_front() {
+                map.remove(&oldest);
+            }
+        }
+    }
+
+    /// A previously rendered snippet, from memory or disk
+    pub async fn cached(&self, hash: &str) -> Option<Arc<Vec<u8>>> {
+        if !is_hash(hash) {
+            return None;
+        }
+
+        let svg = {
+            let entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+            entries.0.get(hash).cloned()
+        };
+        if svg.is_some() {
+            return svg;
+        }
+
+        let svg = Arc::new(tokio::fs::read(self.root.join(format!("{}.svg", hash))).await.ok()?);
+        self.remember(hash, svg.clone());
+        Some(svg)
+    }
+
+    /// Render a snippet, or return the earlier render of the same snippet
+    pub async fn render(&self, snippet: &str) -> Result<RenderedSnippet, AppError> {
+        let snippet = normalize_snippet(snippet)?;
+        let hash = snippet_hash(&snippet);
+        if let Some(svg) = self.cached(&hash).await {
+            return Ok(RenderedSnippet { hash, svg });
+        }
+
+        let dir = self.root.join(format!("work-{}", uuid::Uuid::new_v4()));
+        tokio::fs::create_dir_all(&dir).await?;
+        let result = tokio::time::timeout(RENDER_TIMEOUT, typeset(&dir, &snippet)).await;
+        if let Err(e) = tokio::fs::remove_dir_all(&dir).await {
+            tracing::warn!("Failed to remove {}: {}", dir.display(), e);
+        }
+
+        let svg = match result {
+            Ok(svg) => Arc::new(svg?),
+            Err(_) => {
+                return Err(AppError::Validation(format!(
+                    "Snippet took longer than {} seconds to render",
+                    RENDER_TIMEOUT.as_secs()
+                )))
+            }
+        };
+
+        if let Err(e) = tokio::fs::write(self.root.join(format!("{}.svg", hash)), svg.as_slice()).await {
+            tracing::warn!("Failed to store rendered snippet {}: {}", hash, e);
+        }
+        self.remember(&hash, svg.clone());
+
+        Ok(RenderedSnippet { hash, svg })
+    }
+
+    /// Render the math fragments of a chat message
+    ///
+    /// Fragments already rendered are always returned; new ones are typeset
+    /// while `budget` lasts and otherwise left without a URL for a later request.
+    pub async fn render_fragments(&self, content: &str, budget: &mut usize) -> Vec<RenderedMath> {
+        let mut rendered = Vec::new();
+        for source in math_fragments(content) {
+            let result = match normalize_snippet(&source).map(|snippet| snippet_hash(&snippet)) {
+                Ok(hash) if self.cached(&hash).await.is_some() => Ok(Some(hash)),
+                Ok(_) if *budget == 0 => Ok(None),
+                Ok(_) => {
+                    *budget -= 1;
+                    self.render(&source).await.map(|rendered| Some(rendered.hash))
+                }
+                Err(e) => Err(e),
+            };
+
+            let (url, error) = match result {
+                Ok(hash) => (hash.as_deref().map(asset_url), None),
+                Err(AppError::Validation(message)) => (None, Some(message)),
+                Err(e) => {
+                    tracing::warn!("Failed to render math fragment: {}", e);
+                    (None, Some("Rendering failed".to_string()))
+                }
+            };
+            rendered.push(RenderedMath { source, url, error });
+        }
+        rendered
+    }
+
+    /// Store a render as if it had just been typeset
+    #[cfg(test)]
+    fn insert(&self, snippet: &str, svg: &[u8]) -> String {
+        let hash = snippet_hash(&normalize_snippet(snippet).unwrap());
+        self.remember(&hash, Arc::new(svg.to_vec()));
+        hash
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_snippets_are_normalized() {
+        assert_eq!(normalize_snippet(" $x^2$ ").unwrap(), "x^2");
+        assert_eq!(normalize_snippet("$$ \\frac{a}{b} $$").unwrap(), "\\frac{a}{b}");
+        assert_eq!(normalize_snippet("\\[\\sum_i i\\]").unwrap(), "\\sum_i i");
+        assert_eq!(
+            normalize_snippet("\\begin{pmatrix} a & b \\\\ c & d \\end{pmatrix}").unwrap(),
+            "\\begin{pmatrix} a & b \\\\ c & d \\end{pmatrix}"
+        );
+        assert_eq!(snippet_hash("x^2"), snippet_hash(&normalize_snippet("$x^2$").unwrap()));
+    }
+
+    #[test]
+    fn test_adversarial_snippets_are_rejected() {
+        let rejected = [
+            "\\input{/etc/passwd}",
+            "\\input /etc/passwd",
+            "\\csname input\\endcsname{secret}",
+            "\\immediate\\write18{rm -rf /}",
+            "\\catcode`\\^^5c=0",
+            "^^5cinput{x}",
+            "\\def\\x{\\x}\\x",
+            "\\pdffiledump length 100 {/etc/shadow}",
+            "\\loop\\iftrue\\repeat",
+            "\\usepackage{hyperref}",
+            "\\end{document}",
+            "\\begin{verbatim}x\\end{verbatim}",
+            "\\end",
+            "\\makeatletter\\@@input x",
+            "x$ \\openin1=secret $",
+            "{x",
+            "x}{",
+            "#1",
+        ];
+        for snippet in rejected {
+            assert!(
+                matches!(normalize_snippet(snippet), Err(AppError::Validation(_))),
+                "{} was accepted",
+                snippet
+            );
+        }
+
+        assert!(normalize_snippet(&"x".repeat(MAX_SNIPPET_BYTES + 1)).is_err());
+        assert!(normalize_snippet("$ $").is_err());
+        assert!(normalize_snippet("\\inputlength + \\readiness").is_ok());
+    }
+
+    #[test]
+    fn test_math_fragments() {
+        let fragments = math_fragments("Euler: $e^{i\\pi} + 1 = 0$, and $$\\int_0^1 x\\,dx$$ or \\(a\\) \\[b\\]; costs $5");
+        assert_eq!(fragments, vec!["e^{i\\pi} + 1 = 0", "\\int_0^1 x\\,dx", "a", "b"]);
+
+        let many = "$x$ ".repeat(MAX_FRAGMENTS_PER_MESSAGE + 3);
+        assert_eq!(math_fragments(&many).len(), MAX_FRAGMENTS_PER_MESSAGE);
+    }
+
+    #[tokio::test]
+    async fn test_renders_are_cached_by_hash() {
+        let dir = std::env::temp_dir().join(format!("texler-inline-{}", uuid::Uuid::new_v4()));
+        let renderer = InlineRenderer::new(dir.to_str().unwrap());
+
+        let hash = renderer.insert("$a+b$", b"<svg/>");
+        let rendered = renderer.render("a+b").await.unwrap();
+        assert_eq!(rendered.hash, hash);
+        assert_eq!(rendered.svg.as_slice(), b"<svg/>");
+
+        assert!(renderer.cached(&snippet_hash("c")).await.is_none());
+        assert!(renderer.cached("../../etc/passwd").await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_fragments_respect_render_budget() {
+        let dir = std::env::temp_dir().join(format!("texler-inline-{}", uuid::Uuid::new_v4()));
+        let renderer = InlineRenderer::new(dir.to_str().unwrap());
+        let hash = renderer.insert("x^2", b"<svg/>");
+
+        let mut budget = 0;
+        let rendered = renderer
+            .render_fragments("cached $x^2$, new $y^2$, bad $\\input{a}$", &mut budget)
+            .await;
+        assert_eq!(rendered.len(), 3);
+        assert_eq!(rendered[0].url, Some(asset_url(&hash)));
+        assert_eq!((rendered[1].url.as_ref(), rendered[1].error.as_ref()), (None, None));
+        assert!(rendered[2].error.is_some());
+    }
+}
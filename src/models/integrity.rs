@@ -0,0 +1,476 @@
+//! Verification of stored content against its recorded hash
+//!
+//! Every file row and blob records the SHA-256 of its content. Reads that hand
+//! content to users or to the compiler recompute it: downloads and exports up
+//! to `FILE_VERIFY_MAX_SIZE`, compiles always. A mismatch is logged, recorded
+//! as a `data_integrity_incidents` row and reported as `ContentCorrupted`
+//! instead of serving the damaged bytes. The `integrity_scan` task checks
+//! every stored blob and inline file in the background, so corruption of
+//! rarely read content is found too.
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use sqlx::FromRow;
+use std::path::Path;
+use std::time::Duration;
+use tokio::io::AsyncReadExt;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use super::blob::blob_storage_path;
+use super::file::File;
+use super::StorageStrategy;
+use crate::config::FileStorageConfig;
+use crate::error::AppError;
+use crate::server::AppState;
+
+/// Name of the background scan, for triggering it from the admin API
+pub const INTEGRITY_SCAN_TASK: &str = "integrity_scan";
+
+/// How often the scan runs on its own
+const INTEGRITY_SCAN_INTERVAL: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// Blobs or files checked per batch of the scan
+const SCAN_BATCH_SIZE: i64 = 100;
+
+/// Incidents listed in the summary
+const RECENT_INCIDENTS: i64 = 50;
+
+/// Where a corruption was detected
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReadPath {
+    Download,
+    Export,
+    /// Reading the files a compilation job is given
+    Compile,
+    Scan,
+    /// Reading a file to recompute what is derived from its content
+    Reindex,
+    /// Reconstructing a version from the file's history
+    History,
+}
+
+impl ReadPath {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Download => "download",
+            Self::Export => "export",
+            Self::Compile => "compile",
+            Self::Scan => "scan",
+            Self::Reindex => "reindex",
+            Self::History => "history",
+        }
+    }
+
+    /// Whether reading a file of this size checks its hash
+    fn verifies(self, storage: &FileStorageConfig, size: i64) -> bool {
+        match self {
+            Self::Download | Self::Export => {
+                storage.verify_max_size > 0 && size.max(0) as u64 <= storage.verify_max_size
+            }
+            Self::Compile | Self::Scan | Self::Reindex | Self::History => true,
+        }
+    }
+}
+
+/// Content found not to match its hash, counted once per distinct mismatch
+#[derive(Debug, Clone, Serialize, FromRow, ToSchema)]
+pub struct IntegrityIncident {
+    pub id: Uuid,
+    /// A file with this content, unset when none is left
+    pub file_id: Option<Uuid>,
+    pub project_id: Option<Uuid>,
+    pub expected_hash: String,
+    pub actual_hash: String,
+    /// `download`, `export`, `compile` or `scan`
+    pub detected_by: String,
+    pub occurrences: i32,
+    pub first_detected_at: DateTime<Utc>,
+    pub last_detected_at: DateTime<Utc>,
+}
+
+/// Incidents detected on one read path
+#[derive(Debug, Clone, Serialize, FromRow, ToSchema)]
+pub struct IncidentCount {
+    pub detected_by: String,
+    pub incidents: i64,
+    pub occurrences: i64,
+}
+
+/// Summary of the recorded incidents
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct IntegritySummary {
+    pub incidents: i64,
+    /// Distinct contents found corrupted
+    pub corrupted_contents: i64,
+    pub by_read_path: Vec<IncidentCount>,
+    /// Most recently detected first
+    pub recent: Vec<IntegrityIncident>,
+}
+
+/// Outcome of a scan
+#[derive(Debug, Clone, Default, Serialize, ToSchema)]
+pub struct IntegrityScanReport {
+    pub blobs_checked: u64,
+    pub files_checked: u64,
+    pub corrupted: u64,
+    /// Blobs whose content could not be read, e.g. because it is missing
+    pub unreadable: u64,
+}
+
+impl IntegrityIncident {
+    /// Record a mismatch, or count another occurrence of a known one
+    pub async fn record(
+        db: &sqlx::PgPool,
+        file_id: Option<Uuid>,
+        project_id: Option<Uuid>,
+        expected_hash: &str,
+        actual_hash: &str,
+        detected_by: ReadPath,
+    ) -> Result<Self, AppError> {
+        sqlx::query_as::<_, IntegrityIncident>(
+            r#"
+            INSERT INTO data_integrity_incidents (file_id, project_id, expected_hash, actual_hash, detected_by)
+            VALUES ($1, $2, $3, $4, $5)
+            ON CONFLICT (expected_hash, actual_hash, detected_by) DO UPDATE SET
+                occurrences = data_integrity_incidents.occurrences + 1,
+                file_id = COALESCE(EXCLUDED.file_id, data_integrity_incidents.file_id),
+                project_id = COALESCE(EXCLUDED.project_id, data_integrity_incidents.project_id),
+                last_detected_at = NOW()
+            RETURNING *
+            "#
+        )
+        .bind(file_id)
+        .bind(project_id)
+        .bind(expected_hash)
+        .bind(actual_hash)
+        .bind(detected_by.as_str())
+        .fetch_one(db)
+        .await
+        .map_err(AppError::Database)
+    }
+
+    pub async fn summary(db: &sqlx::PgPool) -> Result<IntegritySummary, AppError> {
+        let (incidents, corrupted_contents) = sqlx::query_as::<_, (i64, i64)>(
+            "SELECT COUNT(*), COUNT(DISTINCT expected_hash) FROM data_integrity_incidents"
+        )
+        .fetch_one(db)
+        .await
+        .map_err(AppError::Database)?;
+
+        let by_read_path = sqlx::query_as::<_, IncidentCount>(
+            r#"
+            SELECT detected_by, COUNT(*) AS incidents, COALESCE(SUM(occurrences), 0)::BIGINT AS occurrences
+            FROM data_integrity_incidents
+            GROUP BY detected_by
+            ORDER BY detected_by
+            "#
+        )
+        .fetch_all(db)
+        .await
+        .map_err(AppError::Database)?;
+
+        let recent = sqlx::query_as::<_, IntegrityIncident>(
+            "SELECT * FROM data_integrity_incidents ORDER BY last_detected_at DESC LIMIT $1"
+        )
+        .bind(RECENT_INCIDENTS)
+        .fetch_all(db)
+        .await
+        .map_err(AppError::Database)?;
+
+        Ok(IntegritySummary {
+            incidents,
+            corrupted_contents,
+            by_read_path,
+            recent,
+        })
+    }
+}
+
+impl File {
+    /// Read the content, checking it against the recorded hash when the read path calls for it
+    pub async fn read_verified(
+        &self,
+        db: &sqlx::PgPool,
+        storage: &FileStorageConfig,
+        read_path: ReadPath,
+    ) -> Result<Vec<u8>, AppError> {
+        let bytes = self.read_bytes(&storage.local_path).await?;
+        if read_path.verifies(storage, self.size) {
+            verify_content(db, self, &bytes, read_path).await?;
+        }
+        Ok(bytes)
+    }
+
+    /// SHA-256 of the content, checked against the recorded hash like
+    /// `read_verified` would
+    ///
+    /// When the read path does not check files of this size the recorded hash
+    /// is returned as is. Content kept in the blob store is hashed in chunks.
+    pub async fn verified_hash(
+        &self,
+        db: &sqlx::PgPool,
+        storage: &FileStorageConfig,
+        read_path: ReadPath,
+    ) -> Result<String, AppError> {
+        if let Some(recorded) = self.content_hash.as_deref().filter(|_| !read_path.verifies(storage, self.size)) {
+            return Ok(recorded.to_ascii_lowercase());
+        }
+
+        let actual = if self.storage_strategy == StorageStrategy::External {
+            let recorded = self.content_hash.as_deref().ok_or_else(|| {
+                AppError::Storage(format!("File {} has no content hash", self.id))
+            })?;
+            hash_stored(&Path::new(&storage.local_path).join(blob_storage_path(recorded)))
+                .await
+                .map_err(|e| AppError::Storage(format!("Failed to read file {}: {}", self.path, e)))?
+        } else {
+            hex::encode(Sha256::digest(self.content.as_bytes()))
+        };
+        check_hash(db, self, &actual, read_path).await?;
+        Ok(actual)
+    }
+}
+
+/// Fail with `ContentCorrupted`, and record the incident, when the bytes do not match the file's hash
+pub async fn verify_content(db: &sqlx::PgPool, file: &File, bytes: &[u8], read_path: ReadPath) -> Result<(), AppError> {
+    check_hash(db, file, &hex::encode(Sha256::digest(bytes)), read_path).await
+}
+
+async fn check_hash(db: &sqlx::PgPool, file: &File, actual: &str, read_path: ReadPath) -> Result<(), AppError> {
+    let Some(expected) = file.content_hash.as_deref() else {
+        return Ok(());
+    };
+
+    if actual.eq_ignore_ascii_case(expected) {
+        return Ok(());
+    }
+
+    tracing::error!(
+        "Content of file {} does not match its hash on {}: expected {}, found {}",
+        file.id,
+        read_path.as_str(),
+        expected,
+        actual
+    );
+    if let Err(e) = IntegrityIncident::record(db, Some(file.id), Some(file.project_id), expected, actual, read_path).await {
+        tracing::error!("Failed to record integrity incident of file {}: {}", file.id, e);
+    }
+
+    Err(AppError::ContentCorrupted(format!(
+        "{} does not match its recorded hash",
+        file.path
+    )))
+}
+
+/// Check every stored blob and every file kept inline against its hash
+pub async fn scan(db: &sqlx::PgPool, storage_root: &str) -> Result<IntegrityScanReport, AppError> {
+    let mut report = IntegrityScanReport::default();
+    let storage_root = Path::new(storage_root);
+
+    let mut after = String::new();
+    loop {
+        let batch = sqlx::query_as::<_, (String, String)>(
+            r#"
+            SELECT hash, storage_path FROM blobs
+            WHERE storage_path IS NOT NULL AND hash > $2
+            ORDER BY hash
+            LIMIT $1
+            "#
+        )
+        .bind(SCAN_BATCH_SIZE)
+        .bind(&after)
+        .fetch_all(db)
+        .await
+        .map_err(AppError::Database)?;
+
+        let Some((last, _)) = batch.last() else {
+            break;
+        };
+        after = last.clone();
+
+        for (hash, storage_path) in batch {
+            report.blobs_checked += 1;
+            let actual = match hash_stored(&storage_root.join(&storage_path)).await {
+                Ok(actual) => actual,
+                Err(e) => {
+                    tracing::warn!("Could not read blob {} for the integrity scan: {}", hash, e);
+                    report.unreadable += 1;
+                    continue;
+                }
+            };
+            if actual.eq_ignore_ascii_case(&hash) {
+                continue;
+            }
+
+            tracing::error!("Stored blob {} does not match its hash: found {}", hash, actual);
+            report.corrupted += 1;
+            let owner = sqlx::query_as::<_, (Uuid, Uuid)>(
+                "SELECT id, project_id FROM files WHERE content_hash = $1 ORDER BY created_at LIMIT 1"
+            )
+            .bind(&hash)
+            .fetch_optional(db)
+            .await
+            .map_err(AppError::Database)?;
+            IntegrityIncident::record(
+                db,
+                owner.map(|(file_id, _)| file_id),
+                owner.map(|(_, project_id)| project_id),
+                &hash,
+                &actual,
+                ReadPath::Scan,
+            )
+            .await?;
+        }
+    }
+
+    let mut after: Option<Uuid> = None;
+    loop {
+        let batch = sqlx::query_as::<_, (Uuid, Uuid, String, String)>(
+            r#"
+            SELECT id, project_id, content_hash, content FROM files
+            WHERE storage_strategy <> 'external' AND content_hash IS NOT NULL
+              AND ($2::uuid IS NULL OR id > $2)
+            ORDER BY id
+            LIMIT $1
+            "#
+        )
+        .bind(SCAN_BATCH_SIZE)
+        .bind(after)
+        .fetch_all(db)
+        .await
+        .map_err(AppError::Database)?;
+
+        let Some((last, ..)) = batch.last() else {
+            break;
+        };
+        after = Some(*last);
+
+        for (file_id, project_id, expected, content) in batch {
+            report.files_checked += 1;
+            let actual = hex::encode(Sha256::digest(content.as_bytes()));
+            if actual.eq_ignore_ascii_case(&expected) {
+                continue;
+            }
+
+            tracing::error!("Content of file {} does not match its hash: expected {}, found {}", file_id, expected, actual);
+            report.corrupted += 1;
+            IntegrityIncident::record(db, Some(file_id), Some(project_id), &expected, &actual, ReadPath::Scan).await?;
+        }
+    }
+
+    Ok(report)
+}
+
+/// SHA-256 of a stored file, read in chunks so large blobs are not held in memory
+async fn hash_stored(path: &Path) -> std::io::Result<String> {
+    let mut file = tokio::fs::File::open(path).await?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0u8; 64 * 1024];
+    loop {
+        let read = file.read(&mut buffer).await?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+    Ok(hex::encode(hasher.finalize()))
+}
+
+/// Checks all stored content against its hash
+pub struct IntegrityScanTask;
+
+impl crate::tasks::PeriodicTask for IntegrityScanTask {
+    fn name(&self) -> &'static str {
+        INTEGRITY_SCAN_TASK
+    }
+
+    fn interval(&self) -> Duration {
+        INTEGRITY_SCAN_INTERVAL
+    }
+
+    fn run<'a>(&'a self, state: &'a AppState) -> futures::future::BoxFuture<'a, Result<(), AppError>> {
+        Box::pin(async move {
+            let report = scan(&state.db_pool, &state.config.features.file_storage.local_path).await?;
+            tracing::info!(
+                "Integrity scan checked {} blobs and {} inline files: {} corrupted, {} unreadable",
+                report.blobs_checked,
+                report.files_checked,
+                report.corrupted,
+                report.unreadable
+            );
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::blob::blob_storage_path;
+    use crate::models::project_limits::ProjectLimits;
+    use crate::models::ContentType;
+    use crate::testing::{create_test_project, create_test_user, test_config, TestDb};
+
+    #[tokio::test]
+    async fn test_corrupted_blob_is_refused_and_recorded() {
+        let Some(db) = TestDb::start().await else { return };
+        let storage_dir = tempfile::tempdir().unwrap();
+        let mut storage = test_config().features.file_storage;
+        storage.local_path = storage_dir.path().to_string_lossy().into_owned();
+
+        let owner = create_test_user(&db.pool).await;
+        let project = create_test_project(&db.pool, &owner, false).await;
+        let bytes = format!("figure {}", Uuid::new_v4()).into_bytes();
+        let file = File::create_from_bytes(
+            &db.pool,
+            &storage.local_path,
+            &ProjectLimits::default(),
+            project.id,
+            "figure.png",
+            "/figure.png",
+            ContentType::Image,
+            &bytes,
+            owner.id,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(file.read_verified(&db.pool, &storage, ReadPath::Download).await.unwrap(), bytes);
+
+        // Flip a byte on disk behind the store's back
+        let blob_path = storage_dir.path().join(blob_storage_path(file.content_hash.as_deref().unwrap()));
+        let mut damaged = bytes.clone();
+        damaged[0] ^= 0xff;
+        tokio::fs::write(&blob_path, &damaged).await.unwrap();
+
+        for _ in 0..2 {
+            let read = file.read_verified(&db.pool, &storage, ReadPath::Download).await;
+            assert!(matches!(read, Err(AppError::ContentCorrupted(_))));
+        }
+
+        // Above the size limit downloads skip the check; compiles never do
+        storage.verify_max_size = 1;
+        assert_eq!(file.read_verified(&db.pool, &storage, ReadPath::Download).await.unwrap(), damaged);
+        assert!(file.read_verified(&db.pool, &storage, ReadPath::Compile).await.is_err());
+
+        let report = scan(&db.pool, &storage.local_path).await.unwrap();
+        assert!(report.corrupted >= 1);
+
+        let incidents = sqlx::query_as::<_, IntegrityIncident>(
+            "SELECT * FROM data_integrity_incidents WHERE file_id = $1 ORDER BY detected_by"
+        )
+        .bind(file.id)
+        .fetch_all(&db.pool)
+        .await
+        .unwrap();
+        let by: Vec<(&str, i32)> = incidents.iter().map(|i| (i.detected_by.as_str(), i.occurrences)).collect();
+        assert_eq!(by, vec![("compile", 1), ("download", 2), ("scan", 1)]);
+        assert!(incidents.iter().all(|i| i.expected_hash == file.content_hash.clone().unwrap()));
+
+        let summary = IntegrityIncident::summary(&db.pool).await.unwrap();
+        assert!(summary.incidents >= 3);
+        assert!(summary.recent.iter().any(|i| i.file_id == Some(file.id)));
+    }
+}
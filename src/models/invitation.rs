@@ -0,0 +1,260 @@
+//! Project invitation models
+//!
+//! Inviting an email that has no account yet leaves a pending invitation
+//! behind; it is turned into a collaborator row once someone registers and
+//! verifies that email address.
+
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use super::{Entity, UserRole};
+use super::settings_history::ProjectSettingsChange;
+
+/// How long an invitation stays valid after it was (re)sent
+pub const INVITATION_EXPIRATION_DAYS: i64 = 14;
+
+/// Pending project invitation
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, ToSchema)]
+pub struct ProjectInvitation {
+    pub id: Uuid,
+    pub project_id: Uuid,
+    pub email: String,
+    pub role: UserRole,
+    #[serde(skip_serializing)]
+    pub token: String,
+    pub invited_by: Uuid,
+    pub expires_at: DateTime<Utc>,
+    pub accepted_by: Option<Uuid>,
+    pub accepted_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl Entity for ProjectInvitation {
+    fn id(&self) -> Uuid {
+        self.id
+    }
+
+    fn created_at(&self) -> DateTime<Utc> {
+        self.created_at
+    }
+
+    fn updated_at(&self) -> DateTime<Utc> {
+        self.updated_at
+    }
+}
+
+impl ProjectInvitation {
+    /// Create an invitation, or refresh the token, role and expiry of the
+    /// open invitation for the same project and email
+    pub async fn upsert(
+        db: &sqlx::PgPool,
+        project_id: Uuid,
+        email: &str,
+        role: UserRole,
+        invited_by: Uuid,
+    ) -> Result<Self, crate::error::AppError> {
+        use crate::models::auth::PasswordUtils;
+
+        let invitation = sqlx::query_as::<_, ProjectInvitation>(
+            r#"
+            INSERT INTO project_invitations (project_id, email, role, token, invited_by, expires_at)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            ON CONFLICT (project_id, email) WHERE accepted_at IS NULL
+            DO UPDATE SET
+                role = EXCLUDED.role,
+                token = EXCLUDED.token,
+                invited_by = EXCLUDED.invited_by,
+                expires_at = EXCLUDED.expires_at,
+                updated_at = NOW()
+            RETURNING *
+            "#
+        )
+        .bind(project_id)
+        .bind(email)
+        .bind(role as UserRole)
+        .bind(PasswordUtils::generate_invitation_token())
+        .bind(invited_by)
+        .bind(Utc::now() + Duration::days(INVITATION_EXPIRATION_DAYS))
+        .fetch_one(db)
+        .await
+        .map_err(crate::error::AppError::Database)?;
+
+        Ok(invitation)
+    }
+
+    /// Open invitations for a project, including expired ones so they can be resent
+    pub async fn list_pending(
+        db: &sqlx::PgPool,
+        project_id: Uuid,
+    ) -> Result<Vec<Self>, crate::error::AppError> {
+        let invitations = sqlx::query_as::<_, ProjectInvitation>(
+            r#"
+            SELECT * FROM project_invitations
+            WHERE project_id = $1 AND accepted_at IS NULL
+            ORDER BY created_at
+            "#
+        )
+        .bind(project_id)
+        .fetch_all(db)
+        .await
+        .map_err(crate::error::AppError::Database)?;
+
+        Ok(invitations)
+    }
+
+    /// Valid invitations waiting for the given email
+    pub async fn pending_for_email(
+        db: &sqlx::PgPool,
+        email: &str,
+    ) -> Result<Vec<Self>, crate::error::AppError> {
+        let invitations = sqlx::query_as::<_, ProjectInvitation>(
+            r#"
+            SELECT * FROM project_invitations
+            WHERE email = $1 AND accepted_at IS NULL AND expires_at > NOW()
+            ORDER BY created_at
+            "#
+        )
+        .bind(email.trim().to_lowercase())
+        .fetch_all(db)
+        .await
+        .map_err(crate::error::AppError::Database)?;
+
+        Ok(invitations)
+    }
+
+    /// Revoke an open invitation; returns false when there was none
+    pub async fn revoke(
+        db: &sqlx::PgPool,
+        project_id: Uuid,
+        invitation_id: Uuid,
+    ) -> Result<bool, crate::error::AppError> {
+        let result = sqlx::query(
+            r#"
+            DELETE FROM project_invitations
+            WHERE id = $1 AND project_id = $2 AND accepted_at IS NULL
+            "#
+        )
+        .bind(invitation_id)
+        .bind(project_id)
+        .execute(db)
+        .await
+        .map_err(crate::error::AppError::Database)?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Turn every valid invitation for a verified email into collaborator rows.
+    ///
+    /// Must only be called once the user has proven they own `email`.
+    pub async fn accept_for_user(
+        db: &sqlx::PgPool,
+        user_id: Uuid,
+        email: &str,
+    ) -> Result<Vec<Self>, crate::error::AppError> {
+        let mut tx = db.begin().await.map_err(crate::error::AppError::Database)?;
+
+        let invitations = sqlx::query_as::<_, ProjectInvitation>(
+            r#"
+            SELECT * FROM project_invitations
+            WHERE email = $1 AND accepted_at IS NULL AND expires_at > NOW()
+            FOR UPDATE
+            "#
+        )
+        .bind(email.trim().to_lowercase())
+        .fetch_all(&mut *tx)
+        .await
+        .map_err(crate::error::AppError::Database)?;
+
+        let mut accepted = Vec::with_capacity(invitations.len());
+        for invitation in invitations {
+            let added = sqlx::query(
+                r#"
+                INSERT INTO project_collaborators (project_id, user_id, role, invited_by, invited_at)
+                VALUES ($1, $2, $3, $4, $5)
+                ON CONFLICT (project_id, user_id) DO NOTHING
+                "#
+            )
+            .bind(invitation.project_id)
+            .bind(user_id)
+            .bind(invitation.role as UserRole)
+            .bind(invitation.invited_by)
+            .bind(invitation.created_at)
+            .execute(&mut *tx)
+            .await
+            .map_err(crate::error::AppError::Database)?;
+
+            if added.rows_affected() > 0 {
+                ProjectSettingsChange::record_role_change(
+                    &mut tx,
+                    invitation.project_id,
+                    user_id,
+                    None,
+                    Some(invitation.role),
+                    invitation.invited_by,
+                )
+                .await?;
+            }
+
+            let invitation = sqlx::query_as::<_, ProjectInvitation>(
+                r#"
+                UPDATE project_invitations
+                SET accepted_by = $2, accepted_at = NOW()
+                WHERE id = $1
+                RETURNING *
+                "#
+            )
+            .bind(invitation.id)
+            .bind(user_id)
+            .fetch_one(&mut *tx)
+            .await
+            .map_err(crate::error::AppError::Database)?;
+
+            accepted.push(invitation);
+        }
+
+        tx.commit().await.map_err(crate::error::AppError::Database)?;
+
+        for invitation in &accepted {
+            super::onboarding::record(db, invitation.invited_by, super::onboarding::Milestone::CollaboratorAdded);
+        }
+
+        Ok(accepted)
+    }
+
+    /// Check if the invitation can still be accepted
+    pub fn is_valid(&self) -> bool {
+        self.accepted_at.is_none() && Utc::now() < self.expires_at
+    }
+}
+
+/// Normalize an invited email address, or `None` when it is not plausible
+pub fn normalize_email(email: &str) -> Option<String> {
+    let email = email.trim().to_lowercase();
+    let (local, domain) = email.split_once('@')?;
+
+    let valid = !local.is_empty()
+        && domain.contains('.')
+        && !domain.starts_with('.')
+        && !domain.ends_with('.')
+        && !email.chars().any(char::is_whitespace);
+
+    valid.then_some(email)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_email() {
+        assert_eq!(normalize_email("  Ada@Example.ORG "), Some("ada@example.org".to_string()));
+        assert_eq!(normalize_email("no-at-sign"), None);
+        assert_eq!(normalize_email("@example.org"), None);
+        assert_eq!(normalize_email("ada@localhost"), None);
+        assert_eq!(normalize_email("ada lovelace@example.org"), None);
+    }
+}
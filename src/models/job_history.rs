@@ -0,0 +1,255 @@
+//! Compilation history of a project
+//!
+//! The project page lists its own jobs, filtered by status, engine and
+//! creation time, to anyone with access to the project; unlike the per-user
+//! job list it does not matter who started a job. Listing a page is one
+//! query on the `(project_id, created_at)` index, which also yields the
+//! number of matching jobs.
+//!
+//! Owners can prune failed jobs older than a cut-off. Jobs that something
+//! still refers to are kept: the project's newest job, which its compilation
+//! status describes, jobs whose outcome waits for a digest email, and jobs
+//! compared in a cached compile diff. Frozen projects keep their history.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use utoipa::{IntoParams, ToSchema};
+use uuid::Uuid;
+
+use super::compilation::CompilationJob;
+use super::{CompilationStatus, LatexEngine, PaginationParams};
+use crate::error::AppError;
+
+/// Finished jobs the failure rate is taken over
+pub const FAILURE_RATE_WINDOW: i64 = 20;
+
+/// Filters of a project's job list
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct JobHistoryParams {
+    pub status: Option<CompilationStatus>,
+    pub engine: Option<LatexEngine>,
+    /// Jobs created at or after this time
+    pub from: Option<DateTime<Utc>>,
+    /// Jobs created before this time
+    pub to: Option<DateTime<Utc>>,
+}
+
+impl JobHistoryParams {
+    pub fn validate(&self) -> Result<(), AppError> {
+        if let (Some(from), Some(to)) = (self.from, self.to) {
+            if from >= to {
+                return Err(AppError::Validation("from must be before to".to_string()));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Cut-off of a history prune
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct PruneJobsParams {
+    /// Failed jobs created before this time are deleted
+    pub before: DateTime<Utc>,
+}
+
+/// Recent compilation outcomes of a project
+#[derive(Debug, Clone, Default, Serialize, FromRow, ToSchema)]
+pub struct CompileSummary {
+    /// Status of the newest job
+    pub last_job_status: Option<CompilationStatus>,
+    pub last_success_at: Option<DateTime<Utc>>,
+    /// Share of failures among the last `FAILURE_RATE_WINDOW` finished jobs, `null` without any
+    pub recent_failure_rate: Option<f64>,
+}
+
+#[derive(FromRow)]
+struct CountedJob {
+    #[sqlx(flatten)]
+    job: CompilationJob,
+    total_count: i64,
+}
+
+/// A page of a project's jobs, newest first, and the number of jobs matching the filters
+pub async fn list(
+    db: &sqlx::PgPool,
+    project_id: Uuid,
+    filters: &JobHistoryParams,
+    params: &PaginationParams,
+) -> Result<(Vec<CompilationJob>, i64), AppError> {
+    let rows = sqlx::query_as::<_, CountedJob>(
+        r#"
+        SELECT cj.*, COUNT(*) OVER () AS total_count
+        FROM compilation_jobs cj
+        WHERE cj.project_id = $1
+          AND ($2::compilationstatus IS NULL OR cj.status = $2)
+          AND ($3::latexengine IS NULL OR cj.engine = $3)
+          AND ($4::timestamptz IS NULL OR cj.created_at >= $4)
+          AND ($5::timestamptz IS NULL OR cj.created_at < $5)
+        ORDER BY cj.created_at DESC
+        LIMIT $6 OFFSET $7
+        "#
+    )
+    .bind(project_id)
+    .bind(filters.status)
+    .bind(filters.engine)
+    .bind(filters.from)
+    .bind(filters.to)
+    .bind(params.limit() as i64)
+    .bind(params.offset() as i64)
+    .fetch_all(db)
+    .await
+    .map_err(AppError::Database)?;
+
+    let total = match rows.first() {
+        Some(row) => row.total_count,
+        // Past the last page the window count is gone with the rows
+        None if params.offset() > 0 => count(db, project_id, filters).await?,
+        None => 0,
+    };
+
+    Ok((rows.into_iter().map(|row| row.job).collect(), total))
+}
+
+async fn count(db: &sqlx::PgPool, project_id: Uuid, filters: &JobHistoryParams) -> Result<i64, AppError> {
+    sqlx::query_scalar::<_, i64>(
+        r#"
+        SELECT COUNT(*) FROM compilation_jobs cj
+        WHERE cj.project_id = $1
+          AND ($2::compilationstatus IS NULL OR cj.status = $2)
+          AND ($3::latexengine IS NULL OR cj.engine = $3)
+          AND ($4::timestamptz IS NULL OR cj.created_at >= $4)
+          AND ($5::timestamptz IS NULL OR cj.created_at < $5)
+        "#
+    )
+    .bind(project_id)
+    .bind(filters.status)
+    .bind(filters.engine)
+    .bind(filters.from)
+    .bind(filters.to)
+    .fetch_one(db)
+    .await
+    .map_err(AppError::Database)
+}
+
+/// Delete the failed jobs of a project created before `before`, keeping the
+/// ones still referred to; returns how many were deleted
+pub async fn prune_failed(db: &sqlx::PgPool, project_id: Uuid, before: DateTime<Utc>) -> Result<u64, AppError> {
+    let result = sqlx::query(
+        r#"
+        DELETE FROM compilation_jobs cj
+        WHERE cj.project_id = $1 AND cj.status = 'error' AND cj.created_at < $2
+          AND cj.id <> (
+            SELECT id FROM compilation_jobs
+            WHERE project_id = $1
+            ORDER BY created_at DESC
+            LIMIT 1
+          )
+          AND NOT EXISTS (
+            SELECT 1 FROM compile_notification_emails e
+            WHERE e.job_id = cj.id AND e.sent_at IS NULL
+          )
+          AND NOT EXISTS (
+            SELECT 1 FROM compile_diffs d
+            WHERE d.status = 'ready' AND (d.from_job_id = cj.id OR d.to_job_id = cj.id)
+          )
+        "#
+    )
+    .bind(project_id)
+    .bind(before)
+    .execute(db)
+    .await
+    .map_err(AppError::Database)?;
+
+    Ok(result.rows_affected())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{create_test_job, create_test_project, create_test_user, TestDb};
+    use chrono::Duration;
+
+    async fn set_job(db: &sqlx::PgPool, job: &CompilationJob, status: CompilationStatus, age: Duration) {
+        sqlx::query("UPDATE compilation_jobs SET status = $2, created_at = $3 WHERE id = $1")
+            .bind(job.id)
+            .bind(status)
+            .bind(Utc::now() - age)
+            .execute(db)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_list_filters_and_prune_keeps_referenced_jobs() {
+        let Some(db) = TestDb::start().await else { return };
+        let owner = create_test_user(&db.pool).await;
+        let project = create_test_project(&db.pool, &owner, false).await;
+        let other = create_test_project(&db.pool, &owner, false).await;
+
+        let old_failed = create_test_job(&db.pool, &project, &owner).await;
+        set_job(&db.pool, &old_failed, CompilationStatus::Error, Duration::days(40)).await;
+        let old_success = create_test_job(&db.pool, &project, &owner).await;
+        set_job(&db.pool, &old_success, CompilationStatus::Success, Duration::days(35)).await;
+        let digest_pending = create_test_job(&db.pool, &project, &owner).await;
+        set_job(&db.pool, &digest_pending, CompilationStatus::Error, Duration::days(32)).await;
+        sqlx::query(
+            r#"
+            INSERT INTO compile_notification_emails (user_id, job_id, project_name, succeeded, summary)
+            VALUES ($1, $2, 'Thesis', false, 'failed')
+            "#
+        )
+        .bind(owner.id)
+        .bind(digest_pending.id)
+        .execute(&db.pool)
+        .await
+        .unwrap();
+        let recent_failed = create_test_job(&db.pool, &project, &owner).await;
+        set_job(&db.pool, &recent_failed, CompilationStatus::Error, Duration::days(1)).await;
+        let elsewhere = create_test_job(&db.pool, &other, &owner).await;
+        set_job(&db.pool, &elsewhere, CompilationStatus::Error, Duration::days(40)).await;
+
+        let all = PaginationParams::default();
+        let (jobs, total) = list(&db.pool, project.id, &JobHistoryParams::default(), &all).await.unwrap();
+        assert_eq!(total, 4);
+        assert_eq!(
+            jobs.iter().map(|job| job.id).collect::<Vec<_>>(),
+            vec![recent_failed.id, digest_pending.id, old_success.id, old_failed.id]
+        );
+
+        let failed_last_month = JobHistoryParams {
+            status: Some(CompilationStatus::Error),
+            from: Some(Utc::now() - Duration::days(38)),
+            ..Default::default()
+        };
+        let (jobs, total) = list(&db.pool, project.id, &failed_last_month, &all).await.unwrap();
+        assert_eq!(total, 2);
+        assert_eq!(jobs.iter().map(|job| job.id).collect::<Vec<_>>(), vec![recent_failed.id, digest_pending.id]);
+
+        let second_page = PaginationParams { page: Some(2), limit: Some(3), ..Default::default() };
+        let (jobs, total) = list(&db.pool, project.id, &JobHistoryParams::default(), &second_page).await.unwrap();
+        assert_eq!((jobs.len(), total), (1, 4));
+        let past_end = PaginationParams { page: Some(9), limit: Some(3), ..Default::default() };
+        let (jobs, total) = list(&db.pool, project.id, &JobHistoryParams::default(), &past_end).await.unwrap();
+        assert_eq!((jobs.len(), total), (0, 4));
+
+        // Only the old failure nothing refers to goes; other projects are untouched
+        assert_eq!(prune_failed(&db.pool, project.id, Utc::now() - Duration::days(30)).await.unwrap(), 1);
+        let (jobs, _) = list(&db.pool, project.id, &JobHistoryParams::default(), &all).await.unwrap();
+        assert!(!jobs.iter().any(|job| job.id == old_failed.id));
+        assert_eq!(jobs.len(), 3);
+        let (jobs, _) = list(&db.pool, other.id, &JobHistoryParams::default(), &all).await.unwrap();
+        assert_eq!(jobs.len(), 1);
+
+        // The newest job stays even when it is an old failure
+        assert_eq!(prune_failed(&db.pool, other.id, Utc::now()).await.unwrap(), 0);
+
+        assert!(matches!(
+            JobHistoryParams { from: Some(Utc::now()), to: Some(Utc::now() - Duration::days(1)), ..Default::default() }
+                .validate(),
+            Err(AppError::Validation(_))
+        ));
+    }
+}
@@ -0,0 +1,280 @@
+//! Per-account login protection
+//!
+//! IP-based rate limiting does not stop credential stuffing from many
+//! addresses, so failed password logins are also counted per account. After
+//! a few failures every further attempt has to wait exponentially longer, and
+//! at the lockout threshold the account is locked until the password is reset
+//! or an administrator unlocks it. Only password logins are counted; OIDC
+//! failures never reach this module.
+
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::FromRow;
+use std::net::IpAddr;
+use std::sync::OnceLock;
+use uuid::Uuid;
+
+use crate::config::LoginProtectionConfig;
+
+/// Failed login counter for an account
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct LoginFailure {
+    pub user_id: Uuid,
+    pub failed_count: i32,
+    pub last_failed_at: DateTime<Utc>,
+    pub locked_at: Option<DateTime<Utc>>,
+}
+
+/// A device a user has signed in from before
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct KnownLogin {
+    pub user_id: Uuid,
+    pub fingerprint: String,
+    pub ip_address: String,
+    pub user_agent: Option<String>,
+    pub first_seen_at: DateTime<Utc>,
+    pub last_seen_at: DateTime<Utc>,
+}
+
+impl LoginFailure {
+    /// Find the failure counter for an account
+    pub async fn find(db: &sqlx::PgPool, user_id: Uuid) -> Result<Option<Self>, crate::error::AppError> {
+        let failure = sqlx::query_as::<_, LoginFailure>(
+            "SELECT * FROM login_failures WHERE user_id = $1"
+        )
+        .bind(user_id)
+        .fetch_optional(db)
+        .await
+        .map_err(crate::error::AppError::Database)?;
+
+        Ok(failure)
+    }
+
+    /// Count a failed login, locking the account once `lockout_threshold` is reached
+    pub async fn record(
+        db: &sqlx::PgPool,
+        user_id: Uuid,
+        lockout_threshold: u32,
+    ) -> Result<Self, crate::error::AppError> {
+        let failure = sqlx::query_as::<_, LoginFailure>(
+            r#"
+            INSERT INTO login_failures (user_id, failed_count, last_failed_at, locked_at)
+            VALUES ($1, 1, NOW(), CASE WHEN 1 >= $2 THEN NOW() END)
+            ON CONFLICT (user_id) DO UPDATE SET
+                failed_count = login_failures.failed_count + 1,
+                last_failed_at = NOW(),
+                locked_at = CASE
+                    WHEN login_failures.locked_at IS NULL AND login_failures.failed_count + 1 >= $2 THEN NOW()
+                    ELSE login_failures.locked_at
+                END
+            RETURNING *
+            "#
+        )
+        .bind(user_id)
+        .bind(lockout_threshold as i32)
+        .fetch_one(db)
+        .await
+        .map_err(crate::error::AppError::Database)?;
+
+        Ok(failure)
+    }
+
+    /// Reset the counter and lift any lock; returns false when there was nothing to clear
+    pub async fn clear(db: &sqlx::PgPool, user_id: Uuid) -> Result<bool, crate::error::AppError> {
+        let result = sqlx::query("DELETE FROM login_failures WHERE user_id = $1")
+            .bind(user_id)
+            .execute(db)
+            .await
+            .map_err(crate::error::AppError::Database)?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Lock the account until the password is reset, without counting a failure
+    pub async fn lock(db: impl sqlx::PgExecutor<'_>, user_id: Uuid) -> Result<(), crate::error::AppError> {
+        sqlx::query(
+            r#"
+            INSERT INTO login_failures (user_id, failed_count, last_failed_at, locked_at)
+            VALUES ($1, 0, NOW(), NOW())
+            ON CONFLICT (user_id) DO UPDATE SET
+                locked_at = COALESCE(login_failures.locked_at, NOW())
+            "#
+        )
+        .bind(user_id)
+        .execute(db)
+        .await
+        .map_err(crate::error::AppError::Database)?;
+
+        Ok(())
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.locked_at.is_some()
+    }
+
+    /// Time the next attempt has to wait, if any
+    pub fn retry_after(&self, config: &LoginProtectionConfig, now: DateTime<Utc>) -> Option<Duration> {
+        let delay = retry_delay(self.failed_count.max(0) as u32, config)?;
+        let ready_at = self.last_failed_at + delay;
+        (ready_at > now).then(|| ready_at - now)
+    }
+}
+
+impl KnownLogin {
+    /// Record a successful login, returning true when the device was not seen before
+    pub async fn record(
+        db: &sqlx::PgPool,
+        user_id: Uuid,
+        fingerprint: &str,
+        ip_address: &str,
+        user_agent: Option<&str>,
+    ) -> Result<bool, crate::error::AppError> {
+        super::fail_point::check("known_login_record")?;
+        let inserted = sqlx::query_scalar::<_, bool>(
+            r#"
+            INSERT INTO known_logins (user_id, fingerprint, ip_address, user_agent)
+            VALUES ($1, $2, $3, $4)
+            ON CONFLICT (user_id, fingerprint) DO UPDATE SET
+                ip_address = EXCLUDED.ip_address,
+                user_agent = EXCLUDED.user_agent,
+                last_seen_at = NOW()
+            RETURNING (xmax = 0)
+            "#
+        )
+        .bind(user_id)
+        .bind(fingerprint)
+        .bind(ip_address)
+        .bind(user_agent)
+        .fetch_one(db)
+        .await
+        .map_err(crate::error::AppError::Database)?;
+
+        Ok(inserted)
+    }
+
+    /// Whether the user has signed in successfully before
+    pub async fn has_any(db: &sqlx::PgPool, user_id: Uuid) -> Result<bool, crate::error::AppError> {
+        let count = sqlx::query_scalar::<_, i64>(
+            "SELECT COUNT(*) FROM known_logins WHERE user_id = $1"
+        )
+        .bind(user_id)
+        .fetch_one(db)
+        .await
+        .map_err(crate::error::AppError::Database)?;
+
+        Ok(count > 0)
+    }
+}
+
+/// Delay required after `failed_count` consecutive failures: nothing up to
+/// `delay_after_failures`, then doubling from `base_delay_ms` up to `max_delay_ms`
+pub fn retry_delay(failed_count: u32, config: &LoginProtectionConfig) -> Option<Duration> {
+    if failed_count <= config.delay_after_failures {
+        return None;
+    }
+
+    let exponent = (failed_count - config.delay_after_failures - 1).min(32);
+    let delay_ms = config
+        .base_delay_ms
+        .saturating_mul(1u64 << exponent)
+        .min(config.max_delay_ms);
+
+    Some(Duration::milliseconds(delay_ms as i64))
+}
+
+/// Coarse device fingerprint: the network the IP belongs to (/24 for IPv4,
+/// /48 for IPv6) plus the user agent with version numbers removed, so routine
+/// browser updates and DHCP churn do not look like a new device
+pub fn login_fingerprint(ip_address: &str, user_agent: Option<&str>) -> String {
+    let network = match ip_address.parse::<IpAddr>() {
+        Ok(IpAddr::V4(ip)) => {
+            let [a, b, c, _] = ip.octets();
+            format!("{}.{}.{}.0/24", a, b, c)
+        }
+        Ok(IpAddr::V6(ip)) => {
+            let segments = ip.segments();
+            format!("{:x}:{:x}:{:x}::/48", segments[0], segments[1], segments[2])
+        }
+        Err(_) => ip_address.to_string(),
+    };
+
+    let agent: String = user_agent
+        .unwrap_or_default()
+        .chars()
+        .filter(|c| !c.is_ascii_digit() && *c != '.' && *c != '_')
+        .collect();
+
+    hex::encode(Sha256::digest(format!("{}|{}", network, agent).as_bytes()))
+}
+
+/// Run a bcrypt verification that always fails, so rejecting an unknown or
+/// locked account costs as much as checking a wrong password
+pub fn burn_password_check(password: &str) {
+    static DUMMY_HASH: OnceLock<String> = OnceLock::new();
+
+    let hash = DUMMY_HASH.get_or_init(|| {
+        crate::models::auth::PasswordUtils::hash_password("texler-login-timing-equalizer")
+            .unwrap_or_default()
+    });
+
+    let _ = crate::models::auth::PasswordUtils::verify_password(password, hash);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> LoginProtectionConfig {
+        LoginProtectionConfig {
+            lockout_threshold: 10,
+            delay_after_failures: 3,
+            base_delay_ms: 1000,
+            max_delay_ms: 8000,
+            min_failure_response_ms: 500,
+        }
+    }
+
+    #[test]
+    fn test_retry_delay_grows_exponentially() {
+        let config = config();
+        assert_eq!(retry_delay(3, &config), None);
+        assert_eq!(retry_delay(4, &config), Some(Duration::seconds(1)));
+        assert_eq!(retry_delay(5, &config), Some(Duration::seconds(2)));
+        assert_eq!(retry_delay(6, &config), Some(Duration::seconds(4)));
+        assert_eq!(retry_delay(9, &config), Some(Duration::seconds(8)));
+        assert_eq!(retry_delay(u32::MAX, &config), Some(Duration::seconds(8)));
+    }
+
+    #[test]
+    fn test_retry_after() {
+        let now = Utc::now();
+        let failure = LoginFailure {
+            user_id: Uuid::new_v4(),
+            failed_count: 5,
+            last_failed_at: now - Duration::seconds(1),
+            locked_at: None,
+        };
+        assert_eq!(failure.retry_after(&config(), now), Some(Duration::seconds(1)));
+        assert_eq!(failure.retry_after(&config(), now + Duration::seconds(5)), None);
+    }
+
+    #[test]
+    fn test_login_fingerprint_is_coarse() {
+        let firefox = "Mozilla/5.0 (X11; Linux x86_64; rv:118.0) Gecko/20100101 Firefox/118.0";
+        let updated = "Mozilla/5.0 (X11; Linux x86_64; rv:119.0) Gecko/20100101 Firefox/119.0";
+
+        assert_eq!(
+            login_fingerprint("203.0.113.7", Some(firefox)),
+            login_fingerprint("203.0.113.99", Some(updated))
+        );
+        assert_ne!(
+            login_fingerprint("203.0.113.7", Some(firefox)),
+            login_fingerprint("198.51.100.7", Some(firefox))
+        );
+        assert_ne!(
+            login_fingerprint("203.0.113.7", Some(firefox)),
+            login_fingerprint("203.0.113.7", Some("curl/8.0"))
+        );
+    }
+}
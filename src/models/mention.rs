@@ -0,0 +1,542 @@
+//! Mentions of users in session chat
+//!
+//! `@username` in a text message mentions a user. Names are only resolved
+//! against people who can already follow the conversation: the project's
+//! owner and collaborators, and participants who have not left the session.
+//! Other names stay plain text, so the chat cannot be used to find out which
+//! usernames exist, and neither do names inside code spans or fenced blocks.
+//! Mentioned users hear about it according to their
+//! `MentionNotificationPreference`; editing a message only notifies the users
+//! it newly mentions.
+
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use std::collections::HashMap;
+use std::sync::Arc;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use super::collaboration::{CollaborationSession, MessageType, SessionMessage};
+use super::file_tree::escape_like;
+use super::notification::Notification;
+use super::session_anonymity::SessionMask;
+use super::user::User;
+use crate::email::templates::NotificationEmail;
+use crate::email::{Locale, Mailer};
+use crate::error::AppError;
+use crate::websocket::{UserChannels, WsMessage};
+
+/// Candidates returned for one autocomplete prefix
+pub const MAX_MENTION_CANDIDATES: i64 = 10;
+
+/// Characters of the message quoted in a mention notification
+const EXCERPT_CHARS: usize = 200;
+
+/// How a user hears about being mentioned
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, sqlx::Type, ToSchema)]
+pub enum MentionNotificationPreference {
+    #[serde(rename = "none")]
+    #[sqlx(rename = "none")]
+    None,
+    #[serde(rename = "in_app")]
+    #[sqlx(rename = "in_app")]
+    #[default]
+    InApp,
+    #[serde(rename = "in_app_and_email")]
+    #[sqlx(rename = "in_app_and_email")]
+    InAppAndEmail,
+}
+
+impl MentionNotificationPreference {
+    /// Preference of a user; users without stored preferences get the default
+    pub async fn for_user(db: &sqlx::PgPool, user_id: Uuid) -> Result<Self, AppError> {
+        let preference = sqlx::query_scalar::<_, MentionNotificationPreference>(
+            "SELECT mention_notifications FROM user_preferences WHERE user_id = $1"
+        )
+        .bind(user_id)
+        .fetch_optional(db)
+        .await
+        .map_err(AppError::Database)?;
+
+        Ok(preference.unwrap_or_default())
+    }
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-')
+}
+
+/// Names mentioned in `content`, in order of first appearance
+///
+/// A mention is `@` at the start of a word followed by a username; trailing
+/// dots and dashes are punctuation, and an `@` inside a word is an email
+/// address. Inline code and fenced code blocks are skipped.
+pub fn parse_mentions(content: &str) -> Vec<String> {
+    let mut names: Vec<String> = Vec::new();
+    let mut in_fence = false;
+
+    for line in content.lines() {
+        if line.trim_start().starts_with("```") {
+            in_fence = !in_fence;
+            continue;
+        }
+        if in_fence {
+            continue;
+        }
+
+        let mut in_code = false;
+        let mut previous: Option<char> = None;
+        for (index, c) in line.char_indices() {
+            if c == '`' {
+                in_code = !in_code;
+            } else if c == '@' && !in_code && previous.map_or(true, |p| !is_name_char(p) && p != '@') {
+                let rest = &line[index + 1..];
+                let end = rest.find(|c: char| !is_name_char(c)).unwrap_or(rest.len());
+                let name = rest[..end].trim_end_matches(['.', '-']);
+                let starts_well = name.starts_with(|c: char| c.is_ascii_alphanumeric() || c == '_');
+                if starts_well && !names.iter().any(|known| known.eq_ignore_ascii_case(name)) {
+                    names.push(name.to_string());
+                }
+            }
+            previous = Some(c);
+        }
+    }
+
+    names
+}
+
+/// Pick the user a name refers to among users whose name matches it ignoring case
+///
+/// An exact match wins; otherwise the name has to be unambiguous.
+fn pick_user(name: &str, users: &[(Uuid, String)]) -> Option<Uuid> {
+    let mut matching = users.iter().filter(|(_, username)| username.eq_ignore_ascii_case(name));
+    if let Some((id, _)) = users.iter().find(|(_, username)| username == name) {
+        return Some(*id);
+    }
+    match (matching.next(), matching.next()) {
+        (Some((id, _)), None) => Some(*id),
+        _ => None,
+    }
+}
+
+/// Users a message of `message_type` mentions; only text messages mention anyone
+pub async fn resolve_mentions(
+    db: &sqlx::PgPool,
+    session: &CollaborationSession,
+    message_type: MessageType,
+    content: &str,
+) -> Result<Vec<Uuid>, AppError> {
+    if message_type != MessageType::Text {
+        return Ok(Vec::new());
+    }
+    let names = parse_mentions(content);
+    if names.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let lowered: Vec<String> = names.iter().map(|name| name.to_lowercase()).collect();
+    let users = sqlx::query_as::<_, (Uuid, String)>(
+        r#"
+        SELECT u.id, u.username FROM users u
+        WHERE u.is_active = true AND LOWER(u.username) = ANY($1) AND (
+            u.id = (SELECT owner_id FROM projects WHERE id = $2)
+            OR u.id IN (SELECT user_id FROM project_collaborators WHERE project_id = $2)
+            OR u.id IN (SELECT user_id FROM session_participants WHERE session_id = $3 AND left_at IS NULL)
+        )
+        "#
+    )
+    .bind(&lowered)
+    .bind(session.project_id)
+    .bind(session.id)
+    .fetch_all(db)
+    .await
+    .map_err(AppError::Database)?;
+
+    let mut mentioned = Vec::new();
+    for name in &names {
+        if let Some(id) = pick_user(name, &users) {
+            if !mentioned.contains(&id) {
+                mentioned.push(id);
+            }
+        }
+    }
+    Ok(mentioned)
+}
+
+/// A mentioned user, or a candidate for a mention
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, ToSchema)]
+pub struct MentionedUser {
+    pub id: Uuid,
+    pub username: String,
+    pub display_name: String,
+    pub avatar_url: Option<String>,
+}
+
+impl MentionedUser {
+    /// Active users among `ids`, by id
+    pub async fn find_many(db: &sqlx::PgPool, ids: &[Uuid]) -> Result<HashMap<Uuid, Self>, AppError> {
+        if ids.is_empty() {
+            return Ok(HashMap::new());
+        }
+
+        let users = sqlx::query_as::<_, MentionedUser>(
+            "SELECT id, username, display_name, avatar_url FROM users WHERE id = ANY($1) AND is_active = true"
+        )
+        .bind(ids)
+        .fetch_all(db)
+        .await
+        .map_err(AppError::Database)?;
+
+        Ok(users.into_iter().map(|user| (user.id, user)).collect())
+    }
+
+    /// Members of a project whose username starts with `prefix`, for autocomplete
+    ///
+    /// With `session_id` set, participants of that session of the project who
+    /// have not left are candidates too.
+    pub async fn candidates(
+        db: &sqlx::PgPool,
+        project_id: Uuid,
+        session_id: Option<Uuid>,
+        prefix: &str,
+    ) -> Result<Vec<Self>, AppError> {
+        sqlx::query_as::<_, MentionedUser>(
+            r#"
+            SELECT u.id, u.username, u.display_name, u.avatar_url FROM users u
+            WHERE u.is_active = true AND LOWER(u.username) LIKE $3 ESCAPE '\' AND (
+                u.id = (SELECT owner_id FROM projects WHERE id = $1)
+                OR u.id IN (SELECT user_id FROM project_collaborators WHERE project_id = $1)
+                OR u.id IN (
+                    SELECT sp.user_id FROM session_participants sp
+                    JOIN collaboration_sessions s ON s.id = sp.session_id
+                    WHERE sp.session_id = $2 AND s.project_id = $1 AND sp.left_at IS NULL
+                )
+            )
+            ORDER BY LOWER(u.username)
+            LIMIT $4
+            "#
+        )
+        .bind(project_id)
+        .bind(session_id)
+        .bind(format!("{}%", escape_like(&prefix.to_lowercase())))
+        .bind(MAX_MENTION_CANDIDATES)
+        .fetch_all(db)
+        .await
+        .map_err(AppError::Database)
+    }
+}
+
+impl SessionMessage {
+    /// Store a message of `user_id`, resolving its mentions
+    pub async fn create(
+        db: &sqlx::PgPool,
+        session: &CollaborationSession,
+        user_id: Uuid,
+        message_type: MessageType,
+        content: String,
+        reply_to: Option<Uuid>,
+    ) -> Result<Self, AppError> {
+        let mentions = resolve_mentions(db, session, message_type, &content).await?;
+
+        sqlx::query_as::<_, SessionMessage>(
+            r#"
+            INSERT INTO session_messages (session_id, user_id, message_type, content, reply_to, mentions, created_at)
+            VALUES ($1, $2, $3, $4, $5, $6, NOW())
+            RETURNING *
+            "#
+        )
+        .bind(session.id)
+        .bind(user_id)
+        .bind(message_type)
+        .bind(content)
+        .bind(reply_to)
+        .bind(&mentions)
+        .fetch_one(db)
+        .await
+        .map_err(AppError::Database)
+    }
+
+    /// Replace the content of a message of `user_id`
+    ///
+    /// Returns the edited message and the users it mentioned before.
+    pub async fn edit(
+        db: &sqlx::PgPool,
+        session: &CollaborationSession,
+        message_id: Uuid,
+        user_id: Uuid,
+        content: String,
+    ) -> Result<(Self, Vec<Uuid>), AppError> {
+        let mut tx = db.begin().await.map_err(AppError::Database)?;
+
+        let message = sqlx::query_as::<_, SessionMessage>(
+            "SELECT * FROM session_messages WHERE id = $1 AND session_id = $2 AND deleted = false FOR UPDATE"
+        )
+        .bind(message_id)
+        .bind(session.id)
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(AppError::Database)?
+        .ok_or_else(|| AppError::NotFound {
+            entity: "SessionMessage".to_string(),
+            id: message_id.to_string(),
+        })?;
+
+        if message.user_id != user_id {
+            return Err(AppError::Authorization("Only the author can edit a message".to_string()));
+        }
+        if !matches!(message.message_type, MessageType::Text | MessageType::Code) {
+            return Err(AppError::Validation("Only text and code messages can be edited".to_string()));
+        }
+
+        let mentions = resolve_mentions(db, session, message.message_type, &content).await?;
+        let edited = sqlx::query_as::<_, SessionMessage>(
+            r#"
+            UPDATE session_messages
+            SET content = $2, mentions = $3, edited = true, edited_at = NOW()
+            WHERE id = $1
+            RETURNING *
+            "#
+        )
+        .bind(message_id)
+        .bind(content)
+        .bind(&mentions)
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(AppError::Database)?;
+
+        tx.commit().await.map_err(AppError::Database)?;
+
+        Ok((edited, message.mentions))
+    }
+}
+
+/// Users `message` mentions that `previously` did not, leaving out its author
+pub fn newly_mentioned(message: &SessionMessage, previously: &[Uuid]) -> Vec<Uuid> {
+    message
+        .mentions
+        .iter()
+        .copied()
+        .filter(|user_id| *user_id != message.user_id && !previously.contains(user_id))
+        .collect()
+}
+
+/// Start of a message as quoted in a notification
+fn excerpt(content: &str) -> String {
+    let content = content.trim();
+    match content.char_indices().nth(EXCERPT_CHARS) {
+        Some((end, _)) => format!("{}…", &content[..end]),
+        None => content.to_string(),
+    }
+}
+
+/// Tells users they were mentioned in session chat
+#[derive(Debug)]
+pub struct MentionNotifier {
+    mailer: Arc<Mailer>,
+    user_channels: Arc<UserChannels>,
+}
+
+impl MentionNotifier {
+    pub fn new(mailer: Arc<Mailer>, user_channels: Arc<UserChannels>) -> Self {
+        Self { mailer, user_channels }
+    }
+
+    /// Notify the users `message` newly mentions; returns how many were notified
+    ///
+    /// `previously` are the users mentioned before an edit, empty for a new message.
+    pub async fn notify(
+        &self,
+        db: &sqlx::PgPool,
+        session: &CollaborationSession,
+        message: &SessionMessage,
+        previously: &[Uuid],
+    ) -> Result<usize, AppError> {
+        let recipients = newly_mentioned(message, previously);
+        if recipients.is_empty() {
+            return Ok(0);
+        }
+
+        let Some(author) = User::find_by_id(db, message.user_id).await? else {
+            return Ok(0);
+        };
+        let place = match &session.title {
+            Some(title) => title.clone(),
+            None => sqlx::query_scalar::<_, String>("SELECT name FROM projects WHERE id = $1")
+                .bind(session.project_id)
+                .fetch_optional(db)
+                .await
+                .map_err(AppError::Database)?
+                .unwrap_or_else(|| "a session".to_string()),
+        };
+        let body = excerpt(&message.content);
+        let now = chrono::Utc::now();
+
+        let mut notified = 0;
+        for user_id in recipients {
+            let preference = MentionNotificationPreference::for_user(db, user_id).await?;
+            if preference == MentionNotificationPreference::None {
+                continue;
+            }
+            let Some(user) = User::find_by_id(db, user_id).await? else {
+                continue;
+            };
+
+            // Anonymous sessions name the author by their pseudonym
+            let author_name = match SessionMask::load(db, session, user.id, now).await? {
+                Some(mut mask) => {
+                    mask.cover(db, [author.id]).await?;
+                    mask.pseudonym(author.id).unwrap_or_else(|| author.display_name.clone())
+                }
+                None => author.display_name.clone(),
+            };
+            let title = format!("{} mentioned you in {}", author_name, place);
+
+            let notification = Notification::create(
+                db,
+                user.id,
+                "mention",
+                &title,
+                &body,
+                Some(serde_json::json!({
+                    "session_id": session.id,
+                    "message_id": message.id,
+                    "project_id": session.project_id,
+                })),
+            )
+            .await?;
+            self.user_channels.send(user.id, WsMessage::Notification { notification });
+
+            if preference == MentionNotificationPreference::InAppAndEmail {
+                let locale = Locale::for_user(db, user.id).await?;
+                let email = NotificationEmail { title, body: body.clone() };
+                self.mailer.send(&user.email, locale, &email).await?;
+            }
+            notified += 1;
+        }
+
+        Ok(notified)
+    }
+
+    /// `notify`, logging instead of failing; the message itself was stored already
+    pub async fn notify_or_warn(
+        &self,
+        db: &sqlx::PgPool,
+        session: &CollaborationSession,
+        message: &SessionMessage,
+        previously: &[Uuid],
+    ) {
+        if let Err(e) = self.notify(db, session, message, previously).await {
+            tracing::warn!("Failed to notify the users mentioned in message {}: {}", message.id, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::UserRole;
+    use crate::testing::{add_collaborator, create_test_project, create_test_session, create_test_user, TestDb};
+
+    #[test]
+    fn test_parse_mentions() {
+        assert_eq!(parse_mentions("hi @alice and @bob_2!"), vec!["alice", "bob_2"]);
+        assert_eq!(parse_mentions("@jane.doe, see @x-ray."), vec!["jane.doe", "x-ray"]);
+        assert_eq!(parse_mentions("(@Alice) @alice @ALICE"), vec!["Alice"]);
+        assert_eq!(parse_mentions("@_under_score__ @42"), vec!["_under_score__", "42"]);
+
+        // Email addresses, a lone @ and doubled @@ are not mentions
+        assert!(parse_mentions("mail alice@example.com @ @@bob @.dot @-").is_empty());
+    }
+
+    #[test]
+    fn test_no_mentions_in_code() {
+        assert_eq!(parse_mentions("`@alice` @bob `x @carol y`"), vec!["bob"]);
+        assert_eq!(
+            parse_mentions("before @alice\n```latex\n\\cite{@bob}\n@carol\n```\nafter @dave"),
+            vec!["alice", "dave"]
+        );
+    }
+
+    #[test]
+    fn test_exact_name_wins_over_case_insensitive_ones() {
+        let (bob, bob_upper, carol) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+        let users = vec![(bob, "bob".to_string()), (bob_upper, "Bob".to_string()), (carol, "Carol".to_string())];
+
+        assert_eq!(pick_user("Bob", &users), Some(bob_upper));
+        assert_eq!(pick_user("bob", &users), Some(bob));
+        assert_eq!(pick_user("BOB", &users), None);
+        assert_eq!(pick_user("carol", &users), Some(carol));
+        assert_eq!(pick_user("dave", &users), None);
+    }
+
+    #[test]
+    fn test_excerpt() {
+        assert_eq!(excerpt("  short  "), "short");
+        let long = "é".repeat(EXCERPT_CHARS + 5);
+        assert_eq!(excerpt(&long).chars().count(), EXCERPT_CHARS + 1);
+    }
+
+    #[tokio::test]
+    async fn test_mentions_resolve_to_members_only() {
+        let Some(db) = TestDb::start().await else { return };
+        let owner = create_test_user(&db.pool).await;
+        let member = create_test_user(&db.pool).await;
+        let outsider = create_test_user(&db.pool).await;
+        let project = create_test_project(&db.pool, &owner, true).await;
+        add_collaborator(&db.pool, &project, &member, UserRole::Collaborator).await;
+
+        let session = create_test_session(&db.pool, &project, &owner).await;
+
+        let content = format!(
+            "@{} @{} @{} `@{}` @nobody",
+            member.username,
+            outsider.username,
+            owner.username.to_uppercase(),
+            member.username
+        );
+        let message = SessionMessage::create(&db.pool, &session, owner.id, MessageType::Text, content, None)
+            .await
+            .unwrap();
+        assert_eq!(message.mentions, vec![member.id, owner.id]);
+        assert_eq!(newly_mentioned(&message, &[]), vec![member.id]);
+
+        // Code messages mention nobody
+        let code = format!("@{}", member.username);
+        let message = SessionMessage::create(&db.pool, &session, owner.id, MessageType::Code, code, None)
+            .await
+            .unwrap();
+        assert!(message.mentions.is_empty());
+
+        // Only the author may edit, and an edit only reports new mentions
+        let first = SessionMessage::create(&db.pool, &session, owner.id, MessageType::Text, format!("@{}", member.username), None)
+            .await
+            .unwrap();
+        assert!(SessionMessage::edit(&db.pool, &session, first.id, member.id, "x".to_string()).await.is_err());
+
+        sqlx::query("DELETE FROM project_collaborators WHERE project_id = $1 AND user_id = $2")
+            .bind(project.id)
+            .bind(member.id)
+            .execute(&db.pool)
+            .await
+            .unwrap();
+        let third = create_test_user(&db.pool).await;
+        add_collaborator(&db.pool, &project, &third, UserRole::Collaborator).await;
+
+        let edited_content = format!("@{} @{}", member.username, third.username);
+        let (edited, previously) = SessionMessage::edit(&db.pool, &session, first.id, owner.id, edited_content)
+            .await
+            .unwrap();
+        assert!(edited.edited);
+        assert_eq!(previously, vec![member.id]);
+        // The removed member is no longer mentioned
+        assert_eq!(edited.mentions, vec![third.id]);
+        assert_eq!(newly_mentioned(&edited, &previously), vec![third.id]);
+
+        // Test usernames all start with "user_"
+        let candidates = MentionedUser::candidates(&db.pool, project.id, None, "USER_").await.unwrap();
+        let ids: Vec<Uuid> = candidates.iter().map(|user| user.id).collect();
+        assert!(ids.contains(&third.id) && ids.contains(&owner.id));
+        assert!(!ids.contains(&member.id) && !ids.contains(&outsider.id));
+        assert!(MentionedUser::candidates(&db.pool, project.id, None, "%").await.unwrap().is_empty());
+    }
+}
@@ -1,20 +1,118 @@
 //! Domain models for the Texler backend
+//!
+//! # Database handles
+//!
+//! What a model function takes for its database says how it may be called:
+//!
+//! - `&sqlx::PgPool` for reads and for mutations that run one statement or
+//!   do work besides the database, such as storage or notifications.
+//! - `impl sqlx::PgExecutor<'_>` for single statements a caller may want in
+//!   its own transaction; pass `&pool` or `&mut *tx`.
+//! - `&mut sqlx::PgConnection` for steps that are only consistent as part
+//!   of a larger mutation and leave the transaction to the caller.
+//! - `impl sqlx::Acquire<'a, Database = sqlx::Postgres>` for mutations of
+//!   several statements, such as `Project::create` or `CompilationJob::create`.
+//!   They begin a transaction on the handle and commit it before returning,
+//!   so they never leave part of their rows behind. Given a transaction
+//!   (`&mut tx`) they run in a savepoint of it, which lets a handler compose
+//!   several of them and commit or roll back everything at once.
+//!
+//! Tests check the rollbacks with the fail points in `fail_point`.
 
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
+use utoipa::{IntoParams, ToSchema};
 use uuid::Uuid;
 
 pub mod user;
 pub mod project;
+pub mod project_archive;
+pub mod archive_checksum;
+pub mod project_clone;
+pub mod project_purge;
 pub mod file;
+pub mod file_tree;
 pub mod collaboration;
 pub mod compilation;
 pub mod auth;
 pub mod token_blacklist;
 pub mod password_reset;
+pub mod email_change;
 pub mod email_verification;
 pub mod workspace;
+pub mod upload;
+pub mod blob;
+pub mod compile_environment;
+pub mod compile_diff;
+pub mod dictionary;
+pub mod invitation;
+pub mod notification;
+pub mod login_protection;
+pub mod readme;
+pub mod compile_notification;
+pub mod compile_preflight;
+pub mod session_summary;
+pub mod autocomplete;
+pub mod settings_history;
+pub mod activity_digest;
+pub mod undo;
+pub mod inline_render;
+pub mod activity_rollup;
+pub mod validation;
+pub mod template_catalog;
+pub mod audit;
+pub mod file_scan;
+pub mod include_graph;
+pub mod session_access;
+pub mod file_reindex;
+pub mod settings_transfer;
+pub mod ws_rate_limit;
+pub mod ws_connection_limit;
+pub mod ws_error;
+pub mod chat_attachment;
+pub mod project_freeze;
+pub mod integrity;
+pub mod session_schedule;
+pub mod detail_fields;
+pub mod session_anonymity;
+pub mod typst;
+pub mod job_history;
+pub mod avatar;
+pub mod fail_point;
+pub mod outline;
+pub mod compile_sandbox;
+pub mod file_history;
+pub mod mention;
+pub mod formatter;
+pub mod embed_token;
+pub mod activity_export;
+pub mod project_secret;
+pub mod word_count;
+pub mod git_import;
+pub mod compile_cancel;
+pub mod storage_usage;
+pub mod project_template;
+pub mod session_activity;
+pub mod discovery;
+pub mod timezone;
+pub mod incremental_build;
+pub mod onboarding;
+pub mod compile_images;
+pub mod quick_search;
+pub mod project_folder;
+pub mod project_limits;
+pub mod oidc_flow;
+pub mod compile_batch;
+pub mod project_presence;
+pub mod file_share;
+pub mod ownership_transfer;
+pub mod conflict_event;
+pub mod compile_thumbnails;
+pub mod usage_period;
+pub mod project_switcher;
+pub mod file_blame;
+pub mod announcement;
 
 /// Common trait for database entities
 pub trait Entity {
@@ -24,12 +122,15 @@ pub trait Entity {
 }
 
 /// Pagination parameters
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Default, Deserialize, ToSchema, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct PaginationParams {
     pub page: Option<u32>,
     pub limit: Option<u32>,
     pub offset: Option<u32>,
+    /// Column to sort by, one of those the list allows
     pub sort_by: Option<String>,
+    /// Defaults to the usual direction of the sort column
     pub sort_order: Option<SortOrder>,
 }
 
@@ -53,12 +154,61 @@ impl PaginationParams {
     pub fn sort_order(&self) -> SortOrder {
         self.sort_order.unwrap_or(SortOrder::Desc)
     }
+
+    /// `ORDER BY` clause for the requested sort, which must be one `sortable`
+    /// allows; without `sort_by` the list keeps its default order
+    ///
+    /// The tiebreaker is appended in the same direction, so rows sharing a
+    /// sort value keep their place between pages and none is repeated or skipped.
+    pub fn order_by(&self, sortable: &Sortable) -> Result<String, crate::error::AppError> {
+        let column = match self.sort_by.as_deref() {
+            None => &sortable.columns[0],
+            Some(name) => sortable.columns.iter().find(|column| column.name == name).ok_or_else(|| {
+                let names: Vec<&str> = sortable.columns.iter().map(|column| column.name).collect();
+                crate::error::AppError::Validation(format!(
+                    "Cannot sort by '{}', expected one of: {}",
+                    name,
+                    names.join(", ")
+                ))
+            })?,
+        };
+        let direction = match self.sort_order.unwrap_or(column.default_order) {
+            SortOrder::Asc => "ASC",
+            SortOrder::Desc => "DESC",
+        };
+
+        Ok(format!(
+            "{} {} NULLS LAST, {} {}",
+            column.expression, direction, sortable.tiebreaker, direction
+        ))
+    }
+}
+
+/// A column a list can be sorted by
+#[derive(Debug, Clone, Copy)]
+pub struct SortColumn {
+    /// Name clients pass as `sort_by`
+    pub name: &'static str,
+    /// SQL expression sorted on, qualified by the list query's table alias
+    pub expression: &'static str,
+    /// Direction when no `sort_order` is given
+    pub default_order: SortOrder,
+}
+
+/// The columns a list can be sorted by, the first being its default
+#[derive(Debug, Clone, Copy)]
+pub struct Sortable {
+    pub columns: &'static [SortColumn],
+    /// Unique column breaking ties, usually the id
+    pub tiebreaker: &'static str,
 }
 
 /// Sort order enum
-#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, ToSchema)]
 pub enum SortOrder {
+    #[serde(alias = "asc")]
     Asc,
+    #[serde(alias = "desc")]
     Desc,
 }
 
@@ -69,7 +219,7 @@ impl Default for SortOrder {
 }
 
 /// Paginated response
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, ToSchema)]
 pub struct PaginatedResponse<T> {
     pub data: Vec<T>,
     pub pagination: PaginationInfo,
@@ -94,7 +244,7 @@ impl<T> PaginatedResponse<T> {
 }
 
 /// Pagination information
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, ToSchema)]
 pub struct PaginationInfo {
     pub page: u32,
     pub limit: u32,
@@ -105,7 +255,8 @@ pub struct PaginationInfo {
 }
 
 /// Search parameters
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, ToSchema, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct SearchParams {
     pub query: Option<String>,
     pub tags: Option<Vec<String>>,
@@ -115,11 +266,13 @@ pub struct SearchParams {
 }
 
 /// Common response wrapper
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, ToSchema)]
 pub struct ApiResponse<T> {
     pub success: bool,
     pub data: Option<T>,
     pub error: Option<ErrorInfo>,
+    /// Only set on envelopes built through `success`/`error`
+    #[schema(required = false)]
     pub timestamp: DateTime<Utc>,
 }
 
@@ -144,14 +297,14 @@ impl<T> ApiResponse<T> {
 }
 
 /// Error information
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, ToSchema)]
 pub struct ErrorInfo {
     pub message: String,
     pub code: Option<String>,
 }
 
 /// Content type for files
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type, ToSchema)]
 pub enum ContentType {
     #[serde(rename = "latex")]
     #[sqlx(rename = "latex")]
@@ -165,6 +318,9 @@ pub enum ContentType {
     #[serde(rename = "other")]
     #[sqlx(rename = "other")]
     Other,
+    #[serde(rename = "typst")]
+    #[sqlx(rename = "typst")]
+    Typst,
 }
 
 impl Default for ContentType {
@@ -173,8 +329,24 @@ impl Default for ContentType {
     }
 }
 
+impl ContentType {
+    /// Content type of a file, by the extension of its name
+    pub fn from_file_name(file_name: &str) -> Self {
+        match std::path::Path::new(file_name)
+            .extension()
+            .and_then(|ext| ext.to_str())
+        {
+            Some("tex") => Self::Latex,
+            Some("bib") => Self::Bibliography,
+            Some("typ") => Self::Typst,
+            Some("png") | Some("jpg") | Some("jpeg") | Some("gif") | Some("svg") => Self::Image,
+            _ => Self::Other,
+        }
+    }
+}
+
 /// File storage strategy
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type, ToSchema)]
 pub enum StorageStrategy {
     #[serde(rename = "inline")]
     #[sqlx(rename = "inline")]
@@ -194,7 +366,7 @@ impl Default for StorageStrategy {
 }
 
 /// User role
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type, ToSchema)]
 pub enum UserRole {
     #[serde(rename = "owner")]
     #[sqlx(rename = "owner")]
@@ -210,8 +382,44 @@ pub enum UserRole {
     Viewer,
 }
 
+impl UserRole {
+    /// Parse a stored role name, including the legacy `editor`/`admin` names
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "owner" => Some(Self::Owner),
+            "maintainer" | "admin" => Some(Self::Maintainer),
+            "collaborator" | "editor" => Some(Self::Collaborator),
+            "viewer" => Some(Self::Viewer),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Owner => "owner",
+            Self::Maintainer => "maintainer",
+            Self::Collaborator => "collaborator",
+            Self::Viewer => "viewer",
+        }
+    }
+
+    fn rank(self) -> u8 {
+        match self {
+            Self::Owner => 3,
+            Self::Maintainer => 2,
+            Self::Collaborator => 1,
+            Self::Viewer => 0,
+        }
+    }
+
+    /// Whether this role grants at least the permissions of `required`
+    pub fn at_least(self, required: UserRole) -> bool {
+        self.rank() >= required.rank()
+    }
+}
+
 /// LaTeX engine type
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type, ToSchema)]
 pub enum LatexEngine {
     #[serde(rename = "pdflatex")]
     #[sqlx(rename = "pdflatex")]
@@ -222,6 +430,10 @@ pub enum LatexEngine {
     #[serde(rename = "lualatex")]
     #[sqlx(rename = "lualatex")]
     Lualatex,
+    /// Typst, for `.typ` targets; see `typst`
+    #[serde(rename = "typst")]
+    #[sqlx(rename = "typst")]
+    Typst,
 }
 
 impl Default for LatexEngine {
@@ -230,8 +442,20 @@ impl Default for LatexEngine {
     }
 }
 
+impl LatexEngine {
+    /// Executable name for this engine
+    pub fn command(&self) -> &'static str {
+        match self {
+            Self::Pdflatex => "pdflatex",
+            Self::Xelatex => "xelatex",
+            Self::Lualatex => "lualatex",
+            Self::Typst => "typst",
+        }
+    }
+}
+
 /// Compilation status
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type, ToSchema)]
 pub enum CompilationStatus {
     #[serde(rename = "never")]
     #[sqlx(rename = "never")]
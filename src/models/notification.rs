@@ -0,0 +1,189 @@
+//! In-app notification models
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use super::Entity;
+
+/// In-app notification
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, ToSchema)]
+pub struct Notification {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub kind: String,
+    pub title: String,
+    pub body: String,
+    pub data: Option<serde_json::Value>,
+    pub read_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl Entity for Notification {
+    fn id(&self) -> Uuid {
+        self.id
+    }
+
+    fn created_at(&self) -> DateTime<Utc> {
+        self.created_at
+    }
+
+    fn updated_at(&self) -> DateTime<Utc> {
+        self.read_at.unwrap_or(self.created_at)
+    }
+}
+
+impl Notification {
+    /// Create a notification for a user
+    pub async fn create(
+        db: &sqlx::PgPool,
+        user_id: Uuid,
+        kind: &str,
+        title: &str,
+        body: &str,
+        data: Option<serde_json::Value>,
+    ) -> Result<Self, crate::error::AppError> {
+        let notification = sqlx::query_as::<_, Notification>(
+            r#"
+            INSERT INTO notifications (user_id, kind, title, body, data)
+            VALUES ($1, $2, $3, $4, $5)
+            RETURNING *
+            "#
+        )
+        .bind(user_id)
+        .bind(kind)
+        .bind(title)
+        .bind(body)
+        .bind(data)
+        .fetch_one(db)
+        .await
+        .map_err(crate::error::AppError::Database)?;
+
+        Ok(notification)
+    }
+
+    /// List a user's notifications, newest first
+    pub async fn list_for_user(
+        db: &sqlx::PgPool,
+        user_id: Uuid,
+        unread_only: bool,
+        params: &super::PaginationParams,
+    ) -> Result<Vec<Self>, crate::error::AppError> {
+        let notifications = sqlx::query_as::<_, Notification>(
+            r#"
+            SELECT * FROM notifications
+            WHERE user_id = $1 AND ($2 = false OR read_at IS NULL)
+            ORDER BY created_at DESC
+            LIMIT $3 OFFSET $4
+            "#
+        )
+        .bind(user_id)
+        .bind(unread_only)
+        .bind(params.limit() as i64)
+        .bind(params.offset() as i64)
+        .fetch_all(db)
+        .await
+        .map_err(crate::error::AppError::Database)?;
+
+        Ok(notifications)
+    }
+
+    /// Count a user's unread notifications
+    pub async fn unread_count(db: &sqlx::PgPool, user_id: Uuid) -> Result<i64, crate::error::AppError> {
+        let count = sqlx::query_scalar::<_, i64>(
+            "SELECT COUNT(*) FROM notifications WHERE user_id = $1 AND read_at IS NULL"
+        )
+        .bind(user_id)
+        .fetch_one(db)
+        .await
+        .map_err(crate::error::AppError::Database)?;
+
+        Ok(count)
+    }
+
+    /// Mark a notification as read; returns false when it does not belong to the user
+    pub async fn mark_read(
+        db: &sqlx::PgPool,
+        notification_id: Uuid,
+        user_id: Uuid,
+    ) -> Result<bool, crate::error::AppError> {
+        let result = sqlx::query(
+            r#"
+            UPDATE notifications
+            SET read_at = COALESCE(read_at, NOW())
+            WHERE id = $1 AND user_id = $2
+            "#
+        )
+        .bind(notification_id)
+        .bind(user_id)
+        .execute(db)
+        .await
+        .map_err(crate::error::AppError::Database)?;
+
+        Ok(result.rows_affected() > 0)
+    }
+}
+
+/// Notification delivery service
+pub struct NotificationService;
+
+impl NotificationService {
+    /// Create an in-app notification and email it when email delivery is enabled
+    pub async fn notify(
+        db: &sqlx::PgPool,
+        mailer: &crate::email::Mailer,
+        user: &crate::models::user::User,
+        kind: &str,
+        title: &str,
+        body: &str,
+        data: Option<serde_json::Value>,
+    ) -> Result<Notification, crate::error::AppError> {
+        let notification = Notification::create(db, user.id, kind, title, body, data).await?;
+
+        if mailer.is_enabled() {
+            let email = crate::email::templates::NotificationEmail {
+                title: title.to_string(),
+                body: body.to_string(),
+            };
+            let sent = async {
+                let locale = crate::email::Locale::for_user(db, user.id).await?;
+                mailer.send(&user.email, locale, &email).await
+            }
+            .await;
+            // The notification exists either way
+            if let Err(e) = sent {
+                tracing::warn!("Failed to email notification {} to {}: {}", notification.id, user.id, e);
+            }
+        }
+
+        Ok(notification)
+    }
+
+    /// Notify in the background, through the buffer of best-effort writes
+    ///
+    /// For notices the request does not depend on: during a database outage
+    /// the notification waits in memory and is created, and emailed, once
+    /// the database is back.
+    pub fn notify_later(
+        resilience: &std::sync::Arc<crate::db_resilience::DbResilience>,
+        mailer: &std::sync::Arc<crate::email::Mailer>,
+        user: &crate::models::user::User,
+        kind: &str,
+        title: &str,
+        body: &str,
+        data: Option<serde_json::Value>,
+    ) -> tokio::task::JoinHandle<()> {
+        let (mailer, user) = (mailer.clone(), user.clone());
+        let (kind, title, body) = (kind.to_string(), title.to_string(), body.to_string());
+        resilience.write("notification", move |db| {
+            let (mailer, user, data) = (mailer.clone(), user.clone(), data.clone());
+            let (kind, title, body) = (kind.clone(), title.clone(), body.clone());
+            async move {
+                Self::notify(&db, &mailer, &user, &kind, &title, &body, data).await?;
+                Ok(())
+            }
+        })
+    }
+}
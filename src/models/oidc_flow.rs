@@ -0,0 +1,663 @@
+//! Server-side state of OIDC logins
+//!
+//! `POST /auth/oidc/login` starts a flow: a random `state`, a PKCE code
+//! verifier and, for providers issuing ID tokens, a nonce are generated and
+//! stored with the provider and the page to return to. The client only gets
+//! the provider's authorization URL; the verifier never leaves the server and
+//! the state is stored as its SHA-256.
+//!
+//! The callback consumes the flow in a single statement, so a state works
+//! once even when callbacks race, and only then exchanges the code with the
+//! stored verifier and checks the ID token's nonce. Flows expire after
+//! `OIDC_FLOW_TTL` seconds; [`OidcFlowCleanupTask`] removes them a day later,
+//! until then a replayed or late callback is told apart from a forged one.
+//! Refused callbacks are counted by reason in
+//! `texler_oidc_callback_failures_total`.
+
+use axum::http::StatusCode;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::{DateTime, Utc};
+use prometheus::{IntCounterVec, Opts, Registry, TextEncoder};
+use rand::RngCore;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use sqlx::FromRow;
+use std::time::Duration;
+use uuid::Uuid;
+
+use super::user::OidcUserInfo;
+use crate::config::OidcProvider;
+use crate::error::AppError;
+use crate::http_client::HttpClient;
+use crate::server::AppState;
+
+/// Issuer of GitHub's OAuth apps, which are not OIDC providers
+const GITHUB_ISSUER: &str = "https://github.com";
+
+/// Days expired and consumed flows are kept
+const FLOW_RETENTION_DAYS: i64 = 1;
+
+/// Time between two cleanup runs
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Why an OIDC callback was refused, also the `reason` of the failure metric
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OidcFailure {
+    /// No login was started with the state
+    UnknownState,
+    ExpiredState,
+    /// The callback for the state already came
+    ConsumedState,
+    /// The login was started with another provider
+    ProviderMismatch,
+    /// The provider sent an error instead of a code, e.g. the user declined
+    Denied,
+    /// The code could not be exchanged, or the user's details fetched
+    ExchangeFailed,
+    InvalidIdToken,
+    NonceMismatch,
+    NoEmail,
+}
+
+impl OidcFailure {
+    pub const ALL: [OidcFailure; 9] = [
+        Self::UnknownState,
+        Self::ExpiredState,
+        Self::ConsumedState,
+        Self::ProviderMismatch,
+        Self::Denied,
+        Self::ExchangeFailed,
+        Self::InvalidIdToken,
+        Self::NonceMismatch,
+        Self::NoEmail,
+    ];
+
+    pub fn reason(&self) -> &'static str {
+        match self {
+            Self::UnknownState => "unknown_state",
+            Self::ExpiredState => "expired_state",
+            Self::ConsumedState => "consumed_state",
+            Self::ProviderMismatch => "provider_mismatch",
+            Self::Denied => "denied",
+            Self::ExchangeFailed => "exchange_failed",
+            Self::InvalidIdToken => "invalid_id_token",
+            Self::NonceMismatch => "nonce_mismatch",
+            Self::NoEmail => "no_email",
+        }
+    }
+
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            Self::UnknownState | Self::ExpiredState | Self::ConsumedState | Self::ProviderMismatch => {
+                StatusCode::BAD_REQUEST
+            }
+            Self::ExchangeFailed => StatusCode::BAD_GATEWAY,
+            Self::Denied | Self::InvalidIdToken | Self::NonceMismatch | Self::NoEmail => StatusCode::UNAUTHORIZED,
+        }
+    }
+
+    pub fn error_code(&self) -> &'static str {
+        match self {
+            Self::ExpiredState => "OIDC_LOGIN_EXPIRED",
+            Self::ConsumedState => "OIDC_LOGIN_USED",
+            Self::UnknownState | Self::ProviderMismatch => "OIDC_LOGIN_INVALID",
+            _ => "OIDC_LOGIN_FAILED",
+        }
+    }
+}
+
+impl std::fmt::Display for OidcFailure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::UnknownState | Self::ProviderMismatch => "This sign-in link is not valid; start the login again",
+            Self::ExpiredState => "This sign-in link has expired; start the login again",
+            Self::ConsumedState => "This sign-in link was already used; start the login again",
+            Self::Denied => "The provider did not allow the sign-in",
+            Self::ExchangeFailed => "The provider could not confirm the sign-in",
+            Self::InvalidIdToken | Self::NonceMismatch => "The provider's identity token is not valid for this sign-in",
+            Self::NoEmail => "The provider did not share an email address",
+        })
+    }
+}
+
+impl From<OidcFailure> for AppError {
+    fn from(failure: OidcFailure) -> Self {
+        AppError::OidcLogin(failure)
+    }
+}
+
+/// A login in progress
+#[derive(Debug, Clone, FromRow)]
+pub struct OidcFlow {
+    pub id: Uuid,
+    pub provider: String,
+    pub code_verifier: String,
+    pub nonce: Option<String>,
+    pub redirect_to: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    pub consumed_at: Option<DateTime<Utc>>,
+}
+
+/// What the authorization URL of a new flow is built from
+#[derive(Debug, Clone)]
+pub struct StartedFlow {
+    pub state: String,
+    /// S256 challenge of the stored verifier
+    pub code_challenge: String,
+    pub nonce: Option<String>,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// 32 random bytes, base64url encoded
+fn random_token() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+fn state_hash(state: &str) -> String {
+    hex::encode(Sha256::digest(state.as_bytes()))
+}
+
+/// PKCE S256 challenge of a code verifier
+pub fn code_challenge(code_verifier: &str) -> String {
+    URL_SAFE_NO_PAD.encode(Sha256::digest(code_verifier.as_bytes()))
+}
+
+/// Check the page a login returns to: a path of the frontend, never another site
+pub fn validate_redirect(target: &str) -> Result<&str, AppError> {
+    if !target.starts_with('/') || target.starts_with("//") || target.contains('\\') || target.len() > 2048 {
+        return Err(AppError::Validation("Redirect target must be a path of this site".to_string()));
+    }
+    Ok(target)
+}
+
+impl OidcFlow {
+    /// Start a login with `provider`, expiring after `ttl`
+    pub async fn start(
+        db: &sqlx::PgPool,
+        provider: &str,
+        with_nonce: bool,
+        redirect_to: Option<&str>,
+        ttl: Duration,
+    ) -> Result<StartedFlow, AppError> {
+        let state = random_token();
+        let code_verifier = random_token();
+        let nonce = with_nonce.then(random_token);
+
+        let expires_at = sqlx::query_scalar::<_, DateTime<Utc>>(
+            r#"
+            INSERT INTO oidc_flows (state_hash, provider, code_verifier, nonce, redirect_to, expires_at)
+            VALUES ($1, $2, $3, $4, $5, NOW() + $6 * INTERVAL '1 second')
+            RETURNING expires_at
+            "#
+        )
+        .bind(state_hash(&state))
+        .bind(provider)
+        .bind(&code_verifier)
+        .bind(&nonce)
+        .bind(redirect_to)
+        .bind(ttl.as_secs_f64())
+        .fetch_one(db)
+        .await
+        .map_err(AppError::Database)?;
+
+        Ok(StartedFlow {
+            state,
+            code_challenge: code_challenge(&code_verifier),
+            nonce,
+            expires_at,
+        })
+    }
+
+    /// Consume the flow of `state` for its callback
+    ///
+    /// Of callbacks racing for one state exactly one gets the flow. With a
+    /// `provider` the flow must have been started with it; a mismatch leaves
+    /// the flow to the right callback.
+    pub async fn consume(db: &sqlx::PgPool, state: &str, provider: Option<&str>) -> Result<Self, AppError> {
+        let hash = state_hash(state);
+        let consumed = sqlx::query_as::<_, OidcFlow>(
+            r#"
+            UPDATE oidc_flows SET consumed_at = NOW()
+            WHERE state_hash = $1 AND consumed_at IS NULL AND expires_at > NOW()
+              AND ($2::VARCHAR IS NULL OR provider = $2)
+            RETURNING id, provider, code_verifier, nonce, redirect_to, created_at, expires_at, consumed_at
+            "#
+        )
+        .bind(&hash)
+        .bind(provider)
+        .fetch_optional(db)
+        .await
+        .map_err(AppError::Database)?;
+        if let Some(flow) = consumed {
+            return Ok(flow);
+        }
+
+        let refused = sqlx::query_as::<_, (bool, bool)>(
+            "SELECT consumed_at IS NOT NULL, expires_at <= NOW() FROM oidc_flows WHERE state_hash = $1"
+        )
+        .bind(&hash)
+        .fetch_optional(db)
+        .await
+        .map_err(AppError::Database)?;
+        Err(match refused {
+            None => OidcFailure::UnknownState,
+            Some((true, _)) => OidcFailure::ConsumedState,
+            Some((false, true)) => OidcFailure::ExpiredState,
+            Some((false, false)) => OidcFailure::ProviderMismatch,
+        }
+        .into())
+    }
+
+    /// Remove flows that expired or were consumed over a day ago
+    pub async fn clean_up(db: &sqlx::PgPool) -> Result<u64, AppError> {
+        let removed = sqlx::query(
+            r#"
+            DELETE FROM oidc_flows
+            WHERE COALESCE(consumed_at, expires_at) < NOW() - $1 * INTERVAL '1 day'
+            "#
+        )
+        .bind(FLOW_RETENTION_DAYS as f64)
+        .execute(db)
+        .await
+        .map_err(AppError::Database)?;
+        Ok(removed.rows_affected())
+    }
+}
+
+/// Where a provider is asked for codes, tokens and the user's details
+#[derive(Debug, Clone)]
+pub struct ProviderEndpoints {
+    pub issuer: String,
+    pub authorization: String,
+    pub token: String,
+    pub userinfo: String,
+    /// GitHub issues no ID tokens and lists emails on an endpoint of its own
+    pub github: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct Discovery {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    userinfo_endpoint: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    id_token: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct IdTokenClaims {
+    nonce: Option<String>,
+}
+
+/// User details of an OIDC userinfo endpoint
+#[derive(Debug, Deserialize)]
+struct StandardUserInfo {
+    sub: String,
+    email: Option<String>,
+    #[serde(default)]
+    email_verified: bool,
+    name: Option<String>,
+    given_name: Option<String>,
+    family_name: Option<String>,
+    picture: Option<String>,
+    locale: Option<String>,
+    preferred_username: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GitHubUser {
+    id: i64,
+    login: String,
+    name: Option<String>,
+    avatar_url: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GitHubEmail {
+    email: String,
+    primary: bool,
+    verified: bool,
+}
+
+/// Log why talking to the provider failed; the user only learns that it did
+fn exchange_failed(detail: impl std::fmt::Display) -> AppError {
+    tracing::warn!("OIDC login failed at the provider: {}", detail);
+    OidcFailure::ExchangeFailed.into()
+}
+
+impl ProviderEndpoints {
+    /// Endpoints of `provider`, from its discovery document unless it is GitHub
+    pub async fn resolve(http: &HttpClient, provider: &OidcProvider) -> Result<Self, AppError> {
+        let issuer = provider.issuer_url.trim_end_matches('/');
+        if issuer == GITHUB_ISSUER {
+            return Ok(Self {
+                issuer: issuer.to_string(),
+                authorization: "https://github.com/login/oauth/authorize".to_string(),
+                token: "https://github.com/login/oauth/access_token".to_string(),
+                userinfo: "https://api.github.com/user".to_string(),
+                github: true,
+            });
+        }
+
+        let request = http
+            .request(reqwest::Method::GET, &format!("{}/.well-known/openid-configuration", issuer))
+            .build()
+            .map_err(|e| AppError::Upstream(format!("Invalid discovery request: {}", e)))?;
+        let response = http.send("oidc", request).await?;
+        if !response.status().is_success() {
+            return Err(AppError::Upstream(format!("OIDC discovery returned {}", response.status())));
+        }
+        let discovery: Discovery = response
+            .json()
+            .await
+            .map_err(|e| AppError::Upstream(format!("Invalid OIDC discovery document: {}", e)))?;
+
+        Ok(Self {
+            issuer: discovery.issuer,
+            authorization: discovery.authorization_endpoint,
+            token: discovery.token_endpoint,
+            userinfo: discovery
+                .userinfo_endpoint
+                .ok_or_else(|| AppError::Upstream("OIDC provider has no userinfo endpoint".to_string()))?,
+            github: false,
+        })
+    }
+
+    /// Whether logins with `provider` get an ID token, and so a nonce
+    pub fn issues_id_tokens(&self, provider: &OidcProvider) -> bool {
+        !self.github && provider.scopes.iter().any(|scope| scope == "openid")
+    }
+
+    /// URL sending the user to the provider for a new flow
+    pub fn authorization_url(&self, provider: &OidcProvider, flow: &StartedFlow) -> Result<String, AppError> {
+        let mut url = url::Url::parse(&self.authorization)
+            .map_err(|e| AppError::Upstream(format!("Invalid authorization endpoint: {}", e)))?;
+        {
+            let mut query = url.query_pairs_mut();
+            query
+                .append_pair("response_type", "code")
+                .append_pair("client_id", &provider.client_id)
+                .append_pair("redirect_uri", &provider.redirect_uri)
+                .append_pair("scope", &provider.scopes.join(" "))
+                .append_pair("state", &flow.state)
+                .append_pair("code_challenge", &flow.code_challenge)
+                .append_pair("code_challenge_method", "S256");
+            if let Some(nonce) = &flow.nonce {
+                query.append_pair("nonce", nonce);
+            }
+        }
+        Ok(url.into())
+    }
+
+    /// Exchange the code of a consumed flow for the user's details
+    pub async fn exchange(
+        &self,
+        http: &HttpClient,
+        provider: &OidcProvider,
+        flow: &OidcFlow,
+        code: &str,
+    ) -> Result<OidcUserInfo, AppError> {
+        let request = http
+            .request(reqwest::Method::POST, &self.token)
+            .header(reqwest::header::ACCEPT, "application/json")
+            .form(&[
+                ("grant_type", "authorization_code"),
+                ("code", code),
+                ("redirect_uri", provider.redirect_uri.as_str()),
+                ("client_id", provider.client_id.as_str()),
+                ("client_secret", provider.client_secret.as_str()),
+                ("code_verifier", flow.code_verifier.as_str()),
+            ])
+            .build()
+            .map_err(exchange_failed)?;
+        let response = http.send("oidc", request).await.map_err(exchange_failed)?;
+        if !response.status().is_success() {
+            return Err(exchange_failed(format!("token endpoint returned {}", response.status())));
+        }
+        let tokens: TokenResponse = response.json().await.map_err(exchange_failed)?;
+
+        if let Some(nonce) = &flow.nonce {
+            let id_token = tokens.id_token.as_deref().ok_or(OidcFailure::InvalidIdToken)?;
+            verify_id_token(id_token, &self.issuer, &provider.client_id, nonce)?;
+        }
+
+        if self.github {
+            self.github_user(http, &tokens.access_token).await
+        } else {
+            let user: StandardUserInfo = self.get(http, &self.userinfo, &tokens.access_token).await?;
+            Ok(OidcUserInfo {
+                sub: user.sub,
+                email: user.email.ok_or(OidcFailure::NoEmail)?,
+                email_verified: user.email_verified,
+                name: user.name,
+                given_name: user.given_name,
+                family_name: user.family_name,
+                picture: user.picture,
+                locale: user.locale,
+                preferred_username: user.preferred_username,
+            })
+        }
+    }
+
+    /// A GitHub account with its primary email
+    async fn github_user(&self, http: &HttpClient, access_token: &str) -> Result<OidcUserInfo, AppError> {
+        let user: GitHubUser = self.get(http, &self.userinfo, access_token).await?;
+        let emails: Vec<GitHubEmail> = self.get(http, &format!("{}/emails", self.userinfo), access_token).await?;
+        let email = emails.into_iter().find(|email| email.primary).ok_or(OidcFailure::NoEmail)?;
+
+        Ok(OidcUserInfo {
+            sub: user.id.to_string(),
+            email: email.email,
+            email_verified: email.verified,
+            name: user.name,
+            given_name: None,
+            family_name: None,
+            picture: user.avatar_url,
+            locale: None,
+            preferred_username: Some(user.login),
+        })
+    }
+
+    async fn get<T: serde::de::DeserializeOwned>(&self, http: &HttpClient, url: &str, access_token: &str) -> Result<T, AppError> {
+        let request = http
+            .request(reqwest::Method::GET, url)
+            .bearer_auth(access_token)
+            .header(reqwest::header::ACCEPT, "application/json")
+            .build()
+            .map_err(exchange_failed)?;
+        let response = http.send("oidc", request).await.map_err(exchange_failed)?;
+        if !response.status().is_success() {
+            return Err(exchange_failed(format!("{} returned {}", url, response.status())));
+        }
+        response.json().await.map_err(exchange_failed)
+    }
+}
+
+/// Check that an ID token is meant for this client and carries the flow's nonce
+///
+/// The token comes straight from the token endpoint over TLS in exchange for
+/// the client secret, so its signature is not checked (OIDC Core 3.1.3.7).
+pub fn verify_id_token(id_token: &str, issuer: &str, client_id: &str, nonce: &str) -> Result<(), AppError> {
+    let mut validation = jsonwebtoken::Validation::default();
+    validation.insecure_disable_signature_validation();
+    validation.set_issuer(&[issuer]);
+    validation.set_audience(&[client_id]);
+
+    let claims = jsonwebtoken::decode::<IdTokenClaims>(id_token, &jsonwebtoken::DecodingKey::from_secret(&[]), &validation)
+        .map_err(|e| {
+            tracing::warn!("Rejected OIDC ID token: {}", e);
+            OidcFailure::InvalidIdToken
+        })?
+        .claims;
+    if claims.nonce.as_deref() != Some(nonce) {
+        return Err(OidcFailure::NonceMismatch.into());
+    }
+    Ok(())
+}
+
+/// Refused OIDC callbacks by reason
+pub struct OidcMetrics {
+    registry: Registry,
+    failures: IntCounterVec,
+}
+
+impl Default for OidcMetrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl OidcMetrics {
+    pub fn new() -> Self {
+        let failures = IntCounterVec::new(
+            Opts::new("texler_oidc_callback_failures_total", "Refused OIDC callbacks by reason"),
+            &["reason"],
+        )
+        .expect("valid metric");
+        // Every reason is listed from the start, at zero
+        for failure in OidcFailure::ALL {
+            failures.with_label_values(&[failure.reason()]);
+        }
+
+        let registry = Registry::new();
+        registry.register(Box::new(failures.clone())).expect("unique metric");
+        Self { registry, failures }
+    }
+
+    /// Count `error` when it refused a callback
+    pub fn record(&self, error: &AppError) {
+        if let AppError::OidcLogin(failure) = error {
+            self.failures.with_label_values(&[failure.reason()]).inc();
+        }
+    }
+
+    pub fn count(&self, failure: OidcFailure) -> u64 {
+        self.failures.with_label_values(&[failure.reason()]).get()
+    }
+
+    /// Failure counts in the Prometheus text format
+    pub fn metrics_text(&self) -> String {
+        let mut text = String::new();
+        if let Err(e) = TextEncoder::new().encode_utf8(&self.registry.gather(), &mut text) {
+            tracing::warn!("Failed to encode OIDC metrics: {}", e);
+        }
+        text
+    }
+}
+
+/// Removes old OIDC flows
+pub struct OidcFlowCleanupTask;
+
+impl crate::tasks::PeriodicTask for OidcFlowCleanupTask {
+    fn name(&self) -> &'static str {
+        "oidc_flow_cleanup"
+    }
+
+    fn interval(&self) -> Duration {
+        CLEANUP_INTERVAL
+    }
+
+    fn run<'a>(&'a self, state: &'a AppState) -> futures::future::BoxFuture<'a, Result<(), AppError>> {
+        Box::pin(async move {
+            let removed = OidcFlow::clean_up(&state.db_pool).await?;
+            if removed > 0 {
+                tracing::debug!("Removed {} old OIDC flows", removed);
+            }
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TestDb;
+
+    fn failure(result: Result<OidcFlow, AppError>) -> OidcFailure {
+        match result {
+            Err(AppError::OidcLogin(failure)) => failure,
+            other => panic!("expected a refused callback, got {:?}", other.map(|flow| flow.id)),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_flows_are_single_use_and_expire() {
+        let Some(db) = TestDb::start().await else { return };
+        let ttl = Duration::from_secs(600);
+
+        let started = OidcFlow::start(&db.pool, "github", false, Some("/projects"), ttl).await.unwrap();
+        assert_eq!(failure(OidcFlow::consume(&db.pool, &started.state, Some("other")).await), OidcFailure::ProviderMismatch);
+        let flow = OidcFlow::consume(&db.pool, &started.state, Some("github")).await.unwrap();
+        assert_eq!(code_challenge(&flow.code_verifier), started.code_challenge);
+        assert_eq!((flow.nonce, flow.redirect_to.as_deref()), (None, Some("/projects")));
+
+        // A replayed callback is refused, as is one for a state never handed out
+        assert_eq!(failure(OidcFlow::consume(&db.pool, &started.state, None).await), OidcFailure::ConsumedState);
+        assert_eq!(failure(OidcFlow::consume(&db.pool, &random_token(), None).await), OidcFailure::UnknownState);
+
+        let expired = OidcFlow::start(&db.pool, "github", true, None, Duration::ZERO).await.unwrap();
+        assert!(expired.nonce.is_some());
+        assert_eq!(failure(OidcFlow::consume(&db.pool, &expired.state, None).await), OidcFailure::ExpiredState);
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_callbacks_consume_a_flow_once() {
+        let Some(db) = TestDb::start().await else { return };
+        let started = OidcFlow::start(&db.pool, "github", false, None, Duration::from_secs(600)).await.unwrap();
+
+        let callbacks: Vec<_> = (0..2)
+            .map(|_| {
+                let (pool, state) = (db.pool.clone(), started.state.clone());
+                tokio::spawn(async move { OidcFlow::consume(&pool, &state, Some("github")).await })
+            })
+            .collect();
+        let mut outcomes = Vec::new();
+        for callback in callbacks {
+            outcomes.push(callback.await.unwrap());
+        }
+
+        assert_eq!(outcomes.iter().filter(|outcome| outcome.is_ok()).count(), 1);
+        let refused = outcomes.into_iter().find(|outcome| outcome.is_err()).unwrap();
+        assert_eq!(failure(refused), OidcFailure::ConsumedState);
+    }
+
+    #[test]
+    fn test_id_token_must_carry_the_nonce() {
+        let token = |nonce: &str, audience: &str| {
+            let claims = serde_json::json!({
+                "iss": "https://id.example.edu",
+                "aud": audience,
+                "sub": "42",
+                "exp": Utc::now().timestamp() + 300,
+                "nonce": nonce,
+            });
+            jsonwebtoken::encode(
+                &jsonwebtoken::Header::default(),
+                &claims,
+                &jsonwebtoken::EncodingKey::from_secret(b"provider key"),
+            )
+            .unwrap()
+        };
+        let verify = |id_token: &str| verify_id_token(id_token, "https://id.example.edu", "texler", "n0nce");
+
+        assert!(verify(&token("n0nce", "texler")).is_ok());
+        assert!(matches!(verify(&token("other", "texler")), Err(AppError::OidcLogin(OidcFailure::NonceMismatch))));
+        assert!(matches!(verify(&token("n0nce", "someone-else")), Err(AppError::OidcLogin(OidcFailure::InvalidIdToken))));
+    }
+
+    #[test]
+    fn test_redirects_stay_on_the_site() {
+        assert!(validate_redirect("/projects/42").is_ok());
+        for target in ["https://evil.example", "//evil.example", "/\\evil.example", "projects"] {
+            assert!(validate_redirect(target).is_err(), "{}", target);
+        }
+    }
+}
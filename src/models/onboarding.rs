@@ -0,0 +1,253 @@
+//! First-run onboarding state
+//!
+//! Each user has a row of milestones the frontend guides new users through:
+//! creating a project, editing a file, compiling successfully and adding a
+//! collaborator. The code paths that reach a milestone [`record`] it in the
+//! background, so they never wait on it; handlers use [`record_later`],
+//! which keeps it through a database outage. A milestone keeps the time it was
+//! first reached and is never unset. The welcome project of the workspace
+//! bootstrap is remembered too, so the UI can offer to delete it once the
+//! user has real work.
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::FromRow;
+use tokio::task::JoinHandle;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::error::AppError;
+
+/// A step of the first-run experience
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Milestone {
+    ProjectCreated,
+    FileEdited,
+    SuccessfulCompile,
+    CollaboratorAdded,
+}
+
+impl Milestone {
+    fn column(self) -> &'static str {
+        match self {
+            Milestone::ProjectCreated => "first_project_created_at",
+            Milestone::FileEdited => "first_file_edited_at",
+            Milestone::SuccessfulCompile => "first_successful_compile_at",
+            Milestone::CollaboratorAdded => "first_collaborator_added_at",
+        }
+    }
+}
+
+/// Where a user is in the first-run experience
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, FromRow, ToSchema)]
+pub struct Onboarding {
+    pub first_project_created_at: Option<DateTime<Utc>>,
+    pub first_file_edited_at: Option<DateTime<Utc>>,
+    pub first_successful_compile_at: Option<DateTime<Utc>>,
+    pub first_collaborator_added_at: Option<DateTime<Utc>>,
+    /// The welcome project created with the user's workspace still exists
+    pub has_sample_project: bool,
+    pub sample_project_id: Option<Uuid>,
+    /// The user dismissed the first-run guide
+    pub dismissed_at: Option<DateTime<Utc>>,
+    /// Every milestone was reached
+    pub completed: bool,
+}
+
+impl Onboarding {
+    /// Onboarding state of a user, empty until they reach a milestone
+    pub async fn for_user(db: &sqlx::PgPool, user_id: Uuid) -> Result<Self, AppError> {
+        let onboarding = sqlx::query_as::<_, Onboarding>(
+            r#"
+            SELECT o.first_project_created_at, o.first_file_edited_at,
+                   o.first_successful_compile_at, o.first_collaborator_added_at,
+                   p.id IS NOT NULL AS has_sample_project, p.id AS sample_project_id,
+                   o.dismissed_at,
+                   o.first_project_created_at IS NOT NULL AND o.first_file_edited_at IS NOT NULL
+                       AND o.first_successful_compile_at IS NOT NULL
+                       AND o.first_collaborator_added_at IS NOT NULL AS completed
+            FROM user_onboarding o
+            LEFT JOIN projects p ON p.id = o.sample_project_id AND p.purging_at IS NULL
+            WHERE o.user_id = $1
+            "#
+        )
+        .bind(user_id)
+        .fetch_optional(db)
+        .await
+        .map_err(AppError::Database)?;
+
+        Ok(onboarding.unwrap_or_default())
+    }
+
+    /// Hide the first-run guide for good
+    pub async fn dismiss(db: &sqlx::PgPool, user_id: Uuid) -> Result<Self, AppError> {
+        sqlx::query(
+            r#"
+            INSERT INTO user_onboarding (user_id, dismissed_at) VALUES ($1, NOW())
+            ON CONFLICT (user_id) DO UPDATE
+            SET dismissed_at = NOW(), updated_at = NOW()
+            WHERE user_onboarding.dismissed_at IS NULL
+            "#
+        )
+        .bind(user_id)
+        .execute(db)
+        .await
+        .map_err(AppError::Database)?;
+
+        Self::for_user(db, user_id).await
+    }
+
+    /// Remember the welcome project created for a user, in the transaction
+    /// that creates it
+    pub async fn mark_sample_project(
+        conn: &mut sqlx::PgConnection,
+        user_id: Uuid,
+        project_id: Uuid,
+    ) -> Result<(), AppError> {
+        sqlx::query(
+            r#"
+            INSERT INTO user_onboarding (user_id, sample_project_id) VALUES ($1, $2)
+            ON CONFLICT (user_id) DO UPDATE
+            SET sample_project_id = EXCLUDED.sample_project_id, updated_at = NOW()
+            "#
+        )
+        .bind(user_id)
+        .bind(project_id)
+        .execute(conn)
+        .await
+        .map_err(AppError::Database)?;
+
+        Ok(())
+    }
+}
+
+/// Record that `user_id` reached `milestone`, unless they already had
+async fn reach(db: &sqlx::PgPool, user_id: Uuid, milestone: Milestone) -> Result<(), AppError> {
+    let column = milestone.column();
+    sqlx::query(&format!(
+        r#"
+        INSERT INTO user_onboarding (user_id, {column}) VALUES ($1, NOW())
+        ON CONFLICT (user_id) DO UPDATE
+        SET {column} = NOW(), updated_at = NOW()
+        WHERE user_onboarding.{column} IS NULL
+        "#
+    ))
+    .bind(user_id)
+    .execute(db)
+    .await
+    .map_err(AppError::Database)?;
+
+    Ok(())
+}
+
+/// Record a milestone in the background; the caller does not wait for it and
+/// a failure is only logged
+pub fn record(db: &sqlx::PgPool, user_id: Uuid, milestone: Milestone) -> JoinHandle<()> {
+    let db = db.clone();
+    crate::correlation::spawn(async move {
+        if let Err(e) = reach(&db, user_id, milestone).await {
+            tracing::warn!("Failed to record onboarding milestone {:?} of user {}: {}", milestone, user_id, e);
+        }
+    })
+}
+
+/// Record a milestone through the buffer of best-effort writes, so it is
+/// not lost while the database is unreachable; for callers holding the
+/// application state
+pub fn record_later(
+    resilience: &std::sync::Arc<crate::db_resilience::DbResilience>,
+    user_id: Uuid,
+    milestone: Milestone,
+) -> JoinHandle<()> {
+    resilience.write("onboarding", move |db| async move { reach(&db, user_id, milestone).await })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::workspace::Workspace;
+    use crate::testing::{create_test_file, create_test_project, create_test_user, TestDb};
+    use std::time::Duration;
+
+    /// Wait for a background record to land
+    async fn until(db: &sqlx::PgPool, user_id: Uuid, reached: impl Fn(&Onboarding) -> bool) -> Onboarding {
+        let started = std::time::Instant::now();
+        loop {
+            let onboarding = Onboarding::for_user(db, user_id).await.unwrap();
+            if reached(&onboarding) {
+                return onboarding;
+            }
+            assert!(started.elapsed() < Duration::from_secs(5), "milestone never recorded: {:?}", onboarding);
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_milestones_are_append_only() {
+        let Some(db) = TestDb::start().await else { return };
+        let user = create_test_user(&db.pool).await;
+        assert_eq!(Onboarding::for_user(&db.pool, user.id).await.unwrap(), Onboarding::default());
+
+        record(&db.pool, user.id, Milestone::ProjectCreated).await.unwrap();
+        let first = Onboarding::for_user(&db.pool, user.id).await.unwrap();
+        assert!(first.first_project_created_at.is_some());
+        assert!(first.first_file_edited_at.is_none() && !first.completed);
+
+        record(&db.pool, user.id, Milestone::ProjectCreated).await.unwrap();
+        let again = Onboarding::for_user(&db.pool, user.id).await.unwrap();
+        assert_eq!(again.first_project_created_at, first.first_project_created_at);
+
+        for milestone in [Milestone::FileEdited, Milestone::SuccessfulCompile, Milestone::CollaboratorAdded] {
+            record(&db.pool, user.id, milestone).await.unwrap();
+        }
+        let dismissed = Onboarding::dismiss(&db.pool, user.id).await.unwrap();
+        assert!(dismissed.completed);
+        assert!(dismissed.dismissed_at.is_some());
+        assert_eq!(Onboarding::dismiss(&db.pool, user.id).await.unwrap().dismissed_at, dismissed.dismissed_at);
+    }
+
+    #[tokio::test]
+    async fn test_edits_do_not_wait_for_the_milestone() {
+        let Some(db) = TestDb::start().await else { return };
+        let user = create_test_user(&db.pool).await;
+        let project = create_test_project(&db.pool, &user, false).await;
+        let file = create_test_file(&db.pool, &project, &user).await;
+
+        // The milestone's upsert blocks on this row until the transaction ends
+        let mut blocker = db.pool.begin().await.unwrap();
+        sqlx::query("INSERT INTO user_onboarding (user_id) VALUES ($1)")
+            .bind(user.id)
+            .execute(&mut *blocker)
+            .await
+            .unwrap();
+
+        let edit = file.update_content(&db.pool, "", "Edited".to_string(), user.id);
+        tokio::time::timeout(Duration::from_secs(5), edit)
+            .await
+            .expect("the edit waited for its onboarding milestone")
+            .unwrap();
+        assert!(Onboarding::for_user(&db.pool, user.id).await.unwrap().first_file_edited_at.is_none());
+
+        blocker.rollback().await.unwrap();
+        until(&db.pool, user.id, |onboarding| onboarding.first_file_edited_at.is_some()).await;
+    }
+
+    #[tokio::test]
+    async fn test_sample_project_is_flagged_while_it_exists() {
+        let Some(db) = TestDb::start().await else { return };
+        let user = create_test_user(&db.pool).await;
+        let workspace = Workspace::ensure_default(&db.pool, user.id).await.unwrap();
+
+        let project = Workspace::seed_welcome_project(&db.pool, user.id, workspace.id).await.unwrap();
+        let onboarding = Onboarding::for_user(&db.pool, user.id).await.unwrap();
+        assert!(onboarding.has_sample_project);
+        assert_eq!(onboarding.sample_project_id, Some(project.id));
+        // The welcome project is not one the user created
+        assert!(onboarding.first_project_created_at.is_none());
+
+        project.delete(&db.pool, user.id).await.unwrap();
+        let onboarding = Onboarding::for_user(&db.pool, user.id).await.unwrap();
+        assert!(!onboarding.has_sample_project);
+        assert_eq!(onboarding.sample_project_id, None);
+    }
+}
@@ -0,0 +1,324 @@
+//! Document outline of LaTeX files
+//!
+//! The outline is the part of a file's `latex_metadata` the editor shows as
+//! its structure: sections, figures, tables and numbered equations. Saving a
+//! file over REST extracts it again; `project_outline` serves the stored
+//! outlines of a whole project for the editor's initial load.
+//!
+//! Collaboration operations change a file's text without saving it, so the
+//! WebSocket server hands every change to an [`OutlineTracker`]. It keeps a
+//! live copy of each edited file, seeded from the stored content, and once
+//! the edits pause for `OUTLINE_DEBOUNCE` it applies them, extracts the
+//! outline, stores it and publishes an [`OutlineUpdate`]. Recording a change
+//! only queues it; loading, extraction and the database write run in one
+//! task per file, which also coalesces everything that arrived meanwhile.
+//! When the stored file changed since the copy was loaded, a save replaced
+//! the text and the copy is loaded again.
+
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::sync::{broadcast, Notify};
+use tokio::time::{Duration, Instant};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use super::file::{extract_latex_metadata, EquationInfo, FigureInfo, FileMetadata, SectionInfo, TableInfo};
+use super::undo::Change;
+use super::ContentType;
+use crate::error::AppError;
+
+/// Quiet time after the last change of a file before its outline is extracted
+pub const OUTLINE_DEBOUNCE: Duration = Duration::from_millis(500);
+
+/// Longest the outline of a file lags behind while edits go on
+pub const OUTLINE_MAX_DELAY: Duration = Duration::from_secs(5);
+
+/// How long the live copy of a file is kept after its last change
+pub const LIVE_FILE_IDLE: Duration = Duration::from_secs(600);
+
+/// Structure of a LaTeX file
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct Outline {
+    pub sections: Vec<SectionInfo>,
+    pub figures: Vec<FigureInfo>,
+    pub tables: Vec<TableInfo>,
+    pub equations: Vec<EquationInfo>,
+}
+
+impl From<FileMetadata> for Outline {
+    fn from(metadata: FileMetadata) -> Self {
+        Self {
+            sections: metadata.sections,
+            figures: metadata.figures,
+            tables: metadata.tables,
+            equations: metadata.equations,
+        }
+    }
+}
+
+/// Outline of one file of a project
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct FileOutline {
+    pub file_id: Uuid,
+    pub path: String,
+    #[serde(flatten)]
+    pub outline: Outline,
+}
+
+#[derive(FromRow)]
+struct StoredOutline {
+    id: Uuid,
+    path: String,
+    latex_metadata: Option<serde_json::Value>,
+}
+
+/// Stored outlines of a project's LaTeX files, main file first
+pub async fn project_outline(db: &sqlx::PgPool, project_id: Uuid) -> Result<Vec<FileOutline>, AppError> {
+    let files = sqlx::query_as::<_, StoredOutline>(
+        r#"
+        SELECT id, path, latex_metadata FROM files
+        WHERE project_id = $1 AND content_type = 'latex' AND is_deleted = false
+        ORDER BY is_main DESC, path
+        "#
+    )
+    .bind(project_id)
+    .fetch_all(db)
+    .await
+    .map_err(AppError::Database)?;
+
+    Ok(files
+        .into_iter()
+        .map(|file| FileOutline {
+            file_id: file.id,
+            path: file.path,
+            // Files stored before figures and equations were extracted lack them
+            outline: file
+                .latex_metadata
+                .and_then(|metadata| serde_json::from_value::<FileMetadata>(metadata).ok())
+                .map(Outline::from)
+                .unwrap_or_default(),
+        })
+        .collect())
+}
+
+/// A changed outline of a file edited in a collaboration session
+#[derive(Debug, Clone)]
+pub struct OutlineUpdate {
+    pub session_id: Uuid,
+    pub project_id: Uuid,
+    pub file_id: Uuid,
+    pub outline: Outline,
+}
+
+/// Changes of a file waiting for its task
+struct PendingChanges {
+    session_id: Uuid,
+    changes: Vec<Change>,
+    wake: Arc<Notify>,
+}
+
+/// Text of a file as collaboration operations left it
+struct LiveFile {
+    project_id: Uuid,
+    /// Hash of the stored content the text was loaded from
+    content_hash: Option<String>,
+    text: String,
+    outline: Option<Outline>,
+}
+
+#[derive(FromRow)]
+struct StoredFile {
+    project_id: Uuid,
+    content_type: ContentType,
+    content_hash: Option<String>,
+    /// Only loaded when the hash differs from the live copy's
+    content: Option<String>,
+}
+
+/// Keeps the outlines of files edited in collaboration sessions current
+pub struct OutlineTracker {
+    db: Arc<sqlx::PgPool>,
+    debounce: Duration,
+    pending: Mutex<HashMap<Uuid, PendingChanges>>,
+    updates: broadcast::Sender<OutlineUpdate>,
+}
+
+impl std::fmt::Debug for OutlineTracker {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("OutlineTracker").field("debounce", &self.debounce).finish_non_exhaustive()
+    }
+}
+
+impl OutlineTracker {
+    pub fn new(db: Arc<sqlx::PgPool>, debounce: Duration) -> Self {
+        Self {
+            db,
+            debounce,
+            pending: Mutex::new(HashMap::new()),
+            updates: broadcast::channel(1000).0,
+        }
+    }
+
+    /// Receive the outlines changed from now on
+    pub fn subscribe(&self) -> broadcast::Receiver<OutlineUpdate> {
+        self.updates.subscribe()
+    }
+
+    /// Queue a change made in a session to one of its project's files
+    pub fn record(self: &Arc<Self>, session_id: Uuid, change: Change) {
+        let Some(file_id) = change.file_id else { return };
+
+        let mut pending = self.pending.lock().unwrap_or_else(|e| e.into_inner());
+        let entry = pending.entry(file_id).or_insert_with(|| {
+            let wake = Arc::new(Notify::new());
+            tokio::spawn(self.clone().track(file_id, wake.clone()));
+            PendingChanges { session_id, changes: Vec::new(), wake }
+        });
+        entry.session_id = session_id;
+        entry.changes.push(change);
+        entry.wake.notify_one();
+    }
+
+    /// The task of one file, running until the file was idle for `LIVE_FILE_IDLE`
+    async fn track(self: Arc<Self>, file_id: Uuid, wake: Arc<Notify>) {
+        let mut live: Option<LiveFile> = None;
+        loop {
+            if tokio::time::timeout(LIVE_FILE_IDLE, wake.notified()).await.is_err() {
+                let mut pending = self.pending.lock().unwrap_or_else(|e| e.into_inner());
+                if pending.get(&file_id).map_or(true, |entry| entry.changes.is_empty()) {
+                    pending.remove(&file_id);
+                    return;
+                }
+            }
+
+            // Wait for the edits to pause, but not for ever while they go on
+            let started = Instant::now();
+            while started.elapsed() < OUTLINE_MAX_DELAY
+                && tokio::time::timeout(self.debounce, wake.notified()).await.is_ok()
+            {}
+
+            let (session_id, changes) = {
+                let mut pending = self.pending.lock().unwrap_or_else(|e| e.into_inner());
+                match pending.get_mut(&file_id) {
+                    Some(entry) => (entry.session_id, std::mem::take(&mut entry.changes)),
+                    None => return,
+                }
+            };
+            if changes.is_empty() {
+                continue;
+            }
+
+            match self.refresh(&mut live, session_id, file_id, &changes).await {
+                Ok(Some(update)) => {
+                    // Nobody listening is fine
+                    let _ = self.updates.send(update);
+                }
+                Ok(None) => {}
+                Err(e) => {
+                    tracing::warn!("Failed to refresh the outline of file {}: {}", file_id, e);
+                    live = None;
+                }
+            }
+        }
+    }
+
+    /// Apply changes to the live copy; the update when its outline changed
+    async fn refresh(
+        &self,
+        live: &mut Option<LiveFile>,
+        session_id: Uuid,
+        file_id: Uuid,
+        changes: &[Change],
+    ) -> Result<Option<OutlineUpdate>, AppError> {
+        let loaded_hash = live.as_ref().and_then(|file| file.content_hash.clone());
+        let stored = sqlx::query_as::<_, StoredFile>(
+            r#"
+            SELECT f.project_id, f.content_type, f.content_hash,
+                CASE WHEN $3::boolean OR f.content_hash IS DISTINCT FROM $4 THEN f.content END AS content
+            FROM files f
+            JOIN collaboration_sessions s ON s.project_id = f.project_id
+            WHERE s.id = $1 AND f.id = $2 AND f.is_deleted = false
+            "#
+        )
+        .bind(session_id)
+        .bind(file_id)
+        .bind(live.is_none())
+        .bind(&loaded_hash)
+        .fetch_optional(&*self.db)
+        .await
+        .map_err(AppError::Database)?;
+
+        // Gone, or not a file of the session's project
+        let Some(stored) = stored else {
+            *live = None;
+            return Ok(None);
+        };
+        if stored.content_type != ContentType::Latex {
+            return Ok(None);
+        }
+
+        let mut changes = changes;
+        if let Some(content) = stored.content {
+            // A save since the copy was loaded already holds the changes its client saw
+            if live.is_some() {
+                changes = &[];
+            }
+            *live = Some(LiveFile {
+                project_id: stored.project_id,
+                content_hash: stored.content_hash,
+                text: content,
+                outline: None,
+            });
+        }
+        let Some(file) = live.as_mut() else { return Ok(None) };
+        for change in changes {
+            apply(&mut file.text, change);
+        }
+
+        let Some(metadata) = extract_latex_metadata(&file.text, ContentType::Latex) else { return Ok(None) };
+        let outline = Outline::from(metadata.clone());
+        if file.outline.as_ref() == Some(&outline) {
+            return Ok(None);
+        }
+
+        sqlx::query("UPDATE files SET latex_metadata = $2 WHERE id = $1")
+            .bind(file_id)
+            .bind(serde_json::to_value(metadata)?)
+            .execute(&*self.db)
+            .await
+            .map_err(AppError::Database)?;
+        file.outline = Some(outline.clone());
+
+        Ok(Some(OutlineUpdate { session_id, project_id: file.project_id, file_id, outline }))
+    }
+}
+
+/// Apply a change to a text, clamping it to the text's length
+fn apply(text: &mut String, change: &Change) {
+    let byte_index = |text: &str, chars: usize| text.char_indices().nth(chars).map_or(text.len(), |(index, _)| index);
+    let start = byte_index(text, change.position);
+    let end = start + byte_index(&text[start..], change.removed);
+    text.replace_range(start..end, &change.inserted);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn change(position: usize, removed: usize, inserted: &str) -> Change {
+        Change { file_id: None, position, removed, removed_text: None, inserted: inserted.to_string() }
+    }
+
+    #[test]
+    fn test_apply_counts_characters() {
+        let mut text = "Größe\n".to_string();
+        apply(&mut text, &change(2, 2, "ss"));
+        assert_eq!(text, "Grsse\n");
+        apply(&mut text, &change(0, 0, "\\section{Größe}\n"));
+        assert_eq!(text, "\\section{Größe}\nGrsse\n");
+        apply(&mut text, &change(100, 5, "!"));
+        assert_eq!(text, "\\section{Größe}\nGrsse\n!");
+    }
+}
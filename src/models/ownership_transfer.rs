@@ -0,0 +1,523 @@
+//! Transfers of project ownership
+//!
+//! The owner offers the project to one of its collaborators, who gets a token
+//! and has `TRANSFER_EXPIRATION_DAYS` to accept with it; until then either
+//! side can cancel, and a project has one open offer at most. Accepting makes
+//! the collaborator the owner and the previous owner a maintainer in a single
+//! transaction, and moves the project into the new owner's first workspace,
+//! since workspaces have no members besides their owner.
+//!
+//! Ownership is `projects.owner_id` and nothing else: collaborator rows never
+//! carry the owner role, so a project has exactly one owner at any time. The
+//! owner cannot leave the project or be removed from it; they have to hand it
+//! over first.
+
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::FromRow;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use super::audit::AuditEvent;
+use super::auth::PasswordUtils;
+use super::project::ProjectActivity;
+use super::settings_history::ProjectSettingsChange;
+use super::workspace::Workspace;
+use super::{Entity, UserRole};
+use crate::error::AppError;
+
+/// How long an offer can be accepted
+pub const TRANSFER_EXPIRATION_DAYS: i64 = 7;
+
+/// Offer of a project's ownership; the token itself is never stored
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, ToSchema)]
+pub struct OwnershipTransfer {
+    pub id: Uuid,
+    pub project_id: Uuid,
+    /// Owner at the time of the offer
+    pub from_user_id: Uuid,
+    pub to_user_id: Uuid,
+    pub expires_at: DateTime<Utc>,
+    pub accepted_at: Option<DateTime<Utc>>,
+    pub cancelled_at: Option<DateTime<Utc>>,
+    pub cancelled_by: Option<Uuid>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl Entity for OwnershipTransfer {
+    fn id(&self) -> Uuid {
+        self.id
+    }
+
+    fn created_at(&self) -> DateTime<Utc> {
+        self.created_at
+    }
+
+    fn updated_at(&self) -> DateTime<Utc> {
+        self.accepted_at.or(self.cancelled_at).unwrap_or(self.created_at)
+    }
+}
+
+/// Ownership transfer request
+#[derive(Debug, Clone, Deserialize, ToSchema)]
+pub struct TransferOwnership {
+    /// Collaborator to become the owner
+    pub user_id: Uuid,
+}
+
+/// Acceptance of an ownership transfer
+#[derive(Debug, Clone, Deserialize, ToSchema)]
+pub struct AcceptOwnershipTransfer {
+    /// Token sent to the new owner with the offer
+    pub token: String,
+}
+
+fn token_hash(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+/// Open offers: neither accepted, cancelled nor expired
+const OPEN: &str = "accepted_at IS NULL AND cancelled_at IS NULL AND expires_at > NOW()";
+
+impl OwnershipTransfer {
+    /// Whether the offer can still be accepted
+    pub fn is_open(&self) -> bool {
+        self.accepted_at.is_none() && self.cancelled_at.is_none() && Utc::now() < self.expires_at
+    }
+
+    /// Offer the project to `to_user_id`, returning the offer and its token
+    ///
+    /// `from_user_id` has to be the owner and `to_user_id` a collaborator.
+    pub async fn offer(
+        db: &sqlx::PgPool,
+        project_id: Uuid,
+        from_user_id: Uuid,
+        to_user_id: Uuid,
+    ) -> Result<(Self, String), AppError> {
+        if from_user_id == to_user_id {
+            return Err(AppError::Validation("You already own this project".to_string()));
+        }
+
+        let mut tx = db.begin().await.map_err(AppError::Database)?;
+
+        // Serializes offers with each other and with acceptances
+        let owner = sqlx::query_scalar::<_, Uuid>(
+            "SELECT owner_id FROM projects WHERE id = $1 AND purging_at IS NULL FOR UPDATE"
+        )
+        .bind(project_id)
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(AppError::Database)?;
+        if owner != Some(from_user_id) {
+            return Err(AppError::Authorization(
+                "Only the project owner can transfer ownership".to_string(),
+            ));
+        }
+
+        let is_collaborator = sqlx::query_scalar::<_, i64>(
+            "SELECT COUNT(*) FROM project_collaborators WHERE project_id = $1 AND user_id = $2"
+        )
+        .bind(project_id)
+        .bind(to_user_id)
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(AppError::Database)?
+            > 0;
+        if !is_collaborator {
+            return Err(AppError::Validation(
+                "Ownership can only be transferred to a collaborator of the project".to_string(),
+            ));
+        }
+
+        let open = sqlx::query_scalar::<_, i64>(&format!(
+            "SELECT COUNT(*) FROM project_ownership_transfers WHERE project_id = $1 AND {}",
+            OPEN
+        ))
+        .bind(project_id)
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(AppError::Database)?;
+        if open > 0 {
+            return Err(AppError::Conflict(
+                "An ownership transfer is already pending; cancel it first".to_string(),
+            ));
+        }
+
+        let token = PasswordUtils::generate_invitation_token();
+        let transfer = sqlx::query_as::<_, OwnershipTransfer>(
+            r#"
+            INSERT INTO project_ownership_transfers (project_id, from_user_id, to_user_id, token_hash, expires_at)
+            VALUES ($1, $2, $3, $4, $5)
+            RETURNING *
+            "#
+        )
+        .bind(project_id)
+        .bind(from_user_id)
+        .bind(to_user_id)
+        .bind(token_hash(&token))
+        .bind(Utc::now() + Duration::days(TRANSFER_EXPIRATION_DAYS))
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(AppError::Database)?;
+
+        AuditEvent::record(
+            &mut *tx,
+            Some(from_user_id),
+            "ownership_transfer_offered",
+            "project",
+            Some(project_id),
+            Some(project_id),
+            serde_json::json!({
+                "transfer_id": transfer.id,
+                "to_user_id": to_user_id,
+                "expires_at": transfer.expires_at,
+            }),
+        )
+        .await?;
+
+        tx.commit().await.map_err(AppError::Database)?;
+
+        ProjectActivity::log(
+            db,
+            project_id,
+            from_user_id,
+            "ownership_transfer_offered",
+            "user",
+            Some(to_user_id),
+            None,
+        )
+        .await?;
+
+        Ok((transfer, token))
+    }
+
+    /// The open offer of a project, if any
+    pub async fn pending(db: &sqlx::PgPool, project_id: Uuid) -> Result<Option<Self>, AppError> {
+        sqlx::query_as::<_, OwnershipTransfer>(&format!(
+            "SELECT * FROM project_ownership_transfers WHERE project_id = $1 AND {}",
+            OPEN
+        ))
+        .bind(project_id)
+        .fetch_optional(db)
+        .await
+        .map_err(AppError::Database)
+    }
+
+    /// Cancel the open offer of a project, by either of its two sides
+    pub async fn cancel(db: &sqlx::PgPool, project_id: Uuid, user_id: Uuid) -> Result<Self, AppError> {
+        let mut tx = db.begin().await.map_err(AppError::Database)?;
+
+        let transfer = sqlx::query_as::<_, OwnershipTransfer>(&format!(
+            r#"
+            UPDATE project_ownership_transfers
+            SET cancelled_at = NOW(), cancelled_by = $2
+            WHERE project_id = $1 AND (from_user_id = $2 OR to_user_id = $2) AND {}
+            RETURNING *
+            "#,
+            OPEN
+        ))
+        .bind(project_id)
+        .bind(user_id)
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(AppError::Database)?
+        .ok_or_else(|| AppError::NotFound {
+            entity: "Ownership transfer".to_string(),
+            id: project_id.to_string(),
+        })?;
+
+        AuditEvent::record(
+            &mut *tx,
+            Some(user_id),
+            "ownership_transfer_cancelled",
+            "project",
+            Some(project_id),
+            Some(project_id),
+            serde_json::json!({ "transfer_id": transfer.id, "to_user_id": transfer.to_user_id }),
+        )
+        .await?;
+
+        tx.commit().await.map_err(AppError::Database)?;
+
+        ProjectActivity::log(
+            db,
+            project_id,
+            user_id,
+            "ownership_transfer_cancelled",
+            "user",
+            Some(transfer.to_user_id),
+            None,
+        )
+        .await?;
+
+        Ok(transfer)
+    }
+
+    /// Accept an offer as `user_id`, making them the owner
+    ///
+    /// Of two acceptances of the same offer one waits for the other and then
+    /// finds it accepted.
+    pub async fn accept(
+        db: &sqlx::PgPool,
+        project_id: Uuid,
+        user_id: Uuid,
+        token: &str,
+    ) -> Result<Self, AppError> {
+        let not_found = || AppError::NotFound {
+            entity: "Ownership transfer".to_string(),
+            id: project_id.to_string(),
+        };
+        let workspace = Workspace::ensure_default(db, user_id).await?;
+
+        let mut tx = db.begin().await.map_err(AppError::Database)?;
+
+        let transfer = sqlx::query_as::<_, OwnershipTransfer>(
+            r#"
+            SELECT * FROM project_ownership_transfers
+            WHERE project_id = $1 AND token_hash = $2
+            FOR UPDATE
+            "#
+        )
+        .bind(project_id)
+        .bind(token_hash(token))
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(AppError::Database)?
+        .filter(|transfer| transfer.to_user_id == user_id)
+        .ok_or_else(not_found)?;
+
+        if transfer.accepted_at.is_some() {
+            return Err(AppError::Conflict("The ownership transfer was already accepted".to_string()));
+        }
+        if transfer.cancelled_at.is_some() {
+            return Err(AppError::Conflict("The ownership transfer was cancelled".to_string()));
+        }
+        if transfer.expires_at <= Utc::now() {
+            return Err(AppError::Validation("The ownership transfer has expired".to_string()));
+        }
+
+        let owner = sqlx::query_scalar::<_, Uuid>(
+            "SELECT owner_id FROM projects WHERE id = $1 AND purging_at IS NULL FOR UPDATE"
+        )
+        .bind(project_id)
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(AppError::Database)?
+        .ok_or_else(not_found)?;
+        if owner != transfer.from_user_id {
+            return Err(AppError::Conflict("The project changed owner since the offer".to_string()));
+        }
+
+        let role = sqlx::query_scalar::<_, UserRole>(
+            "DELETE FROM project_collaborators WHERE project_id = $1 AND user_id = $2 RETURNING role"
+        )
+        .bind(project_id)
+        .bind(user_id)
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(AppError::Database)?
+        .ok_or_else(|| AppError::Validation("You are no longer a collaborator of the project".to_string()))?;
+
+        sqlx::query("UPDATE projects SET owner_id = $2, workspace_id = $3, updated_at = NOW() WHERE id = $1")
+            .bind(project_id)
+            .bind(user_id)
+            .bind(workspace.id)
+            .execute(&mut *tx)
+            .await
+            .map_err(AppError::Database)?;
+
+        sqlx::query(
+            r#"
+            INSERT INTO project_collaborators (project_id, user_id, role, invited_by)
+            VALUES ($1, $2, $3, $4)
+            ON CONFLICT (project_id, user_id) DO UPDATE SET role = EXCLUDED.role
+            "#
+        )
+        .bind(project_id)
+        .bind(owner)
+        .bind(UserRole::Maintainer)
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await
+        .map_err(AppError::Database)?;
+
+        ProjectSettingsChange::record_role_change(&mut tx, project_id, user_id, Some(role), Some(UserRole::Owner), user_id)
+            .await?;
+        ProjectSettingsChange::record_role_change(
+            &mut tx,
+            project_id,
+            owner,
+            Some(UserRole::Owner),
+            Some(UserRole::Maintainer),
+            user_id,
+        )
+        .await?;
+
+        let transfer = sqlx::query_as::<_, OwnershipTransfer>(
+            "UPDATE project_ownership_transfers SET accepted_at = NOW() WHERE id = $1 RETURNING *"
+        )
+        .bind(transfer.id)
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(AppError::Database)?;
+
+        AuditEvent::record(
+            &mut *tx,
+            Some(user_id),
+            "project_ownership_transferred",
+            "project",
+            Some(project_id),
+            Some(project_id),
+            serde_json::json!({
+                "transfer_id": transfer.id,
+                "from_user_id": owner,
+                "to_user_id": user_id,
+            }),
+        )
+        .await?;
+
+        tx.commit().await.map_err(AppError::Database)?;
+
+        ProjectActivity::log(db, project_id, user_id, "ownership_transferred", "user", Some(owner), None).await?;
+
+        Ok(transfer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::project::Project;
+    use crate::testing::{add_collaborator, create_test_project, create_test_user, TestDb};
+
+    /// Owners of a project: the owner column plus any collaborator row claiming it
+    async fn owners(db: &sqlx::PgPool, project_id: Uuid) -> Vec<Uuid> {
+        sqlx::query_scalar::<_, Uuid>(
+            r#"
+            SELECT owner_id FROM projects WHERE id = $1
+            UNION ALL
+            SELECT user_id FROM project_collaborators WHERE project_id = $1 AND role = 'owner'
+            "#
+        )
+        .bind(project_id)
+        .fetch_all(db)
+        .await
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_accepting_swaps_owner_and_maintainer() {
+        let Some(db) = TestDb::start().await else { return };
+        let owner = create_test_user(&db.pool).await;
+        let heir = create_test_user(&db.pool).await;
+        let project = create_test_project(&db.pool, &owner, false).await;
+        add_collaborator(&db.pool, &project, &heir, UserRole::Collaborator).await;
+
+        let stranger = create_test_user(&db.pool).await;
+        assert!(OwnershipTransfer::offer(&db.pool, project.id, owner.id, stranger.id).await.is_err());
+        assert!(OwnershipTransfer::offer(&db.pool, project.id, heir.id, owner.id).await.is_err());
+
+        let (transfer, token) = OwnershipTransfer::offer(&db.pool, project.id, owner.id, heir.id).await.unwrap();
+        assert!(transfer.is_open());
+        assert!(matches!(
+            OwnershipTransfer::offer(&db.pool, project.id, owner.id, heir.id).await,
+            Err(AppError::Conflict(_))
+        ));
+        assert_eq!(owners(&db.pool, project.id).await, vec![owner.id]);
+
+        // Only the recipient can accept, and only with the token
+        assert!(OwnershipTransfer::accept(&db.pool, project.id, owner.id, &token).await.is_err());
+        assert!(OwnershipTransfer::accept(&db.pool, project.id, heir.id, "wrong").await.is_err());
+
+        let accepted = OwnershipTransfer::accept(&db.pool, project.id, heir.id, &token).await.unwrap();
+        assert!(accepted.accepted_at.is_some());
+        assert_eq!(owners(&db.pool, project.id).await, vec![heir.id]);
+        assert_eq!(Project::member_role(&db.pool, project.id, owner.id).await.unwrap(), Some(UserRole::Maintainer));
+        assert_eq!(Project::member_role(&db.pool, project.id, heir.id).await.unwrap(), Some(UserRole::Owner));
+
+        let workspace_owner = sqlx::query_scalar::<_, Uuid>(
+            "SELECT w.owner_id FROM projects p JOIN workspaces w ON w.id = p.workspace_id WHERE p.id = $1"
+        )
+        .bind(project.id)
+        .fetch_one(&db.pool)
+        .await
+        .unwrap();
+        assert_eq!(workspace_owner, heir.id);
+        assert_eq!(AuditEvent::list_by_action(&db.pool, "project_ownership_transferred", 100).await.unwrap()
+            .iter()
+            .filter(|event| event.project_id == Some(project.id))
+            .count(), 1);
+
+        assert!(matches!(
+            OwnershipTransfer::accept(&db.pool, project.id, heir.id, &token).await,
+            Err(AppError::Conflict(_))
+        ));
+        assert!(OwnershipTransfer::pending(&db.pool, project.id).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_expired_and_cancelled_offers_cannot_be_accepted() {
+        let Some(db) = TestDb::start().await else { return };
+        let owner = create_test_user(&db.pool).await;
+        let heir = create_test_user(&db.pool).await;
+        let project = create_test_project(&db.pool, &owner, false).await;
+        add_collaborator(&db.pool, &project, &heir, UserRole::Maintainer).await;
+
+        let (transfer, token) = OwnershipTransfer::offer(&db.pool, project.id, owner.id, heir.id).await.unwrap();
+        sqlx::query("UPDATE project_ownership_transfers SET expires_at = NOW() - INTERVAL '1 second' WHERE id = $1")
+            .bind(transfer.id)
+            .execute(&db.pool)
+            .await
+            .unwrap();
+        assert!(matches!(
+            OwnershipTransfer::accept(&db.pool, project.id, heir.id, &token).await,
+            Err(AppError::Validation(_))
+        ));
+        assert!(OwnershipTransfer::pending(&db.pool, project.id).await.unwrap().is_none());
+        // An expired offer neither blocks a new one nor can be cancelled
+        assert!(OwnershipTransfer::cancel(&db.pool, project.id, owner.id).await.is_err());
+
+        let (_, token) = OwnershipTransfer::offer(&db.pool, project.id, owner.id, heir.id).await.unwrap();
+        let cancelled = OwnershipTransfer::cancel(&db.pool, project.id, heir.id).await.unwrap();
+        assert_eq!(cancelled.cancelled_by, Some(heir.id));
+        assert!(matches!(
+            OwnershipTransfer::accept(&db.pool, project.id, heir.id, &token).await,
+            Err(AppError::Conflict(_))
+        ));
+
+        // Removing the recipient from the project withdraws the offer
+        let (_, token) = OwnershipTransfer::offer(&db.pool, project.id, owner.id, heir.id).await.unwrap();
+        crate::models::project::ProjectCollaborator::remove(&db.pool, project.id, heir.id, owner.id).await.unwrap();
+        assert!(OwnershipTransfer::pending(&db.pool, project.id).await.unwrap().is_none());
+        assert!(OwnershipTransfer::accept(&db.pool, project.id, heir.id, &token).await.is_err());
+        assert_eq!(owners(&db.pool, project.id).await, vec![owner.id]);
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_acceptances_transfer_once() {
+        let Some(db) = TestDb::start().await else { return };
+        let owner = create_test_user(&db.pool).await;
+        let heir = create_test_user(&db.pool).await;
+        let project = create_test_project(&db.pool, &owner, false).await;
+        add_collaborator(&db.pool, &project, &heir, UserRole::Collaborator).await;
+        let (_, token) = OwnershipTransfer::offer(&db.pool, project.id, owner.id, heir.id).await.unwrap();
+
+        let (first, second) = tokio::join!(
+            OwnershipTransfer::accept(&db.pool, project.id, heir.id, &token),
+            OwnershipTransfer::accept(&db.pool, project.id, heir.id, &token),
+        );
+        assert_eq!([first.is_ok(), second.is_ok()].iter().filter(|ok| **ok).count(), 1);
+        assert!(matches!(first.and(second), Err(AppError::Conflict(_))));
+        assert_eq!(owners(&db.pool, project.id).await, vec![heir.id]);
+
+        let roles = sqlx::query_scalar::<_, String>(
+            "SELECT role::text FROM project_collaborators WHERE project_id = $1"
+        )
+        .bind(project.id)
+        .fetch_all(&db.pool)
+        .await
+        .unwrap();
+        assert_eq!(roles, vec!["maintainer".to_string()]);
+    }
+}
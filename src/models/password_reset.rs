@@ -216,6 +216,9 @@ impl PasswordResetService {
         // Mark reset request as used
         reset_request.mark_as_used(db).await?;
 
+        // Proving control of the email also lifts a login lockout
+        crate::models::login_protection::LoginFailure::clear(db, user.id).await?;
+
         Ok(())
     }
 }
\ No newline at end of file
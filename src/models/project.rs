@@ -3,14 +3,27 @@
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
+use utoipa::ToSchema;
 use uuid::Uuid;
 
-use super::{CompilationStatus, Entity, LatexEngine, UserRole};
+use super::{CompilationStatus, Entity, LatexEngine, SortColumn, SortOrder, Sortable, UserRole};
+use super::settings_history::{self, ProjectSettingsChange, SettingsChangeKind};
 use super::workspace::Workspace;
 use super::user::UserProfile;
+use super::project_freeze::ProjectFreeze;
+use super::project_purge::ProjectPurge;
+use super::detail_fields::ProjectFields;
+use super::formatter::FormatSettings;
+use super::compile_images::ImageSettings;
+use super::compile_batch::{validate_targets, CompileTarget};
+use std::collections::HashMap;
+use crate::performance::QueryParam;
+
+/// Formats a project can be compiled to
+pub const OUTPUT_FORMATS: [&str; 3] = ["pdf", "dvi", "ps"];
 
 /// Project model
-#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, ToSchema)]
 pub struct Project {
     pub id: Uuid,
     pub name: String,
@@ -18,6 +31,8 @@ pub struct Project {
     pub owner_id: Uuid,
     pub workspace_id: Uuid,
     pub is_public: bool,
+    /// The latest PDF can be embedded with a token although the project is private
+    pub is_embeddable: bool,
     pub main_file_path: String,
     pub latex_engine: LatexEngine,
     pub output_format: String,
@@ -25,10 +40,45 @@ pub struct Project {
     pub bibliography_path: Option<String>,
     pub last_compilation_at: Option<DateTime<Utc>>,
     pub compilation_status: CompilationStatus,
+    /// Settings from an imported archive that could not be mapped
+    pub imported_settings: Option<serde_json::Value>,
+    /// Markdown file shown as the project's README
+    pub readme_file_id: Option<Uuid>,
+    /// Project this one was cloned from, if it still exists
+    pub cloned_from: Option<Uuid>,
+    /// Repository the project was imported from
+    pub source_url: Option<String>,
+    /// Commit of `source_url` that was imported
+    pub source_commit: Option<String>,
+    #[schema(value_type = ProjectSettings)]
+    pub settings: sqlx::types::Json<ProjectSettings>,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
 }
 
+/// Per-project settings stored as JSON
+///
+/// Missing keys take their defaults, so settings saved by an older version
+/// keep loading.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(default)]
+pub struct ProjectSettings {
+    /// Options for `POST /files/{id}/format`
+    pub formatting: FormatSettings,
+    /// How images are compiled
+    pub images: ImageSettings,
+    /// Files `POST /projects/{id}/compile-all` compiles; the main file when empty
+    pub compile_targets: Vec<CompileTarget>,
+}
+
+impl ProjectSettings {
+    pub fn validate(&self) -> Result<(), crate::error::AppError> {
+        self.formatting.validate()?;
+        self.images.validate()?;
+        validate_targets(&self.compile_targets)
+    }
+}
+
 impl Entity for Project {
     fn id(&self) -> Uuid {
         self.id
@@ -44,9 +94,9 @@ impl Entity for Project {
 }
 
 /// Project creation request
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, ToSchema)]
 pub struct CreateProject {
-    pub name: String,
+    pub name: crate::models::validation::ProjectName,
     pub description: Option<String>,
     pub is_public: Option<bool>,
     pub main_file_path: Option<String>,
@@ -59,29 +109,123 @@ pub struct CreateProject {
 }
 
 /// Project update request
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, ToSchema)]
 pub struct UpdateProject {
-    pub name: Option<String>,
+    pub name: Option<crate::models::validation::ProjectName>,
     pub description: Option<String>,
     pub is_public: Option<bool>,
+    pub is_embeddable: Option<bool>,
     pub main_file_path: Option<String>,
     pub latex_engine: Option<LatexEngine>,
     pub output_format: Option<String>,
     pub custom_args: Option<Vec<String>>,
     pub bibliography_path: Option<String>,
     pub tags: Option<Vec<String>>,
+    /// Must be a file of this project
+    pub readme_file_id: Option<Uuid>,
+    /// Replaces all project settings
+    pub settings: Option<ProjectSettings>,
+}
+
+impl UpdateProject {
+    /// Check the requested values, as they would apply to `project_id`
+    pub async fn validate(
+        &self,
+        conn: &mut sqlx::PgConnection,
+        project_id: Uuid,
+    ) -> Result<(), crate::error::AppError> {
+        if self.main_file_path.as_ref().is_some_and(|path| path.trim().is_empty()) {
+            return Err(crate::error::AppError::Validation("Main file path cannot be empty".to_string()));
+        }
+
+        if let Some(format) = &self.output_format {
+            if !OUTPUT_FORMATS.contains(&format.as_str()) {
+                return Err(crate::error::AppError::Validation(format!(
+                    "Unsupported output format '{}', expected one of {}",
+                    format,
+                    OUTPUT_FORMATS.join(", ")
+                )));
+            }
+        }
+
+        // Arguments are passed to the engine one by one
+        if let Some(arg) = self.custom_args.iter().flatten().find(|arg| {
+            !arg.starts_with('-') || arg.chars().any(|c| c.is_whitespace() || c.is_control())
+        }) {
+            return Err(crate::error::AppError::Validation(format!(
+                "Invalid compiler argument '{}': each argument must be a single option starting with '-'",
+                arg
+            )));
+        }
+
+        if let Some(settings) = &self.settings {
+            settings.validate()?;
+        }
+
+        if let Some(readme_file_id) = self.readme_file_id {
+            let in_project = sqlx::query_scalar::<_, bool>(
+                "SELECT EXISTS(SELECT 1 FROM files WHERE id = $1 AND project_id = $2 AND is_deleted = false)"
+            )
+            .bind(readme_file_id)
+            .bind(project_id)
+            .fetch_one(conn)
+            .await
+            .map_err(crate::error::AppError::Database)?;
+
+            if !in_project {
+                return Err(crate::error::AppError::Validation(
+                    "README must be a file of this project".to_string(),
+                ));
+            }
+        }
+
+        Ok(())
+    }
 }
 
 /// Project with relationships
-#[derive(Debug, Clone, Serialize)]
+///
+/// Sections left out with `?fields=` are missing from the response.
+#[derive(Debug, Clone, Serialize, ToSchema)]
 pub struct ProjectWithDetails {
     #[serde(flatten)]
     pub project: Project,
-    pub owner: UserProfile,
-    pub collaborators: Vec<UserProfile>,
-    pub file_count: i64,
-    pub word_count: i64,
-    pub tag_count: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub owner: Option<UserProfile>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub collaborators: Option<Vec<UserProfile>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file_count: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub word_count: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tag_count: Option<i64>,
+    /// Whether file changes are refused, see `freeze`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub frozen: Option<bool>,
+    /// `null` when the project is not frozen
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub freeze: Option<Option<ProjectFreeze>>,
+    /// Users in the project's sessions right now, approximate; in project
+    /// lists and `GET /projects/{id}`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub active_collaborators_now: Option<u32>,
+}
+
+/// Collaborator of one of several projects
+#[derive(FromRow)]
+struct ProjectCollaboratorProfile {
+    project_id: Uuid,
+    #[sqlx(flatten)]
+    profile: UserProfile,
+}
+
+/// File totals of one of several projects
+#[derive(FromRow)]
+struct ProjectFileTotals {
+    project_id: Uuid,
+    total_files: i64,
+    total_words: i64,
 }
 
 /// Project search response
@@ -95,7 +239,7 @@ pub struct ProjectSearchResult {
 }
 
 /// Project collaborator
-#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, ToSchema)]
 pub struct ProjectCollaborator {
     pub id: Uuid,
     pub project_id: Uuid,
@@ -118,21 +262,27 @@ pub struct ProjectTag {
 }
 
 /// Project statistics
-#[derive(Debug, Clone, Serialize, FromRow)]
+#[derive(Debug, Clone, Serialize, FromRow, ToSchema)]
 pub struct ProjectStats {
     pub project_id: Uuid,
     pub total_files: i64,
+    /// Words following TeX conventions, of the files the main file reaches
+    /// when it is LaTeX and of all files otherwise
     pub total_words: i64,
+    /// Words split on whitespace, of all files
+    pub total_words_raw: i64,
     pub total_lines: i64,
     pub last_compilation_at: Option<DateTime<Utc>>,
     pub total_compilations: i64,
     pub failed_compilations: i64,
+    #[sqlx(flatten)]
+    pub compile_summary: super::job_history::CompileSummary,
     pub total_collaborators: i64,
     pub created_at: DateTime<Utc>,
 }
 
 /// Project activity log
-#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, ToSchema)]
 pub struct ProjectActivity {
     pub id: Uuid,
     pub project_id: Uuid,
@@ -144,17 +294,37 @@ pub struct ProjectActivity {
     pub created_at: DateTime<Utc>,
 }
 
+/// Orders of `Project::list_for_user`, recently updated first by default;
+/// `owner` sorts by the owner's username
+pub const PROJECT_SORT: Sortable = Sortable {
+    columns: &[
+        SortColumn { name: "updated_at", expression: "p.updated_at", default_order: SortOrder::Desc },
+        SortColumn { name: "created_at", expression: "p.created_at", default_order: SortOrder::Desc },
+        SortColumn { name: "name", expression: "p.name", default_order: SortOrder::Asc },
+        SortColumn { name: "owner", expression: "u.username", default_order: SortOrder::Asc },
+    ],
+    tiebreaker: "p.id",
+};
+
 impl Project {
-    /// Create a new project
-    pub async fn create(
-        db: &sqlx::PgPool,
+    /// Create a new project with its tags, logging its creation
+    ///
+    /// Runs in a transaction of its own, nested in `db` when that is one.
+    pub async fn create<'a>(
+        db: impl sqlx::Acquire<'a, Database = sqlx::Postgres>,
         owner_id: Uuid,
         mut create_project: CreateProject,
     ) -> Result<Self, crate::error::AppError> {
         let workspace_id = create_project.workspace_id.ok_or_else(|| {
             crate::error::AppError::Validation("Workspace ID is required".to_string())
         })?;
-        Workspace::find_by_id(db, workspace_id, owner_id).await?;
+        super::typst::check_output_format(
+            create_project.latex_engine.unwrap_or_default(),
+            create_project.output_format.as_deref().unwrap_or("pdf"),
+        )?;
+
+        let mut tx = db.begin().await.map_err(crate::error::AppError::Database)?;
+        Workspace::find_by_id(&mut *tx, workspace_id, owner_id).await?;
 
         let project = sqlx::query_as::<_, Project>(
             r#"
@@ -175,30 +345,29 @@ impl Project {
         .bind(create_project.output_format.unwrap_or_else(|| "pdf".to_string()))
         .bind(create_project.custom_args.unwrap_or_default())
         .bind(create_project.bibliography_path)
-        .fetch_one(db)
+        .fetch_one(&mut *tx)
         .await
         .map_err(crate::error::AppError::Database)?;
+        super::fail_point::check("project_create")?;
 
         // Create tags if provided
         if let Some(tags) = create_project.tags {
-            for tag_name in tags {
-                sqlx::query(
-                    r#"
-                    INSERT INTO project_tags (project_id, name)
-                    VALUES ($1, $2)
-                    "#
-                )
-                .bind(project.id)
-                .bind(tag_name)
-                .execute(db)
-                .await
-                .map_err(crate::error::AppError::Database)?;
-            }
+            sqlx::query(
+                r#"
+                INSERT INTO project_tags (project_id, name)
+                SELECT $1, name FROM UNNEST($2::text[]) AS name
+                "#
+            )
+            .bind(project.id)
+            .bind(tags)
+            .execute(&mut *tx)
+            .await
+            .map_err(crate::error::AppError::Database)?;
         }
 
         // Log activity
         ProjectActivity::log(
-            db,
+            &mut *tx,
             project.id,
             owner_id,
             "project_created",
@@ -208,6 +377,7 @@ impl Project {
         )
         .await?;
 
+        tx.commit().await.map_err(crate::error::AppError::Database)?;
         Ok(project)
     }
 
@@ -217,22 +387,26 @@ impl Project {
         project_id: Uuid,
         user_id: Uuid,
     ) -> Result<Option<Self>, crate::error::AppError> {
-        let project = sqlx::query_as::<_, Project>(
-            r#"
-            SELECT p.* FROM projects p
-            WHERE p.id = $1 AND (
-                p.owner_id = $2 OR
-                p.id IN (
-                    SELECT project_id FROM project_collaborators
-                    WHERE user_id = $2
-                ) OR
-                p.is_public = true
+        let project = crate::performance::timed(
+            "project.find_by_id",
+            &[QueryParam::of(&project_id), QueryParam::of(&user_id)],
+            sqlx::query_as::<_, Project>(
+                r#"
+                SELECT p.* FROM projects p
+                WHERE p.id = $1 AND p.purging_at IS NULL AND (
+                    p.owner_id = $2 OR
+                    p.id IN (
+                        SELECT project_id FROM project_collaborators
+                        WHERE user_id = $2
+                    ) OR
+                    p.is_public = true
+                )
+                "#
             )
-            "#
+            .bind(project_id)
+            .bind(user_id)
+            .fetch_optional(db),
         )
-        .bind(project_id)
-        .bind(user_id)
-        .fetch_optional(db)
         .await
         .map_err(crate::error::AppError::Database)?;
 
@@ -245,10 +419,12 @@ impl Project {
         user_id: Uuid,
         params: &super::PaginationParams,
     ) -> Result<Vec<Self>, crate::error::AppError> {
-        let projects = sqlx::query_as::<_, Project>(
+        let order_by = params.order_by(&PROJECT_SORT)?;
+        let projects = sqlx::query_as::<_, Project>(&format!(
             r#"
-            SELECT DISTINCT p.* FROM projects p
-            WHERE (
+            SELECT p.* FROM projects p
+            JOIN users u ON u.id = p.owner_id
+            WHERE p.purging_at IS NULL AND (
                 p.owner_id = $1 OR
                 p.id IN (
                     SELECT project_id FROM project_collaborators
@@ -256,10 +432,11 @@ impl Project {
                 ) OR
                 p.is_public = true
             )
-            ORDER BY p.updated_at DESC
+            ORDER BY {}
             LIMIT $2 OFFSET $3
-            "#
-        )
+            "#,
+            order_by
+        ))
         .bind(user_id)
         .bind(params.limit() as i64)
         .bind(params.offset() as i64)
@@ -270,13 +447,79 @@ impl Project {
         Ok(projects)
     }
 
-    /// Update project
+    /// Projects a user owns or collaborates on
+    pub async fn list_for_member(
+        db: &sqlx::PgPool,
+        user_id: Uuid,
+    ) -> Result<Vec<Self>, crate::error::AppError> {
+        let projects = sqlx::query_as::<_, Project>(
+            r#"
+            SELECT p.* FROM projects p
+            WHERE p.purging_at IS NULL AND (p.owner_id = $1 OR p.id IN (
+                SELECT project_id FROM project_collaborators
+                WHERE user_id = $1
+            ))
+            ORDER BY p.name
+            "#
+        )
+        .bind(user_id)
+        .fetch_all(db)
+        .await
+        .map_err(crate::error::AppError::Database)?;
+
+        Ok(projects)
+    }
+
+    /// Update project, recording the changed settings in its history
     pub async fn update(
         &self,
         db: &sqlx::PgPool,
         update_project: UpdateProject,
         user_id: Uuid,
     ) -> Result<Self, crate::error::AppError> {
+        self.update_recorded(db, update_project, user_id, None).await
+    }
+
+    /// Restore the settings a history entry replaced
+    pub async fn rollback(
+        &self,
+        db: &sqlx::PgPool,
+        change: &ProjectSettingsChange,
+        user_id: Uuid,
+    ) -> Result<Self, crate::error::AppError> {
+        let update_project = change.rollback_update()?;
+        self.update_recorded(db, update_project, user_id, Some(change.id)).await
+    }
+
+    /// Validate and apply an update and record its history entry in one transaction
+    async fn update_recorded(
+        &self,
+        db: &sqlx::PgPool,
+        update_project: UpdateProject,
+        user_id: Uuid,
+        rollback_of: Option<Uuid>,
+    ) -> Result<Self, crate::error::AppError> {
+        let mut tx = db.begin().await.map_err(crate::error::AppError::Database)?;
+
+        let current = sqlx::query_as::<_, Project>(
+            "SELECT * FROM projects WHERE id = $1 AND owner_id = $2 AND purging_at IS NULL FOR UPDATE"
+        )
+        .bind(self.id)
+        .bind(user_id)
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(crate::error::AppError::Database)?
+        .ok_or_else(|| {
+            crate::error::AppError::Authorization("Only the project owner can update a project".to_string())
+        })?;
+
+        update_project.validate(&mut tx, self.id).await?;
+        super::typst::check_output_format(
+            update_project.latex_engine.unwrap_or(current.latex_engine),
+            update_project.output_format.as_deref().unwrap_or(&current.output_format),
+        )?;
+        let diff = settings_history::settings_diff(&current, &update_project);
+
         let project = sqlx::query_as::<_, Project>(
             r#"
             UPDATE projects SET
@@ -288,8 +531,11 @@ impl Project {
                 output_format = COALESCE($6, output_format),
                 custom_args = COALESCE($7, custom_args),
                 bibliography_path = COALESCE($8, bibliography_path),
+                readme_file_id = COALESCE($9, readme_file_id),
+                settings = COALESCE($10, settings),
+                is_embeddable = COALESCE($11, is_embeddable),
                 updated_at = NOW()
-            WHERE id = $9 AND owner_id = $10
+            WHERE id = $12 AND owner_id = $13
             RETURNING *
             "#
         )
@@ -301,37 +547,60 @@ impl Project {
         .bind(update_project.output_format)
         .bind(update_project.custom_args)
         .bind(update_project.bibliography_path)
+        .bind(update_project.readme_file_id)
+        .bind(update_project.settings.map(sqlx::types::Json))
+        .bind(update_project.is_embeddable)
         .bind(self.id)
         .bind(user_id)
-        .fetch_one(db)
+        .fetch_one(&mut *tx)
         .await
         .map_err(crate::error::AppError::Database)?;
 
+        if !diff.is_empty() {
+            let kind = if rollback_of.is_some() {
+                SettingsChangeKind::Rollback
+            } else {
+                SettingsChangeKind::Settings
+            };
+            ProjectSettingsChange::record(&mut tx, self.id, kind, user_id, None, rollback_of, diff).await?;
+        }
+
+        tx.commit().await.map_err(crate::error::AppError::Database)?;
+
         Ok(project)
     }
 
     /// Delete project
+    ///
+    /// Marks the project purging, which hides it from every lookup, and
+    /// leaves removing its rows to `ProjectPurgeTask`.
     pub async fn delete(
         &self,
         db: &sqlx::PgPool,
         user_id: Uuid,
-    ) -> Result<(), crate::error::AppError> {
-        let rows_affected = sqlx::query(
-            "DELETE FROM projects WHERE id = $1 AND owner_id = $2"
+    ) -> Result<ProjectPurge, crate::error::AppError> {
+        let mut tx = db.begin().await.map_err(crate::error::AppError::Database)?;
+
+        let project = sqlx::query_as::<_, Project>(
+            r#"
+            UPDATE projects SET purging_at = NOW()
+            WHERE id = $1 AND owner_id = $2 AND purging_at IS NULL
+            RETURNING *
+            "#
         )
         .bind(self.id)
         .bind(user_id)
-        .execute(db)
+        .fetch_optional(&mut *tx)
         .await
-        .map_err(crate::error::AppError::Database)?;
+        .map_err(crate::error::AppError::Database)?
+        .ok_or_else(|| {
+            crate::error::AppError::Authorization("Only the project owner can delete a project".to_string())
+        })?;
 
-        if rows_affected.rows_affected() == 0 {
-            return Err(crate::error::AppError::Authorization(
-                "Only the project owner can delete a project".to_string(),
-            ));
-        }
+        let purge = ProjectPurge::begin(&mut tx, &project, user_id).await?;
 
-        Ok(())
+        tx.commit().await.map_err(crate::error::AppError::Database)?;
+        Ok(purge)
     }
 
     /// Check if user has access to project
@@ -343,7 +612,7 @@ impl Project {
         let count = sqlx::query_scalar::<_, i64>(
             r#"
             SELECT COUNT(*) FROM projects p
-            WHERE p.id = $1 AND (
+            WHERE p.id = $1 AND p.purging_at IS NULL AND (
                 p.owner_id = $2 OR
                 p.id IN (
                     SELECT project_id FROM project_collaborators
@@ -362,6 +631,40 @@ impl Project {
         Ok(count > 0)
     }
 
+    /// Role of a user on a project; readers of public projects count as viewers
+    pub async fn member_role(
+        db: &sqlx::PgPool,
+        project_id: Uuid,
+        user_id: Uuid,
+    ) -> Result<Option<UserRole>, crate::error::AppError> {
+        let row = sqlx::query_as::<_, (bool, bool, Option<String>)>(
+            r#"
+            SELECT p.owner_id = $2, p.is_public, pc.role::text
+            FROM projects p
+            LEFT JOIN project_collaborators pc
+                ON pc.project_id = p.id AND pc.user_id = $2
+            WHERE p.id = $1 AND p.purging_at IS NULL
+            "#
+        )
+        .bind(project_id)
+        .bind(user_id)
+        .fetch_optional(db)
+        .await
+        .map_err(crate::error::AppError::Database)?;
+
+        Ok(row.and_then(|(is_owner, is_public, role)| {
+            if is_owner {
+                Some(UserRole::Owner)
+            } else if let Some(role) = role {
+                UserRole::from_name(&role).or(Some(UserRole::Viewer))
+            } else if is_public {
+                Some(UserRole::Viewer)
+            } else {
+                None
+            }
+        }))
+    }
+
     /// Update the project's main file path (and sync file flags)
     pub async fn set_main_file(
         db: &sqlx::PgPool,
@@ -424,6 +727,54 @@ impl Project {
         Ok(project)
     }
 
+    /// Keep the settings of an imported archive that had no equivalent here
+    pub async fn set_imported_settings(
+        db: &sqlx::PgPool,
+        project_id: Uuid,
+        settings: serde_json::Value,
+    ) -> Result<Self, crate::error::AppError> {
+        let project = sqlx::query_as::<_, Project>(
+            r#"
+            UPDATE projects
+            SET imported_settings = $1, updated_at = NOW()
+            WHERE id = $2
+            RETURNING *
+            "#
+        )
+        .bind(settings)
+        .bind(project_id)
+        .fetch_one(db)
+        .await
+        .map_err(crate::error::AppError::Database)?;
+
+        Ok(project)
+    }
+
+    /// Record the repository and commit the project was imported from
+    pub async fn set_source(
+        db: &sqlx::PgPool,
+        project_id: Uuid,
+        source_url: &str,
+        source_commit: &str,
+    ) -> Result<Self, crate::error::AppError> {
+        let project = sqlx::query_as::<_, Project>(
+            r#"
+            UPDATE projects
+            SET source_url = $1, source_commit = $2, updated_at = NOW()
+            WHERE id = $3
+            RETURNING *
+            "#
+        )
+        .bind(source_url)
+        .bind(source_commit)
+        .bind(project_id)
+        .fetch_one(db)
+        .await
+        .map_err(crate::error::AppError::Database)?;
+
+        Ok(project)
+    }
+
     /// Check if user is owner
     pub async fn is_owner(
         db: &sqlx::PgPool,
@@ -431,7 +782,7 @@ impl Project {
         user_id: Uuid,
     ) -> Result<bool, crate::error::AppError> {
         let count = sqlx::query_scalar::<_, i64>(
-            "SELECT COUNT(*) FROM projects WHERE id = $1 AND owner_id = $2"
+            "SELECT COUNT(*) FROM projects WHERE id = $1 AND owner_id = $2 AND purging_at IS NULL"
         )
         .bind(project_id)
         .bind(user_id)
@@ -447,6 +798,16 @@ impl Project {
         db: &sqlx::PgPool,
         project_id: Uuid,
         user_id: Uuid,
+    ) -> Result<ProjectWithDetails, crate::error::AppError> {
+        Self::get_with_fields(db, project_id, user_id, ProjectFields::ALL).await
+    }
+
+    /// Get project with the selected details
+    pub async fn get_with_fields(
+        db: &sqlx::PgPool,
+        project_id: Uuid,
+        user_id: Uuid,
+        fields: ProjectFields,
     ) -> Result<ProjectWithDetails, crate::error::AppError> {
         // Get basic project info with access control
         let project = Self::find_by_id(db, project_id, user_id).await?
@@ -455,47 +816,106 @@ impl Project {
                 id: project_id.to_string(),
             })?;
 
-        // Get owner info
-        let owner = sqlx::query_as::<_, UserProfile>(
-            r#"
-            SELECT id, username, email, display_name, avatar_url,
-                   is_active, email_verified, last_login_at, created_at
-            FROM users
-            WHERE id = $1
-            "#
-        )
-        .bind(project.owner_id)
-        .fetch_one(db)
-        .await
-        .map_err(crate::error::AppError::Database)?;
+        let mut details = Self::with_details(db, vec![project], fields).await?;
+        Ok(details.remove(0))
+    }
 
-        // Get collaborators
-        let collaborators = sqlx::query_as::<_, UserProfile>(
-            r#"
-            SELECT u.id, u.username, u.email, u.display_name, u.avatar_url,
-                   u.is_active, u.email_verified, u.last_login_at, u.created_at
-            FROM users u
-            JOIN project_collaborators pc ON u.id = pc.user_id
-            WHERE pc.project_id = $1
-            ORDER BY pc.created_at
-            "#
-        )
-        .bind(project_id)
-        .fetch_all(db)
-        .await
-        .map_err(crate::error::AppError::Database)?;
+    /// Load the selected details of several projects, one query per section
+    pub async fn with_details(
+        db: &sqlx::PgPool,
+        projects: Vec<Project>,
+        fields: ProjectFields,
+    ) -> Result<Vec<ProjectWithDetails>, crate::error::AppError> {
+        if projects.is_empty() {
+            return Ok(Vec::new());
+        }
+        let project_ids: Vec<Uuid> = projects.iter().map(|project| project.id).collect();
 
-        // Get statistics
-        let stats = ProjectStats::get(db, project_id).await?;
+        let owners = if fields.owner {
+            let owner_ids: Vec<Uuid> = projects.iter().map(|project| project.owner_id).collect();
+            Some(UserProfile::find_many(db, &owner_ids).await?)
+        } else {
+            None
+        };
 
-        Ok(ProjectWithDetails {
-            project,
-            owner,
-            collaborators,
-            file_count: stats.total_files,
-            word_count: stats.total_words,
-            tag_count: 0, // TODO: Implement tag count
-        })
+        let mut collaborators = if fields.collaborators {
+            let rows = sqlx::query_as::<_, ProjectCollaboratorProfile>(
+                r#"
+                SELECT pc.project_id, u.id, u.username, u.email, u.display_name, u.avatar_url,
+                       u.is_active, u.email_verified, u.last_login_at, u.created_at
+                FROM users u
+                JOIN project_collaborators pc ON u.id = pc.user_id
+                WHERE pc.project_id = ANY($1)
+                ORDER BY pc.created_at
+                "#
+            )
+            .bind(&project_ids)
+            .fetch_all(db)
+            .await
+            .map_err(crate::error::AppError::Database)?;
+
+            let mut by_project: HashMap<Uuid, Vec<UserProfile>> = HashMap::new();
+            for row in rows {
+                by_project.entry(row.project_id).or_default().push(row.profile);
+            }
+            Some(by_project)
+        } else {
+            None
+        };
+
+        let totals = if fields.stats {
+            let rows = sqlx::query_as::<_, ProjectFileTotals>(
+                r#"
+                SELECT project_id,
+                       COUNT(*) as total_files,
+                       COALESCE(SUM(COALESCE(word_count_tex, word_count)), 0) as total_words
+                FROM files
+                WHERE project_id = ANY($1) AND is_deleted = false
+                GROUP BY project_id
+                "#
+            )
+            .bind(&project_ids)
+            .fetch_all(db)
+            .await
+            .map_err(crate::error::AppError::Database)?;
+
+            Some(rows.into_iter().map(|row| (row.project_id, row)).collect::<HashMap<_, _>>())
+        } else {
+            None
+        };
+
+        let mut freezes = if fields.freeze {
+            let freezes = ProjectFreeze::find_many(db, &project_ids).await?;
+            Some(freezes.into_iter().map(|freeze| (freeze.project_id, freeze)).collect::<HashMap<_, _>>())
+        } else {
+            None
+        };
+
+        let details = projects
+            .into_iter()
+            .map(|project| {
+                let owner = owners.as_ref().and_then(|owners| owners.get(&project.owner_id).cloned());
+                let collaborators = collaborators
+                    .as_mut()
+                    .map(|by_project| by_project.remove(&project.id).unwrap_or_default());
+                let project_totals = totals.as_ref().map(|totals| totals.get(&project.id));
+                let freeze = freezes.as_mut().map(|freezes| freezes.remove(&project.id));
+
+                ProjectWithDetails {
+                    owner,
+                    collaborators,
+                    file_count: project_totals.map(|totals| totals.map_or(0, |totals| totals.total_files)),
+                    word_count: project_totals.map(|totals| totals.map_or(0, |totals| totals.total_words)),
+                    tag_count: project_totals.map(|_| 0), // TODO: Implement tag count
+                    frozen: freeze.as_ref().map(Option::is_some),
+                    freeze,
+                    active_collaborators_now: None,
+                    project,
+                }
+            })
+            .collect();
+
+        Ok(details)
     }
 
     /// Update compilation status
@@ -530,6 +950,8 @@ impl ProjectCollaborator {
         role: UserRole,
         invited_by: Uuid,
     ) -> Result<Self, crate::error::AppError> {
+        let mut tx = db.begin().await.map_err(crate::error::AppError::Database)?;
+
         let collaborator = sqlx::query_as::<_, ProjectCollaborator>(
             r#"
             INSERT INTO project_collaborators (project_id, user_id, role, invited_by)
@@ -541,10 +963,15 @@ impl ProjectCollaborator {
         .bind(user_id)
         .bind(role as UserRole)
         .bind(invited_by)
-        .fetch_one(db)
+        .fetch_one(&mut *tx)
         .await
         .map_err(crate::error::AppError::Database)?;
 
+        ProjectSettingsChange::record_role_change(&mut tx, project_id, user_id, None, Some(role), invited_by)
+            .await?;
+
+        tx.commit().await.map_err(crate::error::AppError::Database)?;
+
         Ok(collaborator)
     }
 
@@ -553,19 +980,87 @@ impl ProjectCollaborator {
         db: &sqlx::PgPool,
         project_id: Uuid,
         user_id: Uuid,
+        removed_by: Uuid,
     ) -> Result<(), crate::error::AppError> {
+        let mut tx = db.begin().await.map_err(crate::error::AppError::Database)?;
+
+        let role = sqlx::query_scalar::<_, UserRole>(
+            "DELETE FROM project_collaborators WHERE project_id = $1 AND user_id = $2 RETURNING role"
+        )
+        .bind(project_id)
+        .bind(user_id)
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(crate::error::AppError::Database)?;
+
+        if let Some(role) = role {
+            ProjectSettingsChange::record_role_change(&mut tx, project_id, user_id, Some(role), None, removed_by)
+                .await?;
+        }
+
+        // Ownership can only go to collaborators, so an offer to them lapses
         sqlx::query(
-            "DELETE FROM project_collaborators WHERE project_id = $1 AND user_id = $2"
+            r#"
+            UPDATE project_ownership_transfers
+            SET cancelled_at = NOW(), cancelled_by = $3
+            WHERE project_id = $1 AND to_user_id = $2 AND accepted_at IS NULL AND cancelled_at IS NULL
+            "#
         )
         .bind(project_id)
         .bind(user_id)
-        .execute(db)
+        .bind(removed_by)
+        .execute(&mut *tx)
         .await
         .map_err(crate::error::AppError::Database)?;
 
+        tx.commit().await.map_err(crate::error::AppError::Database)?;
+
         Ok(())
     }
 
+    /// Check if a user is already a collaborator on the project
+    pub async fn exists(
+        db: &sqlx::PgPool,
+        project_id: Uuid,
+        user_id: Uuid,
+    ) -> Result<bool, crate::error::AppError> {
+        let count = sqlx::query_scalar::<_, i64>(
+            "SELECT COUNT(*) FROM project_collaborators WHERE project_id = $1 AND user_id = $2"
+        )
+        .bind(project_id)
+        .bind(user_id)
+        .fetch_one(db)
+        .await
+        .map_err(crate::error::AppError::Database)?;
+
+        Ok(count > 0)
+    }
+
+    /// Collaborators plus pending invitations, which become collaborators once accepted
+    ///
+    /// The pending invitation of `except_email` is left out, so refreshing an
+    /// invitation does not need a seat of its own.
+    pub async fn seats_taken(
+        db: &sqlx::PgPool,
+        project_id: Uuid,
+        except_email: Option<&str>,
+    ) -> Result<i64, crate::error::AppError> {
+        sqlx::query_scalar::<_, i64>(
+            r#"
+            SELECT
+                (SELECT COUNT(*) FROM project_collaborators WHERE project_id = $1)
+                + (SELECT COUNT(*) FROM project_invitations
+                   WHERE project_id = $1 AND accepted_at IS NULL AND expires_at > NOW()
+                     AND email IS DISTINCT FROM $2)
+            "#
+        )
+        .bind(project_id)
+        .bind(except_email)
+        .fetch_one(db)
+        .await
+        .map_err(crate::error::AppError::Database)
+    }
+
     /// Get project collaborators
     pub async fn list(
         db: &sqlx::PgPool,
@@ -589,12 +1084,13 @@ impl ProjectStats {
         db: &sqlx::PgPool,
         project_id: Uuid,
     ) -> Result<Self, crate::error::AppError> {
-        let stats = sqlx::query_as::<_, ProjectStats>(
+        let mut stats = sqlx::query_as::<_, ProjectStats>(
             r#"
             WITH file_stats AS (
                 SELECT
                     COUNT(*) as total_files,
-                    COALESCE(SUM(word_count), 0) as total_words,
+                    COALESCE(SUM(COALESCE(word_count_tex, word_count)), 0) as total_words,
+                    COALESCE(SUM(word_count), 0) as total_words_raw,
                     COALESCE(SUM(line_count), 0) as total_lines
                 FROM files
                 WHERE project_id = $1 AND is_deleted = false
@@ -602,17 +1098,37 @@ impl ProjectStats {
             compilation_stats AS (
                 SELECT
                     COUNT(*) as total_compilations,
-                    COUNT(*) FILTER (WHERE status = 'success') as successful_compilations
+                    COUNT(*) FILTER (WHERE status = 'success') as successful_compilations,
+                    MAX(completed_at) FILTER (WHERE status = 'success') as last_success_at
                 FROM compilation_jobs
                 WHERE project_id = $1
+            ),
+            recent_jobs AS (
+                SELECT status FROM compilation_jobs
+                WHERE project_id = $1 AND status IN ('success', 'error')
+                ORDER BY created_at DESC
+                LIMIT $2
             )
             SELECT
                 $1 as project_id,
                 fs.total_files,
                 fs.total_words,
+                fs.total_words_raw,
                 fs.total_lines,
+                p.last_compilation_at,
                 cs.total_compilations,
                 (cs.total_compilations - cs.successful_compilations) as failed_compilations,
+                (
+                    SELECT status FROM compilation_jobs
+                    WHERE project_id = $1
+                    ORDER BY created_at DESC
+                    LIMIT 1
+                ) as last_job_status,
+                cs.last_success_at,
+                (
+                    SELECT AVG(CASE WHEN status = 'error' THEN 1.0 ELSE 0.0 END)::float8
+                    FROM recent_jobs
+                ) as recent_failure_rate,
                 COALESCE(c.total_collaborators, 0) as total_collaborators,
                 p.created_at
             FROM file_stats fs
@@ -626,18 +1142,56 @@ impl ProjectStats {
             "#
         )
         .bind(project_id)
+        .bind(super::job_history::FAILURE_RATE_WINDOW)
         .fetch_one(db)
         .await
         .map_err(crate::error::AppError::Database)?;
 
+        if let Some(total_words) = Self::reachable_words(db, project_id).await? {
+            stats.total_words = total_words;
+        }
         Ok(stats)
     }
+
+    /// Words of the files a LaTeX main file reaches through its includes;
+    /// `None` when the main file is missing or not LaTeX
+    async fn reachable_words(
+        db: &sqlx::PgPool,
+        project_id: Uuid,
+    ) -> Result<Option<i64>, crate::error::AppError> {
+        let main_file_path: Option<String> =
+            sqlx::query_scalar("SELECT main_file_path FROM projects WHERE id = $1")
+                .bind(project_id)
+                .fetch_optional(db)
+                .await
+                .map_err(crate::error::AppError::Database)?;
+        let Some(main_file_path) = main_file_path else { return Ok(None) };
+
+        let sources = super::include_graph::load_sources(db, project_id).await?;
+        let graph = super::include_graph::IncludeGraph::build(&sources, &main_file_path);
+        let main_is_latex = sources.iter().any(|source| {
+            Some(source.file_id) == graph.main_file_id && source.content_type == super::ContentType::Latex
+        });
+        if !main_is_latex {
+            return Ok(None);
+        }
+        let reachable: Vec<Uuid> = graph.nodes.iter().filter(|node| node.reachable).map(|node| node.file_id).collect();
+
+        let total_words: i64 = sqlx::query_scalar(
+            "SELECT COALESCE(SUM(COALESCE(word_count_tex, word_count)), 0)::bigint FROM files WHERE id = ANY($1)"
+        )
+        .bind(&reachable)
+        .fetch_one(db)
+        .await
+        .map_err(crate::error::AppError::Database)?;
+        Ok(Some(total_words))
+    }
 }
 
 impl ProjectActivity {
     /// Log project activity
     pub async fn log(
-        db: &sqlx::PgPool,
+        db: impl sqlx::PgExecutor<'_>,
         project_id: Uuid,
         user_id: Uuid,
         action: &str,
@@ -665,6 +1219,50 @@ impl ProjectActivity {
         Ok(())
     }
 
+    /// Log project activity in the background, through the buffer of
+    /// best-effort writes
+    ///
+    /// The row's ID and time are fixed now, so a write repeated after an
+    /// outage neither duplicates the row nor moves it in time.
+    pub fn log_later(
+        resilience: &std::sync::Arc<crate::db_resilience::DbResilience>,
+        project_id: Uuid,
+        user_id: Uuid,
+        action: &str,
+        entity_type: &str,
+        entity_id: Option<Uuid>,
+        details: Option<String>,
+    ) -> tokio::task::JoinHandle<()> {
+        let id = Uuid::new_v4();
+        let at = Utc::now();
+        let (action, entity_type) = (action.to_string(), entity_type.to_string());
+        resilience.write("activity_log", move |db| {
+            let (action, entity_type, details) = (action.clone(), entity_type.clone(), details.clone());
+            async move {
+                sqlx::query(
+                    r#"
+                    INSERT INTO project_activity (
+                        id, project_id, user_id, action, entity_type, entity_id, details, created_at
+                    ) VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+                    ON CONFLICT (id) DO NOTHING
+                    "#
+                )
+                .bind(id)
+                .bind(project_id)
+                .bind(user_id)
+                .bind(action)
+                .bind(entity_type)
+                .bind(entity_id)
+                .bind(details)
+                .bind(at)
+                .execute(&db)
+                .await
+                .map_err(crate::error::AppError::Database)?;
+                Ok(())
+            }
+        })
+    }
+
     /// Get recent project activities
     pub async fn get_recent(
         db: &sqlx::PgPool,
@@ -687,23 +1285,230 @@ impl ProjectActivity {
 
         Ok(activities)
     }
+
+    /// A page of the project's activity, newest first, with the total count
+    pub async fn list(
+        db: &sqlx::PgPool,
+        project_id: Uuid,
+        params: &super::PaginationParams,
+    ) -> Result<(Vec<Self>, i64), crate::error::AppError> {
+        let activities = sqlx::query_as::<_, ProjectActivity>(
+            r#"
+            SELECT * FROM project_activity
+            WHERE project_id = $1
+            ORDER BY created_at DESC, id
+            LIMIT $2 OFFSET $3
+            "#
+        )
+        .bind(project_id)
+        .bind(params.limit() as i64)
+        .bind(params.offset() as i64)
+        .fetch_all(db)
+        .await
+        .map_err(crate::error::AppError::Database)?;
+
+        let total = sqlx::query_scalar::<_, i64>(
+            "SELECT COUNT(*) FROM project_activity WHERE project_id = $1"
+        )
+        .bind(project_id)
+        .fetch_one(db)
+        .await
+        .map_err(crate::error::AppError::Database)?;
+
+        Ok((activities, total))
+    }
+
+    pub async fn find_by_id(
+        db: &sqlx::PgPool,
+        id: Uuid,
+    ) -> Result<Option<Self>, crate::error::AppError> {
+        sqlx::query_as::<_, ProjectActivity>("SELECT * FROM project_activity WHERE id = $1")
+            .bind(id)
+            .fetch_optional(db)
+            .await
+            .map_err(crate::error::AppError::Database)
+    }
+
+    /// Project activities within a period, oldest first
+    pub async fn list_between(
+        db: &sqlx::PgPool,
+        project_id: Uuid,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+    ) -> Result<Vec<Self>, crate::error::AppError> {
+        let activities = sqlx::query_as::<_, ProjectActivity>(
+            r#"
+            SELECT * FROM project_activity
+            WHERE project_id = $1 AND created_at >= $2 AND created_at < $3
+            ORDER BY created_at
+            "#
+        )
+        .bind(project_id)
+        .bind(start)
+        .bind(end)
+        .fetch_all(db)
+        .await
+        .map_err(crate::error::AppError::Database)?;
+
+        Ok(activities)
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::error::AppError;
+    use crate::models::fail_point;
+    use crate::models::validation::ProjectName;
+    use crate::models::workspace::DEFAULT_PROJECT_NAME;
+    use crate::models::PaginationParams;
+    use crate::testing::{add_collaborator, assert_pages_are_stable, create_test_project, create_test_user, TestDb};
+
+    async fn count_projects(db: &sqlx::PgPool, owner_id: Uuid) -> i64 {
+        sqlx::query_scalar("SELECT COUNT(*) FROM projects WHERE owner_id = $1")
+            .bind(owner_id)
+            .fetch_one(db)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_project_access_by_relationship() {
+        let Some(db) = TestDb::start().await else { return };
+        let owner = create_test_user(&db.pool).await;
+        let collaborator = create_test_user(&db.pool).await;
+        let stranger = create_test_user(&db.pool).await;
+
+        let private = create_test_project(&db.pool, &owner, false).await;
+        let public = create_test_project(&db.pool, &owner, true).await;
+        add_collaborator(&db.pool, &private, &collaborator, UserRole::Collaborator).await;
+
+        for user in [&owner, &collaborator] {
+            let found = Project::find_by_id(&db.pool, private.id, user.id).await.unwrap();
+            assert_eq!(found.map(|p| p.id), Some(private.id));
+            assert!(Project::has_access(&db.pool, private.id, user.id).await.unwrap());
+        }
+
+        assert!(Project::find_by_id(&db.pool, private.id, stranger.id).await.unwrap().is_none());
+        assert!(!Project::has_access(&db.pool, private.id, stranger.id).await.unwrap());
+
+        assert!(Project::find_by_id(&db.pool, public.id, stranger.id).await.unwrap().is_some());
+        assert!(Project::has_access(&db.pool, public.id, stranger.id).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_member_role() {
+        let Some(db) = TestDb::start().await else { return };
+        let owner = create_test_user(&db.pool).await;
+        let maintainer = create_test_user(&db.pool).await;
+        let viewer = create_test_user(&db.pool).await;
+        let stranger = create_test_user(&db.pool).await;
+
+        let private = create_test_project(&db.pool, &owner, false).await;
+        let public = create_test_project(&db.pool, &owner, true).await;
+        add_collaborator(&db.pool, &private, &maintainer, UserRole::Maintainer).await;
+        add_collaborator(&db.pool, &private, &viewer, UserRole::Viewer).await;
+
+        let role = |project: Uuid, user: Uuid| Project::member_role(&db.pool, project, user);
+        assert_eq!(role(private.id, owner.id).await.unwrap(), Some(UserRole::Owner));
+        assert_eq!(role(private.id, maintainer.id).await.unwrap(), Some(UserRole::Maintainer));
+        assert_eq!(role(private.id, viewer.id).await.unwrap(), Some(UserRole::Viewer));
+        assert_eq!(role(private.id, stranger.id).await.unwrap(), None);
+        assert_eq!(role(public.id, stranger.id).await.unwrap(), Some(UserRole::Viewer));
+        assert_eq!(role(Uuid::new_v4(), owner.id).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_failed_create_leaves_no_project() {
+        let Some(db) = TestDb::start().await else { return };
+        let owner = create_test_user(&db.pool).await;
+        let workspace = Workspace::ensure_default(&db.pool, owner.id).await.unwrap();
+        let create = || CreateProject {
+            name: ProjectName::new("Thesis").unwrap(),
+            description: None,
+            is_public: None,
+            main_file_path: None,
+            latex_engine: None,
+            output_format: None,
+            custom_args: None,
+            bibliography_path: None,
+            tags: Some(vec!["draft".to_string(), "physics".to_string()]),
+            workspace_id: Some(workspace.id),
+        };
+
+        {
+            let _armed = fail_point::arm("project_create");
+            assert!(Project::create(&db.pool, owner.id, create()).await.is_err());
+        }
+        assert_eq!(count_projects(&db.pool, owner.id).await, 0);
+        let activity: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM project_activity WHERE user_id = $1")
+            .bind(owner.id)
+            .fetch_one(&db.pool)
+            .await
+            .unwrap();
+        assert_eq!(activity, 0);
+
+        let project = Project::create(&db.pool, owner.id, create()).await.unwrap();
+        let tags: Vec<String> = sqlx::query_scalar("SELECT name FROM project_tags WHERE project_id = $1 ORDER BY name")
+            .bind(project.id)
+            .fetch_all(&db.pool)
+            .await
+            .unwrap();
+        assert_eq!(tags, vec!["draft", "physics"]);
+    }
 
     #[tokio::test]
-    async fn test_project_creation() {
-        // Test project creation logic
-        // This would require a test database
-        assert!(true);
+    async fn test_failed_file_rolls_back_seeded_project() {
+        let Some(db) = TestDb::start().await else { return };
+        let owner = create_test_user(&db.pool).await;
+        let workspace = Workspace::ensure_default(&db.pool, owner.id).await.unwrap();
+
+        {
+            let _armed = fail_point::arm("file_create");
+            assert!(Workspace::seed_welcome_project(&db.pool, owner.id, workspace.id).await.is_err());
+        }
+        assert_eq!(count_projects(&db.pool, owner.id).await, 0);
+
+        // Model calls composed in a caller's transaction go with it
+        let mut tx = db.pool.begin().await.unwrap();
+        let project = Workspace::seed_welcome_project(&mut tx, owner.id, workspace.id).await.unwrap();
+        assert_eq!(project.name, DEFAULT_PROJECT_NAME);
+        drop(tx);
+        assert_eq!(count_projects(&db.pool, owner.id).await, 0);
+
+        let project = Workspace::seed_welcome_project(&db.pool, owner.id, workspace.id).await.unwrap();
+        let files: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM files WHERE project_id = $1")
+            .bind(project.id)
+            .fetch_one(&db.pool)
+            .await
+            .unwrap();
+        assert_eq!(files, 2);
     }
 
-    #[test]
-    fn test_project_access_check() {
-        // Test access control logic
-        assert!(true);
+    #[tokio::test]
+    async fn test_list_pages_are_stable_under_every_sort() {
+        let Some(db) = TestDb::start().await else { return };
+        let owner = create_test_user(&db.pool).await;
+        for _ in 0..5 {
+            create_test_project(&db.pool, &owner, false).await;
+        }
+        // As after a bulk import, every row ties on the timestamps
+        sqlx::query("UPDATE projects SET created_at = NOW(), updated_at = NOW() WHERE owner_id = $1")
+            .bind(owner.id)
+            .execute(&db.pool)
+            .await
+            .unwrap();
+
+        let pool = &db.pool;
+        assert_pages_are_stable(&PROJECT_SORT, move |params| async move {
+            let projects = Project::list_for_user(pool, owner.id, &params).await.unwrap();
+            projects.into_iter().map(|p| p.id).collect()
+        })
+        .await;
+
+        let unknown = PaginationParams { sort_by: Some("owner_id".to_string()), ..Default::default() };
+        assert!(matches!(
+            Project::list_for_user(&db.pool, owner.id, &unknown).await,
+            Err(crate::error::AppError::Validation(_))
+        ));
     }
 }
@@ -0,0 +1,624 @@
+//! Project zip archives
+//!
+//! Exports write every live file at its project path. The native format adds
+//! a `texler.json` manifest with the project settings and the file checksums
+//! of `archive_checksum`; the Overleaf format
+//! leaves it out, puts the main file at the root and carries custom compiler
+//! arguments in a `.latexmkrc` instead.
+//!
+//! Imports accept both, plus plain zips. Overleaf exports are recognized by
+//! their `.latexmkrc` or `olsettings` file. Settings that have no equivalent
+//! here are kept verbatim and reported back instead of being dropped.
+
+use chrono::{DateTime, Utc};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::io::{Cursor, Read, Write};
+use std::sync::OnceLock;
+use utoipa::ToSchema;
+
+use super::archive_checksum::{ChecksumEntry, ChecksumManifest, CHECKSUMS_NAME};
+use super::project::Project;
+use super::LatexEngine;
+
+/// Settings manifest of the native format
+pub const MANIFEST_NAME: &str = "texler.json";
+
+/// latexmk configuration shipped by Overleaf exports
+pub const LATEXMKRC_NAME: &str = ".latexmkrc";
+
+/// Overleaf project settings blob, present in some exports
+pub const OLSETTINGS_NAME: &str = "olsettings";
+
+/// Layout of an exported archive
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ArchiveFormat {
+    #[default]
+    Texler,
+    Overleaf,
+}
+
+/// Where an imported archive came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ArchiveSource {
+    Texler,
+    Overleaf,
+    Plain,
+}
+
+/// A file inside an archive, at its path relative to the project root
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArchiveEntry {
+    pub path: String,
+    pub bytes: Vec<u8>,
+}
+
+/// Project settings stored in `texler.json`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ArchiveManifest {
+    pub name: String,
+    pub description: Option<String>,
+    pub main_file: String,
+    pub latex_engine: LatexEngine,
+    pub output_format: String,
+    pub custom_args: Vec<String>,
+    pub bibliography_path: Option<String>,
+    pub exported_at: DateTime<Utc>,
+}
+
+/// Project settings recovered from an imported archive
+#[derive(Debug, Clone, Default)]
+pub struct ImportedSettings {
+    pub name: Option<String>,
+    pub description: Option<String>,
+    /// Main file path relative to the archive root
+    pub main_file: Option<String>,
+    pub latex_engine: Option<LatexEngine>,
+    pub output_format: Option<String>,
+    pub custom_args: Vec<String>,
+    pub bibliography_path: Option<String>,
+    /// Settings without an equivalent, kept as found
+    pub preserved: serde_json::Map<String, serde_json::Value>,
+    /// Human-readable list of everything that could not be mapped
+    pub unmapped: Vec<String>,
+}
+
+/// Contents of an imported archive
+#[derive(Debug, Clone)]
+pub struct ParsedArchive {
+    pub source: ArchiveSource,
+    pub files: Vec<ArchiveEntry>,
+    pub settings: ImportedSettings,
+    /// Checksums the archive was exported with
+    pub checksums: Option<ChecksumManifest>,
+}
+
+/// Write a project archive in memory
+pub fn build_archive(
+    project: &Project,
+    files: Vec<ArchiveEntry>,
+    format: ArchiveFormat,
+) -> Result<Vec<u8>, crate::error::AppError> {
+    let paths: Vec<String> = files.iter().map(|file| file.path.clone()).collect();
+    let sink = write_archive(project, &paths, files.into_iter().map(Ok), format, Vec::new())?;
+    Ok(sink)
+}
+
+/// Write a project archive to `sink`, one file at a time.
+///
+/// `paths` lists every file up front, since the Overleaf layout depends on
+/// all of them, and `files` yields their entries in that order. Only the
+/// entry being compressed is held in memory; the checksums of the native
+/// format are taken on the way and written last. Stops at the first file
+/// that cannot be read.
+pub fn write_archive<W: Write>(
+    project: &Project,
+    paths: &[String],
+    files: impl IntoIterator<Item = Result<ArchiveEntry, crate::error::AppError>>,
+    format: ArchiveFormat,
+    sink: W,
+) -> Result<W, crate::error::AppError> {
+    let main_file = project.main_file_path.trim_start_matches('/').to_string();
+    let prefix = match format {
+        ArchiveFormat::Texler => None,
+        ArchiveFormat::Overleaf => main_directory_prefix(paths, &main_file),
+    };
+    // Typst takes no custom arguments, so there is nothing for latexmk to carry
+    let writes_latexmkrc = format == ArchiveFormat::Overleaf
+        && !project.custom_args.is_empty()
+        && !project.latex_engine.is_typst();
+
+    let options = zip::write::SimpleFileOptions::default()
+        .compression_method(zip::CompressionMethod::Deflated);
+    let mut writer = zip::ZipWriter::new_stream(sink);
+    let mut checksums = Vec::new();
+
+    for file in files {
+        let mut file = file?;
+        if let Some(path) = prefix.as_deref().and_then(|prefix| file.path.strip_prefix(prefix)) {
+            file.path = path.to_string();
+        }
+        let replaced = match format {
+            ArchiveFormat::Texler => file.path == MANIFEST_NAME || file.path == CHECKSUMS_NAME,
+            ArchiveFormat::Overleaf => writes_latexmkrc && file.path == LATEXMKRC_NAME,
+        };
+        if replaced {
+            continue;
+        }
+        if format == ArchiveFormat::Texler {
+            checksums.push(ChecksumEntry::of(&file));
+        }
+        writer.start_file(file.path.as_str(), options).map_err(archive_error)?;
+        writer.write_all(&file.bytes)?;
+    }
+
+    let mut extra: Vec<ArchiveEntry> = Vec::new();
+    match format {
+        ArchiveFormat::Texler => {
+            let manifest = ArchiveManifest {
+                name: project.name.clone(),
+                description: project.description.clone(),
+                main_file,
+                latex_engine: project.latex_engine,
+                output_format: project.output_format.clone(),
+                custom_args: project.custom_args.clone(),
+                bibliography_path: project.bibliography_path.clone(),
+                exported_at: Utc::now(),
+            };
+            extra.push(ArchiveEntry {
+                path: MANIFEST_NAME.to_string(),
+                bytes: serde_json::to_vec_pretty(&manifest)?,
+            });
+            extra.push(ArchiveEntry {
+                path: CHECKSUMS_NAME.to_string(),
+                bytes: serde_json::to_vec_pretty(&ChecksumManifest::new(checksums))?,
+            });
+        }
+        ArchiveFormat::Overleaf if writes_latexmkrc => {
+            extra.push(ArchiveEntry {
+                path: LATEXMKRC_NAME.to_string(),
+                bytes: latexmkrc(project.latex_engine, &project.custom_args).into_bytes(),
+            });
+        }
+        ArchiveFormat::Overleaf => {}
+    }
+
+    for entry in &extra {
+        writer.start_file(entry.path.as_str(), options).map_err(archive_error)?;
+        writer.write_all(&entry.bytes)?;
+    }
+
+    let mut sink = writer.finish().map_err(archive_error)?.into_inner();
+    sink.flush()?;
+    Ok(sink)
+}
+
+/// Read an uploaded archive.
+///
+/// Rejects entries that would escape the project directory, archives that
+/// unpack to more than `max_size` bytes and checksum manifests that cannot
+/// be read. A single folder wrapping all entries is stripped.
+pub fn read_archive(bytes: &[u8], max_size: u64) -> Result<ParsedArchive, crate::error::AppError> {
+    let mut archive = zip::ZipArchive::new(Cursor::new(bytes))
+        .map_err(|e| crate::error::AppError::Validation(format!("Not a valid zip archive: {}", e)))?;
+
+    let mut files = Vec::new();
+    let mut total: u64 = 0;
+
+    for index in 0..archive.len() {
+        let entry = archive.by_index(index).map_err(archive_error)?;
+        if entry.is_dir() {
+            continue;
+        }
+
+        let path = entry
+            .enclosed_name()
+            .and_then(|path| path.to_str().map(|p| p.replace('\\', "/")))
+            .ok_or_else(|| crate::error::AppError::Validation(format!(
+                "Archive entry '{}' points outside the project",
+                entry.name()
+            )))?;
+
+        if is_archive_junk(&path) {
+            continue;
+        }
+
+        // The declared size can lie, so never read more than the remaining budget
+        let remaining = max_size.saturating_sub(total);
+        let mut content = Vec::with_capacity(entry.size().min(remaining) as usize);
+        entry.take(remaining + 1).read_to_end(&mut content)?;
+        total += content.len() as u64;
+
+        if total > max_size {
+            return Err(crate::error::AppError::Validation(format!(
+                "Archive unpacks to more than {} bytes",
+                max_size
+            )));
+        }
+
+        files.push(ArchiveEntry { path, bytes: content });
+    }
+
+    strip_wrapping_folder(&mut files);
+
+    let take = |files: &mut Vec<ArchiveEntry>, name: &str| {
+        files
+            .iter()
+            .position(|file| file.path == name)
+            .map(|index| files.remove(index))
+    };
+
+    let manifest = take(&mut files, MANIFEST_NAME);
+    let latexmk = take(&mut files, LATEXMKRC_NAME);
+    let olsettings = take(&mut files, OLSETTINGS_NAME);
+    let checksums = take(&mut files, CHECKSUMS_NAME)
+        .map(|checksums| ChecksumManifest::parse(&checksums.bytes))
+        .transpose()?;
+
+    let (source, mut settings) = if let Some(manifest) = manifest {
+        // A stray latexmkrc in a native export is an ordinary project file
+        files.extend(latexmk);
+        files.extend(olsettings);
+        (ArchiveSource::Texler, parse_manifest(&manifest.bytes))
+    } else if latexmk.is_some() || olsettings.is_some() {
+        let mut settings = ImportedSettings::default();
+        if let Some(latexmk) = latexmk {
+            apply_latexmkrc(&String::from_utf8_lossy(&latexmk.bytes), &mut settings);
+        }
+        if let Some(olsettings) = olsettings {
+            apply_olsettings(&olsettings.bytes, &mut settings);
+        }
+        (ArchiveSource::Overleaf, settings)
+    } else {
+        (ArchiveSource::Plain, ImportedSettings::default())
+    };
+
+    let declared_main = settings.main_file.take();
+    settings.main_file = match declared_main {
+        Some(main) if files.iter().any(|file| file.path == main) => Some(main),
+        Some(main) => {
+            settings.unmapped.push(format!("Declared main file '{}' is not in the archive", main));
+            detect_main_file(&files)
+        }
+        None => detect_main_file(&files),
+    };
+
+    Ok(ParsedArchive { source, files, settings, checksums })
+}
+
+/// Pick the main file: the shallowest `.tex` file with a `\documentclass`,
+/// preferring `main.tex`, falling back to `main.tex` or the shallowest
+/// `.tex` file when none declares a class
+pub fn detect_main_file(files: &[ArchiveEntry]) -> Option<String> {
+    let rank = |file: &ArchiveEntry| {
+        let depth = file.path.matches('/').count();
+        let not_main = !(file.path == "main.tex" || file.path.ends_with("/main.tex"));
+        (depth, not_main, file.path.clone())
+    };
+
+    let tex_files: Vec<&ArchiveEntry> = files
+        .iter()
+        .filter(|file| file.path.to_lowercase().ends_with(".tex"))
+        .collect();
+
+    tex_files
+        .iter()
+        .copied()
+        .filter(|file| declares_document_class(&String::from_utf8_lossy(&file.bytes)))
+        .min_by_key(|file| rank(file))
+        .or_else(|| tex_files.iter().copied().min_by_key(|file| rank(file)))
+        .map(|file| file.path.clone())
+}
+
+/// Whether a document has a `\documentclass` outside of comments
+fn declares_document_class(content: &str) -> bool {
+    content.lines().any(|line| {
+        let code = line.split('%').next().unwrap_or_default();
+        code.contains("\\documentclass")
+    })
+}
+
+/// Map an engine name as used by latexmk and Overleaf
+pub fn engine_from_name(name: &str) -> Option<LatexEngine> {
+    match name.trim().to_lowercase().as_str() {
+        "pdflatex" | "pdftex" => Some(LatexEngine::Pdflatex),
+        "xelatex" | "xetex" => Some(LatexEngine::Xelatex),
+        "lualatex" | "luatex" => Some(LatexEngine::Lualatex),
+        "typst" => Some(LatexEngine::Typst),
+        _ => None,
+    }
+}
+
+/// latexmk configuration selecting `engine` with `custom_args`
+pub fn latexmkrc(engine: LatexEngine, custom_args: &[String]) -> String {
+    // latexmk does not run Typst; its LaTeX files compile with the default engine
+    let engine = if engine.is_typst() { LatexEngine::default() } else { engine };
+    let pdf_mode = match engine {
+        LatexEngine::Pdflatex | LatexEngine::Typst => 1,
+        LatexEngine::Lualatex => 4,
+        LatexEngine::Xelatex => 5,
+    };
+
+    let args: Vec<String> = custom_args
+        .iter()
+        .map(|arg| arg.replace('\\', "\\\\").replace('\'', "\\'"))
+        .collect();
+
+    format!(
+        "# Generated by Texler\n$pdf_mode = {};\n${} = '{} {} %O %S';\n",
+        pdf_mode,
+        engine.command(),
+        engine.command(),
+        args.join(" ")
+    )
+}
+
+fn apply_latexmkrc(content: &str, settings: &mut ImportedSettings) {
+    static PDF_MODE: OnceLock<Regex> = OnceLock::new();
+    static COMMAND: OnceLock<Regex> = OnceLock::new();
+    let pdf_mode = PDF_MODE.get_or_init(|| Regex::new(r"^\$pdf_mode\s*=\s*(\d+)\s*;?$").unwrap());
+    let command = COMMAND.get_or_init(|| {
+        Regex::new(r#"^\$(pdflatex|xelatex|lualatex)\s*=\s*['"](.*)['"]\s*;?$"#).unwrap()
+    });
+
+    let mut unmapped_lines = Vec::new();
+
+    for line in content.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        if let Some(captures) = pdf_mode.captures(line) {
+            match &captures[1] {
+                "1" => settings.latex_engine = Some(LatexEngine::Pdflatex),
+                "4" => settings.latex_engine = Some(LatexEngine::Lualatex),
+                "5" => settings.latex_engine = Some(LatexEngine::Xelatex),
+                _ => unmapped_lines.push(line.to_string()),
+            }
+        } else if let Some(captures) = command.captures(line) {
+            settings.custom_args = captures[2]
+                .split_whitespace()
+                .skip(1)
+                .filter(|arg| *arg != "%O" && *arg != "%S")
+                .map(|arg| arg.replace("\\'", "'").replace("\\\\", "\\"))
+                .collect();
+        } else {
+            unmapped_lines.push(line.to_string());
+        }
+    }
+
+    if !unmapped_lines.is_empty() {
+        settings
+            .unmapped
+            .extend(unmapped_lines.iter().map(|line| format!("{}: {}", LATEXMKRC_NAME, line)));
+        settings.preserved.insert("latexmkrc".to_string(), serde_json::json!(unmapped_lines));
+    }
+}
+
+fn apply_olsettings(bytes: &[u8], settings: &mut ImportedSettings) {
+    let Ok(serde_json::Value::Object(values)) = serde_json::from_slice::<serde_json::Value>(bytes) else {
+        settings.unmapped.push(format!("{}: unrecognized format, kept as text", OLSETTINGS_NAME));
+        settings.preserved.insert(
+            OLSETTINGS_NAME.to_string(),
+            serde_json::Value::String(String::from_utf8_lossy(bytes).to_string()),
+        );
+        return;
+    };
+
+    let mut unknown = serde_json::Map::new();
+    for (key, value) in values {
+        match (key.as_str(), value.as_str()) {
+            ("compiler", Some(compiler)) => match engine_from_name(compiler) {
+                // latexmkrc reflects what actually ran, so it wins
+                Some(engine) => {
+                    settings.latex_engine.get_or_insert(engine);
+                }
+                None => {
+                    settings.unmapped.push(format!("{}: compiler '{}' is not supported", OLSETTINGS_NAME, compiler));
+                    unknown.insert(key, value);
+                }
+            },
+            ("rootDoc" | "mainFile", Some(main)) => {
+                settings.main_file = Some(main.trim_start_matches('/').to_string());
+            }
+            _ => {
+                settings.unmapped.push(format!("{}: {}", OLSETTINGS_NAME, key));
+                unknown.insert(key, value);
+            }
+        }
+    }
+
+    if !unknown.is_empty() {
+        settings.preserved.insert(OLSETTINGS_NAME.to_string(), serde_json::Value::Object(unknown));
+    }
+}
+
+fn parse_manifest(bytes: &[u8]) -> ImportedSettings {
+    let mut settings = ImportedSettings::default();
+
+    let Ok(serde_json::Value::Object(values)) = serde_json::from_slice::<serde_json::Value>(bytes) else {
+        settings.unmapped.push(format!("{}: not a JSON object", MANIFEST_NAME));
+        return settings;
+    };
+
+    let text = |value: &serde_json::Value| value.as_str().map(str::to_string);
+    let mut unknown = serde_json::Map::new();
+
+    for (key, value) in values {
+        match key.as_str() {
+            "name" => settings.name = text(&value),
+            "description" => settings.description = text(&value),
+            "main_file" => settings.main_file = text(&value).map(|p| p.trim_start_matches('/').to_string()),
+            "output_format" => settings.output_format = text(&value),
+            "bibliography_path" => settings.bibliography_path = text(&value),
+            "custom_args" => {
+                settings.custom_args = serde_json::from_value(value).unwrap_or_default();
+            }
+            "latex_engine" => match value.as_str().and_then(engine_from_name) {
+                Some(engine) => settings.latex_engine = Some(engine),
+                None => {
+                    settings.unmapped.push(format!("{}: latex_engine {}", MANIFEST_NAME, value));
+                    unknown.insert(key, value);
+                }
+            },
+            "exported_at" => {}
+            _ => {
+                settings.unmapped.push(format!("{}: {}", MANIFEST_NAME, key));
+                unknown.insert(key, value);
+            }
+        }
+    }
+
+    if !unknown.is_empty() {
+        settings.preserved.insert(MANIFEST_NAME.to_string(), serde_json::Value::Object(unknown));
+    }
+    settings
+}
+
+/// The main file's directory, with a trailing slash, when every file is in it
+fn main_directory_prefix(paths: &[String], main_file: &str) -> Option<String> {
+    let (directory, _) = main_file.rsplit_once('/')?;
+    let prefix = format!("{}/", directory);
+    paths.iter().all(|path| path.starts_with(&prefix)).then_some(prefix)
+}
+
+fn strip_wrapping_folder(files: &mut [ArchiveEntry]) {
+    let roots: HashSet<&str> = files
+        .iter()
+        .map(|file| file.path.split_once('/').map_or("", |(root, _)| root))
+        .collect();
+
+    if roots.len() != 1 || roots.contains("") {
+        return;
+    }
+
+    for file in files.iter_mut() {
+        if let Some((_, rest)) = file.path.split_once('/') {
+            file.path = rest.to_string();
+        }
+    }
+}
+
+fn is_archive_junk(path: &str) -> bool {
+    path.starts_with("__MACOSX/") || path == ".DS_Store" || path.ends_with("/.DS_Store")
+}
+
+fn archive_error(e: zip::result::ZipError) -> crate::error::AppError {
+    crate::error::AppError::Validation(format!("Invalid zip archive: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(path: &str, content: &[u8]) -> ArchiveEntry {
+        ArchiveEntry { path: path.to_string(), bytes: content.to_vec() }
+    }
+
+    fn zip_of(entries: &[ArchiveEntry]) -> Vec<u8> {
+        let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        for entry in entries {
+            writer.start_file(entry.path.as_str(), zip::write::SimpleFileOptions::default()).unwrap();
+            writer.write_all(&entry.bytes).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn test_detect_main_file() {
+        let files = vec![
+            entry("chapters/intro.tex", b"\\section{Intro}"),
+            entry("appendix/standalone.tex", b"\\documentclass{standalone}"),
+            entry("thesis.tex", b"% \\documentclass{old}\n\\documentclass{book}"),
+            entry("notes.tex", b"% \\documentclass{article}"),
+        ];
+        assert_eq!(detect_main_file(&files), Some("thesis.tex".to_string()));
+        assert_eq!(detect_main_file(&files[..1]), Some("chapters/intro.tex".to_string()));
+    }
+
+    #[test]
+    fn test_overleaf_import_maps_settings() {
+        let png = [0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a, 0x00, 0xff];
+        let archive = zip_of(&[
+            entry("main.tex", b"\\documentclass{article}"),
+            entry("figures/logo.png", &png),
+            entry(".latexmkrc", b"$pdf_mode = 5;\n$xelatex = 'xelatex -shell-escape %O %S';\n$bibtex_use = 2;\n"),
+            entry("olsettings", br#"{"compiler": "pdflatex", "spellCheckLanguage": "en"}"#),
+        ]);
+
+        let parsed = read_archive(&archive, 1 << 20).unwrap();
+        assert_eq!(parsed.source, ArchiveSource::Overleaf);
+        assert_eq!(parsed.settings.latex_engine, Some(LatexEngine::Xelatex));
+        assert_eq!(parsed.settings.custom_args, vec!["-shell-escape".to_string()]);
+        assert_eq!(parsed.settings.main_file, Some("main.tex".to_string()));
+        assert_eq!(parsed.settings.unmapped.len(), 2);
+        assert!(parsed.settings.preserved.contains_key("latexmkrc"));
+        assert_eq!(parsed.settings.preserved["olsettings"]["spellCheckLanguage"], "en");
+
+        let logo = parsed.files.iter().find(|f| f.path == "figures/logo.png").unwrap();
+        assert_eq!(logo.bytes, png);
+    }
+
+    #[test]
+    fn test_latexmkrc_round_trip() {
+        let args = vec!["-shell-escape".to_string(), "-jobname=it's".to_string()];
+        let mut settings = ImportedSettings::default();
+        apply_latexmkrc(&latexmkrc(LatexEngine::Lualatex, &args), &mut settings);
+
+        assert_eq!(settings.latex_engine, Some(LatexEngine::Lualatex));
+        assert_eq!(settings.custom_args, args);
+        assert!(settings.unmapped.is_empty());
+    }
+
+    #[test]
+    fn test_read_archive_strips_wrapping_folder() {
+        let archive = zip_of(&[
+            entry("paper/main.tex", b"\\documentclass{article}"),
+            entry("paper/refs.bib", b"@book{}"),
+            entry("__MACOSX/paper/._main.tex", b""),
+        ]);
+
+        let parsed = read_archive(&archive, 1 << 20).unwrap();
+        assert_eq!(parsed.source, ArchiveSource::Plain);
+        let paths: Vec<&str> = parsed.files.iter().map(|f| f.path.as_str()).collect();
+        assert_eq!(paths, vec!["main.tex", "refs.bib"]);
+    }
+
+    #[test]
+    fn test_read_archive_rejects_traversal_and_oversize() {
+        let traversal = zip_of(&[entry("../escape.tex", b"x")]);
+        assert!(read_archive(&traversal, 1 << 20).is_err());
+
+        let large = zip_of(&[entry("main.tex", &[b'a'; 2048])]);
+        assert!(read_archive(&large, 1024).is_err());
+    }
+
+    #[test]
+    fn test_read_archive_takes_checksums() {
+        let files = [entry("main.tex", b"\\documentclass{article}"), entry("refs.bib", b"@book{}")];
+        let checksums = serde_json::to_vec(&ChecksumManifest::of_entries(&files)).unwrap();
+        let mut entries = files.to_vec();
+        entries.push(entry(CHECKSUMS_NAME, &checksums));
+
+        let parsed = read_archive(&zip_of(&entries), 1 << 20).unwrap();
+        assert_eq!(parsed.files.len(), 2);
+        assert!(parsed.checksums.unwrap().verify(&parsed.files).is_clean());
+
+        let unreadable = zip_of(&[entry("main.tex", b""), entry(CHECKSUMS_NAME, b"{}")]);
+        assert!(read_archive(&unreadable, 1 << 20).is_err());
+    }
+
+    #[test]
+    fn test_main_directory_prefix() {
+        let paths = vec!["src/main.tex".to_string(), "src/figures/a.png".to_string()];
+        assert_eq!(main_directory_prefix(&paths, "src/main.tex").as_deref(), Some("src/"));
+        assert_eq!(main_directory_prefix(&paths, "main.tex"), None);
+
+        let mut outside = paths;
+        outside.push("shared/macros.tex".to_string());
+        assert_eq!(main_directory_prefix(&outside, "src/main.tex"), None);
+    }
+}
@@ -0,0 +1,666 @@
+//! Cloning projects with a subset of their files
+//!
+//! A clone is a new project in one of the caller's workspaces with the
+//! source's compile settings and copies of its live files, optionally
+//! narrowed by path globs: either `include` patterns, copying only the files
+//! one of them matches, or `exclude` patterns, copying all others. Globs
+//! match paths relative to the project root; `*` and `?` stay within one
+//! path segment, `**` spans any number of them and a trailing `/` stands for
+//! everything below a directory. Matching is case-sensitive unless the
+//! request asks otherwise.
+//!
+//! Text content is copied into the new rows; content in the blob store is
+//! shared by taking another reference on its blob. Quarantined files are
+//! never copied. The clone remembers its source in `projects.cloned_from`.
+//!
+//! A filter can leave out the main file. The caller may name the clone's
+//! main file; otherwise the file most likely to be the root document is
+//! chosen and the response says so. When no file is left that could be the
+//! main file, the clone is refused instead of created uncompilable.
+
+use globset::{Glob, GlobBuilder, GlobSet, GlobSetBuilder};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use super::file::{CreateFile, File};
+use super::project::{CreateProject, Project, ProjectActivity};
+use super::project_archive::{detect_main_file, ArchiveEntry};
+use super::project_limits::{ExceededLimit, ProjectLimits};
+use super::typst::TYPST_EXTENSION;
+use super::validation::{FileName, ProjectName};
+use super::{LatexEngine, StorageStrategy};
+use crate::error::AppError;
+
+/// Most patterns a clone request may carry
+pub const MAX_CLONE_PATTERNS: usize = 64;
+
+/// Longest pattern accepted, in bytes
+pub const MAX_PATTERN_LENGTH: usize = 256;
+
+/// Project clone request
+#[derive(Debug, Clone, Deserialize, ToSchema)]
+pub struct CloneProject {
+    /// Workspace of the caller to create the clone in
+    pub workspace_id: Uuid,
+    pub name: ProjectName,
+    /// Copy only the files matching one of these globs
+    pub include: Option<Vec<String>>,
+    /// Copy every file except those matching one of these globs
+    pub exclude: Option<Vec<String>>,
+    /// Match the globs regardless of case
+    #[serde(default)]
+    pub case_insensitive: bool,
+    /// Main file of the clone; must be one of the copied files
+    pub main_file_path: Option<String>,
+}
+
+/// How the patterns of a clone select files
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum FilterMode {
+    Include,
+    Exclude,
+}
+
+/// Files one pattern decided about
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+pub struct PatternOutcome {
+    pub pattern: String,
+    pub copied: usize,
+    pub skipped: usize,
+}
+
+/// Result of cloning a project
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct CloneProjectResponse {
+    pub project: Project,
+    pub mode: FilterMode,
+    pub copied: usize,
+    pub skipped: usize,
+    /// Files no pattern matched; copied when excluding, skipped when including
+    pub unmatched: usize,
+    /// Quarantined files, which are never copied
+    pub quarantined: usize,
+    /// Files the patterns picked that were left out for going over a limit
+    /// of the clone
+    pub rejected: Vec<ExceededLimit>,
+    pub patterns: Vec<PatternOutcome>,
+    pub main_file_path: String,
+    /// Whether the source's main file was left out and another one chosen
+    pub main_file_changed: bool,
+}
+
+/// Compiled `include` or `exclude` patterns of a clone request
+#[derive(Debug, Clone)]
+pub struct PathFilter {
+    mode: FilterMode,
+    patterns: Vec<String>,
+    globs: GlobSet,
+}
+
+impl PathFilter {
+    /// Validate and compile the patterns; no patterns copy everything
+    pub fn new(
+        include: Option<&[String]>,
+        exclude: Option<&[String]>,
+        case_insensitive: bool,
+    ) -> Result<Self, AppError> {
+        let (mode, patterns) = match (include, exclude) {
+            (Some(_), Some(_)) => {
+                return Err(AppError::Validation(
+                    "Specify either include or exclude patterns, not both".to_string(),
+                ))
+            }
+            (Some([]), None) => {
+                return Err(AppError::Validation("include needs at least one pattern".to_string()))
+            }
+            (Some(patterns), None) => (FilterMode::Include, patterns),
+            (None, patterns) => (FilterMode::Exclude, patterns.unwrap_or_default()),
+        };
+        if patterns.len() > MAX_CLONE_PATTERNS {
+            return Err(AppError::Validation(format!(
+                "At most {} patterns are allowed",
+                MAX_CLONE_PATTERNS
+            )));
+        }
+
+        let mut builder = GlobSetBuilder::new();
+        for pattern in patterns {
+            builder.add(compile(pattern, case_insensitive)?);
+        }
+        let globs = builder
+            .build()
+            .map_err(|e| AppError::Validation(format!("Invalid patterns: {}", e)))?;
+
+        Ok(Self { mode, patterns: patterns.to_vec(), globs })
+    }
+
+    pub fn mode(&self) -> FilterMode {
+        self.mode
+    }
+
+    /// Indexes of the patterns matching a project path
+    pub fn matches(&self, path: &str) -> Vec<usize> {
+        self.globs.matches(relative(path))
+    }
+
+    /// Whether a file matched by these patterns is copied
+    pub fn keeps(&self, matched: &[usize]) -> bool {
+        match self.mode {
+            FilterMode::Include => !matched.is_empty(),
+            FilterMode::Exclude => matched.is_empty(),
+        }
+    }
+}
+
+/// Compile one pattern, relative to the project root
+fn compile(pattern: &str, case_insensitive: bool) -> Result<Glob, AppError> {
+    let trimmed = pattern.trim();
+    if trimmed.is_empty() {
+        return Err(AppError::Validation("Patterns must not be empty".to_string()));
+    }
+    if trimmed.len() > MAX_PATTERN_LENGTH {
+        return Err(AppError::Validation(format!(
+            "Pattern {} exceeds {} bytes",
+            trimmed, MAX_PATTERN_LENGTH
+        )));
+    }
+
+    let mut glob = relative(trimmed).to_string();
+    if glob.ends_with('/') {
+        glob.push_str("**");
+    }
+
+    GlobBuilder::new(&glob)
+        .literal_separator(true)
+        .case_insensitive(case_insensitive)
+        .build()
+        .map_err(|e| AppError::Validation(format!("Invalid pattern {}: {}", trimmed, e)))
+}
+
+/// Project path without its leading slash, as globs see it
+fn relative(path: &str) -> &str {
+    path.trim_start_matches('/')
+}
+
+/// The copied file most likely to be the root document
+fn pick_main_file(engine: LatexEngine, files: &[&File]) -> Option<String> {
+    if engine.is_typst() {
+        let extension = format!(".{}", TYPST_EXTENSION);
+        let main_name = format!("main{}", extension);
+        return files
+            .iter()
+            .map(|file| relative(&file.path))
+            .filter(|path| path.to_lowercase().ends_with(&extension))
+            .min_by_key(|path| {
+                let not_main = !(*path == main_name || path.ends_with(&format!("/{}", main_name)));
+                (path.matches('/').count(), not_main, path.to_string())
+            })
+            .and_then(|path| files.iter().find(|file| relative(&file.path) == path))
+            .map(|file| file.path.clone());
+    }
+
+    // Text lives in the row; documents in the blob store are ranked by path alone
+    let entries: Vec<ArchiveEntry> = files
+        .iter()
+        .map(|file| ArchiveEntry {
+            path: relative(&file.path).to_string(),
+            bytes: file.content.clone().into_bytes(),
+        })
+        .collect();
+    let chosen = detect_main_file(&entries)?;
+    files
+        .iter()
+        .find(|file| relative(&file.path) == chosen)
+        .map(|file| file.path.clone())
+}
+
+/// Copy a live file into another project, with `content` in place of a text
+/// file's own
+///
+/// Content in the blob store is shared by taking another reference on its
+/// blob, and the copy keeps the source's scan verdict.
+pub(crate) async fn copy_file(
+    conn: &mut sqlx::PgConnection,
+    limits: &ProjectLimits,
+    project_id: Uuid,
+    file: &File,
+    content: Option<String>,
+    user_id: Uuid,
+) -> Result<File, AppError> {
+    let copy = match (file.storage_strategy, file.content_hash.as_deref()) {
+        (StorageStrategy::External, Some(content_hash)) => {
+            let (copy, acquired) = File::create_external(
+                &mut *conn,
+                limits,
+                project_id,
+                &file.name,
+                &file.path,
+                file.content_type,
+                file.size,
+                content_hash,
+                user_id,
+            )
+            .await?;
+            if acquired.needs_content {
+                return Err(AppError::Storage(format!(
+                    "Content of {} is missing from the blob store",
+                    file.path
+                )));
+            }
+            copy
+        }
+        _ => {
+            let create_file = CreateFile {
+                name: FileName::new(&file.name)?,
+                path: file.path.clone(),
+                content: Some(content.unwrap_or_else(|| file.content.clone())),
+                content_type: Some(file.content_type),
+            };
+            File::create(&mut *conn, limits, project_id, create_file, user_id).await?
+        }
+    };
+
+    // Same bytes, same verdict
+    sqlx::query(
+        r#"
+        UPDATE files f
+        SET scan_status = s.scan_status, scan_signature = s.scan_signature, scanned_at = s.scanned_at
+        FROM files s
+        WHERE f.id = $1 AND s.id = $2
+        "#
+    )
+    .bind(copy.id)
+    .bind(file.id)
+    .execute(&mut *conn)
+    .await
+    .map_err(AppError::Database)?;
+
+    Ok(copy)
+}
+
+impl Project {
+    /// Copy a project the user can read into one of their workspaces
+    ///
+    /// Files over one of the `limits` are left out and listed, or refuse the
+    /// clone in strict mode.
+    pub async fn clone_filtered(
+        db: &sqlx::PgPool,
+        limits: &ProjectLimits,
+        source_id: Uuid,
+        user_id: Uuid,
+        request: CloneProject,
+    ) -> Result<CloneProjectResponse, AppError> {
+        let source = Self::find_by_id(db, source_id, user_id)
+            .await?
+            .ok_or_else(|| AppError::NotFound {
+                entity: "Project".to_string(),
+                id: source_id.to_string(),
+            })?;
+        let filter = PathFilter::new(
+            request.include.as_deref(),
+            request.exclude.as_deref(),
+            request.case_insensitive,
+        )?;
+
+        let files = File::list_all_for_project(db, source.id).await?;
+        let mut patterns: Vec<PatternOutcome> = filter
+            .patterns
+            .iter()
+            .map(|pattern| PatternOutcome { pattern: pattern.clone(), copied: 0, skipped: 0 })
+            .collect();
+        let mut kept = Vec::new();
+        let (mut unmatched, mut quarantined) = (0, 0);
+        for file in &files {
+            if file.is_quarantined() {
+                quarantined += 1;
+                continue;
+            }
+            let matched = filter.matches(&file.path);
+            let keep = filter.keeps(&matched);
+            if matched.is_empty() {
+                unmatched += 1;
+            }
+            for index in matched {
+                if keep {
+                    patterns[index].copied += 1;
+                } else {
+                    patterns[index].skipped += 1;
+                }
+            }
+            if keep {
+                kept.push(file);
+            }
+        }
+        let screened = limits.screen(kept, |file| (file.path.as_str(), file.size.max(0) as u64))?;
+        let (kept, rejected) = (screened.kept, screened.rejected);
+
+        let source_main = relative(&source.main_file_path);
+        let main_file_path = match request.main_file_path.as_deref() {
+            Some(requested) => kept
+                .iter()
+                .find(|file| relative(&file.path) == relative(requested))
+                .map(|file| file.path.clone())
+                .ok_or_else(|| {
+                    AppError::Validation(format!("Main file {} is not among the copied files", requested))
+                })?,
+            None => match kept.iter().find(|file| relative(&file.path) == source_main) {
+                Some(file) => file.path.clone(),
+                // The source never had its main file; the clone is no worse
+                None if !files.iter().any(|file| relative(&file.path) == source_main) => {
+                    source.main_file_path.clone()
+                }
+                None => pick_main_file(source.latex_engine, &kept).ok_or_else(|| {
+                    AppError::Validation(format!(
+                        "The patterns leave out the main file {} and no other document could replace it; \
+                         copy one or set main_file_path",
+                        source.main_file_path
+                    ))
+                })?,
+            },
+        };
+        let main_file_changed = relative(&main_file_path) != source_main;
+
+        let mut tx = db.begin().await.map_err(AppError::Database)?;
+
+        let project = Self::create(
+            &mut *tx,
+            user_id,
+            CreateProject {
+                name: request.name,
+                description: source.description.clone(),
+                is_public: Some(false),
+                main_file_path: Some(main_file_path.clone()),
+                latex_engine: Some(source.latex_engine),
+                output_format: Some(source.output_format.clone()),
+                custom_args: Some(source.custom_args.clone()),
+                bibliography_path: source.bibliography_path.clone(),
+                tags: None,
+                workspace_id: Some(request.workspace_id),
+            },
+        )
+        .await?;
+
+        let mut copies = HashMap::new();
+        for file in &kept {
+            let copy = copy_file(&mut tx, limits, project.id, file, None, user_id).await?;
+            copies.insert(file.id, copy.id);
+        }
+
+        sqlx::query("UPDATE files SET is_main = (path = $2) WHERE project_id = $1")
+            .bind(project.id)
+            .bind(&main_file_path)
+            .execute(&mut *tx)
+            .await
+            .map_err(AppError::Database)?;
+
+        let readme_file_id = source.readme_file_id.and_then(|id| copies.get(&id).copied());
+        let project = sqlx::query_as::<_, Project>(
+            r#"
+            UPDATE projects SET cloned_from = $2, readme_file_id = $3, settings = $4
+            WHERE id = $1
+            RETURNING *
+            "#
+        )
+        .bind(project.id)
+        .bind(source.id)
+        .bind(readme_file_id)
+        .bind(&source.settings)
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(AppError::Database)?;
+
+        let copied = kept.len();
+        let skipped = files.len() - copied - quarantined - rejected.len();
+        ProjectActivity::log(
+            &mut *tx,
+            project.id,
+            user_id,
+            "project_cloned",
+            "project",
+            Some(source.id),
+            Some(
+                serde_json::json!({
+                    "source_project_id": source.id,
+                    "mode": filter.mode(),
+                    "patterns": filter.patterns,
+                    "copied": copied,
+                    "skipped": skipped,
+                    "rejected": rejected.len(),
+                })
+                .to_string(),
+            ),
+        )
+        .await?;
+
+        tx.commit().await.map_err(AppError::Database)?;
+
+        Ok(CloneProjectResponse {
+            project,
+            mode: filter.mode(),
+            copied,
+            skipped,
+            unmatched,
+            quarantined,
+            rejected,
+            patterns,
+            main_file_path,
+            main_file_changed,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::project_limits::{LimitMode, ProjectLimit};
+    use crate::models::workspace::Workspace;
+    use crate::models::ContentType;
+    use crate::testing::{create_test_file, create_test_project, create_test_user, TestDb};
+
+    fn patterns(patterns: &[&str]) -> Vec<String> {
+        patterns.iter().map(|pattern| pattern.to_string()).collect()
+    }
+
+    fn kept(filter: &PathFilter, path: &str) -> bool {
+        filter.keeps(&filter.matches(path))
+    }
+
+    #[test]
+    fn test_double_star_spans_directories() {
+        let filter = PathFilter::new(None, Some(&patterns(&["solutions/**", "**/*.aux"])), false).unwrap();
+        assert_eq!(filter.mode(), FilterMode::Exclude);
+        assert!(!kept(&filter, "/solutions/sheet1.tex"));
+        assert!(!kept(&filter, "solutions/week2/answers.tex"));
+        assert!(!kept(&filter, "build/deep/main.aux"));
+        assert!(!kept(&filter, "main.aux"));
+        assert!(kept(&filter, "/main.tex"));
+        assert!(kept(&filter, "exercises/solutions.tex"));
+
+        // A single star stays within its segment, a trailing slash means the directory
+        let filter = PathFilter::new(Some(&patterns(&["chapters/*.tex", "figures/"])), None, false).unwrap();
+        assert!(kept(&filter, "chapters/intro.tex"));
+        assert!(!kept(&filter, "chapters/draft/intro.tex"));
+        assert!(kept(&filter, "/figures/plots/a.png"));
+        assert!(!kept(&filter, "main.tex"));
+        assert_eq!(filter.matches("chapters/intro.tex"), vec![0]);
+    }
+
+    #[test]
+    fn test_character_classes() {
+        let filter = PathFilter::new(Some(&patterns(&["sheet[0-9].tex", "[!_]*.bib", "part?.tex"])), None, false).unwrap();
+        assert!(kept(&filter, "sheet3.tex"));
+        assert!(!kept(&filter, "sheet10.tex"));
+        assert!(!kept(&filter, "sheetA.tex"));
+        assert!(kept(&filter, "refs.bib"));
+        assert!(!kept(&filter, "_private.bib"));
+        assert!(kept(&filter, "part1.tex"));
+        assert!(!kept(&filter, "part/.tex"));
+    }
+
+    #[test]
+    fn test_case_handling() {
+        let exclude = patterns(&["Solutions/**", "*.PDF"]);
+        let sensitive = PathFilter::new(None, Some(&exclude), false).unwrap();
+        assert!(!kept(&sensitive, "Solutions/a.tex"));
+        assert!(kept(&sensitive, "solutions/a.tex"));
+        assert!(kept(&sensitive, "scan.pdf"));
+
+        let insensitive = PathFilter::new(None, Some(&exclude), true).unwrap();
+        assert!(!kept(&insensitive, "solutions/a.tex"));
+        assert!(!kept(&insensitive, "SOLUTIONS/a.tex"));
+        assert!(!kept(&insensitive, "scan.pdf"));
+    }
+
+    #[test]
+    fn test_invalid_requests_are_rejected() {
+        let some = patterns(&["*.tex"]);
+        assert!(matches!(PathFilter::new(Some(&some), Some(&some), false), Err(AppError::Validation(_))));
+        assert!(matches!(PathFilter::new(Some(&[]), None, false), Err(AppError::Validation(_))));
+        assert!(matches!(PathFilter::new(None, Some(&patterns(&["a[b"])), false), Err(AppError::Validation(_))));
+        assert!(matches!(PathFilter::new(None, Some(&patterns(&["  "])), false), Err(AppError::Validation(_))));
+        let many = vec!["*.tex".to_string(); MAX_CLONE_PATTERNS + 1];
+        assert!(matches!(PathFilter::new(None, Some(&many), false), Err(AppError::Validation(_))));
+
+        // No patterns copy everything
+        let all = PathFilter::new(None, None, false).unwrap();
+        assert!(kept(&all, "anything/at/all.tex"));
+    }
+
+    async fn add_file(db: &sqlx::PgPool, project: &Project, user_id: Uuid, path: &str, content: &str) -> File {
+        File::create(
+            db,
+            &ProjectLimits::default(),
+            project.id,
+            CreateFile {
+                name: FileName::new(path.rsplit('/').next().unwrap()).unwrap(),
+                path: path.to_string(),
+                content: Some(content.to_string()),
+                content_type: Some(ContentType::Latex),
+            },
+            user_id,
+        )
+        .await
+        .unwrap()
+    }
+
+    fn request(workspace: &Workspace, exclude: &[&str], main_file_path: Option<&str>) -> CloneProject {
+        CloneProject {
+            workspace_id: workspace.id,
+            name: ProjectName::new("Student copy").unwrap(),
+            include: None,
+            exclude: Some(patterns(exclude)),
+            case_insensitive: false,
+            main_file_path: main_file_path.map(str::to_string),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_clone_copies_matching_files_and_settings() {
+        let Some(db) = TestDb::start().await else { return };
+        let owner = create_test_user(&db.pool).await;
+        let source = create_test_project(&db.pool, &owner, true).await;
+        create_test_file(&db.pool, &source, &owner).await;
+        add_file(&db.pool, &source, owner.id, "chapters/intro.tex", "Intro").await;
+        add_file(&db.pool, &source, owner.id, "solutions/sheet1.tex", "Answers").await;
+        let removed = add_file(&db.pool, &source, owner.id, "old.tex", "Old").await;
+        removed.soft_delete(&db.pool, owner.id).await.unwrap();
+        sqlx::query("UPDATE projects SET latex_engine = 'xelatex', custom_args = '{-shell-escape}' WHERE id = $1")
+            .bind(source.id)
+            .execute(&db.pool)
+            .await
+            .unwrap();
+
+        let student = create_test_user(&db.pool).await;
+        let workspace = Workspace::ensure_default(&db.pool, student.id).await.unwrap();
+        let limits = ProjectLimits::default();
+        let clone = Project::clone_filtered(&db.pool, &limits, source.id, student.id, request(&workspace, &["solutions/**"], None))
+            .await
+            .unwrap();
+
+        assert_eq!((clone.copied, clone.skipped, clone.unmatched), (2, 1, 2));
+        assert_eq!(
+            clone.patterns,
+            vec![PatternOutcome { pattern: "solutions/**".to_string(), copied: 0, skipped: 1 }]
+        );
+        assert!(!clone.main_file_changed);
+
+        let project = clone.project;
+        assert_eq!(project.owner_id, student.id);
+        assert_eq!(project.workspace_id, workspace.id);
+        assert_eq!(project.cloned_from, Some(source.id));
+        assert_eq!(project.latex_engine, LatexEngine::Xelatex);
+        assert_eq!(project.custom_args, vec!["-shell-escape".to_string()]);
+        assert!(!project.is_public);
+
+        let files = File::list_all_for_project(&db.pool, project.id).await.unwrap();
+        let paths: Vec<&str> = files.iter().map(|file| file.path.as_str()).collect();
+        assert_eq!(paths, vec!["chapters/intro.tex", "main.tex"]);
+        assert!(files.iter().find(|file| file.path == "main.tex").unwrap().is_main);
+        assert_eq!(files[0].content, "Intro");
+    }
+
+    #[tokio::test]
+    async fn test_clone_without_main_file_picks_or_refuses() {
+        let Some(db) = TestDb::start().await else { return };
+        let owner = create_test_user(&db.pool).await;
+        let workspace = Workspace::ensure_default(&db.pool, owner.id).await.unwrap();
+        let source = create_test_project(&db.pool, &owner, false).await;
+        create_test_file(&db.pool, &source, &owner).await;
+        add_file(&db.pool, &source, owner.id, "notes/todo.tex", "Todo").await;
+        let limits = ProjectLimits::default();
+
+        let refused = Project::clone_filtered(&db.pool, &limits, source.id, owner.id, request(&workspace, &["*.tex", "**/*.tex"], None)).await;
+        assert!(matches!(refused, Err(AppError::Validation(_))));
+
+        let missing = Project::clone_filtered(&db.pool, &limits, source.id, owner.id, request(&workspace, &["main.tex"], Some("main.tex"))).await;
+        assert!(matches!(missing, Err(AppError::Validation(_))));
+
+        add_file(&db.pool, &source, owner.id, "handout/handout.tex", "\\documentclass{article}\n").await;
+        let picked = Project::clone_filtered(&db.pool, &limits, source.id, owner.id, request(&workspace, &["main.tex"], None))
+            .await
+            .unwrap();
+        assert!(picked.main_file_changed);
+        assert_eq!(picked.main_file_path, "handout/handout.tex");
+        assert_eq!(picked.project.main_file_path, "handout/handout.tex");
+
+        let chosen = Project::clone_filtered(&db.pool, &limits, source.id, owner.id, request(&workspace, &["main.tex"], Some("/notes/todo.tex")))
+            .await
+            .unwrap();
+        assert_eq!(chosen.main_file_path, "notes/todo.tex");
+        let main = File::list_all_for_project(&db.pool, chosen.project.id)
+            .await
+            .unwrap()
+            .into_iter()
+            .filter(|file| file.is_main)
+            .map(|file| file.path)
+            .collect::<Vec<_>>();
+        assert_eq!(main, vec!["notes/todo.tex".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn test_clone_over_limits_skips_or_refuses() {
+        let Some(db) = TestDb::start().await else { return };
+        let owner = create_test_user(&db.pool).await;
+        let workspace = Workspace::ensure_default(&db.pool, owner.id).await.unwrap();
+        let source = create_test_project(&db.pool, &owner, false).await;
+        create_test_file(&db.pool, &source, &owner).await;
+        add_file(&db.pool, &source, owner.id, "chapters/one.tex", "One").await;
+        add_file(&db.pool, &source, owner.id, "chapters/two.tex", "Two").await;
+
+        let mut limits = ProjectLimits { max_path_depth: 0, ..ProjectLimits::default() };
+        let clone = Project::clone_filtered(&db.pool, &limits, source.id, owner.id, request(&workspace, &["*.bib"], None))
+            .await
+            .unwrap();
+        assert_eq!((clone.copied, clone.skipped), (1, 0));
+        let rejected: Vec<&str> = clone.rejected.iter().map(|rejected| rejected.path.as_str()).collect();
+        assert_eq!(rejected, vec!["chapters/one.tex", "chapters/two.tex"]);
+        assert!(clone.rejected.iter().all(|rejected| rejected.limit == ProjectLimit::PathDepth));
+
+        limits.import_mode = LimitMode::Strict;
+        let refused = Project::clone_filtered(&db.pool, &limits, source.id, owner.id, request(&workspace, &["*.bib"], None)).await;
+        assert!(matches!(refused, Err(AppError::LimitExceeded { ref rejected, .. }) if rejected.len() == 2));
+    }
+}
@@ -0,0 +1,833 @@
+//! Personal folders and manual order of projects
+//!
+//! Folders are a user's own view of the projects they can open: each belongs
+//! to one of the user's workspaces, and collaborators never see them. Where a
+//! user put a project is a placement, one per user and project, so a project
+//! sits in at most one of that user's folders; projects without a placement
+//! are unfiled. Deleting a folder unfiles its projects.
+//!
+//! Folders and the projects of each folder (or unfiled) keep a manual order in
+//! integer positions `POSITION_GAP` apart. A moved item takes the position
+//! halfway between its new neighbours; when they leave no room the list is
+//! renumbered first. Every change of a user's arrangement holds a transaction
+//! lock for that user, so concurrent moves from several devices apply one
+//! after the other and never end up sharing a position.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgConnection};
+use std::collections::HashMap;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use super::detail_fields::ProjectFields;
+use super::project::{Project, ProjectWithDetails, PROJECT_SORT};
+use super::validation;
+use super::workspace::Workspace;
+use super::PaginationParams;
+use crate::error::AppError;
+
+/// Distance between the positions of neighbouring items after renumbering
+pub const POSITION_GAP: i64 = 1 << 16;
+
+/// A user's folder of projects
+#[derive(Debug, Clone, Serialize, FromRow, ToSchema)]
+pub struct ProjectFolder {
+    pub id: Uuid,
+    pub workspace_id: Uuid,
+    pub owner_id: Uuid,
+    pub name: String,
+    /// Folders are listed by ascending position
+    pub position: i64,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// Where a user put a project
+#[derive(Debug, Clone, Serialize, FromRow, ToSchema)]
+pub struct ProjectPlacement {
+    pub project_id: Uuid,
+    /// `null` when unfiled
+    pub folder_id: Option<Uuid>,
+    /// Projects are listed by ascending position
+    pub position: i64,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// Folder creation request
+#[derive(Debug, Clone, Deserialize, ToSchema)]
+pub struct NewProjectFolder {
+    pub name: String,
+}
+
+/// Folder rename request
+#[derive(Debug, Clone, Deserialize, ToSchema)]
+pub struct RenameProjectFolder {
+    pub name: String,
+}
+
+/// Folder reorder request
+#[derive(Debug, Clone, Default, Deserialize, ToSchema)]
+pub struct MoveProjectFolder {
+    /// Folder to put it right after; first when unset
+    pub after: Option<Uuid>,
+}
+
+/// Project move request
+#[derive(Debug, Clone, Default, Deserialize, ToSchema)]
+pub struct MoveProject {
+    /// Folder to put it in; unfiled when unset
+    pub folder_id: Option<Uuid>,
+    /// Project of that folder to put it right after; first when unset
+    pub after: Option<Uuid>,
+}
+
+/// How to group a project list; `folder` groups by the user's folders
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ProjectGrouping {
+    Folder,
+}
+
+/// A folder with a page of its projects
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct FolderProjects {
+    pub folder: ProjectFolder,
+    pub projects: Vec<ProjectWithDetails>,
+    /// Projects in the folder, including those past the page
+    pub project_count: i64,
+}
+
+/// A page of the projects in no folder
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct UnfiledProjects {
+    pub projects: Vec<ProjectWithDetails>,
+    /// Unfiled projects, including those past the page
+    pub project_count: i64,
+}
+
+/// Projects grouped by the user's folders
+///
+/// Folders are in workspace order, then by position. Each folder's projects,
+/// like the unfiled ones, are ordered by position; unfiled projects never
+/// moved come first, in the requested sort order.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct GroupedProjects {
+    pub folders: Vec<FolderProjects>,
+    pub unfiled: UnfiledProjects,
+}
+
+impl GroupedProjects {
+    /// Projects of every folder, then the unfiled ones
+    pub fn projects_mut(&mut self) -> impl Iterator<Item = &mut ProjectWithDetails> {
+        self.folders
+            .iter_mut()
+            .flat_map(|folder| folder.projects.iter_mut())
+            .chain(self.unfiled.projects.iter_mut())
+    }
+}
+
+/// Project of a grouped list with the bucket it is in
+#[derive(FromRow)]
+struct GroupedProjectRow {
+    #[sqlx(flatten)]
+    project: Project,
+    bucket_folder_id: Option<Uuid>,
+    bucket_rank: i64,
+    bucket_size: i64,
+}
+
+/// Items kept in one manual order
+#[derive(Debug, Clone, Copy)]
+enum Order {
+    /// A user's folders in one workspace
+    Folders { workspace_id: Uuid, owner_id: Uuid },
+    /// A user's projects in one of their folders, or unfiled
+    Projects { user_id: Uuid, folder_id: Option<Uuid> },
+}
+
+/// Position for an item put at `index` among items at `positions`, or `None`
+/// when its neighbours leave no room
+fn slot(positions: &[i64], index: usize) -> Option<i64> {
+    let previous = index.checked_sub(1).map(|i| positions[i]);
+    match (previous, positions.get(index)) {
+        (None, None) => Some(POSITION_GAP),
+        (None, Some(&next)) => next.checked_sub(POSITION_GAP),
+        (Some(previous), None) => previous.checked_add(POSITION_GAP),
+        (Some(previous), Some(&next)) if next - previous > 1 => Some(previous + (next - previous) / 2),
+        (Some(_), Some(_)) => None,
+    }
+}
+
+/// Wait for the other changes of a user's arrangement, until the transaction ends
+async fn lock(conn: &mut PgConnection, user_id: Uuid) -> Result<(), AppError> {
+    sqlx::query("SELECT pg_advisory_xact_lock(hashtextextended($1, 0))")
+        .bind(format!("project_order:{}", user_id))
+        .execute(&mut *conn)
+        .await
+        .map_err(AppError::Database)?;
+    Ok(())
+}
+
+fn is_unique_violation(e: &sqlx::Error) -> bool {
+    e.as_database_error().is_some_and(|e| e.is_unique_violation())
+}
+
+fn name_taken(name: &str) -> AppError {
+    AppError::Conflict(format!("A folder named '{}' already exists in this workspace", name))
+}
+
+fn folder_not_found(folder_id: Uuid) -> AppError {
+    AppError::NotFound {
+        entity: "Folder".to_string(),
+        id: folder_id.to_string(),
+    }
+}
+
+impl Order {
+    /// Ids and positions of the items, in order
+    async fn items(&self, conn: &mut PgConnection) -> Result<Vec<(Uuid, i64)>, AppError> {
+        match *self {
+            Order::Folders { workspace_id, owner_id } => sqlx::query_as::<_, (Uuid, i64)>(
+                r#"
+                SELECT id, position FROM project_folders
+                WHERE workspace_id = $1 AND owner_id = $2
+                ORDER BY position, id
+                "#
+            )
+            .bind(workspace_id)
+            .bind(owner_id),
+            Order::Projects { user_id, folder_id } => sqlx::query_as::<_, (Uuid, i64)>(
+                r#"
+                SELECT project_id, position FROM project_placements
+                WHERE user_id = $1 AND folder_id IS NOT DISTINCT FROM $2
+                ORDER BY position, project_id
+                "#
+            )
+            .bind(user_id)
+            .bind(folder_id),
+        }
+        .fetch_all(&mut *conn)
+        .await
+        .map_err(AppError::Database)
+    }
+
+    /// Spread the items `POSITION_GAP` apart, in the order of `ids`
+    async fn renumber(&self, conn: &mut PgConnection, ids: &[Uuid]) -> Result<(), AppError> {
+        match *self {
+            Order::Folders { .. } => sqlx::query(
+                r#"
+                UPDATE project_folders f SET position = o.n * $2
+                FROM unnest($1::uuid[]) WITH ORDINALITY AS o(id, n)
+                WHERE f.id = o.id
+                "#
+            )
+            .bind(ids)
+            .bind(POSITION_GAP),
+            Order::Projects { user_id, .. } => sqlx::query(
+                r#"
+                UPDATE project_placements pl SET position = o.n * $2
+                FROM unnest($1::uuid[]) WITH ORDINALITY AS o(id, n)
+                WHERE pl.user_id = $3 AND pl.project_id = o.id
+                "#
+            )
+            .bind(ids)
+            .bind(POSITION_GAP)
+            .bind(user_id),
+        }
+        .execute(&mut *conn)
+        .await
+        .map_err(AppError::Database)?;
+        Ok(())
+    }
+
+    /// Position for `item` right after `after`, or first without it,
+    /// renumbering the other items when they leave no room
+    ///
+    /// `after` must be one of the items, or the list changed since the client
+    /// last saw it.
+    async fn position_after(&self, conn: &mut PgConnection, item: Uuid, after: Option<Uuid>) -> Result<i64, AppError> {
+        if after == Some(item) {
+            return Err(AppError::Validation("Cannot put an item after itself".to_string()));
+        }
+
+        let mut items = self.items(conn).await?;
+        items.retain(|(id, _)| *id != item);
+        let index = match after {
+            None => 0,
+            Some(after) => items.iter().position(|(id, _)| *id == after).map(|i| i + 1).ok_or_else(|| {
+                AppError::Conflict(format!("{} is no longer in this list; reload it and try again", after))
+            })?,
+        };
+
+        let positions: Vec<i64> = items.iter().map(|(_, position)| *position).collect();
+        if let Some(position) = slot(&positions, index) {
+            return Ok(position);
+        }
+
+        let ids: Vec<Uuid> = items.iter().map(|(id, _)| *id).collect();
+        self.renumber(conn, &ids).await?;
+        let positions: Vec<i64> = (1..=ids.len() as i64).map(|n| n * POSITION_GAP).collect();
+        Ok(slot(&positions, index).expect("renumbered items are spread apart"))
+    }
+}
+
+impl ProjectFolder {
+    /// The user's folders in a workspace, in order
+    pub async fn list(db: &sqlx::PgPool, workspace_id: Uuid, owner_id: Uuid) -> Result<Vec<Self>, AppError> {
+        Workspace::find_by_id(db, workspace_id, owner_id).await?;
+
+        sqlx::query_as::<_, ProjectFolder>(
+            r#"
+            SELECT * FROM project_folders
+            WHERE workspace_id = $1 AND owner_id = $2
+            ORDER BY position, id
+            "#
+        )
+        .bind(workspace_id)
+        .bind(owner_id)
+        .fetch_all(db)
+        .await
+        .map_err(AppError::Database)
+    }
+
+    /// Create a folder after the user's other folders of the workspace
+    pub async fn create<'a>(
+        db: impl sqlx::Acquire<'a, Database = sqlx::Postgres>,
+        workspace_id: Uuid,
+        owner_id: Uuid,
+        name: &str,
+    ) -> Result<Self, AppError> {
+        let name = validation::validate_name("Folder name", name)?;
+
+        let mut tx = db.begin().await.map_err(AppError::Database)?;
+        Workspace::find_by_id(&mut *tx, workspace_id, owner_id).await?;
+        lock(&mut tx, owner_id).await?;
+
+        let order = Order::Folders { workspace_id, owner_id };
+        let last = order.items(&mut tx).await?.last().map(|(id, _)| *id);
+        // The new folder has no id yet; the nil id stands for it
+        let position = order.position_after(&mut tx, Uuid::nil(), last).await?;
+
+        let folder = sqlx::query_as::<_, ProjectFolder>(
+            r#"
+            INSERT INTO project_folders (workspace_id, owner_id, name, position)
+            VALUES ($1, $2, $3, $4)
+            RETURNING *
+            "#
+        )
+        .bind(workspace_id)
+        .bind(owner_id)
+        .bind(&name)
+        .bind(position)
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(|e| if is_unique_violation(&e) { name_taken(&name) } else { AppError::Database(e) })?;
+
+        tx.commit().await.map_err(AppError::Database)?;
+        Ok(folder)
+    }
+
+    /// Rename one of the user's folders
+    pub async fn rename(
+        db: impl sqlx::PgExecutor<'_>,
+        folder_id: Uuid,
+        workspace_id: Uuid,
+        owner_id: Uuid,
+        name: &str,
+    ) -> Result<Self, AppError> {
+        let name = validation::validate_name("Folder name", name)?;
+
+        sqlx::query_as::<_, ProjectFolder>(
+            r#"
+            UPDATE project_folders SET name = $4, updated_at = NOW()
+            WHERE id = $1 AND workspace_id = $2 AND owner_id = $3
+            RETURNING *
+            "#
+        )
+        .bind(folder_id)
+        .bind(workspace_id)
+        .bind(owner_id)
+        .bind(&name)
+        .fetch_optional(db)
+        .await
+        .map_err(|e| if is_unique_violation(&e) { name_taken(&name) } else { AppError::Database(e) })?
+        .ok_or_else(|| folder_not_found(folder_id))
+    }
+
+    /// Put one of the user's folders right after another, or first
+    pub async fn move_after<'a>(
+        db: impl sqlx::Acquire<'a, Database = sqlx::Postgres>,
+        folder_id: Uuid,
+        workspace_id: Uuid,
+        owner_id: Uuid,
+        after: Option<Uuid>,
+    ) -> Result<Self, AppError> {
+        let mut tx = db.begin().await.map_err(AppError::Database)?;
+        lock(&mut tx, owner_id).await?;
+
+        let order = Order::Folders { workspace_id, owner_id };
+        if !order.items(&mut tx).await?.iter().any(|(id, _)| *id == folder_id) {
+            return Err(folder_not_found(folder_id));
+        }
+        let position = order.position_after(&mut tx, folder_id, after).await?;
+
+        let folder = sqlx::query_as::<_, ProjectFolder>(
+            "UPDATE project_folders SET position = $2, updated_at = NOW() WHERE id = $1 RETURNING *"
+        )
+        .bind(folder_id)
+        .bind(position)
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(AppError::Database)?;
+
+        tx.commit().await.map_err(AppError::Database)?;
+        Ok(folder)
+    }
+
+    /// Delete one of the user's folders, moving its projects to the end of
+    /// the unfiled ones
+    ///
+    /// Returns how many projects were unfiled.
+    pub async fn delete<'a>(
+        db: impl sqlx::Acquire<'a, Database = sqlx::Postgres>,
+        folder_id: Uuid,
+        workspace_id: Uuid,
+        owner_id: Uuid,
+    ) -> Result<u64, AppError> {
+        let mut tx = db.begin().await.map_err(AppError::Database)?;
+        lock(&mut tx, owner_id).await?;
+
+        let exists = sqlx::query_scalar::<_, bool>(
+            "SELECT EXISTS (SELECT 1 FROM project_folders WHERE id = $1 AND workspace_id = $2 AND owner_id = $3)"
+        )
+        .bind(folder_id)
+        .bind(workspace_id)
+        .bind(owner_id)
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(AppError::Database)?;
+        if !exists {
+            return Err(folder_not_found(folder_id));
+        }
+
+        let unfiled = Order::Projects { user_id: owner_id, folder_id: None };
+        let last = unfiled.items(&mut tx).await?.last().map_or(0, |(_, position)| *position);
+        let moved = sqlx::query(
+            r#"
+            UPDATE project_placements pl
+            SET folder_id = NULL, position = $3 + o.n * $4, updated_at = NOW()
+            FROM (
+                SELECT project_id, ROW_NUMBER() OVER (ORDER BY position, project_id) AS n
+                FROM project_placements
+                WHERE user_id = $1 AND folder_id = $2
+            ) o
+            WHERE pl.user_id = $1 AND pl.project_id = o.project_id
+            "#
+        )
+        .bind(owner_id)
+        .bind(folder_id)
+        .bind(last)
+        .bind(POSITION_GAP)
+        .execute(&mut *tx)
+        .await
+        .map_err(AppError::Database)?
+        .rows_affected();
+
+        sqlx::query("DELETE FROM project_folders WHERE id = $1")
+            .bind(folder_id)
+            .execute(&mut *tx)
+            .await
+            .map_err(AppError::Database)?;
+
+        tx.commit().await.map_err(AppError::Database)?;
+        Ok(moved)
+    }
+}
+
+impl ProjectPlacement {
+    /// Put a project the user can open into one of their folders, or unfile
+    /// it, right after another project there or first
+    pub async fn move_project<'a>(
+        db: impl sqlx::Acquire<'a, Database = sqlx::Postgres>,
+        user_id: Uuid,
+        project_id: Uuid,
+        request: &MoveProject,
+    ) -> Result<Self, AppError> {
+        let mut tx = db.begin().await.map_err(AppError::Database)?;
+        lock(&mut tx, user_id).await?;
+
+        if let Some(folder_id) = request.folder_id {
+            let owned = sqlx::query_scalar::<_, bool>(
+                "SELECT EXISTS (SELECT 1 FROM project_folders WHERE id = $1 AND owner_id = $2)"
+            )
+            .bind(folder_id)
+            .bind(user_id)
+            .fetch_one(&mut *tx)
+            .await
+            .map_err(AppError::Database)?;
+            if !owned {
+                return Err(folder_not_found(folder_id));
+            }
+        } else {
+            Self::place_unfiled(&mut tx, user_id).await?;
+        }
+
+        let order = Order::Projects { user_id, folder_id: request.folder_id };
+        let position = order.position_after(&mut tx, project_id, request.after).await?;
+
+        let placement = sqlx::query_as::<_, ProjectPlacement>(
+            r#"
+            INSERT INTO project_placements (user_id, project_id, folder_id, position)
+            VALUES ($1, $2, $3, $4)
+            ON CONFLICT (user_id, project_id) DO UPDATE
+            SET folder_id = EXCLUDED.folder_id, position = EXCLUDED.position, updated_at = NOW()
+            RETURNING *
+            "#
+        )
+        .bind(user_id)
+        .bind(project_id)
+        .bind(request.folder_id)
+        .bind(position)
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(AppError::Database)?;
+
+        tx.commit().await.map_err(AppError::Database)?;
+        Ok(placement)
+    }
+
+    /// Give the user's unfiled projects that were never moved positions
+    /// ahead of the others, in the order they are listed in
+    ///
+    /// Unplaced projects are listed first, most recently updated on top; once
+    /// the user arranges the unfiled list they need positions to be arranged
+    /// among.
+    async fn place_unfiled(conn: &mut PgConnection, user_id: Uuid) -> Result<(), AppError> {
+        sqlx::query(
+            r#"
+            INSERT INTO project_placements (user_id, project_id, folder_id, position)
+            SELECT $1, p.id, NULL,
+                   COALESCE((SELECT MIN(position) FROM project_placements WHERE user_id = $1 AND folder_id IS NULL), 0)
+                   - $2 * (COUNT(*) OVER () - ROW_NUMBER() OVER (ORDER BY p.updated_at DESC, p.id DESC) + 1)
+            FROM projects p
+            WHERE p.purging_at IS NULL AND (
+                p.owner_id = $1 OR
+                p.id IN (
+                    SELECT project_id FROM project_collaborators
+                    WHERE user_id = $1
+                )
+            ) AND NOT EXISTS (
+                SELECT 1 FROM project_placements pl
+                WHERE pl.user_id = $1 AND pl.project_id = p.id
+            )
+            "#
+        )
+        .bind(user_id)
+        .bind(POSITION_GAP)
+        .execute(&mut *conn)
+        .await
+        .map_err(AppError::Database)?;
+        Ok(())
+    }
+
+    /// The projects a user owns or collaborates on, and those they filed,
+    /// grouped by their folders
+    ///
+    /// One query ranks the projects within their folder, or unfiled, and
+    /// counts each bucket; `params` pages and sorts every bucket on its own,
+    /// after the manual positions.
+    pub async fn grouped_for_user(
+        db: &sqlx::PgPool,
+        user_id: Uuid,
+        params: &PaginationParams,
+        fields: ProjectFields,
+    ) -> Result<GroupedProjects, AppError> {
+        let order_by = params.order_by(&PROJECT_SORT)?;
+
+        let folders = sqlx::query_as::<_, ProjectFolder>(
+            r#"
+            SELECT f.* FROM project_folders f
+            JOIN workspaces w ON w.id = f.workspace_id
+            WHERE f.owner_id = $1
+            ORDER BY w.created_at, w.id, f.position, f.id
+            "#
+        )
+        .bind(user_id)
+        .fetch_all(db)
+        .await
+        .map_err(AppError::Database)?;
+
+        let rows = sqlx::query_as::<_, GroupedProjectRow>(&format!(
+            r#"
+            SELECT * FROM (
+                SELECT p.*, pl.folder_id AS bucket_folder_id,
+                       ROW_NUMBER() OVER (
+                           PARTITION BY pl.folder_id
+                           ORDER BY pl.position ASC NULLS FIRST, {}
+                       ) AS bucket_rank,
+                       COUNT(*) OVER (PARTITION BY pl.folder_id) AS bucket_size
+                FROM projects p
+                JOIN users u ON u.id = p.owner_id
+                LEFT JOIN project_placements pl ON pl.project_id = p.id AND pl.user_id = $1
+                WHERE p.purging_at IS NULL AND (
+                    p.owner_id = $1 OR
+                    p.id IN (
+                        SELECT project_id FROM project_collaborators
+                        WHERE user_id = $1
+                    ) OR
+                    (p.is_public = true AND pl.project_id IS NOT NULL)
+                )
+            ) ranked
+            WHERE bucket_rank <= $2::bigint + $3::bigint AND (bucket_rank > $2 OR bucket_rank = 1)
+            ORDER BY bucket_rank
+            "#,
+            order_by
+        ))
+        .bind(user_id)
+        .bind(params.offset() as i64)
+        .bind(params.limit() as i64)
+        .fetch_all(db)
+        .await
+        .map_err(AppError::Database)?;
+
+        // The first project of each bucket comes along on every page so that
+        // buckets the page is past the end of still have their count
+        let mut by_folder: HashMap<Option<Uuid>, (Vec<ProjectWithDetails>, i64)> = HashMap::new();
+        let mut buckets = Vec::new();
+        let mut page = Vec::new();
+        for row in rows {
+            by_folder.entry(row.bucket_folder_id).or_insert_with(|| (Vec::new(), row.bucket_size));
+            if row.bucket_rank > params.offset() as i64 {
+                buckets.push(row.bucket_folder_id);
+                page.push(row.project);
+            }
+        }
+
+        let projects = Project::with_details(db, page, fields).await?;
+        for (folder_id, project) in buckets.into_iter().zip(projects) {
+            if let Some(bucket) = by_folder.get_mut(&folder_id) {
+                bucket.0.push(project);
+            }
+        }
+
+        let folders = folders
+            .into_iter()
+            .map(|folder| {
+                let (projects, project_count) = by_folder.remove(&Some(folder.id)).unwrap_or_default();
+                FolderProjects { folder, projects, project_count }
+            })
+            .collect();
+        let (projects, project_count) = by_folder.remove(&None).unwrap_or_default();
+
+        Ok(GroupedProjects {
+            folders,
+            unfiled: UnfiledProjects { projects, project_count },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::UserRole;
+    use crate::testing::{add_collaborator, create_test_project, create_test_user, TestDb};
+
+    fn into_folder(folder: &ProjectFolder, after: Option<Uuid>) -> MoveProject {
+        MoveProject { folder_id: Some(folder.id), after }
+    }
+
+    async fn arrangement(db: &sqlx::PgPool, user_id: Uuid, folder_id: Option<Uuid>) -> Vec<(Uuid, i64)> {
+        let mut conn = db.acquire().await.unwrap();
+        Order::Projects { user_id, folder_id }.items(&mut conn).await.unwrap()
+    }
+
+    fn ids(projects: &[ProjectWithDetails]) -> Vec<Uuid> {
+        projects.iter().map(|project| project.project.id).collect()
+    }
+
+    fn assert_increasing(items: &[(Uuid, i64)]) {
+        assert!(items.windows(2).all(|pair| pair[0].1 < pair[1].1), "positions not distinct: {:?}", items);
+    }
+
+    #[test]
+    fn test_slot_between_neighbours() {
+        assert_eq!(slot(&[], 0), Some(POSITION_GAP));
+        assert_eq!(slot(&[10, 20], 0), Some(10 - POSITION_GAP));
+        assert_eq!(slot(&[10, 20], 1), Some(15));
+        assert_eq!(slot(&[10, 20], 2), Some(20 + POSITION_GAP));
+        assert_eq!(slot(&[10, 11], 1), None);
+        assert_eq!(slot(&[i64::MIN + 1], 0), None);
+    }
+
+    #[tokio::test]
+    async fn test_folders_are_personal_and_deleting_unfiles() {
+        let Some(db) = TestDb::start().await else { return };
+        let owner = create_test_user(&db.pool).await;
+        let collaborator = create_test_user(&db.pool).await;
+        let project = create_test_project(&db.pool, &owner, false).await;
+        add_collaborator(&db.pool, &project, &collaborator, UserRole::Editor).await;
+        let workspace = project.workspace_id;
+
+        let papers = ProjectFolder::create(&db.pool, workspace, owner.id, "Papers").await.unwrap();
+        let talks = ProjectFolder::create(&db.pool, workspace, owner.id, "Talks").await.unwrap();
+        assert!(papers.position < talks.position);
+        let duplicate = ProjectFolder::create(&db.pool, workspace, owner.id, "papers").await;
+        assert!(matches!(duplicate, Err(AppError::Conflict(_))), "{:?}", duplicate);
+        let slides = ProjectFolder::rename(&db.pool, talks.id, workspace, owner.id, "Slides").await.unwrap();
+        assert_eq!(slides.name, "Slides");
+
+        // Nobody else can touch the folders or file projects in them
+        let foreign = ProjectFolder::rename(&db.pool, papers.id, workspace, collaborator.id, "Mine").await;
+        assert!(matches!(foreign, Err(AppError::NotFound { .. })), "{:?}", foreign);
+        let foreign = ProjectPlacement::move_project(&db.pool, collaborator.id, project.id, &into_folder(&papers, None)).await;
+        assert!(matches!(foreign, Err(AppError::NotFound { .. })), "{:?}", foreign);
+
+        ProjectPlacement::move_project(&db.pool, owner.id, project.id, &into_folder(&papers, None)).await.unwrap();
+        let params = PaginationParams::default();
+        let grouped = ProjectPlacement::grouped_for_user(&db.pool, owner.id, &params, ProjectFields::ALL).await.unwrap();
+        assert_eq!(ids(&grouped.folders[0].projects), vec![project.id]);
+        assert!(grouped.unfiled.projects.is_empty());
+
+        let theirs = ProjectPlacement::grouped_for_user(&db.pool, collaborator.id, &params, ProjectFields::ALL).await.unwrap();
+        assert!(theirs.folders.is_empty());
+        assert_eq!(ids(&theirs.unfiled.projects), vec![project.id]);
+
+        assert_eq!(ProjectFolder::delete(&db.pool, papers.id, workspace, owner.id).await.unwrap(), 1);
+        let grouped = ProjectPlacement::grouped_for_user(&db.pool, owner.id, &params, ProjectFields::ALL).await.unwrap();
+        assert_eq!(grouped.folders.len(), 1);
+        assert_eq!(grouped.folders[0].folder.id, slides.id);
+        assert_eq!(ids(&grouped.unfiled.projects), vec![project.id]);
+        assert!(Project::has_access(&db.pool, project.id, owner.id).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_grouped_listing_orders_and_pages_each_folder() {
+        let Some(db) = TestDb::start().await else { return };
+        let user = create_test_user(&db.pool).await;
+        let stranger = create_test_user(&db.pool).await;
+        let a = create_test_project(&db.pool, &user, false).await;
+        let b = create_test_project(&db.pool, &user, false).await;
+        let c = create_test_project(&db.pool, &user, false).await;
+        let public = create_test_project(&db.pool, &stranger, true).await;
+        let folder = ProjectFolder::create(&db.pool, a.workspace_id, user.id, "Thesis").await.unwrap();
+        let empty = ProjectFolder::create(&db.pool, a.workspace_id, user.id, "Later").await.unwrap();
+
+        ProjectPlacement::move_project(&db.pool, user.id, a.id, &into_folder(&folder, None)).await.unwrap();
+        ProjectPlacement::move_project(&db.pool, user.id, b.id, &into_folder(&folder, Some(a.id))).await.unwrap();
+        ProjectPlacement::move_project(&db.pool, user.id, b.id, &into_folder(&folder, None)).await.unwrap();
+        ProjectFolder::move_after(&db.pool, empty.id, a.workspace_id, user.id, None).await.unwrap();
+
+        let page = |page| PaginationParams { page: Some(page), limit: Some(1), ..Default::default() };
+        let grouped = ProjectPlacement::grouped_for_user(&db.pool, user.id, &page(1), ProjectFields::ALL).await.unwrap();
+        let folders: Vec<Uuid> = grouped.folders.iter().map(|group| group.folder.id).collect();
+        assert_eq!(folders, vec![empty.id, folder.id]);
+        assert_eq!(grouped.folders[0].project_count, 0);
+        assert_eq!(ids(&grouped.folders[1].projects), vec![b.id]);
+        assert_eq!(grouped.folders[1].project_count, 2);
+        assert_eq!(ids(&grouped.unfiled.projects), vec![c.id]);
+        assert_eq!(grouped.unfiled.project_count, 1);
+
+        // Buckets the page is past the end of keep their count
+        let grouped = ProjectPlacement::grouped_for_user(&db.pool, user.id, &page(2), ProjectFields::ALL).await.unwrap();
+        assert_eq!(ids(&grouped.folders[1].projects), vec![a.id]);
+        assert!(grouped.unfiled.projects.is_empty());
+        assert_eq!(grouped.unfiled.project_count, 1);
+
+        // Public projects of others show up once filed
+        ProjectPlacement::move_project(&db.pool, user.id, public.id, &into_folder(&folder, Some(a.id))).await.unwrap();
+        let params = PaginationParams::default();
+        let grouped = ProjectPlacement::grouped_for_user(&db.pool, user.id, &params, ProjectFields::ALL).await.unwrap();
+        assert_eq!(ids(&grouped.folders[1].projects), vec![b.id, a.id, public.id]);
+
+        let moved = ProjectPlacement::move_project(&db.pool, user.id, a.id, &into_folder(&folder, Some(c.id))).await;
+        assert!(matches!(moved, Err(AppError::Conflict(_))), "{:?}", moved);
+    }
+
+    #[tokio::test]
+    async fn test_unfiled_projects_keep_their_order_once_arranged() {
+        let Some(db) = TestDb::start().await else { return };
+        let user = create_test_user(&db.pool).await;
+        let a = create_test_project(&db.pool, &user, false).await;
+        let b = create_test_project(&db.pool, &user, false).await;
+        let c = create_test_project(&db.pool, &user, false).await;
+        let params = PaginationParams::default();
+
+        let grouped = ProjectPlacement::grouped_for_user(&db.pool, user.id, &params, ProjectFields::ALL).await.unwrap();
+        assert_eq!(ids(&grouped.unfiled.projects), vec![c.id, b.id, a.id]);
+
+        let unfiled = MoveProject { folder_id: None, after: Some(a.id) };
+        ProjectPlacement::move_project(&db.pool, user.id, c.id, &unfiled).await.unwrap();
+        let grouped = ProjectPlacement::grouped_for_user(&db.pool, user.id, &params, ProjectFields::ALL).await.unwrap();
+        assert_eq!(ids(&grouped.unfiled.projects), vec![b.id, a.id, c.id]);
+
+        // A new project is listed on top of the arranged ones
+        let d = create_test_project(&db.pool, &user, false).await;
+        let grouped = ProjectPlacement::grouped_for_user(&db.pool, user.id, &params, ProjectFields::ALL).await.unwrap();
+        assert_eq!(ids(&grouped.unfiled.projects), vec![d.id, b.id, a.id, c.id]);
+    }
+
+    #[tokio::test]
+    async fn test_interleaved_moves_keep_positions_distinct() {
+        let Some(db) = TestDb::start().await else { return };
+        let user = create_test_user(&db.pool).await;
+        let anchor = create_test_project(&db.pool, &user, false).await;
+        let folder = ProjectFolder::create(&db.pool, anchor.workspace_id, user.id, "Drafts").await.unwrap();
+        ProjectPlacement::move_project(&db.pool, user.id, anchor.id, &into_folder(&folder, None)).await.unwrap();
+
+        let mut projects = Vec::new();
+        for _ in 0..12 {
+            projects.push(create_test_project(&db.pool, &user, false).await.id);
+        }
+
+        // Everything dropped right after the anchor at once ends up there
+        let moves = projects.iter().map(|&project_id| {
+            let (pool, request) = (db.pool.clone(), into_folder(&folder, Some(anchor.id)));
+            tokio::spawn(async move { ProjectPlacement::move_project(&pool, user.id, project_id, &request).await })
+        });
+        for placed in futures::future::join_all(moves).await {
+            placed.unwrap().unwrap();
+        }
+        let filed = arrangement(&db.pool, user.id, Some(folder.id)).await;
+        assert_increasing(&filed);
+        assert_eq!(filed.len(), 13);
+        assert_eq!(filed[0].0, anchor.id);
+
+        // Half leave the folder while the rest move within it
+        let moves = projects.iter().enumerate().map(|(i, &project_id)| {
+            let pool = db.pool.clone();
+            let request = if i % 2 == 0 { MoveProject::default() } else { into_folder(&folder, Some(anchor.id)) };
+            tokio::spawn(async move { ProjectPlacement::move_project(&pool, user.id, project_id, &request).await })
+        });
+        for placed in futures::future::join_all(moves).await {
+            placed.unwrap().unwrap();
+        }
+        let filed = arrangement(&db.pool, user.id, Some(folder.id)).await;
+        let unfiled = arrangement(&db.pool, user.id, None).await;
+        assert_increasing(&filed);
+        assert_increasing(&unfiled);
+        assert_eq!(filed.len(), 7);
+        assert_eq!(unfiled.len(), 6);
+        for (i, project_id) in projects.iter().enumerate() {
+            let listed = if i % 2 == 0 { &unfiled } else { &filed };
+            assert!(listed.iter().any(|(id, _)| id == project_id));
+        }
+
+        // Dropping into the same gap over and over renumbers the folder
+        for &project_id in &projects {
+            ProjectPlacement::move_project(&db.pool, user.id, project_id, &into_folder(&folder, Some(anchor.id)))
+                .await
+                .unwrap();
+        }
+        for &project_id in &projects {
+            ProjectPlacement::move_project(&db.pool, user.id, project_id, &into_folder(&folder, Some(anchor.id)))
+                .await
+                .unwrap();
+        }
+        let filed = arrangement(&db.pool, user.id, Some(folder.id)).await;
+        assert_increasing(&filed);
+        let order: Vec<Uuid> = filed.iter().map(|(id, _)| *id).collect();
+        let expected: Vec<Uuid> = std::iter::once(anchor.id).chain(projects.iter().rev().copied()).collect();
+        assert_eq!(order, expected);
+    }
+}
@@ -0,0 +1,375 @@
+//! Read-only freezes of projects
+//!
+//! After submitting a paper the owner can freeze its project so the submitted
+//! version is not edited by accident. While frozen, every change to files is
+//! refused with `ProjectFrozen` (HTTP 423), including edits in collaboration
+//! sessions, whose participants are told the session is now read-only.
+//! Collaborators can still be managed, and compiling is allowed since it only
+//! reads the content. A freeze can end on its own at `unfreeze_at`; it stops
+//! applying at that time and `AutoUnfreezeTask` removes it and logs the end.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use std::time::Duration;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use super::audit::AuditEvent;
+use super::project::ProjectActivity;
+use super::session_access;
+use crate::error::AppError;
+
+/// Time between two passes of the auto-unfreeze task
+const AUTO_UNFREEZE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Session status announced when a session's project is frozen
+pub const SESSION_READ_ONLY: &str = "read_only";
+
+/// Session status announced when a session's project is unfrozen
+pub const SESSION_READ_WRITE: &str = "read_write";
+
+/// Freeze of a project
+#[derive(Debug, Clone, Serialize, FromRow, ToSchema)]
+pub struct ProjectFreeze {
+    pub project_id: Uuid,
+    pub reason: Option<String>,
+    pub frozen_by: Uuid,
+    pub frozen_at: DateTime<Utc>,
+    /// When the freeze ends on its own
+    pub unfreeze_at: Option<DateTime<Utc>>,
+}
+
+/// Freeze request
+#[derive(Debug, Clone, Default, Deserialize, ToSchema)]
+pub struct FreezeProject {
+    /// Shown to anyone whose change is refused, e.g. "Submitted to ICML"
+    pub reason: Option<String>,
+    /// Unfreeze automatically at this time
+    pub unfreeze_at: Option<DateTime<Utc>>,
+}
+
+impl ProjectFreeze {
+    /// The freeze in effect on a project
+    pub async fn find(db: impl sqlx::PgExecutor<'_>, project_id: Uuid) -> Result<Option<Self>, AppError> {
+        sqlx::query_as::<_, ProjectFreeze>(
+            r#"
+            SELECT * FROM project_freezes
+            WHERE project_id = $1 AND (unfreeze_at IS NULL OR unfreeze_at > NOW())
+            "#
+        )
+        .bind(project_id)
+        .fetch_optional(db)
+        .await
+        .map_err(AppError::Database)
+    }
+
+    /// Freezes in effect for any of the given projects
+    pub async fn find_many(db: &sqlx::PgPool, project_ids: &[Uuid]) -> Result<Vec<Self>, AppError> {
+        sqlx::query_as::<_, ProjectFreeze>(
+            r#"
+            SELECT * FROM project_freezes
+            WHERE project_id = ANY($1) AND (unfreeze_at IS NULL OR unfreeze_at > NOW())
+            "#
+        )
+        .bind(project_ids)
+        .fetch_all(db)
+        .await
+        .map_err(AppError::Database)
+    }
+
+    /// Refuse changes to the files of a frozen project
+    pub async fn ensure_writable(db: impl sqlx::PgExecutor<'_>, project_id: Uuid) -> Result<(), AppError> {
+        match Self::find(db, project_id).await? {
+            Some(freeze) => Err(freeze.error()),
+            None => Ok(()),
+        }
+    }
+
+    /// Refuse edits in a session on a frozen project
+    pub async fn ensure_session_writable(db: impl sqlx::PgExecutor<'_>, session_id: Uuid) -> Result<(), AppError> {
+        let freeze = sqlx::query_as::<_, ProjectFreeze>(
+            r#"
+            SELECT f.* FROM project_freezes f
+            JOIN collaboration_sessions s ON s.project_id = f.project_id
+            WHERE s.id = $1 AND (f.unfreeze_at IS NULL OR f.unfreeze_at > NOW())
+            "#
+        )
+        .bind(session_id)
+        .fetch_optional(db)
+        .await
+        .map_err(AppError::Database)?;
+
+        match freeze {
+            Some(freeze) => Err(freeze.error()),
+            None => Ok(()),
+        }
+    }
+
+    pub fn error(&self) -> AppError {
+        AppError::ProjectFrozen(
+            self.reason.clone().unwrap_or_else(|| "changes are disabled by the owner".to_string()),
+        )
+    }
+
+    /// Freeze a project, or change the reason or end of its freeze
+    pub async fn freeze(
+        db: &sqlx::PgPool,
+        project_id: Uuid,
+        user_id: Uuid,
+        request: FreezeProject,
+    ) -> Result<Self, AppError> {
+        let reason = request.reason.map(|reason| reason.trim().to_string()).filter(|reason| !reason.is_empty());
+        if request.unfreeze_at.is_some_and(|at| at <= Utc::now()) {
+            return Err(AppError::Validation("The unfreeze time must be in the future".to_string()));
+        }
+
+        let mut tx = db.begin().await.map_err(AppError::Database)?;
+
+        let freeze = sqlx::query_as::<_, ProjectFreeze>(
+            r#"
+            INSERT INTO project_freezes (project_id, reason, frozen_by, frozen_at, unfreeze_at)
+            VALUES ($1, $2, $3, NOW(), $4)
+            ON CONFLICT (project_id) DO UPDATE SET
+                reason = EXCLUDED.reason,
+                frozen_by = EXCLUDED.frozen_by,
+                frozen_at = EXCLUDED.frozen_at,
+                unfreeze_at = EXCLUDED.unfreeze_at
+            RETURNING *
+            "#
+        )
+        .bind(project_id)
+        .bind(&reason)
+        .bind(user_id)
+        .bind(request.unfreeze_at)
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(AppError::Database)?;
+
+        AuditEvent::record(
+            &mut *tx,
+            Some(user_id),
+            "project_frozen",
+            "project",
+            Some(project_id),
+            Some(project_id),
+            serde_json::json!({ "reason": freeze.reason, "unfreeze_at": freeze.unfreeze_at }),
+        )
+        .await?;
+
+        tx.commit().await.map_err(AppError::Database)?;
+
+        ProjectActivity::log(
+            db,
+            project_id,
+            user_id,
+            "project_frozen",
+            "project",
+            Some(project_id),
+            freeze.reason.clone(),
+        )
+        .await?;
+        announce_to_sessions(db, project_id, SESSION_READ_ONLY).await;
+
+        Ok(freeze)
+    }
+
+    /// Lift the freeze of a project; `user_id` is unset when it ran out on its own.
+    /// Returns false when the project was not frozen.
+    pub async fn unfreeze(db: &sqlx::PgPool, project_id: Uuid, user_id: Option<Uuid>) -> Result<bool, AppError> {
+        let mut tx = db.begin().await.map_err(AppError::Database)?;
+
+        let lifted = sqlx::query_as::<_, ProjectFreeze>(
+            "DELETE FROM project_freezes WHERE project_id = $1 RETURNING *"
+        )
+        .bind(project_id)
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(AppError::Database)?;
+
+        let Some(lifted) = lifted else {
+            return Ok(false);
+        };
+
+        AuditEvent::record(
+            &mut *tx,
+            user_id,
+            "project_unfrozen",
+            "project",
+            Some(project_id),
+            Some(project_id),
+            serde_json::json!({ "automatic": user_id.is_none(), "frozen_at": lifted.frozen_at }),
+        )
+        .await?;
+
+        tx.commit().await.map_err(AppError::Database)?;
+
+        // Activity needs a user; an automatic unfreeze is attributed to whoever froze the project
+        ProjectActivity::log(
+            db,
+            project_id,
+            user_id.unwrap_or(lifted.frozen_by),
+            "project_unfrozen",
+            "project",
+            Some(project_id),
+            None,
+        )
+        .await?;
+        announce_to_sessions(db, project_id, SESSION_READ_WRITE).await;
+
+        Ok(true)
+    }
+
+    /// Lift the freezes whose time is up
+    pub async fn unfreeze_expired(db: &sqlx::PgPool) -> Result<u64, AppError> {
+        let expired = sqlx::query_scalar::<_, Uuid>(
+            "SELECT project_id FROM project_freezes WHERE unfreeze_at <= NOW()"
+        )
+        .fetch_all(db)
+        .await
+        .map_err(AppError::Database)?;
+
+        let mut lifted = 0;
+        for project_id in expired {
+            if Self::unfreeze(db, project_id, None).await? {
+                lifted += 1;
+            }
+        }
+
+        Ok(lifted)
+    }
+}
+
+/// Tell the participants of the project's active sessions whether they can edit
+async fn announce_to_sessions(db: &sqlx::PgPool, project_id: Uuid, status: &str) {
+    let sessions = sqlx::query_scalar::<_, Uuid>(
+        "SELECT id FROM collaboration_sessions WHERE project_id = $1 AND is_active = true"
+    )
+    .bind(project_id)
+    .fetch_all(db)
+    .await;
+
+    let sessions = match sessions {
+        Ok(sessions) => sessions,
+        Err(e) => {
+            tracing::warn!("Failed to list sessions of project {}: {}", project_id, e);
+            return;
+        }
+    };
+
+    for session_id in sessions {
+        if let Err(e) = session_access::announce_status(db, session_id, status).await {
+            tracing::warn!("Failed to announce {} to session {}: {}", status, session_id, e);
+        }
+    }
+}
+
+/// Lifts freezes at their unfreeze time
+pub struct AutoUnfreezeTask;
+
+impl crate::tasks::PeriodicTask for AutoUnfreezeTask {
+    fn name(&self) -> &'static str {
+        "project_auto_unfreeze"
+    }
+
+    fn interval(&self) -> Duration {
+        AUTO_UNFREEZE_INTERVAL
+    }
+
+    fn run<'a>(
+        &'a self,
+        state: &'a crate::server::AppState,
+    ) -> futures::future::BoxFuture<'a, Result<(), AppError>> {
+        Box::pin(async move {
+            let lifted = ProjectFreeze::unfreeze_expired(&state.db_pool).await?;
+            if lifted > 0 {
+                tracing::info!("Unfroze {} projects whose freeze ended", lifted);
+            }
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::file::File;
+    use crate::testing::{create_test_file, create_test_project, create_test_session, create_test_user, TestDb};
+
+    #[tokio::test]
+    async fn test_frozen_project_refuses_file_changes() {
+        let Some(db) = TestDb::start().await else { return };
+        let owner = create_test_user(&db.pool).await;
+        let project = create_test_project(&db.pool, &owner, false).await;
+        let file = create_test_file(&db.pool, &project, &owner).await;
+        let session = create_test_session(&db.pool, &project, &owner).await;
+
+        let request = FreezeProject {
+            reason: Some(" Submitted to ICML ".to_string()),
+            unfreeze_at: None,
+        };
+        let freeze = ProjectFreeze::freeze(&db.pool, project.id, owner.id, request).await.unwrap();
+        assert_eq!(freeze.reason.as_deref(), Some("Submitted to ICML"));
+
+        let edit = file.update_content(&db.pool, "", "changed".to_string(), owner.id).await;
+        assert!(matches!(edit, Err(AppError::ProjectFrozen(reason)) if reason == "Submitted to ICML"));
+        assert!(matches!(file.soft_delete(&db.pool, owner.id).await, Err(AppError::ProjectFrozen(_))));
+        assert!(matches!(
+            ProjectFreeze::ensure_session_writable(&db.pool, session.id).await,
+            Err(AppError::ProjectFrozen(_))
+        ));
+
+        let events = AuditEvent::list_by_action(&db.pool, "project_frozen", 100).await.unwrap();
+        assert!(events.iter().any(|event| event.project_id == Some(project.id)));
+
+        assert!(ProjectFreeze::unfreeze(&db.pool, project.id, Some(owner.id)).await.unwrap());
+        assert!(!ProjectFreeze::unfreeze(&db.pool, project.id, Some(owner.id)).await.unwrap());
+        let edited = file.update_content(&db.pool, "", "changed".to_string(), owner.id).await.unwrap();
+        assert_eq!(edited.content, "changed");
+        assert!(File::find_by_id(&db.pool, file.id, owner.id).await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_freeze_ends_at_its_unfreeze_time() {
+        let Some(db) = TestDb::start().await else { return };
+        let owner = create_test_user(&db.pool).await;
+        let project = create_test_project(&db.pool, &owner, false).await;
+
+        let past = FreezeProject {
+            reason: None,
+            unfreeze_at: Some(Utc::now() - chrono::Duration::minutes(1)),
+        };
+        assert!(matches!(
+            ProjectFreeze::freeze(&db.pool, project.id, owner.id, past).await,
+            Err(AppError::Validation(_))
+        ));
+
+        let soon = FreezeProject {
+            reason: None,
+            unfreeze_at: Some(Utc::now() + chrono::Duration::hours(1)),
+        };
+        ProjectFreeze::freeze(&db.pool, project.id, owner.id, soon).await.unwrap();
+        assert!(ProjectFreeze::ensure_writable(&db.pool, project.id).await.is_err());
+
+        // Once the time has passed the freeze no longer applies, and the task removes it
+        sqlx::query("UPDATE project_freezes SET unfreeze_at = NOW() - INTERVAL '1 second' WHERE project_id = $1")
+            .bind(project.id)
+            .execute(&db.pool)
+            .await
+            .unwrap();
+        assert!(ProjectFreeze::ensure_writable(&db.pool, project.id).await.is_ok());
+
+        ProjectFreeze::unfreeze_expired(&db.pool).await.unwrap();
+        let remaining: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM project_freezes WHERE project_id = $1")
+            .bind(project.id)
+            .fetch_one(&db.pool)
+            .await
+            .unwrap();
+        assert_eq!(remaining, 0);
+
+        let events = AuditEvent::list_by_action(&db.pool, "project_unfrozen", 100).await.unwrap();
+        let event = events.iter().find(|event| event.project_id == Some(project.id)).unwrap();
+        assert_eq!(event.actor_id, None);
+        assert_eq!(event.details["automatic"], true);
+    }
+}
@@ -0,0 +1,376 @@
+//! Limits on the files of a project
+//!
+//! A project holds at most `max_files` live files, none nested in more than
+//! `max_path_depth` folders or with a path longer than `max_path_length`
+//! bytes, and its files take up at most `max_project_size` bytes as the
+//! storage quota counts them. The defaults are generous; they keep projects
+//! within what the file tree, compiles and exports handle rather than shape
+//! ordinary ones. Exceeding a limit is a 422 naming it.
+//!
+//! Every way a file comes into being checks the limits. [`File::create`] and
+//! [`File::create_external`] count the project's files and bytes holding a
+//! lock on the project's file count, so racing creates cannot both take the
+//! last slot; restores do the same and moves check the new path.
+//!
+//! Imports and clones bring in many files at once and screen them first with
+//! [`ProjectLimits::screen`]. In strict mode an entry over a limit refuses the
+//! whole import, the error listing every such entry; in skip mode those
+//! entries are left out and listed with the limit each went over.
+//!
+//! [`File::create`]: super::file::File::create
+//! [`File::create_external`]: super::file::File::create_external
+
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::config::FileStorageConfig;
+use crate::error::AppError;
+
+/// Files of a project by default
+pub const DEFAULT_MAX_FILES: u64 = 5_000;
+
+/// Folders a file may be nested in by default
+pub const DEFAULT_MAX_PATH_DEPTH: u64 = 20;
+
+/// Bytes of a path by default
+pub const DEFAULT_MAX_PATH_LENGTH: u64 = 1_024;
+
+/// A limit on the files of a project
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ProjectLimit {
+    Files,
+    PathDepth,
+    PathLength,
+    ProjectSize,
+}
+
+/// What an import or clone does with entries over a limit: refuse the whole
+/// import, or leave them out
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum LimitMode {
+    Strict,
+    Skip,
+}
+
+/// A file that went over a limit of its project
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct ExceededLimit {
+    pub path: String,
+    pub limit: ProjectLimit,
+    pub max: u64,
+    /// What the file's path or the project would have come to
+    pub actual: u64,
+}
+
+/// Current usage of one limit
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+pub struct LimitUsage {
+    pub limit: ProjectLimit,
+    /// Files, the deepest nesting, the longest path or bytes
+    pub used: u64,
+    pub max: u64,
+}
+
+/// Entries of an import that are within the limits, and those left out
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Screened<T> {
+    pub kept: Vec<T>,
+    pub rejected: Vec<ExceededLimit>,
+}
+
+/// Limits on the files of every project
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProjectLimits {
+    pub max_files: u64,
+    pub max_path_depth: u64,
+    /// Bytes, without the leading slash
+    pub max_path_length: u64,
+    /// Bytes of live files and pending uploads, as the quota counts them
+    pub max_project_size: u64,
+    pub import_mode: LimitMode,
+}
+
+impl Default for ProjectLimits {
+    fn default() -> Self {
+        Self {
+            max_files: DEFAULT_MAX_FILES,
+            max_path_depth: DEFAULT_MAX_PATH_DEPTH,
+            max_path_length: DEFAULT_MAX_PATH_LENGTH,
+            max_project_size: 2 * 1024 * 1024 * 1024,
+            import_mode: LimitMode::Skip,
+        }
+    }
+}
+
+impl std::fmt::Display for ExceededLimit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.limit {
+            ProjectLimit::Files => write!(
+                f,
+                "{} would make {} files, more than the {} a project may have",
+                self.path, self.actual, self.max
+            ),
+            ProjectLimit::PathDepth => write!(
+                f,
+                "{} is nested in {} folders, more than the {} allowed",
+                self.path, self.actual, self.max
+            ),
+            ProjectLimit::PathLength => write!(
+                f,
+                "{} is {} bytes long, more than the {} a path may have",
+                self.path, self.actual, self.max
+            ),
+            ProjectLimit::ProjectSize => write!(
+                f,
+                "{} would bring the project to {} bytes, more than the {} allowed",
+                self.path, self.actual, self.max
+            ),
+        }
+    }
+}
+
+impl From<ExceededLimit> for AppError {
+    fn from(exceeded: ExceededLimit) -> Self {
+        AppError::LimitExceeded { exceeded, rejected: Vec::new() }
+    }
+}
+
+/// Folders a project path is nested in
+pub fn path_depth(path: &str) -> u64 {
+    path.trim_start_matches('/').matches('/').count() as u64
+}
+
+/// Bytes of a project path, without its leading slash
+pub fn path_length(path: &str) -> u64 {
+    path.trim_start_matches('/').len() as u64
+}
+
+impl ProjectLimits {
+    pub fn from_config(storage: &FileStorageConfig) -> Self {
+        Self {
+            max_files: storage.max_project_files,
+            max_path_depth: storage.max_path_depth,
+            max_path_length: storage.max_path_length,
+            max_project_size: storage.project_quota,
+            import_mode: match storage.import_limit_mode.as_str() {
+                "strict" => LimitMode::Strict,
+                _ => LimitMode::Skip,
+            },
+        }
+    }
+
+    /// Check the depth and length of a path
+    pub fn check_path(&self, path: &str) -> Result<(), ExceededLimit> {
+        let exceeded = |limit, max, actual| ExceededLimit { path: path.to_string(), limit, max, actual };
+        let depth = path_depth(path);
+        if depth > self.max_path_depth {
+            return Err(exceeded(ProjectLimit::PathDepth, self.max_path_depth, depth));
+        }
+        let length = path_length(path);
+        if length > self.max_path_length {
+            return Err(exceeded(ProjectLimit::PathLength, self.max_path_length, length));
+        }
+        Ok(())
+    }
+
+    /// Check that the project has room for one more file at `path` of `bytes`
+    ///
+    /// Holds a lock on the project's file count until the transaction ends,
+    /// so the file must be inserted in the same one.
+    pub async fn ensure_room(
+        &self,
+        conn: &mut sqlx::PgConnection,
+        project_id: Uuid,
+        path: &str,
+        bytes: i64,
+    ) -> Result<(), AppError> {
+        self.check_path(path)?;
+
+        sqlx::query("SELECT pg_advisory_xact_lock(hashtextextended($1, 0))")
+            .bind(format!("project_files:{}", project_id))
+            .execute(&mut *conn)
+            .await
+            .map_err(AppError::Database)?;
+
+        let files = sqlx::query_scalar::<_, i64>(
+            "SELECT COUNT(*) FROM files WHERE project_id = $1 AND is_deleted = false"
+        )
+        .bind(project_id)
+        .fetch_one(&mut *conn)
+        .await
+        .map_err(AppError::Database)? as u64;
+        if files >= self.max_files {
+            return Err(ExceededLimit {
+                path: path.to_string(),
+                limit: ProjectLimit::Files,
+                max: self.max_files,
+                actual: files + 1,
+            }
+            .into());
+        }
+
+        let size = (super::storage_usage::quota_usage(&mut *conn, project_id).await? + bytes.max(0)) as u64;
+        if size > self.max_project_size {
+            return Err(ExceededLimit {
+                path: path.to_string(),
+                limit: ProjectLimit::ProjectSize,
+                max: self.max_project_size,
+                actual: size,
+            }
+            .into());
+        }
+        Ok(())
+    }
+
+    /// Screen the entries of an import into a new project, in order
+    ///
+    /// `describe` gives an entry's path and size. Entries over a limit are
+    /// left out in skip mode; in strict mode any of them refuses the import.
+    pub fn screen<T>(&self, entries: Vec<T>, describe: impl Fn(&T) -> (&str, u64)) -> Result<Screened<T>, AppError> {
+        let mut screened = Screened { kept: Vec::with_capacity(entries.len()), rejected: Vec::new() };
+        let mut size = 0u64;
+        for entry in entries {
+            let (path, bytes) = describe(&entry);
+            let exceeded = |limit, max, actual| ExceededLimit { path: path.to_string(), limit, max, actual };
+            let rejected = if let Err(exceeded) = self.check_path(path) {
+                Some(exceeded)
+            } else if screened.kept.len() as u64 >= self.max_files {
+                Some(exceeded(ProjectLimit::Files, self.max_files, screened.kept.len() as u64 + 1))
+            } else if size + bytes > self.max_project_size {
+                Some(exceeded(ProjectLimit::ProjectSize, self.max_project_size, size + bytes))
+            } else {
+                None
+            };
+
+            match rejected {
+                Some(rejected) => screened.rejected.push(rejected),
+                None => {
+                    size += bytes;
+                    screened.kept.push(entry);
+                }
+            }
+        }
+
+        match (self.import_mode, screened.rejected.first()) {
+            (LimitMode::Strict, Some(first)) => Err(AppError::LimitExceeded {
+                exceeded: first.clone(),
+                rejected: screened.rejected,
+            }),
+            _ => Ok(screened),
+        }
+    }
+
+    /// A project's files, deepest nesting, longest path and bytes against
+    /// their limits
+    pub async fn usage(&self, db: &sqlx::PgPool, project_id: Uuid) -> Result<Vec<LimitUsage>, AppError> {
+        let (files, depth, length) = sqlx::query_as::<_, (i64, i64, i64)>(
+            r#"
+            SELECT COUNT(*),
+                   COALESCE(MAX(length(ltrim(path, '/')) - length(replace(ltrim(path, '/'), '/', ''))), 0)::BIGINT,
+                   COALESCE(MAX(octet_length(ltrim(path, '/'))), 0)::BIGINT
+            FROM files
+            WHERE project_id = $1 AND is_deleted = false
+            "#
+        )
+        .bind(project_id)
+        .fetch_one(db)
+        .await
+        .map_err(AppError::Database)?;
+        let size = super::storage_usage::quota_usage(db, project_id).await?;
+
+        let usage = |limit, used: i64, max| LimitUsage { limit, used: used as u64, max };
+        Ok(vec![
+            usage(ProjectLimit::Files, files, self.max_files),
+            usage(ProjectLimit::PathDepth, depth, self.max_path_depth),
+            usage(ProjectLimit::PathLength, length, self.max_path_length),
+            usage(ProjectLimit::ProjectSize, size, self.max_project_size),
+        ])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limits(import_mode: LimitMode) -> ProjectLimits {
+        ProjectLimits {
+            max_files: 3,
+            max_path_depth: 2,
+            max_path_length: 40,
+            max_project_size: 100,
+            import_mode,
+        }
+    }
+
+    #[test]
+    fn test_check_path() {
+        let limits = limits(LimitMode::Strict);
+        assert_eq!(path_depth("/main.tex"), 0);
+        assert_eq!(path_depth("a/b/c.tex"), 2);
+        assert!(limits.check_path("/a/b/c.tex").is_ok());
+
+        let exceeded = limits.check_path("/a/b/c/d.tex").unwrap_err();
+        assert_eq!((exceeded.limit, exceeded.max, exceeded.actual), (ProjectLimit::PathDepth, 2, 3));
+        let long = format!("/{}.tex", "x".repeat(40));
+        let exceeded = limits.check_path(&long).unwrap_err();
+        assert_eq!((exceeded.limit, exceeded.actual), (ProjectLimit::PathLength, 44));
+    }
+
+    /// An import of five files, one too deep, into a project of three files
+    /// and 100 bytes
+    fn oversized() -> Vec<(String, u64)> {
+        vec![
+            ("main.tex".to_string(), 10),
+            ("a/b/c/deep.tex".to_string(), 10),
+            ("big.pdf".to_string(), 95),
+            ("chapter.tex".to_string(), 10),
+            ("appendix.tex".to_string(), 10),
+            ("notes.tex".to_string(), 10),
+        ]
+    }
+
+    #[test]
+    fn test_screen_skip_lists_each_rejected_entry() {
+        let screened = limits(LimitMode::Skip)
+            .screen(oversized(), |(path, size)| (path.as_str(), *size))
+            .unwrap();
+
+        let kept: Vec<&str> = screened.kept.iter().map(|(path, _)| path.as_str()).collect();
+        assert_eq!(kept, vec!["main.tex", "chapter.tex", "appendix.tex"]);
+        let rejected: Vec<(&str, ProjectLimit)> =
+            screened.rejected.iter().map(|rejected| (rejected.path.as_str(), rejected.limit)).collect();
+        assert_eq!(
+            rejected,
+            vec![
+                ("a/b/c/deep.tex", ProjectLimit::PathDepth),
+                ("big.pdf", ProjectLimit::ProjectSize),
+                ("notes.tex", ProjectLimit::Files),
+            ]
+        );
+        assert_eq!(screened.rejected[1].actual, 105);
+    }
+
+    #[test]
+    fn test_screen_strict_refuses_naming_the_first_limit() {
+        let error = limits(LimitMode::Strict)
+            .screen(oversized(), |(path, size)| (path.as_str(), *size))
+            .unwrap_err();
+
+        match error {
+            AppError::LimitExceeded { exceeded, rejected } => {
+                assert_eq!(exceeded.limit, ProjectLimit::PathDepth);
+                assert_eq!(exceeded.path, "a/b/c/deep.tex");
+                assert_eq!(rejected.len(), 3);
+            }
+            other => panic!("expected a limit error, got {:?}", other),
+        }
+
+        let within = limits(LimitMode::Strict)
+            .screen(oversized().into_iter().take(1).collect(), |(path, size)| (path.as_str(), *size))
+            .unwrap();
+        assert_eq!(within.kept.len(), 1);
+    }
+}
@@ -0,0 +1,275 @@
+//! Approximate number of users active in each project
+//!
+//! Project cards show how many people are in the project's collaboration
+//! sessions right now. Asking the WebSocket server for every card would take
+//! its connection locks dozens of times per list, so it reports each session
+//! join and leave here instead, and lists read lock-free counters.
+//!
+//! A user counts once per project however many of their connections are in
+//! its sessions. Session guests, who joined with a password, join code or
+//! invitation but cannot read the project, count only with
+//! `WEBSOCKET_PRESENCE_COUNT_GUESTS`.
+//!
+//! The per-user and per-project counters are updated one after the other, so
+//! a join and leave racing each other, or a connection that went away without
+//! leaving, can leave a project's count off. `PresenceReconcileTask`
+//! recomputes every count from the connections still open and reports how
+//! far they had drifted. Like the connection limits the counts live in
+//! memory, so with several server instances each counts its own connections.
+
+use dashmap::DashMap;
+use prometheus::{IntCounter, IntGauge, Registry, TextEncoder};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::Duration;
+use uuid::Uuid;
+
+use crate::error::AppError;
+use crate::server::AppState;
+
+use super::project::ProjectWithDetails;
+
+/// Projects one `GET /projects/active-counts` may ask about
+pub const MAX_ACTIVE_COUNT_IDS: usize = 100;
+
+/// Time between two reconciliations
+const RECONCILE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Where a connection is present
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Presence {
+    project_id: Uuid,
+    user_id: Uuid,
+}
+
+/// Users in each project's sessions, as counted by the WebSocket server
+pub struct ProjectPresence {
+    /// Users present per project
+    projects: DashMap<Uuid, AtomicU32>,
+    /// Connections present per project and user
+    users: DashMap<(Uuid, Uuid), AtomicU32>,
+    /// Where each connection in a counted session is present
+    connections: DashMap<String, Presence>,
+    registry: Registry,
+    drift: IntCounter,
+    last_drift: IntGauge,
+}
+
+impl Default for ProjectPresence {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ProjectPresence {
+    pub fn new() -> Self {
+        let drift = IntCounter::new(
+            "texler_presence_drift_total",
+            "Corrections to per-project active user counts made by reconciliation",
+        )
+        .expect("valid metric");
+        let last_drift = IntGauge::new(
+            "texler_presence_last_drift",
+            "Corrections made by the latest reconciliation of active user counts",
+        )
+        .expect("valid metric");
+
+        let registry = Registry::new();
+        registry.register(Box::new(drift.clone())).expect("unique metric");
+        registry.register(Box::new(last_drift.clone())).expect("unique metric");
+        Self {
+            projects: DashMap::new(),
+            users: DashMap::new(),
+            connections: DashMap::new(),
+            registry,
+            drift,
+            last_drift,
+        }
+    }
+
+    /// Count a connection that joined a session of `project_id`
+    ///
+    /// A connection is present in one project at a time; joining another
+    /// session moves it.
+    pub fn enter(&self, connection_id: &str, project_id: Uuid, user_id: Uuid) {
+        let presence = Presence { project_id, user_id };
+        match self.connections.insert(connection_id.to_string(), presence) {
+            Some(previous) if previous == presence => return,
+            Some(previous) => self.release(previous),
+            None => {}
+        }
+
+        let first = self.users.entry((project_id, user_id)).or_default().fetch_add(1, Ordering::Relaxed) == 0;
+        if first {
+            self.projects.entry(project_id).or_default().fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Stop counting a connection that left its session or closed
+    pub fn leave(&self, connection_id: &str) {
+        if let Some((_, presence)) = self.connections.remove(connection_id) {
+            self.release(presence);
+        }
+    }
+
+    fn release(&self, presence: Presence) {
+        let key = (presence.project_id, presence.user_id);
+        let last = self.users.get(&key).is_some_and(|connections| decrement(&connections));
+        if !last {
+            return;
+        }
+        self.users.remove_if(&key, |_, connections| connections.load(Ordering::Relaxed) == 0);
+
+        if self.projects.get(&presence.project_id).is_some_and(|users| decrement(&users)) {
+            self.projects.remove_if(&presence.project_id, |_, users| users.load(Ordering::Relaxed) == 0);
+        }
+    }
+
+    /// Users present in a project
+    pub fn count(&self, project_id: Uuid) -> u32 {
+        self.projects.get(&project_id).map_or(0, |users| users.load(Ordering::Relaxed))
+    }
+
+    /// Users present in each of `project_ids`, zero for those nobody is in
+    pub fn counts(&self, project_ids: &[Uuid]) -> HashMap<Uuid, u32> {
+        project_ids.iter().map(|&project_id| (project_id, self.count(project_id))).collect()
+    }
+
+    /// Fill in `active_collaborators_now` of projects about to be sent
+    pub fn annotate<'a>(&self, projects: impl IntoIterator<Item = &'a mut ProjectWithDetails>) {
+        for details in projects {
+            details.active_collaborators_now = Some(self.count(details.project.id));
+        }
+    }
+
+    /// Forget connections that are no longer open and recompute every count
+    ///
+    /// Returns by how much the counts were corrected in total.
+    pub fn reconcile(&self, is_open: impl Fn(&str) -> bool) -> u64 {
+        self.connections.retain(|connection_id, _| is_open(connection_id));
+
+        let mut users: HashMap<(Uuid, Uuid), u32> = HashMap::new();
+        for presence in self.connections.iter() {
+            *users.entry((presence.project_id, presence.user_id)).or_default() += 1;
+        }
+        let mut projects: HashMap<Uuid, u32> = HashMap::new();
+        for (project_id, _) in users.keys() {
+            *projects.entry(*project_id).or_default() += 1;
+        }
+
+        let user_drift = correct(&self.users, &users);
+        let project_drift = correct(&self.projects, &projects);
+        self.drift.inc_by(project_drift);
+        self.last_drift.set(project_drift as i64);
+        if user_drift + project_drift > 0 {
+            tracing::info!("Corrected active user counts of projects by {} ({} per user)", project_drift, user_drift);
+        }
+        project_drift
+    }
+
+    /// Reconciliation corrections in the Prometheus text format
+    pub fn metrics_text(&self) -> String {
+        let mut text = String::new();
+        if let Err(e) = TextEncoder::new().encode_utf8(&self.registry.gather(), &mut text) {
+            tracing::warn!("Failed to encode presence metrics: {}", e);
+        }
+        text
+    }
+}
+
+/// Take one from a counter that is not zero; whether it reached zero
+fn decrement(counter: &AtomicU32) -> bool {
+    counter
+        .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |count| count.checked_sub(1))
+        .is_ok_and(|previous| previous == 1)
+}
+
+/// Set counters to their expected values; returns the total difference
+fn correct<K>(counters: &DashMap<K, AtomicU32>, expected: &HashMap<K, u32>) -> u64
+where
+    K: std::hash::Hash + Eq + Copy,
+{
+    let mut drift = 0;
+    counters.retain(|key, counter| {
+        expected.contains_key(key) || {
+            drift += u64::from(counter.load(Ordering::Relaxed));
+            false
+        }
+    });
+    for (key, &count) in expected {
+        let was = counters.entry(*key).or_default().swap(count, Ordering::Relaxed);
+        drift += u64::from(was.abs_diff(count));
+    }
+    drift
+}
+
+/// Corrects the approximate active user counts of projects
+pub struct PresenceReconcileTask;
+
+impl crate::tasks::PeriodicTask for PresenceReconcileTask {
+    fn name(&self) -> &'static str {
+        "presence_reconcile"
+    }
+
+    fn interval(&self) -> Duration {
+        RECONCILE_INTERVAL
+    }
+
+    fn run<'a>(&'a self, state: &'a AppState) -> futures::future::BoxFuture<'a, Result<(), AppError>> {
+        Box::pin(async move {
+            let connections = &state.ws_connection_limiter;
+            state.project_presence.reconcile(|connection_id| connections.is_open(connection_id));
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_users_count_once_per_project() {
+        let presence = ProjectPresence::new();
+        let (project, other_project) = (Uuid::new_v4(), Uuid::new_v4());
+        let (alice, bob) = (Uuid::new_v4(), Uuid::new_v4());
+
+        presence.enter("a1", project, alice);
+        presence.enter("a2", project, alice);
+        presence.enter("b1", project, bob);
+        assert_eq!(presence.count(project), 2);
+
+        // Joining a session of another project moves the connection
+        presence.enter("b1", other_project, bob);
+        assert_eq!((presence.count(project), presence.count(other_project)), (1, 1));
+
+        presence.leave("a1");
+        assert_eq!(presence.count(project), 1);
+        presence.leave("a2");
+        presence.leave("a2");
+        presence.leave("b1");
+        assert_eq!(presence.counts(&[project, other_project]), HashMap::from([(project, 0), (other_project, 0)]));
+        assert!(presence.projects.is_empty() && presence.users.is_empty());
+    }
+
+    #[test]
+    fn test_reconciliation_corrects_drift() {
+        let presence = ProjectPresence::new();
+        let project = Uuid::new_v4();
+        let (alice, bob) = (Uuid::new_v4(), Uuid::new_v4());
+        presence.enter("a1", project, alice);
+        presence.enter("b1", project, bob);
+
+        // Counts that agree with the open connections are left alone
+        assert_eq!(presence.reconcile(|_| true), 0);
+
+        // A count off by two, and a connection that closed without leaving
+        presence.projects.get(&project).unwrap().store(4, Ordering::Relaxed);
+        assert_eq!(presence.reconcile(|connection_id| connection_id != "b1"), 3);
+        assert_eq!(presence.count(project), 1);
+        assert!(presence.metrics_text().contains("texler_presence_drift_total 3"));
+
+        presence.leave("a1");
+        assert_eq!(presence.count(project), 0);
+    }
+}
@@ -0,0 +1,740 @@
+//! Staged deletion of projects
+//!
+//! Deleting a project only marks it purging and records a `project_purges`
+//! row; from then on every lookup treats it as missing. `ProjectPurgeTask`
+//! removes its rows in the background, one stage at a time in the order of
+//! `PurgeStage` and in batches of `PURGE_BATCH_SIZE` rows, each batch in its
+//! own short transaction so no table stays locked for long. The stage and
+//! the running totals are saved with every batch, so a restart resumes where
+//! the last committed batch left off. Blobs no longer referenced, chat
+//! attachments, upload chunks and the compile working directory go along
+//! with their rows. The project row goes last, together with an audit record
+//! of the rows deleted per table and the bytes reclaimed from storage.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use super::audit::AuditEvent;
+use super::blob::{remove_blob_content, Blob};
+use super::compilation::PROJECT_WORKING_DIRECTORY_ROOT;
+use super::project::Project;
+use crate::error::AppError;
+
+/// Rows deleted per transaction
+pub const PURGE_BATCH_SIZE: i64 = 500;
+
+/// Sessions deleted per transaction, each with its attachments
+const SESSION_BATCH_SIZE: i64 = 50;
+
+/// Pause between two batches, leaving the database to foreground requests
+const BATCH_PAUSE: Duration = Duration::from_millis(50);
+
+/// Time one run of the purge task works before leaving the rest to the next
+const RUN_BUDGET: Duration = Duration::from_secs(20);
+
+/// Time between two runs of the purge task
+const PURGE_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Completed purges kept in the listings
+const LISTED_PURGES: i64 = 100;
+
+/// Name of the purge task, for triggering it after a deletion
+pub const PROJECT_PURGE_TASK: &str = "project_purge";
+
+/// Step of a purge; stages run in declaration order
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, sqlx::Type, ToSchema)]
+#[sqlx(type_name = "purgestage")]
+pub enum PurgeStage {
+    #[serde(rename = "compilation_jobs")]
+    #[sqlx(rename = "compilation_jobs")]
+    CompilationJobs,
+    #[serde(rename = "session_operations")]
+    #[sqlx(rename = "session_operations")]
+    SessionOperations,
+    #[serde(rename = "session_messages")]
+    #[sqlx(rename = "session_messages")]
+    SessionMessages,
+    #[serde(rename = "sessions")]
+    #[sqlx(rename = "sessions")]
+    Sessions,
+    #[serde(rename = "activity")]
+    #[sqlx(rename = "activity")]
+    Activity,
+    #[serde(rename = "file_versions")]
+    #[sqlx(rename = "file_versions")]
+    FileVersions,
+    #[serde(rename = "files")]
+    #[sqlx(rename = "files")]
+    Files,
+    #[serde(rename = "project")]
+    #[sqlx(rename = "project")]
+    Project,
+    #[serde(rename = "done")]
+    #[sqlx(rename = "done")]
+    Done,
+}
+
+impl PurgeStage {
+    const ALL: [PurgeStage; 9] = [
+        Self::CompilationJobs,
+        Self::SessionOperations,
+        Self::SessionMessages,
+        Self::Sessions,
+        Self::Activity,
+        Self::FileVersions,
+        Self::Files,
+        Self::Project,
+        Self::Done,
+    ];
+
+    fn index(self) -> usize {
+        Self::ALL.iter().position(|stage| *stage == self).unwrap_or(0)
+    }
+
+    fn next(self) -> Self {
+        Self::ALL.get(self.index() + 1).copied().unwrap_or(Self::Done)
+    }
+
+    /// Table the stage deletes from, the key of its count in `rows_deleted`
+    fn table(self) -> &'static str {
+        match self {
+            Self::CompilationJobs => "compilation_jobs",
+            Self::SessionOperations => "session_operations",
+            Self::SessionMessages => "session_messages",
+            Self::Sessions => "collaboration_sessions",
+            Self::Activity => "project_activity",
+            Self::FileVersions => "file_versions",
+            Self::Files => "files",
+            Self::Project | Self::Done => "projects",
+        }
+    }
+}
+
+/// Tables emptied by the cascade of the project row, counted before it goes
+const CASCADED_TABLES: [&str; 9] = [
+    "project_collaborators",
+    "project_invitations",
+    "project_tags",
+    "project_dictionary",
+    "project_settings_history",
+    "project_activity_rollup",
+    "upload_sessions",
+    "project_freezes",
+    "project_embed_tokens",
+];
+
+/// Progress of a project deletion
+#[derive(Debug, Clone, Serialize, FromRow, ToSchema)]
+pub struct ProjectPurge {
+    /// The project no longer exists once the purge is done
+    pub project_id: Uuid,
+    pub project_name: String,
+    pub owner_id: Option<Uuid>,
+    pub requested_by: Option<Uuid>,
+    /// Stage the next batch belongs to
+    pub stage: PurgeStage,
+    /// Rows deleted so far per table
+    #[schema(value_type = HashMap<String, i64>)]
+    pub rows_deleted: sqlx::types::Json<BTreeMap<String, i64>>,
+    /// Bytes of blobs, attachments and compile output removed from storage so far
+    pub bytes_reclaimed: i64,
+    /// Error of the last failed batch; the batch is retried on the next run
+    pub last_error: Option<String>,
+    pub started_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    pub completed_at: Option<DateTime<Utc>>,
+}
+
+/// A purge with its position in the sequence of stages
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct PurgeProgress {
+    #[serde(flatten)]
+    pub purge: ProjectPurge,
+    pub stages_completed: usize,
+    pub stages_total: usize,
+}
+
+impl From<ProjectPurge> for PurgeProgress {
+    fn from(purge: ProjectPurge) -> Self {
+        Self {
+            stages_completed: purge.stage.index(),
+            stages_total: PurgeStage::Done.index(),
+            purge,
+        }
+    }
+}
+
+/// What one batch deleted
+#[derive(Debug, Default)]
+struct BatchOutcome {
+    rows: BTreeMap<String, i64>,
+    bytes: i64,
+    /// Set when the stage has nothing left to delete
+    stage_done: bool,
+}
+
+impl BatchOutcome {
+    fn deleted(table: &str, rows: i64, bytes: i64, limit: i64) -> Self {
+        Self {
+            rows: BTreeMap::from([(table.to_string(), rows)]),
+            bytes,
+            stage_done: rows < limit,
+        }
+    }
+}
+
+impl ProjectPurge {
+    /// Record the start of a project's purge; the project must already be marked purging
+    pub async fn begin(
+        conn: &mut sqlx::PgConnection,
+        project: &Project,
+        requested_by: Uuid,
+    ) -> Result<Self, AppError> {
+        sqlx::query_as::<_, ProjectPurge>(
+            r#"
+            INSERT INTO project_purges (project_id, project_name, owner_id, requested_by)
+            VALUES ($1, $2, $3, $4)
+            RETURNING *
+            "#
+        )
+        .bind(project.id)
+        .bind(&project.name)
+        .bind(project.owner_id)
+        .bind(requested_by)
+        .fetch_one(&mut *conn)
+        .await
+        .map_err(AppError::Database)
+    }
+
+    pub async fn find(db: &sqlx::PgPool, project_id: Uuid) -> Result<Option<Self>, AppError> {
+        sqlx::query_as::<_, ProjectPurge>("SELECT * FROM project_purges WHERE project_id = $1")
+            .bind(project_id)
+            .fetch_optional(db)
+            .await
+            .map_err(AppError::Database)
+    }
+
+    /// Unfinished purges oldest first, then the most recently completed
+    pub async fn list(db: &sqlx::PgPool) -> Result<Vec<Self>, AppError> {
+        sqlx::query_as::<_, ProjectPurge>(
+            r#"
+            SELECT * FROM project_purges
+            ORDER BY completed_at IS NOT NULL, CASE WHEN completed_at IS NULL THEN started_at END, completed_at DESC
+            LIMIT $1
+            "#
+        )
+        .bind(LISTED_PURGES)
+        .fetch_all(db)
+        .await
+        .map_err(AppError::Database)
+    }
+
+    /// Deleted projects of an owner, newest first
+    pub async fn list_for_owner(db: &sqlx::PgPool, owner_id: Uuid) -> Result<Vec<Self>, AppError> {
+        sqlx::query_as::<_, ProjectPurge>(
+            r#"
+            SELECT * FROM project_purges
+            WHERE owner_id = $1
+            ORDER BY started_at DESC
+            LIMIT $2
+            "#
+        )
+        .bind(owner_id)
+        .bind(LISTED_PURGES)
+        .fetch_all(db)
+        .await
+        .map_err(AppError::Database)
+    }
+
+    /// Work through unfinished purges until they are done or `budget` runs out
+    ///
+    /// Returns the number of purges that completed.
+    pub async fn run_pending(db: &sqlx::PgPool, storage_root: &str, budget: Duration) -> Result<u64, AppError> {
+        let deadline = Instant::now() + budget;
+        let pending = sqlx::query_scalar::<_, Uuid>(
+            "SELECT project_id FROM project_purges WHERE completed_at IS NULL ORDER BY started_at"
+        )
+        .fetch_all(db)
+        .await
+        .map_err(AppError::Database)?;
+
+        let mut completed = 0;
+        for project_id in pending {
+            while Instant::now() < deadline {
+                match Self::step(db, storage_root, project_id).await {
+                    Ok(Some(purge)) if purge.completed_at.is_some() => {
+                        completed += 1;
+                        break;
+                    }
+                    Ok(Some(_)) => {}
+                    // Another instance holds it, or it finished meanwhile
+                    Ok(None) => break,
+                    Err(e) => {
+                        Self::record_error(db, project_id, &e).await?;
+                        return Err(e);
+                    }
+                }
+                tokio::task::yield_now().await;
+                tokio::time::sleep(BATCH_PAUSE).await;
+            }
+        }
+
+        Ok(completed)
+    }
+
+    /// Delete one batch of the purge's current stage and save the progress
+    ///
+    /// Returns `None` when the purge is finished or another worker is on it.
+    pub async fn step(db: &sqlx::PgPool, storage_root: &str, project_id: Uuid) -> Result<Option<Self>, AppError> {
+        let mut tx = db.begin().await.map_err(AppError::Database)?;
+
+        let Some(purge) = sqlx::query_as::<_, ProjectPurge>(
+            r#"
+            SELECT * FROM project_purges
+            WHERE project_id = $1 AND completed_at IS NULL
+            FOR UPDATE SKIP LOCKED
+            "#
+        )
+        .bind(project_id)
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(AppError::Database)?
+        else {
+            return Ok(None);
+        };
+
+        let outcome = match purge.stage {
+            PurgeStage::CompilationJobs => delete_compilation_jobs(&mut tx, project_id).await?,
+            PurgeStage::SessionOperations | PurgeStage::SessionMessages => {
+                delete_session_rows(&mut tx, purge.stage, project_id).await?
+            }
+            PurgeStage::Sessions => delete_sessions(&mut tx, storage_root, project_id).await?,
+            PurgeStage::Activity => delete_activity(&mut tx, project_id).await?,
+            PurgeStage::FileVersions => delete_file_versions(&mut tx, storage_root, project_id).await?,
+            PurgeStage::Files => delete_files(&mut tx, storage_root, project_id).await?,
+            PurgeStage::Project => delete_project(&mut tx, storage_root, project_id).await?,
+            PurgeStage::Done => BatchOutcome { stage_done: true, ..Default::default() },
+        };
+
+        let mut rows_deleted = purge.rows_deleted.0.clone();
+        for (table, rows) in outcome.rows {
+            *rows_deleted.entry(table).or_default() += rows;
+        }
+        let stage = if outcome.stage_done { purge.stage.next() } else { purge.stage };
+        let bytes_reclaimed = purge.bytes_reclaimed + outcome.bytes;
+
+        if stage == PurgeStage::Done {
+            AuditEvent::record(
+                &mut *tx,
+                purge.requested_by,
+                "project_purged",
+                "project",
+                Some(project_id),
+                None,
+                serde_json::json!({
+                    "project_name": purge.project_name,
+                    "owner_id": purge.owner_id,
+                    "rows_deleted": rows_deleted,
+                    "bytes_reclaimed": bytes_reclaimed,
+                    "started_at": purge.started_at,
+                }),
+            )
+            .await?;
+        }
+
+        let purge = sqlx::query_as::<_, ProjectPurge>(
+            r#"
+            UPDATE project_purges SET
+                stage = $2, rows_deleted = $3, bytes_reclaimed = $4, last_error = NULL,
+                updated_at = NOW(),
+                completed_at = CASE WHEN $2 = 'done'::purgestage THEN NOW() END
+            WHERE project_id = $1
+            RETURNING *
+            "#
+        )
+        .bind(project_id)
+        .bind(stage)
+        .bind(sqlx::types::Json(rows_deleted))
+        .bind(bytes_reclaimed)
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(AppError::Database)?;
+
+        tx.commit().await.map_err(AppError::Database)?;
+
+        if purge.completed_at.is_some() {
+            tracing::info!(
+                "Purged project {} ({} bytes reclaimed)",
+                project_id,
+                purge.bytes_reclaimed
+            );
+        }
+
+        Ok(Some(purge))
+    }
+
+    async fn record_error(db: &sqlx::PgPool, project_id: Uuid, error: &AppError) -> Result<(), AppError> {
+        sqlx::query("UPDATE project_purges SET last_error = $2, updated_at = NOW() WHERE project_id = $1")
+            .bind(project_id)
+            .bind(error.to_string())
+            .execute(db)
+            .await
+            .map_err(AppError::Database)?;
+        Ok(())
+    }
+}
+
+async fn delete_compilation_jobs(tx: &mut sqlx::PgConnection, project_id: Uuid) -> Result<BatchOutcome, AppError> {
+    // Output lives in the project's working directory, removed with the project
+    let sizes = sqlx::query_scalar::<_, i64>(
+        r#"
+        DELETE FROM compilation_jobs WHERE id IN (
+            SELECT id FROM compilation_jobs WHERE project_id = $1 LIMIT $2
+        )
+        RETURNING output_size_bytes
+        "#
+    )
+    .bind(project_id)
+    .bind(PURGE_BATCH_SIZE)
+    .fetch_all(&mut *tx)
+    .await
+    .map_err(AppError::Database)?;
+
+    Ok(BatchOutcome::deleted(
+        PurgeStage::CompilationJobs.table(),
+        sizes.len() as i64,
+        sizes.iter().sum(),
+        PURGE_BATCH_SIZE,
+    ))
+}
+
+async fn delete_session_rows(
+    tx: &mut sqlx::PgConnection,
+    stage: PurgeStage,
+    project_id: Uuid,
+) -> Result<BatchOutcome, AppError> {
+    let table = stage.table();
+    let deleted = sqlx::query(&format!(
+        r#"
+        DELETE FROM {0} WHERE id IN (
+            SELECT r.id FROM {0} r
+            JOIN collaboration_sessions s ON s.id = r.session_id
+            WHERE s.project_id = $1
+            LIMIT $2
+        )
+        "#,
+        table
+    ))
+    .bind(project_id)
+    .bind(PURGE_BATCH_SIZE)
+    .execute(&mut *tx)
+    .await
+    .map_err(AppError::Database)?
+    .rows_affected() as i64;
+
+    Ok(BatchOutcome::deleted(table, deleted, 0, PURGE_BATCH_SIZE))
+}
+
+async fn delete_sessions(
+    tx: &mut sqlx::PgConnection,
+    storage_root: &str,
+    project_id: Uuid,
+) -> Result<BatchOutcome, AppError> {
+    let sessions = sqlx::query_scalar::<_, Uuid>(
+        "DELETE FROM collaboration_sessions WHERE id IN (SELECT id FROM collaboration_sessions WHERE project_id = $1 LIMIT $2) RETURNING id"
+    )
+    .bind(project_id)
+    .bind(SESSION_BATCH_SIZE)
+    .fetch_all(&mut *tx)
+    .await
+    .map_err(AppError::Database)?;
+
+    // The attachment rows went with the cascade; their files are still on disk
+    let mut bytes = 0;
+    for session_id in &sessions {
+        bytes += remove_dir(&super::chat_attachment::session_dir(storage_root, *session_id)).await?;
+    }
+
+    Ok(BatchOutcome::deleted(PurgeStage::Sessions.table(), sessions.len() as i64, bytes, SESSION_BATCH_SIZE))
+}
+
+async fn delete_activity(tx: &mut sqlx::PgConnection, project_id: Uuid) -> Result<BatchOutcome, AppError> {
+    let deleted = sqlx::query(
+        "DELETE FROM project_activity WHERE id IN (SELECT id FROM project_activity WHERE project_id = $1 LIMIT $2)"
+    )
+    .bind(project_id)
+    .bind(PURGE_BATCH_SIZE)
+    .execute(&mut *tx)
+    .await
+    .map_err(AppError::Database)?
+    .rows_affected() as i64;
+
+    Ok(BatchOutcome::deleted(PurgeStage::Activity.table(), deleted, 0, PURGE_BATCH_SIZE))
+}
+
+async fn delete_file_versions(
+    tx: &mut sqlx::PgConnection,
+    storage_root: &str,
+    project_id: Uuid,
+) -> Result<BatchOutcome, AppError> {
+    let hashes = sqlx::query_scalar::<_, Option<String>>(
+        r#"
+        DELETE FROM file_versions WHERE id IN (
+            SELECT v.id FROM file_versions v
+            JOIN files f ON f.id = v.file_id
+            WHERE f.project_id = $1
+            LIMIT $2
+        )
+        RETURNING content_hash
+        "#
+    )
+    .bind(project_id)
+    .bind(PURGE_BATCH_SIZE)
+    .fetch_all(&mut *tx)
+    .await
+    .map_err(AppError::Database)?;
+
+    let bytes = release_blobs(tx, storage_root, hashes.iter().flatten()).await?;
+    Ok(BatchOutcome::deleted(PurgeStage::FileVersions.table(), hashes.len() as i64, bytes, PURGE_BATCH_SIZE))
+}
+
+async fn delete_files(
+    tx: &mut sqlx::PgConnection,
+    storage_root: &str,
+    project_id: Uuid,
+) -> Result<BatchOutcome, AppError> {
+    let hashes = sqlx::query_scalar::<_, Option<String>>(
+        r#"
+        DELETE FROM files WHERE id IN (
+            SELECT id FROM files WHERE project_id = $1 LIMIT $2
+        )
+        RETURNING content_hash
+        "#
+    )
+    .bind(project_id)
+    .bind(PURGE_BATCH_SIZE)
+    .fetch_all(&mut *tx)
+    .await
+    .map_err(AppError::Database)?;
+
+    let bytes = release_blobs(tx, storage_root, hashes.iter().flatten()).await?;
+    Ok(BatchOutcome::deleted(PurgeStage::Files.table(), hashes.len() as i64, bytes, PURGE_BATCH_SIZE))
+}
+
+/// Count what the cascade takes along, then delete the project row
+async fn delete_project(
+    tx: &mut sqlx::PgConnection,
+    storage_root: &str,
+    project_id: Uuid,
+) -> Result<BatchOutcome, AppError> {
+    let mut outcome = BatchOutcome { stage_done: true, ..Default::default() };
+
+    for table in CASCADED_TABLES {
+        let rows = sqlx::query_scalar::<_, i64>(&format!("SELECT COUNT(*) FROM {} WHERE project_id = $1", table))
+            .bind(project_id)
+            .fetch_one(&mut *tx)
+            .await
+            .map_err(AppError::Database)?;
+        if rows > 0 {
+            outcome.rows.insert(table.to_string(), rows);
+        }
+    }
+
+    let uploads = sqlx::query_scalar::<_, Uuid>("SELECT id FROM upload_sessions WHERE project_id = $1")
+        .bind(project_id)
+        .fetch_all(&mut *tx)
+        .await
+        .map_err(AppError::Database)?;
+
+    let deleted = sqlx::query("DELETE FROM projects WHERE id = $1")
+        .bind(project_id)
+        .execute(&mut *tx)
+        .await
+        .map_err(AppError::Database)?
+        .rows_affected() as i64;
+    outcome.rows.insert(PurgeStage::Project.table().to_string(), deleted);
+
+    for upload_id in uploads {
+        outcome.bytes += remove_dir(&super::upload::chunk_dir(storage_root, upload_id)).await?;
+    }
+    // Compile output was counted with its jobs
+    remove_dir(&std::path::Path::new(PROJECT_WORKING_DIRECTORY_ROOT).join(project_id.to_string())).await?;
+
+    Ok(outcome)
+}
+
+/// Drop a reference to each blob and remove the content of those left unreferenced
+///
+/// Returns the size of the removed content.
+async fn release_blobs<'a>(
+    tx: &mut sqlx::PgConnection,
+    storage_root: &str,
+    hashes: impl Iterator<Item = &'a String>,
+) -> Result<i64, AppError> {
+    let mut bytes = 0;
+    for hash in hashes {
+        if let Some(released) = Blob::release(&mut *tx, hash).await? {
+            remove_blob_content(storage_root, &released).await?;
+            if released.storage_path.is_some() {
+                bytes += released.size;
+            }
+        }
+    }
+    Ok(bytes)
+}
+
+/// Remove a directory tree, returning the size of the files in it
+async fn remove_dir(path: &std::path::Path) -> Result<i64, AppError> {
+    let mut bytes = 0;
+    let mut pending = vec![path.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        let mut entries = match tokio::fs::read_dir(&dir).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(AppError::Storage(format!("Failed to read {}: {}", dir.display(), e))),
+        };
+        while let Some(entry) = entries.next_entry().await? {
+            let metadata = entry.metadata().await?;
+            if metadata.is_dir() {
+                pending.push(entry.path());
+            } else {
+                bytes += metadata.len() as i64;
+            }
+        }
+    }
+
+    match tokio::fs::remove_dir_all(path).await {
+        Ok(()) => Ok(bytes),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(bytes),
+        Err(e) => Err(AppError::Storage(format!("Failed to remove {}: {}", path.display(), e))),
+    }
+}
+
+/// Carries out the deletions of projects in the background
+pub struct ProjectPurgeTask;
+
+impl crate::tasks::PeriodicTask for ProjectPurgeTask {
+    fn name(&self) -> &'static str {
+        PROJECT_PURGE_TASK
+    }
+
+    fn interval(&self) -> Duration {
+        PURGE_INTERVAL
+    }
+
+    fn run<'a>(
+        &'a self,
+        state: &'a crate::server::AppState,
+    ) -> futures::future::BoxFuture<'a, Result<(), AppError>> {
+        Box::pin(async move {
+            let completed = ProjectPurge::run_pending(
+                &state.db_pool,
+                &state.config.features.file_storage.local_path,
+                RUN_BUDGET,
+            )
+            .await?;
+            if completed > 0 {
+                tracing::info!("Finished purging {} projects", completed);
+            }
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{
+        create_test_file, create_test_job, create_test_project, create_test_session, create_test_user, TestDb,
+    };
+
+    #[test]
+    fn test_stages_run_in_order_and_end_done() {
+        let mut stage = PurgeStage::CompilationJobs;
+        let mut seen = vec![stage];
+        while stage != PurgeStage::Done {
+            stage = stage.next();
+            seen.push(stage);
+        }
+        assert_eq!(seen, PurgeStage::ALL);
+        assert_eq!(PurgeStage::Done.next(), PurgeStage::Done);
+        assert!(PurgeStage::Sessions < PurgeStage::Files);
+    }
+
+    #[tokio::test]
+    async fn test_deleted_project_is_hidden_then_purged_with_accounting() {
+        let Some(db) = TestDb::start().await else { return };
+        let storage = tempfile::tempdir().unwrap();
+        let root = storage.path().to_str().unwrap();
+        let owner = create_test_user(&db.pool).await;
+        let project = create_test_project(&db.pool, &owner, false).await;
+        let file = create_test_file(&db.pool, &project, &owner).await;
+        create_test_job(&db.pool, &project, &owner).await;
+        create_test_session(&db.pool, &project, &owner).await;
+
+        project.delete(&db.pool, owner.id).await.unwrap();
+
+        // Gone for every reader while the rows are still there
+        assert!(Project::find_by_id(&db.pool, project.id, owner.id).await.unwrap().is_none());
+        assert!(!Project::has_access(&db.pool, project.id, owner.id).await.unwrap());
+        assert!(crate::models::file::File::find_by_id(&db.pool, file.id, owner.id).await.unwrap().is_none());
+        assert!(project.delete(&db.pool, owner.id).await.is_err());
+
+        let purge = ProjectPurge::find(&db.pool, project.id).await.unwrap().unwrap();
+        assert_eq!(purge.stage, PurgeStage::CompilationJobs);
+        assert_eq!(PurgeProgress::from(purge).stages_completed, 0);
+
+        // One step at a time, as a restart between them would leave it
+        let mut steps = 0;
+        while let Some(purge) = ProjectPurge::step(&db.pool, root, project.id).await.unwrap() {
+            steps += 1;
+            if purge.completed_at.is_some() {
+                break;
+            }
+        }
+        assert!(steps >= PurgeStage::Done.index());
+
+        let purge = ProjectPurge::find(&db.pool, project.id).await.unwrap().unwrap();
+        assert_eq!(purge.stage, PurgeStage::Done);
+        assert_eq!(purge.rows_deleted.0.get("compilation_jobs"), Some(&1));
+        assert_eq!(purge.rows_deleted.0.get("collaboration_sessions"), Some(&1));
+        assert_eq!(purge.rows_deleted.0.get("files"), Some(&1));
+        assert_eq!(purge.rows_deleted.0.get("projects"), Some(&1));
+        assert!(ProjectPurge::step(&db.pool, root, project.id).await.unwrap().is_none());
+
+        let exists = sqlx::query_scalar::<_, bool>("SELECT EXISTS (SELECT 1 FROM projects WHERE id = $1)")
+            .bind(project.id)
+            .fetch_one(&db.pool)
+            .await
+            .unwrap();
+        assert!(!exists);
+
+        let events = AuditEvent::list_by_action(&db.pool, "project_purged", 10).await.unwrap();
+        let event = events.iter().find(|event| event.entity_id == Some(project.id)).unwrap();
+        assert_eq!(event.details["rows_deleted"]["files"], 1);
+
+        let trash = ProjectPurge::list_for_owner(&db.pool, owner.id).await.unwrap();
+        assert_eq!(trash.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_pending_purges_run_within_budget() {
+        let Some(db) = TestDb::start().await else { return };
+        let storage = tempfile::tempdir().unwrap();
+        let owner = create_test_user(&db.pool).await;
+        let project = create_test_project(&db.pool, &owner, false).await;
+        create_test_file(&db.pool, &project, &owner).await;
+        project.delete(&db.pool, owner.id).await.unwrap();
+
+        let completed = ProjectPurge::run_pending(&db.pool, storage.path().to_str().unwrap(), Duration::from_secs(30))
+            .await
+            .unwrap();
+        assert!(completed >= 1);
+        assert!(ProjectPurge::find(&db.pool, project.id).await.unwrap().unwrap().completed_at.is_some());
+    }
+}
@@ -0,0 +1,549 @@
+//! Project secrets for compile-time injection
+//!
+//! Documents that pull data at build time need credentials, which otherwise
+//! end up in committed files. A project owner stores them as named secrets
+//! instead: values are encrypted with AES-256-GCM under the instance master
+//! key (`SECRETS_MASTER_KEY`), with the project and name bound in as
+//! associated data, and are never returned by the API once set.
+//!
+//! A secret reaches a compile only when flagged `expose_to_compile`; the
+//! worker loads [`CompileSecrets::for_job`] and sets them as environment
+//! variables of the engine process with [`CompileSecrets::apply`]. Whatever
+//! the flag, every value of the project is replaced by `***` in the output a
+//! job stores when it completes. Secrets live in their own table, so export,
+//! clone and fork never carry them. Changes and injections are audited.
+//!
+//! Rotating the master key: set the new key, move the old one to
+//! `SECRETS_PREVIOUS_MASTER_KEYS`, and [`ProjectSecretRekeyTask`] re-encrypts
+//! every secret under the new key in the background.
+
+use aes_gcm::aead::{Aead, KeyInit, Payload};
+use aes_gcm::{Aes256Gcm, Nonce};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chrono::{DateTime, Utc};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::FromRow;
+use std::time::Duration;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use super::audit::AuditEvent;
+use super::compilation::CompilationJob;
+use crate::config::SecretsConfig;
+use crate::error::AppError;
+
+/// What a secret value is replaced with in stored output
+pub const REDACTED: &str = "***";
+
+/// Longest secret value accepted, in bytes
+pub const MAX_SECRET_VALUE_LENGTH: usize = 8192;
+
+/// Shortest secret value accepted; shorter ones would redact ordinary log text
+pub const MIN_SECRET_VALUE_LENGTH: usize = 4;
+
+/// Variables a secret may not replace, besides any starting with `TEXMF`
+const RESERVED_NAMES: &[&str] = &["PATH", "HOME", "SHELL", "USER", "TMPDIR", "LD_PRELOAD", "LD_LIBRARY_PATH"];
+
+/// Time between two rekey runs
+const REKEY_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// Secrets re-encrypted per rekey run
+const REKEY_BATCH: i64 = 500;
+
+/// A master key with the ID stored next to what it encrypted
+struct MasterKey {
+    id: String,
+    cipher: Aes256Gcm,
+}
+
+impl MasterKey {
+    fn parse(encoded: &str) -> Result<Self, AppError> {
+        let bytes = STANDARD
+            .decode(encoded.trim())
+            .map_err(|_| AppError::Config("Secrets master keys must be base64".to_string()))?;
+        let cipher = Aes256Gcm::new_from_slice(&bytes)
+            .map_err(|_| AppError::Config("Secrets master keys must be 32 bytes".to_string()))?;
+        Ok(Self { id: hex::encode(&Sha256::digest(&bytes)[..8]), cipher })
+    }
+}
+
+/// A value encrypted under a master key
+struct Sealed {
+    ciphertext: Vec<u8>,
+    nonce: Vec<u8>,
+    key_id: String,
+}
+
+/// Master keys of this instance: the current one and those it replaced
+pub struct SecretKeyring {
+    current: Option<MasterKey>,
+    previous: Vec<MasterKey>,
+}
+
+impl std::fmt::Debug for SecretKeyring {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SecretKeyring")
+            .field("current", &self.current.as_ref().map(|key| &key.id))
+            .field("previous", &self.previous.iter().map(|key| &key.id).collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+impl SecretKeyring {
+    pub fn from_config(config: &SecretsConfig) -> Result<Self, AppError> {
+        Ok(Self {
+            current: config.master_key.as_deref().map(MasterKey::parse).transpose()?,
+            previous: config.previous_master_keys.iter().map(|key| MasterKey::parse(key)).collect::<Result<_, _>>()?,
+        })
+    }
+
+    /// Whether secrets can be stored on this instance
+    pub fn is_enabled(&self) -> bool {
+        self.current.is_some()
+    }
+
+    fn current(&self) -> Result<&MasterKey, AppError> {
+        self.current
+            .as_ref()
+            .ok_or_else(|| AppError::Validation("Project secrets are not enabled on this instance".to_string()))
+    }
+
+    fn find(&self, key_id: &str) -> Option<&MasterKey> {
+        self.current.iter().chain(&self.previous).find(|key| key.id == key_id)
+    }
+
+    fn seal(&self, project_id: Uuid, name: &str, value: &str) -> Result<Sealed, AppError> {
+        let key = self.current()?;
+        let mut nonce = [0u8; 12];
+        rand::thread_rng().fill_bytes(&mut nonce);
+        let aad = associated_data(project_id, name);
+        let ciphertext = key
+            .cipher
+            .encrypt(Nonce::from_slice(&nonce), Payload { msg: value.as_bytes(), aad: aad.as_bytes() })
+            .map_err(|_| AppError::Internal("Failed to encrypt secret".to_string()))?;
+        Ok(Sealed { ciphertext, nonce: nonce.to_vec(), key_id: key.id.clone() })
+    }
+
+    fn open(&self, project_id: Uuid, name: &str, sealed: &Sealed) -> Result<String, AppError> {
+        let key = self.find(&sealed.key_id).ok_or_else(|| {
+            AppError::Config(format!("Secret {} is encrypted with unknown master key {}", name, sealed.key_id))
+        })?;
+        let aad = associated_data(project_id, name);
+        let plaintext = key
+            .cipher
+            .decrypt(Nonce::from_slice(&sealed.nonce), Payload { msg: &sealed.ciphertext, aad: aad.as_bytes() })
+            .map_err(|_| AppError::Internal(format!("Secret {} could not be decrypted", name)))?;
+        String::from_utf8(plaintext).map_err(|_| AppError::Internal(format!("Secret {} is not UTF-8", name)))
+    }
+}
+
+fn associated_data(project_id: Uuid, name: &str) -> String {
+    format!("{}:{}", project_id, name)
+}
+
+/// A secret of a project, without its value
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, ToSchema)]
+pub struct ProjectSecret {
+    pub id: Uuid,
+    pub project_id: Uuid,
+    /// Name of the environment variable it is exposed as
+    pub name: String,
+    pub expose_to_compile: bool,
+    pub created_by: Option<Uuid>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// Secret creation request; setting an existing name replaces its value
+#[derive(Deserialize, ToSchema)]
+pub struct SetProjectSecret {
+    /// Upper case letters, digits and underscores, not starting with a digit
+    pub name: String,
+    pub value: String,
+    /// Set it as an environment variable of the engine in compile jobs
+    #[serde(default)]
+    pub expose_to_compile: bool,
+}
+
+#[derive(FromRow)]
+struct SealedRow {
+    id: Uuid,
+    project_id: Uuid,
+    name: String,
+    ciphertext: Vec<u8>,
+    nonce: Vec<u8>,
+    key_id: String,
+    expose_to_compile: bool,
+}
+
+impl SealedRow {
+    fn sealed(&self) -> Sealed {
+        Sealed { ciphertext: self.ciphertext.clone(), nonce: self.nonce.clone(), key_id: self.key_id.clone() }
+    }
+}
+
+const SECRET_COLUMNS: &str = "id, project_id, name, expose_to_compile, created_by, created_at, updated_at";
+
+/// Check a secret name, which doubles as an environment variable name
+pub fn validate_name(name: &str) -> Result<(), AppError> {
+    let valid = !name.is_empty()
+        && name.len() <= 64
+        && !name.starts_with(|c: char| c.is_ascii_digit())
+        && name.chars().all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == '_');
+    if !valid {
+        return Err(AppError::Validation(
+            "Secret names are up to 64 upper case letters, digits and underscores, not starting with a digit".to_string(),
+        ));
+    }
+    if RESERVED_NAMES.contains(&name) || name.starts_with("TEXMF") {
+        return Err(AppError::Validation(format!("{} is reserved for the compile environment", name)));
+    }
+    Ok(())
+}
+
+impl ProjectSecret {
+    /// Store a secret, replacing the value of an existing one of that name
+    ///
+    /// Returns the secret and whether it is new.
+    pub async fn set(
+        db: &sqlx::PgPool,
+        keyring: &SecretKeyring,
+        project_id: Uuid,
+        actor_id: Uuid,
+        request: SetProjectSecret,
+    ) -> Result<(Self, bool), AppError> {
+        validate_name(&request.name)?;
+        if request.value.len() < MIN_SECRET_VALUE_LENGTH || request.value.len() > MAX_SECRET_VALUE_LENGTH {
+            return Err(AppError::Validation(format!(
+                "Secret values must be {} to {} bytes long",
+                MIN_SECRET_VALUE_LENGTH, MAX_SECRET_VALUE_LENGTH
+            )));
+        }
+        let sealed = keyring.seal(project_id, &request.name, &request.value)?;
+
+        let mut tx = db.begin().await.map_err(AppError::Database)?;
+        let (created,): (bool,) = sqlx::query_as(
+            r#"
+            INSERT INTO project_secrets (project_id, name, ciphertext, nonce, key_id, expose_to_compile, created_by)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)
+            ON CONFLICT (project_id, name) DO UPDATE
+            SET ciphertext = EXCLUDED.ciphertext, nonce = EXCLUDED.nonce, key_id = EXCLUDED.key_id,
+                expose_to_compile = EXCLUDED.expose_to_compile, updated_at = NOW()
+            RETURNING (xmax = 0)
+            "#
+        )
+        .bind(project_id)
+        .bind(&request.name)
+        .bind(&sealed.ciphertext)
+        .bind(&sealed.nonce)
+        .bind(&sealed.key_id)
+        .bind(request.expose_to_compile)
+        .bind(actor_id)
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(AppError::Database)?;
+
+        let secret = sqlx::query_as::<_, ProjectSecret>(&format!(
+            "SELECT {} FROM project_secrets WHERE project_id = $1 AND name = $2",
+            SECRET_COLUMNS
+        ))
+        .bind(project_id)
+        .bind(&request.name)
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(AppError::Database)?;
+
+        AuditEvent::record(
+            &mut *tx,
+            Some(actor_id),
+            if created { "project_secret_created" } else { "project_secret_updated" },
+            "project_secret",
+            Some(secret.id),
+            Some(project_id),
+            serde_json::json!({ "name": secret.name, "expose_to_compile": secret.expose_to_compile }),
+        )
+        .await?;
+        tx.commit().await.map_err(AppError::Database)?;
+
+        Ok((secret, created))
+    }
+
+    /// Secrets of a project by name
+    pub async fn list(db: &sqlx::PgPool, project_id: Uuid) -> Result<Vec<Self>, AppError> {
+        sqlx::query_as::<_, ProjectSecret>(&format!(
+            "SELECT {} FROM project_secrets WHERE project_id = $1 ORDER BY name",
+            SECRET_COLUMNS
+        ))
+        .bind(project_id)
+        .fetch_all(db)
+        .await
+        .map_err(AppError::Database)
+    }
+
+    /// Delete a secret; false when the project has none of that name
+    pub async fn delete(db: &sqlx::PgPool, project_id: Uuid, actor_id: Uuid, name: &str) -> Result<bool, AppError> {
+        let mut tx = db.begin().await.map_err(AppError::Database)?;
+        let deleted: Option<(Uuid,)> =
+            sqlx::query_as("DELETE FROM project_secrets WHERE project_id = $1 AND name = $2 RETURNING id")
+                .bind(project_id)
+                .bind(name)
+                .fetch_optional(&mut *tx)
+                .await
+                .map_err(AppError::Database)?;
+        let Some((secret_id,)) = deleted else { return Ok(false) };
+
+        AuditEvent::record(
+            &mut *tx,
+            Some(actor_id),
+            "project_secret_deleted",
+            "project_secret",
+            Some(secret_id),
+            Some(project_id),
+            serde_json::json!({ "name": name }),
+        )
+        .await?;
+        tx.commit().await.map_err(AppError::Database)?;
+        Ok(true)
+    }
+
+    /// Re-encrypt up to `limit` secrets still under a previous master key
+    ///
+    /// Returns how many were moved to the current key.
+    pub async fn rekey(db: &sqlx::PgPool, keyring: &SecretKeyring, limit: i64) -> Result<u64, AppError> {
+        let Some(current) = keyring.current.as_ref() else { return Ok(0) };
+        let rows = sqlx::query_as::<_, SealedRow>(
+            "SELECT * FROM project_secrets WHERE key_id <> $1 ORDER BY created_at LIMIT $2"
+        )
+        .bind(&current.id)
+        .bind(limit)
+        .fetch_all(db)
+        .await
+        .map_err(AppError::Database)?;
+
+        let mut moved = 0;
+        for row in rows {
+            let value = match keyring.open(row.project_id, &row.name, &row.sealed()) {
+                Ok(value) => value,
+                Err(e) => {
+                    tracing::warn!("Cannot rekey secret {}: {}", row.id, e);
+                    continue;
+                }
+            };
+            let sealed = keyring.seal(row.project_id, &row.name, &value)?;
+            // A secret replaced meanwhile already has the current key
+            let result = sqlx::query(
+                "UPDATE project_secrets SET ciphertext = $1, nonce = $2, key_id = $3 WHERE id = $4 AND key_id = $5"
+            )
+            .bind(&sealed.ciphertext)
+            .bind(&sealed.nonce)
+            .bind(&sealed.key_id)
+            .bind(row.id)
+            .bind(&row.key_id)
+            .execute(db)
+            .await
+            .map_err(AppError::Database)?;
+            moved += result.rows_affected();
+        }
+        Ok(moved)
+    }
+}
+
+/// Decrypted secrets of a project, for one compile
+#[derive(Default)]
+pub struct CompileSecrets {
+    /// Secrets exposed to the engine, as variable name and value
+    env: Vec<(String, String)>,
+    /// Every value of the project, longest first
+    values: Vec<String>,
+}
+
+impl std::fmt::Debug for CompileSecrets {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CompileSecrets")
+            .field("env", &self.env.iter().map(|(name, _)| name).collect::<Vec<_>>())
+            .finish_non_exhaustive()
+    }
+}
+
+impl CompileSecrets {
+    /// All secrets of a project; ones that cannot be decrypted are skipped,
+    /// as they cannot have been injected either
+    pub async fn load(db: &sqlx::PgPool, keyring: &SecretKeyring, project_id: Uuid) -> Result<Self, AppError> {
+        if !keyring.is_enabled() && keyring.previous.is_empty() {
+            return Ok(Self::default());
+        }
+        let rows = sqlx::query_as::<_, SealedRow>("SELECT * FROM project_secrets WHERE project_id = $1 ORDER BY name")
+            .bind(project_id)
+            .fetch_all(db)
+            .await
+            .map_err(AppError::Database)?;
+
+        let mut secrets = Self::default();
+        for row in rows {
+            match keyring.open(project_id, &row.name, &row.sealed()) {
+                Ok(value) => {
+                    if row.expose_to_compile {
+                        secrets.env.push((row.name, value.clone()));
+                    }
+                    secrets.values.push(value);
+                }
+                Err(e) => tracing::warn!("Skipped secret {} of project {}: {}", row.name, project_id, e),
+            }
+        }
+        secrets.values.sort_by_key(|value| std::cmp::Reverse(value.len()));
+        Ok(secrets)
+    }
+
+    /// Secrets for running `job`, recording which ones it receives
+    pub async fn for_job(db: &sqlx::PgPool, keyring: &SecretKeyring, job: &CompilationJob) -> Result<Self, AppError> {
+        let secrets = Self::load(db, keyring, job.project_id).await?;
+        if !secrets.env.is_empty() {
+            AuditEvent::record(
+                db,
+                Some(job.user_id),
+                "project_secrets_injected",
+                "compilation_job",
+                Some(job.id),
+                Some(job.project_id),
+                serde_json::json!({ "names": secrets.names() }),
+            )
+            .await?;
+        }
+        Ok(secrets)
+    }
+
+    /// Names of the secrets exposed to the engine
+    pub fn names(&self) -> Vec<&str> {
+        self.env.iter().map(|(name, _)| name.as_str()).collect()
+    }
+
+    /// Set the exposed secrets as environment variables of the engine
+    pub fn apply<'a>(&self, command: &'a mut tokio::process::Command) -> &'a mut tokio::process::Command {
+        command.envs(self.env.iter().map(|(name, value)| (name, value)))
+    }
+
+    /// `text` with every secret value replaced by `***`
+    pub fn redact(&self, text: &str) -> String {
+        let mut redacted = text.to_string();
+        for value in &self.values {
+            if redacted.contains(value.as_str()) {
+                redacted = redacted.replace(value.as_str(), REDACTED);
+            }
+        }
+        redacted
+    }
+}
+
+/// Moves secrets encrypted under a previous master key to the current one
+pub struct ProjectSecretRekeyTask;
+
+impl crate::tasks::PeriodicTask for ProjectSecretRekeyTask {
+    fn name(&self) -> &'static str {
+        "project_secret_rekey"
+    }
+
+    fn interval(&self) -> Duration {
+        REKEY_INTERVAL
+    }
+
+    fn run<'a>(
+        &'a self,
+        state: &'a crate::server::AppState,
+    ) -> futures::future::BoxFuture<'a, Result<(), AppError>> {
+        Box::pin(async move {
+            let moved = ProjectSecret::rekey(&state.db_pool, &state.secret_keyring, REKEY_BATCH).await?;
+            if moved > 0 {
+                tracing::info!("Re-encrypted {} project secrets under the current master key", moved);
+            }
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{create_test_project, create_test_user, TestDb};
+
+    fn keyring(current: u8, previous: &[u8]) -> SecretKeyring {
+        let key = |byte: u8| STANDARD.encode([byte; 32]);
+        SecretKeyring::from_config(&SecretsConfig {
+            master_key: Some(key(current)),
+            previous_master_keys: previous.iter().map(|&byte| key(byte)).collect(),
+        })
+        .unwrap()
+    }
+
+    fn secret(name: &str, value: &str, expose_to_compile: bool) -> SetProjectSecret {
+        SetProjectSecret { name: name.to_string(), value: value.to_string(), expose_to_compile }
+    }
+
+    #[test]
+    fn test_names_must_be_environment_variables() {
+        assert!(validate_name("STATS_API_KEY").is_ok());
+        for name in ["", "api_key", "1KEY", "KEY-1", "PATH", "TEXMFHOME", &"A".repeat(65)] {
+            assert!(validate_name(name).is_err(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_values_are_bound_to_their_project_and_name() {
+        let keyring = keyring(1, &[]);
+        let project_id = Uuid::new_v4();
+        let sealed = keyring.seal(project_id, "TOKEN", "hunter22").unwrap();
+        assert_eq!(keyring.open(project_id, "TOKEN", &sealed).unwrap(), "hunter22");
+        assert!(keyring.open(project_id, "OTHER", &sealed).is_err());
+        assert!(keyring.open(Uuid::new_v4(), "TOKEN", &sealed).is_err());
+        assert!(SecretKeyring::from_config(&SecretsConfig { master_key: Some("c2hvcnQ=".to_string()), previous_master_keys: Vec::new() }).is_err());
+    }
+
+    #[test]
+    fn test_redaction_prefers_longer_values() {
+        let secrets = CompileSecrets {
+            env: Vec::new(),
+            values: vec!["token-and-more".to_string(), "token".to_string()],
+        };
+        assert_eq!(secrets.redact("a token-and-more b token c"), "a *** b *** c");
+    }
+
+    #[tokio::test]
+    async fn test_secrets_survive_key_rotation() {
+        let Some(db) = TestDb::start().await else { return };
+        let owner = create_test_user(&db.pool).await;
+        let project = create_test_project(&db.pool, &owner, false).await;
+
+        let old = keyring(1, &[]);
+        ProjectSecret::set(&db.pool, &old, project.id, owner.id, secret("STATS_API_KEY", "s3cr3t-value", true)).await.unwrap();
+        let (listed, created) =
+            ProjectSecret::set(&db.pool, &old, project.id, owner.id, secret("HIDDEN", "never-exposed", false)).await.unwrap();
+        assert!(created && !listed.expose_to_compile);
+        let (_, created) =
+            ProjectSecret::set(&db.pool, &old, project.id, owner.id, secret("HIDDEN", "still-hidden", false)).await.unwrap();
+        assert!(!created);
+
+        let rotated = keyring(2, &[1]);
+        assert_eq!(ProjectSecret::rekey(&db.pool, &rotated, 100).await.unwrap(), 2);
+        assert_eq!(ProjectSecret::rekey(&db.pool, &rotated, 100).await.unwrap(), 0);
+
+        // The old key is no longer needed
+        let secrets = CompileSecrets::load(&db.pool, &keyring(2, &[]), project.id).await.unwrap();
+        assert_eq!(secrets.names(), vec!["STATS_API_KEY"]);
+        assert_eq!(secrets.redact("key=s3cr3t-value hidden=still-hidden"), "key=*** hidden=***");
+
+        let names: Vec<String> = ProjectSecret::list(&db.pool, project.id).await.unwrap().into_iter().map(|s| s.name).collect();
+        assert_eq!(names, vec!["HIDDEN", "STATS_API_KEY"]);
+        assert!(ProjectSecret::delete(&db.pool, project.id, owner.id, "HIDDEN").await.unwrap());
+        assert!(!ProjectSecret::delete(&db.pool, project.id, owner.id, "HIDDEN").await.unwrap());
+
+        let (audited,): (i64,) = sqlx::query_as(
+            "SELECT COUNT(*) FROM audit_log WHERE project_id = $1 AND entity_type = 'project_secret'"
+        )
+        .bind(project.id)
+        .fetch_one(&db.pool)
+        .await
+        .unwrap();
+        assert_eq!(audited, 4);
+    }
+}
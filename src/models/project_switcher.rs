@@ -0,0 +1,228 @@
+//! Project switcher
+//!
+//! The switcher is opened on almost every navigation, often before anything
+//! else of the app has loaded, so it gets its own listing instead of the
+//! full project list: one statement returns the few columns it shows for
+//! the most relevant projects, favorites first and then the ones the user
+//! opened most recently. Nothing is loaded per row; the active users come
+//! from the in-memory presence counts. Names are cut short and the list
+//! capped so the response stays a few KB however many projects the user
+//! has, with `has_more` telling the client to offer the full list.
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use sqlx::FromRow;
+use std::time::Duration;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use super::project_presence::ProjectPresence;
+use super::{CompilationStatus, UserRole};
+use crate::error::AppError;
+
+/// Projects listed at most
+pub const SWITCHER_LIMIT: i64 = 50;
+
+/// Characters of project and workspace names kept
+pub const SWITCHER_NAME_CHARS: i32 = 64;
+
+/// Seconds clients may reuse a listing without asking again
+pub const SWITCHER_MAX_AGE: u32 = 5;
+
+/// Time a listing should take to serve
+pub const SWITCHER_BUDGET: Duration = Duration::from_millis(10);
+
+/// A project in the switcher
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct SwitcherProject {
+    pub id: Uuid,
+    /// Project name, cut to `SWITCHER_NAME_CHARS` characters
+    pub name: String,
+    /// Name of the project's workspace, cut the same way
+    pub workspace_name: String,
+    /// The user's role; `viewer` for public projects they are not a member of
+    pub role: UserRole,
+    pub favorite: bool,
+    /// When the user last opened the project, if ever
+    pub last_opened_at: Option<DateTime<Utc>>,
+    pub compilation_status: CompilationStatus,
+    /// Users in the project's collaboration sessions right now
+    pub active_collaborators_now: u32,
+}
+
+/// Projects for the switcher, most relevant first
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct ProjectSwitcher {
+    pub projects: Vec<SwitcherProject>,
+    /// Whether the user has projects beyond the listed ones
+    pub has_more: bool,
+}
+
+#[derive(Debug, FromRow)]
+struct SwitcherRow {
+    id: Uuid,
+    name: String,
+    workspace_name: String,
+    role: String,
+    favorite: bool,
+    last_opened_at: Option<DateTime<Utc>>,
+    compilation_status: CompilationStatus,
+}
+
+impl ProjectSwitcher {
+    /// The switcher of `user_id`, in a single statement
+    ///
+    /// Lists the projects the user owns or collaborates on, and public
+    /// projects they opened or marked as favorite while they stay public.
+    pub async fn load(
+        db: impl sqlx::PgExecutor<'_>,
+        presence: &ProjectPresence,
+        user_id: Uuid,
+    ) -> Result<Self, AppError> {
+        let mut rows = sqlx::query_as::<_, SwitcherRow>(
+            r#"
+            SELECT p.id,
+                   LEFT(p.name, $3) AS name,
+                   LEFT(w.name, $3) AS workspace_name,
+                   CASE WHEN p.owner_id = $1 THEN 'owner' ELSE COALESCE(pc.role::text, 'viewer') END AS role,
+                   f.user_id IS NOT NULL AS favorite,
+                   o.last_opened_at,
+                   p.compilation_status
+            FROM projects p
+            JOIN workspaces w ON w.id = p.workspace_id
+            LEFT JOIN project_collaborators pc ON pc.project_id = p.id AND pc.user_id = $1
+            LEFT JOIN project_opens o ON o.project_id = p.id AND o.user_id = $1
+            LEFT JOIN project_favorites f ON f.project_id = p.id AND f.user_id = $1
+            WHERE p.purging_at IS NULL
+              AND (p.owner_id = $1
+                   OR pc.user_id IS NOT NULL
+                   OR (p.is_public AND (o.user_id IS NOT NULL OR f.user_id IS NOT NULL)))
+            ORDER BY favorite DESC, o.last_opened_at DESC NULLS LAST, p.updated_at DESC, p.id
+            LIMIT $2
+            "#,
+        )
+        .bind(user_id)
+        .bind(SWITCHER_LIMIT + 1)
+        .bind(SWITCHER_NAME_CHARS)
+        .fetch_all(db)
+        .await
+        .map_err(AppError::Database)?;
+
+        let has_more = rows.len() as i64 > SWITCHER_LIMIT;
+        rows.truncate(SWITCHER_LIMIT as usize);
+        let ids: Vec<Uuid> = rows.iter().map(|row| row.id).collect();
+        let counts = presence.counts(&ids);
+
+        let projects = rows
+            .into_iter()
+            .map(|row| SwitcherProject {
+                id: row.id,
+                name: row.name,
+                workspace_name: row.workspace_name,
+                role: UserRole::from_name(&row.role).unwrap_or(UserRole::Viewer),
+                favorite: row.favorite,
+                last_opened_at: row.last_opened_at,
+                compilation_status: row.compilation_status,
+                active_collaborators_now: counts.get(&row.id).copied().unwrap_or(0),
+            })
+            .collect();
+        Ok(Self { projects, has_more })
+    }
+
+    /// Strong validator of the listing, as an ETag
+    pub fn etag(&self) -> String {
+        let json = serde_json::to_vec(self).unwrap_or_default();
+        let digest = Sha256::digest(&json);
+        format!("\"{}\"", hex::encode(&digest[..16]))
+    }
+
+    /// Note that `user_id` opened `project_id` just now
+    pub async fn record_open(db: &sqlx::PgPool, user_id: Uuid, project_id: Uuid) -> Result<(), AppError> {
+        sqlx::query(
+            r#"
+            INSERT INTO project_opens (user_id, project_id)
+            VALUES ($1, $2)
+            ON CONFLICT (user_id, project_id) DO UPDATE SET last_opened_at = NOW()
+            "#,
+        )
+        .bind(user_id)
+        .bind(project_id)
+        .execute(db)
+        .await
+        .map_err(AppError::Database)?;
+        Ok(())
+    }
+
+    /// Mark `project_id` as a favorite of `user_id`, or unmark it
+    pub async fn set_favorite(
+        db: &sqlx::PgPool,
+        user_id: Uuid,
+        project_id: Uuid,
+        favorite: bool,
+    ) -> Result<(), AppError> {
+        let query = if favorite {
+            sqlx::query(
+                "INSERT INTO project_favorites (user_id, project_id) VALUES ($1, $2) ON CONFLICT DO NOTHING",
+            )
+        } else {
+            sqlx::query("DELETE FROM project_favorites WHERE user_id = $1 AND project_id = $2")
+        };
+        query
+            .bind(user_id)
+            .bind(project_id)
+            .execute(db)
+            .await
+            .map_err(AppError::Database)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{add_collaborator, create_test_project, create_test_user, TestDb};
+
+    #[tokio::test]
+    async fn test_switcher_lists_favorites_then_recent() {
+        let Some(db) = TestDb::start().await else { return };
+        let presence = ProjectPresence::new();
+        let user = create_test_user(&db.pool).await;
+        let other = create_test_user(&db.pool).await;
+        let older = create_test_project(&db.pool, &user, false).await;
+        let newer = create_test_project(&db.pool, &user, false).await;
+        let pinned = create_test_project(&db.pool, &user, false).await;
+        let shared = create_test_project(&db.pool, &other, false).await;
+        add_collaborator(&db.pool, &shared, &user, UserRole::Viewer).await;
+        let public = create_test_project(&db.pool, &other, true).await;
+        let private = create_test_project(&db.pool, &other, false).await;
+
+        ProjectSwitcher::record_open(&db.pool, user.id, older.id).await.unwrap();
+        ProjectSwitcher::record_open(&db.pool, user.id, newer.id).await.unwrap();
+        ProjectSwitcher::set_favorite(&db.pool, user.id, pinned.id, true).await.unwrap();
+        presence.enter("connection", newer.id, other.id);
+
+        let ids = |switcher: &ProjectSwitcher| switcher.projects.iter().map(|p| p.id).collect::<Vec<_>>();
+        let switcher = ProjectSwitcher::load(&db.pool, &presence, user.id).await.unwrap();
+        assert_eq!(ids(&switcher)[..3], [pinned.id, newer.id, older.id]);
+        assert_eq!(ids(&switcher).len(), 4);
+        assert!(!switcher.has_more);
+
+        let listed = |id: Uuid| switcher.projects.iter().find(|p| p.id == id).unwrap();
+        assert_eq!(listed(pinned.id).role, UserRole::Owner);
+        assert!(listed(pinned.id).favorite);
+        assert!(listed(pinned.id).last_opened_at.is_none());
+        assert_eq!(listed(shared.id).role, UserRole::Viewer);
+        assert_eq!(listed(newer.id).active_collaborators_now, 1);
+        assert!(!listed(newer.id).workspace_name.is_empty());
+
+        // Public projects show up once opened, private ones never
+        ProjectSwitcher::record_open(&db.pool, user.id, public.id).await.unwrap();
+        ProjectSwitcher::record_open(&db.pool, user.id, private.id).await.unwrap();
+        ProjectSwitcher::set_favorite(&db.pool, user.id, pinned.id, false).await.unwrap();
+        let again = ProjectSwitcher::load(&db.pool, &presence, user.id).await.unwrap();
+        assert_eq!(ids(&again)[0], public.id);
+        assert!(!ids(&again).contains(&private.id));
+        assert_ne!(again.etag(), switcher.etag());
+    }
+}
@@ -0,0 +1,429 @@
+//! Projects created from templates
+//!
+//! A template can name a source project whose live files new projects start
+//! with, and declare variables for the things every user changes at once:
+//! author, title, course number. Text files may contain `{{name}}`
+//! placeholders, which are filled in while the files are copied.
+//!
+//! Only a declared name between the double braces, with nothing else inside
+//! them, is a placeholder; every other brace is copied as it is, so TikZ
+//! options such as `{{0,1}}` and TeX groups survive. Substitution knows
+//! nothing of TeX: a placeholder in a `verbatim` environment or a comment is
+//! filled in like any other, and values are inserted as written. Binary
+//! files are copied unchanged.
+//!
+//! Values are checked against the declarations before anything is created,
+//! and every problem is reported with the variable it concerns.
+
+use regex::{Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use super::compilation::CompilationTemplate;
+use super::file::File;
+use super::project::{CreateProject, Project, ProjectActivity};
+use super::project_clone::copy_file;
+use super::project_limits::ProjectLimits;
+use super::validation::ProjectName;
+use super::StorageStrategy;
+use crate::error::AppError;
+
+/// Most variables a template may declare
+pub const MAX_TEMPLATE_VARIABLES: usize = 50;
+
+/// Longest variable name, in bytes
+pub const MAX_VARIABLE_NAME_LENGTH: usize = 64;
+
+/// Longest label, in characters
+pub const MAX_VARIABLE_LABEL_CHARS: usize = 200;
+
+/// Longest validation pattern, in bytes
+pub const MAX_PATTERN_LENGTH: usize = 500;
+
+/// Longest value, in bytes
+pub const MAX_VALUE_LENGTH: usize = 1000;
+
+/// Compiled size a validation pattern may take
+const PATTERN_SIZE_LIMIT: usize = 1 << 20;
+
+/// A variable declared by a template
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct TemplateVariable {
+    /// Used as `{{name}}` in the files: ASCII letters, digits and
+    /// underscores, not starting with a digit
+    pub name: String,
+    /// Shown next to the form field
+    pub label: String,
+    /// Used when no value is given
+    pub default: Option<String>,
+    /// Whether a value, or a default, must be present
+    #[serde(default)]
+    pub required: bool,
+    /// Regular expression the whole value must match
+    pub pattern: Option<String>,
+}
+
+/// A problem with the value of one variable
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+pub struct FieldError {
+    /// Name of the variable
+    pub field: String,
+    pub message: String,
+}
+
+/// Request to create a project from a template
+#[derive(Debug, Clone, Deserialize, ToSchema)]
+pub struct CreateFromTemplate {
+    /// Workspace of the caller to create the project in
+    pub workspace_id: Uuid,
+    pub name: ProjectName,
+    /// Value of each variable, by name
+    #[serde(default)]
+    pub values: HashMap<String, String>,
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+fn compile_pattern(pattern: &str) -> Result<Regex, regex::Error> {
+    RegexBuilder::new(&format!("^(?:{})$", pattern))
+        .size_limit(PATTERN_SIZE_LIMIT)
+        .build()
+}
+
+/// Check the variables a template declares
+pub fn validate_declarations(variables: &[TemplateVariable]) -> Result<(), AppError> {
+    if variables.len() > MAX_TEMPLATE_VARIABLES {
+        return Err(AppError::Validation(format!(
+            "A template declares at most {} variables",
+            MAX_TEMPLATE_VARIABLES
+        )));
+    }
+
+    let mut seen = std::collections::HashSet::new();
+    for variable in variables {
+        let name = variable.name.as_str();
+        if name.is_empty()
+            || name.len() > MAX_VARIABLE_NAME_LENGTH
+            || name.starts_with(|c: char| c.is_ascii_digit())
+            || !name.chars().all(is_name_char)
+        {
+            return Err(AppError::Validation(format!(
+                "Variable name {:?} must be up to {} ASCII letters, digits and underscores, not starting with a digit",
+                name, MAX_VARIABLE_NAME_LENGTH
+            )));
+        }
+        if !seen.insert(name) {
+            return Err(AppError::Validation(format!("Variable {} is declared twice", name)));
+        }
+        if variable.label.trim().is_empty() || variable.label.chars().count() > MAX_VARIABLE_LABEL_CHARS {
+            return Err(AppError::Validation(format!(
+                "Label of {} must be 1 to {} characters",
+                name, MAX_VARIABLE_LABEL_CHARS
+            )));
+        }
+        if let Some(pattern) = &variable.pattern {
+            if pattern.len() > MAX_PATTERN_LENGTH {
+                return Err(AppError::Validation(format!(
+                    "Pattern of {} exceeds {} bytes",
+                    name, MAX_PATTERN_LENGTH
+                )));
+            }
+            let regex = compile_pattern(pattern)
+                .map_err(|e| AppError::Validation(format!("Invalid pattern of {}: {}", name, e)))?;
+            if let Some(default) = &variable.default {
+                if !regex.is_match(default) {
+                    return Err(AppError::Validation(format!(
+                        "Default of {} does not match its pattern",
+                        name
+                    )));
+                }
+            }
+        }
+        if variable.default.as_ref().is_some_and(|default| default.len() > MAX_VALUE_LENGTH) {
+            return Err(AppError::Validation(format!(
+                "Default of {} exceeds {} bytes",
+                name, MAX_VALUE_LENGTH
+            )));
+        }
+    }
+
+    Ok(())
+}
+
+/// The value of every declared variable, from `values` or the defaults
+///
+/// An empty value counts as missing. Optional variables without a value or
+/// default are filled in with nothing.
+pub fn resolve(
+    variables: &[TemplateVariable],
+    values: &HashMap<String, String>,
+) -> Result<HashMap<String, String>, Vec<FieldError>> {
+    let mut errors = Vec::new();
+    let mut unknown: Vec<&String> = values
+        .keys()
+        .filter(|name| !variables.iter().any(|variable| &variable.name == *name))
+        .collect();
+    unknown.sort();
+
+    let mut resolved = HashMap::new();
+    for variable in variables {
+        let error = |message: String| FieldError { field: variable.name.clone(), message };
+        let value = values
+            .get(&variable.name)
+            .filter(|value| !value.is_empty())
+            .or(variable.default.as_ref().filter(|default| !default.is_empty()));
+        let Some(value) = value else {
+            if variable.required {
+                errors.push(error(format!("{} is required", variable.label)));
+            } else {
+                resolved.insert(variable.name.clone(), String::new());
+            }
+            continue;
+        };
+
+        if value.len() > MAX_VALUE_LENGTH {
+            errors.push(error(format!("{} exceeds {} bytes", variable.label, MAX_VALUE_LENGTH)));
+            continue;
+        }
+        if let Some(pattern) = &variable.pattern {
+            // Declarations were checked when the template was saved
+            let matches = compile_pattern(pattern).map(|regex| regex.is_match(value)).unwrap_or(false);
+            if !matches {
+                errors.push(error(format!("{} must match {}", variable.label, pattern)));
+                continue;
+            }
+        }
+        resolved.insert(variable.name.clone(), value.clone());
+    }
+
+    errors.extend(unknown.into_iter().map(|name| FieldError {
+        field: name.clone(),
+        message: "Not a variable of this template".to_string(),
+    }));
+    if errors.is_empty() {
+        Ok(resolved)
+    } else {
+        Err(errors)
+    }
+}
+
+/// Fill the `{{name}}` placeholders of `values` into `content`
+pub fn substitute(content: &str, values: &HashMap<String, String>) -> String {
+    let mut substituted = String::with_capacity(content.len());
+    let mut rest = content;
+    while let Some(start) = rest.find("{{") {
+        substituted.push_str(&rest[..start]);
+        let inner = &rest[start + 2..];
+        let name_length = inner.find(|c: char| !is_name_char(c)).unwrap_or(inner.len());
+        match values.get(&inner[..name_length]) {
+            Some(value) if name_length > 0 && inner[name_length..].starts_with("}}") => {
+                substituted.push_str(value);
+                rest = &inner[name_length + 2..];
+            }
+            // Not a placeholder; the next brace may start one, as in `{{{title}}}`
+            _ => {
+                substituted.push('{');
+                rest = &rest[start + 1..];
+            }
+        }
+    }
+    substituted.push_str(rest);
+    substituted
+}
+
+impl Project {
+    /// Create a project from a template's starter files, with `values`
+    /// resolved against its variables
+    pub async fn create_from_template(
+        db: &sqlx::PgPool,
+        limits: &ProjectLimits,
+        template: &CompilationTemplate,
+        user_id: Uuid,
+        request: CreateFromTemplate,
+        values: &HashMap<String, String>,
+    ) -> Result<Self, AppError> {
+        let source = match template.source_project_id {
+            Some(source_id) => sqlx::query_as::<_, Project>("SELECT * FROM projects WHERE id = $1 AND purging_at IS NULL")
+                .bind(source_id)
+                .fetch_optional(db)
+                .await
+                .map_err(AppError::Database)?,
+            None => None,
+        }
+        .ok_or_else(|| AppError::Validation(format!("Template {} has no starter files", template.name)))?;
+
+        let files = File::list_all_for_project(db, source.id).await?;
+
+        let mut tx = db.begin().await.map_err(AppError::Database)?;
+
+        let project = Self::create(
+            &mut *tx,
+            user_id,
+            CreateProject {
+                name: request.name,
+                description: template.description.clone(),
+                is_public: Some(false),
+                main_file_path: Some(source.main_file_path.clone()),
+                latex_engine: Some(template.engine),
+                output_format: Some(source.output_format.clone()),
+                custom_args: Some(source.custom_args.clone()),
+                bibliography_path: source.bibliography_path.clone(),
+                tags: None,
+                workspace_id: Some(request.workspace_id),
+            },
+        )
+        .await?;
+
+        let mut copied = 0;
+        for file in files.iter().filter(|file| !file.is_quarantined()) {
+            let content = (file.storage_strategy != StorageStrategy::External).then(|| substitute(&file.content, values));
+            copy_file(&mut tx, limits, project.id, file, content, user_id).await?;
+            copied += 1;
+        }
+
+        sqlx::query("UPDATE files SET is_main = (path = $2) WHERE project_id = $1")
+            .bind(project.id)
+            .bind(&project.main_file_path)
+            .execute(&mut *tx)
+            .await
+            .map_err(AppError::Database)?;
+
+        ProjectActivity::log(
+            &mut *tx,
+            project.id,
+            user_id,
+            "project_created_from_template",
+            "project",
+            Some(template.id),
+            Some(
+                serde_json::json!({
+                    "template_id": template.id,
+                    "copied": copied,
+                    "variables": values.len(),
+                })
+                .to_string(),
+            ),
+        )
+        .await?;
+
+        tx.commit().await.map_err(AppError::Database)?;
+
+        Ok(project)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn variable(name: &str, required: bool, default: Option<&str>, pattern: Option<&str>) -> TemplateVariable {
+        TemplateVariable {
+            name: name.to_string(),
+            label: name.replace('_', " "),
+            default: default.map(str::to_string),
+            required,
+            pattern: pattern.map(str::to_string),
+        }
+    }
+
+    fn values(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect()
+    }
+
+    #[test]
+    fn test_placeholders_are_filled_in() {
+        let values = values(&[("author", "Ada Lovelace"), ("title", "Notes"), ("course", "CS 101")]);
+
+        assert_eq!(substitute("\\author{{{author}}}", &values), "\\author{Ada Lovelace}");
+        assert_eq!(substitute("{{course}}: {{title}}", &values), "CS 101: Notes");
+        // Adjacent placeholders
+        assert_eq!(substitute("{{course}}{{title}}{{author}}", &values), "CS 101NotesAda Lovelace");
+        // Creation-time templating, not TeX: verbatim and comments too
+        assert_eq!(
+            substitute("\\begin{verbatim}\n{{title}}\n\\end{verbatim}\n% by {{author}}\n", &values),
+            "\\begin{verbatim}\nNotes\n\\end{verbatim}\n% by Ada Lovelace\n"
+        );
+        // Values are inserted as written, never substituted again
+        let nested = HashMap::from([("a".to_string(), "{{b}}".to_string()), ("b".to_string(), "x".to_string())]);
+        assert_eq!(substitute("{{a}}", &nested), "{{b}}");
+    }
+
+    #[test]
+    fn test_other_braces_are_left_alone() {
+        let values = values(&[("title", "Notes"), ("x", "1")]);
+
+        for content in [
+            "\\draw[->] (0,0) -- (1,1) node[pos={{0.5}}] {};",
+            "\\foreach \\p in {{0,1},{1,0}} {}",
+            "{{ title }}",
+            "{{Title}}",
+            "{{undeclared}}",
+            "{{title}",
+            "{{title",
+            "{{}}",
+            "}}{{",
+            "{",
+            "",
+        ] {
+            assert_eq!(substitute(content, &values), content);
+        }
+        assert_eq!(substitute("{{{{title}}}}", &values), "{{Notes}}");
+        assert_eq!(substitute("{{x}}{{0,1}}", &values), "1{{0,1}}");
+        assert_eq!(substitute("é{{title}}ü", &values), "éNotesü");
+    }
+
+    #[test]
+    fn test_values_are_checked_per_field() {
+        let variables = vec![
+            variable("author", true, None, None),
+            variable("course", true, Some("CS 101"), Some("[A-Z]+ [0-9]{3}")),
+            variable("subtitle", false, None, None),
+            variable("year", false, Some("2026"), Some("[0-9]{4}")),
+        ];
+
+        let resolved = resolve(&variables, &values(&[("author", "Ada")])).unwrap();
+        assert_eq!(resolved, values(&[("author", "Ada"), ("course", "CS 101"), ("subtitle", ""), ("year", "2026")]));
+
+        let errors = resolve(
+            &variables,
+            &values(&[("author", ""), ("course", "cs101"), ("year", "26"), ("instructor", "Bob"), ("abstract", "x")]),
+        )
+        .unwrap_err();
+        let fields: Vec<&str> = errors.iter().map(|error| error.field.as_str()).collect();
+        assert_eq!(fields, vec!["author", "course", "year", "abstract", "instructor"]);
+        assert_eq!(errors[0].message, "author is required");
+        assert_eq!(errors[1].message, "course must match [A-Z]+ [0-9]{3}");
+
+        // The pattern covers the whole value
+        assert!(resolve(&variables, &values(&[("author", "Ada"), ("year", "20261")])).is_err());
+        let long = "x".repeat(MAX_VALUE_LENGTH + 1);
+        assert!(resolve(&variables, &values(&[("author", long.as_str())])).is_err());
+    }
+
+    #[test]
+    fn test_declarations_are_validated() {
+        assert!(validate_declarations(&[variable("author_name", true, None, Some("[A-Za-z ]+"))]).is_ok());
+
+        for declarations in [
+            vec![variable("author name", false, None, None)],
+            vec![variable("1st", false, None, None)],
+            vec![variable("", false, None, None)],
+            vec![variable("a", false, None, None), variable("a", false, None, None)],
+            vec![variable("year", false, None, Some("[0-9"))],
+            vec![variable("year", false, Some("soon"), Some("[0-9]{4}"))],
+            vec![TemplateVariable { label: " ".to_string(), ..variable("a", false, None, None) }],
+        ] {
+            assert!(
+                matches!(validate_declarations(&declarations), Err(AppError::Validation(_))),
+                "{:?}",
+                declarations
+            );
+        }
+        let many: Vec<_> = (0..=MAX_TEMPLATE_VARIABLES).map(|i| variable(&format!("v{}", i), false, None, None)).collect();
+        assert!(validate_declarations(&many).is_err());
+    }
+}
@@ -0,0 +1,559 @@
+//! Quick search for the command palette
+//!
+//! One query string is matched against everything a user can jump to: their
+//! projects, the files and members of the project they are in, the open
+//! sessions they take part in, and the actions they may perform there. Each
+//! kind of result is a facet with its own small query, filtered by the same
+//! access rules as the endpoints it links to, so the mixed list can only hold
+//! what each facet was allowed to return. Facets run side by side and get at
+//! most `FACET_LIMIT` results; one that has not answered within the time
+//! budget is dropped and named in the response rather than holding up the
+//! rest. Matching uses the trigram indexes of migration 055, so typos still
+//! find their target.
+
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use std::future::Future;
+use std::time::Duration;
+use utoipa::{IntoParams, ToSchema};
+use uuid::Uuid;
+
+use super::file_tree::escape_like;
+use super::project::Project;
+use super::UserRole;
+use crate::error::AppError;
+
+/// Time the facets of one search get to answer
+pub const QUICK_SEARCH_BUDGET: Duration = Duration::from_millis(100);
+
+/// Results of each facet
+pub const FACET_LIMIT: i64 = 5;
+
+/// Results returned when the request does not say
+pub const DEFAULT_QUICK_SEARCH_LIMIT: u32 = 20;
+
+/// Most results returned
+pub const MAX_QUICK_SEARCH_LIMIT: u32 = 50;
+
+/// Longest query accepted, in characters
+pub const MAX_QUERY_CHARS: usize = 100;
+
+/// Quick search parameters
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct QuickSearchParams {
+    /// Text to look for
+    pub q: String,
+    /// Project the user is in; files, members and project actions come from it
+    pub project_id: Option<Uuid>,
+    /// Results to return, 20 by default and at most 50
+    pub limit: Option<u32>,
+}
+
+/// What a result points at
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum QuickSearchKind {
+    Project,
+    File,
+    Session,
+    Collaborator,
+    Action,
+}
+
+impl QuickSearchKind {
+    /// Added to the score, so equally good matches list what is closest at hand first
+    fn weight(self) -> f32 {
+        match self {
+            Self::File => 0.4,
+            Self::Project => 0.3,
+            Self::Action => 0.2,
+            Self::Session => 0.1,
+            Self::Collaborator => 0.0,
+        }
+    }
+}
+
+/// Something to jump to
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct QuickSearchResult {
+    pub kind: QuickSearchKind,
+    /// ID of the entity, or the name of the action
+    pub id: String,
+    pub label: String,
+    pub sublabel: Option<String>,
+    /// Project the result belongs to
+    pub project_id: Option<Uuid>,
+    pub project_name: Option<String>,
+    /// Higher is better
+    pub score: f32,
+}
+
+/// Ranked results, and the facets that ran out of time
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct QuickSearchResults {
+    pub results: Vec<QuickSearchResult>,
+    pub dropped: Vec<QuickSearchKind>,
+}
+
+/// A row of a facet's query
+#[derive(Debug, FromRow)]
+struct Hit {
+    id: Uuid,
+    label: String,
+    sublabel: Option<String>,
+    project_id: Option<Uuid>,
+    project_name: Option<String>,
+    similarity: f32,
+}
+
+/// An action of the command palette
+struct Action {
+    name: &'static str,
+    label: &'static str,
+    /// Role needed on the current project, `None` for actions outside projects
+    role: Option<UserRole>,
+}
+
+const ACTIONS: &[Action] = &[
+    Action { name: "create_project", label: "Create project", role: None },
+    Action { name: "compile_project", label: "Compile project", role: Some(UserRole::Viewer) },
+    Action { name: "export_project", label: "Download project archive", role: Some(UserRole::Viewer) },
+    Action { name: "invite_collaborators", label: "Invite collaborators", role: Some(UserRole::Owner) },
+    Action { name: "project_settings", label: "Project settings", role: Some(UserRole::Owner) },
+];
+
+/// How well `label` matches the lowercase `query`: whole, as a prefix, as the
+/// prefix of a word, anywhere, or not literally at all
+fn match_score(label: &str, query: &str) -> f32 {
+    let label = label.to_lowercase();
+    if label == query {
+        4.0
+    } else if label.starts_with(query) {
+        3.0
+    } else if label.split(|c: char| !c.is_alphanumeric()).any(|word| word.starts_with(query)) {
+        2.0
+    } else if label.contains(query) {
+        1.0
+    } else {
+        0.0
+    }
+}
+
+impl Hit {
+    fn into_result(self, kind: QuickSearchKind, query: &str) -> QuickSearchResult {
+        // A match in the sublabel, such as a file's directory, counts for less
+        let sublabel = self.sublabel.as_deref().map_or(0.0, |s| match_score(s, query) / 2.0);
+        let literal = match_score(&self.label, query).max(sublabel);
+        QuickSearchResult {
+            kind,
+            id: self.id.to_string(),
+            score: literal + self.similarity + kind.weight(),
+            label: self.label,
+            sublabel: self.sublabel,
+            project_id: self.project_id,
+            project_name: self.project_name,
+        }
+    }
+}
+
+/// Run a facet within `budget`; `None` when it ran out of time
+async fn within(
+    budget: Duration,
+    facet: impl Future<Output = Result<Vec<QuickSearchResult>, AppError>>,
+) -> Result<Option<Vec<QuickSearchResult>>, AppError> {
+    match tokio::time::timeout(budget, facet).await {
+        Ok(results) => results.map(Some),
+        Err(_) => Ok(None),
+    }
+}
+
+/// Search everything `user_id` can jump to
+pub async fn search(
+    db: &sqlx::PgPool,
+    user_id: Uuid,
+    params: &QuickSearchParams,
+    budget: Duration,
+) -> Result<QuickSearchResults, AppError> {
+    let query = params.q.trim().to_lowercase();
+    if query.is_empty() {
+        return Err(AppError::Validation("Search query cannot be empty".to_string()));
+    }
+    if query.chars().count() > MAX_QUERY_CHARS {
+        return Err(AppError::Validation(format!(
+            "Search query can be at most {} characters",
+            MAX_QUERY_CHARS
+        )));
+    }
+    let limit = params.limit.unwrap_or(DEFAULT_QUICK_SEARCH_LIMIT).clamp(1, MAX_QUICK_SEARCH_LIMIT) as usize;
+    let pattern = format!("%{}%", escape_like(&query));
+    let project_id = params.project_id;
+
+    let (projects, files, sessions, collaborators, actions) = tokio::join!(
+        within(budget, projects(db, user_id, &query, &pattern)),
+        within(budget, async {
+            match project_id {
+                Some(project_id) => files(db, user_id, project_id, &query, &pattern).await,
+                None => Ok(Vec::new()),
+            }
+        }),
+        within(budget, sessions(db, user_id, project_id, &query, &pattern)),
+        within(budget, async {
+            match project_id {
+                Some(project_id) => collaborators(db, user_id, project_id, &query, &pattern).await,
+                None => Ok(Vec::new()),
+            }
+        }),
+        within(budget, actions(db, user_id, project_id, &query)),
+    );
+
+    let mut results = Vec::new();
+    let mut dropped = Vec::new();
+    for (kind, facet) in [
+        (QuickSearchKind::Project, projects?),
+        (QuickSearchKind::File, files?),
+        (QuickSearchKind::Session, sessions?),
+        (QuickSearchKind::Collaborator, collaborators?),
+        (QuickSearchKind::Action, actions?),
+    ] {
+        match facet {
+            Some(facet) => results.extend(facet),
+            None => {
+                tracing::debug!("Quick search facet {:?} ran out of time", kind);
+                dropped.push(kind);
+            }
+        }
+    }
+
+    results.sort_by(|a, b| b.score.total_cmp(&a.score).then_with(|| a.label.cmp(&b.label)));
+    results.truncate(limit);
+    Ok(QuickSearchResults { results, dropped })
+}
+
+/// Projects the user owns or collaborates on
+async fn projects(db: &sqlx::PgPool, user_id: Uuid, query: &str, pattern: &str) -> Result<Vec<QuickSearchResult>, AppError> {
+    let hits = sqlx::query_as::<_, Hit>(
+        r#"
+        SELECT p.id, p.name AS label, p.description AS sublabel,
+               p.id AS project_id, p.name AS project_name,
+               similarity(p.name, $2) AS similarity
+        FROM projects p
+        WHERE p.purging_at IS NULL
+          AND (p.owner_id = $1 OR p.id IN (SELECT project_id FROM project_collaborators WHERE user_id = $1))
+          AND (p.name ILIKE $3 ESCAPE '\' OR p.name % $2)
+        ORDER BY similarity DESC, p.updated_at DESC
+        LIMIT $4
+        "#
+    )
+    .bind(user_id)
+    .bind(query)
+    .bind(pattern)
+    .bind(FACET_LIMIT)
+    .fetch_all(db)
+    .await
+    .map_err(AppError::Database)?;
+
+    Ok(hits.into_iter().map(|hit| hit.into_result(QuickSearchKind::Project, query)).collect())
+}
+
+/// Files of the current project, by name or path
+async fn files(
+    db: &sqlx::PgPool,
+    user_id: Uuid,
+    project_id: Uuid,
+    query: &str,
+    pattern: &str,
+) -> Result<Vec<QuickSearchResult>, AppError> {
+    let hits = sqlx::query_as::<_, Hit>(
+        r#"
+        SELECT f.id, f.name AS label, f.path AS sublabel,
+               p.id AS project_id, p.name AS project_name,
+               GREATEST(similarity(f.name, $3), similarity(f.path, $3)) AS similarity
+        FROM files f
+        JOIN projects p ON p.id = f.project_id
+        WHERE f.project_id = $1 AND f.is_deleted = false AND p.purging_at IS NULL
+          AND (p.owner_id = $2 OR p.is_public = true
+               OR p.id IN (SELECT project_id FROM project_collaborators WHERE user_id = $2))
+          AND (f.name ILIKE $4 ESCAPE '\' OR f.path ILIKE $4 ESCAPE '\' OR f.name % $3)
+        ORDER BY similarity DESC, LENGTH(f.path)
+        LIMIT $5
+        "#
+    )
+    .bind(project_id)
+    .bind(user_id)
+    .bind(query)
+    .bind(pattern)
+    .bind(FACET_LIMIT)
+    .fetch_all(db)
+    .await
+    .map_err(AppError::Database)?;
+
+    Ok(hits.into_iter().map(|hit| hit.into_result(QuickSearchKind::File, query)).collect())
+}
+
+/// Open sessions the user created or joined, of the current project when there is one
+async fn sessions(
+    db: &sqlx::PgPool,
+    user_id: Uuid,
+    project_id: Option<Uuid>,
+    query: &str,
+    pattern: &str,
+) -> Result<Vec<QuickSearchResult>, AppError> {
+    let hits = sqlx::query_as::<_, Hit>(
+        r#"
+        SELECT cs.id, cs.title AS label, p.name AS sublabel,
+               p.id AS project_id, p.name AS project_name,
+               similarity(cs.title, $3) AS similarity
+        FROM collaboration_sessions cs
+        JOIN projects p ON p.id = cs.project_id
+        WHERE cs.is_active = true AND cs.title IS NOT NULL AND p.purging_at IS NULL
+          AND ($2::uuid IS NULL OR cs.project_id = $2)
+          AND (cs.created_by = $1 OR EXISTS (
+              SELECT 1 FROM session_participants sp
+              WHERE sp.session_id = cs.id AND sp.user_id = $1
+          ))
+          AND (cs.title ILIKE $4 ESCAPE '\' OR cs.title % $3)
+        ORDER BY similarity DESC, cs.created_at DESC
+        LIMIT $5
+        "#
+    )
+    .bind(user_id)
+    .bind(project_id)
+    .bind(query)
+    .bind(pattern)
+    .bind(FACET_LIMIT)
+    .fetch_all(db)
+    .await
+    .map_err(AppError::Database)?;
+
+    Ok(hits.into_iter().map(|hit| hit.into_result(QuickSearchKind::Session, query)).collect())
+}
+
+/// Owner and collaborators of the current project, by username or display name
+async fn collaborators(
+    db: &sqlx::PgPool,
+    user_id: Uuid,
+    project_id: Uuid,
+    query: &str,
+    pattern: &str,
+) -> Result<Vec<QuickSearchResult>, AppError> {
+    let hits = sqlx::query_as::<_, Hit>(
+        r#"
+        SELECT u.id, u.username AS label, u.display_name AS sublabel,
+               p.id AS project_id, p.name AS project_name,
+               similarity(u.username, $3) AS similarity
+        FROM projects p
+        JOIN users u ON u.id = p.owner_id
+            OR u.id IN (SELECT user_id FROM project_collaborators WHERE project_id = p.id)
+        WHERE p.id = $1 AND p.purging_at IS NULL AND u.is_active = true
+          AND (p.owner_id = $2 OR p.is_public = true
+               OR p.id IN (SELECT project_id FROM project_collaborators WHERE user_id = $2))
+          AND (u.username ILIKE $4 ESCAPE '\' OR u.display_name ILIKE $4 ESCAPE '\' OR u.username % $3)
+        ORDER BY similarity DESC, u.username
+        LIMIT $5
+        "#
+    )
+    .bind(project_id)
+    .bind(user_id)
+    .bind(query)
+    .bind(pattern)
+    .bind(FACET_LIMIT)
+    .fetch_all(db)
+    .await
+    .map_err(AppError::Database)?;
+
+    Ok(hits.into_iter().map(|hit| hit.into_result(QuickSearchKind::Collaborator, query)).collect())
+}
+
+/// Actions matching the query that the user may perform
+async fn actions(
+    db: &sqlx::PgPool,
+    user_id: Uuid,
+    project_id: Option<Uuid>,
+    query: &str,
+) -> Result<Vec<QuickSearchResult>, AppError> {
+    let role = match project_id {
+        Some(project_id) => Project::member_role(db, project_id, user_id).await?,
+        None => None,
+    };
+
+    Ok(ACTIONS
+        .iter()
+        .filter(|action| match action.role {
+            None => true,
+            Some(required) => role.is_some_and(|role| role.at_least(required)),
+        })
+        .filter_map(|action| {
+            let score = match_score(action.label, query);
+            (score > 0.0).then(|| QuickSearchResult {
+                kind: QuickSearchKind::Action,
+                id: action.name.to_string(),
+                label: action.label.to_string(),
+                sublabel: None,
+                project_id: action.role.and(project_id),
+                project_name: None,
+                score: score + QuickSearchKind::Action.weight(),
+            })
+        })
+        .take(FACET_LIMIT as usize)
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::file::{CreateFile, File};
+    use crate::models::project_limits::ProjectLimits;
+    use crate::models::validation::FileName;
+    use crate::testing::{add_collaborator, create_test_project, create_test_session, create_test_user, TestDb};
+    use std::time::Instant;
+
+    async fn named_project(db: &sqlx::PgPool, owner: &crate::models::user::User, name: &str, is_public: bool) -> Project {
+        let project = create_test_project(db, owner, is_public).await;
+        sqlx::query_as::<_, Project>("UPDATE projects SET name = $2 WHERE id = $1 RETURNING *")
+            .bind(project.id)
+            .bind(name)
+            .fetch_one(db)
+            .await
+            .unwrap()
+    }
+
+    async fn named_file(db: &sqlx::PgPool, project: &Project, path: &str) -> File {
+        File::create(
+            db,
+            &ProjectLimits::default(),
+            project.id,
+            CreateFile {
+                name: FileName::new(path.rsplit('/').next().unwrap()).unwrap(),
+                path: path.to_string(),
+                content: Some(String::new()),
+                content_type: None,
+            },
+            project.owner_id,
+        )
+        .await
+        .unwrap()
+    }
+
+    fn params(q: &str, project_id: Option<Uuid>) -> QuickSearchParams {
+        QuickSearchParams { q: q.to_string(), project_id, limit: None }
+    }
+
+    fn ids(found: &QuickSearchResults, kind: QuickSearchKind) -> Vec<String> {
+        found.results.iter().filter(|r| r.kind == kind).map(|r| r.id.clone()).collect()
+    }
+
+    #[test]
+    fn test_literal_matches_rank_above_partial_ones() {
+        assert_eq!(match_score("Thesis", "thesis"), 4.0);
+        assert_eq!(match_score("Thesis draft", "thesis"), 3.0);
+        assert_eq!(match_score("My thesis", "thesis"), 2.0);
+        assert_eq!(match_score("chapters/intro.tex", "intro"), 2.0);
+        assert_eq!(match_score("Hypothesis", "thesis"), 1.0);
+        assert_eq!(match_score("Notes", "thesis"), 0.0);
+    }
+
+    #[tokio::test]
+    async fn test_results_never_leak_other_users_work() {
+        let Some(db) = TestDb::start().await else { return };
+        let user = create_test_user(&db.pool).await;
+        let stranger = create_test_user(&db.pool).await;
+
+        let own = named_project(&db.pool, &user, "Bibliography tools", false).await;
+        let own_file = named_file(&db.pool, &own, "bibliography.bib").await;
+        let shared = named_project(&db.pool, &stranger, "Bibliography shared", false).await;
+        add_collaborator(&db.pool, &shared, &user, UserRole::Viewer).await;
+        let private = named_project(&db.pool, &stranger, "Bibliography private", false).await;
+        named_file(&db.pool, &private, "bibliography-secret.bib").await;
+        let mut session = create_test_session(&db.pool, &private, &stranger).await;
+        session = sqlx::query_as("UPDATE collaboration_sessions SET title = 'Bibliography review' WHERE id = $1 RETURNING *")
+            .bind(session.id)
+            .fetch_one(&db.pool)
+            .await
+            .unwrap();
+        let public = named_project(&db.pool, &stranger, "Bibliography public", true).await;
+        let public_file = named_file(&db.pool, &public, "bibliography-public.bib").await;
+
+        // A typo still finds the user's projects, and only theirs
+        let found = search(&db.pool, user.id, &params("bibliografy", None), QUICK_SEARCH_BUDGET).await.unwrap();
+        let mut projects = ids(&found, QuickSearchKind::Project);
+        projects.sort();
+        let mut expected = vec![own.id.to_string(), shared.id.to_string()];
+        expected.sort();
+        assert_eq!(projects, expected);
+        assert!(ids(&found, QuickSearchKind::Session).is_empty());
+
+        let found = search(&db.pool, user.id, &params("bibliography", Some(own.id)), QUICK_SEARCH_BUDGET).await.unwrap();
+        assert_eq!(ids(&found, QuickSearchKind::File), vec![own_file.id.to_string()]);
+
+        // Naming someone else's private project as the current one reveals nothing of it
+        let found = search(&db.pool, user.id, &params("bibliography", Some(private.id)), QUICK_SEARCH_BUDGET).await.unwrap();
+        assert!(found.results.iter().all(|r| r.project_id != Some(private.id)), "{:?}", found.results);
+        assert!(ids(&found, QuickSearchKind::Session).is_empty());
+        let found = search(&db.pool, user.id, &params(&stranger.username, Some(private.id)), QUICK_SEARCH_BUDGET).await.unwrap();
+        assert!(ids(&found, QuickSearchKind::Collaborator).is_empty());
+        let found = search(&db.pool, user.id, &params("compile", Some(private.id)), QUICK_SEARCH_BUDGET).await.unwrap();
+        assert!(ids(&found, QuickSearchKind::Action).is_empty());
+
+        // The stranger sees their own session
+        let found = search(&db.pool, stranger.id, &params("bibliography", Some(private.id)), QUICK_SEARCH_BUDGET).await.unwrap();
+        assert_eq!(ids(&found, QuickSearchKind::Session), vec![session.id.to_string()]);
+
+        // Public projects are readable, but only owners get owner actions
+        let found = search(&db.pool, user.id, &params("bibliography", Some(public.id)), QUICK_SEARCH_BUDGET).await.unwrap();
+        assert_eq!(ids(&found, QuickSearchKind::File), vec![public_file.id.to_string()]);
+        let found = search(&db.pool, user.id, &params(&stranger.username, Some(public.id)), QUICK_SEARCH_BUDGET).await.unwrap();
+        assert_eq!(ids(&found, QuickSearchKind::Collaborator), vec![stranger.id.to_string()]);
+        let found = search(&db.pool, user.id, &params("project", Some(public.id)), QUICK_SEARCH_BUDGET).await.unwrap();
+        let actions = ids(&found, QuickSearchKind::Action);
+        assert!(actions.contains(&"compile_project".to_string()), "{:?}", actions);
+        assert!(!actions.contains(&"project_settings".to_string()), "{:?}", actions);
+        let found = search(&db.pool, stranger.id, &params("project", Some(public.id)), QUICK_SEARCH_BUDGET).await.unwrap();
+        assert!(ids(&found, QuickSearchKind::Action).contains(&"project_settings".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_slow_facets_are_dropped() {
+        let Some(db) = TestDb::start().await else { return };
+        let user = create_test_user(&db.pool).await;
+
+        let found = search(&db.pool, user.id, &params("create", None), Duration::ZERO).await.unwrap();
+        assert_eq!(found.dropped, vec![QuickSearchKind::Project, QuickSearchKind::Session]);
+        assert_eq!(ids(&found, QuickSearchKind::Action), vec!["create_project".to_string()]);
+
+        for q in ["   ", &"x".repeat(MAX_QUERY_CHARS + 1)] {
+            match search(&db.pool, user.id, &params(q, None), QUICK_SEARCH_BUDGET).await {
+                Err(AppError::Validation(_)) => {}
+                other => panic!("{:?} searched as {:?}", q, other),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_quick_search_latency() {
+        let Some(db) = TestDb::start().await else { return };
+        let user = create_test_user(&db.pool).await;
+        let mut current = None;
+        for i in 0..30 {
+            let project = named_project(&db.pool, &user, &format!("Paper {} on graph colouring", i), false).await;
+            current = Some(project.id);
+            for j in 0..10 {
+                named_file(&db.pool, &project, &format!("chapters/section{}-{}.tex", i, j)).await;
+            }
+        }
+
+        let params = QuickSearchParams { q: "graph".to_string(), project_id: current, limit: Some(MAX_QUICK_SEARCH_LIMIT) };
+        search(&db.pool, user.id, &params, QUICK_SEARCH_BUDGET).await.unwrap();
+        let mut timings = Vec::new();
+        for _ in 0..11 {
+            let started = Instant::now();
+            let found = search(&db.pool, user.id, &params, QUICK_SEARCH_BUDGET).await.unwrap();
+            timings.push(started.elapsed());
+            assert!(found.dropped.is_empty());
+            assert_eq!(ids(&found, QuickSearchKind::Project).len(), FACET_LIMIT as usize);
+        }
+        timings.sort();
+        let median = timings[timings.len() / 2];
+        assert!(median < Duration::from_millis(30), "quick search took {:?}", median);
+    }
+}
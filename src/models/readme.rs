@@ -0,0 +1,355 @@
+//! Project README rendering
+//!
+//! A project can designate one of its Markdown files as its README. It is
+//! rendered with pulldown-cmark and sanitized with ammonia, so raw HTML in the
+//! source cannot smuggle scripts, styles or frames onto project pages.
+//! Rendering depends only on the file content, so results are cached by
+//! content hash; images pointing at project files are left as numbered
+//! placeholders and linked to download URLs per request.
+
+use pulldown_cmark::{CowStr, Event, Options, Parser, Tag};
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+/// Longest README source rendered; the rest is cut off
+pub const MAX_README_BYTES: usize = 512 * 1024;
+
+/// Deepest element nesting kept; deeper markup is flattened into its parent
+pub const MAX_NESTING_DEPTH: usize = 32;
+
+/// Rendered READMEs kept in memory
+pub const README_CACHE_CAPACITY: usize = 256;
+
+/// URL scheme of image placeholders in cached HTML
+const IMAGE_PLACEHOLDER_SCHEME: &str = "project-file";
+
+/// README of a project, as raw Markdown and sanitized HTML
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct ProjectReadme {
+    pub file_id: Uuid,
+    pub path: String,
+    pub content_hash: Option<String>,
+    pub markdown: String,
+    pub html: String,
+    /// Whether the source was longer than `MAX_README_BYTES` and cut off
+    pub truncated: bool,
+}
+
+/// Sanitized HTML of a Markdown document, before images are linked
+#[derive(Debug, Clone, PartialEq)]
+pub struct RenderedMarkdown {
+    pub html: String,
+    /// Targets of project-relative images, by placeholder index
+    pub image_links: Vec<String>,
+    pub truncated: bool,
+}
+
+impl RenderedMarkdown {
+    /// Replace image placeholders with URLs; unresolved images keep their original target
+    pub fn link_images(&self, mut resolve: impl FnMut(&str) -> Option<String>) -> String {
+        let mut html = self.html.clone();
+        for (index, link) in self.image_links.iter().enumerate() {
+            let url = resolve(link).unwrap_or_else(|| link.clone());
+            html = html.replace(
+                &format!("\"{}:{}\"", IMAGE_PLACEHOLDER_SCHEME, index),
+                &format!("\"{}\"", escape_attribute(&url)),
+            );
+        }
+        html
+    }
+}
+
+/// Cut the source to `MAX_README_BYTES` on a character boundary
+pub fn truncate_source(source: &str) -> (&str, bool) {
+    if source.len() <= MAX_README_BYTES {
+        return (source, false);
+    }
+
+    let mut end = MAX_README_BYTES;
+    while !source.is_char_boundary(end) {
+        end -= 1;
+    }
+    (&source[..end], true)
+}
+
+/// Render Markdown to sanitized HTML
+pub fn render_markdown(source: &str) -> RenderedMarkdown {
+    let (source, truncated) = truncate_source(source);
+
+    let options = Options::ENABLE_TABLES
+        | Options::ENABLE_STRIKETHROUGH
+        | Options::ENABLE_TASKLISTS
+        | Options::ENABLE_FOOTNOTES;
+
+    let mut image_links = Vec::new();
+    let mut depth = 0usize;
+    // Elements left open by raw HTML so far
+    let mut html_depth = 0usize;
+    // Whether each open tag was kept, so its end tag is treated the same way
+    let mut kept = Vec::new();
+    let mut events = Vec::new();
+
+    for event in Parser::new_ext(source, options) {
+        match event {
+            Event::Start(tag) => {
+                let keep = depth < MAX_NESTING_DEPTH;
+                kept.push(keep);
+                if !keep {
+                    continue;
+                }
+                depth += 1;
+
+                let tag = match tag {
+                    Tag::Image { link_type, dest_url, title, id } if is_project_link(&dest_url) => {
+                        image_links.push(dest_url.to_string());
+                        Tag::Image {
+                            link_type,
+                            dest_url: CowStr::from(format!("{}:{}", IMAGE_PLACEHOLDER_SCHEME, image_links.len() - 1)),
+                            title,
+                            id,
+                        }
+                    }
+                    tag => tag,
+                };
+                events.push(Event::Start(tag));
+            }
+            Event::End(tag) => {
+                if kept.pop().unwrap_or(false) {
+                    depth -= 1;
+                    events.push(Event::End(tag));
+                }
+            }
+            Event::Html(html) | Event::InlineHtml(html) => {
+                // Raw HTML nests on top of the Markdown structure; past the
+                // limit it is shown as text instead
+                let (opened, peak) = raw_html_nesting(&html);
+                if depth + html_depth + peak > MAX_NESTING_DEPTH {
+                    events.push(Event::Text(html));
+                } else {
+                    html_depth = html_depth.saturating_add_signed(opened);
+                    events.push(Event::Html(html));
+                }
+            }
+            event => events.push(event),
+        }
+    }
+
+    let mut unsafe_html = String::with_capacity(source.len() * 3 / 2);
+    pulldown_cmark::html::push_html(&mut unsafe_html, events.into_iter());
+
+    let html = ammonia::Builder::default()
+        .add_url_schemes(&[IMAGE_PLACEHOLDER_SCHEME])
+        .clean(&unsafe_html)
+        .to_string();
+
+    RenderedMarkdown { html, image_links, truncated }
+}
+
+/// Whether a link points into the project rather than elsewhere
+fn is_project_link(link: &str) -> bool {
+    if link.is_empty() || link.starts_with('#') || link.starts_with("//") {
+        return false;
+    }
+
+    // A scheme is letters, digits, `+`, `-` or `.` before the first `:`
+    match link.find(':') {
+        Some(colon) => {
+            let scheme = &link[..colon];
+            scheme.contains('/')
+                || scheme.is_empty()
+                || !scheme.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'))
+        }
+        None => true,
+    }
+}
+
+/// Project path a link in the file at `base_path` points to.
+///
+/// Absolute links start at the project root. Query strings and fragments
+/// are dropped; links escaping the project root resolve to nothing.
+pub fn resolve_project_path(base_path: &str, link: &str) -> Option<String> {
+    if !is_project_link(link) {
+        return None;
+    }
+
+    let link = link.split(['?', '#']).next().unwrap_or_default();
+    let link = percent_encoding::percent_decode_str(link).decode_utf8().ok()?;
+
+    let mut segments: Vec<&str> = if link.starts_with('/') {
+        Vec::new()
+    } else {
+        let mut base: Vec<&str> = base_path.split('/').filter(|s| !s.is_empty()).collect();
+        base.pop();
+        base
+    };
+
+    for segment in link.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => {
+                segments.pop()?;
+            }
+            segment => segments.push(segment),
+        }
+    }
+
+    if segments.is_empty() {
+        return None;
+    }
+    Some(format!("/{}", segments.join("/")))
+}
+
+/// Elements a raw HTML fragment leaves open and the deepest nesting inside it
+fn raw_html_nesting(html: &str) -> (isize, usize) {
+    const VOID_ELEMENTS: &[&str] = &[
+        "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "source", "track", "wbr",
+    ];
+
+    let mut depth = 0isize;
+    let mut peak = 0usize;
+    for tag in html.split('<').skip(1) {
+        let tag = tag.split('>').next().unwrap_or_default();
+        if let Some(closing) = tag.strip_prefix('/') {
+            if closing.chars().next().is_some_and(|c| c.is_ascii_alphabetic()) {
+                depth -= 1;
+            }
+            continue;
+        }
+
+        let name: String = tag
+            .chars()
+            .take_while(|c| c.is_ascii_alphanumeric())
+            .collect::<String>()
+            .to_ascii_lowercase();
+        if name.is_empty() || tag.ends_with('/') || VOID_ELEMENTS.contains(&name.as_str()) {
+            continue;
+        }
+
+        depth += 1;
+        peak = peak.max(depth.max(0) as usize);
+    }
+    (depth, peak)
+}
+
+fn escape_attribute(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('"', "&quot;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// Rendered READMEs keyed by content hash
+#[derive(Debug, Default)]
+pub struct ReadmeCache {
+    entries: Mutex<(HashMap<String, Arc<RenderedMarkdown>>, VecDeque<String>)>,
+}
+
+impl ReadmeCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, content_hash: &str) -> Option<Arc<RenderedMarkdown>> {
+        let entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        entries.0.get(content_hash).cloned()
+    }
+
+    /// Store a rendering, evicting the oldest one when full
+    pub fn insert(&self, content_hash: String, rendered: Arc<RenderedMarkdown>) {
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        let (map, order) = &mut *entries;
+
+        if map.insert(content_hash.clone(), rendered).is_none() {
+            order.push_back(content_hash);
+        }
+        while order.len() > README_CACHE_CAPACITY {
+            if let Some(oldest) = order.pop_front() {
+                map.remove(&oldest);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_strips_active_content() {
+        let rendered = render_markdown(
+            "# Title\n\n<script>alert(1)</script>\n\n<style>body{}</style>\n\n\
+             <iframe src=\"https://example.com\"></iframe>\n\n[x](javascript:alert(1)) *ok*",
+        );
+
+        assert!(rendered.html.contains("<h1>Title</h1>"));
+        assert!(rendered.html.contains("<em>ok</em>"));
+        for forbidden in ["<script", "alert(1)</", "<style", "<iframe", "javascript:"] {
+            assert!(!rendered.html.contains(forbidden), "{} survived: {}", forbidden, rendered.html);
+        }
+    }
+
+    #[test]
+    fn test_project_images_become_placeholders() {
+        let rendered = render_markdown(
+            "![plot](figures/plot.png) ![logo](https://example.com/logo.png) ![up](../x.png)",
+        );
+
+        assert_eq!(rendered.image_links, vec!["figures/plot.png", "../x.png"]);
+        assert!(rendered.html.contains("https://example.com/logo.png"));
+
+        let html = rendered.link_images(|link| {
+            resolve_project_path("/docs/README.md", link).map(|path| format!("/download{}", path))
+        });
+        assert!(html.contains("src=\"/download/docs/figures/plot.png\""));
+        assert!(html.contains("src=\"/download/x.png\""));
+        assert!(!html.contains(IMAGE_PLACEHOLDER_SCHEME));
+    }
+
+    #[test]
+    fn test_resolve_project_path() {
+        assert_eq!(resolve_project_path("/README.md", "img/a.png"), Some("/img/a.png".to_string()));
+        assert_eq!(resolve_project_path("/docs/README.md", "./a%20b.png?raw=1"), Some("/docs/a b.png".to_string()));
+        assert_eq!(resolve_project_path("/docs/README.md", "/img/a.png"), Some("/img/a.png".to_string()));
+        assert_eq!(resolve_project_path("/README.md", "../../etc/passwd"), None);
+        assert_eq!(resolve_project_path("/README.md", "https://example.com/a.png"), None);
+        assert_eq!(resolve_project_path("/README.md", "data:image/png;base64,AAAA"), None);
+        assert_eq!(resolve_project_path("/README.md", "//cdn.example.com/a.png"), None);
+    }
+
+    #[test]
+    fn test_pathological_input_is_bounded() {
+        let nested = format!("{}deep", ">".repeat(10_000));
+        let rendered = render_markdown(&nested);
+        assert!(rendered.html.matches("<blockquote>").count() <= MAX_NESTING_DEPTH);
+        assert!(rendered.html.contains("deep"));
+
+        let html_nested = "<div>".repeat(10_000);
+        let rendered = render_markdown(&html_nested);
+        assert!(!rendered.html.contains("<div>"));
+
+        let html_blocks = "<div>\n\n".repeat(10_000);
+        let rendered = render_markdown(&html_blocks);
+        assert!(rendered.html.matches("<div>").count() <= MAX_NESTING_DEPTH);
+
+        let huge = "word ".repeat(2 * 1024 * 1024);
+        let rendered = render_markdown(&huge);
+        assert!(rendered.truncated);
+        assert!(rendered.html.len() < MAX_README_BYTES * 2);
+    }
+
+    #[test]
+    fn test_cache_evicts_oldest() {
+        let cache = ReadmeCache::new();
+        let rendered = Arc::new(render_markdown("hi"));
+        for i in 0..=README_CACHE_CAPACITY {
+            cache.insert(format!("hash-{}", i), rendered.clone());
+        }
+
+        assert!(cache.get("hash-0").is_none());
+        assert!(cache.get(&format!("hash-{}", README_CACHE_CAPACITY)).is_some());
+    }
+}
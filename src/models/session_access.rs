@@ -0,0 +1,346 @@
+//! Who may join a collaboration session
+//!
+//! A session can require a password, a short numeric join code, or both; a
+//! joiner needs either one. Join codes are for classrooms: the host rotates
+//! them, they expire after `WEBSOCKET_JOIN_CODE_TTL` and only their hash is
+//! stored. Six digits are guessable, so wrong secrets are counted per session
+//! and after `WEBSOCKET_JOIN_MAX_FAILURES` of them the session refuses every
+//! secret for `WEBSOCKET_JOIN_LOCKOUT`. Changing the secrets ends a lockout.
+//! The host never needs a secret, and participants who already joined stay.
+
+use chrono::{DateTime, Duration, Utc};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::FromRow;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use super::collaboration::CollaborationSession;
+use crate::config::WebSocketConfig;
+use crate::error::AppError;
+
+/// Channel on which session status changes are announced to the WebSocket server
+pub const SESSION_STATUS_CHANNEL: &str = "session_status";
+
+/// Join code and failed join counter of a session
+#[derive(Debug, Clone, FromRow)]
+pub struct SessionAccess {
+    pub session_id: Uuid,
+    pub join_code_hash: Option<String>,
+    pub join_code_expires_at: Option<DateTime<Utc>>,
+    pub failed_attempts: i32,
+    pub locked_until: Option<DateTime<Utc>>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// Secrets offered when joining
+#[derive(Debug, Clone, Copy, Default)]
+pub struct JoinCredentials<'a> {
+    pub password: Option<&'a str>,
+    pub join_code: Option<&'a str>,
+}
+
+/// A freshly generated join code; the only time it is shown
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct JoinCode {
+    pub join_code: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// Status change announced to connected participants
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionStatusChange {
+    pub session_id: Uuid,
+    pub status: String,
+}
+
+impl SessionAccess {
+    pub async fn find(db: &sqlx::PgPool, session_id: Uuid) -> Result<Option<Self>, AppError> {
+        sqlx::query_as::<_, SessionAccess>("SELECT * FROM session_access WHERE session_id = $1")
+            .bind(session_id)
+            .fetch_optional(db)
+            .await
+            .map_err(AppError::Database)
+    }
+
+    /// Whether an unexpired join code exists
+    pub fn join_code_active(&self, now: DateTime<Utc>) -> bool {
+        self.join_code_hash.is_some() && self.join_code_expires_at.is_some_and(|expires_at| expires_at > now)
+    }
+
+    /// Check the secrets of a user joining an active session, which must have
+    /// reached its scheduled start unless the user hosts it
+    pub async fn authorize_join(
+        db: &sqlx::PgPool,
+        session: &CollaborationSession,
+        user_id: Uuid,
+        credentials: JoinCredentials<'_>,
+        config: &WebSocketConfig,
+    ) -> Result<(), AppError> {
+        if session.created_by == user_id {
+            return Ok(());
+        }
+
+        let now = Utc::now();
+        session.ensure_started_for(user_id, now)?;
+
+        let access = Self::find(db, session.id).await?;
+        let code_active = access.as_ref().is_some_and(|access| access.join_code_active(now));
+        if session.password_hash.is_none() && !code_active {
+            return Ok(());
+        }
+
+        // Checked before any secret, so a locked session cannot be guessed at
+        if access.as_ref().and_then(|access| access.locked_until).is_some_and(|until| until > now) {
+            return Err(AppError::RateLimit);
+        }
+
+        if credentials.password.is_none() && credentials.join_code.is_none() {
+            return Err(AppError::Authorization(
+                "This session requires a password or join code".to_string(),
+            ));
+        }
+
+        let password_ok = match (&session.password_hash, credentials.password) {
+            (Some(hash), Some(password)) => bcrypt::verify(password, hash).unwrap_or(false),
+            _ => false,
+        };
+        let code_ok = code_active
+            && match (access.as_ref().and_then(|access| access.join_code_hash.as_deref()), credentials.join_code) {
+                (Some(hash), Some(code)) => hash_join_code(session.id, code.trim()) == hash,
+                _ => false,
+            };
+
+        if password_ok || code_ok {
+            if access.is_some_and(|access| access.failed_attempts > 0) {
+                Self::reset_failures(db, session.id).await?;
+            }
+            return Ok(());
+        }
+
+        let access = Self::record_failure(db, session.id, config).await?;
+        if access.locked_until.is_some_and(|until| until > now) {
+            tracing::warn!("Session {} locked after {} failed joins", session.id, config.join_max_failures);
+            return Err(AppError::RateLimit);
+        }
+        Err(AppError::Authorization("Invalid session password or join code".to_string()))
+    }
+
+    /// Count a wrong secret, locking the session once `join_max_failures` is reached
+    async fn record_failure(db: &sqlx::PgPool, session_id: Uuid, config: &WebSocketConfig) -> Result<Self, AppError> {
+        sqlx::query_as::<_, SessionAccess>(
+            r#"
+            INSERT INTO session_access (session_id, failed_attempts, locked_until)
+            VALUES ($1, 1, CASE WHEN 1 >= $2 THEN NOW() + $3 * INTERVAL '1 second' END)
+            ON CONFLICT (session_id) DO UPDATE SET
+                failed_attempts = CASE
+                    WHEN session_access.failed_attempts + 1 >= $2 THEN 0
+                    ELSE session_access.failed_attempts + 1
+                END,
+                locked_until = CASE
+                    WHEN session_access.failed_attempts + 1 >= $2 THEN NOW() + $3 * INTERVAL '1 second'
+                    ELSE session_access.locked_until
+                END,
+                updated_at = NOW()
+            RETURNING *
+            "#
+        )
+        .bind(session_id)
+        .bind(config.join_max_failures.max(1) as i32)
+        .bind(config.join_lockout as f64)
+        .fetch_one(db)
+        .await
+        .map_err(AppError::Database)
+    }
+
+    async fn reset_failures(db: &sqlx::PgPool, session_id: Uuid) -> Result<(), AppError> {
+        sqlx::query("UPDATE session_access SET failed_attempts = 0, updated_at = NOW() WHERE session_id = $1")
+            .bind(session_id)
+            .execute(db)
+            .await
+            .map_err(AppError::Database)?;
+
+        Ok(())
+    }
+
+    /// Set, change or remove (`None`) the session password
+    pub async fn set_password(db: &sqlx::PgPool, session_id: Uuid, password: Option<&str>) -> Result<(), AppError> {
+        let password_hash = match password {
+            Some(password) => Some(bcrypt::hash(password, bcrypt::DEFAULT_COST)?),
+            None => None,
+        };
+
+        let mut tx = db.begin().await.map_err(AppError::Database)?;
+        sqlx::query("UPDATE collaboration_sessions SET password_hash = $2, updated_at = NOW() WHERE id = $1")
+            .bind(session_id)
+            .bind(password_hash)
+            .execute(&mut *tx)
+            .await
+            .map_err(AppError::Database)?;
+        sqlx::query(
+            "UPDATE session_access SET failed_attempts = 0, locked_until = NULL, updated_at = NOW() WHERE session_id = $1"
+        )
+        .bind(session_id)
+        .execute(&mut *tx)
+        .await
+        .map_err(AppError::Database)?;
+        tx.commit().await.map_err(AppError::Database)?;
+
+        Ok(())
+    }
+
+    /// Replace the session's join code with a new one valid for `ttl_secs`
+    pub async fn rotate_join_code(db: &sqlx::PgPool, session_id: Uuid, ttl_secs: u64) -> Result<JoinCode, AppError> {
+        let join_code = format!("{:06}", rand::thread_rng().gen_range(0..1_000_000));
+        let expires_at = Utc::now() + Duration::seconds(ttl_secs as i64);
+
+        sqlx::query(
+            r#"
+            INSERT INTO session_access (session_id, join_code_hash, join_code_expires_at)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (session_id) DO UPDATE SET
+                join_code_hash = EXCLUDED.join_code_hash,
+                join_code_expires_at = EXCLUDED.join_code_expires_at,
+                failed_attempts = 0,
+                locked_until = NULL,
+                updated_at = NOW()
+            "#
+        )
+        .bind(session_id)
+        .bind(hash_join_code(session_id, &join_code))
+        .bind(expires_at)
+        .execute(db)
+        .await
+        .map_err(AppError::Database)?;
+
+        Ok(JoinCode { join_code, expires_at })
+    }
+}
+
+/// Tell connected participants that the session changed, e.g. `join_code_rotated`
+///
+/// Goes through Postgres so the WebSocket server hears it whichever process
+/// made the change.
+pub async fn announce_status(db: &sqlx::PgPool, session_id: Uuid, status: &str) -> Result<(), AppError> {
+    let payload = serde_json::to_string(&SessionStatusChange {
+        session_id,
+        status: status.to_string(),
+    })?;
+
+    sqlx::query("SELECT pg_notify($1, $2)")
+        .bind(SESSION_STATUS_CHANNEL)
+        .bind(payload)
+        .execute(db)
+        .await
+        .map_err(AppError::Database)?;
+
+    Ok(())
+}
+
+/// Join codes are hashed with their session, so equal codes of two sessions differ
+fn hash_join_code(session_id: Uuid, code: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(session_id.as_bytes());
+    hasher.update(code.as_bytes());
+    format!("{:x}", hasher.finalize())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{create_test_project, create_test_session, create_test_user, test_config, TestDb};
+
+    fn config() -> WebSocketConfig {
+        let mut config = test_config().websocket;
+        config.join_max_failures = 3;
+        config.join_lockout = 600;
+        config
+    }
+
+    fn code(join_code: &str) -> JoinCredentials<'_> {
+        JoinCredentials {
+            password: None,
+            join_code: Some(join_code),
+        }
+    }
+
+    #[test]
+    fn test_join_code_hash_depends_on_session() {
+        let code = "123456";
+        assert_ne!(hash_join_code(Uuid::new_v4(), code), hash_join_code(Uuid::new_v4(), code));
+        assert_eq!(hash_join_code(Uuid::nil(), code).len(), 64);
+    }
+
+    #[tokio::test]
+    async fn test_join_codes_and_lockout() {
+        let Some(db) = TestDb::start().await else { return };
+        let config = config();
+        let host = create_test_user(&db.pool).await;
+        let guest = create_test_user(&db.pool).await;
+        let project = create_test_project(&db.pool, &host, false).await;
+        let session = create_test_session(&db.pool, &project, &host).await;
+
+        // Open sessions need no secret
+        SessionAccess::authorize_join(&db.pool, &session, guest.id, JoinCredentials::default(), &config).await.unwrap();
+
+        let issued = SessionAccess::rotate_join_code(&db.pool, session.id, 600).await.unwrap();
+        assert_eq!(issued.join_code.len(), 6);
+        assert!(matches!(
+            SessionAccess::authorize_join(&db.pool, &session, guest.id, JoinCredentials::default(), &config).await,
+            Err(AppError::Authorization(_))
+        ));
+        SessionAccess::authorize_join(&db.pool, &session, guest.id, code(&issued.join_code), &config).await.unwrap();
+        SessionAccess::authorize_join(&db.pool, &session, host.id, JoinCredentials::default(), &config).await.unwrap();
+
+        // A rotated code replaces the old one, and rotating resets the failure count
+        let rotated = SessionAccess::rotate_join_code(&db.pool, session.id, 600).await.unwrap();
+        if rotated.join_code != issued.join_code {
+            assert!(SessionAccess::authorize_join(&db.pool, &session, guest.id, code(&issued.join_code), &config)
+                .await
+                .is_err());
+        }
+        let rotated = SessionAccess::rotate_join_code(&db.pool, session.id, 600).await.unwrap();
+
+        // The third wrong guess locks the session, even for the right code
+        let wrong = if rotated.join_code == "000000" { "000001" } else { "000000" };
+        for _ in 0..2 {
+            assert!(matches!(
+                SessionAccess::authorize_join(&db.pool, &session, guest.id, code(wrong), &config).await,
+                Err(AppError::Authorization(_))
+            ));
+        }
+        assert!(matches!(
+            SessionAccess::authorize_join(&db.pool, &session, guest.id, code(wrong), &config).await,
+            Err(AppError::RateLimit)
+        ));
+        assert!(matches!(
+            SessionAccess::authorize_join(&db.pool, &session, guest.id, code(&rotated.join_code), &config).await,
+            Err(AppError::RateLimit)
+        ));
+
+        // Setting a password lifts the lockout; either secret then works
+        SessionAccess::set_password(&db.pool, session.id, Some("classroom")).await.unwrap();
+        let session = CollaborationSession::find_by_id(&db.pool, session.id).await.unwrap().unwrap();
+        let password = JoinCredentials {
+            password: Some("classroom"),
+            join_code: None,
+        };
+        SessionAccess::authorize_join(&db.pool, &session, guest.id, password, &config).await.unwrap();
+        SessionAccess::authorize_join(&db.pool, &session, guest.id, code(&rotated.join_code), &config).await.unwrap();
+
+        // Expired codes no longer count, leaving only the password
+        sqlx::query("UPDATE session_access SET join_code_expires_at = NOW() - INTERVAL '1 second' WHERE session_id = $1")
+            .bind(session.id)
+            .execute(&db.pool)
+            .await
+            .unwrap();
+        assert!(SessionAccess::authorize_join(&db.pool, &session, guest.id, code(&rotated.join_code), &config)
+            .await
+            .is_err());
+
+        SessionAccess::set_password(&db.pool, session.id, None).await.unwrap();
+        let session = CollaborationSession::find_by_id(&db.pool, session.id).await.unwrap().unwrap();
+        SessionAccess::authorize_join(&db.pool, &session, guest.id, JoinCredentials::default(), &config).await.unwrap();
+    }
+}
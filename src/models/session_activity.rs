@@ -0,0 +1,464 @@
+//! Idle detection of collaboration session participants
+//!
+//! A participant is active while they edit, move their cursor or chat; the
+//! socket heartbeat keeps them online but does not count. With an idle policy
+//! in the session's settings, an editor idle for `downgrade_after_minutes`
+//! becomes a viewer and frees their edit slot, and is marked away after
+//! `away_after_minutes` more. Their next activity gives the editor role back
+//! if a slot is free; otherwise they wait in line and get it, with a
+//! notification, as soon as one opens. Slots are counted per user against
+//! `max_editors`, so the tabs of one editor share theirs.
+//!
+//! The timestamps are kept here and only written out every
+//! [`ACTIVITY_FLUSH_INTERVAL`], so the sweep every [`IDLE_CHECK_INTERVAL`]
+//! reads no rows; only the role changes it makes are persisted right away.
+//! Like the rate limits this lives in memory: each server instance enforces
+//! the policy for the participants connected to it.
+
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+use uuid::Uuid;
+
+use super::collaboration::{ParticipantActivity, ParticipantRole, SessionParticipant, SessionSettings};
+
+/// How often idle participants are looked for
+pub const IDLE_CHECK_INTERVAL: Duration = Duration::from_secs(15);
+
+/// How often activity timestamps are written to the participant rows
+pub const ACTIVITY_FLUSH_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone)]
+struct TrackedParticipant {
+    participant_id: Uuid,
+    user_id: Uuid,
+    role: ParticipantRole,
+    restore_role: Option<ParticipantRole>,
+    activity: ParticipantActivity,
+    last_activity_at: DateTime<Utc>,
+    queued_at: Option<DateTime<Utc>>,
+    /// Activity since the last flush
+    unflushed: bool,
+}
+
+#[derive(Debug, Default)]
+struct TrackedSession {
+    settings: SessionSettings,
+    participants: Vec<TrackedParticipant>,
+}
+
+/// Role or activity of a participant changed by the idle policy
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ActivityChange {
+    pub participant_id: Uuid,
+    pub user_id: Uuid,
+    pub role: ParticipantRole,
+    pub restore_role: Option<ParticipantRole>,
+    pub activity: ParticipantActivity,
+    pub last_activity_at: DateTime<Utc>,
+    pub queued_at: Option<DateTime<Utc>>,
+    /// Got their role back from the line, when a slot opened
+    pub slot_opened: bool,
+}
+
+/// Activity of the participants connected to this server, by session
+#[derive(Debug, Default)]
+pub struct SessionActivity {
+    sessions: Mutex<HashMap<Uuid, TrackedSession>>,
+}
+
+impl TrackedParticipant {
+    fn change(&self, slot_opened: bool) -> ActivityChange {
+        ActivityChange {
+            participant_id: self.participant_id,
+            user_id: self.user_id,
+            role: self.role,
+            restore_role: self.restore_role,
+            activity: self.activity,
+            last_activity_at: self.last_activity_at,
+            queued_at: self.queued_at,
+            slot_opened,
+        }
+    }
+}
+
+impl TrackedSession {
+    /// Users holding the editor role, each once
+    fn editors(&self) -> Vec<Uuid> {
+        let mut editors: Vec<Uuid> = self
+            .participants
+            .iter()
+            .filter(|p| p.role == ParticipantRole::Editor)
+            .map(|p| p.user_id)
+            .collect();
+        editors.sort_unstable();
+        editors.dedup();
+        editors
+    }
+
+    /// Whether `user_id` may hold the editor role without going past `max_editors`
+    fn has_slot_for(&self, user_id: Uuid) -> bool {
+        let Some(max_editors) = self.settings.max_editors else {
+            return true;
+        };
+        let editors = self.editors();
+        editors.contains(&user_id) || editors.len() < max_editors as usize
+    }
+
+    /// Give waiting participants their role back while slots are free, longest waiting first
+    fn fill_slots(&mut self, changes: &mut Vec<ActivityChange>) {
+        loop {
+            let next = self
+                .participants
+                .iter()
+                .filter_map(|p| p.queued_at.map(|queued_at| (queued_at, p.user_id)))
+                .min();
+            let Some((_, user_id)) = next else { break };
+            if !self.has_slot_for(user_id) {
+                break;
+            }
+
+            for participant in self.participants.iter_mut().filter(|p| p.user_id == user_id && p.queued_at.is_some()) {
+                participant.role = participant.restore_role.take().unwrap_or(participant.role);
+                participant.queued_at = None;
+                changes.push(participant.change(true));
+            }
+        }
+    }
+
+    /// Downgrade and mark away whoever has been idle too long
+    fn sweep(&mut self, now: DateTime<Utc>, changes: &mut Vec<ActivityChange>) {
+        let Some(policy) = self.settings.idle_policy else {
+            return;
+        };
+        let downgrade_after = chrono::Duration::minutes(i64::from(policy.downgrade_after_minutes));
+        let away_after = downgrade_after + chrono::Duration::minutes(i64::from(policy.away_after_minutes));
+
+        for participant in &mut self.participants {
+            let idle = now - participant.last_activity_at;
+            let changed = match participant.activity {
+                ParticipantActivity::Active if idle >= downgrade_after => {
+                    if participant.role == ParticipantRole::Editor {
+                        participant.role = ParticipantRole::Viewer;
+                        participant.restore_role = Some(ParticipantRole::Editor);
+                    }
+                    // Waiting resumes with their next activity
+                    participant.queued_at = None;
+                    participant.activity = ParticipantActivity::Idle;
+                    true
+                }
+                ParticipantActivity::Idle if idle >= away_after => {
+                    participant.activity = ParticipantActivity::Away;
+                    true
+                }
+                _ => false,
+            };
+            if changed {
+                changes.push(participant.change(false));
+            }
+        }
+
+        self.fill_slots(changes);
+    }
+}
+
+impl SessionActivity {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Track a participant that joined
+    ///
+    /// An editor finding every slot taken joins as a viewer waiting for one;
+    /// the returned change has to be persisted before the join is announced.
+    pub fn track(&self, settings: SessionSettings, participant: &SessionParticipant, now: DateTime<Utc>) -> Option<ActivityChange> {
+        let mut sessions = self.sessions.lock().unwrap_or_else(|e| e.into_inner());
+        let session = sessions.entry(participant.session_id).or_default();
+        session.settings = settings;
+
+        let mut tracked = TrackedParticipant {
+            participant_id: participant.id,
+            user_id: participant.user_id,
+            role: participant.role,
+            restore_role: None,
+            activity: ParticipantActivity::Active,
+            last_activity_at: now,
+            queued_at: None,
+            unflushed: false,
+        };
+        let queued = tracked.role == ParticipantRole::Editor && !session.has_slot_for(tracked.user_id);
+        if queued {
+            tracked.role = ParticipantRole::Viewer;
+            tracked.restore_role = Some(ParticipantRole::Editor);
+            tracked.queued_at = Some(now);
+        }
+        let change = queued.then(|| tracked.change(false));
+        session.participants.push(tracked);
+        change
+    }
+
+    /// Stop tracking a participant that left
+    ///
+    /// Returns their last activity still to be written and the participants
+    /// that got the slot they freed.
+    pub fn forget(&self, session_id: Uuid, participant_id: Uuid) -> (Option<(Uuid, DateTime<Utc>)>, Vec<ActivityChange>) {
+        let mut sessions = self.sessions.lock().unwrap_or_else(|e| e.into_inner());
+        let Some(session) = sessions.get_mut(&session_id) else {
+            return (None, Vec::new());
+        };
+        let Some(index) = session.participants.iter().position(|p| p.participant_id == participant_id) else {
+            return (None, Vec::new());
+        };
+
+        let left = session.participants.remove(index);
+        let unflushed = left.unflushed.then_some((left.participant_id, left.last_activity_at));
+        let mut changes = Vec::new();
+        session.fill_slots(&mut changes);
+        if session.participants.is_empty() {
+            sessions.remove(&session_id);
+        }
+        (unflushed, changes)
+    }
+
+    /// Record activity of a user in a session, restoring or queueing those
+    /// the policy downgraded
+    pub fn touch(&self, session_id: Uuid, user_id: Uuid, now: DateTime<Utc>) -> Vec<ActivityChange> {
+        let mut sessions = self.sessions.lock().unwrap_or_else(|e| e.into_inner());
+        let Some(session) = sessions.get_mut(&session_id) else {
+            return Vec::new();
+        };
+
+        let has_slot = session.has_slot_for(user_id);
+        let mut changes = Vec::new();
+        for participant in session.participants.iter_mut().filter(|p| p.user_id == user_id) {
+            participant.last_activity_at = participant.last_activity_at.max(now);
+            participant.unflushed = true;
+
+            let woke = participant.activity != ParticipantActivity::Active;
+            participant.activity = ParticipantActivity::Active;
+            let restored = match participant.restore_role {
+                Some(role) if has_slot => {
+                    participant.role = role;
+                    participant.restore_role = None;
+                    participant.queued_at = None;
+                    true
+                }
+                Some(_) if participant.queued_at.is_none() => {
+                    participant.queued_at = Some(now);
+                    true
+                }
+                _ => false,
+            };
+            if woke || restored {
+                changes.push(participant.change(false));
+            }
+        }
+        changes
+    }
+
+    /// Whether a user may edit in a session; `None` when none of their
+    /// participants is tracked here
+    pub fn may_edit(&self, session_id: Uuid, user_id: Uuid) -> Option<bool> {
+        let sessions = self.sessions.lock().unwrap_or_else(|e| e.into_inner());
+        let mut roles = sessions
+            .get(&session_id)?
+            .participants
+            .iter()
+            .filter(|p| p.user_id == user_id)
+            .map(|p| p.role)
+            .peekable();
+        roles.peek()?;
+        Some(roles.any(|role| role != ParticipantRole::Viewer))
+    }
+
+    /// Apply the idle policy of every session
+    pub fn sweep(&self, now: DateTime<Utc>) -> Vec<(Uuid, ActivityChange)> {
+        let mut sessions = self.sessions.lock().unwrap_or_else(|e| e.into_inner());
+        let mut changes = Vec::new();
+        for (session_id, session) in sessions.iter_mut() {
+            let mut session_changes = Vec::new();
+            session.sweep(now, &mut session_changes);
+            changes.extend(session_changes.into_iter().map(|change| (*session_id, change)));
+        }
+        changes
+    }
+
+    /// New settings of a session; raising `max_editors` lets waiting participants in
+    pub fn update_settings(&self, session_id: Uuid, settings: SessionSettings) -> Vec<ActivityChange> {
+        let mut sessions = self.sessions.lock().unwrap_or_else(|e| e.into_inner());
+        let Some(session) = sessions.get_mut(&session_id) else {
+            return Vec::new();
+        };
+        session.settings = settings;
+
+        let mut changes = Vec::new();
+        session.fill_slots(&mut changes);
+        changes
+    }
+
+    /// Activity not written out yet, by participant; it counts as written
+    pub fn take_unflushed(&self) -> Vec<(Uuid, DateTime<Utc>)> {
+        let mut sessions = self.sessions.lock().unwrap_or_else(|e| e.into_inner());
+        sessions
+            .values_mut()
+            .flat_map(|session| session.participants.iter_mut())
+            .filter_map(|p| std::mem::take(&mut p.unflushed).then_some((p.participant_id, p.last_activity_at)))
+            .collect()
+    }
+
+    /// Bring persisted participants up to date with the activity kept here
+    pub fn overlay(&self, participants: &mut [SessionParticipant]) {
+        let sessions = self.sessions.lock().unwrap_or_else(|e| e.into_inner());
+        for participant in participants {
+            let tracked = sessions
+                .get(&participant.session_id)
+                .and_then(|session| session.participants.iter().find(|p| p.participant_id == participant.id));
+            if let Some(tracked) = tracked {
+                participant.last_activity_at = participant.last_activity_at.max(tracked.last_activity_at);
+            }
+        }
+    }
+
+    /// Users holding the editor role in a session
+    pub fn editors(&self, session_id: Uuid) -> usize {
+        let sessions = self.sessions.lock().unwrap_or_else(|e| e.into_inner());
+        sessions.get(&session_id).map_or(0, |session| session.editors().len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::collaboration::IdlePolicy;
+
+    fn settings(max_editors: Option<u32>) -> SessionSettings {
+        SessionSettings {
+            idle_policy: Some(IdlePolicy { downgrade_after_minutes: 5, away_after_minutes: 10 }),
+            max_editors,
+        }
+    }
+
+    fn participant(session_id: Uuid, user_id: Uuid, role: ParticipantRole, now: DateTime<Utc>) -> SessionParticipant {
+        SessionParticipant {
+            id: Uuid::new_v4(),
+            session_id,
+            user_id,
+            role,
+            joined_at: now,
+            left_at: None,
+            cursor_position: None,
+            selection: None,
+            is_online: true,
+            last_seen_at: now,
+            permissions: None,
+            current_file_id: None,
+            last_activity_at: now,
+            activity: ParticipantActivity::Active,
+            restore_role: None,
+            queued_at: None,
+            pseudonym: None,
+        }
+    }
+
+    fn minutes(minutes: i64) -> chrono::Duration {
+        chrono::Duration::minutes(minutes)
+    }
+
+    #[test]
+    fn test_idle_editors_are_downgraded_then_away() {
+        let activity = SessionActivity::new();
+        let (session_id, start) = (Uuid::new_v4(), Utc::now());
+        let editor = participant(session_id, Uuid::new_v4(), ParticipantRole::Editor, start);
+        let host = participant(session_id, Uuid::new_v4(), ParticipantRole::Host, start);
+        assert_eq!(activity.track(settings(None), &editor, start), None);
+        activity.track(settings(None), &host, start);
+
+        // The heartbeat alone does not count; the host keeps working
+        activity.touch(session_id, host.user_id, start + minutes(4));
+        assert!(activity.sweep(start + minutes(4)).is_empty());
+
+        let changes = activity.sweep(start + minutes(5));
+        assert_eq!(changes.len(), 1);
+        let (_, change) = &changes[0];
+        assert_eq!(change.participant_id, editor.id);
+        assert_eq!((change.role, change.restore_role), (ParticipantRole::Viewer, Some(ParticipantRole::Editor)));
+        assert_eq!(change.activity, ParticipantActivity::Idle);
+        assert_eq!(activity.may_edit(session_id, editor.user_id), Some(false));
+        assert_eq!(activity.editors(session_id), 0);
+
+        let changes = activity.sweep(start + minutes(15));
+        let away: Vec<_> = changes.iter().map(|(_, change)| (change.participant_id, change.activity)).collect();
+        assert!(away.contains(&(editor.id, ParticipantActivity::Away)), "{:?}", away);
+        assert!(activity.sweep(start + minutes(30)).iter().all(|(_, change)| change.participant_id != editor.id));
+
+        // Any activity gives the role back while a slot is free
+        let changes = activity.touch(session_id, editor.user_id, start + minutes(31));
+        assert_eq!(changes.len(), 1);
+        assert_eq!((changes[0].role, changes[0].restore_role), (ParticipantRole::Editor, None));
+        assert_eq!(changes[0].activity, ParticipantActivity::Active);
+        assert_eq!(activity.may_edit(session_id, editor.user_id), Some(true));
+        assert_eq!(activity.may_edit(session_id, Uuid::new_v4()), None);
+    }
+
+    #[test]
+    fn test_edit_slots_are_queued_in_order() {
+        let activity = SessionActivity::new();
+        let (session_id, start) = (Uuid::new_v4(), Utc::now());
+        let first = participant(session_id, Uuid::new_v4(), ParticipantRole::Editor, start);
+        let second = participant(session_id, Uuid::new_v4(), ParticipantRole::Editor, start);
+        let first_tab = participant(session_id, first.user_id, ParticipantRole::Editor, start);
+        assert_eq!(activity.track(settings(Some(1)), &first, start), None);
+        // Another tab of the editor takes no slot of its own
+        assert_eq!(activity.track(settings(Some(1)), &first_tab, start), None);
+
+        let queued = activity.track(settings(Some(1)), &second, start).unwrap();
+        assert_eq!((queued.role, queued.queued_at), (ParticipantRole::Viewer, Some(start)));
+        assert_eq!(activity.editors(session_id), 1);
+
+        // The first editor idles; the second, still at work, takes over the slot
+        activity.touch(session_id, second.user_id, start + minutes(4));
+        let changes = activity.sweep(start + minutes(6));
+        let opened: Vec<_> = changes.iter().filter(|(_, change)| change.slot_opened).collect();
+        assert_eq!(opened.len(), 1);
+        assert_eq!((opened[0].1.participant_id, opened[0].1.role), (second.id, ParticipantRole::Editor));
+        assert_eq!(activity.editors(session_id), 1);
+
+        // Coming back now means waiting, and nothing changes while waiting
+        let changes = activity.touch(session_id, first.user_id, start + minutes(7));
+        assert_eq!(changes.len(), 2);
+        assert!(changes.iter().all(|change| change.role == ParticipantRole::Viewer && change.queued_at.is_some()));
+        let repeated = activity.touch(session_id, first.user_id, start + minutes(8));
+        assert!(repeated.is_empty());
+
+        let (unflushed, changes) = activity.forget(session_id, second.id);
+        assert_eq!(unflushed, Some((second.id, start + minutes(4))));
+        assert_eq!(changes.len(), 2);
+        assert!(changes.iter().all(|change| change.slot_opened && change.role == ParticipantRole::Editor));
+        assert_eq!(activity.editors(session_id), 1);
+    }
+
+    #[test]
+    fn test_activity_is_flushed_once() {
+        let activity = SessionActivity::new();
+        let (session_id, start) = (Uuid::new_v4(), Utc::now());
+        let editor = participant(session_id, Uuid::new_v4(), ParticipantRole::Editor, start);
+        activity.track(SessionSettings::default(), &editor, start);
+        assert!(activity.take_unflushed().is_empty());
+
+        activity.touch(session_id, editor.user_id, start + minutes(1));
+        activity.touch(session_id, editor.user_id, start + minutes(2));
+        assert_eq!(activity.take_unflushed(), vec![(editor.id, start + minutes(2))]);
+        assert!(activity.take_unflushed().is_empty());
+
+        // Without a policy nobody is downgraded
+        assert!(activity.sweep(start + minutes(600)).is_empty());
+
+        let mut persisted = vec![editor.clone()];
+        activity.overlay(&mut persisted);
+        assert_eq!(persisted[0].last_activity_at, start + minutes(2));
+
+        assert_eq!(activity.forget(session_id, editor.id), (None, Vec::new()));
+        assert_eq!(activity.editors(session_id), 0);
+    }
+}
@@ -0,0 +1,354 @@
+//! Anonymous review mode of collaboration sessions
+//!
+//! For double-blind reviews the host can hide who wrote what until a set
+//! time. While the mode is on, everyone but the host sees the other
+//! participants under a pseudonym ("Reviewer 2") and an alias id in place of
+//! their user id: in participant lists, chat messages with their attachments
+//! and mentions, and operation broadcasts. The masking is done by the server before a
+//! payload leaves it; the host and the database keep real identities. The
+//! host is known as the session's creator and is not masked, and everyone
+//! sees their own identity.
+//!
+//! Pseudonyms are stored per session, so they stay the same across
+//! reconnects and when the mode is turned on again. Turning the mode off
+//! while the session runs reveals everyone at once, so it needs an explicit
+//! confirmation and is announced to the participants.
+
+use chrono::{DateTime, Duration, Utc};
+use sqlx::FromRow;
+use std::collections::HashMap;
+use uuid::Uuid;
+
+use super::chat_attachment::AttachmentView;
+use super::collaboration::{CollaborationSession, SessionMessage, SessionParticipant};
+use super::mention::MentionedUser;
+use super::session_access;
+use crate::error::AppError;
+
+/// Longest anonymous period
+pub const MAX_ANONYMITY_DAYS: i64 = 30;
+
+/// Session status announced when identities are hidden
+pub const ANONYMITY_ENABLED: &str = "anonymity_enabled";
+
+/// Session status announced when identities are revealed before the set time
+pub const ANONYMITY_DISABLED: &str = "anonymity_disabled";
+
+/// Pseudonym of a user in a session
+#[derive(Debug, Clone, FromRow)]
+pub struct SessionPseudonym {
+    pub session_id: Uuid,
+    pub user_id: Uuid,
+    /// Shown in place of `user_id`
+    pub alias_id: Uuid,
+    pub number: i32,
+    pub created_at: DateTime<Utc>,
+}
+
+impl SessionPseudonym {
+    pub fn label(&self) -> String {
+        format!("Reviewer {}", self.number)
+    }
+
+    pub async fn list(db: &sqlx::PgPool, session_id: Uuid) -> Result<Vec<Self>, AppError> {
+        sqlx::query_as::<_, SessionPseudonym>("SELECT * FROM session_pseudonyms WHERE session_id = $1")
+            .bind(session_id)
+            .fetch_all(db)
+            .await
+            .map_err(AppError::Database)
+    }
+
+    /// Give the users without a pseudonym in the session the next numbers
+    pub async fn assign(db: &sqlx::PgPool, session_id: Uuid, user_ids: &[Uuid]) -> Result<Vec<Self>, AppError> {
+        let mut tx = db.begin().await.map_err(AppError::Database)?;
+
+        // Numbers are handed out one session at a time
+        sqlx::query("SELECT id FROM collaboration_sessions WHERE id = $1 FOR UPDATE")
+            .bind(session_id)
+            .execute(&mut *tx)
+            .await
+            .map_err(AppError::Database)?;
+
+        let mut pseudonyms = sqlx::query_as::<_, SessionPseudonym>(
+            "SELECT * FROM session_pseudonyms WHERE session_id = $1"
+        )
+        .bind(session_id)
+        .fetch_all(&mut *tx)
+        .await
+        .map_err(AppError::Database)?;
+
+        let mut next = pseudonyms.iter().map(|pseudonym| pseudonym.number).max().unwrap_or(0) + 1;
+        for &user_id in user_ids {
+            if pseudonyms.iter().any(|pseudonym| pseudonym.user_id == user_id) {
+                continue;
+            }
+
+            let pseudonym = sqlx::query_as::<_, SessionPseudonym>(
+                r#"
+                INSERT INTO session_pseudonyms (session_id, user_id, alias_id, number)
+                VALUES ($1, $2, $3, $4)
+                RETURNING *
+                "#
+            )
+            .bind(session_id)
+            .bind(user_id)
+            .bind(Uuid::new_v4())
+            .bind(next)
+            .fetch_one(&mut *tx)
+            .await
+            .map_err(AppError::Database)?;
+            pseudonyms.push(pseudonym);
+            next += 1;
+        }
+
+        tx.commit().await.map_err(AppError::Database)?;
+        Ok(pseudonyms)
+    }
+}
+
+impl CollaborationSession {
+    pub fn is_anonymous(&self, now: DateTime<Utc>) -> bool {
+        self.anonymous_until.is_some_and(|until| until > now)
+    }
+
+    /// Hide identities until `until`, replacing an earlier end
+    pub async fn enable_anonymity(
+        &self,
+        db: &sqlx::PgPool,
+        until: DateTime<Utc>,
+        now: DateTime<Utc>,
+    ) -> Result<Self, AppError> {
+        if until <= now {
+            return Err(AppError::Validation("Anonymity must end in the future".to_string()));
+        }
+        if until > now + Duration::days(MAX_ANONYMITY_DAYS) {
+            return Err(AppError::Validation(format!(
+                "Anonymity can last at most {} days",
+                MAX_ANONYMITY_DAYS
+            )));
+        }
+
+        let session = sqlx::query_as::<_, CollaborationSession>(
+            r#"
+            UPDATE collaboration_sessions
+            SET anonymous_until = $2, updated_at = NOW()
+            WHERE id = $1
+            RETURNING *
+            "#
+        )
+        .bind(self.id)
+        .bind(until)
+        .fetch_one(db)
+        .await
+        .map_err(AppError::Database)?;
+
+        // Those present get their pseudonyms in the order they joined
+        let user_ids = sqlx::query_scalar::<_, Uuid>(
+            r#"
+            SELECT user_id FROM session_participants
+            WHERE session_id = $1 AND user_id <> $2
+            GROUP BY user_id
+            ORDER BY MIN(joined_at)
+            "#
+        )
+        .bind(self.id)
+        .bind(self.created_by)
+        .fetch_all(db)
+        .await
+        .map_err(AppError::Database)?;
+        SessionPseudonym::assign(db, self.id, &user_ids).await?;
+
+        session_access::announce_status(db, self.id, ANONYMITY_ENABLED).await?;
+        Ok(session)
+    }
+
+    /// Reveal identities before the set time; needs `confirmed` once the session started
+    pub async fn disable_anonymity(
+        &self,
+        db: &sqlx::PgPool,
+        confirmed: bool,
+        now: DateTime<Utc>,
+    ) -> Result<Self, AppError> {
+        if !self.is_anonymous(now) {
+            return Err(AppError::Validation("Session is not anonymous".to_string()));
+        }
+        let running = self.started_at.is_some() && self.ended_at.is_none();
+        if running && !confirmed {
+            return Err(AppError::Conflict(
+                "Turning anonymity off reveals every participant to everyone; repeat with confirm=true".to_string(),
+            ));
+        }
+
+        let session = sqlx::query_as::<_, CollaborationSession>(
+            r#"
+            UPDATE collaboration_sessions
+            SET anonymous_until = NULL, updated_at = NOW()
+            WHERE id = $1
+            RETURNING *
+            "#
+        )
+        .bind(self.id)
+        .fetch_one(db)
+        .await
+        .map_err(AppError::Database)?;
+
+        session_access::announce_status(db, self.id, ANONYMITY_DISABLED).await?;
+        Ok(session)
+    }
+}
+
+/// Identities as one user of an anonymous session may see them
+#[derive(Debug, Clone)]
+pub struct SessionMask {
+    pub session_id: Uuid,
+    host: Uuid,
+    viewer: Uuid,
+    until: DateTime<Utc>,
+    pseudonyms: HashMap<Uuid, SessionPseudonym>,
+}
+
+impl SessionMask {
+    /// The mask of `viewer`, `None` when the session is not anonymous or the viewer hosts it
+    pub async fn load(
+        db: &sqlx::PgPool,
+        session: &CollaborationSession,
+        viewer: Uuid,
+        now: DateTime<Utc>,
+    ) -> Result<Option<Self>, AppError> {
+        let Some(until) = session.anonymous_until.filter(|_| session.is_anonymous(now)) else {
+            return Ok(None);
+        };
+        if viewer == session.created_by {
+            return Ok(None);
+        }
+
+        let pseudonyms = SessionPseudonym::list(db, session.id).await?;
+        Ok(Some(Self {
+            session_id: session.id,
+            host: session.created_by,
+            viewer,
+            until,
+            pseudonyms: pseudonyms.into_iter().map(|pseudonym| (pseudonym.user_id, pseudonym)).collect(),
+        }))
+    }
+
+    /// Whether identities are still hidden
+    pub fn is_active(&self, now: DateTime<Utc>) -> bool {
+        now < self.until
+    }
+
+    /// Make sure the masked users among `user_ids` have a pseudonym
+    pub async fn cover(&mut self, db: &sqlx::PgPool, user_ids: impl IntoIterator<Item = Uuid>) -> Result<(), AppError> {
+        let mut missing: Vec<Uuid> = user_ids
+            .into_iter()
+            .filter(|user_id| self.is_masked(*user_id) && !self.pseudonyms.contains_key(user_id))
+            .collect();
+        if missing.is_empty() {
+            return Ok(());
+        }
+        missing.sort_unstable();
+        missing.dedup();
+
+        let pseudonyms = SessionPseudonym::assign(db, self.session_id, &missing).await?;
+        self.pseudonyms = pseudonyms.into_iter().map(|pseudonym| (pseudonym.user_id, pseudonym)).collect();
+        Ok(())
+    }
+
+    fn is_masked(&self, user_id: Uuid) -> bool {
+        user_id != self.host && user_id != self.viewer
+    }
+
+    /// The id shown for `user_id`; the nil id for a masked user without a pseudonym
+    pub fn user_id(&self, user_id: Uuid) -> Uuid {
+        if !self.is_masked(user_id) {
+            return user_id;
+        }
+        self.pseudonyms.get(&user_id).map_or(Uuid::nil(), |pseudonym| pseudonym.alias_id)
+    }
+
+    /// The pseudonym shown for `user_id`; the host has none
+    pub fn pseudonym(&self, user_id: Uuid) -> Option<String> {
+        if user_id == self.host {
+            return None;
+        }
+        Some(self.pseudonyms.get(&user_id).map_or_else(|| "Reviewer".to_string(), SessionPseudonym::label))
+    }
+
+    pub fn participant(&self, participant: SessionParticipant) -> SessionParticipant {
+        SessionParticipant {
+            user_id: self.user_id(participant.user_id),
+            pseudonym: self.pseudonym(participant.user_id),
+            ..participant
+        }
+    }
+
+    pub fn message(&self, message: SessionMessage) -> SessionMessage {
+        SessionMessage {
+            user_id: self.user_id(message.user_id),
+            // Reactions name the users who reacted
+            reactions: None,
+            mentions: message.mentions.iter().map(|user_id| self.user_id(*user_id)).collect(),
+            ..message
+        }
+    }
+
+    /// A mentioned user as shown; masked users appear under their pseudonym
+    pub fn mentioned_user(&self, user: MentionedUser) -> MentionedUser {
+        if !self.is_masked(user.id) {
+            return user;
+        }
+        let pseudonym = self.pseudonym(user.id).unwrap_or_default();
+        MentionedUser {
+            id: self.user_id(user.id),
+            username: pseudonym.clone(),
+            display_name: pseudonym,
+            avatar_url: None,
+        }
+    }
+
+    pub fn attachment(&self, mut view: AttachmentView) -> AttachmentView {
+        view.attachment.uploaded_by = self.user_id(view.attachment.uploaded_by);
+        view
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mask_hides_others_only() {
+        let host = Uuid::new_v4();
+        let viewer = Uuid::new_v4();
+        let reviewer = Uuid::new_v4();
+        let now = Utc::now();
+        let pseudonym = |user_id, number| SessionPseudonym {
+            session_id: Uuid::nil(),
+            user_id,
+            alias_id: Uuid::new_v4(),
+            number,
+            created_at: now,
+        };
+        let reviewer_pseudonym = pseudonym(reviewer, 2);
+        let mask = SessionMask {
+            session_id: Uuid::nil(),
+            host,
+            viewer,
+            until: now + Duration::hours(1),
+            pseudonyms: HashMap::from([(viewer, pseudonym(viewer, 1)), (reviewer, reviewer_pseudonym.clone())]),
+        };
+
+        assert_eq!(mask.user_id(reviewer), reviewer_pseudonym.alias_id);
+        assert_eq!(mask.pseudonym(reviewer).as_deref(), Some("Reviewer 2"));
+        assert_eq!(mask.user_id(viewer), viewer);
+        assert_eq!(mask.pseudonym(viewer).as_deref(), Some("Reviewer 1"));
+        assert_eq!(mask.user_id(host), host);
+        assert_eq!(mask.pseudonym(host), None);
+
+        // Never the real id, even without a pseudonym
+        assert_eq!(mask.user_id(Uuid::new_v4()), Uuid::nil());
+
+        assert!(mask.is_active(now));
+        assert!(!mask.is_active(now + Duration::hours(2)));
+    }
+}
@@ -0,0 +1,448 @@
+//! Scheduled collaboration sessions
+//!
+//! A session created with a `scheduled_start` waits for its time: only the
+//! host can join earlier, everyone else is told when it starts. Times are
+//! stored in UTC and clients convert them. `SessionScheduleTask` reminds the
+//! accepted invitees `WEBSOCKET_SESSION_REMINDER_MINUTES` before the start,
+//! and cancels sessions that reach their `scheduled_end` without anyone
+//! having joined. Sessions can be added to a calendar as an iCalendar event.
+
+use chrono::{DateTime, Duration, Utc};
+use uuid::Uuid;
+
+use super::collaboration::CollaborationSession;
+use super::notification::NotificationService;
+use super::session_access;
+use super::user::User;
+use crate::error::AppError;
+
+/// Time between two passes of the schedule task
+const SCHEDULE_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60);
+
+/// Window `upcoming` looks ahead by default
+pub const DEFAULT_UPCOMING_WINDOW_DAYS: i64 = 30;
+
+/// Longest window `upcoming` looks ahead
+pub const MAX_UPCOMING_WINDOW_DAYS: i64 = 366;
+
+/// Session status announced when a session is cancelled
+pub const SESSION_CANCELLED: &str = "cancelled";
+
+/// iCalendar lines are folded after this many octets
+const ICS_LINE_LIMIT: usize = 75;
+
+/// Check the schedule of a new session
+pub fn validate_schedule(
+    start: Option<DateTime<Utc>>,
+    end: Option<DateTime<Utc>>,
+    now: DateTime<Utc>,
+) -> Result<(), AppError> {
+    match (start, end) {
+        (None, Some(_)) => Err(AppError::Validation(
+            "scheduled_end requires scheduled_start".to_string(),
+        )),
+        (Some(start), _) if start <= now => Err(AppError::Validation(
+            "scheduled_start must be in the future".to_string(),
+        )),
+        (Some(start), Some(end)) if end <= start => Err(AppError::Validation(
+            "scheduled_end must be after scheduled_start".to_string(),
+        )),
+        _ => Ok(()),
+    }
+}
+
+impl CollaborationSession {
+    /// Refuse users other than the host before the scheduled start, unless
+    /// the session already started
+    pub fn ensure_started_for(&self, user_id: Uuid, now: DateTime<Utc>) -> Result<(), AppError> {
+        if self.created_by == user_id || self.started_at.is_some() {
+            return Ok(());
+        }
+
+        match self.scheduled_start {
+            Some(starts_at) if starts_at > now => Err(AppError::SessionNotStarted { starts_at }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Active sessions the user hosts or is invited to, scheduled to overlap `from..to`
+    pub async fn upcoming(
+        db: &sqlx::PgPool,
+        user_id: Uuid,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Result<Vec<Self>, AppError> {
+        sqlx::query_as::<_, CollaborationSession>(
+            r#"
+            SELECT cs.* FROM collaboration_sessions cs
+            WHERE cs.is_active = true
+              AND cs.scheduled_start < $3
+              AND COALESCE(cs.scheduled_end, cs.scheduled_start) >= $2
+              AND (
+                cs.created_by = $1
+                OR EXISTS (
+                    SELECT 1 FROM session_invitations si
+                    WHERE si.session_id = cs.id AND si.invited_user = $1 AND si.declined = false
+                )
+              )
+            ORDER BY cs.scheduled_start, cs.id
+            "#
+        )
+        .bind(user_id)
+        .bind(from)
+        .bind(to)
+        .fetch_all(db)
+        .await
+        .map_err(AppError::Database)
+    }
+
+    /// Whether the user hosts the session, joined it or has an invitation they did not decline
+    pub async fn is_member(&self, db: &sqlx::PgPool, user_id: Uuid) -> Result<bool, AppError> {
+        if self.created_by == user_id {
+            return Ok(true);
+        }
+
+        sqlx::query_scalar::<_, bool>(
+            r#"
+            SELECT EXISTS (
+                SELECT 1 FROM session_invitations
+                WHERE session_id = $1 AND invited_user = $2 AND declined = false
+            ) OR EXISTS (
+                SELECT 1 FROM session_participants WHERE session_id = $1 AND user_id = $2
+            )
+            "#
+        )
+        .bind(self.id)
+        .bind(user_id)
+        .fetch_one(db)
+        .await
+        .map_err(AppError::Database)
+    }
+
+    /// The session as an iCalendar file with a single event, or `None` when it is not scheduled
+    pub fn to_ics(&self, now: DateTime<Utc>) -> Option<String> {
+        let start = self.scheduled_start?;
+
+        let mut lines = vec![
+            "BEGIN:VCALENDAR".to_string(),
+            "VERSION:2.0".to_string(),
+            "PRODID:-//Texler//Collaboration Sessions//EN".to_string(),
+            "CALSCALE:GREGORIAN".to_string(),
+            "METHOD:PUBLISH".to_string(),
+            "BEGIN:VEVENT".to_string(),
+            format!("UID:{}@texler", self.id),
+            format!("DTSTAMP:{}", ics_time(now)),
+            format!("DTSTART:{}", ics_time(start)),
+        ];
+        if let Some(end) = self.scheduled_end {
+            lines.push(format!("DTEND:{}", ics_time(end)));
+        }
+        lines.push(format!(
+            "SUMMARY:{}",
+            ics_text(self.title.as_deref().unwrap_or("Collaboration session"))
+        ));
+        if let Some(description) = self.description.as_deref().filter(|description| !description.is_empty()) {
+            lines.push(format!("DESCRIPTION:{}", ics_text(description)));
+        }
+        lines.push(format!("LAST-MODIFIED:{}", ics_time(self.updated_at)));
+        let status = if self.cancelled_at.is_some() { "CANCELLED" } else { "CONFIRMED" };
+        lines.push(format!("STATUS:{}", status));
+        lines.push("END:VEVENT".to_string());
+        lines.push("END:VCALENDAR".to_string());
+
+        let mut ics = String::new();
+        for line in lines {
+            ics.push_str(&fold_ics_line(&line));
+            ics.push_str("\r\n");
+        }
+        Some(ics)
+    }
+}
+
+/// UTC date-time in iCalendar's basic format
+fn ics_time(time: DateTime<Utc>) -> String {
+    time.format("%Y%m%dT%H%M%SZ").to_string()
+}
+
+/// Escape a TEXT value
+fn ics_text(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            ';' => escaped.push_str("\\;"),
+            ',' => escaped.push_str("\\,"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => {}
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Split a content line into lines of at most 75 octets, continued with a leading space
+fn fold_ics_line(line: &str) -> String {
+    let mut folded = String::with_capacity(line.len() + line.len() / ICS_LINE_LIMIT * 3);
+    let mut octets = 0;
+    for c in line.chars() {
+        if octets + c.len_utf8() > ICS_LINE_LIMIT {
+            folded.push_str("\r\n ");
+            // The leading space counts towards the continued line
+            octets = 1;
+        }
+        folded.push(c);
+        octets += c.len_utf8();
+    }
+    folded
+}
+
+/// Remind the accepted invitees of sessions starting within `minutes`; each session is reminded once
+pub async fn send_reminders(
+    db: &sqlx::PgPool,
+    mailer: &crate::email::Mailer,
+    minutes: u64,
+) -> Result<usize, AppError> {
+    if minutes == 0 {
+        return Ok(0);
+    }
+
+    let sessions = sqlx::query_as::<_, CollaborationSession>(
+        r#"
+        UPDATE collaboration_sessions
+        SET reminder_sent_at = NOW()
+        WHERE is_active = true
+          AND reminder_sent_at IS NULL
+          AND scheduled_start > NOW()
+          AND scheduled_start <= NOW() + $1 * INTERVAL '1 minute'
+        RETURNING *
+        "#
+    )
+    .bind(minutes as f64)
+    .fetch_all(db)
+    .await
+    .map_err(AppError::Database)?;
+
+    let mut reminded = 0;
+    for session in sessions {
+        let Some(starts_at) = session.scheduled_start else { continue };
+        let invitees = sqlx::query_as::<_, User>(
+            r#"
+            SELECT u.* FROM users u
+            JOIN session_invitations si ON si.invited_user = u.id
+            WHERE si.session_id = $1 AND si.accepted = true AND si.declined = false
+            "#
+        )
+        .bind(session.id)
+        .fetch_all(db)
+        .await
+        .map_err(AppError::Database)?;
+
+        let title = session.title.as_deref().unwrap_or("Collaboration session");
+        let body = format!("{} starts at {}.", title, starts_at.to_rfc3339());
+        for invitee in invitees {
+            // One failed delivery should not keep the others from being reminded
+            if let Err(e) = NotificationService::notify(
+                db,
+                mailer,
+                &invitee,
+                "session_reminder",
+                &format!("Starting soon: {}", title),
+                &body,
+                Some(serde_json::json!({
+                    "session_id": session.id,
+                    "scheduled_start": starts_at,
+                })),
+            )
+            .await
+            {
+                tracing::warn!("Failed to remind {} of session {}: {}", invitee.id, session.id, e);
+                continue;
+            }
+            reminded += 1;
+        }
+    }
+
+    Ok(reminded)
+}
+
+/// Cancel sessions that passed their scheduled end without starting
+pub async fn cancel_unstarted(db: &sqlx::PgPool) -> Result<usize, AppError> {
+    let cancelled = sqlx::query_scalar::<_, Uuid>(
+        r#"
+        UPDATE collaboration_sessions
+        SET is_active = false, cancelled_at = NOW(), updated_at = NOW()
+        WHERE is_active = true
+          AND started_at IS NULL
+          AND scheduled_end < NOW()
+        RETURNING id
+        "#
+    )
+    .fetch_all(db)
+    .await
+    .map_err(AppError::Database)?;
+
+    for session_id in &cancelled {
+        if let Err(e) = session_access::announce_status(db, *session_id, SESSION_CANCELLED).await {
+            tracing::warn!("Failed to announce cancellation of session {}: {}", session_id, e);
+        }
+    }
+
+    Ok(cancelled.len())
+}
+
+/// Sends session reminders and cancels sessions that never started
+pub struct SessionScheduleTask;
+
+impl crate::tasks::PeriodicTask for SessionScheduleTask {
+    fn name(&self) -> &'static str {
+        "session_schedule"
+    }
+
+    fn interval(&self) -> std::time::Duration {
+        SCHEDULE_INTERVAL
+    }
+
+    fn run<'a>(
+        &'a self,
+        state: &'a crate::server::AppState,
+    ) -> futures::future::BoxFuture<'a, Result<(), AppError>> {
+        Box::pin(async move {
+            let reminded = send_reminders(
+                &state.db_pool,
+                &state.mailer,
+                state.config.websocket.session_reminder_minutes,
+            )
+            .await?;
+            let cancelled = cancel_unstarted(&state.db_pool).await?;
+            if reminded > 0 || cancelled > 0 {
+                tracing::info!("Sent {} session reminders, cancelled {} unstarted sessions", reminded, cancelled);
+            }
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::collaboration::{ParticipantRole, SessionInvitation};
+    use crate::email::Mailer;
+    use crate::models::notification::Notification;
+    use crate::testing::{create_test_project, create_test_session, create_test_user, test_config, TestDb};
+
+    #[test]
+    fn test_ics_event() {
+        let now = Utc::now();
+        let start = DateTime::parse_from_rfc3339("2026-11-02T14:30:00+01:00").unwrap().with_timezone(&Utc);
+        let session = CollaborationSession {
+            id: Uuid::new_v4(),
+            project_id: Uuid::new_v4(),
+            file_id: None,
+            created_by: Uuid::new_v4(),
+            session_type: Default::default(),
+            title: Some("Thesis review; chapter 2, draft".to_string()),
+            description: Some(format!("Line one\nLine two {}", "é".repeat(60))),
+            is_active: true,
+            max_participants: 10,
+            password_hash: None,
+            settings: None,
+            started_at: None,
+            ended_at: None,
+            created_at: now,
+            updated_at: now,
+            scheduled_start: Some(start),
+            scheduled_end: Some(start + Duration::hours(1)),
+            reminder_sent_at: None,
+            cancelled_at: None,
+            anonymous_until: None,
+        };
+
+        let ics = session.to_ics(now).unwrap();
+        assert!(ics.starts_with("BEGIN:VCALENDAR\r\n"));
+        assert!(ics.ends_with("END:VEVENT\r\nEND:VCALENDAR\r\n"));
+        assert!(ics.contains("\r\nDTSTART:20261102T133000Z\r\n"));
+        assert!(ics.contains("\r\nDTEND:20261102T143000Z\r\n"));
+        assert!(ics.contains("\r\nSUMMARY:Thesis review\\; chapter 2\\, draft\r\n"));
+        assert!(ics.contains("\r\nDESCRIPTION:Line one\\nLine two "));
+        assert!(ics.contains(&format!("\r\nUID:{}@texler\r\n", session.id)));
+        for line in ics.split("\r\n") {
+            assert!(line.len() <= ICS_LINE_LIMIT, "{:?} is not folded", line);
+        }
+
+        let unscheduled = CollaborationSession { scheduled_start: None, scheduled_end: None, ..session };
+        assert!(unscheduled.to_ics(now).is_none());
+    }
+
+    #[test]
+    fn test_validate_schedule() {
+        let now = Utc::now();
+        let start = now + Duration::hours(1);
+        assert!(validate_schedule(None, None, now).is_ok());
+        assert!(validate_schedule(Some(start), None, now).is_ok());
+        assert!(validate_schedule(Some(start), Some(start + Duration::hours(1)), now).is_ok());
+        assert!(validate_schedule(None, Some(start), now).is_err());
+        assert!(validate_schedule(Some(now - Duration::minutes(1)), None, now).is_err());
+        assert!(validate_schedule(Some(start), Some(start), now).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_reminders_and_cancellation() {
+        let Some(db) = TestDb::start().await else { return };
+        let host = create_test_user(&db.pool).await;
+        let invitee = create_test_user(&db.pool).await;
+        let pending = create_test_user(&db.pool).await;
+        let project = create_test_project(&db.pool, &host, false).await;
+
+        // Scheduled in ten minutes, and one that ended without starting
+        let soon = create_test_session(&db.pool, &project, &host).await;
+        let missed = create_test_session(&db.pool, &project, &host).await;
+        sqlx::query(
+            r#"
+            UPDATE collaboration_sessions SET started_at = NULL,
+                scheduled_start = CASE WHEN id = $1 THEN NOW() + INTERVAL '10 minutes' ELSE NOW() - INTERVAL '2 hours' END,
+                scheduled_end = CASE WHEN id = $1 THEN NOW() + INTERVAL '70 minutes' ELSE NOW() - INTERVAL '1 hour' END
+            WHERE id = ANY($2)
+            "#
+        )
+        .bind(soon.id)
+        .bind(vec![soon.id, missed.id])
+        .execute(&db.pool)
+        .await
+        .unwrap();
+        let soon = CollaborationSession::find_by_id(&db.pool, soon.id).await.unwrap().unwrap();
+
+        let invitation = SessionInvitation::create(&db.pool, &soon, host.id, Some(invitee.id), None, ParticipantRole::Editor, None)
+            .await
+            .unwrap();
+        invitation.accept(&db.pool, &invitee).await.unwrap();
+        SessionInvitation::create(&db.pool, &soon, host.id, Some(pending.id), None, ParticipantRole::Viewer, None)
+            .await
+            .unwrap();
+
+        // Invitees cannot join early, the host can
+        assert!(matches!(
+            soon.ensure_started_for(invitee.id, Utc::now()),
+            Err(AppError::SessionNotStarted { starts_at }) if Some(starts_at) == soon.scheduled_start
+        ));
+        assert!(soon.ensure_started_for(host.id, Utc::now()).is_ok());
+
+        let upcoming = CollaborationSession::upcoming(&db.pool, pending.id, Utc::now(), Utc::now() + Duration::days(1))
+            .await
+            .unwrap();
+        assert_eq!(upcoming.iter().map(|session| session.id).collect::<Vec<_>>(), vec![soon.id]);
+
+        // Only accepted invitees are reminded, and only once
+        let mailer = Mailer::new(&test_config(), db.pool.clone()).unwrap();
+        assert_eq!(send_reminders(&db.pool, &mailer, 5).await.unwrap(), 0);
+        assert_eq!(send_reminders(&db.pool, &mailer, 15).await.unwrap(), 1);
+        assert_eq!(send_reminders(&db.pool, &mailer, 15).await.unwrap(), 0);
+        assert_eq!(Notification::unread_count(&db.pool, invitee.id).await.unwrap(), 1);
+        assert_eq!(Notification::unread_count(&db.pool, pending.id).await.unwrap(), 0);
+
+        assert_eq!(cancel_unstarted(&db.pool).await.unwrap(), 1);
+        let missed = CollaborationSession::find_by_id(&db.pool, missed.id).await.unwrap().unwrap();
+        assert!(!missed.is_active);
+        assert!(missed.cancelled_at.is_some());
+        assert!(CollaborationSession::find_by_id(&db.pool, soon.id).await.unwrap().unwrap().is_active);
+    }
+}
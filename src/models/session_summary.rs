@@ -0,0 +1,411 @@
+//! Compaction of collaboration session operations
+//!
+//! Every edit in a session is stored in `session_operations`. Once a session
+//! has ended its applied operations are collapsed into one `session_summaries`
+//! row per file, and raw rows older than the retention window are deleted,
+//! except for sessions that are still being recorded. `SessionStats` reads the
+//! summaries for compacted sessions, so its totals do not change.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use std::time::Duration;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::error::AppError;
+
+/// Time between two compaction passes
+const COMPACTION_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// Applied operations of one file in a compacted session
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct SessionSummary {
+    pub id: Uuid,
+    pub session_id: Uuid,
+    /// None for operations that were not tied to a file
+    pub file_id: Option<Uuid>,
+    /// Hash of the file content when the session was compacted
+    pub content_hash: Option<String>,
+    pub operation_count: i64,
+    pub characters_typed: i64,
+    pub contributions: sqlx::types::Json<Vec<Contribution>>,
+    pub first_operation_at: DateTime<Utc>,
+    pub last_operation_at: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// One participant's share of the operations on a file
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Contribution {
+    pub user_id: Uuid,
+    pub operation_count: i64,
+    pub characters_typed: i64,
+}
+
+/// What a compaction pass did, or would do in a dry run
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct CompactionReport {
+    pub dry_run: bool,
+    /// Ended sessions whose operations were (or would be) summarized
+    pub sessions_compacted: i64,
+    /// Raw operation rows deleted (or that would be deleted)
+    pub operations_removed: i64,
+    /// Operations recorded before this are past the retention window
+    pub cutoff: DateTime<Utc>,
+}
+
+impl SessionSummary {
+    /// Summaries of a compacted session
+    pub async fn list(db: &sqlx::PgPool, session_id: Uuid) -> Result<Vec<Self>, AppError> {
+        sqlx::query_as::<_, SessionSummary>(
+            "SELECT * FROM session_summaries WHERE session_id = $1 ORDER BY first_operation_at"
+        )
+        .bind(session_id)
+        .fetch_all(db)
+        .await
+        .map_err(AppError::Database)
+    }
+
+    /// Summarize an ended session's applied operations.
+    ///
+    /// Returns false when the session is still active or was already compacted.
+    pub async fn compact_session(db: &sqlx::PgPool, session_id: Uuid) -> Result<bool, AppError> {
+        let mut tx = db.begin().await.map_err(AppError::Database)?;
+
+        // Claim the session first so concurrent passes cannot summarize it twice
+        let claimed = sqlx::query(
+            r#"
+            UPDATE collaboration_sessions SET compacted_at = NOW()
+            WHERE id = $1 AND is_active = false AND ended_at IS NOT NULL AND compacted_at IS NULL
+            "#
+        )
+        .bind(session_id)
+        .execute(&mut *tx)
+        .await
+        .map_err(AppError::Database)?
+        .rows_affected();
+
+        if claimed == 0 {
+            return Ok(false);
+        }
+
+        sqlx::query(
+            r#"
+            WITH per_user AS (
+                SELECT
+                    file_id,
+                    user_id,
+                    COUNT(*) AS operation_count,
+                    COALESCE(SUM(LENGTH(content)), 0) AS characters_typed,
+                    MIN(timestamp) AS first_at,
+                    MAX(timestamp) AS last_at
+                FROM session_operations
+                WHERE session_id = $1 AND applied = true
+                GROUP BY file_id, user_id
+            )
+            INSERT INTO session_summaries (
+                session_id, file_id, content_hash, operation_count, characters_typed,
+                contributions, first_operation_at, last_operation_at
+            )
+            SELECT
+                $1,
+                u.file_id,
+                f.content_hash,
+                SUM(u.operation_count)::bigint,
+                SUM(u.characters_typed)::bigint,
+                jsonb_agg(jsonb_build_object(
+                    'user_id', u.user_id,
+                    'operation_count', u.operation_count,
+                    'characters_typed', u.characters_typed
+                ) ORDER BY u.user_id),
+                MIN(u.first_at),
+                MAX(u.last_at)
+            FROM per_user u
+            LEFT JOIN files f ON f.id = u.file_id
+            GROUP BY u.file_id, f.content_hash
+            "#
+        )
+        .bind(session_id)
+        .execute(&mut *tx)
+        .await
+        .map_err(AppError::Database)?;
+
+        tx.commit().await.map_err(AppError::Database)?;
+
+        Ok(true)
+    }
+
+    /// Summarize every ended session, then delete raw operations of compacted
+    /// sessions recorded before `now - retention`
+    pub async fn compact(db: &sqlx::PgPool, retention: chrono::Duration) -> Result<CompactionReport, AppError> {
+        let cutoff = Utc::now() - retention;
+
+        let pending = sqlx::query_scalar::<_, Uuid>(
+            r#"
+            SELECT id FROM collaboration_sessions
+            WHERE is_active = false AND ended_at IS NOT NULL AND compacted_at IS NULL
+            ORDER BY ended_at
+            "#
+        )
+        .fetch_all(db)
+        .await
+        .map_err(AppError::Database)?;
+
+        let mut sessions_compacted = 0;
+        for session_id in pending {
+            if Self::compact_session(db, session_id).await? {
+                sessions_compacted += 1;
+            }
+        }
+
+        let operations_removed = sqlx::query(
+            r#"
+            DELETE FROM session_operations so
+            USING collaboration_sessions s
+            WHERE so.session_id = s.id
+              AND s.compacted_at IS NOT NULL
+              AND so.timestamp < $1
+              AND NOT EXISTS (
+                  SELECT 1 FROM session_recordings r
+                  WHERE r.session_id = s.id AND r.ended_at IS NULL
+              )
+            "#
+        )
+        .bind(cutoff)
+        .execute(db)
+        .await
+        .map_err(AppError::Database)?
+        .rows_affected() as i64;
+
+        Ok(CompactionReport {
+            dry_run: false,
+            sessions_compacted,
+            operations_removed,
+            cutoff,
+        })
+    }
+
+    /// What `compact` would do now, without changing anything
+    pub async fn compact_dry_run(db: &sqlx::PgPool, retention: chrono::Duration) -> Result<CompactionReport, AppError> {
+        let cutoff = Utc::now() - retention;
+
+        let (sessions_compacted, operations_removed) = sqlx::query_as::<_, (i64, i64)>(
+            r#"
+            SELECT
+                (SELECT COUNT(*) FROM collaboration_sessions
+                 WHERE is_active = false AND ended_at IS NOT NULL AND compacted_at IS NULL),
+                (SELECT COUNT(*) FROM session_operations so
+                 JOIN collaboration_sessions s ON s.id = so.session_id
+                 WHERE (s.compacted_at IS NOT NULL OR (s.is_active = false AND s.ended_at IS NOT NULL))
+                   AND so.timestamp < $1
+                   AND NOT EXISTS (
+                       SELECT 1 FROM session_recordings r
+                       WHERE r.session_id = s.id AND r.ended_at IS NULL
+                   ))
+            "#
+        )
+        .bind(cutoff)
+        .fetch_one(db)
+        .await
+        .map_err(AppError::Database)?;
+
+        Ok(CompactionReport {
+            dry_run: true,
+            sessions_compacted,
+            operations_removed,
+            cutoff,
+        })
+    }
+}
+
+/// Retention window for raw operations of ended sessions
+pub fn operation_retention(config: &crate::config::Config) -> chrono::Duration {
+    chrono::Duration::hours(config.websocket.operation_retention_hours as i64)
+}
+
+/// Compacts ended sessions and prunes their old operations
+pub struct SessionCompactionTask;
+
+impl crate::tasks::PeriodicTask for SessionCompactionTask {
+    fn name(&self) -> &'static str {
+        "session_compaction"
+    }
+
+    fn interval(&self) -> Duration {
+        COMPACTION_INTERVAL
+    }
+
+    fn run<'a>(
+        &'a self,
+        state: &'a crate::server::AppState,
+    ) -> futures::future::BoxFuture<'a, Result<(), AppError>> {
+        Box::pin(async move {
+            let report = SessionSummary::compact(&state.db_pool, operation_retention(&state.config)).await?;
+            if report.sessions_compacted > 0 || report.operations_removed > 0 {
+                tracing::info!(
+                    "Compacted {} sessions, removed {} session operations",
+                    report.sessions_compacted,
+                    report.operations_removed
+                );
+            }
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::collaboration::{CollaborationSession, OperationType, SessionOperation, SessionStats};
+    use crate::testing::{create_test_file, create_test_project, create_test_session, create_test_user, TestDb};
+
+    /// `compact` works on every session, so the tests calling it take turns
+    static COMPACTION: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+    async fn edit(
+        db: &sqlx::PgPool,
+        session: &CollaborationSession,
+        user_id: Uuid,
+        file_id: Uuid,
+        content: &str,
+        applied: bool,
+    ) {
+        let operation = SessionOperation::create(
+            db,
+            session.id,
+            user_id,
+            OperationType::Insert,
+            "{}".to_string(),
+            Some(file_id),
+            Some(0),
+            Some(content.to_string()),
+        )
+        .await
+        .unwrap();
+
+        if applied {
+            operation.apply(db).await.unwrap();
+        } else {
+            operation.reject(db, Some("conflict".to_string())).await.unwrap();
+        }
+    }
+
+    async fn raw_operations(db: &sqlx::PgPool, session_id: Uuid) -> i64 {
+        sqlx::query_scalar("SELECT COUNT(*) FROM session_operations WHERE session_id = $1")
+            .bind(session_id)
+            .fetch_one(db)
+            .await
+            .unwrap()
+    }
+
+    /// An ended session with four applied edits over two files and one rejected edit
+    async fn ended_session(db: &sqlx::PgPool) -> (CollaborationSession, Uuid, Uuid) {
+        let owner = create_test_user(db).await;
+        let guest = create_test_user(db).await;
+        let project = create_test_project(db, &owner, false).await;
+        let main = create_test_file(db, &project, &owner).await;
+        let other: Uuid = sqlx::query_scalar(
+            r#"
+            INSERT INTO files (project_id, name, path, content, created_by)
+            VALUES ($1, 'refs.bib', 'refs.bib', '', $2)
+            RETURNING id
+            "#
+        )
+        .bind(project.id)
+        .bind(owner.id)
+        .fetch_one(db)
+        .await
+        .unwrap();
+
+        let session = create_test_session(db, &project, &owner).await;
+        edit(db, &session, owner.id, main.id, "Hello", true).await;
+        edit(db, &session, guest.id, main.id, "abc", true).await;
+        edit(db, &session, guest.id, main.id, "d", true).await;
+        edit(db, &session, owner.id, other, "xy", true).await;
+        edit(db, &session, owner.id, other, "rejected", false).await;
+        session.end(db).await.unwrap();
+
+        (session, owner.id, guest.id)
+    }
+
+    #[tokio::test]
+    async fn test_compaction_keeps_session_stats() {
+        let Some(db) = TestDb::start().await else { return };
+        let _turn = COMPACTION.lock().await;
+        let (session, owner, guest) = ended_session(&db.pool).await;
+
+        let before = SessionStats::get(&db.pool, session.id).await.unwrap();
+        assert_eq!(before.total_operations, 4);
+        assert_eq!(before.total_characters_typed, 11);
+        assert_eq!(before.files_edited, 2);
+
+        SessionSummary::compact(&db.pool, chrono::Duration::zero()).await.unwrap();
+        assert_eq!(raw_operations(&db.pool, session.id).await, 0);
+
+        let after = SessionStats::get(&db.pool, session.id).await.unwrap();
+        assert_eq!(after.total_operations, before.total_operations);
+        assert_eq!(after.total_characters_typed, before.total_characters_typed);
+        assert_eq!(after.files_edited, before.files_edited);
+
+        let summaries = SessionSummary::list(&db.pool, session.id).await.unwrap();
+        assert_eq!(summaries.len(), 2);
+        let main = summaries.iter().find(|s| s.operation_count == 3).unwrap();
+        assert_eq!(main.characters_typed, 9);
+        assert!(main.content_hash.is_some());
+
+        let mut contributions = main.contributions.0.clone();
+        contributions.sort_by_key(|c| c.operation_count);
+        assert_eq!(
+            contributions,
+            vec![
+                Contribution { user_id: owner, operation_count: 1, characters_typed: 5 },
+                Contribution { user_id: guest, operation_count: 2, characters_typed: 4 },
+            ]
+        );
+
+        // A second pass leaves the summaries alone
+        assert!(!SessionSummary::compact_session(&db.pool, session.id).await.unwrap());
+        assert_eq!(SessionSummary::list(&db.pool, session.id).await.unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_recorded_sessions_keep_raw_operations() {
+        let Some(db) = TestDb::start().await else { return };
+        let _turn = COMPACTION.lock().await;
+        let (session, _, _) = ended_session(&db.pool).await;
+
+        sqlx::query(
+            "INSERT INTO session_recordings (session_id, file_path, format, quality) VALUES ($1, 'rec.webm', 'webm', 'high')"
+        )
+        .bind(session.id)
+        .execute(&db.pool)
+        .await
+        .unwrap();
+
+        let dry_run = SessionSummary::compact_dry_run(&db.pool, chrono::Duration::zero()).await.unwrap();
+        let report = SessionSummary::compact(&db.pool, chrono::Duration::zero()).await.unwrap();
+        assert!(dry_run.dry_run && !report.dry_run);
+        assert_eq!(dry_run.sessions_compacted, report.sessions_compacted);
+        assert_eq!(dry_run.operations_removed, report.operations_removed);
+
+        assert_eq!(raw_operations(&db.pool, session.id).await, 5);
+        assert_eq!(SessionSummary::list(&db.pool, session.id).await.unwrap().len(), 2);
+
+        // Raw rows and summaries are not counted twice
+        let stats = SessionStats::get(&db.pool, session.id).await.unwrap();
+        assert_eq!(stats.total_operations, 4);
+        assert_eq!(stats.total_characters_typed, 11);
+    }
+
+    #[tokio::test]
+    async fn test_active_sessions_are_not_compacted() {
+        let Some(db) = TestDb::start().await else { return };
+        let owner = create_test_user(&db.pool).await;
+        let project = create_test_project(&db.pool, &owner, false).await;
+        let session = create_test_session(&db.pool, &project, &owner).await;
+
+        assert!(!SessionSummary::compact_session(&db.pool, session.id).await.unwrap());
+        assert!(SessionSummary::list(&db.pool, session.id).await.unwrap().is_empty());
+    }
+}
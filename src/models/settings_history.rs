@@ -0,0 +1,379 @@
+//! Project settings history
+//!
+//! Every change made through [`UpdateProject`] is recorded together with the
+//! previous values of the changed fields, in the same transaction as the
+//! update, so owners can see what a setting was before and roll it back.
+//! Collaborator role changes are recorded in the same stream.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use sqlx::{FromRow, PgConnection};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use super::project::{Project, UpdateProject};
+use super::{PaginationParams, UserRole};
+
+/// What a history entry records
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type, ToSchema)]
+pub enum SettingsChangeKind {
+    /// Project settings changed through an update
+    #[serde(rename = "settings")]
+    #[sqlx(rename = "settings")]
+    Settings,
+    /// Project settings restored from an earlier entry
+    #[serde(rename = "rollback")]
+    #[sqlx(rename = "rollback")]
+    Rollback,
+    /// A collaborator was added, removed or given another role
+    #[serde(rename = "collaborator_role")]
+    #[sqlx(rename = "collaborator_role")]
+    CollaboratorRole,
+}
+
+/// One recorded change
+#[derive(Debug, Clone, Serialize, FromRow, ToSchema)]
+pub struct ProjectSettingsChange {
+    pub id: Uuid,
+    pub project_id: Uuid,
+    pub kind: SettingsChangeKind,
+    pub changed_by: Option<Uuid>,
+    /// Collaborator whose role changed
+    pub collaborator_id: Option<Uuid>,
+    /// Entry whose previous values a rollback restored
+    pub rollback_of: Option<Uuid>,
+    /// Previous values of the changed fields
+    pub before: Value,
+    /// New values of the changed fields
+    pub after: Value,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Changed fields of a project, with their old and new values
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SettingsDiff {
+    pub before: Map<String, Value>,
+    pub after: Map<String, Value>,
+}
+
+impl SettingsDiff {
+    pub fn is_empty(&self) -> bool {
+        self.after.is_empty()
+    }
+
+    fn compare<T: Serialize + PartialEq>(&mut self, field: &str, current: &T, requested: Option<T>) {
+        let Some(requested) = requested.filter(|requested| requested != current) else {
+            return;
+        };
+        self.before.insert(field.to_string(), serde_json::to_value(current).unwrap_or(Value::Null));
+        self.after.insert(field.to_string(), serde_json::to_value(requested).unwrap_or(Value::Null));
+    }
+}
+
+/// The fields `update` would change on `project`
+pub fn settings_diff(project: &Project, update: &UpdateProject) -> SettingsDiff {
+    let update = update.clone();
+    let mut diff = SettingsDiff::default();
+    diff.compare("name", &project.name, update.name.map(String::from));
+    diff.compare("description", &project.description, update.description.map(Some));
+    diff.compare("is_public", &project.is_public, update.is_public);
+    diff.compare("is_embeddable", &project.is_embeddable, update.is_embeddable);
+    diff.compare("main_file_path", &project.main_file_path, update.main_file_path);
+    diff.compare("latex_engine", &project.latex_engine, update.latex_engine);
+    diff.compare("output_format", &project.output_format, update.output_format);
+    diff.compare("custom_args", &project.custom_args, update.custom_args);
+    diff.compare("bibliography_path", &project.bibliography_path, update.bibliography_path.map(Some));
+    diff.compare("readme_file_id", &project.readme_file_id, update.readme_file_id.map(Some));
+    diff.compare("settings", &project.settings.0, update.settings);
+    diff
+}
+
+impl ProjectSettingsChange {
+    /// Record a change as part of the caller's transaction
+    pub async fn record(
+        conn: &mut PgConnection,
+        project_id: Uuid,
+        kind: SettingsChangeKind,
+        changed_by: Uuid,
+        collaborator_id: Option<Uuid>,
+        rollback_of: Option<Uuid>,
+        diff: SettingsDiff,
+    ) -> Result<Self, crate::error::AppError> {
+        let change = sqlx::query_as::<_, ProjectSettingsChange>(
+            r#"
+            INSERT INTO project_settings_history (
+                project_id, kind, changed_by, collaborator_id, rollback_of, before, after
+            ) VALUES ($1, $2, $3, $4, $5, $6, $7)
+            RETURNING *
+            "#
+        )
+        .bind(project_id)
+        .bind(kind)
+        .bind(changed_by)
+        .bind(collaborator_id)
+        .bind(rollback_of)
+        .bind(Value::Object(diff.before))
+        .bind(Value::Object(diff.after))
+        .fetch_one(conn)
+        .await
+        .map_err(crate::error::AppError::Database)?;
+
+        Ok(change)
+    }
+
+    /// Record a collaborator's role going from `before` to `after` (`None` when not a member)
+    pub async fn record_role_change(
+        conn: &mut PgConnection,
+        project_id: Uuid,
+        collaborator_id: Uuid,
+        before: Option<UserRole>,
+        after: Option<UserRole>,
+        changed_by: Uuid,
+    ) -> Result<Self, crate::error::AppError> {
+        let mut diff = SettingsDiff::default();
+        diff.compare("role", &before, Some(after));
+
+        Self::record(
+            conn,
+            project_id,
+            SettingsChangeKind::CollaboratorRole,
+            changed_by,
+            Some(collaborator_id),
+            None,
+            diff,
+        )
+        .await
+    }
+
+    /// A page of the project's history, newest first, with the total count
+    pub async fn list(
+        db: &sqlx::PgPool,
+        project_id: Uuid,
+        params: &PaginationParams,
+    ) -> Result<(Vec<Self>, i64), crate::error::AppError> {
+        let changes = sqlx::query_as::<_, ProjectSettingsChange>(
+            r#"
+            SELECT * FROM project_settings_history
+            WHERE project_id = $1
+            ORDER BY created_at DESC, id
+            LIMIT $2 OFFSET $3
+            "#
+        )
+        .bind(project_id)
+        .bind(params.limit() as i64)
+        .bind(params.offset() as i64)
+        .fetch_all(db)
+        .await
+        .map_err(crate::error::AppError::Database)?;
+
+        let total = sqlx::query_scalar::<_, i64>(
+            "SELECT COUNT(*) FROM project_settings_history WHERE project_id = $1"
+        )
+        .bind(project_id)
+        .fetch_one(db)
+        .await
+        .map_err(crate::error::AppError::Database)?;
+
+        Ok((changes, total))
+    }
+
+    /// Changes made within a period, oldest first
+    pub async fn list_between(
+        db: &sqlx::PgPool,
+        project_id: Uuid,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+    ) -> Result<Vec<Self>, crate::error::AppError> {
+        sqlx::query_as::<_, ProjectSettingsChange>(
+            r#"
+            SELECT * FROM project_settings_history
+            WHERE project_id = $1 AND created_at >= $2 AND created_at < $3
+            ORDER BY created_at
+            "#
+        )
+        .bind(project_id)
+        .bind(start)
+        .bind(end)
+        .fetch_all(db)
+        .await
+        .map_err(crate::error::AppError::Database)
+    }
+
+    pub async fn find(
+        db: &sqlx::PgPool,
+        project_id: Uuid,
+        change_id: Uuid,
+    ) -> Result<Option<Self>, crate::error::AppError> {
+        sqlx::query_as::<_, ProjectSettingsChange>(
+            "SELECT * FROM project_settings_history WHERE id = $1 AND project_id = $2"
+        )
+        .bind(change_id)
+        .bind(project_id)
+        .fetch_optional(db)
+        .await
+        .map_err(crate::error::AppError::Database)
+    }
+
+    /// The update that restores the values this change replaced
+    ///
+    /// Like any [`UpdateProject`], it cannot clear a field that was empty
+    /// before the change.
+    pub fn rollback_update(&self) -> Result<UpdateProject, crate::error::AppError> {
+        if self.kind == SettingsChangeKind::CollaboratorRole {
+            return Err(crate::error::AppError::Validation(
+                "Collaborator role changes cannot be rolled back".to_string(),
+            ));
+        }
+
+        // Values recorded before names were validated may no longer be accepted
+        serde_json::from_value(self.before.clone()).map_err(|e| {
+            crate::error::AppError::Validation(format!("Settings history entry {} cannot be restored: {}", self.id, e))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::LatexEngine;
+    use crate::testing::{create_test_project, create_test_user, TestDb};
+
+    fn first_page() -> PaginationParams {
+        PaginationParams {
+            page: None,
+            limit: None,
+            offset: None,
+            sort_by: None,
+            sort_order: None,
+        }
+    }
+
+    fn empty_update() -> UpdateProject {
+        UpdateProject {
+            name: None,
+            description: None,
+            is_public: None,
+            is_embeddable: None,
+            main_file_path: None,
+            latex_engine: None,
+            output_format: None,
+            custom_args: None,
+            bibliography_path: None,
+            tags: None,
+            readme_file_id: None,
+            settings: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_update_records_only_changed_fields() {
+        let Some(db) = TestDb::start().await else { return };
+        let owner = create_test_user(&db.pool).await;
+        let project = create_test_project(&db.pool, &owner, false).await;
+
+        let updated = project
+            .update(
+                &db.pool,
+                UpdateProject {
+                    name: Some(crate::models::validation::ProjectName::new(&project.name).unwrap()),
+                    latex_engine: Some(LatexEngine::Xelatex),
+                    custom_args: Some(vec!["-shell-escape".to_string()]),
+                    ..empty_update()
+                },
+                owner.id,
+            )
+            .await
+            .unwrap();
+        assert_eq!(updated.latex_engine, LatexEngine::Xelatex);
+
+        let (changes, total) = ProjectSettingsChange::list(&db.pool, project.id, &first_page())
+            .await
+            .unwrap();
+        assert_eq!(total, 1);
+        assert_eq!(changes[0].kind, SettingsChangeKind::Settings);
+        assert_eq!(changes[0].changed_by, Some(owner.id));
+        assert_eq!(
+            changes[0].before,
+            serde_json::json!({ "latex_engine": project.latex_engine, "custom_args": [] })
+        );
+        assert_eq!(
+            changes[0].after,
+            serde_json::json!({ "latex_engine": "xelatex", "custom_args": ["-shell-escape"] })
+        );
+
+        // A no-op update leaves no entry
+        updated.update(&db.pool, empty_update(), owner.id).await.unwrap();
+        let (_, total) = ProjectSettingsChange::list(&db.pool, project.id, &first_page())
+            .await
+            .unwrap();
+        assert_eq!(total, 1);
+    }
+
+    #[tokio::test]
+    async fn test_rollback_restores_previous_values() {
+        let Some(db) = TestDb::start().await else { return };
+        let owner = create_test_user(&db.pool).await;
+        let project = create_test_project(&db.pool, &owner, false).await;
+
+        let broken = project
+            .update(
+                &db.pool,
+                UpdateProject {
+                    output_format: Some("dvi".to_string()),
+                    custom_args: Some(vec!["-halt-on-error".to_string()]),
+                    ..empty_update()
+                },
+                owner.id,
+            )
+            .await
+            .unwrap();
+        let (changes, _) = ProjectSettingsChange::list(&db.pool, project.id, &first_page())
+            .await
+            .unwrap();
+
+        let restored = broken.rollback(&db.pool, &changes[0], owner.id).await.unwrap();
+        assert_eq!(restored.output_format, project.output_format);
+        assert_eq!(restored.custom_args, project.custom_args);
+
+        let (changes, total) = ProjectSettingsChange::list(&db.pool, project.id, &first_page())
+            .await
+            .unwrap();
+        assert_eq!(total, 2);
+        assert_eq!(changes[0].kind, SettingsChangeKind::Rollback);
+        assert_eq!(changes[0].rollback_of, Some(changes[1].id));
+    }
+
+    #[tokio::test]
+    async fn test_rollback_is_validated() {
+        let Some(db) = TestDb::start().await else { return };
+        let owner = create_test_user(&db.pool).await;
+        let project = create_test_project(&db.pool, &owner, false).await;
+
+        // An entry from before the argument rules existed
+        let mut conn = db.pool.acquire().await.unwrap();
+        let change = ProjectSettingsChange::record(
+            &mut conn,
+            project.id,
+            SettingsChangeKind::Settings,
+            owner.id,
+            None,
+            None,
+            SettingsDiff {
+                before: serde_json::json!({ "custom_args": ["-jobname=a b"] }).as_object().unwrap().clone(),
+                after: serde_json::json!({ "custom_args": [] }).as_object().unwrap().clone(),
+            },
+        )
+        .await
+        .unwrap();
+        drop(conn);
+
+        let result = project.rollback(&db.pool, &change, owner.id).await;
+        assert!(matches!(result, Err(crate::error::AppError::Validation(_))), "{:?}", result);
+
+        let (_, total) = ProjectSettingsChange::list(&db.pool, project.id, &first_page())
+            .await
+            .unwrap();
+        assert_eq!(total, 1);
+    }
+}
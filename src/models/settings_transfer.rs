@@ -0,0 +1,534 @@
+//! Carrying a user's setup between instances
+//!
+//! `SettingsExport` is a versioned JSON blob of what users configure for
+//! themselves: editor preferences, notification preferences and the personal
+//! dictionary. Nothing that identifies the account goes into it, and an import
+//! never touches the account, even when a hand-edited blob carries such fields.
+//!
+//! Imports are lenient so blobs from newer servers still load: categories and
+//! fields this server does not know are skipped with a warning. Each category
+//! is applied on its own and either fully or not at all, and importing the
+//! same blob again changes nothing.
+
+use chrono::{DateTime, Utc};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashSet;
+use utoipa::ToSchema;
+
+use super::activity_digest::DigestFrequency;
+use super::compile_notification::CompileNotificationPreference;
+use super::mention::MentionNotificationPreference;
+use super::dictionary::{
+    normalize_term, term_key, DictionaryEntry, DictionaryScope, NewTerm, TermOutcome, MAX_USER_DICTIONARY_ENTRIES,
+};
+use super::user::{User, UserPreferences};
+use crate::error::AppError;
+
+/// Version of the blobs this server writes
+pub const SETTINGS_EXPORT_VERSION: u64 = 1;
+
+/// Fields that identify an account, never imported wherever they appear
+const IDENTITY_FIELDS: [&str; 10] = [
+    "id",
+    "user_id",
+    "username",
+    "email",
+    "email_verified",
+    "password",
+    "password_hash",
+    "display_name",
+    "avatar_url",
+    "oidc_subject",
+];
+
+/// Top-level fields that describe the blob itself
+const BLOB_FIELDS: [&str; 2] = ["version", "exported_at"];
+
+/// Part of the settings that is imported as a unit
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SettingsCategory {
+    Preferences,
+    Notifications,
+    Dictionary,
+}
+
+impl SettingsCategory {
+    pub fn from_key(key: &str) -> Option<Self> {
+        match key {
+            "preferences" => Some(Self::Preferences),
+            "notifications" => Some(Self::Notifications),
+            "dictionary" => Some(Self::Dictionary),
+            _ => None,
+        }
+    }
+
+    pub fn key(self) -> &'static str {
+        match self {
+            Self::Preferences => "preferences",
+            Self::Notifications => "notifications",
+            Self::Dictionary => "dictionary",
+        }
+    }
+
+    /// Fields of an object category
+    fn fields(self) -> &'static [&'static str] {
+        match self {
+            Self::Preferences => &[
+                "theme",
+                "language",
+                "latex_engine",
+                "auto_save",
+                "line_numbers",
+                "word_wrap",
+                "font_size",
+                "tab_size",
+            ],
+            Self::Notifications => &["compile_notifications", "digest_frequency", "mention_notifications"],
+            Self::Dictionary => &[],
+        }
+    }
+}
+
+/// Editor preferences; on import, missing fields keep their current value
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct EditorSettings {
+    pub theme: Option<String>,
+    pub language: Option<String>,
+    pub latex_engine: Option<String>,
+    pub auto_save: Option<bool>,
+    pub line_numbers: Option<bool>,
+    pub word_wrap: Option<bool>,
+    pub font_size: Option<i32>,
+    pub tab_size: Option<i32>,
+}
+
+/// Notification preferences; on import, missing fields keep their current value
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct NotificationSettings {
+    pub compile_notifications: Option<CompileNotificationPreference>,
+    pub digest_frequency: Option<DigestFrequency>,
+    pub mention_notifications: Option<MentionNotificationPreference>,
+}
+
+/// Exported settings of a user
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct SettingsExport {
+    pub version: u64,
+    pub exported_at: DateTime<Utc>,
+    pub preferences: EditorSettings,
+    pub notifications: NotificationSettings,
+    pub dictionary: Vec<NewTerm>,
+}
+
+/// A setting an import changed, or would change on a dry run
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct SettingChange {
+    pub category: SettingsCategory,
+    /// Preference name, or the term for dictionary changes
+    pub field: String,
+    pub from: Value,
+    pub to: Value,
+}
+
+/// A category left untouched because part of it was invalid
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct RejectedCategory {
+    pub category: SettingsCategory,
+    pub message: String,
+}
+
+/// Outcome of an import
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct SettingsImportReport {
+    pub dry_run: bool,
+    /// Version of the imported blob
+    pub version: u64,
+    pub changes: Vec<SettingChange>,
+    pub rejected: Vec<RejectedCategory>,
+    /// Skipped fields and other things worth knowing
+    pub warnings: Vec<String>,
+}
+
+impl SettingsExport {
+    /// Export the settings of a user
+    pub async fn build(db: &sqlx::PgPool, user: &User) -> Result<Self, AppError> {
+        let preferences = user.get_preferences(db).await?;
+        let dictionary = DictionaryEntry::list(db, DictionaryScope::User(user.id)).await?;
+
+        Ok(Self {
+            version: SETTINGS_EXPORT_VERSION,
+            exported_at: Utc::now(),
+            preferences: EditorSettings {
+                theme: Some(preferences.theme),
+                language: Some(preferences.language),
+                latex_engine: Some(preferences.latex_engine),
+                auto_save: Some(preferences.auto_save),
+                line_numbers: Some(preferences.line_numbers),
+                word_wrap: Some(preferences.word_wrap),
+                font_size: Some(preferences.font_size),
+                tab_size: Some(preferences.tab_size),
+            },
+            notifications: NotificationSettings {
+                compile_notifications: Some(preferences.compile_notifications),
+                digest_frequency: Some(preferences.digest_frequency),
+                mention_notifications: Some(preferences.mention_notifications),
+            },
+            dictionary: dictionary
+                .into_iter()
+                .map(|entry| NewTerm {
+                    term: entry.term,
+                    case_sensitive: entry.case_sensitive,
+                })
+                .collect(),
+        })
+    }
+
+    /// Import a blob for a user; a dry run only reports what would change
+    pub async fn import(
+        db: &sqlx::PgPool,
+        user: &User,
+        blob: &Value,
+        dry_run: bool,
+    ) -> Result<SettingsImportReport, AppError> {
+        let object = blob
+            .as_object()
+            .ok_or_else(|| AppError::Validation("Settings must be a JSON object".to_string()))?;
+        let version = check_version(blob)?;
+
+        let mut report = SettingsImportReport {
+            dry_run,
+            version,
+            changes: Vec::new(),
+            rejected: Vec::new(),
+            warnings: Vec::new(),
+        };
+        if version > SETTINGS_EXPORT_VERSION {
+            report.warnings.push(format!(
+                "Settings were exported by a newer server (version {}); anything this server does not know is skipped",
+                version
+            ));
+        }
+
+        let mut categories = Vec::new();
+        for (key, value) in object {
+            if BLOB_FIELDS.contains(&key.as_str()) {
+                continue;
+            }
+            match SettingsCategory::from_key(key) {
+                Some(category) => categories.push((category, value)),
+                None => report.warnings.push(skipped_field_warning(key)),
+            }
+        }
+
+        let mut preferences = user.get_preferences(db).await?;
+        for (category, value) in categories {
+            let outcome = match category {
+                SettingsCategory::Preferences => {
+                    parse_category::<EditorSettings>(category, value, &mut report.warnings)
+                        .map(|settings| overlay_editor_settings(&mut preferences, &settings))
+                }
+                SettingsCategory::Notifications => {
+                    parse_category::<NotificationSettings>(category, value, &mut report.warnings)
+                        .map(|settings| overlay_notification_settings(&mut preferences, &settings))
+                }
+                SettingsCategory::Dictionary => import_dictionary(db, user, value, dry_run).await?,
+            };
+
+            match outcome {
+                Ok(changes) => {
+                    // One upsert per category, so a category applies fully or not at all
+                    let writes_preferences = category != SettingsCategory::Dictionary;
+                    if writes_preferences && !dry_run && !changes.is_empty() {
+                        preferences = user.update_preferences(db, &preferences).await?;
+                    }
+                    report.changes.extend(changes);
+                }
+                Err(message) => report.rejected.push(RejectedCategory { category, message }),
+            }
+        }
+
+        Ok(report)
+    }
+}
+
+/// Version of a blob; blobs without one were not written by an export
+fn check_version(blob: &Value) -> Result<u64, AppError> {
+    match blob.get("version").and_then(Value::as_u64) {
+        Some(version) if version >= 1 => Ok(version),
+        _ => Err(AppError::Validation(
+            "Settings have no valid version; only blobs from a settings export can be imported".to_string(),
+        )),
+    }
+}
+
+fn skipped_field_warning(field: &str) -> String {
+    if IDENTITY_FIELDS.contains(&field) {
+        format!("`{}` belongs to the account and is never imported", field)
+    } else {
+        format!("Unknown setting `{}` skipped", field)
+    }
+}
+
+/// Parse an object category, warning about the fields it does not know
+fn parse_category<T: DeserializeOwned>(
+    category: SettingsCategory,
+    value: &Value,
+    warnings: &mut Vec<String>,
+) -> Result<T, String> {
+    let object = value
+        .as_object()
+        .ok_or_else(|| "Expected an object".to_string())?;
+
+    for key in object.keys() {
+        if !category.fields().contains(&key.as_str()) {
+            warnings.push(skipped_field_warning(&format!("{}.{}", category.key(), key)));
+        }
+    }
+
+    serde_json::from_value(value.clone()).map_err(|e| e.to_string())
+}
+
+/// Set `current` to `imported` when given, recording the change
+fn overlay<T: PartialEq + Clone + Serialize>(
+    changes: &mut Vec<SettingChange>,
+    category: SettingsCategory,
+    field: &str,
+    current: &mut T,
+    imported: &Option<T>,
+) {
+    if let Some(imported) = imported {
+        if current != imported {
+            changes.push(SettingChange {
+                category,
+                field: field.to_string(),
+                from: serde_json::json!(current),
+                to: serde_json::json!(imported),
+            });
+            *current = imported.clone();
+        }
+    }
+}
+
+fn overlay_editor_settings(preferences: &mut UserPreferences, settings: &EditorSettings) -> Vec<SettingChange> {
+    let category = SettingsCategory::Preferences;
+    let mut changes = Vec::new();
+    overlay(&mut changes, category, "theme", &mut preferences.theme, &settings.theme);
+    overlay(&mut changes, category, "language", &mut preferences.language, &settings.language);
+    overlay(&mut changes, category, "latex_engine", &mut preferences.latex_engine, &settings.latex_engine);
+    overlay(&mut changes, category, "auto_save", &mut preferences.auto_save, &settings.auto_save);
+    overlay(&mut changes, category, "line_numbers", &mut preferences.line_numbers, &settings.line_numbers);
+    overlay(&mut changes, category, "word_wrap", &mut preferences.word_wrap, &settings.word_wrap);
+    overlay(&mut changes, category, "font_size", &mut preferences.font_size, &settings.font_size);
+    overlay(&mut changes, category, "tab_size", &mut preferences.tab_size, &settings.tab_size);
+    changes
+}
+
+fn overlay_notification_settings(
+    preferences: &mut UserPreferences,
+    settings: &NotificationSettings,
+) -> Vec<SettingChange> {
+    let category = SettingsCategory::Notifications;
+    let mut changes = Vec::new();
+    overlay(
+        &mut changes,
+        category,
+        "compile_notifications",
+        &mut preferences.compile_notifications,
+        &settings.compile_notifications,
+    );
+    overlay(
+        &mut changes,
+        category,
+        "digest_frequency",
+        &mut preferences.digest_frequency,
+        &settings.digest_frequency,
+    );
+    overlay(
+        &mut changes,
+        category,
+        "mention_notifications",
+        &mut preferences.mention_notifications,
+        &settings.mention_notifications,
+    );
+    changes
+}
+
+/// Add the terms the personal dictionary lacks; `Err` rejects the whole list
+async fn import_dictionary(
+    db: &sqlx::PgPool,
+    user: &User,
+    value: &Value,
+    dry_run: bool,
+) -> Result<Result<Vec<SettingChange>, String>, AppError> {
+    let terms = match serde_json::from_value::<Vec<NewTerm>>(value.clone()) {
+        Ok(terms) => terms,
+        Err(e) => return Ok(Err(e.to_string())),
+    };
+
+    let scope = DictionaryScope::User(user.id);
+    let existing = DictionaryEntry::list(db, scope).await?;
+    let mut keys: HashSet<(String, bool)> = existing
+        .iter()
+        .map(|entry| (term_key(&entry.term, entry.case_sensitive), entry.case_sensitive))
+        .collect();
+
+    let mut missing = Vec::new();
+    for new_term in terms {
+        let term = match normalize_term(&new_term.term) {
+            Ok(term) => term,
+            Err(message) => return Ok(Err(format!("{}: {}", new_term.term, message))),
+        };
+        if keys.insert((term_key(&term, new_term.case_sensitive), new_term.case_sensitive)) {
+            missing.push(NewTerm {
+                term,
+                case_sensitive: new_term.case_sensitive,
+            });
+        }
+    }
+
+    if existing.len() + missing.len() > MAX_USER_DICTIONARY_ENTRIES as usize {
+        return Ok(Err(format!(
+            "The personal dictionary would exceed {} entries",
+            MAX_USER_DICTIONARY_ENTRIES
+        )));
+    }
+
+    let change = |term: String| SettingChange {
+        category: SettingsCategory::Dictionary,
+        field: term.clone(),
+        from: Value::Null,
+        to: Value::String(term),
+    };
+
+    if dry_run || missing.is_empty() {
+        return Ok(Ok(missing.into_iter().map(|new_term| change(new_term.term)).collect()));
+    }
+
+    let outcomes = DictionaryEntry::add_terms(db, scope, missing, user.id).await?;
+    Ok(Ok(outcomes
+        .into_iter()
+        .filter_map(|outcome| match outcome {
+            TermOutcome::Added { term, .. } => Some(change(term)),
+            _ => None,
+        })
+        .collect()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{create_test_user, TestDb};
+    use serde_json::json;
+
+    #[test]
+    fn test_version_is_required() {
+        assert!(check_version(&json!({ "preferences": {} })).is_err());
+        assert!(check_version(&json!({ "version": 0 })).is_err());
+        assert!(check_version(&json!({ "version": "1" })).is_err());
+        assert_eq!(check_version(&json!({ "version": 3 })).unwrap(), 3);
+    }
+
+    #[test]
+    fn test_overlay_records_only_differences() {
+        let mut preferences = UserPreferences::default(uuid::Uuid::nil());
+        let settings = EditorSettings {
+            theme: Some(preferences.theme.clone()),
+            font_size: Some(18),
+            ..EditorSettings::default()
+        };
+
+        let changes = overlay_editor_settings(&mut preferences, &settings);
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].field, "font_size");
+        assert_eq!(changes[0].to, json!(18));
+        assert_eq!(preferences.font_size, 18);
+    }
+
+    #[tokio::test]
+    async fn test_settings_round_trip() {
+        let Some(db) = TestDb::start().await else { return };
+        let source = create_test_user(&db.pool).await;
+        let target = create_test_user(&db.pool).await;
+
+        let mut preferences = source.get_preferences(&db.pool).await.unwrap();
+        preferences.theme = "solarized".to_string();
+        preferences.font_size = 17;
+        preferences.digest_frequency = DigestFrequency::Weekly;
+        source.update_preferences(&db.pool, &preferences).await.unwrap();
+        let terms = vec![
+            NewTerm { term: "Texler".to_string(), case_sensitive: true },
+            NewTerm { term: "bibliographie".to_string(), case_sensitive: false },
+        ];
+        DictionaryEntry::add_terms(&db.pool, DictionaryScope::User(source.id), terms, source.id).await.unwrap();
+
+        let export = SettingsExport::build(&db.pool, &source).await.unwrap();
+        let blob = serde_json::to_value(&export).unwrap();
+        for field in IDENTITY_FIELDS {
+            assert!(blob.get(field).is_none(), "export carries {}", field);
+        }
+
+        // A dry run reports the changes without making them
+        let report = SettingsExport::import(&db.pool, &target, &blob, true).await.unwrap();
+        assert!(report.rejected.is_empty() && report.warnings.is_empty());
+        assert!(report.changes.iter().any(|change| change.field == "theme"));
+        assert!(report.changes.iter().any(|change| change.field == "Texler"));
+        assert_eq!(target.get_preferences(&db.pool).await.unwrap().theme, "dark");
+
+        let report = SettingsExport::import(&db.pool, &target, &blob, false).await.unwrap();
+        assert_eq!(report.changes.len(), 5);
+        let imported = SettingsExport::build(&db.pool, &target).await.unwrap();
+        assert_eq!(imported.preferences, export.preferences);
+        assert_eq!(imported.notifications, export.notifications);
+        assert_eq!(
+            serde_json::to_value(&imported.dictionary).unwrap(),
+            serde_json::to_value(&export.dictionary).unwrap()
+        );
+
+        // Importing the same blob again is a no-op
+        let report = SettingsExport::import(&db.pool, &target, &blob, false).await.unwrap();
+        assert!(report.changes.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_import_from_newer_version() {
+        let Some(db) = TestDb::start().await else { return };
+        let user = create_test_user(&db.pool).await;
+
+        let blob = json!({
+            "version": SETTINGS_EXPORT_VERSION + 1,
+            "exported_at": Utc::now(),
+            "email": "someone-else@example.com",
+            "snippets": [{ "trigger": "beq", "body": "\\begin{equation}" }],
+            "preferences": { "theme": "light", "minimap": true, "user_id": uuid::Uuid::new_v4() },
+            "notifications": { "digest_frequency": "daily" },
+            "dictionary": [{ "term": "Texler", "case_sensitive": true, "source": "personal" }],
+        });
+
+        let report = SettingsExport::import(&db.pool, &user, &blob, false).await.unwrap();
+        assert!(report.rejected.is_empty());
+        assert_eq!(report.changes.len(), 3);
+        assert_eq!(report.warnings.len(), 5, "{:?}", report.warnings);
+        assert!(report.warnings.iter().any(|warning| warning.contains("`email` belongs to the account")));
+
+        let stored = User::find_by_id(&db.pool, user.id).await.unwrap().unwrap();
+        assert_eq!(stored.email, user.email);
+        let preferences = stored.get_preferences(&db.pool).await.unwrap();
+        assert_eq!(preferences.theme, "light");
+        assert_eq!(preferences.digest_frequency, DigestFrequency::Daily);
+
+        // A category with a bad value is left alone while the others apply
+        let blob = json!({
+            "version": SETTINGS_EXPORT_VERSION,
+            "preferences": { "theme": "dark", "font_size": "huge" },
+            "notifications": { "digest_frequency": "weekly" },
+        });
+        let report = SettingsExport::import(&db.pool, &user, &blob, false).await.unwrap();
+        assert_eq!(report.rejected.len(), 1);
+        assert_eq!(report.rejected[0].category, SettingsCategory::Preferences);
+        let preferences = user.get_preferences(&db.pool).await.unwrap();
+        assert_eq!(preferences.theme, "light");
+        assert_eq!(preferences.digest_frequency, DigestFrequency::Weekly);
+    }
+}
@@ -0,0 +1,292 @@
+//! Storage accounting of projects
+//!
+//! Every figure is an aggregate in SQL. The bytes of live files are what the
+//! upload quota counts, and [`quota_usage`] and the breakdown share the same
+//! expressions so the two cannot disagree: each distinct content of a live
+//! file counts once, as the blob store keeps it once.
+//!
+//! History is reported twice. The raw figure is the full size of every
+//! recorded version, as if each were kept whole; the stored one is what
+//! history actually takes up, the deltas and snapshots in its rows plus the
+//! blobs of binary versions that no live file of the project shares.
+//! Artifacts are the output sizes of the project's compiles.
+//!
+//! Aggregates are cached per project in `project_storage_usage` for
+//! [`USAGE_TTL`]; the quota check itself never reads the cache.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use utoipa::{IntoParams, ToSchema};
+use uuid::Uuid;
+
+use super::project_limits::{LimitUsage, ProjectLimits};
+use super::ContentType;
+use crate::error::AppError;
+
+/// How long cached aggregates are served before they are recomputed
+pub const USAGE_TTL: chrono::Duration = chrono::Duration::minutes(10);
+
+/// Files listed in a breakdown
+pub const LARGEST_FILES_LIMIT: i64 = 20;
+
+/// Bytes of a project's live files, each distinct content once
+const LIVE_BYTES: &str = r#"
+    COALESCE((SELECT SUM(b.size) FROM blobs b WHERE b.hash IN (
+        SELECT content_hash FROM files WHERE project_id = $1 AND is_deleted = false
+    )), 0)::BIGINT
+"#;
+
+/// Sizes declared by a project's uploads still in progress
+const PENDING_UPLOAD_BYTES: &str = r#"
+    COALESCE((SELECT SUM(declared_size) FROM upload_sessions
+              WHERE project_id = $1 AND status = 'pending' AND expires_at > NOW()), 0)::BIGINT
+"#;
+
+/// Bytes counted against a project's quota: live files and pending uploads
+pub async fn quota_usage(db: impl sqlx::PgExecutor<'_>, project_id: Uuid) -> Result<i64, AppError> {
+    sqlx::query_scalar::<_, i64>(&format!("SELECT {LIVE_BYTES} + {PENDING_UPLOAD_BYTES}"))
+        .bind(project_id)
+        .fetch_one(db)
+        .await
+        .map_err(AppError::Database)
+}
+
+/// Storage usage parameters
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct StorageParams {
+    /// Recompute the aggregates now, e.g. to see the effect of a deletion
+    #[serde(default)]
+    pub refresh: bool,
+}
+
+/// Live bytes of one content type
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct ContentTypeUsage {
+    pub content_type: ContentType,
+    pub files: i64,
+    /// Each distinct content once; files of different types sharing content
+    /// count under each
+    pub bytes: i64,
+}
+
+/// Cached storage aggregates of a project
+#[derive(Debug, Clone, Serialize, FromRow, ToSchema)]
+pub struct ProjectStorageUsage {
+    pub project_id: Uuid,
+    /// Bytes of live files, as counted against the quota
+    pub live_bytes: i64,
+    /// Declared sizes of uploads in progress, also counted against the quota
+    pub pending_upload_bytes: i64,
+    #[schema(value_type = Vec<ContentTypeUsage>)]
+    pub by_content_type: sqlx::types::Json<Vec<ContentTypeUsage>>,
+    /// Full size of every recorded version
+    pub history_raw_bytes: i64,
+    /// Size history takes up with deltas and shared blobs
+    pub history_stored_bytes: i64,
+    /// Output of the project's compiles
+    pub artifact_bytes: i64,
+    pub computed_at: DateTime<Utc>,
+}
+
+/// A file in the list of a project's largest
+#[derive(Debug, Clone, Serialize, FromRow, ToSchema)]
+pub struct LargestFile {
+    pub id: Uuid,
+    pub path: String,
+    pub size: i64,
+    pub last_modified: DateTime<Utc>,
+}
+
+/// Where a project's storage goes
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct StorageBreakdown {
+    #[serde(flatten)]
+    pub usage: ProjectStorageUsage,
+    /// Quota of `live_bytes` and `pending_upload_bytes` together
+    pub quota_bytes: u64,
+    /// Largest live files, by size
+    pub largest_files: Vec<LargestFile>,
+    /// Current usage of each project limit, never cached
+    pub limits: Vec<LimitUsage>,
+}
+
+/// Storage of a workspace's projects
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct WorkspaceStorage {
+    pub workspace_id: Uuid,
+    pub live_bytes: i64,
+    pub pending_upload_bytes: i64,
+    pub history_raw_bytes: i64,
+    pub history_stored_bytes: i64,
+    pub artifact_bytes: i64,
+    /// Projects by live bytes, largest first
+    pub projects: Vec<ProjectStorageUsage>,
+}
+
+impl ProjectStorageUsage {
+    /// Recompute a project's aggregates and cache them
+    pub async fn compute(db: &sqlx::PgPool, project_id: Uuid) -> Result<Self, AppError> {
+        sqlx::query_as::<_, ProjectStorageUsage>(&format!(
+            r#"
+            INSERT INTO project_storage_usage
+                (project_id, live_bytes, pending_upload_bytes, by_content_type,
+                 history_raw_bytes, history_stored_bytes, artifact_bytes, computed_at)
+            SELECT
+                $1,
+                {LIVE_BYTES},
+                {PENDING_UPLOAD_BYTES},
+                (SELECT COALESCE(jsonb_agg(jsonb_build_object(
+                            'content_type', t.content_type, 'files', t.files, 'bytes', t.bytes
+                        ) ORDER BY t.content_type), '[]'::jsonb)
+                 FROM (
+                     SELECT f.content_type, COUNT(*) AS files,
+                            COALESCE((SELECT SUM(b.size) FROM blobs b WHERE b.hash IN (
+                                SELECT content_hash FROM files
+                                WHERE project_id = $1 AND is_deleted = false AND content_type = f.content_type
+                            )), 0)::BIGINT AS bytes
+                     FROM files f
+                     WHERE f.project_id = $1 AND f.is_deleted = false
+                     GROUP BY f.content_type
+                 ) t),
+                (SELECT COALESCE(SUM(b.size), 0)::BIGINT
+                 FROM file_versions v
+                 JOIN files f ON f.id = v.file_id
+                 JOIN blobs b ON b.hash = v.content_hash
+                 WHERE f.project_id = $1),
+                (SELECT COALESCE(SUM(COALESCE(octet_length(v.changes), 0) + COALESCE(octet_length(v.snapshot), 0)), 0)::BIGINT
+                 FROM file_versions v
+                 JOIN files f ON f.id = v.file_id
+                 WHERE f.project_id = $1 AND v.encoding <> 'blob')
+                + (SELECT COALESCE(SUM(b.size), 0)::BIGINT FROM blobs b
+                   WHERE b.storage_path IS NOT NULL
+                     AND b.hash IN (
+                         SELECT v.content_hash FROM file_versions v
+                         JOIN files f ON f.id = v.file_id
+                         WHERE f.project_id = $1 AND v.encoding = 'blob'
+                     )
+                     AND NOT EXISTS (
+                         SELECT 1 FROM files
+                         WHERE project_id = $1 AND is_deleted = false AND content_hash = b.hash
+                     )),
+                (SELECT COALESCE(SUM(output_size_bytes), 0)::BIGINT FROM compilation_jobs WHERE project_id = $1),
+                NOW()
+            ON CONFLICT (project_id) DO UPDATE SET
+                live_bytes = EXCLUDED.live_bytes,
+                pending_upload_bytes = EXCLUDED.pending_upload_bytes,
+                by_content_type = EXCLUDED.by_content_type,
+                history_raw_bytes = EXCLUDED.history_raw_bytes,
+                history_stored_bytes = EXCLUDED.history_stored_bytes,
+                artifact_bytes = EXCLUDED.artifact_bytes,
+                computed_at = EXCLUDED.computed_at
+            RETURNING *
+            "#
+        ))
+        .bind(project_id)
+        .fetch_one(db)
+        .await
+        .map_err(AppError::Database)
+    }
+
+    /// Cached aggregates of a project, recomputed when older than
+    /// [`USAGE_TTL`] or when `refresh` is set
+    pub async fn current(db: &sqlx::PgPool, project_id: Uuid, refresh: bool) -> Result<Self, AppError> {
+        if !refresh {
+            let cached = sqlx::query_as::<_, ProjectStorageUsage>(
+                "SELECT * FROM project_storage_usage WHERE project_id = $1 AND computed_at > $2"
+            )
+            .bind(project_id)
+            .bind(Utc::now() - USAGE_TTL)
+            .fetch_optional(db)
+            .await
+            .map_err(AppError::Database)?;
+            if let Some(cached) = cached {
+                return Ok(cached);
+            }
+        }
+
+        Self::compute(db, project_id).await
+    }
+}
+
+impl LargestFile {
+    /// Largest live files of a project
+    pub async fn for_project(db: &sqlx::PgPool, project_id: Uuid, limit: i64) -> Result<Vec<Self>, AppError> {
+        sqlx::query_as::<_, LargestFile>(
+            r#"
+            SELECT id, path, size, last_modified FROM files
+            WHERE project_id = $1 AND is_deleted = false
+            ORDER BY size DESC, path
+            LIMIT $2
+            "#
+        )
+        .bind(project_id)
+        .bind(limit)
+        .fetch_all(db)
+        .await
+        .map_err(AppError::Database)
+    }
+}
+
+impl StorageBreakdown {
+    pub async fn build(db: &sqlx::PgPool, project_id: Uuid, limits: &ProjectLimits, refresh: bool) -> Result<Self, AppError> {
+        let usage = ProjectStorageUsage::current(db, project_id, refresh).await?;
+        let largest_files = LargestFile::for_project(db, project_id, LARGEST_FILES_LIMIT).await?;
+
+        Ok(Self {
+            usage,
+            quota_bytes: limits.max_project_size,
+            largest_files,
+            limits: limits.usage(db, project_id).await?,
+        })
+    }
+}
+
+impl WorkspaceStorage {
+    /// Roll up the storage of a workspace's projects, recomputing those whose
+    /// aggregates are stale, or all of them with `refresh`
+    pub async fn build(db: &sqlx::PgPool, workspace_id: Uuid, refresh: bool) -> Result<Self, AppError> {
+        let stale = sqlx::query_scalar::<_, Uuid>(
+            r#"
+            SELECT p.id FROM projects p
+            LEFT JOIN project_storage_usage u ON u.project_id = p.id
+            WHERE p.workspace_id = $1 AND p.purging_at IS NULL
+              AND ($2 OR u.computed_at IS NULL OR u.computed_at <= $3)
+            "#
+        )
+        .bind(workspace_id)
+        .bind(refresh)
+        .bind(Utc::now() - USAGE_TTL)
+        .fetch_all(db)
+        .await
+        .map_err(AppError::Database)?;
+        for project_id in stale {
+            ProjectStorageUsage::compute(db, project_id).await?;
+        }
+
+        let projects = sqlx::query_as::<_, ProjectStorageUsage>(
+            r#"
+            SELECT u.* FROM project_storage_usage u
+            JOIN projects p ON p.id = u.project_id
+            WHERE p.workspace_id = $1 AND p.purging_at IS NULL
+            ORDER BY u.live_bytes DESC, u.project_id
+            "#
+        )
+        .bind(workspace_id)
+        .fetch_all(db)
+        .await
+        .map_err(AppError::Database)?;
+
+        let total = |bytes: fn(&ProjectStorageUsage) -> i64| projects.iter().map(bytes).sum::<i64>();
+        Ok(Self {
+            workspace_id,
+            live_bytes: total(|p| p.live_bytes),
+            pending_upload_bytes: total(|p| p.pending_upload_bytes),
+            history_raw_bytes: total(|p| p.history_raw_bytes),
+            history_stored_bytes: total(|p| p.history_stored_bytes),
+            artifact_bytes: total(|p| p.artifact_bytes),
+            projects,
+        })
+    }
+}
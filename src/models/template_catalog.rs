@@ -0,0 +1,411 @@
+//! Compilation template catalog
+//!
+//! Public templates can be searched, rated and forked. Ratings are one per
+//! user and template; rating again replaces the earlier rating, and the
+//! count and average stored on the template are recomputed in the same
+//! transaction, with the template row locked so concurrent ratings cannot
+//! lose an update. A fork is a private copy owned by the caller that
+//! remembers its source in `forked_from`; each user has at most one fork of
+//! a template, and forking again returns it.
+
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use utoipa::{IntoParams, ToSchema};
+use uuid::Uuid;
+
+use super::compilation::CompilationTemplate;
+use super::file_tree::escape_like;
+use super::{LatexEngine, PaginationParams};
+use crate::error::AppError;
+
+/// Lowest rating a user can give
+pub const MIN_RATING: i16 = 1;
+
+/// Highest rating a user can give
+pub const MAX_RATING: i16 = 5;
+
+/// Template catalog filters
+#[derive(Debug, Clone, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct TemplateSearchParams {
+    /// Matched against name and description, case-insensitively
+    pub query: Option<String>,
+    pub engine: Option<LatexEngine>,
+}
+
+/// Public profile of a template's author
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct TemplateAuthor {
+    pub id: Uuid,
+    pub username: String,
+    pub display_name: String,
+    pub avatar_url: Option<String>,
+}
+
+/// A template as listed in the catalog
+#[derive(Debug, Clone, Serialize, FromRow, ToSchema)]
+pub struct TemplateListing {
+    #[serde(flatten)]
+    #[sqlx(flatten)]
+    pub template: CompilationTemplate,
+    #[sqlx(json)]
+    pub author: TemplateAuthor,
+    /// The caller's own rating, if any
+    pub my_rating: Option<i16>,
+}
+
+impl CompilationTemplate {
+    /// A template `user_id` may see: public ones and their own
+    pub async fn find_visible(
+        db: impl sqlx::PgExecutor<'_>,
+        template_id: Uuid,
+        user_id: Uuid,
+    ) -> Result<Self, AppError> {
+        sqlx::query_as::<_, CompilationTemplate>(
+            "SELECT * FROM compilation_templates WHERE id = $1 AND (is_public = true OR created_by = $2)"
+        )
+        .bind(template_id)
+        .bind(user_id)
+        .fetch_optional(db)
+        .await
+        .map_err(AppError::Database)?
+        .ok_or_else(|| AppError::NotFound {
+            entity: "CompilationTemplate".to_string(),
+            id: template_id.to_string(),
+        })
+    }
+
+    /// Public templates matching `search`, verified ones first
+    ///
+    /// Authors and the viewer's ratings are joined in, so a page is one query.
+    pub async fn list_public(
+        db: &sqlx::PgPool,
+        viewer_id: Uuid,
+        search: &TemplateSearchParams,
+        params: &PaginationParams,
+    ) -> Result<(Vec<TemplateListing>, i64), AppError> {
+        let pattern = search
+            .query
+            .as_deref()
+            .map(str::trim)
+            .filter(|query| !query.is_empty())
+            .map(|query| format!("%{}%", escape_like(query)));
+
+        const FILTER: &str = r#"
+            t.is_public = true
+              AND ($1::TEXT IS NULL OR t.name ILIKE $1 OR t.description ILIKE $1)
+              AND ($2::latexengine IS NULL OR t.engine = $2)
+        "#;
+
+        let templates = sqlx::query_as::<_, TemplateListing>(&format!(
+            r#"
+            SELECT
+                t.*,
+                json_build_object(
+                    'id', u.id,
+                    'username', u.username,
+                    'display_name', COALESCE(u.display_name, u.username),
+                    'avatar_url', u.avatar_url
+                ) AS author,
+                r.rating AS my_rating
+            FROM compilation_templates t
+            JOIN users u ON u.id = t.created_by
+            LEFT JOIN compilation_template_ratings r ON r.template_id = t.id AND r.user_id = $3
+            WHERE {}
+            ORDER BY t.verified DESC, t.success_rate DESC, t.usage_count DESC, t.id
+            LIMIT $4 OFFSET $5
+            "#,
+            FILTER
+        ))
+        .bind(&pattern)
+        .bind(search.engine)
+        .bind(viewer_id)
+        .bind(params.limit() as i64)
+        .bind(params.offset() as i64)
+        .fetch_all(db)
+        .await
+        .map_err(AppError::Database)?;
+
+        let total = sqlx::query_scalar::<_, i64>(&format!(
+            "SELECT COUNT(*) FROM compilation_templates t WHERE {}",
+            FILTER
+        ))
+        .bind(&pattern)
+        .bind(search.engine)
+        .fetch_one(db)
+        .await
+        .map_err(AppError::Database)?;
+
+        Ok((templates, total))
+    }
+
+    /// Rate a template, replacing the user's earlier rating
+    ///
+    /// Returns the template with its updated rating count and average.
+    pub async fn rate(
+        db: &sqlx::PgPool,
+        template_id: Uuid,
+        user_id: Uuid,
+        rating: i16,
+    ) -> Result<Self, AppError> {
+        if !(MIN_RATING..=MAX_RATING).contains(&rating) {
+            return Err(AppError::Validation(format!(
+                "Rating must be between {} and {}",
+                MIN_RATING, MAX_RATING
+            )));
+        }
+
+        let mut tx = db.begin().await.map_err(AppError::Database)?;
+
+        let template = sqlx::query_as::<_, CompilationTemplate>(
+            "SELECT * FROM compilation_templates WHERE id = $1 AND is_public = true FOR UPDATE"
+        )
+        .bind(template_id)
+        .fetch_optional(&mut *tx)
+        .await
+        .map_err(AppError::Database)?
+        .ok_or_else(|| AppError::NotFound {
+            entity: "CompilationTemplate".to_string(),
+            id: template_id.to_string(),
+        })?;
+
+        if template.created_by == user_id {
+            return Err(AppError::Authorization("Authors cannot rate their own templates".to_string()));
+        }
+
+        sqlx::query(
+            r#"
+            INSERT INTO compilation_template_ratings (template_id, user_id, rating)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (template_id, user_id)
+            DO UPDATE SET rating = EXCLUDED.rating, updated_at = NOW()
+            "#
+        )
+        .bind(template_id)
+        .bind(user_id)
+        .bind(rating)
+        .execute(&mut *tx)
+        .await
+        .map_err(AppError::Database)?;
+
+        let template = sqlx::query_as::<_, CompilationTemplate>(
+            r#"
+            UPDATE compilation_templates t
+            SET rating_count = ratings.count, rating_average = ratings.average
+            FROM (
+                SELECT COUNT(*) AS count, COALESCE(AVG(rating), 0)::DOUBLE PRECISION AS average
+                FROM compilation_template_ratings
+                WHERE template_id = $1
+            ) ratings
+            WHERE t.id = $1
+            RETURNING t.*
+            "#
+        )
+        .bind(template_id)
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(AppError::Database)?;
+
+        tx.commit().await.map_err(AppError::Database)?;
+
+        Ok(template)
+    }
+
+    /// Copy a template into the user's ownership as a private template
+    ///
+    /// Returns the fork and whether it was created now; a user who already
+    /// forked the template gets their existing fork back.
+    pub async fn fork(
+        db: &sqlx::PgPool,
+        template_id: Uuid,
+        user_id: Uuid,
+    ) -> Result<(Self, bool), AppError> {
+        let source = Self::find_visible(db, template_id, user_id).await?;
+
+        let forked = sqlx::query_as::<_, CompilationTemplate>(
+            r#"
+            INSERT INTO compilation_templates (
+                name, description, engine, command_template, default_args,
+                required_files, output_patterns, is_public, created_by, forked_from,
+                source_project_id, variables
+            )
+            SELECT name, description, engine, command_template, default_args,
+                   required_files, output_patterns, false, $2, id,
+                   source_project_id, variables
+            FROM compilation_templates
+            WHERE id = $1
+            ON CONFLICT (created_by, forked_from) WHERE forked_from IS NOT NULL DO NOTHING
+            RETURNING *
+            "#
+        )
+        .bind(source.id)
+        .bind(user_id)
+        .fetch_optional(db)
+        .await
+        .map_err(AppError::Database)?;
+
+        if let Some(forked) = forked {
+            return Ok((forked, true));
+        }
+
+        let existing = sqlx::query_as::<_, CompilationTemplate>(
+            "SELECT * FROM compilation_templates WHERE created_by = $1 AND forked_from = $2"
+        )
+        .bind(user_id)
+        .bind(source.id)
+        .fetch_one(db)
+        .await
+        .map_err(AppError::Database)?;
+
+        Ok((existing, false))
+    }
+
+    /// Mark a template as officially maintained, or withdraw the mark
+    pub async fn set_verified(
+        db: &sqlx::PgPool,
+        template_id: Uuid,
+        verified: bool,
+    ) -> Result<Self, AppError> {
+        sqlx::query_as::<_, CompilationTemplate>(
+            "UPDATE compilation_templates SET verified = $2, updated_at = NOW() WHERE id = $1 RETURNING *"
+        )
+        .bind(template_id)
+        .bind(verified)
+        .fetch_optional(db)
+        .await
+        .map_err(AppError::Database)?
+        .ok_or_else(|| AppError::NotFound {
+            entity: "CompilationTemplate".to_string(),
+            id: template_id.to_string(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::compilation::CreateCompilationTemplate;
+    use crate::testing::{create_test_user, TestDb};
+
+    fn first_page() -> PaginationParams {
+        PaginationParams {
+            page: None,
+            limit: Some(100),
+            offset: None,
+            sort_by: None,
+            sort_order: None,
+        }
+    }
+
+    async fn create_template(db: &sqlx::PgPool, owner: Uuid, name: &str, is_public: bool) -> CompilationTemplate {
+        CompilationTemplate::create(
+            db,
+            owner,
+            CreateCompilationTemplate {
+                name: name.to_string(),
+                description: Some("Builds with latexmk".to_string()),
+                engine: LatexEngine::Lualatex,
+                command_template: "latexmk -lualatex {main}".to_string(),
+                default_args: Some(vec!["-interaction=nonstopmode".to_string()]),
+                required_files: None,
+                output_patterns: None,
+                is_public: Some(is_public),
+                source_project_id: None,
+                variables: None,
+            },
+        )
+        .await
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_rerating_replaces_the_earlier_rating() {
+        let Some(db) = TestDb::start().await else { return };
+        let author = create_test_user(&db.pool).await;
+        let first = create_test_user(&db.pool).await;
+        let second = create_test_user(&db.pool).await;
+        let template = create_template(&db.pool, author.id, "Rated", true).await;
+
+        CompilationTemplate::rate(&db.pool, template.id, first.id, 2).await.unwrap();
+        CompilationTemplate::rate(&db.pool, template.id, second.id, 4).await.unwrap();
+        let rated = CompilationTemplate::rate(&db.pool, template.id, first.id, 5).await.unwrap();
+        assert_eq!(rated.rating_count, 2);
+        assert_eq!(rated.rating_average, 4.5);
+
+        assert!(matches!(
+            CompilationTemplate::rate(&db.pool, template.id, first.id, 6).await,
+            Err(AppError::Validation(_))
+        ));
+        assert!(matches!(
+            CompilationTemplate::rate(&db.pool, template.id, author.id, 5).await,
+            Err(AppError::Authorization(_))
+        ));
+
+        let private = create_template(&db.pool, author.id, "Private", false).await;
+        assert!(matches!(
+            CompilationTemplate::rate(&db.pool, private.id, first.id, 5).await,
+            Err(AppError::NotFound { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_forking_is_idempotent_and_private() {
+        let Some(db) = TestDb::start().await else { return };
+        let author = create_test_user(&db.pool).await;
+        let user = create_test_user(&db.pool).await;
+        let template = create_template(&db.pool, author.id, "Forkable", true).await;
+
+        let (fork, created) = CompilationTemplate::fork(&db.pool, template.id, user.id).await.unwrap();
+        assert!(created);
+        assert_eq!(fork.created_by, user.id);
+        assert_eq!(fork.forked_from, Some(template.id));
+        assert_eq!(fork.command_template, template.command_template);
+        assert!(!fork.is_public);
+
+        let (again, created) = CompilationTemplate::fork(&db.pool, template.id, user.id).await.unwrap();
+        assert!(!created);
+        assert_eq!(again.id, fork.id);
+
+        // The fork is private, so nobody else can fork it
+        let other = create_test_user(&db.pool).await;
+        assert!(matches!(
+            CompilationTemplate::fork(&db.pool, fork.id, other.id).await,
+            Err(AppError::NotFound { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_listing_searches_and_puts_verified_first() {
+        let Some(db) = TestDb::start().await else { return };
+        let author = create_test_user(&db.pool).await;
+        let viewer = create_test_user(&db.pool).await;
+        let marker = Uuid::new_v4().simple().to_string();
+        let plain = create_template(&db.pool, author.id, &format!("{} plain", marker), true).await;
+        let verified = create_template(&db.pool, author.id, &format!("{} official", marker), true).await;
+        create_template(&db.pool, author.id, &format!("{} hidden", marker), false).await;
+        CompilationTemplate::set_verified(&db.pool, verified.id, true).await.unwrap();
+        CompilationTemplate::rate(&db.pool, plain.id, viewer.id, 3).await.unwrap();
+
+        let search = TemplateSearchParams {
+            query: Some(marker.to_uppercase()),
+            engine: Some(LatexEngine::Lualatex),
+        };
+        let (listed, total) = CompilationTemplate::list_public(&db.pool, viewer.id, &search, &first_page())
+            .await
+            .unwrap();
+        assert_eq!(total, 2);
+        assert_eq!(listed.iter().map(|l| l.template.id).collect::<Vec<_>>(), vec![verified.id, plain.id]);
+        assert_eq!(listed[0].author.username, author.username);
+        assert_eq!(listed[1].my_rating, Some(3));
+        assert_eq!(listed[1].template.rating_count, 1);
+
+        let other_engine = TemplateSearchParams {
+            engine: Some(LatexEngine::Xelatex),
+            ..search
+        };
+        let (_, total) = CompilationTemplate::list_public(&db.pool, viewer.id, &other_engine, &first_page())
+            .await
+            .unwrap();
+        assert_eq!(total, 0);
+    }
+}
@@ -0,0 +1,136 @@
+//! Time zones of users and date-filtered requests
+//!
+//! Timestamps are stored and compared in UTC. Where a request or a schedule
+//! is about calendar days, they are the days of a time zone: the request's
+//! `tz` parameter, or the zone in the user's preferences, UTC by default. Day
+//! boundaries always come from the zone's rules and never from adding 24
+//! hours, because the days on which daylight saving time starts or ends last
+//! 23 or 25 hours.
+
+use chrono::{DateTime, Duration as ChronoDuration, NaiveDate, NaiveTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use serde::Deserialize;
+use utoipa::IntoParams;
+use uuid::Uuid;
+
+use crate::error::AppError;
+
+/// Time zone of users who have not chosen one
+pub const DEFAULT_TIME_ZONE: &str = "UTC";
+
+/// Time zone parameters of date-filtered endpoints
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct TimeZoneParams {
+    /// IANA time zone, such as `Europe/Berlin`, whose calendar days the period
+    /// is counted in; defaults to UTC
+    pub tz: Option<String>,
+}
+
+impl TimeZoneParams {
+    pub fn time_zone(&self) -> Result<Tz, AppError> {
+        self.tz.as_deref().map_or(Ok(Tz::UTC), parse)
+    }
+}
+
+/// Parse an IANA time zone name
+pub fn parse(name: &str) -> Result<Tz, AppError> {
+    name.trim().parse::<Tz>().map_err(|_| {
+        AppError::Validation(format!(
+            "Unknown time zone '{}': expected an IANA time zone name such as 'Europe/Berlin', 'America/New_York' or 'UTC'",
+            name
+        ))
+    })
+}
+
+/// Time zone a user chose in their preferences
+///
+/// A stored name this build does not know falls back to UTC rather than
+/// failing whatever needed it.
+pub async fn for_user(db: &sqlx::PgPool, user_id: Uuid) -> Result<Tz, AppError> {
+    let name = sqlx::query_scalar::<_, String>("SELECT timezone FROM user_preferences WHERE user_id = $1")
+        .bind(user_id)
+        .fetch_optional(db)
+        .await
+        .map_err(AppError::Database)?;
+
+    Ok(name.and_then(|name| name.parse().ok()).unwrap_or(Tz::UTC))
+}
+
+/// Calendar date in `tz` at `instant`
+pub fn local_date(tz: Tz, instant: DateTime<Utc>) -> NaiveDate {
+    instant.with_timezone(&tz).date_naive()
+}
+
+/// First instant of `day` in `tz`
+///
+/// Where a daylight saving change skips midnight, the day starts when the
+/// clocks resume.
+pub fn start_of_day(tz: Tz, day: NaiveDate) -> DateTime<Utc> {
+    let mut time = day.and_time(NaiveTime::MIN);
+    loop {
+        if let Some(start) = tz.from_local_datetime(&time).earliest() {
+            return start.with_timezone(&Utc);
+        }
+        time += ChronoDuration::minutes(15);
+    }
+}
+
+/// Start of the `days` calendar days in `tz` that end with the one `now`
+/// falls in
+pub fn start_of_days_until(tz: Tz, now: DateTime<Utc>, days: i64) -> DateTime<Utc> {
+    start_of_day(tz, local_date(tz, now) - ChronoDuration::days(days.max(1) - 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(s: &str) -> NaiveDate {
+        s.parse().unwrap()
+    }
+
+    fn hours_in(tz: Tz, day: &str) -> i64 {
+        let day = date(day);
+        (start_of_day(tz, day.succ_opt().unwrap()) - start_of_day(tz, day)).num_hours()
+    }
+
+    #[test]
+    fn test_days_around_daylight_saving_changes() {
+        let berlin: Tz = parse("Europe/Berlin").unwrap();
+        assert_eq!(hours_in(berlin, "2030-03-31"), 23);
+        assert_eq!(hours_in(berlin, "2030-10-27"), 25);
+        assert_eq!(hours_in(berlin, "2030-10-28"), 24);
+        assert_eq!(start_of_day(berlin, date("2030-10-27")), Utc.with_ymd_and_hms(2030, 10, 26, 22, 0, 0).unwrap());
+
+        let new_york: Tz = parse("America/New_York").unwrap();
+        assert_eq!(hours_in(new_york, "2030-03-10"), 23);
+        assert_eq!(hours_in(new_york, "2030-11-03"), 25);
+        assert_eq!(hours_in(Tz::UTC, "2030-03-31"), 24);
+
+        // Santiago skips from midnight to one in the morning
+        let santiago: Tz = parse("America/Santiago").unwrap();
+        assert_eq!(start_of_day(santiago, date("2030-09-08")), Utc.with_ymd_and_hms(2030, 9, 8, 4, 0, 0).unwrap());
+    }
+
+    #[test]
+    fn test_days_until_now_follow_local_dates() {
+        let tokyo: Tz = parse("Asia/Tokyo").unwrap();
+        // Already the 15th in Tokyo, still the 14th in UTC
+        let now = Utc.with_ymd_and_hms(2030, 1, 14, 16, 0, 0).unwrap();
+        assert_eq!(start_of_days_until(tokyo, now, 1), Utc.with_ymd_and_hms(2030, 1, 14, 15, 0, 0).unwrap());
+        assert_eq!(start_of_days_until(Tz::UTC, now, 1), Utc.with_ymd_and_hms(2030, 1, 14, 0, 0, 0).unwrap());
+        assert_eq!(start_of_days_until(tokyo, now, 7), Utc.with_ymd_and_hms(2030, 1, 8, 15, 0, 0).unwrap());
+    }
+
+    #[test]
+    fn test_unknown_time_zones_are_rejected() {
+        assert_eq!(parse(" Europe/Berlin ").unwrap(), chrono_tz::Europe::Berlin);
+        for name in ["Mars/Olympus_Mons", "+02:00", "CEST", ""] {
+            match parse(name) {
+                Err(AppError::Validation(message)) => assert!(message.contains("IANA time zone name")),
+                other => panic!("{:?} parsed as {:?}", name, other),
+            }
+        }
+    }
+}
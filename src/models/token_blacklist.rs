@@ -81,18 +81,24 @@ impl BlacklistedToken {
         Ok(count > 0)
     }
 
-    /// Check if any tokens are blacklisted for a user
-    pub async fn has_blacklisted_tokens(
+    /// Check if the user's sessions were revoked after a token was issued
+    ///
+    /// Token timestamps have whole seconds, so a revocation counts from the
+    /// start of its second and tokens issued later in that second stay valid.
+    pub async fn revoked_since(
         db: &sqlx::PgPool,
         user_id: Uuid,
+        issued_at: i64,
     ) -> Result<bool, crate::error::AppError> {
         let count = sqlx::query_scalar::<_, i64>(
             r#"
             SELECT COUNT(*) FROM blacklisted_tokens
-            WHERE user_id = $1 AND expires_at > NOW()
+            WHERE user_id = $1 AND token_type = 'all_tokens' AND expires_at > NOW()
+              AND date_trunc('second', blacklisted_at) > to_timestamp($2)
             "#
         )
         .bind(user_id)
+        .bind(issued_at as f64)
         .fetch_one(db)
         .await
         .map_err(crate::error::AppError::Database)?;
@@ -100,26 +106,16 @@ impl BlacklistedToken {
         Ok(count > 0)
     }
 
-    /// Blacklist all tokens for a user
+    /// Revoke every token issued to a user so far
     pub async fn blacklist_all_for_user(
-        db: &sqlx::PgPool,
+        db: impl sqlx::PgExecutor<'_>,
         user_id: Uuid,
         reason: String,
     ) -> Result<u64, crate::error::AppError> {
         let result = sqlx::query(
             r#"
             INSERT INTO blacklisted_tokens (jti, token_type, user_id, expires_at, blacklisted_at, reason)
-            SELECT
-                gen_random_uuid()::text as jti,
-                'all_tokens' as token_type,
-                $1 as user_id,
-                NOW() + INTERVAL '7 days' as expires_at,
-                NOW() as blacklisted_at,
-                $2 as reason
-            WHERE NOT EXISTS (
-                SELECT 1 FROM blacklisted_tokens
-                WHERE user_id = $1 AND token_type = 'all_tokens' AND expires_at > NOW()
-            )
+            VALUES (gen_random_uuid()::text, 'all_tokens', $1, NOW() + INTERVAL '7 days', NOW(), $2)
             "#
         )
         .bind(user_id)
@@ -179,14 +175,15 @@ impl TokenBlacklistService {
         db: &sqlx::PgPool,
         jti: &str,
         user_id: Uuid,
+        issued_at: i64,
     ) -> Result<bool, crate::error::AppError> {
         // Check if specific token is blacklisted
         if BlacklistedToken::is_blacklisted(db, jti).await? {
             return Ok(true);
         }
 
-        // Check if all user tokens are blacklisted
-        if BlacklistedToken::has_blacklisted_tokens(db, user_id).await? {
+        // Check if the user's sessions were revoked since the token was issued
+        if BlacklistedToken::revoked_since(db, user_id, issued_at).await? {
             return Ok(true);
         }
 
@@ -214,7 +211,7 @@ impl TokenBlacklistService {
         Ok(())
     }
 
-    /// Blacklist all tokens for user (full logout)
+    /// Blacklist all tokens issued to a user so far (full logout)
     pub async fn blacklist_all_user_tokens(
         db: &sqlx::PgPool,
         user_id: Uuid,
@@ -0,0 +1,139 @@
+//! Typst documents
+//!
+//! Typst files (`.typ`) can live in a project next to LaTeX ones. The compile
+//! target decides which toolchain runs: a `.typ` target always compiles with
+//! `typst compile`, anything else with the requested LaTeX engine, so one
+//! project can hold documents of both kinds.
+//!
+//! Typst only produces PDF and takes none of the LaTeX engine options, so
+//! custom arguments are not passed to it. It reports diagnostics on stderr;
+//! jobs ask for the `short` format, one `file:line:column: severity: message`
+//! line per diagnostic, which reads like LaTeX's `-file-line-error` output.
+
+use std::path::Path;
+
+use super::LatexEngine;
+use crate::error::AppError;
+
+/// Extension of Typst sources
+pub const TYPST_EXTENSION: &str = "typ";
+
+/// The only output format Typst produces
+pub const TYPST_OUTPUT_FORMAT: &str = "pdf";
+
+/// Whether `path` is compiled with Typst
+pub fn is_typst_target(path: &str) -> bool {
+    Path::new(path).extension().and_then(|ext| ext.to_str()) == Some(TYPST_EXTENSION)
+}
+
+impl LatexEngine {
+    pub fn is_typst(self) -> bool {
+        self == Self::Typst
+    }
+
+    /// Engine that compiles `target`; a LaTeX target asked to compile with
+    /// Typst falls back to the default LaTeX engine
+    pub fn for_target(self, target: &str) -> Self {
+        match (is_typst_target(target), self) {
+            (true, _) => Self::Typst,
+            (false, Self::Typst) => Self::default(),
+            (false, engine) => engine,
+        }
+    }
+}
+
+/// Arguments of `typst compile` for `target`, writing the PDF to `output/`
+pub fn compile_args(target: &str) -> Vec<String> {
+    let target = target.trim_start_matches('/');
+    let stem = Path::new(target)
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .unwrap_or("main");
+
+    vec![
+        "compile".to_string(),
+        "--root".to_string(),
+        ".".to_string(),
+        "--diagnostic-format".to_string(),
+        "short".to_string(),
+        target.to_string(),
+        format!("output/{}.pdf", stem),
+    ]
+}
+
+/// Check that `engine` can produce `output_format`
+pub fn check_output_format(engine: LatexEngine, output_format: &str) -> Result<(), AppError> {
+    if engine.is_typst() && output_format != TYPST_OUTPUT_FORMAT {
+        return Err(AppError::Validation(format!(
+            "Typst only produces {} output, not '{}'",
+            TYPST_OUTPUT_FORMAT, output_format
+        )));
+    }
+    Ok(())
+}
+
+/// Check that a template written for `template_engine` can compile with `engine`
+pub fn check_template_engine(template_engine: LatexEngine, engine: LatexEngine) -> Result<(), AppError> {
+    if template_engine.is_typst() != engine.is_typst() {
+        return Err(AppError::Validation(format!(
+            "A {} template cannot compile a {} document",
+            template_engine.command(),
+            engine.command()
+        )));
+    }
+    Ok(())
+}
+
+/// First errors, then warnings, from Typst's short diagnostics
+pub fn diagnostics(output: &str) -> Vec<String> {
+    let lines = || output.lines().map(str::trim);
+    let errors = lines().filter(|line| line.starts_with("error: ") || line.contains(": error: "));
+    let warnings = lines().filter(|line| line.starts_with("warning: ") || line.contains(": warning: "));
+
+    errors.chain(warnings).map(str::to_string).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_target_decides_toolchain() {
+        assert_eq!(LatexEngine::Xelatex.for_target("paper.typ"), LatexEngine::Typst);
+        assert_eq!(LatexEngine::Xelatex.for_target("main.tex"), LatexEngine::Xelatex);
+        assert_eq!(LatexEngine::Typst.for_target("main.tex"), LatexEngine::Pdflatex);
+
+        assert_eq!(
+            compile_args("/slides/talk.typ"),
+            vec!["compile", "--root", ".", "--diagnostic-format", "short", "slides/talk.typ", "output/talk.pdf"]
+        );
+
+        assert!(check_output_format(LatexEngine::Typst, "pdf").is_ok());
+        assert!(matches!(check_output_format(LatexEngine::Typst, "dvi"), Err(AppError::Validation(_))));
+        assert!(check_output_format(LatexEngine::Pdflatex, "dvi").is_ok());
+
+        assert!(check_template_engine(LatexEngine::Xelatex, LatexEngine::Pdflatex).is_ok());
+        assert!(matches!(
+            check_template_engine(LatexEngine::Pdflatex, LatexEngine::Typst),
+            Err(AppError::Validation(_))
+        ));
+    }
+
+    #[test]
+    fn test_typst_diagnostics() {
+        let stderr = "main.typ:4:12: warning: unknown font family: libertine\n\
+                      main.typ:9:2: error: unknown variable: tabel\n\
+                      hint: if you meant to use subtraction, try adding spaces around the minus sign\n\
+                      error: file not found (searched at refs.bib)\n";
+
+        assert_eq!(
+            diagnostics(stderr),
+            vec![
+                "main.typ:9:2: error: unknown variable: tabel",
+                "error: file not found (searched at refs.bib)",
+                "main.typ:4:12: warning: unknown font family: libertine",
+            ]
+        );
+        assert!(diagnostics("").is_empty());
+    }
+}
@@ -0,0 +1,739 @@
+//! Per-user undo and redo in collaboration sessions
+//!
+//! Every text change a participant makes is kept, inverted, on their own
+//! undo stack. Whenever anyone changes a file, the pending entries for that
+//! file are transformed against the change, so they always apply to the
+//! current text: undoing an insert removes only the part nobody else deleted
+//! yet, and text typed by others inside it is left alone. Entries are
+//! batches of pieces at distinct positions of one document state, applied
+//! highest position first so no piece moves another.
+//!
+//! Positions and lengths count characters. Deletes can only be undone when
+//! the client sent the removed text as the operation's content.
+//!
+//! Entries changed by edits of other participants are reported as
+//! [`Contention`], for conflict telemetry: once when an edit leaves nothing
+//! of one, and when one that edits of others moved is reverted.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use uuid::Uuid;
+
+use super::collaboration::OperationType;
+
+/// Undo entries kept per participant
+pub const UNDO_DEPTH: usize = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EditKind {
+    Insert,
+    Delete,
+}
+
+/// A single insertion or deletion, ready to be applied and broadcast
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextEdit {
+    pub kind: EditKind,
+    pub position: usize,
+    pub text: String,
+}
+
+impl TextEdit {
+    pub fn operation_type(&self) -> OperationType {
+        match self.kind {
+            EditKind::Insert => OperationType::Insert,
+            EditKind::Delete => OperationType::Delete,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.text.chars().count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.text.is_empty()
+    }
+
+    fn change(&self, file_id: Option<Uuid>) -> Change {
+        match self.kind {
+            EditKind::Insert => Change {
+                file_id,
+                position: self.position,
+                removed: 0,
+                removed_text: Some(String::new()),
+                inserted: self.text.clone(),
+            },
+            EditKind::Delete => Change {
+                file_id,
+                position: self.position,
+                removed: self.len(),
+                removed_text: Some(self.text.clone()),
+                inserted: String::new(),
+            },
+        }
+    }
+}
+
+/// A change to a file's text as the server applied it: `removed` characters
+/// at `position` replaced by `inserted`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Change {
+    pub file_id: Option<Uuid>,
+    pub position: usize,
+    pub removed: usize,
+    /// The removed text, when the client sent it
+    pub removed_text: Option<String>,
+    pub inserted: String,
+}
+
+impl Change {
+    /// The text change an operation makes; `None` for operations that leave the text alone
+    pub fn from_operation(
+        operation_type: OperationType,
+        position: Option<i32>,
+        content: Option<&str>,
+        length: Option<i32>,
+        file_id: Option<Uuid>,
+    ) -> Option<Self> {
+        let position = usize::try_from(position?).ok()?;
+        let content = content.unwrap_or_default();
+
+        match operation_type {
+            OperationType::Insert => Some(Self {
+                file_id,
+                position,
+                removed: 0,
+                removed_text: Some(String::new()),
+                inserted: content.to_string(),
+            }),
+            OperationType::Delete => {
+                let count = content.chars().count();
+                let removed = length.and_then(|length| usize::try_from(length).ok()).unwrap_or(count);
+                Some(Self {
+                    file_id,
+                    position,
+                    removed,
+                    removed_text: (count == removed).then(|| content.to_string()),
+                    inserted: String::new(),
+                })
+            }
+            OperationType::Replace => Some(Self {
+                file_id,
+                position,
+                removed: usize::try_from(length?).ok()?,
+                removed_text: None,
+                inserted: content.to_string(),
+            }),
+            OperationType::Format | OperationType::Cursor | OperationType::Selection => None,
+        }
+    }
+
+    /// The batch that reverts this change, if it can be reverted
+    fn inverse(&self) -> Option<EditBatch> {
+        let removed_text = self.removed_text.as_ref()?;
+        let batch = match (removed_text.is_empty(), self.inserted.is_empty()) {
+            (true, false) => EditBatch {
+                kind: EditKind::Delete,
+                pieces: vec![(self.position, self.inserted.clone())],
+            },
+            (false, true) => EditBatch {
+                kind: EditKind::Insert,
+                pieces: vec![(self.position, removed_text.clone())],
+            },
+            _ => return None,
+        };
+        Some(batch)
+    }
+}
+
+fn char_len(text: &str) -> usize {
+    text.chars().count()
+}
+
+/// Split at a character offset
+fn split_chars(text: &str, at: usize) -> (String, String) {
+    let index = text.char_indices().nth(at).map_or(text.len(), |(index, _)| index);
+    (text[..index].to_string(), text[index..].to_string())
+}
+
+/// Edits of one kind at distinct positions of the same document state,
+/// ordered by position
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct EditBatch {
+    kind: EditKind,
+    pieces: Vec<(usize, String)>,
+}
+
+impl EditBatch {
+    fn is_empty(&self) -> bool {
+        self.pieces.is_empty()
+    }
+
+    /// Move the batch past a change applied after it was recorded
+    fn transform(&mut self, change: &Change) {
+        if change.removed > 0 {
+            self.transform_delete(change.position, change.removed);
+        }
+        if !change.inserted.is_empty() {
+            self.transform_insert(change.position, char_len(&change.inserted));
+        }
+        self.normalize();
+    }
+
+    fn transform_insert(&mut self, at: usize, len: usize) {
+        let mut pieces = Vec::with_capacity(self.pieces.len());
+        for (start, text) in self.pieces.drain(..) {
+            let end = start + char_len(&text);
+            if at <= start {
+                pieces.push((start + len, text));
+            } else if self.kind == EditKind::Delete && at < end {
+                // Keep the inserted text out of the range to delete
+                let (before, after) = split_chars(&text, at - start);
+                pieces.push((start, before));
+                pieces.push((at + len, after));
+            } else {
+                pieces.push((start, text));
+            }
+        }
+        self.pieces = pieces;
+    }
+
+    fn transform_delete(&mut self, at: usize, len: usize) {
+        let deleted_end = at + len;
+        let map = |position: usize| {
+            if position <= at {
+                position
+            } else if position <= deleted_end {
+                at
+            } else {
+                position - len
+            }
+        };
+
+        for (start, text) in &mut self.pieces {
+            if self.kind == EditKind::Delete {
+                // Only what is left of the range can still be deleted
+                let end = *start + char_len(text);
+                let (kept_before, rest) = split_chars(text, at.clamp(*start, end) - *start);
+                let (_, kept_after) = split_chars(&rest, deleted_end.clamp(*start, end) - at.clamp(*start, end));
+                *text = kept_before + &kept_after;
+            }
+            *start = map(*start);
+        }
+    }
+
+    /// Drop empty pieces and merge the ones that now touch
+    fn normalize(&mut self) {
+        let mut pieces: Vec<(usize, String)> = Vec::with_capacity(self.pieces.len());
+        for (start, text) in self.pieces.drain(..) {
+            if text.is_empty() {
+                continue;
+            }
+            if let Some((last_start, last_text)) = pieces.last_mut() {
+                let touches = match self.kind {
+                    EditKind::Insert => *last_start == start,
+                    EditKind::Delete => *last_start + char_len(last_text) == start,
+                };
+                if touches {
+                    last_text.push_str(&text);
+                    continue;
+                }
+            }
+            pieces.push((start, text));
+        }
+        self.pieces = pieces;
+    }
+
+    /// The batch that reverts this one once it has been applied
+    fn inverse(&self) -> EditBatch {
+        let mut offset = 0;
+        let pieces = self
+            .pieces
+            .iter()
+            .map(|(start, text)| {
+                let position = match self.kind {
+                    EditKind::Insert => start + offset,
+                    EditKind::Delete => start - offset,
+                };
+                offset += char_len(text);
+                (position, text.clone())
+            })
+            .collect();
+
+        EditBatch {
+            kind: match self.kind {
+                EditKind::Insert => EditKind::Delete,
+                EditKind::Delete => EditKind::Insert,
+            },
+            pieces,
+        }
+    }
+
+    /// The pieces as edits to apply one after another, highest position first
+    fn edits(&self) -> Vec<TextEdit> {
+        self.pieces
+            .iter()
+            .rev()
+            .map(|(position, text)| TextEdit {
+                kind: self.kind,
+                position: *position,
+                text: text.clone(),
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone)]
+struct HistoryEntry {
+    file_id: Option<Uuid>,
+    batch: EditBatch,
+    /// Other participants whose edits transformed the entry
+    merged_with: Vec<Uuid>,
+}
+
+impl HistoryEntry {
+    fn new(file_id: Option<Uuid>, batch: EditBatch) -> Self {
+        Self { file_id, batch, merged_with: Vec::new() }
+    }
+
+    fn contention(&self, owner: Uuid, discarded: bool) -> Contention {
+        Contention {
+            file_id: self.file_id,
+            users: std::iter::once(owner).chain(self.merged_with.iter().copied()).collect(),
+            discarded,
+        }
+    }
+}
+
+/// An entry of a participant that edits of others transformed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Contention {
+    pub file_id: Option<Uuid>,
+    /// The entry's owner, then the participants whose edits transformed it
+    pub users: Vec<Uuid>,
+    /// The edits left nothing of it to revert
+    pub discarded: bool,
+}
+
+#[derive(Debug, Default)]
+struct UserHistory {
+    undo: VecDeque<HistoryEntry>,
+    redo: Vec<HistoryEntry>,
+}
+
+impl UserHistory {
+    fn push_undo(&mut self, entry: HistoryEntry) {
+        if self.undo.len() == UNDO_DEPTH {
+            self.undo.pop_front();
+        }
+        self.undo.push_back(entry);
+    }
+
+    fn entries_mut(&mut self) -> impl Iterator<Item = &mut HistoryEntry> {
+        self.undo.iter_mut().chain(self.redo.iter_mut())
+    }
+}
+
+/// Edits to apply for an undo or redo
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Reverted {
+    pub file_id: Option<Uuid>,
+    /// In the order they have to be applied
+    pub edits: Vec<TextEdit>,
+    /// The reverted entry if others' edits transformed it, and the entries
+    /// of others the edits leave nothing of
+    pub contention: Vec<Contention>,
+}
+
+/// Undo and redo stacks of every participant, by session
+#[derive(Debug, Default)]
+pub struct UndoHistory {
+    sessions: Mutex<HashMap<Uuid, HashMap<Uuid, UserHistory>>>,
+}
+
+/// Transform every pending entry for the changed file; returns the entries
+/// of others that `author`'s change left nothing of
+fn transform_all(users: &mut HashMap<Uuid, UserHistory>, change: &Change, author: Uuid) -> Vec<Contention> {
+    let mut discarded = Vec::new();
+    for (owner, history) in users.iter_mut() {
+        for entry in history.entries_mut().filter(|entry| entry.file_id == change.file_id) {
+            if *owner == author || entry.batch.is_empty() {
+                entry.batch.transform(change);
+                continue;
+            }
+
+            let before = entry.batch.clone();
+            entry.batch.transform(change);
+            if entry.batch != before {
+                if !entry.merged_with.contains(&author) {
+                    entry.merged_with.push(author);
+                }
+                if entry.batch.is_empty() {
+                    discarded.push(entry.contention(*owner, true));
+                }
+            }
+        }
+    }
+    discarded
+}
+
+impl UndoHistory {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Start a participant off with empty stacks; nothing from before can be undone
+    pub fn join(&self, session_id: Uuid, user_id: Uuid) {
+        let mut sessions = self.sessions.lock().unwrap_or_else(|e| e.into_inner());
+        sessions.entry(session_id).or_default().insert(user_id, UserHistory::default());
+    }
+
+    pub fn leave(&self, session_id: Uuid, user_id: Uuid) {
+        let mut sessions = self.sessions.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(users) = sessions.get_mut(&session_id) {
+            users.remove(&user_id);
+            if users.is_empty() {
+                sessions.remove(&session_id);
+            }
+        }
+    }
+
+    /// Record a change a participant made, clearing their redo stack;
+    /// returns the entries of others it left nothing of
+    pub fn record(&self, session_id: Uuid, user_id: Uuid, change: &Change) -> Vec<Contention> {
+        let mut sessions = self.sessions.lock().unwrap_or_else(|e| e.into_inner());
+        let users = sessions.entry(session_id).or_default();
+        let discarded = transform_all(users, change, user_id);
+
+        let history = users.entry(user_id).or_default();
+        history.redo.clear();
+        if let Some(batch) = change.inverse() {
+            history.push_undo(HistoryEntry::new(change.file_id, batch));
+        }
+        discarded
+    }
+
+    /// Revert the participant's latest change that still has an effect
+    pub fn undo(&self, session_id: Uuid, user_id: Uuid) -> Option<Reverted> {
+        self.revert(session_id, user_id, false)
+    }
+
+    /// Reapply the participant's latest undone change
+    pub fn redo(&self, session_id: Uuid, user_id: Uuid) -> Option<Reverted> {
+        self.revert(session_id, user_id, true)
+    }
+
+    fn revert(&self, session_id: Uuid, user_id: Uuid, redo: bool) -> Option<Reverted> {
+        let mut sessions = self.sessions.lock().unwrap_or_else(|e| e.into_inner());
+        let users = sessions.get_mut(&session_id)?;
+
+        // Entries whose text others removed entirely have nothing left to revert
+        let entry = {
+            let history = users.get_mut(&user_id)?;
+            loop {
+                let entry = if redo { history.redo.pop()? } else { history.undo.pop_back()? };
+                if !entry.batch.is_empty() {
+                    break entry;
+                }
+            }
+        };
+
+        let mut contention = Vec::new();
+        if !entry.merged_with.is_empty() {
+            contention.push(entry.contention(user_id, false));
+        }
+        let edits = entry.batch.edits();
+        for edit in &edits {
+            contention.extend(transform_all(users, &edit.change(entry.file_id), user_id));
+        }
+
+        let inverse = HistoryEntry::new(entry.file_id, entry.batch.inverse());
+        let history = users.entry(user_id).or_default();
+        if redo {
+            history.push_undo(inverse);
+        } else {
+            history.redo.push(inverse);
+        }
+
+        Some(Reverted { file_id: entry.file_id, edits, contention })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A client's copy of one file
+    #[derive(Debug, Default, Clone, PartialEq)]
+    struct Document(Vec<char>);
+
+    impl Document {
+        /// Apply a change, checking it still fits the text
+        fn apply(&mut self, change: &Change) {
+            let end = change.position + change.removed;
+            assert!(end <= self.0.len(), "{:?} is out of range of {:?}", change, self.text());
+            if let Some(removed_text) = &change.removed_text {
+                let actual: String = self.0[change.position..end].iter().collect();
+                assert_eq!(&actual, removed_text, "{:?} deletes the wrong text", change);
+            }
+            self.0.splice(change.position..end, change.inserted.chars());
+        }
+
+        fn text(&self) -> String {
+            self.0.iter().collect()
+        }
+    }
+
+    /// Server plus clients, each receiving every broadcast change in order
+    struct Simulation {
+        session_id: Uuid,
+        history: UndoHistory,
+        clients: Vec<Document>,
+        /// Reported by changes, undos and redos since last taken
+        contention: Vec<Contention>,
+    }
+
+    impl Simulation {
+        fn new(users: &[Uuid]) -> Self {
+            let session_id = Uuid::new_v4();
+            let history = UndoHistory::new();
+            for user in users {
+                history.join(session_id, *user);
+            }
+            Self { session_id, history, clients: vec![Document::default(); 3], contention: Vec::new() }
+        }
+
+        fn broadcast(&mut self, change: &Change) {
+            for client in &mut self.clients {
+                client.apply(change);
+            }
+        }
+
+        fn insert(&mut self, user: Uuid, position: i32, text: &str) {
+            let change = Change::from_operation(OperationType::Insert, Some(position), Some(text), None, None).unwrap();
+            self.contention.extend(self.history.record(self.session_id, user, &change));
+            self.broadcast(&change);
+        }
+
+        fn delete(&mut self, user: Uuid, position: usize, length: usize) {
+            let removed: String = self.clients[0].0[position..position + length].iter().collect();
+            let change = Change::from_operation(
+                OperationType::Delete,
+                Some(position as i32),
+                Some(&removed),
+                Some(length as i32),
+                None,
+            )
+            .unwrap();
+            self.contention.extend(self.history.record(self.session_id, user, &change));
+            self.broadcast(&change);
+        }
+
+        fn revert(&mut self, user: Uuid, redo: bool) -> bool {
+            let reverted = if redo {
+                self.history.redo(self.session_id, user)
+            } else {
+                self.history.undo(self.session_id, user)
+            };
+            let Some(reverted) = reverted else { return false };
+            self.contention.extend(reverted.contention.iter().cloned());
+            for edit in &reverted.edits {
+                self.broadcast(&edit.change(reverted.file_id));
+            }
+            true
+        }
+
+        fn text(&self) -> String {
+            let text = self.clients[0].text();
+            assert!(self.clients.iter().all(|client| client.text() == text), "clients diverged");
+            text
+        }
+    }
+
+    #[test]
+    fn test_undo_keeps_edits_of_others() {
+        let (alice, bob) = (Uuid::new_v4(), Uuid::new_v4());
+        let mut sim = Simulation::new(&[alice, bob]);
+
+        sim.insert(alice, 0, "Hello");
+        sim.insert(bob, 5, " world");
+        sim.insert(bob, 0, ">> ");
+        assert_eq!(sim.text(), ">> Hello world");
+
+        assert!(sim.revert(alice, false));
+        assert_eq!(sim.text(), ">>  world");
+        assert!(!sim.revert(alice, false));
+
+        assert!(sim.revert(alice, true));
+        assert_eq!(sim.text(), ">> Hello world");
+
+        assert!(sim.revert(bob, false));
+        assert_eq!(sim.text(), "Hello world");
+    }
+
+    #[test]
+    fn test_undo_removes_only_what_remains() {
+        let (alice, bob) = (Uuid::new_v4(), Uuid::new_v4());
+        let mut sim = Simulation::new(&[alice, bob]);
+
+        sim.insert(bob, 0, "[]");
+        sim.insert(alice, 1, "abcdef");
+        // Bob deletes "cd" and types inside what is left of Alice's insert
+        sim.delete(bob, 3, 2);
+        sim.insert(bob, 3, "XY");
+        assert_eq!(sim.text(), "[abXYef]");
+
+        assert!(sim.revert(alice, false));
+        assert_eq!(sim.text(), "[XY]");
+
+        // Redo brings back only the text the undo removed
+        assert!(sim.revert(alice, true));
+        assert_eq!(sim.text(), "[abXYef]");
+
+        // Once all of it is gone there is nothing to undo
+        sim.delete(bob, 1, 6);
+        assert_eq!(sim.text(), "[]");
+        assert!(!sim.revert(alice, false));
+    }
+
+    #[test]
+    fn test_transformed_entries_are_reported() {
+        let (alice, bob) = (Uuid::new_v4(), Uuid::new_v4());
+        let mut sim = Simulation::new(&[alice, bob]);
+
+        sim.insert(alice, 0, "Hello");
+        sim.insert(bob, 0, ">> ");
+        assert!(sim.contention.is_empty());
+
+        // Bob's insert moved Alice's, so undoing it was merged
+        assert!(sim.revert(alice, false));
+        assert_eq!(
+            std::mem::take(&mut sim.contention),
+            vec![Contention { file_id: None, users: vec![alice, bob], discarded: false }]
+        );
+        // Alice's undo came after Bob's insert and did not move it
+        assert!(sim.revert(bob, false));
+        assert!(sim.contention.is_empty());
+
+        // Bob deleting all of Alice's text leaves her nothing to undo
+        sim.insert(alice, 0, "abc");
+        sim.delete(bob, 0, 3);
+        assert_eq!(
+            std::mem::take(&mut sim.contention),
+            vec![Contention { file_id: None, users: vec![alice, bob], discarded: true }]
+        );
+        assert!(!sim.revert(alice, false));
+        assert!(sim.contention.is_empty());
+    }
+
+    #[test]
+    fn test_undone_delete_is_restored_in_place() {
+        let (alice, bob) = (Uuid::new_v4(), Uuid::new_v4());
+        let mut sim = Simulation::new(&[alice, bob]);
+
+        sim.insert(bob, 0, "The quick brown fox");
+        sim.delete(alice, 4, 6);
+        sim.insert(bob, 0, "See: ");
+        assert_eq!(sim.text(), "See: The brown fox");
+
+        assert!(sim.revert(alice, false));
+        assert_eq!(sim.text(), "See: The quick brown fox");
+    }
+
+    #[test]
+    fn test_new_edit_clears_redo() {
+        let alice = Uuid::new_v4();
+        let mut sim = Simulation::new(&[alice]);
+
+        sim.insert(alice, 0, "one");
+        sim.insert(alice, 3, " two");
+        assert!(sim.revert(alice, false));
+        assert_eq!(sim.text(), "one");
+
+        sim.insert(alice, 3, " three");
+        assert!(!sim.revert(alice, true));
+        assert!(sim.revert(alice, false));
+        assert!(sim.revert(alice, false));
+        assert_eq!(sim.text(), "");
+    }
+
+    #[test]
+    fn test_only_changes_since_joining_are_undoable() {
+        let (alice, bob) = (Uuid::new_v4(), Uuid::new_v4());
+        let mut sim = Simulation::new(&[alice]);
+
+        sim.insert(alice, 0, "draft");
+        sim.history.leave(sim.session_id, alice);
+        sim.history.join(sim.session_id, alice);
+        sim.history.join(sim.session_id, bob);
+        assert!(!sim.revert(alice, false));
+        assert!(!sim.revert(bob, false));
+        assert_eq!(sim.text(), "draft");
+    }
+
+    #[test]
+    fn test_interleaved_undo_redo_converges() {
+        let users: Vec<Uuid> = (0..3).map(|_| Uuid::new_v4()).collect();
+        let mut sim = Simulation::new(&users);
+
+        // A fixed pseudo-random script of edits, undos and redos
+        let mut seed: u64 = 0x2545_f491_4f6c_dd1d;
+        let mut next = |bound: usize| {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            (seed % bound as u64) as usize
+        };
+
+        for step in 0..2000 {
+            let user = users[next(users.len())];
+            let len = sim.clients[0].0.len();
+            match next(6) {
+                0 | 1 => sim.insert(user, next(len + 1) as i32, &format!("<{}>", step % 10)),
+                2 if len > 0 => {
+                    let position = next(len);
+                    sim.delete(user, position, 1 + next((len - position).min(4)));
+                }
+                3 | 4 => {
+                    sim.revert(user, false);
+                }
+                _ => {
+                    sim.revert(user, true);
+                }
+            }
+            sim.text();
+        }
+
+        for user in &users {
+            while sim.revert(*user, false) {}
+        }
+        sim.text();
+    }
+
+    #[test]
+    fn test_depth_is_bounded() {
+        let alice = Uuid::new_v4();
+        let mut sim = Simulation::new(&[alice]);
+
+        for _ in 0..UNDO_DEPTH + 5 {
+            sim.insert(alice, 0, "x");
+        }
+        let mut undone = 0;
+        while sim.revert(alice, false) {
+            undone += 1;
+        }
+        assert_eq!(undone, UNDO_DEPTH);
+        assert_eq!(sim.text(), "x".repeat(5));
+    }
+
+    #[test]
+    fn test_non_text_operations_are_ignored() {
+        assert!(Change::from_operation(OperationType::Cursor, Some(3), None, None, None).is_none());
+        assert!(Change::from_operation(OperationType::Insert, None, Some("a"), None, None).is_none());
+
+        // A delete without the removed text cannot be inverted
+        let change = Change::from_operation(OperationType::Delete, Some(0), None, Some(3), None).unwrap();
+        assert_eq!(change.removed, 3);
+        assert!(change.inverse().is_none());
+    }
+}
@@ -0,0 +1,445 @@
+//! Resumable upload session models
+
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use super::{ContentType, Entity};
+use super::blob::AcquiredBlob;
+use super::file::File;
+use super::project_limits::ProjectLimits;
+
+/// How often abandoned upload sessions are expired
+const CLEANUP_INTERVAL: std::time::Duration = std::time::Duration::from_secs(600);
+
+/// Upload session status
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type, ToSchema)]
+pub enum UploadStatus {
+    #[serde(rename = "pending")]
+    #[sqlx(rename = "pending")]
+    Pending,
+    #[serde(rename = "completed")]
+    #[sqlx(rename = "completed")]
+    Completed,
+    #[serde(rename = "expired")]
+    #[sqlx(rename = "expired")]
+    Expired,
+}
+
+impl Default for UploadStatus {
+    fn default() -> Self {
+        Self::Pending
+    }
+}
+
+/// Upload session model
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, ToSchema)]
+pub struct UploadSession {
+    pub id: Uuid,
+    pub project_id: Uuid,
+    pub user_id: Uuid,
+    pub name: String,
+    pub path: String,
+    pub content_type: ContentType,
+    pub declared_size: i64,
+    pub chunk_size: i64,
+    pub total_chunks: i32,
+    pub content_hash: String,
+    pub status: UploadStatus,
+    pub file_id: Option<Uuid>,
+    pub expires_at: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl Entity for UploadSession {
+    fn id(&self) -> Uuid {
+        self.id
+    }
+
+    fn created_at(&self) -> DateTime<Utc> {
+        self.created_at
+    }
+
+    fn updated_at(&self) -> DateTime<Utc> {
+        self.updated_at
+    }
+}
+
+/// A chunk that has been received and verified
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct UploadChunk {
+    pub session_id: Uuid,
+    pub chunk_index: i32,
+    pub size: i64,
+    pub checksum: String,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Upload session creation request
+#[derive(Debug, Clone, Deserialize, ToSchema)]
+pub struct CreateUploadSession {
+    pub project_id: Uuid,
+    pub path: String,
+    pub size: i64,
+    pub content_hash: String,
+    pub chunk_size: Option<i64>,
+}
+
+impl UploadSession {
+    /// Create a new upload session
+    pub async fn create(
+        db: &sqlx::PgPool,
+        user_id: Uuid,
+        name: crate::models::validation::FileName,
+        content_type: ContentType,
+        create: &CreateUploadSession,
+        chunk_size: i64,
+        ttl_seconds: i64,
+    ) -> Result<Self, crate::error::AppError> {
+        let total_chunks = chunk_count(create.size, chunk_size);
+
+        let session = sqlx::query_as::<_, UploadSession>(
+            r#"
+            INSERT INTO upload_sessions (
+                project_id, user_id, name, path, content_type, declared_size,
+                chunk_size, total_chunks, content_hash, status, expires_at
+            ) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, 'pending', $10)
+            RETURNING *
+            "#
+        )
+        .bind(create.project_id)
+        .bind(user_id)
+        .bind(name)
+        .bind(&create.path)
+        .bind(content_type)
+        .bind(create.size)
+        .bind(chunk_size)
+        .bind(total_chunks)
+        .bind(create.content_hash.to_lowercase())
+        .bind(Utc::now() + Duration::seconds(ttl_seconds))
+        .fetch_one(db)
+        .await
+        .map_err(crate::error::AppError::Database)?;
+
+        Ok(session)
+    }
+
+    /// Find an upload session owned by the given user
+    pub async fn find_for_user(
+        db: &sqlx::PgPool,
+        session_id: Uuid,
+        user_id: Uuid,
+    ) -> Result<Option<Self>, crate::error::AppError> {
+        let session = sqlx::query_as::<_, UploadSession>(
+            "SELECT * FROM upload_sessions WHERE id = $1 AND user_id = $2"
+        )
+        .bind(session_id)
+        .bind(user_id)
+        .fetch_optional(db)
+        .await
+        .map_err(crate::error::AppError::Database)?;
+
+        Ok(session)
+    }
+
+    /// Unique bytes stored by a project, including pending uploads
+    pub async fn project_usage(
+        db: &sqlx::PgPool,
+        project_id: Uuid,
+    ) -> Result<i64, crate::error::AppError> {
+        super::storage_usage::quota_usage(db, project_id).await
+    }
+
+    /// Record a verified chunk, replacing any earlier attempt at the same index
+    pub async fn record_chunk(
+        db: &sqlx::PgPool,
+        session_id: Uuid,
+        chunk_index: i32,
+        size: i64,
+        checksum: &str,
+    ) -> Result<UploadChunk, crate::error::AppError> {
+        let chunk = sqlx::query_as::<_, UploadChunk>(
+            r#"
+            INSERT INTO upload_chunks (session_id, chunk_index, size, checksum)
+            VALUES ($1, $2, $3, $4)
+            ON CONFLICT (session_id, chunk_index)
+            DO UPDATE SET size = EXCLUDED.size, checksum = EXCLUDED.checksum, created_at = NOW()
+            RETURNING *
+            "#
+        )
+        .bind(session_id)
+        .bind(chunk_index)
+        .bind(size)
+        .bind(checksum)
+        .fetch_one(db)
+        .await
+        .map_err(crate::error::AppError::Database)?;
+
+        Ok(chunk)
+    }
+
+    /// Indexes of the chunks received so far
+    pub async fn received_chunks(
+        db: &sqlx::PgPool,
+        session_id: Uuid,
+    ) -> Result<Vec<i32>, crate::error::AppError> {
+        let indexes = sqlx::query_scalar::<_, i32>(
+            "SELECT chunk_index FROM upload_chunks WHERE session_id = $1 ORDER BY chunk_index"
+        )
+        .bind(session_id)
+        .fetch_all(db)
+        .await
+        .map_err(crate::error::AppError::Database)?;
+
+        Ok(indexes)
+    }
+
+    /// Create the file row and close the session in one transaction.
+    ///
+    /// The session is closed first so its reservation stops counting against
+    /// the project's size limit as the file starts to. `place` runs inside the transaction once the blob reference is held so
+    /// the assembled content can be moved into the blob store, or discarded
+    /// when identical content is already stored; if it fails nothing is committed.
+    pub async fn complete<F>(
+        &self,
+        db: &sqlx::PgPool,
+        limits: &ProjectLimits,
+        place: F,
+    ) -> Result<File, crate::error::AppError>
+    where
+        F: FnOnce(&AcquiredBlob) -> Result<(), crate::error::AppError>,
+    {
+        let mut tx = db.begin().await.map_err(crate::error::AppError::Database)?;
+
+        // Lock the session so two concurrent completions cannot both succeed
+        let status = sqlx::query_scalar::<_, UploadStatus>(
+            "SELECT status FROM upload_sessions WHERE id = $1 FOR UPDATE"
+        )
+        .bind(self.id)
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(crate::error::AppError::Database)?;
+
+        if status != UploadStatus::Pending {
+            return Err(crate::error::AppError::Conflict(
+                "Upload session is no longer pending".to_string(),
+            ));
+        }
+
+        sqlx::query("UPDATE upload_sessions SET status = 'completed' WHERE id = $1")
+            .bind(self.id)
+            .execute(&mut *tx)
+            .await
+            .map_err(crate::error::AppError::Database)?;
+
+        let (file, blob) = File::create_external(
+            &mut tx,
+            limits,
+            self.project_id,
+            &self.name,
+            &self.path,
+            self.content_type,
+            self.declared_size,
+            &self.content_hash,
+            self.user_id,
+        )
+        .await?;
+
+        sqlx::query("UPDATE upload_sessions SET file_id = $2 WHERE id = $1")
+            .bind(self.id)
+            .bind(file.id)
+            .execute(&mut *tx)
+            .await
+            .map_err(crate::error::AppError::Database)?;
+
+        place(&blob)?;
+
+        tx.commit().await.map_err(crate::error::AppError::Database)?;
+
+        Ok(file)
+    }
+
+    /// Expire pending sessions past their deadline
+    pub async fn expire_abandoned(db: &sqlx::PgPool) -> Result<Vec<Uuid>, crate::error::AppError> {
+        let expired = sqlx::query_scalar::<_, Uuid>(
+            r#"
+            UPDATE upload_sessions
+            SET status = 'expired'
+            WHERE status = 'pending' AND expires_at <= NOW()
+            RETURNING id
+            "#
+        )
+        .fetch_all(db)
+        .await
+        .map_err(crate::error::AppError::Database)?;
+
+        if !expired.is_empty() {
+            sqlx::query("DELETE FROM upload_chunks WHERE session_id = ANY($1)")
+                .bind(&expired)
+                .execute(db)
+                .await
+                .map_err(crate::error::AppError::Database)?;
+        }
+
+        Ok(expired)
+    }
+
+    /// Whether the session still accepts chunks
+    pub fn is_open(&self) -> bool {
+        self.status == UploadStatus::Pending && self.expires_at > Utc::now()
+    }
+
+    /// Expected byte length of the chunk at `index`; only the last chunk may be short
+    pub fn expected_chunk_size(&self, index: i32) -> Option<i64> {
+        if index < 0 || index >= self.total_chunks {
+            return None;
+        }
+
+        if index == self.total_chunks - 1 {
+            Some(self.declared_size - self.chunk_size * index as i64)
+        } else {
+            Some(self.chunk_size)
+        }
+    }
+
+    /// Chunk indexes that have not been received yet
+    pub fn missing_chunks(&self, received: &[i32]) -> Vec<i32> {
+        let received: std::collections::HashSet<i32> = received.iter().copied().collect();
+        (0..self.total_chunks)
+            .filter(|index| !received.contains(index))
+            .collect()
+    }
+}
+
+/// Directory holding the chunks of an upload session under the storage root
+pub fn chunk_dir(root: &str, session_id: Uuid) -> std::path::PathBuf {
+    std::path::PathBuf::from(root).join(".uploads").join(session_id.to_string())
+}
+
+/// Expires abandoned upload sessions and removes their chunks
+pub struct UploadCleanupTask;
+
+impl crate::tasks::PeriodicTask for UploadCleanupTask {
+    fn name(&self) -> &'static str {
+        "upload_cleanup"
+    }
+
+    fn interval(&self) -> std::time::Duration {
+        CLEANUP_INTERVAL
+    }
+
+    fn run<'a>(
+        &'a self,
+        state: &'a crate::server::AppState,
+    ) -> futures::future::BoxFuture<'a, Result<(), crate::error::AppError>> {
+        Box::pin(async move {
+            let expired = UploadSession::expire_abandoned(&state.db_pool).await?;
+            for session_id in &expired {
+                let dir = chunk_dir(&state.config.features.file_storage.local_path, *session_id);
+                if let Err(e) = tokio::fs::remove_dir_all(&dir).await {
+                    if e.kind() != std::io::ErrorKind::NotFound {
+                        tracing::warn!("Failed to remove upload directory {}: {}", dir.display(), e);
+                    }
+                }
+            }
+
+            if !expired.is_empty() {
+                tracing::debug!("Expired {} abandoned upload sessions", expired.len());
+            }
+            Ok(())
+        })
+    }
+}
+
+/// Number of chunks needed to cover `size` bytes
+fn chunk_count(size: i64, chunk_size: i64) -> i32 {
+    ((size + chunk_size - 1) / chunk_size) as i32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session(declared_size: i64, chunk_size: i64) -> UploadSession {
+        UploadSession {
+            id: Uuid::new_v4(),
+            project_id: Uuid::new_v4(),
+            user_id: Uuid::new_v4(),
+            name: "data.csv".to_string(),
+            path: "/data/data.csv".to_string(),
+            content_type: ContentType::Other,
+            declared_size,
+            chunk_size,
+            total_chunks: chunk_count(declared_size, chunk_size),
+            content_hash: "0".repeat(64),
+            status: UploadStatus::Pending,
+            file_id: None,
+            expires_at: Utc::now() + Duration::hours(1),
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn test_chunk_sizes() {
+        let upload = session(25, 10);
+        assert_eq!(upload.total_chunks, 3);
+        assert_eq!(upload.expected_chunk_size(0), Some(10));
+        assert_eq!(upload.expected_chunk_size(2), Some(5));
+        assert_eq!(upload.expected_chunk_size(3), None);
+
+        let exact = session(20, 10);
+        assert_eq!(exact.total_chunks, 2);
+        assert_eq!(exact.expected_chunk_size(1), Some(10));
+    }
+
+    #[test]
+    fn test_missing_chunks() {
+        let upload = session(50, 10);
+        assert_eq!(upload.missing_chunks(&[0, 2, 4]), vec![1, 3]);
+        assert!(upload.missing_chunks(&[4, 3, 2, 1, 0]).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_cleanup_task_expires_abandoned_sessions() {
+        use crate::tasks::PeriodicTask;
+        use crate::testing::{create_test_project, create_test_user, test_config, TestDb};
+
+        let Some(db) = TestDb::start().await else { return };
+        let storage = tempfile::tempdir().unwrap();
+        let mut config = test_config();
+        config.features.file_storage.local_path = storage.path().to_string_lossy().into_owned();
+        let state = crate::server::AppState::new(config, db.pool.clone()).await.unwrap();
+        let owner = create_test_user(&db.pool).await;
+        let project = create_test_project(&db.pool, &owner, false).await;
+
+        let start = |ttl_seconds| {
+            let create = CreateUploadSession {
+                project_id: project.id,
+                path: "/data.csv".to_string(),
+                size: 25,
+                content_hash: "0".repeat(64),
+                chunk_size: None,
+            };
+            let name = crate::models::validation::FileName::new("data.csv").unwrap();
+            let (pool, user_id) = (db.pool.clone(), owner.id);
+            async move { UploadSession::create(&pool, user_id, name, ContentType::Other, &create, 10, ttl_seconds).await.unwrap() }
+        };
+        let (abandoned, open) = (start(-1).await, start(3600).await);
+        for session in [&abandoned, &open] {
+            tokio::fs::create_dir_all(chunk_dir(&state.config.features.file_storage.local_path, session.id)).await.unwrap();
+        }
+
+        UploadCleanupTask.run(&state).await.unwrap();
+
+        let abandoned = UploadSession::find_for_user(&db.pool, abandoned.id, owner.id).await.unwrap().unwrap();
+        let open = UploadSession::find_for_user(&db.pool, open.id, owner.id).await.unwrap().unwrap();
+        assert_eq!((abandoned.status, open.status), (UploadStatus::Expired, UploadStatus::Pending));
+        let root = &state.config.features.file_storage.local_path;
+        assert!(!chunk_dir(root, abandoned.id).exists());
+        assert!(chunk_dir(root, open.id).exists());
+    }
+}
@@ -0,0 +1,508 @@
+//! Monthly usage of workspaces, for billing and chargeback
+//!
+//! Figures are computed from facts that do not change once written: file
+//! versions and compilation jobs, by the month they were created in (UTC).
+//! Active users are the distinct authors of versions and creators of jobs.
+//! Storage is the size each live file had at the end of the month, from its
+//! latest version by then; files never versioned count at their current
+//! size. Compile time is the run time of the month's finished jobs.
+//!
+//! [`UsageRollupTask`] records every closed month in `usage_periods`.
+//! Rolling up again gives the same figures unless data arrived late, a job
+//! finishing after its month closed or a restored file; such a month is
+//! restated: its revision goes up and the figures it replaced are kept, so
+//! history never changes silently. Months are restated for
+//! `RESTATEMENT_MONTHS` after they close. The current month is computed on
+//! demand and marked provisional.
+
+use chrono::{DateTime, Datelike, Months, NaiveDate, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use std::time::Duration;
+use utoipa::{IntoParams, ToSchema};
+use uuid::Uuid;
+
+use super::activity_export::csv_field;
+use crate::error::AppError;
+
+/// How often closed months are rolled up
+pub const USAGE_ROLLUP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Closed months that are recomputed and restated when their figures change
+pub const RESTATEMENT_MONTHS: u32 = 3;
+
+/// Closed months a report covers at most; older months are never rolled up
+pub const MAX_REPORT_MONTHS: u32 = 24;
+
+/// Closed months a report covers by default
+pub const DEFAULT_REPORT_MONTHS: u32 = 6;
+
+/// Columns of the CSV export, in order
+const CSV_COLUMNS: [&str; 13] = [
+    "workspace_id", "workspace_name", "period_start", "provisional", "restated", "revision", "active_users",
+    "storage_bytes", "compile_minutes", "jobs_succeeded", "jobs_failed", "jobs_cancelled", "jobs_unfinished",
+];
+
+/// Figures of a workspace for one month
+const FIGURES: &str = r#"
+    SELECT
+        w.id AS workspace_id,
+        w.name AS workspace_name,
+        COALESCE(active.active_users, 0)::BIGINT AS active_users,
+        COALESCE(storage.storage_bytes, 0)::BIGINT AS storage_bytes,
+        COALESCE(jobs.compile_ms, 0)::BIGINT AS compile_ms,
+        COALESCE(jobs.jobs_succeeded, 0)::BIGINT AS jobs_succeeded,
+        COALESCE(jobs.jobs_failed, 0)::BIGINT AS jobs_failed,
+        COALESCE(jobs.jobs_cancelled, 0)::BIGINT AS jobs_cancelled,
+        COALESCE(jobs.jobs_unfinished, 0)::BIGINT AS jobs_unfinished
+    FROM workspaces w
+    LEFT JOIN (
+        SELECT workspace_id, COUNT(DISTINCT user_id) AS active_users FROM (
+            SELECT p.workspace_id, v.author_id AS user_id
+            FROM file_versions v
+            JOIN files f ON f.id = v.file_id
+            JOIN projects p ON p.id = f.project_id
+            WHERE v.created_at >= $1 AND v.created_at < $2
+            UNION ALL
+            SELECT p.workspace_id, cj.user_id
+            FROM compilation_jobs cj
+            JOIN projects p ON p.id = cj.project_id
+            WHERE cj.created_at >= $1 AND cj.created_at < $2
+        ) authors
+        GROUP BY workspace_id
+    ) active ON active.workspace_id = w.id
+    LEFT JOIN (
+        SELECT p.workspace_id, SUM(COALESCE(b.size, f.size, 0)) AS storage_bytes
+        FROM files f
+        JOIN projects p ON p.id = f.project_id
+        LEFT JOIN LATERAL (
+            SELECT v.content_hash FROM file_versions v
+            WHERE v.file_id = f.id AND v.created_at < $2
+            ORDER BY v.version DESC
+            LIMIT 1
+        ) latest ON true
+        LEFT JOIN blobs b ON b.hash = latest.content_hash
+        WHERE f.created_at < $2
+          AND (latest.content_hash IS NOT NULL
+               OR NOT EXISTS (SELECT 1 FROM file_versions v WHERE v.file_id = f.id))
+          AND ((f.deleted_at IS NULL AND NOT COALESCE(f.is_deleted, false)) OR f.deleted_at >= $2)
+        GROUP BY p.workspace_id
+    ) storage ON storage.workspace_id = w.id
+    LEFT JOIN (
+        SELECT
+            p.workspace_id,
+            SUM(cj.duration_ms) FILTER (WHERE cj.completed_at IS NOT NULL) AS compile_ms,
+            COUNT(*) FILTER (WHERE cj.status = 'success') AS jobs_succeeded,
+            COUNT(*) FILTER (WHERE cj.status = 'error') AS jobs_failed,
+            COUNT(*) FILTER (WHERE cj.status = 'cancelled') AS jobs_cancelled,
+            COUNT(*) FILTER (WHERE cj.status IN ('never', 'pending', 'running')) AS jobs_unfinished
+        FROM compilation_jobs cj
+        JOIN projects p ON p.id = cj.project_id
+        WHERE cj.created_at >= $1 AND cj.created_at < $2
+        GROUP BY p.workspace_id
+    ) jobs ON jobs.workspace_id = w.id
+    WHERE w.created_at < $2 AND ($3::UUID IS NULL OR w.id = $3)
+"#;
+
+/// Usage of a workspace in one month
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, FromRow, ToSchema)]
+pub struct UsageFigures {
+    /// Distinct authors of file versions and creators of compile jobs
+    pub active_users: i64,
+    /// Bytes of live files at the end of the month
+    pub storage_bytes: i64,
+    /// Run time of the month's finished compile jobs
+    pub compile_ms: i64,
+    pub jobs_succeeded: i64,
+    pub jobs_failed: i64,
+    pub jobs_cancelled: i64,
+    /// Jobs still queued or running when the figures were computed
+    pub jobs_unfinished: i64,
+}
+
+/// A month of a workspace's usage
+#[derive(Debug, Clone, Serialize, FromRow, ToSchema)]
+pub struct UsagePeriod {
+    pub workspace_id: Uuid,
+    pub workspace_name: String,
+    /// First day of the month, UTC
+    pub period_start: NaiveDate,
+    #[serde(flatten)]
+    #[sqlx(flatten)]
+    pub figures: UsageFigures,
+    /// `compile_ms` in minutes, rounded to hundredths
+    pub compile_minutes: f64,
+    /// The month is not over; the figures are computed on demand and will change
+    pub provisional: bool,
+    /// The figures changed after they were first recorded
+    pub restated: bool,
+    /// 1 for figures never restated
+    pub revision: i32,
+    /// Figures the last restatement replaced
+    #[schema(value_type = Option<UsageFigures>)]
+    pub previous: Option<sqlx::types::Json<UsageFigures>>,
+    pub restated_at: Option<DateTime<Utc>>,
+    pub computed_at: DateTime<Utc>,
+}
+
+/// Usage report parameters
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct UsageParams {
+    /// Closed months before the current one, 1 to 24 (default 6)
+    pub months: Option<u32>,
+}
+
+impl UsageParams {
+    pub fn months(&self) -> u32 {
+        self.months.unwrap_or(DEFAULT_REPORT_MONTHS).clamp(1, MAX_REPORT_MONTHS)
+    }
+}
+
+/// Encoding of a usage report
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum UsageFormat {
+    #[default]
+    Json,
+    Csv,
+}
+
+/// Outcome of rolling up closed months
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct UsageRollup {
+    /// Workspace months recorded for the first time
+    pub recorded: u64,
+    /// Workspace months whose figures changed
+    pub restated: u64,
+}
+
+/// First day of the month of `time`, UTC
+pub fn month_of(time: DateTime<Utc>) -> NaiveDate {
+    NaiveDate::from_ymd_opt(time.year(), time.month(), 1).expect("the first of a month is a date")
+}
+
+/// Start and end of the month starting on `period_start`
+pub fn month_bounds(period_start: NaiveDate) -> (DateTime<Utc>, DateTime<Utc>) {
+    let midnight = |day: NaiveDate| Utc.from_utc_datetime(&day.and_hms_opt(0, 0, 0).unwrap());
+    (midnight(period_start), midnight(period_start + Months::new(1)))
+}
+
+/// The `count` months closed before `now`, oldest first
+pub fn closed_months(now: DateTime<Utc>, count: u32) -> Vec<NaiveDate> {
+    let current = month_of(now);
+    (1..=count).rev().map(|back| current - Months::new(back)).collect()
+}
+
+/// Compute a closed month and record it for every workspace, restating
+/// months recorded with different figures
+pub async fn roll_up(db: &sqlx::PgPool, period_start: NaiveDate) -> Result<UsageRollup, AppError> {
+    let (start, end) = month_bounds(period_start);
+    let inserted = sqlx::query_scalar::<_, bool>(&format!(
+        r#"
+        INSERT INTO usage_periods AS up
+            (workspace_id, period_start, active_users, storage_bytes, compile_ms,
+             jobs_succeeded, jobs_failed, jobs_cancelled, jobs_unfinished)
+        SELECT workspace_id, $4, active_users, storage_bytes, compile_ms,
+               jobs_succeeded, jobs_failed, jobs_cancelled, jobs_unfinished
+        FROM ({FIGURES}) figures
+        ON CONFLICT (workspace_id, period_start) DO UPDATE SET
+            active_users = EXCLUDED.active_users,
+            storage_bytes = EXCLUDED.storage_bytes,
+            compile_ms = EXCLUDED.compile_ms,
+            jobs_succeeded = EXCLUDED.jobs_succeeded,
+            jobs_failed = EXCLUDED.jobs_failed,
+            jobs_cancelled = EXCLUDED.jobs_cancelled,
+            jobs_unfinished = EXCLUDED.jobs_unfinished,
+            revision = up.revision + 1,
+            previous = jsonb_build_object(
+                'active_users', up.active_users,
+                'storage_bytes', up.storage_bytes,
+                'compile_ms', up.compile_ms,
+                'jobs_succeeded', up.jobs_succeeded,
+                'jobs_failed', up.jobs_failed,
+                'jobs_cancelled', up.jobs_cancelled,
+                'jobs_unfinished', up.jobs_unfinished
+            ),
+            restated_at = NOW(),
+            computed_at = NOW()
+        WHERE (up.active_users, up.storage_bytes, up.compile_ms, up.jobs_succeeded,
+               up.jobs_failed, up.jobs_cancelled, up.jobs_unfinished)
+            IS DISTINCT FROM
+              (EXCLUDED.active_users, EXCLUDED.storage_bytes, EXCLUDED.compile_ms, EXCLUDED.jobs_succeeded,
+               EXCLUDED.jobs_failed, EXCLUDED.jobs_cancelled, EXCLUDED.jobs_unfinished)
+        RETURNING xmax = 0
+        "#
+    ))
+    .bind(start)
+    .bind(end)
+    .bind(None::<Uuid>)
+    .bind(period_start)
+    .fetch_all(db)
+    .await
+    .map_err(AppError::Database)?;
+
+    let recorded = inserted.iter().filter(|inserted| **inserted).count() as u64;
+    Ok(UsageRollup {
+        recorded,
+        restated: inserted.len() as u64 - recorded,
+    })
+}
+
+/// Roll up the months closed before `now`: the last `RESTATEMENT_MONTHS`
+/// every time, older ones up to `MAX_REPORT_MONTHS` once
+pub async fn roll_up_closed(db: &sqlx::PgPool, now: DateTime<Utc>) -> Result<UsageRollup, AppError> {
+    let recent = closed_months(now, RESTATEMENT_MONTHS);
+    let mut total = UsageRollup::default();
+    for period_start in closed_months(now, MAX_REPORT_MONTHS) {
+        if !recent.contains(&period_start) {
+            let recorded = sqlx::query_scalar::<_, bool>("SELECT EXISTS (SELECT 1 FROM usage_periods WHERE period_start = $1)")
+                .bind(period_start)
+                .fetch_one(db)
+                .await
+                .map_err(AppError::Database)?;
+            if recorded {
+                continue;
+            }
+        }
+        let rollup = roll_up(db, period_start).await?;
+        total.recorded += rollup.recorded;
+        total.restated += rollup.restated;
+    }
+    Ok(total)
+}
+
+/// The `months` closed months before `now` as recorded and the current month
+/// so far, of one workspace or all; by workspace, then month
+pub async fn report(
+    db: &sqlx::PgPool,
+    workspace_id: Option<Uuid>,
+    months: u32,
+    now: DateTime<Utc>,
+) -> Result<Vec<UsagePeriod>, AppError> {
+    let current = month_of(now);
+    let mut periods = sqlx::query_as::<_, UsagePeriod>(
+        r#"
+        SELECT up.workspace_id, w.name AS workspace_name, up.period_start,
+               up.active_users, up.storage_bytes, up.compile_ms, up.jobs_succeeded,
+               up.jobs_failed, up.jobs_cancelled, up.jobs_unfinished,
+               ROUND(up.compile_ms / 60000.0, 2)::FLOAT8 AS compile_minutes,
+               false AS provisional, up.revision > 1 AS restated, up.revision,
+               up.previous, up.restated_at, up.computed_at
+        FROM usage_periods up
+        JOIN workspaces w ON w.id = up.workspace_id
+        WHERE up.period_start >= $1 AND up.period_start < $2
+          AND ($3::UUID IS NULL OR up.workspace_id = $3)
+        "#
+    )
+    .bind(current - Months::new(months))
+    .bind(current)
+    .bind(workspace_id)
+    .fetch_all(db)
+    .await
+    .map_err(AppError::Database)?;
+
+    let (start, _) = month_bounds(current);
+    let provisional = sqlx::query_as::<_, UsagePeriod>(&format!(
+        r#"
+        SELECT workspace_id, workspace_name, $4::DATE AS period_start,
+               active_users, storage_bytes, compile_ms, jobs_succeeded,
+               jobs_failed, jobs_cancelled, jobs_unfinished,
+               ROUND(compile_ms / 60000.0, 2)::FLOAT8 AS compile_minutes,
+               true AS provisional, false AS restated, 1 AS revision,
+               NULL::JSONB AS previous, NULL::TIMESTAMPTZ AS restated_at, $2 AS computed_at
+        FROM ({FIGURES}) figures
+        "#
+    ))
+    .bind(start)
+    .bind(now)
+    .bind(workspace_id)
+    .bind(current)
+    .fetch_all(db)
+    .await
+    .map_err(AppError::Database)?;
+
+    periods.extend(provisional);
+    periods.sort_by(|a, b| {
+        (&a.workspace_name, a.workspace_id, a.period_start).cmp(&(&b.workspace_name, b.workspace_id, b.period_start))
+    });
+    Ok(periods)
+}
+
+/// A usage report as CSV, one row per workspace and month
+pub fn to_csv(periods: &[UsagePeriod]) -> String {
+    let mut csv = format!("{}\r\n", CSV_COLUMNS.join(","));
+    for period in periods {
+        let figures = &period.figures;
+        let fields = [
+            period.workspace_id.to_string(),
+            csv_field(&period.workspace_name).into_owned(),
+            period.period_start.to_string(),
+            period.provisional.to_string(),
+            period.restated.to_string(),
+            period.revision.to_string(),
+            figures.active_users.to_string(),
+            figures.storage_bytes.to_string(),
+            format!("{:.2}", period.compile_minutes),
+            figures.jobs_succeeded.to_string(),
+            figures.jobs_failed.to_string(),
+            figures.jobs_cancelled.to_string(),
+            figures.jobs_unfinished.to_string(),
+        ];
+        csv.push_str(&fields.join(","));
+        csv.push_str("\r\n");
+    }
+    csv
+}
+
+/// Records the usage of closed months
+pub struct UsageRollupTask;
+
+impl crate::tasks::PeriodicTask for UsageRollupTask {
+    fn name(&self) -> &'static str {
+        "usage_rollup"
+    }
+
+    fn interval(&self) -> Duration {
+        USAGE_ROLLUP_INTERVAL
+    }
+
+    fn run<'a>(
+        &'a self,
+        state: &'a crate::server::AppState,
+    ) -> futures::future::BoxFuture<'a, Result<(), AppError>> {
+        Box::pin(async move {
+            let rollup = roll_up_closed(&state.db_pool, Utc::now()).await?;
+            if rollup.recorded > 0 || rollup.restated > 0 {
+                tracing::info!(
+                    "Recorded the usage of {} workspace months and restated {}",
+                    rollup.recorded,
+                    rollup.restated
+                );
+            }
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::file_history::FileVersion;
+    use crate::models::CompilationStatus;
+    use crate::testing::{create_test_file, create_test_job, create_test_project, create_test_user, TestDb};
+
+    #[test]
+    fn test_months() {
+        let now = Utc.with_ymd_and_hms(2026, 2, 14, 9, 30, 0).unwrap();
+        assert_eq!(month_of(now), NaiveDate::from_ymd_opt(2026, 2, 1).unwrap());
+        assert_eq!(
+            closed_months(now, 3),
+            vec![
+                NaiveDate::from_ymd_opt(2025, 11, 1).unwrap(),
+                NaiveDate::from_ymd_opt(2025, 12, 1).unwrap(),
+                NaiveDate::from_ymd_opt(2026, 1, 1).unwrap(),
+            ]
+        );
+        let (start, end) = month_bounds(NaiveDate::from_ymd_opt(2025, 12, 1).unwrap());
+        assert_eq!(start, Utc.with_ymd_and_hms(2025, 12, 1, 0, 0, 0).unwrap());
+        assert_eq!(end, Utc.with_ymd_and_hms(2026, 1, 1, 0, 0, 0).unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_rollup_is_idempotent_and_restates_late_data() {
+        let Some(db) = TestDb::start().await else { return };
+        let owner = create_test_user(&db.pool).await;
+        let editor = create_test_user(&db.pool).await;
+        let project = create_test_project(&db.pool, &owner, false).await;
+        let main = create_test_file(&db.pool, &project, &owner).await;
+
+        let now = Utc::now();
+        let period = closed_months(now, 1)[0];
+        let (start, _) = month_bounds(period);
+        let during = start + chrono::Duration::days(3);
+        let backdate = |table: &'static str, id: Uuid| {
+            let pool = db.pool.clone();
+            async move {
+                sqlx::query(&format!("UPDATE {} SET created_at = $2 WHERE id = $1", table))
+                    .bind(id)
+                    .bind(during)
+                    .execute(&pool)
+                    .await
+                    .unwrap();
+            }
+        };
+        backdate("workspaces", project.workspace_id).await;
+        backdate("files", main.id).await;
+        let version = FileVersion::create(&db.pool, main.id, 1, &main.content, editor.id, "First").await.unwrap();
+        backdate("file_versions", version.id).await;
+
+        let finish = |status: CompilationStatus, duration_ms: i64| {
+            let (pool, project, owner) = (db.pool.clone(), project.clone(), owner.clone());
+            async move {
+                let job = create_test_job(&pool, &project, &owner).await;
+                sqlx::query(
+                    "UPDATE compilation_jobs SET status = $2, duration_ms = $3, created_at = $4, completed_at = $4 WHERE id = $1",
+                )
+                .bind(job.id)
+                .bind(status)
+                .bind(duration_ms)
+                .bind(during)
+                .execute(&pool)
+                .await
+                .unwrap();
+            }
+        };
+        finish(CompilationStatus::Success, 90_000).await;
+        finish(CompilationStatus::Error, 30_000).await;
+
+        let recorded = |pool: sqlx::PgPool| async move {
+            report(&pool, Some(project.workspace_id), 1, now)
+                .await
+                .unwrap()
+                .into_iter()
+                .filter(|usage| !usage.provisional)
+                .collect::<Vec<_>>()
+        };
+
+        roll_up(&db.pool, period).await.unwrap();
+        let first = recorded(db.pool.clone()).await;
+        assert_eq!(first.len(), 1);
+        let expected = UsageFigures {
+            active_users: 2,
+            storage_bytes: main.content.len() as i64,
+            compile_ms: 120_000,
+            jobs_succeeded: 1,
+            jobs_failed: 1,
+            jobs_cancelled: 0,
+            jobs_unfinished: 0,
+        };
+        assert_eq!(first[0].figures, expected);
+        assert_eq!(first[0].compile_minutes, 2.0);
+        assert!(!first[0].restated);
+
+        // Rolling up again changes nothing
+        roll_up(&db.pool, period).await.unwrap();
+        let second = recorded(db.pool.clone()).await;
+        assert_eq!(second[0].figures, first[0].figures);
+        assert_eq!((second[0].revision, second[0].computed_at), (1, first[0].computed_at));
+
+        // A job of the month that shows up late restates it
+        finish(CompilationStatus::Cancelled, 6_000).await;
+        roll_up(&db.pool, period).await.unwrap();
+        let restated = recorded(db.pool.clone()).await;
+        assert!(restated[0].restated);
+        assert_eq!(restated[0].revision, 2);
+        assert_eq!(restated[0].figures, UsageFigures { compile_ms: 126_000, jobs_cancelled: 1, ..expected.clone() });
+        assert_eq!(restated[0].previous.as_ref().map(|previous| &previous.0), Some(&expected));
+
+        let all = report(&db.pool, Some(project.workspace_id), 1, now).await.unwrap();
+        let current = all.iter().find(|usage| usage.provisional).unwrap();
+        assert_eq!(current.period_start, month_of(now));
+        assert_eq!(current.figures.jobs_succeeded, 0);
+
+        let csv = to_csv(&restated);
+        assert!(csv.starts_with("workspace_id,workspace_name,period_start,provisional,restated"));
+        assert!(csv.contains(&format!("{},false,true,2,2,", period)), "{}", csv);
+    }
+}
@@ -3,9 +3,11 @@
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
+use utoipa::ToSchema;
 use uuid::Uuid;
 
 use crate::models::{Entity, UserRole};
+use crate::models::invitation::ProjectInvitation;
 
 /// Authentication method
 #[derive(Debug, Clone, Serialize, Deserialize, sqlx::Type)]
@@ -89,7 +91,7 @@ pub struct CreateUser {
     pub username: String,
     pub email: String,
     pub password: String,
-    pub display_name: String,
+    pub display_name: crate::models::validation::DisplayName,
     pub avatar_url: Option<String>,
 }
 
@@ -105,15 +107,15 @@ pub struct CreateOidcUser {
 }
 
 /// User update request
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, ToSchema)]
 pub struct UpdateUser {
-    pub display_name: Option<String>,
+    pub display_name: Option<crate::models::validation::DisplayName>,
     pub avatar_url: Option<String>,
     pub is_active: Option<bool>,
 }
 
 /// User profile response (without sensitive data)
-#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, ToSchema)]
 pub struct UserProfile {
     pub id: Uuid,
     pub username: String,
@@ -142,8 +144,31 @@ impl From<User> for UserProfile {
     }
 }
 
+impl UserProfile {
+    /// Profiles of the given users, keyed by ID
+    pub async fn find_many(
+        db: &sqlx::PgPool,
+        user_ids: &[Uuid],
+    ) -> Result<std::collections::HashMap<Uuid, Self>, crate::error::AppError> {
+        let profiles = sqlx::query_as::<_, UserProfile>(
+            r#"
+            SELECT id, username, email, display_name, avatar_url,
+                   is_active, email_verified, last_login_at, created_at
+            FROM users
+            WHERE id = ANY($1)
+            "#
+        )
+        .bind(user_ids)
+        .fetch_all(db)
+        .await
+        .map_err(crate::error::AppError::Database)?;
+
+        Ok(profiles.into_iter().map(|profile| (profile.id, profile)).collect())
+    }
+}
+
 /// User preferences
-#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, ToSchema)]
 pub struct UserPreferences {
     pub user_id: Uuid,
     pub theme: String,
@@ -154,6 +179,11 @@ pub struct UserPreferences {
     pub word_wrap: bool,
     pub font_size: i32,
     pub tab_size: i32,
+    pub compile_notifications: super::compile_notification::CompileNotificationPreference,
+    pub digest_frequency: super::activity_digest::DigestFrequency,
+    pub mention_notifications: super::mention::MentionNotificationPreference,
+    /// IANA time zone of digests and the user's calendar days
+    pub timezone: String,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
 }
@@ -189,14 +219,14 @@ pub struct EmailVerificationRequest {
 }
 
 /// Login request
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, ToSchema)]
 pub struct LoginRequest {
     pub email: String,
     pub password: String,
 }
 
 /// Login response
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, ToSchema)]
 pub struct LoginResponse {
     pub user: UserProfile,
     pub access_token: String,
@@ -218,21 +248,34 @@ pub struct RefreshTokenResponse {
 }
 
 /// OIDC login request
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, ToSchema)]
 pub struct OidcLoginRequest {
     pub provider: String,
+    /// Path of the frontend to return to once signed in
+    pub redirect_to: Option<String>,
 }
 
 /// OIDC login URL response
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, ToSchema)]
 pub struct OidcLoginUrlResponse {
+    /// Where to send the user; state, PKCE challenge and nonce are kept on
+    /// the server
     pub auth_url: String,
-    pub state: String,
-    pub pkce_challenge: Option<String>,
+    /// The provider must redirect back before this time
+    pub expires_at: DateTime<Utc>,
+}
+
+/// OIDC callback response
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct OidcCallbackResponse {
+    #[serde(flatten)]
+    pub login: LoginResponse,
+    /// Path given when the login was started
+    pub redirect_to: Option<String>,
 }
 
 /// OIDC callback request
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, ToSchema)]
 pub struct OidcCallbackRequest {
     pub code: String,
     pub state: String,
@@ -375,10 +418,14 @@ impl User {
     }
 
     /// Find or create OIDC user from provider information
+    ///
+    /// With a `mirror`, the picture of a new account is stored as its avatar
+    /// rather than linked; when that fails the account has no avatar.
     pub async fn find_or_create_oidc(
         db: &sqlx::PgPool,
         user_info: &OidcUserInfo,
         provider: &str,
+        mirror: Option<&crate::models::avatar::PictureMirror<'_>>,
     ) -> Result<Self, crate::error::AppError> {
         // First, try to find existing user by OIDC info
         if let Some(user) = Self::find_by_oidc(db, provider, &user_info.sub).await? {
@@ -409,6 +456,10 @@ impl User {
             .fetch_one(db)
             .await
             .map_err(crate::error::AppError::Database)?;
+
+            if user_info.email_verified {
+                ProjectInvitation::accept_for_user(db, user.id, &user.email).await?;
+            }
             return Ok(user);
         }
 
@@ -424,15 +475,30 @@ impl User {
         let create_user = CreateOidcUser {
             username: Self::generate_unique_username(db, &username).await?,
             email: user_info.email.clone(),
-            display_name: user_info.name.as_ref()
-                .unwrap_or(&user_info.email)
-                .to_string(),
-            avatar_url: user_info.picture.clone(),
+            // The provider's name is not ours to reject, so it is cleaned up instead
+            display_name: crate::models::validation::display_lossy(
+                user_info.name.as_deref().unwrap_or(&user_info.email),
+                &user_info.email,
+            ),
+            avatar_url: user_info.picture.clone().filter(|_| mirror.is_none()),
             provider: provider.to_string(),
             provider_id: user_info.sub.clone(),
         };
 
-        Self::create_oidc(db, create_user, user_info.email_verified).await
+        let mut user = Self::create_oidc(db, create_user, user_info.email_verified).await?;
+        if let (Some(mirror), Some(picture)) = (mirror, user_info.picture.as_deref()) {
+            match mirror.mirror(db, user.id, picture).await {
+                Ok(mirrored) => user = mirrored,
+                Err(e) => tracing::warn!("Failed to mirror the OIDC picture of user {}: {}", user.id, e),
+            }
+        }
+
+        // Only a provider-verified address may claim invitations sent to it
+        if user_info.email_verified {
+            ProjectInvitation::accept_for_user(db, user.id, &user.email).await?;
+        }
+
+        Ok(user)
     }
 
     /// Generate a unique username by appending a number if needed
@@ -528,7 +594,7 @@ impl User {
         Ok(())
     }
 
-    /// Get user preferences
+    /// Get user preferences; users who never saved any get the defaults
     pub async fn get_preferences(
         &self,
         db: &sqlx::PgPool,
@@ -540,11 +606,11 @@ impl User {
             "#
         )
         .bind(self.id)
-        .fetch_one(db)
+        .fetch_optional(db)
         .await
         .map_err(crate::error::AppError::Database)?;
 
-        Ok(preferences)
+        Ok(preferences.unwrap_or_else(|| UserPreferences::default(self.id)))
     }
 
     /// Update user preferences
@@ -557,8 +623,9 @@ impl User {
             r#"
             INSERT INTO user_preferences (
                 user_id, theme, language, latex_engine, auto_save,
-                line_numbers, word_wrap, font_size, tab_size
-            ) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+                line_numbers, word_wrap, font_size, tab_size, compile_notifications,
+                digest_frequency, mention_notifications, timezone
+            ) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13)
             ON CONFLICT (user_id)
             DO UPDATE SET
                 theme = EXCLUDED.theme,
@@ -569,6 +636,10 @@ impl User {
                 word_wrap = EXCLUDED.word_wrap,
                 font_size = EXCLUDED.font_size,
                 tab_size = EXCLUDED.tab_size,
+                compile_notifications = EXCLUDED.compile_notifications,
+                digest_frequency = EXCLUDED.digest_frequency,
+                mention_notifications = EXCLUDED.mention_notifications,
+                timezone = EXCLUDED.timezone,
                 updated_at = NOW()
             RETURNING *
             "#
@@ -582,6 +653,10 @@ impl User {
         .bind(preferences.word_wrap)
         .bind(preferences.font_size)
         .bind(preferences.tab_size)
+        .bind(preferences.compile_notifications)
+        .bind(preferences.digest_frequency)
+        .bind(preferences.mention_notifications)
+        .bind(&preferences.timezone)
         .fetch_one(db)
         .await
         .map_err(crate::error::AppError::Database)?;
@@ -603,6 +678,10 @@ impl UserPreferences {
             word_wrap: true,
             font_size: 14,
             tab_size: 2,
+            compile_notifications: Default::default(),
+            digest_frequency: Default::default(),
+            mention_notifications: Default::default(),
+            timezone: super::timezone::DEFAULT_TIME_ZONE.to_string(),
             created_at: Utc::now(),
             updated_at: Utc::now(),
         }
@@ -612,21 +691,28 @@ impl UserPreferences {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::error::AppError;
+    use crate::testing::TestDb;
 
     #[tokio::test]
     async fn test_user_creation() {
-        // This test would require a test database
-        // It's just an example of how to structure tests
+        let Some(db) = TestDb::start().await else { return };
+        let suffix = &Uuid::new_v4().simple().to_string()[..12];
         let user_data = CreateUser {
-            username: "testuser".to_string(),
-            email: "test@example.com".to_string(),
+            username: format!("testuser_{}", suffix),
+            email: format!("test_{}@example.com", suffix),
             password: "password123".to_string(),
-            display_name: "Test User".to_string(),
+            display_name: crate::models::validation::DisplayName::new("Test User").unwrap(),
             avatar_url: None,
         };
 
-        // assert!(User::create(&pool, user_data).await.is_ok());
+        let user = User::create(&db.pool, user_data.clone()).await.unwrap();
+        assert!(user.verify_password("password123"));
+
+        let found = User::find_by_email(&db.pool, &user_data.email).await.unwrap();
+        assert_eq!(found.map(|u| u.id), Some(user.id));
+
+        // Usernames are unique
+        assert!(User::create(&db.pool, user_data).await.is_err());
     }
 
     #[test]
@@ -635,17 +721,11 @@ mod tests {
         let hash = bcrypt::hash(password, bcrypt::DEFAULT_COST).unwrap();
 
         let user = User {
-            id: Uuid::new_v4(),
             username: "test".to_string(),
             email: "test@example.com".to_string(),
-            password_hash: hash,
+            password_hash: Some(hash),
             display_name: "Test".to_string(),
-            avatar_url: None,
-            is_active: true,
-            email_verified: false,
-            last_login_at: None,
-            created_at: Utc::now(),
-            updated_at: Utc::now(),
+            ..User::default()
         };
 
         assert!(user.verify_password(password));
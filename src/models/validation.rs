@@ -0,0 +1,309 @@
+//! Validated names and display strings
+//!
+//! Names typed by users end up in the database, the UI, exported archives and
+//! next to compile paths, so they are checked once when a request is
+//! deserialized or a value is constructed, instead of deep in SQL. Every
+//! wrapper normalizes to NFC, trims surrounding whitespace, enforces a length
+//! limit and rejects control characters, bidirectional overrides (which can
+//! make `gnp.exe` display as `exe.png`) and zero-width characters. File names
+//! additionally reject path separators, characters Windows cannot store and
+//! reserved names such as `..`, `CON` or `aux.tex`, so exports unpack anywhere.
+//!
+//! Rows written before these rules existed are left as they are. Row types
+//! keep plain `String`s, so reading them never fails; where a stored name is
+//! shown outside the JSON API it goes through [`display_lossy`] instead.
+
+use serde::{Deserialize, Serialize};
+use unicode_normalization::UnicodeNormalization;
+use utoipa::ToSchema;
+
+use crate::error::AppError;
+
+/// Longest project (and workspace) name, in characters
+pub const MAX_PROJECT_NAME_CHARS: usize = 100;
+
+/// Longest file name, in bytes, as most file systems count it
+pub const MAX_FILE_NAME_BYTES: usize = 255;
+
+/// Longest display name, in characters
+pub const MAX_DISPLAY_NAME_CHARS: usize = 100;
+
+/// Device names Windows reserves, with or without an extension
+const RESERVED_FILE_STEMS: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8", "COM9",
+    "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Characters Windows does not allow in file names, besides the separators
+const FORBIDDEN_FILE_CHARS: &[char] = &['<', '>', ':', '"', '|', '?', '*'];
+
+/// Characters that reorder the text around them
+fn is_bidi_control(c: char) -> bool {
+    matches!(c, '\u{061C}' | '\u{200E}' | '\u{200F}' | '\u{202A}'..='\u{202E}' | '\u{2066}'..='\u{2069}')
+}
+
+/// Characters that render as nothing
+fn is_invisible(c: char) -> bool {
+    matches!(
+        c,
+        '\u{00AD}' | '\u{034F}' | '\u{115F}' | '\u{1160}' | '\u{180E}' | '\u{200B}'..='\u{200D}'
+            | '\u{2060}'..='\u{2064}' | '\u{3164}' | '\u{FEFF}'
+    )
+}
+
+/// NFC-normalize and trim `value`, then apply the checks every wrapper shares
+fn normalize(label: &str, value: &str, max_chars: usize) -> Result<String, AppError> {
+    let reject = |reason: &str| Err(AppError::Validation(format!("{} {}", label, reason)));
+
+    let normalized: String = value.nfc().collect();
+    let normalized = normalized.trim();
+
+    if normalized.is_empty() {
+        return reject("cannot be empty");
+    }
+    if normalized.chars().count() > max_chars {
+        return reject(&format!("cannot be longer than {} characters", max_chars));
+    }
+    if normalized.chars().any(char::is_control) {
+        return reject("cannot contain control characters or line breaks");
+    }
+    if normalized.chars().any(is_bidi_control) {
+        return reject("cannot contain text direction overrides");
+    }
+    if normalized.chars().any(is_invisible) {
+        return reject("cannot contain zero-width or invisible characters");
+    }
+
+    Ok(normalized.to_string())
+}
+
+/// A stored name made presentable: normalized, trimmed and stripped of
+/// control, direction and invisible characters
+///
+/// For values that were never validated; it never fails, and falls back to
+/// `fallback` when nothing is left.
+pub fn display_lossy(value: &str, fallback: &str) -> String {
+    let cleaned: String = value
+        .nfc()
+        .filter(|c| !c.is_control() && !is_bidi_control(*c) && !is_invisible(*c))
+        .collect();
+    let cleaned = cleaned.trim();
+
+    if cleaned.is_empty() {
+        fallback.to_string()
+    } else {
+        cleaned.to_string()
+    }
+}
+
+/// Check a name that is not one of the wrapped kinds, e.g. a workspace name
+pub fn validate_name(label: &str, value: &str) -> Result<String, AppError> {
+    normalize(label, value, MAX_PROJECT_NAME_CHARS)
+}
+
+/// Project name
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize, sqlx::Type, ToSchema)]
+#[serde(try_from = "String", into = "String")]
+#[sqlx(transparent)]
+pub struct ProjectName(String);
+
+impl ProjectName {
+    pub fn new(value: &str) -> Result<Self, AppError> {
+        normalize("Project name", value, MAX_PROJECT_NAME_CHARS).map(Self)
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+/// File name: a single path segment
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize, sqlx::Type, ToSchema)]
+#[serde(try_from = "String", into = "String")]
+#[sqlx(transparent)]
+pub struct FileName(String);
+
+impl FileName {
+    pub fn new(value: &str) -> Result<Self, AppError> {
+        let name = normalize("File name", value, MAX_FILE_NAME_BYTES)?;
+        let reject = |reason: String| Err(AppError::Validation(format!("File name {}", reason)));
+
+        if name.len() > MAX_FILE_NAME_BYTES {
+            return reject(format!("cannot be longer than {} bytes", MAX_FILE_NAME_BYTES));
+        }
+        if name.contains(['/', '\\']) {
+            return reject("cannot contain path separators".to_string());
+        }
+        if let Some(c) = name.chars().find(|c| FORBIDDEN_FILE_CHARS.contains(c)) {
+            return reject(format!("cannot contain '{}'", c));
+        }
+        if name == "." || name == ".." {
+            return reject(format!("'{}' is reserved", name));
+        }
+        if name.ends_with('.') {
+            return reject("cannot end with a dot".to_string());
+        }
+        let stem = name.split('.').next().unwrap_or_default().trim_end();
+        if RESERVED_FILE_STEMS.iter().any(|reserved| reserved.eq_ignore_ascii_case(stem)) {
+            return reject(format!("'{}' is reserved on Windows", name));
+        }
+
+        Ok(Self(name))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+/// Name shown for a user
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize, sqlx::Type, ToSchema)]
+#[serde(try_from = "String", into = "String")]
+#[sqlx(transparent)]
+pub struct DisplayName(String);
+
+impl DisplayName {
+    pub fn new(value: &str) -> Result<Self, AppError> {
+        normalize("Display name", value, MAX_DISPLAY_NAME_CHARS).map(Self)
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl TryFrom<String> for ProjectName {
+    type Error = AppError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        Self::new(&value)
+    }
+}
+
+impl TryFrom<String> for FileName {
+    type Error = AppError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        Self::new(&value)
+    }
+}
+
+impl TryFrom<String> for DisplayName {
+    type Error = AppError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        Self::new(&value)
+    }
+}
+
+impl From<ProjectName> for String {
+    fn from(name: ProjectName) -> Self {
+        name.0
+    }
+}
+
+impl From<FileName> for String {
+    fn from(name: FileName) -> Self {
+        name.0
+    }
+}
+
+impl From<DisplayName> for String {
+    fn from(name: DisplayName) -> Self {
+        name.0
+    }
+}
+
+impl std::fmt::Display for ProjectName {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::fmt::Display for FileName {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::fmt::Display for DisplayName {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_adversarial_names() {
+        let long = "x".repeat(MAX_PROJECT_NAME_CHARS + 1);
+        let huge = "a".repeat(10 * 1024);
+        // (input, accepted as project name, accepted as file name, accepted as display name)
+        let cases: &[(&str, bool, bool, bool)] = &[
+            ("Thesis", true, true, true),
+            ("  Thesis  ", true, true, true),
+            ("main.tex", true, true, true),
+            ("Zoë's notes", true, true, true),
+            ("数学 ノート", true, true, true),
+            ("", false, false, false),
+            ("   \t ", false, false, false),
+            (&long, false, true, false),
+            (&huge, false, false, false),
+            ("gnp\u{202E}.exe", false, false, false),
+            ("report\u{2067}fdp.exe", false, false, false),
+            ("ad\u{200D}min", false, false, false),
+            ("\u{FEFF}main.tex", false, false, false),
+            ("line\nbreak", false, false, false),
+            ("bell\u{7}", false, false, false),
+            ("chapters/intro.tex", true, false, true),
+            ("..\\..\\boot.ini", true, false, true),
+            (".", true, false, true),
+            ("..", true, false, true),
+            ("aux", true, false, true),
+            ("CON", true, false, true),
+            ("con.tex", true, false, true),
+            ("Lpt1.log", true, false, true),
+            ("main.aux", true, true, true),
+            ("auxiliary.tex", true, true, true),
+            ("draft.", true, false, true),
+            ("what?.tex", true, false, true),
+            (".latexmkrc", true, true, true),
+        ];
+
+        for (input, project, file, display) in cases {
+            assert_eq!(ProjectName::new(input).is_ok(), *project, "project name {:?}", input);
+            assert_eq!(FileName::new(input).is_ok(), *file, "file name {:?}", input);
+            assert_eq!(DisplayName::new(input).is_ok(), *display, "display name {:?}", input);
+        }
+    }
+
+    #[test]
+    fn test_names_are_normalized() {
+        // "e" followed by a combining acute accent composes to "é"
+        let name = ProjectName::new("  Caf\u{0065}\u{0301}  ").unwrap();
+        assert_eq!(name.as_str(), "Caf\u{00E9}");
+
+        // The byte limit of file names counts the normalized form
+        let composed = "é".repeat(MAX_FILE_NAME_BYTES / 2);
+        assert!(FileName::new(&"e\u{0301}".repeat(MAX_FILE_NAME_BYTES / 2)).is_ok());
+        assert!(FileName::new(&format!("{}é", composed)).is_err());
+    }
+
+    #[test]
+    fn test_deserialization_rejects_invalid_names() {
+        let name: ProjectName = serde_json::from_str("\" Thesis \"").unwrap();
+        assert_eq!(name.as_str(), "Thesis");
+        assert_eq!(serde_json::to_string(&name).unwrap(), "\"Thesis\"");
+
+        let error = serde_json::from_str::<FileName>("\"gnp\\u202e.exe\"").unwrap_err();
+        assert!(error.to_string().contains("File name cannot contain text direction overrides"), "{}", error);
+    }
+
+    #[test]
+    fn test_display_lossy() {
+        assert_eq!(display_lossy("gnp\u{202E}.exe", "file"), "gnp.exe");
+        assert_eq!(display_lossy(" \u{200B} ", "Untitled"), "Untitled");
+    }
+}
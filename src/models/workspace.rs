@@ -3,13 +3,17 @@
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
+use utoipa::ToSchema;
 use uuid::Uuid;
 
 use crate::error::AppError;
 
 use super::file::{CreateFile, File};
+use super::onboarding::Onboarding;
 use super::project::{CreateProject, Project};
+use super::project_limits::ProjectLimits;
 use super::ContentType;
+use super::validation::{self, FileName, ProjectName};
 
 pub const DEFAULT_WORKSPACE_NAME: &str = "Personal Workspace";
 pub const DEFAULT_WORKSPACE_DESCRIPTION: &str = "Sandbox workspace for your LaTeX experiments.";
@@ -87,7 +91,7 @@ pub struct Workspace {
 }
 
 /// Workspace summary with project metadata for API responses
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, ToSchema)]
 pub struct WorkspaceSummary {
     pub id: Uuid,
     pub name: String,
@@ -100,7 +104,7 @@ pub struct WorkspaceSummary {
 }
 
 /// Lightweight project summary returned alongside workspaces
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, ToSchema)]
 pub struct ProjectSummary {
     pub id: Uuid,
     pub workspace_id: Uuid,
@@ -148,28 +152,28 @@ pub struct ProjectFileDetails {
 }
 
 /// Workspace creation payload
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, ToSchema)]
 pub struct NewWorkspace {
     pub name: String,
     pub description: Option<String>,
 }
 
 /// Workspace-level project creation payload
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, ToSchema)]
 pub struct NewWorkspaceProject {
-    pub name: Option<String>,
+    pub name: Option<crate::models::validation::ProjectName>,
     pub description: Option<String>,
 }
 
 /// File upsert payload used by workspace endpoints
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, ToSchema)]
 pub struct FileUpsert {
     pub path: String,
     pub content: String,
 }
 
 /// Request body for changing project main file
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, ToSchema)]
 pub struct MainFileUpdate {
     pub path: String,
 }
@@ -216,7 +220,7 @@ impl Workspace {
                     WHERE is_deleted = false
                     GROUP BY project_id
                 ) f ON f.project_id = p.id
-                WHERE p.workspace_id = ANY($1)
+                WHERE p.workspace_id = ANY($1) AND p.purging_at IS NULL
                 ORDER BY p.created_at
                 "#
             )
@@ -272,7 +276,7 @@ impl Workspace {
         name: String,
         description: Option<String>,
     ) -> Result<Self, AppError> {
-        let trimmed = normalize_name(&name)?;
+        let trimmed = validation::validate_name("Workspace name", &name)?;
         let workspace = sqlx::query_as::<_, Workspace>(
             r#"
             INSERT INTO workspaces (name, description, owner_id)
@@ -326,7 +330,7 @@ impl Workspace {
 
     /// Fetch a specific workspace ensuring ownership
     pub async fn find_by_id(
-        db: &sqlx::PgPool,
+        db: impl sqlx::PgExecutor<'_>,
         workspace_id: Uuid,
         owner_id: Uuid,
     ) -> Result<Self, AppError> {
@@ -375,7 +379,7 @@ impl Workspace {
         let project = sqlx::query_as::<_, Project>(
             r#"
             SELECT * FROM projects
-            WHERE id = $1 AND workspace_id = $2
+            WHERE id = $1 AND workspace_id = $2 AND purging_at IS NULL
             "#
         )
         .bind(project_id)
@@ -423,14 +427,15 @@ impl Workspace {
         Ok(details)
     }
 
-    /// Create a welcome project pre-populated with useful files
-    pub async fn seed_welcome_project(
-        db: &sqlx::PgPool,
+    /// Create a welcome project pre-populated with useful files; either all
+    /// of it is created or none
+    pub async fn seed_welcome_project<'a>(
+        db: impl sqlx::Acquire<'a, Database = sqlx::Postgres>,
         owner_id: Uuid,
         workspace_id: Uuid,
     ) -> Result<Project, AppError> {
         let create_project = CreateProject {
-            name: format!("{}", DEFAULT_PROJECT_NAME),
+            name: ProjectName::new(DEFAULT_PROJECT_NAME)?,
             description: Some(DEFAULT_PROJECT_DESCRIPTION.to_string()),
             is_public: Some(false),
             main_file_path: Some("main.tex".to_string()),
@@ -442,14 +447,18 @@ impl Workspace {
             workspace_id: Some(workspace_id),
         };
 
-        let project = Project::create(db, owner_id, create_project).await?;
+        let mut tx = db.begin().await.map_err(AppError::Database)?;
+        let project = Project::create(&mut *tx, owner_id, create_project).await?;
+        // Two small files of our own, within any limits an instance sets
+        let limits = ProjectLimits::default();
 
         // main.tex
         File::create(
-            db,
+            &mut *tx,
+            &limits,
             project.id,
             CreateFile {
-                name: "main.tex".to_string(),
+                name: FileName::new("main.tex")?,
                 path: "main.tex".to_string(),
                 content: Some(DEFAULT_MAIN_TEX.to_string()),
                 content_type: Some(ContentType::Latex),
@@ -460,10 +469,11 @@ impl Workspace {
 
         // sections/introduction.tex
         File::create(
-            db,
+            &mut *tx,
+            &limits,
             project.id,
             CreateFile {
-                name: "introduction.tex".to_string(),
+                name: FileName::new("introduction.tex")?,
                 path: "sections/introduction.tex".to_string(),
                 content: Some(DEFAULT_INTRO_TEX.to_string()),
                 content_type: Some(ContentType::Latex),
@@ -472,14 +482,9 @@ impl Workspace {
         )
         .await?;
 
-        Ok(project)
-    }
-}
+        Onboarding::mark_sample_project(&mut tx, owner_id, project.id).await?;
 
-fn normalize_name(name: &str) -> Result<String, AppError> {
-    let trimmed = name.trim();
-    if trimmed.is_empty() {
-        return Err(AppError::Validation("Name cannot be empty".to_string()));
+        tx.commit().await.map_err(AppError::Database)?;
+        Ok(project)
     }
-    Ok(trimmed.to_string())
 }
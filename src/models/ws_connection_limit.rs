@@ -0,0 +1,259 @@
+//! Per-user cap on concurrent WebSocket connections
+//!
+//! Every authenticated connection is indexed under its user. Once a user has
+//! more than `max_connections_per_user`, the new connection is kept and their
+//! least recently active ones are closed instead, so a tab left behind or a
+//! client stuck reconnecting loses its sockets rather than the tab in use.
+//! Connections that never authenticate are bounded by the server-wide limit
+//! only.
+//!
+//! The index is shared with the HTTP server, which reports it in the admin
+//! statistics and metrics. Like the rate limits it lives in memory, so with
+//! several server instances each caps its own share.
+
+use prometheus::{IntCounter, IntGauge, Registry, TextEncoder};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use tokio::time::Instant;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::config::WebSocketConfig;
+
+/// Users listed in the statistics, those with the most connections first
+pub const BUSIEST_USERS: usize = 20;
+
+#[derive(Debug)]
+struct TrackedConnection {
+    connection_id: String,
+    /// Last message received on the connection
+    last_active: Instant,
+}
+
+#[derive(Debug, Default)]
+struct IndexState {
+    /// Connections of each user, in the order they authenticated
+    users: HashMap<Uuid, Vec<TrackedConnection>>,
+    owners: HashMap<String, Uuid>,
+}
+
+/// Connections of one user
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct UserConnectionCount {
+    pub user_id: Uuid,
+    pub connections: usize,
+}
+
+/// Authenticated WebSocket connections of this server
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct WsConnectionStats {
+    pub connections: usize,
+    pub users: usize,
+    pub max_connections_per_user: usize,
+    /// Connections closed to make room for newer ones since the server started
+    pub evicted: u64,
+    /// Users with the most connections, at most `BUSIEST_USERS`
+    pub busiest: Vec<UserConnectionCount>,
+}
+
+/// Authenticated connections by user, with the cap on how many each may hold
+#[derive(Debug)]
+pub struct WsConnectionLimiter {
+    max_per_user: usize,
+    state: Mutex<IndexState>,
+    evicted: AtomicU64,
+}
+
+impl WsConnectionLimiter {
+    pub fn new(max_per_user: usize) -> Self {
+        Self {
+            // A user always keeps the connection they just opened
+            max_per_user: max_per_user.max(1),
+            state: Mutex::new(IndexState::default()),
+            evicted: AtomicU64::new(0),
+        }
+    }
+
+    pub fn from_config(config: &WebSocketConfig) -> Self {
+        Self::new(config.max_connections_per_user)
+    }
+
+    /// Index a connection that authenticated as `user_id`
+    ///
+    /// Returns the user's connections to close for it, the least recently
+    /// active first. They are no longer indexed.
+    pub fn admit(&self, user_id: Uuid, connection_id: &str) -> Vec<String> {
+        self.admit_at(user_id, connection_id, Instant::now())
+    }
+
+    pub fn admit_at(&self, user_id: Uuid, connection_id: &str, now: Instant) -> Vec<String> {
+        let mut guard = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let state = &mut *guard;
+        // Authenticating again, possibly as someone else, moves the connection
+        Self::forget(state, connection_id);
+
+        state.owners.insert(connection_id.to_string(), user_id);
+        let connections = state.users.entry(user_id).or_default();
+        connections.push(TrackedConnection { connection_id: connection_id.to_string(), last_active: now });
+        let excess = connections.len().saturating_sub(self.max_per_user);
+        if excess == 0 {
+            return Vec::new();
+        }
+
+        // The sort is stable, so of equally idle connections the oldest goes first
+        let mut idlest: Vec<&TrackedConnection> =
+            connections.iter().filter(|tracked| tracked.connection_id != connection_id).collect();
+        idlest.sort_by_key(|tracked| tracked.last_active);
+        let evicted: Vec<String> = idlest.into_iter().take(excess).map(|tracked| tracked.connection_id.clone()).collect();
+
+        connections.retain(|tracked| !evicted.contains(&tracked.connection_id));
+        for connection_id in &evicted {
+            state.owners.remove(connection_id);
+        }
+        self.evicted.fetch_add(evicted.len() as u64, Ordering::Relaxed);
+        evicted
+    }
+
+    /// Note a message received on a connection
+    pub fn touch(&self, connection_id: &str) {
+        self.touch_at(connection_id, Instant::now());
+    }
+
+    pub fn touch_at(&self, connection_id: &str, now: Instant) {
+        let mut guard = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let state = &mut *guard;
+        let Some(user_id) = state.owners.get(connection_id) else { return };
+        if let Some(tracked) = state
+            .users
+            .get_mut(user_id)
+            .and_then(|connections| connections.iter_mut().find(|tracked| tracked.connection_id == connection_id))
+        {
+            tracked.last_active = now;
+        }
+    }
+
+    /// Drop a closed connection from the index
+    pub fn remove(&self, connection_id: &str) {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        Self::forget(&mut state, connection_id);
+    }
+
+    fn forget(state: &mut IndexState, connection_id: &str) {
+        let Some(user_id) = state.owners.remove(connection_id) else { return };
+        if let Some(connections) = state.users.get_mut(&user_id) {
+            connections.retain(|tracked| tracked.connection_id != connection_id);
+            if connections.is_empty() {
+                state.users.remove(&user_id);
+            }
+        }
+    }
+
+    /// Whether a connection is authenticated and still open
+    pub fn is_open(&self, connection_id: &str) -> bool {
+        let state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        state.owners.contains_key(connection_id)
+    }
+
+    /// Authenticated connections of a user
+    pub fn count(&self, user_id: Uuid) -> usize {
+        let state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        state.users.get(&user_id).map_or(0, Vec::len)
+    }
+
+    pub fn stats(&self) -> WsConnectionStats {
+        let state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let mut busiest: Vec<UserConnectionCount> = state
+            .users
+            .iter()
+            .map(|(&user_id, connections)| UserConnectionCount { user_id, connections: connections.len() })
+            .collect();
+        busiest.sort_by(|a, b| b.connections.cmp(&a.connections).then(a.user_id.cmp(&b.user_id)));
+        busiest.truncate(BUSIEST_USERS);
+
+        WsConnectionStats {
+            connections: state.owners.len(),
+            users: state.users.len(),
+            max_connections_per_user: self.max_per_user,
+            evicted: self.evicted.load(Ordering::Relaxed),
+            busiest,
+        }
+    }
+
+    /// Connection counts in the Prometheus text format
+    pub fn metrics_text(&self) -> String {
+        let stats = self.stats();
+        let registry = Registry::new();
+        let gauges = [
+            ("texler_websocket_connections", "Authenticated WebSocket connections", stats.connections),
+            ("texler_websocket_users", "Users with an authenticated WebSocket connection", stats.users),
+            (
+                "texler_websocket_user_connections_max",
+                "Connections of the user with the most",
+                stats.busiest.first().map_or(0, |busiest| busiest.connections),
+            ),
+        ];
+        for (name, help, value) in gauges {
+            let gauge = IntGauge::new(name, help).expect("valid metric");
+            gauge.set(value as i64);
+            registry.register(Box::new(gauge)).expect("unique metric");
+        }
+        let evicted = IntCounter::new(
+            "texler_websocket_connections_evicted_total",
+            "Connections closed for exceeding the per-user limit",
+        )
+        .expect("valid metric");
+        evicted.inc_by(stats.evicted);
+        registry.register(Box::new(evicted)).expect("unique metric");
+
+        let mut text = String::new();
+        if let Err(e) = TextEncoder::new().encode_utf8(&registry.gather(), &mut text) {
+            tracing::warn!("Failed to encode WebSocket metrics: {}", e);
+        }
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::time::Duration;
+
+    #[test]
+    fn test_idlest_connections_make_room() {
+        let limiter = WsConnectionLimiter::new(2);
+        let user = Uuid::new_v4();
+        let start = Instant::now();
+        let at = |seconds| start + Duration::from_secs(seconds);
+
+        assert!(limiter.admit_at(user, "a", at(0)).is_empty());
+        assert!(limiter.admit_at(user, "b", at(1)).is_empty());
+        // "a" is in use, so the idle "b" goes
+        limiter.touch_at("a", at(2));
+        assert_eq!(limiter.admit_at(user, "c", at(3)), vec!["b"]);
+        assert_eq!(limiter.admit_at(user, "d", at(4)), vec!["a"]);
+        assert_eq!(limiter.count(user), 2);
+
+        // Other users are not affected, and closed connections free their slot
+        assert!(limiter.admit_at(Uuid::new_v4(), "e", at(5)).is_empty());
+        limiter.remove("c");
+        assert!(limiter.admit_at(user, "f", at(6)).is_empty());
+
+        let stats = limiter.stats();
+        assert_eq!((stats.connections, stats.users, stats.evicted), (3, 2, 2));
+        assert_eq!(stats.busiest[0], UserConnectionCount { user_id: user, connections: 2 });
+        assert!(limiter.metrics_text().contains("texler_websocket_connections_evicted_total 2"));
+    }
+
+    #[test]
+    fn test_reauthentication_moves_the_connection() {
+        let limiter = WsConnectionLimiter::new(1);
+        let (first, second) = (Uuid::new_v4(), Uuid::new_v4());
+
+        limiter.admit(first, "a");
+        assert!(limiter.admit(first, "a").is_empty());
+        assert!(limiter.admit(second, "a").is_empty());
+        assert_eq!((limiter.count(first), limiter.count(second)), (0, 1));
+    }
+}
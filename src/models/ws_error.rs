@@ -0,0 +1,248 @@
+//! Error codes of WebSocket error frames
+//!
+//! Every `Error` frame carries one of a closed set of codes, so clients can
+//! tell a token to refresh from a session to give up on from a blip to retry.
+//! Each code says whether sending the same message again can succeed; frames
+//! may add `retry_after_ms` for when. Failures of the server's own code map
+//! from `AppError` with [`WsError::from`], which lists every variant so a new
+//! one has to pick its code.
+//!
+//! The wire names are also exported as string constants in [`codes`], for
+//! code that matches on them without the enum.
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::AppError;
+
+/// Wire names of the error codes
+pub mod codes {
+    pub const AUTH_EXPIRED: &str = "AUTH_EXPIRED";
+    pub const AUTH_INVALID: &str = "AUTH_INVALID";
+    pub const SESSION_NOT_FOUND: &str = "SESSION_NOT_FOUND";
+    pub const SESSION_FULL: &str = "SESSION_FULL";
+    pub const SESSION_ENDED: &str = "SESSION_ENDED";
+    pub const PERMISSION_DENIED: &str = "PERMISSION_DENIED";
+    pub const RATE_LIMITED: &str = "RATE_LIMITED";
+    pub const OPERATION_REJECTED: &str = "OPERATION_REJECTED";
+    pub const INTERNAL_RETRYABLE: &str = "INTERNAL_RETRYABLE";
+    pub const INTERNAL_FATAL: &str = "INTERNAL_FATAL";
+}
+
+/// Why a WebSocket message failed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum WsErrorCode {
+    /// The token expired; authenticate again with a fresh one
+    AuthExpired,
+    /// Not authenticated, or the token is not valid
+    AuthInvalid,
+    SessionNotFound,
+    /// The session has `max_participants` other users online
+    SessionFull,
+    SessionEnded,
+    PermissionDenied,
+    RateLimited,
+    /// The message is malformed or not allowed in the current state
+    OperationRejected,
+    /// A transient server failure
+    InternalRetryable,
+    InternalFatal,
+}
+
+impl WsErrorCode {
+    pub const ALL: [WsErrorCode; 10] = [
+        Self::AuthExpired,
+        Self::AuthInvalid,
+        Self::SessionNotFound,
+        Self::SessionFull,
+        Self::SessionEnded,
+        Self::PermissionDenied,
+        Self::RateLimited,
+        Self::OperationRejected,
+        Self::InternalRetryable,
+        Self::InternalFatal,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::AuthExpired => codes::AUTH_EXPIRED,
+            Self::AuthInvalid => codes::AUTH_INVALID,
+            Self::SessionNotFound => codes::SESSION_NOT_FOUND,
+            Self::SessionFull => codes::SESSION_FULL,
+            Self::SessionEnded => codes::SESSION_ENDED,
+            Self::PermissionDenied => codes::PERMISSION_DENIED,
+            Self::RateLimited => codes::RATE_LIMITED,
+            Self::OperationRejected => codes::OPERATION_REJECTED,
+            Self::InternalRetryable => codes::INTERNAL_RETRYABLE,
+            Self::InternalFatal => codes::INTERNAL_FATAL,
+        }
+    }
+
+    /// Whether sending the same message again can succeed; for `AuthExpired`
+    /// once the client authenticated with a fresh token
+    pub fn is_retryable(self) -> bool {
+        matches!(self, Self::AuthExpired | Self::RateLimited | Self::InternalRetryable)
+    }
+}
+
+impl std::fmt::Display for WsErrorCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Contents of an `Error` frame
+#[derive(Debug, Clone, PartialEq)]
+pub struct WsError {
+    pub code: WsErrorCode,
+    pub message: String,
+    pub retryable: bool,
+    /// Earliest time a retry can succeed, when known
+    pub retry_after_ms: Option<u64>,
+}
+
+impl WsError {
+    pub fn new(code: WsErrorCode, message: impl Into<String>) -> Self {
+        Self { code, message: message.into(), retryable: code.is_retryable(), retry_after_ms: None }
+    }
+
+    /// Retryable after `retry_after_ms`, whatever the code
+    pub fn retry_after(mut self, retry_after_ms: u64) -> Self {
+        self.retryable = true;
+        self.retry_after_ms = Some(retry_after_ms);
+        self
+    }
+
+    pub fn rejected(message: impl Into<String>) -> Self {
+        Self::new(WsErrorCode::OperationRejected, message)
+    }
+
+    pub fn not_authenticated() -> Self {
+        Self::new(WsErrorCode::AuthInvalid, "Authenticate before session messages")
+    }
+}
+
+impl From<&AppError> for WsErrorCode {
+    fn from(error: &AppError) -> Self {
+        match error {
+            AppError::Authentication(_) | AppError::Auth(_) => Self::AuthInvalid,
+            AppError::Jwt(e) if matches!(e.kind(), jsonwebtoken::errors::ErrorKind::ExpiredSignature) => {
+                Self::AuthExpired
+            }
+            AppError::Jwt(_) => Self::AuthInvalid,
+            AppError::NotFound { entity, .. } if entity == "CollaborationSession" => Self::SessionNotFound,
+            AppError::SessionFull { .. } => Self::SessionFull,
+            AppError::SessionEnded => Self::SessionEnded,
+            AppError::Authorization(_) | AppError::ProjectFrozen(_) | AppError::SessionNotStarted { .. } => {
+                Self::PermissionDenied
+            }
+            AppError::RateLimit => Self::RateLimited,
+            AppError::Database(sqlx::Error::RowNotFound)
+            | AppError::NotFound { .. }
+            | AppError::Validation(_)
+            | AppError::BadRequest(_)
+            | AppError::Conflict(_)
+            | AppError::Compilation(_)
+            | AppError::WebSocket(_)
+            | AppError::Json(_)
+            | AppError::MalwareDetected(_) => Self::OperationRejected,
+            AppError::Database(_)
+            | AppError::Redis(_)
+            | AppError::Server(_)
+            | AppError::Storage(_)
+            | AppError::Io(_)
+            | AppError::Timeout { .. }
+            | AppError::Job(_)
+            | AppError::Upstream(_) => Self::InternalRetryable,
+            AppError::Internal(_) | AppError::Config(_) | AppError::Bcrypt(_) | AppError::ContentCorrupted(_) => {
+                Self::InternalFatal
+            }
+        }
+    }
+}
+
+impl From<&AppError> for WsError {
+    fn from(error: &AppError) -> Self {
+        let ws_error = Self::new(WsErrorCode::from(error), error.to_string());
+        match error {
+            // Joining works once the session starts
+            AppError::SessionNotStarted { starts_at } => {
+                let wait = (*starts_at - chrono::Utc::now()).num_milliseconds().max(0);
+                ws_error.retry_after(wait as u64)
+            }
+            _ => ws_error,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_codes_serialize_to_their_constants() {
+        for code in WsErrorCode::ALL {
+            assert_eq!(serde_json::to_value(code).unwrap(), code.as_str());
+            assert_eq!(serde_json::from_value::<WsErrorCode>(code.as_str().into()).unwrap(), code);
+        }
+        let retryable: Vec<&str> =
+            WsErrorCode::ALL.iter().filter(|code| code.is_retryable()).map(|code| code.as_str()).collect();
+        assert_eq!(retryable, vec![codes::AUTH_EXPIRED, codes::RATE_LIMITED, codes::INTERNAL_RETRYABLE]);
+    }
+
+    #[test]
+    fn test_app_errors_map_to_codes() {
+        let expired = jsonwebtoken::errors::Error::from(jsonwebtoken::errors::ErrorKind::ExpiredSignature);
+        let forged = jsonwebtoken::errors::Error::from(jsonwebtoken::errors::ErrorKind::InvalidSignature);
+        let not_found = |entity: &str| AppError::NotFound { entity: entity.to_string(), id: "1".to_string() };
+        let table = [
+            (AppError::Database(sqlx::Error::PoolTimedOut), WsErrorCode::InternalRetryable),
+            (AppError::Database(sqlx::Error::RowNotFound), WsErrorCode::OperationRejected),
+            (AppError::Redis((redis::ErrorKind::IoError, "down").into()), WsErrorCode::InternalRetryable),
+            (AppError::Authentication(String::new()), WsErrorCode::AuthInvalid),
+            (AppError::Auth(String::new()), WsErrorCode::AuthInvalid),
+            (AppError::Jwt(expired), WsErrorCode::AuthExpired),
+            (AppError::Jwt(forged), WsErrorCode::AuthInvalid),
+            (AppError::Authorization(String::new()), WsErrorCode::PermissionDenied),
+            (AppError::Server(String::new()), WsErrorCode::InternalRetryable),
+            (AppError::Storage(String::new()), WsErrorCode::InternalRetryable),
+            (AppError::Validation(String::new()), WsErrorCode::OperationRejected),
+            (not_found("CollaborationSession"), WsErrorCode::SessionNotFound),
+            (not_found("File"), WsErrorCode::OperationRejected),
+            (AppError::Conflict(String::new()), WsErrorCode::OperationRejected),
+            (AppError::Compilation(String::new()), WsErrorCode::OperationRejected),
+            (AppError::WebSocket(String::new()), WsErrorCode::OperationRejected),
+            (AppError::Io(std::io::Error::other("io")), WsErrorCode::InternalRetryable),
+            (AppError::Json(serde_json::from_str::<u8>("x").unwrap_err()), WsErrorCode::OperationRejected),
+            (AppError::Bcrypt(String::new()), WsErrorCode::InternalFatal),
+            (AppError::RateLimit, WsErrorCode::RateLimited),
+            (AppError::Timeout { request_id: None, after_secs: 1 }, WsErrorCode::InternalRetryable),
+            (AppError::BadRequest(String::new()), WsErrorCode::OperationRejected),
+            (AppError::Internal(String::new()), WsErrorCode::InternalFatal),
+            (AppError::Config(String::new()), WsErrorCode::InternalFatal),
+            (AppError::Job(String::new()), WsErrorCode::InternalRetryable),
+            (AppError::Upstream(String::new()), WsErrorCode::InternalRetryable),
+            (AppError::MalwareDetected(String::new()), WsErrorCode::OperationRejected),
+            (AppError::ProjectFrozen(String::new()), WsErrorCode::PermissionDenied),
+            (AppError::ContentCorrupted(String::new()), WsErrorCode::InternalFatal),
+            (AppError::SessionNotStarted { starts_at: chrono::Utc::now() }, WsErrorCode::PermissionDenied),
+            (AppError::SessionFull { max_participants: 2 }, WsErrorCode::SessionFull),
+            (AppError::SessionEnded, WsErrorCode::SessionEnded),
+        ];
+        for (error, code) in &table {
+            assert_eq!(WsErrorCode::from(error), *code, "{:?}", error);
+        }
+    }
+
+    #[test]
+    fn test_sessions_not_started_can_be_retried_at_their_start() {
+        let starts_at = chrono::Utc::now() + chrono::Duration::minutes(5);
+        let error = WsError::from(&AppError::SessionNotStarted { starts_at });
+        assert_eq!(error.code, WsErrorCode::PermissionDenied);
+        assert!(error.retryable);
+        assert!(error.retry_after_ms.unwrap() > 4 * 60 * 1000);
+
+        let error = WsError::from(&AppError::SessionFull { max_participants: 2 });
+        assert!(!error.retryable && error.retry_after_ms.is_none());
+    }
+}
@@ -0,0 +1,327 @@
+//! Rate limits for WebSocket traffic
+//!
+//! The HTTP rate limiter never sees WebSocket frames, so messages are metered
+//! here with token buckets, one per connection and one per user for each
+//! `WsBudget`. A user's connections share twice the budget of one connection,
+//! enough for a second tab but not for opening many to multiply the limit.
+//! A message over budget is rejected with a retry hint; a connection that keeps
+//! sending into a limit within `VIOLATION_WINDOW` is told to disconnect.
+//!
+//! Counters live in memory, so with several server instances each enforces
+//! its own share.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use tokio::time::{Duration, Instant};
+use uuid::Uuid;
+
+use crate::config::WebSocketConfig;
+
+/// Budget of a user relative to one of their connections
+pub const USER_BUDGET_FACTOR: f64 = 2.0;
+
+/// Rejected messages are counted over this window before they lead to a disconnect
+pub const VIOLATION_WINDOW: Duration = Duration::from_secs(10);
+
+/// Buckets untouched for this long are dropped
+const IDLE_BUCKET_TTL: Duration = Duration::from_secs(600);
+
+/// Kind of traffic with its own budget
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum WsBudget {
+    /// Edits, undo and redo, cursor and focus updates
+    Operations,
+    Chat,
+    /// Session joins, which may carry a password or join code guess
+    Joins,
+}
+
+impl WsBudget {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Operations => "operations",
+            Self::Chat => "chat",
+            Self::Joins => "joins",
+        }
+    }
+}
+
+/// Refill rate and capacity of a bucket
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BucketLimit {
+    pub per_second: f64,
+    pub burst: f64,
+}
+
+impl BucketLimit {
+    fn scaled(self, factor: f64) -> Self {
+        Self {
+            per_second: self.per_second * factor,
+            burst: self.burst * factor,
+        }
+    }
+}
+
+/// Limits of every budget for one connection
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WsRateLimits {
+    pub operations: BucketLimit,
+    pub chat: BucketLimit,
+    pub joins: BucketLimit,
+    /// Rejected messages within `VIOLATION_WINDOW` before the connection is closed
+    pub max_violations: u32,
+}
+
+impl WsRateLimits {
+    pub fn from_config(config: &WebSocketConfig) -> Self {
+        Self {
+            operations: BucketLimit {
+                per_second: config.rate_operations_per_second,
+                burst: config.rate_operations_burst as f64,
+            },
+            chat: BucketLimit {
+                per_second: config.rate_chat_per_second,
+                burst: config.rate_chat_burst as f64,
+            },
+            joins: BucketLimit {
+                per_second: config.rate_joins_per_minute / 60.0,
+                burst: config.rate_joins_burst as f64,
+            },
+            max_violations: config.rate_max_violations,
+        }
+    }
+
+    fn limit(&self, budget: WsBudget) -> BucketLimit {
+        match budget {
+            WsBudget::Operations => self.operations,
+            WsBudget::Chat => self.chat,
+            WsBudget::Joins => self.joins,
+        }
+    }
+}
+
+/// Decision on one message
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RateVerdict {
+    Allowed,
+    /// Reject the message; the budget has room again after `retry_after_ms`
+    Limited { retry_after_ms: u64 },
+    /// Reject the message and close the connection
+    Disconnect { violations: u32 },
+}
+
+#[derive(Debug, Clone, Copy)]
+struct TokenBucket {
+    tokens: f64,
+    updated_at: Instant,
+}
+
+impl TokenBucket {
+    fn full(limit: BucketLimit, now: Instant) -> Self {
+        Self {
+            tokens: limit.burst,
+            updated_at: now,
+        }
+    }
+
+    fn refill(&mut self, limit: BucketLimit, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated_at).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * limit.per_second).min(limit.burst);
+        self.updated_at = now;
+    }
+
+    /// Milliseconds until a token is available
+    fn wait_ms(&self, limit: BucketLimit) -> u64 {
+        if limit.per_second <= 0.0 {
+            return u64::MAX;
+        }
+        (((1.0 - self.tokens) / limit.per_second) * 1000.0).ceil().max(1.0) as u64
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Violations {
+    count: u32,
+    since: Instant,
+}
+
+#[derive(Debug, Default)]
+struct LimiterState {
+    connections: HashMap<(String, WsBudget), TokenBucket>,
+    users: HashMap<(Uuid, WsBudget), TokenBucket>,
+    violations: HashMap<String, Violations>,
+}
+
+/// Token buckets of all connections of this server
+#[derive(Debug)]
+pub struct WsRateLimiter {
+    limits: WsRateLimits,
+    state: Mutex<LimiterState>,
+}
+
+impl WsRateLimiter {
+    pub fn new(limits: WsRateLimits) -> Self {
+        Self {
+            limits,
+            state: Mutex::new(LimiterState::default()),
+        }
+    }
+
+    /// Meter one message of a connection, and of its user once authenticated
+    pub fn check(&self, connection_id: &str, user_id: Option<Uuid>, budget: WsBudget) -> RateVerdict {
+        self.check_at(connection_id, user_id, budget, Instant::now())
+    }
+
+    pub fn check_at(
+        &self,
+        connection_id: &str,
+        user_id: Option<Uuid>,
+        budget: WsBudget,
+        now: Instant,
+    ) -> RateVerdict {
+        let limit = self.limits.limit(budget);
+        let user_limit = limit.scaled(USER_BUDGET_FACTOR);
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+
+        let connection = state
+            .connections
+            .entry((connection_id.to_string(), budget))
+            .or_insert_with(|| TokenBucket::full(limit, now));
+        connection.refill(limit, now);
+        let mut wait_ms = (connection.tokens < 1.0).then(|| connection.wait_ms(limit));
+
+        if let Some(user_id) = user_id {
+            let user = state
+                .users
+                .entry((user_id, budget))
+                .or_insert_with(|| TokenBucket::full(user_limit, now));
+            user.refill(user_limit, now);
+            if user.tokens < 1.0 {
+                wait_ms = Some(wait_ms.unwrap_or(0).max(user.wait_ms(user_limit)));
+            }
+        }
+
+        let Some(retry_after_ms) = wait_ms else {
+            // Both have room; only now take the tokens, so a rejection costs nothing
+            if let Some(bucket) = state.connections.get_mut(&(connection_id.to_string(), budget)) {
+                bucket.tokens -= 1.0;
+            }
+            if let Some(bucket) = user_id.and_then(|user_id| state.users.get_mut(&(user_id, budget))) {
+                bucket.tokens -= 1.0;
+            }
+            return RateVerdict::Allowed;
+        };
+
+        let violations = state
+            .violations
+            .entry(connection_id.to_string())
+            .or_insert(Violations { count: 0, since: now });
+        if now.saturating_duration_since(violations.since) > VIOLATION_WINDOW {
+            *violations = Violations { count: 0, since: now };
+        }
+        violations.count += 1;
+
+        if violations.count >= self.limits.max_violations {
+            RateVerdict::Disconnect {
+                violations: violations.count,
+            }
+        } else {
+            RateVerdict::Limited { retry_after_ms }
+        }
+    }
+
+    /// Drop the buckets of a closed connection and of users idle for a while
+    pub fn remove_connection(&self, connection_id: &str) {
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        state.connections.retain(|(id, _), _| id != connection_id);
+        state.violations.remove(connection_id);
+        state
+            .users
+            .retain(|_, bucket| now.saturating_duration_since(bucket.updated_at) < IDLE_BUCKET_TTL);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limits() -> WsRateLimits {
+        WsRateLimits {
+            operations: BucketLimit { per_second: 10.0, burst: 20.0 },
+            chat: BucketLimit { per_second: 1.0, burst: 2.0 },
+            joins: BucketLimit { per_second: 0.1, burst: 1.0 },
+            max_violations: 5,
+        }
+    }
+
+    #[test]
+    fn test_flood_escalates_to_disconnect() {
+        let limiter = WsRateLimiter::new(limits());
+        let user = Some(Uuid::new_v4());
+        let now = Instant::now();
+
+        // The burst goes through, then messages are limited with a retry hint
+        for _ in 0..20 {
+            assert_eq!(limiter.check_at("flood", user, WsBudget::Operations, now), RateVerdict::Allowed);
+        }
+        for _ in 0..4 {
+            assert_eq!(
+                limiter.check_at("flood", user, WsBudget::Operations, now),
+                RateVerdict::Limited { retry_after_ms: 100 }
+            );
+        }
+
+        // Other budgets are separate
+        assert_eq!(limiter.check_at("flood", user, WsBudget::Chat, now), RateVerdict::Allowed);
+
+        // Keeping on within the violation window ends the connection
+        assert_eq!(
+            limiter.check_at("flood", user, WsBudget::Operations, now),
+            RateVerdict::Disconnect { violations: 5 }
+        );
+    }
+
+    #[test]
+    fn test_budget_refills_and_violations_expire() {
+        let limiter = WsRateLimiter::new(limits());
+        let now = Instant::now();
+
+        assert_eq!(limiter.check_at("c", None, WsBudget::Joins, now), RateVerdict::Allowed);
+        assert_eq!(
+            limiter.check_at("c", None, WsBudget::Joins, now),
+            RateVerdict::Limited { retry_after_ms: 10_000 }
+        );
+        for _ in 0..3 {
+            limiter.check_at("c", None, WsBudget::Joins, now);
+        }
+
+        // After the window the old violations no longer count
+        let later = now + VIOLATION_WINDOW + Duration::from_secs(1);
+        assert_eq!(limiter.check_at("c", None, WsBudget::Joins, later), RateVerdict::Allowed);
+        assert!(matches!(
+            limiter.check_at("c", None, WsBudget::Joins, later),
+            RateVerdict::Limited { .. }
+        ));
+    }
+
+    #[test]
+    fn test_user_budget_spans_connections() {
+        let limiter = WsRateLimiter::new(limits());
+        let user = Some(Uuid::new_v4());
+        let now = Instant::now();
+
+        // Two chat messages per connection, four for the user
+        for connection in ["a", "a", "b", "b"] {
+            assert_eq!(limiter.check_at(connection, user, WsBudget::Chat, now), RateVerdict::Allowed);
+        }
+        assert!(matches!(
+            limiter.check_at("c", user, WsBudget::Chat, now),
+            RateVerdict::Limited { .. }
+        ));
+        assert_eq!(limiter.check_at("c", None, WsBudget::Chat, now), RateVerdict::Allowed);
+
+        limiter.remove_connection("a");
+        assert_eq!(limiter.check_at("a", None, WsBudget::Chat, now), RateVerdict::Allowed);
+    }
+}
@@ -0,0 +1,416 @@
+//! OpenAPI document
+//!
+//! Handlers carry `#[utoipa::path]` annotations and request/response types
+//! derive `ToSchema`; the structs below collect them per route builder in
+//! `server.rs` and nest them under the same prefixes. The generated document
+//! is served at `GET /api/v1/openapi.json`, and with the `swagger-ui` feature
+//! a Swagger UI is mounted at `/api/docs`.
+
+use axum::{response::IntoResponse, Json};
+use serde::Serialize;
+use utoipa::openapi::security::{HttpAuthScheme, HttpBuilder, SecurityScheme};
+use utoipa::{Modify, OpenApi, ToSchema};
+
+use crate::handlers;
+
+/// Body of endpoints that only report an outcome
+#[derive(Debug, Serialize, ToSchema)]
+pub struct MessageResponse {
+    pub success: bool,
+    pub message: String,
+}
+
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "Texler API",
+        description = "REST API of the Texler collaborative LaTeX editor",
+        version = "1"
+    ),
+    paths(
+        handlers::health::liveness,
+        handlers::health::readiness,
+        handlers::capabilities::get_capabilities,
+    ),
+    nest(
+        (path = "/api/v1/auth", api = AuthApi, tags = ["auth"]),
+        (path = "/api/v1/users", api = UserApi, tags = ["users"]),
+        (path = "/api/v1/projects", api = ProjectApi, tags = ["projects"]),
+        (path = "/api/v1/files", api = FileApi, tags = ["files"]),
+        (path = "/api/v1/compilation", api = CompilationApi, tags = ["compilation"]),
+        (path = "/api/v1/latex", api = LatexProxyApi, tags = ["latex"]),
+        (path = "/api/v1/workspaces", api = WorkspaceApi, tags = ["workspaces"]),
+        (path = "/api/v1/collaboration", api = CollaborationApi, tags = ["collaboration"]),
+        (path = "/api/v1/admin", api = AdminApi, tags = ["admin"]),
+        (path = "/api/v1/discover", api = DiscoverApi, tags = ["discover"]),
+        (path = "/api/v1/shared", api = SharedApi, tags = ["shared"]),
+        (path = "/api/v1/search", api = SearchApi, tags = ["search"]),
+    ),
+    modifiers(&BearerAuth),
+    security(("bearer_auth" = []))
+)]
+pub struct ApiDoc;
+
+/// `auth_routes`
+#[derive(OpenApi)]
+#[openapi(paths(
+    handlers::auth::register,
+    handlers::auth::login,
+    handlers::auth::refresh,
+    handlers::auth::logout,
+    handlers::auth::forgot_password,
+    handlers::auth::reset_password,
+    handlers::auth::verify_email,
+    handlers::auth::undo_email_change,
+    handlers::auth::get_oidc_providers,
+    handlers::auth::oidc_login,
+    handlers::auth::oidc_callback,
+    handlers::auth::oidc_callback_post,
+))]
+struct AuthApi;
+
+/// `user_routes`
+#[derive(OpenApi)]
+#[openapi(paths(
+    handlers::user::get_current_user,
+    handlers::user::update_user,
+    handlers::user::get_preferences,
+    handlers::user::update_preferences,
+    handlers::user::preview_digest,
+    handlers::user::export_settings,
+    handlers::user::import_settings,
+    handlers::user::search_users,
+    handlers::user::list_notifications,
+    handlers::user::mark_notification_read,
+    handlers::user::get_activity_export,
+    handlers::user::download_activity_export,
+    handlers::user::upload_avatar,
+    handlers::user::delete_avatar,
+    handlers::user::get_onboarding,
+    handlers::user::dismiss_onboarding,
+    handlers::user::get_announcements,
+    handlers::user::acknowledge_announcements,
+    handlers::user::request_email_change,
+    handlers::user::cancel_email_change,
+    handlers::user::confirm_email_change,
+    handlers::user::get_avatar,
+    handlers::dictionary::list_personal_dictionary,
+    handlers::dictionary::add_personal_terms,
+    handlers::dictionary::remove_personal_term,
+))]
+struct UserApi;
+
+/// `project_routes`
+#[derive(OpenApi)]
+#[openapi(paths(
+    handlers::project::list_projects,
+    handlers::project::create_project,
+    handlers::project::get_project,
+    handlers::project::update_project,
+    handlers::project::delete_project,
+    handlers::project::get_collaborators,
+    handlers::project::add_collaborator,
+    handlers::project::remove_collaborator,
+    handlers::project::transfer_ownership,
+    handlers::project::get_ownership_transfer,
+    handlers::project::cancel_ownership_transfer,
+    handlers::project::accept_ownership_transfer,
+    handlers::project::get_contended_files,
+    handlers::project::list_invitations,
+    handlers::project::invite_collaborators,
+    handlers::project::revoke_invitation,
+    handlers::project::create_embed_token,
+    handlers::project::list_embed_tokens,
+    handlers::project::revoke_embed_token,
+    handlers::project::set_project_secret,
+    handlers::project::list_project_secrets,
+    handlers::project::delete_project_secret,
+    handlers::project::get_latest_pdf,
+    handlers::project::freeze_project,
+    handlers::project::unfreeze_project,
+    handlers::project::move_project,
+    handlers::project::favorite_project,
+    handlers::project::unfavorite_project,
+    handlers::project::compile_project,
+    handlers::project::compile_all,
+    handlers::project::get_compile_preflight,
+    handlers::compilation::list_project_jobs,
+    handlers::compilation::prune_project_jobs,
+    handlers::project::get_project_stats,
+    handlers::project::get_word_count,
+    handlers::project::get_storage_breakdown,
+    handlers::project::get_activity,
+    handlers::project::get_activity_counts,
+    handlers::project::export_activity,
+    handlers::project::get_compile_environment_diff,
+    handlers::project::get_compile_diff,
+    handlers::file::get_project_tree,
+    handlers::project::get_project_readme,
+    handlers::project::get_autocomplete,
+    handlers::project::get_mention_candidates,
+    handlers::project::get_include_graph,
+    handlers::project::get_project_outline,
+    handlers::project::get_settings_history,
+    handlers::project::rollback_settings,
+    handlers::project::export_project,
+    handlers::project::export_checksum,
+    handlers::dictionary::list_project_dictionary,
+    handlers::dictionary::add_project_terms,
+    handlers::dictionary::import_project_terms,
+    handlers::dictionary::get_effective_dictionary,
+    handlers::dictionary::remove_project_term,
+    handlers::project::clone_project,
+    handlers::project::create_project_from_template,
+    handlers::project::import_project,
+    handlers::project::import_project_from_git,
+    handlers::project::get_git_import,
+    handlers::project::search_projects,
+    handlers::project::active_counts,
+    handlers::project::get_project_switcher,
+    handlers::project::list_trash,
+))]
+struct ProjectApi;
+
+/// `file_routes`
+#[derive(OpenApi)]
+#[openapi(paths(
+    handlers::file::list_files,
+    handlers::file::create_file,
+    handlers::file::get_file,
+    handlers::file::update_file,
+    handlers::file::delete_file,
+    handlers::file::get_file_content,
+    handlers::file::get_file_blame,
+    handlers::file::update_file_content,
+    handlers::file::download_file,
+    handlers::file::format_file,
+    handlers::file::format_selection,
+    handlers::file::create_share_link,
+    handlers::file::list_share_links,
+    handlers::file::revoke_share_link,
+    handlers::file::upload_file,
+    handlers::file::create_upload_session,
+    handlers::file::get_upload_session,
+    handlers::file::upload_chunk,
+    handlers::file::complete_upload,
+    handlers::file::get_file_tree,
+    handlers::file::search_files,
+))]
+struct FileApi;
+
+/// `compilation_routes`
+#[derive(OpenApi)]
+#[openapi(paths(
+    handlers::compilation::list_jobs,
+    handlers::compilation::create_job,
+    handlers::compilation::get_job,
+    handlers::compilation::cancel_job,
+    handlers::compilation::get_job_logs,
+    handlers::compilation::get_job_artifacts,
+    handlers::compilation::list_job_thumbnails,
+    handlers::compilation::get_job_thumbnail,
+    handlers::compilation::get_batch,
+    handlers::compilation::cancel_batch,
+    handlers::compilation::get_queue_status,
+    handlers::compilation::list_templates,
+    handlers::compilation::create_template,
+    handlers::compilation::get_template,
+    handlers::compilation::rate_template,
+    handlers::compilation::fork_template,
+    handlers::compilation::get_compilation_stats,
+))]
+struct CompilationApi;
+
+/// `latex_proxy_routes`
+#[derive(OpenApi)]
+#[openapi(paths(
+    handlers::latex_proxy::compile_latex,
+    handlers::latex_proxy::latex_health_check,
+    handlers::latex_proxy::render_inline,
+    handlers::latex_proxy::get_inline_render,
+))]
+struct LatexProxyApi;
+
+/// `workspace_routes`
+#[derive(OpenApi)]
+#[openapi(paths(
+    handlers::workspace::list_workspaces,
+    handlers::workspace::create_workspace,
+    handlers::workspace::get_workspace,
+    handlers::workspace::export_workspace_activity,
+    handlers::workspace::get_workspace_storage,
+    handlers::workspace::get_workspace_usage,
+    handlers::workspace::list_folders,
+    handlers::workspace::create_folder,
+    handlers::workspace::rename_folder,
+    handlers::workspace::move_folder,
+    handlers::workspace::delete_folder,
+    handlers::workspace::create_project,
+    handlers::workspace::get_project,
+    handlers::workspace::add_file,
+    handlers::workspace::update_file,
+    handlers::workspace::set_main_file,
+))]
+struct WorkspaceApi;
+
+/// `collaboration_routes`
+#[derive(OpenApi)]
+#[openapi(paths(
+    handlers::collaboration::list_sessions,
+    handlers::collaboration::create_session,
+    handlers::collaboration::upcoming_sessions,
+    handlers::collaboration::get_session,
+    handlers::collaboration::update_session,
+    handlers::collaboration::delete_session,
+    handlers::collaboration::join_session,
+    handlers::collaboration::set_session_password,
+    handlers::collaboration::create_join_code,
+    handlers::collaboration::enable_anonymity,
+    handlers::collaboration::disable_anonymity,
+    handlers::collaboration::leave_session,
+    handlers::collaboration::get_participants,
+    handlers::collaboration::create_operation,
+    handlers::collaboration::get_messages,
+    handlers::collaboration::send_message,
+    handlers::collaboration::edit_message,
+    handlers::collaboration::upload_attachment,
+    handlers::collaboration::download_attachment,
+    handlers::collaboration::get_attachment_thumbnail,
+    handlers::collaboration::invite_participant,
+    handlers::collaboration::get_session_stats,
+    handlers::collaboration::get_session_ics,
+    handlers::collaboration::get_invitation,
+    handlers::collaboration::accept_invitation,
+))]
+struct CollaborationApi;
+
+/// `admin_routes`
+#[derive(OpenApi)]
+#[openapi(paths(
+    handlers::admin::blob_consistency,
+    handlers::admin::session_compaction_dry_run,
+    handlers::admin::unlock_user,
+    handlers::admin::list_tasks,
+    handlers::admin::run_task,
+    handlers::admin::set_template_verified,
+    handlers::admin::metrics,
+    handlers::admin::websocket_connections,
+    handlers::admin::list_quarantined_files,
+    handlers::admin::release_quarantined_file,
+    handlers::admin::start_reindex,
+    handlers::admin::get_reindex_status,
+    handlers::admin::migrations_applied,
+    handlers::admin::integrity_summary,
+    handlers::admin::start_integrity_scan,
+    handlers::admin::effective_config,
+    handlers::admin::compile_environment,
+    handlers::admin::compile_sandbox_self_test,
+    handlers::admin::list_purges,
+    handlers::admin::list_collections,
+    handlers::admin::create_collection,
+    handlers::admin::update_collection,
+    handlers::admin::delete_collection,
+    handlers::admin::list_announcements,
+    handlers::admin::create_announcement,
+    handlers::admin::update_announcement,
+    handlers::admin::delete_announcement,
+    handlers::admin::seed,
+    handlers::admin::performance,
+    handlers::admin::conflicts,
+    handlers::admin::conflict_resolution,
+    handlers::admin::usage,
+))]
+struct AdminApi;
+
+/// `discover_routes`
+#[derive(OpenApi)]
+#[openapi(paths(
+    handlers::discovery::trending_projects,
+    handlers::discovery::recent_projects,
+    handlers::discovery::list_collections,
+))]
+struct DiscoverApi;
+
+/// `shared_routes`
+#[derive(OpenApi)]
+#[openapi(paths(
+    handlers::shared::get_shared_file,
+    handlers::shared::get_shared_file_pdf,
+))]
+struct SharedApi;
+
+/// `search_routes`
+#[derive(OpenApi)]
+#[openapi(paths(handlers::search::quick_search))]
+struct SearchApi;
+
+/// Registers the JWT bearer scheme that protected endpoints require
+struct BearerAuth;
+
+impl Modify for BearerAuth {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "bearer_auth",
+            SecurityScheme::Http(
+                HttpBuilder::new()
+                    .scheme(HttpAuthScheme::Bearer)
+                    .bearer_format("JWT")
+                    .build(),
+            ),
+        );
+    }
+}
+
+/// Serve the OpenAPI document
+pub async fn openapi_json() -> impl IntoResponse {
+    Json(ApiDoc::openapi())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Checked-in copy of the document; breaking changes to it must be deliberate
+    const SNAPSHOT: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/openapi.json");
+
+    #[test]
+    fn test_document_matches_snapshot() {
+        let document = ApiDoc::openapi().to_pretty_json().unwrap() + "\n";
+
+        if std::env::var_os("UPDATE_OPENAPI_SNAPSHOT").is_some() {
+            std::fs::write(SNAPSHOT, &document).unwrap();
+            return;
+        }
+
+        let snapshot = std::fs::read_to_string(SNAPSHOT).unwrap_or_default();
+        assert!(
+            document == snapshot,
+            "OpenAPI document differs from openapi.json; review the change and rerun with UPDATE_OPENAPI_SNAPSHOT=1 to accept it"
+        );
+    }
+
+    #[test]
+    fn test_enums_use_serde_names() {
+        let document = serde_json::to_value(ApiDoc::openapi()).unwrap();
+        let schemas = &document["components"]["schemas"];
+
+        assert_eq!(schemas["LatexEngine"]["enum"][0], "pdflatex");
+        assert!(schemas["UserRole"]["enum"]
+            .as_array()
+            .unwrap()
+            .contains(&serde_json::json!("owner")));
+    }
+
+    #[test]
+    fn test_public_endpoints_opt_out_of_auth() {
+        let document = serde_json::to_value(ApiDoc::openapi()).unwrap();
+        let paths = &document["paths"];
+
+        assert_eq!(paths["/api/v1/auth/login"]["post"]["security"], serde_json::json!([{}]));
+        assert_eq!(paths["/api/v1/capabilities"]["get"]["security"], serde_json::json!([{}]));
+        assert_eq!(paths["/api/v1/discover/trending"]["get"]["security"], serde_json::json!([{}]));
+        assert!(paths["/api/v1/projects/{id}"]["get"]["security"].is_null());
+        assert_eq!(document["security"], serde_json::json!([{ "bearer_auth": [] }]));
+    }
+}
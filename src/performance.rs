@@ -0,0 +1,538 @@
+//! In-memory performance capture
+//!
+//! With `FEATURE_METRICS` on, the logging middleware records how long each
+//! request took and how it ended under its route, and the hottest model
+//! functions run their queries through [`timed`], which logs a `Slow query`
+//! event for those taking longer than `DATABASE_SLOW_QUERY_MS`. The event
+//! names the query site and the type and size of each bound parameter, never
+//! the values. Requests log under a span carrying the caller's user ID, so
+//! slow queries can be traced back to the user who caused them.
+//!
+//! `GET /admin/performance` reports latency percentiles and error rates per
+//! route, the query sites most often slow in the last hour and how busy the
+//! connection pool has been. Every history is a ring buffer of fixed size
+//! and nothing survives a restart.
+//!
+//! Model functions do not see the application state, so the slow query log
+//! is shared by the whole process and set up by [`PerformanceMonitor::new`].
+
+use chrono::{DateTime, Utc};
+use futures::future::BoxFuture;
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::config::Config;
+use crate::error::AppError;
+use crate::server::AppState;
+
+/// Period the report looks back on
+pub const WINDOW: Duration = Duration::from_secs(3600);
+
+/// Requests kept per route
+const ROUTE_SAMPLES: usize = 1024;
+
+/// Slow queries kept across all sites
+const SLOW_QUERIES: usize = 1024;
+
+/// Time between two samples of the connection pool
+const POOL_SAMPLE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Pool samples kept, an hour's worth
+const POOL_SAMPLES: usize = (WINDOW.as_secs() / POOL_SAMPLE_INTERVAL.as_secs()) as usize;
+
+/// Route of requests that matched none
+const UNMATCHED_ROUTE: &str = "unmatched";
+
+/// Slow query log the model functions report to
+static QUERY_LOG: SlowQueryLog = SlowQueryLog::new();
+
+/// Type and size of a bound query parameter
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QueryParam {
+    pub kind: &'static str,
+    /// Bytes
+    pub size: usize,
+}
+
+impl QueryParam {
+    pub fn of<T: ParamShape + ?Sized>(value: &T) -> Self {
+        value.shape()
+    }
+}
+
+impl std::fmt::Display for QueryParam {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.kind, self.size)
+    }
+}
+
+/// Values that can be described as a [`QueryParam`]
+pub trait ParamShape {
+    fn shape(&self) -> QueryParam;
+}
+
+impl ParamShape for Uuid {
+    fn shape(&self) -> QueryParam {
+        QueryParam { kind: "uuid", size: 16 }
+    }
+}
+
+impl ParamShape for str {
+    fn shape(&self) -> QueryParam {
+        QueryParam { kind: "text", size: self.len() }
+    }
+}
+
+impl ParamShape for String {
+    fn shape(&self) -> QueryParam {
+        self.as_str().shape()
+    }
+}
+
+impl ParamShape for i32 {
+    fn shape(&self) -> QueryParam {
+        QueryParam { kind: "int4", size: 4 }
+    }
+}
+
+impl ParamShape for i64 {
+    fn shape(&self) -> QueryParam {
+        QueryParam { kind: "int8", size: 8 }
+    }
+}
+
+impl ParamShape for bool {
+    fn shape(&self) -> QueryParam {
+        QueryParam { kind: "bool", size: 1 }
+    }
+}
+
+impl<T: ParamShape> ParamShape for Option<T> {
+    fn shape(&self) -> QueryParam {
+        match self {
+            Some(value) => value.shape(),
+            None => QueryParam { kind: "null", size: 0 },
+        }
+    }
+}
+
+impl<T: ParamShape + ?Sized> ParamShape for &T {
+    fn shape(&self) -> QueryParam {
+        (**self).shape()
+    }
+}
+
+/// Await the query at `site`, logging it when it is slow
+///
+/// `site` is a fixed name for the query, like `project.find_by_id`, and
+/// `params` describe what it binds, in order.
+pub async fn timed<F: Future>(site: &'static str, params: &[QueryParam], query: F) -> F::Output {
+    if !QUERY_LOG.enabled.load(Ordering::Relaxed) {
+        return query.await;
+    }
+    let started = Instant::now();
+    let output = query.await;
+    QUERY_LOG.observe(site, params, started.elapsed());
+    output
+}
+
+/// A query that took longer than the threshold
+#[derive(Debug, Clone)]
+struct SlowQuery {
+    at: Instant,
+    recorded_at: DateTime<Utc>,
+    site: &'static str,
+    params: String,
+    duration: Duration,
+}
+
+/// Recent slow queries
+struct SlowQueryLog {
+    enabled: AtomicBool,
+    threshold_ms: AtomicU64,
+    recent: Mutex<VecDeque<SlowQuery>>,
+}
+
+impl SlowQueryLog {
+    const fn new() -> Self {
+        Self {
+            enabled: AtomicBool::new(false),
+            threshold_ms: AtomicU64::new(0),
+            recent: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// Log slow queries when metrics are on and the threshold is not 0
+    fn configure(&self, metrics: bool, threshold_ms: u64) {
+        self.threshold_ms.store(threshold_ms, Ordering::Relaxed);
+        self.enabled.store(metrics && threshold_ms > 0, Ordering::Relaxed);
+    }
+
+    fn observe(&self, site: &'static str, params: &[QueryParam], duration: Duration) {
+        if duration.as_millis() < self.threshold_ms.load(Ordering::Relaxed) as u128 {
+            return;
+        }
+        let params = params.iter().map(|param| param.to_string()).collect::<Vec<_>>().join(", ");
+        tracing::warn!(
+            site,
+            params = %params,
+            duration_ms = duration.as_millis() as u64,
+            "Slow query"
+        );
+        self.record(site, params, duration, Instant::now());
+    }
+
+    fn record(&self, site: &'static str, params: String, duration: Duration, at: Instant) {
+        let mut recent = self.recent.lock().unwrap();
+        if recent.len() == SLOW_QUERIES {
+            recent.pop_front();
+        }
+        recent.push_back(SlowQuery { at, recorded_at: Utc::now(), site, params, duration });
+    }
+
+    /// Sites with the most slow queries within the window before `now`
+    fn top_sites(&self, limit: usize, now: Instant) -> Vec<SlowQuerySite> {
+        let recent = self.recent.lock().unwrap();
+        let mut sites: HashMap<&'static str, SlowQuerySite> = HashMap::new();
+        let mut total: HashMap<&'static str, Duration> = HashMap::new();
+        for query in recent.iter().filter(|query| now.duration_since(query.at) <= WINDOW) {
+            let site = sites.entry(query.site).or_insert_with(|| SlowQuerySite {
+                site: query.site.to_string(),
+                count: 0,
+                mean_ms: 0,
+                max_ms: 0,
+                params: String::new(),
+                last_seen_at: query.recorded_at,
+            });
+            site.count += 1;
+            site.max_ms = site.max_ms.max(query.duration.as_millis() as u64);
+            site.params = query.params.clone();
+            site.last_seen_at = query.recorded_at;
+            *total.entry(query.site).or_default() += query.duration;
+        }
+
+        let mut sites: Vec<SlowQuerySite> = sites
+            .into_iter()
+            .map(|(name, mut site)| {
+                site.mean_ms = (total[name].as_millis() / site.count as u128) as u64;
+                site
+            })
+            .collect();
+        sites.sort_by(|a, b| b.count.cmp(&a.count).then(b.max_ms.cmp(&a.max_ms)).then(a.site.cmp(&b.site)));
+        sites.truncate(limit);
+        sites
+    }
+}
+
+/// How a request ended
+#[derive(Debug, Clone, Copy)]
+struct RequestSample {
+    at: Instant,
+    duration: Duration,
+    status: u16,
+}
+
+/// A query site with slow queries
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+pub struct SlowQuerySite {
+    pub site: String,
+    /// Slow queries in the window
+    pub count: u64,
+    pub mean_ms: u64,
+    pub max_ms: u64,
+    /// Parameters of the latest, as `type:bytes`
+    pub params: String,
+    pub last_seen_at: DateTime<Utc>,
+}
+
+/// Latency and outcomes of a route in the window
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct RouteLatency {
+    /// Method and path pattern, like `GET /api/v1/projects/:id`
+    pub route: String,
+    pub requests: u64,
+    pub p50_ms: f64,
+    pub p95_ms: f64,
+    pub p99_ms: f64,
+    /// Share of requests answered with a 5xx status
+    pub error_rate: f64,
+    /// Share of requests answered with a 4xx status
+    pub client_error_rate: f64,
+}
+
+/// Connections of the database pool at one time
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct PoolSample {
+    pub at: DateTime<Utc>,
+    /// Open connections
+    pub size: u32,
+    pub idle: u32,
+    pub max_connections: u32,
+    /// Share of the maximum in use
+    pub saturation: f64,
+}
+
+/// Performance of the last hour
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct PerformanceReport {
+    /// Whether anything is recorded, see `FEATURE_METRICS`
+    pub enabled: bool,
+    pub window_secs: u64,
+    /// Busiest first; each route keeps its last 1024 requests
+    pub routes: Vec<RouteLatency>,
+    /// 0 when slow queries are not logged
+    pub slow_query_threshold_ms: u64,
+    /// Most frequent first
+    pub slow_queries: Vec<SlowQuerySite>,
+    /// Oldest first, one every 10 seconds
+    pub pool: Vec<PoolSample>,
+}
+
+/// Request latencies per route and pool history
+pub struct PerformanceMonitor {
+    enabled: bool,
+    routes: Mutex<HashMap<String, VecDeque<RequestSample>>>,
+    pool: Mutex<VecDeque<PoolSample>>,
+}
+
+impl PerformanceMonitor {
+    /// Monitor that records when metrics are on; also sets up the slow query log
+    pub fn new(config: &Config) -> Self {
+        QUERY_LOG.configure(config.features.metrics, config.database.slow_query_ms);
+        Self {
+            enabled: config.features.metrics,
+            routes: Mutex::new(HashMap::new()),
+            pool: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Record a request to `route`, the matched path pattern if there was one
+    pub fn record_request(&self, method: &str, route: Option<&str>, status: u16, duration: Duration) {
+        if self.enabled {
+            let route = format!("{} {}", method, route.unwrap_or(UNMATCHED_ROUTE));
+            self.record_request_at(route, status, duration, Instant::now());
+        }
+    }
+
+    fn record_request_at(&self, route: String, status: u16, duration: Duration, at: Instant) {
+        let mut routes = self.routes.lock().unwrap();
+        let samples = routes.entry(route).or_default();
+        if samples.len() == ROUTE_SAMPLES {
+            samples.pop_front();
+        }
+        samples.push_back(RequestSample { at, duration, status });
+    }
+
+    /// Note how many connections of `pool` are in use
+    pub fn sample_pool(&self, pool: &sqlx::PgPool) {
+        let size = pool.size();
+        let idle = pool.num_idle() as u32;
+        let max_connections = pool.options().get_max_connections();
+        self.record_pool(PoolSample {
+            at: Utc::now(),
+            size,
+            idle,
+            max_connections,
+            saturation: size.saturating_sub(idle) as f64 / max_connections.max(1) as f64,
+        });
+    }
+
+    fn record_pool(&self, sample: PoolSample) {
+        let mut pool = self.pool.lock().unwrap();
+        if pool.len() == POOL_SAMPLES {
+            pool.pop_front();
+        }
+        pool.push_back(sample);
+    }
+
+    /// Current report, with at most `limit` query sites
+    pub fn report(&self, limit: usize) -> PerformanceReport {
+        self.report_at(limit, Instant::now())
+    }
+
+    fn report_at(&self, limit: usize, now: Instant) -> PerformanceReport {
+        let mut routes: Vec<RouteLatency> = self
+            .routes
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(|(route, samples)| route_latency(route, samples, now))
+            .collect();
+        routes.sort_by(|a, b| b.requests.cmp(&a.requests).then(a.route.cmp(&b.route)));
+
+        PerformanceReport {
+            enabled: self.enabled,
+            window_secs: WINDOW.as_secs(),
+            routes,
+            slow_query_threshold_ms: if QUERY_LOG.enabled.load(Ordering::Relaxed) {
+                QUERY_LOG.threshold_ms.load(Ordering::Relaxed)
+            } else {
+                0
+            },
+            slow_queries: QUERY_LOG.top_sites(limit, now),
+            pool: self.pool.lock().unwrap().iter().cloned().collect(),
+        }
+    }
+}
+
+/// Percentiles and error rates of the samples within the window
+fn route_latency(route: &str, samples: &VecDeque<RequestSample>, now: Instant) -> Option<RouteLatency> {
+    let recent: Vec<&RequestSample> =
+        samples.iter().filter(|sample| now.duration_since(sample.at) <= WINDOW).collect();
+    if recent.is_empty() {
+        return None;
+    }
+
+    let mut durations: Vec<Duration> = recent.iter().map(|sample| sample.duration).collect();
+    durations.sort();
+    let share = |range: std::ops::RangeInclusive<u16>| {
+        recent.iter().filter(|sample| range.contains(&sample.status)).count() as f64 / recent.len() as f64
+    };
+
+    Some(RouteLatency {
+        route: route.to_string(),
+        requests: recent.len() as u64,
+        p50_ms: percentile(&durations, 0.50),
+        p95_ms: percentile(&durations, 0.95),
+        p99_ms: percentile(&durations, 0.99),
+        error_rate: share(500..=599),
+        client_error_rate: share(400..=499),
+    })
+}
+
+/// Nearest-rank percentile of sorted durations, in milliseconds
+fn percentile(sorted: &[Duration], p: f64) -> f64 {
+    let rank = ((p * sorted.len() as f64).ceil() as usize).clamp(1, sorted.len());
+    sorted[rank - 1].as_micros() as f64 / 1000.0
+}
+
+/// Samples the database pool for the performance report
+pub struct PoolSampleTask;
+
+impl crate::tasks::PeriodicTask for PoolSampleTask {
+    fn name(&self) -> &'static str {
+        "pool_sample"
+    }
+
+    fn interval(&self) -> Duration {
+        POOL_SAMPLE_INTERVAL
+    }
+
+    fn run<'a>(&'a self, state: &'a AppState) -> BoxFuture<'a, Result<(), AppError>> {
+        Box::pin(async move {
+            if state.performance.enabled() {
+                state.performance.sample_pool(&state.db_pool);
+            }
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A recording monitor that leaves the process-wide slow query log alone
+    fn monitor() -> PerformanceMonitor {
+        PerformanceMonitor {
+            enabled: true,
+            routes: Mutex::new(HashMap::new()),
+            pool: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    #[test]
+    fn test_route_percentiles_and_error_rates() {
+        let monitor = monitor();
+        let start = Instant::now();
+        for ms in 1..=100u64 {
+            let status = match ms {
+                1..=2 => 500,
+                3..=7 => 404,
+                _ => 200,
+            };
+            monitor.record_request_at(
+                "GET /api/v1/projects/:id".to_string(),
+                status,
+                Duration::from_millis(ms),
+                start,
+            );
+        }
+        monitor.record_request_at("POST /api/v1/projects/".to_string(), 201, Duration::from_millis(40), start);
+
+        let report = monitor.report_at(10, start);
+        assert_eq!(report.routes.len(), 2);
+        let project = &report.routes[0];
+        assert_eq!(project.route, "GET /api/v1/projects/:id");
+        assert_eq!(project.requests, 100);
+        assert_eq!(project.p50_ms, 50.0);
+        assert_eq!(project.p95_ms, 95.0);
+        assert_eq!(project.p99_ms, 99.0);
+        assert_eq!(project.error_rate, 0.02);
+        assert_eq!(project.client_error_rate, 0.05);
+        assert_eq!(report.routes[1].p99_ms, 40.0);
+
+        // Requests older than the window no longer count
+        let later = monitor.report_at(10, start + WINDOW + Duration::from_secs(1));
+        assert!(later.routes.is_empty());
+    }
+
+    #[test]
+    fn test_ring_buffers_are_bounded() {
+        let monitor = monitor();
+        let start = Instant::now();
+        for ms in 0..(ROUTE_SAMPLES as u64 + 10) {
+            monitor.record_request_at("GET /health".to_string(), 200, Duration::from_millis(ms), start);
+        }
+        for _ in 0..(POOL_SAMPLES + 5) {
+            monitor.record_pool(PoolSample { at: Utc::now(), size: 5, idle: 1, max_connections: 20, saturation: 0.2 });
+        }
+
+        let report = monitor.report_at(10, start);
+        assert_eq!(report.routes[0].requests, ROUTE_SAMPLES as u64);
+        // The oldest, fastest requests were dropped
+        assert_eq!(report.routes[0].p50_ms, (10 + ROUTE_SAMPLES as u64 / 2 - 1) as f64);
+        assert_eq!(report.pool.len(), POOL_SAMPLES);
+    }
+
+    #[test]
+    fn test_slow_query_sites_are_ranked() {
+        let log = SlowQueryLog::new();
+        log.configure(true, 100);
+        let start = Instant::now();
+        let params = |values: &[QueryParam]| values.iter().map(|p| p.to_string()).collect::<Vec<_>>().join(", ");
+
+        let project = params(&[QueryParam::of(&Uuid::nil()), QueryParam::of(&Uuid::nil())]);
+        let file = params(&[QueryParam::of(&Uuid::nil()), QueryParam::of(&"chapters/one.tex"), QueryParam::of(&None::<i64>)]);
+        assert_eq!(project, "uuid:16, uuid:16");
+        assert_eq!(file, "uuid:16, text:16, null:0");
+
+        log.record("project.find_by_id", project.clone(), Duration::from_millis(300), start);
+        log.record("project.find_by_id", project, Duration::from_millis(100), start);
+        log.record("file.find_by_path", file, Duration::from_millis(900), start);
+
+        let sites = log.top_sites(10, start);
+        assert_eq!(sites.len(), 2);
+        assert_eq!(sites[0].site, "project.find_by_id");
+        assert_eq!(sites[0].count, 2);
+        assert_eq!(sites[0].mean_ms, 200);
+        assert_eq!(sites[0].max_ms, 300);
+        assert_eq!(sites[1].params, "uuid:16, text:16, null:0");
+        assert_eq!(log.top_sites(1, start).len(), 1);
+        assert!(log.top_sites(10, start + WINDOW + Duration::from_secs(1)).is_empty());
+
+        // Only queries at or above the threshold are kept
+        log.observe("job.find_by_id", &[], Duration::from_millis(99));
+        assert!(log.top_sites(10, Instant::now()).iter().all(|site| site.site != "job.find_by_id"));
+    }
+}
@@ -0,0 +1,352 @@
+//! Malware scanning of uploaded files
+//!
+//! Uploads pass through a `FileScanner` before a file row is created, so
+//! content other collaborators download has been checked. The scanner is
+//! picked by `SCAN_BACKEND`: the default does nothing and leaves files
+//! unscanned, `clamav` streams each file to clamd with its INSTREAM command
+//! over TCP or a unix socket. Content is read from disk or the database in
+//! fixed-size chunks and never buffered whole, and every scan opens its own
+//! connection, so concurrent uploads do not share state. Files above
+//! `SCAN_MAX_SIZE` are accepted without a scan and flagged instead, since
+//! clamd refuses streams beyond its own limit anyway.
+
+use futures::future::BoxFuture;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::config::ScanConfig;
+use crate::error::AppError;
+
+/// Bytes sent to clamd per INSTREAM chunk
+pub(crate) const INSTREAM_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Longest reply clamd sends for a scan
+const MAX_REPLY_LEN: u64 = 4096;
+
+/// What scanning a file found
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScanVerdict {
+    Clean,
+    Infected { signature: String },
+    /// Scanning is disabled
+    NotScanned,
+    /// Larger than the scan limit, accepted without a scan
+    Skipped,
+}
+
+/// Checks content for malware
+pub trait FileScanner: Send + Sync + 'static {
+    /// Name for logs
+    fn name(&self) -> &'static str;
+
+    /// Whether this scanner looks at content at all
+    fn enabled(&self) -> bool {
+        true
+    }
+
+    /// Scan `content` to its end
+    fn scan<'a>(
+        &'a self,
+        content: &'a mut (dyn AsyncRead + Send + Unpin),
+    ) -> BoxFuture<'a, Result<ScanVerdict, AppError>>;
+}
+
+/// Scanner used when scanning is disabled
+pub struct NoopScanner;
+
+impl FileScanner for NoopScanner {
+    fn name(&self) -> &'static str {
+        "none"
+    }
+
+    fn enabled(&self) -> bool {
+        false
+    }
+
+    fn scan<'a>(
+        &'a self,
+        _content: &'a mut (dyn AsyncRead + Send + Unpin),
+    ) -> BoxFuture<'a, Result<ScanVerdict, AppError>> {
+        Box::pin(async { Ok(ScanVerdict::NotScanned) })
+    }
+}
+
+/// Where clamd listens
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ClamdAddress {
+    Tcp(String),
+    Unix(PathBuf),
+}
+
+impl ClamdAddress {
+    /// Parse `tcp://host:port`, `host:port`, `unix:/path` or an absolute socket path
+    pub fn parse(address: &str) -> Result<Self, AppError> {
+        let address = address.trim();
+        if let Some(path) = address.strip_prefix("unix://").or_else(|| address.strip_prefix("unix:")) {
+            return Ok(Self::Unix(PathBuf::from(path)));
+        }
+        if address.starts_with('/') {
+            return Ok(Self::Unix(PathBuf::from(address)));
+        }
+
+        let host = address.strip_prefix("tcp://").unwrap_or(address);
+        if host.rsplit_once(':').is_none_or(|(name, port)| name.is_empty() || port.parse::<u16>().is_err()) {
+            return Err(AppError::Config(format!("Invalid clamd address: {}", address)));
+        }
+
+        Ok(Self::Tcp(host.to_string()))
+    }
+}
+
+/// Scans with a clamd daemon
+pub struct ClamAvScanner {
+    address: ClamdAddress,
+    timeout: Duration,
+}
+
+impl ClamAvScanner {
+    pub fn new(address: ClamdAddress, timeout: Duration) -> Self {
+        Self { address, timeout }
+    }
+
+    async fn scan_over<S>(stream: S, content: &mut (dyn AsyncRead + Send + Unpin)) -> Result<ScanVerdict, AppError>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let (mut reader, mut writer) = tokio::io::split(stream);
+
+        let sent = instream(&mut writer, content).await;
+
+        // clamd answers and hangs up early when the stream exceeds its limit,
+        // so its reply explains a failed write better than the write error
+        let mut reply = Vec::new();
+        let received = (&mut reader).take(MAX_REPLY_LEN).read_to_end(&mut reply).await;
+
+        match (sent, received) {
+            (Ok(()), Ok(_)) => parse_reply(&String::from_utf8_lossy(&reply)),
+            (Err(_), Ok(_)) if !reply.is_empty() => parse_reply(&String::from_utf8_lossy(&reply)),
+            (Err(e), _) | (Ok(()), Err(e)) => Err(AppError::Upstream(format!("clamd connection failed: {}", e))),
+        }
+    }
+}
+
+impl FileScanner for ClamAvScanner {
+    fn name(&self) -> &'static str {
+        "clamav"
+    }
+
+    fn scan<'a>(
+        &'a self,
+        content: &'a mut (dyn AsyncRead + Send + Unpin),
+    ) -> BoxFuture<'a, Result<ScanVerdict, AppError>> {
+        Box::pin(async move {
+            let scan = async {
+                let unreachable = |e: std::io::Error| AppError::Upstream(format!("clamd is unreachable: {}", e));
+                match &self.address {
+                    ClamdAddress::Tcp(address) => {
+                        let stream = tokio::net::TcpStream::connect(address).await.map_err(unreachable)?;
+                        Self::scan_over(stream, content).await
+                    }
+                    #[cfg(unix)]
+                    ClamdAddress::Unix(path) => {
+                        let stream = tokio::net::UnixStream::connect(path).await.map_err(unreachable)?;
+                        Self::scan_over(stream, content).await
+                    }
+                    #[cfg(not(unix))]
+                    ClamdAddress::Unix(_) => Err(AppError::Config(
+                        "clamd unix sockets are not supported on this platform".to_string(),
+                    )),
+                }
+            };
+
+            tokio::time::timeout(self.timeout, scan)
+                .await
+                .map_err(|_| AppError::Upstream(format!("clamd did not answer within {:?}", self.timeout)))?
+        })
+    }
+}
+
+/// Send `content` with clamd's INSTREAM command: length-prefixed chunks
+/// ending with an empty one
+async fn instream<W>(writer: &mut W, content: &mut (dyn AsyncRead + Send + Unpin)) -> std::io::Result<()>
+where
+    W: AsyncWrite + Unpin,
+{
+    writer.write_all(b"zINSTREAM\0").await?;
+
+    let mut buffer = vec![0u8; INSTREAM_CHUNK_SIZE];
+    loop {
+        let read = content.read(&mut buffer).await?;
+        if read == 0 {
+            break;
+        }
+        writer.write_all(&(read as u32).to_be_bytes()).await?;
+        writer.write_all(&buffer[..read]).await?;
+    }
+
+    writer.write_all(&0u32.to_be_bytes()).await?;
+    writer.flush().await
+}
+
+/// Interpret a clamd reply such as `stream: OK` or `stream: Eicar-Signature FOUND`
+pub fn parse_reply(reply: &str) -> Result<ScanVerdict, AppError> {
+    let reply = reply.trim_end_matches(['\0', '\n', '\r']).trim();
+    let result = reply.strip_prefix("stream:").map(str::trim).unwrap_or(reply);
+
+    if result == "OK" {
+        Ok(ScanVerdict::Clean)
+    } else if let Some(signature) = result.strip_suffix(" FOUND") {
+        Ok(ScanVerdict::Infected { signature: signature.trim().to_string() })
+    } else if result.is_empty() {
+        Err(AppError::Upstream("clamd closed the connection without a verdict".to_string()))
+    } else {
+        Err(AppError::Upstream(format!("clamd failed to scan: {}", result)))
+    }
+}
+
+/// The configured scanner and size limit, shared by the upload paths and the re-scan task
+pub struct FileScanService {
+    scanner: Box<dyn FileScanner>,
+    max_size: u64,
+}
+
+impl FileScanService {
+    pub fn new(scanner: Box<dyn FileScanner>, max_size: u64) -> Self {
+        Self { scanner, max_size }
+    }
+
+    pub fn from_config(config: &ScanConfig) -> Result<Self, AppError> {
+        let scanner: Box<dyn FileScanner> = match config.backend.as_str() {
+            "none" | "" => Box::new(NoopScanner),
+            "clamav" => Box::new(ClamAvScanner::new(
+                ClamdAddress::parse(&config.clamd_address)?,
+                Duration::from_secs(config.timeout),
+            )),
+            other => return Err(AppError::Config(format!("Unknown scan backend: {}", other))),
+        };
+
+        Ok(Self::new(scanner, config.max_size))
+    }
+
+    /// Whether files are scanned at all
+    pub fn enabled(&self) -> bool {
+        self.scanner.enabled()
+    }
+
+    /// Scan a file on disk of `size` bytes
+    pub async fn scan_path(&self, path: &Path, size: u64) -> Result<ScanVerdict, AppError> {
+        if let Some(verdict) = self.precheck(size) {
+            return Ok(verdict);
+        }
+
+        let file = tokio::fs::File::open(path)
+            .await
+            .map_err(|e| AppError::Storage(format!("Failed to open file for scanning: {}", e)))?;
+        let mut reader = tokio::io::BufReader::new(file);
+        self.scanner.scan(&mut reader).await
+    }
+
+    /// Scan content that is already in memory
+    pub async fn scan_bytes(&self, bytes: &[u8]) -> Result<ScanVerdict, AppError> {
+        if let Some(verdict) = self.precheck(bytes.len() as u64) {
+            return Ok(verdict);
+        }
+
+        let mut reader = bytes;
+        self.scanner.scan(&mut reader).await
+    }
+
+    /// The verdict for content that is not sent to the scanner
+    fn precheck(&self, size: u64) -> Option<ScanVerdict> {
+        if !self.scanner.enabled() {
+            return Some(ScanVerdict::NotScanned);
+        }
+        if size > self.max_size {
+            tracing::warn!(
+                "Not scanning {} bytes with {}, above the limit of {} bytes",
+                size,
+                self.scanner.name(),
+                self.max_size
+            );
+            return Some(ScanVerdict::Skipped);
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{clamav_service, mock_clamd, EICAR};
+    use std::sync::atomic::Ordering;
+
+    #[test]
+    fn test_parse_address() {
+        assert_eq!(ClamdAddress::parse("tcp://clamd:3310").unwrap(), ClamdAddress::Tcp("clamd:3310".to_string()));
+        assert_eq!(ClamdAddress::parse("127.0.0.1:3310").unwrap(), ClamdAddress::Tcp("127.0.0.1:3310".to_string()));
+        assert_eq!(
+            ClamdAddress::parse("unix:///run/clamd.ctl").unwrap(),
+            ClamdAddress::Unix(PathBuf::from("/run/clamd.ctl"))
+        );
+        assert_eq!(ClamdAddress::parse("/run/clamd.ctl").unwrap(), ClamdAddress::Unix(PathBuf::from("/run/clamd.ctl")));
+        assert!(ClamdAddress::parse("clamd").is_err());
+        assert!(ClamdAddress::parse("tcp://:3310").is_err());
+    }
+
+    #[test]
+    fn test_parse_reply() {
+        assert_eq!(parse_reply("stream: OK\0").unwrap(), ScanVerdict::Clean);
+        assert_eq!(
+            parse_reply("stream: Win.Test.EICAR_HDB-1 FOUND\0").unwrap(),
+            ScanVerdict::Infected { signature: "Win.Test.EICAR_HDB-1".to_string() }
+        );
+        assert!(matches!(parse_reply("INSTREAM size limit exceeded. ERROR\0"), Err(AppError::Upstream(_))));
+        assert!(matches!(parse_reply(""), Err(AppError::Upstream(_))));
+    }
+
+    #[tokio::test]
+    async fn test_clamav_streams_content() {
+        let (address, streams) = mock_clamd().await;
+        let service = clamav_service(&address, 10 * 1024 * 1024);
+
+        // Larger than one chunk, with the marker straddling a chunk boundary
+        let mut infected = vec![b'a'; INSTREAM_CHUNK_SIZE - 10];
+        infected.extend_from_slice(EICAR);
+        infected.extend(std::iter::repeat_n(b'b', INSTREAM_CHUNK_SIZE));
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("upload");
+        tokio::fs::write(&path, &infected).await.unwrap();
+
+        let (clean, found) = tokio::join!(
+            service.scan_bytes(b"\\documentclass{article}"),
+            service.scan_path(&path, infected.len() as u64),
+        );
+        assert_eq!(clean.unwrap(), ScanVerdict::Clean);
+        assert_eq!(found.unwrap(), ScanVerdict::Infected { signature: "Eicar-Signature".to_string() });
+        assert_eq!(streams.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_size_limit_and_noop() {
+        let (address, streams) = mock_clamd().await;
+        let service = clamav_service(&address, 4);
+        assert_eq!(service.scan_bytes(b"too large").await.unwrap(), ScanVerdict::Skipped);
+        assert_eq!(streams.load(Ordering::SeqCst), 0);
+
+        let noop = FileScanService::new(Box::new(NoopScanner), 4);
+        assert!(!noop.enabled());
+        assert_eq!(noop.scan_bytes(b"ok").await.unwrap(), ScanVerdict::NotScanned);
+    }
+
+    #[tokio::test]
+    async fn test_unreachable_clamd_fails_closed() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        drop(listener);
+
+        let service = clamav_service(&address, 1024);
+        assert!(matches!(service.scan_bytes(b"content").await, Err(AppError::Upstream(_))));
+    }
+}
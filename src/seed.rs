@@ -0,0 +1,696 @@
+//! Declarative seed data for development and demo instances
+//!
+//! `texler-backend seed --file seed.yaml` and `POST /admin/seed` read a YAML
+//! description of users, workspaces, projects with their files and
+//! collaborators, and finished compile jobs, and create what is missing
+//! through the same model functions the API uses, so names are validated and
+//! activity is recorded as usual. Entries are matched by natural key: users
+//! by username, workspaces by owner and name, projects by workspace and
+//! name, files by path and collaborators by user. Running a seed again
+//! updates what changed instead of creating duplicates. Passwords and emails
+//! are only set when a user is created, and compile jobs only for projects
+//! that have none yet.
+//!
+//! Seeds are meant for new instances, so one is refused when the database has
+//! more than [`MAX_UNSEEDED_USERS`] users besides the administrator that it
+//! does not describe, unless forced. `demo/seed.yaml` describes a small demo
+//! instance.
+
+use serde::{Deserialize, Serialize};
+use std::path::{Component, Path, PathBuf};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::admin_init::ADMIN_USERNAME;
+use crate::error::AppError;
+use crate::models::auth::PasswordUtils;
+use crate::models::compilation::{CompilationJob, CreateCompilationJob, PROJECT_WORKING_DIRECTORY_ROOT};
+use crate::models::file::{CreateFile, File};
+use crate::models::project::{CreateProject, Project, ProjectCollaborator, UpdateProject};
+use crate::models::project_limits::ProjectLimits;
+use crate::models::user::{CreateUser, UpdateUser, User};
+use crate::models::validation::{self, DisplayName, FileName, ProjectName};
+use crate::models::workspace::Workspace;
+use crate::models::{ContentType, LatexEngine, UserRole};
+use crate::server::AppState;
+
+/// Users a seeded database may have that are neither the administrator nor
+/// described by the seed
+pub const MAX_UNSEEDED_USERS: i64 = 5;
+
+/// A seed file
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SeedData {
+    #[serde(default)]
+    pub users: Vec<SeedUser>,
+    #[serde(default)]
+    pub workspaces: Vec<SeedWorkspace>,
+}
+
+/// A user who signs in with a password
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SeedUser {
+    pub username: String,
+    pub email: String,
+    pub display_name: DisplayName,
+    pub password: String,
+}
+
+/// A workspace and its projects
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SeedWorkspace {
+    pub name: String,
+    /// Username of the owner, who also owns the projects
+    pub owner: String,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub projects: Vec<SeedProject>,
+}
+
+/// A project with its files, collaborators and compile history
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SeedProject {
+    pub name: ProjectName,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub is_public: bool,
+    #[serde(default)]
+    pub main_file: Option<String>,
+    #[serde(default)]
+    pub engine: Option<LatexEngine>,
+    #[serde(default)]
+    pub files: Vec<SeedFile>,
+    #[serde(default)]
+    pub collaborators: Vec<SeedCollaborator>,
+    #[serde(default)]
+    pub compile_jobs: Vec<SeedCompileJob>,
+}
+
+/// A text file, given inline or read from next to the seed file
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SeedFile {
+    pub path: String,
+    #[serde(default)]
+    pub content: Option<String>,
+    /// Relative to the directory of the seed file
+    #[serde(default)]
+    pub source: Option<String>,
+}
+
+/// A collaborator and their project role
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SeedCollaborator {
+    pub user: String,
+    pub role: UserRole,
+}
+
+/// A compile job that already finished
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SeedCompileJob {
+    pub status: SeedJobStatus,
+    /// File compiled, the main file when left out
+    #[serde(default)]
+    pub file: Option<String>,
+    /// Compiler output shown as the job's log
+    #[serde(default)]
+    pub log: Option<String>,
+}
+
+/// How a seeded compile job ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SeedJobStatus {
+    Success,
+    Error,
+    Cancelled,
+}
+
+/// Entries of each kind
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, ToSchema)]
+pub struct SeedCounts {
+    pub users: usize,
+    pub workspaces: usize,
+    pub projects: usize,
+    pub files: usize,
+    pub collaborators: usize,
+    pub compile_jobs: usize,
+}
+
+/// What loading a seed changed
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, ToSchema)]
+pub struct SeedReport {
+    pub created: SeedCounts,
+    pub updated: SeedCounts,
+}
+
+impl std::fmt::Display for SeedCounts {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} users, {} workspaces, {} projects, {} files, {} collaborators, {} compile jobs",
+            self.users, self.workspaces, self.projects, self.files, self.collaborators, self.compile_jobs
+        )
+    }
+}
+
+/// `seed --file <path> [--force]` on the command line
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SeedCommand {
+    pub file: PathBuf,
+    pub force: bool,
+}
+
+impl SeedCommand {
+    /// The seed command in the arguments after the program name, if any
+    pub fn from_args(args: impl IntoIterator<Item = String>) -> Result<Option<Self>, AppError> {
+        let mut args = args.into_iter();
+        if args.next().as_deref() != Some("seed") {
+            return Ok(None);
+        }
+
+        let mut file = None;
+        let mut force = false;
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--file" => file = args.next().map(PathBuf::from),
+                "--force" => force = true,
+                _ => return Err(AppError::BadRequest(format!("Unknown seed argument '{}'", arg))),
+            }
+        }
+
+        let file = file.ok_or_else(|| AppError::BadRequest("Usage: seed --file <seed.yaml> [--force]".to_string()))?;
+        Ok(Some(Self { file, force }))
+    }
+
+    /// Load the seed file; its file references are read from its directory
+    pub async fn run(&self, state: &AppState) -> Result<SeedReport, AppError> {
+        let yaml = tokio::fs::read_to_string(&self.file)
+            .await
+            .map_err(|e| AppError::BadRequest(format!("Failed to read {}: {}", self.file.display(), e)))?;
+        let seed = SeedData::parse(&yaml)?;
+        let base_dir = self.file.parent().unwrap_or(Path::new("."));
+        apply(state, &seed, Some(base_dir), self.force).await
+    }
+}
+
+impl SeedData {
+    /// Parse and check a seed file
+    pub fn parse(yaml: &str) -> Result<Self, AppError> {
+        let seed: Self = serde_yaml::from_str(yaml)
+            .map_err(|e| AppError::Validation(format!("Invalid seed data: {}", e)))?;
+        seed.validate()?;
+        Ok(seed)
+    }
+
+    fn validate(&self) -> Result<(), AppError> {
+        for user in &self.users {
+            if user.username.len() < 3 {
+                return Err(AppError::Validation(format!("Username '{}' is shorter than 3 characters", user.username)));
+            }
+            if !user.email.contains('@') {
+                return Err(AppError::Validation(format!("Invalid email address of user '{}'", user.username)));
+            }
+            PasswordUtils::validate_password_strength(&user.password)?;
+        }
+
+        for project in self.workspaces.iter().flat_map(|workspace| &workspace.projects) {
+            for file in &project.files {
+                if file.content.is_some() == file.source.is_some() {
+                    return Err(AppError::Validation(format!(
+                        "File '{}' of project '{}' needs either content or a source",
+                        file.path, project.name
+                    )));
+                }
+            }
+            if let Some(collaborator) = project.collaborators.iter().find(|c| c.role == UserRole::Owner) {
+                return Err(AppError::Validation(format!(
+                    "'{}' cannot be an owner of project '{}'; projects belong to their workspace's owner",
+                    collaborator.user, project.name
+                )));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Create or update everything `seed` describes
+///
+/// `base_dir` is where file sources are read from; without one, files must
+/// be given inline.
+pub async fn apply(
+    state: &AppState,
+    seed: &SeedData,
+    base_dir: Option<&Path>,
+    force: bool,
+) -> Result<SeedReport, AppError> {
+    let db = &state.db_pool;
+    if !force {
+        ensure_new_instance(db, seed).await?;
+    }
+
+    let mut report = SeedReport::default();
+    for user in &seed.users {
+        seed_user(db, user, &mut report).await?;
+    }
+
+    for workspace in &seed.workspaces {
+        let owner = find_user(db, &workspace.owner).await?;
+        let workspace_id = seed_workspace(db, &owner, workspace, &mut report).await?;
+        for project in &workspace.projects {
+            seed_project(state, &owner, workspace_id, project, base_dir, &mut report).await?;
+        }
+    }
+
+    Ok(report)
+}
+
+/// Refuse to seed a database that already has users of its own
+async fn ensure_new_instance(db: &sqlx::PgPool, seed: &SeedData) -> Result<(), AppError> {
+    let known: Vec<&str> = seed
+        .users
+        .iter()
+        .map(|user| user.username.as_str())
+        .chain([ADMIN_USERNAME])
+        .collect();
+    let unseeded = sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM users WHERE NOT (username = ANY($1))")
+        .bind(&known)
+        .fetch_one(db)
+        .await
+        .map_err(AppError::Database)?;
+
+    if unseeded > MAX_UNSEEDED_USERS {
+        return Err(AppError::Conflict(format!(
+            "The database has {} users the seed does not describe; seeds are meant for new instances, force it to load anyway",
+            unseeded
+        )));
+    }
+
+    Ok(())
+}
+
+async fn find_user(db: &sqlx::PgPool, username: &str) -> Result<User, AppError> {
+    User::find_by_username(db, username).await?.ok_or_else(|| AppError::NotFound {
+        entity: "User".to_string(),
+        id: username.to_string(),
+    })
+}
+
+async fn seed_user(db: &sqlx::PgPool, user: &SeedUser, report: &mut SeedReport) -> Result<(), AppError> {
+    match User::find_by_username(db, &user.username).await? {
+        Some(existing) if existing.display_name == user.display_name.as_str() => {}
+        Some(existing) => {
+            let update = UpdateUser {
+                display_name: Some(user.display_name.clone()),
+                avatar_url: None,
+                is_active: None,
+            };
+            existing.update(db, update).await?;
+            report.updated.users += 1;
+        }
+        None => {
+            let create = CreateUser {
+                username: user.username.clone(),
+                email: user.email.clone(),
+                password: user.password.clone(),
+                display_name: user.display_name.clone(),
+                avatar_url: None,
+            };
+            User::create(db, create).await?;
+            report.created.users += 1;
+        }
+    }
+
+    Ok(())
+}
+
+async fn seed_workspace(
+    db: &sqlx::PgPool,
+    owner: &User,
+    workspace: &SeedWorkspace,
+    report: &mut SeedReport,
+) -> Result<Uuid, AppError> {
+    let name = validation::validate_name("Workspace name", &workspace.name)?;
+    let existing = sqlx::query_scalar::<_, Uuid>(
+        "SELECT id FROM workspaces WHERE owner_id = $1 AND name = $2 ORDER BY created_at LIMIT 1"
+    )
+    .bind(owner.id)
+    .bind(&name)
+    .fetch_optional(db)
+    .await
+    .map_err(AppError::Database)?;
+
+    if let Some(workspace_id) = existing {
+        return Ok(workspace_id);
+    }
+
+    let created = Workspace::create(db, owner.id, name, workspace.description.clone()).await?;
+    report.created.workspaces += 1;
+    Ok(created.id)
+}
+
+async fn seed_project(
+    state: &AppState,
+    owner: &User,
+    workspace_id: Uuid,
+    project: &SeedProject,
+    base_dir: Option<&Path>,
+    report: &mut SeedReport,
+) -> Result<(), AppError> {
+    let db = &state.db_pool;
+
+    // Read every source first, so a missing one leaves the project alone
+    let mut contents = Vec::with_capacity(project.files.len());
+    for file in &project.files {
+        contents.push(file_content(file, base_dir).await?);
+    }
+
+    let existing = sqlx::query_as::<_, Project>(
+        r#"
+        SELECT * FROM projects
+        WHERE workspace_id = $1 AND name = $2 AND purging_at IS NULL
+        ORDER BY created_at LIMIT 1
+        "#
+    )
+    .bind(workspace_id)
+    .bind(&project.name)
+    .fetch_optional(db)
+    .await
+    .map_err(AppError::Database)?;
+
+    let existing = match existing {
+        Some(existing) => existing,
+        None => {
+            let create = CreateProject {
+                name: project.name.clone(),
+                description: project.description.clone(),
+                is_public: Some(project.is_public),
+                main_file_path: project.main_file.clone(),
+                latex_engine: project.engine,
+                output_format: None,
+                custom_args: None,
+                bibliography_path: None,
+                tags: None,
+                workspace_id: Some(workspace_id),
+            };
+            let created = Project::create(db, owner.id, create).await?;
+            report.created.projects += 1;
+            created
+        }
+    };
+
+    let limits = ProjectLimits::from_config(&state.config.features.file_storage);
+    for (file, content) in project.files.iter().zip(contents) {
+        seed_file(state, &limits, &existing, owner, file, content, report).await?;
+    }
+
+    let changed = |seeded: Option<String>, current: Option<&String>| seeded.filter(|value| Some(value) != current);
+    let update = UpdateProject {
+        name: None,
+        description: changed(project.description.clone(), existing.description.as_ref()),
+        is_public: Some(project.is_public).filter(|&is_public| is_public != existing.is_public),
+        is_embeddable: None,
+        main_file_path: changed(project.main_file.clone(), Some(&existing.main_file_path)),
+        latex_engine: project.engine.filter(|&engine| engine != existing.latex_engine),
+        output_format: None,
+        custom_args: None,
+        bibliography_path: None,
+        tags: None,
+        readme_file_id: None,
+        settings: None,
+    };
+    if update.description.is_some() || update.is_public.is_some() || update.main_file_path.is_some() || update.latex_engine.is_some() {
+        existing.update(db, update, owner.id).await?;
+        report.updated.projects += 1;
+    }
+
+    for collaborator in &project.collaborators {
+        seed_collaborator(db, &existing, owner, collaborator, report).await?;
+    }
+
+    seed_compile_jobs(state, existing.id, owner, project, report).await
+}
+
+async fn file_content(file: &SeedFile, base_dir: Option<&Path>) -> Result<String, AppError> {
+    let (Some(source), None) = (&file.source, &file.content) else {
+        return Ok(file.content.clone().unwrap_or_default());
+    };
+    let base_dir = base_dir.ok_or_else(|| {
+        AppError::Validation(format!("File '{}' must be given inline; sources are only read from seed files", file.path))
+    })?;
+
+    // Sources stay inside the seed's directory
+    let relative = Path::new(source);
+    if !relative.components().all(|component| matches!(component, Component::Normal(_) | Component::CurDir)) {
+        return Err(AppError::Validation(format!("Source '{}' must be a path below the seed file's directory", source)));
+    }
+
+    let path = base_dir.join(relative);
+    tokio::fs::read_to_string(&path)
+        .await
+        .map_err(|e| AppError::Validation(format!("Failed to read {}: {}", path.display(), e)))
+}
+
+async fn seed_file(
+    state: &AppState,
+    limits: &ProjectLimits,
+    project: &Project,
+    owner: &User,
+    file: &SeedFile,
+    content: String,
+    report: &mut SeedReport,
+) -> Result<(), AppError> {
+    let db = &state.db_pool;
+    let saved = match File::find_by_path(db, project.id, &file.path, owner.id).await? {
+        Some(existing) if existing.content == content => return Ok(()),
+        Some(existing) => {
+            let storage_root = &state.config.features.file_storage.local_path;
+            let updated = existing.update_content(db, storage_root, content, owner.id).await?;
+            report.updated.files += 1;
+            updated
+        }
+        None => {
+            let file_name = file.path.rsplit('/').next().unwrap_or(&file.path);
+            let create = CreateFile {
+                name: FileName::new(file_name)?,
+                path: file.path.clone(),
+                content: Some(content),
+                content_type: Some(ContentType::from_file_name(file_name)),
+            };
+            let created = File::create(db, limits, project.id, create, owner.id).await?;
+            report.created.files += 1;
+            created
+        }
+    };
+
+    state.autocomplete_cache.invalidate(saved.project_id);
+    state.include_graph_cache.file_changed(&saved);
+    Ok(())
+}
+
+async fn seed_collaborator(
+    db: &sqlx::PgPool,
+    project: &Project,
+    owner: &User,
+    collaborator: &SeedCollaborator,
+    report: &mut SeedReport,
+) -> Result<(), AppError> {
+    let user = find_user(db, &collaborator.user).await?;
+    if user.id == owner.id {
+        return Err(AppError::Validation(format!(
+            "'{}' owns project '{}' and cannot also be its collaborator",
+            collaborator.user, project.name
+        )));
+    }
+
+    let current = ProjectCollaborator::list(db, project.id)
+        .await?
+        .into_iter()
+        .find(|existing| existing.user_id == user.id);
+    match current {
+        Some(existing) if existing.role == collaborator.role => {}
+        Some(_) => {
+            // Both changes are recorded in the project's settings history
+            ProjectCollaborator::remove(db, project.id, user.id, owner.id).await?;
+            ProjectCollaborator::add(db, project.id, user.id, collaborator.role, owner.id).await?;
+            report.updated.collaborators += 1;
+        }
+        None => {
+            ProjectCollaborator::add(db, project.id, user.id, collaborator.role, owner.id).await?;
+            report.created.collaborators += 1;
+        }
+    }
+
+    Ok(())
+}
+
+/// Record the project's compile history, unless it has one already
+async fn seed_compile_jobs(
+    state: &AppState,
+    project_id: Uuid,
+    owner: &User,
+    project: &SeedProject,
+    report: &mut SeedReport,
+) -> Result<(), AppError> {
+    let db = &state.db_pool;
+    let compiled = sqlx::query_scalar::<_, bool>("SELECT EXISTS(SELECT 1 FROM compilation_jobs WHERE project_id = $1)")
+        .bind(project_id)
+        .fetch_one(db)
+        .await
+        .map_err(AppError::Database)?;
+    if compiled || project.compile_jobs.is_empty() {
+        return Ok(());
+    }
+
+    let current = Project::find_by_id(db, project_id, owner.id).await?.ok_or_else(|| AppError::NotFound {
+        entity: "Project".to_string(),
+        id: project_id.to_string(),
+    })?;
+    let files = File::list_all_for_project(db, project_id).await?;
+    let input_files: Vec<String> = files.iter().map(|file| file.path.clone()).collect();
+
+    for seeded in &project.compile_jobs {
+        let target = seeded.file.clone().unwrap_or_else(|| current.main_file_path.clone());
+        let file_id = match &seeded.file {
+            Some(path) => Some(files.iter().find(|file| &file.path == path).map(|file| file.id).ok_or_else(|| {
+                AppError::Validation(format!("Compile job of project '{}' names a missing file '{}'", current.name, path))
+            })?),
+            None => None,
+        };
+
+        let create_job = CreateCompilationJob {
+            file_id,
+            engine: None,
+            args: None,
+            priority: None,
+            template_id: None,
+            clean: false,
+            batch_id: None,
+        };
+        let job = CompilationJob::create(
+            db,
+            project_id,
+            owner.id,
+            create_job,
+            current.latex_engine,
+            &target,
+            format!("{}/{}", PROJECT_WORKING_DIRECTORY_ROOT, project_id),
+            input_files.clone(),
+        )
+        .await?;
+
+        if seeded.status == SeedJobStatus::Cancelled {
+            job.cancel(db, "Cancelled before it started").await?;
+        } else {
+            job.start(db, Some("seed".to_string())).await?;
+            let started = CompilationJob::find_by_id(db, job.id, owner.id).await?.unwrap_or(job);
+            let exit_code = if seeded.status == SeedJobStatus::Success { 0 } else { 1 };
+            started
+                .complete(
+                    db,
+                    &state.compile_notifier,
+                    &state.secret_keyring,
+                    exit_code,
+                    seeded.log.clone().unwrap_or_default(),
+                    String::new(),
+                    vec![],
+                    0,
+                    0,
+                )
+                .await?;
+        }
+        report.created.compile_jobs += 1;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{create_test_user, test_state, TestDb};
+
+    fn demo_dir() -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("demo")
+    }
+
+    /// Rows of each kind belonging to the given users
+    async fn row_counts(db: &sqlx::PgPool, usernames: &[&str]) -> Vec<i64> {
+        let mut counts = Vec::new();
+        for query in [
+            "SELECT COUNT(*) FROM users WHERE username = ANY($1)",
+            "SELECT COUNT(*) FROM workspaces w JOIN users u ON u.id = w.owner_id WHERE u.username = ANY($1)",
+            "SELECT COUNT(*) FROM projects p JOIN users u ON u.id = p.owner_id WHERE u.username = ANY($1)",
+            "SELECT COUNT(*) FROM files f JOIN projects p ON p.id = f.project_id JOIN users u ON u.id = p.owner_id WHERE u.username = ANY($1)",
+            "SELECT COUNT(*) FROM project_collaborators c JOIN users u ON u.id = c.user_id WHERE u.username = ANY($1)",
+            "SELECT COUNT(*) FROM compilation_jobs j JOIN users u ON u.id = j.user_id WHERE u.username = ANY($1)",
+        ] {
+            let count = sqlx::query_scalar::<_, i64>(query).bind(usernames).fetch_one(db).await.unwrap();
+            counts.push(count);
+        }
+        counts
+    }
+
+    #[test]
+    fn test_seed_files_are_checked() {
+        assert!(SeedData::parse("users: []\nteams: []\n").is_err());
+
+        let both = "workspaces:\n  - name: W\n    owner: alice\n    projects:\n      - name: P\n        files:\n          - { path: main.tex, content: x, source: main.tex }\n";
+        assert!(SeedData::parse(both).is_err());
+
+        let owner = "workspaces:\n  - name: W\n    owner: alice\n    projects:\n      - name: P\n        collaborators:\n          - { user: bob, role: owner }\n";
+        assert!(SeedData::parse(owner).is_err());
+
+        assert_eq!(
+            SeedCommand::from_args(["seed", "--file", "demo/seed.yaml", "--force"].map(String::from)).unwrap(),
+            Some(SeedCommand { file: PathBuf::from("demo/seed.yaml"), force: true })
+        );
+        assert_eq!(SeedCommand::from_args(["--dry-run".to_string()]).unwrap(), None);
+        assert!(SeedCommand::from_args(["seed".to_string()]).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_demo_seed_loads_once() {
+        let Some(db) = TestDb::start().await else { return };
+        let state = test_state(&db).await;
+
+        let demo = demo_dir();
+        let yaml = std::fs::read_to_string(demo.join("seed.yaml")).unwrap();
+        let seed = SeedData::parse(&yaml).unwrap();
+        let usernames: Vec<&str> = seed.users.iter().map(|user| user.username.as_str()).collect();
+
+        // The test database is shared, so other tests' users must not stop it
+        apply(&state, &seed, Some(demo.as_path()), true).await.unwrap();
+        let counts = row_counts(&db.pool, &usernames).await;
+        assert!(counts.iter().all(|&count| count > 0), "every kind is seeded: {:?}", counts);
+
+        let again = apply(&state, &seed, Some(demo.as_path()), true).await.unwrap();
+        assert_eq!(again, SeedReport::default());
+        assert_eq!(row_counts(&db.pool, &usernames).await, counts);
+
+        // File sources are only read from seed files on disk
+        assert!(matches!(apply(&state, &seed, None, true).await, Err(AppError::Validation(_))));
+    }
+
+    #[tokio::test]
+    async fn test_seed_is_refused_on_a_used_instance() {
+        let Some(db) = TestDb::start().await else { return };
+        let state = test_state(&db).await;
+        for _ in 0..=MAX_UNSEEDED_USERS {
+            create_test_user(&db.pool).await;
+        }
+
+        let seed = SeedData::parse("users: []\n").unwrap();
+        assert!(matches!(apply(&state, &seed, None, false).await, Err(AppError::Conflict(_))));
+        assert_eq!(apply(&state, &seed, None, true).await.unwrap(), SeedReport::default());
+    }
+}
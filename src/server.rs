@@ -3,7 +3,7 @@
 use crate::config::Config;
 use crate::error::{AppError, RequestId};
 use axum::{
-    extract::{DefaultBodyLimit, Request, State},
+    extract::{DefaultBodyLimit, MatchedPath, Request, State},
     http::{HeaderMap, HeaderValue, Method, StatusCode},
     middleware::{self, Next},
     response::{IntoResponse, Response},
@@ -22,16 +22,52 @@ use tower_http::{
     trace::{DefaultMakeSpan, DefaultOnResponse, TraceLayer},
 };
 use tower::make::Shared;
-use tracing::{info, warn};
+use tracing::{info, warn, Instrument};
 
 /// Application state
 #[derive(Clone)]
 pub struct AppState {
     pub config: Arc<Config>,
     pub db_pool: sqlx::PgPool,
-    pub oidc_clients: Arc<std::collections::HashMap<String, authware::OidcClient>>,
     pub jwt_service: Arc<crate::models::auth::JwtService>,
     pub rate_limiter: Arc<crate::middleware::RateLimiter>,
+    pub health: Arc<crate::handlers::health::HealthChecker>,
+    pub tasks: Arc<crate::tasks::TaskRegistry>,
+    pub readme_cache: Arc<crate::models::readme::ReadmeCache>,
+    pub autocomplete_cache: Arc<crate::models::autocomplete::AutocompleteCache>,
+    pub include_graph_cache: Arc<crate::models::include_graph::IncludeGraphCache>,
+    pub blame_cache: Arc<crate::models::file_blame::BlameCache>,
+    /// Live WebSocket connections by user, shared with the WebSocket server
+    pub user_channels: Arc<crate::websocket::UserChannels>,
+    /// Authenticated WebSocket connections per user, shared with the WebSocket server
+    pub ws_connection_limiter: Arc<crate::models::ws_connection_limit::WsConnectionLimiter>,
+    /// Users in each project's sessions, counted by the WebSocket server
+    pub project_presence: Arc<crate::models::project_presence::ProjectPresence>,
+    pub compile_notifier: Arc<crate::models::compile_notification::CompileNotifier>,
+    pub mention_notifier: Arc<crate::models::mention::MentionNotifier>,
+    pub inline_renderer: Arc<crate::models::inline_render::InlineRenderer>,
+    /// Confinement of TeX runs and its latest self-test
+    pub compile_sandbox: Arc<crate::models::compile_sandbox::CompileSandbox>,
+    /// Shared client for requests to other services
+    pub http_client: Arc<crate::http_client::HttpClient>,
+    /// Malware scanner for uploads
+    pub file_scanner: Arc<crate::scanner::FileScanService>,
+    /// Renders and queues outgoing email
+    pub mailer: Arc<crate::email::Mailer>,
+    /// Master keys project secrets are encrypted with
+    pub secret_keyring: Arc<crate::models::project_secret::SecretKeyring>,
+    /// Compile runs of this server, for cancellation
+    pub running_compiles: Arc<crate::models::compile_cancel::RunningCompiles>,
+    /// Views of public projects not written yet
+    pub project_views: Arc<crate::models::discovery::ProjectViews>,
+    /// Refused OIDC callbacks by reason
+    pub oidc_metrics: Arc<crate::models::oidc_flow::OidcMetrics>,
+    /// Request latencies per route and database pool history
+    pub performance: Arc<crate::performance::PerformanceMonitor>,
+    /// Edit conflicts, counted and sampled for `conflict_events`
+    pub conflicts: Arc<crate::models::conflict_event::ConflictRecorder>,
+    /// Read retries, best-effort writes and circuit breaking during database outages
+    pub db_resilience: Arc<crate::db_resilience::DbResilience>,
 }
 
 // Ensure AppState satisfies the bounds required by Axum's State extractor
@@ -52,19 +88,22 @@ pub fn create_router(state: &AppState) -> Router<AppState> {
             axum::http::header::ORIGIN,
             axum::http::header::USER_AGENT,
         ])
+        // Lets the frontend show the ID of a failed request
+        .expose_headers([axum::http::HeaderName::from_static(crate::correlation::REQUEST_ID_HEADER)])
         .allow_credentials(true);
 
     let compression = CompressionLayer::new();
 
-    let request_body_limit = RequestBodyLimitLayer::new(
-        state.config.latex.output_size_limit as usize * 10, // Allow 10x output size for input
-    );
+    let request_body_limit = RequestBodyLimitLayer::new(request_body_limit(&state.config));
 
     Router::new()
-        // Health check endpoint
-        .route("/health", get(health_check))
+        // Health check endpoints; `/health` is kept as an alias for readiness
+        .route("/health", get(crate::handlers::health::readiness))
+        .route("/health/live", get(crate::handlers::health::liveness))
+        .route("/health/ready", get(crate::handlers::health::readiness))
         // API routes
         .nest("/api/v1", api_routes())
+        .merge(docs_routes())
         // Apply CORS first to handle preflight requests
         .layer(cors)
         // Other middleware layers
@@ -73,17 +112,33 @@ pub fn create_router(state: &AppState) -> Router<AppState> {
                 .make_span_with(DefaultMakeSpan::default().include_headers(true))
                 .on_response(DefaultOnResponse::new())
         )
-        .layer(middleware::from_fn_with_state(state.clone(), request_id_middleware))
-        .layer(middleware::from_fn_with_state(state.clone(), logging_middleware))
+        // Inside authentication, so conflicts are recorded with their user
+        .layer(middleware::from_fn_with_state(state.conflicts.clone(), crate::middleware::conflict_middleware))
         .layer(middleware::from_fn_with_state(state.clone(), auth_middleware))
+        .layer(middleware::from_fn_with_state(
+            crate::middleware::RequestTimeouts::from_config(&state.config.server),
+            crate::middleware::timeout_middleware,
+        ))
+        .layer(middleware::from_fn_with_state(state.clone(), logging_middleware))
+        // Outermost of the three so logging and timeouts see the request ID
+        .layer(middleware::from_fn_with_state(state.clone(), request_id_middleware))
         .layer(request_body_limit)
         .layer(compression)
         .fallback(not_found_handler)
 }
 
+/// Largest request body accepted, in bytes
+pub fn request_body_limit(config: &Config) -> usize {
+    config.latex.output_size_limit as usize * 10 // Allow 10x output size for input
+}
+
 /// API routes
 fn api_routes() -> Router<AppState> {
     Router::new()
+        // Machine-readable API description
+        .route("/openapi.json", get(crate::openapi::openapi_json))
+        // Features and limits of this instance
+        .route("/capabilities", get(crate::handlers::capabilities::get_capabilities))
         // Authentication routes
         .nest("/auth", auth_routes())
         // User routes
@@ -100,6 +155,14 @@ fn api_routes() -> Router<AppState> {
         .nest("/latex", latex_proxy_routes())
         // Collaboration routes
         .nest("/collaboration", collaboration_routes())
+        // Administrative routes
+        .nest("/admin", admin_routes())
+        // Public project discovery, open to logged-out visitors
+        .nest("/discover", discover_routes())
+        // Files shared by link, open to anyone holding the token
+        .nest("/shared", shared_routes())
+        // Search routes
+        .nest("/search", search_routes())
         // Handle trailing slashes explicitly
         .route("/users/", get(crate::handlers::user::get_current_user))
         .route("/users/", post(crate::handlers::user::update_user))
@@ -109,6 +172,22 @@ fn api_routes() -> Router<AppState> {
         .route("/files/", post(crate::handlers::file::create_file))
 }
 
+/// Swagger UI over `/api/v1/openapi.json`, when built with the `swagger-ui` feature
+fn docs_routes() -> Router<AppState> {
+    #[cfg(feature = "swagger-ui")]
+    {
+        Router::from(
+            utoipa_swagger_ui::SwaggerUi::new("/api/docs")
+                .config(utoipa_swagger_ui::Config::from("/api/v1/openapi.json")),
+        )
+    }
+
+    #[cfg(not(feature = "swagger-ui"))]
+    {
+        Router::new()
+    }
+}
+
 /// Authentication routes
 fn auth_routes() -> Router<AppState> {
     Router::new()
@@ -119,6 +198,7 @@ fn auth_routes() -> Router<AppState> {
         .route("/forgot-password", post(crate::handlers::auth::forgot_password))
         .route("/reset-password", post(crate::handlers::auth::reset_password))
         .route("/verify-email", post(crate::handlers::auth::verify_email))
+        .route("/undo-email-change", post(crate::handlers::auth::undo_email_change))
         // OIDC routes
         .route("/oidc/providers", get(crate::handlers::auth::get_oidc_providers))
         .route("/oidc/login", post(crate::handlers::auth::oidc_login))
@@ -138,7 +218,24 @@ fn user_routes() -> Router<AppState> {
         .route("/", post(crate::handlers::user::update_user))
         .route("/preferences", get(crate::handlers::user::get_preferences))
         .route("/preferences", post(crate::handlers::user::update_preferences))
+        .route("/digest/preview", post(crate::handlers::user::preview_digest))
+        .route("/settings/export", get(crate::handlers::user::export_settings))
+        .route("/settings/import", post(crate::handlers::user::import_settings))
         .route("/search", get(crate::handlers::user::search_users))
+        .route("/notifications", get(crate::handlers::user::list_notifications))
+        .route("/notifications/:id/read", post(crate::handlers::user::mark_notification_read))
+        .route("/me/avatar", post(crate::handlers::user::upload_avatar).delete(crate::handlers::user::delete_avatar))
+        .route("/me/email", post(crate::handlers::user::request_email_change).delete(crate::handlers::user::cancel_email_change))
+        .route("/me/email/confirm", post(crate::handlers::user::confirm_email_change))
+        .route("/me/onboarding", get(crate::handlers::user::get_onboarding))
+        .route("/me/onboarding/dismiss", post(crate::handlers::user::dismiss_onboarding))
+        .route("/me/announcements", get(crate::handlers::user::get_announcements))
+        .route("/me/announcements/ack", post(crate::handlers::user::acknowledge_announcements))
+        .route("/:id/avatar", get(crate::handlers::user::get_avatar))
+        .route("/activity-exports/:id", get(crate::handlers::user::get_activity_export))
+        .route("/activity-exports/:id/download", get(crate::handlers::user::download_activity_export))
+        .route("/dictionary", get(crate::handlers::dictionary::list_personal_dictionary).post(crate::handlers::dictionary::add_personal_terms))
+        .route("/dictionary/:entry_id", delete(crate::handlers::dictionary::remove_personal_term))
 }
 
 /// Project routes
@@ -148,10 +245,56 @@ fn project_routes() -> Router<AppState> {
         .route("/:id", get(crate::handlers::project::get_project).put(crate::handlers::project::update_project).delete(crate::handlers::project::delete_project))
         .route("/:id/collaborators", get(crate::handlers::project::get_collaborators).post(crate::handlers::project::add_collaborator))
         .route("/:id/collaborators/:user_id", delete(crate::handlers::project::remove_collaborator))
+        .route("/:id/transfer-ownership", get(crate::handlers::project::get_ownership_transfer).post(crate::handlers::project::transfer_ownership).delete(crate::handlers::project::cancel_ownership_transfer))
+        .route("/:id/transfer-ownership/accept", post(crate::handlers::project::accept_ownership_transfer))
+        .route("/:id/conflicts", get(crate::handlers::project::get_contended_files))
+        .route("/:id/invitations", get(crate::handlers::project::list_invitations).post(crate::handlers::project::invite_collaborators))
+        .route("/:id/invitations/:invitation_id", delete(crate::handlers::project::revoke_invitation))
+        .route("/:id/embed-tokens", get(crate::handlers::project::list_embed_tokens).post(crate::handlers::project::create_embed_token))
+        .route("/:id/embed-tokens/:token_id", delete(crate::handlers::project::revoke_embed_token))
+        .route("/:id/secrets", get(crate::handlers::project::list_project_secrets).post(crate::handlers::project::set_project_secret))
+        .route("/:id/secrets/:name", delete(crate::handlers::project::delete_project_secret))
+        .route("/:id/latest.pdf", get(crate::handlers::project::get_latest_pdf))
+        .route("/:id/freeze", post(crate::handlers::project::freeze_project))
+        .route("/:id/unfreeze", post(crate::handlers::project::unfreeze_project))
+        .route("/:id/placement", put(crate::handlers::project::move_project))
+        .route("/:id/favorite", put(crate::handlers::project::favorite_project).delete(crate::handlers::project::unfavorite_project))
         .route("/:id/compile", post(crate::handlers::project::compile_project))
+        .route("/:id/compile-all", post(crate::handlers::project::compile_all))
+        .route("/:id/compile-preflight", get(crate::handlers::project::get_compile_preflight))
+        .route("/:id/compilation/jobs", get(crate::handlers::compilation::list_project_jobs))
+        .route("/:id/compilation/jobs/history", delete(crate::handlers::compilation::prune_project_jobs))
         .route("/:id/stats", get(crate::handlers::project::get_project_stats))
+        .route("/:id/word-count", get(crate::handlers::project::get_word_count))
+        .route("/:id/storage/breakdown", get(crate::handlers::project::get_storage_breakdown))
         .route("/:id/activity", get(crate::handlers::project::get_activity))
+        .route("/:id/activity/daily", get(crate::handlers::project::get_activity_counts))
+        .route("/:id/activity/export", get(crate::handlers::project::export_activity))
+        .route("/:id/tree", get(crate::handlers::file::get_project_tree))
+        .route("/:id/readme", get(crate::handlers::project::get_project_readme))
+        .route("/:id/autocomplete", get(crate::handlers::project::get_autocomplete))
+        .route("/:id/mention-candidates", get(crate::handlers::project::get_mention_candidates))
+        .route("/:id/include-graph", get(crate::handlers::project::get_include_graph))
+        .route("/:id/outline", get(crate::handlers::project::get_project_outline))
+        .route("/:id/settings/history", get(crate::handlers::project::get_settings_history))
+        .route("/:id/settings/rollback/:history_id", post(crate::handlers::project::rollback_settings))
+        .route("/:id/compile-environment/diff", get(crate::handlers::project::get_compile_environment_diff))
+        .route("/:id/compile-diff", get(crate::handlers::project::get_compile_diff))
+        .route("/:id/export", get(crate::handlers::project::export_project))
+        .route("/:id/export/checksum", get(crate::handlers::project::export_checksum))
+        .route("/:id/dictionary", get(crate::handlers::dictionary::list_project_dictionary).post(crate::handlers::dictionary::add_project_terms))
+        .route("/:id/dictionary/import", post(crate::handlers::dictionary::import_project_terms))
+        .route("/:id/dictionary/effective", get(crate::handlers::dictionary::get_effective_dictionary))
+        .route("/:id/dictionary/:entry_id", delete(crate::handlers::dictionary::remove_project_term))
+        .route("/:id/clone", post(crate::handlers::project::clone_project))
+        .route("/from-template/:id", post(crate::handlers::project::create_project_from_template))
+        .route("/import", post(crate::handlers::project::import_project))
+        .route("/import/git", post(crate::handlers::project::import_project_from_git))
+        .route("/import/git/:import_id", get(crate::handlers::project::get_git_import))
         .route("/search", get(crate::handlers::project::search_projects))
+        .route("/active-counts", get(crate::handlers::project::active_counts))
+        .route("/switcher", get(crate::handlers::project::get_project_switcher))
+        .route("/trash", get(crate::handlers::project::list_trash))
 }
 
 /// File routes
@@ -160,8 +303,17 @@ fn file_routes() -> Router<AppState> {
         .route("/", get(crate::handlers::file::list_files).post(crate::handlers::file::create_file))
         .route("/:id", get(crate::handlers::file::get_file).put(crate::handlers::file::update_file).delete(crate::handlers::file::delete_file))
         .route("/:id/content", get(crate::handlers::file::get_file_content).put(crate::handlers::file::update_file_content))
+        .route("/:id/blame", get(crate::handlers::file::get_file_blame))
         .route("/:id/download", get(crate::handlers::file::download_file))
+        .route("/:id/format", post(crate::handlers::file::format_file))
+        .route("/:id/format/selection", post(crate::handlers::file::format_selection))
+        .route("/:id/share-links", get(crate::handlers::file::list_share_links).post(crate::handlers::file::create_share_link))
+        .route("/:id/share-links/:link_id", axum::routing::delete(crate::handlers::file::revoke_share_link))
         .route("/upload", post(crate::handlers::file::upload_file))
+        .route("/uploads", post(crate::handlers::file::create_upload_session))
+        .route("/uploads/:id", get(crate::handlers::file::get_upload_session))
+        .route("/uploads/:id/chunks/:index", put(crate::handlers::file::upload_chunk))
+        .route("/uploads/:id/complete", post(crate::handlers::file::complete_upload))
         .route("/tree", get(crate::handlers::file::get_file_tree))
         .route("/search", get(crate::handlers::file::search_files))
 }
@@ -174,9 +326,15 @@ fn compilation_routes() -> Router<AppState> {
         .route("/jobs/:id/cancel", post(crate::handlers::compilation::cancel_job))
         .route("/jobs/:id/logs", get(crate::handlers::compilation::get_job_logs))
         .route("/jobs/:id/artifacts", get(crate::handlers::compilation::get_job_artifacts))
+        .route("/jobs/:id/thumbnails", get(crate::handlers::compilation::list_job_thumbnails))
+        .route("/jobs/:id/thumbnails/:page", get(crate::handlers::compilation::get_job_thumbnail))
+        .route("/batches/:id", get(crate::handlers::compilation::get_batch))
+        .route("/batches/:id/cancel", post(crate::handlers::compilation::cancel_batch))
         .route("/queue", get(crate::handlers::compilation::get_queue_status))
         .route("/templates", get(crate::handlers::compilation::list_templates).post(crate::handlers::compilation::create_template))
         .route("/templates/:id", get(crate::handlers::compilation::get_template))
+        .route("/templates/:id/rate", post(crate::handlers::compilation::rate_template))
+        .route("/templates/:id/fork", post(crate::handlers::compilation::fork_template))
         .route("/stats", get(crate::handlers::compilation::get_compilation_stats))
 }
 
@@ -185,6 +343,8 @@ fn latex_proxy_routes() -> Router<AppState> {
     Router::new()
         .route("/compile", post(crate::handlers::latex_proxy::compile_latex))
         .route("/health", get(crate::handlers::latex_proxy::latex_health_check))
+        .route("/render-inline", post(crate::handlers::latex_proxy::render_inline))
+        .route("/render-inline/:hash", get(crate::handlers::latex_proxy::get_inline_render))
         // Skip auth middleware for these routes to allow direct frontend access
         .layer(middleware::from_fn(skip_auth_middleware))
 }
@@ -205,6 +365,32 @@ fn workspace_routes() -> Router<AppState> {
             "/:workspace_id/projects",
             post(crate::handlers::workspace::create_project),
         )
+        .route(
+            "/:workspace_id/activity/export",
+            get(crate::handlers::workspace::export_workspace_activity),
+        )
+        .route(
+            "/:workspace_id/storage",
+            get(crate::handlers::workspace::get_workspace_storage),
+        )
+        .route(
+            "/:workspace_id/usage",
+            get(crate::handlers::workspace::get_workspace_usage),
+        )
+        .route(
+            "/:workspace_id/folders",
+            get(crate::handlers::workspace::list_folders)
+                .post(crate::handlers::workspace::create_folder),
+        )
+        .route(
+            "/:workspace_id/folders/:folder_id",
+            put(crate::handlers::workspace::rename_folder)
+                .delete(crate::handlers::workspace::delete_folder),
+        )
+        .route(
+            "/:workspace_id/folders/:folder_id/move",
+            post(crate::handlers::workspace::move_folder),
+        )
         .route(
             "/:workspace_id/projects/:project_id",
             get(crate::handlers::workspace::get_project),
@@ -220,35 +406,91 @@ fn workspace_routes() -> Router<AppState> {
         )
 }
 
+/// Administrative routes
+fn admin_routes() -> Router<AppState> {
+    Router::new()
+        .route("/blobs/consistency", get(crate::handlers::admin::blob_consistency))
+        .route("/sessions/compaction", get(crate::handlers::admin::session_compaction_dry_run))
+        .route("/users/:id/unlock", post(crate::handlers::admin::unlock_user))
+        .route("/tasks", get(crate::handlers::admin::list_tasks))
+        .route("/tasks/:name/run", post(crate::handlers::admin::run_task))
+        .route("/templates/:id/verified", put(crate::handlers::admin::set_template_verified))
+        .route("/metrics", get(crate::handlers::admin::metrics))
+        .route("/websocket/connections", get(crate::handlers::admin::websocket_connections))
+        .route("/files/quarantined", get(crate::handlers::admin::list_quarantined_files))
+        .route("/files/:id/release", post(crate::handlers::admin::release_quarantined_file))
+        .route("/reindex", post(crate::handlers::admin::start_reindex))
+        .route("/reindex/status", get(crate::handlers::admin::get_reindex_status))
+        .route("/migrations", get(crate::handlers::admin::migrations_applied))
+        .route("/integrity", get(crate::handlers::admin::integrity_summary))
+        .route("/integrity/scan", post(crate::handlers::admin::start_integrity_scan))
+        .route("/config", get(crate::handlers::admin::effective_config))
+        .route("/compile-environment", get(crate::handlers::admin::compile_environment))
+        .route("/compile-environment/selftest", post(crate::handlers::admin::compile_sandbox_self_test))
+        .route("/purges", get(crate::handlers::admin::list_purges))
+        .route("/collections", get(crate::handlers::admin::list_collections).post(crate::handlers::admin::create_collection))
+        .route("/collections/:id", put(crate::handlers::admin::update_collection).delete(crate::handlers::admin::delete_collection))
+        .route("/announcements", get(crate::handlers::admin::list_announcements).post(crate::handlers::admin::create_announcement))
+        .route("/announcements/:id", put(crate::handlers::admin::update_announcement).delete(crate::handlers::admin::delete_announcement))
+        .route("/seed", post(crate::handlers::admin::seed))
+        .route("/performance", get(crate::handlers::admin::performance))
+        .route("/conflicts", get(crate::handlers::admin::conflicts))
+        .route("/conflicts/resolution", get(crate::handlers::admin::conflict_resolution))
+        .route("/usage", get(crate::handlers::admin::usage))
+}
+
+/// File share link routes
+fn shared_routes() -> Router<AppState> {
+    Router::new()
+        .route("/files/:token", get(crate::handlers::shared::get_shared_file))
+        .route("/files/:token/pdf", get(crate::handlers::shared::get_shared_file_pdf))
+        .layer(middleware::from_fn(skip_auth_middleware))
+}
+
+/// Public project discovery routes
+fn discover_routes() -> Router<AppState> {
+    Router::new()
+        .route("/trending", get(crate::handlers::discovery::trending_projects))
+        .route("/recent", get(crate::handlers::discovery::recent_projects))
+        .route("/collections", get(crate::handlers::discovery::list_collections))
+        .layer(middleware::from_fn(skip_auth_middleware))
+}
+
+/// Search routes
+fn search_routes() -> Router<AppState> {
+    Router::new()
+        .route("/quick", get(crate::handlers::search::quick_search))
+}
+
 /// Collaboration routes
 fn collaboration_routes() -> Router<AppState> {
     Router::new()
         // Session routes (require auth)
         .route("/sessions", get(crate::handlers::collaboration::list_sessions).post(crate::handlers::collaboration::create_session))
+        .route("/sessions/upcoming", get(crate::handlers::collaboration::upcoming_sessions))
         .route("/sessions/:id", get(crate::handlers::collaboration::get_session).put(crate::handlers::collaboration::update_session).delete(crate::handlers::collaboration::delete_session))
         .route("/sessions/:id/join", post(crate::handlers::collaboration::join_session))
+        .route("/sessions/:id/password", put(crate::handlers::collaboration::set_session_password))
+        .route("/sessions/:id/join-code", post(crate::handlers::collaboration::create_join_code))
+        .route("/sessions/:id/anonymity", put(crate::handlers::collaboration::enable_anonymity).delete(crate::handlers::collaboration::disable_anonymity))
         .route("/sessions/:id/leave", post(crate::handlers::collaboration::leave_session))
         .route("/sessions/:id/participants", get(crate::handlers::collaboration::get_participants))
         .route("/sessions/:id/operations", post(crate::handlers::collaboration::create_operation))
         .route("/sessions/:id/messages", get(crate::handlers::collaboration::get_messages).post(crate::handlers::collaboration::send_message))
+        .route("/sessions/:id/messages/:message_id", put(crate::handlers::collaboration::edit_message))
+        .route("/sessions/:id/attachments", post(crate::handlers::collaboration::upload_attachment))
+        .route("/sessions/:id/attachments/:attachment_id", get(crate::handlers::collaboration::download_attachment))
+        .route("/sessions/:id/attachments/:attachment_id/thumbnail", get(crate::handlers::collaboration::get_attachment_thumbnail))
         .route("/sessions/:id/invite", post(crate::handlers::collaboration::invite_participant))
         .route("/sessions/:id/stats", get(crate::handlers::collaboration::get_session_stats))
-        // Public invitation routes (no auth required)
+        .route("/sessions/:id/ics", get(crate::handlers::collaboration::get_session_ics))
+        // Invitation routes; only viewing one is public
         .nest("/invitations", Router::new()
             .route("/:token", get(crate::handlers::collaboration::get_invitation).post(crate::handlers::collaboration::accept_invitation))
             .layer(middleware::from_fn(skip_auth_middleware))
         )
 }
 
-/// Health check endpoint
-async fn health_check() -> impl IntoResponse {
-    Json(serde_json::json!({
-        "status": "healthy",
-        "timestamp": chrono::Utc::now(),
-        "version": env!("CARGO_PKG_VERSION")
-    }))
-}
-
 /// Not found handler
 async fn not_found_handler() -> impl IntoResponse {
     let status = StatusCode::NOT_FOUND;
@@ -280,7 +522,16 @@ async fn request_id_middleware(
     let mut request = request;
     request.extensions_mut().insert(request_id);
 
-    Ok(next.run(request).await)
+    // Everything the request does, including work it spawns, is logged in its span
+    let span = crate::correlation::request_span(request_id, request.method(), request.uri(), request.headers());
+    let mut response = crate::correlation::scope(Some(request_id), next.run(request))
+        .instrument(span)
+        .await;
+    if let Ok(value) = HeaderValue::from_str(&request_id.to_string()) {
+        response.headers_mut().insert(crate::correlation::REQUEST_ID_HEADER, value);
+    }
+
+    Ok(response)
 }
 
 /// Skip authentication middleware for specific routes
@@ -298,13 +549,29 @@ async fn auth_middleware(
     mut request: Request,
     next: Next,
 ) -> Result<Response, Infallible> {
-    // Skip authentication for health check, auth routes, LaTeX proxy routes, collaboration invitations, and OPTIONS requests
+    // Skip authentication for health check, API docs, capabilities, auth routes, LaTeX proxy routes, collaboration invitations, avatars, embedded PDFs, discovery, shared files, and OPTIONS requests
     let path = request.uri().path();
     let method = request.method();
+    // Rendering inline math is rate limited per user, only fetching a render is public
+    let renders_inline = path == "/api/v1/latex/render-inline" && method == axum::http::Method::POST;
+    // Looking up a session invitation is public, accepting one needs an account
+    let views_invitation = path.starts_with("/api/v1/collaboration/invitations") && method == axum::http::Method::GET;
+    // Avatars are shown in image tags, which send no token
+    let views_avatar = path.starts_with("/api/v1/users/") && path.ends_with("/avatar") && method == axum::http::Method::GET;
+    // Embedded PDFs are loaded by iframes on other sites and carry their own token
+    let views_embed = path.starts_with("/api/v1/projects/") && path.ends_with("/latest.pdf") && method == axum::http::Method::GET;
     if path == "/health"
+        || path.starts_with("/health/")
+        || path == "/api/v1/openapi.json"
+        || path == "/api/v1/capabilities"
+        || path.starts_with("/api/docs")
         || path.starts_with("/api/v1/auth")
-        || path.starts_with("/api/v1/latex")
-        || path.starts_with("/api/v1/collaboration/invitations")
+        || (path.starts_with("/api/v1/latex") && !renders_inline)
+        || views_invitation
+        || views_avatar
+        || views_embed
+        || path.starts_with("/api/v1/discover/")
+        || (path.starts_with("/api/v1/shared/") && method == axum::http::Method::GET)
         || method == axum::http::Method::OPTIONS {
         return Ok(next.run(request).await);
     }
@@ -316,9 +583,14 @@ async fn auth_middleware(
 
     if let Some(auth_header) = auth_header {
         if let Some(token) = auth_header.strip_prefix("Bearer ") {
-            match state.jwt_service.verify_token_with_db(token, &state.db_pool).await {
+            let verified = state
+                .db_resilience
+                .read(|| state.jwt_service.verify_token_with_db(token, &state.db_pool))
+                .await;
+            match verified {
                 Ok(claims) => {
                     let auth_context = crate::models::auth::AuthContext::from(claims);
+                    tracing::Span::current().record("user_id", tracing::field::display(auth_context.user_id));
 
                     if auth_context.is_expired() {
                         return Ok(AppError::Authentication("Token has expired".to_string()).into_response());
@@ -339,27 +611,37 @@ async fn auth_middleware(
 
 /// Logging middleware
 async fn logging_middleware(
-    State(_state): State<AppState>,
+    State(state): State<AppState>,
     request: Request,
     next: Next,
 ) -> Result<Response, Infallible> {
     let method = request.method().clone();
     let uri = request.uri().clone();
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .filter(|_| state.performance.enabled())
+        .map(|path| path.as_str().to_string());
     let request_id = request
         .extensions()
         .get::<RequestId>()
         .map(|id| id.to_string())
         .unwrap_or_else(|| "unknown".to_string());
 
+    // The switcher is polled on every navigation; its successes are not logged
+    let quiet = uri.path() == "/api/v1/projects/switcher";
+
     let start_time = std::time::Instant::now();
 
     let response = next.run(request).await;
 
     let duration = start_time.elapsed();
     let status = response.status();
+    state.performance.record_request(method.as_str(), route.as_deref(), status.as_u16(), duration);
 
     // Log request
     match status.as_u16() {
+        200..=299 if quiet => {}
         200..=299 => {
             info!(
                 request_id = %request_id,
@@ -397,7 +679,7 @@ async fn logging_middleware(
 }
 
 impl AppState {
-    /// Create new application state with OIDC clients
+    /// Create new application state
     pub async fn new(config: Config, db_pool: sqlx::PgPool) -> Result<Self, AppError> {
         // Initialize JWT service
         let jwt_service = crate::models::auth::JwtService::new(
@@ -407,35 +689,59 @@ impl AppState {
             config.jwt.refresh_expiration as i64,
         )?;
 
-        // Initialize OIDC clients if enabled
-        let mut oidc_clients = std::collections::HashMap::new();
-
-        if config.oidc.enabled {
-            for provider in &config.oidc.providers {
-                // For now, only support GitHub
-                if provider.name == "github" {
-                    let oidc_client = authware::OidcClient::builder()
-                        .new(authware::OidcProvider::GitHub, provider.client_id.clone(), provider.redirect_uri.clone())
-                        .client_secret(provider.client_secret.clone())
-                        .scopes(provider.scopes.clone())
-                        .pkce(true)
-                        .build()
-                        .map_err(|e| AppError::Internal(format!("Failed to initialize GitHub OIDC client: {}", e)))?;
-
-                    oidc_clients.insert(provider.name.clone(), oidc_client);
-                    info!("Initialized GitHub OIDC client");
-                } else {
-                    warn!("OIDC provider '{}' not supported yet. Only GitHub is supported.", provider.name);
-                }
-            }
-        }
+        let user_channels = Arc::new(crate::websocket::UserChannels::new());
+        let ws_connection_limiter = Arc::new(crate::models::ws_connection_limit::WsConnectionLimiter::from_config(
+            &config.websocket,
+        ));
+        let mailer = Arc::new(crate::email::Mailer::new(&config, db_pool.clone())?);
+        let compile_notifier = Arc::new(crate::models::compile_notification::CompileNotifier::new(
+            mailer.clone(),
+            user_channels.clone(),
+        ));
+        let mention_notifier = Arc::new(crate::models::mention::MentionNotifier::new(
+            mailer.clone(),
+            user_channels.clone(),
+        ));
+        let inline_renderer = Arc::new(crate::models::inline_render::InlineRenderer::new(&config.latex.temp_dir));
+        let compile_sandbox = Arc::new(crate::models::compile_sandbox::CompileSandbox::new(&config.latex));
+        let http_client = Arc::new(crate::http_client::HttpClient::new((&config.http_client).into())?);
+        let file_scanner = Arc::new(crate::scanner::FileScanService::from_config(&config.scanning)?);
+        let secret_keyring = Arc::new(crate::models::project_secret::SecretKeyring::from_config(&config.secrets)?);
+        let performance = Arc::new(crate::performance::PerformanceMonitor::new(&config));
+        let conflicts = Arc::new(crate::models::conflict_event::ConflictRecorder::new(&config));
+        let db_resilience = Arc::new(crate::db_resilience::DbResilience::new(
+            (&config.database).into(),
+            db_pool.clone(),
+        ));
 
         Ok(AppState {
             config: Arc::new(config),
             db_pool,
-            oidc_clients: Arc::new(oidc_clients),
             jwt_service: Arc::new(jwt_service),
             rate_limiter: Arc::new(crate::middleware::RateLimiter::new()),
+            health: Arc::new(crate::handlers::health::HealthChecker::new()),
+            tasks: Arc::new(crate::tasks::TaskRegistry::with_default_tasks()),
+            readme_cache: Arc::new(crate::models::readme::ReadmeCache::new()),
+            autocomplete_cache: Arc::new(crate::models::autocomplete::AutocompleteCache::new()),
+            include_graph_cache: Arc::new(crate::models::include_graph::IncludeGraphCache::new()),
+            blame_cache: Arc::new(crate::models::file_blame::BlameCache::new()),
+            user_channels,
+            ws_connection_limiter,
+            project_presence: Arc::new(crate::models::project_presence::ProjectPresence::new()),
+            compile_notifier,
+            mention_notifier,
+            inline_renderer,
+            compile_sandbox,
+            http_client,
+            file_scanner,
+            mailer,
+            secret_keyring,
+            running_compiles: Arc::new(crate::models::compile_cancel::RunningCompiles::new()),
+            project_views: Arc::new(crate::models::discovery::ProjectViews::new()),
+            oidc_metrics: Arc::new(crate::models::oidc_flow::OidcMetrics::new()),
+            performance,
+            conflicts,
+            db_resilience,
         })
     }
 }
@@ -461,12 +767,27 @@ pub async fn start_server(config: Config, db_pool: sqlx::PgPool) -> Result<(), A
     let listener = tokio::net::TcpListener::bind(addr).await
         .map_err(|e| AppError::Config(format!("Failed to bind to {}: {}", config.server.bind_address(), e)))?;
 
+    state.tasks.start(state.clone());
+    if state.mailer.is_enabled() {
+        state.mailer.start(&config.email)?;
+    }
+
     let make_service = tower::make::Shared::new(app);
+    let health = state.health.clone();
 
     axum::serve(listener, make_service)
+        .with_graceful_shutdown(async move {
+            let _ = tokio::signal::ctrl_c().await;
+            info!("Shutdown signal received, draining connections");
+            health.begin_shutdown();
+        })
         .await
         .map_err(|e| AppError::Server(format!("Server error: {}", e)))?;
 
+    state.tasks.shutdown(std::time::Duration::from_secs(30)).await;
+    // After the tasks, which may still queue emails
+    state.mailer.shutdown(std::time::Duration::from_secs(30)).await;
+
     Ok(())
 }
 
@@ -478,8 +799,11 @@ mod tests {
 
     #[tokio::test]
     async fn test_health_check() {
-        let response = health_check().await.into_response();
-        assert_eq!(response.status(), StatusCode::OK);
+        let health = crate::handlers::health::HealthChecker::new();
+        assert!(!health.is_shutting_down());
+
+        health.begin_shutdown();
+        assert!(health.is_shutting_down());
     }
 
     #[tokio::test]
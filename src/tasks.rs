@@ -0,0 +1,403 @@
+//! Periodic background tasks
+//!
+//! Housekeeping jobs implement `PeriodicTask` and are registered with the
+//! `TaskRegistry` instead of spawning their own loops. The registry runs each
+//! task on its interval with a little jitter, keeps a panicking task from
+//! taking its loop down, remembers recent outcomes for `/admin/tasks` and
+//! stops every loop on shutdown after letting in-flight runs finish.
+
+use chrono::{DateTime, Utc};
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use rand::Rng;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::future::Future;
+use std::panic::AssertUnwindSafe;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tracing::{debug, info, warn};
+use utoipa::ToSchema;
+
+use crate::error::AppError;
+use crate::server::AppState;
+
+/// Outcomes kept per task
+const RECENT_RUNS: usize = 10;
+
+/// Share of the interval a run may be moved earlier or later
+const JITTER_RATIO: f64 = 0.1;
+
+/// A job that runs on a fixed interval
+pub trait PeriodicTask: Send + Sync + 'static {
+    /// Unique name, used in the admin API
+    fn name(&self) -> &'static str;
+
+    /// Time between two runs
+    fn interval(&self) -> Duration;
+
+    fn run<'a>(&'a self, state: &'a AppState) -> BoxFuture<'a, Result<(), AppError>>;
+}
+
+/// What started a run
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum TaskTrigger {
+    Scheduled,
+    Manual,
+}
+
+/// Outcome of a single run
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct TaskRun {
+    pub trigger: TaskTrigger,
+    pub started_at: DateTime<Utc>,
+    pub duration_ms: u64,
+    /// Error message, or the panic message when the task panicked
+    pub error: Option<String>,
+}
+
+/// Schedule and recent outcomes of a task
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct TaskStatus {
+    pub name: &'static str,
+    pub interval_secs: u64,
+    pub running: bool,
+    pub next_run_at: Option<DateTime<Utc>>,
+    pub run_count: u64,
+    pub failure_count: u64,
+    pub last_error: Option<String>,
+    pub last_error_at: Option<DateTime<Utc>>,
+    /// Newest first
+    pub recent_runs: Vec<TaskRun>,
+}
+
+#[derive(Debug, Default)]
+struct TaskHistory {
+    next_run_at: Option<DateTime<Utc>>,
+    run_count: u64,
+    failure_count: u64,
+    last_error: Option<String>,
+    last_error_at: Option<DateTime<Utc>>,
+    recent_runs: VecDeque<TaskRun>,
+}
+
+struct RegisteredTask {
+    task: Box<dyn PeriodicTask>,
+    running: AtomicBool,
+    history: Mutex<TaskHistory>,
+}
+
+impl RegisteredTask {
+    fn new(task: Box<dyn PeriodicTask>) -> Self {
+        Self {
+            task,
+            running: AtomicBool::new(false),
+            history: Mutex::new(TaskHistory::default()),
+        }
+    }
+
+    /// Run the task once unless a run is already in progress
+    async fn run_once(&self, state: &AppState, trigger: TaskTrigger) -> Option<TaskRun> {
+        self.execute(trigger, self.task.run(state)).await
+    }
+
+    async fn execute<F>(&self, trigger: TaskTrigger, run: F) -> Option<TaskRun>
+    where
+        F: Future<Output = Result<(), AppError>>,
+    {
+        if self.running.swap(true, Ordering::SeqCst) {
+            debug!("Task {} is still running, skipping {:?} run", self.task.name(), trigger);
+            return None;
+        }
+
+        let started_at = Utc::now();
+        let start = Instant::now();
+        let error = match AssertUnwindSafe(run).catch_unwind().await {
+            Ok(Ok(())) => None,
+            Ok(Err(e)) => Some(e.to_string()),
+            Err(panic) => Some(format!("panicked: {}", panic_message(panic.as_ref()))),
+        };
+
+        let run = TaskRun {
+            trigger,
+            started_at,
+            duration_ms: start.elapsed().as_millis() as u64,
+            error,
+        };
+
+        match &run.error {
+            Some(error) => warn!("Task {} failed after {}ms: {}", self.task.name(), run.duration_ms, error),
+            None => debug!("Task {} finished in {}ms", self.task.name(), run.duration_ms),
+        }
+
+        {
+            let mut history = self.history.lock().unwrap();
+            history.run_count += 1;
+            if let Some(error) = &run.error {
+                history.failure_count += 1;
+                history.last_error = Some(error.clone());
+                history.last_error_at = Some(started_at);
+            }
+            history.recent_runs.push_front(run.clone());
+            history.recent_runs.truncate(RECENT_RUNS);
+        }
+
+        self.running.store(false, Ordering::SeqCst);
+        Some(run)
+    }
+
+    fn status(&self) -> TaskStatus {
+        let history = self.history.lock().unwrap();
+        TaskStatus {
+            name: self.task.name(),
+            interval_secs: self.task.interval().as_secs(),
+            running: self.running.load(Ordering::SeqCst),
+            next_run_at: history.next_run_at,
+            run_count: history.run_count,
+            failure_count: history.failure_count,
+            last_error: history.last_error.clone(),
+            last_error_at: history.last_error_at,
+            recent_runs: history.recent_runs.iter().cloned().collect(),
+        }
+    }
+
+    fn schedule(&self, delay: Duration) {
+        self.history.lock().unwrap().next_run_at =
+            Some(Utc::now() + chrono::Duration::from_std(delay).unwrap_or_default());
+    }
+}
+
+/// Runs the registered periodic tasks
+pub struct TaskRegistry {
+    tasks: Vec<Arc<RegisteredTask>>,
+    shutdown: watch::Sender<bool>,
+    handles: Mutex<Vec<JoinHandle<()>>>,
+}
+
+impl Default for TaskRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TaskRegistry {
+    pub fn new() -> Self {
+        Self {
+            tasks: Vec::new(),
+            shutdown: watch::channel(false).0,
+            handles: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Registry with the housekeeping tasks every server runs
+    pub fn with_default_tasks() -> Self {
+        let mut registry = Self::new();
+        registry.register(crate::middleware::rate_limit::RateLimitCleanupTask);
+        registry.register(crate::models::compile_notification::CompileDigestTask);
+        registry.register(crate::models::session_summary::SessionCompactionTask);
+        registry.register(crate::models::activity_digest::ActivityDigestTask);
+        registry.register(crate::models::activity_rollup::ActivityRollupTask);
+        registry.register(crate::models::file_scan::FileRescanTask);
+        registry.register(crate::models::file_reindex::FileReindexTask);
+        registry.register(crate::models::chat_attachment::AttachmentCleanupTask);
+        registry.register(crate::models::project_freeze::AutoUnfreezeTask);
+        registry.register(crate::models::integrity::IntegrityScanTask);
+        registry.register(crate::models::session_schedule::SessionScheduleTask);
+        registry.register(crate::models::file_history::VersionCompactionTask);
+        registry.register(crate::models::project_purge::ProjectPurgeTask);
+        registry.register(crate::models::activity_export::ActivityExportCleanupTask);
+        registry.register(crate::models::project_secret::ProjectSecretRekeyTask);
+        registry.register(crate::models::word_count::WordCountBackfillTask);
+        registry.register(crate::models::git_import::GitImportCleanupTask);
+        registry.register(crate::models::discovery::ViewFlushTask);
+        registry.register(crate::models::discovery::TrendingTask);
+        registry.register(crate::models::incremental_build::BuildCacheTask);
+        registry.register(crate::models::oidc_flow::OidcFlowCleanupTask);
+        registry.register(crate::models::project_presence::PresenceReconcileTask);
+        registry.register(crate::performance::PoolSampleTask);
+        registry.register(crate::models::conflict_event::ConflictFlushTask);
+        registry.register(crate::models::compile_thumbnails::ThumbnailTask);
+        registry.register(crate::models::usage_period::UsageRollupTask);
+        registry.register(crate::db_resilience::DbFlushTask);
+        registry.register(crate::models::announcement::AnnouncementDeliveryTask);
+        registry.register(crate::models::upload::UploadCleanupTask);
+        registry.register(crate::models::compile_sandbox::SandboxScanTask);
+        registry
+    }
+
+    /// Add a task; panics when the name is already taken
+    pub fn register<T: PeriodicTask>(&mut self, task: T) {
+        assert!(
+            self.find(task.name()).is_none(),
+            "periodic task {} registered twice",
+            task.name()
+        );
+        self.tasks.push(Arc::new(RegisteredTask::new(Box::new(task))));
+    }
+
+    /// Start one loop per task
+    pub fn start(&self, state: AppState) {
+        let mut handles = self.handles.lock().unwrap();
+
+        for entry in &self.tasks {
+            let entry = entry.clone();
+            let state = state.clone();
+            let mut shutdown = self.shutdown.subscribe();
+
+            handles.push(tokio::spawn(async move {
+                let interval = entry.task.interval();
+                // Spread the first runs so tasks do not all fire at boot
+                let mut delay = interval.mul_f64(rand::thread_rng().gen_range(0.0..JITTER_RATIO));
+
+                loop {
+                    entry.schedule(delay);
+                    tokio::select! {
+                        _ = tokio::time::sleep(delay) => {}
+                        _ = shutdown.changed() => break,
+                    }
+
+                    entry.run_once(&state, TaskTrigger::Scheduled).await;
+                    delay = jittered(interval);
+                }
+
+                debug!("Task {} stopped", entry.task.name());
+            }));
+        }
+
+        info!("Started {} periodic tasks", self.tasks.len());
+    }
+
+    /// Stop scheduling runs and wait up to `grace` for running ones to finish
+    pub async fn shutdown(&self, grace: Duration) {
+        let _ = self.shutdown.send(true);
+
+        let handles: Vec<JoinHandle<()>> = self.handles.lock().unwrap().drain(..).collect();
+        let aborts: Vec<_> = handles.iter().map(|handle| handle.abort_handle()).collect();
+
+        if tokio::time::timeout(grace, futures::future::join_all(handles)).await.is_err() {
+            warn!("Periodic tasks did not finish within {:?}, aborting them", grace);
+            for abort in aborts {
+                abort.abort();
+            }
+        }
+    }
+
+    /// Status of every task, in registration order
+    pub fn statuses(&self) -> Vec<TaskStatus> {
+        self.tasks.iter().map(|entry| entry.status()).collect()
+    }
+
+    /// Run a task now in the background, outside its schedule
+    pub fn trigger(&self, name: &str, state: AppState) -> Result<TaskStatus, AppError> {
+        let entry = self.find(name).ok_or_else(|| AppError::NotFound {
+            entity: "Task".to_string(),
+            id: name.to_string(),
+        })?;
+
+        if entry.running.load(Ordering::SeqCst) {
+            return Err(AppError::Conflict(format!("Task {} is already running", name)));
+        }
+
+        let task = entry.clone();
+        crate::correlation::spawn(async move {
+            task.run_once(&state, TaskTrigger::Manual).await;
+        });
+
+        Ok(entry.status())
+    }
+
+    fn find(&self, name: &str) -> Option<&Arc<RegisteredTask>> {
+        self.tasks.iter().find(|entry| entry.task.name() == name)
+    }
+}
+
+/// Interval moved randomly by up to `JITTER_RATIO` in either direction
+fn jittered(interval: Duration) -> Duration {
+    interval.mul_f64(1.0 + rand::thread_rng().gen_range(-JITTER_RATIO..=JITTER_RATIO))
+}
+
+fn panic_message(panic: &(dyn std::any::Any + Send)) -> String {
+    panic
+        .downcast_ref::<&str>()
+        .map(|message| message.to_string())
+        .or_else(|| panic.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "unknown panic".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct NoopTask;
+
+    impl PeriodicTask for NoopTask {
+        fn name(&self) -> &'static str {
+            "noop"
+        }
+
+        fn interval(&self) -> Duration {
+            Duration::from_secs(60)
+        }
+
+        fn run<'a>(&'a self, _state: &'a AppState) -> BoxFuture<'a, Result<(), AppError>> {
+            Box::pin(async { Ok(()) })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_execute_records_failures_and_panics() {
+        let entry = RegisteredTask::new(Box::new(NoopTask));
+
+        let failed = entry
+            .execute(TaskTrigger::Scheduled, async { Err(AppError::Internal("disk full".to_string())) })
+            .await
+            .unwrap();
+        assert!(failed.error.unwrap().contains("disk full"));
+
+        let panicked = entry
+            .execute(TaskTrigger::Manual, async { panic!("boom") })
+            .await
+            .unwrap();
+        assert_eq!(panicked.error.as_deref(), Some("panicked: boom"));
+
+        entry.execute(TaskTrigger::Scheduled, async { Ok(()) }).await.unwrap();
+
+        let status = entry.status();
+        assert!(!status.running);
+        assert_eq!(status.run_count, 3);
+        assert_eq!(status.failure_count, 2);
+        assert_eq!(status.last_error.as_deref(), Some("panicked: boom"));
+        assert_eq!(status.recent_runs[0].error, None);
+        assert_eq!(status.recent_runs[1].trigger, TaskTrigger::Manual);
+    }
+
+    #[tokio::test]
+    async fn test_execute_skips_overlapping_runs() {
+        let entry = RegisteredTask::new(Box::new(NoopTask));
+        entry.running.store(true, Ordering::SeqCst);
+
+        assert!(entry.execute(TaskTrigger::Manual, async { Ok(()) }).await.is_none());
+        assert_eq!(entry.status().run_count, 0);
+    }
+
+    #[test]
+    fn test_jittered_stays_within_bounds() {
+        let interval = Duration::from_secs(100);
+        for _ in 0..100 {
+            let delay = jittered(interval);
+            assert!(delay >= Duration::from_secs(90) && delay <= Duration::from_secs(110));
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "registered twice")]
+    fn test_register_rejects_duplicate_names() {
+        let mut registry = TaskRegistry::new();
+        registry.register(NoopTask);
+        registry.register(NoopTask);
+    }
+}
@@ -0,0 +1,105 @@
+//! Log output and trace export
+//!
+//! Logs go to stdout, filtered by `RUST_LOG`. Builds with the `otlp` feature
+//! also export spans over OTLP when metrics are enabled and `OTLP_ENDPOINT`
+//! is set, see `correlation` for how they are tied together.
+
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::EnvFilter;
+
+use crate::config::Config;
+
+/// Service name traces are exported under
+#[cfg(feature = "otlp")]
+const SERVICE_NAME: &str = "texler-backend";
+
+/// Install the global subscriber
+pub fn init(config: &Config) -> Result<(), Box<dyn std::error::Error>> {
+    let registry = tracing_subscriber::registry()
+        .with(EnvFilter::from_default_env())
+        .with(tracing_subscriber::fmt::layer());
+
+    #[cfg(feature = "otlp")]
+    registry.with(otlp_layer(config)?).try_init()?;
+
+    #[cfg(not(feature = "otlp"))]
+    {
+        registry.try_init()?;
+        if config.logging.otlp_endpoint.is_some() {
+            tracing::warn!("OTLP_ENDPOINT is set, but this build cannot export traces (feature otlp)");
+        }
+    }
+
+    Ok(())
+}
+
+/// Flush spans not exported yet
+pub fn shutdown() {
+    #[cfg(feature = "otlp")]
+    opentelemetry::global::shutdown_tracer_provider();
+}
+
+#[cfg(feature = "otlp")]
+fn otlp_layer<S>(
+    config: &Config,
+) -> Result<Option<tracing_opentelemetry::OpenTelemetryLayer<S, opentelemetry_sdk::trace::Tracer>>, Box<dyn std::error::Error>>
+where
+    S: tracing::Subscriber + for<'span> tracing_subscriber::registry::LookupSpan<'span>,
+{
+    use opentelemetry::trace::TracerProvider as _;
+    use opentelemetry_otlp::WithExportConfig;
+
+    let Some(endpoint) = config.logging.otlp_endpoint.as_deref().filter(|_| config.features.metrics) else {
+        return Ok(None);
+    };
+
+    let exporter = opentelemetry_otlp::SpanExporter::builder()
+        .with_tonic()
+        .with_endpoint(endpoint)
+        .build()?;
+    let provider = opentelemetry_sdk::trace::TracerProvider::builder()
+        .with_batch_exporter(exporter, opentelemetry_sdk::runtime::Tokio)
+        .with_resource(opentelemetry_sdk::Resource::new(vec![opentelemetry::KeyValue::new(
+            "service.name",
+            SERVICE_NAME,
+        )]))
+        .build();
+    let tracer = provider.tracer(SERVICE_NAME);
+
+    opentelemetry::global::set_text_map_propagator(opentelemetry_sdk::propagation::TraceContextPropagator::new());
+    opentelemetry::global::set_tracer_provider(provider);
+
+    Ok(Some(tracing_opentelemetry::layer().with_tracer(tracer)))
+}
+
+/// Reads trace context from request headers
+#[cfg(feature = "otlp")]
+pub(crate) struct HeaderExtractor<'a>(pub &'a axum::http::HeaderMap);
+
+#[cfg(feature = "otlp")]
+impl opentelemetry::propagation::Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|key| key.as_str()).collect()
+    }
+}
+
+/// Writes trace context into request headers
+#[cfg(feature = "otlp")]
+pub(crate) struct HeaderInjector<'a>(pub &'a mut axum::http::HeaderMap);
+
+#[cfg(feature = "otlp")]
+impl opentelemetry::propagation::Injector for HeaderInjector<'_> {
+    fn set(&mut self, key: &str, value: String) {
+        if let (Ok(name), Ok(value)) = (
+            axum::http::HeaderName::from_bytes(key.as_bytes()),
+            axum::http::HeaderValue::from_str(&value),
+        ) {
+            self.0.insert(name, value);
+        }
+    }
+}
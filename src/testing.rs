@@ -0,0 +1,469 @@
+//! Test harness backed by a real PostgreSQL database
+//!
+//! Tests that need a database call [`TestDb::start`], which connects to
+//! `TEST_DATABASE_URL` when set and otherwise starts a throwaway Postgres
+//! container. Either way the schema is brought up with
+//! [`crate::migrate::run_migrations`]. When neither is available the test is
+//! skipped, unless `TEXLER_REQUIRE_TEST_DB` is set (as in CI), in which case
+//! it fails instead.
+
+use axum::{
+    body::Body,
+    http::{Request, StatusCode},
+    Extension, Router,
+};
+use chrono::{Duration, Utc};
+use sqlx::{postgres::PgPoolOptions, PgPool};
+use testcontainers::{runners::AsyncRunner, ContainerAsync, ImageExt};
+use testcontainers_modules::postgres::Postgres;
+use tower::ServiceExt;
+use uuid::Uuid;
+
+use crate::config::Config;
+use crate::models::auth::AuthContext;
+use crate::models::collaboration::CollaborationSession;
+use crate::models::compilation::{CompilationJob, CreateCompilationJob};
+use crate::models::file::{CreateFile, File};
+use crate::models::project::{CreateProject, Project, ProjectCollaborator};
+use crate::models::project_limits::ProjectLimits;
+use crate::models::user::{CreateUser, User};
+use crate::models::validation::{DisplayName, FileName, ProjectName};
+use crate::models::workspace::Workspace;
+use crate::models::{PaginationParams, SortOrder, Sortable, UserRole};
+use crate::scanner::{ClamAvScanner, ClamdAddress, FileScanService};
+use crate::server::AppState;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+/// Postgres image tag used when no `TEST_DATABASE_URL` is given
+const POSTGRES_TAG: &str = "16-alpine";
+
+/// Serializes migration runs against a shared `TEST_DATABASE_URL`
+static MIGRATIONS: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+/// A migrated database for the duration of one test
+pub struct TestDb {
+    pub pool: PgPool,
+    // Stops the container when the test finishes
+    _container: Option<ContainerAsync<Postgres>>,
+}
+
+impl TestDb {
+    /// Connect to a migrated database, or `None` when none is available
+    pub async fn start() -> Option<Self> {
+        let (url, container) = match std::env::var("TEST_DATABASE_URL") {
+            Ok(url) => (url, None),
+            Err(_) => {
+                let container = match Postgres::default().with_tag(POSTGRES_TAG).start().await {
+                    Ok(container) => container,
+                    Err(e) => return skip(&format!("could not start Postgres container: {}", e)),
+                };
+                let host = container.get_host().await.ok()?;
+                let port = container.get_host_port_ipv4(5432).await.ok()?;
+                (
+                    format!("postgres://postgres:postgres@{}:{}/postgres", host, port),
+                    Some(container),
+                )
+            }
+        };
+
+        let pool = match PgPoolOptions::new().max_connections(5).connect(&url).await {
+            Ok(pool) => pool,
+            Err(e) => return skip(&format!("could not connect to {}: {}", url, e)),
+        };
+
+        {
+            let _guard = MIGRATIONS.lock().await;
+            crate::migrate::run_migrations(&pool)
+                .await
+                .expect("migrations should apply to the test database");
+        }
+
+        Some(Self {
+            pool,
+            _container: container,
+        })
+    }
+}
+
+fn skip(reason: &str) -> Option<TestDb> {
+    if std::env::var_os("TEXLER_REQUIRE_TEST_DB").is_some() {
+        panic!("test database required: {}", reason);
+    }
+    eprintln!("skipping database test: {}", reason);
+    None
+}
+
+/// Configuration loaded from the environment, with a test JWT secret
+pub fn test_config() -> Config {
+    static JWT_SECRET: std::sync::Once = std::sync::Once::new();
+    JWT_SECRET.call_once(|| {
+        if std::env::var_os("JWT_SECRET").is_none() {
+            std::env::set_var("JWT_SECRET", "texler-test-secret-that-is-long-enough");
+        }
+    });
+
+    Config::load().expect("test configuration should load")
+}
+
+/// Application state over the test database
+pub async fn test_state(db: &TestDb) -> AppState {
+    AppState::new(test_config(), db.pool.clone())
+        .await
+        .expect("application state should build")
+}
+
+/// A user with a unique name and the password `password123`
+pub async fn create_test_user(db: &PgPool) -> User {
+    let suffix = Uuid::new_v4().simple().to_string();
+    let username = format!("user_{}", &suffix[..12]);
+
+    User::create(
+        db,
+        CreateUser {
+            email: format!("{}@example.com", username),
+            username,
+            password: "password123".to_string(),
+            display_name: DisplayName::new("Test User").unwrap(),
+            avatar_url: None,
+        },
+    )
+    .await
+    .expect("test user should be created")
+}
+
+/// A project in the owner's default workspace
+pub async fn create_test_project(db: &PgPool, owner: &User, is_public: bool) -> Project {
+    let workspace = Workspace::ensure_default(db, owner.id)
+        .await
+        .expect("default workspace should exist");
+
+    Project::create(
+        db,
+        owner.id,
+        CreateProject {
+            name: ProjectName::new("Test Project").unwrap(),
+            description: None,
+            is_public: Some(is_public),
+            main_file_path: None,
+            latex_engine: None,
+            output_format: None,
+            custom_args: None,
+            bibliography_path: None,
+            tags: None,
+            workspace_id: Some(workspace.id),
+        },
+    )
+    .await
+    .expect("test project should be created")
+}
+
+/// Add `user` to `project` with `role`, invited by the project owner
+pub async fn add_collaborator(
+    db: &PgPool,
+    project: &Project,
+    user: &User,
+    role: UserRole,
+) -> ProjectCollaborator {
+    ProjectCollaborator::add(db, project.id, user.id, role, project.owner_id)
+        .await
+        .expect("collaborator should be added")
+}
+
+/// The project's `main.tex`
+pub async fn create_test_file(db: &PgPool, project: &Project, author: &User) -> File {
+    File::create(
+        db,
+        &ProjectLimits::default(),
+        project.id,
+        CreateFile {
+            name: FileName::new("main.tex").unwrap(),
+            path: "main.tex".to_string(),
+            content: Some("\\documentclass{article}\n\\begin{document}\nHi\n\\end{document}\n".to_string()),
+            content_type: None,
+        },
+        author.id,
+    )
+    .await
+    .expect("test file should be created")
+}
+
+/// A queued compilation of the project's main file
+pub async fn create_test_job(db: &PgPool, project: &Project, user: &User) -> CompilationJob {
+    CompilationJob::create(
+        db,
+        project.id,
+        user.id,
+        CreateCompilationJob {
+            file_id: None,
+            engine: None,
+            args: None,
+            priority: None,
+            template_id: None,
+            clean: false,
+            batch_id: None,
+        },
+        project.latex_engine,
+        &project.main_file_path,
+        format!("/tmp/texler-test/{}", project.id),
+        vec![project.main_file_path.clone()],
+    )
+    .await
+    .expect("test compilation job should be created")
+}
+
+/// An active collaboration session on `project`
+pub async fn create_test_session(db: &PgPool, project: &Project, creator: &User) -> CollaborationSession {
+    sqlx::query_as::<_, CollaborationSession>(
+        r#"
+        INSERT INTO collaboration_sessions (project_id, created_by, title, started_at)
+        VALUES ($1, $2, 'Test Session', NOW())
+        RETURNING *
+        "#
+    )
+    .bind(project.id)
+    .bind(creator.id)
+    .fetch_one(db)
+    .await
+    .expect("test session should be created")
+}
+
+/// Walk the first two pages of three under the default order and every
+/// column of `sortable` in both directions; together they must be exactly the
+/// start of the whole list, without a row repeated or skipped
+pub async fn assert_pages_are_stable<F, Fut>(sortable: &Sortable, list: F)
+where
+    F: Fn(PaginationParams) -> Fut,
+    Fut: std::future::Future<Output = Vec<Uuid>>,
+{
+    let mut sorts = vec![(None, None)];
+    for column in sortable.columns {
+        for order in [SortOrder::Asc, SortOrder::Desc] {
+            sorts.push((Some(column.name.to_string()), Some(order)));
+        }
+    }
+
+    for (sort_by, sort_order) in sorts {
+        let params = |page: u32, limit: u32| PaginationParams {
+            page: Some(page),
+            limit: Some(limit),
+            offset: None,
+            sort_by: sort_by.clone(),
+            sort_order,
+        };
+        let all = list(params(1, 100)).await;
+        assert!(all.len() > 3, "the list should span two pages");
+
+        let mut walked = list(params(1, 3)).await;
+        walked.extend(list(params(2, 3)).await);
+        assert_eq!(walked, &all[..all.len().min(6)], "sorted by {:?} {:?}", sort_by, sort_order);
+    }
+}
+
+/// The context the auth middleware would attach for `user`
+pub fn auth_context(user: &User) -> AuthContext {
+    let now = Utc::now();
+    AuthContext {
+        user_id: user.id,
+        username: user.username.clone(),
+        email: user.email.clone(),
+        roles: vec![UserRole::Collaborator],
+        token_issued_at: now,
+        token_expires_at: now + Duration::hours(1),
+    }
+}
+
+/// Send `request` through `router` as `user`, bypassing token validation
+///
+/// Returns the status and the JSON body (`Value::Null` when the body is empty).
+pub async fn oneshot_as(
+    router: Router<AppState>,
+    state: AppState,
+    user: &User,
+    request: Request<Body>,
+) -> (StatusCode, serde_json::Value) {
+    let response = router
+        .layer(Extension(auth_context(user)))
+        .with_state(state)
+        .oneshot(request)
+        .await
+        .expect("router is infallible");
+
+    let status = response.status();
+    let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .expect("response body should be readable");
+    let body = if bytes.is_empty() {
+        serde_json::Value::Null
+    } else {
+        serde_json::from_slice(&bytes).expect("response body should be JSON")
+    };
+
+    (status, body)
+}
+
+/// Counts the SQL statements sqlx runs on the current thread
+///
+/// Tests run on a current-thread runtime, so every query of a request sent
+/// while the counter is alive is seen. Use a pool from
+/// [`single_connection_pool`] and send the request once before counting:
+/// sqlx looks up column types once per connection, which would otherwise
+/// show up as extra queries.
+pub struct QueryCounter {
+    count: Arc<AtomicUsize>,
+    _guard: tracing::subscriber::DefaultGuard,
+}
+
+impl QueryCounter {
+    pub fn start() -> Self {
+        use tracing_subscriber::layer::SubscriberExt;
+
+        let count = Arc::new(AtomicUsize::new(0));
+        let subscriber = tracing_subscriber::registry().with(CountQueries(count.clone()));
+        Self {
+            count,
+            _guard: tracing::subscriber::set_default(subscriber),
+        }
+    }
+
+    /// Statements run since the counter started
+    pub fn count(&self) -> usize {
+        self.count.load(Ordering::SeqCst)
+    }
+}
+
+struct CountQueries(Arc<AtomicUsize>);
+
+impl<S: tracing::Subscriber> tracing_subscriber::Layer<S> for CountQueries {
+    fn on_event(&self, event: &tracing::Event<'_>, _ctx: tracing_subscriber::layer::Context<'_, S>) {
+        if event.metadata().target() == "sqlx::query" {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+}
+
+/// A pool over the test database holding a single connection
+pub async fn single_connection_pool(db: &TestDb) -> PgPool {
+    PgPoolOptions::new()
+        .max_connections(1)
+        .connect_with(db.pool.connect_options().as_ref().clone())
+        .await
+        .expect("test database should accept another connection")
+}
+
+/// A TCP proxy in front of the test database that can cut it off, the way
+/// a database restart does
+#[derive(Clone)]
+pub struct DbProxy {
+    refusing: Arc<std::sync::atomic::AtomicBool>,
+    connections: Arc<std::sync::Mutex<Vec<tokio::task::AbortHandle>>>,
+}
+
+impl DbProxy {
+    /// Start a proxy to the database of `db`; returns it with a pool that
+    /// connects through it and gives up waiting for a connection after 500 ms
+    pub async fn start(db: &TestDb) -> (Self, PgPool) {
+        let options = db.pool.connect_options().as_ref().clone();
+        let target = format!("{}:{}", options.get_host(), options.get_port());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let proxy = Self {
+            refusing: Arc::new(std::sync::atomic::AtomicBool::new(false)),
+            connections: Arc::new(std::sync::Mutex::new(Vec::new())),
+        };
+
+        let accepting = proxy.clone();
+        tokio::spawn(async move {
+            loop {
+                let Ok((mut client, _)) = listener.accept().await else { break };
+                if accepting.refusing.load(Ordering::SeqCst) {
+                    continue;
+                }
+                let target = target.clone();
+                let forward = tokio::spawn(async move {
+                    if let Ok(mut server) = tokio::net::TcpStream::connect(&target).await {
+                        let _ = tokio::io::copy_bidirectional(&mut client, &mut server).await;
+                    }
+                });
+                accepting.connections.lock().unwrap().push(forward.abort_handle());
+            }
+        });
+
+        let pool = PgPoolOptions::new()
+            .max_connections(2)
+            .acquire_timeout(std::time::Duration::from_millis(500))
+            .connect_with(options.host("127.0.0.1").port(port))
+            .await
+            .expect("the proxy should forward to the test database");
+        (proxy, pool)
+    }
+
+    /// Close every connection made so far
+    pub fn sever(&self) {
+        for connection in self.connections.lock().unwrap().drain(..) {
+            connection.abort();
+        }
+    }
+
+    /// Close new connections as soon as they are accepted, or stop doing so
+    pub fn refuse(&self, refusing: bool) {
+        self.refusing.store(refusing, Ordering::SeqCst);
+    }
+}
+
+/// The EICAR test string, which the mock clamd reports as infected
+pub const EICAR: &[u8] = b"X5O!P%@AP[4\\PZX54(P^)7CC)7}$EICAR-STANDARD-ANTIVIRUS-TEST-FILE!$H+H*";
+
+/// A clamd stand-in speaking INSTREAM over TCP; reports streams containing
+/// the EICAR test string as infected. Returns its address and the number
+/// of streams it received.
+pub async fn mock_clamd() -> (String, Arc<AtomicUsize>) {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+    let streams = Arc::new(AtomicUsize::new(0));
+    let counter = streams.clone();
+
+    tokio::spawn(async move {
+        loop {
+            let Ok((mut socket, _)) = listener.accept().await else { break };
+            let counter = counter.clone();
+            tokio::spawn(async move {
+                let mut command = [0u8; 10];
+                socket.read_exact(&mut command).await.unwrap();
+                assert_eq!(&command, b"zINSTREAM\0");
+
+                let mut content = Vec::new();
+                loop {
+                    let mut length = [0u8; 4];
+                    socket.read_exact(&mut length).await.unwrap();
+                    let length = u32::from_be_bytes(length) as usize;
+                    if length == 0 {
+                        break;
+                    }
+                    assert!(length <= crate::scanner::INSTREAM_CHUNK_SIZE);
+                    let mut chunk = vec![0u8; length];
+                    socket.read_exact(&mut chunk).await.unwrap();
+                    content.extend_from_slice(&chunk);
+                }
+                counter.fetch_add(1, Ordering::SeqCst);
+
+                let infected = content
+                    .windows(EICAR.len())
+                    .any(|window| window == EICAR);
+                let reply: &[u8] = if infected { b"stream: Eicar-Signature FOUND\0" } else { b"stream: OK\0" };
+                socket.write_all(reply).await.unwrap();
+            });
+        }
+    });
+
+    (address, streams)
+}
+
+/// A ClamAV scan service talking to `address`
+pub fn clamav_service(address: &str, max_size: u64) -> FileScanService {
+    FileScanService::new(
+        Box::new(ClamAvScanner::new(ClamdAddress::parse(address).unwrap(), std::time::Duration::from_secs(5))),
+        max_size,
+    )
+}
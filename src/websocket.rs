@@ -4,29 +4,117 @@ use crate::config::Config;
 use crate::error::AppError;
 use crate::models::collaboration::{
     CollaborationSession, SessionOperation, SessionMessage, SessionParticipant,
-    OperationType, MessageType, ParticipantRole,
+    OperationType, MessageType, ParticipantRole, SETTINGS_UPDATED,
 };
+use crate::models::audit::AuditEvent;
 use crate::models::auth::{AuthContext, JwtService};
+use crate::models::chat_attachment::{AttachmentView, ChatAttachment};
+use crate::models::compile_batch::CompileBatch;
+use crate::models::mention::MentionNotifier;
+use crate::models::notification::Notification;
+use crate::models::CompilationStatus;
+use crate::models::file::{EquationInfo, FigureInfo, SectionInfo, TableInfo};
+use crate::models::outline::{OutlineTracker, OutlineUpdate, OUTLINE_DEBOUNCE};
+use crate::models::project::{Project, ProjectActivity};
+use crate::models::project_freeze::ProjectFreeze;
+use crate::models::project_presence::ProjectPresence;
+use crate::models::session_access::{JoinCredentials, SessionAccess, SessionStatusChange, SESSION_STATUS_CHANNEL};
+use crate::models::session_activity::{ActivityChange, SessionActivity, ACTIVITY_FLUSH_INTERVAL, IDLE_CHECK_INTERVAL};
+use crate::models::session_anonymity::{SessionMask, ANONYMITY_DISABLED, ANONYMITY_ENABLED};
+use crate::models::conflict_event::{Conflict, ConflictKind, ConflictOutcome, ConflictRecorder};
+use crate::models::undo::{Change, Contention, UndoHistory};
+use crate::models::ws_connection_limit::WsConnectionLimiter;
+use crate::models::ws_error::{WsError, WsErrorCode};
+use crate::models::ws_rate_limit::{RateVerdict, WsBudget, WsRateLimiter, WsRateLimits};
 use chrono::Utc;
 use futures::{sink::SinkExt, stream::StreamExt};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
-use tokio::sync::{broadcast, RwLock};
-use tokio::time::{interval, Duration};
+use tokio::sync::{broadcast, Notify, RwLock};
+use tokio::time::{interval, Duration, Instant};
 use tokio_tungstenite::{
     connect_async, tungstenite::protocol::Message,
     WebSocketStream as WsStream,
 };
 use tokio_tungstenite::tungstenite::handshake::derive_accept_key;
-use tracing::{debug, error, info, warn};
+use tokio_tungstenite::tungstenite::protocol::{frame::coding::CloseCode, CloseFrame};
+use tracing::{debug, error, info, warn, Instrument};
 use uuid::Uuid;
 
+/// WebSocket protocol version spoken by this server.
+///
+/// Any change to the shape of `WsMessage` must bump this; the serialization
+/// snapshot in the tests below is keyed to it.
+pub const PROTOCOL_VERSION: ProtocolVersion = ProtocolVersion { major: 1, minor: 12 };
+
+/// Features advertised to clients in `ServerHello`
+pub const SERVER_CAPABILITIES: &[&str] = &["ot", "cursor", "chat", "presence", "focus", "notifications", "compile_progress", "undo", "activity", "outline", "mentions", "idle", "compile_batches"];
+
+/// Minimum time between persisted focus changes of one connection; broadcasts are not throttled
+pub const FOCUS_PERSIST_INTERVAL: Duration = Duration::from_secs(3);
+
+/// Postgres channel announcing new project activity rows by id
+pub const ACTIVITY_CHANNEL: &str = "project_activity";
+
+/// Close code sent when the client speaks an incompatible major version
+pub const CLOSE_PROTOCOL_MISMATCH: u16 = 4001;
+
+/// Close code sent when a client keeps sending past its rate limits
+pub const CLOSE_RATE_LIMITED: u16 = 4008;
+
+/// Close code sent to a user's idlest connection when they open one too many
+pub const CLOSE_CONNECTION_LIMIT: u16 = 4009;
+
+/// Protocol version as `major.minor`; only the major version has to match
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProtocolVersion {
+    pub major: u32,
+    pub minor: u32,
+}
+
+impl ProtocolVersion {
+    /// Parse a `major.minor` string; a bare major version means minor 0
+    pub fn parse(version: &str) -> Option<Self> {
+        let mut parts = version.trim().splitn(2, '.');
+        let major = parts.next()?.parse().ok()?;
+        let minor = match parts.next() {
+            Some(minor) => minor.parse().ok()?,
+            None => 0,
+        };
+
+        Some(Self { major, minor })
+    }
+
+    pub fn is_compatible_with(&self, other: &ProtocolVersion) -> bool {
+        self.major == other.major
+    }
+}
+
+impl std::fmt::Display for ProtocolVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{}", self.major, self.minor)
+    }
+}
+
+/// Client identification sent with `Hello`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClientInfo {
+    pub name: String,
+    pub version: Option<String>,
+    pub platform: Option<String>,
+}
+
 /// WebSocket message types
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "type")]
 pub enum WsMessage {
     /// Client messages
+    /// Protocol handshake, expected as the first frame
+    Hello {
+        protocol_version: String,
+        client_info: Option<ClientInfo>,
+    },
     /// Authenticate with JWT token
     Authenticate {
         token: String,
@@ -37,6 +125,7 @@ pub enum WsMessage {
         session_id: Uuid,
         role: ParticipantRole,
         password: Option<String>,
+        join_code: Option<String>,
     },
     /// Leave current session
     LeaveSession,
@@ -49,12 +138,25 @@ pub enum WsMessage {
         length: Option<i32>,
         file_id: Option<Uuid>,
     },
+    /// Undo the user's latest change in the session
+    Undo {
+        session_id: Uuid,
+    },
+    /// Reapply the user's latest undone change
+    Redo {
+        session_id: Uuid,
+    },
     /// Update cursor position
     Cursor {
         session_id: Uuid,
         position: i32,
         selection: Option<String>,
     },
+    /// Switch the file the participant has open
+    FocusFile {
+        session_id: Uuid,
+        file_id: Uuid,
+    },
     /// Send chat message
     ChatMessage {
         session_id: Uuid,
@@ -62,15 +164,39 @@ pub enum WsMessage {
         message_type: MessageType,
         reply_to: Option<Uuid>,
     },
+    /// Receive activity of a project
+    SubscribeProject {
+        project_id: Uuid,
+    },
+    /// Stop receiving activity of a project
+    UnsubscribeProject {
+        project_id: Uuid,
+    },
+    /// Receive the compile progress of every job of a batch
+    SubscribeCompileBatch {
+        batch_id: Uuid,
+    },
+    /// Stop receiving the compile progress of a batch
+    UnsubscribeCompileBatch {
+        batch_id: Uuid,
+    },
     /// Keep alive
     Ping,
 
     /// Server messages
+    /// Handshake reply advertising the server protocol and features
+    ServerHello {
+        protocol_version: String,
+        capabilities: Vec<String>,
+        heartbeat_interval: u64,
+    },
     /// Authentication success/failure
     AuthResult {
         success: bool,
         user: Option<AuthContext>,
         error: Option<String>,
+        /// `AUTH_EXPIRED` or `AUTH_INVALID` when authentication failed
+        error_code: Option<WsErrorCode>,
     },
     /// Session joined
     SessionJoined {
@@ -88,6 +214,12 @@ pub enum WsMessage {
         session_id: Uuid,
         user_id: Uuid,
     },
+    /// Participant switched files
+    ParticipantFocus {
+        session_id: Uuid,
+        user_id: Uuid,
+        file_id: Uuid,
+    },
     /// Operation from another user
     ServerOperation {
         session_id: Uuid,
@@ -98,6 +230,8 @@ pub enum WsMessage {
         length: Option<i32>,
         file_id: Option<Uuid>,
         timestamp: chrono::DateTime<Utc>,
+        /// Produced by an Undo or Redo
+        is_undo: bool,
     },
     /// Chat message from another user
     ServerChatMessage {
@@ -107,6 +241,10 @@ pub enum WsMessage {
         content: String,
         message_type: MessageType,
         reply_to: Option<Uuid>,
+        /// Attachment of `file` messages
+        attachment: Option<AttachmentView>,
+        /// Users mentioned as `@username`
+        mentions: Vec<Uuid>,
         timestamp: chrono::DateTime<Utc>,
     },
     /// Session status update
@@ -114,15 +252,168 @@ pub enum WsMessage {
         session_id: Uuid,
         status: String,
     },
+    /// In-app notification for the connected user
+    Notification {
+        notification: Notification,
+    },
+    /// New activity in a subscribed project
+    ActivityEvent {
+        activity: ProjectActivity,
+    },
+    /// Structure of a file after collaboration operations changed it
+    OutlineUpdated {
+        file_id: Uuid,
+        sections: Vec<SectionInfo>,
+        figures: Vec<FigureInfo>,
+        tables: Vec<TableInfo>,
+        equations: Vec<EquationInfo>,
+    },
+    /// Status change of a compilation job the connected user started, or of
+    /// a batch they subscribed to
+    CompileProgress {
+        job_id: Uuid,
+        project_id: Uuid,
+        /// Batch the job was queued by
+        batch_id: Option<Uuid>,
+        status: CompilationStatus,
+        message: Option<String>,
+    },
     /// Error message
     Error {
-        code: String,
+        code: WsErrorCode,
         message: String,
+        /// Whether sending the same message again can succeed
+        retryable: bool,
+        /// When a retry can succeed, if known; with `RATE_LIMITED`, when the
+        /// budget has room again
+        retry_after_ms: Option<u64>,
     },
     /// Keep alive response
     Pong,
 }
 
+impl WsMessage {
+    /// Session of a session broadcast
+    fn session_id(&self) -> Option<Uuid> {
+        match self {
+            WsMessage::SessionJoined { session_id, .. }
+            | WsMessage::ParticipantUpdate { session_id, .. }
+            | WsMessage::ParticipantLeft { session_id, .. }
+            | WsMessage::ParticipantFocus { session_id, .. }
+            | WsMessage::ServerOperation { session_id, .. }
+            | WsMessage::ServerChatMessage { session_id, .. }
+            | WsMessage::SessionStatus { session_id, .. } => Some(*session_id),
+            _ => None,
+        }
+    }
+
+    /// Users named in the message
+    fn user_ids(&self) -> Vec<Uuid> {
+        match self {
+            WsMessage::SessionJoined { participants, .. } => {
+                participants.iter().map(|participant| participant.user_id).collect()
+            }
+            WsMessage::ParticipantUpdate { participant, .. } => vec![participant.user_id],
+            WsMessage::ParticipantLeft { user_id, .. }
+            | WsMessage::ParticipantFocus { user_id, .. }
+            | WsMessage::ServerOperation { user_id, .. } => vec![*user_id],
+            WsMessage::ServerChatMessage { user_id, attachment, mentions, .. } => {
+                let mut user_ids = vec![*user_id];
+                user_ids.extend(attachment.as_ref().map(|view| view.attachment.uploaded_by));
+                user_ids.extend(mentions.iter().copied());
+                user_ids
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    /// The message with identities hidden behind `mask`
+    ///
+    /// Every variant is listed, so a new one has to decide what it reveals.
+    pub fn masked(mut self, mask: &SessionMask) -> Self {
+        match &mut self {
+            WsMessage::SessionJoined { participants, .. } => {
+                *participants = std::mem::take(participants)
+                    .into_iter()
+                    .map(|participant| mask.participant(participant))
+                    .collect();
+            }
+            WsMessage::ParticipantUpdate { participant, .. } => {
+                *participant = mask.participant(participant.clone());
+            }
+            WsMessage::ParticipantLeft { user_id, .. }
+            | WsMessage::ParticipantFocus { user_id, .. }
+            | WsMessage::ServerOperation { user_id, .. } => {
+                *user_id = mask.user_id(*user_id);
+            }
+            WsMessage::ServerChatMessage { user_id, attachment, mentions, .. } => {
+                *user_id = mask.user_id(*user_id);
+                *attachment = attachment.take().map(|view| mask.attachment(view));
+                for mentioned in mentions.iter_mut() {
+                    *mentioned = mask.user_id(*mentioned);
+                }
+            }
+            // Client messages, and messages naming nobody or only the receiver
+            WsMessage::Hello { .. }
+            | WsMessage::Authenticate { .. }
+            | WsMessage::JoinSession { .. }
+            | WsMessage::LeaveSession
+            | WsMessage::Operation { .. }
+            | WsMessage::Undo { .. }
+            | WsMessage::Redo { .. }
+            | WsMessage::Cursor { .. }
+            | WsMessage::FocusFile { .. }
+            | WsMessage::ChatMessage { .. }
+            | WsMessage::SubscribeProject { .. }
+            | WsMessage::UnsubscribeProject { .. }
+            | WsMessage::SubscribeCompileBatch { .. }
+            | WsMessage::UnsubscribeCompileBatch { .. }
+            | WsMessage::Ping
+            | WsMessage::ServerHello { .. }
+            | WsMessage::AuthResult { .. }
+            | WsMessage::SessionStatus { .. }
+            | WsMessage::Notification { .. }
+            | WsMessage::ActivityEvent { .. }
+            | WsMessage::OutlineUpdated { .. }
+            | WsMessage::CompileProgress { .. }
+            | WsMessage::Error { .. }
+            | WsMessage::Pong => {}
+        }
+
+        self
+    }
+}
+
+impl From<WsError> for WsMessage {
+    fn from(error: WsError) -> Self {
+        WsMessage::Error {
+            code: error.code,
+            message: error.message,
+            retryable: error.retryable,
+            retry_after_ms: error.retry_after_ms,
+        }
+    }
+}
+
+impl From<OutlineUpdate> for WsMessage {
+    fn from(update: OutlineUpdate) -> Self {
+        WsMessage::OutlineUpdated {
+            file_id: update.file_id,
+            sections: update.outline.sections,
+            figures: update.outline.figures,
+            tables: update.outline.tables,
+            equations: update.outline.equations,
+        }
+    }
+}
+
+/// Message types a client may send
+const CLIENT_MESSAGE_TYPES: &[&str] = &[
+    "Hello", "Authenticate", "JoinSession", "LeaveSession", "Operation", "Undo", "Redo", "Cursor", "FocusFile",
+    "ChatMessage", "SubscribeProject", "UnsubscribeProject", "SubscribeCompileBatch", "UnsubscribeCompileBatch",
+    "Ping",
+];
+
 /// WebSocket connection state
 #[derive(Debug, Clone)]
 pub struct ConnectionState {
@@ -131,6 +422,14 @@ pub struct ConnectionState {
     pub participant_id: Option<Uuid>,
     pub last_heartbeat: chrono::DateTime<Utc>,
     pub authenticated: bool,
+    pub protocol_version: Option<ProtocolVersion>,
+    pub focus: FocusState,
+    /// Projects whose activity the connection receives
+    pub project_subscriptions: HashSet<Uuid>,
+    /// Compile batches whose progress the connection's user receives
+    pub batch_subscriptions: HashSet<Uuid>,
+    /// Tells the connection's task to close it with `CLOSE_CONNECTION_LIMIT`
+    pub evicted: Arc<Notify>,
 }
 
 impl Default for ConnectionState {
@@ -141,10 +440,139 @@ impl Default for ConnectionState {
             participant_id: None,
             last_heartbeat: Utc::now(),
             authenticated: false,
+            protocol_version: None,
+            focus: FocusState::default(),
+            project_subscriptions: HashSet::new(),
+            batch_subscriptions: HashSet::new(),
+            evicted: Arc::new(Notify::new()),
+        }
+    }
+}
+
+/// File focus of a connection and what of it has been persisted
+#[derive(Debug, Clone, Default)]
+pub struct FocusState {
+    pub file_id: Option<Uuid>,
+    persisted_file_id: Option<Uuid>,
+    persisted_at: Option<Instant>,
+    /// Files already checked against the session's project
+    checked_files: HashSet<Uuid>,
+}
+
+impl FocusState {
+    pub fn is_checked(&self, file_id: Uuid) -> bool {
+        self.checked_files.contains(&file_id)
+    }
+
+    /// Move focus to a checked file; returns it if it should be persisted now
+    pub fn focus(&mut self, file_id: Uuid, now: Instant) -> Option<Uuid> {
+        self.checked_files.insert(file_id);
+        self.file_id = Some(file_id);
+        self.take_due(now)
+    }
+
+    /// The focused file if it has not been persisted and the persist interval has passed
+    pub fn take_due(&mut self, now: Instant) -> Option<Uuid> {
+        let file_id = self.file_id?;
+        if self.persisted_file_id == Some(file_id) {
+            return None;
+        }
+        if let Some(persisted_at) = self.persisted_at {
+            if now.duration_since(persisted_at) < FOCUS_PERSIST_INTERVAL {
+                return None;
+            }
+        }
+
+        self.persisted_file_id = Some(file_id);
+        self.persisted_at = Some(now);
+        Some(file_id)
+    }
+}
+
+/// Per-user broadcast channels for messages that follow a user across sessions.
+///
+/// Shared with the HTTP server so request handlers and background jobs can
+/// reach users that have a live connection.
+#[derive(Debug, Default)]
+pub struct UserChannels {
+    channels: std::sync::RwLock<HashMap<Uuid, broadcast::Sender<WsMessage>>>,
+    /// Users subscribed to the progress of compile batches, with how many of
+    /// their connections subscribed
+    batch_watchers: std::sync::Mutex<HashMap<Uuid, HashMap<Uuid, usize>>>,
+}
+
+impl UserChannels {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Receive messages sent to a user
+    pub fn subscribe(&self, user_id: Uuid) -> broadcast::Receiver<WsMessage> {
+        let mut channels = self.channels.write().unwrap_or_else(|e| e.into_inner());
+        channels
+            .entry(user_id)
+            .or_insert_with(|| broadcast::channel(100).0)
+            .subscribe()
+    }
+
+    /// Send to every live connection of a user; returns false when there is none
+    pub fn send(&self, user_id: Uuid, message: WsMessage) -> bool {
+        let mut channels = self.channels.write().unwrap_or_else(|e| e.into_inner());
+        let delivered = match channels.get(&user_id) {
+            Some(sender) => sender.send(message).is_ok(),
+            None => false,
+        };
+
+        // Channels without receivers belong to users who went offline
+        if !delivered {
+            channels.remove(&user_id);
+        }
+        delivered
+    }
+
+    /// Send the progress of a batch's jobs to a connection of `user_id` too
+    pub fn watch_batch(&self, batch_id: Uuid, user_id: Uuid) {
+        let mut watchers = self.batch_watchers.lock().unwrap_or_else(|e| e.into_inner());
+        *watchers.entry(batch_id).or_default().entry(user_id).or_default() += 1;
+    }
+
+    /// Undo one `watch_batch`
+    pub fn unwatch_batch(&self, batch_id: Uuid, user_id: Uuid) {
+        let mut watchers = self.batch_watchers.lock().unwrap_or_else(|e| e.into_inner());
+        let Some(users) = watchers.get_mut(&batch_id) else { return };
+        if let Some(count) = users.get_mut(&user_id) {
+            *count -= 1;
+            if *count == 0 {
+                users.remove(&user_id);
+            }
+        }
+        if users.is_empty() {
+            watchers.remove(&batch_id);
+        }
+    }
+
+    /// Send a message about a batch's job to its creator and every user
+    /// watching the batch, once each
+    pub fn send_to_batch(&self, batch_id: Uuid, creator_id: Uuid, message: WsMessage) {
+        let watchers: Vec<Uuid> = {
+            let watchers = self.batch_watchers.lock().unwrap_or_else(|e| e.into_inner());
+            watchers.get(&batch_id).map(|users| users.keys().copied().collect()).unwrap_or_default()
+        };
+        for user_id in watchers.into_iter().filter(|user_id| *user_id != creator_id) {
+            self.send(user_id, message.clone());
         }
+        self.send(creator_id, message);
     }
 }
 
+/// Identities a connection may see in its session's broadcasts
+#[derive(Debug)]
+pub struct BroadcastMask {
+    session_id: Uuid,
+    /// `None` while the session is not anonymous to the connection
+    mask: Option<SessionMask>,
+}
+
 /// WebSocket server state
 #[derive(Debug)]
 pub struct WsServerState {
@@ -152,15 +580,54 @@ pub struct WsServerState {
     pub db_pool: Arc<sqlx::PgPool>,
     pub connections: Arc<RwLock<HashMap<String, Arc<RwLock<ConnectionState>>>>>,
     pub session_broadcasts: Arc<RwLock<HashMap<Uuid, broadcast::Sender<WsMessage>>>>,
+    pub user_channels: Arc<UserChannels>,
+    /// Undo and redo stacks of session participants
+    pub undo_history: Arc<UndoHistory>,
+    /// New project activity, filtered per connection by its subscriptions
+    pub activity_feed: broadcast::Sender<ProjectActivity>,
+    /// Message budgets per connection and user
+    pub rate_limiter: Arc<WsRateLimiter>,
+    /// Authenticated connections per user, shared with the HTTP server
+    pub connection_limiter: Arc<WsConnectionLimiter>,
+    /// Outlines of files changed by operations, sent to their sessions and project subscribers
+    pub outline: Arc<OutlineTracker>,
+    /// Notifies users mentioned in chat messages
+    pub mention_notifier: Arc<MentionNotifier>,
+    /// Activity of session participants, for their sessions' idle policies
+    pub session_activity: Arc<SessionActivity>,
+    /// Users in each project's sessions, shared with the HTTP server
+    pub presence: Arc<ProjectPresence>,
+    /// Records undo entries transformed by edits of others, shared with the HTTP server
+    pub conflicts: Arc<ConflictRecorder>,
 }
 
 impl WsServerState {
-    pub fn new(config: Config, db_pool: sqlx::PgPool) -> Self {
+    pub fn new(
+        config: Config,
+        db_pool: sqlx::PgPool,
+        user_channels: Arc<UserChannels>,
+        mention_notifier: Arc<MentionNotifier>,
+        connection_limiter: Arc<WsConnectionLimiter>,
+        presence: Arc<ProjectPresence>,
+        conflicts: Arc<ConflictRecorder>,
+    ) -> Self {
+        let rate_limiter = Arc::new(WsRateLimiter::new(WsRateLimits::from_config(&config.websocket)));
+        let db_pool = Arc::new(db_pool);
         Self {
             config: Arc::new(config),
-            db_pool: Arc::new(db_pool),
+            outline: Arc::new(OutlineTracker::new(db_pool.clone(), OUTLINE_DEBOUNCE)),
+            db_pool,
             connections: Arc::new(RwLock::new(HashMap::new())),
             session_broadcasts: Arc::new(RwLock::new(HashMap::new())),
+            user_channels,
+            undo_history: Arc::new(UndoHistory::new()),
+            activity_feed: broadcast::channel(1000).0,
+            rate_limiter,
+            connection_limiter,
+            mention_notifier,
+            session_activity: Arc::new(SessionActivity::new()),
+            presence,
+            conflicts,
         }
     }
 
@@ -169,22 +636,53 @@ impl WsServerState {
         Uuid::new_v4().to_string()
     }
 
-    /// Register new connection
-    pub async fn register_connection(&self, connection_id: String) {
+    /// Register new connection; returns what notifies it of its eviction
+    pub async fn register_connection(&self, connection_id: String) -> Arc<Notify> {
+        let state = ConnectionState::default();
+        let evicted = state.evicted.clone();
         let mut connections = self.connections.write().await;
-        connections.insert(
-            connection_id.clone(),
-            Arc::new(RwLock::new(ConnectionState::default())),
-        );
+        connections.insert(connection_id.clone(), Arc::new(RwLock::new(state)));
         debug!("Registered WebSocket connection: {}", connection_id);
+        evicted
+    }
+
+    /// Count an authenticated connection against its user's limit
+    ///
+    /// The user's idlest connections beyond the limit are unregistered right
+    /// away, leaving their sessions, and their tasks told to close them.
+    pub async fn admit_connection(&self, connection_id: &str, user_id: Uuid) {
+        for evicted_id in self.connection_limiter.admit(user_id, connection_id) {
+            let connection = self.connections.read().await.get(&evicted_id).cloned();
+            if let Some(connection) = connection {
+                connection.read().await.evicted.notify_one();
+            }
+            self.unregister_connection(&evicted_id).await;
+            info!("Closed WebSocket connection {} of user {} for a newer one", evicted_id, user_id);
+        }
+    }
+
+    /// User a connection authenticated as, if it did
+    pub async fn connection_user(&self, connection_id: &str) -> Option<Uuid> {
+        let connection = self.connections.read().await.get(connection_id).cloned()?;
+        let user_id = connection.read().await.user.as_ref().map(|user| user.user_id);
+        user_id
     }
 
     /// Unregister connection
     pub async fn unregister_connection(&self, connection_id: &str) {
+        self.rate_limiter.remove_connection(connection_id);
+        self.connection_limiter.remove(connection_id);
+        self.presence.leave(connection_id);
+
         // Remove from connections
         let mut connections = self.connections.write().await;
         if let Some(state) = connections.remove(connection_id) {
             let state_read = state.read().await;
+            if let Some(user) = &state_read.user {
+                for batch_id in &state_read.batch_subscriptions {
+                    self.user_channels.unwatch_batch(*batch_id, user.user_id);
+                }
+            }
 
             // Leave session if in one
             if let (Some(session_id), Some(participant_id)) = (state_read.session_id, state_read.participant_id) {
@@ -231,6 +729,68 @@ impl WsServerState {
         Ok(())
     }
 
+    /// The mask of the connection's user in an anonymous session
+    async fn load_mask(&self, connection_id: &str, session_id: Uuid) -> Result<Option<SessionMask>, AppError> {
+        let viewer = {
+            let connections = self.connections.read().await;
+            let connection = connections.get(connection_id)
+                .ok_or_else(|| AppError::Authentication("Connection not found".to_string()))?;
+            let conn = connection.read().await;
+            conn.user.as_ref().map(|user| user.user_id)
+                .ok_or_else(|| AppError::Authentication("Not authenticated".to_string()))?
+        };
+
+        let session = CollaborationSession::find_by_id(&*self.db_pool, session_id)
+            .await?
+            .ok_or_else(|| AppError::NotFound {
+                entity: "CollaborationSession".to_string(),
+                id: session_id.to_string(),
+            })?;
+        SessionMask::load(&self.db_pool, &session, viewer, Utc::now()).await
+    }
+
+    /// Hide identities in a session broadcast from the connection receiving it
+    ///
+    /// The mask is kept in `cache` and reloaded when the session's anonymity
+    /// changes. A message that cannot be masked is dropped, never sent with
+    /// real identities.
+    pub async fn mask_broadcast(
+        &self,
+        connection_id: &str,
+        cache: &mut Option<BroadcastMask>,
+        message: WsMessage,
+    ) -> Option<WsMessage> {
+        let Some(session_id) = message.session_id() else {
+            return Some(message);
+        };
+
+        let anonymity_changed = matches!(
+            &message,
+            WsMessage::SessionStatus { status, .. } if status == ANONYMITY_ENABLED || status == ANONYMITY_DISABLED
+        );
+        if anonymity_changed || cache.as_ref().map_or(true, |cached| cached.session_id != session_id) {
+            match self.load_mask(connection_id, session_id).await {
+                Ok(mask) => *cache = Some(BroadcastMask { session_id, mask }),
+                Err(e) => {
+                    warn!("Dropped broadcast to {}, identities could not be masked: {}", connection_id, e);
+                    *cache = None;
+                    return None;
+                }
+            }
+        }
+
+        let mask = match cache.as_mut().and_then(|cached| cached.mask.as_mut()) {
+            Some(mask) if mask.is_active(Utc::now()) => mask,
+            _ => return Some(message),
+        };
+        if let Err(e) = mask.cover(&self.db_pool, message.user_ids()).await {
+            warn!("Dropped broadcast to {}, identities could not be masked: {}", connection_id, e);
+            return None;
+        }
+
+        Some(message.masked(mask))
+    }
+
     /// Handle session join
     pub async fn handle_session_join(
         &self,
@@ -238,29 +798,41 @@ impl WsServerState {
         session_id: Uuid,
         user_id: Uuid,
         role: ParticipantRole,
-        password: Option<String>,
+        credentials: JoinCredentials<'_>,
     ) -> Result<SessionParticipant, AppError> {
         // Validate session access
-        let session = CollaborationSession::find_with_access(
-            &*self.db_pool,
-            session_id,
-            user_id,
-            password.as_deref(),
-        )
-        .await?
-        .ok_or_else(|| AppError::NotFound {
-            entity: "CollaborationSession".to_string(),
-            id: session_id.to_string(),
-        })?;
+        let session = CollaborationSession::find_by_id(&*self.db_pool, session_id)
+            .await?
+            .ok_or_else(|| AppError::NotFound {
+                entity: "CollaborationSession".to_string(),
+                id: session_id.to_string(),
+            })?;
+        if !session.is_active {
+            return Err(AppError::SessionEnded);
+        }
+        SessionAccess::authorize_join(&self.db_pool, &session, user_id, credentials, &self.config.websocket).await?;
 
         // Add participant to session
-        let participant = SessionParticipant::join(
+        let mut participant = SessionParticipant::join(
             &*self.db_pool,
             session_id,
             user_id,
             role,
         )
         .await?;
+        if session.started_at.is_none() {
+            session.start(&*self.db_pool).await?;
+        }
+
+        // Editors finding every edit slot taken wait for one as viewers
+        if let Some(change) = self.session_activity.track(session.session_settings(), &participant, Utc::now()) {
+            if let Some(queued) = SessionParticipant::record_activity(&self.db_pool, &change).await? {
+                participant = queued;
+            }
+        }
+
+        // Changes made before joining cannot be undone
+        self.undo_history.join(session_id, user_id);
 
         // Update connection state
         {
@@ -270,9 +842,19 @@ impl WsServerState {
                 state_write.session_id = Some(session_id);
                 state_write.participant_id = Some(participant.id);
                 state_write.last_heartbeat = Utc::now();
+                state_write.focus = FocusState::default();
             }
         }
 
+        // Guests count as active in the project only if configured to
+        if self.config.websocket.presence_count_guests
+            || Project::has_access(&self.db_pool, session.project_id, user_id).await?
+        {
+            self.presence.enter(connection_id, session.project_id, user_id);
+        } else {
+            self.presence.leave(connection_id);
+        }
+
         // Get current participants
         let current_participants = SessionParticipant::get_active_participants(&*self.db_pool, session_id).await?;
 
@@ -302,8 +884,13 @@ impl WsServerState {
         .await
         .map_err(AppError::Database)?;
 
+        let (unflushed, opened) = self.session_activity.forget(session_id, participant_id);
         if let Some(participant) = participant {
+            if let Some(activity) = unflushed {
+                SessionParticipant::flush_activity(&self.db_pool, &[activity]).await?;
+            }
             participant.leave(&*self.db_pool).await?;
+            self.undo_history.leave(session_id, participant.user_id);
 
             // Broadcast participant leave
             let broadcast_msg = WsMessage::ParticipantLeft {
@@ -311,6 +898,7 @@ impl WsServerState {
                 user_id: participant.user_id,
             };
             self.broadcast_to_session(session_id, broadcast_msg).await?;
+            self.apply_activity(session_id, opened).await?;
 
             info!("User {} left session {}", participant.user_id, session_id);
         }
@@ -318,6 +906,137 @@ impl WsServerState {
         Ok(())
     }
 
+    /// Persist and announce changes of the idle policy
+    ///
+    /// Users who got their role back after waiting for a slot are also
+    /// notified, once for all their tabs.
+    pub async fn apply_activity(&self, session_id: Uuid, changes: Vec<ActivityChange>) -> Result<(), AppError> {
+        let mut notified = HashSet::new();
+        for change in changes {
+            let Some(participant) = SessionParticipant::record_activity(&self.db_pool, &change).await? else {
+                continue;
+            };
+            let broadcast_msg = WsMessage::ParticipantUpdate {
+                session_id,
+                participant,
+            };
+            self.broadcast_to_session(session_id, broadcast_msg).await?;
+
+            if change.slot_opened && notified.insert(change.user_id) {
+                let notification = Notification::create(
+                    &self.db_pool,
+                    change.user_id,
+                    "edit_slot_available",
+                    "You can edit again",
+                    "An edit slot opened up in the session and your editor role is back.",
+                    Some(serde_json::json!({ "session_id": session_id })),
+                )
+                .await?;
+                self.user_channels.send(change.user_id, WsMessage::Notification { notification });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Record an operation, cursor move or chat message of a user
+    pub async fn note_activity(&self, session_id: Uuid, user_id: Uuid, now: chrono::DateTime<Utc>) -> Result<(), AppError> {
+        let changes = self.session_activity.touch(session_id, user_id, now);
+        self.apply_activity(session_id, changes).await
+    }
+
+    /// Refuse edits of users whom the idle policy left viewers only
+    fn ensure_may_edit(&self, session_id: Uuid, user_id: Uuid) -> Result<(), AppError> {
+        if self.session_activity.may_edit(session_id, user_id) == Some(false) {
+            return Err(AppError::Authorization(
+                "Viewers cannot edit; wait for an edit slot to open".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Downgrade and mark away idle participants of every session
+    pub async fn enforce_idle_policy(&self, now: chrono::DateTime<Utc>) {
+        for (session_id, change) in self.session_activity.sweep(now) {
+            if let Err(e) = self.apply_activity(session_id, vec![change]).await {
+                warn!("Failed to apply the idle policy of session {}: {}", session_id, e);
+            }
+        }
+    }
+
+    /// Write out activity timestamps kept in memory
+    pub async fn flush_activity(&self) {
+        let activity = self.session_activity.take_unflushed();
+        if let Err(e) = SessionParticipant::flush_activity(&self.db_pool, &activity).await {
+            warn!("Failed to persist the activity of {} participants: {}", activity.len(), e);
+        }
+    }
+
+    /// Handle a participant switching files
+    pub async fn handle_focus(
+        &self,
+        connection_id: &str,
+        session_id: Uuid,
+        user_id: Uuid,
+        file_id: Uuid,
+    ) -> Result<(), AppError> {
+        let connection = self.connections.read().await.get(connection_id).cloned()
+            .ok_or_else(|| AppError::Authentication("Connection not found".to_string()))?;
+
+        let checked = {
+            let conn = connection.read().await;
+            if conn.session_id != Some(session_id) {
+                return Err(AppError::Authorization("Not a participant of this session".to_string()));
+            }
+            conn.focus.is_checked(file_id)
+        };
+
+        if !checked && !CollaborationSession::contains_file(&*self.db_pool, session_id, file_id).await? {
+            return Err(AppError::NotFound {
+                entity: "File".to_string(),
+                id: file_id.to_string(),
+            });
+        }
+
+        let (participant_id, due) = {
+            let mut conn = connection.write().await;
+            (conn.participant_id, conn.focus.focus(file_id, Instant::now()))
+        };
+
+        // Everyone sees every switch; only the database write is throttled
+        let broadcast_msg = WsMessage::ParticipantFocus {
+            session_id,
+            user_id,
+            file_id,
+        };
+        self.broadcast_to_session(session_id, broadcast_msg).await?;
+
+        if let (Some(participant_id), Some(file_id)) = (participant_id, due) {
+            SessionParticipant::update_focus(&*self.db_pool, participant_id, file_id).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Persist a focus change held back by `FOCUS_PERSIST_INTERVAL`
+    pub async fn flush_focus(&self, connection_id: &str) {
+        let connection = match self.connections.read().await.get(connection_id).cloned() {
+            Some(connection) => connection,
+            None => return,
+        };
+
+        let (participant_id, due) = {
+            let mut conn = connection.write().await;
+            (conn.participant_id, conn.focus.take_due(Instant::now()))
+        };
+
+        if let (Some(participant_id), Some(file_id)) = (participant_id, due) {
+            if let Err(e) = SessionParticipant::update_focus(&*self.db_pool, participant_id, file_id).await {
+                warn!("Failed to persist focus for {}: {}", connection_id, e);
+            }
+        }
+    }
+
     /// Handle operation
     pub async fn handle_operation(
         &self,
@@ -329,6 +1048,12 @@ impl WsServerState {
         length: Option<i32>,
         file_id: Option<Uuid>,
     ) -> Result<(), AppError> {
+        // Activity may give an idle editor their role back before the check
+        self.note_activity(session_id, user_id, Utc::now()).await?;
+        if operation_type.is_edit() {
+            self.ensure_may_edit(session_id, user_id)?;
+        }
+
         // Create operation record
         let operation_data = serde_json::json!({
             "position": position,
@@ -351,6 +1076,12 @@ impl WsServerState {
         // Apply operation (simplified - real implementation would need conflict resolution)
         operation.apply(&*self.db_pool).await?;
 
+        if let Some(change) = Change::from_operation(operation_type, position, content.as_deref(), length, file_id) {
+            let discarded = self.undo_history.record(session_id, user_id, &change);
+            self.record_contention(session_id, "Operation", discarded);
+            self.outline.record(session_id, change);
+        }
+
         // Broadcast to session
         let broadcast_msg = WsMessage::ServerOperation {
             session_id,
@@ -361,12 +1092,96 @@ impl WsServerState {
             length,
             file_id,
             timestamp: operation.timestamp,
+            is_undo: false,
         };
         self.broadcast_to_session(session_id, broadcast_msg).await?;
 
         Ok(())
     }
 
+    /// Record undo entries others' edits transformed as conflicts of `message`
+    fn record_contention(&self, session_id: Uuid, message: &str, contention: Vec<Contention>) {
+        for contention in contention {
+            self.conflicts.record(Conflict {
+                kind: ConflictKind::Transform,
+                outcome: if contention.discarded { ConflictOutcome::Discarded } else { ConflictOutcome::AutoMerged },
+                endpoint: format!("WS {}", message),
+                project_id: None,
+                file_id: contention.file_id,
+                session_id: Some(session_id),
+                users: contention.users,
+                occurred_at: Utc::now(),
+            });
+        }
+    }
+
+    /// Handle undo or redo, applying and broadcasting the edits it takes
+    pub async fn handle_undo(
+        &self,
+        session_id: Uuid,
+        user_id: Uuid,
+        redo: bool,
+    ) -> Result<(), AppError> {
+        self.note_activity(session_id, user_id, Utc::now()).await?;
+        self.ensure_may_edit(session_id, user_id)?;
+
+        // Check before taking the change off the history, so it is not lost
+        ProjectFreeze::ensure_session_writable(&*self.db_pool, session_id).await?;
+
+        let reverted = if redo {
+            self.undo_history.redo(session_id, user_id)
+        } else {
+            self.undo_history.undo(session_id, user_id)
+        };
+        let reverted = reverted.ok_or_else(|| {
+            AppError::Validation(format!("Nothing to {}", if redo { "redo" } else { "undo" }))
+        })?;
+        self.record_contention(session_id, if redo { "Redo" } else { "Undo" }, reverted.contention);
+
+        for edit in reverted.edits {
+            let position = Some(edit.position as i32);
+            let length = Some(edit.len() as i32);
+            let operation_data = serde_json::json!({
+                "position": position,
+                "content": edit.text,
+                "length": length,
+                "is_undo": true,
+            });
+
+            let operation = SessionOperation::create(
+                &*self.db_pool,
+                session_id,
+                user_id,
+                edit.operation_type(),
+                operation_data.to_string(),
+                reverted.file_id,
+                position,
+                Some(edit.text.clone()),
+            )
+            .await?;
+            operation.apply(&*self.db_pool).await?;
+            let change = Change::from_operation(edit.operation_type(), position, Some(&edit.text), length, reverted.file_id);
+            if let Some(change) = change {
+                self.outline.record(session_id, change);
+            }
+
+            let broadcast_msg = WsMessage::ServerOperation {
+                session_id,
+                user_id,
+                operation_type: edit.operation_type(),
+                position,
+                content: Some(edit.text),
+                length,
+                file_id: reverted.file_id,
+                timestamp: operation.timestamp,
+                is_undo: true,
+            };
+            self.broadcast_to_session(session_id, broadcast_msg).await?;
+        }
+
+        Ok(())
+    }
+
     /// Handle chat message
     pub async fn handle_chat_message(
         &self,
@@ -376,23 +1191,25 @@ impl WsServerState {
         message_type: MessageType,
         reply_to: Option<Uuid>,
     ) -> Result<(), AppError> {
+        self.note_activity(session_id, user_id, Utc::now()).await?;
+
+        // File messages carry the id of an attachment the sender uploaded
+        let attachment = if message_type == MessageType::File {
+            Some(ChatAttachment::resolve_message(&self.db_pool, session_id, user_id, &content).await?)
+        } else {
+            None
+        };
+
+        let session = CollaborationSession::find_by_id(&self.db_pool, session_id)
+            .await?
+            .ok_or_else(|| AppError::NotFound {
+                entity: "CollaborationSession".to_string(),
+                id: session_id.to_string(),
+            })?;
+
         // Create message record
-        let message = sqlx::query_as::<_, SessionMessage>(
-            r#"
-            INSERT INTO session_messages (session_id, user_id, message_type, content, reply_to, created_at)
-            VALUES ($1, $2, $3, $4, $5, $6)
-            RETURNING *
-            "#
-        )
-        .bind(session_id)
-        .bind(user_id)
-        .bind(message_type as MessageType)
-        .bind(content)
-        .bind(reply_to)
-        .bind(Utc::now())
-        .fetch_one(&*self.db_pool)
-        .await
-        .map_err(AppError::Database)?;
+        let message = SessionMessage::create(&self.db_pool, &session, user_id, message_type, content, reply_to).await?;
+        self.mention_notifier.notify_or_warn(&self.db_pool, &session, &message, &[]).await;
 
         // Broadcast to session
         let broadcast_msg = WsMessage::ServerChatMessage {
@@ -402,6 +1219,8 @@ impl WsServerState {
             content: message.content,
             message_type,
             reply_to: message.reply_to,
+            attachment: attachment.map(ChatAttachment::view),
+            mentions: message.mentions,
             timestamp: message.created_at,
         };
         self.broadcast_to_session(session_id, broadcast_msg).await?;
@@ -410,16 +1229,82 @@ impl WsServerState {
     }
 }
 
-/// WebSocket handler for a single connection
-pub async fn handle_websocket_connection(
-    stream: WsStream<tokio::net::TcpStream>,
-    connection_id: String,
-    state: Arc<WsServerState>,
-) {
+/// Forward project activity announced by Postgres to `activity_feed`
+///
+/// Rows are announced by a trigger on `project_activity`, so activity logged
+/// by any server instance reaches the subscribers connected to this one.
+/// Session status changes made over REST, such as a new session password,
+/// arrive the same way and go to the participants of that session.
+pub async fn forward_project_activity(state: Arc<WsServerState>) {
+    loop {
+        if let Err(e) = listen_for_activity(&state).await {
+            warn!("Project activity listener failed: {}", e);
+        }
+        tokio::time::sleep(Duration::from_secs(5)).await;
+    }
+}
+
+async fn listen_for_activity(state: &WsServerState) -> Result<(), AppError> {
+    let mut listener = sqlx::postgres::PgListener::connect_with(&*state.db_pool)
+        .await
+        .map_err(AppError::Database)?;
+    listener
+        .listen_all([ACTIVITY_CHANNEL, SESSION_STATUS_CHANNEL])
+        .await
+        .map_err(AppError::Database)?;
+
+    loop {
+        let notification = listener.recv().await.map_err(AppError::Database)?;
+        if notification.channel() == SESSION_STATUS_CHANNEL {
+            if let Ok(change) = serde_json::from_str::<SessionStatusChange>(notification.payload()) {
+                if change.status == SETTINGS_UPDATED {
+                    if let Some(session) = CollaborationSession::find_by_id(&state.db_pool, change.session_id).await? {
+                        let opened = state.session_activity.update_settings(session.id, session.session_settings());
+                        state.apply_activity(session.id, opened).await?;
+                    }
+                }
+                let message = WsMessage::SessionStatus { session_id: change.session_id, status: change.status };
+                state.broadcast_to_session(change.session_id, message).await?;
+            }
+            continue;
+        }
+
+        let Ok(activity_id) = notification.payload().parse::<Uuid>() else {
+            continue;
+        };
+
+        // Only read the row when a connection subscribed to some project
+        if state.activity_feed.receiver_count() == 0 {
+            continue;
+        }
+        if let Some(activity) = ProjectActivity::find_by_id(&*state.db_pool, activity_id).await? {
+            let _ = state.activity_feed.send(activity);
+        }
+    }
+}
+
+/// Apply the idle policies of sessions and write out participant activity
+pub async fn enforce_idle_policies(state: Arc<WsServerState>) {
+    let mut idle_check = interval(IDLE_CHECK_INTERVAL);
+    let mut activity_flush = interval(ACTIVITY_FLUSH_INTERVAL);
+    loop {
+        tokio::select! {
+            _ = idle_check.tick() => state.enforce_idle_policy(Utc::now()).await,
+            _ = activity_flush.tick() => state.flush_activity().await,
+        }
+    }
+}
+
+/// WebSocket handler for a single connection
+pub async fn handle_websocket_connection(
+    stream: WsStream<tokio::net::TcpStream>,
+    connection_id: String,
+    state: Arc<WsServerState>,
+) {
     info!("New WebSocket connection: {}", connection_id);
 
     // Register connection
-    state.register_connection(connection_id.clone()).await;
+    let evicted = state.register_connection(connection_id.clone()).await;
 
     let (mut sender, mut receiver) = stream.split();
 
@@ -440,8 +1325,23 @@ pub async fn handle_websocket_connection(
         None
     };
 
+    // Identities hidden from this connection in session broadcasts
+    let mut broadcast_mask: Option<BroadcastMask> = None;
+
+    // Messages for the authenticated user, e.g. notifications
+    let mut user_receiver: Option<broadcast::Receiver<WsMessage>> = None;
+
+    // Project activity, once the connection subscribed to a project
+    let mut activity_receiver: Option<broadcast::Receiver<ProjectActivity>> = None;
+
+    // Outlines changed in the connection's session or subscribed projects
+    let mut outline_receiver = state.outline.subscribe();
+
     // Heartbeat interval
-    let mut heartbeat_interval = interval(Duration::from_secs(30));
+    let mut heartbeat_interval = interval(Duration::from_secs(state.config.websocket.heartbeat_interval));
+
+    // Writes out focus changes that arrived inside the persist interval
+    let mut focus_flush_interval = interval(FOCUS_PERSIST_INTERVAL);
 
     loop {
         tokio::select! {
@@ -449,7 +1349,7 @@ pub async fn handle_websocket_connection(
             Some(msg_result) = receiver.next() => {
                 match msg_result {
                     Ok(msg) => {
-                        if let Err(e) = handle_message(&connection_id, msg, &state, &mut sender, &mut broadcast_receiver).await {
+                        if let Err(e) = handle_message(&connection_id, msg, &state, &mut sender, &mut broadcast_receiver, &mut user_receiver, &mut activity_receiver).await {
                             error!("Error handling message for {}: {}", connection_id, e);
                             break;
                         }
@@ -469,6 +1369,10 @@ pub async fn handle_websocket_connection(
                     std::future::pending().await
                 }
             } => {
+                let message = match message {
+                    Some(message) => state.mask_broadcast(&connection_id, &mut broadcast_mask, message).await,
+                    None => None,
+                };
                 if let Some(message) = message {
                     if let Ok(text) = serde_json::to_string(&message) {
                         if let Err(e) = sender.send(Message::Text(text)).await {
@@ -479,6 +1383,77 @@ pub async fn handle_websocket_connection(
                 }
             }
 
+            // Handle messages addressed to the user
+            message = async {
+                if let Some(ref mut receiver) = user_receiver {
+                    receiver.recv().await.ok()
+                } else {
+                    std::future::pending().await
+                }
+            } => {
+                if let Some(message) = message {
+                    if let Ok(text) = serde_json::to_string(&message) {
+                        if let Err(e) = sender.send(Message::Text(text)).await {
+                            error!("Failed to send user message to {}: {}", connection_id, e);
+                            break;
+                        }
+                    }
+                }
+            }
+
+            // Handle activity of subscribed projects
+            activity = async {
+                if let Some(ref mut receiver) = activity_receiver {
+                    receiver.recv().await.ok()
+                } else {
+                    std::future::pending().await
+                }
+            } => {
+                let Some(activity) = activity else { continue };
+                let subscribed = {
+                    let connections = state.connections.read().await;
+                    match connections.get(&connection_id) {
+                        Some(connection) => connection.read().await.project_subscriptions.contains(&activity.project_id),
+                        None => false,
+                    }
+                };
+                if !subscribed {
+                    continue;
+                }
+
+                if let Ok(text) = serde_json::to_string(&WsMessage::ActivityEvent { activity }) {
+                    if let Err(e) = sender.send(Message::Text(text)).await {
+                        error!("Failed to send activity to {}: {}", connection_id, e);
+                        break;
+                    }
+                }
+            }
+
+            // Handle outlines changed by collaboration operations
+            Ok(update) = outline_receiver.recv() => {
+                let receives = {
+                    let connections = state.connections.read().await;
+                    match connections.get(&connection_id) {
+                        Some(connection) => {
+                            let conn = connection.read().await;
+                            conn.session_id == Some(update.session_id)
+                                || conn.project_subscriptions.contains(&update.project_id)
+                        }
+                        None => false,
+                    }
+                };
+                if !receives {
+                    continue;
+                }
+
+                if let Ok(text) = serde_json::to_string(&WsMessage::from(update)) {
+                    if let Err(e) = sender.send(Message::Text(text)).await {
+                        error!("Failed to send outline to {}: {}", connection_id, e);
+                        break;
+                    }
+                }
+            }
+
             // Send periodic pings
             _ = heartbeat_interval.tick() => {
                 if let Err(e) = sender.send(Message::Ping(vec![])).await {
@@ -486,6 +1461,25 @@ pub async fn handle_websocket_connection(
                     break;
                 }
             }
+
+            _ = focus_flush_interval.tick() => {
+                state.flush_focus(&connection_id).await;
+            }
+
+            // The user opened more connections than they may hold
+            _ = evicted.notified() => {
+                let reason = "Too many connections, closed the least recently used".to_string();
+                if let Err(e) = send_error(&mut sender, WsError::rejected(reason.clone())).await {
+                    debug!("Failed to tell evicted connection {}: {}", connection_id, e);
+                }
+                if let Err(e) = sender.send(Message::Close(Some(CloseFrame {
+                    code: CloseCode::from(CLOSE_CONNECTION_LIMIT),
+                    reason: reason.into(),
+                }))).await {
+                    debug!("Failed to send close frame to {}: {}", connection_id, e);
+                }
+                break;
+            }
         }
     }
 
@@ -501,13 +1495,26 @@ async fn handle_message(
     state: &Arc<WsServerState>,
     sender: &mut futures::stream::SplitSink<WsStream<tokio::net::TcpStream>, Message>,
     broadcast_receiver: &mut Option<broadcast::Receiver<WsMessage>>,
+    user_receiver: &mut Option<broadcast::Receiver<WsMessage>>,
+    activity_receiver: &mut Option<broadcast::Receiver<ProjectActivity>>,
 ) -> Result<(), AppError> {
     match msg {
         Message::Text(text) => {
-            let ws_message: WsMessage = serde_json::from_str(&text)
-                .map_err(|e| AppError::BadRequest(format!("Invalid WebSocket message: {}", e)))?;
+            state.connection_limiter.touch(connection_id);
+            let span = message_span(connection_id, state).await;
+            async {
+                let ws_message = match parse_client_message(&text) {
+                    Ok(ws_message) => ws_message,
+                    Err(error) => {
+                        debug!("Rejected WebSocket message from {}: {}", connection_id, error.message);
+                        return send_error(sender, error).await;
+                    }
+                };
 
-            handle_ws_message(connection_id, ws_message, state, sender, broadcast_receiver).await
+                handle_ws_message(connection_id, ws_message, state, sender, broadcast_receiver, user_receiver, activity_receiver).await
+            }
+            .instrument(span)
+            .await
         }
         Message::Binary(_) => {
             warn!("Received binary message on WebSocket connection: {}", connection_id);
@@ -541,6 +1548,114 @@ async fn handle_message(
     }
 }
 
+/// Span of one client message, naming the connection's user and session
+async fn message_span(connection_id: &str, state: &WsServerState) -> tracing::Span {
+    let (user_id, session_id) = match state.connections.read().await.get(connection_id) {
+        Some(connection) => {
+            let connection = connection.read().await;
+            (connection.user.as_ref().map(|user| user.user_id), connection.session_id)
+        }
+        None => (None, None),
+    };
+
+    tracing::info_span!(
+        "ws_message",
+        user_id = user_id.map(tracing::field::display),
+        session_id = session_id.map(tracing::field::display)
+    )
+}
+
+/// Parse a client frame, telling unknown message types apart from malformed ones
+fn parse_client_message(text: &str) -> Result<WsMessage, WsError> {
+    let value: serde_json::Value = serde_json::from_str(text)
+        .map_err(|e| WsError::rejected(format!("Invalid WebSocket message: {}", e)))?;
+
+    let message_type = value.get("type").and_then(|t| t.as_str()).unwrap_or_default().to_string();
+    if !CLIENT_MESSAGE_TYPES.contains(&message_type.as_str()) {
+        return Err(WsError::rejected(format!(
+            "Unsupported message type '{}' for protocol {}",
+            message_type, PROTOCOL_VERSION
+        )));
+    }
+
+    serde_json::from_value(value)
+        .map_err(|e| WsError::rejected(format!("Invalid {} message: {}", message_type, e)))
+}
+
+/// Send an error frame to the client
+async fn send_error(
+    sender: &mut futures::stream::SplitSink<WsStream<tokio::net::TcpStream>, Message>,
+    error: WsError,
+) -> Result<(), AppError> {
+    let error_text = serde_json::to_string(&WsMessage::from(error))?;
+    sender.send(Message::Text(error_text)).await
+        .map_err(|e| AppError::Server(format!("Failed to send error response: {}", e)))
+}
+
+/// Meter a message against its budget; false when it has to be dropped.
+///
+/// Connections that keep going past the limit are closed with
+/// `CLOSE_RATE_LIMITED` and recorded in the audit log.
+async fn enforce_rate_limit(
+    connection_id: &str,
+    budget: WsBudget,
+    state: &Arc<WsServerState>,
+    sender: &mut futures::stream::SplitSink<WsStream<tokio::net::TcpStream>, Message>,
+) -> Result<bool, AppError> {
+    let user_id = {
+        let connections = state.connections.read().await;
+        match connections.get(connection_id) {
+            Some(connection) => connection.read().await.user.as_ref().map(|user| user.user_id),
+            None => None,
+        }
+    };
+
+    match state.rate_limiter.check(connection_id, user_id, budget) {
+        RateVerdict::Allowed => Ok(true),
+        RateVerdict::Limited { retry_after_ms } => {
+            let error = WsError::new(
+                WsErrorCode::RateLimited,
+                format!("Too many {} messages, slow down", budget.as_str()),
+            );
+            send_error(sender, error.retry_after(retry_after_ms)).await?;
+            Ok(false)
+        }
+        RateVerdict::Disconnect { violations } => {
+            warn!(
+                "Closing WebSocket connection {} after {} rate limited {} messages",
+                connection_id, violations, budget.as_str()
+            );
+            if let Err(e) = AuditEvent::record(
+                &*state.db_pool,
+                user_id,
+                "websocket_rate_limited",
+                "websocket_connection",
+                None,
+                None,
+                serde_json::json!({
+                    "connection_id": connection_id,
+                    "budget": budget.as_str(),
+                    "violations": violations,
+                }),
+            )
+            .await
+            {
+                warn!("Failed to audit rate limited connection {}: {}", connection_id, e);
+            }
+
+            let reason = "Rate limits exceeded repeatedly".to_string();
+            send_error(sender, WsError::new(WsErrorCode::RateLimited, reason.clone())).await?;
+            sender.send(Message::Close(Some(CloseFrame {
+                code: CloseCode::from(CLOSE_RATE_LIMITED),
+                reason: reason.clone().into(),
+            }))).await
+                .map_err(|e| AppError::Server(format!("Failed to send close frame: {}", e)))?;
+
+            Err(AppError::WebSocket(reason))
+        }
+    }
+}
+
 /// Handle parsed WebSocket message
 async fn handle_ws_message(
     connection_id: &str,
@@ -548,8 +1663,91 @@ async fn handle_ws_message(
     state: &Arc<WsServerState>,
     sender: &mut futures::stream::SplitSink<WsStream<tokio::net::TcpStream>, Message>,
     broadcast_receiver: &mut Option<broadcast::Receiver<WsMessage>>,
+    user_receiver: &mut Option<broadcast::Receiver<WsMessage>>,
+    activity_receiver: &mut Option<broadcast::Receiver<ProjectActivity>>,
 ) -> Result<(), AppError> {
+    // Session traffic is only accepted once the client has negotiated a protocol version
+    let requires_hello = matches!(
+        ws_message,
+        WsMessage::JoinSession { .. } | WsMessage::Operation { .. } | WsMessage::Undo { .. }
+            | WsMessage::Redo { .. } | WsMessage::Cursor { .. } | WsMessage::FocusFile { .. }
+            | WsMessage::ChatMessage { .. } | WsMessage::SubscribeProject { .. }
+            | WsMessage::UnsubscribeProject { .. } | WsMessage::SubscribeCompileBatch { .. }
+            | WsMessage::UnsubscribeCompileBatch { .. }
+    );
+    if requires_hello {
+        let negotiated = {
+            let connections = state.connections.read().await;
+            match connections.get(connection_id) {
+                Some(connection) => connection.read().await.protocol_version.is_some(),
+                None => false,
+            }
+        };
+
+        if !negotiated {
+            return send_error(sender, WsError::rejected("Send Hello before session messages")).await;
+        }
+    }
+
+    let budget = match ws_message {
+        WsMessage::Operation { .. } | WsMessage::Undo { .. } | WsMessage::Redo { .. }
+            | WsMessage::Cursor { .. } | WsMessage::FocusFile { .. } => Some(WsBudget::Operations),
+        WsMessage::ChatMessage { .. } => Some(WsBudget::Chat),
+        WsMessage::JoinSession { .. } => Some(WsBudget::Joins),
+        _ => None,
+    };
+    if let Some(budget) = budget {
+        if !enforce_rate_limit(connection_id, budget, state, sender).await? {
+            return Ok(());
+        }
+    }
+
     match ws_message {
+        WsMessage::Hello { protocol_version, client_info } => {
+            let client_version = ProtocolVersion::parse(&protocol_version);
+
+            let client_version = match client_version {
+                Some(version) if version.is_compatible_with(&PROTOCOL_VERSION) => version,
+                _ => {
+                    let reason = format!(
+                        "Unsupported protocol version '{}', server speaks {}",
+                        protocol_version, PROTOCOL_VERSION
+                    );
+                    send_error(sender, WsError::rejected(reason.clone())).await?;
+                    sender.send(Message::Close(Some(CloseFrame {
+                        code: CloseCode::from(CLOSE_PROTOCOL_MISMATCH),
+                        reason: reason.clone().into(),
+                    }))).await
+                        .map_err(|e| AppError::Server(format!("Failed to send close frame: {}", e)))?;
+
+                    return Err(AppError::WebSocket(reason));
+                }
+            };
+
+            {
+                let connections = state.connections.read().await;
+                if let Some(connection) = connections.get(connection_id) {
+                    let mut state_write = connection.write().await;
+                    state_write.protocol_version = Some(client_version);
+                    state_write.last_heartbeat = Utc::now();
+                }
+            }
+
+            debug!(
+                "Connection {} negotiated protocol {} (client: {:?})",
+                connection_id, client_version, client_info
+            );
+
+            let response = WsMessage::ServerHello {
+                protocol_version: PROTOCOL_VERSION.to_string(),
+                capabilities: SERVER_CAPABILITIES.iter().map(|c| c.to_string()).collect(),
+                heartbeat_interval: state.config.websocket.heartbeat_interval,
+            };
+            let response_text = serde_json::to_string(&response)?;
+            sender.send(Message::Text(response_text)).await
+                .map_err(|e| AppError::Server(format!("Failed to send server hello: {}", e)))?;
+        }
+
         WsMessage::Authenticate { token, session_id } => {
             // Verify JWT token
             let jwt_service = crate::models::auth::JwtService::new(
@@ -559,7 +1757,7 @@ async fn handle_ws_message(
                 state.config.jwt.refresh_expiration as i64,
             )?;
 
-            let auth_result = match jwt_service.verify_token(&token) {
+            let auth_result = match jwt_service.decode_token(&token) {
                 Ok(claims) => {
                     let auth_context = crate::models::auth::AuthContext::from(claims);
 
@@ -573,6 +1771,9 @@ async fn handle_ws_message(
                             state_write.last_heartbeat = Utc::now();
                         }
                     }
+                    state.admit_connection(connection_id, auth_context.user_id).await;
+
+                    *user_receiver = Some(state.user_channels.subscribe(auth_context.user_id));
 
                     // Set up broadcast receiver for session if specified
                     if let Some(session_id) = session_id {
@@ -583,6 +1784,7 @@ async fn handle_ws_message(
                         success: true,
                         user: Some(auth_context),
                         error: None,
+                        error_code: None,
                     }
                 }
                 Err(e) => {
@@ -590,6 +1792,7 @@ async fn handle_ws_message(
                         success: false,
                         user: None,
                         error: Some(format!("Authentication failed: {}", e)),
+                        error_code: Some(WsErrorCode::from(&e)),
                     }
                 }
             };
@@ -599,24 +1802,15 @@ async fn handle_ws_message(
                 .map_err(|e| AppError::Server(format!("Failed to send auth response: {}", e)))?;
         }
 
-        WsMessage::JoinSession { session_id, role, password } => {
+        WsMessage::JoinSession { session_id, role, password, join_code } => {
             // Get user from connection state
-            let user_id = {
-                let connections = state.connections.read().await;
-                if let Some(connection) = connections.get(connection_id) {
-                    let conn = connection.read().await;
-                    if let Some(user) = &conn.user {
-                        user.user_id
-                    } else {
-                        return Err(AppError::Authentication("Not authenticated".to_string()));
-                    }
-                } else {
-                    return Err(AppError::Authentication("Connection not found".to_string()));
-                }
+            let Some(user_id) = state.connection_user(connection_id).await else {
+                return send_error(sender, WsError::not_authenticated()).await;
             };
 
             // Handle session join
-            match state.handle_session_join(connection_id, session_id, user_id, role, password).await {
+            let credentials = JoinCredentials { password: password.as_deref(), join_code: join_code.as_deref() };
+            match state.handle_session_join(connection_id, session_id, user_id, role, credentials).await {
                 Ok(participant) => {
                     // Get session info and current participants
                     let session_info = CollaborationSession::find_by_id(&*state.db_pool, session_id).await?
@@ -625,13 +1819,19 @@ async fn handle_ws_message(
                             id: session_id.to_string(),
                         })?;
 
-                    let current_participants = SessionParticipant::get_active_participants(&*state.db_pool, session_id).await?;
+                    let mut current_participants = SessionParticipant::get_active_participants(&*state.db_pool, session_id).await?;
+                    state.session_activity.overlay(&mut current_participants);
+                    let mask = SessionMask::load(&state.db_pool, &session_info, user_id, Utc::now()).await?;
 
-                    let response = WsMessage::SessionJoined {
+                    let mut response = WsMessage::SessionJoined {
                         session_id,
                         participants: current_participants,
                         session_info,
                     };
+                    if let Some(mut mask) = mask {
+                        mask.cover(&state.db_pool, response.user_ids()).await?;
+                        response = response.masked(&mask);
+                    }
 
                     let response_text = serde_json::to_string(&response)?;
                     sender.send(Message::Text(response_text)).await
@@ -641,13 +1841,7 @@ async fn handle_ws_message(
                     *broadcast_receiver = Some(state.get_session_broadcast(session_id).await.subscribe());
                 }
                 Err(e) => {
-                    let error_response = WsMessage::Error {
-                        code: "JOIN_FAILED".to_string(),
-                        message: e.to_string(),
-                    };
-                    let error_text = serde_json::to_string(&error_response)?;
-                    sender.send(Message::Text(error_text)).await
-                        .map_err(|e| AppError::Server(format!("Failed to send error response: {}", e)))?;
+                    send_error(sender, WsError::from(&e)).await?;
                 }
             }
         }
@@ -663,60 +1857,40 @@ async fn handle_ws_message(
                 }
             };
 
+            state.presence.leave(connection_id);
             if let (Some(session_id), Some(participant_id)) = (session_id, participant_id) {
                 state.handle_session_leave(session_id, participant_id).await?;
             }
         }
 
         WsMessage::Operation { session_id, operation_type, position, content, length, file_id } => {
-            let user_id = {
-                let connections = state.connections.read().await;
-                if let Some(connection) = connections.get(connection_id) {
-                    let conn = connection.read().await;
-                    if let Some(user) = &conn.user {
-                        user.user_id
-                    } else {
-                        return Err(AppError::Authentication("Not authenticated".to_string()));
-                    }
-                } else {
-                    return Err(AppError::Authentication("Connection not found".to_string()));
-                }
+            let Some(user_id) = state.connection_user(connection_id).await else {
+                return send_error(sender, WsError::not_authenticated()).await;
             };
 
             if let Err(e) = state.handle_operation(session_id, user_id, operation_type, position, content, length, file_id).await {
-                let error_response = WsMessage::Error {
-                    code: "OPERATION_FAILED".to_string(),
-                    message: e.to_string(),
-                };
-                let error_text = serde_json::to_string(&error_response)?;
-                sender.send(Message::Text(error_text)).await
-                    .map_err(|e| AppError::Server(format!("Failed to send error response: {}", e)))?;
+                send_error(sender, WsError::from(&e)).await?;
+            }
+        }
+
+        WsMessage::Undo { session_id } | WsMessage::Redo { session_id } => {
+            let redo = matches!(ws_message, WsMessage::Redo { .. });
+            let Some(user_id) = state.connection_user(connection_id).await else {
+                return send_error(sender, WsError::not_authenticated()).await;
+            };
+
+            if let Err(e) = state.handle_undo(session_id, user_id, redo).await {
+                send_error(sender, WsError::from(&e)).await?;
             }
         }
 
         WsMessage::ChatMessage { session_id, content, message_type, reply_to } => {
-            let user_id = {
-                let connections = state.connections.read().await;
-                if let Some(connection) = connections.get(connection_id) {
-                    let conn = connection.read().await;
-                    if let Some(user) = &conn.user {
-                        user.user_id
-                    } else {
-                        return Err(AppError::Authentication("Not authenticated".to_string()));
-                    }
-                } else {
-                    return Err(AppError::Authentication("Connection not found".to_string()));
-                }
+            let Some(user_id) = state.connection_user(connection_id).await else {
+                return send_error(sender, WsError::not_authenticated()).await;
             };
 
             if let Err(e) = state.handle_chat_message(session_id, user_id, content, message_type, reply_to).await {
-                let error_response = WsMessage::Error {
-                    code: "MESSAGE_FAILED".to_string(),
-                    message: e.to_string(),
-                };
-                let error_text = serde_json::to_string(&error_response)?;
-                sender.send(Message::Text(error_text)).await
-                    .map_err(|e| AppError::Server(format!("Failed to send error response: {}", e)))?;
+                send_error(sender, WsError::from(&e)).await?;
             }
         }
 
@@ -727,20 +1901,117 @@ async fn handle_ws_message(
                 .map_err(|e| AppError::Server(format!("Failed to send pong: {}", e)))?;
         }
 
+        WsMessage::Cursor { session_id, .. } => {
+            let Some(user_id) = state.connection_user(connection_id).await else {
+                return send_error(sender, WsError::not_authenticated()).await;
+            };
+
+            // Cursor updates are accepted but not relayed yet; they count as activity
+            debug!("Cursor update from {} in session {}", connection_id, session_id);
+            if let Err(e) = state.note_activity(session_id, user_id, Utc::now()).await {
+                send_error(sender, WsError::from(&e)).await?;
+            }
+        }
+
+        WsMessage::FocusFile { session_id, file_id } => {
+            let Some(user_id) = state.connection_user(connection_id).await else {
+                return send_error(sender, WsError::not_authenticated()).await;
+            };
+
+            if let Err(e) = state.handle_focus(connection_id, session_id, user_id, file_id).await {
+                send_error(sender, WsError::from(&e)).await?;
+            }
+        }
+
+        WsMessage::SubscribeProject { project_id } => {
+            let Some(user_id) = state.connection_user(connection_id).await else {
+                return send_error(sender, WsError::not_authenticated()).await;
+            };
+
+            if !Project::has_access(&*state.db_pool, project_id, user_id).await? {
+                return send_error(sender, WsError::rejected(format!("Project {} not found", project_id))).await;
+            }
+
+            {
+                let connections = state.connections.read().await;
+                if let Some(connection) = connections.get(connection_id) {
+                    connection.write().await.project_subscriptions.insert(project_id);
+                }
+            }
+            if activity_receiver.is_none() {
+                *activity_receiver = Some(state.activity_feed.subscribe());
+            }
+        }
+
+        WsMessage::UnsubscribeProject { project_id } => {
+            let connections = state.connections.read().await;
+            if let Some(connection) = connections.get(connection_id) {
+                connection.write().await.project_subscriptions.remove(&project_id);
+            }
+        }
+
+        WsMessage::SubscribeCompileBatch { batch_id } => {
+            let Some(user_id) = state.connection_user(connection_id).await else {
+                return send_error(sender, WsError::not_authenticated()).await;
+            };
+
+            if CompileBatch::find_by_id(&state.db_pool, batch_id, user_id).await?.is_none() {
+                return send_error(sender, WsError::rejected(format!("Compile batch {} not found", batch_id))).await;
+            }
+
+            let connections = state.connections.read().await;
+            if let Some(connection) = connections.get(connection_id) {
+                if connection.write().await.batch_subscriptions.insert(batch_id) {
+                    state.user_channels.watch_batch(batch_id, user_id);
+                }
+            }
+        }
+
+        WsMessage::UnsubscribeCompileBatch { batch_id } => {
+            let Some(user_id) = state.connection_user(connection_id).await else {
+                return Ok(());
+            };
+
+            let connections = state.connections.read().await;
+            if let Some(connection) = connections.get(connection_id) {
+                if connection.write().await.batch_subscriptions.remove(&batch_id) {
+                    state.user_channels.unwatch_batch(batch_id, user_id);
+                }
+            }
+        }
+
         _ => {
-            warn!("Unhandled WebSocket message type from connection: {}", connection_id);
+            // Server-to-client message types are never valid from a client
+            send_error(sender, WsError::rejected("Message type is not accepted from clients")).await?;
         }
     }
 
     Ok(())
 }
 
-/// Start WebSocket server
+/// Start WebSocket server; `user_channels`, `mention_notifier`,
+/// `connection_limiter`, `presence` and `conflicts` should be the HTTP
+/// server's, from its `AppState`
 pub async fn start_websocket_server(
     config: Config,
     db_pool: sqlx::PgPool,
+    user_channels: Arc<UserChannels>,
+    mention_notifier: Arc<MentionNotifier>,
+    connection_limiter: Arc<WsConnectionLimiter>,
+    presence: Arc<ProjectPresence>,
+    conflicts: Arc<ConflictRecorder>,
 ) -> Result<(), AppError> {
-    let state = Arc::new(WsServerState::new(config.clone(), db_pool));
+    let state = Arc::new(WsServerState::new(
+        config.clone(),
+        db_pool,
+        user_channels,
+        mention_notifier,
+        connection_limiter,
+        presence,
+        conflicts,
+    ));
+    tokio::spawn(forward_project_activity(state.clone()));
+    tokio::spawn(enforce_idle_policies(state.clone()));
     let addr = format!("0.0.0.0:{}", config.websocket.port);
 
     let listener = tokio::net::TcpListener::bind(&addr)
@@ -759,6 +2030,7 @@ pub async fn start_websocket_server(
 
         info!("New WebSocket connection from: {}", addr);
 
+        let span = tracing::info_span!("ws_connection", connection_id = %connection_id, peer = %addr);
         tokio::spawn(async move {
             // Upgrade to WebSocket connection
             let ws_stream = match tokio_tungstenite::accept_async(stream).await {
@@ -770,7 +2042,7 @@ pub async fn start_websocket_server(
             };
 
             handle_websocket_connection(ws_stream, connection_id, state_clone).await;
-        });
+        }.instrument(span));
     }
 }
 
@@ -778,6 +2050,21 @@ pub async fn start_websocket_server(
 mod tests {
     use super::*;
     use crate::config::Config;
+    use crate::models::collaboration::ParticipantActivity;
+
+    fn test_ws_state(db: &crate::testing::TestDb) -> WsServerState {
+        ws_state_with(crate::testing::test_config(), db)
+    }
+
+    fn ws_state_with(config: Config, db: &crate::testing::TestDb) -> WsServerState {
+        let user_channels = Arc::new(UserChannels::new());
+        let mailer = Arc::new(crate::email::Mailer::new(&config, db.pool.clone()).unwrap());
+        let mention_notifier = Arc::new(MentionNotifier::new(mailer, user_channels.clone()));
+        let connection_limiter = Arc::new(WsConnectionLimiter::from_config(&config.websocket));
+        let presence = Arc::new(ProjectPresence::new());
+        let conflicts = Arc::new(ConflictRecorder::new(&config));
+        WsServerState::new(config, db.pool.clone(), user_channels, mention_notifier, connection_limiter, presence, conflicts)
+    }
 
     #[test]
     fn test_connection_state_default() {
@@ -794,6 +2081,821 @@ mod tests {
         assert!(json.contains("\"type\":\"Ping\""));
     }
 
+    /// Message shapes at `PROTOCOL_VERSION`. If this snapshot has to change,
+    /// bump `PROTOCOL_VERSION` in the same commit.
+    const PROTOCOL_SNAPSHOT: (ProtocolVersion, &[&str]) = (
+        ProtocolVersion { major: 1, minor: 12 },
+        &[
+            "ActivityEvent(activity)",
+            "AuthResult(error,error_code,success,user)",
+            "Authenticate(session_id,token)",
+            "ChatMessage(content,message_type,reply_to,session_id)",
+            "CompileProgress(batch_id,job_id,message,project_id,status)",
+            "Cursor(position,selection,session_id)",
+            "Error(code,message,retry_after_ms,retryable)",
+            "FocusFile(file_id,session_id)",
+            "Hello(client_info,protocol_version)",
+            "JoinSession(join_code,password,role,session_id)",
+            "LeaveSession()",
+            "Notification(notification)",
+            "Operation(content,file_id,length,operation_type,position,session_id)",
+            "OutlineUpdated(equations,figures,file_id,sections,tables)",
+            "ParticipantFocus(file_id,session_id,user_id)",
+            "ParticipantLeft(session_id,user_id)",
+            "ParticipantUpdate(participant,session_id)",
+            "Ping()",
+            "Pong()",
+            "Redo(session_id)",
+            "ServerChatMessage(attachment,content,id,mentions,message_type,reply_to,session_id,timestamp,user_id)",
+            "ServerHello(capabilities,heartbeat_interval,protocol_version)",
+            "ServerOperation(content,file_id,is_undo,length,operation_type,position,session_id,timestamp,user_id)",
+            "SessionJoined(participants,session_id,session_info)",
+            "SessionStatus(session_id,status)",
+            "SubscribeCompileBatch(batch_id)",
+            "SubscribeProject(project_id)",
+            "Undo(session_id)",
+            "UnsubscribeCompileBatch(batch_id)",
+            "UnsubscribeProject(project_id)",
+        ],
+    );
+
+    /// One instance of every message; the exhaustive match makes a new variant
+    /// fail to compile until it is added here.
+    fn sample_messages() -> Vec<WsMessage> {
+        let id = Uuid::new_v4();
+        let now = Utc::now();
+        let participant = SessionParticipant {
+            id,
+            session_id: id,
+            user_id: id,
+            role: ParticipantRole::Editor,
+            joined_at: now,
+            left_at: None,
+            cursor_position: None,
+            selection: None,
+            is_online: true,
+            last_seen_at: now,
+            permissions: None,
+            current_file_id: None,
+            last_activity_at: now,
+            activity: ParticipantActivity::Active,
+            restore_role: None,
+            queued_at: None,
+            pseudonym: None,
+        };
+        let session = CollaborationSession {
+            id,
+            project_id: id,
+            file_id: None,
+            created_by: id,
+            session_type: crate::models::collaboration::SessionType::Realtime,
+            title: None,
+            description: None,
+            is_active: true,
+            max_participants: 10,
+            password_hash: None,
+            settings: None,
+            started_at: None,
+            ended_at: None,
+            created_at: now,
+            updated_at: now,
+            scheduled_start: None,
+            scheduled_end: None,
+            reminder_sent_at: None,
+            cancelled_at: None,
+            anonymous_until: None,
+        };
+
+        let messages = vec![
+            WsMessage::Hello { protocol_version: "1.0".to_string(), client_info: None },
+            WsMessage::Authenticate { token: String::new(), session_id: None },
+            WsMessage::JoinSession { session_id: id, role: ParticipantRole::Editor, password: None, join_code: None },
+            WsMessage::LeaveSession,
+            WsMessage::Operation {
+                session_id: id,
+                operation_type: OperationType::Insert,
+                position: None,
+                content: None,
+                length: None,
+                file_id: None,
+            },
+            WsMessage::Undo { session_id: id },
+            WsMessage::Redo { session_id: id },
+            WsMessage::Cursor { session_id: id, position: 0, selection: None },
+            WsMessage::FocusFile { session_id: id, file_id: id },
+            WsMessage::ChatMessage { session_id: id, content: String::new(), message_type: MessageType::Text, reply_to: None },
+            WsMessage::SubscribeProject { project_id: id },
+            WsMessage::UnsubscribeProject { project_id: id },
+            WsMessage::SubscribeCompileBatch { batch_id: id },
+            WsMessage::UnsubscribeCompileBatch { batch_id: id },
+            WsMessage::Ping,
+            WsMessage::ServerHello { protocol_version: "1.0".to_string(), capabilities: vec![], heartbeat_interval: 30 },
+            WsMessage::AuthResult { success: true, user: None, error: None, error_code: None },
+            WsMessage::SessionJoined { session_id: id, participants: vec![], session_info: session },
+            WsMessage::ParticipantUpdate { session_id: id, participant },
+            WsMessage::ParticipantLeft { session_id: id, user_id: id },
+            WsMessage::ParticipantFocus { session_id: id, user_id: id, file_id: id },
+            WsMessage::ServerOperation {
+                session_id: id,
+                user_id: id,
+                operation_type: OperationType::Insert,
+                position: None,
+                content: None,
+                length: None,
+                file_id: None,
+                timestamp: now,
+                is_undo: false,
+            },
+            WsMessage::ServerChatMessage {
+                session_id: id,
+                id,
+                user_id: id,
+                content: String::new(),
+                message_type: MessageType::Text,
+                reply_to: None,
+                attachment: None,
+                mentions: vec![id],
+                timestamp: now,
+            },
+            WsMessage::SessionStatus { session_id: id, status: String::new() },
+            WsMessage::Notification {
+                notification: Notification {
+                    id,
+                    user_id: id,
+                    kind: String::new(),
+                    title: String::new(),
+                    body: String::new(),
+                    data: None,
+                    read_at: None,
+                    created_at: now,
+                },
+            },
+            WsMessage::ActivityEvent {
+                activity: ProjectActivity {
+                    id,
+                    project_id: id,
+                    user_id: id,
+                    action: String::new(),
+                    entity_type: String::new(),
+                    entity_id: None,
+                    details: None,
+                    created_at: now,
+                },
+            },
+            WsMessage::OutlineUpdated {
+                file_id: id,
+                sections: vec![],
+                figures: vec![],
+                tables: vec![],
+                equations: vec![],
+            },
+            WsMessage::CompileProgress {
+                job_id: id,
+                project_id: id,
+                batch_id: None,
+                status: CompilationStatus::Cancelled,
+                message: None,
+            },
+            WsMessage::from(WsError::rejected(String::new())),
+            WsMessage::Pong,
+        ];
+
+        for message in &messages {
+            match message {
+                WsMessage::Hello { .. }
+                | WsMessage::Authenticate { .. }
+                | WsMessage::JoinSession { .. }
+                | WsMessage::LeaveSession
+                | WsMessage::Operation { .. }
+                | WsMessage::Undo { .. }
+                | WsMessage::Redo { .. }
+                | WsMessage::Cursor { .. }
+                | WsMessage::FocusFile { .. }
+                | WsMessage::ChatMessage { .. }
+                | WsMessage::SubscribeProject { .. }
+                | WsMessage::UnsubscribeProject { .. }
+                | WsMessage::SubscribeCompileBatch { .. }
+                | WsMessage::UnsubscribeCompileBatch { .. }
+                | WsMessage::Ping
+                | WsMessage::ServerHello { .. }
+                | WsMessage::AuthResult { .. }
+                | WsMessage::SessionJoined { .. }
+                | WsMessage::ParticipantUpdate { .. }
+                | WsMessage::ParticipantLeft { .. }
+                | WsMessage::ParticipantFocus { .. }
+                | WsMessage::ServerOperation { .. }
+                | WsMessage::ServerChatMessage { .. }
+                | WsMessage::SessionStatus { .. }
+                | WsMessage::Notification { .. }
+                | WsMessage::ActivityEvent { .. }
+                | WsMessage::OutlineUpdated { .. }
+                | WsMessage::CompileProgress { .. }
+                | WsMessage::Error { .. }
+                | WsMessage::Pong => {}
+            }
+        }
+
+        messages
+    }
+
+    #[test]
+    fn test_protocol_snapshot_matches_version() {
+        let mut shapes: Vec<String> = sample_messages()
+            .iter()
+            .map(|message| {
+                let value = serde_json::to_value(message).unwrap();
+                let object = value.as_object().unwrap();
+                let mut fields: Vec<&str> = object.keys().map(String::as_str).filter(|k| *k != "type").collect();
+                fields.sort_unstable();
+                format!("{}({})", object["type"].as_str().unwrap(), fields.join(","))
+            })
+            .collect();
+        shapes.sort();
+
+        assert_eq!(
+            PROTOCOL_VERSION, PROTOCOL_SNAPSHOT.0,
+            "PROTOCOL_VERSION changed; refresh PROTOCOL_SNAPSHOT for the new version"
+        );
+        assert_eq!(
+            shapes, PROTOCOL_SNAPSHOT.1,
+            "WsMessage shapes changed; bump PROTOCOL_VERSION and update PROTOCOL_SNAPSHOT"
+        );
+    }
+
+    #[test]
+    fn test_client_message_types_are_known() {
+        for message_type in CLIENT_MESSAGE_TYPES {
+            assert!(
+                PROTOCOL_SNAPSHOT.1.iter().any(|shape| shape.starts_with(&format!("{}(", message_type))),
+                "{} is not part of the protocol",
+                message_type
+            );
+        }
+    }
+
+    #[test]
+    fn test_protocol_version_compatibility() {
+        let v1_3 = ProtocolVersion::parse("1.3").unwrap();
+        assert!(v1_3.is_compatible_with(&ProtocolVersion { major: 1, minor: 0 }));
+        assert_eq!(ProtocolVersion::parse("1"), Some(ProtocolVersion { major: 1, minor: 0 }));
+        assert!(!ProtocolVersion::parse("2.0").unwrap().is_compatible_with(&PROTOCOL_VERSION));
+        assert!(ProtocolVersion::parse("one").is_none());
+        assert_eq!(ProtocolVersion { major: 1, minor: 4 }.to_string(), "1.4");
+    }
+
+    #[tokio::test]
+    async fn test_user_channels_reach_live_connections_only() {
+        let channels = UserChannels::new();
+        let (online, offline) = (Uuid::new_v4(), Uuid::new_v4());
+
+        let mut receiver = channels.subscribe(online);
+        assert!(channels.send(online, WsMessage::Pong));
+        assert!(matches!(receiver.recv().await, Ok(WsMessage::Pong)));
+
+        assert!(!channels.send(offline, WsMessage::Pong));
+
+        drop(receiver);
+        assert!(!channels.send(online, WsMessage::Pong));
+    }
+
+    #[tokio::test]
+    async fn test_batch_progress_reaches_its_watchers() {
+        let channels = UserChannels::new();
+        let (batch_id, creator, watcher) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+        let mut creator_receiver = channels.subscribe(creator);
+        let mut watcher_receiver = channels.subscribe(watcher);
+
+        // Two connections of the watcher subscribed; one leaving keeps the watch
+        channels.watch_batch(batch_id, watcher);
+        channels.watch_batch(batch_id, watcher);
+        channels.watch_batch(batch_id, creator);
+        channels.unwatch_batch(batch_id, watcher);
+        channels.send_to_batch(batch_id, creator, WsMessage::Pong);
+        assert!(matches!(watcher_receiver.try_recv(), Ok(WsMessage::Pong)));
+        assert!(matches!(creator_receiver.try_recv(), Ok(WsMessage::Pong)));
+        assert!(creator_receiver.try_recv().is_err());
+
+        channels.unwatch_batch(batch_id, watcher);
+        channels.send_to_batch(batch_id, creator, WsMessage::Pong);
+        assert!(watcher_receiver.try_recv().is_err());
+        assert!(matches!(creator_receiver.try_recv(), Ok(WsMessage::Pong)));
+    }
+
+    #[test]
+    fn test_focus_persistence_is_throttled() {
+        let (a, b, c) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+        let start = Instant::now();
+        let mut focus = FocusState::default();
+
+        assert_eq!(focus.focus(a, start), Some(a));
+        assert!(focus.is_checked(a));
+
+        // Switches inside the interval are held back and only the latest one is kept
+        assert_eq!(focus.focus(b, start + Duration::from_secs(1)), None);
+        assert_eq!(focus.focus(c, start + Duration::from_secs(2)), None);
+        assert_eq!(focus.file_id, Some(c));
+        assert_eq!(focus.take_due(start + Duration::from_secs(2)), None);
+
+        assert_eq!(focus.take_due(start + FOCUS_PERSIST_INTERVAL), Some(c));
+        assert_eq!(focus.take_due(start + FOCUS_PERSIST_INTERVAL * 3), None);
+
+        // Refocusing the persisted file writes nothing
+        assert_eq!(focus.focus(c, start + FOCUS_PERSIST_INTERVAL * 4), None);
+    }
+
+    #[test]
+    fn test_unknown_message_type_is_unsupported() {
+        for (text, message) in [
+            (r#"{"type":"Teleport"}"#, "Unsupported message type 'Teleport'"),
+            (r#"{"type":"Pong"}"#, "Unsupported message type 'Pong'"),
+            (r#"{"type":"Cursor"}"#, "Invalid Cursor message"),
+        ] {
+            let error = parse_client_message(text).unwrap_err();
+            assert_eq!(error.code, WsErrorCode::OperationRejected);
+            assert!(error.message.starts_with(message), "{}", error.message);
+        }
+
+        assert!(matches!(
+            parse_client_message(r#"{"type":"Hello","protocol_version":"1.0"}"#),
+            Ok(WsMessage::Hello { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_session_members_count_as_active_in_the_project() {
+        async fn join(state: &WsServerState, connection_id: &str, session_id: Uuid, user_id: Uuid) {
+            state.register_connection(connection_id.to_string()).await;
+            let credentials = JoinCredentials { password: None, join_code: None };
+            state.handle_session_join(connection_id, session_id, user_id, ParticipantRole::Viewer, credentials).await.unwrap();
+        }
+
+        let Some(db) = crate::testing::TestDb::start().await else { return };
+        let host = crate::testing::create_test_user(&db.pool).await;
+        let guest = crate::testing::create_test_user(&db.pool).await;
+        let project = crate::testing::create_test_project(&db.pool, &host, false).await;
+        let session = crate::testing::create_test_session(&db.pool, &project, &host).await;
+
+        let state = test_ws_state(&db);
+        join(&state, "host-1", session.id, host.id).await;
+        join(&state, "host-2", session.id, host.id).await;
+        // The guest cannot read the private project
+        join(&state, "guest", session.id, guest.id).await;
+        assert_eq!(state.presence.count(project.id), 1);
+
+        state.unregister_connection("host-1").await;
+        assert_eq!(state.presence.count(project.id), 1);
+        state.unregister_connection("host-2").await;
+        assert_eq!(state.presence.count(project.id), 0);
+
+        let mut config = crate::testing::test_config();
+        config.websocket.presence_count_guests = true;
+        let state = ws_state_with(config, &db);
+        join(&state, "guest", session.id, guest.id).await;
+        assert_eq!(state.presence.count(project.id), 1);
+    }
+
+    #[tokio::test]
+    async fn test_logged_activity_reaches_the_feed() {
+        let Some(db) = crate::testing::TestDb::start().await else { return };
+        let owner = crate::testing::create_test_user(&db.pool).await;
+        let project = crate::testing::create_test_project(&db.pool, &owner, false).await;
+
+        let state = Arc::new(test_ws_state(&db));
+        let mut feed = state.activity_feed.subscribe();
+        let listener = tokio::spawn(forward_project_activity(state.clone()));
+
+        // The listener connects in the background; log until it picks something up
+        let activity = tokio::time::timeout(Duration::from_secs(10), async {
+            loop {
+                ProjectActivity::log(&db.pool, project.id, owner.id, "file_updated", "file", None, None)
+                    .await
+                    .unwrap();
+                if let Ok(Ok(activity)) = tokio::time::timeout(Duration::from_millis(200), feed.recv()).await {
+                    if activity.project_id == project.id {
+                        return activity;
+                    }
+                }
+            }
+        })
+        .await
+        .expect("activity should be forwarded");
+        listener.abort();
+
+        assert_eq!(activity.action, "file_updated");
+        assert_eq!(activity.user_id, owner.id);
+    }
+
+    #[tokio::test]
+    async fn test_anonymous_broadcasts_hide_identities() {
+        let Some(db) = crate::testing::TestDb::start().await else { return };
+        let host = crate::testing::create_test_user(&db.pool).await;
+        let author = crate::testing::create_test_user(&db.pool).await;
+        let reviewer = crate::testing::create_test_user(&db.pool).await;
+        let project = crate::testing::create_test_project(&db.pool, &host, false).await;
+        let session = crate::testing::create_test_session(&db.pool, &project, &host).await;
+        let participant = SessionParticipant::join(&db.pool, session.id, author.id, ParticipantRole::Editor).await.unwrap();
+        SessionParticipant::join(&db.pool, session.id, reviewer.id, ParticipantRole::Viewer).await.unwrap();
+
+        let state = test_ws_state(&db);
+        let connection_id = WsServerState::generate_connection_id();
+        state.register_connection(connection_id.clone()).await;
+        state.connections.read().await[&connection_id].write().await.user = Some(crate::testing::auth_context(&reviewer));
+        let mut cache = None;
+
+        let now = Utc::now();
+        let attachment = ChatAttachment {
+            id: Uuid::new_v4(),
+            session_id: session.id,
+            uploaded_by: author.id,
+            file_name: "figure.png".to_string(),
+            media_type: "image/png".to_string(),
+            size: 1,
+            content_hash: String::new(),
+            created_at: now,
+        };
+        let broadcasts = |session: &CollaborationSession| {
+            vec![
+                WsMessage::SessionJoined {
+                    session_id: session.id,
+                    participants: vec![participant.clone()],
+                    session_info: session.clone(),
+                },
+                WsMessage::ParticipantUpdate { session_id: session.id, participant: participant.clone() },
+                WsMessage::ParticipantLeft { session_id: session.id, user_id: author.id },
+                WsMessage::ParticipantFocus { session_id: session.id, user_id: author.id, file_id: Uuid::new_v4() },
+                WsMessage::ServerOperation {
+                    session_id: session.id,
+                    user_id: author.id,
+                    operation_type: OperationType::Insert,
+                    position: Some(0),
+                    content: Some("x".to_string()),
+                    length: Some(1),
+                    file_id: None,
+                    timestamp: now,
+                    is_undo: false,
+                },
+                WsMessage::ServerChatMessage {
+                    session_id: session.id,
+                    id: Uuid::new_v4(),
+                    user_id: author.id,
+                    content: attachment.id.to_string(),
+                    message_type: MessageType::File,
+                    reply_to: None,
+                    attachment: Some(attachment.clone().view()),
+                    mentions: Vec::new(),
+                    timestamp: now,
+                },
+            ]
+        };
+        let status = |status: &str| WsMessage::SessionStatus { session_id: session.id, status: status.to_string() };
+        let leaks = |text: &str| {
+            text.contains(&author.id.to_string()) || text.contains(&author.username) || text.contains(&author.email)
+        };
+
+        let text = serde_json::to_string(&state.mask_broadcast(&connection_id, &mut cache, broadcasts(&session)[2].clone()).await).unwrap();
+        assert!(leaks(&text), "identities are shown before anonymity is enabled");
+
+        let session = session.enable_anonymity(&db.pool, now + chrono::Duration::hours(1), now).await.unwrap();
+        assert!(state.mask_broadcast(&connection_id, &mut cache, status(ANONYMITY_ENABLED)).await.is_some());
+
+        let mut aliases = HashSet::new();
+        for message in broadcasts(&session) {
+            let masked = state.mask_broadcast(&connection_id, &mut cache, message).await.unwrap();
+            let text = serde_json::to_string(&masked).unwrap();
+            assert!(!leaks(&text), "{}", text);
+            // The reviewer sees the host, and the author under one alias throughout
+            match &masked {
+                WsMessage::SessionJoined { session_info, .. } => assert_eq!(session_info.created_by, host.id),
+                WsMessage::ParticipantUpdate { participant, .. } => {
+                    assert_eq!(participant.pseudonym.as_deref(), Some("Reviewer 1"));
+                }
+                _ => {}
+            }
+            aliases.extend(masked.user_ids());
+        }
+        assert_eq!(aliases.len(), 1);
+
+        // A reconnect gets the same pseudonym
+        let mut reconnected = None;
+        let masked = state.mask_broadcast(&connection_id, &mut reconnected, broadcasts(&session)[2].clone()).await.unwrap();
+        assert_eq!(masked.user_ids(), aliases.into_iter().collect::<Vec<_>>());
+
+        // The host sees everyone
+        let host_connection = WsServerState::generate_connection_id();
+        state.register_connection(host_connection.clone()).await;
+        state.connections.read().await[&host_connection].write().await.user = Some(crate::testing::auth_context(&host));
+        let masked = state.mask_broadcast(&host_connection, &mut None, broadcasts(&session)[2].clone()).await.unwrap();
+        assert_eq!(masked.user_ids(), vec![author.id]);
+
+        let session = session.disable_anonymity(&db.pool, true, Utc::now()).await.unwrap();
+        state.mask_broadcast(&connection_id, &mut cache, status(ANONYMITY_DISABLED)).await;
+        let masked = state.mask_broadcast(&connection_id, &mut cache, broadcasts(&session)[2].clone()).await.unwrap();
+        assert_eq!(masked.user_ids(), vec![author.id]);
+    }
+
+    #[tokio::test]
+    async fn test_operations_update_the_outline() {
+        let Some(db) = crate::testing::TestDb::start().await else { return };
+        let owner = crate::testing::create_test_user(&db.pool).await;
+        let project = crate::testing::create_test_project(&db.pool, &owner, false).await;
+        let session = crate::testing::create_test_session(&db.pool, &project, &owner).await;
+        let file = crate::testing::create_test_file(&db.pool, &project, &owner).await;
+
+        let state = test_ws_state(&db);
+        let mut outlines = state.outline.subscribe();
+
+        // Typed in two pieces before the body text
+        let position = file.content.find("Hi").unwrap() as i32;
+        for (offset, text) in [(0, "\\section{Res"), (12, "ults}\n")] {
+            let content = Some(text.to_string());
+            state
+                .handle_operation(session.id, owner.id, OperationType::Insert, Some(position + offset), content, None, Some(file.id))
+                .await
+                .unwrap();
+        }
+
+        let update = tokio::time::timeout(OUTLINE_DEBOUNCE + Duration::from_secs(2), outlines.recv())
+            .await
+            .expect("outline should be updated after the debounce")
+            .unwrap();
+        assert_eq!((update.session_id, update.project_id, update.file_id), (session.id, project.id, file.id));
+        assert_eq!(
+            update.outline.sections,
+            vec![SectionInfo { title: "Results".to_string(), level: 1, line_number: 3, label: None }]
+        );
+        // Both operations went into one extraction
+        assert!(outlines.try_recv().is_err());
+
+        let text = serde_json::to_string(&WsMessage::from(update)).unwrap();
+        assert!(text.contains("\"type\":\"OutlineUpdated\"") && text.contains("Results"), "{}", text);
+
+        let stored = crate::models::outline::project_outline(&db.pool, project.id).await.unwrap();
+        assert_eq!(stored[0].outline.sections[0].title, "Results");
+    }
+
+    #[tokio::test]
+    async fn test_merged_undo_is_recorded_as_a_conflict() {
+        let Some(db) = crate::testing::TestDb::start().await else { return };
+        let owner = crate::testing::create_test_user(&db.pool).await;
+        let editor = crate::testing::create_test_user(&db.pool).await;
+        let project = crate::testing::create_test_project(&db.pool, &owner, false).await;
+        crate::testing::add_collaborator(&db.pool, &project, &editor, crate::models::UserRole::Collaborator).await;
+        let session = crate::testing::create_test_session(&db.pool, &project, &owner).await;
+        let file = crate::testing::create_test_file(&db.pool, &project, &owner).await;
+
+        let state = test_ws_state(&db);
+        state.undo_history.join(session.id, owner.id);
+        state.undo_history.join(session.id, editor.id);
+        let insert = |user_id: Uuid, text: &str| {
+            state.handle_operation(session.id, user_id, OperationType::Insert, Some(0), Some(text.to_string()), None, Some(file.id))
+        };
+
+        // The editor's insert moves the owner's, so undoing it has to be merged
+        insert(owner.id, "Hello").await.unwrap();
+        insert(editor.id, ">> ").await.unwrap();
+        state.handle_undo(session.id, owner.id, false).await.unwrap();
+        assert_eq!(state.conflicts.count(ConflictKind::Transform, ConflictOutcome::AutoMerged), 1);
+        // The editor's own undo was not moved by anyone
+        state.handle_undo(session.id, editor.id, false).await.unwrap();
+        assert_eq!(state.conflicts.count(ConflictKind::Transform, ConflictOutcome::AutoMerged), 1);
+        assert_eq!(state.conflicts.count(ConflictKind::Transform, ConflictOutcome::Discarded), 0);
+
+        assert_eq!(state.conflicts.flush(&db.pool).await.unwrap(), 1);
+        let (endpoint, project_id, file_id, users): (String, Uuid, Option<Uuid>, i32) = sqlx::query_as(
+            "SELECT endpoint, project_id, file_id, cardinality(user_hashes) FROM conflict_events",
+        )
+        .fetch_one(&db.pool)
+        .await
+        .unwrap();
+        assert_eq!((endpoint.as_str(), project_id, file_id, users), ("WS Undo", project.id, Some(file.id), 2));
+    }
+
+    #[tokio::test]
+    async fn test_excess_connections_close_the_idlest() {
+        type Client = WsStream<tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>>;
+
+        async fn send(client: &mut Client, message: WsMessage) {
+            client.send(Message::Text(serde_json::to_string(&message).unwrap())).await.unwrap();
+        }
+        /// The next message of `message_type`, skipping broadcasts
+        async fn expect(client: &mut Client, message_type: &str) -> serde_json::Value {
+            loop {
+                let message = tokio::time::timeout(Duration::from_secs(5), client.next())
+                    .await
+                    .expect("server should answer")
+                    .unwrap()
+                    .unwrap();
+                if let Message::Text(text) = message {
+                    let message: serde_json::Value = serde_json::from_str(&text).unwrap();
+                    if message["type"] == message_type {
+                        return message;
+                    }
+                }
+            }
+        }
+
+        let Some(db) = crate::testing::TestDb::start().await else { return };
+        let owner = crate::testing::create_test_user(&db.pool).await;
+        let project = crate::testing::create_test_project(&db.pool, &owner, false).await;
+        let session = crate::testing::create_test_session(&db.pool, &project, &owner).await;
+
+        let mut config = crate::testing::test_config();
+        config.websocket.max_connections_per_user = 3;
+        let jwt = JwtService::new(&config.jwt.secret, config.jwt.issuer.clone(), 3600, 3600).unwrap();
+        let token = jwt.generate_access_token(&owner, vec![crate::models::UserRole::Collaborator]).unwrap();
+        let state = Arc::new(ws_state_with(config, &db));
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let server_state = state.clone();
+        let server = tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let stream = tokio_tungstenite::accept_async(stream).await.unwrap();
+                tokio::spawn(handle_websocket_connection(stream, WsServerState::generate_connection_id(), server_state.clone()));
+            }
+        });
+
+        let mut clients = Vec::new();
+        for _ in 0..5 {
+            let (mut client, _) = connect_async(format!("ws://{}", address)).await.unwrap();
+            send(&mut client, WsMessage::Hello { protocol_version: PROTOCOL_VERSION.to_string(), client_info: None }).await;
+            expect(&mut client, "ServerHello").await;
+            send(&mut client, WsMessage::Authenticate { token: token.clone(), session_id: None }).await;
+            assert_eq!(expect(&mut client, "AuthResult").await["success"], true);
+            let join = WsMessage::JoinSession {
+                session_id: session.id,
+                role: ParticipantRole::Editor,
+                password: None,
+                join_code: None,
+            };
+            send(&mut client, join).await;
+            expect(&mut client, "SessionJoined").await;
+            clients.push(client);
+        }
+
+        // The two oldest, idlest connections made room for the last two
+        for client in &mut clients[..2] {
+            let closed = tokio::time::timeout(Duration::from_secs(5), async {
+                while let Some(Ok(message)) = client.next().await {
+                    if let Message::Close(frame) = message {
+                        return frame;
+                    }
+                }
+                None
+            })
+            .await
+            .expect("evicted connection should be closed")
+            .expect("close frame should carry a code");
+            assert_eq!(closed.code, CloseCode::from(CLOSE_CONNECTION_LIMIT));
+        }
+        assert_eq!(state.connection_limiter.count(owner.id), 3);
+        assert_eq!(state.connections.read().await.len(), 3);
+
+        let online: Vec<bool> = sqlx::query_scalar(
+            "SELECT is_online FROM session_participants WHERE session_id = $1 ORDER BY joined_at, id"
+        )
+        .bind(session.id)
+        .fetch_all(&db.pool)
+        .await
+        .unwrap();
+        assert_eq!(online, vec![false, false, true, true, true]);
+        server.abort();
+    }
+
+    #[tokio::test]
+    async fn test_joins_past_capacity_or_end_are_typed() {
+        let Some(db) = crate::testing::TestDb::start().await else { return };
+        let owner = crate::testing::create_test_user(&db.pool).await;
+        let first = crate::testing::create_test_user(&db.pool).await;
+        let second = crate::testing::create_test_user(&db.pool).await;
+        let project = crate::testing::create_test_project(&db.pool, &owner, false).await;
+        let session = crate::testing::create_test_session(&db.pool, &project, &owner).await;
+        sqlx::query("UPDATE collaboration_sessions SET max_participants = 2 WHERE id = $1")
+            .bind(session.id)
+            .execute(&db.pool)
+            .await
+            .unwrap();
+
+        let state = test_ws_state(&db);
+        let join = |session_id: Uuid, user_id: Uuid| {
+            let state = &state;
+            async move {
+                let credentials = JoinCredentials { password: None, join_code: None };
+                state.handle_session_join("connection", session_id, user_id, ParticipantRole::Editor, credentials).await
+            }
+        };
+
+        join(session.id, owner.id).await.unwrap();
+        join(session.id, first.id).await.unwrap();
+        let full = WsError::from(&join(session.id, second.id).await.unwrap_err());
+        assert_eq!((full.code, full.retryable), (WsErrorCode::SessionFull, false));
+        // Another tab of a participant takes no place of its own
+        join(session.id, first.id).await.unwrap();
+
+        let missing = WsError::from(&join(Uuid::new_v4(), owner.id).await.unwrap_err());
+        assert_eq!(missing.code, WsErrorCode::SessionNotFound);
+        session.end(&db.pool).await.unwrap();
+        let ended = WsError::from(&join(session.id, owner.id).await.unwrap_err());
+        assert_eq!(ended.code, WsErrorCode::SessionEnded);
+
+        let frame = serde_json::to_value(WsMessage::from(ended)).unwrap();
+        assert_eq!(
+            frame,
+            serde_json::json!({
+                "type": "Error",
+                "code": "SESSION_ENDED",
+                "message": "Session has ended",
+                "retryable": false,
+                "retry_after_ms": null,
+            })
+        );
+        let limited = WsError::new(WsErrorCode::RateLimited, "Slow down").retry_after(1500);
+        let frame = serde_json::to_value(WsMessage::from(limited)).unwrap();
+        assert_eq!((&frame["code"], &frame["retryable"], &frame["retry_after_ms"]), (&"RATE_LIMITED".into(), &true.into(), &1500.into()));
+    }
+
+    #[tokio::test]
+    async fn test_idle_editors_give_up_their_slot() {
+        async fn next_update(receiver: &mut broadcast::Receiver<WsMessage>) -> SessionParticipant {
+            loop {
+                let message = tokio::time::timeout(Duration::from_secs(5), receiver.recv()).await.unwrap().unwrap();
+                if let WsMessage::ParticipantUpdate { participant, .. } = message {
+                    return participant;
+                }
+            }
+        }
+
+        let Some(db) = crate::testing::TestDb::start().await else { return };
+        let host = crate::testing::create_test_user(&db.pool).await;
+        let author = crate::testing::create_test_user(&db.pool).await;
+        let reviewer = crate::testing::create_test_user(&db.pool).await;
+        let project = crate::testing::create_test_project(&db.pool, &host, false).await;
+        let session = crate::testing::create_test_session(&db.pool, &project, &host).await;
+        let policy = r#"{"idle_policy":{"downgrade_after_minutes":5,"away_after_minutes":10},"max_editors":1}"#;
+        session.update_settings(&db.pool, policy).await.unwrap();
+
+        let state = test_ws_state(&db);
+        let mut updates = state.get_session_broadcast(session.id).await.subscribe();
+        let mut reviewer_messages = state.user_channels.subscribe(reviewer.id);
+        let join = |user_id: Uuid| {
+            let state = &state;
+            async move {
+                let credentials = JoinCredentials { password: None, join_code: None };
+                state.handle_session_join("connection", session.id, user_id, ParticipantRole::Editor, credentials).await.unwrap()
+            }
+        };
+        let minutes = chrono::Duration::minutes;
+
+        let start = Utc::now();
+        assert_eq!(join(author.id).await.role, ParticipantRole::Editor);
+        // The only edit slot is taken, so the reviewer waits as a viewer
+        let waiting = join(reviewer.id).await;
+        assert_eq!((waiting.role, waiting.restore_role), (ParticipantRole::Viewer, Some(ParticipantRole::Editor)));
+        assert!(waiting.queued_at.is_some());
+        assert_eq!(next_update(&mut updates).await.user_id, author.id);
+        assert_eq!(next_update(&mut updates).await.queued_at, waiting.queued_at);
+        assert_eq!(state.session_activity.editors(session.id), 1);
+
+        // The author idles while the reviewer keeps chatting
+        state.note_activity(session.id, reviewer.id, start + minutes(4)).await.unwrap();
+        state.enforce_idle_policy(start + minutes(6)).await;
+        let downgraded = next_update(&mut updates).await;
+        assert_eq!(downgraded.user_id, author.id);
+        assert_eq!((downgraded.role, downgraded.restore_role), (ParticipantRole::Viewer, Some(ParticipantRole::Editor)));
+        assert_eq!(downgraded.activity, ParticipantActivity::Idle);
+        let promoted = next_update(&mut updates).await;
+        assert_eq!((promoted.id, promoted.role, promoted.queued_at), (waiting.id, ParticipantRole::Editor, None));
+        assert_eq!(state.session_activity.editors(session.id), 1);
+        match reviewer_messages.try_recv().unwrap() {
+            WsMessage::Notification { notification } => assert_eq!(notification.kind, "edit_slot_available"),
+            message => panic!("expected a notification, got {:?}", message),
+        }
+
+        // Back at work, the author waits for a slot and cannot edit meanwhile
+        let insert = state.handle_operation(session.id, author.id, OperationType::Insert, Some(0), Some("x".to_string()), None, None);
+        assert_eq!(WsError::from(&insert.await.unwrap_err()).code, WsErrorCode::PermissionDenied);
+        let queued = next_update(&mut updates).await;
+        assert_eq!((queued.role, queued.activity), (ParticipantRole::Viewer, ParticipantActivity::Active));
+        assert!(queued.queued_at.is_some());
+
+        // The reviewer leaving hands the slot back
+        state.handle_session_leave(session.id, promoted.id).await.unwrap();
+        let restored = next_update(&mut updates).await;
+        assert_eq!((restored.id, restored.role, restored.restore_role), (downgraded.id, ParticipantRole::Editor, None));
+        assert_eq!(state.session_activity.editors(session.id), 1);
+        assert_eq!(state.session_activity.may_edit(session.id, author.id), Some(true));
+
+        // The reviewer's activity was written out as they left
+        let last_activity_at: chrono::DateTime<Utc> =
+            sqlx::query_scalar("SELECT last_activity_at FROM session_participants WHERE id = $1")
+                .bind(promoted.id)
+                .fetch_one(&db.pool)
+                .await
+                .unwrap();
+        assert!((last_activity_at - (start + minutes(4))).num_milliseconds().abs() < 1);
+    }
+
     #[tokio::test]
     async fn test_ws_server_state_creation() {
         // This test would need a proper config and database pool